@@ -53,6 +53,11 @@ pub struct CliOptions {
     /// Do not load or store anything on disk.
     #[structopt(long)]
     pub tmp: bool,
+    /// Identifier (4 ASCII characters) of a consensus engine, other than Aura, Babe, and
+    /// GrandPa, whose digest items can be found in the headers of the chain. Can be passed
+    /// multiple times.
+    #[structopt(long)]
+    pub custom_consensus_engine: Vec<ConsensusEngineId>,
 }
 
 #[derive(Debug)]
@@ -180,6 +185,22 @@ impl AsRef<[u8; 32]> for NodeKey {
     }
 }
 
+#[derive(Debug)]
+pub struct ConsensusEngineId(pub [u8; 4]);
+
+impl core::str::FromStr for ConsensusEngineId {
+    type Err = ConsensusEngineIdParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id = <[u8; 4]>::try_from(s.as_bytes()).map_err(|_| ConsensusEngineIdParseError)?;
+        Ok(ConsensusEngineId(id))
+    }
+}
+
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Consensus engine identifiers must be 4 bytes long")]
+pub struct ConsensusEngineIdParseError;
+
 #[derive(Debug, derive_more::Display)]
 pub enum NodeKeyParseError {
     #[display(fmt = "Expected 64 hexadecimal digits")]
@@ -0,0 +1,543 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Queue of finalized blocks waiting to be committed to the database.
+//!
+//! The sync state machine verifies blocks, which includes executing them by calling the
+//! `Core_execute_block` runtime function, on top of the storage of the latest finalized block it
+//! knows about. Once blocks are finalized, they are pushed to the [`ImportQueue`] with
+//! [`ImportQueue::push`], and a background task commits them to the database.
+//!
+//! Writing blocks to the database is slow, and the sync state machine doesn't wait for the
+//! blocks to be committed before continuing. Instead, the [`ImportQueue`] keeps in memory the
+//! storage changes of the blocks that have been pushed but not committed yet, and answers storage
+//! accesses by combining these changes with the content of the database. From the point of view
+//! of the sync state machine, the storage is always the one of the latest finalized block that
+//! has been pushed.
+
+use futures::{channel::mpsc, prelude::*};
+use smoldot::{database::full_sqlite, sync::optimistic};
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    ops::Bound,
+    sync::Arc,
+    thread,
+};
+
+/// Maximum number of calls to [`ImportQueue::push`] whose list of modified keys is kept in
+/// order to answer [`ImportQueue::changed_keys_since`].
+const MAX_RECENT_CHANGES: usize = 64;
+
+/// See [the module-level documentation](..).
+pub struct ImportQueue {
+    /// Database where blocks are committed.
+    database: Arc<full_sqlite::SqliteFullDatabase>,
+
+    /// Channel to the background task that commits blocks.
+    to_commit_task: mpsc::Sender<Vec<optimistic::Block<()>>>,
+
+    /// Locked while accessing the database, so that the finalized block of the database and
+    /// [`Inner::pending`] are always in sync.
+    inner: parking_lot::Mutex<Inner>,
+}
+
+struct Inner {
+    /// Hash of the finalized block of the database.
+    database_finalized_hash: [u8; 32],
+
+    /// Hash of the latest finalized block that has been pushed, or of the finalized block of the
+    /// database if no block is waiting to be committed.
+    latest_finalized_hash: [u8; 32],
+
+    /// For each list of blocks passed to [`ImportQueue::push`] and not committed yet, oldest
+    /// first, the storage changes that these blocks perform when applied one after the other.
+    /// `None` means that the key is removed.
+    pending: VecDeque<BTreeMap<Vec<u8>, Option<Vec<u8>>>>,
+
+    /// For each of the latest calls to [`ImportQueue::push`], oldest first, the latest finalized
+    /// block before the call, and the keys modified by the pushed blocks. Contains at most
+    /// [`MAX_RECENT_CHANGES`] elements.
+    recent_changes: VecDeque<([u8; 32], Vec<Vec<u8>>)>,
+}
+
+impl ImportQueue {
+    /// Initializes a new queue. Also returns the background task that commits the blocks, which
+    /// must be spawned.
+    pub fn new(
+        database: Arc<full_sqlite::SqliteFullDatabase>,
+    ) -> (Arc<Self>, impl Future<Output = ()> + Send) {
+        let (to_commit_task, from_queue) = mpsc::channel(4);
+
+        let database_finalized_hash = database.finalized_block_hash().unwrap();
+        let queue = Arc::new(ImportQueue {
+            inner: parking_lot::Mutex::new(Inner {
+                database_finalized_hash,
+                latest_finalized_hash: database_finalized_hash,
+                pending: VecDeque::new(),
+                recent_changes: VecDeque::new(),
+            }),
+            database,
+            to_commit_task,
+        });
+
+        let task = commit_task(queue.clone(), from_queue);
+        (queue, task)
+    }
+
+    /// Queues the given list of blocks for committing to the database. The blocks must be
+    /// finalized, ordered by increasing block number, and the first block must be a child of the
+    /// latest block that has been pushed, or of the finalized block of the database if no block
+    /// has been pushed yet.
+    ///
+    /// Waits if too many blocks are waiting to be committed.
+    pub async fn push(&self, blocks: Vec<optimistic::Block<()>>) {
+        let mut changes = BTreeMap::new();
+        for block in &blocks {
+            for (key, value) in &block.storage_top_trie_changes {
+                changes.insert(key.clone(), value.clone());
+            }
+        }
+
+        {
+            let mut inner = self.inner.lock();
+            if let Some(last_block) = blocks.last() {
+                let previous_finalized_hash = inner.latest_finalized_hash;
+                inner.latest_finalized_hash = last_block.header.hash();
+
+                if inner.recent_changes.len() >= MAX_RECENT_CHANGES {
+                    inner.recent_changes.pop_front();
+                }
+                inner
+                    .recent_changes
+                    .push_back((previous_finalized_hash, changes.keys().cloned().collect()));
+            }
+            inner.pending.push_back(changes);
+        }
+
+        // The commit task only stops when the queue is destroyed.
+        self.to_commit_task.clone().send(blocks).await.unwrap();
+    }
+
+    /// Returns the hash of the latest finalized block that has been pushed, or of the finalized
+    /// block of the database if no block has been pushed yet.
+    pub fn finalized_block_hash(&self) -> [u8; 32] {
+        self.inner.lock().latest_finalized_hash
+    }
+
+    /// Returns the list of keys whose storage value might have been modified between the block
+    /// with the given hash and the latest finalized block that has been pushed.
+    ///
+    /// Returns `None` if the given block isn't the latest finalized block nor one of the
+    /// finalized blocks that preceded the most recent calls to [`ImportQueue::push`].
+    pub fn changed_keys_since(&self, block_hash: &[u8; 32]) -> Option<BTreeSet<Vec<u8>>> {
+        let inner = self.inner.lock();
+        if inner.latest_finalized_hash == *block_hash {
+            return Some(BTreeSet::new());
+        }
+
+        let first = inner
+            .recent_changes
+            .iter()
+            .position(|(previous, _)| previous == block_hash)?;
+        Some(
+            inner
+                .recent_changes
+                .iter()
+                .skip(first)
+                .flat_map(|(_, keys)| keys.iter().cloned())
+                .collect(),
+        )
+    }
+
+    /// Returns the value of the given key in the storage of the latest finalized block that has
+    /// been pushed.
+    pub fn storage_get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.with_database(|inner| {
+            if let Some(value) = inner.pending_value(key) {
+                return Ok(value.clone());
+            }
+
+            self.database
+                .finalized_block_storage_top_trie_get(&inner.database_finalized_hash, key)
+        })
+    }
+
+    /// Returns the key that immediately follows the given key in the storage of the latest
+    /// finalized block that has been pushed.
+    pub fn storage_next_key(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.with_database(|inner| {
+            inner.next_key(key, |after| {
+                self.database.finalized_block_storage_top_trie_next_key(
+                    &inner.database_finalized_hash,
+                    after,
+                )
+            })
+        })
+    }
+
+    /// Returns the key that immediately precedes the given key in the storage of the latest
+    /// finalized block that has been pushed, or the last key of the storage if `None` is passed.
+    pub fn storage_prev_key(&self, key: Option<&[u8]>) -> Option<Vec<u8>> {
+        self.with_database(|inner| {
+            inner.prev_key(key, |before| {
+                self.database.finalized_block_storage_top_trie_prev_key(
+                    &inner.database_finalized_hash,
+                    before,
+                )
+            })
+        })
+    }
+
+    /// Returns the list of keys that start with the given prefix in the storage of the latest
+    /// finalized block that has been pushed.
+    pub fn storage_prefix_keys(&self, prefix: &[u8]) -> Vec<Vec<u8>> {
+        self.with_database(|inner| {
+            let in_database = self
+                .database
+                .finalized_block_storage_top_trie_keys(&inner.database_finalized_hash, prefix)?;
+            Ok(inner.prefix_keys(prefix, in_database.into_iter()))
+        })
+    }
+
+    /// Calls `access` with the state of the queue, and calls it again if the finalized block of
+    /// the database has been updated by the commit task in-between.
+    ///
+    /// The commit task updates the finalized block of the database before updating
+    /// [`Inner::database_finalized_hash`], during which accessing the database fails with
+    /// [`full_sqlite::FinalizedAccessError::Obsolete`].
+    fn with_database<T>(
+        &self,
+        mut access: impl FnMut(&Inner) -> Result<T, full_sqlite::FinalizedAccessError>,
+    ) -> T {
+        loop {
+            let inner = self.inner.lock();
+            match access(&inner) {
+                Ok(value) => return value,
+                Err(full_sqlite::FinalizedAccessError::Obsolete) => {
+                    drop(inner);
+                    thread::yield_now();
+                }
+                Err(err) => panic!("{}", err),
+            }
+        }
+    }
+}
+
+impl Inner {
+    /// Returns the value of the given key according to the blocks that haven't been committed
+    /// yet, or `None` if these blocks don't modify this key.
+    fn pending_value(&self, key: &[u8]) -> Option<&Option<Vec<u8>>> {
+        self.pending
+            .iter()
+            .rev()
+            .find_map(|changes| changes.get(key))
+    }
+
+    /// Returns the key that immediately follows the given key, taking into account the blocks
+    /// that haven't been committed yet. `database_next_key` must return the key that immediately
+    /// follows the given key in the database.
+    fn next_key<E>(
+        &self,
+        key: &[u8],
+        mut database_next_key: impl FnMut(&[u8]) -> Result<Option<Vec<u8>>, E>,
+    ) -> Result<Option<Vec<u8>>, E> {
+        let mut after = key.to_vec();
+        loop {
+            let in_database = database_next_key(&after)?;
+            let in_pending = self
+                .pending
+                .iter()
+                .filter_map(|changes| {
+                    changes
+                        .range::<[u8], _>((Bound::Excluded(&after[..]), Bound::Unbounded))
+                        .next()
+                        .map(|(k, _)| k)
+                })
+                .min()
+                .cloned();
+
+            let candidate = match (in_database, in_pending) {
+                (Some(a), Some(b)) => a.min(b),
+                (Some(k), None) | (None, Some(k)) => k,
+                (None, None) => return Ok(None),
+            };
+
+            // The candidate might have been removed by one of the pending blocks.
+            if let Some(None) = self.pending_value(&candidate) {
+                after = candidate;
+                continue;
+            }
+
+            return Ok(Some(candidate));
+        }
+    }
+
+    /// Returns the key that immediately precedes the given key, or the last key if `None` is
+    /// passed, taking into account the blocks that haven't been committed yet.
+    /// `database_prev_key` must return the same thing as this function, but for the database.
+    fn prev_key<E>(
+        &self,
+        key: Option<&[u8]>,
+        mut database_prev_key: impl FnMut(Option<&[u8]>) -> Result<Option<Vec<u8>>, E>,
+    ) -> Result<Option<Vec<u8>>, E> {
+        let mut before = key.map(|k| k.to_vec());
+        loop {
+            let in_database = database_prev_key(before.as_deref())?;
+            let upper_bound = match &before {
+                Some(before) => Bound::Excluded(&before[..]),
+                None => Bound::Unbounded,
+            };
+            let in_pending = self
+                .pending
+                .iter()
+                .filter_map(|changes| {
+                    changes
+                        .range::<[u8], _>((Bound::Unbounded, upper_bound))
+                        .next_back()
+                        .map(|(k, _)| k)
+                })
+                .max()
+                .cloned();
+
+            let candidate = match (in_database, in_pending) {
+                (Some(a), Some(b)) => a.max(b),
+                (Some(k), None) | (None, Some(k)) => k,
+                (None, None) => return Ok(None),
+            };
+
+            // The candidate might have been removed by one of the pending blocks.
+            if let Some(None) = self.pending_value(&candidate) {
+                before = Some(candidate);
+                continue;
+            }
+
+            return Ok(Some(candidate));
+        }
+    }
+
+    /// Returns the list of keys that start with the given prefix, taking into account the
+    /// blocks that haven't been committed yet. `in_database` must contain the keys that start
+    /// with this prefix in the database.
+    fn prefix_keys(
+        &self,
+        prefix: &[u8],
+        in_database: impl Iterator<Item = Vec<u8>>,
+    ) -> Vec<Vec<u8>> {
+        let mut keys = in_database.collect::<BTreeSet<_>>();
+
+        for changes in &self.pending {
+            for (key, value) in changes
+                .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+                .take_while(|(k, _)| k.starts_with(prefix))
+            {
+                if value.is_some() {
+                    keys.insert(key.clone());
+                } else {
+                    keys.remove(key);
+                }
+            }
+        }
+
+        keys.into_iter().collect()
+    }
+}
+
+/// Background task that commits to the database the blocks pushed to the [`ImportQueue`].
+async fn commit_task(
+    queue: Arc<ImportQueue>,
+    mut from_queue: mpsc::Receiver<Vec<optimistic::Block<()>>>,
+) {
+    while let Some(finalized_blocks) = from_queue.next().await {
+        let span = tracing::trace_span!("blocks-db-write", len = finalized_blocks.len());
+        let _enter = span.enter();
+
+        // The blocks are written without locking the queue, as this doesn't modify the storage
+        // of the finalized block of the database, and storage accesses can continue meanwhile.
+        for block in &finalized_blocks {
+            // TODO: overhead for building the SCALE encoding of the header
+            let result = queue.database.insert(
+                &block.header.scale_encoding().fold(Vec::new(), |mut a, b| {
+                    a.extend_from_slice(b.as_ref());
+                    a
+                }),
+                true, // TODO: is_new_best?
+                block.body.iter(),
+                block
+                    .storage_top_trie_changes
+                    .iter()
+                    .map(|(k, v)| (k, v.as_ref())),
+            );
+
+            match result {
+                Ok(()) => {}
+                Err(full_sqlite::InsertError::Duplicate) => {} // TODO: this should be an error ; right now we silence them because non-finalized blocks aren't loaded from the database at startup, resulting in them being downloaded again
+                Err(err) => panic!("{}", err),
+            }
+        }
+
+        // Storage accesses performed between `set_finalized` and the update of `inner` fail
+        // with `Obsolete` and are retried. See `ImportQueue::with_database`.
+        let new_finalized_hash = finalized_blocks.last().map(|block| block.header.hash());
+        if let Some(new_finalized_hash) = &new_finalized_hash {
+            queue.database.set_finalized(new_finalized_hash).unwrap();
+        }
+
+        let mut inner = queue.inner.lock();
+        if let Some(new_finalized_hash) = new_finalized_hash {
+            inner.database_finalized_hash = new_finalized_hash;
+        }
+        inner.pending.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Inner;
+    use core::convert::Infallible;
+    use std::{
+        collections::{BTreeMap, VecDeque},
+        ops::Bound,
+    };
+
+    /// Builds an [`Inner`] whose pending blocks perform the given changes, oldest first.
+    fn inner(pending: &[&[(&[u8], Option<&[u8]>)]]) -> Inner {
+        Inner {
+            database_finalized_hash: [0; 32],
+            latest_finalized_hash: [0; 32],
+            recent_changes: VecDeque::new(),
+            pending: pending
+                .iter()
+                .map(|changes| {
+                    changes
+                        .iter()
+                        .map(|(k, v)| (k.to_vec(), v.map(|v| v.to_vec())))
+                        .collect::<BTreeMap<_, _>>()
+                })
+                .collect::<VecDeque<_>>(),
+        }
+    }
+
+    fn database(keys: &[&[u8]]) -> BTreeMap<Vec<u8>, ()> {
+        keys.iter().map(|k| (k.to_vec(), ())).collect()
+    }
+
+    fn next_key(inner: &Inner, database: &BTreeMap<Vec<u8>, ()>, key: &[u8]) -> Option<Vec<u8>> {
+        inner
+            .next_key(key, |after| {
+                Ok::<_, Infallible>(
+                    database
+                        .range::<[u8], _>((Bound::Excluded(after), Bound::Unbounded))
+                        .next()
+                        .map(|(k, _)| k.clone()),
+                )
+            })
+            .unwrap()
+    }
+
+    fn prev_key(
+        inner: &Inner,
+        database: &BTreeMap<Vec<u8>, ()>,
+        key: Option<&[u8]>,
+    ) -> Option<Vec<u8>> {
+        inner
+            .prev_key(key, |before| {
+                let upper_bound = match before {
+                    Some(before) => Bound::Excluded(before),
+                    None => Bound::Unbounded,
+                };
+                Ok::<_, Infallible>(
+                    database
+                        .range::<[u8], _>((Bound::Unbounded, upper_bound))
+                        .next_back()
+                        .map(|(k, _)| k.clone()),
+                )
+            })
+            .unwrap()
+    }
+
+    fn prefix_keys(inner: &Inner, database: &BTreeMap<Vec<u8>, ()>, prefix: &[u8]) -> Vec<Vec<u8>> {
+        inner.prefix_keys(
+            prefix,
+            database.keys().filter(|k| k.starts_with(prefix)).cloned(),
+        )
+    }
+
+    #[test]
+    fn no_pending_block() {
+        let queue = inner(&[]);
+        let storage = database(&[b"a", b"b", b"c"]);
+        assert_eq!(next_key(&queue, &storage, b"a"), Some(b"b".to_vec()));
+        assert_eq!(next_key(&queue, &storage, b"c"), None);
+        assert_eq!(
+            prefix_keys(&queue, &storage, b""),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec()]
+        );
+    }
+
+    #[test]
+    fn pending_insertions() {
+        let queue = inner(&[&[(b"ab", Some(b"1"))], &[(b"d", Some(b"2"))]]);
+        let storage = database(&[b"a", b"c"]);
+        assert_eq!(next_key(&queue, &storage, b"a"), Some(b"ab".to_vec()));
+        assert_eq!(next_key(&queue, &storage, b"ab"), Some(b"c".to_vec()));
+        assert_eq!(next_key(&queue, &storage, b"c"), Some(b"d".to_vec()));
+        assert_eq!(next_key(&queue, &storage, b"d"), None);
+        assert_eq!(prev_key(&queue, &storage, None), Some(b"d".to_vec()));
+        assert_eq!(prev_key(&queue, &storage, Some(b"c")), Some(b"ab".to_vec()));
+        assert_eq!(prev_key(&queue, &storage, Some(b"a")), None);
+        assert_eq!(
+            prefix_keys(&queue, &storage, b"a"),
+            vec![b"a".to_vec(), b"ab".to_vec()]
+        );
+    }
+
+    #[test]
+    fn deletions_across_batches() {
+        // `b` is removed from the database by the first batch, `c` is inserted by the first
+        // batch then removed by the second, and `d` is removed then inserted again.
+        let queue = inner(&[
+            &[(b"b", None), (b"c", Some(b"1")), (b"d", None)],
+            &[(b"c", None), (b"d", Some(b"2"))],
+        ]);
+        let storage = database(&[b"a", b"b", b"d", b"e"]);
+
+        assert_eq!(next_key(&queue, &storage, b"a"), Some(b"d".to_vec()));
+        assert_eq!(next_key(&queue, &storage, b""), Some(b"a".to_vec()));
+        assert_eq!(next_key(&queue, &storage, b"d"), Some(b"e".to_vec()));
+        assert_eq!(prev_key(&queue, &storage, Some(b"d")), Some(b"a".to_vec()));
+        assert_eq!(prev_key(&queue, &storage, Some(b"da")), Some(b"d".to_vec()));
+        assert_eq!(
+            prefix_keys(&queue, &storage, b""),
+            vec![b"a".to_vec(), b"d".to_vec(), b"e".to_vec()]
+        );
+
+        // Key absent from the database, inserted by a batch then removed by the next one.
+        let queue = inner(&[&[(b"b", Some(b"1"))], &[(b"b", None)]]);
+        let storage = database(&[b"a"]);
+        assert_eq!(next_key(&queue, &storage, b"a"), None);
+        assert_eq!(prefix_keys(&queue, &storage, b"b"), Vec::<Vec<u8>>::new());
+    }
+
+    #[test]
+    fn all_following_keys_removed() {
+        let queue = inner(&[&[(b"b", None)], &[(b"c", None), (b"d", None)]]);
+        let storage = database(&[b"a", b"b", b"c", b"d"]);
+        assert_eq!(next_key(&queue, &storage, b"a"), None);
+        assert_eq!(prev_key(&queue, &storage, None), Some(b"a".to_vec()));
+        assert_eq!(prefix_keys(&queue, &storage, b""), vec![b"a".to_vec()]);
+    }
+}
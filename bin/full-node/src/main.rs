@@ -35,7 +35,9 @@ use structopt::StructOpt as _;
 use tracing::Instrument as _;
 
 mod cli;
+mod import_queue;
 mod network_service;
+mod proof_requests;
 mod sync_service;
 
 fn main() {
@@ -264,6 +266,12 @@ async fn async_main() {
         network_events_receiver: network_events_receivers.next().unwrap(),
         network_service: (network_service.clone(), 0),
         database,
+        babe_relaxed_secondary_slots: chain_spec.babe_relaxed_secondary_slots(),
+        custom_consensus_engines: cli_options
+            .custom_consensus_engine
+            .iter()
+            .map(|engine| engine.0)
+            .collect(),
     })
     .instrument(tracing::debug_span!("sync-service-init"))
     .await;
@@ -278,6 +286,11 @@ async fn async_main() {
                 network_events_receiver: network_events_receivers.next().unwrap(),
                 network_service: (network_service.clone(), 1),
                 database: relay_chain_database,
+                babe_relaxed_secondary_slots: relay_chain_spec
+                    .as_ref()
+                    .unwrap()
+                    .babe_relaxed_secondary_slots(),
+                custom_consensus_engines: Vec::new(),
             })
             .instrument(tracing::debug_span!("relay-chain-sync-service-init"))
             .await,
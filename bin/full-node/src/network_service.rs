@@ -102,6 +102,22 @@ pub enum Event {
         peer_id: PeerId,
         announce: service::EncodedBlockAnnounce,
     },
+    /// A light client has requested a proof of some storage entries. Must be answered by calling
+    /// [`NetworkService::respond_storage_proof`].
+    StorageProofRequest {
+        chain_index: usize,
+        peer_id: PeerId,
+        request_id: service::InRequestId,
+        request: protocol::StorageProofRequest,
+    },
+    /// A light client has requested a proof of the storage entries accessed by a runtime call.
+    /// Must be answered by calling [`NetworkService::respond_call_proof`].
+    CallProofRequest {
+        chain_index: usize,
+        peer_id: PeerId,
+        request_id: service::InRequestId,
+        request: protocol::CallProofRequest,
+    },
 }
 
 pub struct NetworkService {
@@ -209,6 +225,7 @@ impl NetworkService {
                 best_number: chain.best_block.0,
                 genesis_hash: chain.genesis_block_hash,
                 role: protocol::Role::Full,
+                allow_inbound_light_requests: true,
                 grandpa_protocol_config: if chain.has_grandpa_protocol {
                     // TODO: dummy values
                     Some(service::GrandpaState {
@@ -235,7 +252,13 @@ impl NetworkService {
                 // TODO: we use an abnormally large channel in order to by pass https://github.com/paritytech/smoldot/issues/615
                 // once the issue is solved, this should be restored to a smaller value, such as 64
                 pending_api_events_buffer_size: NonZeroUsize::new(2048).unwrap(),
+                max_connection_receive_buffer_size: 128 * 1024 * 1024,
                 randomness_seed: rand::random(),
+                extra_request_response_protocols: Vec::new(),
+                // The full node is expected to be directly reachable and to connect to nodes
+                // that are directly reachable as well.
+                allow_relayed_connections: false,
+                response_decompressor: None,
             }),
         });
 
@@ -256,6 +279,30 @@ impl NetworkService {
                         match network_service.network.next_event().await {
                             service::Event::Connected(peer_id) => {
                                 tracing::debug!(%peer_id, "connected");
+
+                                // The answer to the identify request is remembered by the
+                                // `ChainNetwork` in order to not send requests on protocols the
+                                // peer doesn't support.
+                                let network_service2 = network_service.clone();
+                                (network_service.guarded.lock().tasks_executor)(Box::pin(
+                                    async move {
+                                        match network_service2
+                                            .network
+                                            .identify_request(Instant::now(), peer_id.clone())
+                                            .await
+                                        {
+                                            Ok(response) => tracing::debug!(
+                                                %peer_id,
+                                                agent_version = ?response.agent_version,
+                                                num_protocols = response.protocols.len(),
+                                                "identify-response"
+                                            ),
+                                            Err(error) => {
+                                                tracing::debug!(%peer_id, %error, "identify-error")
+                                            }
+                                        }
+                                    },
+                                ));
                             }
                             service::Event::Disconnected {
                                 peer_id,
@@ -305,10 +352,59 @@ impl NetworkService {
                                     peer_id,
                                 };
                             }
+                            service::Event::GenesisMismatch {
+                                peer_id,
+                                chain_index,
+                                remote_genesis_hash,
+                            } => {
+                                tracing::warn!(
+                                    %chain_index, %peer_id,
+                                    genesis = %HashDisplay(&remote_genesis_hash),
+                                    "genesis-mismatch"
+                                );
+                            }
                             service::Event::IdentifyRequestIn { peer_id, request } => {
                                 tracing::debug!(%peer_id, "identify-request");
                                 request.respond("smoldot").await;
                             }
+                            service::Event::StorageProofRequestIn {
+                                chain_index,
+                                peer_id,
+                                request_id,
+                                request,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    block = %HashDisplay(&request.block_hash),
+                                    num_keys = request.keys.len(),
+                                    "storage-proof-request"
+                                );
+                                break Event::StorageProofRequest {
+                                    chain_index,
+                                    peer_id,
+                                    request_id,
+                                    request,
+                                };
+                            }
+                            service::Event::CallProofRequestIn {
+                                chain_index,
+                                peer_id,
+                                request_id,
+                                request,
+                            } => {
+                                tracing::debug!(
+                                    %chain_index, %peer_id,
+                                    block = %HashDisplay(&request.block_hash),
+                                    method = %request.method,
+                                    "call-proof-request"
+                                );
+                                break Event::CallProofRequest {
+                                    chain_index,
+                                    peer_id,
+                                    request_id,
+                                    request,
+                                };
+                            }
                             service::Event::GrandpaCommitMessage {
                                 chain_index,
                                 message,
@@ -464,6 +560,32 @@ impl NetworkService {
             .blocks_request(Instant::now(), target, chain_index, config)
             .await
     }
+
+    /// Sends back the response to a [`Event::StorageProofRequest`]. Passing `None` refuses the
+    /// request.
+    #[tracing::instrument(skip(self, proof))]
+    pub async fn respond_storage_proof(
+        &self,
+        request_id: service::InRequestId,
+        proof: Option<Vec<Vec<u8>>>,
+    ) {
+        self.network
+            .respond_storage_proof(request_id, proof.as_ref().map(|p| p.iter()))
+            .await
+    }
+
+    /// Sends back the response to a [`Event::CallProofRequest`]. Passing `None` refuses the
+    /// request.
+    #[tracing::instrument(skip(self, proof))]
+    pub async fn respond_call_proof(
+        &self,
+        request_id: service::InRequestId,
+        proof: Option<Vec<Vec<u8>>>,
+    ) {
+        self.network
+            .respond_call_proof(request_id, proof.as_ref().map(|p| p.iter()))
+            .await
+    }
 }
 
 /// Error when initializing the network service.
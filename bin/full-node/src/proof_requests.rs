@@ -0,0 +1,847 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Answering the storage proof and call proof requests of light clients.
+//!
+//! Generating a proof is expensive: the node values of the storage trie must be calculated, and
+//! a call proof requires executing the runtime. In order to not slow down the synchronization,
+//! requests are queued with [`ProofRequests::queue`] and answered by a separate background task.
+//!
+//! The number of requests waiting to be answered is bounded, both in total and per peer.
+//! Requests that exceed these limits are refused immediately.
+//!
+//! Proofs can be requested against the latest finalized block pushed to the
+//! [`import_queue::ImportQueue`], or against a descendant of this block, in which case the
+//! request must indicate the storage changes between the two blocks.
+//!
+//! The database only contains the storage entries and not the trie nodes. The nodes along the
+//! paths of the requested keys are found by querying the storage entries, and the Merkle values
+//! of the nodes whose subtree contains a large number of entries are kept in memory between
+//! requests. When the finalized block changes, only the Merkle values of the nodes whose
+//! subtree has been modified are discarded.
+
+use crate::{import_queue, network_service};
+
+use core::{convert::TryFrom as _, iter, ops::Bound};
+use futures::{channel::mpsc, prelude::*};
+use smoldot::{
+    executor::{self, read_only_runtime_host},
+    libp2p::PeerId,
+    network::{protocol, service},
+    trie::{self, node_value, Nibble},
+};
+use std::{collections::BTreeMap, sync::Arc};
+
+/// Maximum number of requests waiting to be answered.
+const MAX_PENDING_REQUESTS: usize = 16;
+
+/// Maximum number of requests of a single peer waiting to be answered.
+const MAX_PENDING_REQUESTS_PER_PEER: usize = 2;
+
+/// Minimum number of storage entries that the subtree of a node must contain in order for the
+/// Merkle value of this node to be kept in memory between requests. The Merkle values of
+/// smaller subtrees are cheap to calculate again from the database.
+const MIN_CACHED_NODE_ENTRIES: usize = 32;
+
+/// Number of requests waiting to be answered for each peer.
+type PendingPerPeer = hashbrown::HashMap<PeerId, usize, fnv::FnvBuildHasher>;
+
+/// Merkle values of nodes of a storage trie, indexed by the full key of the node. Also contains
+/// the number of nibbles of the key that precede the partial key of the node, which is 0 for the
+/// root node.
+type MerkleValues = BTreeMap<Vec<Nibble>, (usize, node_value::Output)>;
+
+/// See [the module-level documentation](..).
+pub struct ProofRequests {
+    /// Channel to the background task that answers the requests.
+    to_task: mpsc::Sender<(PeerId, service::InRequestId, Request)>,
+
+    /// Number of requests waiting to be answered for each peer. Peers without any request
+    /// waiting aren't in the list.
+    pending_per_peer: Arc<parking_lot::Mutex<PendingPerPeer>>,
+}
+
+/// Request to queue with [`ProofRequests::queue`].
+pub enum Request {
+    /// Storage proof request. Answered with [`network_service::NetworkService::respond_storage_proof`].
+    StorageProof {
+        request: protocol::StorageProofRequest,
+        /// Storage of the block the proof is requested against.
+        storage_diff: BlockStorageDiff,
+    },
+    /// Call proof request. Answered with [`network_service::NetworkService::respond_call_proof`].
+    CallProof {
+        request: protocol::CallProofRequest,
+        /// State root of the block the call is made against.
+        state_root: [u8; 32],
+        /// Storage of the block the call is made against.
+        storage_diff: BlockStorageDiff,
+    },
+}
+
+/// Storage of a block, relative to the storage of a finalized block.
+pub struct BlockStorageDiff {
+    /// Hash of the finalized block the changes apply to. The request is refused if this block
+    /// isn't the latest finalized block of the [`import_queue::ImportQueue`] when the request
+    /// is answered.
+    pub finalized_block_hash: [u8; 32],
+
+    /// Storage entries modified between the finalized block and the block the proof is
+    /// requested against. Contains `None` for entries that have been removed. Empty if the proof
+    /// is requested against the finalized block.
+    pub changes: BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl ProofRequests {
+    /// Initializes a new [`ProofRequests`]. Also returns the background task that answers the
+    /// requests, which must be spawned.
+    pub fn new(
+        import_queue: Arc<import_queue::ImportQueue>,
+        network_service: Arc<network_service::NetworkService>,
+    ) -> (Self, impl Future<Output = ()> + Send) {
+        let (to_task, from_queue) = mpsc::channel(MAX_PENDING_REQUESTS);
+        let pending_per_peer = Arc::new(parking_lot::Mutex::new(Default::default()));
+
+        let task = answer_task(
+            import_queue,
+            network_service,
+            from_queue,
+            pending_per_peer.clone(),
+        );
+
+        let requests = ProofRequests {
+            to_task,
+            pending_per_peer,
+        };
+
+        (requests, task)
+    }
+
+    /// Queues the given request, received from the given peer, for the background task to
+    /// answer it.
+    ///
+    /// Returns an error if too many requests are waiting to be answered, in which case the
+    /// request must be refused.
+    pub fn queue(
+        &mut self,
+        peer_id: PeerId,
+        request_id: service::InRequestId,
+        request: Request,
+    ) -> Result<(), ()> {
+        // The lock is kept until the counter is updated, so that the background task can't
+        // decrease the counter before it is increased.
+        let mut pending_per_peer = self.pending_per_peer.lock();
+
+        if matches!(pending_per_peer.get(&peer_id), Some(n) if *n >= MAX_PENDING_REQUESTS_PER_PEER)
+        {
+            return Err(());
+        }
+
+        self.to_task
+            .try_send((peer_id.clone(), request_id, request))
+            .map_err(|_| ())?;
+
+        *pending_per_peer.entry(peer_id).or_insert(0) += 1;
+        Ok(())
+    }
+}
+
+/// Background task that answers the requests queued with [`ProofRequests::queue`].
+async fn answer_task(
+    import_queue: Arc<import_queue::ImportQueue>,
+    network_service: Arc<network_service::NetworkService>,
+    mut from_queue: mpsc::Receiver<(PeerId, service::InRequestId, Request)>,
+    pending_per_peer: Arc<parking_lot::Mutex<PendingPerPeer>>,
+) {
+    // Merkle values of nodes of the storage trie of the block whose hash is indicated, kept
+    // between requests.
+    let mut cache = (import_queue.finalized_block_hash(), MerkleValues::new());
+
+    while let Some((peer_id, request_id, request)) = from_queue.next().await {
+        match request {
+            Request::StorageProof {
+                request,
+                storage_diff,
+            } => {
+                let proof = build_proof(
+                    &import_queue,
+                    &mut cache,
+                    &storage_diff,
+                    request.keys.iter().map(|k| &k[..]),
+                );
+
+                tracing::debug!(%peer_id, success = proof.is_some(), "storage-proof-response");
+                network_service
+                    .respond_storage_proof(request_id, proof)
+                    .await;
+            }
+            Request::CallProof {
+                request,
+                state_root,
+                storage_diff,
+            } => {
+                let storage = BlockStorage {
+                    import_queue: &import_queue,
+                    diff: &storage_diff.changes,
+                };
+                let accessed_keys =
+                    call_accessed_keys(&storage, &state_root, &request.method, &request.parameter);
+                let proof = accessed_keys.and_then(|accessed_keys| {
+                    build_proof(
+                        &import_queue,
+                        &mut cache,
+                        &storage_diff,
+                        accessed_keys.iter().map(|k| &k[..]),
+                    )
+                });
+
+                tracing::debug!(%peer_id, success = proof.is_some(), "call-proof-response");
+                network_service.respond_call_proof(request_id, proof).await;
+            }
+        }
+
+        let mut pending_per_peer = pending_per_peer.lock();
+        let pending = pending_per_peer.get_mut(&peer_id).unwrap();
+        *pending -= 1;
+        if *pending == 0 {
+            pending_per_peer.remove(&peer_id);
+        }
+    }
+}
+
+/// Generates a proof of the storage values of the given keys in the storage described by
+/// `storage_diff`.
+///
+/// `cache` contains the hash of the finalized block whose Merkle values are in cache. It is
+/// updated to the latest finalized block of the import queue.
+///
+/// Returns `None` if the latest finalized block of the import queue isn't the one indicated in
+/// `storage_diff`.
+fn build_proof<'a>(
+    import_queue: &import_queue::ImportQueue,
+    cache: &mut ([u8; 32], MerkleValues),
+    storage_diff: &BlockStorageDiff,
+    requested_keys: impl Iterator<Item = &'a [u8]>,
+) -> Option<Vec<Vec<u8>>> {
+    let finalized_block_hash = import_queue.finalized_block_hash();
+    if finalized_block_hash != storage_diff.finalized_block_hash {
+        return None;
+    }
+
+    // Discard the Merkle values of the nodes whose subtree has been modified since the cache
+    // has been filled. The nodes whose partial key has changed are detected when reading the
+    // cache.
+    if cache.0 != finalized_block_hash {
+        match import_queue.changed_keys_since(&cache.0) {
+            Some(changed_keys) => {
+                for key in changed_keys {
+                    let key = trie::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+                    for len in 0..=key.len() {
+                        cache.1.remove(&key[..len]);
+                    }
+                }
+            }
+            None => cache.1.clear(),
+        }
+        cache.0 = finalized_block_hash;
+    }
+
+    let mut trie = BlockTrie {
+        storage: BlockStorage {
+            import_queue,
+            diff: &storage_diff.changes,
+        },
+        cache: &cache.1,
+        new_cache_entries: MerkleValues::new(),
+    };
+    let proof = trie.build_proof(requested_keys);
+    let new_cache_entries = trie.new_cache_entries;
+
+    // Blocks might have been pushed to the queue while the storage was being read, in which case
+    // the proof might mix entries of different blocks.
+    if import_queue.finalized_block_hash() != finalized_block_hash {
+        return None;
+    }
+
+    cache.1.extend(new_cache_entries);
+    Some(proof)
+}
+
+/// Storage of a block, made of the storage of the latest finalized block of the import queue
+/// and of the changes on top of it.
+struct BlockStorage<'a> {
+    import_queue: &'a import_queue::ImportQueue,
+    diff: &'a BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+}
+
+impl<'a> BlockStorage<'a> {
+    /// Returns the value of the given key.
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        match self.diff.get(key) {
+            Some(value) => value.clone(),
+            None => self.import_queue.storage_get(key),
+        }
+    }
+
+    /// Returns the key that immediately follows the given key.
+    fn next_key(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut after = key.to_vec();
+        loop {
+            let in_diff = self
+                .diff
+                .range::<[u8], _>((Bound::Excluded(&after[..]), Bound::Unbounded))
+                .next()
+                .map(|(k, _)| k.clone());
+
+            let candidate = match (self.import_queue.storage_next_key(&after), in_diff) {
+                (Some(a), Some(b)) => a.min(b),
+                (Some(k), None) | (None, Some(k)) => k,
+                (None, None) => return None,
+            };
+
+            // The candidate might have been removed by the changes.
+            if let Some(None) = self.diff.get(&candidate) {
+                after = candidate;
+                continue;
+            }
+
+            return Some(candidate);
+        }
+    }
+
+    /// Returns the key that immediately precedes the given key, or the last key if `None` is
+    /// passed.
+    fn prev_key(&self, key: Option<&[u8]>) -> Option<Vec<u8>> {
+        let mut before = key.map(|k| k.to_vec());
+        loop {
+            let upper_bound = match &before {
+                Some(before) => Bound::Excluded(&before[..]),
+                None => Bound::Unbounded,
+            };
+            let in_diff = self
+                .diff
+                .range::<[u8], _>((Bound::Unbounded, upper_bound))
+                .next_back()
+                .map(|(k, _)| k.clone());
+
+            let candidate = match (
+                self.import_queue.storage_prev_key(before.as_deref()),
+                in_diff,
+            ) {
+                (Some(a), Some(b)) => a.max(b),
+                (Some(k), None) | (None, Some(k)) => k,
+                (None, None) => return None,
+            };
+
+            // The candidate might have been removed by the changes.
+            if let Some(None) = self.diff.get(&candidate) {
+                before = Some(candidate);
+                continue;
+            }
+
+            return Some(candidate);
+        }
+    }
+
+    /// Returns the first and last keys that start with the given nibbles, or `None` if there
+    /// isn't any such key.
+    fn first_last_with_prefix(&self, prefix: &[Nibble]) -> Option<(Vec<u8>, Vec<u8>)> {
+        let (lower, upper) = prefix_range(prefix);
+
+        let first = if self.get(&lower).is_some() {
+            lower.clone()
+        } else {
+            self.next_key(&lower)?
+        };
+        if matches!(&upper, Some(upper) if first >= *upper) {
+            return None;
+        }
+
+        // Since `first` is within the range, the range contains at least one key.
+        let last = self.prev_key(upper.as_deref())?;
+        debug_assert!(last >= lower);
+        Some((first, last))
+    }
+
+    /// Returns all the entries whose key starts with the given nibbles, ordered by key.
+    fn entries_with_prefix(&self, prefix: &[Nibble]) -> Vec<(Vec<Nibble>, Vec<u8>)> {
+        let bytes_prefix = nibbles_to_bytes(&prefix[..prefix.len() - prefix.len() % 2]);
+        let (lower, upper) = prefix_range(prefix);
+
+        let mut keys = self.import_queue.storage_prefix_keys(&bytes_prefix);
+        keys.extend(
+            self.diff
+                .range::<[u8], _>((
+                    Bound::Included(&lower[..]),
+                    upper.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+                ))
+                .map(|(k, _)| k.clone()),
+        );
+        keys.sort_unstable();
+        keys.dedup();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let nibbles = trie::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+                if !nibbles.starts_with(prefix) {
+                    return None;
+                }
+                let value = self.get(&key)?;
+                Some((nibbles, value))
+            })
+            .collect()
+    }
+
+    /// Returns `true` if the changes modify an entry whose key starts with the given nibbles.
+    fn is_modified(&self, prefix: &[Nibble]) -> bool {
+        if self.diff.is_empty() {
+            return false;
+        }
+
+        let (lower, upper) = prefix_range(prefix);
+        self.diff
+            .range::<[u8], _>((
+                Bound::Included(&lower[..]),
+                upper.as_deref().map_or(Bound::Unbounded, Bound::Excluded),
+            ))
+            .next()
+            .is_some()
+    }
+}
+
+/// Storage trie of a block, whose nodes are calculated from a [`BlockStorage`].
+struct BlockTrie<'a> {
+    storage: BlockStorage<'a>,
+
+    /// Merkle values of nodes of the storage trie of the latest finalized block of the import
+    /// queue.
+    cache: &'a MerkleValues,
+
+    /// Merkle values calculated while generating the proof that can be added to the cache.
+    new_cache_entries: MerkleValues,
+}
+
+impl<'a> BlockTrie<'a> {
+    /// Generates a proof of the storage values of the given keys. The proof is identical to
+    /// the one that [`trie::proof_encode::build_proof`] generates.
+    fn build_proof<'k>(&mut self, requested_keys: impl Iterator<Item = &'k [u8]>) -> Vec<Vec<u8>> {
+        let root_key = self.node_with_prefix(&[]);
+
+        // Node values of the nodes that have already been calculated, as multiple requested
+        // keys typically share the nodes close to the root.
+        let mut node_values = BTreeMap::<Vec<Nibble>, Vec<u8>>::new();
+        let mut proof = Vec::new();
+
+        for requested_key in requested_keys {
+            let requested_key =
+                trie::bytes_to_nibbles(requested_key.iter().copied()).collect::<Vec<_>>();
+
+            let mut node = match &root_key {
+                Some(root_key) => Some((root_key.clone(), 0)),
+                None => {
+                    proof.push(node_value::calculate_node_value(node_value::Config {
+                        ty: node_value::NodeTy::Root {
+                            key: iter::empty::<Nibble>(),
+                        },
+                        children: (0..16).map(|_| None),
+                        stored_value: None::<&[u8]>,
+                    }));
+                    continue;
+                }
+            };
+
+            while let Some((key, parent_key_len)) = node.take() {
+                let node_value = match node_values.get(&key) {
+                    Some(node_value) => node_value.clone(),
+                    None => {
+                        let (stored_value, children) = self.node_components(&key);
+                        let node_value = node_value::calculate_node_value(node_value::Config {
+                            ty: node_ty(&key, parent_key_len),
+                            children: children.iter().map(|c| c.as_ref()),
+                            stored_value,
+                        });
+                        node_values.insert(key.clone(), node_value.clone());
+                        node_value
+                    }
+                };
+
+                // Node values shorter than 32 bytes are directly included in the node value of
+                // their parent, and thus don't need to be part of the proof.
+                if parent_key_len == 0 || node_value.len() >= 32 {
+                    proof.push(node_value);
+                }
+
+                if requested_key.len() > key.len() && requested_key.starts_with(&key) {
+                    node = self
+                        .node_with_prefix(&requested_key[..=key.len()])
+                        .map(|child| (child, key.len() + 1));
+                }
+            }
+        }
+
+        proof.sort_unstable();
+        proof.dedup();
+        proof
+    }
+
+    /// Returns the full key of the node that is the closest common ancestor of all the keys
+    /// that start with the given nibbles, or `None` if there isn't any such key.
+    fn node_with_prefix(&self, prefix: &[Nibble]) -> Option<Vec<Nibble>> {
+        let (first, last) = self.storage.first_last_with_prefix(prefix)?;
+        Some(
+            trie::bytes_to_nibbles(first.iter().copied())
+                .zip(trie::bytes_to_nibbles(last.iter().copied()))
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a)
+                .collect(),
+        )
+    }
+
+    /// Returns the storage value and the Merkle values of the children of the node with the
+    /// given full key.
+    fn node_components(
+        &mut self,
+        key: &[Nibble],
+    ) -> (Option<Vec<u8>>, Vec<Option<node_value::Output>>) {
+        let stored_value = if key.len() % 2 == 0 {
+            self.storage.get(&nibbles_to_bytes(key))
+        } else {
+            None
+        };
+
+        let children = trie::all_nibbles()
+            .map(|nibble| {
+                let mut child_prefix = key.to_vec();
+                child_prefix.push(nibble);
+                let child_key = self.node_with_prefix(&child_prefix)?;
+                Some(self.merkle_value(&child_key, key.len() + 1))
+            })
+            .collect();
+
+        (stored_value, children)
+    }
+
+    /// Returns the Merkle value of the node with the given full key. `parent_key_len` is the
+    /// number of nibbles of the key that precede the partial key of the node.
+    fn merkle_value(&mut self, key: &[Nibble], parent_key_len: usize) -> node_value::Output {
+        let modified = self.storage.is_modified(key);
+
+        if !modified {
+            if let Some((cached_parent_key_len, merkle_value)) = self.cache.get(key) {
+                if *cached_parent_key_len == parent_key_len {
+                    return merkle_value.clone();
+                }
+            }
+        }
+
+        // If no descendant of the node is in cache, loading all the entries of the subtree at
+        // once is faster than querying the nodes one by one.
+        let has_cached_descendant = matches!(
+            self.cache
+                .range::<[Nibble], _>((Bound::Excluded(key), Bound::Unbounded))
+                .next(),
+            Some((k, _)) if k.starts_with(key)
+        );
+        if !has_cached_descendant {
+            let entries = self.storage.entries_with_prefix(key);
+            return self.calculate_subtree(&entries, parent_key_len);
+        }
+
+        let (stored_value, children) = self.node_components(key);
+        let merkle_value = node_value::calculate_merkle_root(node_value::Config {
+            ty: node_ty(key, parent_key_len),
+            children: children.iter().map(|c| c.as_ref()),
+            stored_value,
+        });
+
+        if !modified {
+            self.new_cache_entries
+                .insert(key.to_vec(), (parent_key_len, merkle_value.clone()));
+        }
+
+        merkle_value
+    }
+
+    /// Calculates the Merkle value of the node that is the closest common ancestor of all the
+    /// entries of `entries`, which must be non-empty and ordered by key. `parent_key_len` is the
+    /// number of nibbles of the key that precede the partial key of the node.
+    fn calculate_subtree(
+        &mut self,
+        entries: &[(Vec<Nibble>, Vec<u8>)],
+        parent_key_len: usize,
+    ) -> node_value::Output {
+        // Since `entries` is ordered, the longest prefix shared by all the keys is the prefix
+        // shared by the first and the last keys.
+        let first_key = &entries[0].0;
+        let key_len = first_key
+            .iter()
+            .zip(entries[entries.len() - 1].0.iter())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let (stored_value, mut children_entries) = if first_key.len() == key_len {
+            (Some(&entries[0].1), &entries[1..])
+        } else {
+            (None, entries)
+        };
+
+        let mut children = (0..16).map(|_| None).collect::<Vec<_>>();
+        while !children_entries.is_empty() {
+            let child_index = usize::from(u8::from(children_entries[0].0[key_len]));
+            let num_entries = children_entries
+                .iter()
+                .take_while(|(key, _)| usize::from(u8::from(key[key_len])) == child_index)
+                .count();
+            let (child_entries, rest) = children_entries.split_at(num_entries);
+            children[child_index] = Some(self.calculate_subtree(child_entries, key_len + 1));
+            children_entries = rest;
+        }
+
+        let key = &first_key[..key_len];
+        let merkle_value = node_value::calculate_merkle_root(node_value::Config {
+            ty: node_ty(key, parent_key_len),
+            children: children.iter().map(|c| c.as_ref()),
+            stored_value,
+        });
+
+        if entries.len() >= MIN_CACHED_NODE_ENTRIES && !self.storage.is_modified(key) {
+            self.new_cache_entries
+                .insert(key.to_vec(), (parent_key_len, merkle_value.clone()));
+        }
+
+        merkle_value
+    }
+}
+
+/// Returns the type of the node with the given full key. `parent_key_len` is the number of
+/// nibbles of the key that precede the partial key of the node.
+fn node_ty(
+    key: &[Nibble],
+    parent_key_len: usize,
+) -> node_value::NodeTy<iter::Copied<core::slice::Iter<'_, Nibble>>> {
+    if parent_key_len == 0 {
+        node_value::NodeTy::Root {
+            key: key.iter().copied(),
+        }
+    } else {
+        node_value::NodeTy::NonRoot {
+            partial_key: key[parent_key_len..].iter().copied(),
+        }
+    }
+}
+
+/// Turns a list of nibbles into bytes. The last byte is padded with a `0` nibble if the number
+/// of nibbles is odd.
+fn nibbles_to_bytes(nibbles: &[Nibble]) -> Vec<u8> {
+    trie::nibbles_to_bytes_extend(nibbles.iter().copied()).collect()
+}
+
+/// Returns the range of the keys that start with the given nibbles, as an inclusive lower bound
+/// and an exclusive upper bound. The upper bound is `None` if the range is unbounded.
+fn prefix_range(prefix: &[Nibble]) -> (Vec<u8>, Option<Vec<u8>>) {
+    let lower = nibbles_to_bytes(prefix);
+    let upper = prefix.iter().rposition(|n| u8::from(*n) != 0xf).map(|pos| {
+        let mut upper = prefix[..=pos].to_vec();
+        upper[pos] = Nibble::try_from(u8::from(upper[pos]) + 1).unwrap();
+        nibbles_to_bytes(&upper)
+    });
+    (lower, upper)
+}
+
+/// Performs a runtime call against the given storage, and returns the list of all the storage
+/// keys whose storage entries must be included in a proof of this call.
+///
+/// Returns `None` if the call couldn't be performed.
+fn call_accessed_keys(
+    storage: &BlockStorage,
+    state_root: &[u8; 32],
+    method: &str,
+    parameter: &[u8],
+) -> Option<Vec<Vec<u8>>> {
+    // The code and heap pages are part of the proof, as the remote needs them in order to
+    // perform the call.
+    let mut accessed_keys = vec![b":code".to_vec(), b":heappages".to_vec()];
+
+    let module = storage.get(b":code")?;
+    let heap_pages =
+        executor::storage_heap_pages_to_value(storage.get(b":heappages").as_deref()).ok()?;
+    let virtual_machine =
+        executor::host::HostVmPrototype::new(&module, heap_pages, executor::vm::ExecHint::Oneshot)
+            .ok()?;
+
+    let mut call = read_only_runtime_host::run(read_only_runtime_host::Config {
+        virtual_machine,
+        function_to_call: method,
+        parameter: iter::once(parameter),
+    })
+    .ok()?;
+
+    loop {
+        match call {
+            read_only_runtime_host::RuntimeHostVm::Finished(Ok(_)) => break,
+            read_only_runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                tracing::debug!(%method, %error, "call-proof-failed");
+                return None;
+            }
+            read_only_runtime_host::RuntimeHostVm::StorageGet(req) => {
+                let key = req.key_as_vec();
+                let value = storage.get(&key);
+                accessed_keys.push(key);
+                call = req.inject_value(value.as_ref().map(iter::once));
+            }
+            read_only_runtime_host::RuntimeHostVm::NextKey(req) => {
+                let key = req.key().as_ref().to_vec();
+                let next_key = storage.next_key(&key);
+                accessed_keys.push(key);
+                accessed_keys.extend(next_key.clone());
+                call = req.inject_key(next_key);
+            }
+            read_only_runtime_host::RuntimeHostVm::StorageRoot(req) => {
+                call = req.resume(state_root);
+            }
+            read_only_runtime_host::RuntimeHostVm::SignatureVerification(req) => {
+                call = req.verify_and_resume();
+            }
+        }
+    }
+
+    Some(accessed_keys)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockStorage, BlockTrie, MerkleValues};
+    use crate::import_queue;
+
+    use smoldot::{chain, chain_spec, database::full_sqlite, trie::proof_encode};
+    use std::{collections::BTreeMap, sync::Arc};
+
+    /// Initializes an [`import_queue::ImportQueue`] whose finalized block is the genesis block
+    /// of the given chain, and returns it along with the genesis storage.
+    fn genesis_import_queue(
+        chain_spec: &[u8],
+    ) -> (Arc<import_queue::ImportQueue>, BTreeMap<Vec<u8>, Vec<u8>>) {
+        let chain_spec = chain_spec::ChainSpec::from_json_bytes(chain_spec).unwrap();
+        let chain_information =
+            chain::chain_information::ChainInformation::from_chain_spec(&chain_spec).unwrap();
+
+        let database = match full_sqlite::open(full_sqlite::Config {
+            ty: full_sqlite::ConfigTy::Memory,
+        })
+        .unwrap()
+        {
+            full_sqlite::DatabaseOpen::Empty(empty) => empty
+                .initialize(
+                    &chain_information,
+                    core::iter::empty(),
+                    None,
+                    chain_spec.genesis_storage(),
+                )
+                .unwrap(),
+            full_sqlite::DatabaseOpen::Open(_) => unreachable!(),
+        };
+
+        let storage = chain_spec
+            .genesis_storage()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect();
+        let (import_queue, _) = import_queue::ImportQueue::new(Arc::new(database));
+        (import_queue, storage)
+    }
+
+    fn check(
+        import_queue: &import_queue::ImportQueue,
+        cache: &mut MerkleValues,
+        diff: &BTreeMap<Vec<u8>, Option<Vec<u8>>>,
+        storage: &BTreeMap<Vec<u8>, Vec<u8>>,
+        requested_keys: &[Vec<u8>],
+    ) {
+        let mut trie = BlockTrie {
+            storage: BlockStorage { import_queue, diff },
+            cache,
+            new_cache_entries: MerkleValues::new(),
+        };
+        let mut proof = trie.build_proof(requested_keys.iter().map(|k| &k[..]));
+        let new_cache_entries = trie.new_cache_entries;
+        cache.extend(new_cache_entries);
+
+        let mut expected = proof_encode::build_proof(proof_encode::Config {
+            entries: storage.iter().map(|(k, v)| (&k[..], &v[..])),
+            requested_keys: requested_keys.iter().map(|k| &k[..]),
+        });
+        proof.sort_unstable();
+        expected.sort_unstable();
+        assert_eq!(proof, expected);
+    }
+
+    #[test]
+    fn matches_full_proof() {
+        let (import_queue, mut storage) =
+            genesis_import_queue(include_bytes!("../../westend.json"));
+
+        let requested_keys = storage
+            .keys()
+            .step_by(7)
+            .cloned()
+            .chain([b"".to_vec(), b"\x26\xaa".to_vec(), b"\xff\xff".to_vec()])
+            .collect::<Vec<_>>();
+
+        // The first proof fills the cache, and the second one uses it.
+        let mut cache = MerkleValues::new();
+        check(
+            &import_queue,
+            &mut cache,
+            &BTreeMap::new(),
+            &storage,
+            &requested_keys,
+        );
+        assert!(!cache.is_empty());
+        check(
+            &import_queue,
+            &mut cache,
+            &BTreeMap::new(),
+            &storage,
+            &requested_keys,
+        );
+
+        // Modify, remove, and insert entries on top of the finalized storage.
+        let mut diff = BTreeMap::new();
+        for (n, key) in storage.keys().step_by(5).enumerate() {
+            diff.insert(
+                key.clone(),
+                if n % 2 == 0 {
+                    None
+                } else {
+                    Some(vec![n as u8; 40])
+                },
+            );
+        }
+        diff.insert(b"\x26\xaa\x39".to_vec(), Some(b"inserted".to_vec()));
+        for (key, value) in &diff {
+            match value {
+                Some(value) => {
+                    storage.insert(key.clone(), value.clone());
+                }
+                None => {
+                    storage.remove(key);
+                }
+            }
+        }
+
+        check(&import_queue, &mut cache, &diff, &storage, &requested_keys);
+    }
+}
@@ -24,18 +24,22 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::network_service;
+use crate::{import_queue, network_service, proof_requests};
 
 use core::{convert::TryFrom as _, num::NonZeroU32, pin::Pin};
 use futures::{channel::mpsc, lock::Mutex, prelude::*};
-use smoldot::{
-    database::full_sqlite,
-    executor, header, libp2p, network,
-    sync::{all, optimistic},
+use smoldot::{database::full_sqlite, executor, header, libp2p, network, sync::all};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::SystemTime,
 };
-use std::{collections::BTreeMap, sync::Arc, time::SystemTime};
 use tracing::Instrument as _;
 
+/// Maximum number of blocks between the latest finalized block and a non-finalized block that
+/// storage proofs and call proofs can be requested against.
+const MAX_PROOF_REQUEST_BLOCK_DEPTH: u64 = 64;
+
 /// Configuration for a [`SyncService`].
 pub struct Config {
     /// Closure that spawns background tasks.
@@ -51,6 +55,19 @@ pub struct Config {
     /// Receiver for events coming from the network, as returned by
     /// [`network_service::NetworkService::new`].
     pub network_events_receiver: mpsc::Receiver<network_service::Event>,
+
+    /// If `true`, Babe secondary slot claims are accepted regardless of the types of slot claims
+    /// allowed by the Babe configuration. See [`all::Config::babe_relaxed_secondary_slots`].
+    pub babe_relaxed_secondary_slots: bool,
+
+    /// Consensus engines, other than Aura, Babe, and GrandPa, whose digest items can be found
+    /// in the headers of the chain. See [`all::Config::custom_consensus_engines`].
+    ///
+    /// The full node has no knowledge of these engines. The `PreRuntime` and `Consensus` items
+    /// are checked by the runtime when the block is executed, but the `Seal` items can't be
+    /// verified, and blocks containing any of them are refused. Headers whose body isn't
+    /// executed are refused if they contain any item of these engines.
+    pub custom_consensus_engines: Vec<[u8; 4]>,
 }
 
 /// Identifier for a blocks request to be performed.
@@ -76,49 +93,59 @@ impl SyncService {
     /// Initializes the [`SyncService`] with the given configuration.
     #[tracing::instrument(skip(config))]
     pub async fn new(mut config: Config) -> Arc<Self> {
-        let (to_database, messages_rx) = mpsc::channel(4);
-
         let finalized_block_hash = config.database.finalized_block_hash().unwrap();
         let best_block_hash = config.database.best_block_hash().unwrap();
 
         let sync_state = Arc::new(Mutex::new(SyncState {
             best_block_hash,
-            best_block_number: header::decode(
+            best_block_number: header::decode_with_custom_engines(
                 &config
                     .database
                     .block_scale_encoded_header(&best_block_hash)
                     .unwrap()
                     .unwrap(),
+                &config.custom_consensus_engines,
             )
             .unwrap()
             .number,
             finalized_block_hash,
-            finalized_block_number: header::decode(
+            finalized_block_number: header::decode_with_custom_engines(
                 &config
                     .database
                     .block_scale_encoded_header(&finalized_block_hash)
                     .unwrap()
                     .unwrap(),
+                &config.custom_consensus_engines,
             )
             .unwrap()
             .number,
         }));
 
+        let (import_queue, commit_task) = import_queue::ImportQueue::new(config.database.clone());
+        let (proof_requests, proofs_task) = proof_requests::ProofRequests::new(
+            import_queue.clone(),
+            config.network_service.0.clone(),
+        );
+
         (config.tasks_executor)(Box::pin(start_sync(
-            config.database.clone(),
+            config.database,
+            import_queue,
+            proof_requests,
             sync_state.clone(),
-            config.network_service.0,
-            config.network_service.1,
+            config.network_service,
             config.network_events_receiver,
-            to_database,
+            config.babe_relaxed_secondary_slots,
+            config.custom_consensus_engines,
         )));
 
         (config.tasks_executor)(Box::pin(
-            start_database_write(config.database, messages_rx).instrument(
-                tracing::debug_span!(parent: None, "database-write", root = ?finalized_block_hash), // TDOO: better display
-            ),
+            proofs_task.instrument(tracing::debug_span!(parent: None, "proof-requests")),
         ));
 
+        (config.tasks_executor)(Box::pin(commit_task.instrument(
+            tracing::debug_span!(parent: None, "database-write", root = ?finalized_block_hash), // TDOO: better display
+        )));
+
         Arc::new(SyncService { sync_state })
     }
 
@@ -132,36 +159,29 @@ impl SyncService {
     }
 }
 
-enum ToDatabase {
-    FinalizedBlocks(Vec<optimistic::Block<()>>),
-}
-
 /// Returns the background task of the sync service.
 #[tracing::instrument(skip(
     database,
+    import_queue,
+    proof_requests,
     sync_state,
     network_service,
-    from_network_service,
-    to_database
+    from_network_service
 ))]
 fn start_sync(
     database: Arc<full_sqlite::SqliteFullDatabase>,
+    import_queue: Arc<import_queue::ImportQueue>,
+    mut proof_requests: proof_requests::ProofRequests,
     sync_state: Arc<Mutex<SyncState>>,
-    network_service: Arc<network_service::NetworkService>,
-    network_chain_index: usize,
+    (network_service, network_chain_index): (Arc<network_service::NetworkService>, usize),
     mut from_network_service: mpsc::Receiver<network_service::Event>,
-    mut to_database: mpsc::Sender<ToDatabase>,
+    babe_relaxed_secondary_slots: bool,
+    custom_consensus_engines: Vec<[u8; 4]>,
 ) -> impl Future<Output = ()> {
-    // Holds, in parallel of the database, the storage of the latest finalized block.
-    // At the time of writing, this state is stable around ~3MiB for Polkadot, meaning that it is
-    // completely acceptable to hold it entirely in memory.
-    // While reading the storage from the database is an option, doing so considerably slows down
-    // the verification, and also makes it impossible to insert blocks in the database in
-    // parallel of this verification.
-    let mut finalized_block_storage: BTreeMap<Vec<u8>, Vec<u8>> = database
-        .finalized_block_storage_top_trie(&database.finalized_block_hash().unwrap())
-        .unwrap();
-
+    // Blocks are executed against the storage of the latest finalized block, which is read from
+    // the database through the import queue. The import queue also takes into account the
+    // finalized blocks that haven't been committed to the database yet, which makes it possible
+    // to continue the verification in parallel of the database writes.
     let mut sync = all::AllSync::<(), libp2p::PeerId, ()>::new(all::Config {
         chain_information: database
             .to_chain_information(&database.finalized_block_hash().unwrap())
@@ -172,6 +192,7 @@ fn start_sync(
             1024
         },
         source_selection_randomness_seed: rand::random(),
+        randomness_seed: rand::random(),
         blocks_request_granularity: NonZeroU32::new(128).unwrap(),
         download_ahead_blocks: {
             // Assuming a verification speed of 1k blocks/sec and a 95% latency of one second,
@@ -183,21 +204,22 @@ fn start_sync(
                 // Builds the runtime of the finalized block.
                 // Assumed to always be valid, otherwise the block wouldn't have been saved in the
                 // database, hence the large number of unwraps here.
-                let module = finalized_block_storage.get(&b":code"[..]).unwrap();
+                let module = import_queue.storage_get(b":code").unwrap();
                 let heap_pages = executor::storage_heap_pages_to_value(
-                    finalized_block_storage
-                        .get(&b":heappages"[..])
-                        .map(|v| &v[..]),
+                    import_queue.storage_get(b":heappages").as_deref(),
                 )
                 .unwrap();
                 executor::host::HostVmPrototype::new(
-                    module,
+                    &module,
                     heap_pages,
                     executor::vm::ExecHint::CompileAheadOfTime, // TODO: probably should be decided by the optimisticsync
                 )
                 .unwrap()
             },
         }),
+        custom_consensus_engines,
+        babe_relaxed_secondary_slots,
+        sr25519_hooks: None,
     });
 
     async move {
@@ -261,7 +283,9 @@ fn start_sync(
                                     header: request_headers,
                                     body: request_bodies,
                                     justification: request_justification,
+                                    indexed_body: false,
                                 },
+                                accept_compressed_response: false,
                             },
                         );
 
@@ -304,7 +328,16 @@ fn start_sync(
                     }
                     all::ProcessOne::VerifyWarpSyncFragment(_) => unreachable!(),
                     all::ProcessOne::VerifyHeaderBody(verify) => {
-                        let mut verify = verify.start(unix_time, ());
+                        // See the documentation of `Config::custom_consensus_engines`.
+                        let has_custom_seal = verify
+                            .custom_digest_items()
+                            .iter()
+                            .any(|item| item.kind == header::CustomDigestItemKind::Seal);
+                        let mut verify = if has_custom_seal {
+                            verify.reject(())
+                        } else {
+                            verify.start(unix_time, ())
+                        };
                         loop {
                             match verify {
                                 all::BlockVerification::Error {
@@ -323,40 +356,15 @@ fn start_sync(
                                     next_actions,
                                     finalized_blocks,
                                 } => {
-                                    // TODO: restore this code
-                                    // Processing has made a step forward.
-                                    // There is nothing to do, but this is used to update to best block
-                                    // shown on the informant.
-                                    /*let mut lock = sync_state.lock().await;
-                                    lock.best_block_hash = new_best_hash;
-                                    lock.best_block_number = new_best_number;
-                                    drop(lock);
-
+                                    // Used to update the finalized block shown on the
+                                    // informant. The best block is updated below.
                                     if let Some(last_finalized) = finalized_blocks.last() {
                                         let mut lock = sync_state.lock().await;
                                         lock.finalized_block_hash = last_finalized.header.hash();
                                         lock.finalized_block_number = last_finalized.header.number;
-                                    }*/
-
-                                    // TODO: maybe write in a separate task? but then we can't access the finalized storage immediately after?
-                                    for block in &finalized_blocks {
-                                        for (key, value) in &block.storage_top_trie_changes {
-                                            if let Some(value) = value {
-                                                finalized_block_storage
-                                                    .insert(key.clone(), value.clone());
-                                            } else {
-                                                let _was_there =
-                                                    finalized_block_storage.remove(key);
-                                                // TODO: if a block inserts a new value, then removes it in the next block, the key will remain in `finalized_block_storage`; either solve this or document this
-                                                // assert!(_was_there.is_some());
-                                            }
-                                        }
                                     }
 
-                                    to_database
-                                        .send(ToDatabase::FinalizedBlocks(finalized_blocks))
-                                        .await
-                                        .unwrap();
+                                    import_queue.push(finalized_blocks).await;
 
                                     requests_to_start.extend(next_actions);
                                     sync = sync_out;
@@ -384,36 +392,31 @@ fn start_sync(
                                 }
 
                                 all::BlockVerification::FinalizedStorageGet(req) => {
-                                    let value = finalized_block_storage
-                                        .get(&req.key_as_vec())
-                                        .map(|v| &v[..]);
-                                    verify = req.inject_value(value);
+                                    let value = import_queue.storage_get(&req.key_as_vec());
+                                    verify = req.inject_value(value.as_deref());
                                 }
                                 all::BlockVerification::FinalizedStorageNextKey(req) => {
-                                    // TODO: to_vec() :-/
-                                    let req_key = req.key().as_ref().to_vec();
-                                    // TODO: to_vec() :-/
-                                    let next_key = finalized_block_storage
-                                        .range(req.key().as_ref().to_vec()..)
-                                        .find(move |(k, _)| k[..] > req_key[..])
-                                        .map(|(k, _)| k);
+                                    let next_key =
+                                        import_queue.storage_next_key(req.key().as_ref());
                                     verify = req.inject_key(next_key);
                                 }
                                 all::BlockVerification::FinalizedStoragePrefixKeys(req) => {
-                                    // TODO: to_vec() :-/
-                                    let prefix = req.prefix().as_ref().to_vec();
-                                    // TODO: to_vec() :-/
-                                    let keys = finalized_block_storage
-                                        .range(req.prefix().as_ref().to_vec()..)
-                                        .take_while(|(k, _)| k.starts_with(&prefix))
-                                        .map(|(k, _)| k);
-                                    verify = req.inject_keys(keys);
+                                    let keys =
+                                        import_queue.storage_prefix_keys(req.prefix().as_ref());
+                                    verify = req.inject_keys(keys.iter());
                                 }
                             }
                         }
                     }
                     all::ProcessOne::VerifyHeader(verify) => {
-                        match verify.perform(unix_time, ()) {
+                        // See the documentation of `Config::custom_consensus_engines`.
+                        let outcome = if verify.custom_digest_items().is_empty() {
+                            verify.perform(unix_time, ())
+                        } else {
+                            verify.reject(())
+                        };
+
+                        match outcome {
                             all::HeaderVerifyOutcome::Success {
                                 sync: sync_out,
                                 next_actions,
@@ -488,6 +491,49 @@ fn start_sync(
                                 },
                             }
                         },
+                        network_service::Event::StorageProofRequest { chain_index, peer_id, request_id, request }
+                            if chain_index == network_chain_index =>
+                        {
+                            // The proof is generated by a separate task, in order to not slow
+                            // down the synchronization.
+                            let queued = match block_storage_diff(&sync, &request.block_hash) {
+                                Some((storage_diff, _)) => proof_requests.queue(
+                                    peer_id.clone(),
+                                    request_id,
+                                    proof_requests::Request::StorageProof {
+                                        request,
+                                        storage_diff,
+                                    },
+                                ).is_ok(),
+                                None => false,
+                            };
+
+                            if !queued {
+                                tracing::debug!(%peer_id, success = false, "storage-proof-response");
+                                network_service.respond_storage_proof(request_id, None).await;
+                            }
+                        },
+                        network_service::Event::CallProofRequest { chain_index, peer_id, request_id, request }
+                            if chain_index == network_chain_index =>
+                        {
+                            let queued = match block_storage_diff(&sync, &request.block_hash) {
+                                Some((storage_diff, state_root)) => proof_requests.queue(
+                                    peer_id.clone(),
+                                    request_id,
+                                    proof_requests::Request::CallProof {
+                                        request,
+                                        state_root,
+                                        storage_diff,
+                                    },
+                                ).is_ok(),
+                                None => false,
+                            };
+
+                            if !queued {
+                                tracing::debug!(%peer_id, success = false, "call-proof-response");
+                                network_service.respond_call_proof(request_id, None).await;
+                            }
+                        },
                         _ => {
                             // Different chain index.
                         }
@@ -524,51 +570,58 @@ fn start_sync(
     }
 }
 
-/// Starts the task that writes blocks to the database.
-#[tracing::instrument(skip(database, messages_rx))]
-async fn start_database_write(
-    database: Arc<full_sqlite::SqliteFullDatabase>,
-    mut messages_rx: mpsc::Receiver<ToDatabase>,
-) {
-    loop {
-        match messages_rx.next().await {
-            None => break,
-            Some(ToDatabase::FinalizedBlocks(finalized_blocks)) => {
-                let span = tracing::trace_span!("blocks-db-write", len = finalized_blocks.len());
-                let _enter = span.enter();
-
-                let new_finalized_hash = if let Some(last_finalized) = finalized_blocks.last() {
-                    Some(last_finalized.header.hash())
-                } else {
-                    None
-                };
-
-                for block in finalized_blocks {
-                    // TODO: overhead for building the SCALE encoding of the header
-                    let result = database.insert(
-                        &block.header.scale_encoding().fold(Vec::new(), |mut a, b| {
-                            a.extend_from_slice(b.as_ref());
-                            a
-                        }),
-                        true, // TODO: is_new_best?
-                        block.body.iter(),
-                        block
-                            .storage_top_trie_changes
-                            .iter()
-                            .map(|(k, v)| (k, v.as_ref())),
-                    );
+/// Returns the storage of the block with the given hash, relative to the latest finalized block,
+/// and the state root of the block.
+///
+/// Returns `None` if the block isn't the latest finalized block nor a known non-finalized block
+/// at most [`MAX_PROOF_REQUEST_BLOCK_DEPTH`] blocks away from it.
+fn block_storage_diff(
+    sync: &all::AllSync<(), libp2p::PeerId, ()>,
+    block_hash: &[u8; 32],
+) -> Option<(proof_requests::BlockStorageDiff, [u8; 32])> {
+    let finalized_header = sync.finalized_block_header();
+    let finalized_block_hash = finalized_header.hash();
+
+    let mut storage_diff = proof_requests::BlockStorageDiff {
+        finalized_block_hash,
+        changes: BTreeMap::new(),
+    };
+
+    if *block_hash == finalized_block_hash {
+        return Some((storage_diff, *finalized_header.state_root));
+    }
 
-                    match result {
-                        Ok(()) => {}
-                        Err(full_sqlite::InsertError::Duplicate) => {} // TODO: this should be an error ; right now we silence them because non-finalized blocks aren't loaded from the database at startup, resulting in them being downloaded again
-                        Err(err) => panic!("{}", err),
-                    }
-                }
+    let non_finalized_blocks = sync
+        .non_finalized_blocks()
+        .map(|header| (header.hash(), header))
+        .collect::<HashMap<_, _>>();
 
-                if let Some(new_finalized_hash) = new_finalized_hash {
-                    database.set_finalized(&new_finalized_hash).unwrap();
-                }
-            }
+    let header = non_finalized_blocks.get(block_hash)?;
+    if header.number > finalized_header.number + MAX_PROOF_REQUEST_BLOCK_DEPTH {
+        return None;
+    }
+    let state_root = *header.state_root;
+
+    // Hashes of the blocks between the finalized block (excluded) and the requested block
+    // (included), from the requested block to the finalized block.
+    let mut ancestry = vec![*block_hash];
+    loop {
+        let parent_hash = *non_finalized_blocks
+            .get(ancestry.last().unwrap())?
+            .parent_hash;
+        if parent_hash == finalized_block_hash {
+            break;
         }
+        ancestry.push(parent_hash);
     }
+
+    for hash in ancestry.iter().rev() {
+        for (key, value) in sync.non_finalized_block_storage_top_trie_changes(hash)? {
+            storage_diff
+                .changes
+                .insert(key.to_vec(), value.map(|v| v.to_vec()));
+        }
+    }
+
+    Some((storage_diff, state_root))
 }
@@ -30,6 +30,10 @@ export default (config) => {
     // The indices within this array are chosen by the Rust code.
     let connections = {};
 
+    // Used below to accumulate the chunks of responses emitted with `json_rpc_respond_chunk`.
+    // Keys are a combination of the chain index and user data of the request.
+    let jsonRpcPendingChunks = {};
+
     const bindings = {
         // Must throw an error. A human-readable message can be found in the WebAssembly memory in
         // the given buffer.
@@ -46,6 +50,41 @@ export default (config) => {
             }
         },
 
+        // Used by the Rust side to report an event about the peers of a chain. The event is a
+        // JSON-encoded object.
+        peer_event: (ptr, len, chainIndex) => {
+            if (config.peerEventCallback) {
+                let event = Buffer.from(config.instance.exports.memory.buffer).toString('utf8', ptr, ptr + len);
+                config.peerEventCallback(JSON.parse(event), chainIndex);
+            }
+        },
+
+        // Used by the Rust side to report a checkpoint of a chain, in the same format as the
+        // database content.
+        checkpoint: (ptr, len, chainIndex) => {
+            if (config.checkpointCallback) {
+                let checkpoint = Buffer.from(config.instance.exports.memory.buffer).toString('utf8', ptr, ptr + len);
+                config.checkpointCallback(checkpoint, chainIndex);
+            }
+        },
+
+        // Used by the Rust side to emit a chunk of a JSON-RPC response. The response is the
+        // concatenation of all the chunks up to and including the one where `isFinal` is non-zero.
+        json_rpc_respond_chunk: (ptr, len, chainIndex, userData, isFinal) => {
+            const key = chainIndex + '/' + userData;
+            const chunk = Buffer.from(config.instance.exports.memory.buffer).toString('utf8', ptr, ptr + len);
+            if (!jsonRpcPendingChunks[key])
+                jsonRpcPendingChunks[key] = [];
+            jsonRpcPendingChunks[key].push(chunk);
+            if (isFinal == 0)
+                return;
+            const message = jsonRpcPendingChunks[key].join('');
+            delete jsonRpcPendingChunks[key];
+            if (config.jsonRpcCallback) {
+                config.jsonRpcCallback(message, chainIndex, userData);
+            }
+        },
+
         // Used by the Rust side to emit a log entry.
         // See also the `max_log_level` parameter in the configuration.
         log: (level, target_ptr, target_len, message_ptr, message_len) => {
@@ -214,10 +253,193 @@ export default (config) => {
                 // TCP
                 connection.write(data);
             }
-        }
+        },
+
+        // Must perform an HTTP GET request, then call `http_fetch_finished` with the body of the
+        // response or with an error message. Only ever called if a DNS-over-HTTPS server has
+        // been passed to `init`.
+        http_fetch: (id, url_ptr, url_len, accept_ptr, accept_len) => {
+            const mem = Buffer.from(config.instance.exports.memory.buffer);
+            const url = mem.toString('utf8', url_ptr, url_ptr + url_len);
+            const accept = mem.toString('utf8', accept_ptr, accept_ptr + accept_len);
+
+            const finish = (success, data) => {
+                const ptr = config.instance.exports.alloc(data.length);
+                data.copy(Buffer.from(config.instance.exports.memory.buffer), ptr);
+                config.instance.exports.http_fetch_finished(id, success ? 1 : 0, ptr, data.length);
+            };
+
+            // `fetch` will be missing in old versions of NodeJS.
+            if (typeof fetch === 'undefined') {
+                // Report the error asynchronously, like the other outcomes.
+                setTimeout(() => finish(false, Buffer.from('fetch not available', 'utf8')), 0);
+                return;
+            }
+
+            fetch(url, { headers: { 'Accept': accept } })
+                .then((response) => {
+                    if (!response.ok)
+                        throw new Error('HTTP status ' + response.status);
+                    return response.arrayBuffer();
+                })
+                .then((body) => finish(true, Buffer.from(body)))
+                .catch((error) => finish(false, Buffer.from(error.toString(), 'utf8')));
+        },
+
+        // Must obtain the runtime code whose blake2b hash is found at `hash_ptr`, then call
+        // `code_substitute_fetch_finished` with the code or with an error message. Only ever
+        // called if a chain specification contains a code substitute of which only the hash
+        // is known.
+        code_substitute_fetch: (id, hash_ptr) => {
+            const mem = Buffer.from(config.instance.exports.memory.buffer);
+            const hash = '0x' + mem.toString('hex', hash_ptr, hash_ptr + 32);
+
+            const finish = (success, data) => {
+                const ptr = config.instance.exports.alloc(data.length);
+                data.copy(Buffer.from(config.instance.exports.memory.buffer), ptr);
+                config.instance.exports.code_substitute_fetch_finished(id, success ? 1 : 0, ptr, data.length);
+            };
+
+            // Each entry is either the code itself or a URL where to download it from.
+            const substitute = (config.codeSubstitutes || {})[hash];
+            if (substitute instanceof Uint8Array) {
+                setTimeout(() => finish(true, Buffer.from(substitute)), 0);
+            } else if (typeof substitute === 'string' && typeof fetch !== 'undefined') {
+                fetch(substitute)
+                    .then((response) => {
+                        if (!response.ok)
+                            throw new Error('HTTP status ' + response.status);
+                        return response.arrayBuffer();
+                    })
+                    .then((body) => finish(true, Buffer.from(body)))
+                    .catch((error) => finish(false, Buffer.from(error.toString(), 'utf8')));
+            } else {
+                // Report the error asynchronously, like the other outcomes.
+                setTimeout(() => finish(false, Buffer.from('code substitute ' + hash + ' not available', 'utf8')), 0);
+            }
+        },
+
+        // Must verify an sr25519 signature and return 1 if it is valid. Only ever called if
+        // `config.hostCrypto.sr25519Verify` is defined.
+        host_sr25519_verify: (signature_ptr, message_ptr, message_len, public_key_ptr) => {
+            const mem = Buffer.from(config.instance.exports.memory.buffer);
+            const signature = mem.slice(signature_ptr, signature_ptr + 64);
+            const message = mem.slice(message_ptr, message_ptr + message_len);
+            const publicKey = mem.slice(public_key_ptr, public_key_ptr + 32);
+            return config.hostCrypto.sr25519Verify(signature, message, publicKey) ? 1 : 0;
+        },
+
+        // Same as `host_sr25519_verify`, but for the deprecated variant of sr25519. Only ever
+        // called if `config.hostCrypto.sr25519VerifyDeprecated` is defined.
+        host_sr25519_verify_deprecated: (signature_ptr, message_ptr, message_len, public_key_ptr) => {
+            const mem = Buffer.from(config.instance.exports.memory.buffer);
+            const signature = mem.slice(signature_ptr, signature_ptr + 64);
+            const message = mem.slice(message_ptr, message_ptr + message_len);
+            const publicKey = mem.slice(public_key_ptr, public_key_ptr + 32);
+            return config.hostCrypto.sr25519VerifyDeprecated(signature, message, publicKey) ? 1 : 0;
+        },
+
+        // Must verify the VRF proof of a Babe header and, if it is valid, write the 16 bytes
+        // generated from the VRF at `out_ptr` and return 1. Only ever called if
+        // `config.hostCrypto.babeVrfVerify` is defined.
+        host_babe_vrf_verify: (public_key_ptr, transcript_ptr, vrf_output_ptr, vrf_proof_ptr, out_ptr) => {
+            const mem = Buffer.from(config.instance.exports.memory.buffer);
+            const publicKey = mem.slice(public_key_ptr, public_key_ptr + 32);
+            const slotNumber = mem.readBigUInt64LE(transcript_ptr);
+            const epochIndex = mem.readBigUInt64LE(transcript_ptr + 8);
+            const randomness = mem.slice(transcript_ptr + 16, transcript_ptr + 48);
+            const vrfOutput = mem.slice(vrf_output_ptr, vrf_output_ptr + 32);
+            const vrfProof = mem.slice(vrf_proof_ptr, vrf_proof_ptr + 64);
+            const result = config.hostCrypto.babeVrfVerify(publicKey, slotNumber, epochIndex, randomness, vrfOutput, vrfProof);
+            if (!result)
+                return 0;
+            const bytes = Buffer.from(result);
+            if (bytes.length != 16)
+                throw new Error('babeVrfVerify must return 16 bytes');
+            bytes.copy(Buffer.from(config.instance.exports.memory.buffer), out_ptr);
+            return 1;
+        },
+
+        // Must write the 32 bytes blake2b hash of the given data at `out_ptr`. Only ever called
+        // if `config.hostCrypto.blake2b256` is defined.
+        host_blake2_256: (data_ptr, data_len, out_ptr) => {
+            const mem = Buffer.from(config.instance.exports.memory.buffer);
+            const hash = Buffer.from(config.hostCrypto.blake2b256(mem.slice(data_ptr, data_ptr + data_len)));
+            if (hash.length != 32)
+                throw new Error('blake2b256 must return 32 bytes');
+            hash.copy(Buffer.from(config.instance.exports.memory.buffer), out_ptr);
+        },
+
+        // Must decompress the given zstandard frames and, on success, write the pointer and
+        // length of a buffer allocated with `alloc` containing the decompressed data at
+        // `out_ptr`, then return 1. Only ever called if `config.hostDecompression.zstdDecompress`
+        // is defined.
+        host_zstd_decompress: (data_ptr, data_len, max_size, out_ptr) => {
+            const mem = Buffer.from(config.instance.exports.memory.buffer);
+            const result = config.hostDecompression.zstdDecompress(mem.slice(data_ptr, data_ptr + data_len), max_size);
+            if (!result)
+                return 0;
+            const decompressed = Buffer.from(result);
+            if (decompressed.length > max_size)
+                return 0;
+            // Note that `alloc` can grow the memory, in which case `mem` is invalidated.
+            const ptr = config.instance.exports.alloc(decompressed.length);
+            const newMem = Buffer.from(config.instance.exports.memory.buffer);
+            decompressed.copy(newMem, ptr);
+            newMem.writeUInt32LE(ptr, out_ptr);
+            newMem.writeUInt32LE(decompressed.length, out_ptr + 4);
+            return 1;
+        },
+
+        // Must verify the digest items of custom consensus engines found in a header and return
+        // 1 if they are all valid. Only ever called for chains that have custom consensus
+        // engines, in which case `config.customDigestItemsVerifier` is always defined.
+        verify_custom_digest_items: (chain_index, items_ptr, items_len) => {
+            const mem = Buffer.from(config.instance.exports.memory.buffer);
+            const items = JSON.parse(mem.toString('utf8', items_ptr, items_ptr + items_len))
+                .map((item) => ({
+                    kind: item.kind,
+                    engineId: item.engineId,
+                    data: Uint8Array.from(Buffer.from(item.data.slice(2), 'hex')),
+                }));
+            return config.customDigestItemsVerifier.verifyCustomDigestItems(chain_index, items) ? 1 : 0;
+        },
     };
 
+    // Flags to pass to `init` indicating which host-accelerated cryptographic functions are
+    // available. Must match the `HOST_CRYPTO_*` constants of the Rust code.
+    let hostCryptoFlags = 0;
+    if (config.hostCrypto && config.hostCrypto.sr25519Verify)
+        hostCryptoFlags |= 1;
+    if (config.hostCrypto && config.hostCrypto.blake2b256)
+        hostCryptoFlags |= 2;
+    if (config.hostCrypto && config.hostCrypto.sr25519VerifyDeprecated)
+        hostCryptoFlags |= 4;
+    if (config.hostCrypto && config.hostCrypto.babeVrfVerify)
+        hostCryptoFlags |= 8;
+
+    // Flags to pass to `init` related to the compression of the responses of peers. Must match
+    // the `COMPRESSION_*` constants of the Rust code.
+    let compressionFlags = 0;
+    if (config.requestCompressedResponses)
+        compressionFlags |= 1;
+    if (config.hostDecompression && config.hostDecompression.zstdDecompress)
+        compressionFlags |= 2;
+
+    // Flags to pass to `init` indicating which transports `connection_new` is able to open.
+    // Must match the `TRANSPORT_*` constants of the Rust code.
+    let supportedTransports = 0;
+    if (net && !config.forbidTcp)
+        supportedTransports |= 1;
+    if (!config.forbidWs)
+        supportedTransports |= 2;
+    if (!config.forbidWss)
+        supportedTransports |= 4;
+
     return {
         bindings,
+        hostCryptoFlags,
+        compressionFlags,
+        supportedTransports,
     }
 }
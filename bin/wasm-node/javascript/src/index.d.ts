@@ -22,20 +22,193 @@ declare class SmoldotError extends Error {
 export interface SmoldotClient {
   sendJsonRpc(rpc: string, chainIndex: number, userData?: number): void;
   cancelAll(userData: number): void;
+  detachAll(userData: number, token: number): void;
+  reattach(token: number, userData: number): void;
+  requestCheckpoint(chainIndex: number): void;
   terminate(): void;
 }
 
 export type SmoldotJsonRpcCallback = (response: string, chainIndex: number, userData?: number) => void;
 export type SmoldotLogCallback = (level: number, target: string, message: string) => void;
+export type SmoldotPeerEventCallback = (event: SmoldotPeerEvent, chainIndex: number) => void;
+export type SmoldotPeerEventV2Callback = (event: SmoldotPeerEventV2, chainIndex: number) => void;
+export type SmoldotCheckpointCallback = (checkpoint: string, chainIndex: number) => void;
+
+/**
+ * Format of the peer events when `peerEventsVersion` is `1`.
+ *
+ * @deprecated Use `peerEventsVersion: 2` and {@link SmoldotPeerEventV2} instead.
+ */
+export type SmoldotPeerEvent =
+  { kind: 'connected', peerId: string, role: 'full' | 'light' | 'authority', bestNumber: number, bestHash: string } |
+  { kind: 'disconnected', peerId: string, reason: 'connection-closed' | 'chain-substream-closed' } |
+  { kind: 'best-block', peerId: string, bestNumber: number, bestHash: string } |
+  { kind: 'genesis-mismatch', peerId: string, genesisHash: string, numMismatches: number } |
+  { kind: 'chain-spec-mismatch', genesisHash: string } |
+  {
+    kind: 'state-root-mismatch', peerId: string, proofPeerId: string, blockHash: string,
+    expectedStateRoot: string, reportedStateRoot: string
+  };
+
+export interface SmoldotPeer {
+  peerId: string;
+}
+
+export interface SmoldotBlock {
+  number: number;
+  hash: string;
+}
+
+/**
+ * Format of the peer events when `peerEventsVersion` is `2`.
+ */
+export type SmoldotPeerEventV2 =
+  { version: 2, kind: 'peer-connected', peer: SmoldotPeer & { role: 'full' | 'light' | 'authority' }, bestBlock: SmoldotBlock } |
+  { version: 2, kind: 'peer-disconnected', peer: SmoldotPeer, reason: 'connection-closed' | 'chain-substream-closed' } |
+  { version: 2, kind: 'peer-best-block', peer: SmoldotPeer, bestBlock: SmoldotBlock } |
+  { version: 2, kind: 'peer-genesis-mismatch', peer: SmoldotPeer, genesisHash: string, numMismatches: number } |
+  { version: 2, kind: 'chain-spec-mismatch', genesisHash: string } |
+  {
+    version: 2, kind: 'state-root-mismatch', block: SmoldotBlock,
+    header: { peer: SmoldotPeer, hash: string, stateRoot: string }, proof: { peer: SmoldotPeer, stateRoot: string }
+  };
+
+export interface SmoldotJsonRpcMethodsFilter {
+  allow?: string[];
+  deny?: string[];
+  allowUnsafe?: boolean;
+}
+
+export interface SmoldotCrossValidation {
+  address: string;
+  methods: string[];
+}
+
+export type SmoldotSyncMode = 'headers' | 'headersAndJustifications' | { recentBodies: number };
+
+export interface SmoldotPrivacyOptions {
+  refuseIdentify?: boolean;
+  hideBestBlock?: boolean;
+  peerIdRotationInterval?: number;
+  maxRequestJitter?: number;
+}
+
+export interface SmoldotP2pProtocol {
+  name: string;
+  maxRequestSize?: number;
+  maxResponseSize?: number;
+  timeout?: number;
+}
 
 export interface SmoldotOptions {
   maxLogLevel?: number;
   chainSpecs: string[];
+  jsonRpcMethodsFilters?: (SmoldotJsonRpcMethodsFilter | undefined)[];
+  chainCpuWeights?: (number | undefined)[];
+  chainSyncModes?: (SmoldotSyncMode | undefined)[];
+  chainLazyStart?: (boolean | undefined)[];
+  chainLazyIdleTimeout?: (number | undefined)[];
+  chainIsolatedNetwork?: (boolean | undefined)[];
+  chainCrossValidation?: (SmoldotCrossValidation | undefined)[];
+  chainRpcFallback?: (string | undefined)[];
+  chainStoragePrefetch?: (number | undefined)[];
+  chainQuorumSize?: (number | undefined)[];
+  chainValidateTransactions?: (boolean | undefined)[];
+  chainCustomConsensusEngines?: (string[] | undefined)[];
+  chainMaxRuntimeMemory?: (number | undefined)[];
   jsonRpcCallback?: SmoldotJsonRpcCallback;
   logCallback?: SmoldotLogCallback;
+  peerEventCallback?: SmoldotPeerEventCallback | SmoldotPeerEventV2Callback;
+  peerEventsVersion?: 1 | 2;
+  checkpointCallback?: SmoldotCheckpointCallback;
   forbidTcp?: boolean;
   forbidWs?: boolean;
   forbidWss?: boolean;
+  forbidRelays?: boolean;
+  requestCompressedResponses?: boolean;
+  maxRuntimeMemory?: number;
+  dnsOverHttpsUrl?: string;
+  unstableP2pRequests?: boolean;
+  unstableP2pProtocols?: SmoldotP2pProtocol[];
+  jsonRpcMaxConcurrentRequests?: number;
+  jsonRpcMaxQueuedRequests?: number;
+  jsonRpcMaxRequestsPerSecond?: number;
+  dialStrategy?: 'parallel' | 'staggered';
+  dialDelay?: number;
+  dialTimeout?: number;
+  peersTarget?: number;
+  maxOutboundMessageSize?: number;
+  maxInboundMessageSize?: number;
+  privacy?: SmoldotPrivacyOptions;
+  networkKey?: Uint8Array;
+  codeSubstitutes?: { [hash: string]: Uint8Array | string };
+  /**
+   * URL of a JavaScript module exporting host-accelerated cryptographic functions. See
+   * {@link SmoldotHostCrypto}. The module is imported from within the worker that runs the
+   * client, as functions can't be sent to a worker.
+   */
+  hostCryptoModule?: string;
+  /**
+   * URL of a JavaScript module exporting the verifier of the digest items of the consensus
+   * engines passed in `chainCustomConsensusEngines`. See {@link SmoldotCustomDigestItemsVerifier}.
+   * Mandatory if any chain has custom consensus engines.
+   */
+  customDigestItemsVerifierModule?: string;
+  /**
+   * URL of a JavaScript module exporting a zstandard decompression function. See
+   * {@link SmoldotHostDecompression}. If present, the responses that peers have compressed
+   * following `requestCompressedResponses` are decompressed by this function rather than within
+   * the client. Imported from within the worker, like `hostCryptoModule`.
+   */
+  hostDecompressionModule?: string;
+}
+
+/**
+ * Exports of the module whose URL is passed as `hostCryptoModule`. All functions are optional.
+ *
+ * `sr25519Verify` verifies signatures with the signing context `substrate`, and
+ * `sr25519VerifyDeprecated` does the same with the deprecated "pre-audit" variant of sr25519.
+ * `babeVrfVerify` verifies the VRF proof of a Babe header, whose input is a transcript labelled
+ * `BABE` containing the slot number, epoch index, and randomness, and returns either the 16
+ * bytes generated from the VRF with the context `substrate-babe-vrf`, or `null` if the proof is
+ * invalid.
+ */
+export interface SmoldotHostCrypto {
+  sr25519Verify?: (signature: Uint8Array, message: Uint8Array, publicKey: Uint8Array) => boolean;
+  sr25519VerifyDeprecated?: (signature: Uint8Array, message: Uint8Array, publicKey: Uint8Array) => boolean;
+  babeVrfVerify?: (
+    publicKey: Uint8Array, slotNumber: bigint, epochIndex: bigint, randomness: Uint8Array,
+    vrfOutput: Uint8Array, vrfProof: Uint8Array
+  ) => Uint8Array | null;
+  blake2b256?: (data: Uint8Array) => Uint8Array;
+}
+
+/**
+ * Exports of the module whose URL is passed as `hostDecompressionModule`.
+ *
+ * `zstdDecompress` decompresses zstandard frames, and must return `null` if the data is invalid
+ * or decompresses to more than `maxSize` bytes. As the data comes from other nodes, the
+ * decompression should be interrupted as soon as this size is reached.
+ */
+export interface SmoldotHostDecompression {
+  zstdDecompress: (data: Uint8Array, maxSize: number) => Uint8Array | null;
+}
+
+/**
+ * Digest item of a custom consensus engine found in a header.
+ */
+export interface SmoldotCustomDigestItem {
+  kind: 'preRuntime' | 'consensus' | 'seal';
+  engineId: string;
+  data: Uint8Array;
+}
+
+/**
+ * Exports of the module whose URL is passed as `customDigestItemsVerifierModule`. Must return
+ * `false` if any of the items is invalid, in which case the header is refused.
+ */
+export interface SmoldotCustomDigestItemsVerifier {
+  verifyCustomDigestItems: (chainIndex: number, items: SmoldotCustomDigestItem[]) => boolean;
 }
 
 export interface Smoldot {
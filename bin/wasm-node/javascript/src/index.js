@@ -26,6 +26,11 @@ export class SmoldotError extends Error {
 export async function start(config) {
   if (!Array.isArray(config.chainSpecs))
     throw new SmoldotError('config must include a field `chainSpecs` of type Array');
+  if (config.networkKey !== undefined &&
+    (!(config.networkKey instanceof Uint8Array) || config.networkKey.length != 32))
+    throw new SmoldotError('`networkKey` must be a Uint8Array of 32 bytes');
+  if (config.networkKey !== undefined && config.privacy && config.privacy.peerIdRotationInterval)
+    throw new SmoldotError('`networkKey` and `privacy.peerIdRotationInterval` are incompatible');
 
   const logCallback = config.logCallback || ((level, target, message) => {
     if (level <= 1) {
@@ -91,6 +96,14 @@ export async function start(config) {
     } else if (message.kind == 'log') {
       logCallback(message.level, message.target, message.message);
 
+    } else if (message.kind == 'peerEvent') {
+      if (config.peerEventCallback)
+        config.peerEventCallback(message.event, message.chainIndex);
+
+    } else if (message.kind == 'checkpoint') {
+      if (config.checkpointCallback)
+        config.checkpointCallback(message.checkpoint, message.chainIndex);
+
     } else {
       console.error('Unknown message type', message);
     }
@@ -115,12 +128,165 @@ export async function start(config) {
   // The first message expected by the worker contains the configuration.
   worker.postMessage({
     chainSpecs: config.chainSpecs,
+    // For each chain, in the same order as `chainSpecs`, an optional object of the form
+    // `{ allow: [...], deny: [...] }` indicating which JSON-RPC methods can be called.
+    jsonRpcMethodsFilters: config.jsonRpcMethodsFilters || [],
+    // For each chain, in the same order as `chainSpecs`, an optional relative CPU weight. Chains
+    // whose weight is lower than the highest weight are paused after performing CPU-intensive
+    // operations, so that they can't starve the other chains. Defaults to `1`.
+    chainCpuWeights: config.chainCpuWeights || [],
+    // For each chain, in the same order as `chainSpecs`, an optional sync mode indicating what
+    // is downloaded from the network: `'headers'`, `'headersAndJustifications'` (the default),
+    // or `{ recentBodies: n }` to also download and keep in memory the bodies of the `n` most
+    // recent best blocks.
+    chainSyncModes: config.chainSyncModes || [],
+    // For each chain, in the same order as `chainSpecs`, an optional boolean. If `true`, the
+    // chain is only connected to and synchronized once the first JSON-RPC request targeting it
+    // is received. Ignored for parachains and relay chains of parachains.
+    chainLazyStart: config.chainLazyStart || [],
+    // For each chain, in the same order as `chainSpecs`, an optional number of milliseconds. If
+    // set and non-zero, a chain started lazily stops being synchronized and stops opening new
+    // connections after this long without any JSON-RPC request, provided that it has no active
+    // subscription. It is synchronized again, starting from its latest finalized block, when
+    // the next JSON-RPC request targeting it is received. By default, a chain keeps being
+    // synchronized once started.
+    chainLazyIdleTimeout: config.chainLazyIdleTimeout || [],
+    // For each chain, in the same order as `chainSpecs`, an optional boolean. If `true`, the
+    // chain doesn't share its connections with the other chains and uses a random network
+    // identity of its own. Parachains share the connections of their relay chain, and can only
+    // be isolated if their relay chain is isolated as well.
+    chainIsolatedNetwork: config.chainIsolatedNetwork || [],
+    // For each chain, in the same order as `chainSpecs`, an optional object of the form
+    // `{ address: '/dns/.../tcp/443/wss', methods: [...] }`. If present, the JSON-RPC requests
+    // whose method matches one of `methods` are also sent to the full node at `address`, and a
+    // warning is logged if the responses differ. Meant for debugging purposes only.
+    chainCrossValidation: config.chainCrossValidation || [],
+    // For each chain, in the same order as `chainSpecs`, an optional multiaddress of the form
+    // `/dns/.../tcp/443/wss` of a trusted JSON-RPC server. If present, storage queries and
+    // runtime calls that can't be answered from the network are answered by this server
+    // instead, and the responses contain an additional `"unverified": true` field.
+    chainRpcFallback: config.chainRpcFallback || [],
+    // For each chain, in the same order as `chainSpecs`, an optional number. If present and
+    // non-zero, the values of up to this number of storage keys recently requested through
+    // JSON-RPC are downloaded ahead of time at each new best block.
+    chainStoragePrefetch: config.chainStoragePrefetch || [],
+    // For each chain, in the same order as `chainSpecs`, an optional number of distinct peers
+    // that must agree before the result of a critical query, such as the download of the runtime
+    // code or the confirmation of the finalized block, is accepted. Defaults to 1.
+    chainQuorumSize: config.chainQuorumSize || [],
+    // For each chain, in the same order as `chainSpecs`, an optional boolean. If `true`, the
+    // transactions submitted with `author_submitExtrinsic` or `author_submitAndWatchExtrinsic`
+    // are validated against the best block before being sent out, and the ones that the runtime
+    // reports as invalid are refused with an error. Transactions whose validation can't be
+    // performed are sent out anyway. Defaults to `false`.
+    chainValidateTransactions: config.chainValidateTransactions || [],
+    // For each chain, in the same order as `chainSpecs`, an optional list of 4-characters
+    // identifiers of consensus engines, other than Aura, Babe, and GrandPa, whose digest items
+    // can be found in the headers of the chain. These items are verified by the module passed
+    // as `customDigestItemsVerifierModule`. Ignored for parachains.
+    chainCustomConsensusEngines: config.chainCustomConsensusEngines || [],
+    // For each chain, in the same order as `chainSpecs`, an optional maximum number of bytes of
+    // memory that the runtime of the chain is allowed to use. Overrides `maxRuntimeMemory`.
+    chainMaxRuntimeMemory: config.chainMaxRuntimeMemory || [],
     // Maximum level of log entries sent by the client.
     // 0 = Logging disabled, 1 = Error, 2 = Warn, 3 = Info, 4 = Debug, 5 = Trace
     maxLogLevel: config.maxLogLevel || 5,
     forbidTcp: config.forbidTcp,
     forbidWs: config.forbidWs,
     forbidWss: config.forbidWss,
+    // If true, nodes that can only be reached through a relay (i.e. whose address contains
+    // `/p2p-circuit`) are never connected to.
+    forbidRelays: !!config.forbidRelays,
+    // If true, networking requests indicate to peers that responses can be compressed. This
+    // relies on an extension of the networking protocol specific to smoldot, that other
+    // implementations ignore.
+    requestCompressedResponses: !!config.requestCompressedResponses,
+    // Maximum number of bytes of memory that the runtime of each chain is allowed to use, unless
+    // overridden with `chainMaxRuntimeMemory`. `undefined` for no limit.
+    maxRuntimeMemory: config.maxRuntimeMemory,
+    // URL of a DNS-over-HTTPS server (e.g. `https://cloudflare-dns.com/dns-query`) used to
+    // resolve the `/dnsaddr` addresses of bootstrap nodes. `undefined` to not resolve them.
+    dnsOverHttpsUrl: config.dnsOverHttpsUrl,
+    // If true, the `sudo_unstable_p2pRequest` JSON-RPC method, which sends arbitrary requests to
+    // peers, can be called.
+    unstableP2pRequests: !!config.unstableP2pRequests,
+    // Request-response protocols, on top of the ones used by the chains, that
+    // `sudo_unstable_p2pRequest` can send requests on. Each protocol is an object containing a
+    // `name`, and optionally a `maxRequestSize` and `maxResponseSize` in bytes and a `timeout` in
+    // milliseconds.
+    unstableP2pProtocols: config.unstableP2pProtocols || [],
+    // Limits applied to each JSON-RPC consumer, as identified by the `userData` passed to
+    // `sendJsonRpc`. At most `jsonRpcMaxConcurrentRequests` requests of a consumer are processed
+    // at the same time, and at most `jsonRpcMaxQueuedRequests` additional requests are queued.
+    // At most `jsonRpcMaxRequestsPerSecond` requests of a consumer are accepted every second.
+    // Requests exceeding these limits are answered with an error. A value of `0` for
+    // `jsonRpcMaxConcurrentRequests` or `jsonRpcMaxRequestsPerSecond` means no limit.
+    jsonRpcMaxConcurrentRequests: config.jsonRpcMaxConcurrentRequests || 0,
+    jsonRpcMaxQueuedRequests: config.jsonRpcMaxQueuedRequests || 0,
+    jsonRpcMaxRequestsPerSecond: config.jsonRpcMaxRequestsPerSecond || 0,
+    // Strategy used to open outgoing connections, such as to the bootstrap nodes. With
+    // `'parallel'`, as many nodes as possible are dialed at the same time. With `'staggered'`
+    // (the default), nodes are dialed one by one, waiting `dialDelay` milliseconds (1000 by
+    // default) between two dials. The Rust code expects a delay of `0` for `'parallel'`.
+    dialDelay: config.dialStrategy === 'parallel' ? 0 :
+      (config.dialDelay !== undefined ? Math.max(1, config.dialDelay) : 1000),
+    // Number of milliseconds after which a dial attempt is considered as failed. `0` for the
+    // default value.
+    dialTimeout: config.dialTimeout || 0,
+    // Number of peers the client tries to be connected to. `0` for the default value.
+    peersTarget: config.peersTarget || 0,
+    // Maximum size in bytes of the messages sent to peers. Larger data is split into multiple
+    // messages, which helps with WebSocket gateways that silently drop frames above a certain
+    // size. `0` for no limit.
+    maxOutboundMessageSize: config.maxOutboundMessageSize || 0,
+    // Maximum size in bytes of a message received on a connection. Connections on which a
+    // larger message is received are closed. `0` for the default value of 16 MiB.
+    maxInboundMessageSize: config.maxInboundMessageSize || 0,
+    // Settings that reduce the amount of information other nodes can learn about the client.
+    // `privacyFlags` is a bitwise OR of `1` (refuse identify requests, which would otherwise
+    // reveal the name of the client and the list of chains it is connected to) and `2` (report
+    // the genesis block as best block to peers). If `peerIdRotationInterval` isn't `0`, a new
+    // identity is used for the connections opened after each interval of this number of
+    // milliseconds. Requests are delayed by a random number of milliseconds between `0` and
+    // `maxRequestJitter`.
+    privacyFlags: (config.privacy && config.privacy.refuseIdentify ? 1 : 0) |
+      (config.privacy && config.privacy.hideBestBlock ? 2 : 0),
+    peerIdRotationInterval: (config.privacy && config.privacy.peerIdRotationInterval) || 0,
+    maxRequestJitter: (config.privacy && config.privacy.maxRequestJitter) || 0,
+    // Ed25519 private key (32 bytes) of the client on the peer-to-peer network. Persisting this
+    // key and passing it back on the next start keeps the identity (`PeerId`) of the client the
+    // same across restarts, which is necessary for example for peers that have the client
+    // configured as a reserved peer. `undefined` to generate a new random key.
+    networkKey: config.networkKey,
+    // Version of the format of the events passed to `peerEventCallback`. Version `1`, the
+    // default, is deprecated, and a warning is logged when it is used. Without a callback, the
+    // format doesn't matter and the latest version is used in order to not log this warning.
+    peerEventsVersion: config.peerEventCallback ? (config.peerEventsVersion || 1) : 2,
+    // Object whose keys are the `0x`-prefixed hex blake2b hashes of runtime codes, and whose
+    // values are either the code or a URL where to download it from. Used for the code
+    // substitutes of chain specifications that only contain the hash of the code.
+    codeSubstitutes: config.codeSubstitutes || {},
+    // URL of a JavaScript module that optionally exports `sr25519Verify(signature, message,
+    // publicKey)` and `sr25519VerifyDeprecated(signature, message, publicKey)`, returning a
+    // boolean, `babeVrfVerify(publicKey, slotNumber, epochIndex, randomness, vrfOutput,
+    // vrfProof)`, returning 16 bytes or `null`, and `blake2b256(data)`, returning a 32 bytes
+    // `Uint8Array`. If present, these functions are used instead of the slower implementations
+    // compiled to Wasm. The module is imported by the worker, as functions can't be sent to it.
+    hostCryptoModule: config.hostCryptoModule,
+    // URL of a JavaScript module that exports `verifyCustomDigestItems(chainIndex, items)`,
+    // returning `false` if any of the given digest items of custom consensus engines is invalid.
+    // Mandatory if `chainCustomConsensusEngines` isn't empty. Imported by the worker, like
+    // `hostCryptoModule`.
+    customDigestItemsVerifierModule: config.customDigestItemsVerifierModule,
+    // URL of a JavaScript module that exports `zstdDecompress(data, maxSize)`, returning the
+    // decompressed `Uint8Array`, or `null` if the data is invalid or decompresses to more than
+    // `maxSize` bytes. If present, compressed responses are decompressed by this function
+    // rather than within Wasm. Imported by the worker, like `hostCryptoModule`.
+    hostDecompressionModule: config.hostDecompressionModule,
+    // If false, the worker doesn't bother sending back events about peers.
+    reportPeerEvents: !!config.peerEventCallback,
+    // If false, the worker doesn't bother sending back checkpoints of the chains.
+    reportCheckpoints: !!config.checkpointCallback,
   });
 
   // Initialization happens asynchronous, both because we have a worker, but also asynchronously
@@ -160,6 +326,35 @@ export async function start(config) {
         throw workerError;
       }
     },
+    // Similar to `cancelAll`, except that the subscriptions are kept alive for a short amount of
+    // time and can be given to a different `userData` by passing the same `token` to `reattach`.
+    // A limited number of notifications is buffered for each subscription in the meantime and
+    // sent when reattaching. If some had to be discarded, a `smoldot_unstable_notificationsDropped`
+    // notification containing their number is sent beforehand.
+    detachAll: (userData, token) => {
+      if (!workerError) {
+        pendingCancelConfirmations.push(userData);
+        worker.postMessage({ ty: 'detachAll', userData, token });
+      } else {
+        throw workerError;
+      }
+    },
+    reattach: (token, userData) => {
+      if (!workerError) {
+        worker.postMessage({ ty: 'reattach', token, userData });
+      } else {
+        throw workerError;
+      }
+    },
+    // Asks for a checkpoint of the given chain to be reported through `checkpointCallback` as
+    // soon as possible, even if its finalized block hasn't changed.
+    requestCheckpoint: (chainIndex) => {
+      if (!workerError) {
+        worker.postMessage({ ty: 'requestCheckpoint', chainIndex });
+      } else {
+        throw workerError;
+      }
+    },
     terminate: () => {
       worker.terminate();
       if (!workerError)
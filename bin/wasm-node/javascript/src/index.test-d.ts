@@ -1,4 +1,4 @@
-import smoldot, { Smoldot, SmoldotClient } from 'smoldot';
+import smoldot, { Smoldot, SmoldotClient, SmoldotPeerEventV2 } from 'smoldot';
 
 // Test the export type
 
@@ -15,6 +15,65 @@ let sp = smoldot.start({
   forbidTcp: false,
   forbidWs: false,
   forbidWss: false,
+  forbidRelays: false,
+  privacy: {
+    refuseIdentify: true,
+    hideBestBlock: true,
+    peerIdRotationInterval: 600000,
+    maxRequestJitter: 500,
+  },
+});
+
+// Test when supplying a persisted network key
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: [''],
+  networkKey: new Uint8Array(32),
+});
+
+// Test when sending requests on custom peer-to-peer protocols
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: [''],
+  unstableP2pRequests: true,
+  unstableP2pProtocols: [{ name: '/sampling/1' }, { name: '/foo/1', maxResponseSize: 1024, timeout: 5000 }],
+});
+
+// Test when supplying host-accelerated cryptographic functions
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: [''],
+  hostCryptoModule: './host-crypto.js',
+});
+
+// Test when decompressing the responses of peers on the host
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: [''],
+  requestCompressedResponses: true,
+  hostDecompressionModule: './host-decompression.js',
+});
+
+// Test when opting into the version 2 of the peer events
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: [''],
+  peerEventsVersion: 2,
+  peerEventCallback: (event: SmoldotPeerEventV2, chainIndex) => { },
+});
+
+// Test when supplying connection limits
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: [''],
+  maxOutboundMessageSize: 8192,
+  maxInboundMessageSize: 1048576,
 });
 
 // Test when not supplying optional options and optional params
@@ -31,5 +90,37 @@ sp.then(sm => {
   // $ExpectType void
   sm.cancelAll(0);
   // $ExpectType void
+  sm.detachAll(0, 12);
+  // $ExpectType void
+  sm.reattach(12, 1);
+  // $ExpectType void
+  sm.requestCheckpoint(0);
+  // $ExpectType void
   sm.terminate();
 });
+
+// Test when enabling the validation of transactions
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: ['', ''],
+  chainValidateTransactions: [true, undefined],
+});
+
+// Test when enabling custom consensus engines
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: ['', ''],
+  chainCustomConsensusEngines: [['test'], undefined],
+  customDigestItemsVerifierModule: './custom-digest-items.js',
+});
+
+// Test when limiting the memory of the runtime of some chains
+
+// $ExpectType Promise<SmoldotClient>
+sp = smoldot.start({
+  chainSpecs: ['', ''],
+  maxRuntimeMemory: 64 * 1024 * 1024,
+  chainMaxRuntimeMemory: [16 * 1024 * 1024, undefined],
+});
@@ -48,12 +48,33 @@ const startInstance = async (config) => {
       // `compat.postMessage` is the same as `postMessage`, but works across environments.
       compat.postMessage({ kind: 'jsonrpc', data, chainIndex, userData });
     },
+    peerEventCallback: config.reportPeerEvents ? (event, chainIndex) => {
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'peerEvent', event, chainIndex });
+    } : null,
+    checkpointCallback: config.reportCheckpoints ? (checkpoint, chainIndex) => {
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'checkpoint', checkpoint, chainIndex });
+    } : null,
     forbidTcp: config.forbidTcp,
     forbidWs: config.forbidWs,
     forbidWss: config.forbidWss,
+    codeSubstitutes: config.codeSubstitutes,
+    // Functions can't be sent to a worker. Instead, the module that contains the host-accelerated
+    // cryptographic functions is imported here, so that they run on the same thread as the Wasm
+    // VM, which calls them synchronously.
+    hostCrypto: config.hostCryptoModule ? await import(config.hostCryptoModule) : null,
+    // Same as above, for the module that verifies the digest items of custom consensus engines.
+    customDigestItemsVerifier: config.customDigestItemsVerifierModule ?
+      await import(config.customDigestItemsVerifierModule) : null,
+    // Same as above, for the module that decompresses the responses received from peers.
+    hostDecompression: config.hostDecompressionModule ?
+      await import(config.hostDecompressionModule) : null,
+    requestCompressedResponses: config.requestCompressedResponses,
   };
 
-  const { bindings: smoldotJsBindings } = smoldot_js_builder(smoldotJsConfig);
+  const { bindings: smoldotJsBindings, hostCryptoFlags, compressionFlags, supportedTransports } =
+    smoldot_js_builder(smoldotJsConfig);
 
   // Used to bind with the Wasi bindings. See the `bindings-wasi.js` file.
   const wasiConfig = {};
@@ -75,7 +96,7 @@ const startInstance = async (config) => {
   // The logic below is a bit complicated due to the necessity to pass a list of strings through
   // the FFI layer. See the documentation of `init` in the Rust code.
   let chainSpecsPointersContent = [];
-  for (let chainSpec of config.chainSpecs) {
+  config.chainSpecs.forEach((chainSpec, chainIndex) => {
     if (Object.prototype.toString.call(chainSpec) !== '[object String]')
       throw new SmoldotError('chain spec must be a string');
 
@@ -85,15 +106,102 @@ const startInstance = async (config) => {
       .write(chainSpec, chainSpecPtr);
     chainSpecsPointersContent.push(chainSpecPtr);
     chainSpecsPointersContent.push(chainSpecLen);
-  }
+
+    // The rest of the configuration of the chain is passed as a JSON object. See the
+    // documentation of `init` in the Rust code.
+    const methodsFilter = config.jsonRpcMethodsFilters[chainIndex];
+    const syncMode = config.chainSyncModes[chainIndex];
+    const crossValidation = config.chainCrossValidation[chainIndex];
+    const customConsensusEngines = config.chainCustomConsensusEngines[chainIndex] || [];
+    if (customConsensusEngines.length != 0 && !smoldotJsConfig.customDigestItemsVerifier)
+      throw new SmoldotError('chains with custom consensus engines require a customDigestItemsVerifierModule');
+    const chainConfigJson = JSON.stringify({
+      jsonRpcMethodsFilter: methodsFilter ? {
+        allow: methodsFilter.allow,
+        deny: methodsFilter.deny,
+        allowUnsafe: methodsFilter.allowUnsafe,
+      } : null,
+      cpuWeight: config.chainCpuWeights[chainIndex] || 1,
+      syncMode: syncMode === 'headers' ? 'headers' :
+        (syncMode && syncMode.recentBodies) ? { recentBodies: syncMode.recentBodies } : null,
+      lazyStart: !!config.chainLazyStart[chainIndex],
+      lazyIdleTimeout: config.chainLazyIdleTimeout[chainIndex] || 0,
+      isolatedNetwork: !!config.chainIsolatedNetwork[chainIndex],
+      crossValidation: crossValidation ?
+        { address: crossValidation.address, methods: crossValidation.methods } : null,
+      rpcFallback: config.chainRpcFallback[chainIndex] || null,
+      storagePrefetchKeys: config.chainStoragePrefetch[chainIndex] || 0,
+      quorumSize: config.chainQuorumSize[chainIndex] || 1,
+      validateTransactions: !!config.chainValidateTransactions[chainIndex],
+      customConsensusEngines,
+      maxRuntimeMemoryPages: config.chainMaxRuntimeMemory[chainIndex] ?
+        Math.max(1, Math.floor(config.chainMaxRuntimeMemory[chainIndex] / 65536)) : 0,
+    });
+    const chainConfigLen = Buffer.byteLength(chainConfigJson, 'utf8');
+    const chainConfigPtr = result.instance.exports.alloc(chainConfigLen);
+    Buffer.from(result.instance.exports.memory.buffer)
+      .write(chainConfigJson, chainConfigPtr);
+    chainSpecsPointersContent.push(chainConfigPtr);
+    chainSpecsPointersContent.push(chainConfigLen);
+  });
   const chainSpecsPointersPtr = result.instance.exports.alloc(chainSpecsPointersContent.length * 4);
   for (let idx in chainSpecsPointersContent) {
     Buffer.from(result.instance.exports.memory.buffer)
       .writeUInt32LE(chainSpecsPointersContent[idx], chainSpecsPointersPtr + idx * 4);
   }
+  // The Rust code expects a number of 64 kiB pages, where 0 means "no limit".
+  const maxRuntimeMemoryPages = config.maxRuntimeMemory ?
+    Math.max(1, Math.floor(config.maxRuntimeMemory / 65536)) : 0;
+
+  // The URL of the DNS-over-HTTPS server is passed as a buffer, where an empty buffer means
+  // that `/dnsaddr` addresses aren't resolved.
+  let dohUrlPtr = 0;
+  let dohUrlLen = 0;
+  if (config.dnsOverHttpsUrl) {
+    dohUrlLen = Buffer.byteLength(config.dnsOverHttpsUrl, 'utf8');
+    dohUrlPtr = result.instance.exports.alloc(dohUrlLen);
+    Buffer.from(result.instance.exports.memory.buffer)
+      .write(config.dnsOverHttpsUrl, dohUrlPtr);
+  }
+
+  // The network key is passed the same way, where an empty buffer means that a random key is
+  // generated.
+  let networkKeyPtr = 0;
+  let networkKeyLen = 0;
+  if (config.networkKey) {
+    networkKeyLen = config.networkKey.length;
+    networkKeyPtr = result.instance.exports.alloc(networkKeyLen);
+    Buffer.from(result.instance.exports.memory.buffer)
+      .set(config.networkKey, networkKeyPtr);
+  }
+
+  // The additional peer-to-peer protocols are passed as a JSON array, where an empty buffer
+  // means that there isn't any.
+  let p2pProtocolsPtr = 0;
+  let p2pProtocolsLen = 0;
+  if (config.unstableP2pProtocols.length != 0) {
+    const p2pProtocolsJson = JSON.stringify(config.unstableP2pProtocols.map((protocol) => ({
+      name: protocol.name,
+      maxRequestSize: protocol.maxRequestSize,
+      maxResponseSize: protocol.maxResponseSize,
+      timeoutMs: protocol.timeout,
+    })));
+    p2pProtocolsLen = Buffer.byteLength(p2pProtocolsJson, 'utf8');
+    p2pProtocolsPtr = result.instance.exports.alloc(p2pProtocolsLen);
+    Buffer.from(result.instance.exports.memory.buffer)
+      .write(p2pProtocolsJson, p2pProtocolsPtr);
+  }
+
   result.instance.exports.init(
     chainSpecsPointersPtr, chainSpecsPointersContent.length * 4,
-    config.maxLogLevel
+    config.maxLogLevel, hostCryptoFlags, compressionFlags,
+    maxRuntimeMemoryPages, dohUrlPtr, dohUrlLen, config.unstableP2pRequests ? 1 : 0,
+    config.jsonRpcMaxConcurrentRequests, config.jsonRpcMaxQueuedRequests,
+    config.jsonRpcMaxRequestsPerSecond, config.dialDelay, config.dialTimeout,
+    config.peersTarget, config.maxOutboundMessageSize, config.maxInboundMessageSize,
+    supportedTransports, config.forbidRelays ? 0 : 1,
+    config.privacyFlags, config.peerIdRotationInterval, config.maxRequestJitter,
+    networkKeyPtr, networkKeyLen, config.peerEventsVersion, p2pProtocolsPtr, p2pProtocolsLen
   );
 
   state.forEach((message) => {
@@ -106,6 +214,14 @@ const startInstance = async (config) => {
       result.instance.exports.json_rpc_unsubscribe_all(message.userData);
       // `compat.postMessage` is the same as `postMessage`, but works across environments.
       compat.postMessage({ kind: 'unsubscribeAllConfirmation', userData: message.userData });
+    } else if (message.ty == 'detachAll') {
+      result.instance.exports.json_rpc_detach_all(message.userData, message.token);
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'unsubscribeAllConfirmation', userData: message.userData });
+    } else if (message.ty == 'reattach') {
+      result.instance.exports.json_rpc_reattach(message.token, message.userData);
+    } else if (message.ty == 'requestCheckpoint') {
+      result.instance.exports.request_checkpoint(message.chainIndex);
     } else
       throw new Error('unrecognized message type');
   });
@@ -135,6 +251,14 @@ compat.setOnMessage((message) => {
       state.exports.json_rpc_unsubscribe_all(message.userData);
       // `compat.postMessage` is the same as `postMessage`, but works across environments.
       compat.postMessage({ kind: 'unsubscribeAllConfirmation', userData: message.userData });
+    } else if (message.ty == 'detachAll') {
+      state.exports.json_rpc_detach_all(message.userData, message.token);
+      // `compat.postMessage` is the same as `postMessage`, but works across environments.
+      compat.postMessage({ kind: 'unsubscribeAllConfirmation', userData: message.userData });
+    } else if (message.ty == 'reattach') {
+      state.exports.json_rpc_reattach(message.token, message.userData);
+    } else if (message.ty == 'requestCheckpoint') {
+      state.exports.request_checkpoint(message.chainIndex);
     } else
       throw new Error('unrecognized message type');
   }
@@ -0,0 +1,83 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Light client running outside of a browser, serving JSON-RPC clients through WebSocket.
+//!
+//! Usage: `smoldot-light [--json-rpc-address <address>] [--log-level <level>] <chain-spec>...`
+//!
+//! See the `standalone` module of the library for more information.
+
+use std::{fs, net, process};
+
+fn main() {
+    let mut json_rpc_address: net::SocketAddr = ([127, 0, 0, 1], 9944).into();
+    let mut max_log_level = log::LevelFilter::Info;
+    let mut chain_specs = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &*arg {
+            "--json-rpc-address" => {
+                json_rpc_address = match args.next().map(|a| a.parse()) {
+                    Some(Ok(a)) => a,
+                    _ => exit_with_usage("Invalid or missing value for --json-rpc-address"),
+                };
+            }
+            "--log-level" => {
+                max_log_level = match args.next().map(|l| l.parse()) {
+                    Some(Ok(l)) => l,
+                    _ => exit_with_usage("Invalid or missing value for --log-level"),
+                };
+            }
+            path if !path.starts_with("--") => match fs::read_to_string(path) {
+                Ok(spec) => chain_specs.push(spec),
+                Err(error) => {
+                    eprintln!("Failed to read {}: {}", path, error);
+                    process::exit(1);
+                }
+            },
+            other => exit_with_usage(&format!("Unknown option: {}", other)),
+        }
+    }
+
+    if chain_specs.is_empty() {
+        exit_with_usage("At least one chain specification is required");
+    }
+
+    let config = smoldot_js::standalone::Config {
+        chain_specs,
+        json_rpc_address,
+        max_log_level,
+    };
+
+    if let Err(error) = async_std::task::block_on(smoldot_js::standalone::run(config)) {
+        eprintln!(
+            "Failed to start the JSON-RPC server on {}: {}",
+            json_rpc_address, error
+        );
+        process::exit(1);
+    }
+}
+
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{}", message);
+    eprintln!(
+        "Usage: smoldot-light [--json-rpc-address <address>] [--log-level <level>] \
+        <chain-spec>..."
+    );
+    process::exit(1)
+}
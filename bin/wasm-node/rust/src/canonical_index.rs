@@ -0,0 +1,222 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Index of the hashes of the recent blocks of the canonical chain, by block number.
+//!
+//! The [`CanonicalIndex`] is informed of the changes of best and finalized blocks, and of the
+//! ancestors of the finalized block that have been verified by other means. It can then answer,
+//! for a given block number, the hash of the block of the canonical chain with this number,
+//! provided that this block is known and not too old.
+//!
+//! The index never contains any block that isn't part of the canonical chain. Because only the
+//! best block and its parent are reported when the best block changes, it is not always possible
+//! to know which non-finalized blocks are still part of the canonical chain. In case of doubt,
+//! the non-finalized blocks are removed from the index. Finalized blocks, on the other hand, are
+//! part of the canonical chain forever and are only ever removed once they are too old.
+
+use std::collections::BTreeMap;
+
+/// See [the module-level documentation](..).
+#[derive(Debug)]
+pub struct CanonicalIndex {
+    /// Maximum distance between the number of the best block and the number of the blocks in
+    /// the index.
+    capacity: u64,
+    /// Number of the current finalized block.
+    finalized_number: u64,
+    /// Hash of the blocks of the canonical chain, indexed by their number.
+    hashes: BTreeMap<u64, [u8; 32]>,
+}
+
+impl CanonicalIndex {
+    /// Initializes a new index containing only the given finalized block, which is also the
+    /// best block.
+    pub fn new(capacity: u64, finalized_number: u64, finalized_hash: [u8; 32]) -> Self {
+        let mut hashes = BTreeMap::new();
+        hashes.insert(finalized_number, finalized_hash);
+
+        CanonicalIndex {
+            capacity,
+            finalized_number,
+            hashes,
+        }
+    }
+
+    /// Returns the hash of the block of the canonical chain with the given number, if it is in
+    /// the index.
+    pub fn get(&self, number: u64) -> Option<[u8; 32]> {
+        self.hashes.get(&number).copied()
+    }
+
+    /// Returns the number of the given block if it is in the index and finalized.
+    pub fn finalized_block_number(&self, hash: &[u8; 32]) -> Option<u64> {
+        self.hashes
+            .range(..=self.finalized_number)
+            .rev()
+            .find(|(_, h)| *h == hash)
+            .map(|(n, _)| *n)
+    }
+
+    /// Updates the index following a change of the best block.
+    ///
+    /// Has no effect if the number of the block isn't superior to the number of the current
+    /// finalized block.
+    pub fn set_best(&mut self, number: u64, hash: [u8; 32], parent_hash: [u8; 32]) {
+        // The best block and finalized block are reported through different channels, and a
+        // best block might be reported after a more recent finalized block. Such a best block
+        // is either already in the index, or not part of the canonical chain anymore.
+        if number <= self.finalized_number {
+            return;
+        }
+
+        // Blocks with a higher number than the best block are no longer part of the canonical
+        // chain.
+        self.hashes.split_off(&(number + 1));
+
+        if self.hashes.get(&number) == Some(&hash) {
+            return;
+        }
+
+        // If the parent of the new best block isn't known, it is impossible to know whether the
+        // non-finalized blocks of the index are part of the canonical chain.
+        if self.hashes.get(&(number - 1)) != Some(&parent_hash) {
+            self.hashes.split_off(&(self.finalized_number + 1));
+            if number - 1 > self.finalized_number {
+                self.hashes.insert(number - 1, parent_hash);
+            }
+        }
+
+        self.hashes.insert(number, hash);
+        self.prune();
+    }
+
+    /// Updates the index following a change of the finalized block. The new finalized block
+    /// must be an ancestor of or equal to the current best block.
+    pub fn set_finalized(&mut self, number: u64, hash: [u8; 32]) {
+        if self.hashes.get(&number) != Some(&hash) {
+            // The best block reported before this finalized block isn't a descendant of it.
+            // This is not supposed to happen, but is handled by clearing all the non-finalized
+            // blocks.
+            self.hashes.split_off(&(self.finalized_number + 1));
+            self.hashes.insert(number, hash);
+        }
+
+        self.finalized_number = number;
+        self.prune();
+    }
+
+    /// Inserts in the index a block that is known to be an ancestor of the current finalized
+    /// block.
+    ///
+    /// Has no effect if the block is too old to be kept in the index, or if its number isn't
+    /// inferior to the number of the finalized block.
+    pub fn insert_finalized_ancestor(&mut self, number: u64, hash: [u8; 32]) {
+        if number >= self.finalized_number {
+            return;
+        }
+
+        if self.is_too_old(number) {
+            return;
+        }
+
+        self.hashes.insert(number, hash);
+    }
+
+    /// Returns true if a block with this number is too old to be kept in the index.
+    fn is_too_old(&self, number: u64) -> bool {
+        let highest = self.hashes.keys().next_back().copied().unwrap_or(0);
+        highest.saturating_sub(number) > self.capacity
+    }
+
+    /// Removes the blocks that are too old from the index.
+    fn prune(&mut self) {
+        let highest = match self.hashes.keys().next_back() {
+            Some(n) => *n,
+            None => return,
+        };
+
+        if let Some(lowest_kept) = highest.checked_sub(self.capacity) {
+            self.hashes = self.hashes.split_off(&lowest_kept);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CanonicalIndex;
+
+    fn hash(number: u64, fork: u8) -> [u8; 32] {
+        let mut hash = [fork; 32];
+        hash[..8].copy_from_slice(&number.to_le_bytes());
+        hash
+    }
+
+    #[test]
+    fn follows_best_chain() {
+        let mut index = CanonicalIndex::new(1000, 0, hash(0, 0));
+        for n in 1..=10 {
+            index.set_best(n, hash(n, 0), hash(n - 1, 0));
+        }
+        index.set_finalized(5, hash(5, 0));
+
+        for n in 0..=10 {
+            assert_eq!(index.get(n), Some(hash(n, 0)));
+        }
+        assert_eq!(index.get(11), None);
+
+        // Re-organization from block 8 onwards. Only the finalized blocks are kept.
+        index.set_best(9, hash(9, 1), hash(8, 1));
+        for n in 0..=5 {
+            assert_eq!(index.get(n), Some(hash(n, 0)));
+        }
+        assert_eq!(index.get(6), None);
+        assert_eq!(index.get(8), Some(hash(8, 1)));
+        assert_eq!(index.get(9), Some(hash(9, 1)));
+        assert_eq!(index.get(10), None);
+    }
+
+    #[test]
+    fn finalized_block_number() {
+        let mut index = CanonicalIndex::new(1000, 0, hash(0, 0));
+        for n in 1..=10 {
+            index.set_best(n, hash(n, 0), hash(n - 1, 0));
+        }
+        index.set_finalized(5, hash(5, 0));
+
+        assert_eq!(index.finalized_block_number(&hash(0, 0)), Some(0));
+        assert_eq!(index.finalized_block_number(&hash(5, 0)), Some(5));
+        assert_eq!(index.finalized_block_number(&hash(6, 0)), None);
+        assert_eq!(index.finalized_block_number(&hash(5, 1)), None);
+    }
+
+    #[test]
+    fn old_blocks_are_pruned() {
+        let mut index = CanonicalIndex::new(4, 0, hash(0, 0));
+        for n in 1..=10 {
+            index.set_best(n, hash(n, 0), hash(n - 1, 0));
+            index.set_finalized(n, hash(n, 0));
+        }
+
+        assert_eq!(index.get(5), None);
+        assert_eq!(index.get(6), Some(hash(6, 0)));
+
+        index.insert_finalized_ancestor(5, hash(5, 0));
+        assert_eq!(index.get(5), None);
+        index.insert_finalized_ancestor(7, hash(7, 0));
+        assert_eq!(index.get(7), Some(hash(7, 0)));
+    }
+}
@@ -0,0 +1,234 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Accounting of the CPU time spent by each chain.
+//!
+//! Each chain owns a [`CpuUsage`], shared between the services of this chain, in which the time
+//! spent in the most CPU-intensive operations is accumulated: executing the runtime, verifying
+//! Merkle proofs, and verifying headers. Time is measured using [`platform::Host`], in other
+//! words using the clock provided by the host.
+//!
+//! Each chain is also assigned a relative weight. All the tasks of the client run on the same
+//! thread, meaning that a chain continuously performing expensive operations would prevent the
+//! tasks of the other chains from running. To prevent this from happening, the CPU time spent
+//! by a chain whose weight is lower than the highest weight is compensated with pauses, during
+//! which the other chains can make progress. A chain whose weight is `w`, where `max` is the
+//! highest weight, is paused for `max / w - 1` times the CPU time it has spent. See
+//! [`CpuUsage::throttle`].
+
+use crate::{
+    memory_usage,
+    platform::{self, Host, Platform as _},
+};
+
+use core::{convert::TryFrom as _, num::NonZeroU32, time::Duration};
+use std::sync::atomic;
+
+/// Category of CPU-intensive operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Category {
+    /// Compiling or executing the runtime.
+    RuntimeExecution,
+    /// Verifying a Merkle proof of the storage.
+    ProofVerification,
+    /// Verifying a block header or a finality proof.
+    HeaderVerification,
+}
+
+impl Category {
+    /// Number of variants of [`Category`].
+    pub(crate) const NUM: usize = 3;
+
+    /// Returns a number between 0 and [`Category::NUM`] (excluded) unique to this category.
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            Category::RuntimeExecution => 0,
+            Category::ProofVerification => 1,
+            Category::HeaderVerification => 2,
+        }
+    }
+}
+
+/// CPU time accounting of a chain.
+pub struct CpuUsage {
+    /// Relative weight of the chain.
+    weight: NonZeroU32,
+    /// Highest weight amongst all the chains.
+    max_weight: NonZeroU32,
+    /// Number of microseconds spent in each category, indexed by [`Category::index`].
+    totals: [atomic::AtomicU64; Category::NUM],
+    /// Sum of all the values in [`CpuUsage::totals`]. Used in order to exclude from a
+    /// measurement the time spent in measurements nested within it.
+    grand_total: atomic::AtomicU64,
+    /// Number of microseconds that the chain must be paused for during the next call to
+    /// [`CpuUsage::throttle`].
+    owed_pause: atomic::AtomicU64,
+}
+
+impl CpuUsage {
+    /// Initializes a new [`CpuUsage`]. `weight` is the relative weight of the chain, and
+    /// `max_weight` the highest weight amongst all the chains of the client.
+    pub fn new(weight: NonZeroU32, max_weight: NonZeroU32) -> Self {
+        debug_assert!(weight <= max_weight);
+        CpuUsage {
+            weight,
+            max_weight,
+            totals: Default::default(),
+            grand_total: atomic::AtomicU64::new(0),
+            owed_pause: atomic::AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the relative weight of the chain, as passed to [`CpuUsage::new`].
+    pub fn weight(&self) -> NonZeroU32 {
+        self.weight
+    }
+
+    /// Starts measuring the time spent in an operation of the given category. The measurement
+    /// ends when the returned [`Measurement`] is dropped.
+    ///
+    /// Measurements can be nested, in which case the time spent in the inner measurement is
+    /// only accounted for in the category of the inner measurement.
+    ///
+    /// The memory allocated during the measurement is attributed to the same category. See
+    /// [`memory_usage::enter`].
+    pub fn measure(&self, category: Category) -> Measurement<'_> {
+        Measurement {
+            cpu_usage: self,
+            category,
+            _memory_scope: memory_usage::enter(category),
+            start: Host::now(),
+            grand_total_before: self.grand_total.load(atomic::Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the total time spent in operations of the given category.
+    pub fn total(&self, category: Category) -> Duration {
+        Duration::from_micros(self.totals[category.index()].load(atomic::Ordering::Relaxed))
+    }
+
+    /// Pauses for the time owed by the chain because of its measured CPU usage since the
+    /// previous call, if any.
+    ///
+    /// Should be called regularly by the tasks of the chain, before performing CPU-intensive
+    /// operations.
+    pub async fn throttle(&self) {
+        let owed = self.owed_pause.swap(0, atomic::Ordering::Relaxed);
+        if owed != 0 {
+            Host::sleep(Duration::from_micros(owed)).await;
+        }
+    }
+}
+
+/// Measurement in progress. See [`CpuUsage::measure`].
+#[must_use]
+pub struct Measurement<'a> {
+    cpu_usage: &'a CpuUsage,
+    category: Category,
+    _memory_scope: memory_usage::Scope<'static>,
+    start: platform::Instant,
+    /// Value of [`CpuUsage::grand_total`] when the measurement has started.
+    grand_total_before: u64,
+}
+
+impl<'a> Drop for Measurement<'a> {
+    fn drop(&mut self) {
+        let elapsed =
+            u64::try_from((Host::now() - self.start).as_micros()).unwrap_or(u64::max_value());
+        let nested = self
+            .cpu_usage
+            .grand_total
+            .load(atomic::Ordering::Relaxed)
+            .saturating_sub(self.grand_total_before);
+        let own = elapsed.saturating_sub(nested);
+
+        self.cpu_usage.totals[self.category.index()].fetch_add(own, atomic::Ordering::Relaxed);
+        self.cpu_usage
+            .grand_total
+            .fetch_add(own, atomic::Ordering::Relaxed);
+
+        let weight = u64::from(self.cpu_usage.weight.get());
+        let max_weight = u64::from(self.cpu_usage.max_weight.get());
+        let pause = own.saturating_mul(max_weight - weight) / weight;
+        self.cpu_usage
+            .owed_pause
+            .fetch_add(pause, atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Category, CpuUsage};
+    use crate::{
+        platform::{Host, Platform as _},
+        test_utils,
+    };
+    use core::{num::NonZeroU32, time::Duration};
+
+    #[test]
+    fn nested_measurements() {
+        let cpu_usage = CpuUsage::new(NonZeroU32::new(1).unwrap(), NonZeroU32::new(1).unwrap());
+
+        {
+            let _outer = cpu_usage.measure(Category::RuntimeExecution);
+            test_utils::advance(Duration::from_millis(10));
+            {
+                let _inner = cpu_usage.measure(Category::ProofVerification);
+                test_utils::advance(Duration::from_millis(3));
+            }
+            test_utils::advance(Duration::from_millis(5));
+        }
+
+        assert_eq!(
+            cpu_usage.total(Category::RuntimeExecution),
+            Duration::from_millis(15)
+        );
+        assert_eq!(
+            cpu_usage.total(Category::ProofVerification),
+            Duration::from_millis(3)
+        );
+        assert_eq!(
+            cpu_usage.total(Category::HeaderVerification),
+            Duration::new(0, 0)
+        );
+    }
+
+    #[test]
+    fn throttle_proportional_to_weight() {
+        test_utils::block_on(
+            async move {
+                let cpu_usage =
+                    CpuUsage::new(NonZeroU32::new(1).unwrap(), NonZeroU32::new(4).unwrap());
+
+                {
+                    let _measure = cpu_usage.measure(Category::HeaderVerification);
+                    test_utils::advance(Duration::from_millis(10));
+                }
+
+                let start = Host::now();
+                cpu_usage.throttle().await;
+                assert_eq!(Host::now() - start, Duration::from_millis(30));
+
+                // The owed pause has been consumed.
+                let start = Host::now();
+                cpu_usage.throttle().await;
+                assert_eq!(Host::now() - start, Duration::new(0, 0));
+            },
+            None,
+        )
+    }
+}
@@ -0,0 +1,498 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Mirroring of JSON-RPC requests to a full node, in order to compare the responses.
+//!
+//! When cross-validation is enabled for a chain, the JSON-RPC requests targeting this chain and
+//! whose method matches [`Config::methods`] are, in addition to being answered normally, sent to
+//! the JSON-RPC server of the full node found at [`Config::address`]. Once both responses are
+//! known, a warning is logged if they differ. This makes it possible to spot situations where
+//! the answer of the client, which is verified against proofs, diverges from the one of a full
+//! node.
+//!
+//! A difference doesn't necessarily indicate a bug. For example, the client and the full node
+//! might not agree on the current best block. Only methods whose response doesn't depend on the
+//! state of the client, such as querying the storage of a specific block, are worth mirroring.
+//! Two error responses are always considered identical, as their messages are implementation
+//! specific.
+//!
+//! > **Note**: This is a debugging tool. All the mirrored requests are revealed to the full
+//! >           node.
+
+use crate::{
+    json_rpc_service::MethodsFilter,
+    platform::{self, Host, Platform as _},
+};
+
+use futures::{channel::mpsc, prelude::*};
+use smoldot::json_rpc::methods;
+use std::{cmp, collections::HashMap, pin::Pin};
+
+/// Configuration for cross-validation.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Multiaddress of the WebSocket JSON-RPC server of the full node, for example
+    /// `/dns/rpc.example.com/tcp/443/wss`. See [`platform::Transport::from_multiaddr`].
+    ///
+    /// The requests are sent as binary WebSocket frames.
+    pub address: String,
+
+    /// Which JSON-RPC methods to mirror to the full node.
+    pub methods: MethodsFilter,
+}
+
+/// Maximum number of requests that can be waiting for their comparison at the same time.
+/// Requests beyond this limit aren't mirrored.
+const MAX_PENDING: usize = 256;
+
+/// Handle to a background task that mirrors JSON-RPC requests and compares the responses.
+pub struct CrossValidation {
+    /// See [`Config::methods`].
+    methods: MethodsFilter,
+
+    /// Channel to the background task.
+    to_background: mpsc::UnboundedSender<ToBackground>,
+}
+
+impl CrossValidation {
+    /// Spawns the background task of the cross-validation. `log_name` is the name of the chain,
+    /// used in the log messages.
+    pub fn new(
+        config: Config,
+        log_name: String,
+        tasks_executor: &mut dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>),
+    ) -> Self {
+        let (to_background, from_foreground) = mpsc::unbounded();
+
+        tasks_executor(
+            "cross-validation".into(),
+            Box::pin(run_background(config.address, log_name, from_foreground)),
+        );
+
+        CrossValidation {
+            methods: config.methods,
+            to_background,
+        }
+    }
+
+    /// Must be called when a JSON-RPC request is about to be processed. Does nothing if the
+    /// method isn't mirrored.
+    pub fn on_request(&self, user_data: u32, request_id: &str, method: &str, request: &str) {
+        if !self.methods.is_allowed(method) {
+            return;
+        }
+
+        let _ = self.to_background.unbounded_send(ToBackground::Request {
+            user_data,
+            request_id: normalize_id(request_id),
+            method: method.to_owned(),
+            request: request.to_owned(),
+        });
+    }
+
+    /// Must be called with every response or notification sent back to the JSON-RPC client.
+    pub fn on_response(&self, user_data: u32, message: &str) {
+        let message = match serde_json::from_str::<serde_json::Value>(message) {
+            Ok(m) => m,
+            Err(_) => return,
+        };
+
+        // Notifications don't have an `id` field, and are ignored.
+        let (request_id, outcome) = match (message.get("id"), Outcome::from_response(&message)) {
+            (Some(id), Some(outcome)) => (id.to_string(), outcome),
+            _ => return,
+        };
+
+        let _ = self.to_background.unbounded_send(ToBackground::Response {
+            user_data,
+            request_id,
+            outcome,
+        });
+    }
+
+    /// Same as [`CrossValidation::on_response`], for a successful response whose result is the
+    /// hexadecimal encoding of `result`.
+    pub fn on_hex_response(&self, user_data: u32, request_id: &str, result: &[u8]) {
+        let _ = self.to_background.unbounded_send(ToBackground::Response {
+            user_data,
+            request_id: normalize_id(request_id),
+            outcome: Outcome::Success(
+                serde_json::to_value(methods::HexString(result.to_vec())).unwrap(),
+            ),
+        });
+    }
+}
+
+enum ToBackground {
+    Request {
+        user_data: u32,
+        request_id: String,
+        method: String,
+        request: String,
+    },
+    Response {
+        user_data: u32,
+        request_id: String,
+        outcome: Outcome,
+    },
+}
+
+/// Outcome of a JSON-RPC request.
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    /// Content of the `result` field of the response.
+    Success(serde_json::Value),
+    /// Content of the `error` field of the response.
+    Error(serde_json::Value),
+}
+
+impl Outcome {
+    /// Extracts the outcome of the given JSON-RPC response. Returns `None` if the value isn't a
+    /// response.
+    fn from_response(response: &serde_json::Value) -> Option<Self> {
+        if let Some(result) = response.get("result") {
+            Some(Outcome::Success(result.clone()))
+        } else {
+            response
+                .get("error")
+                .map(|error| Outcome::Error(error.clone()))
+        }
+    }
+
+    /// Returns `true` if the two outcomes are considered identical.
+    fn matches(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Success(a), Outcome::Success(b)) => a == b,
+            (Outcome::Error(_), Outcome::Error(_)) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Request that has been sent to the full node and whose responses are being waited for.
+struct Comparison {
+    /// User data and normalized identifier of the request received from the JSON-RPC client.
+    request_key: (u32, String),
+    /// Name of the JSON-RPC method.
+    method: String,
+    /// Request as received from the JSON-RPC client.
+    request: String,
+    /// Response sent back by the client, if known yet.
+    client: Option<Outcome>,
+    /// Response of the full node, if known yet.
+    full_node: Option<Outcome>,
+}
+
+async fn run_background(
+    address: String,
+    log_name: String,
+    mut from_foreground: mpsc::UnboundedReceiver<ToBackground>,
+) {
+    // Connection to the full node. Opened when the first request is mirrored, and re-opened
+    // after it has been closed when the next request is mirrored.
+    let mut connection: Option<platform::Connection> = None;
+    // Data received from the full node and not yet parsed.
+    let mut received = Vec::new();
+
+    // Requests sent to the full node whose comparison hasn't been performed yet, indexed by the
+    // identifier of the request sent to the full node.
+    let mut pending = HashMap::<u64, Comparison>::new();
+    // Identifier of the request sent to the full node, indexed by the user data and identifier
+    // of the request received from the JSON-RPC client. Entries are removed once the client
+    // has answered.
+    let mut by_client_request = HashMap::<(u32, String), u64>::new();
+    let mut next_request_id: u64 = 0;
+
+    loop {
+        enum Event {
+            Foreground(ToBackground),
+            Received(usize),
+            Closed,
+        }
+
+        let event = match connection.as_mut() {
+            Some(connection) => {
+                match future::select(from_foreground.next(), Host::read_buffer(connection)).await {
+                    future::Either::Left((Some(message), _)) => Event::Foreground(message),
+                    future::Either::Left((None, _)) => return,
+                    future::Either::Right((Some(buffer), _)) => {
+                        received.extend_from_slice(buffer);
+                        Event::Received(buffer.len())
+                    }
+                    future::Either::Right((None, _)) => Event::Closed,
+                }
+            }
+            None => match from_foreground.next().await {
+                Some(message) => Event::Foreground(message),
+                None => return,
+            },
+        };
+
+        match event {
+            Event::Foreground(ToBackground::Request {
+                user_data,
+                request_id,
+                method,
+                request,
+            }) => {
+                if pending.len() >= MAX_PENDING {
+                    log::debug!(
+                        target: "cross-validation",
+                        "Too many requests in progress; not cross-validating {} on {}",
+                        method, log_name
+                    );
+                    continue;
+                }
+
+                // The identifier of the request is replaced, as multiple JSON-RPC clients might
+                // use the same identifiers.
+                let mut full_node_request =
+                    match serde_json::from_str::<serde_json::Value>(&request) {
+                        Ok(serde_json::Value::Object(rq)) => rq,
+                        _ => continue,
+                    };
+                full_node_request.insert("id".into(), next_request_id.into());
+
+                if connection.is_none() {
+                    // The default limits never split the JSON-RPC messages, which would corrupt
+                    // them.
+                    match Host::connect(&address, platform::ConnectionLimits::default()).await {
+                        Ok(c) => connection = Some(c),
+                        Err(err) => {
+                            log::warn!(
+                                target: "cross-validation",
+                                "Failed to connect to {} in order to cross-validate {} on {}: {}",
+                                address, method, log_name, err
+                            );
+                            continue;
+                        }
+                    }
+                }
+
+                Host::send(
+                    connection.as_mut().unwrap(),
+                    serde_json::Value::Object(full_node_request)
+                        .to_string()
+                        .as_bytes(),
+                );
+
+                let request_key = (user_data, request_id);
+                by_client_request.insert(request_key.clone(), next_request_id);
+                pending.insert(
+                    next_request_id,
+                    Comparison {
+                        request_key,
+                        method,
+                        request,
+                        client: None,
+                        full_node: None,
+                    },
+                );
+                next_request_id += 1;
+            }
+            Event::Foreground(ToBackground::Response {
+                user_data,
+                request_id,
+                outcome,
+            }) => {
+                let id = match by_client_request.remove(&(user_data, request_id)) {
+                    Some(id) => id,
+                    None => continue,
+                };
+
+                let comparison = pending.get_mut(&id).unwrap();
+                comparison.client = Some(outcome);
+                if comparison.full_node.is_some() {
+                    report(&log_name, pending.remove(&id).unwrap());
+                }
+            }
+            Event::Received(len) => {
+                Host::advance_read_cursor(connection.as_mut().unwrap(), len);
+
+                let responses = match extract_values(&mut received) {
+                    Ok(responses) => responses,
+                    Err(()) => {
+                        log::warn!(
+                            target: "cross-validation",
+                            "Invalid JSON received from {}; closing the connection", address
+                        );
+                        connection = None;
+                        abort_pending(&log_name, &mut pending, &mut by_client_request);
+                        received.clear();
+                        continue;
+                    }
+                };
+
+                for response in responses {
+                    // Notifications don't have an `id` field, and are ignored.
+                    let (id, outcome) = match (
+                        response.get("id").and_then(|id| id.as_u64()),
+                        Outcome::from_response(&response),
+                    ) {
+                        (Some(id), Some(outcome)) => (id, outcome),
+                        _ => continue,
+                    };
+
+                    let comparison = match pending.get_mut(&id) {
+                        Some(c) => c,
+                        None => continue,
+                    };
+
+                    comparison.full_node = Some(outcome);
+                    if comparison.client.is_some() {
+                        report(&log_name, pending.remove(&id).unwrap());
+                    }
+                }
+            }
+            Event::Closed => {
+                log::debug!(
+                    target: "cross-validation",
+                    "Connection to {} closed", address
+                );
+                connection = None;
+                abort_pending(&log_name, &mut pending, &mut by_client_request);
+                received.clear();
+            }
+        }
+    }
+}
+
+/// Removes from `pending` the requests that the full node hasn't answered, after the connection
+/// to the full node has been closed.
+fn abort_pending(
+    log_name: &str,
+    pending: &mut HashMap<u64, Comparison>,
+    by_client_request: &mut HashMap<(u32, String), u64>,
+) {
+    let num_before = pending.len();
+    pending.retain(|_, comparison| {
+        if comparison.full_node.is_some() {
+            return true;
+        }
+        by_client_request.remove(&comparison.request_key);
+        false
+    });
+
+    if pending.len() != num_before {
+        log::warn!(
+            target: "cross-validation",
+            "Connection to the full node of {} closed; {} request(s) not cross-validated",
+            log_name, num_before - pending.len()
+        );
+    }
+}
+
+/// Compares the two responses of a request and logs the outcome.
+fn report(log_name: &str, comparison: Comparison) {
+    let (client, full_node) = match (&comparison.client, &comparison.full_node) {
+        (Some(c), Some(f)) => (c, f),
+        _ => unreachable!(),
+    };
+
+    if client.matches(full_node) {
+        log::debug!(
+            target: "cross-validation",
+            "Identical responses to {} on {}", comparison.method, log_name
+        );
+    } else {
+        log::warn!(
+            target: "cross-validation",
+            "Discrepancy in the response to {} on {}. Request: {}. Client: {}. Full node: {}",
+            comparison.method,
+            log_name,
+            preview(&comparison.request),
+            preview(&format!("{:?}", client)),
+            preview(&format!("{:?}", full_node))
+        );
+    }
+}
+
+/// Removes from `buffer` all the complete JSON values found at its beginning, and returns them.
+///
+/// Returns an error if `buffer` doesn't start with valid JSON.
+pub(crate) fn extract_values(buffer: &mut Vec<u8>) -> Result<Vec<serde_json::Value>, ()> {
+    let mut stream = serde_json::Deserializer::from_slice(buffer).into_iter();
+    let mut values = Vec::new();
+
+    loop {
+        match stream.next() {
+            Some(Ok(value)) => values.push(value),
+            Some(Err(err)) if err.is_eof() => break,
+            Some(Err(_)) => return Err(()),
+            None => break,
+        }
+    }
+
+    let consumed = stream.byte_offset();
+    buffer.drain(..consumed);
+    Ok(values)
+}
+
+/// Normalizes the JSON representation of a request identifier, so that it can be compared with
+/// the identifier of a response.
+fn normalize_id(id_json: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(id_json) {
+        Ok(id) => id.to_string(),
+        Err(_) => id_json.to_owned(),
+    }
+}
+
+/// Returns the beginning of the given string, for the log messages.
+fn preview(s: &str) -> &str {
+    let mut end = cmp::min(s.len(), 512);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_values, Outcome};
+
+    #[test]
+    fn extract_values_split() {
+        let mut buffer = br#"{"id":0,"result":"0x12"}{"id":1,"res"#.to_vec();
+        let values = extract_values(&mut buffer).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values[0]["result"], "0x12");
+
+        buffer.extend_from_slice(br#"ult":null} "#);
+        let values = extract_values(&mut buffer).unwrap();
+        assert_eq!(values.len(), 1);
+        assert!(values[0]["result"].is_null());
+        assert!(buffer.is_empty());
+
+        let mut buffer = b"not json".to_vec();
+        assert!(extract_values(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn outcomes_comparison() {
+        let parse = |json: &str| {
+            Outcome::from_response(&serde_json::from_str::<serde_json::Value>(json).unwrap())
+                .unwrap()
+        };
+
+        let success = parse(r#"{"id":0,"result":{"a":1}}"#);
+        assert!(success.matches(&parse(r#"{"id":5,"result":{"a":1}}"#)));
+        assert!(!success.matches(&parse(r#"{"id":0,"result":{"a":2}}"#)));
+        assert!(!success.matches(&parse(r#"{"id":0,"error":{"code":1}}"#)));
+        assert!(parse(r#"{"id":0,"error":{"code":1}}"#)
+            .matches(&parse(r#"{"id":0,"error":{"code":2,"message":"x"}}"#)));
+    }
+}
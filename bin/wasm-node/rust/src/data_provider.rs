@@ -0,0 +1,266 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Sources of chain data used by the runtime service.
+//!
+//! The runtime service needs to follow the best block of the chain, to download block headers,
+//! to read storage items, and to obtain call proofs. Each of these capabilities is represented
+//! by a trait of this module, and [`ChainDataProvider`] combines all of them.
+//!
+//! The sync service of each chain implements these traits by sending requests to the peers of
+//! the network. The tests of this crate instead use a mock chain whose blocks and storage are
+//! held in memory. Alternative implementations can be plugged as well, for example one backed
+//! by a JSON-RPC server or by a local database.
+
+use crate::sync_service::{self, SyncService};
+
+pub use crate::{request_trace::RequestTrace, sync_service::HeaderNotification};
+
+use futures::{future::BoxFuture, prelude::*, stream::BoxStream};
+use smoldot::network::protocol;
+use std::{iter, slice, sync::Arc};
+
+/// Provides information about the blocks of the chain.
+pub trait BlocksProvider: Send + Sync {
+    /// Returns the header of the current best block, plus a stream that yields the header of the
+    /// new best block whenever it changes.
+    ///
+    /// Intermediary best blocks can be skipped if the stream isn't polled often enough.
+    ///
+    /// The stream can end, for example if the provider resets its internal state, in which case
+    /// this method can be called again. An error is returned if the provider is permanently
+    /// unable to provide blocks.
+    fn subscribe_best(
+        &self,
+    ) -> BoxFuture<'_, Result<(HeaderNotification, BoxStream<'static, HeaderNotification>), ()>>;
+
+    /// Returns `true` if the best block is believed to be close to the head of the chain.
+    fn is_near_head_of_chain_heuristic(&self) -> BoxFuture<'_, bool>;
+
+    /// Returns the SCALE-encoded header of the block with the given hash.
+    ///
+    /// Implementations must guarantee that the returned header is valid and hashes to `hash`.
+    fn header_query(self: Arc<Self>, hash: [u8; 32]) -> BoxFuture<'static, Result<Vec<u8>, ()>>;
+}
+
+/// Provides access to the storage of the blocks of the chain.
+pub trait StorageProvider: Send + Sync {
+    /// Returns the values of the given keys in the storage of the given block, or `None` for
+    /// the keys that don't have any value.
+    ///
+    /// The returned `Vec` has the same length as `requested_keys`. Implementations must
+    /// guarantee that the values match the given storage trie root.
+    fn storage_query<'a>(
+        self: Arc<Self>,
+        block_hash: &'a [u8; 32],
+        storage_trie_root: &'a [u8; 32],
+        requested_keys: Vec<Vec<u8>>,
+    ) -> BoxFuture<'a, Result<Vec<Option<Vec<u8>>>, StorageQueryError>>;
+
+    /// Same as [`StorageProvider::storage_query`], but used for critical queries, such as
+    /// downloading the runtime code, for which the implementation is encouraged to trade
+    /// latency for additional confidence in the result.
+    ///
+    /// The default implementation calls [`StorageProvider::storage_query`].
+    ///
+    /// `block_number` is the number of the block whose hash is `block_hash`.
+    fn storage_query_quorum<'a>(
+        self: Arc<Self>,
+        _block_number: u64,
+        block_hash: &'a [u8; 32],
+        storage_trie_root: &'a [u8; 32],
+        requested_keys: Vec<Vec<u8>>,
+    ) -> BoxFuture<'a, Result<Vec<Option<Vec<u8>>>, StorageQueryError>> {
+        self.storage_query(block_hash, storage_trie_root, requested_keys)
+    }
+}
+
+/// Error potentially returned by [`StorageProvider::storage_query`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "{}", message)]
+pub struct StorageQueryError {
+    /// Human-readable description of the error.
+    pub message: String,
+    /// `true` if the error is caused by networking issues, as opposed to for example a
+    /// consensus-related issue.
+    pub network_problem: bool,
+}
+
+impl StorageQueryError {
+    /// Returns `true` if this is caused by networking issues, as opposed to a consensus-related
+    /// issue.
+    pub fn is_network_problem(&self) -> bool {
+        self.network_problem
+    }
+}
+
+impl From<sync_service::StorageQueryError> for StorageQueryError {
+    fn from(error: sync_service::StorageQueryError) -> Self {
+        StorageQueryError {
+            message: error.to_string(),
+            network_problem: error.is_network_problem(),
+        }
+    }
+}
+
+/// Provides call proofs, in other words the storage items accessed by a runtime call.
+pub trait CallProofProvider: Send + Sync {
+    /// Returns the list of trie nodes that the runtime accesses when performing the call
+    /// described by `config` on top of the block with the given number and hash.
+    ///
+    /// The returned proof isn't trusted by the caller, and it isn't necessary to verify it.
+    ///
+    /// If `trace` is `Some`, the call is performed on behalf of a JSON-RPC request, and the
+    /// network requests emitted by the implementation should be recorded in it.
+    fn call_proof_query<'a>(
+        self: Arc<Self>,
+        block_number: u64,
+        config: protocol::CallProofRequestConfig<'a, ParameterVectored<'a>>,
+        trace: Option<&'a RequestTrace>,
+    ) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ()>>;
+
+    /// Returns the list of trie nodes that prove the storage values of the given keys in the
+    /// block with the given number and hash.
+    ///
+    /// Used in order to complete a call proof that doesn't contain all the storage entries that
+    /// the runtime accesses. As with [`CallProofProvider::call_proof_query`], the returned proof
+    /// isn't trusted by the caller.
+    fn storage_proof_query<'a>(
+        self: Arc<Self>,
+        block_number: u64,
+        block_hash: &'a [u8; 32],
+        keys: Vec<Vec<u8>>,
+        trace: Option<&'a RequestTrace>,
+    ) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ()>>;
+}
+
+/// Iterator to the buffers of bytes that, concatenated, form the parameter of a runtime call.
+/// See [`protocol::CallProofRequestConfig::parameter_vectored`].
+pub type ParameterVectored<'a> = iter::Copied<slice::Iter<'a, &'a [u8]>>;
+
+/// Combination of all the traits of this module.
+///
+/// Automatically implemented on all the types that implement these traits.
+pub trait ChainDataProvider: BlocksProvider + StorageProvider + CallProofProvider {}
+
+impl<T: ?Sized + BlocksProvider + StorageProvider + CallProofProvider> ChainDataProvider for T {}
+
+impl BlocksProvider for SyncService {
+    fn subscribe_best(
+        &self,
+    ) -> BoxFuture<'_, Result<(HeaderNotification, BoxStream<'static, HeaderNotification>), ()>>
+    {
+        Box::pin(async move {
+            let (current, stream) = SyncService::try_subscribe_best(self).await?;
+            Ok((current, stream.boxed()))
+        })
+    }
+
+    fn is_near_head_of_chain_heuristic(&self) -> BoxFuture<'_, bool> {
+        Box::pin(SyncService::is_near_head_of_chain_heuristic(self))
+    }
+
+    fn header_query(self: Arc<Self>, hash: [u8; 32]) -> BoxFuture<'static, Result<Vec<u8>, ()>> {
+        Box::pin(async move {
+            let block = self
+                .block_query(
+                    hash,
+                    protocol::BlocksRequestFields {
+                        header: true,
+                        body: false,
+                        justification: false,
+                        indexed_body: false,
+                    },
+                )
+                .await?;
+
+            // Note that the `block_query` method guarantees that the header is present
+            // and valid.
+            Ok(block.header.unwrap())
+        })
+    }
+}
+
+impl StorageProvider for SyncService {
+    fn storage_query<'a>(
+        self: Arc<Self>,
+        block_hash: &'a [u8; 32],
+        storage_trie_root: &'a [u8; 32],
+        requested_keys: Vec<Vec<u8>>,
+    ) -> BoxFuture<'a, Result<Vec<Option<Vec<u8>>>, StorageQueryError>> {
+        Box::pin(async move {
+            SyncService::storage_query(
+                self,
+                block_hash,
+                storage_trie_root,
+                requested_keys.iter(),
+                None,
+            )
+            .await
+            .map_err(StorageQueryError::from)
+        })
+    }
+
+    fn storage_query_quorum<'a>(
+        self: Arc<Self>,
+        block_number: u64,
+        block_hash: &'a [u8; 32],
+        storage_trie_root: &'a [u8; 32],
+        requested_keys: Vec<Vec<u8>>,
+    ) -> BoxFuture<'a, Result<Vec<Option<Vec<u8>>>, StorageQueryError>> {
+        Box::pin(async move {
+            SyncService::storage_query_quorum(
+                self,
+                block_number,
+                block_hash,
+                storage_trie_root,
+                requested_keys.iter(),
+            )
+            .await
+            .map_err(StorageQueryError::from)
+        })
+    }
+}
+
+impl CallProofProvider for SyncService {
+    fn call_proof_query<'a>(
+        self: Arc<Self>,
+        block_number: u64,
+        config: protocol::CallProofRequestConfig<'a, ParameterVectored<'a>>,
+        trace: Option<&'a RequestTrace>,
+    ) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ()>> {
+        Box::pin(async move {
+            SyncService::call_proof_query(self, block_number, config, trace)
+                .await
+                .map_err(|_| ())
+        })
+    }
+
+    fn storage_proof_query<'a>(
+        self: Arc<Self>,
+        block_number: u64,
+        block_hash: &'a [u8; 32],
+        keys: Vec<Vec<u8>>,
+        trace: Option<&'a RequestTrace>,
+    ) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ()>> {
+        Box::pin(async move {
+            SyncService::storage_proof_query(self, block_number, block_hash, keys.iter(), trace)
+                .await
+                .map_err(|_| ())
+        })
+    }
+}
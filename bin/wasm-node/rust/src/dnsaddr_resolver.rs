@@ -0,0 +1,202 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Resolution of `/dnsaddr` multiaddresses through DNS-over-HTTPS.
+//!
+//! A multiaddress of the form `/dnsaddr/example.com` designates the list of multiaddresses found
+//! in the TXT records of the `_dnsaddr.example.com` domain, each of the form
+//! `dnsaddr=<multiaddr>`. These multiaddresses can themselves be `/dnsaddr` multiaddresses.
+//! See <https://github.com/multiformats/multiaddr/blob/master/protocols/DNSADDR.md>.
+//!
+//! Browsers don't provide any way to perform DNS queries. Instead, the queries are sent to a
+//! DNS-over-HTTPS server supporting the JSON API, through
+//! [`crate::platform::Platform::http_fetch`].
+
+use crate::platform::{Host, Platform as _};
+
+use smoldot::libp2p::{
+    multiaddr::{Multiaddr, Protocol},
+    peer_id::PeerId,
+};
+
+/// Maximum number of DNS queries performed when resolving a single multiaddress.
+const MAX_QUERIES: usize = 8;
+
+/// Resolves the given `/dnsaddr` multiaddress into a list of concrete multiaddresses of the
+/// node whose identity is `peer_id`, by sending queries to the DNS-over-HTTPS server at
+/// `doh_url`.
+///
+/// `multiaddr` must not contain the `/p2p` suffix.
+///
+/// Only WebSocket multiaddresses are returned, as they are the only ones that browsers can
+/// connect to. The returned multiaddresses don't contain the `/p2p` suffix.
+pub async fn resolve(
+    doh_url: &str,
+    multiaddr: &Multiaddr,
+    peer_id: &PeerId,
+) -> Result<Vec<Multiaddr>, ResolveError> {
+    let mut to_resolve = vec![multiaddr.clone()];
+    let mut num_queries = 0;
+    let mut out = Vec::new();
+
+    while let Some(multiaddr) = to_resolve.pop() {
+        let domain = match multiaddr.iter().next() {
+            Some(Protocol::Dnsaddr(domain)) if multiaddr.iter().count() == 1 => domain,
+            _ => {
+                // Multiaddresses other than `/dnsaddr` are reported as is.
+                if multiaddr
+                    .iter()
+                    .any(|p| matches!(p, Protocol::Ws(_) | Protocol::Wss(_)))
+                {
+                    out.push(multiaddr);
+                }
+                continue;
+            }
+        };
+
+        if num_queries >= MAX_QUERIES {
+            return Err(ResolveError::TooManyQueries);
+        }
+        num_queries += 1;
+
+        let url = format!(
+            "{}{}name=_dnsaddr.{}&type=TXT",
+            doh_url,
+            if doh_url.contains('?') { '&' } else { '?' },
+            domain
+        );
+
+        let response = Host::http_fetch(&url, "application/dns-json")
+            .await
+            .map_err(ResolveError::Request)?;
+
+        for record in decode_txt_records(&response).ok_or(ResolveError::InvalidResponse)? {
+            let mut address = match record
+                .strip_prefix("dnsaddr=")
+                .and_then(|a| a.parse::<Multiaddr>().ok())
+            {
+                Some(a) => a,
+                None => continue,
+            };
+
+            // Entries that concern a different peer are ignored, as the same domain can contain
+            // the addresses of multiple nodes.
+            match address.pop() {
+                Some(Protocol::P2p(hash)) => match PeerId::from_multihash(hash) {
+                    Ok(p) if p == *peer_id => {}
+                    _ => continue,
+                },
+                Some(other) => address.push(other),
+                None => continue,
+            }
+
+            to_resolve.push(address);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Error potentially returned by [`resolve`].
+#[derive(Debug, derive_more::Display)]
+pub enum ResolveError {
+    /// Error while sending the request to the DNS-over-HTTPS server.
+    #[display(fmt = "DNS-over-HTTPS request failed: {}", _0)]
+    Request(String),
+    /// The DNS-over-HTTPS server has sent back an invalid response.
+    #[display(fmt = "Invalid DNS-over-HTTPS response")]
+    InvalidResponse,
+    /// Resolving the multiaddress requires too many queries.
+    #[display(fmt = "Too many nested dnsaddr records")]
+    TooManyQueries,
+}
+
+/// Decodes a response of the JSON API of a DNS-over-HTTPS server and returns the content of the
+/// TXT records it contains. Returns `None` if the response is invalid.
+fn decode_txt_records(response: &[u8]) -> Option<Vec<String>> {
+    /// DNS record type of TXT records.
+    const TXT_RECORD_TYPE: u64 = 16;
+
+    let response: serde_json::Value = serde_json::from_slice(response).ok()?;
+
+    // A missing `Answer` field means that there isn't any record.
+    let answers = match response.get("Answer") {
+        Some(a) => a.as_array()?,
+        None => return Some(Vec::new()),
+    };
+
+    let mut out = Vec::with_capacity(answers.len());
+    for answer in answers {
+        if answer.get("type").and_then(|t| t.as_u64()) != Some(TXT_RECORD_TYPE) {
+            continue;
+        }
+
+        // The content of TXT records is made of one or more strings, each surrounded with
+        // quotes, which must be concatenated. Some servers omit the quotes altogether.
+        let data = answer.get("data")?.as_str()?;
+        if data.starts_with('"') {
+            out.push(
+                data.split('"')
+                    .enumerate()
+                    .filter(|(n, _)| n % 2 == 1)
+                    .map(|(_, s)| s)
+                    .collect::<String>(),
+            );
+        } else {
+            out.push(data.to_owned());
+        }
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode_txt_records_basic() {
+        let response = br#"{
+            "Status": 0,
+            "Answer": [
+                {"name": "_dnsaddr.example.com.", "type": 16, "TTL": 60, "data": "\"dnsaddr=/dns/a.example.com/tcp/443/wss\""},
+                {"name": "_dnsaddr.example.com.", "type": 5, "TTL": 60, "data": "other.example.com."},
+                {"name": "_dnsaddr.example.com.", "type": 16, "TTL": 60, "data": "\"dnsaddr=/dns/b.exa\" \"mple.com/tcp/443/wss\""},
+                {"name": "_dnsaddr.example.com.", "type": 16, "TTL": 60, "data": "dnsaddr=/dnsaddr/c.example.com"}
+            ]
+        }"#;
+
+        assert_eq!(
+            super::decode_txt_records(response).unwrap(),
+            vec![
+                "dnsaddr=/dns/a.example.com/tcp/443/wss".to_owned(),
+                "dnsaddr=/dns/b.example.com/tcp/443/wss".to_owned(),
+                "dnsaddr=/dnsaddr/c.example.com".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_txt_records_no_answer() {
+        let response = br#"{"Status": 3}"#;
+        assert!(super::decode_txt_records(response).unwrap().is_empty());
+    }
+
+    #[test]
+    fn decode_txt_records_invalid() {
+        assert!(super::decode_txt_records(b"not json").is_none());
+        assert!(super::decode_txt_records(br#"{"Answer": 5}"#).is_none());
+    }
+}
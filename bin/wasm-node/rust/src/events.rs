@@ -0,0 +1,394 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Events reported to the embedder of the client, and the versions of their format.
+//!
+//! The services report events in the form of Rust types, such as
+//! [`network_service::PeerEvent`]. These events are only converted to JSON, which is part of the
+//! public API of the client, when they are passed to the embedder. In order to let embedders
+//! migrate from one format to the next at their own pace, multiple versions of the format are
+//! supported at the same time, and the embedder picks the one it understands. See [`Version`].
+//!
+//! Using a version that is deprecated prints a warning, once, through the `log` crate. The
+//! embedder receives it like any other log entry. See [`warn_deprecated`].
+
+use crate::network_service;
+
+use smoldot::{json_rpc::methods, network::protocol};
+use std::{collections::HashSet, sync::Mutex};
+
+/// Version of the format of the events passed to the embedder.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Version {
+    /// Original format, where all the fields of an event are found at the root of a flat JSON
+    /// object. Deprecated in favor of [`Version::V2`].
+    V1,
+    /// Every event contains a `version` field equal to `2`. Information is grouped into nested
+    /// objects, such as `peer` or `bestBlock`, that have the same fields in all the events.
+    V2,
+}
+
+impl Version {
+    /// Returns the version corresponding to the given number, as passed through the FFI
+    /// boundary. Returns `None` if the version isn't supported.
+    pub fn from_u32(version: u32) -> Option<Self> {
+        match version {
+            1 => Some(Version::V1),
+            2 => Some(Version::V2),
+            _ => None,
+        }
+    }
+
+    /// Returns true if this version is deprecated and will be removed in the future.
+    pub fn is_deprecated(&self) -> bool {
+        matches!(self, Version::V1)
+    }
+}
+
+/// Converts the given event to JSON, in the given version of the format. See
+/// [`crate::ffi::bindings::peer_event`].
+///
+/// Also returns the index of the chain the event relates to, as found in the event.
+pub fn peer_event_json(
+    event: &network_service::PeerEvent,
+    version: Version,
+) -> (serde_json::Value, usize) {
+    match version {
+        Version::V1 => peer_event_v1(event),
+        Version::V2 => peer_event_v2(event),
+    }
+}
+
+/// Prints a warning indicating that `feature` is deprecated, and that `replacement` should be
+/// used instead. Does nothing if a warning has already been printed for this `feature`.
+///
+/// Returns `true` if the warning has been printed.
+pub fn warn_deprecated(feature: &'static str, replacement: &'static str) -> bool {
+    lazy_static::lazy_static! {
+        static ref WARNED: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+    }
+
+    if !WARNED.lock().unwrap().insert(feature) {
+        return false;
+    }
+
+    log::warn!(
+        target: "deprecation",
+        "{} is deprecated and will be removed in a future version. Use {} instead.",
+        feature,
+        replacement
+    );
+    true
+}
+
+fn peer_event_v1(event: &network_service::PeerEvent) -> (serde_json::Value, usize) {
+    match event {
+        network_service::PeerEvent::Connected(info) => (
+            serde_json::json!({
+                "kind": "connected",
+                "peerId": info.peer_id.to_string(),
+                "role": role(info.role),
+                "bestNumber": info.best_block_number,
+                "bestHash": methods::HashHexString(info.best_block_hash),
+            }),
+            info.chain_index,
+        ),
+        network_service::PeerEvent::Disconnected {
+            peer_id,
+            chain_index,
+            reason,
+        } => (
+            serde_json::json!({
+                "kind": "disconnected",
+                "peerId": peer_id.to_string(),
+                "reason": disconnect_reason(*reason),
+            }),
+            *chain_index,
+        ),
+        network_service::PeerEvent::BestBlockUpdate {
+            peer_id,
+            chain_index,
+            best_block_number,
+            best_block_hash,
+        } => (
+            serde_json::json!({
+                "kind": "best-block",
+                "peerId": peer_id.to_string(),
+                "bestNumber": best_block_number,
+                "bestHash": methods::HashHexString(*best_block_hash),
+            }),
+            *chain_index,
+        ),
+        network_service::PeerEvent::GenesisMismatch {
+            peer_id,
+            chain_index,
+            genesis_hash,
+            num_mismatches,
+        } => (
+            serde_json::json!({
+                "kind": "genesis-mismatch",
+                "peerId": peer_id.to_string(),
+                "genesisHash": methods::HashHexString(*genesis_hash),
+                "numMismatches": num_mismatches,
+            }),
+            *chain_index,
+        ),
+        network_service::PeerEvent::ChainSpecMismatch {
+            chain_index,
+            genesis_hash,
+        } => (
+            serde_json::json!({
+                "kind": "chain-spec-mismatch",
+                "genesisHash": methods::HashHexString(*genesis_hash),
+            }),
+            *chain_index,
+        ),
+        network_service::PeerEvent::StateRootMismatch {
+            peer_id,
+            proof_peer_id,
+            chain_index,
+            block_hash,
+            expected_state_root,
+            reported_state_root,
+            ..
+        } => (
+            serde_json::json!({
+                "kind": "state-root-mismatch",
+                "peerId": peer_id.to_string(),
+                "proofPeerId": proof_peer_id.to_string(),
+                "blockHash": methods::HashHexString(*block_hash),
+                "expectedStateRoot": methods::HashHexString(*expected_state_root),
+                "reportedStateRoot": methods::HashHexString(*reported_state_root),
+            }),
+            *chain_index,
+        ),
+    }
+}
+
+fn peer_event_v2(event: &network_service::PeerEvent) -> (serde_json::Value, usize) {
+    let (mut json, chain_index) = match event {
+        network_service::PeerEvent::Connected(info) => (
+            serde_json::json!({
+                "kind": "peer-connected",
+                "peer": {
+                    "peerId": info.peer_id.to_string(),
+                    "role": role(info.role),
+                },
+                "bestBlock": {
+                    "number": info.best_block_number,
+                    "hash": methods::HashHexString(info.best_block_hash),
+                },
+            }),
+            info.chain_index,
+        ),
+        network_service::PeerEvent::Disconnected {
+            peer_id,
+            chain_index,
+            reason,
+        } => (
+            serde_json::json!({
+                "kind": "peer-disconnected",
+                "peer": { "peerId": peer_id.to_string() },
+                "reason": disconnect_reason(*reason),
+            }),
+            *chain_index,
+        ),
+        network_service::PeerEvent::BestBlockUpdate {
+            peer_id,
+            chain_index,
+            best_block_number,
+            best_block_hash,
+        } => (
+            serde_json::json!({
+                "kind": "peer-best-block",
+                "peer": { "peerId": peer_id.to_string() },
+                "bestBlock": {
+                    "number": best_block_number,
+                    "hash": methods::HashHexString(*best_block_hash),
+                },
+            }),
+            *chain_index,
+        ),
+        network_service::PeerEvent::GenesisMismatch {
+            peer_id,
+            chain_index,
+            genesis_hash,
+            num_mismatches,
+        } => (
+            serde_json::json!({
+                "kind": "peer-genesis-mismatch",
+                "peer": { "peerId": peer_id.to_string() },
+                "genesisHash": methods::HashHexString(*genesis_hash),
+                "numMismatches": num_mismatches,
+            }),
+            *chain_index,
+        ),
+        network_service::PeerEvent::ChainSpecMismatch {
+            chain_index,
+            genesis_hash,
+        } => (
+            serde_json::json!({
+                "kind": "chain-spec-mismatch",
+                "genesisHash": methods::HashHexString(*genesis_hash),
+            }),
+            *chain_index,
+        ),
+        network_service::PeerEvent::StateRootMismatch {
+            peer_id,
+            proof_peer_id,
+            chain_index,
+            block_number,
+            block_hash,
+            expected_state_root,
+            reported_block_hash,
+            reported_state_root,
+        } => (
+            serde_json::json!({
+                "kind": "state-root-mismatch",
+                "block": {
+                    "number": block_number,
+                    "hash": methods::HashHexString(*block_hash),
+                },
+                "header": {
+                    "peer": { "peerId": peer_id.to_string() },
+                    "hash": methods::HashHexString(*reported_block_hash),
+                    "stateRoot": methods::HashHexString(*reported_state_root),
+                },
+                "proof": {
+                    "peer": { "peerId": proof_peer_id.to_string() },
+                    "stateRoot": methods::HashHexString(*expected_state_root),
+                },
+            }),
+            *chain_index,
+        ),
+    };
+
+    json.as_object_mut()
+        .unwrap()
+        .insert("version".to_owned(), 2.into());
+    (json, chain_index)
+}
+
+fn role(role: protocol::Role) -> &'static str {
+    match role {
+        protocol::Role::Full => "full",
+        protocol::Role::Light => "light",
+        protocol::Role::Authority => "authority",
+    }
+}
+
+fn disconnect_reason(reason: network_service::DisconnectReason) -> &'static str {
+    match reason {
+        network_service::DisconnectReason::ConnectionClosed => "connection-closed",
+        network_service::DisconnectReason::ChainSubstreamClosed => "chain-substream-closed",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{peer_event_json, warn_deprecated, Version};
+    use crate::network_service::{DisconnectReason, PeerEvent, PeerInfo};
+    use smoldot::{libp2p::peer_id::PeerId, network::protocol};
+
+    fn peer_id() -> PeerId {
+        PeerId::from_public_key(&smoldot::libp2p::peer_id::PublicKey::Ed25519([0; 32]))
+    }
+
+    #[test]
+    fn v1_format_unchanged() {
+        let (json, chain_index) = peer_event_json(
+            &PeerEvent::BestBlockUpdate {
+                peer_id: peer_id(),
+                chain_index: 3,
+                best_block_number: 12,
+                best_block_hash: [0xab; 32],
+            },
+            Version::V1,
+        );
+        assert_eq!(chain_index, 3);
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "kind": "best-block",
+                "peerId": peer_id().to_string(),
+                "bestNumber": 12,
+                "bestHash": "0x".to_owned() + &"ab".repeat(32),
+            })
+        );
+    }
+
+    #[test]
+    fn v2_format() {
+        let (json, chain_index) = peer_event_json(
+            &PeerEvent::Connected(PeerInfo {
+                peer_id: peer_id(),
+                chain_index: 1,
+                role: protocol::Role::Full,
+                agent_version: None,
+                protocols: Vec::new(),
+                best_block_number: 12,
+                best_block_hash: [0xab; 32],
+            }),
+            Version::V2,
+        );
+        assert_eq!(chain_index, 1);
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "version": 2,
+                "kind": "peer-connected",
+                "peer": {
+                    "peerId": peer_id().to_string(),
+                    "role": "full",
+                },
+                "bestBlock": {
+                    "number": 12,
+                    "hash": "0x".to_owned() + &"ab".repeat(32),
+                },
+            })
+        );
+
+        let (json, _) = peer_event_json(
+            &PeerEvent::Disconnected {
+                peer_id: peer_id(),
+                chain_index: 1,
+                reason: DisconnectReason::ChainSubstreamClosed,
+            },
+            Version::V2,
+        );
+        assert_eq!(json["kind"], "peer-disconnected");
+        assert_eq!(json["reason"], "chain-substream-closed");
+        assert_eq!(json["version"], 2);
+    }
+
+    #[test]
+    fn versions() {
+        assert_eq!(Version::from_u32(1), Some(Version::V1));
+        assert_eq!(Version::from_u32(2), Some(Version::V2));
+        assert_eq!(Version::from_u32(0), None);
+        assert_eq!(Version::from_u32(3), None);
+        assert!(Version::V1.is_deprecated());
+        assert!(!Version::V2.is_deprecated());
+    }
+
+    #[test]
+    fn deprecation_warned_once() {
+        assert!(warn_deprecated("test feature", "something else"));
+        assert!(!warn_deprecated("test feature", "something else"));
+        assert!(warn_deprecated("other test feature", "something else"));
+    }
+}
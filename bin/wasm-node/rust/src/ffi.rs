@@ -17,12 +17,19 @@
 
 // TODO: the quality of this module is sub-par
 
+// Outside of Wasm, the rest of the code goes through another `Platform` implementation and most
+// of the functions below are unused.
+#![cfg_attr(not(target_arch = "wasm32"), allow(dead_code))]
+
+use super::platform::{ConnectionLimits, JsonRpcMessage, Transport};
+
 use core::{
     cmp::Ordering,
     convert::TryFrom as _,
     fmt,
     future::Future,
     marker,
+    num::{NonZeroU32, NonZeroUsize},
     ops::{Add, Sub},
     pin::Pin,
     slice, str,
@@ -60,10 +67,206 @@ pub(crate) fn throw(message: String) -> ! {
 }
 
 /// Returns the duration elapsed since the UNIX epoch, ignoring leap seconds.
-pub(crate) fn unix_time() -> Duration {
+pub fn unix_time() -> Duration {
     Duration::from_secs_f64(unsafe { bindings::unix_time_ms() } / 1000.0)
 }
 
+/// Value of the `host_crypto_flags` parameter passed to [`bindings::init`].
+static HOST_CRYPTO_FLAGS: atomic::AtomicU32 = atomic::AtomicU32::new(0);
+
+/// Value of the `compression_flags` parameter passed to [`bindings::init`].
+static COMPRESSION_FLAGS: atomic::AtomicU32 = atomic::AtomicU32::new(0);
+
+/// Value of the `supported_transports` parameter passed to [`bindings::init`].
+static SUPPORTED_TRANSPORTS: atomic::AtomicU32 = atomic::AtomicU32::new(0);
+
+/// Returns `true` if the host has indicated that it is capable of opening connections using
+/// this transport.
+pub fn is_transport_supported(transport: Transport) -> bool {
+    let flag = match transport {
+        Transport::Tcp => bindings::TRANSPORT_TCP,
+        Transport::WebSocket => bindings::TRANSPORT_WS,
+        Transport::SecureWebSocket => bindings::TRANSPORT_WSS,
+    };
+
+    SUPPORTED_TRANSPORTS.load(atomic::Ordering::Relaxed) & flag != 0
+}
+
+/// Verifies an sr25519 signature using the host-provided implementation.
+///
+/// Returns `None` if the host hasn't indicated that it supports this operation, in which case
+/// the verification must be performed locally.
+///
+/// The signature context is always `b"substrate"`.
+pub(crate) fn host_sr25519_verify(
+    signature: &[u8; 64],
+    message: &[u8],
+    public_key: &[u8; 32],
+) -> Option<bool> {
+    if HOST_CRYPTO_FLAGS.load(atomic::Ordering::Relaxed) & bindings::HOST_CRYPTO_SR25519_VERIFY == 0
+    {
+        return None;
+    }
+
+    let outcome = unsafe {
+        bindings::host_sr25519_verify(
+            u32::try_from(signature.as_ptr() as usize).unwrap(),
+            u32::try_from(message.as_ptr() as usize).unwrap(),
+            u32::try_from(message.len()).unwrap(),
+            u32::try_from(public_key.as_ptr() as usize).unwrap(),
+        )
+    };
+
+    Some(outcome != 0)
+}
+
+/// Same as [`host_sr25519_verify`], but for the deprecated variant of sr25519. See
+/// [`bindings::host_sr25519_verify_deprecated`].
+pub(crate) fn host_sr25519_verify_deprecated(
+    signature: &[u8; 64],
+    message: &[u8],
+    public_key: &[u8; 32],
+) -> Option<bool> {
+    if HOST_CRYPTO_FLAGS.load(atomic::Ordering::Relaxed)
+        & bindings::HOST_CRYPTO_SR25519_VERIFY_DEPRECATED
+        == 0
+    {
+        return None;
+    }
+
+    let outcome = unsafe {
+        bindings::host_sr25519_verify_deprecated(
+            u32::try_from(signature.as_ptr() as usize).unwrap(),
+            u32::try_from(message.as_ptr() as usize).unwrap(),
+            u32::try_from(message.len()).unwrap(),
+            u32::try_from(public_key.as_ptr() as usize).unwrap(),
+        )
+    };
+
+    Some(outcome != 0)
+}
+
+/// Verifies the VRF proof of a Babe header using the host-provided implementation.
+///
+/// Returns `None` if the host hasn't indicated that it supports this operation, in which case
+/// the verification must be performed locally.
+pub(crate) fn host_babe_vrf_verify(
+    input: smoldot::verify::babe::VrfVerifyRef,
+) -> Option<Result<[u8; 16], ()>> {
+    if HOST_CRYPTO_FLAGS.load(atomic::Ordering::Relaxed) & bindings::HOST_CRYPTO_BABE_VRF_VERIFY
+        == 0
+    {
+        return None;
+    }
+
+    let mut transcript = [0; 48];
+    transcript[..8].copy_from_slice(&input.slot_number.to_le_bytes());
+    transcript[8..16].copy_from_slice(&input.epoch_index.to_le_bytes());
+    transcript[16..].copy_from_slice(input.randomness);
+
+    let mut out = [0; 16];
+    let outcome = unsafe {
+        bindings::host_babe_vrf_verify(
+            u32::try_from(input.public_key.as_ptr() as usize).unwrap(),
+            u32::try_from(transcript.as_ptr() as usize).unwrap(),
+            u32::try_from(input.vrf_output.as_ptr() as usize).unwrap(),
+            u32::try_from(input.vrf_proof.as_ptr() as usize).unwrap(),
+            u32::try_from(out.as_mut_ptr() as usize).unwrap(),
+        )
+    };
+
+    Some(if outcome != 0 { Ok(out) } else { Err(()) })
+}
+
+/// Asks the host to verify digest items of custom consensus engines found in a header of the
+/// given chain. See [`bindings::verify_custom_digest_items`].
+pub(crate) fn verify_custom_digest_items(
+    chain_index: usize,
+    items: &[smoldot::header::CustomDigestItem],
+) -> bool {
+    let items = items
+        .iter()
+        .map(|item| {
+            serde_json::json!({
+                "kind": match item.kind {
+                    smoldot::header::CustomDigestItemKind::PreRuntime => "preRuntime",
+                    smoldot::header::CustomDigestItemKind::Consensus => "consensus",
+                    smoldot::header::CustomDigestItemKind::Seal => "seal",
+                },
+                "engineId": String::from_utf8_lossy(&item.engine_id),
+                "data": smoldot::json_rpc::methods::HexString(item.opaque.clone()),
+            })
+        })
+        .collect::<Vec<_>>();
+    let items = serde_json::to_string(&items).unwrap();
+
+    let outcome = unsafe {
+        bindings::verify_custom_digest_items(
+            u32::try_from(chain_index).unwrap(),
+            u32::try_from(items.as_ptr() as usize).unwrap(),
+            u32::try_from(items.len()).unwrap(),
+        )
+    };
+
+    outcome != 0
+}
+
+/// Calculates the 32 bytes BLAKE2b hash of the given data, using the host-provided
+/// implementation if the host has indicated that it supports this operation.
+pub(crate) fn blake2_256(data: &[u8]) -> [u8; 32] {
+    if HOST_CRYPTO_FLAGS.load(atomic::Ordering::Relaxed) & bindings::HOST_CRYPTO_BLAKE2_256 == 0 {
+        let mut out = [0; 32];
+        out.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes());
+        return out;
+    }
+
+    let mut out = [0; 32];
+    unsafe {
+        bindings::host_blake2_256(
+            u32::try_from(data.as_ptr() as usize).unwrap(),
+            u32::try_from(data.len()).unwrap(),
+            u32::try_from(out.as_mut_ptr() as usize).unwrap(),
+        );
+    }
+    out
+}
+
+/// Decompresses zstandard frames using the host-provided implementation.
+///
+/// Returns `None` if the host hasn't indicated that it supports this operation, in which case
+/// the decompression must be performed locally.
+pub(crate) fn host_zstd_decompress(data: &[u8], max_size: usize) -> Option<Result<Vec<u8>, ()>> {
+    if COMPRESSION_FLAGS.load(atomic::Ordering::Relaxed) & bindings::COMPRESSION_HOST_DECOMPRESS
+        == 0
+    {
+        return None;
+    }
+
+    let mut out_ptr = [0u8; 8];
+    let outcome = unsafe {
+        bindings::host_zstd_decompress(
+            u32::try_from(data.as_ptr() as usize).unwrap(),
+            u32::try_from(data.len()).unwrap(),
+            u32::try_from(max_size).unwrap_or(u32::max_value()),
+            u32::try_from(&mut out_ptr as *mut [u8; 8] as usize).unwrap(),
+        )
+    };
+
+    if outcome == 0 {
+        return Some(Err(()));
+    }
+
+    let ptr = u32::from_le_bytes(<[u8; 4]>::try_from(&out_ptr[0..4]).unwrap());
+    let len = u32::from_le_bytes(<[u8; 4]>::try_from(&out_ptr[4..8]).unwrap());
+    let decompressed: Box<[u8]> = unsafe {
+        Box::from_raw(slice::from_raw_parts_mut(
+            usize::try_from(ptr).unwrap() as *mut u8,
+            usize::try_from(len).unwrap(),
+        ))
+    };
+    Some(Ok(decompressed.into_vec()))
+}
+
 /// Spawn a background task that runs forever.
 fn spawn_task(future: impl Future<Output = ()> + Send + 'static) {
     struct Waker {
@@ -119,6 +322,50 @@ fn start_timer_wrap(duration: Duration, closure: impl FnOnce()) {
     unsafe { bindings::start_timer(timer_id, (milliseconds as f64).ceil()) }
 }
 
+/// Sends an HTTP `GET` request to the given URL, with the `Accept` header set to the given value.
+/// Returns the body of the response on success, or an error message on failure.
+///
+/// See [`bindings::http_fetch`].
+pub(crate) fn http_fetch(url: &str, accept: &str) -> impl Future<Output = Result<Vec<u8>, String>> {
+    let (tx, rx) = oneshot::channel();
+
+    let callback: Box<oneshot::Sender<Result<Vec<u8>, String>>> = Box::new(tx);
+    let id = u32::try_from(Box::into_raw(callback) as usize).unwrap();
+
+    unsafe {
+        bindings::http_fetch(
+            id,
+            u32::try_from(url.as_bytes().as_ptr() as usize).unwrap(),
+            u32::try_from(url.as_bytes().len()).unwrap(),
+            u32::try_from(accept.as_bytes().as_ptr() as usize).unwrap(),
+            u32::try_from(accept.as_bytes().len()).unwrap(),
+        );
+    }
+
+    rx.map(|result| result.unwrap())
+}
+
+/// Asks the host for the runtime code whose BLAKE2-256 hash is `code_hash`. Returns the code on
+/// success, or an error message on failure.
+///
+/// The returned code isn't verified to match the hash.
+///
+/// See [`bindings::code_substitute_fetch`].
+pub(crate) fn code_substitute_fetch(
+    code_hash: &[u8; 32],
+) -> impl Future<Output = Result<Vec<u8>, String>> {
+    let (tx, rx) = oneshot::channel();
+
+    let callback: Box<oneshot::Sender<Result<Vec<u8>, String>>> = Box::new(tx);
+    let id = u32::try_from(Box::into_raw(callback) as usize).unwrap();
+
+    unsafe {
+        bindings::code_substitute_fetch(id, u32::try_from(code_hash.as_ptr() as usize).unwrap());
+    }
+
+    rx.map(|result| result.unwrap())
+}
+
 // TODO: cancel the timer if the `Delay` is destroyed? we create and destroy a lot of `Delay`s
 pub struct Delay {
     rx: oneshot::Receiver<()>,
@@ -216,40 +463,32 @@ impl Sub<Instant> for Instant {
     fn sub(self, other: Instant) -> Duration {
         let ms = self.inner - other.inner;
         assert!(ms >= 0.0);
-        Duration::from_millis(ms as u64)
+        // The clock of the host has a sub-millisecond precision, which is preserved.
+        Duration::from_nanos((ms * 1_000_000.0) as u64)
     }
 }
 
-/// Implementation of [`log::Log`] that sends out logs to the FFI.
-pub(crate) struct Logger;
+/// Sends out the given log message to the FFI.
+pub(crate) fn log(record: &log::Record) {
+    let target = record.target();
+    let message = format!("{}", record.args());
 
-impl log::Log for Logger {
-    fn enabled(&self, _: &log::Metadata) -> bool {
-        true
-    }
-
-    fn log(&self, record: &log::Record) {
-        let target = record.target();
-        let message = format!("{}", record.args());
-
-        unsafe {
-            bindings::log(
-                record.level() as usize as u32,
-                u32::try_from(target.as_bytes().as_ptr() as usize).unwrap(),
-                u32::try_from(target.as_bytes().len()).unwrap(),
-                u32::try_from(message.as_bytes().as_ptr() as usize).unwrap(),
-                u32::try_from(message.as_bytes().len()).unwrap(),
-            )
-        }
+    unsafe {
+        bindings::log(
+            record.level() as usize as u32,
+            u32::try_from(target.as_bytes().as_ptr() as usize).unwrap(),
+            u32::try_from(target.as_bytes().len()).unwrap(),
+            u32::try_from(message.as_bytes().as_ptr() as usize).unwrap(),
+            u32::try_from(message.as_bytes().len()).unwrap(),
+        )
     }
-
-    fn flush(&self) {}
 }
 
 /// Connection connected to a target.
 pub struct Connection {
     /// If `Some`, [`bindings::connection_close`] must be called. Set to a value after
-    /// [`bindings::connection_new`] returns success.
+    /// [`bindings::connection_new`] returns success, and set back to `None` if the connection
+    /// is closed because the [`ConnectionLimits`] have been exceeded.
     id: Option<u32>,
     /// True if [`bindings::connection_open`] has been called.
     open: bool,
@@ -262,13 +501,20 @@ pub struct Connection {
     messages_queue_first_offset: usize,
     /// Waker to wake up whenever one of the fields above is modified.
     waker: Option<Waker>,
+    /// Limits passed to [`Connection::connect`].
+    limits: ConnectionLimits,
     /// Prevents the [`Connection`] from being unpinned.
     _pinned: marker::PhantomPinned,
 }
 
 impl Connection {
     /// Connects to the given URL. Returns a [`Connection`] on success.
-    pub fn connect(url: &str) -> impl Future<Output = Result<Pin<Box<Self>>, String>> {
+    ///
+    /// See [`ConnectionLimits`] for the meaning of `limits`.
+    pub fn connect(
+        url: &str,
+        limits: ConnectionLimits,
+    ) -> impl Future<Output = Result<Pin<Box<Self>>, String>> {
         let mut pointer = Box::pin(Connection {
             id: None,
             open: false,
@@ -276,6 +522,7 @@ impl Connection {
             messages_queue: VecDeque::with_capacity(32),
             messages_queue_first_offset: 0,
             waker: None,
+            limits,
             _pinned: marker::PhantomPinned,
         });
 
@@ -395,7 +642,9 @@ impl Connection {
         };
     }
 
-    /// Queues the given buffer. For WebSocket connections, queues it as a binary frame.
+    /// Queues the given buffer. For WebSocket connections, queues it as a binary frame, or as
+    /// multiple binary frames if it is larger than
+    /// [`ConnectionLimits::max_outbound_message_size`].
     pub fn send(self: &mut Pin<Box<Self>>, data: &[u8]) {
         unsafe {
             let this = Pin::get_unchecked_mut(self.as_mut());
@@ -405,20 +654,20 @@ impl Connection {
                 return;
             }
 
-            bindings::connection_send(
-                this.id.unwrap(),
-                u32::try_from(data.as_ptr() as usize).unwrap(),
-                u32::try_from(data.len()).unwrap(),
-            );
+            for message in this.limits.split_outbound(data) {
+                bindings::connection_send(
+                    this.id.unwrap(),
+                    u32::try_from(message.as_ptr() as usize).unwrap(),
+                    u32::try_from(message.len()).unwrap(),
+                );
+            }
         }
     }
 }
 
 impl fmt::Debug for Connection {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_tuple("Connection")
-            .field(self.id.as_ref().unwrap())
-            .finish()
+        f.debug_tuple("Connection").field(&self.id).finish()
     }
 }
 
@@ -442,7 +691,255 @@ fn alloc(len: u32) -> u32 {
     u32::try_from(ptr as *mut u8 as usize).unwrap()
 }
 
-fn init(chain_specs_pointers_ptr: u32, chain_specs_pointers_len: u32, max_log_level: u32) {
+/// Decodes the JSON configuration of a chain passed to [`init`], whose specification is
+/// `specification`. See the documentation of [`bindings::init`].
+///
+/// `default_max_runtime_memory_pages` is used if the configuration doesn't contain any
+/// `maxRuntimeMemoryPages` field.
+///
+/// Returns `None` if the configuration is invalid.
+fn decode_chain_config(
+    specification: String,
+    config: &[u8],
+    default_max_runtime_memory_pages: Option<u32>,
+) -> Option<super::ChainConfig> {
+    let config: serde_json::Value = if config.is_empty() {
+        serde_json::Value::Object(Default::default())
+    } else {
+        serde_json::from_slice(config).ok()?
+    };
+    let config = config.as_object()?;
+
+    // Returns the given field, or `None` if it is missing or `null`.
+    let field = |name: &str| match config.get(name) {
+        Some(serde_json::Value::Null) | None => None,
+        Some(value) => Some(value),
+    };
+    // Returns the value of the given boolean field, or `Some(false)` if it is missing.
+    let flag = |name: &str| match field(name) {
+        Some(value) => value.as_bool(),
+        None => Some(false),
+    };
+    // Returns the value of the given numeric field, or `Some(0)` if it is missing.
+    let number = |name: &str| match field(name) {
+        Some(value) => usize::try_from(value.as_u64()?).ok(),
+        None => Some(0),
+    };
+
+    Some(super::ChainConfig {
+        specification,
+        json_rpc_running: true,
+        json_rpc_methods_filter: match field("jsonRpcMethodsFilter") {
+            Some(filter) => decode_methods_filter(filter)?,
+            None => Default::default(),
+        },
+        cpu_weight: NonZeroU32::new(u32::try_from(number("cpuWeight")?).ok()?)
+            .unwrap_or(NonZeroU32::new(1).unwrap()),
+        sync_mode: match field("syncMode") {
+            None => super::sync_service::SyncMode::HeadersAndJustifications,
+            Some(mode) if mode.as_str() == Some("headersAndJustifications") => {
+                super::sync_service::SyncMode::HeadersAndJustifications
+            }
+            Some(mode) if mode.as_str() == Some("headers") => {
+                super::sync_service::SyncMode::HeadersOnly
+            }
+            Some(mode) => super::sync_service::SyncMode::RecentBodies {
+                num_blocks: NonZeroU32::new(
+                    u32::try_from(mode.get("recentBodies")?.as_u64()?).ok()?,
+                )
+                .unwrap_or(NonZeroU32::new(1).unwrap()),
+            },
+        },
+        quorum_size: NonZeroUsize::new(number("quorumSize")?)
+            .unwrap_or(NonZeroUsize::new(1).unwrap()),
+        lazy: flag("lazyStart")?,
+        lazy_idle_timeout: match number("lazyIdleTimeout")? {
+            0 => None,
+            ms => Some(Duration::from_millis(u64::try_from(ms).ok()?)),
+        },
+        isolated_network: flag("isolatedNetwork")?,
+        json_rpc_cross_validation: match field("crossValidation") {
+            Some(config) => Some(decode_cross_validation(config)?),
+            None => None,
+        },
+        json_rpc_fallback: match field("rpcFallback") {
+            Some(address) => Some(super::rpc_fallback::Config {
+                address: address.as_str()?.to_owned(),
+            }),
+            None => None,
+        },
+        json_rpc_storage_prefetch_keys: NonZeroUsize::new(number("storagePrefetchKeys")?),
+        json_rpc_validate_transactions: flag("validateTransactions")?,
+        custom_consensus_engines: match field("customConsensusEngines") {
+            Some(engines) => engines
+                .as_array()?
+                .iter()
+                .map(|engine| <[u8; 4]>::try_from(engine.as_str()?.as_bytes()).ok())
+                .collect::<Option<Vec<_>>>()?,
+            None => Vec::new(),
+        },
+        max_runtime_memory_pages: match number("maxRuntimeMemoryPages")? {
+            0 => default_max_runtime_memory_pages,
+            pages => Some(u32::try_from(pages).ok()?),
+        },
+    })
+}
+
+/// Decodes a JSON-RPC methods filter found in the configuration of a chain. See the
+/// documentation of [`bindings::init`]. Returns `None` if the filter is invalid.
+fn decode_methods_filter(
+    filter: &serde_json::Value,
+) -> Option<super::json_rpc_service::MethodsFilter> {
+    let decode_list = |list: &serde_json::Value| -> Option<Vec<String>> {
+        list.as_array()?
+            .iter()
+            .map(|pattern| pattern.as_str().map(|p| p.to_owned()))
+            .collect()
+    };
+
+    Some(super::json_rpc_service::MethodsFilter {
+        allow: match filter.get("allow") {
+            Some(serde_json::Value::Null) | None => None,
+            Some(list) => Some(decode_list(list)?),
+        },
+        deny: match filter.get("deny") {
+            Some(serde_json::Value::Null) | None => Vec::new(),
+            Some(list) => decode_list(list)?,
+        },
+        allow_unsafe: match filter.get("allowUnsafe") {
+            Some(serde_json::Value::Null) | None => false,
+            Some(value) => value.as_bool()?,
+        },
+    })
+}
+
+/// Decodes a JSON array of objects of the form
+/// `{"name": "...", "maxRequestSize": ..., "maxResponseSize": ..., "timeoutMs": ...}`, where
+/// only `name` is mandatory. See the documentation of [`bindings::init`].
+///
+/// Returns `None` if the array is invalid.
+fn decode_p2p_protocols(
+    protocols: &[u8],
+) -> Option<Vec<super::network_service::ExtraRequestResponseProtocol>> {
+    let protocols: serde_json::Value = serde_json::from_slice(protocols).ok()?;
+
+    protocols
+        .as_array()?
+        .iter()
+        .map(|protocol| {
+            // Returns the value of the given field, or `default` if it is missing.
+            let field = |name: &str, default: u64| match protocol.get(name) {
+                Some(serde_json::Value::Null) | None => Some(default),
+                Some(value) => value.as_u64(),
+            };
+
+            Some(super::network_service::ExtraRequestResponseProtocol {
+                name: protocol.get("name")?.as_str()?.to_owned(),
+                max_request_size: usize::try_from(field("maxRequestSize", 1024 * 1024)?).ok()?,
+                max_response_size: usize::try_from(field("maxResponseSize", 16 * 1024 * 1024)?)
+                    .ok()?,
+                timeout: Duration::from_millis(field("timeoutMs", 20000)?),
+            })
+        })
+        .collect()
+}
+
+/// Decodes a JSON object of the form `{"address": "...", "methods": ["pattern", ...]}`.
+///
+/// Returns `None` if the object is invalid.
+fn decode_cross_validation(config: &serde_json::Value) -> Option<super::cross_validation::Config> {
+    let methods = config
+        .get("methods")?
+        .as_array()?
+        .iter()
+        .map(|pattern| pattern.as_str().map(|p| p.to_owned()))
+        .collect::<Option<Vec<_>>>()?;
+
+    Some(super::cross_validation::Config {
+        address: config.get("address")?.as_str()?.to_owned(),
+        methods: super::json_rpc_service::MethodsFilter {
+            allow: Some(methods),
+            deny: Vec::new(),
+            allow_unsafe: false,
+        },
+    })
+}
+
+fn init(
+    chain_specs_pointers_ptr: u32,
+    chain_specs_pointers_len: u32,
+    max_log_level: u32,
+    host_crypto_flags: u32,
+    compression_flags: u32,
+    max_runtime_memory_pages: u32,
+    doh_url_ptr: u32,
+    doh_url_len: u32,
+    unstable_p2p_requests: u32,
+    json_rpc_max_concurrent_requests: u32,
+    json_rpc_max_queued_requests: u32,
+    json_rpc_max_requests_per_second: u32,
+    dial_delay_ms: u32,
+    dial_timeout_ms: u32,
+    peers_target: u32,
+    max_outbound_message_size: u32,
+    max_inbound_message_size: u32,
+    supported_transports: u32,
+    relayed_connections: u32,
+    privacy_flags: u32,
+    peer_id_rotation_ms: u32,
+    max_request_jitter_ms: u32,
+    network_key_ptr: u32,
+    network_key_len: u32,
+    peer_events_version: u32,
+    p2p_protocols_ptr: u32,
+    p2p_protocols_len: u32,
+) {
+    HOST_CRYPTO_FLAGS.store(host_crypto_flags, atomic::Ordering::Relaxed);
+    COMPRESSION_FLAGS.store(compression_flags, atomic::Ordering::Relaxed);
+    SUPPORTED_TRANSPORTS.store(supported_transports, atomic::Ordering::Relaxed);
+
+    let dns_over_https_url = if doh_url_len != 0 {
+        let doh_url: Box<[u8]> = unsafe {
+            Box::from_raw(slice::from_raw_parts_mut(
+                usize::try_from(doh_url_ptr).unwrap() as *mut u8,
+                usize::try_from(doh_url_len).unwrap(),
+            ))
+        };
+        Some(String::from_utf8(Vec::from(doh_url)).expect("non-utf8 DNS-over-HTTPS URL"))
+    } else {
+        None
+    };
+
+    let network_key = if network_key_len != 0 {
+        let network_key: Box<[u8]> = unsafe {
+            Box::from_raw(slice::from_raw_parts_mut(
+                usize::try_from(network_key_ptr).unwrap() as *mut u8,
+                usize::try_from(network_key_len).unwrap(),
+            ))
+        };
+        Some(<[u8; 32]>::try_from(&network_key[..]).expect("network key must be 32 bytes"))
+    } else {
+        None
+    };
+
+    let unstable_p2p_protocols = if p2p_protocols_len != 0 {
+        let protocols: Box<[u8]> = unsafe {
+            Box::from_raw(slice::from_raw_parts_mut(
+                usize::try_from(p2p_protocols_ptr).unwrap() as *mut u8,
+                usize::try_from(p2p_protocols_len).unwrap(),
+            ))
+        };
+        decode_p2p_protocols(&protocols).expect("invalid peer-to-peer protocols")
+    } else {
+        Vec::new()
+    };
+
+    let default_max_runtime_memory_pages = if max_runtime_memory_pages != 0 {
+        Some(max_runtime_memory_pages)
+    } else {
+        None
+    };
+
     let chain_specs_pointers_ptr = usize::try_from(chain_specs_pointers_ptr).unwrap();
     let chain_specs_pointers_len = usize::try_from(chain_specs_pointers_len).unwrap();
 
@@ -453,35 +950,39 @@ fn init(chain_specs_pointers_ptr: u32, chain_specs_pointers_len: u32, max_log_le
         ))
     };
 
-    assert_eq!(chain_specs_pointers.len() % 8, 0);
-    let mut chain_specs = Vec::with_capacity(chain_specs_pointers.len() / 8);
+    assert_eq!(chain_specs_pointers.len() % 16, 0);
+    let mut chain_specs = Vec::with_capacity(chain_specs_pointers.len() / 16);
 
     for chain_spec_index in 0..(chain_specs.capacity()) {
-        let spec_pointer = {
-            let val = <[u8; 4]>::try_from(
-                &chain_specs_pointers[(chain_spec_index * 8)..(chain_spec_index * 8 + 4)],
-            )
-            .unwrap();
+        // Reads the `n`th little-endian u32 of the group of this chain.
+        let read_u32 = |n: usize| {
+            let offset = chain_spec_index * 16 + n * 4;
+            let val = <[u8; 4]>::try_from(&chain_specs_pointers[offset..(offset + 4)]).unwrap();
             usize::try_from(u32::from_le_bytes(val)).unwrap()
         };
 
-        let spec_len = {
-            let val = <[u8; 4]>::try_from(
-                &chain_specs_pointers[(chain_spec_index * 8 + 4)..(chain_spec_index * 8 + 8)],
-            )
-            .unwrap();
-            usize::try_from(u32::from_le_bytes(val)).unwrap()
-        };
+        let (spec_pointer, spec_len) = (read_u32(0), read_u32(1));
+        let (config_pointer, config_len) = (read_u32(2), read_u32(3));
 
         let chain_spec: Box<[u8]> =
             unsafe { Box::from_raw(slice::from_raw_parts_mut(spec_pointer as *mut u8, spec_len)) };
-
         let chain_spec = String::from_utf8(Vec::from(chain_spec)).expect("non-utf8 chain spec");
 
-        chain_specs.push(super::ChainConfig {
-            specification: chain_spec,
-            json_rpc_running: true,
-        });
+        let config: Box<[u8]> = if config_len != 0 {
+            unsafe {
+                Box::from_raw(slice::from_raw_parts_mut(
+                    config_pointer as *mut u8,
+                    config_len,
+                ))
+            }
+        } else {
+            Box::new([])
+        };
+
+        chain_specs.push(
+            decode_chain_config(chain_spec, &config, default_max_runtime_memory_pages)
+                .expect("invalid chain configuration"),
+        );
     }
 
     debug_assert_eq!(chain_specs.len(), chain_specs.capacity());
@@ -495,18 +996,66 @@ fn init(chain_specs_pointers_ptr: u32, chain_specs_pointers_len: u32, max_log_le
         _ => log::LevelFilter::Trace,
     };
 
-    spawn_task(super::start_client(chain_specs.into_iter(), max_log_level));
-}
-
-pub(crate) enum JsonRpcMessage {
-    Request {
-        json_rpc_request: Box<[u8]>,
-        chain_index: usize,
-        user_data: u32,
-    },
-    UnsubscribeAll {
-        user_data: u32,
-    },
+    spawn_task(super::start_client(
+        chain_specs,
+        super::ClientConfig {
+            max_log_level,
+            request_compressed_responses: compression_flags
+                & bindings::COMPRESSION_REQUEST_RESPONSES
+                != 0,
+            dns_over_https_url,
+            unstable_p2p_requests: unstable_p2p_requests != 0,
+            unstable_p2p_protocols,
+            json_rpc_consumer_limits: super::json_rpc_service::ConsumerLimits {
+                max_concurrent_requests: NonZeroUsize::new(
+                    usize::try_from(json_rpc_max_concurrent_requests).unwrap(),
+                ),
+                max_queued_requests: usize::try_from(json_rpc_max_queued_requests).unwrap(),
+                max_requests_per_second: NonZeroU32::new(json_rpc_max_requests_per_second),
+            },
+            dial_strategy: if dial_delay_ms == 0 {
+                super::network_service::DialStrategy::Parallel
+            } else {
+                super::network_service::DialStrategy::Staggered {
+                    delay: Duration::from_millis(u64::from(dial_delay_ms)),
+                }
+            },
+            dial_timeout: Duration::from_millis(u64::from(if dial_timeout_ms != 0 {
+                dial_timeout_ms
+            } else {
+                10000
+            })),
+            connection_limits: ConnectionLimits {
+                max_outbound_message_size: NonZeroUsize::new(
+                    usize::try_from(max_outbound_message_size).unwrap(),
+                ),
+                max_inbound_message_size: if max_inbound_message_size != 0 {
+                    usize::try_from(max_inbound_message_size).unwrap()
+                } else {
+                    ConnectionLimits::default().max_inbound_message_size
+                },
+            },
+            peers_target: if peers_target != 0 {
+                usize::try_from(peers_target).unwrap()
+            } else {
+                10
+            },
+            allow_relayed_connections: relayed_connections != 0,
+            privacy: super::network_service::PrivacyConfig {
+                refuse_identify: privacy_flags & bindings::PRIVACY_REFUSE_IDENTIFY != 0,
+                hide_best_block: privacy_flags & bindings::PRIVACY_HIDE_BEST_BLOCK != 0,
+                peer_id_rotation_interval: if peer_id_rotation_ms != 0 {
+                    Some(Duration::from_millis(u64::from(peer_id_rotation_ms)))
+                } else {
+                    None
+                },
+                max_request_jitter: Duration::from_millis(u64::from(max_request_jitter_ms)),
+            },
+            network_key,
+            peer_events_version: super::events::Version::from_u32(peer_events_version)
+                .expect("unsupported peer events version"),
+        },
+    ));
 }
 
 lazy_static::lazy_static! {
@@ -538,6 +1087,39 @@ fn json_rpc_unsubscribe_all(user_data: u32) {
         .unwrap();
 }
 
+fn json_rpc_detach_all(user_data: u32, token: u32) {
+    JSON_RPC_CHANNEL
+        .0
+        .unbounded_send(JsonRpcMessage::DetachAll { user_data, token })
+        .unwrap();
+}
+
+fn json_rpc_reattach(token: u32, user_data: u32) {
+    JSON_RPC_CHANNEL
+        .0
+        .unbounded_send(JsonRpcMessage::Reattach { token, user_data })
+        .unwrap();
+}
+
+lazy_static::lazy_static! {
+    static ref CHECKPOINT_REQUESTS: (mpsc::UnboundedSender<usize>, futures::lock::Mutex<mpsc::UnboundedReceiver<usize>>) = {
+        let (tx, rx) = mpsc::unbounded();
+        (tx, futures::lock::Mutex::new(rx))
+    };
+}
+
+fn request_checkpoint(chain_index: u32) {
+    let chain_index = usize::try_from(chain_index).unwrap();
+    CHECKPOINT_REQUESTS.0.unbounded_send(chain_index).unwrap();
+}
+
+/// Waits for the next checkpoint request coming from the JavaScript side, and returns the index
+/// of the chain concerned.
+pub(crate) async fn next_checkpoint_request() -> usize {
+    let mut lock = CHECKPOINT_REQUESTS.1.lock().await;
+    lock.next().await.unwrap()
+}
+
 /// Waits for the next JSON-RPC request coming from the JavaScript side.
 // TODO: maybe tie the JSON-RPC system to a certain "client", instead of being global?
 pub(crate) async fn next_json_rpc() -> JsonRpcMessage {
@@ -558,6 +1140,49 @@ pub(crate) fn emit_json_rpc_response(rpc: &str, chain_index: usize, user_data: u
     }
 }
 
+/// Emit a chunk of a JSON-RPC response in destination to the JavaScript side. See
+/// [`bindings::json_rpc_respond_chunk`].
+pub(crate) fn emit_json_rpc_response_chunk(
+    chunk: &str,
+    chain_index: usize,
+    user_data: u32,
+    is_final: bool,
+) {
+    unsafe {
+        bindings::json_rpc_respond_chunk(
+            u32::try_from(chunk.as_bytes().as_ptr() as usize).unwrap(),
+            u32::try_from(chunk.as_bytes().len()).unwrap(),
+            u32::try_from(chain_index).unwrap(),
+            user_data,
+            if is_final { 1 } else { 0 },
+        );
+    }
+}
+
+/// Emit an event about the peers of a chain in destination to the JavaScript side. See
+/// [`bindings::peer_event`].
+pub(crate) fn emit_peer_event(event: &str, chain_index: usize) {
+    unsafe {
+        bindings::peer_event(
+            u32::try_from(event.as_bytes().as_ptr() as usize).unwrap(),
+            u32::try_from(event.as_bytes().len()).unwrap(),
+            u32::try_from(chain_index).unwrap(),
+        );
+    }
+}
+
+/// Emit a checkpoint of a chain in destination to the JavaScript side. See
+/// [`bindings::checkpoint`].
+pub(crate) fn emit_checkpoint(checkpoint: &str, chain_index: usize) {
+    unsafe {
+        bindings::checkpoint(
+            u32::try_from(checkpoint.as_ptr() as usize).unwrap(),
+            u32::try_from(checkpoint.len()).unwrap(),
+            u32::try_from(chain_index).unwrap(),
+        );
+    }
+}
+
 fn timer_finished(timer_id: u32) {
     let callback = {
         let ptr = timer_id as *mut Box<dyn FnOnce()>;
@@ -567,6 +1192,35 @@ fn timer_finished(timer_id: u32) {
     callback();
 }
 
+fn http_fetch_finished(id: u32, success: u32, ptr: u32, len: u32) {
+    fetch_finished(id, success, ptr, len)
+}
+
+fn code_substitute_fetch_finished(id: u32, success: u32, ptr: u32, len: u32) {
+    fetch_finished(id, success, ptr, len)
+}
+
+/// Common implementation of [`http_fetch_finished`] and [`code_substitute_fetch_finished`].
+fn fetch_finished(id: u32, success: u32, ptr: u32, len: u32) {
+    let callback = {
+        let ptr = id as *mut oneshot::Sender<Result<Vec<u8>, String>>;
+        unsafe { Box::from_raw(ptr) }
+    };
+
+    let buffer: Box<[u8]> = unsafe {
+        Box::from_raw(slice::from_raw_parts_mut(
+            usize::try_from(ptr).unwrap() as *mut u8,
+            usize::try_from(len).unwrap(),
+        ))
+    };
+
+    let _ = callback.send(if success != 0 {
+        Ok(Vec::from(buffer))
+    } else {
+        Err(String::from_utf8_lossy(&buffer).into_owned())
+    });
+}
+
 fn connection_open(id: u32) {
     let connection = unsafe { &mut *(usize::try_from(id).unwrap() as *mut Connection) };
     connection.open = true;
@@ -593,7 +1247,29 @@ fn connection_message(id: u32, ptr: u32, len: u32) {
         connection.messages_queue_first_offset = 0;
     }
 
-    // TODO: add some limit to `messages_queue`, to avoid DoS attacks?
+    // TODO: add some limit to the total size of `messages_queue`, to avoid DoS attacks?
+
+    // The JavaScript code reassembles the WebSocket frames into messages before calling this
+    // function, and the limit can thus only be enforced once the entire message has been
+    // received.
+    if message.len() > connection.limits.max_inbound_message_size {
+        if let Some(id) = connection.id.take() {
+            unsafe {
+                bindings::connection_close(id);
+            }
+        }
+
+        connection.closed_message = Some(format!(
+            "Received a message of {} bytes, while the limit is {} bytes",
+            message.len(),
+            connection.limits.max_inbound_message_size
+        ));
+
+        if let Some(waker) = connection.waker.take() {
+            waker.wake();
+        }
+        return;
+    }
 
     connection.messages_queue.push_back(message);
 
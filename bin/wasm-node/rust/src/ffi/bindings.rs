@@ -76,6 +76,84 @@ extern "C" {
     /// that the request was made to. `user_data` is the value that was passed to [`json_rpc_send`].
     pub fn json_rpc_respond(ptr: u32, len: u32, chain_index: u32, user_data: u32);
 
+    /// Client is emitting a chunk of a response to a previous JSON-RPC request sent using
+    /// [`json_rpc_send`]. Used instead of [`json_rpc_respond`] for responses that are
+    /// potentially very large.
+    ///
+    /// The chunk is a UTF-8 string found in the memory of the WebAssembly virtual machine at
+    /// offset `ptr` and with length `len`. `chain_index` and `user_data` are the same as for
+    /// [`json_rpc_respond`].
+    ///
+    /// The response is the concatenation of all the chunks emitted for the given `chain_index`
+    /// and `user_data`, up to and including the chunk where `is_final` is non-zero. Chunks of a
+    /// response are always emitted consecutively, without any other call to
+    /// [`json_rpc_respond`] or `json_rpc_respond_chunk` in-between.
+    ///
+    /// Each chunk is guaranteed to be valid UTF-8 on its own.
+    pub fn json_rpc_respond_chunk(
+        ptr: u32,
+        len: u32,
+        chain_index: u32,
+        user_data: u32,
+        is_final: u32,
+    );
+
+    /// Client is emitting an event about the peers of a chain.
+    ///
+    /// The event is a UTF-8 JSON object found in the memory of the WebAssembly virtual machine at
+    /// offset `ptr` and with length `len`. `chain_index` is the chain the event relates to.
+    ///
+    /// The format of the object depends on the `peer_events_version` passed to [`init`].
+    ///
+    /// In version 2, the object always contains a `version` field equal to `2` and a `kind`
+    /// field. Peers are described by a `peer` object with a `peerId` field, and blocks by an
+    /// object with a `number` and/or `hash` field. The `kind` is one of:
+    ///
+    /// - `"peer-connected"`, with the fields `peer` (which also has a `role` field) and
+    ///   `bestBlock`.
+    /// - `"peer-disconnected"`, with the fields `peer` and `reason`.
+    /// - `"peer-best-block"`, with the fields `peer` and `bestBlock`.
+    /// - `"peer-genesis-mismatch"`, with the fields `peer`, `genesisHash` and `numMismatches`.
+    /// - `"chain-spec-mismatch"`, with the field `genesisHash`.
+    /// - `"state-root-mismatch"`, with the fields `block`, and `header` and `proof` which both
+    ///   contain the `peer` that has served them and the `stateRoot` they indicate. The `header`
+    ///   also contains its `hash`. Emitted when a peer serves, for the number of a finalized
+    ///   block whose storage has been queried, a different header.
+    ///
+    /// The fields have the same meaning as in version 1, described below.
+    ///
+    /// In version 1, which is deprecated, the object always contains a `kind` field, which is
+    /// one of:
+    ///
+    /// - `"connected"`, with the fields `peerId`, `role` (`"full"`, `"light"` or
+    /// `"authority"`), `bestNumber` and `bestHash`.
+    /// - `"disconnected"`, with the fields `peerId` and `reason` (`"connection-closed"` or
+    /// `"chain-substream-closed"`).
+    /// - `"best-block"`, with the fields `peerId`, `bestNumber` and `bestHash`.
+    /// - `"genesis-mismatch"`, with the fields `peerId`, `genesisHash` and `numMismatches`, when
+    /// a peer reports a genesis block different from the one of the chain. The peer is then
+    /// ignored for this chain. `numMismatches` is the number of such peers so far.
+    /// - `"chain-spec-mismatch"`, with the field `genesisHash`, when all the bootnodes of the
+    /// chain have reported a different genesis block. This most likely indicates that the chain
+    /// specification doesn't match the network. Emitted at most once per chain.
+    ///
+    /// A `"disconnected"` or `"best-block"` event is only ever emitted for a peer that has
+    /// previously been reported with a `"connected"` event. The same applies to the
+    /// corresponding events of version 2.
+    pub fn peer_event(ptr: u32, len: u32, chain_index: u32);
+
+    /// Client is emitting a checkpoint of a chain.
+    ///
+    /// The checkpoint is a UTF-8 string found in the memory of the WebAssembly virtual machine
+    /// at offset `ptr` and with length `len`. `chain_index` is the chain the checkpoint relates
+    /// to. It contains the latest finalized block header and the information about the chain at
+    /// this block, in the same format as the database content.
+    ///
+    /// Checkpoints are emitted periodically whenever the finalized block of a chain has changed,
+    /// and in response to [`request_checkpoint`]. They can be used to keep the chain
+    /// specifications bundled with an application up to date.
+    pub fn checkpoint(ptr: u32, len: u32, chain_index: u32);
+
     /// Client is emitting a log entry.
     ///
     /// Each log entry is made of a log level (1 = Error, 2 = Warn, 3 = Info, 4 = Debug,
@@ -181,8 +259,177 @@ extern "C" {
     /// The connection must currently be in the `Open` state. See the documentation of
     /// [`connection_new`] for details.
     pub fn connection_send(id: u32, ptr: u32, len: u32);
+
+    /// Must start an HTTP `GET` request towards the given URL, with the `Accept` header set to the
+    /// given value.
+    ///
+    /// The URL is a UTF-8 string found in the WebAssembly memory at offset `url_ptr` and with
+    /// `url_len` bytes. The value of the `Accept` header is a UTF-8 string found at offset
+    /// `accept_ptr` and with `accept_len` bytes.
+    ///
+    /// The `id` parameter is an identifier for this request, as chosen by the Rust code. Once the
+    /// request has finished, either successfully or not, [`http_fetch_finished`] must be called
+    /// exactly once with this identifier.
+    ///
+    /// This function is only ever called if a DNS-over-HTTPS server has been passed to [`init`].
+    pub fn http_fetch(id: u32, url_ptr: u32, url_len: u32, accept_ptr: u32, accept_len: u32);
+
+    /// Must obtain the runtime code whose BLAKE2-256 hash is found in the WebAssembly memory at
+    /// offset `hash_ptr` (32 bytes).
+    ///
+    /// The `id` parameter is an identifier for this request, as chosen by the Rust code. Once
+    /// the code has been obtained, or if it can't be obtained, [`code_substitute_fetch_finished`]
+    /// must be called exactly once with this identifier. The code is verified against the hash.
+    ///
+    /// This function is only ever called if the chain specification of a chain contains a
+    /// `codeSubstitutes` entry of the form `{ "hash": "0x..." }`, once the best block of that
+    /// chain has reached the block number of the entry. This makes it possible to not include
+    /// potentially large runtimes in the chain specification.
+    pub fn code_substitute_fetch(id: u32, hash_ptr: u32);
+
+    /// Must verify whether an sr25519 signature is valid, and return 1 if it is or 0 if it
+    /// isn't.
+    ///
+    /// The signature (64 bytes) is found in the memory of the WebAssembly virtual machine at
+    /// offset `signature_ptr`, the public key (32 bytes) at offset `public_key_ptr`, and the
+    /// signed message at offset `message_ptr` and with length `message_len`. The signing
+    /// context is always `b"substrate"`.
+    ///
+    /// This function is only ever called if the [`HOST_CRYPTO_SR25519_VERIFY`] flag has been
+    /// passed to [`init`]. Implementations that don't support it can simply throw.
+    pub fn host_sr25519_verify(
+        signature_ptr: u32,
+        message_ptr: u32,
+        message_len: u32,
+        public_key_ptr: u32,
+    ) -> u32;
+
+    /// Same as [`host_sr25519_verify`], except that the signature must be verified using the
+    /// deprecated variant of sr25519 that runtimes access through the
+    /// `ext_crypto_sr25519_verify_version_1` host function. This variant corresponds to
+    /// `verify_simple_preaudit_deprecated` in the `schnorrkel` Rust library.
+    ///
+    /// This function is only ever called if the [`HOST_CRYPTO_SR25519_VERIFY_DEPRECATED`] flag
+    /// has been passed to [`init`]. Implementations that don't support it can simply throw.
+    pub fn host_sr25519_verify_deprecated(
+        signature_ptr: u32,
+        message_ptr: u32,
+        message_len: u32,
+        public_key_ptr: u32,
+    ) -> u32;
+
+    /// Must verify the VRF proof found in a Babe block header. If the proof is valid, must write
+    /// 16 bytes generated from the VRF input and output with the context `b"substrate-babe-vrf"`
+    /// in the memory of the WebAssembly virtual machine at offset `out_ptr`, then return 1.
+    /// Must return 0 if the proof is invalid.
+    ///
+    /// The public key (32 bytes), VRF output (32 bytes), and VRF proof (64 bytes) are found in
+    /// the memory of the WebAssembly virtual machine at offsets `public_key_ptr`,
+    /// `vrf_output_ptr`, and `vrf_proof_ptr`. The VRF input is a transcript labelled `b"BABE"`,
+    /// built from the 48 bytes found at offset `transcript_ptr`: the slot number as a
+    /// little-endian 64 bits number labelled `b"slot number"`, then the epoch index as a
+    /// little-endian 64 bits number labelled `b"current epoch"`, then 32 bytes of randomness
+    /// labelled `b"chain randomness"`.
+    ///
+    /// This function is only ever called if the [`HOST_CRYPTO_BABE_VRF_VERIFY`] flag has been
+    /// passed to [`init`]. Implementations that don't support it can simply throw.
+    pub fn host_babe_vrf_verify(
+        public_key_ptr: u32,
+        transcript_ptr: u32,
+        vrf_output_ptr: u32,
+        vrf_proof_ptr: u32,
+        out_ptr: u32,
+    ) -> u32;
+
+    /// Must verify the digest items, found in a header of the chain whose index is
+    /// `chain_index`, that belong to one of the custom consensus engines of this chain, and
+    /// return 1 if they are all valid or 0 if any of them is invalid. The header is refused if 0
+    /// is returned.
+    ///
+    /// The items are found in the memory of the WebAssembly virtual machine at offset
+    /// `items_ptr` and with length `items_len`, and consist in a UTF-8 JSON array of objects of
+    /// the form `{ "kind": "...", "engineId": "...", "data": "0x..." }`, where `kind` is one of
+    /// `preRuntime`, `consensus`, or `seal`, and `engineId` is the identifier of the consensus
+    /// engine.
+    ///
+    /// This function is only ever called for chains whose configuration passed to [`init`]
+    /// contains a non-empty `customConsensusEngines` field, and never with an empty array.
+    pub fn verify_custom_digest_items(chain_index: u32, items_ptr: u32, items_len: u32) -> u32;
+
+    /// Must calculate the 32 bytes BLAKE2b hash of the data found in the memory of the
+    /// WebAssembly virtual machine at offset `data_ptr` and with length `data_len`, and write it
+    /// in the memory of the WebAssembly virtual machine at offset `out_ptr`.
+    ///
+    /// This function is only ever called if the [`HOST_CRYPTO_BLAKE2_256`] flag has been passed
+    /// to [`init`]. Implementations that don't support it can simply throw.
+    pub fn host_blake2_256(data_ptr: u32, data_len: u32, out_ptr: u32);
+
+    /// Must decompress the zstandard frames found in the memory of the WebAssembly virtual
+    /// machine at offset `data_ptr` and with length `data_len`.
+    ///
+    /// On success, must allocate a buffer using [`alloc`], write the decompressed data in it,
+    /// then write at offset `out_ptr` two little-endian u32s: the pointer and the length of this
+    /// buffer. Ownership of the buffer is then transferred to the Rust code. Must then return 1.
+    ///
+    /// Must return 0 without allocating anything if the data is invalid, or if it decompresses
+    /// to more than `max_size` bytes. Implementations should stop decompressing as soon as this
+    /// limit is reached, as the data comes from other nodes of the network and might have been
+    /// crafted to decompress to a very large size.
+    ///
+    /// This function is only ever called if the [`COMPRESSION_HOST_DECOMPRESS`] flag has been
+    /// passed to [`init`]. Implementations that don't support it can simply throw.
+    pub fn host_zstd_decompress(data_ptr: u32, data_len: u32, max_size: u32, out_ptr: u32) -> u32;
 }
 
+/// Flag that can be passed to [`init`] in order to indicate that [`host_sr25519_verify`] is
+/// implemented by the host.
+pub const HOST_CRYPTO_SR25519_VERIFY: u32 = 1 << 0;
+
+/// Flag that can be passed to [`init`] in order to indicate that [`host_blake2_256`] is
+/// implemented by the host.
+pub const HOST_CRYPTO_BLAKE2_256: u32 = 1 << 1;
+
+/// Flag that can be passed to [`init`] in order to indicate that
+/// [`host_sr25519_verify_deprecated`] is implemented by the host.
+pub const HOST_CRYPTO_SR25519_VERIFY_DEPRECATED: u32 = 1 << 2;
+
+/// Flag that can be passed to [`init`] in order to indicate that [`host_babe_vrf_verify`] is
+/// implemented by the host.
+pub const HOST_CRYPTO_BABE_VRF_VERIFY: u32 = 1 << 3;
+
+/// Flag that can be passed to [`init`] in order to indicate, in the block and storage proof
+/// requests sent to peers, that the responses can be zstandard-compressed.
+///
+/// This relies on a smoldot-specific extension of the networking protocol. Peers that don't
+/// support it ignore it and answer with uncompressed responses. See the documentation of the
+/// `smoldot::network::protocol` module.
+pub const COMPRESSION_REQUEST_RESPONSES: u32 = 1 << 0;
+
+/// Flag that can be passed to [`init`] in order to indicate that [`host_zstd_decompress`] is
+/// implemented by the host. Compressed responses are then handed to the host rather than
+/// decompressed within the WebAssembly virtual machine.
+pub const COMPRESSION_HOST_DECOMPRESS: u32 = 1 << 1;
+
+/// Flag that can be passed to [`init`] in order to indicate that [`connection_new`] is capable of
+/// opening plain TCP connections, i.e. multiaddresses of the form `/ip4/.../tcp/...`.
+pub const TRANSPORT_TCP: u32 = 1 << 0;
+
+/// Flag that can be passed to [`init`] in order to indicate that [`connection_new`] is capable of
+/// opening WebSocket connections, i.e. multiaddresses ending with `/ws`.
+pub const TRANSPORT_WS: u32 = 1 << 1;
+
+/// Flag that can be passed to [`init`] in order to indicate that [`connection_new`] is capable of
+/// opening secure WebSocket connections, i.e. multiaddresses ending with `/wss`.
+pub const TRANSPORT_WSS: u32 = 1 << 2;
+
+/// Flag that can be passed to [`init`] in order to refuse the identify requests sent by other
+/// nodes, as they would reveal the name of the client and the list of chains it is connected to.
+pub const PRIVACY_REFUSE_IDENTIFY: u32 = 1 << 0;
+
+/// Flag that can be passed to [`init`] in order to report the genesis block as best block to
+/// other nodes, instead of the block the client has started from.
+pub const PRIVACY_HIDE_BEST_BLOCK: u32 = 1 << 1;
+
 /// Allocates a buffer of the given length, with an alignment of 1.
 ///
 /// This must be used in the context of [`init`].
@@ -198,9 +445,76 @@ pub extern "C" fn alloc(len: u32) -> u32 {
 /// called.
 /// Write the chain specs in these buffers.
 ///
-/// Then, use [`alloc`] to allocate one additional buffer containing a list of pairs of
-/// little-endian u32s. Each pair must be a pointer and a length to the buffers allocated in the
-/// previous step.
+/// Each chain can optionally be given a configuration, in which case use [`alloc`] to allocate
+/// an additional buffer for this chain and write in it a UTF-8 JSON object such as
+/// `{"cpuWeight": 2, "syncMode": "headers", "quorumSize": 3}`. All the fields of this object are
+/// optional, and a missing or `null` field is equivalent to its default value. The fields are:
+///
+/// - `jsonRpcMethodsFilter`: an object such as `{"allow": ["chain_*", "state_getStorage"],
+///   "deny": ["author_*"], "allowUnsafe": false}`, where all fields are optional. If `allow` is
+///   present, only the methods matching one of its patterns can be called. Methods matching one
+///   of the patterns of `deny` can never be called. A pattern ending with `*` matches all the
+///   methods starting with what precedes the `*`. Unsafe methods, such as
+///   `author_removeExtrinsic`, can only be called if `allowUnsafe` is `true`, including when the
+///   chain doesn't have any methods filter. By default, all the safe methods can be called.
+/// - `cpuWeight`: the CPU weight of the chain, relative to the CPU weights of the other chains. A
+///   chain whose weight is lower than the highest weight is paused after performing CPU-intensive
+///   operations, in order to leave time for the other chains to make progress. Defaults to 1, and
+///   0 is interpreted as 1. Use the same value for all chains to never pause any chain.
+/// - `syncMode`: what is downloaded from the network when synchronizing the chain. Either
+///   `"headersAndJustifications"` (the default), `"headers"`, or an object such as
+///   `{"recentBodies": 16}` for headers and justifications plus the bodies of this number of most
+///   recent best blocks, which are kept in memory.
+/// - `lazyStart`: if `true`, the chain isn't synchronized and no connection is opened on its
+///   behalf until the first JSON-RPC request targeting it is received. Ignored for parachains and
+///   for relay chains of parachains.
+/// - `lazyIdleTimeout`: number of milliseconds after which a chain started because of
+///   `lazyStart` is suspended if it hasn't received any JSON-RPC request and has no active
+///   subscription. A suspended chain is no longer synchronized and no new connection is opened
+///   on its behalf, until the next JSON-RPC request targeting it is received. Defaults to 0,
+///   meaning that chains are never suspended.
+/// - `isolatedNetwork`: if `true`, the chain doesn't share its connections with the other
+///   chains, and uses a random network identity of its own. This prevents peers from finding out
+///   that the same client also follows other chains. Parachains always share the connections of
+///   their relay chain, and can only have this flag set if their relay chain has it as well.
+/// - `crossValidation`: an object such as `{"address": "/dns/rpc.example.com/tcp/443/wss",
+///   "methods": ["state_getStorage", "chain_getHeader"]}`. The JSON-RPC requests targeting this
+///   chain whose method matches one of the patterns of `methods`, using the same syntax as the
+///   methods filter, are then also sent to the WebSocket JSON-RPC server of a full node found at
+///   `address`, through [`connection_new`]. A warning is logged if the responses differ. This is
+///   meant for debugging purposes only.
+/// - `rpcFallback`: the multiaddress of a trusted JSON-RPC server, such as
+///   `"/dns/rpc.example.com/tcp/443/wss"`. The storage queries and runtime calls that can't be
+///   answered from the network, for example because they target an old block, are then
+///   forwarded to this server through [`connection_new`]. The responses obtained this way can't
+///   be verified, and contain an additional `"unverified": true` field.
+/// - `storagePrefetchKeys`: if non-zero, the values of up to this number of storage keys
+///   recently requested through JSON-RPC are downloaded ahead of time whenever a new best block is
+///   received, so that clients polling the same storage items at every block are answered
+///   immediately. Defaults to 0, which disables prefetching.
+/// - `quorumSize`: the number of distinct peers that must agree before the result of a critical
+///   query, such as the download of the runtime code or the confirmation of the finalized block,
+///   is accepted. Higher values trade latency for a stronger resistance to being surrounded by
+///   malicious peers. Defaults to 1, and 0 is interpreted as 1.
+/// - `validateTransactions`: if `true`, the transactions submitted through JSON-RPC are
+///   validated against the best block before being sent out to peers, and the ones that the
+///   runtime reports as invalid are refused with an error. Transactions whose validation can't be
+///   performed are sent out anyway. By default, transactions are sent out without being
+///   validated.
+/// - `customConsensusEngines`: array of 4-characters identifiers of consensus engines, other
+///   than Aura, Babe, and GrandPa, whose digest items can be found in the headers of the chain.
+///   These items are passed to [`verify_custom_digest_items`]. Ignored for parachains. Defaults
+///   to an empty array, in which case headers containing such items are refused.
+/// - `maxRuntimeMemoryPages`: maximum number of 64 kiB pages of memory of the virtual machine
+///   running the runtime of the chain, including the pages that the runtime allocates while
+///   being executed. Defaults to 0, meaning that the `max_runtime_memory_pages` parameter of
+///   this function applies.
+///
+/// Then, use [`alloc`] to allocate one additional buffer containing a list of groups of four
+/// little-endian u32s, one group per chain. Each group must be a pointer and a length to the
+/// chain spec buffer allocated in the first step, followed with a pointer and a length to the
+/// configuration buffer of this chain. If the chain doesn't have any configuration, the pointer
+/// and length of the configuration buffer must be 0.
 ///
 /// Then, pass the pointer and length (in bytes) of this last buffer to this function.
 ///
@@ -208,16 +522,162 @@ pub extern "C" fn alloc(len: u32) -> u32 {
 ///
 /// The client will emit log messages by calling the [`log()`] function, provided the log level is
 /// inferior or equal to the value of `max_log_level` passed here.
+///
+/// `host_crypto_flags` is a bitwise OR of [`HOST_CRYPTO_SR25519_VERIFY`],
+/// [`HOST_CRYPTO_SR25519_VERIFY_DEPRECATED`], [`HOST_CRYPTO_BABE_VRF_VERIFY`], and
+/// [`HOST_CRYPTO_BLAKE2_256`], and indicates which cryptographic functions the host provides an
+/// accelerated implementation of. The client performs the corresponding operations by calling
+/// into the host rather than within the WebAssembly virtual machine. Pass 0 to always use the
+/// implementations found within the WebAssembly virtual machine.
+///
+/// `compression_flags` is a bitwise OR of [`COMPRESSION_REQUEST_RESPONSES`] and
+/// [`COMPRESSION_HOST_DECOMPRESS`]. If [`COMPRESSION_REQUEST_RESPONSES`] is set, the block and
+/// storage proof requests sent to peers indicate that the response can be zstandard-compressed,
+/// which reduces the bandwidth usage. Peers that don't support compression answer with
+/// uncompressed responses. Compressed responses are decompressed by calling
+/// [`host_zstd_decompress`] if [`COMPRESSION_HOST_DECOMPRESS`] is set, and within the
+/// WebAssembly virtual machine otherwise.
+///
+/// If `max_runtime_memory_pages` is non-zero, the memory of the virtual machine running the
+/// runtime of each chain whose configuration doesn't contain any `maxRuntimeMemoryPages` field
+/// is limited to this number of 64 kiB pages. Runtimes that would need more memory at
+/// initialization are considered as invalid, and JSON-RPC requests that require calling them
+/// return an error. Runtime calls during which the runtime tries to grow its memory beyond this
+/// limit fail. Pass 0 for no limit.
+///
+/// If `doh_url_len` is non-zero, `doh_url_ptr` and `doh_url_len` must be the pointer and length
+/// of a buffer allocated with [`alloc`] containing the UTF-8 URL of a DNS-over-HTTPS server
+/// supporting the JSON API (e.g. `https://cloudflare-dns.com/dns-query`). Bootstrap nodes whose
+/// address is a `/dnsaddr` multiaddress are then resolved by sending requests to this server
+/// through [`http_fetch`]. The buffer is freed when this function is called. If `doh_url_len` is
+/// zero, `/dnsaddr` multiaddresses aren't resolved and [`http_fetch`] is never called.
+///
+/// If `unstable_p2p_requests` is non-zero, the `sudo_unstable_p2pRequest` JSON-RPC method can be
+/// called in order to send arbitrary requests to peers. This method is unstable and intended for
+/// experimentation only. Pass 0 to refuse calls to this method.
+///
+/// If `p2p_protocols_len` is non-zero, `p2p_protocols_ptr` and `p2p_protocols_len` must be the
+/// pointer and length of a buffer allocated with [`alloc`] containing a UTF-8 JSON array of
+/// request-response protocols that `sudo_unstable_p2pRequest` can send requests on, in addition
+/// to the protocols used by the chains. Each element of this array is an object of the form
+/// `{"name": "...", "maxRequestSize": ..., "maxResponseSize": ..., "timeoutMs": ...}`, where
+/// `maxRequestSize` and `maxResponseSize` are numbers of bytes (1 MiB and 16 MiB by default) and
+/// `timeoutMs` a number of milliseconds (20 seconds by default). Incoming requests on these
+/// protocols are refused. The buffer is freed when this function is called, similar to
+/// `doh_url_ptr`.
+///
+/// The `json_rpc_max_*` parameters are limits applied individually to each JSON-RPC consumer,
+/// as identified by the `user_data` passed to [`json_rpc_send`]:
+///
+/// - At most `json_rpc_max_concurrent_requests` requests of a consumer are processed at the same
+/// time. Additional requests are queued, and at most `json_rpc_max_queued_requests` requests of a
+/// consumer can be queued. Pass 0 for `json_rpc_max_concurrent_requests` for no limit.
+/// - At most `json_rpc_max_requests_per_second` requests of a consumer are accepted each second.
+/// Pass 0 for no limit.
+///
+/// Requests exceeding these limits are answered with an error.
+///
+/// `dial_delay_ms` is the number of milliseconds to wait between two attempts at opening an
+/// outgoing connection. If 0, as many connections as possible are instead opened at the same
+/// time, which is useful when many bootstrap nodes are unreachable.
+///
+/// `dial_timeout_ms` is the number of milliseconds after which an attempt at opening an outgoing
+/// connection is considered as failed. Pass 0 for the default value of 10 seconds.
+///
+/// `peers_target` is the number of peers that the client tries to be connected to. Pass 0 for
+/// the default value of 10.
+///
+/// If `max_outbound_message_size` is non-zero, the data sent to other nodes is split so that
+/// [`connection_send`] is never called with a buffer larger than this number of bytes. This is
+/// useful for WebSocket connections that go through gateways which silently drop frames above
+/// a certain size. Pass 0 for no limit. Connections that aren't with other nodes, such as the
+/// ones with the JSON-RPC servers, are never split, as this would corrupt the messages.
+///
+/// `max_inbound_message_size` is the maximum size, in bytes, of a buffer passed to
+/// [`connection_message`]. Connections on which a larger buffer is received are closed with
+/// [`connection_close`]. Pass 0 for the default value of 16 MiB.
+///
+/// `supported_transports` is a bitwise OR of [`TRANSPORT_TCP`], [`TRANSPORT_WS`] and
+/// [`TRANSPORT_WSS`], and indicates which kinds of connections [`connection_new`] is capable of
+/// opening. Bootstrap nodes whose address uses an unsupported transport are ignored. If none of
+/// the bootstrap nodes of a chain is usable, [`throw`] is called.
+///
+/// If `relayed_connections` is non-zero, nodes whose address goes through a relay (i.e. contains
+/// `/p2p-circuit`) can be connected to, by first connecting to the relay. Pass 0 to ignore such
+/// addresses.
+///
+/// `privacy_flags` is a bitwise OR of [`PRIVACY_REFUSE_IDENTIFY`] and [`PRIVACY_HIDE_BEST_BLOCK`].
+/// If `peer_id_rotation_ms` is non-zero, a new identity is generated every `peer_id_rotation_ms`
+/// milliseconds and used for new connections. Requests sent to other nodes are delayed by a
+/// random number of milliseconds between 0 and `max_request_jitter_ms`.
+///
+/// If `network_key_len` is non-zero, `network_key_ptr` must point to a buffer of 32 bytes
+/// containing the ed25519 private key to use on the peer-to-peer network. This makes it possible
+/// for the client to keep the same identity across restarts. Ownership of the buffer is
+/// transferred to this function, similar to `doh_url_ptr`. If `network_key_len` is zero, a random
+/// key is generated.
+///
+/// `peer_events_version` is the version of the format of the events passed to [`peer_event`],
+/// and must be 1 or 2. Version 1 is deprecated, and a warning is emitted through [`log()`] when
+/// it is used. See the documentation of [`peer_event`].
 #[no_mangle]
 pub extern "C" fn init(
     chain_specs_pointers_ptr: u32,
     chain_specs_pointers_len: u32,
     max_log_level: u32,
+    host_crypto_flags: u32,
+    compression_flags: u32,
+    max_runtime_memory_pages: u32,
+    doh_url_ptr: u32,
+    doh_url_len: u32,
+    unstable_p2p_requests: u32,
+    json_rpc_max_concurrent_requests: u32,
+    json_rpc_max_queued_requests: u32,
+    json_rpc_max_requests_per_second: u32,
+    dial_delay_ms: u32,
+    dial_timeout_ms: u32,
+    peers_target: u32,
+    max_outbound_message_size: u32,
+    max_inbound_message_size: u32,
+    supported_transports: u32,
+    relayed_connections: u32,
+    privacy_flags: u32,
+    peer_id_rotation_ms: u32,
+    max_request_jitter_ms: u32,
+    network_key_ptr: u32,
+    network_key_len: u32,
+    peer_events_version: u32,
+    p2p_protocols_ptr: u32,
+    p2p_protocols_len: u32,
 ) {
     super::init(
         chain_specs_pointers_ptr,
         chain_specs_pointers_len,
         max_log_level,
+        host_crypto_flags,
+        compression_flags,
+        max_runtime_memory_pages,
+        doh_url_ptr,
+        doh_url_len,
+        unstable_p2p_requests,
+        json_rpc_max_concurrent_requests,
+        json_rpc_max_queued_requests,
+        json_rpc_max_requests_per_second,
+        dial_delay_ms,
+        dial_timeout_ms,
+        peers_target,
+        max_outbound_message_size,
+        max_inbound_message_size,
+        supported_transports,
+        relayed_connections,
+        privacy_flags,
+        peer_id_rotation_ms,
+        max_request_jitter_ms,
+        network_key_ptr,
+        network_key_len,
+        peer_events_version,
+        p2p_protocols_ptr,
+        p2p_protocols_len,
     )
 }
 
@@ -248,6 +708,41 @@ pub extern "C" fn json_rpc_unsubscribe_all(user_data: u32) {
     super::json_rpc_unsubscribe_all(user_data)
 }
 
+/// Detach all the JSON-RPC subscriptions of a source and store them under `token`, an arbitrary
+/// value chosen by the caller. From the point of view of `user_data`, this is equivalent to
+/// [`json_rpc_unsubscribe_all`].
+///
+/// The subscriptions remain active but their notifications are discarded, until they are
+/// passed to [`json_rpc_reattach`]. Subscriptions that aren't reattached within a minute are
+/// destroyed.
+///
+/// Should be called instead of [`json_rpc_unsubscribe_all`] when disconnecting from a source
+/// that is expected to reconnect soon, for example because of a page refresh.
+#[no_mangle]
+pub extern "C" fn json_rpc_detach_all(user_data: u32, token: u32) {
+    super::json_rpc_detach_all(user_data, token)
+}
+
+/// Reattach the JSON-RPC subscriptions previously passed to [`json_rpc_detach_all`] with the
+/// given token to a source. The subscriptions keep their identifiers, and their notifications
+/// are later sent through [`json_rpc_respond`] with the new `user_data`.
+///
+/// Has no effect if no subscriptions have been detached with this token, or if the source
+/// identified by `user_data` already has active subscriptions.
+#[no_mangle]
+pub extern "C" fn json_rpc_reattach(token: u32, user_data: u32) {
+    super::json_rpc_reattach(token, user_data)
+}
+
+/// Ask for a checkpoint of the given chain to be emitted through [`checkpoint`] as soon as
+/// possible, even if its finalized block hasn't changed since the previous checkpoint.
+///
+/// Has no effect if the chain doesn't exist or hasn't been started yet.
+#[no_mangle]
+pub extern "C" fn request_checkpoint(chain_index: u32) {
+    super::request_checkpoint(chain_index)
+}
+
 /// Must be called in response to [`start_timer`] after the given duration has passed.
 #[no_mangle]
 pub extern "C" fn timer_finished(timer_id: u32) {
@@ -272,6 +767,9 @@ pub extern "C" fn connection_open(id: u32) {
 ///
 /// The buffer **must** have been allocated with [`alloc`]. It is freed when this function is
 /// called.
+///
+/// If the message is larger than the `max_inbound_message_size` passed to [`init`],
+/// [`connection_close`] is called from within this function.
 #[no_mangle]
 pub extern "C" fn connection_message(id: u32, ptr: u32, len: u32) {
     super::connection_message(id, ptr, len)
@@ -290,3 +788,29 @@ pub extern "C" fn connection_message(id: u32, ptr: u32, len: u32) {
 pub extern "C" fn connection_closed(id: u32, ptr: u32, len: u32) {
     super::connection_closed(id, ptr, len)
 }
+
+/// Must be called in response to [`code_substitute_fetch`] once the runtime code has been
+/// obtained, or if it can't be obtained.
+///
+/// If `success` is non-zero, the buffer contains the runtime code. Otherwise, it must contain a
+/// UTF-8 string indicating the reason for the failure.
+///
+/// The buffer **must** have been allocated with [`alloc`]. It is freed when this function is
+/// called.
+#[no_mangle]
+pub extern "C" fn code_substitute_fetch_finished(id: u32, success: u32, ptr: u32, len: u32) {
+    super::code_substitute_fetch_finished(id, success, ptr, len)
+}
+
+/// Must be called in response to [`http_fetch`] once the request has finished.
+///
+/// If `success` is non-zero, the buffer contains the body of the response. Otherwise, it must
+/// contain a UTF-8 string indicating the reason for the failure. A non-2xx status code must be
+/// considered as a failure.
+///
+/// The buffer **must** have been allocated with [`alloc`]. It is freed when this function is
+/// called.
+#[no_mangle]
+pub extern "C" fn http_fetch_finished(id: u32, success: u32, ptr: u32, len: u32) {
+    super::http_fetch_finished(id, success, ptr, len)
+}
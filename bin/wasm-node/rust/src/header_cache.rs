@@ -0,0 +1,433 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cache of recent block headers of a chain.
+//!
+//! The [`HeaderCache`] holds a bounded number of block headers, in both their SCALE-encoded and
+//! decoded forms, and is shared between the services of a chain. This avoids each service
+//! keeping its own copy of the same headers and decoding them again and again.
+//!
+//! Use [`start`] to create a [`HeaderCache`] that follows the best and finalized blocks
+//! reported by a [`sync_service::SyncService`]. The current best and finalized blocks are
+//! always available in the cache, while the other headers are evicted in a least-recently-used
+//! fashion once the capacity is reached.
+//!
+//! Additionally, the headers of all the blocks verified by the sync service, including the ones
+//! that aren't part of the canonical chain, are kept until they are more than
+//! [`Config::fork_blocks_window`] blocks below the finalized block. This makes it possible to
+//! find the header of a block shortly after it has been retracted by a reorganization, similar
+//! to what a full node does.
+
+use crate::{
+    platform::{Host, Platform as _},
+    sync_service,
+};
+
+use futures::{lock::Mutex, prelude::*};
+use smoldot::header;
+use std::{collections::HashMap, pin::Pin, sync::Arc};
+
+/// Configuration for [`start`].
+pub struct Config {
+    /// Closure that spawns background tasks.
+    pub tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+
+    /// Service whose best and finalized blocks are inserted in the cache.
+    pub sync_service: Arc<sync_service::SyncService>,
+
+    /// Maximum number of headers, in addition to the best and finalized block headers, to keep
+    /// in the cache.
+    pub capacity: usize,
+
+    /// Number of blocks below the finalized block during which the headers of all the blocks
+    /// verified by the sync service, canonical or not, are kept in the cache. They aren't
+    /// subject to [`Config::capacity`].
+    pub fork_blocks_window: u64,
+
+    /// Consensus engines, other than Aura, Babe, and GrandPa, whose digest items can be found
+    /// in the headers of the chain. Must be the same as the ones passed to the sync service.
+    pub custom_consensus_engines: Vec<[u8; 4]>,
+}
+
+/// Creates a new [`HeaderCache`] and spawns a background task that inserts the new best and
+/// finalized blocks in it.
+pub async fn start(mut config: Config) -> Arc<HeaderCache> {
+    let (finalized_block_header, finalized_blocks_subscription) =
+        config.sync_service.subscribe_finalized().await;
+    let (best_block_header, best_blocks_subscription) = config.sync_service.subscribe_best().await;
+
+    let cache = Arc::new(HeaderCache::new(
+        config.capacity,
+        config.fork_blocks_window,
+        config.custom_consensus_engines,
+        best_block_header.scale_encoded_header,
+        finalized_block_header.scale_encoded_header,
+    ));
+
+    (config.tasks_executor)("header-cache-forks".into(), {
+        let cache = cache.clone();
+        let sync_service = config.sync_service.clone();
+        Box::pin(async move {
+            loop {
+                let subscribe_all = match sync_service.try_subscribe_all(32).await {
+                    Ok(s) => s,
+                    // The sync service has shut down.
+                    Err(()) => break,
+                };
+
+                for block in subscribe_all.non_finalized_blocks {
+                    cache.insert_fork(block.scale_encoded_header).await;
+                }
+
+                let mut new_blocks = subscribe_all.new_blocks;
+                while let Some(block) = new_blocks.next().await {
+                    cache.insert_fork(block.scale_encoded_header).await;
+                }
+
+                // The channel is closed if it is full, in which case a new subscription is
+                // opened. Its list of non-finalized blocks contains the missed blocks.
+            }
+        })
+    });
+
+    (config.tasks_executor)("header-cache-update".into(), {
+        let cache = cache.clone();
+        Box::pin(async move {
+            futures::pin_mut!(best_blocks_subscription, finalized_blocks_subscription);
+
+            loop {
+                match future::select(
+                    best_blocks_subscription.next(),
+                    finalized_blocks_subscription.next(),
+                )
+                .await
+                {
+                    future::Either::Left((Some(block), _)) => {
+                        cache.set_best(block.scale_encoded_header).await
+                    }
+                    future::Either::Right((Some(block), _)) => {
+                        cache.set_finalized(block.scale_encoded_header).await
+                    }
+
+                    // One of the two streams is over.
+                    _ => break,
+                }
+            }
+        })
+    });
+
+    cache
+}
+
+/// Block header stored in a [`HeaderCache`].
+#[derive(Debug)]
+pub struct CachedHeader {
+    /// Hash of the block.
+    pub hash: [u8; 32],
+    /// SCALE-encoded header of the block.
+    pub scale_encoded: Vec<u8>,
+    /// Height of the block.
+    pub number: u64,
+    /// Hash of the parent of the block.
+    pub parent_hash: [u8; 32],
+    /// Merkle value of the root node of the storage trie of the block.
+    pub state_root: [u8; 32],
+}
+
+impl CachedHeader {
+    fn decode(
+        scale_encoded: Vec<u8>,
+        custom_consensus_engines: &[[u8; 4]],
+    ) -> Result<Self, header::Error> {
+        let decoded = header::decode_with_custom_engines(&scale_encoded, custom_consensus_engines)?;
+        Ok(CachedHeader {
+            hash: Host::blake2_256(&scale_encoded),
+            number: decoded.number,
+            parent_hash: *decoded.parent_hash,
+            state_root: *decoded.state_root,
+            scale_encoded,
+        })
+    }
+}
+
+/// See [the module-level documentation](..).
+pub struct HeaderCache {
+    inner: Mutex<Inner>,
+    /// See [`Config::custom_consensus_engines`].
+    custom_consensus_engines: Vec<[u8; 4]>,
+}
+
+struct Inner {
+    /// Header of the current best block. Never evicted.
+    best: Arc<CachedHeader>,
+    /// Header of the current finalized block. Never evicted.
+    finalized: Arc<CachedHeader>,
+    /// Other headers. Might contain the best and finalized blocks as well.
+    recent: lru::LruCache<[u8; 32], Arc<CachedHeader>>,
+    /// Headers of the blocks verified by the sync service, indexed by hash. Pruned whenever the
+    /// finalized block changes. Might contain headers found in the other fields as well.
+    forks: HashMap<[u8; 32], Arc<CachedHeader>, fnv::FnvBuildHasher>,
+    /// See [`Config::fork_blocks_window`].
+    fork_blocks_window: u64,
+}
+
+impl HeaderCache {
+    /// Initializes a new cache, with the given SCALE-encoded best and finalized block headers.
+    ///
+    /// # Panic
+    ///
+    /// Panics if one of the headers can't be decoded.
+    ///
+    pub fn new(
+        capacity: usize,
+        fork_blocks_window: u64,
+        custom_consensus_engines: Vec<[u8; 4]>,
+        best_block_header: Vec<u8>,
+        finalized_block_header: Vec<u8>,
+    ) -> Self {
+        let best = CachedHeader::decode(best_block_header, &custom_consensus_engines).unwrap();
+        let finalized =
+            CachedHeader::decode(finalized_block_header, &custom_consensus_engines).unwrap();
+
+        HeaderCache {
+            inner: Mutex::new(Inner {
+                best: Arc::new(best),
+                finalized: Arc::new(finalized),
+                recent: lru::LruCache::new(capacity),
+                forks: HashMap::default(),
+                fork_blocks_window,
+            }),
+            custom_consensus_engines,
+        }
+    }
+
+    /// Returns the header of the current best block.
+    pub async fn best(&self) -> Arc<CachedHeader> {
+        self.inner.lock().await.best.clone()
+    }
+
+    /// Returns the header of the current finalized block.
+    pub async fn finalized(&self) -> Arc<CachedHeader> {
+        self.inner.lock().await.finalized.clone()
+    }
+
+    /// Returns the header of the block with the given hash, if it is in the cache.
+    pub async fn get(&self, hash: &[u8; 32]) -> Option<Arc<CachedHeader>> {
+        let mut inner = self.inner.lock().await;
+        if inner.best.hash == *hash {
+            return Some(inner.best.clone());
+        }
+        if inner.finalized.hash == *hash {
+            return Some(inner.finalized.clone());
+        }
+        if let Some(cached) = inner.recent.get(hash) {
+            return Some(cached.clone());
+        }
+        inner.forks.get(hash).cloned()
+    }
+
+    /// Inserts the given SCALE-encoded header in the cache, and returns its cached version.
+    ///
+    /// If the header is already in the cache, it isn't decoded again.
+    ///
+    /// The caller must guarantee that the header is valid, as it is then returned by
+    /// [`HeaderCache::get`] to the other users of the cache.
+    pub async fn insert(&self, scale_encoded: Vec<u8>) -> Result<Arc<CachedHeader>, header::Error> {
+        let hash = Host::blake2_256(&scale_encoded);
+        if let Some(cached) = self.get(&hash).await {
+            return Ok(cached);
+        }
+
+        let cached = Arc::new(CachedHeader::decode(
+            scale_encoded,
+            &self.custom_consensus_engines,
+        )?);
+        self.inner.lock().await.recent.put(hash, cached.clone());
+        Ok(cached)
+    }
+
+    /// Inserts the given SCALE-encoded header of a block verified by the sync service. It is
+    /// kept until it is more than [`Config::fork_blocks_window`] blocks below the finalized
+    /// block.
+    ///
+    /// The header isn't inserted in the least-recently-used cache, as the sync service verifies
+    /// many blocks and they would otherwise evict the headers inserted with
+    /// [`HeaderCache::insert`].
+    async fn insert_fork(&self, scale_encoded: Vec<u8>) {
+        let hash = Host::blake2_256(&scale_encoded);
+        let mut inner = self.inner.lock().await;
+        if inner.forks.contains_key(&hash) {
+            return;
+        }
+
+        let cached = match inner.recent.peek(&hash) {
+            Some(cached) => cached.clone(),
+            None => Arc::new(
+                CachedHeader::decode(scale_encoded, &self.custom_consensus_engines).unwrap(),
+            ),
+        };
+        if cached.number.saturating_add(inner.fork_blocks_window) >= inner.finalized.number {
+            inner.forks.insert(hash, cached);
+        }
+    }
+
+    async fn set_best(&self, scale_encoded: Vec<u8>) {
+        let cached = self.insert(scale_encoded).await.unwrap();
+        self.inner.lock().await.best = cached;
+    }
+
+    async fn set_finalized(&self, scale_encoded: Vec<u8>) {
+        let cached = self.insert(scale_encoded).await.unwrap();
+        let mut inner = self.inner.lock().await;
+        inner.finalized = cached;
+
+        let finalized_number = inner.finalized.number;
+        let fork_blocks_window = inner.fork_blocks_window;
+        inner.forks.retain(|_, header| {
+            header.number.saturating_add(fork_blocks_window) >= finalized_number
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HeaderCache;
+    use crate::{
+        platform::{Host, Platform as _},
+        test_utils,
+    };
+    use smoldot::header;
+
+    fn header(number: u64) -> Vec<u8> {
+        header::HeaderRef {
+            parent_hash: &[0; 32],
+            number,
+            state_root: &[number as u8; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::empty(),
+        }
+        .scale_encoding_vec()
+    }
+
+    #[test]
+    fn best_and_finalized_never_evicted() {
+        test_utils::block_on(
+            async move {
+                let cache = HeaderCache::new(2, 0, Vec::new(), header(1), header(0));
+
+                let best_hash = Host::blake2_256(&header(1));
+                let finalized_hash = Host::blake2_256(&header(0));
+
+                for n in 2..10 {
+                    let cached = cache.insert(header(n)).await.unwrap();
+                    assert_eq!(cached.number, n);
+                    assert_eq!(cached.state_root, [n as u8; 32]);
+                }
+
+                assert_eq!(cache.get(&best_hash).await.unwrap().number, 1);
+                assert_eq!(cache.get(&finalized_hash).await.unwrap().number, 0);
+                assert!(cache.get(&Host::blake2_256(&header(2))).await.is_none());
+                assert!(cache.get(&Host::blake2_256(&header(9))).await.is_some());
+            },
+            None,
+        )
+    }
+
+    /// Same as `header`, but for a block of a different fork.
+    fn fork_header(number: u64) -> Vec<u8> {
+        header::HeaderRef {
+            parent_hash: &[1; 32],
+            number,
+            state_root: &[number as u8; 32],
+            extrinsics_root: &[1; 32],
+            digest: header::DigestRef::empty(),
+        }
+        .scale_encoding_vec()
+    }
+
+    #[test]
+    fn fork_blocks_kept_within_window() {
+        test_utils::block_on(
+            async move {
+                let cache = HeaderCache::new(2, 3, Vec::new(), header(1), header(0));
+
+                let fork_hash = Host::blake2_256(&fork_header(2));
+                cache.insert_fork(fork_header(2)).await;
+
+                // The fork block is no longer in the least-recently-used cache, but is still
+                // found.
+                for n in 2..10 {
+                    cache.insert(header(n)).await.unwrap();
+                }
+                assert_eq!(cache.get(&fork_hash).await.unwrap().number, 2);
+
+                // Still within the window.
+                cache.set_finalized(header(5)).await;
+                assert!(cache.get(&fork_hash).await.is_some());
+
+                // More than 3 blocks below the finalized block.
+                cache.set_finalized(header(6)).await;
+                assert!(cache.get(&fork_hash).await.is_none());
+
+                // Blocks already outside of the window aren't inserted.
+                cache.insert_fork(fork_header(1)).await;
+                assert!(cache
+                    .get(&Host::blake2_256(&fork_header(1)))
+                    .await
+                    .is_none());
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn fork_blocks_dont_evict_recent() {
+        test_utils::block_on(
+            async move {
+                let cache = HeaderCache::new(2, 100, Vec::new(), header(1), header(0));
+                cache.insert(header(2)).await.unwrap();
+                cache.insert(header(3)).await.unwrap();
+
+                for n in 2..10 {
+                    cache.insert_fork(fork_header(n)).await;
+                }
+
+                assert!(cache.get(&Host::blake2_256(&header(2))).await.is_some());
+                assert!(cache.get(&Host::blake2_256(&header(3))).await.is_some());
+                assert!(cache
+                    .get(&Host::blake2_256(&fork_header(9)))
+                    .await
+                    .is_some());
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn set_best_updates_best() {
+        test_utils::block_on(
+            async move {
+                let cache = HeaderCache::new(2, 0, Vec::new(), header(1), header(0));
+                cache.set_best(header(5)).await;
+                assert_eq!(cache.best().await.number, 5);
+                assert_eq!(cache.finalized().await.number, 0);
+                assert_eq!(cache.best().await.scale_encoded, header(5));
+            },
+            None,
+        )
+    }
+}
@@ -18,7 +18,8 @@
 //! Background JSON-RPC service.
 //!
 //! The [`start`] function returns a future whose role is to pull events using
-//! [`ffi::next_json_rpc`] and send back answers using [`ffi::emit_json_rpc_response`].
+//! [`platform::Platform::next_json_rpc`] and send back answers using
+//! [`platform::Platform::emit_json_rpc_response`].
 //!
 //! > **Note**: Because of the racy nature of these two functions, it is strongly discouraged to
 //! >           spawn multiple JSON-RPC services, especially if they don't use the same
@@ -27,130 +28,556 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::{ffi, network_service, runtime_service, sync_service, transactions_service};
+use crate::{
+    cpu_usage, cross_validation, header_cache, memory_usage, network_service,
+    platform::{self, Host, Platform as _},
+    request_trace, rpc_fallback, runtime_service, storage_prefetch, sync_service,
+    transactions_service, work_queues,
+};
 
-use futures::{channel::oneshot, lock::Mutex, prelude::*};
+use futures::{
+    channel::{mpsc, oneshot},
+    lock::Mutex,
+    prelude::*,
+};
 use methods::MethodCall;
 use smoldot::{
-    chain_spec, header,
+    chain_spec, executor, header,
     json_rpc::{self, methods},
+    libp2p::peer_id::PeerId,
+    metadata,
     network::protocol,
+    sync::para,
+    trie::proof_verify,
+    util,
 };
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     convert::TryFrom as _,
-    iter,
+    iter, mem,
+    num::{NonZeroU32, NonZeroUsize},
     pin::Pin,
     str,
     sync::{atomic, Arc},
+    time::Duration,
 };
 
+/// JSON-RPC service of a chain, possibly not started yet or suspended.
+///
+/// The services of a lazy chain are started when the first request targeting the chain arrives,
+/// and can later be suspended using [`LazyJsonRpcService::suspend_if_idle`], in which case they
+/// are started again by the next request. See [`already_started`] for chains whose services are
+/// started immediately and never suspended.
+#[derive(Clone)]
+pub struct LazyJsonRpcService {
+    inner: Arc<LazyJsonRpcServiceInner>,
+}
+
+struct LazyJsonRpcServiceInner {
+    /// Service of the chain, or future that yields it while the services are starting. `None`
+    /// if the services haven't been started yet or have been suspended.
+    service: Mutex<Option<future::Shared<future::BoxFuture<'static, Arc<JsonRpcService>>>>>,
+
+    /// Functions that start and suspend the services of the chain. `None` for services built
+    /// with [`already_started`].
+    start_suspend: Option<(LazyStart, LazySuspend)>,
+
+    /// Number of requests targeting the chain that are currently being processed.
+    num_requests_in_progress: atomic::AtomicUsize,
+
+    /// Moment when the last request targeting the chain has finished being processed.
+    last_request_finished: Mutex<platform::Instant>,
+}
+
+/// Starts the services of a lazy chain. See [`LazyJsonRpcService::lazy`].
+pub type LazyStart = Box<dyn Fn() -> future::BoxFuture<'static, Arc<JsonRpcService>> + Send + Sync>;
+
+/// Suspends the services of a lazy chain. See [`LazyJsonRpcService::lazy`].
+pub type LazySuspend = Box<dyn Fn() -> future::BoxFuture<'static, ()> + Send + Sync>;
+
+/// Builds a [`LazyJsonRpcService`] out of a service that has already been started.
+pub fn already_started(service: Arc<JsonRpcService>) -> LazyJsonRpcService {
+    let service = future::ready(service).boxed().shared();
+    // Poll a clone of the future once so that `Shared::peek` returns the service. The returned
+    // future itself must not be polled, as a `Shared` panics if polled again after completion.
+    let _ = service.clone().now_or_never();
+    LazyJsonRpcService {
+        inner: Arc::new(LazyJsonRpcServiceInner {
+            service: Mutex::new(Some(service)),
+            start_suspend: None,
+            num_requests_in_progress: atomic::AtomicUsize::new(0),
+            last_request_finished: Mutex::new(Host::now()),
+        }),
+    }
+}
+
+impl LazyJsonRpcService {
+    /// Builds a [`LazyJsonRpcService`] whose services are started by calling `start` when a
+    /// request targeting the chain arrives.
+    ///
+    /// `suspend` is called by [`LazyJsonRpcService::suspend_if_idle`], after the service
+    /// returned by `start` has been discarded. It must stop the services of the chain, so that
+    /// `start` can be called again later.
+    pub fn lazy(start: LazyStart, suspend: LazySuspend) -> Self {
+        LazyJsonRpcService {
+            inner: Arc::new(LazyJsonRpcServiceInner {
+                service: Mutex::new(None),
+                start_suspend: Some((start, suspend)),
+                num_requests_in_progress: atomic::AtomicUsize::new(0),
+                last_request_finished: Mutex::new(Host::now()),
+            }),
+        }
+    }
+
+    /// Suspends the services of the chain if they are running, if no request targeting the
+    /// chain has been processed during the last `idle_timeout`, and if no JSON-RPC client has
+    /// any subscription on the chain, including subscriptions that are detached.
+    ///
+    /// Returns `true` if the services have been suspended. Always returns `false` for services
+    /// built with [`already_started`].
+    pub async fn suspend_if_idle(&self, idle_timeout: Duration) -> bool {
+        let suspend = match &self.inner.start_suspend {
+            Some((_, suspend)) => suspend,
+            None => return false,
+        };
+
+        let mut service = self.inner.service.lock().await;
+
+        // Services that are still starting are never suspended.
+        let running = match service.as_ref().and_then(|s| s.peek()) {
+            Some(running) => running.clone(),
+            None => return false,
+        };
+
+        if self
+            .inner
+            .num_requests_in_progress
+            .load(atomic::Ordering::SeqCst)
+            != 0
+        {
+            return false;
+        }
+
+        if Host::now() - *self.inner.last_request_finished.lock().await < idle_timeout {
+            return false;
+        }
+
+        if running.has_subscriptions().await {
+            return false;
+        }
+
+        *service = None;
+        drop(running);
+        suspend().await;
+        true
+    }
+
+    /// Returns the service of the chain if it is running.
+    ///
+    /// Services that haven't been started or are suspended can't have any subscription.
+    async fn running(&self) -> Option<Arc<JsonRpcService>> {
+        self.inner
+            .service
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|s| s.peek().cloned())
+    }
+
+    /// Returns the service of the chain, starting it if necessary.
+    ///
+    /// Must be followed by a call to [`LazyJsonRpcService::request_finished`] once the request
+    /// has been processed.
+    async fn request_started(&self) -> Arc<JsonRpcService> {
+        self.inner
+            .num_requests_in_progress
+            .fetch_add(1, atomic::Ordering::SeqCst);
+
+        let service = {
+            let mut service = self.inner.service.lock().await;
+            match &*service {
+                Some(s) => s.clone(),
+                None => {
+                    // `start_suspend` is always `Some` if `service` can be `None`.
+                    let (start, _) = self.inner.start_suspend.as_ref().unwrap();
+                    let s = start().shared();
+                    *service = Some(s.clone());
+                    s
+                }
+            }
+        };
+
+        service.await
+    }
+
+    /// See [`LazyJsonRpcService::request_started`].
+    async fn request_finished(&self) {
+        *self.inner.last_request_finished.lock().await = Host::now();
+        self.inner
+            .num_requests_in_progress
+            .fetch_sub(1, atomic::Ordering::SeqCst);
+    }
+}
+
 /// Spawns a task to handle incoming JSON-RPC requests.
 ///
 /// The task queries incoming requests and dispatches them to the JSON-RPC
-/// services passed as parameter.
+/// services passed as parameter. Services that haven't been started yet are started when a
+/// request targeting their chain arrives.
+///
+/// Requests are grouped by consumer, as identified by their `user_data`, and the limits in
+/// `consumer_limits` are applied to each consumer individually. This prevents a single consumer
+/// from starving the others.
 pub async fn spawn_request_handling_task(
     tasks_executor: Arc<
         Mutex<Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>>,
     >,
-    json_rpc_services: HashMap<usize, Arc<JsonRpcService>>,
+    json_rpc_services: HashMap<usize, LazyJsonRpcService>,
+    consumer_limits: ConsumerLimits,
 ) {
     let json_rpc_services = Arc::new(json_rpc_services);
 
     (tasks_executor.clone().lock().await)(
         "jsonrpc-requests-handling".into(),
         Box::pin(async move {
-            loop {
-                match ffi::next_json_rpc().await {
-                    ffi::JsonRpcMessage::Request {
-                        json_rpc_request,
-                        chain_index,
-                        user_data,
-                    } => {
-                        // Each incoming request gets its own separate task.
-                        let json_rpc_services = json_rpc_services.clone();
-                        (tasks_executor.lock().await)(
-                            "jsonrpc-request-process".into(),
-                            Box::pin(async move {
-                                let request_str = match str::from_utf8(&*json_rpc_request) {
-                                    Ok(s) => s,
-                                    Err(error) => {
-                                        log::warn!(
-                                            target: "json-rpc",
-                                            "Failed to parse JSON-RPC query as UTF-8 (chain_index: {}): {}",
-                                            chain_index, error
-                                        );
-                                        return;
-                                    }
-                                };
+            let mut consumers = Consumers::new(consumer_limits);
 
-                                log::debug!(
-                                    target: "json-rpc",
-                                    "JSON-RPC => {:?}{}",
-                                    if request_str.len() > 100 { &request_str[..100] } else { &request_str[..] },
-                                    if request_str.len() > 100 { "…" } else { "" }
-                                );
-
-                                let (request_id, call) = match methods::parse_json_call(request_str)
-                                {
-                                    Ok(rq) => rq,
-                                    Err(methods::ParseError::Method { request_id, error }) => {
-                                        log::warn!(
-                                            target: "json-rpc",
-                                            "Error in JSON-RPC method call: {}", error
-                                        );
-                                        send_back(
-                                            &error.to_json_error(request_id),
-                                            chain_index,
-                                            user_data,
-                                        );
-                                        return;
-                                    }
-                                    Err(error) => {
-                                        log::warn!(
-                                            target: "json-rpc",
-                                            "Ignoring malformed JSON-RPC call: {}", error
-                                        );
-                                        return;
-                                    }
-                                };
+            // Every time a request has finished being processed, the `user_data` of its
+            // consumer is sent on this channel.
+            let (finished_tx, mut finished_rx) = mpsc::unbounded();
 
-                                match json_rpc_services.get(&chain_index).cloned() {
-                                    Some(service) => {
-                                        service.handle_rpc(user_data, request_id, call).await
-                                    }
-                                    None => {
-                                        send_back(
-                                    &json_rpc::parse::build_error_response(
-                                        request_id,
-                                        json_rpc::parse::ErrorResponse::ApplicationDefined(
-                                            -33000,
-                                            &format!(
-                                            "A JSON-RPC service has not been started for chain index {}",
-                                            chain_index
-                                        ),
-                                        ),
-                                        None,
-                                    ),
-                                    chain_index,
-                                    user_data
-                                );
-                                    }
+            loop {
+                let (user_data, to_start) =
+                    match future::select(Host::next_json_rpc(), finished_rx.next()).await {
+                        future::Either::Left((
+                            platform::JsonRpcMessage::Request {
+                                json_rpc_request,
+                                chain_index,
+                                user_data,
+                            },
+                            _,
+                        )) => {
+                            match consumers.push(
+                                user_data,
+                                Host::now(),
+                                (json_rpc_request, chain_index),
+                            ) {
+                                Admission::Start(request) => (user_data, request),
+                                Admission::Queued => continue,
+                                Admission::Rejected((json_rpc_request, chain_index)) => {
+                                    reject_request(&json_rpc_request, chain_index, user_data);
+                                    continue;
                                 }
-                            }),
-                        );
-                    }
-                    ffi::JsonRpcMessage::UnsubscribeAll { user_data } => {
-                        for service in json_rpc_services.values().cloned() {
-                            service.handle_unsubscribe_all(user_data).await;
+                            }
                         }
-                    }
-                }
+                        future::Either::Left((
+                            platform::JsonRpcMessage::UnsubscribeAll { user_data },
+                            _,
+                        )) => {
+                            consumers.clear_queue(user_data);
+                            for service in json_rpc_services.values() {
+                                if let Some(service) = service.running().await {
+                                    service.handle_unsubscribe_all(user_data).await;
+                                }
+                            }
+                            continue;
+                        }
+                        future::Either::Left((
+                            platform::JsonRpcMessage::DetachAll { user_data, token },
+                            _,
+                        )) => {
+                            consumers.clear_queue(user_data);
+                            for service in json_rpc_services.values() {
+                                if let Some(service) = service.running().await {
+                                    service.handle_detach_all(user_data, token).await;
+                                }
+                            }
+                            continue;
+                        }
+                        future::Either::Left((
+                            platform::JsonRpcMessage::Reattach { token, user_data },
+                            _,
+                        )) => {
+                            for service in json_rpc_services.values() {
+                                if let Some(service) = service.running().await {
+                                    service.handle_reattach(token, user_data).await;
+                                }
+                            }
+                            continue;
+                        }
+                        future::Either::Right((user_data, _)) => {
+                            // `finished_tx` is never dropped, so the channel can't be closed.
+                            let user_data = user_data.unwrap();
+                            match consumers.finished(user_data, Host::now()) {
+                                Some(request) => (user_data, request),
+                                None => continue,
+                            }
+                        }
+                    };
+
+                // Each request that is started gets its own separate task.
+                let (json_rpc_request, chain_index) = to_start;
+                let json_rpc_services = json_rpc_services.clone();
+                let finished_tx = finished_tx.clone();
+                (tasks_executor.lock().await)(
+                    "jsonrpc-request-process".into(),
+                    Box::pin(async move {
+                        process_request(
+                            &json_rpc_services,
+                            &json_rpc_request,
+                            chain_index,
+                            user_data,
+                        )
+                        .await;
+                        let _ = finished_tx.unbounded_send(user_data);
+                    }),
+                );
             }
         }),
     );
 }
 
+/// Parses the given JSON-RPC request and dispatches it to the appropriate service.
+async fn process_request(
+    json_rpc_services: &HashMap<usize, LazyJsonRpcService>,
+    json_rpc_request: &[u8],
+    chain_index: usize,
+    user_data: u32,
+) {
+    let request_str = match str::from_utf8(json_rpc_request) {
+        Ok(s) => s,
+        Err(error) => {
+            log::warn!(
+                target: "json-rpc",
+                "Failed to parse JSON-RPC query as UTF-8 (chain_index: {}): {}",
+                chain_index, error
+            );
+            return;
+        }
+    };
+
+    log::debug!(
+        target: "json-rpc",
+        "JSON-RPC => {:?}{}",
+        if request_str.len() > 100 { &request_str[..100] } else { &request_str[..] },
+        if request_str.len() > 100 { "…" } else { "" }
+    );
+
+    let (request_id, call) = match methods::parse_json_call(request_str) {
+        Ok(rq) => rq,
+        Err(methods::ParseError::Method { request_id, error }) => {
+            log::warn!(
+                target: "json-rpc",
+                "Error in JSON-RPC method call: {}", error
+            );
+            send_back(&error.to_json_error(request_id), chain_index, user_data);
+            return;
+        }
+        Err(error) => {
+            log::warn!(
+                target: "json-rpc",
+                "Ignoring malformed JSON-RPC call: {}", error
+            );
+            return;
+        }
+    };
+
+    match json_rpc_services.get(&chain_index).cloned() {
+        Some(lazy) => {
+            let service = lazy.request_started().await;
+            if let Some(cross_validation) = &service.cross_validation {
+                if service.methods_filter.is_allowed(call.name()) {
+                    cross_validation.on_request(user_data, request_id, call.name(), request_str);
+                }
+            }
+            service.handle_rpc(user_data, request_id, call).await;
+            lazy.request_finished().await;
+        }
+        None => {
+            send_back(
+                &json_rpc::parse::build_error_response(
+                    request_id,
+                    json_rpc::parse::ErrorResponse::ApplicationDefined(
+                        -33000,
+                        &format!(
+                            "A JSON-RPC service has not been started for chain index {}",
+                            chain_index
+                        ),
+                    ),
+                    None,
+                ),
+                chain_index,
+                user_data,
+            );
+        }
+    }
+}
+
+/// Sends back an error response to a request that has been refused because its consumer
+/// exceeded the limits of [`ConsumerLimits`].
+fn reject_request(json_rpc_request: &[u8], chain_index: usize, user_data: u32) {
+    let request_id = match str::from_utf8(json_rpc_request)
+        .ok()
+        .and_then(|rq| json_rpc::parse::parse_call(rq).ok())
+        .and_then(|call| call.id_json)
+    {
+        Some(id) => id,
+        None => {
+            log::warn!(
+                target: "json-rpc",
+                "Discarding JSON-RPC request of user data {} exceeding limits", user_data
+            );
+            return;
+        }
+    };
+
+    log::debug!(
+        target: "json-rpc",
+        "Refusing JSON-RPC request of user data {}: too many requests", user_data
+    );
+
+    send_back(
+        &json_rpc::parse::build_error_response(
+            request_id,
+            json_rpc::parse::ErrorResponse::ServerError(-32005, "Too many requests"),
+            None,
+        ),
+        chain_index,
+        user_data,
+    );
+}
+
+/// Limits applied to each consumer of the JSON-RPC services individually. Consumers are
+/// identified by the `user_data` passed alongside their requests.
+#[derive(Debug, Clone, Default)]
+pub struct ConsumerLimits {
+    /// Maximum number of requests of a single consumer that are processed at the same time.
+    /// Additional requests are queued. `None` for no limit.
+    pub max_concurrent_requests: Option<NonZeroUsize>,
+
+    /// Maximum number of requests of a single consumer that can be queued waiting for the
+    /// requests being processed to finish. Additional requests are refused with an error.
+    /// Irrelevant if [`ConsumerLimits::max_concurrent_requests`] is `None`.
+    pub max_queued_requests: usize,
+
+    /// Maximum number of requests that a single consumer can send within a second. Additional
+    /// requests are refused with an error. `None` for no limit.
+    pub max_requests_per_second: Option<NonZeroU32>,
+}
+
+/// Scheduling state of all the consumers of the JSON-RPC services.
+///
+/// `T` is the type of the requests.
+struct Consumers<T> {
+    /// See [`ConsumerLimits`].
+    limits: ConsumerLimits,
+    /// State of each consumer, indexed by `user_data`. A consumer is removed when its last
+    /// request being processed finishes, if its rate limiting window is over.
+    list: HashMap<u32, Consumer<T>>,
+}
+
+struct Consumer<T> {
+    /// Number of requests of this consumer currently being processed.
+    in_progress: usize,
+    /// Requests waiting for [`Consumer::in_progress`] to decrease.
+    queue: VecDeque<T>,
+    /// When the current rate limiting window has started.
+    window_start: platform::Instant,
+    /// Number of requests received since [`Consumer::window_start`].
+    window_requests: u32,
+}
+
+/// Outcome of [`Consumers::push`].
+enum Admission<T> {
+    /// The request must start being processed immediately.
+    Start(T),
+    /// The request has been queued, and will later be returned by [`Consumers::finished`].
+    Queued,
+    /// The request exceeds the limits of its consumer and must be refused.
+    Rejected(T),
+}
+
+impl<T> Consumers<T> {
+    fn new(limits: ConsumerLimits) -> Self {
+        Consumers {
+            limits,
+            list: HashMap::new(),
+        }
+    }
+
+    /// Registers a new request of the given consumer.
+    fn push(&mut self, user_data: u32, now: platform::Instant, request: T) -> Admission<T> {
+        let consumer = self.list.entry(user_data).or_insert_with(|| Consumer {
+            in_progress: 0,
+            queue: VecDeque::new(),
+            window_start: now,
+            window_requests: 0,
+        });
+
+        if now - consumer.window_start >= Duration::from_secs(1) {
+            consumer.window_start = now;
+            consumer.window_requests = 0;
+        }
+
+        if let Some(max_requests_per_second) = self.limits.max_requests_per_second {
+            if consumer.window_requests >= max_requests_per_second.get() {
+                return Admission::Rejected(request);
+            }
+        }
+
+        if self
+            .limits
+            .max_concurrent_requests
+            .map_or(true, |max| consumer.in_progress < max.get())
+        {
+            consumer.window_requests += 1;
+            consumer.in_progress += 1;
+            Admission::Start(request)
+        } else if consumer.queue.len() < self.limits.max_queued_requests {
+            consumer.window_requests += 1;
+            consumer.queue.push_back(request);
+            Admission::Queued
+        } else {
+            Admission::Rejected(request)
+        }
+    }
+
+    /// Must be called when a request of the given consumer has finished being processed.
+    /// Returns the next request of this consumer that must start being processed, if any.
+    fn finished(&mut self, user_data: u32, now: platform::Instant) -> Option<T> {
+        let consumer = self.list.get_mut(&user_data)?;
+
+        if let Some(request) = consumer.queue.pop_front() {
+            return Some(request);
+        }
+
+        debug_assert!(consumer.in_progress >= 1);
+        consumer.in_progress -= 1;
+        if consumer.in_progress == 0 && now - consumer.window_start >= Duration::from_secs(1) {
+            self.list.remove(&user_data);
+        }
+        None
+    }
+
+    /// Discards all the queued requests of the given consumer.
+    fn clear_queue(&mut self, user_data: u32) {
+        if let Some(consumer) = self.list.get_mut(&user_data) {
+            consumer.queue.clear();
+        }
+    }
+}
+
+/// Duration after which subscriptions that have been detached and not reattached are destroyed.
+const DETACHED_SUBSCRIPTIONS_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Maximum number of notifications buffered for each subscription while the subscriptions are
+/// detached. The oldest notifications are discarded when this limit is reached.
+const MAX_DETACHED_NOTIFICATIONS: usize = 32;
+
+/// Maximum duration that `sudo_unstable_chainHeadState` waits for the runtime of the best block
+/// to be available before reporting it as unknown.
+const CHAIN_HEAD_STATE_RUNTIME_TIMEOUT: Duration = Duration::from_secs(2);
+
 /// Configuration for a JSON-RPC service.
 pub struct Config {
     /// Closure that spawns background tasks.
@@ -169,6 +596,9 @@ pub struct Config {
     /// Service that provides a ready-to-be-called runtime for the current best block.
     pub runtime_service: Arc<runtime_service::RuntimeService>,
 
+    /// Cache of the recent headers of the chain, shared with the other services of the chain.
+    pub header_cache: Arc<header_cache::HeaderCache>,
+
     /// Specifications of the chain.
     pub chain_spec: chain_spec::ChainSpec,
 
@@ -190,84 +620,169 @@ pub struct Config {
 
     /// The index of the chain that this service is handling requests for. Used only for the FFI layer.
     pub chain_index: usize,
+
+    /// Which JSON-RPC methods can be called. Calling a method that isn't allowed results in an
+    /// error response.
+    pub methods_filter: MethodsFilter,
+
+    /// If `true`, the `sudo_unstable_p2pRequest` JSON-RPC method, which sends arbitrary requests
+    /// to peers, can be called. If `false`, calling it results in an error response regardless
+    /// of [`Config::methods_filter`].
+    pub unstable_p2p_requests: bool,
+
+    /// CPU time accounting of the chain, reported by the `system_unstable_cpuUsage` JSON-RPC
+    /// method.
+    pub cpu_usage: Arc<cpu_usage::CpuUsage>,
+
+    /// If the chain is a parachain, contains the services of its relay chain. Used by the
+    /// `sudo_unstable_parachainMessageQueues` JSON-RPC method.
+    pub relay_chain: Option<ConfigRelayChain>,
+
+    /// If `Some`, some of the requests are also sent to a full node, and the responses are
+    /// compared. See the [`cross_validation`] module.
+    pub cross_validation: Option<cross_validation::Config>,
+
+    /// If `Some`, storage queries and runtime calls that can't be answered from the network are
+    /// instead answered by a trusted full node. See the [`rpc_fallback`] module.
+    pub rpc_fallback: Option<rpc_fallback::Config>,
+
+    /// If `Some`, the values of up to this number of recently-requested storage keys are
+    /// downloaded ahead of time at each new best block. See the [`storage_prefetch`] module.
+    pub storage_prefetch_keys: Option<NonZeroUsize>,
+}
+
+/// See [`Config::relay_chain`].
+pub struct ConfigRelayChain {
+    /// Identifier of the parachain within the relay chain.
+    pub parachain_id: u32,
+
+    /// Service responsible for synchronizing the relay chain.
+    pub sync_service: Arc<sync_service::SyncService>,
+
+    /// Service that provides a ready-to-be-called runtime for the best block of the relay chain.
+    pub runtime_service: Arc<runtime_service::RuntimeService>,
+}
+
+/// Filter indicating which JSON-RPC methods can be called.
+///
+/// Each pattern is either the name of a method, for example `author_submitExtrinsic`, or a
+/// prefix followed with `*`, for example `author_*`, matching all the methods that start with
+/// this prefix. Aliases are resolved before the filter is applied, meaning that patterns must
+/// match the non-alias name of the methods.
+///
+/// The methods listed in [`UNSAFE_METHODS`] are denied unless [`MethodsFilter::allow_unsafe`] is
+/// `true`, even if they match [`MethodsFilter::allow`].
+#[derive(Debug, Clone, Default)]
+pub struct MethodsFilter {
+    /// If `Some`, only the methods matching at least one of these patterns are allowed. If
+    /// `None`, all the methods are allowed unless they match [`MethodsFilter::deny`].
+    pub allow: Option<Vec<String>>,
+
+    /// Methods matching at least one of these patterns are never allowed, even if they also
+    /// match [`MethodsFilter::allow`].
+    pub deny: Vec<String>,
+
+    /// If `true`, the methods of [`UNSAFE_METHODS`] are subject to the same rules as the other
+    /// methods. If `false`, they are never allowed.
+    pub allow_unsafe: bool,
+}
+
+/// Methods that modify the state of the client in a way that affects all the users of the
+/// JSON-RPC service, and that shouldn't be exposed to untrusted users. Equivalent to the methods
+/// that Substrate refuses when `--rpc-methods` is `Safe`.
+pub const UNSAFE_METHODS: &[&str] = &[
+    "author_hasKey",
+    "author_insertKey",
+    "author_removeExtrinsic",
+    "author_rotateKeys",
+];
+
+impl MethodsFilter {
+    /// Returns `true` if the method with the given name can be called.
+    pub fn is_allowed(&self, method: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => method.starts_with(prefix),
+            None => pattern == method,
+        };
+
+        if !self.allow_unsafe && UNSAFE_METHODS.contains(&method) {
+            return false;
+        }
+
+        if self.deny.iter().any(matches) {
+            return false;
+        }
+
+        self.allow
+            .as_ref()
+            .map_or(true, |allow| allow.iter().any(matches))
+    }
 }
 
 /// Initializes the JSON-RPC service with the given configuration.
-pub async fn start(config: Config) -> Arc<JsonRpcService> {
-    let (_finalized_block_header, finalized_blocks_subscription) =
-        config.sync_service.subscribe_best().await;
-    let (best_block_header, best_blocks_subscription) = config.sync_service.subscribe_best().await;
-    debug_assert_eq!(_finalized_block_header, best_block_header);
-    let best_block_hash = header::hash_from_scale_encoded_header(&best_block_header);
-
-    let mut known_blocks = lru::LruCache::new(256);
-    known_blocks.put(
-        best_block_hash,
-        header::decode(&best_block_header).unwrap().into(),
-    );
+pub async fn start(mut config: Config) -> Arc<JsonRpcService> {
+    let cross_validation = config.cross_validation.take().map(|cross_validation| {
+        cross_validation::CrossValidation::new(
+            cross_validation,
+            config.chain_spec.name().to_owned(),
+            &mut config.tasks_executor,
+        )
+    });
+
+    let rpc_fallback = config.rpc_fallback.take().map(|rpc_fallback| {
+        rpc_fallback::RpcFallback::new(
+            rpc_fallback,
+            config.chain_spec.name().to_owned(),
+            &mut config.tasks_executor,
+        )
+    });
+
+    let storage_prefetch = config.storage_prefetch_keys.map(|max_keys| {
+        storage_prefetch::start(
+            storage_prefetch::Config {
+                sync_service: config.sync_service.clone(),
+                max_keys,
+            },
+            &mut config.tasks_executor,
+        )
+    });
 
-    let client = Arc::new(JsonRpcService {
+    Arc::new(JsonRpcService {
         tasks_executor: Mutex::new(config.tasks_executor),
         chain_spec: config.chain_spec,
         network_service: config.network_service.0,
+        network_chain_index: config.network_service.1,
         sync_service: config.sync_service,
         runtime_service: config.runtime_service,
         transactions_service: config.transactions_service,
-        blocks: Mutex::new(Blocks {
-            known_blocks,
-            best_block: best_block_hash,
-            finalized_block: best_block_hash,
-        }),
+        header_cache: config.header_cache,
         genesis_block: config.genesis_block_hash,
         next_subscription: atomic::AtomicU64::new(0),
         per_userdata_subscriptions: Default::default(),
+        detached_subscriptions: Default::default(),
         chain_index: config.chain_index,
-    });
-
-    // Spawns a task whose role is to update `blocks` with the new best and finalized blocks.
-    (client.clone().tasks_executor.lock().await)("jsonrpc-known-blocks-update".into(), {
-        let client = client.clone();
-        Box::pin(async move {
-            futures::pin_mut!(best_blocks_subscription, finalized_blocks_subscription);
-
-            loop {
-                match future::select(
-                    best_blocks_subscription.next(),
-                    finalized_blocks_subscription.next(),
-                )
-                .await
-                {
-                    future::Either::Left((Some(block), _)) => {
-                        let hash = header::hash_from_scale_encoded_header(&block);
-                        let mut blocks = client.blocks.lock().await;
-                        let blocks = &mut *blocks;
-                        blocks.best_block = hash;
-                        // As a small trick, we re-query the finalized block from `known_blocks` in
-                        let header = header::decode(&block).unwrap().into();
-                        // order to ensure that it never leaves the LRU cache.
-                        blocks.known_blocks.get(&blocks.finalized_block);
-                        blocks.known_blocks.put(hash, header);
-                    }
-                    future::Either::Right((Some(block), _)) => {
-                        let hash = header::hash_from_scale_encoded_header(&block);
-                        let header = header::decode(&block).unwrap().into();
-                        let mut blocks = client.blocks.lock().await;
-                        blocks.finalized_block = hash;
-                        blocks.known_blocks.put(hash, header);
-                    }
-
-                    // One of the two streams is over.
-                    _ => break,
-                }
-            }
-        })
-    });
-
-    client
+        methods_filter: config.methods_filter,
+        unstable_p2p_requests: config.unstable_p2p_requests,
+        cpu_usage: config.cpu_usage,
+        relay_chain: config.relay_chain,
+        cross_validation,
+        rpc_fallback,
+        storage_prefetch,
+        request_traces: request_trace::RecentTraces::new(
+            NonZeroUsize::new(MAX_REQUEST_TRACES).unwrap(),
+        ),
+    })
 }
 
-#[derive(Default)]
+/// Number of JSON-RPC requests whose trace is reported by `system_unstable_requestTraces`.
+const MAX_REQUEST_TRACES: usize = 64;
+
 struct PerUserDataSubscriptions {
+    /// Value of `user_data` the subscriptions currently belong to. Modified when the
+    /// subscriptions are reattached to a different user data.
+    /// See [`JsonRpcService::handle_reattach`].
+    user_data: atomic::AtomicU32,
+
     /// For each active finalized blocks subscription (the key), a sender. If the user
     /// unsubscribes, send the unsubscription request ID of the channel in order to close the
     /// subscription.
@@ -287,6 +802,72 @@ struct PerUserDataSubscriptions {
 
     /// Same principle as [`PerUserDataSubscriptions::all_heads`], but for runtime specs.
     runtime_specs: Mutex<HashMap<String, oneshot::Sender<String>>>,
+
+    /// Same principle as [`PerUserDataSubscriptions::all_heads`], but for watched accounts.
+    accounts: Mutex<HashMap<String, oneshot::Sender<String>>>,
+
+    /// Same principle as [`PerUserDataSubscriptions::all_heads`], but for candidate events.
+    candidate_events: Mutex<HashMap<String, oneshot::Sender<String>>>,
+
+    /// Notifications generated while the subscriptions are detached, indexed by subscription
+    /// ID. Sent once the subscriptions are reattached.
+    /// See [`JsonRpcService::handle_reattach`].
+    detached_notifications: Mutex<HashMap<String, DetachedNotifications>>,
+}
+
+/// See [`PerUserDataSubscriptions::detached_notifications`].
+#[derive(Default)]
+struct DetachedNotifications {
+    /// Notifications in the order in which they have been generated. Contains at most
+    /// [`MAX_DETACHED_NOTIFICATIONS`] elements.
+    queue: VecDeque<String>,
+    /// Number of notifications that have been discarded because `queue` was full.
+    num_dropped: u64,
+}
+
+impl DetachedNotifications {
+    /// Adds a notification to the queue, discarding the oldest one if the queue is full.
+    fn push(&mut self, notification: String) {
+        if self.queue.len() >= MAX_DETACHED_NOTIFICATIONS {
+            self.queue.pop_front();
+            self.num_dropped += 1;
+        }
+        self.queue.push_back(notification);
+    }
+}
+
+impl PerUserDataSubscriptions {
+    fn new(user_data: u32) -> Self {
+        PerUserDataSubscriptions {
+            user_data: atomic::AtomicU32::new(user_data),
+            all_heads: Default::default(),
+            new_heads: Default::default(),
+            finalized_heads: Default::default(),
+            storage: Default::default(),
+            transactions: Default::default(),
+            runtime_specs: Default::default(),
+            accounts: Default::default(),
+            candidate_events: Default::default(),
+            detached_notifications: Default::default(),
+        }
+    }
+
+    /// Returns the value of `user_data` the subscriptions currently belong to.
+    fn user_data(&self) -> u32 {
+        self.user_data.load(atomic::Ordering::Relaxed)
+    }
+
+    /// Returns `true` if there isn't any active subscription.
+    async fn is_empty(&self) -> bool {
+        self.all_heads.lock().await.is_empty()
+            && self.new_heads.lock().await.is_empty()
+            && self.finalized_heads.lock().await.is_empty()
+            && self.storage.lock().await.is_empty()
+            && self.transactions.lock().await.is_empty()
+            && self.runtime_specs.lock().await.is_empty()
+            && self.accounts.lock().await.is_empty()
+            && self.candidate_events.lock().await.is_empty()
+    }
 }
 
 pub struct JsonRpcService {
@@ -297,6 +878,9 @@ pub struct JsonRpcService {
 
     /// See [`Config::network_service`].
     network_service: Arc<network_service::NetworkService>,
+    /// Index of the chain within [`JsonRpcService::network_service`]. See
+    /// [`Config::network_service`].
+    network_chain_index: usize,
     /// See [`Config::sync_service`].
     sync_service: Arc<sync_service::SyncService>,
     /// See [`Config::runtime_service`].
@@ -304,8 +888,8 @@ pub struct JsonRpcService {
     /// See [`Config::transactions_service`].
     transactions_service: Arc<transactions_service::TransactionsService>,
 
-    /// Blocks that are temporarily saved in order to serve JSON-RPC requests.
-    blocks: Mutex<Blocks>,
+    /// See [`Config::header_cache`].
+    header_cache: Arc<header_cache::HeaderCache>,
 
     /// Hash of the genesis block.
     /// Keeping the genesis block is important, as the genesis block hash is included in
@@ -317,32 +901,51 @@ pub struct JsonRpcService {
     /// For each value of `user_data` passed when a JSON-RPC request is received, the list of
     /// active subscriptions.
     ///
-    /// In order to properly implement [`ffi::JsonRpcMessage::UnsubscribeAll`], "destroying" a
+    /// In order to properly implement [`platform::JsonRpcMessage::UnsubscribeAll`], "destroying" a
     /// "user data" must be performed instantaneously, and each "user data" must refer to a unique
     /// `Arc`.
     per_userdata_subscriptions: Mutex<HashMap<u32, Arc<PerUserDataSubscriptions>>>,
 
+    /// Subscriptions that have been detached from their user data, indexed by the token passed
+    /// to [`platform::JsonRpcMessage::DetachAll`]. The subscriptions remain active, but their
+    /// notifications are discarded until they are reattached.
+    ///
+    /// Must always be locked after [`JsonRpcService::per_userdata_subscriptions`] if both are
+    /// locked at the same time.
+    detached_subscriptions: Mutex<HashMap<u32, Arc<PerUserDataSubscriptions>>>,
+
     /// The index of the chain that this service is handling requests for.
     chain_index: usize,
-}
 
-struct Blocks {
-    /// Blocks that are temporarily saved in order to serve JSON-RPC requests.
-    ///
-    /// Always contains `best_block` and `finalized_block`.
-    known_blocks: lru::LruCache<[u8; 32], header::Header>,
+    /// See [`Config::methods_filter`].
+    methods_filter: MethodsFilter,
+
+    /// See [`Config::unstable_p2p_requests`].
+    unstable_p2p_requests: bool,
+
+    /// See [`Config::cpu_usage`].
+    cpu_usage: Arc<cpu_usage::CpuUsage>,
+
+    /// See [`Config::relay_chain`].
+    relay_chain: Option<ConfigRelayChain>,
+
+    /// See [`Config::cross_validation`].
+    cross_validation: Option<cross_validation::CrossValidation>,
 
-    /// Hash of the current best block.
-    best_block: [u8; 32],
+    /// See [`Config::rpc_fallback`].
+    rpc_fallback: Option<rpc_fallback::RpcFallback>,
 
-    /// Hash of the latest finalized block.
-    finalized_block: [u8; 32],
+    /// Built from [`Config::storage_prefetch_keys`].
+    storage_prefetch: Option<Arc<storage_prefetch::StoragePrefetch>>,
+
+    /// Traces of the most recent requests, reported by `system_unstable_requestTraces`.
+    request_traces: request_trace::RecentTraces,
 }
 
 /// Send back a response or a notification to the JSON-RPC client.
 ///
-/// > **Note**: This method wraps around [`ffi::emit_json_rpc_response`] and exists primarily
-/// >           in order to print a log message.
+/// > **Note**: This method wraps around [`platform::Platform::emit_json_rpc_response`] and
+/// >           exists primarily in order to print a log message.
 fn send_back(message: &str, chain_index: usize, user_data: u32) {
     log::debug!(
         target: "json-rpc",
@@ -351,52 +954,496 @@ fn send_back(message: &str, chain_index: usize, user_data: u32) {
         if message.len() > 100 { "…" } else { "" }
     );
 
-    ffi::emit_json_rpc_response(message, chain_index, user_data);
+    Host::emit_json_rpc_response(message, chain_index, user_data);
 }
 
-impl JsonRpcService {
-    /// Send back a response or a notification to the JSON-RPC client.
-    fn send_back(&self, message: &str, user_data: u32) {
-        send_back(message, self.chain_index, user_data)
+/// Send back a successful response whose result is the hexadecimal encoding of `result`.
+///
+/// Contrary to [`send_back`], the response is serialized and sent in chunks using
+/// [`platform::Platform::emit_json_rpc_response_chunk`], in order to avoid holding the entire
+/// response in memory. Meant to be used for potentially very large responses, such as the
+/// runtime metadata.
+fn send_back_hex_chunked(request_id: &str, result: &[u8], chain_index: usize, user_data: u32) {
+    /// Maximum number of bytes of `result` to encode in a single chunk.
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    log::debug!(
+        target: "json-rpc",
+        "JSON-RPC <= (id: {}) {} bytes of hexadecimal result in chunks",
+        request_id,
+        result.len()
+    );
+
+    let mut chunks =
+        json_rpc::parse::build_success_response_hex_chunks(request_id, result, CHUNK_SIZE)
+            .peekable();
+    while let Some(chunk) = chunks.next() {
+        let is_final = chunks.peek().is_none();
+        Host::emit_json_rpc_response_chunk(&chunk, chain_index, user_data, is_final);
     }
+}
 
-    /// Analyzes the given JSON-RPC call and processes it.
-    ///
-    /// Depending on the request, either calls [`JsonRpcService::send_back`] immediately or
-    /// spawns a background task for further processing.
+/// Builds the JSON-RPC error response corresponding to a transaction that has been refused by
+/// the transactions service.
+fn submit_extrinsic_error_response(
+    request_id: &str,
+    error: &transactions_service::ValidateTransactionError,
+) -> String {
+    let kind = match error {
+        transactions_service::ValidateTransactionError::Invalid(_)
+        | transactions_service::ValidateTransactionError::CannotPayFees { .. } => {
+            ErrorKind::InvalidTransaction
+        }
+        transactions_service::ValidateTransactionError::Unknown(_) => {
+            ErrorKind::UnknownTransactionValidity
+        }
+        transactions_service::ValidateTransactionError::Call(_)
+        | transactions_service::ValidateTransactionError::Output(_) => {
+            ErrorKind::TransactionVerification
+        }
+    };
+
+    error_response(request_id, kind, &error.to_string())
+}
+
+/// Category of failure that happened while answering a JSON-RPC request.
+///
+/// Each kind is reported with the code and message that Substrate full nodes use for the same
+/// failure, so that the error handling of existing clients keeps working. See
+/// [`ErrorKind::to_error_response`]. The `data` field of the error contains an object of the
+/// form `{"kind": ..., "message": ...}` with more details.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ErrorKind {
+    /// The parameters of the request are invalid.
+    InvalidParams,
+    /// The transaction is invalid according to the runtime.
+    InvalidTransaction,
+    /// The runtime couldn't determine whether the transaction is valid.
+    UnknownTransactionValidity,
+    /// Error while asking the runtime to validate a transaction.
+    TransactionVerification,
+    /// No peer was available to answer the request.
+    NoPeer,
+    /// The request to the peers has failed.
+    Network,
+    /// The requested block is unknown, or has been pruned by the peers.
+    UnknownBlock,
+    /// A proof sent by the peers is invalid.
+    InvalidProof,
+    /// Error while executing the runtime.
+    RuntimeCall,
+    /// The client doesn't have enough memory to hold the requested data.
+    OutOfMemory,
+}
+
+impl ErrorKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::InvalidParams => "invalidParams",
+            ErrorKind::InvalidTransaction => "invalidTransaction",
+            ErrorKind::UnknownTransactionValidity => "unknownTransactionValidity",
+            ErrorKind::TransactionVerification => "transactionVerification",
+            ErrorKind::NoPeer => "noPeer",
+            ErrorKind::Network => "network",
+            ErrorKind::UnknownBlock => "unknownBlock",
+            ErrorKind::InvalidProof => "invalidProof",
+            ErrorKind::RuntimeCall => "runtimeCall",
+            ErrorKind::OutOfMemory => "outOfMemory",
+        }
+    }
+
+    /// Returns the code and message of the JSON-RPC error.
+    ///
+    /// Substrate full nodes report the failures that aren't specific to a JSON-RPC method, such
+    /// as an unknown block or a failed runtime call, as a generic internal error with the
+    /// message `Unknown error occurred`. Transactions refused by the pool use the codes of the
+    /// `author` methods.
+    fn to_error_response(self) -> json_rpc::parse::ErrorResponse<'static> {
+        match self {
+            ErrorKind::InvalidParams => json_rpc::parse::ErrorResponse::InvalidParams,
+            ErrorKind::TransactionVerification => {
+                json_rpc::parse::ErrorResponse::ApplicationDefined(1002, "Verification Error")
+            }
+            ErrorKind::InvalidTransaction => {
+                json_rpc::parse::ErrorResponse::ApplicationDefined(1010, "Invalid Transaction")
+            }
+            ErrorKind::UnknownTransactionValidity => {
+                json_rpc::parse::ErrorResponse::ApplicationDefined(
+                    1011,
+                    "Unknown Transaction Validity",
+                )
+            }
+            ErrorKind::NoPeer
+            | ErrorKind::Network
+            | ErrorKind::UnknownBlock
+            | ErrorKind::InvalidProof
+            | ErrorKind::RuntimeCall
+            | ErrorKind::OutOfMemory => {
+                json_rpc::parse::ErrorResponse::InternalErrorWithMessage("Unknown error occurred")
+            }
+        }
+    }
+
+    fn from_storage_query_error(error: &StorageQueryError) -> Self {
+        match error {
+            StorageQueryError::FindStorageRootHashError => ErrorKind::UnknownBlock,
+            StorageQueryError::StorageRetrieval(error) => {
+                ErrorKind::from_sync_storage_query_error(error)
+            }
+        }
+    }
+
+    fn from_sync_storage_query_error(error: &sync_service::StorageQueryError) -> Self {
+        if error.errors.is_empty() {
+            return ErrorKind::NoPeer;
+        }
+
+        if error
+            .errors
+            .iter()
+            .any(|err| matches!(err, sync_service::StorageQueryErrorDetail::OutOfMemory(_)))
+        {
+            return ErrorKind::OutOfMemory;
+        }
+
+        // TODO: as a temporary hack, we consider `TrieRootNotFound` as the remote not knowing about the requested block; see https://github.com/paritytech/substrate/pull/8046
+        if error.errors.iter().all(|err| {
+            matches!(
+                err,
+                sync_service::StorageQueryErrorDetail::ProofVerification(
+                    proof_verify::Error::TrieRootNotFound
+                )
+            )
+        }) {
+            return ErrorKind::UnknownBlock;
+        }
+
+        if error.is_network_problem() {
+            ErrorKind::Network
+        } else {
+            ErrorKind::InvalidProof
+        }
+    }
+
+    fn from_runtime_call_error(error: &runtime_service::RuntimeCallError) -> Self {
+        match error {
+            runtime_service::RuntimeCallError::StorageRetrieval(
+                proof_verify::Error::TrieRootNotFound,
+            ) => ErrorKind::UnknownBlock,
+            runtime_service::RuntimeCallError::StorageRetrieval(_)
+            | runtime_service::RuntimeCallError::InvalidCallProof
+            | runtime_service::RuntimeCallError::IncompleteCallProof => ErrorKind::InvalidProof,
+            runtime_service::RuntimeCallError::CallError(_)
+            | runtime_service::RuntimeCallError::StartError(_)
+            | runtime_service::RuntimeCallError::InvalidRuntime
+            | runtime_service::RuntimeCallError::MemoryLimitExceeded
+            | runtime_service::RuntimeCallError::CallAfterInitializeError(_) => {
+                ErrorKind::RuntimeCall
+            }
+            runtime_service::RuntimeCallError::OutOfMemory(_)
+            | runtime_service::RuntimeCallError::RuntimeOutOfMemory => ErrorKind::OutOfMemory,
+            runtime_service::RuntimeCallError::CallProofDownload => ErrorKind::Network,
+            runtime_service::RuntimeCallError::FinalizedRuntimeDownload(err) => {
+                if err.is_network_problem() {
+                    ErrorKind::Network
+                } else {
+                    ErrorKind::InvalidProof
+                }
+            }
+        }
+    }
+}
+
+/// Builds the JSON-RPC error response corresponding to a failure of the given kind. See
+/// [`ErrorKind`].
+fn error_response(request_id: &str, kind: ErrorKind, message: &str) -> String {
+    let data = serde_json::json!({
+        "kind": kind.as_str(),
+        "message": message,
+    });
+
+    json_rpc::parse::build_error_response(
+        request_id,
+        kind.to_error_response(),
+        Some(&data.to_string()),
+    )
+}
+
+/// Converts the GrandPa state reported by the syncing service to its JSON-RPC representation.
+/// Waits for `future` to finish, or for `timeout` to elapse, in which case `None` is returned.
+async fn with_timeout<T>(future: impl Future<Output = T>, timeout: Duration) -> Option<T> {
+    futures::pin_mut!(future);
+    match future::select(future, Host::sleep(timeout)).await {
+        future::Either::Left((output, _)) => Some(output),
+        future::Either::Right(((), _)) => None,
+    }
+}
+
+/// Builds the value returned by `system_properties`.
+///
+/// The properties of the chain specification are returned as they are, except for the
+/// well-known properties that can't be interpreted, which are removed in order to not mislead
+/// the UIs that rely on them. See [`chain_spec::ChainSpec::known_properties`].
+fn system_properties(chain_spec: &chain_spec::ChainSpec) -> Box<serde_json::value::RawValue> {
+    let mut properties =
+        serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(chain_spec.properties())
+            .unwrap_or_default();
+
+    let known = chain_spec.known_properties();
+    for (name, is_valid) in [
+        ("tokenDecimals", known.token_decimals.is_some()),
+        ("tokenSymbol", known.token_symbol.is_some()),
+        ("ss58Format", known.ss58_format.is_some()),
+    ] {
+        if !is_valid {
+            properties.remove(name);
+        }
+    }
+
+    serde_json::value::to_raw_value(&properties).unwrap()
+}
+
+fn grandpa_round_state(state: sync_service::GrandpaState) -> methods::GrandpaRoundState {
+    let convert = |list: &[header::GrandpaAuthority]| {
+        list.iter()
+            .map(|a| methods::GrandpaAuthority {
+                public_key: methods::HashHexString(a.public_key),
+                weight: a.weight.get(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let total_weight = state
+        .authorities
+        .iter()
+        .fold(0u64, |acc, a| acc.saturating_add(a.weight.get()));
+    // A block is finalized once more than two thirds of the total weight have voted for it.
+    let threshold_weight = total_weight - total_weight.saturating_sub(1) / 3;
+
+    methods::GrandpaRoundState {
+        set_id: state.set_id,
+        finalized_block_hash: methods::HashHexString(state.finalized_block_hash),
+        finalized_block_number: state.finalized_block_number,
+        authorities: convert(&state.authorities),
+        total_weight,
+        threshold_weight,
+        scheduled_change: state
+            .scheduled_change
+            .map(|(n, list)| methods::GrandpaScheduledChange {
+                block_number: n,
+                authorities: convert(&list),
+            }),
+    }
+}
+
+/// Builds the JSON-RPC representation of the given trace.
+fn request_trace_json(trace: &request_trace::RequestTrace) -> methods::RequestTrace {
+    let micros = |duration: Duration| u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+
+    methods::RequestTrace {
+        trace_id: trace.id(),
+        method: trace.method().to_owned(),
+        // The identifier has been successfully parsed as JSON when the request was received.
+        request_id: serde_json::value::RawValue::from_string(trace.request_id_json().to_owned())
+            .unwrap(),
+        duration_us: trace.duration().map(micros),
+        events: trace
+            .events()
+            .into_iter()
+            .map(|event| {
+                let (kind, peer_id, response_size, function) = match event.kind {
+                    request_trace::TraceEventKind::NetworkRequest {
+                        protocol,
+                        peer_id,
+                        response_size,
+                    } => (
+                        protocol.to_owned(),
+                        Some(peer_id.to_string()),
+                        response_size.map(|size| u64::try_from(size).unwrap()),
+                        None,
+                    ),
+                    request_trace::TraceEventKind::RuntimeExecution { function } => {
+                        ("runtimeExecution".to_owned(), None, None, Some(function))
+                    }
+                };
+
+                methods::RequestTraceEvent {
+                    start_us: micros(event.start),
+                    duration_us: micros(event.duration),
+                    kind,
+                    peer_id,
+                    response_size,
+                    function,
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Builds the JSON-RPC representation of the state of the queue of network requests of the
+/// given class.
+fn work_queue_state(
+    sync_service: &sync_service::SyncService,
+    class: work_queues::WorkClass,
+) -> methods::ChainHeadStateQueue {
+    let (in_progress, queued) = sync_service.work_queue_depth(class);
+    methods::ChainHeadStateQueue {
+        in_progress: u64::try_from(in_progress).unwrap(),
+        queued: u64::try_from(queued).unwrap(),
+    }
+}
+
+impl JsonRpcService {
+    /// Send back a response or a notification to the JSON-RPC client.
+    fn send_back(&self, message: &str, user_data: u32) {
+        if let Some(cross_validation) = &self.cross_validation {
+            cross_validation.on_response(user_data, message);
+        }
+        send_back(message, self.chain_index, user_data)
+    }
+
+    /// Send back a successful response whose result is the hexadecimal encoding of `result`.
+    /// See [`send_back_hex_chunked`].
+    fn send_back_hex_chunked(&self, request_id: &str, result: &[u8], user_data: u32) {
+        if let Some(cross_validation) = &self.cross_validation {
+            cross_validation.on_hex_response(user_data, request_id, result);
+        }
+        send_back_hex_chunked(request_id, result, self.chain_index, user_data)
+    }
+
+    /// Analyzes the given JSON-RPC call and processes it.
+    ///
+    /// Depending on the request, either calls [`JsonRpcService::send_back`] immediately or
+    /// spawns a background task for further processing.
+    ///
+    /// The work performed in order to answer the request is recorded in a new
+    /// [`request_trace::RequestTrace`].
     pub async fn handle_rpc<'a>(
         self: Arc<JsonRpcService>,
         user_data: u32,
         request_id: &'a str,
         call: MethodCall<'a>,
     ) {
+        let trace = self.request_traces.start(call.name(), request_id);
+        log::debug!(
+            target: "json-rpc",
+            "Trace({}) <= {} (request id: {})", trace.id(), call.name(), request_id
+        );
+
+        self.handle_rpc_inner(user_data, request_id, call, &trace)
+            .await;
+        trace.finish();
+    }
+
+    /// See [`JsonRpcService::handle_rpc`].
+    async fn handle_rpc_inner<'a>(
+        self: Arc<JsonRpcService>,
+        user_data: u32,
+        request_id: &'a str,
+        call: MethodCall<'a>,
+        trace: &request_trace::RequestTrace,
+    ) {
+        if !self.methods_filter.is_allowed(call.name()) {
+            log::debug!(
+                target: "json-rpc",
+                "JSON-RPC call to {} denied by the methods filter", call.name()
+            );
+            self.send_back(
+                &json_rpc::parse::build_error_response(
+                    request_id,
+                    json_rpc::parse::ErrorResponse::ServerError(-32001, "Method not allowed"),
+                    None,
+                ),
+                user_data,
+            );
+            return;
+        }
+
         // Most calls are handled directly in this method's body. The most voluminous (in terms
         // of lines of code) have their dedicated methods.
         match call {
             methods::MethodCall::author_pendingExtrinsics {} => {
-                // TODO: ask transactions service
+                let list = self
+                    .transactions_service
+                    .pending_transactions()
+                    .await
+                    .into_iter()
+                    .map(|tx| methods::HexString(tx.scale_encoded))
+                    .collect();
+                self.send_back(
+                    &methods::Response::author_pendingExtrinsics(list).to_json_response(request_id),
+                    user_data,
+                );
+            }
+            methods::MethodCall::author_removeExtrinsic { bytes_or_hash } => {
+                let mut removed = Vec::with_capacity(bytes_or_hash.len());
+                for item in bytes_or_hash {
+                    let hash = match item {
+                        methods::ExtrinsicOrHash::Hash(hash) => hash.0,
+                        methods::ExtrinsicOrHash::Extrinsic(bytes) => Host::blake2_256(&bytes.0),
+                    };
+                    if self.transactions_service.remove_transaction(&hash).await {
+                        removed.push(methods::HashHexString(hash));
+                    }
+                }
                 self.send_back(
-                    &methods::Response::author_pendingExtrinsics(Vec::new())
+                    &methods::Response::author_removeExtrinsic(removed)
                         .to_json_response(request_id),
                     user_data,
                 );
             }
+            methods::MethodCall::author_unstable_pool {} => {
+                let to_hex_list = |tags: &[Vec<u8>]| {
+                    tags.iter()
+                        .map(|tag| methods::HexString(tag.clone()))
+                        .collect::<Vec<_>>()
+                };
+
+                let list = self
+                    .transactions_service
+                    .pending_transactions()
+                    .await
+                    .into_iter()
+                    .map(|tx| methods::PooledTransaction {
+                        hash: methods::HashHexString(Host::blake2_256(&tx.scale_encoded)),
+                        status: if tx.num_broadcasts == 0 {
+                            methods::PooledTransactionStatus::Ready
+                        } else {
+                            methods::PooledTransactionStatus::Broadcast
+                        },
+                        provides: tx.validity.as_ref().map(|v| to_hex_list(&v.provides)),
+                        requires: tx.validity.as_ref().map(|v| to_hex_list(&v.requires)),
+                        broadcast_count: tx.num_broadcasts,
+                        death_block: tx.death_block,
+                        extrinsic: methods::HexString(tx.scale_encoded),
+                    })
+                    .collect();
+                self.send_back(
+                    &methods::Response::author_unstable_pool(list).to_json_response(request_id),
+                    user_data,
+                );
+            }
             methods::MethodCall::author_submitExtrinsic { transaction } => {
                 // Send the transaction to the transactions service. It will be sent to the
                 // rest of the network asynchronously.
-                self.transactions_service
+                if let Err(error) = self
+                    .transactions_service
                     .submit_extrinsic(&transaction.0)
-                    .await;
+                    .await
+                {
+                    self.send_back(
+                        &submit_extrinsic_error_response(request_id, &error),
+                        user_data,
+                    );
+                    return;
+                }
 
                 // In Substrate, `author_submitExtrinsic` returns the hash of the extrinsic. It
                 // is unclear whether it has to actually be the hash of the transaction or if it
                 // could be any opaque value. Additionally, there isn't any other JSON-RPC method
                 // that accepts as parameter the value returned here. When in doubt, we return
                 // the hash as well.
-                let mut hash_context = blake2_rfc::blake2b::Blake2b::new(32);
-                hash_context.update(&transaction.0);
-                let mut transaction_hash: [u8; 32] = Default::default();
-                transaction_hash.copy_from_slice(hash_context.finalize().as_bytes());
+                let transaction_hash = Host::blake2_256(&transaction.0);
 
                 self.send_back(
                     &methods::Response::author_submitExtrinsic(methods::HashHexString(
@@ -444,22 +1491,29 @@ impl JsonRpcService {
                 // `hash` equal to `None` means "the current best block".
                 let hash = match hash {
                     Some(h) => h.0,
-                    None => self.blocks.lock().await.best_block,
+                    None => self.header_cache.best().await.hash,
                 };
 
-                // Block bodies and justifications aren't stored locally. Ask the network.
-                let result = self
-                    .sync_service
-                    .clone()
-                    .block_query(
-                        hash,
-                        protocol::BlocksRequestFields {
-                            header: true,
-                            body: true,
-                            justification: true,
-                        },
-                    )
-                    .await;
+                // Block bodies and justifications are only stored locally for the most recent
+                // blocks, and only if the sync service is configured to do so. Otherwise, ask the
+                // network.
+                let result = match self.sync_service.recent_block(&hash).await {
+                    Some(block) => Ok(block),
+                    None => {
+                        self.sync_service
+                            .clone()
+                            .block_query(
+                                hash,
+                                protocol::BlocksRequestFields {
+                                    header: true,
+                                    body: true,
+                                    justification: true,
+                                    indexed_body: false,
+                                },
+                            )
+                            .await
+                    }
+                };
 
                 // The `block_query` function guarantees that the header and body are present and
                 // are correct.
@@ -490,24 +1544,44 @@ impl JsonRpcService {
                 self.get_block_hash(user_data, request_id, height).await;
             }
             methods::MethodCall::chain_getFinalizedHead {} => {
-                self.send_back(
-                    &methods::Response::chain_getFinalizedHead(methods::HashHexString(
-                        self.blocks.lock().await.finalized_block,
+                let finalized = self.header_cache.finalized().await;
+                let finalized_hash = finalized.hash;
+
+                // If configured, make sure that enough peers agree with the finalized block
+                // before reporting it.
+                let response = match self
+                    .sync_service
+                    .block_quorum_check(finalized.number, finalized_hash)
+                    .await
+                {
+                    Ok(()) => methods::Response::chain_getFinalizedHead(methods::HashHexString(
+                        finalized_hash,
                     ))
                     .to_json_response(request_id),
-                    user_data,
-                );
+                    Err(error) => error_response(
+                        request_id,
+                        if error.num_confirmations == 0 && error.num_disagreements == 0 {
+                            ErrorKind::NoPeer
+                        } else {
+                            ErrorKind::Network
+                        },
+                        &error.to_string(),
+                    ),
+                };
+
+                self.send_back(&response, user_data);
             }
             methods::MethodCall::chain_getHeader { hash } => {
                 let hash = match hash {
                     Some(h) => h.0,
-                    None => self.blocks.lock().await.best_block,
+                    None => self.header_cache.best().await.hash,
                 };
 
                 self.send_back(
                     &match self.header_query(&hash).await {
                         Ok(header) => methods::Response::chain_getHeader(
-                            methods::Header::from_scale_encoded_header(&header).unwrap(),
+                            methods::Header::from_scale_encoded_header(&header.scale_encoded)
+                                .unwrap(),
                         )
                         .to_json_response(request_id),
                         // TODO: error or null?
@@ -564,134 +1638,453 @@ impl JsonRpcService {
                     user_data,
                 );
             }
-            methods::MethodCall::rpc_methods {} => {
+            methods::MethodCall::sudo_unstable_chainHeadState {} => {
+                let sync_state = self.sync_service.debug_state().await;
+                let grandpa = self.sync_service.grandpa_state().await;
+                // Obtaining the runtime of the best block might wait for it to be downloaded,
+                // which never finishes if the syncing is stuck. The state is a debugging tool
+                // and must remain available in that situation.
+                let runtime_spec_version = with_timeout(
+                    self.runtime_service.best_block_runtime(),
+                    CHAIN_HEAD_STATE_RUNTIME_TIMEOUT,
+                )
+                .await
+                .and_then(|spec| spec.ok())
+                .map(|spec| u64::from(spec.decode().spec_version));
+
                 self.send_back(
-                    &methods::Response::rpc_methods(methods::RpcMethods {
-                        version: 1,
-                        methods: methods::MethodCall::method_names()
-                            .map(|n| n.into())
+                    &methods::Response::sudo_unstable_chainHeadState(methods::ChainHeadState {
+                        finalized_block_hash: methods::HashHexString(
+                            sync_state.finalized_block_hash,
+                        ),
+                        finalized_block_number: sync_state.finalized_block_number,
+                        best_block_hash: methods::HashHexString(sync_state.best_block_hash),
+                        non_finalized_blocks: sync_state
+                            .non_finalized_blocks
+                            .into_iter()
+                            .map(|block| methods::ChainHeadStateBlock {
+                                hash: methods::HashHexString(block.hash),
+                                number: block.number,
+                                parent_hash: methods::HashHexString(block.parent_hash),
+                            })
+                            .collect(),
+                        grandpa: grandpa.map(grandpa_round_state),
+                        runtime_spec_version,
+                        sources: u64::try_from(sync_state.num_sources).unwrap(),
+                        pending_block_requests: u64::try_from(sync_state.pending_block_requests)
+                            .unwrap(),
+                        pending_grandpa_requests: u64::try_from(
+                            sync_state.pending_grandpa_requests,
+                        )
+                        .unwrap(),
+                        pending_storage_requests: u64::try_from(
+                            sync_state.pending_storage_requests,
+                        )
+                        .unwrap(),
+                        block_tracking_queue: work_queue_state(
+                            &self.sync_service,
+                            work_queues::WorkClass::BlockTracking,
+                        ),
+                        json_rpc_queue: work_queue_state(
+                            &self.sync_service,
+                            work_queues::WorkClass::JsonRpc,
+                        ),
+                        ancestry_queries: self
+                            .sync_service
+                            .ancestry_queries()
+                            .into_iter()
+                            .map(|query| methods::ChainHeadStateAncestryQuery {
+                                target_number: match query.target {
+                                    sync_service::AncestryTarget::Number(n) => Some(n),
+                                    sync_service::AncestryTarget::Hash(_) => None,
+                                },
+                                target_hash: match query.target {
+                                    sync_service::AncestryTarget::Number(_) => None,
+                                    sync_service::AncestryTarget::Hash(hash) => {
+                                        Some(methods::HashHexString(hash))
+                                    }
+                                },
+                                anchor_number: query.anchor_number,
+                                verified_number: query.verified_number,
+                                remaining: query.remaining,
+                            })
                             .collect(),
                     })
                     .to_json_response(request_id),
                     user_data,
                 );
             }
-            methods::MethodCall::state_getKeysPaged {
-                prefix,
-                count,
-                start_key,
-                hash,
+            methods::MethodCall::sudo_unstable_p2pRequest {
+                peer_id,
+                protocol_name,
+                request,
             } => {
-                assert!(hash.is_none()); // TODO: not implemented
-
-                let mut lock = self.blocks.lock().await;
-
-                let block_hash = lock.best_block;
-                let (state_root, block_number) = {
-                    let block = lock.known_blocks.get(&block_hash).unwrap();
-                    (block.state_root, block.number)
-                };
-                drop(lock);
-
-                let outcome = self
-                    .sync_service
-                    .clone()
-                    .storage_prefix_keys_query(
-                        block_number,
-                        &block_hash,
-                        &prefix.unwrap().0, // TODO: don't unwrap! what is this Option?
-                        &state_root,
+                let response = if !self.unstable_p2p_requests {
+                    json_rpc::parse::build_error_response(
+                        request_id,
+                        json_rpc::parse::ErrorResponse::ServerError(-32001, "Method not allowed"),
+                        None,
                     )
-                    .await;
-
-                self.send_back(
-                    &match outcome {
-                        Ok(keys) => {
-                            // TODO: instead of requesting all keys with that prefix from the network, pass `start_key` to the network service
-                            let out = keys
-                                .into_iter()
-                                .filter(|k| start_key.as_ref().map_or(true, |start| k >= &start.0)) // TODO: not sure if start should be in the set or not?
-                                .map(methods::HexString)
-                                .take(usize::try_from(count).unwrap_or(usize::max_value()))
-                                .collect::<Vec<_>>();
-                            methods::Response::state_getKeysPaged(out).to_json_response(request_id)
-                        }
+                } else if let Ok(peer_id) = peer_id.parse::<PeerId>() {
+                    match self
+                        .network_service
+                        .clone()
+                        .raw_request(peer_id, &protocol_name, request.0)
+                        .await
+                    {
+                        Ok(response) => methods::Response::sudo_unstable_p2pRequest(
+                            methods::HexString(response),
+                        )
+                        .to_json_response(request_id),
                         Err(error) => json_rpc::parse::build_error_response(
                             request_id,
                             json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
                             None,
                         ),
-                    },
-                    user_data,
-                );
-            }
-            methods::MethodCall::state_queryStorageAt { keys, at } => {
-                let blocks = self.blocks.lock().await;
-                let at = at.as_ref().map(|h| h.0).unwrap_or(blocks.best_block);
-
-                // TODO: have no idea what this describes actually
-                let mut out = methods::StorageChangeSet {
-                    block: methods::HashHexString(blocks.best_block),
-                    changes: Vec::new(),
-                };
-
-                // Drop the lock to make sure that we don't accidentally lock it again below.
-                drop(blocks);
-
-                for key in keys {
-                    // TODO: parallelism?
-                    if let Ok(value) = self.storage_query(&key.0, &at).await {
-                        out.changes.push((key, value.map(methods::HexString)));
                     }
-                }
+                } else {
+                    error_response(request_id, ErrorKind::InvalidParams, "Invalid peer ID")
+                };
 
-                self.send_back(
-                    &methods::Response::state_queryStorageAt(vec![out])
-                        .to_json_response(request_id),
-                    user_data,
-                );
+                self.send_back(&response, user_data);
             }
-            methods::MethodCall::state_getMetadata {} => {
-                let response = match self.runtime_service.clone().metadata().await {
-                    Ok(metadata) => {
-                        methods::Response::state_getMetadata(methods::HexString(metadata))
-                            .to_json_response(request_id)
+            methods::MethodCall::sudo_unstable_parachainMessageQueues {} => {
+                let response = match self.parachain_message_queues().await {
+                    Ok(queues) => methods::Response::sudo_unstable_parachainMessageQueues(queues)
+                        .to_json_response(request_id),
+                    Err(ParachainMessageQueuesError::NotParachain) => {
+                        json_rpc::parse::build_error_response(
+                            request_id,
+                            json_rpc::parse::ErrorResponse::ServerError(
+                                -32000,
+                                "The chain isn't a parachain",
+                            ),
+                            None,
+                        )
                     }
-                    Err(error) => json_rpc::parse::build_error_response(
+                    Err(error) => error_response(
                         request_id,
-                        json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
-                        None,
+                        match &error {
+                            ParachainMessageQueuesError::Metadata(
+                                runtime_service::MetadataError::CallError(error),
+                            ) => ErrorKind::from_runtime_call_error(error),
+                            ParachainMessageQueuesError::StorageQuery(error) => {
+                                ErrorKind::from_sync_storage_query_error(error)
+                            }
+                            _ => ErrorKind::RuntimeCall,
+                        },
+                        &error.to_string(),
                     ),
                 };
 
                 self.send_back(&response, user_data);
             }
-            methods::MethodCall::state_getStorage { key, hash } => {
-                let hash = hash
-                    .as_ref()
-                    .map(|h| h.0)
-                    .unwrap_or(self.blocks.lock().await.best_block);
+            methods::MethodCall::sudo_unstable_refreshRuntime {} => {
+                // Skips the delays of the runtime service and downloads the runtime of the
+                // current best block again, for example after an upgrade has been enacted.
+                self.runtime_service.refresh_now().await;
+                self.send_back(
+                    &methods::Response::sudo_unstable_refreshRuntime(())
+                        .to_json_response(request_id),
+                    user_data,
+                );
+            }
+            methods::MethodCall::sudo_unstable_runtimeVersionAt { block_number } => {
+                let entry = self
+                    .runtime_service
+                    .runtime_versions_history()
+                    .await
+                    .entry_at(block_number)
+                    .map(|(first_observed_block, spec_version)| {
+                        methods::RuntimeVersionHistoryEntry {
+                            spec_version,
+                            first_observed_block,
+                        }
+                    });
 
                 self.send_back(
-                    &match self.storage_query(&key.0, &hash).await {
-                        Ok(Some(value)) => {
-                            methods::Response::state_getStorage(methods::HexString(
-                                value.to_owned(),
-                            )) // TODO: overhead
-                            .to_json_response(request_id)
+                    &methods::Response::sudo_unstable_runtimeVersionAt(entry)
+                        .to_json_response(request_id),
+                    user_data,
+                );
+            }
+            methods::MethodCall::sudo_unstable_storageKey {
+                module,
+                entry,
+                keys,
+            } => {
+                let response = match self.runtime_service.clone().metadata().await {
+                    Ok(metadata) => match smoldot::metadata::decode(&metadata) {
+                        Ok(metadata) => {
+                            let keys = keys.iter().map(|k| &k.0[..]).collect::<Vec<_>>();
+                            match metadata::storage::storage_key(metadata, &module, &entry, &keys) {
+                                Ok(key) => methods::Response::sudo_unstable_storageKey(
+                                    methods::HexString(key.key),
+                                )
+                                .to_json_response(request_id),
+                                Err(error) => json_rpc::parse::build_error_response(
+                                    request_id,
+                                    json_rpc::parse::ErrorResponse::ServerError(
+                                        -32000,
+                                        &error.to_string(),
+                                    ),
+                                    None,
+                                ),
+                            }
                         }
-                        Ok(None) => json_rpc::parse::build_success_response(request_id, "null"),
-                        Err(error) => json_rpc::parse::build_error_response(
+                        Err(_) => error_response(
                             request_id,
-                            json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
-                            None,
+                            ErrorKind::RuntimeCall,
+                            "Failed to decode the metadata",
                         ),
                     },
-                    user_data,
-                );
-            }
-            methods::MethodCall::state_subscribeRuntimeVersion {} => {
-                let subscription = self
+                    Err(error) => error_response(
+                        request_id,
+                        match &error {
+                            runtime_service::MetadataError::CallError(error) => {
+                                ErrorKind::from_runtime_call_error(error)
+                            }
+                            _ => ErrorKind::RuntimeCall,
+                        },
+                        &error.to_string(),
+                    ),
+                };
+
+                self.send_back(&response, user_data);
+            }
+            methods::MethodCall::sudo_unstable_watchAccount { account } => {
+                self.watch_account(user_data, request_id, account).await;
+            }
+            methods::MethodCall::sudo_unstable_unwatchAccount { subscription } => {
+                let invalid = if let Some(subs) = self
+                    .per_userdata_subscriptions
+                    .lock()
+                    .await
+                    .get_mut(&user_data)
+                {
+                    if let Some(cancel_tx) = subs.accounts.lock().await.remove(&subscription[..]) {
+                        cancel_tx.send(request_id.to_owned()).is_err()
+                    } else {
+                        true
+                    }
+                } else {
+                    true
+                };
+
+                if invalid {
+                    self.send_back(
+                        &methods::Response::sudo_unstable_unwatchAccount(false)
+                            .to_json_response(request_id),
+                        user_data,
+                    );
+                }
+            }
+            methods::MethodCall::sudo_unstable_watchCandidateEvents {} => {
+                self.watch_candidate_events(user_data, request_id).await;
+            }
+            methods::MethodCall::sudo_unstable_unwatchCandidateEvents { subscription } => {
+                let invalid = if let Some(subs) = self
+                    .per_userdata_subscriptions
+                    .lock()
+                    .await
+                    .get_mut(&user_data)
+                {
+                    if let Some(cancel_tx) = subs.candidate_events.lock().await.remove(subscription)
+                    {
+                        cancel_tx.send(request_id.to_owned()).is_err()
+                    } else {
+                        true
+                    }
+                } else {
+                    true
+                };
+
+                if invalid {
+                    self.send_back(
+                        &methods::Response::sudo_unstable_unwatchCandidateEvents(false)
+                            .to_json_response(request_id),
+                        user_data,
+                    );
+                }
+            }
+            methods::MethodCall::rpc_methods {} => {
+                self.send_back(
+                    &methods::Response::rpc_methods(methods::RpcMethods {
+                        version: 1,
+                        methods: methods::MethodCall::method_names()
+                            .map(|n| n.into())
+                            .collect(),
+                    })
+                    .to_json_response(request_id),
+                    user_data,
+                );
+            }
+            methods::MethodCall::state_getKeysPaged {
+                prefix,
+                count,
+                start_key,
+                hash,
+            } => {
+                assert!(hash.is_none()); // TODO: not implemented
+
+                let best_block = self.header_cache.best().await;
+                let (block_hash, state_root, block_number) =
+                    (best_block.hash, best_block.state_root, best_block.number);
+
+                let outcome = self
+                    .sync_service
+                    .clone()
+                    .storage_prefix_keys_query(
+                        block_number,
+                        &block_hash,
+                        &prefix.unwrap().0, // TODO: don't unwrap! what is this Option?
+                        &state_root,
+                    )
+                    .await;
+
+                self.send_back(
+                    &match outcome {
+                        Ok(keys) => {
+                            // TODO: instead of requesting all keys with that prefix from the network, pass `start_key` to the network service
+                            let out = keys
+                                .into_iter()
+                                .filter(|k| start_key.as_ref().map_or(true, |start| k >= &start.0)) // TODO: not sure if start should be in the set or not?
+                                .map(methods::HexString)
+                                .take(usize::try_from(count).unwrap_or(usize::max_value()))
+                                .collect::<Vec<_>>();
+                            methods::Response::state_getKeysPaged(out).to_json_response(request_id)
+                        }
+                        Err(error) => error_response(
+                            request_id,
+                            ErrorKind::from_sync_storage_query_error(&error),
+                            &error.to_string(),
+                        ),
+                    },
+                    user_data,
+                );
+            }
+            methods::MethodCall::state_queryStorageAt { keys, at } => {
+                // All the keys are queried at once, so that all the values are read from the same
+                // block. If no block was explicitly requested, any recent block is acceptable and
+                // the query can be retargeted if the best block has been pruned.
+                let keys_slices = keys.iter().map(|key| &key.0[..]).collect::<Vec<_>>();
+                let result = match at {
+                    Some(at) => self
+                        .storage_query_keys(&keys_slices, &at.0, trace)
+                        .await
+                        .map(|values| (at.0, values)),
+                    None => {
+                        let best_block = self.header_cache.best().await.hash;
+                        self.storage_query_keys_finalized_or_newer(&keys_slices, &best_block, trace)
+                            .await
+                    }
+                };
+
+                match result {
+                    Ok((block_hash, values)) => {
+                        // TODO: have no idea what this describes actually
+                        let out = methods::StorageChangeSet {
+                            block: methods::HashHexString(block_hash),
+                            changes: keys
+                                .into_iter()
+                                .zip(values)
+                                .map(|(key, value)| (key, value.map(methods::HexString)))
+                                .collect(),
+                        };
+
+                        self.send_back(
+                            &methods::Response::state_queryStorageAt(vec![out])
+                                .to_json_response(request_id),
+                            user_data,
+                        );
+                    }
+                    Err(error) => self.send_back(
+                        &error_response(
+                            request_id,
+                            ErrorKind::from_storage_query_error(&error),
+                            &error.to_string(),
+                        ),
+                        user_data,
+                    ),
+                }
+            }
+            methods::MethodCall::state_getMetadata {} => {
+                match self.runtime_service.clone().metadata().await {
+                    Ok(metadata) => self.send_back_hex_chunked(request_id, &metadata, user_data),
+                    Err(error) => self.send_back(
+                        &error_response(
+                            request_id,
+                            match &error {
+                                runtime_service::MetadataError::CallError(error) => {
+                                    ErrorKind::from_runtime_call_error(error)
+                                }
+                                _ => ErrorKind::RuntimeCall,
+                            },
+                            &error.to_string(),
+                        ),
+                        user_data,
+                    ),
+                }
+            }
+            methods::MethodCall::state_getStorage { key, hash } => {
+                // If no block was explicitly requested, any recent block is acceptable and the
+                // query can be retargeted if the best block has been pruned.
+                let (at, result) = match hash {
+                    Some(hash) => (hash.0, self.storage_query(&key.0, &hash.0, trace).await),
+                    None => {
+                        let best_block = self.header_cache.best().await.hash;
+                        let result = self
+                            .storage_query_finalized_or_newer(&key.0, &best_block, trace)
+                            .await
+                            .map(|(_, value)| value);
+                        (best_block, result)
+                    }
+                };
+
+                // If the network couldn't provide the value, try the fallback data source.
+                if let (Err(error), Some(rpc_fallback)) = (&result, &self.rpc_fallback) {
+                    match rpc_fallback.storage_query(&at, &key.0).await {
+                        Ok(value) => {
+                            let value =
+                                serde_json::to_string(&value.map(methods::HexString)).unwrap();
+                            self.send_back(
+                                &json_rpc::parse::build_unverified_success_response(
+                                    request_id, &value,
+                                ),
+                                user_data,
+                            );
+                            return;
+                        }
+                        Err(fallback_error) => {
+                            log::debug!(
+                                target: "json-rpc",
+                                "Storage query failed ({}), and fallback failed as well: {}",
+                                error, fallback_error
+                            );
+                        }
+                    }
+                }
+
+                // Storage values can be large (e.g. the runtime code), and are thus sent back in
+                // chunks.
+                match result {
+                    Ok(Some(value)) => self.send_back_hex_chunked(request_id, &value, user_data),
+                    Ok(None) => self.send_back(
+                        &json_rpc::parse::build_success_response(request_id, "null"),
+                        user_data,
+                    ),
+                    Err(error) => self.send_back(
+                        &error_response(
+                            request_id,
+                            ErrorKind::from_storage_query_error(&error),
+                            &error.to_string(),
+                        ),
+                        user_data,
+                    ),
+                }
+            }
+            methods::MethodCall::state_subscribeRuntimeVersion {} => {
+                let subscription = self
                     .next_subscription
                     .fetch_add(1, atomic::Ordering::Relaxed)
                     .to_string();
@@ -705,7 +2098,7 @@ impl JsonRpcService {
                     .lock()
                     .await
                     .entry(user_data)
-                    .or_insert_with(|| Arc::new(PerUserDataSubscriptions::default()))
+                    .or_insert_with(|| Arc::new(PerUserDataSubscriptions::new(user_data)))
                     .clone();
                 reference_arc
                     .runtime_specs
@@ -777,22 +2170,18 @@ impl JsonRpcService {
                                             "null".to_string()
                                         };
 
-                                    let per_source_subscriptions =
-                                        client.per_userdata_subscriptions.lock().await;
-
-                                    if per_source_subscriptions
-                                        .get(&user_data)
-                                        .map_or(false, |arc| Arc::ptr_eq(arc, &reference_arc))
-                                    {
-                                        client.send_back(
+                                    if !client
+                                        .send_subscription_notification(
+                                            &reference_arc,
+                                            &subscription,
                                             &smoldot::json_rpc::parse::build_subscription_event(
                                                 "state_runtimeVersion",
                                                 &subscription,
                                                 &notification_body,
                                             ),
-                                            user_data,
-                                        );
-                                    } else {
+                                        )
+                                        .await
+                                    {
                                         break;
                                     }
                                 }
@@ -800,7 +2189,7 @@ impl JsonRpcService {
                                     let response =
                                         methods::Response::state_unsubscribeRuntimeVersion(true)
                                             .to_json_response(&unsub_request_id);
-                                    client.send_back(&response, user_data);
+                                    client.send_back(&response, reference_arc.user_data());
                                     break;
                                 }
                                 future::Either::Right((Err(_), _)) => break,
@@ -858,22 +2247,119 @@ impl JsonRpcService {
                         .to_json_response(request_id)
                     } else {
                         // TODO: error can also be because we failed the storage query; should be more precise
-                        json_rpc::parse::build_error_response(
-                            request_id,
-                            json_rpc::parse::ErrorResponse::ServerError(-32000, "Invalid runtime"),
-                            None,
-                        )
+                        error_response(request_id, ErrorKind::RuntimeCall, "Invalid runtime")
                     },
                     user_data,
                 );
             }
+            methods::MethodCall::grandpa_proveFinality { block_number } => {
+                self.prove_finality(user_data, request_id, block_number)
+                    .await;
+            }
+            methods::MethodCall::grandpa_roundState {} => {
+                let response = match self.sync_service.grandpa_state().await {
+                    Some(state) => {
+                        methods::Response::grandpa_roundState(grandpa_round_state(state))
+                            .to_json_response(request_id)
+                    }
+                    None => json_rpc::parse::build_error_response(
+                        request_id,
+                        json_rpc::parse::ErrorResponse::ServerError(
+                            -32000,
+                            "Chain doesn't use GrandPa",
+                        ),
+                        None,
+                    ),
+                };
+
+                self.send_back(&response, user_data);
+            }
+            methods::MethodCall::state_call {
+                name,
+                parameters,
+                hash,
+            } => {
+                // Only calls on the best and finalized blocks are supported locally at the
+                // moment.
+                let best_block_hash = self.header_cache.best().await.hash;
+                let finalized_block_hash = self.header_cache.finalized().await.hash;
+                let at = hash.map_or(best_block_hash, |hash| hash.0);
+                let mut response = if at != best_block_hash && at != finalized_block_hash {
+                    json_rpc::parse::build_error_response(
+                        request_id,
+                        json_rpc::parse::ErrorResponse::ServerError(
+                            -32000,
+                            "Calls on blocks other than the best and finalized blocks aren't \
+                            supported",
+                        ),
+                        None,
+                    )
+                } else {
+                    let result = if at == best_block_hash {
+                        self.runtime_service
+                            .recent_best_block_runtime_call(
+                                &name,
+                                &[&parameters.0[..]],
+                                Some(trace),
+                            )
+                            .await
+                    } else {
+                        // During a runtime upgrade, the runtime of the finalized block can be
+                        // different from the one of the best block.
+                        self.runtime_service
+                            .recent_finalized_block_runtime_call(
+                                &name,
+                                &[&parameters.0[..]],
+                                Some(trace),
+                            )
+                            .await
+                    };
+
+                    match result {
+                        Ok(return_value) => {
+                            self.send_back(
+                                &methods::Response::state_call(methods::HexString(return_value))
+                                    .to_json_response(request_id),
+                                user_data,
+                            );
+                            return;
+                        }
+                        Err(error) => error_response(
+                            request_id,
+                            ErrorKind::from_runtime_call_error(&error),
+                            &error.to_string(),
+                        ),
+                    }
+                };
+
+                // If the call couldn't be performed locally, try the fallback data source.
+                if let Some(rpc_fallback) = &self.rpc_fallback {
+                    match rpc_fallback.runtime_call(&at, &name, &parameters.0).await {
+                        Ok(return_value) => {
+                            response = json_rpc::parse::build_unverified_success_response(
+                                request_id,
+                                &serde_json::to_string(&methods::HexString(return_value)).unwrap(),
+                            );
+                        }
+                        Err(error) => {
+                            log::debug!(
+                                target: "json-rpc",
+                                "Fallback of call to {} failed: {}", name, error
+                            );
+                        }
+                    }
+                }
+
+                self.send_back(&response, user_data);
+            }
             methods::MethodCall::system_accountNextIndex { account } => {
                 self.send_back(
                     &match self
                         .runtime_service
                         .recent_best_block_runtime_call(
                             "AccountNonceApi_account_nonce",
-                            iter::once(&account.0),
+                            &[&account.0[..]],
+                            Some(trace),
                         )
                         .await
                     {
@@ -885,10 +2371,10 @@ impl JsonRpcService {
                             methods::Response::system_accountNextIndex(u64::from(index))
                                 .to_json_response(request_id)
                         }
-                        Err(error) => json_rpc::parse::build_error_response(
+                        Err(error) => error_response(
                             request_id,
-                            json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
-                            None,
+                            ErrorKind::from_runtime_call_error(&error),
+                            &error.to_string(),
                         ),
                     },
                     user_data,
@@ -908,7 +2394,47 @@ impl JsonRpcService {
                     user_data,
                 );
             }
+            methods::MethodCall::system_dryRun { extrinsic, hash } => {
+                // Only the best block is supported at the moment, similarly to `state_call`.
+                let best_block_hash = self.header_cache.best().await.hash;
+                let response = if hash.map_or(false, |hash| hash.0 != best_block_hash) {
+                    json_rpc::parse::build_error_response(
+                        request_id,
+                        json_rpc::parse::ErrorResponse::ServerError(
+                            -32000,
+                            "Dry runs on blocks other than the best block aren't supported",
+                        ),
+                        None,
+                    )
+                } else {
+                    // The extrinsic is applied on top of a block being built on top of the best
+                    // block. The return value is the SCALE-encoded `ApplyExtrinsicResult`, which
+                    // is passed as is to the JSON-RPC client.
+                    match self
+                        .runtime_service
+                        .recent_best_block_runtime_call_after_initialize(
+                            "BlockBuilder_apply_extrinsic",
+                            &[&extrinsic.0[..]],
+                            Some(trace),
+                        )
+                        .await
+                    {
+                        Ok(outcome) => {
+                            methods::Response::system_dryRun(methods::HexString(outcome))
+                                .to_json_response(request_id)
+                        }
+                        Err(error) => error_response(
+                            request_id,
+                            ErrorKind::from_runtime_call_error(&error),
+                            &error.to_string(),
+                        ),
+                    }
+                };
+
+                self.send_back(&response, user_data);
+            }
             methods::MethodCall::system_health {} => {
+                let peer_diversity = self.sync_service.peer_diversity().await;
                 self.send_back(
                     &methods::Response::system_health(methods::SystemHealth {
                         // In smoldot, `is_syncing` equal to `false` means that GrandPa warp sync
@@ -918,6 +2444,8 @@ impl JsonRpcService {
                         peers: u64::try_from(self.network_service.peers_list().await.count())
                             .unwrap_or(u64::max_value()),
                         should_have_peers: self.chain_spec.has_live_network(),
+                        peer_origins: Some(peer_diversity.num_origins as u64),
+                        required_peer_origins: Some(peer_diversity.required_origins as u64),
                     })
                     .to_json_response(request_id),
                     user_data,
@@ -941,14 +2469,22 @@ impl JsonRpcService {
             methods::MethodCall::system_peers {} => {
                 self.send_back(
                     &methods::Response::system_peers(
-                        self.sync_service
-                            .syncing_peers()
+                        self.network_service
+                            .peers_info(self.network_chain_index)
                             .await
-                            .map(|(peer_id, best_number, best_hash)| methods::SystemPeer {
-                                peer_id: peer_id.to_string(),
-                                roles: "unknown".to_string(), // TODO: do properly
-                                best_hash: methods::HashHexString(best_hash),
-                                best_number,
+                            .into_iter()
+                            .map(|info| methods::SystemPeer {
+                                peer_id: info.peer_id.to_string(),
+                                roles: match info.role {
+                                    protocol::Role::Authority => "AUTHORITY",
+                                    protocol::Role::Full => "FULL",
+                                    protocol::Role::Light => "LIGHT",
+                                }
+                                .to_string(),
+                                agent_version: info.agent_version,
+                                protocols: info.protocols,
+                                best_hash: methods::HashHexString(info.best_block_hash),
+                                best_number: info.best_block_number,
                             })
                             .collect(),
                     )
@@ -958,44 +2494,275 @@ impl JsonRpcService {
             }
             methods::MethodCall::system_properties {} => {
                 self.send_back(
-                    &methods::Response::system_properties(
-                        serde_json::from_str(self.chain_spec.properties()).unwrap(),
-                    )
-                    .to_json_response(request_id),
+                    &methods::Response::system_properties(system_properties(&self.chain_spec))
+                        .to_json_response(request_id),
                     user_data,
                 );
             }
-            methods::MethodCall::system_version {} => {
+            methods::MethodCall::system_unstable_cpuUsage {} => {
+                let total_ms = |category| {
+                    u64::try_from(self.cpu_usage.total(category).as_millis())
+                        .unwrap_or(u64::max_value())
+                };
+
                 self.send_back(
-                    &methods::Response::system_version(env!("CARGO_PKG_VERSION"))
-                        .to_json_response(request_id),
+                    &methods::Response::system_unstable_cpuUsage(methods::CpuUsage {
+                        runtime_execution_ms: total_ms(cpu_usage::Category::RuntimeExecution),
+                        proof_verification_ms: total_ms(cpu_usage::Category::ProofVerification),
+                        header_verification_ms: total_ms(cpu_usage::Category::HeaderVerification),
+                        weight: self.cpu_usage.weight().get(),
+                    })
+                    .to_json_response(request_id),
                     user_data,
                 );
             }
-            _method => {
-                log::error!(target: "json-rpc", "JSON-RPC call not supported yet: {:?}", _method);
+            methods::MethodCall::system_unstable_memoryUsage {} => {
+                let snapshot = memory_usage::snapshot();
+                let bytes = |n: usize| u64::try_from(n).unwrap_or(u64::MAX);
+                let category_peak = |category: cpu_usage::Category| {
+                    bytes(snapshot.category_peaks[category.index()])
+                };
+
                 self.send_back(
-                    &json_rpc::parse::build_error_response(
-                        request_id,
-                        json_rpc::parse::ErrorResponse::ServerError(
-                            -32000,
-                            "Not implemented in smoldot yet",
+                    &methods::Response::system_unstable_memoryUsage(methods::MemoryUsage {
+                        allocated_bytes: bytes(snapshot.allocated),
+                        peak_allocated_bytes: bytes(snapshot.peak),
+                        runtime_execution_peak_bytes: category_peak(
+                            cpu_usage::Category::RuntimeExecution,
                         ),
-                        None,
-                    ),
+                        proof_verification_peak_bytes: category_peak(
+                            cpu_usage::Category::ProofVerification,
+                        ),
+                        header_verification_peak_bytes: category_peak(
+                            cpu_usage::Category::HeaderVerification,
+                        ),
+                        failed_allocations: snapshot.failed_allocations,
+                        linear_memory_bytes: snapshot.linear_memory.map(bytes),
+                    })
+                    .to_json_response(request_id),
                     user_data,
                 );
             }
-        }
-    }
-
-    async fn handle_unsubscribe_all(self: Arc<JsonRpcService>, user_data: u32) {
-        self.per_userdata_subscriptions
-            .lock()
-            .await
+            methods::MethodCall::system_unstable_requestTraces {} => {
+                let traces = self
+                    .request_traces
+                    .list()
+                    .iter()
+                    .map(|trace| request_trace_json(trace))
+                    .collect();
+                self.send_back(
+                    &methods::Response::system_unstable_requestTraces(traces)
+                        .to_json_response(request_id),
+                    user_data,
+                );
+            }
+            methods::MethodCall::system_unstable_runtimeCallRecordings {} => {
+                // Each recording is already serialized by `to_json`, and is included as is.
+                let recordings = self
+                    .runtime_service
+                    .failed_call_recordings()
+                    .await
+                    .iter()
+                    .map(|recording| {
+                        serde_json::value::RawValue::from_string(recording.to_json()).unwrap()
+                    })
+                    .collect();
+                self.send_back(
+                    &methods::Response::system_unstable_runtimeCallRecordings(recordings)
+                        .to_json_response(request_id),
+                    user_data,
+                );
+            }
+            methods::MethodCall::system_version {} => {
+                self.send_back(
+                    &methods::Response::system_version(env!("CARGO_PKG_VERSION"))
+                        .to_json_response(request_id),
+                    user_data,
+                );
+            }
+            _method => {
+                log::error!(target: "json-rpc", "JSON-RPC call not supported yet: {:?}", _method);
+                self.send_back(
+                    &json_rpc::parse::build_error_response(
+                        request_id,
+                        json_rpc::parse::ErrorResponse::MethodNotFound,
+                        Some(r#"{"message":"Not implemented in smoldot yet"}"#),
+                    ),
+                    user_data,
+                );
+            }
+        }
+    }
+
+    /// Returns `true` if any JSON-RPC client has a subscription on this chain, including
+    /// subscriptions that are detached.
+    async fn has_subscriptions(&self) -> bool {
+        for subscriptions in self.per_userdata_subscriptions.lock().await.values() {
+            if !subscriptions.is_empty().await {
+                return true;
+            }
+        }
+
+        for subscriptions in self.detached_subscriptions.lock().await.values() {
+            if !subscriptions.is_empty().await {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    async fn handle_unsubscribe_all(self: Arc<JsonRpcService>, user_data: u32) {
+        self.per_userdata_subscriptions
+            .lock()
+            .await
             .remove(&user_data);
     }
 
+    /// Detaches all the subscriptions of `user_data` and stores them under `token`, from where
+    /// they can be reattached using [`JsonRpcService::handle_reattach`].
+    ///
+    /// From the point of view of `user_data`, this is equivalent to unsubscribing from
+    /// everything. The subscriptions are destroyed if they aren't reattached within
+    /// [`DETACHED_SUBSCRIPTIONS_TIMEOUT`]. In the meantime, up to
+    /// [`MAX_DETACHED_NOTIFICATIONS`] notifications are buffered for each subscription.
+    async fn handle_detach_all(self: Arc<JsonRpcService>, user_data: u32, token: u32) {
+        let subscriptions = {
+            let mut per_userdata_subscriptions = self.per_userdata_subscriptions.lock().await;
+            let subscriptions = match per_userdata_subscriptions.remove(&user_data) {
+                Some(s) => s,
+                None => return,
+            };
+
+            let mut detached_subscriptions = self.detached_subscriptions.lock().await;
+            if detached_subscriptions.contains_key(&token) {
+                log::warn!(
+                    target: "json-rpc",
+                    "Subscriptions already detached with token {}; discarding new ones", token
+                );
+                return;
+            }
+            detached_subscriptions.insert(token, subscriptions.clone());
+            subscriptions
+        };
+
+        // Spawn a task that destroys the subscriptions if they are still detached after the
+        // timeout.
+        let client = self.clone();
+        (self.tasks_executor.lock().await)(
+            "jsonrpc-detached-subscriptions-timeout".into(),
+            Box::pin(async move {
+                Host::sleep(DETACHED_SUBSCRIPTIONS_TIMEOUT).await;
+                let mut detached_subscriptions = client.detached_subscriptions.lock().await;
+                if detached_subscriptions
+                    .get(&token)
+                    .map_or(false, |arc| Arc::ptr_eq(arc, &subscriptions))
+                {
+                    detached_subscriptions.remove(&token);
+                }
+            }),
+        );
+    }
+
+    /// Reattaches the subscriptions that have been detached with the given token to
+    /// `user_data`. The subscriptions keep their identifiers, and notifications resume being
+    /// sent to `user_data`.
+    ///
+    /// The notifications buffered while the subscriptions were detached are sent first. If some
+    /// of them had to be discarded, a `smoldot_unstable_notificationsDropped` notification,
+    /// whose result is the number of discarded notifications, is sent for the subscription
+    /// before the ones that have been kept.
+    ///
+    /// Does nothing if no subscriptions have been detached with this token, or if `user_data`
+    /// already has active subscriptions.
+    async fn handle_reattach(self: Arc<JsonRpcService>, token: u32, user_data: u32) {
+        let mut per_userdata_subscriptions = self.per_userdata_subscriptions.lock().await;
+        let mut detached_subscriptions = self.detached_subscriptions.lock().await;
+
+        if per_userdata_subscriptions.contains_key(&user_data) {
+            log::warn!(
+                target: "json-rpc",
+                "Can't reattach subscriptions to user data {}, as it already has subscriptions",
+                user_data
+            );
+            return;
+        }
+
+        if let Some(subscriptions) = detached_subscriptions.remove(&token) {
+            subscriptions
+                .user_data
+                .store(user_data, atomic::Ordering::Relaxed);
+
+            // The notifications are sent while `per_userdata_subscriptions` is locked, in order
+            // for them to not be interleaved with new notifications.
+            let buffered = mem::take(&mut *subscriptions.detached_notifications.lock().await);
+            for (subscription, notifications) in buffered {
+                if notifications.num_dropped != 0 {
+                    log::warn!(
+                        target: "json-rpc",
+                        "Discarded {} notifications of subscription {} while detached",
+                        notifications.num_dropped, subscription
+                    );
+                    self.send_back(
+                        &smoldot::json_rpc::parse::build_subscription_event(
+                            "smoldot_unstable_notificationsDropped",
+                            &subscription,
+                            &notifications.num_dropped.to_string(),
+                        ),
+                        user_data,
+                    );
+                }
+
+                for notification in notifications.queue {
+                    self.send_back(&notification, user_data);
+                }
+            }
+
+            per_userdata_subscriptions.insert(user_data, subscriptions);
+        }
+    }
+
+    /// Sends a subscription notification to the user data that the given subscriptions
+    /// currently belong to.
+    ///
+    /// Returns `false` if the subscriptions have been destroyed, in which case the subscription
+    /// should stop. If the subscriptions are detached, the notification is buffered and sent
+    /// once they are reattached. See [`JsonRpcService::handle_reattach`].
+    async fn send_subscription_notification(
+        &self,
+        subscriptions: &Arc<PerUserDataSubscriptions>,
+        subscription: &str,
+        notification: &str,
+    ) -> bool {
+        let per_userdata_subscriptions = self.per_userdata_subscriptions.lock().await;
+
+        let user_data = subscriptions.user_data();
+        if per_userdata_subscriptions
+            .get(&user_data)
+            .map_or(false, |arc| Arc::ptr_eq(arc, subscriptions))
+        {
+            self.send_back(notification, user_data);
+            return true;
+        }
+
+        let is_detached = self
+            .detached_subscriptions
+            .lock()
+            .await
+            .values()
+            .any(|arc| Arc::ptr_eq(arc, subscriptions));
+
+        if is_detached {
+            let mut detached_notifications = subscriptions.detached_notifications.lock().await;
+            detached_notifications
+                .entry(subscription.to_owned())
+                .or_default()
+                .push(notification.to_owned());
+        }
+
+        is_detached
+    }
+
     /// Handles a call to [`methods::MethodCall::author_submitAndWatchExtrinsic`].
     async fn submit_and_watch_extrinsic(
         self: Arc<JsonRpcService>,
@@ -1003,10 +2770,20 @@ impl JsonRpcService {
         request_id: &str,
         transaction: methods::HexString,
     ) {
-        let mut transaction_updates = self
+        let mut transaction_updates = match self
             .transactions_service
             .submit_extrinsic(&transaction.0)
-            .await;
+            .await
+        {
+            Ok(updates) => updates,
+            Err(error) => {
+                self.send_back(
+                    &submit_extrinsic_error_response(request_id, &error),
+                    user_data,
+                );
+                return;
+            }
+        };
 
         let subscription = self
             .next_subscription
@@ -1019,7 +2796,7 @@ impl JsonRpcService {
             .lock()
             .await
             .entry(user_data)
-            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::default()))
+            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::new(user_data)))
             .clone();
         reference_arc
             .transactions
@@ -1060,6 +2837,9 @@ impl JsonRpcService {
                                 transactions_service::TransactionStatus::Dropped => {
                                     methods::TransactionStatus::Dropped
                                 }
+                                transactions_service::TransactionStatus::Invalid => {
+                                    methods::TransactionStatus::Invalid
+                                }
                                 transactions_service::TransactionStatus::Finalized(block) => {
                                     methods::TransactionStatus::Finalized(block)
                                 }
@@ -1068,29 +2848,25 @@ impl JsonRpcService {
                                 }
                             };
 
-                            let per_source_subscriptions =
-                                client.per_userdata_subscriptions.lock().await;
-
-                            if per_source_subscriptions
-                                .get(&user_data)
-                                .map_or(false, |arc| Arc::ptr_eq(arc, &reference_arc))
-                            {
-                                client.send_back(
+                            if !client
+                                .send_subscription_notification(
+                                    &reference_arc,
+                                    &subscription,
                                     &smoldot::json_rpc::parse::build_subscription_event(
                                         "author_extrinsicUpdate",
                                         &subscription,
                                         &serde_json::to_string(&update).unwrap(),
                                     ),
-                                    user_data,
-                                );
-                            } else {
+                                )
+                                .await
+                            {
                                 break;
                             }
                         }
                         future::Either::Right((Ok(unsub_request_id), _)) => {
                             let response = methods::Response::chain_unsubscribeNewHeads(true)
                                 .to_json_response(&unsub_request_id);
-                            client.send_back(&response, user_data);
+                            client.send_back(&response, reference_arc.user_data());
                             break;
                         }
                         future::Either::Left((None, _)) => {
@@ -1115,49 +2891,125 @@ impl JsonRpcService {
         request_id: &str,
         height: Option<u64>,
     ) {
-        let mut blocks = self.blocks.lock().await;
-        let blocks = &mut *blocks;
-
-        self.send_back(
-            &match height {
-                Some(0) => methods::Response::chain_getBlockHash(methods::HashHexString(
-                    self.genesis_block,
-                ))
+        let best_block = self.header_cache.best().await;
+        let finalized_block = self.header_cache.finalized().await;
+
+        let response = match height {
+            Some(0) => {
+                methods::Response::chain_getBlockHash(methods::HashHexString(self.genesis_block))
+                    .to_json_response(request_id)
+            }
+            None => methods::Response::chain_getBlockHash(methods::HashHexString(best_block.hash))
                 .to_json_response(request_id),
-                None => {
-                    methods::Response::chain_getBlockHash(methods::HashHexString(blocks.best_block))
-                        .to_json_response(request_id)
-                }
-                Some(n)
-                    if blocks
-                        .known_blocks
-                        .get(&blocks.best_block)
-                        .map_or(false, |h| h.number == n) =>
-                {
-                    methods::Response::chain_getBlockHash(methods::HashHexString(blocks.best_block))
-                        .to_json_response(request_id)
-                }
-                Some(n)
-                    if blocks
-                        .known_blocks
-                        .get(&blocks.finalized_block)
-                        .map_or(false, |h| h.number == n) =>
-                {
-                    methods::Response::chain_getBlockHash(methods::HashHexString(
-                        blocks.finalized_block,
-                    ))
+            Some(n) if best_block.number == n => {
+                methods::Response::chain_getBlockHash(methods::HashHexString(best_block.hash))
                     .to_json_response(request_id)
+            }
+            Some(n) if finalized_block.number == n => {
+                methods::Response::chain_getBlockHash(methods::HashHexString(finalized_block.hash))
+                    .to_json_response(request_id)
+            }
+            Some(n) => {
+                // While the block could be found in the header cache, there is no guarantee
+                // that the blocks in the cache are canonical. Instead, use the index of
+                // canonical blocks maintained by the sync service, and fall back to asking the
+                // network for the header, which the sync service verifies against the
+                // canonical chain.
+                let hash = match self.sync_service.canonical_block_hash(n).await {
+                    Some(hash) => Ok(hash),
+                    None => self
+                        .sync_service
+                        .clone()
+                        .header_query_by_number(n)
+                        .await
+                        .map(|header| Host::blake2_256(&header)),
+                };
+
+                match hash {
+                    Ok(hash) => methods::Response::chain_getBlockHash(methods::HashHexString(hash))
+                        .to_json_response(request_id),
+                    // TODO: error or null?
+                    Err(()) => json_rpc::parse::build_success_response(request_id, "null"),
                 }
-                Some(_) => {
-                    // While the block could be found in `known_blocks`, there is no guarantee
-                    // that blocks in `known_blocks` are canonical, and we have no choice but to
-                    // return null.
-                    // TODO: ask a full node instead? or maybe keep a list of canonical blocks?
-                    json_rpc::parse::build_success_response(request_id, "null")
-                }
-            },
-            user_data,
-        );
+            }
+        };
+
+        self.send_back(&response, user_data);
+    }
+
+    /// Handles a call to [`methods::MethodCall::grandpa_proveFinality`].
+    ///
+    /// Sends back a SCALE-encoded finality proof made of the hash of the block, its
+    /// justification, and an empty list of headers, or `null` if no justification could be
+    /// found for this block.
+    async fn prove_finality(
+        self: Arc<JsonRpcService>,
+        user_data: u32,
+        request_id: &str,
+        block_number: u64,
+    ) {
+        let block_hash = match self
+            .sync_service
+            .clone()
+            .header_query_by_number(block_number)
+            .await
+        {
+            Ok(header) => Host::blake2_256(&header),
+            Err(()) => {
+                self.send_back(
+                    &json_rpc::parse::build_success_response(request_id, "null"),
+                    user_data,
+                );
+                return;
+            }
+        };
+
+        let response = match self
+            .sync_service
+            .clone()
+            .justification_query(block_hash)
+            .await
+        {
+            Ok(justification) => {
+                let mut proof = Vec::with_capacity(32 + 5 + justification.len() + 1);
+                proof.extend_from_slice(&block_hash);
+                proof.extend_from_slice(
+                    util::encode_scale_compact_usize(justification.len()).as_ref(),
+                );
+                proof.extend_from_slice(&justification);
+                // Empty list of unknown headers.
+                proof.extend_from_slice(util::encode_scale_compact_usize(0).as_ref());
+                methods::Response::grandpa_proveFinality(Some(methods::HexString(proof)))
+                    .to_json_response(request_id)
+            }
+            Err(sync_service::JustificationQueryError::NoValidJustification) => {
+                methods::Response::grandpa_proveFinality(None).to_json_response(request_id)
+            }
+            Err(sync_service::JustificationQueryError::NotGrandpa) => {
+                // Substrate full nodes don't expose the `grandpa_*` methods on chains that don't
+                // use GrandPa.
+                json_rpc::parse::build_error_response(
+                    request_id,
+                    json_rpc::parse::ErrorResponse::MethodNotFound,
+                    None,
+                )
+            }
+            Err(error) => {
+                let kind = match &error {
+                    sync_service::JustificationQueryError::NoPeer
+                    | sync_service::JustificationQueryError::Ancestry(
+                        sync_service::AncestryQueryError::NoPeer,
+                    ) => ErrorKind::NoPeer,
+                    sync_service::JustificationQueryError::Ancestry(
+                        sync_service::AncestryQueryError::RequestsFailed,
+                    ) => ErrorKind::Network,
+                    _ => ErrorKind::UnknownBlock,
+                };
+                error_response(request_id, kind, &error.to_string())
+            }
+        };
+
+        self.send_back(&response, user_data);
     }
 
     /// Handles a call to [`methods::MethodCall::chain_subscribeAllHeads`].
@@ -1173,7 +3025,7 @@ impl JsonRpcService {
             .lock()
             .await
             .entry(user_data)
-            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::default()))
+            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::new(user_data)))
             .clone();
         reference_arc
             .all_heads
@@ -1186,7 +3038,7 @@ impl JsonRpcService {
             // TODO: is it correct to return all non-finalized blocks first? have to compare with PolkadotJS
             stream::iter(subscribe_all.non_finalized_blocks)
                 .chain(subscribe_all.new_blocks)
-                .map(|notif| notif.scale_encoded_header)
+                .map(|notif| (notif.scale_encoded_header, notif.author))
         };
 
         let confirmation =
@@ -1208,33 +3060,31 @@ impl JsonRpcService {
                     match future::select(next_block, &mut unsubscribe_rx).await {
                         future::Either::Left((block, _)) => {
                             // TODO: don't unwrap `block`! channel can be legitimately closed if full
-                            let header =
-                                methods::Header::from_scale_encoded_header(&block.unwrap())
+                            let (scale_encoded_header, author) = block.unwrap();
+                            let mut header =
+                                methods::Header::from_scale_encoded_header(&scale_encoded_header)
                                     .unwrap();
+                            header.author = author.map(methods::HashHexString);
 
-                            let per_source_subscriptions =
-                                client.per_userdata_subscriptions.lock().await;
-
-                            if per_source_subscriptions
-                                .get(&user_data)
-                                .map_or(false, |arc| Arc::ptr_eq(arc, &reference_arc))
-                            {
-                                client.send_back(
+                            if !client
+                                .send_subscription_notification(
+                                    &reference_arc,
+                                    &subscription,
                                     &smoldot::json_rpc::parse::build_subscription_event(
                                         "chain_newHead",
                                         &subscription,
                                         &serde_json::to_string(&header).unwrap(),
                                     ),
-                                    user_data,
-                                );
-                            } else {
+                                )
+                                .await
+                            {
                                 break;
                             }
                         }
                         future::Either::Right((Ok(unsub_request_id), _)) => {
                             let response = methods::Response::chain_unsubscribeAllHeads(true)
                                 .to_json_response(&unsub_request_id);
-                            client.send_back(&response, user_data);
+                            client.send_back(&response, reference_arc.user_data());
                             break;
                         }
                         future::Either::Right((Err(_), _)) => break,
@@ -1257,7 +3107,7 @@ impl JsonRpcService {
             .lock()
             .await
             .entry(user_data)
-            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::default()))
+            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::new(user_data)))
             .clone();
         reference_arc
             .new_heads
@@ -1288,33 +3138,38 @@ impl JsonRpcService {
                     futures::pin_mut!(next_block);
                     match future::select(next_block, &mut unsubscribe_rx).await {
                         future::Either::Left((block, _)) => {
-                            let header =
-                                methods::Header::from_scale_encoded_header(&block.unwrap())
-                                    .unwrap();
-
-                            let per_source_subscriptions =
-                                client.per_userdata_subscriptions.lock().await;
-
-                            if per_source_subscriptions
-                                .get(&user_data)
-                                .map_or(false, |arc| Arc::ptr_eq(arc, &reference_arc))
-                            {
-                                client.send_back(
+                            let block = block.unwrap();
+                            let mut header = methods::Header::from_scale_encoded_header(
+                                &block.scale_encoded_header,
+                            )
+                            .unwrap();
+                            // The block might have been finalized and pruned from the syncing
+                            // service in the meantime, in which case the author is omitted.
+                            header.author = client
+                                .sync_service
+                                .block_author(block.hash)
+                                .await
+                                .map(methods::HashHexString);
+
+                            if !client
+                                .send_subscription_notification(
+                                    &reference_arc,
+                                    &subscription,
                                     &smoldot::json_rpc::parse::build_subscription_event(
                                         "chain_newHead",
                                         &subscription,
                                         &serde_json::to_string(&header).unwrap(),
                                     ),
-                                    user_data,
-                                );
-                            } else {
+                                )
+                                .await
+                            {
                                 break;
                             }
                         }
                         future::Either::Right((Ok(unsub_request_id), _)) => {
                             let response = methods::Response::chain_unsubscribeNewHeads(true)
                                 .to_json_response(&unsub_request_id);
-                            client.send_back(&response, user_data);
+                            client.send_back(&response, reference_arc.user_data());
                             break;
                         }
                         future::Either::Right((Err(_), _)) => break,
@@ -1341,7 +3196,7 @@ impl JsonRpcService {
             .lock()
             .await
             .entry(user_data)
-            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::default()))
+            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::new(user_data)))
             .clone();
         reference_arc
             .finalized_heads
@@ -1373,33 +3228,30 @@ impl JsonRpcService {
                     futures::pin_mut!(next_block);
                     match future::select(next_block, &mut unsubscribe_rx).await {
                         future::Either::Left((block, _)) => {
-                            let header =
-                                methods::Header::from_scale_encoded_header(&block.unwrap())
-                                    .unwrap();
-
-                            let per_source_subscriptions =
-                                client.per_userdata_subscriptions.lock().await;
+                            let header = methods::Header::from_scale_encoded_header(
+                                &block.unwrap().scale_encoded_header,
+                            )
+                            .unwrap();
 
-                            if per_source_subscriptions
-                                .get(&user_data)
-                                .map_or(false, |arc| Arc::ptr_eq(arc, &reference_arc))
-                            {
-                                client.send_back(
+                            if !client
+                                .send_subscription_notification(
+                                    &reference_arc,
+                                    &subscription,
                                     &smoldot::json_rpc::parse::build_subscription_event(
                                         "chain_finalizedHead",
                                         &subscription,
                                         &serde_json::to_string(&header).unwrap(),
                                     ),
-                                    user_data,
-                                );
-                            } else {
+                                )
+                                .await
+                            {
                                 break;
                             }
                         }
                         future::Either::Right((Ok(unsub_request_id), _)) => {
                             let response = methods::Response::chain_unsubscribeFinalizedHeads(true)
                                 .to_json_response(&unsub_request_id);
-                            client.send_back(&response, user_data);
+                            client.send_back(&response, reference_arc.user_data());
                             break;
                         }
                         future::Either::Right((Err(_), _)) => break,
@@ -1427,7 +3279,7 @@ impl JsonRpcService {
             .lock()
             .await
             .entry(user_data)
-            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::default()))
+            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::new(user_data)))
             .clone();
         reference_arc
             .storage
@@ -1450,8 +3302,8 @@ impl JsonRpcService {
                     async move {
                         loop {
                             let block = blocks_stream.next().await?;
-                            let block_hash = header::hash_from_scale_encoded_header(&block);
-                            let state_trie_root = header::decode(&block).unwrap().state_root;
+                            let block_hash = block.hash;
+                            let state_trie_root = &block.state_root;
 
                             let mut out = methods::StorageChangeSet {
                                 block: methods::HashHexString(block_hash),
@@ -1463,7 +3315,12 @@ impl JsonRpcService {
                                 match client
                                     .sync_service
                                     .clone()
-                                    .storage_query(&block_hash, state_trie_root, iter::once(&key.0))
+                                    .storage_query(
+                                        &block_hash,
+                                        state_trie_root,
+                                        iter::once(&key.0),
+                                        None,
+                                    )
                                     .await
                                 {
                                     Ok(mut values) => {
@@ -1519,29 +3376,25 @@ impl JsonRpcService {
                     futures::pin_mut!(next_block);
                     match future::select(next_block, &mut unsubscribe_rx).await {
                         future::Either::Left((changes, _)) => {
-                            let per_source_subscriptions =
-                                client.per_userdata_subscriptions.lock().await;
-
-                            if per_source_subscriptions
-                                .get(&user_data)
-                                .map_or(false, |arc| Arc::ptr_eq(arc, &reference_arc))
-                            {
-                                client.send_back(
+                            if !client
+                                .send_subscription_notification(
+                                    &reference_arc,
+                                    &subscription,
                                     &smoldot::json_rpc::parse::build_subscription_event(
                                         "state_storage",
                                         &subscription,
                                         &serde_json::to_string(&changes).unwrap(),
                                     ),
-                                    user_data,
-                                );
-                            } else {
+                                )
+                                .await
+                            {
                                 break;
                             }
                         }
                         future::Either::Right((Ok(unsub_request_id), _)) => {
                             let response = methods::Response::state_unsubscribeStorage(true)
                                 .to_json_response(&unsub_request_id);
-                            client.send_back(&response, user_data);
+                            client.send_back(&response, reference_arc.user_data());
                             break;
                         }
                         future::Either::Right((Err(_), _)) => break,
@@ -1551,56 +3404,737 @@ impl JsonRpcService {
         );
     }
 
+    /// Handles a call to [`methods::MethodCall::sudo_unstable_watchAccount`].
+    async fn watch_account(
+        self: Arc<JsonRpcService>,
+        user_data: u32,
+        request_id: &str,
+        account: methods::AccountId,
+    ) {
+        let subscription = self
+            .next_subscription
+            .fetch_add(1, atomic::Ordering::Relaxed)
+            .to_string();
+
+        let (unsubscribe_tx, mut unsubscribe_rx) = oneshot::channel();
+        let reference_arc = self
+            .per_userdata_subscriptions
+            .lock()
+            .await
+            .entry(user_data)
+            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::new(user_data)))
+            .clone();
+        reference_arc
+            .accounts
+            .lock()
+            .await
+            .insert(subscription.clone(), unsubscribe_tx);
+
+        // Build a stream of `methods::AccountState` items to send back to the user.
+        // The runtime service is used rather than the sync service, as it guarantees that the
+        // metadata used to find the account in the storage is the one of the notified block or
+        // of a more recent block.
+        let account_updates = {
+            let client = self.clone();
+            let (block, blocks_subscription) = self.runtime_service.subscribe_best().await;
+            let blocks_stream = stream::once(future::ready(block)).chain(blocks_subscription);
+
+            stream::unfold(
+                (
+                    blocks_stream,
+                    None::<AccountStorageCache>,
+                    None::<metadata::account::AccountInfo>,
+                ),
+                move |(mut blocks_stream, mut storage_cache, mut known_info)| {
+                    let client = client.clone();
+                    let account = account.clone();
+                    async move {
+                        loop {
+                            let block = blocks_stream.next().await?;
+                            let info = match client
+                                .account_info(&account.0, &block, &mut storage_cache)
+                                .await
+                            {
+                                Ok(info) => info,
+                                Err(error) => {
+                                    log::log!(
+                                        target: "json-rpc",
+                                        if error.is_network_problem() { log::Level::Debug } else { log::Level::Warn },
+                                        "sudo_unstable_watchAccount check failed: {}",
+                                        error
+                                    );
+                                    continue;
+                                }
+                            };
+
+                            if known_info.as_ref() == Some(&info) {
+                                continue;
+                            }
+
+                            let out = methods::AccountState {
+                                block: methods::HashHexString(block.hash),
+                                nonce: info.nonce,
+                                free: info.free,
+                                reserved: info.reserved,
+                                misc_frozen: info.misc_frozen,
+                                fee_frozen: info.fee_frozen,
+                            };
+                            known_info = Some(info);
+                            return Some((out, (blocks_stream, storage_cache, known_info)));
+                        }
+                    }
+                },
+            )
+        };
+
+        let confirmation = methods::Response::sudo_unstable_watchAccount(&subscription)
+            .to_json_response(request_id);
+
+        let client = self.clone();
+
+        // Spawn a separate task for the subscription.
+        (self.tasks_executor.lock().await)(
+            "jsonrpc-subscription-account".into(),
+            Box::pin(async move {
+                futures::pin_mut!(account_updates);
+
+                // Send back to the user the confirmation of the registration.
+                client.send_back(&confirmation, user_data);
+
+                loop {
+                    // Wait for either a new account state, or for the subscription to be
+                    // canceled.
+                    let next_state = account_updates.next();
+                    futures::pin_mut!(next_state);
+                    match future::select(next_state, &mut unsubscribe_rx).await {
+                        future::Either::Left((Some(state), _)) => {
+                            if !client
+                                .send_subscription_notification(
+                                    &reference_arc,
+                                    &subscription,
+                                    &smoldot::json_rpc::parse::build_subscription_event(
+                                        "sudo_unstable_accountState",
+                                        &subscription,
+                                        &serde_json::to_string(&state).unwrap(),
+                                    ),
+                                )
+                                .await
+                            {
+                                break;
+                            }
+                        }
+                        future::Either::Left((None, _)) => break,
+                        future::Either::Right((Ok(unsub_request_id), _)) => {
+                            let response = methods::Response::sudo_unstable_unwatchAccount(true)
+                                .to_json_response(&unsub_request_id);
+                            client.send_back(&response, reference_arc.user_data());
+                            break;
+                        }
+                        future::Either::Right((Err(_), _)) => break,
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Handles a call to [`methods::MethodCall::sudo_unstable_watchCandidateEvents`].
+    async fn watch_candidate_events(self: Arc<JsonRpcService>, user_data: u32, request_id: &str) {
+        let relay_chain = match &self.relay_chain {
+            Some(relay_chain) => relay_chain,
+            None => {
+                self.send_back(
+                    &json_rpc::parse::build_error_response(
+                        request_id,
+                        json_rpc::parse::ErrorResponse::ServerError(
+                            -32000,
+                            "The chain isn't a parachain",
+                        ),
+                        None,
+                    ),
+                    user_data,
+                );
+                return;
+            }
+        };
+
+        let subscription = self
+            .next_subscription
+            .fetch_add(1, atomic::Ordering::Relaxed)
+            .to_string();
+
+        let (unsubscribe_tx, mut unsubscribe_rx) = oneshot::channel();
+        let reference_arc = self
+            .per_userdata_subscriptions
+            .lock()
+            .await
+            .entry(user_data)
+            .or_insert_with(|| Arc::new(PerUserDataSubscriptions::new(user_data)))
+            .clone();
+        reference_arc
+            .candidate_events
+            .lock()
+            .await
+            .insert(subscription.clone(), unsubscribe_tx);
+
+        // Build a stream of lists of `methods::CandidateEvent` items, one for each new best
+        // block of the relay chain, to send back to the user.
+        let candidate_events = {
+            let parachain_id = relay_chain.parachain_id;
+            let relay_runtime_service = relay_chain.runtime_service.clone();
+            let (block, blocks_subscription) = relay_runtime_service.subscribe_best().await;
+            let blocks_stream = stream::once(future::ready(block)).chain(blocks_subscription);
+
+            stream::unfold(
+                (blocks_stream, None::<[u8; 32]>),
+                move |(mut blocks_stream, mut previous_block)| {
+                    let relay_runtime_service = relay_runtime_service.clone();
+                    async move {
+                        loop {
+                            // Only the latest block is interesting, as the runtime call below
+                            // is performed on the latest best block known to the runtime
+                            // service.
+                            let mut block = blocks_stream.next().await?;
+                            while let Some(Some(b)) = blocks_stream.next().now_or_never() {
+                                block = b;
+                            }
+                            if previous_block == Some(block.hash) {
+                                continue;
+                            }
+                            previous_block = Some(block.hash);
+
+                            let result = relay_runtime_service
+                                .recent_best_block_runtime_call(
+                                    "ParachainHost_candidate_events",
+                                    &[],
+                                    None,
+                                )
+                                .await;
+                            let encoded_events = match result {
+                                Ok(encoded_events) => encoded_events,
+                                Err(error) => {
+                                    log::log!(
+                                        target: "json-rpc",
+                                        if error.is_network_problem() { log::Level::Debug } else { log::Level::Warn },
+                                        "sudo_unstable_watchCandidateEvents runtime call failed: {}",
+                                        error
+                                    );
+                                    continue;
+                                }
+                            };
+
+                            let events =
+                                match para::decode_candidate_events_return_value(&encoded_events) {
+                                    Ok(events) => events,
+                                    Err(error) => {
+                                        log::warn!(
+                                            target: "json-rpc",
+                                            "Failed to decode candidate events: {}",
+                                            error
+                                        );
+                                        continue;
+                                    }
+                                };
+
+                            let out = events
+                                .into_iter()
+                                .filter(|event| event.descriptor.para_id == parachain_id)
+                                .map(|event| methods::CandidateEvent {
+                                    relay_block_hash: methods::HashHexString(block.hash),
+                                    kind: match event.kind {
+                                        para::CandidateEventKind::Backed => "backed",
+                                        para::CandidateEventKind::Included => "included",
+                                        para::CandidateEventKind::TimedOut => "timedOut",
+                                    },
+                                    relay_parent: methods::HashHexString(
+                                        *event.descriptor.relay_parent,
+                                    ),
+                                    collator: methods::HashHexString(*event.descriptor.collator),
+                                    pov_hash: methods::HashHexString(*event.descriptor.pov_hash),
+                                    para_head: methods::HashHexString(*event.descriptor.para_head),
+                                    commitments_hash: methods::HashHexString(
+                                        *event.commitments_hash,
+                                    ),
+                                    head_data: methods::HexString(event.head_data.to_vec()),
+                                    core_index: event.core_index,
+                                    group_index: event.group_index,
+                                })
+                                .collect::<Vec<_>>();
+                            if out.is_empty() {
+                                continue;
+                            }
+
+                            return Some((out, (blocks_stream, previous_block)));
+                        }
+                    }
+                },
+            )
+        };
+
+        let confirmation = methods::Response::sudo_unstable_watchCandidateEvents(&subscription)
+            .to_json_response(request_id);
+
+        let client = self.clone();
+
+        // Spawn a separate task for the subscription.
+        (self.tasks_executor.lock().await)(
+            "jsonrpc-subscription-candidate-events".into(),
+            Box::pin(async move {
+                futures::pin_mut!(candidate_events);
+
+                // Send back to the user the confirmation of the registration.
+                client.send_back(&confirmation, user_data);
+
+                'outer: loop {
+                    // Wait for either new candidate events, or for the subscription to be
+                    // canceled.
+                    let next_events = candidate_events.next();
+                    futures::pin_mut!(next_events);
+                    match future::select(next_events, &mut unsubscribe_rx).await {
+                        future::Either::Left((Some(events), _)) => {
+                            for event in events {
+                                if !client
+                                    .send_subscription_notification(
+                                        &reference_arc,
+                                        &subscription,
+                                        &smoldot::json_rpc::parse::build_subscription_event(
+                                            "sudo_unstable_candidateEvent",
+                                            &subscription,
+                                            &serde_json::to_string(&event).unwrap(),
+                                        ),
+                                    )
+                                    .await
+                                {
+                                    break 'outer;
+                                }
+                            }
+                        }
+                        future::Either::Left((None, _)) => break,
+                        future::Either::Right((Ok(unsub_request_id), _)) => {
+                            let response =
+                                methods::Response::sudo_unstable_unwatchCandidateEvents(true)
+                                    .to_json_response(&unsub_request_id);
+                            client.send_back(&response, reference_arc.user_data());
+                            break;
+                        }
+                        future::Either::Right((Err(_), _)) => break,
+                    }
+                }
+            }),
+        );
+    }
+
+    /// Reads and decodes the `System::Account` storage entry of the given account at the given
+    /// block, using the metadata of the runtime of the best block.
+    ///
+    /// The storage key and layout derived from the metadata are stored in `storage_cache`, and
+    /// are only derived again if the runtime of the best block has changed.
+    async fn account_info(
+        self: &Arc<JsonRpcService>,
+        account_id: &[u8; 32],
+        block: &sync_service::HeaderNotification,
+        storage_cache: &mut Option<AccountStorageCache>,
+    ) -> Result<metadata::account::AccountInfo, AccountInfoError> {
+        let runtime = self
+            .runtime_service
+            .best_block_runtime()
+            .await
+            .map_err(|()| {
+                AccountInfoError::Metadata(runtime_service::MetadataError::InvalidRuntime)
+            })?;
+
+        if !matches!(storage_cache, Some(cache) if cache.runtime == runtime) {
+            // Clear the cache first, so that it isn't left stale in case of error.
+            *storage_cache = None;
+
+            let metadata = self
+                .runtime_service
+                .clone()
+                .metadata()
+                .await
+                .map_err(AccountInfoError::Metadata)?;
+            let metadata = smoldot::metadata::decode(&metadata)
+                .map_err(|_| AccountInfoError::MetadataDecode)?;
+            let storage_key = metadata::account::account_storage_key(metadata, account_id)
+                .map_err(AccountInfoError::StorageKey)?;
+
+            *storage_cache = Some(AccountStorageCache {
+                runtime,
+                key: storage_key.key,
+                default_value: storage_key.default_value.to_vec(),
+                layout: storage_key.layout,
+            });
+        }
+
+        let storage_key = storage_cache.as_ref().unwrap();
+
+        let value = self
+            .sync_service
+            .clone()
+            .storage_query(
+                &block.hash,
+                &block.state_root,
+                iter::once(&storage_key.key),
+                None,
+            )
+            .await
+            .map_err(AccountInfoError::StorageQuery)?
+            .pop()
+            .unwrap();
+
+        metadata::account::decode_account_info(
+            storage_key.layout,
+            value.as_deref().unwrap_or(&storage_key.default_value),
+        )
+        .map_err(AccountInfoError::Decode)
+    }
+
+    /// Reads the state of the message queues of the parachain from the storage of the best block
+    /// of its relay chain.
+    async fn parachain_message_queues(
+        self: &Arc<JsonRpcService>,
+    ) -> Result<methods::ParachainMessageQueues, ParachainMessageQueuesError> {
+        let relay_chain = self
+            .relay_chain
+            .as_ref()
+            .ok_or(ParachainMessageQueuesError::NotParachain)?;
+
+        let (block, _) = relay_chain.runtime_service.subscribe_best().await;
+        let metadata = relay_chain
+            .runtime_service
+            .clone()
+            .metadata()
+            .await
+            .map_err(ParachainMessageQueuesError::Metadata)?;
+        let metadata = smoldot::metadata::decode(&metadata)
+            .map_err(|_| ParachainMessageQueuesError::MetadataDecode)?;
+
+        // Storage entries that the runtime of the relay chain doesn't have are considered empty,
+        // as not all relay chains support all the kinds of messages.
+        let storage_key = |module_name: &str, entry_name: &str, map_key: &[u8]| {
+            match metadata::storage::storage_key(metadata, module_name, entry_name, &[map_key]) {
+                Ok(key) => Ok(Some(key.key)),
+                Err(metadata::storage::StorageKeyError::NoModule)
+                | Err(metadata::storage::StorageKeyError::NoEntry) => Ok(None),
+                Err(error) => Err(ParachainMessageQueuesError::StorageKey(error)),
+            }
+        };
+
+        let para_id = relay_chain.parachain_id;
+        let para_id_encoded = para_id.to_le_bytes();
+
+        let mut values = storage_query_optional_keys(
+            &relay_chain.sync_service,
+            &block,
+            vec![
+                storage_key("Hrmp", "HrmpIngressChannelsIndex", &para_id_encoded)?,
+                storage_key("Hrmp", "HrmpEgressChannelsIndex", &para_id_encoded)?,
+                storage_key("Ump", "RelayDispatchQueueSize", &para_id_encoded)?,
+                storage_key("Dmp", "DownwardMessageQueueHeads", &para_id_encoded)?,
+            ],
+        )
+        .await
+        .map_err(ParachainMessageQueuesError::StorageQuery)?
+        .into_iter();
+
+        let mut decode_para_ids = |entry_name| match values.next().unwrap() {
+            Some(value) => para::decode_para_ids(&value)
+                .map_err(|_| ParachainMessageQueuesError::Decode(entry_name)),
+            None => Ok(Vec::new()),
+        };
+        let senders = decode_para_ids("Hrmp::HrmpIngressChannelsIndex")?;
+        let recipients = decode_para_ids("Hrmp::HrmpEgressChannelsIndex")?;
+        let (upward_queue_count, upward_queue_size) = match values.next().unwrap() {
+            Some(value) => para::decode_upward_queue_size(&value)
+                .map_err(|_| ParachainMessageQueuesError::Decode("Ump::RelayDispatchQueueSize"))?,
+            None => (0, 0),
+        };
+        // The head of a queue that has never received any message is set to zero.
+        let downward_queue_head = match values.next().unwrap() {
+            Some(value) => Some(para::decode_message_queue_head(&value).map_err(|_| {
+                ParachainMessageQueuesError::Decode("Dmp::DownwardMessageQueueHeads")
+            })?)
+            .filter(|head| *head != [0; 32]),
+            None => None,
+        };
+
+        let channels = senders
+            .iter()
+            .map(|sender| (*sender, para_id))
+            .chain(recipients.iter().map(|recipient| (para_id, *recipient)))
+            .collect::<Vec<_>>();
+        let channels_keys = channels
+            .iter()
+            .map(|(sender, recipient)| {
+                let channel_id = para::hrmp_channel_id_scale_encoded(*sender, *recipient);
+                storage_key("Hrmp", "HrmpChannels", &channel_id)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let channels_values =
+            storage_query_optional_keys(&relay_chain.sync_service, &block, channels_keys)
+                .await
+                .map_err(ParachainMessageQueuesError::StorageQuery)?;
+
+        let mut inbound_channels = Vec::with_capacity(senders.len());
+        let mut outbound_channels = Vec::with_capacity(recipients.len());
+        for ((sender, recipient), value) in channels.into_iter().zip(channels_values) {
+            // Channels in the indices are normally always present in `HrmpChannels`.
+            let value = match value {
+                Some(v) => v,
+                None => continue,
+            };
+
+            let channel = para::decode_hrmp_channel(&value)
+                .map_err(|_| ParachainMessageQueuesError::Decode("Hrmp::HrmpChannels"))?;
+            let state = methods::HrmpChannelState {
+                para_id: if recipient == para_id {
+                    sender
+                } else {
+                    recipient
+                },
+                message_count: channel.msg_count,
+                total_size: channel.total_size,
+                max_capacity: channel.max_capacity,
+                max_total_size: channel.max_total_size,
+                max_message_size: channel.max_message_size,
+                mqc_head: channel.mqc_head.map(methods::HashHexString),
+            };
+
+            if recipient == para_id {
+                inbound_channels.push(state);
+            } else {
+                outbound_channels.push(state);
+            }
+        }
+
+        Ok(methods::ParachainMessageQueues {
+            relay_block_hash: methods::HashHexString(block.hash),
+            para_id,
+            downward_queue_head: downward_queue_head.map(methods::HashHexString),
+            upward_queue_count,
+            upward_queue_size,
+            inbound_channels,
+            outbound_channels,
+        })
+    }
+
     async fn storage_query(
         self: &Arc<JsonRpcService>,
         key: &[u8],
         hash: &[u8; 32],
+        trace: &request_trace::RequestTrace,
     ) -> Result<Option<Vec<u8>>, StorageQueryError> {
-        // TODO: risk of deadlock here?
-        let header = self
+        let mut result = self.storage_query_keys(&[key], hash, trace).await?;
+        Ok(result.pop().unwrap())
+    }
+
+    /// Similar to [`JsonRpcService::storage_query`], but queries multiple keys at once. Returns
+    /// one value per key, in the same order as `keys`.
+    async fn storage_query_keys(
+        self: &Arc<JsonRpcService>,
+        keys: &[&[u8]],
+        hash: &[u8; 32],
+        trace: &request_trace::RequestTrace,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryError> {
+        if let Some(values) = self.storage_prefetch_keys(keys, hash).await {
+            return Ok(values);
+        }
+
+        let trie_root_hash = self
             .header_query(hash)
             .await
-            .map_err(|_| StorageQueryError::FindStorageRootHashError)?;
-        let trie_root_hash = header::decode(&header).unwrap().state_root;
+            .map_err(|_| StorageQueryError::FindStorageRootHashError)?
+            .state_root;
 
-        let mut result = self
-            .sync_service
+        self.sync_service
             .clone()
-            .storage_query(hash, &trie_root_hash, iter::once(key))
+            .storage_query(hash, &trie_root_hash, keys.iter(), Some(trace))
             .await
-            .map_err(StorageQueryError::StorageRetrieval)?;
-        Ok(result.pop().unwrap())
+            .map_err(StorageQueryError::StorageRetrieval)
     }
 
-    async fn header_query(self: &Arc<JsonRpcService>, hash: &[u8; 32]) -> Result<Vec<u8>, ()> {
-        // TODO: risk of deadlock here?
-        let mut blocks = self.blocks.lock().await;
-        let blocks = &mut *blocks;
+    /// Similar to [`JsonRpcService::storage_query`], but the query is retargeted to the current
+    /// finalized block if `hash` turns out to have been pruned by the peers. Returns the hash of
+    /// the block the value has been read from.
+    async fn storage_query_finalized_or_newer(
+        self: &Arc<JsonRpcService>,
+        key: &[u8],
+        hash: &[u8; 32],
+        trace: &request_trace::RequestTrace,
+    ) -> Result<([u8; 32], Option<Vec<u8>>), StorageQueryError> {
+        let (block_hash, mut result) = self
+            .storage_query_keys_finalized_or_newer(&[key], hash, trace)
+            .await?;
+        Ok((block_hash, result.pop().unwrap()))
+    }
 
-        if let Some(header) = blocks.known_blocks.get(hash) {
-            Ok(header.scale_encoding_vec())
-        } else {
-            // Header isn't known locally. Ask the network.
-            let result = self
-                .sync_service
-                .clone()
-                .block_query(
-                    *hash,
-                    protocol::BlocksRequestFields {
-                        header: true,
-                        body: false,
-                        justification: false,
-                    },
-                )
-                .await;
+    /// Similar to [`JsonRpcService::storage_query_finalized_or_newer`], but queries multiple keys
+    /// at once. All the values are read from the same block, whose hash is returned.
+    async fn storage_query_keys_finalized_or_newer(
+        self: &Arc<JsonRpcService>,
+        keys: &[&[u8]],
+        hash: &[u8; 32],
+        trace: &request_trace::RequestTrace,
+    ) -> Result<([u8; 32], Vec<Option<Vec<u8>>>), StorageQueryError> {
+        if let Some(values) = self.storage_prefetch_keys(keys, hash).await {
+            return Ok((*hash, values));
+        }
 
-            // Note that the `block_query` method guarantees that the header is present
-            // and valid.
-            if let Ok(block) = result {
-                Ok(block.header.unwrap())
-            } else {
-                Err(())
+        let trie_root_hash = self
+            .header_query(hash)
+            .await
+            .map_err(|_| StorageQueryError::FindStorageRootHashError)?
+            .state_root;
+
+        self.sync_service
+            .clone()
+            .storage_query_finalized_or_newer(hash, &trie_root_hash, keys.iter(), Some(trace))
+            .await
+            .map_err(StorageQueryError::StorageRetrieval)
+    }
+
+    /// Returns the values of the given keys in the storage of the given block if all of them are
+    /// found in the storage prefetch cache.
+    async fn storage_prefetch_keys(
+        &self,
+        keys: &[&[u8]],
+        hash: &[u8; 32],
+    ) -> Option<Vec<Option<Vec<u8>>>> {
+        let storage_prefetch = self.storage_prefetch.as_ref()?;
+
+        // All the keys are looked up, even after a miss, as the cache uses the lookups in order
+        // to determine which keys to prefetch.
+        let mut values = Vec::with_capacity(keys.len());
+        for key in keys {
+            values.push(storage_prefetch.get(hash, key).await);
+        }
+        values.into_iter().collect()
+    }
+
+    /// Returns the header of the given block, either from the header cache or from the network.
+    /// Headers downloaded from the network are inserted in the cache.
+    async fn header_query(
+        self: &Arc<JsonRpcService>,
+        hash: &[u8; 32],
+    ) -> Result<Arc<header_cache::CachedHeader>, ()> {
+        if let Some(header) = self.header_cache.get(hash).await {
+            return Ok(header);
+        }
+
+        // Header isn't known locally. Ask the network directly. Walking the chain backwards from
+        // the finalized block would also work for finalized blocks, but requires downloading
+        // potentially thousands of headers, and is only done for lookups by block number.
+        let result = self
+            .sync_service
+            .clone()
+            .block_query(
+                *hash,
+                protocol::BlocksRequestFields {
+                    header: true,
+                    body: false,
+                    justification: false,
+                    indexed_body: false,
+                },
+            )
+            .await;
+
+        // Note that the `block_query` method guarantees that the header is present and valid.
+        let header = match result {
+            Ok(block) => block.header.unwrap(),
+            Err(()) => return Err(()),
+        };
+
+        self.header_cache.insert(header).await.map_err(|_| ())
+    }
+}
+
+/// Queries the storage of the given block, where some of the keys might be missing. Returns, for
+/// each key, the storage value, or `None` if the key is `None` or if there is no value.
+async fn storage_query_optional_keys(
+    sync_service: &Arc<sync_service::SyncService>,
+    block: &sync_service::HeaderNotification,
+    keys: Vec<Option<Vec<u8>>>,
+) -> Result<Vec<Option<Vec<u8>>>, sync_service::StorageQueryError> {
+    if keys.iter().all(Option::is_none) {
+        return Ok(keys);
+    }
+
+    let mut values = sync_service
+        .clone()
+        .storage_query(&block.hash, &block.state_root, keys.iter().flatten(), None)
+        .await?
+        .into_iter();
+
+    Ok(keys
+        .iter()
+        .map(|key| key.as_ref().and_then(|_| values.next().unwrap()))
+        .collect())
+}
+
+/// Error potentially returned by [`JsonRpcService::parachain_message_queues`].
+#[derive(Debug, derive_more::Display)]
+enum ParachainMessageQueuesError {
+    /// The chain isn't a parachain.
+    NotParachain,
+    /// Failed to obtain the metadata of the runtime of the relay chain.
+    #[display(fmt = "Failed to obtain the relay chain metadata: {}", _0)]
+    Metadata(runtime_service::MetadataError),
+    /// Failed to decode the metadata of the runtime of the relay chain.
+    #[display(fmt = "Failed to decode the relay chain metadata")]
+    MetadataDecode,
+    /// Failed to build the key of a storage entry of the relay chain.
+    #[display(fmt = "Unsupported relay chain storage: {}", _0)]
+    StorageKey(metadata::storage::StorageKeyError),
+    /// Error while retrieving the storage items from other nodes.
+    #[display(fmt = "{}", _0)]
+    StorageQuery(sync_service::StorageQueryError),
+    /// Failed to decode the value of the given storage entry.
+    #[display(fmt = "Failed to decode {}", _0)]
+    Decode(&'static str),
+}
+
+/// Information about the `System::Account` storage entry of an account, derived from the
+/// metadata of a specific runtime. See [`JsonRpcService::account_info`].
+struct AccountStorageCache {
+    /// Runtime whose metadata has been used to derive the other fields.
+    runtime: executor::CoreVersion,
+    /// See [`metadata::account::AccountStorageKey::key`].
+    key: Vec<u8>,
+    /// See [`metadata::account::AccountStorageKey::default_value`].
+    default_value: Vec<u8>,
+    /// See [`metadata::account::AccountStorageKey::layout`].
+    layout: metadata::account::AccountInfoLayout,
+}
+
+/// Error potentially returned by [`JsonRpcService::account_info`].
+#[derive(Debug, derive_more::Display)]
+enum AccountInfoError {
+    /// Failed to obtain the metadata of the runtime.
+    #[display(fmt = "Failed to obtain the metadata: {}", _0)]
+    Metadata(runtime_service::MetadataError),
+    /// Failed to decode the metadata of the runtime.
+    #[display(fmt = "Failed to decode the metadata")]
+    MetadataDecode,
+    /// The metadata doesn't describe the storage of accounts in a supported way.
+    #[display(fmt = "Unsupported accounts storage: {}", _0)]
+    StorageKey(metadata::account::AccountStorageKeyError),
+    /// Error while retrieving the storage item from other nodes.
+    #[display(fmt = "{}", _0)]
+    StorageQuery(sync_service::StorageQueryError),
+    /// Failed to decode the storage value.
+    #[display(fmt = "Failed to decode the account: {}", _0)]
+    Decode(metadata::account::DecodeAccountInfoError),
+}
+
+impl AccountInfoError {
+    /// Returns `true` if this is caused by networking issues, as opposed to a consensus-related
+    /// issue.
+    fn is_network_problem(&self) -> bool {
+        match self {
+            AccountInfoError::Metadata(runtime_service::MetadataError::CallError(err)) => {
+                err.is_network_problem()
             }
+            AccountInfoError::StorageQuery(err) => err.is_network_problem(),
+            _ => false,
         }
     }
 }
@@ -1614,3 +4148,227 @@ enum StorageQueryError {
     #[display(fmt = "{}", _0)]
     StorageRetrieval(sync_service::StorageQueryError),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        system_properties, with_timeout, Admission, ConsumerLimits, Consumers,
+        DetachedNotifications, MethodsFilter, MAX_DETACHED_NOTIFICATIONS,
+    };
+    use crate::{
+        platform::{Host, Platform as _},
+        test_utils,
+    };
+    use core::{
+        num::{NonZeroU32, NonZeroUsize},
+        time::Duration,
+    };
+    use futures::prelude::*;
+
+    #[test]
+    fn methods_filter() {
+        let filter = MethodsFilter::default();
+        assert!(filter.is_allowed("author_submitExtrinsic"));
+
+        let filter = MethodsFilter {
+            allow: None,
+            deny: vec!["author_*".to_owned(), "system_peers".to_owned()],
+            allow_unsafe: false,
+        };
+        assert!(!filter.is_allowed("author_submitExtrinsic"));
+        assert!(!filter.is_allowed("system_peers"));
+        assert!(filter.is_allowed("system_peersList"));
+        assert!(filter.is_allowed("chain_getHeader"));
+
+        let filter = MethodsFilter {
+            allow: Some(vec!["chain_*".to_owned(), "state_getStorage".to_owned()]),
+            deny: vec!["chain_subscribe*".to_owned()],
+            allow_unsafe: false,
+        };
+        assert!(filter.is_allowed("chain_getHeader"));
+        assert!(filter.is_allowed("state_getStorage"));
+        assert!(!filter.is_allowed("state_getMetadata"));
+        assert!(!filter.is_allowed("chain_subscribeNewHeads"));
+    }
+
+    #[test]
+    fn system_properties_drops_malformed_known_properties() {
+        let mut spec =
+            serde_json::from_slice::<serde_json::Value>(include_bytes!("../../../westend.json"))
+                .unwrap();
+        spec["properties"] = serde_json::json!({
+            "tokenDecimals": "twelve",
+            "tokenSymbol": ["WND"],
+            "foo": 1,
+        });
+        let chain_spec =
+            smoldot::chain_spec::ChainSpec::from_json_bytes(&serde_json::to_vec(&spec).unwrap())
+                .unwrap();
+
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(system_properties(&chain_spec).get())
+                .unwrap(),
+            serde_json::json!({ "tokenSymbol": ["WND"], "foo": 1 })
+        );
+    }
+
+    #[test]
+    fn detached_notifications_bounded() {
+        let mut notifications = DetachedNotifications::default();
+        for n in 0..MAX_DETACHED_NOTIFICATIONS + 3 {
+            notifications.push(n.to_string());
+        }
+
+        assert_eq!(notifications.num_dropped, 3);
+        assert_eq!(notifications.queue.len(), MAX_DETACHED_NOTIFICATIONS);
+        assert_eq!(notifications.queue.front().unwrap(), "3");
+        assert_eq!(
+            *notifications.queue.back().unwrap(),
+            (MAX_DETACHED_NOTIFICATIONS + 2).to_string()
+        );
+    }
+
+    #[test]
+    fn methods_filter_unsafe() {
+        let filter = MethodsFilter::default();
+        assert!(!filter.is_allowed("author_removeExtrinsic"));
+
+        let filter = MethodsFilter {
+            allow: Some(vec!["author_*".to_owned()]),
+            deny: Vec::new(),
+            allow_unsafe: false,
+        };
+        assert!(filter.is_allowed("author_submitExtrinsic"));
+        assert!(!filter.is_allowed("author_removeExtrinsic"));
+
+        let filter = MethodsFilter {
+            allow: None,
+            deny: Vec::new(),
+            allow_unsafe: true,
+        };
+        assert!(filter.is_allowed("author_removeExtrinsic"));
+
+        let filter = MethodsFilter {
+            allow: None,
+            deny: vec!["author_removeExtrinsic".to_owned()],
+            allow_unsafe: true,
+        };
+        assert!(!filter.is_allowed("author_removeExtrinsic"));
+    }
+
+    #[test]
+    fn consumers_concurrency_limit() {
+        let mut consumers = Consumers::new(ConsumerLimits {
+            max_concurrent_requests: NonZeroUsize::new(1),
+            max_queued_requests: 1,
+            max_requests_per_second: None,
+        });
+        let now = Host::now();
+
+        assert!(matches!(consumers.push(1, now, 'a'), Admission::Start('a')));
+        assert!(matches!(consumers.push(1, now, 'b'), Admission::Queued));
+        assert!(matches!(
+            consumers.push(1, now, 'c'),
+            Admission::Rejected('c')
+        ));
+
+        // Other consumers aren't affected.
+        assert!(matches!(consumers.push(2, now, 'd'), Admission::Start('d')));
+
+        assert_eq!(consumers.finished(1, now), Some('b'));
+        assert_eq!(consumers.finished(1, now), None);
+        assert!(matches!(consumers.push(1, now, 'e'), Admission::Start('e')));
+
+        consumers.finished(1, now);
+        assert!(matches!(consumers.push(1, now, 'f'), Admission::Start('f')));
+        assert!(matches!(consumers.push(1, now, 'g'), Admission::Queued));
+        consumers.clear_queue(1);
+        assert_eq!(consumers.finished(1, now), None);
+    }
+
+    #[test]
+    fn consumers_rate_limit() {
+        let mut consumers = Consumers::new(ConsumerLimits {
+            max_concurrent_requests: None,
+            max_queued_requests: 0,
+            max_requests_per_second: NonZeroU32::new(2),
+        });
+        let now = Host::now();
+
+        assert!(matches!(consumers.push(1, now, 'a'), Admission::Start('a')));
+        assert!(matches!(consumers.push(1, now, 'b'), Admission::Start('b')));
+        assert!(matches!(
+            consumers.push(1, now, 'c'),
+            Admission::Rejected('c')
+        ));
+        assert!(matches!(consumers.push(2, now, 'd'), Admission::Start('d')));
+
+        let later = now + Duration::from_secs(1);
+        assert!(matches!(
+            consumers.push(1, later, 'e'),
+            Admission::Start('e')
+        ));
+    }
+
+    #[test]
+    fn internal_error_response() {
+        let response = super::error_response(
+            "5",
+            super::ErrorKind::NoPeer,
+            "No node available for storage query",
+        );
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["id"], 5);
+        assert_eq!(response["error"]["code"], -32603);
+        assert_eq!(response["error"]["message"], "Unknown error occurred");
+        assert_eq!(response["error"]["data"]["kind"], "noPeer");
+        assert_eq!(
+            response["error"]["data"]["message"],
+            "No node available for storage query"
+        );
+    }
+
+    #[test]
+    fn substrate_error_codes() {
+        let code = |kind| {
+            let response = super::error_response("5", kind, "");
+            serde_json::from_str::<serde_json::Value>(&response).unwrap()["error"]["code"].clone()
+        };
+
+        assert_eq!(code(super::ErrorKind::InvalidParams), -32602);
+        assert_eq!(code(super::ErrorKind::TransactionVerification), 1002);
+        assert_eq!(code(super::ErrorKind::InvalidTransaction), 1010);
+        assert_eq!(code(super::ErrorKind::UnknownTransactionValidity), 1011);
+        assert_eq!(code(super::ErrorKind::UnknownBlock), -32603);
+        assert_eq!(code(super::ErrorKind::RuntimeCall), -32603);
+    }
+
+    #[test]
+    fn with_timeout_gives_up() {
+        let (output, elapsed) = test_utils::block_on(
+            async move {
+                let start = Host::now();
+                let output = with_timeout(future::pending::<()>(), Duration::from_secs(2)).await;
+                (output, Host::now() - start)
+            },
+            None,
+        );
+        assert_eq!(output, None);
+        assert_eq!(elapsed, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn with_timeout_finishes() {
+        let output = test_utils::block_on(
+            async move {
+                with_timeout(
+                    Host::sleep(Duration::from_secs(1)).map(|()| 5),
+                    Duration::from_secs(2),
+                )
+                .await
+            },
+            None,
+        );
+        assert_eq!(output, Some(5));
+    }
+}
@@ -22,82 +22,338 @@
 #![deny(broken_intra_doc_links)]
 #![deny(unused_crate_dependencies)]
 
-use futures::{channel::mpsc, lock::Mutex, prelude::*};
+use futures::{
+    channel::{mpsc, oneshot},
+    lock::Mutex,
+    prelude::*,
+};
+use platform::{Host, Platform as _};
 use smoldot::{
     chain, chain_spec,
-    libp2p::{multiaddr, peer_id::PeerId},
+    libp2p::{multiaddr, peer_id::PeerId, relay},
+};
+use std::{
+    collections::HashMap,
+    mem,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    pin::Pin,
+    sync::Arc,
+    task,
+    time::Duration,
 };
-use std::{collections::HashMap, pin::Pin, sync::Arc, task};
 
 pub mod ffi;
 
+mod canonical_index;
+mod cpu_usage;
+mod cross_validation;
+pub mod data_provider;
+mod dnsaddr_resolver;
+mod events;
+mod header_cache;
 mod json_rpc_service;
 mod lossy_channel;
+mod memory_usage;
 mod network_service;
+mod platform;
+mod request_trace;
+mod rpc_fallback;
 mod runtime_service;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod standalone;
+mod storage_prefetch;
 mod sync_service;
+#[cfg(test)]
+mod test_utils;
 mod transactions_service;
+mod work_queues;
 
-// Use the default "system" allocator. In the context of Wasm, this uses the `dlmalloc` library.
-// See <https://github.com/rust-lang/rust/tree/1.47.0/library/std/src/sys/wasm>.
-//
-// While the `wee_alloc` crate is usually the recommended choice in WebAssembly, testing has shown
-// that using it makes memory usage explode from ~100MiB to ~2GiB and more (the environment then
-// refuses to allocate 4GiB).
-#[global_allocator]
-static ALLOC: std::alloc::System = std::alloc::System;
-
+#[derive(Clone)]
 pub struct ChainConfig {
     pub specification: String,
     pub json_rpc_running: bool,
+    /// Which JSON-RPC methods can be called on this chain. Ignored if `json_rpc_running` is
+    /// `false`.
+    pub json_rpc_methods_filter: json_rpc_service::MethodsFilter,
+    /// Relative weight of this chain when it comes to sharing the CPU with the other chains.
+    /// See the [`cpu_usage`] module.
+    pub cpu_weight: NonZeroU32,
+    /// What to download from the network when synchronizing this chain.
+    pub sync_mode: sync_service::SyncMode,
+    /// Number of distinct peers that must agree before the result of a critical query, such as
+    /// the download of the runtime code, is accepted. See [`sync_service::Config::quorum_size`].
+    pub quorum_size: NonZeroUsize,
+    /// If `true`, the syncing, runtime, transactions, and JSON-RPC services of this chain are
+    /// only started when the first JSON-RPC request targeting this chain arrives, rather than
+    /// at initialization. Until then, no connection is opened on behalf of this chain.
+    ///
+    /// Ignored for parachains, for relay chains of other chains, and if `json_rpc_running` is
+    /// `false`. See also [`ChainConfig::lazy_idle_timeout`].
+    pub lazy: bool,
+    /// If `Some`, the services of a chain started because of [`ChainConfig::lazy`] are suspended
+    /// once the chain has gone this long without receiving any JSON-RPC request, provided that
+    /// no JSON-RPC client has any subscription on it. Their tasks are stopped, no new
+    /// connection is opened on behalf of the chain, and the chain is synchronized again from
+    /// its latest finalized block when the next JSON-RPC request arrives. Connections that are
+    /// already open aren't closed, as they might be used by other chains.
+    ///
+    /// If `None`, the services of the chain keep running once started. Ignored if the chain
+    /// isn't lazy.
+    pub lazy_idle_timeout: Option<Duration>,
+    /// If `true`, the chain doesn't share its network service with the other chains. It opens
+    /// its own connections, and uses a random network identity that is never used by any other
+    /// chain, so that peers can't find out that the client also follows other chains.
+    ///
+    /// Parachains always use the network service of their relay chain, and can only be isolated
+    /// if their relay chain is isolated as well.
+    pub isolated_network: bool,
+    /// If `Some`, some of the JSON-RPC requests targeting this chain are also sent to a full
+    /// node, and the responses are compared. See the [`cross_validation`] module. Ignored if
+    /// `json_rpc_running` is `false`.
+    pub json_rpc_cross_validation: Option<cross_validation::Config>,
+    /// If `Some`, storage queries and runtime calls that can't be answered from the network,
+    /// such as queries targeting old blocks, are answered by a trusted full node. See the
+    /// [`rpc_fallback`] module. Ignored if `json_rpc_running` is `false`.
+    pub json_rpc_fallback: Option<rpc_fallback::Config>,
+    /// If `Some`, the values of up to this number of storage keys recently requested through
+    /// JSON-RPC are downloaded ahead of time at each new best block. See the
+    /// [`storage_prefetch`] module. Ignored if `json_rpc_running` is `false`.
+    pub json_rpc_storage_prefetch_keys: Option<NonZeroUsize>,
+    /// If `true`, the transactions submitted through JSON-RPC are validated against the best
+    /// block before being sent out, and the ones found invalid are refused. See
+    /// [`transactions_service::Config::validate_locally`]. Ignored if `json_rpc_running` is
+    /// `false`.
+    pub json_rpc_validate_transactions: bool,
+    /// Consensus engines, other than Aura, Babe, and GrandPa, whose digest items can be found
+    /// in the headers of this chain. These items are verified by calling
+    /// [`platform::Platform::verify_custom_digest_items`]. Ignored for parachains.
+    pub custom_consensus_engines: Vec<[u8; 4]>,
+    /// If `Some`, the memory of the virtual machine running the runtime of this chain is
+    /// limited to the given number of 64 kiB pages, including when the runtime grows its memory
+    /// while being executed.
+    pub max_runtime_memory_pages: Option<u32>,
 }
 
-/// Starts a client running the given chain specifications.
-pub async fn start_client(
-    chains: impl Iterator<Item = ChainConfig>,
-    max_log_level: log::LevelFilter,
-) {
+/// Options of the client that aren't specific to a chain. See [`start_client`].
+pub struct ClientConfig {
+    /// Maximum level of the logs emitted through [`platform::Logger`].
+    pub max_log_level: log::LevelFilter,
+    /// If true, networking requests indicate to peers that the responses can be compressed.
+    pub request_compressed_responses: bool,
+    /// If `Some`, bootstrap nodes whose address is a `/dnsaddr` multiaddress are resolved by
+    /// sending queries to the DNS-over-HTTPS server at this URL. See the [`dnsaddr_resolver`]
+    /// module.
+    pub dns_over_https_url: Option<String>,
+    /// If true, the `sudo_unstable_p2pRequest` JSON-RPC method, which sends arbitrary requests
+    /// to peers, can be called.
+    pub unstable_p2p_requests: bool,
+    /// Request-response protocols, on top of the ones used by the chains, that
+    /// `sudo_unstable_p2pRequest` can send requests on. See
+    /// [`network_service::Config::extra_request_response_protocols`].
+    pub unstable_p2p_protocols: Vec<network_service::ExtraRequestResponseProtocol>,
+    /// Limits applied to each JSON-RPC consumer, as identified by the `user_data` of its
+    /// requests.
+    pub json_rpc_consumer_limits: json_rpc_service::ConsumerLimits,
+    /// See [`network_service::Config::dial_strategy`].
+    pub dial_strategy: network_service::DialStrategy,
+    /// See [`network_service::Config::dial_timeout`].
+    pub dial_timeout: Duration,
+    /// Limits applied to the connections opened with other nodes. See
+    /// [`network_service::Config::connection_limits`].
+    pub connection_limits: platform::ConnectionLimits,
+    /// See [`network_service::Config::peers_target`].
+    pub peers_target: usize,
+    /// If true, bootstrap nodes and discovered nodes that can only be reached through a relay
+    /// are connected to. See [`network_service::Config::allow_relayed_connections`].
+    pub allow_relayed_connections: bool,
+    /// Settings that reduce the amount of information other nodes can learn about the client.
+    /// See [`network_service::Config::privacy`].
+    pub privacy: network_service::PrivacyConfig,
+    /// If `Some`, used as the ed25519 private key of the client on the peer-to-peer network.
+    /// See [`network_service::Config::network_key`].
+    pub network_key: Option<[u8; 32]>,
+    /// Version of the format of the events about peers passed to
+    /// [`platform::Platform::emit_peer_event`]. See the [`events`] module.
+    pub peer_events_version: events::Version,
+}
+
+/// Chain passed to [`start_client`], after its specification has been decoded.
+struct DecodedChain {
+    config: ChainConfig,
+    chain_spec: chain_spec::ChainSpec,
+    /// Bootstrap nodes of the chain that can be connected to directly.
+    bootstrap_nodes: Vec<(PeerId, multiaddr::Multiaddr)>,
+    /// Information about the chain at which to start syncing it, either the genesis block or
+    /// the checkpoint found in the chain specification.
+    chain_information: chain::chain_information::ValidChainInformation,
+    /// Information about the genesis block of the chain.
+    genesis_chain_information: chain::chain_information::ValidChainInformation,
+}
+
+/// Starts a client running the given chains.
+pub async fn start_client(chains: Vec<ChainConfig>, config: ClientConfig) {
     // Try initialize the logging and the panic hook.
     // Note that `start_client` can theoretically be called multiple times, meaning that these
     // calls shouldn't panic if reached multiple times.
-    let _ =
-        log::set_boxed_logger(Box::new(ffi::Logger)).map(|()| log::set_max_level(max_log_level));
+    let _ = log::set_boxed_logger(Box::new(platform::Logger))
+        .map(|()| log::set_max_level(config.max_log_level));
     std::panic::set_hook(Box::new(|info| {
-        ffi::throw(info.to_string());
+        Host::throw(info.to_string());
     }));
 
     // Fool-proof check to make sure that randomness is properly implemented.
     assert_ne!(rand::random::<u64>(), 0);
     assert_ne!(rand::random::<u64>(), rand::random::<u64>());
 
-    // Decode the chain specifications, and whether the chain should be running a JSON-RPC service.
-    let (chain_specs, json_rpc_running) = {
-        let mut chain_specs = Vec::new();
-        let mut json_rpc_running = Vec::new();
-
-        for chain in chains {
-            chain_specs.push(
-                match chain_spec::ChainSpec::from_json_bytes(&chain.specification) {
-                    Ok(cs) => {
-                        log::info!("Loaded chain specs for {}", cs.name());
-                        cs
-                    }
-                    Err(err) => ffi::throw(format!("Error while opening chain specs: {}", err)),
-                },
-            );
+    // Bootstrap nodes whose address is a `/dnsaddr` multiaddress, if `dns_over_https_url` is
+    // `Some`. Contains the index of the chain, the identity of the node, and its address. These
+    // addresses can't be connected to directly, and are instead resolved after the network
+    // service has started.
+    let mut dnsaddr_bootstrap_nodes = Vec::new();
+
+    // Decode the chain specifications and classify their bootstrap nodes.
+    let mut decoded_chains = Vec::new();
+    for (chain_index, chain) in chains.into_iter().enumerate() {
+        let chain_spec = match chain_spec::ChainSpec::from_json_bytes(&chain.specification) {
+            Ok(cs) => {
+                log::info!("Loaded chain specs for {}", cs.name());
+                cs
+            }
+            Err(err) => Host::throw(format!("Error while opening chain specs: {}", err)),
+        };
+
+        // Classify the bootstrap nodes depending on whether the host is capable of
+        // connecting to them.
+        let mut usable = Vec::with_capacity(chain_spec.boot_nodes().len());
+        let mut num_unsupported = 0;
+        let mut num_invalid = 0;
+        let num_dnsaddr_before = dnsaddr_bootstrap_nodes.len();
+
+        for node in chain_spec.boot_nodes() {
+            let mut address = match node.parse::<multiaddr::Multiaddr>() {
+                Ok(a) => a,
+                Err(err) => {
+                    log::warn!(
+                        target: "network",
+                        "Invalid bootnode address in chain specs of {}: {} ({})",
+                        chain_spec.name(),
+                        node,
+                        err
+                    );
+                    num_invalid += 1;
+                    continue;
+                }
+            };
+
+            let peer_id = match address.pop() {
+                Some(multiaddr::Protocol::P2p(peer_id)) => PeerId::from_multihash(peer_id).ok(),
+                _ => None,
+            };
+            let peer_id = match peer_id {
+                Some(p) => p,
+                None => {
+                    log::warn!(
+                        target: "network",
+                        "Bootnode address in chain specs of {} doesn't end with a valid \
+                        `/p2p` component: {}",
+                        chain_spec.name(),
+                        node
+                    );
+                    num_invalid += 1;
+                    continue;
+                }
+            };
+
+            if config.dns_over_https_url.is_some()
+                && matches!(address.iter().next(), Some(multiaddr::Protocol::Dnsaddr(_)))
+            {
+                dnsaddr_bootstrap_nodes.push((chain_index, peer_id, address));
+                continue;
+            }
+
+            // Nodes reached through a relay are usable if the relay itself is.
+            let transport_address = match relay::split_relayed_address(&address) {
+                Some((relay_address, _)) if config.allow_relayed_connections => relay_address,
+                _ => address.clone(),
+            };
+
+            match platform::Transport::from_multiaddr(&transport_address) {
+                Some(transport) if Host::supports_transport(transport) => {
+                    usable.push((peer_id, address))
+                }
+                transport => {
+                    log::debug!(
+                        target: "network",
+                        "Ignoring bootnode of {} with unsupported transport ({:?}): {}",
+                        chain_spec.name(),
+                        transport,
+                        node
+                    );
+                    num_unsupported += 1;
+                }
+            }
+        }
+
+        let num_dnsaddr = dnsaddr_bootstrap_nodes.len() - num_dnsaddr_before;
+
+        log::info!(
+            target: "network",
+            "Bootnodes of {}: {} usable, {} pending /dnsaddr resolution, {} with an \
+            unsupported transport, {} invalid",
+            chain_spec.name(),
+            usable.len(),
+            num_dnsaddr,
+            num_unsupported,
+            num_invalid
+        );
 
-            json_rpc_running.push(chain.json_rpc_running);
+        // A chain without any bootnode at all is accepted, as it might be intentional. A
+        // chain whose bootnodes are all unusable, however, would silently never connect to
+        // anything.
+        if !chain_spec.boot_nodes().is_empty() && usable.is_empty() && num_dnsaddr == 0 {
+            Host::throw(format!(
+                "None of the {} bootnodes of {} can be connected to on this platform \
+                    ({} with an unsupported transport, {} invalid)",
+                chain_spec.boot_nodes().len(),
+                chain_spec.name(),
+                num_unsupported,
+                num_invalid
+            ));
         }
 
-        (chain_specs, json_rpc_running)
-    };
+        // Similarly, the full nodes that JSON-RPC requests are sent to would otherwise only be
+        // found to be unreachable when the first request is sent to them.
+        if chain.json_rpc_running {
+            let full_nodes = chain
+                .json_rpc_cross_validation
+                .iter()
+                .map(|config| ("cross-validation", &config.address))
+                .chain(
+                    chain
+                        .json_rpc_fallback
+                        .iter()
+                        .map(|config| ("RPC fallback", &config.address)),
+                );
+            for (purpose, address) in full_nodes {
+                let transport = address
+                    .parse::<multiaddr::Multiaddr>()
+                    .ok()
+                    .and_then(|address| platform::Transport::from_multiaddr(&address));
+                if !transport.map_or(false, Host::supports_transport) {
+                    Host::throw(format!(
+                        "The {} full node of {} can't be connected to on this platform: {}",
+                        purpose,
+                        chain_spec.name(),
+                        address
+                    ));
+                }
+            }
+        }
 
-    // Load the information about the chains from the chain specs. If a light sync state is
-    // present in the chain specs, it is possible to start sync at the finalized block it
-    // describes.
-    let genesis_chain_information = chain_specs
-        .iter()
-        .map(|chain_spec| {
+        // Load the information about the chain from the chain specs. If a light sync state is
+        // present in the chain specs, it is possible to start sync at the finalized block it
+        // describes.
+        let genesis_chain_information =
             match chain::chain_information::ValidChainInformation::from_chain_spec(&chain_spec) {
                 Ok(ci) => ci,
                 Err(err) => panic!(
@@ -105,28 +361,29 @@ pub async fn start_client(
                     chain_spec.name(),
                     err
                 ),
-            }
-        })
-        .collect::<Vec<_>>();
-    let chain_information = chain_specs
-        .iter()
-        .zip(genesis_chain_information.iter())
-        .map(|(chain_spec, genesis_chain_information)| {
-            if let Some(light_sync_state) = chain_spec.light_sync_state() {
-                log::info!(
-                    "Using light checkpoint starting at #{}",
-                    light_sync_state
-                        .as_chain_information()
-                        .as_ref()
-                        .finalized_block_header
-                        .number
-                );
-                light_sync_state.as_chain_information()
-            } else {
-                genesis_chain_information.clone()
-            }
-        })
-        .collect::<Vec<_>>();
+            };
+        let chain_information = if let Some(light_sync_state) = chain_spec.light_sync_state() {
+            log::info!(
+                "Using light checkpoint starting at #{}",
+                light_sync_state
+                    .as_chain_information()
+                    .as_ref()
+                    .finalized_block_header
+                    .number
+            );
+            light_sync_state.as_chain_information()
+        } else {
+            genesis_chain_information.clone()
+        };
+
+        decoded_chains.push(DecodedChain {
+            config: chain,
+            chain_spec,
+            bootstrap_nodes: usable,
+            chain_information,
+            genesis_chain_information,
+        });
+    }
 
     // Starting here, the code below initializes the various "services" that make up the node.
     // Services need to be able to spawn asynchronous tasks on their own. Since "spawning a task"
@@ -149,14 +406,7 @@ pub async fn start_client(
         .clone()
         .unbounded_send((
             "services-initialization".into(),
-            start_services(
-                new_task_tx,
-                chain_information,
-                genesis_chain_information,
-                chain_specs,
-                json_rpc_running,
-            )
-            .boxed(),
+            start_services(new_task_tx, decoded_chains, dnsaddr_bootstrap_nodes, config).boxed(),
         ))
         .unwrap();
 
@@ -216,40 +466,120 @@ async fn start_services(
         String,
         Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
     )>,
-    chain_information: Vec<chain::chain_information::ValidChainInformation>,
-    genesis_chain_information: Vec<chain::chain_information::ValidChainInformation>,
-    chain_specs: Vec<chain_spec::ChainSpec>,
-    json_rpc_running: Vec<bool>,
+    mut chains: Vec<DecodedChain>,
+    dnsaddr_bootstrap_nodes: Vec<(usize, PeerId, multiaddr::Multiaddr)>,
+    config: ClientConfig,
 ) {
-    // The network service is responsible for connecting to the peer-to-peer network
-    // of all chains.
-    let (network_service, mut network_event_receivers) =
-        network_service::NetworkService::new(network_service::Config {
-            tasks_executor: Box::new({
-                let new_task_tx = new_task_tx.clone();
-                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+    // Chains are split between networks. All the chains share the same network, except for the
+    // chains that are isolated, which each get their own. Parachains always belong to the network
+    // of their relay chain, as they send requests to the peers of the relay chain.
+    // `chain_networks` contains, for each chain, the index of its network within
+    // `networks_chains` and the index of the chain within that network, while `networks_chains`
+    // contains the indices of the chains of each network.
+    let mut chain_networks: Vec<Option<(usize, usize)>> = vec![None; chains.len()];
+    let mut networks_chains: Vec<Vec<usize>> = Vec::new();
+    let mut shared_network = None;
+    for (chain_index, chain) in chains.iter().enumerate() {
+        if chain.chain_spec.relay_chain().is_some() {
+            continue;
+        }
+
+        let network_index = if chain.config.isolated_network {
+            networks_chains.push(Vec::new());
+            networks_chains.len() - 1
+        } else {
+            *shared_network.get_or_insert_with(|| {
+                networks_chains.push(Vec::new());
+                networks_chains.len() - 1
+            })
+        };
+
+        chain_networks[chain_index] = Some((network_index, networks_chains[network_index].len()));
+        networks_chains[network_index].push(chain_index);
+    }
+    for (chain_index, chain) in chains.iter().enumerate() {
+        let (relay_chain_id, _) = match chain.chain_spec.relay_chain() {
+            Some(v) => v,
+            None => continue,
+        };
+
+        // If the relay chain can't be found, the parachain is put in the shared network. The
+        // initialization of its services fails later on anyway.
+        let relay_network = chains
+            .iter()
+            .position(|c| c.chain_spec.id() == relay_chain_id)
+            .and_then(|relay_chain_index| chain_networks[relay_chain_index])
+            .map(|(network_index, _)| network_index);
+        if chain.config.isolated_network
+            && (relay_network.is_none() || relay_network == shared_network)
+        {
+            panic!(
+                "Parachain `{}` can only have an isolated network if its relay chain `{}` has one",
+                chain.chain_spec.id(),
+                relay_chain_id
+            );
+        }
+
+        let network_index = match relay_network {
+            Some(network_index) => network_index,
+            None => *shared_network.get_or_insert_with(|| {
+                networks_chains.push(Vec::new());
+                networks_chains.len() - 1
             }),
-            num_events_receivers: chain_information.len(), // Configures the length of `network_event_receivers`
-            chains: chain_information
-                .iter()
-                .zip(chain_specs.iter())
-                .zip(genesis_chain_information.iter())
-                .map(
-                    |((chain_information, chain_spec), genesis_chain_information)| {
+        };
+
+        chain_networks[chain_index] = Some((network_index, networks_chains[network_index].len()));
+        networks_chains[network_index].push(chain_index);
+    }
+
+    // Chains whose services are started on the first JSON-RPC request. Until then, the network
+    // services don't open connections on their behalf.
+    let lazy_chains = lazy_chains(
+        &chains
+            .iter()
+            .map(|chain| {
+                (
+                    &chain.chain_spec,
+                    chain.config.lazy && chain.config.json_rpc_running,
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    // Each network service is responsible for connecting to the peer-to-peer network of the
+    // chains of its network.
+    let mut networks = Vec::with_capacity(networks_chains.len());
+    for (network_index, network_chains) in networks_chains.iter().enumerate() {
+        let (network_service, network_event_receivers) =
+            network_service::NetworkService::new(network_service::Config {
+                tasks_executor: Box::new({
+                    let new_task_tx = new_task_tx.clone();
+                    move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+                }),
+                num_events_receivers: network_chains.len(), // Configures the length of `network_event_receivers`
+                request_compressed_responses: config.request_compressed_responses,
+                dial_strategy: config.dial_strategy,
+                dial_timeout: config.dial_timeout,
+                connection_limits: config.connection_limits,
+                peers_target: config.peers_target,
+                allow_relayed_connections: config.allow_relayed_connections,
+                privacy: config.privacy.clone(),
+                // Isolated networks always use a random identity, in order to not be linkable
+                // to the other networks.
+                network_key: if Some(network_index) == shared_network {
+                    config.network_key
+                } else {
+                    None
+                },
+                extra_request_response_protocols: config.unstable_p2p_protocols.clone(),
+                chains: network_chains
+                    .iter()
+                    .map(|&chain_index| {
+                        let chain = &mut chains[chain_index];
+                        let chain_information = &chain.chain_information;
+                        let genesis_chain_information = &chain.genesis_chain_information;
                         network_service::ConfigChain {
-                            bootstrap_nodes: {
-                                let mut list = Vec::with_capacity(chain_spec.boot_nodes().len());
-                                for node in chain_spec.boot_nodes() {
-                                    let mut address: multiaddr::Multiaddr = node.parse().unwrap(); // TODO: don't unwrap?
-                                    if let Some(multiaddr::Protocol::P2p(peer_id)) = address.pop() {
-                                        let peer_id = PeerId::from_multihash(peer_id).unwrap(); // TODO: don't unwrap
-                                        list.push((peer_id, address));
-                                    } else {
-                                        panic!() // TODO:
-                                    }
-                                }
-                                list
-                            },
+                            bootstrap_nodes: mem::take(&mut chain.bootstrap_nodes),
                             has_grandpa_protocol: matches!(
                                 genesis_chain_information.as_ref().finality,
                                 chain::chain_information::ChainInformationFinalityRef::Grandpa { .. }
@@ -261,83 +591,201 @@ async fn start_services(
                                 chain_information.as_ref().finalized_block_header.number,
                                 chain_information.as_ref().finalized_block_header.hash(),
                             ),
-                            protocol_id: chain_spec.protocol_id().to_string(),
+                            protocol_id: chain.chain_spec.protocol_id().to_string(),
+                            paused: lazy_chains[chain_index],
                         }
-                    },
-                )
-                .collect(),
+                    })
+                    .collect(),
+            })
+            .await;
+        networks.push((network_service, network_event_receivers));
+    }
+
+    // Network service of each chain, and index of the chain within this network service.
+    let chain_network_services = chain_networks
+        .iter()
+        .map(|chain_network| {
+            let (network_index, network_chain_index) = chain_network.unwrap();
+            (networks[network_index].0.clone(), network_chain_index)
         })
-        .await;
+        .collect::<Vec<_>>();
 
-    // The network service is the only one in common between all chains. Other services run once
+    // Spawn a task that resolves the `/dnsaddr` addresses of bootstrap nodes and adds the outcome
+    // to the network service.
+    if let Some(dns_over_https_url) = config.dns_over_https_url {
+        if !dnsaddr_bootstrap_nodes.is_empty() {
+            new_task_tx
+                .unbounded_send((
+                    "dnsaddr-resolution".into(),
+                    Box::pin({
+                        let chain_network_services = chain_network_services.clone();
+                        async move {
+                            for (chain_index, peer_id, address) in dnsaddr_bootstrap_nodes {
+                                let (network_service, network_chain_index) =
+                                    &chain_network_services[chain_index];
+                                match dnsaddr_resolver::resolve(
+                                    &dns_over_https_url,
+                                    &address,
+                                    &peer_id,
+                                )
+                                .await
+                                {
+                                    Ok(addrs) if !addrs.is_empty() => {
+                                        log::debug!(
+                                            target: "dnsaddr",
+                                            "Resolved {} into {:?}",
+                                            address,
+                                            addrs
+                                        );
+                                        network_service
+                                            .add_known_addresses(
+                                                *network_chain_index,
+                                                peer_id,
+                                                addrs,
+                                            )
+                                            .await;
+                                    }
+                                    Ok(_) => {
+                                        log::warn!(
+                                            target: "dnsaddr",
+                                            "No WebSocket address found when resolving {}",
+                                            address
+                                        );
+                                    }
+                                    Err(err) => {
+                                        log::warn!(
+                                            target: "dnsaddr",
+                                            "Failed to resolve {}: {}",
+                                            address,
+                                            err
+                                        );
+                                    }
+                                }
+                            }
+                        }
+                    }),
+                ))
+                .unwrap();
+        }
+    }
+
+    // Spawn tasks that report the events about the peers of all chains to the embedder.
+    for ((network_service, _), network_chains) in networks.iter().zip(networks_chains.iter()) {
+        let peer_events_version = config.peer_events_version;
+        new_task_tx
+            .unbounded_send((
+                "peer-events".into(),
+                Box::pin({
+                    let network_service = network_service.clone();
+                    let chains = network_chains.clone();
+                    async move {
+                        if peer_events_version.is_deprecated() {
+                            events::warn_deprecated(
+                                "Version 1 of the format of the peer events",
+                                "version 2, through the `peerEventsVersion` option",
+                            );
+                        }
+
+                        let (initial_peers, mut peer_events) =
+                            network_service.subscribe_peer_events().await;
+                        drop(network_service);
+
+                        for info in initial_peers {
+                            report_peer_event(
+                                network_service::PeerEvent::Connected(info),
+                                &chains,
+                                peer_events_version,
+                            );
+                        }
+                        while let Some(event) = peer_events.next().await {
+                            report_peer_event(event, &chains, peer_events_version);
+                        }
+                    }
+                }),
+            ))
+            .unwrap();
+    }
+
+    // The network services are the only ones in common between chains. Other services run once
     // per chain.
 
+    // Runtimes compiled by the runtime services are shared between all chains, in order to avoid
+    // compiling the same runtime code multiple times.
+    let compilation_cache = Arc::new(runtime_service::CompilationCache::new());
+
+    // CPU time accounting of each chain, shared between the services of the chain.
+    let max_cpu_weight = chains
+        .iter()
+        .map(|chain| chain.config.cpu_weight)
+        .max()
+        .unwrap_or(NonZeroU32::new(1).unwrap());
+    let cpu_usages = chains
+        .iter()
+        .map(|chain| {
+            Arc::new(cpu_usage::CpuUsage::new(
+                chain.config.cpu_weight,
+                max_cpu_weight,
+            ))
+        })
+        .collect::<Vec<_>>();
+
     // The `Vec` below is filled when we start the services of a chain.
     let mut per_chain: Vec<
         Option<(
             Arc<sync_service::SyncService>,
             Arc<runtime_service::RuntimeService>,
+            Arc<header_cache::HeaderCache>,
         )>,
-    > = (0..chain_specs.len()).map(|_| None).collect();
+    > = (0..chains.len()).map(|_| None).collect();
+
+    // Network events receivers of the chains in `lazy_chains`, indexed by chain. They are drained
+    // until the chain starts. See [`drain_network_events`].
+    let mut lazy_network_events = HashMap::new();
 
     // Start the services of the chains that aren't parachains.
-    for (chain_index, ((chain_information, chain_spec), genesis_chain_information)) in
-        chain_information
-            .iter()
-            .zip(chain_specs.iter())
-            .zip(genesis_chain_information.iter())
-            .filter(|((_, chain_spec), _)| chain_spec.relay_chain().is_none())
-            .enumerate()
+    for (chain_index, chain) in chains
+        .iter()
+        .enumerate()
+        .filter(|(_, chain)| chain.chain_spec.relay_chain().is_none())
     {
-        // The sync service is leveraging the network service, downloads block headers,
-        // and verifies them, to determine what are the best and finalized blocks of the
-        // chain.
-        let sync_service = Arc::new(
-            sync_service::SyncService::new(sync_service::Config {
-                chain_information: chain_information.clone(),
-                tasks_executor: Box::new({
-                    let new_task_tx = new_task_tx.clone();
-                    move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
-                }),
-                network_service: (network_service.clone(), chain_index),
-                network_events_receiver: network_event_receivers.pop().unwrap(),
-                parachain: None,
-            })
-            .await,
-        );
+        let network_index = chain_networks[chain_index].unwrap().0;
+        let network_events_receiver = networks[network_index].1.pop().unwrap();
 
-        // The runtime service follows the runtime of the best block of the chain,
-        // and allows performing runtime calls.
-        let runtime_service = runtime_service::RuntimeService::new(runtime_service::Config {
-            tasks_executor: Box::new({
-                let new_task_tx = new_task_tx.clone();
-                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
-            }),
-            sync_service: sync_service.clone(),
-            chain_spec: &chain_spec,
-            genesis_block_hash: genesis_chain_information
-                .as_ref()
-                .finalized_block_header
-                .hash(),
-            genesis_block_state_root: *genesis_chain_information
-                .as_ref()
-                .finalized_block_header
-                .state_root,
-        })
+        if lazy_chains[chain_index] {
+            lazy_network_events.insert(
+                chain_index,
+                drain_network_events(&new_task_tx, network_events_receiver),
+            );
+            continue;
+        }
+
+        let services = start_standalone_chain(
+            &new_task_tx,
+            chain_network_services[chain_index].clone(),
+            network_events_receiver,
+            &chain.chain_information,
+            &chain.chain_spec,
+            &cpu_usages[chain_index],
+            chain.config.sync_mode,
+            chain.config.quorum_size,
+            chain_index,
+            chain.config.custom_consensus_engines.clone(),
+            &compilation_cache,
+            chain.config.max_runtime_memory_pages,
+        )
         .await;
 
         debug_assert!(per_chain[chain_index].is_none());
-        per_chain[chain_index] = Some((sync_service.clone(), runtime_service));
+        per_chain[chain_index] = Some(services);
     }
 
     // Start the services of the parachains.
-    for (chain_index, ((chain_information, chain_spec), genesis_chain_information)) in
-        chain_information
-            .iter()
-            .zip(chain_specs.iter())
-            .zip(genesis_chain_information.iter())
-            .enumerate()
-    {
+    let mut relay_chains = (0..chains.len())
+        .map(|_| None)
+        .collect::<Vec<Option<json_rpc_service::ConfigRelayChain>>>();
+    for (chain_index, chain) in chains.iter().enumerate() {
+        let (chain_information, chain_spec) = (&chain.chain_information, &chain.chain_spec);
+
         // Skip non-parachains.
         let (relay_chain_id, parachain_id) = match chain_spec.relay_chain() {
             Some(v) => v,
@@ -345,7 +793,10 @@ async fn start_services(
         };
 
         // Find the index of the relay chain in the list of chains.
-        let relay_chain_index = match chain_specs.iter().position(|s| s.id() == relay_chain_id) {
+        let relay_chain_index = match chains
+            .iter()
+            .position(|c| c.chain_spec.id() == relay_chain_id)
+        {
             Some(idx) => idx,
             None => panic!("Couldn't find relay chain `{}`", relay_chain_id),
         };
@@ -372,17 +823,49 @@ async fn start_services(
                     let new_task_tx = new_task_tx.clone();
                     move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
                 }),
-                network_service: (network_service.clone(), chain_index),
-                network_events_receiver: network_event_receivers.pop().unwrap(),
+                network_service: chain_network_services[chain_index].clone(),
+                network_events_receiver: networks[chain_networks[chain_index].unwrap().0]
+                    .1
+                    .pop()
+                    .unwrap(),
                 parachain: Some(sync_service::ConfigParachain {
                     parachain_id,
                     relay_chain_sync: relay_chain_services.1.clone(),
-                    relay_network_chain_index: relay_chain_index,
+                    relay_network_chain_index: chain_network_services[relay_chain_index].1,
                 }),
+                cpu_usage: cpu_usages[chain_index].clone(),
+                slot_duration: slot_duration(chain_information, chain_spec),
+                max_announce_future_drift: max_announce_future_drift(chain_spec),
+                babe_relaxed_secondary_slots: chain_spec.babe_relaxed_secondary_slots(),
+                work_queues: work_queues::Config {
+                    max_in_progress: NonZeroUsize::new(16).unwrap(),
+                    block_tracking_weight: NonZeroU32::new(2).unwrap(),
+                    json_rpc_weight: NonZeroU32::new(1).unwrap(),
+                },
+                canonical_index_capacity: 16384,
+                max_proof_size: 8 * 1024 * 1024,
+                sync_mode: chain.config.sync_mode,
+                quorum_size: chain.config.quorum_size,
+                // Ignored for parachains.
+                custom_consensus_engines: None,
             })
             .await,
         );
 
+        // The header cache holds the recent headers of the chain, and is shared between the
+        // services of this chain.
+        let header_cache = header_cache::start(header_cache::Config {
+            tasks_executor: Box::new({
+                let new_task_tx = new_task_tx.clone();
+                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+            }),
+            sync_service: sync_service.clone(),
+            capacity: 256,
+            fork_blocks_window: 64,
+            custom_consensus_engines: chain.config.custom_consensus_engines.clone(),
+        })
+        .await;
+
         // The runtime service follows the runtime of the best block of the chain,
         // and allows performing runtime calls.
         let runtime_service = runtime_service::RuntimeService::new(runtime_service::Config {
@@ -390,71 +873,178 @@ async fn start_services(
                 let new_task_tx = new_task_tx.clone();
                 move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
             }),
-            sync_service: sync_service.clone(),
+            data_provider: sync_service.clone(),
+            header_cache: header_cache.clone(),
             chain_spec,
-            genesis_block_hash: genesis_chain_information
-                .as_ref()
-                .finalized_block_header
-                .hash(),
-            genesis_block_state_root: *genesis_chain_information
-                .as_ref()
-                .finalized_block_header
-                .state_root,
+            genesis_block_hash: None,
+            genesis_block_state_root: None,
+            compilation_cache: compilation_cache.clone(),
+            max_runtime_memory_pages: chain.config.max_runtime_memory_pages,
+            best_block_debounce: Duration::from_millis(500),
+            max_notifications_per_second: None,
+            cpu_usage: cpu_usages[chain_index].clone(),
+            max_failed_call_recordings: 8,
         })
         .await;
 
+        relay_chains[chain_index] = Some(json_rpc_service::ConfigRelayChain {
+            parachain_id,
+            sync_service: relay_chain_services.0.clone(),
+            runtime_service: relay_chain_services.1.clone(),
+        });
+
         debug_assert!(per_chain[chain_index].is_none());
-        per_chain[chain_index] = Some((sync_service.clone(), runtime_service));
+        per_chain[chain_index] = Some((sync_service.clone(), runtime_service, header_cache));
     }
 
-    debug_assert!(per_chain.iter().all(Option::is_some));
+    debug_assert!(per_chain
+        .iter()
+        .enumerate()
+        .all(|(chain_index, services)| services.is_some() || lazy_chains[chain_index]));
+
+    // Spawn a task that reports checkpoints of the chains to the embedder, both
+    // periodically if the finalized block has changed and on demand. Chains whose services are
+    // started lazily aren't covered.
+    new_task_tx
+        .unbounded_send((
+            "checkpoints".into(),
+            Box::pin({
+                let sync_services = per_chain
+                    .iter()
+                    .map(|services| services.as_ref().map(|(sync, _, _)| sync.clone()))
+                    .collect::<Vec<_>>();
+                async move {
+                    let mut last_emitted = vec![None; sync_services.len()];
+                    loop {
+                        let requested = match future::select(
+                            Host::next_checkpoint_request(),
+                            Host::sleep(Duration::from_secs(5 * 60)),
+                        )
+                        .await
+                        {
+                            future::Either::Left((chain_index, _)) => Some(chain_index),
+                            future::Either::Right(((), _)) => None,
+                        };
+
+                        for (chain_index, sync_service) in sync_services.iter().enumerate() {
+                            if requested.is_some() && requested != Some(chain_index) {
+                                continue;
+                            }
+
+                            let sync_service = match sync_service {
+                                Some(s) => s,
+                                None => continue,
+                            };
+
+                            let checkpoint = match sync_service.serialize_chain_information().await
+                            {
+                                Some(c) => c,
+                                None => continue,
+                            };
+
+                            // Periodic checkpoints are only emitted if they have changed.
+                            if requested.is_none()
+                                && last_emitted[chain_index].as_ref() == Some(&checkpoint)
+                            {
+                                continue;
+                            }
+
+                            Host::emit_checkpoint(&checkpoint, chain_index);
+                            last_emitted[chain_index] = Some(checkpoint);
+                        }
+                    }
+                }
+            }),
+        ))
+        .unwrap();
 
     // Spawn the JSON-RPC services. They are responsible for answering incoming JSON-RPC requests.
     let mut json_rpc_services = HashMap::new();
-    for (chain_index, (((services, json_rpc_running), genesis_chain_information), chain_spec)) in
-        per_chain
-            .into_iter()
-            .zip(json_rpc_running)
-            .zip(genesis_chain_information)
-            .zip(chain_specs)
-            .enumerate()
-    {
-        if !json_rpc_running {
+    for (chain_index, (chain, services)) in chains.into_iter().zip(per_chain).enumerate() {
+        let DecodedChain {
+            config: chain_config,
+            chain_spec,
+            chain_information,
+            genesis_chain_information,
+            ..
+        } = chain;
+
+        if !chain_config.json_rpc_running {
             continue;
         }
 
-        let (sync_service, runtime_service) = services.unwrap();
+        let services = match services {
+            Some(services) => services,
+            None => {
+                // The chain is lazy. Its services, including the JSON-RPC service, are started
+                // when the first request targeting the chain arrives, and possibly suspended
+                // again after the chain has been idle.
+                let idle_timeout = chain_config.lazy_idle_timeout;
+                let lazy_chain = Arc::new(LazyChain {
+                    new_task_tx: new_task_tx.clone(),
+                    network_service: chain_network_services[chain_index].clone(),
+                    network_events: lazy_network_events.remove(&chain_index).unwrap(),
+                    cpu_usage: cpu_usages[chain_index].clone(),
+                    compilation_cache: compilation_cache.clone(),
+                    unstable_p2p_requests: config.unstable_p2p_requests,
+                    chain_index,
+                    chain_name: chain_spec.name().to_owned(),
+                    config: chain_config,
+                    genesis_chain_information,
+                    state: Mutex::new(LazyChainState {
+                        chain_information,
+                        running: None,
+                    }),
+                });
 
-        let finalized_header = genesis_chain_information.as_ref().finalized_block_header;
-        let transactions_service = Arc::new(
-            transactions_service::TransactionsService::new(transactions_service::Config {
-                tasks_executor: Box::new({
-                    let new_task_tx = new_task_tx.clone();
-                    move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
-                }),
-                network_service: (network_service.clone(), 0),
-                sync_service: sync_service.clone(),
-            })
-            .await,
-        );
+                let lazy_service = json_rpc_service::LazyJsonRpcService::lazy(
+                    Box::new({
+                        let lazy_chain = lazy_chain.clone();
+                        move || lazy_chain.clone().start().boxed()
+                    }),
+                    Box::new(move || lazy_chain.clone().suspend().boxed()),
+                );
 
-        let json_rpc_service = json_rpc_service::start(json_rpc_service::Config {
-            tasks_executor: Box::new({
-                let new_task_tx = new_task_tx.clone();
-                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
-            }),
-            network_service: (network_service.clone(), 0),
-            sync_service,
-            transactions_service,
-            runtime_service,
+                if let Some(idle_timeout) = idle_timeout {
+                    new_task_tx
+                        .unbounded_send((
+                            "lazy-chain-idle-timeout".into(),
+                            Box::pin({
+                                let lazy_service = lazy_service.clone();
+                                async move {
+                                    loop {
+                                        Host::sleep(idle_timeout).await;
+                                        lazy_service.suspend_if_idle(idle_timeout).await;
+                                    }
+                                }
+                            }),
+                        ))
+                        .unwrap();
+                }
+
+                json_rpc_services.insert(chain_index, lazy_service);
+                continue;
+            }
+        };
+
+        let json_rpc_service = start_json_rpc_service(
+            &new_task_tx,
+            chain_network_services[chain_index].clone(),
+            services,
+            &genesis_chain_information,
             chain_spec,
-            genesis_block_hash: finalized_header.hash(),
-            genesis_block_state_root: *finalized_header.state_root,
             chain_index,
-        })
+            chain_config,
+            config.unstable_p2p_requests,
+            cpu_usages[chain_index].clone(),
+            relay_chains[chain_index].take(),
+        )
         .await;
 
-        json_rpc_services.insert(chain_index, json_rpc_service);
+        json_rpc_services.insert(
+            chain_index,
+            json_rpc_service::already_started(json_rpc_service),
+        );
     }
 
     new_task_tx
@@ -466,6 +1056,7 @@ async fn start_services(
                     move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
                 }))),
                 json_rpc_services,
+                config.json_rpc_consumer_limits,
             )
             .boxed(),
         ))
@@ -474,6 +1065,475 @@ async fn start_services(
     log::info!("Initialization complete");
 }
 
+/// Starts the syncing, header cache, and runtime services of a chain that isn't a parachain.
+///
+/// `network_service` contains the network service of the chain and the index of the chain
+/// within it.
+async fn start_standalone_chain(
+    new_task_tx: &mpsc::UnboundedSender<(
+        String,
+        Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+    )>,
+    network_service: (Arc<network_service::NetworkService>, usize),
+    network_events_receiver: mpsc::Receiver<network_service::Event>,
+    chain_information: &chain::chain_information::ValidChainInformation,
+    chain_spec: &chain_spec::ChainSpec,
+    cpu_usage: &Arc<cpu_usage::CpuUsage>,
+    sync_mode: sync_service::SyncMode,
+    quorum_size: NonZeroUsize,
+    chain_index: usize,
+    custom_consensus_engines: Vec<[u8; 4]>,
+    compilation_cache: &Arc<runtime_service::CompilationCache>,
+    max_runtime_memory_pages: Option<u32>,
+) -> (
+    Arc<sync_service::SyncService>,
+    Arc<runtime_service::RuntimeService>,
+    Arc<header_cache::HeaderCache>,
+) {
+    // The sync service is leveraging the network service, downloads block headers,
+    // and verifies them, to determine what are the best and finalized blocks of the
+    // chain.
+    let sync_service = Arc::new(
+        sync_service::SyncService::new(sync_service::Config {
+            chain_information: chain_information.clone(),
+            tasks_executor: Box::new({
+                let new_task_tx = new_task_tx.clone();
+                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+            }),
+            network_service,
+            network_events_receiver,
+            parachain: None,
+            cpu_usage: cpu_usage.clone(),
+            slot_duration: slot_duration(chain_information, chain_spec),
+            max_announce_future_drift: max_announce_future_drift(chain_spec),
+            babe_relaxed_secondary_slots: chain_spec.babe_relaxed_secondary_slots(),
+            work_queues: work_queues::Config {
+                max_in_progress: NonZeroUsize::new(16).unwrap(),
+                block_tracking_weight: NonZeroU32::new(2).unwrap(),
+                json_rpc_weight: NonZeroU32::new(1).unwrap(),
+            },
+            canonical_index_capacity: 16384,
+            max_proof_size: 8 * 1024 * 1024,
+            sync_mode,
+            quorum_size,
+            custom_consensus_engines: if custom_consensus_engines.is_empty() {
+                None
+            } else {
+                Some(sync_service::ConfigCustomConsensusEngines {
+                    engines: custom_consensus_engines.clone(),
+                    verifier: Box::new(move |items| {
+                        Host::verify_custom_digest_items(chain_index, items)
+                    }),
+                })
+            },
+        })
+        .await,
+    );
+
+    // The header cache holds the recent headers of the chain, and is shared between the
+    // services of this chain.
+    let header_cache = header_cache::start(header_cache::Config {
+        tasks_executor: Box::new({
+            let new_task_tx = new_task_tx.clone();
+            move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+        }),
+        sync_service: sync_service.clone(),
+        capacity: 256,
+        fork_blocks_window: 64,
+        custom_consensus_engines,
+    })
+    .await;
+
+    // The runtime service follows the runtime of the best block of the chain,
+    // and allows performing runtime calls.
+    let runtime_service = runtime_service::RuntimeService::new(runtime_service::Config {
+        tasks_executor: Box::new({
+            let new_task_tx = new_task_tx.clone();
+            move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+        }),
+        data_provider: sync_service.clone(),
+        header_cache: header_cache.clone(),
+        chain_spec,
+        genesis_block_hash: None,
+        genesis_block_state_root: None,
+        compilation_cache: compilation_cache.clone(),
+        max_runtime_memory_pages,
+        best_block_debounce: Duration::from_millis(500),
+        max_notifications_per_second: None,
+        cpu_usage: cpu_usage.clone(),
+        max_failed_call_recordings: 8,
+    })
+    .await;
+
+    (sync_service, runtime_service, header_cache)
+}
+
+/// Starts the transactions and JSON-RPC services of a chain, on top of its other services.
+///
+/// `network_service` contains the network service of the chain and the index of the chain
+/// within it, while `chain_index` is the index of the chain within the list of chains of the
+/// client. `relay_chain` must be `Some` if the chain is a parachain.
+async fn start_json_rpc_service(
+    new_task_tx: &mpsc::UnboundedSender<(
+        String,
+        Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+    )>,
+    network_service: (Arc<network_service::NetworkService>, usize),
+    (sync_service, runtime_service, header_cache): (
+        Arc<sync_service::SyncService>,
+        Arc<runtime_service::RuntimeService>,
+        Arc<header_cache::HeaderCache>,
+    ),
+    genesis_chain_information: &chain::chain_information::ValidChainInformation,
+    chain_spec: chain_spec::ChainSpec,
+    chain_index: usize,
+    chain_config: ChainConfig,
+    unstable_p2p_requests: bool,
+    cpu_usage: Arc<cpu_usage::CpuUsage>,
+    relay_chain: Option<json_rpc_service::ConfigRelayChain>,
+) -> Arc<json_rpc_service::JsonRpcService> {
+    let finalized_header = genesis_chain_information.as_ref().finalized_block_header;
+    let transactions_service = Arc::new(
+        transactions_service::TransactionsService::new(transactions_service::Config {
+            tasks_executor: Box::new({
+                let new_task_tx = new_task_tx.clone();
+                move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+            }),
+            network_service: network_service.clone(),
+            sync_service: sync_service.clone(),
+            runtime_service: runtime_service.clone(),
+            validate_locally: chain_config.json_rpc_validate_transactions,
+            chain_properties: chain_spec.known_properties(),
+        })
+        .await,
+    );
+
+    json_rpc_service::start(json_rpc_service::Config {
+        tasks_executor: Box::new({
+            let new_task_tx = new_task_tx.clone();
+            move |name, fut| new_task_tx.unbounded_send((name, fut)).unwrap()
+        }),
+        network_service,
+        sync_service,
+        transactions_service,
+        runtime_service,
+        header_cache,
+        chain_spec,
+        genesis_block_hash: finalized_header.hash(),
+        genesis_block_state_root: *finalized_header.state_root,
+        chain_index,
+        methods_filter: chain_config.json_rpc_methods_filter,
+        unstable_p2p_requests,
+        cpu_usage,
+        relay_chain,
+        cross_validation: chain_config.json_rpc_cross_validation,
+        rpc_fallback: chain_config.json_rpc_fallback,
+        storage_prefetch_keys: chain_config.json_rpc_storage_prefetch_keys,
+    })
+    .await
+}
+
+/// Returns, for each chain, whether its services are started on the first JSON-RPC request
+/// rather than at initialization.
+///
+/// `chains` contains the specification of each chain, and whether a lazy start has been
+/// requested for it and its JSON-RPC service is running. Parachains and relay chains of other
+/// chains are always started immediately, as parachains depend on the services of their relay
+/// chain.
+fn lazy_chains(chains: &[(&chain_spec::ChainSpec, bool)]) -> Vec<bool> {
+    chains
+        .iter()
+        .map(|(chain_spec, lazy)| {
+            *lazy
+                && chain_spec.relay_chain().is_none()
+                && !chains.iter().any(|(spec, _)| {
+                    matches!(spec.relay_chain(), Some((relay, _)) if relay == chain_spec.id())
+                })
+        })
+        .collect()
+}
+
+/// Spawns a task that discards the events received on the given network events receiver, in
+/// order to not block the network service, except while they are forwarded to a receiver
+/// retrieved through the returned sender.
+///
+/// Events are forwarded to the most recently retrieved receiver until it is destroyed, which
+/// happens when the services of a lazy chain are suspended. Since the sync service needs to know
+/// which peers are connected, the `Connected` events of the peers that are still connected are
+/// replayed on each retrieved receiver.
+fn drain_network_events(
+    new_task_tx: &mpsc::UnboundedSender<(
+        String,
+        Pin<Box<dyn Future<Output = ()> + Send + 'static>>,
+    )>,
+    mut receiver: mpsc::Receiver<network_service::Event>,
+) -> NetworkEventsRetriever {
+    let (start_tx, mut start_rx) =
+        mpsc::unbounded::<oneshot::Sender<mpsc::Receiver<network_service::Event>>>();
+
+    new_task_tx
+        .unbounded_send((
+            "lazy-chain-network-events".into(),
+            Box::pin(async move {
+                let mut connected = HashMap::new();
+                let mut forward_tx = None::<mpsc::Sender<network_service::Event>>;
+
+                loop {
+                    let event = match future::select(receiver.next(), start_rx.next()).await {
+                        future::Either::Left((Some(event), _)) => event,
+                        future::Either::Left((None, _)) => return,
+                        future::Either::Right((Some(send_back), _)) => {
+                            let (mut tx, rx) = mpsc::channel(16);
+                            if send_back.send(rx).is_err() {
+                                continue;
+                            }
+
+                            for event in connected.values() {
+                                if tx.send(Clone::clone(event)).await.is_err() {
+                                    break;
+                                }
+                            }
+                            forward_tx = Some(tx);
+                            continue;
+                        }
+                        future::Either::Right((None, _)) => return,
+                    };
+
+                    match event {
+                        network_service::Event::Connected { ref peer_id, .. } => {
+                            connected.insert(peer_id.clone(), event.clone());
+                        }
+                        network_service::Event::Disconnected { ref peer_id, .. } => {
+                            connected.remove(peer_id);
+                        }
+                        _ => {}
+                    }
+
+                    if let Some(tx) = &mut forward_tx {
+                        if tx.send(event).await.is_err() {
+                            forward_tx = None;
+                        }
+                    }
+                }
+            }),
+        ))
+        .unwrap();
+
+    start_tx
+}
+
+/// Sender of the tasks to spawn, alongside with their name.
+type TasksSender =
+    mpsc::UnboundedSender<(String, Pin<Box<dyn Future<Output = ()> + Send + 'static>>)>;
+
+/// Used to retrieve a receiver of the network events of a chain. See [`drain_network_events`].
+type NetworkEventsRetriever =
+    mpsc::UnboundedSender<oneshot::Sender<mpsc::Receiver<network_service::Event>>>;
+
+/// Chain whose services are started when the first JSON-RPC request targeting it arrives, and
+/// suspended after it has been idle. See [`ChainConfig::lazy`] and
+/// [`ChainConfig::lazy_idle_timeout`].
+struct LazyChain {
+    new_task_tx: TasksSender,
+    /// Network service of the chain and index of the chain within it.
+    network_service: (Arc<network_service::NetworkService>, usize),
+    /// Used to retrieve a receiver of the network events of the chain. See
+    /// [`drain_network_events`].
+    network_events: NetworkEventsRetriever,
+    cpu_usage: Arc<cpu_usage::CpuUsage>,
+    compilation_cache: Arc<runtime_service::CompilationCache>,
+    unstable_p2p_requests: bool,
+    chain_index: usize,
+    chain_name: String,
+    config: ChainConfig,
+    genesis_chain_information: chain::chain_information::ValidChainInformation,
+    state: Mutex<LazyChainState>,
+}
+
+/// See [`LazyChain::state`].
+struct LazyChainState {
+    /// Information about the chain at which to start syncing it. Updated when the services of
+    /// the chain are suspended.
+    chain_information: chain::chain_information::ValidChainInformation,
+    /// If the services of the chain are running, their sync service and a sender whose
+    /// destruction stops all their tasks. See [`stoppable_tasks`].
+    running: Option<(Arc<sync_service::SyncService>, oneshot::Sender<()>)>,
+}
+
+impl LazyChain {
+    /// Starts the services of the chain, from the information stored in
+    /// [`LazyChainState::chain_information`].
+    async fn start(self: Arc<Self>) -> Arc<json_rpc_service::JsonRpcService> {
+        let mut state = self.state.lock().await;
+        debug_assert!(state.running.is_none());
+
+        log::info!(
+            "Starting services of chain {} after a JSON-RPC request",
+            self.chain_name
+        );
+
+        // The chain specification has been successfully parsed at initialization.
+        let chain_spec =
+            chain_spec::ChainSpec::from_json_bytes(&self.config.specification).unwrap();
+
+        self.network_service.0.resume_chain(self.network_service.1);
+
+        let (send_back, network_events_receiver) = oneshot::channel();
+        self.network_events.unbounded_send(send_back).unwrap();
+        let network_events_receiver = network_events_receiver.await.unwrap();
+
+        let (stop_tx, chain_task_tx) = stoppable_tasks(&self.new_task_tx);
+
+        let services = start_standalone_chain(
+            &chain_task_tx,
+            self.network_service.clone(),
+            network_events_receiver,
+            &state.chain_information,
+            &chain_spec,
+            &self.cpu_usage,
+            self.config.sync_mode,
+            self.config.quorum_size,
+            self.chain_index,
+            self.config.custom_consensus_engines.clone(),
+            &self.compilation_cache,
+            self.config.max_runtime_memory_pages,
+        )
+        .await;
+
+        state.running = Some((services.0.clone(), stop_tx));
+
+        start_json_rpc_service(
+            &chain_task_tx,
+            self.network_service.clone(),
+            services,
+            &self.genesis_chain_information,
+            chain_spec,
+            self.chain_index,
+            self.config.clone(),
+            self.unstable_p2p_requests,
+            self.cpu_usage.clone(),
+            None,
+        )
+        .await
+    }
+
+    /// Stops the services of the chain, after having saved the information about its latest
+    /// finalized block in [`LazyChainState::chain_information`].
+    async fn suspend(self: Arc<Self>) {
+        let mut state = self.state.lock().await;
+        let (sync_service, stop_tx) = match state.running.take() {
+            Some(running) => running,
+            None => return,
+        };
+
+        if let Some(serialized) = sync_service.serialize_chain_information().await {
+            match smoldot::database::finalized_serialize::decode_chain(&serialized) {
+                Ok((chain_information, _, _)) => state.chain_information = chain_information,
+                Err(error) => log::warn!(
+                    "Failed to save the state of chain {} before suspending it: {}",
+                    self.chain_name,
+                    error
+                ),
+            }
+        }
+
+        drop(stop_tx);
+        self.network_service.0.pause_chain(self.network_service.1);
+
+        log::info!(
+            "Suspended services of chain {} at #{} after a period without JSON-RPC request",
+            self.chain_name,
+            state
+                .chain_information
+                .as_ref()
+                .finalized_block_header
+                .number
+        );
+    }
+}
+
+/// Returns a sender of tasks that are spawned through `new_task_tx` and that are all stopped
+/// when the returned [`oneshot::Sender`] is destroyed.
+fn stoppable_tasks(new_task_tx: &TasksSender) -> (oneshot::Sender<()>, TasksSender) {
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let stop_rx = stop_rx.shared();
+    let (tasks_tx, mut tasks_rx) = mpsc::unbounded();
+
+    new_task_tx
+        .unbounded_send((
+            "stoppable-tasks".into(),
+            Box::pin({
+                let new_task_tx = new_task_tx.clone();
+                async move {
+                    loop {
+                        let (name, task) =
+                            match future::select(tasks_rx.next(), stop_rx.clone()).await {
+                                future::Either::Left((Some(task), _)) => task,
+                                future::Either::Left((None, _)) => return,
+                                future::Either::Right(_) => return,
+                            };
+
+                        let stop_rx = stop_rx.clone();
+                        new_task_tx
+                            .unbounded_send((
+                                name,
+                                Box::pin(async move {
+                                    future::select(task, stop_rx).await;
+                                }),
+                            ))
+                            .unwrap();
+                    }
+                }
+            }),
+        ))
+        .unwrap();
+
+    (stop_tx, tasks_tx)
+}
+
+/// Returns the duration, in milliseconds, of a slot of the consensus engine of the given chain,
+/// or `None` if it doesn't use slots or if the duration is unknown.
+///
+/// The duration indicated in the chain specification, if any, takes precedence. Otherwise, it is
+/// extracted from the chain information, which only contains it for Aura chains.
+fn slot_duration(
+    chain_information: &chain::chain_information::ValidChainInformation,
+    chain_spec: &chain_spec::ChainSpec,
+) -> Option<NonZeroU64> {
+    if let Some(slot_duration) = chain_spec.slot_duration() {
+        return Some(slot_duration);
+    }
+
+    match chain_information.as_ref().consensus {
+        chain::chain_information::ChainInformationConsensusRef::Aura { slot_duration, .. } => {
+            Some(slot_duration)
+        }
+        chain::chain_information::ChainInformationConsensusRef::AllAuthorized
+        | chain::chain_information::ChainInformationConsensusRef::Babe { .. } => None,
+    }
+}
+
+/// Returns the maximum amount of time by which the slot of a block announce of the given chain
+/// is allowed to be in the future. See [`sync_service::Config::max_announce_future_drift`].
+fn max_announce_future_drift(chain_spec: &chain_spec::ChainSpec) -> Duration {
+    chain_spec
+        .max_announce_future_drift()
+        .unwrap_or(Duration::from_secs(30))
+}
+
+/// Sends the given peer event to the embedder. See [`platform::Platform::emit_peer_event`].
+///
+/// `network_chains` contains, for each chain of the network service that has generated the
+/// event, the index of this chain within the list of chains of the client.
+fn report_peer_event(
+    event: network_service::PeerEvent,
+    network_chains: &[usize],
+    version: events::Version,
+) {
+    let (json, chain_index) = events::peer_event_json(&event, version);
+    Host::emit_peer_event(&json.to_string(), network_chains[chain_index]);
+}
+
 /// Use in an asynchronous context to interrupt the current task execution and schedule it back.
 ///
 /// This function is useful in order to guarantee a fine granularity of tasks execution time in
@@ -491,3 +1551,137 @@ async fn yield_once() {
     })
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{drain_network_events, lazy_chains, network_service, stoppable_tasks};
+    use futures::{
+        channel::{mpsc, oneshot},
+        executor, future,
+        prelude::*,
+    };
+    use smoldot::{
+        chain_spec,
+        libp2p::{peer_id, PeerId},
+    };
+
+    #[test]
+    fn lazy_chains_exclude_parachains_and_relay_chains() {
+        let chain_specs = [
+            &include_bytes!("../../../westend.json")[..],
+            &include_bytes!("../../../westend-westmint.json")[..],
+            &include_bytes!("../../../polkadot.json")[..],
+            &include_bytes!("../../../kusama.json")[..],
+        ]
+        .iter()
+        .map(|spec| chain_spec::ChainSpec::from_json_bytes(spec).unwrap())
+        .collect::<Vec<_>>();
+
+        // Westend is the relay chain of Westmint, and Kusama doesn't run JSON-RPC.
+        let lazy = [true, true, true, false];
+        assert_eq!(
+            lazy_chains(&chain_specs.iter().zip(lazy).collect::<Vec<_>>()),
+            [false, false, true, false]
+        );
+        assert_eq!(
+            lazy_chains(&chain_specs.iter().map(|s| (s, false)).collect::<Vec<_>>()),
+            [false; 4]
+        );
+    }
+
+    #[test]
+    fn drained_network_events_replay_connected_peers() {
+        let peer = |n: u8| PeerId::from_public_key(&peer_id::PublicKey::Ed25519([n; 32]));
+        let connected = |n: u8| network_service::Event::Connected {
+            peer_id: peer(n),
+            chain_index: 0,
+            best_block_number: u64::from(n),
+            best_block_hash: [n; 32],
+        };
+        let disconnected = |n: u8| network_service::Event::Disconnected {
+            peer_id: peer(n),
+            chain_index: 0,
+        };
+
+        let (new_task_tx, mut new_task_rx) = mpsc::unbounded();
+        let (mut events_tx, events_rx) = mpsc::channel(16);
+        let start = drain_network_events(&new_task_tx, events_rx);
+        let (_, task) = new_task_rx.try_next().unwrap().unwrap();
+
+        let test = async move {
+            events_tx.send(connected(1)).await.unwrap();
+            events_tx.send(connected(2)).await.unwrap();
+            events_tx.send(disconnected(1)).await.unwrap();
+
+            let (send_back, receiver) = oneshot::channel();
+            start.unbounded_send(send_back).unwrap();
+            let mut receiver = receiver.await.unwrap();
+
+            // Only the peer that is still connected is replayed.
+            match receiver.next().await.unwrap() {
+                network_service::Event::Connected {
+                    peer_id,
+                    best_block_number,
+                    ..
+                } => {
+                    assert_eq!(peer_id, peer(2));
+                    assert_eq!(best_block_number, 2);
+                }
+                _ => panic!(),
+            }
+
+            // Events received afterwards are forwarded as they are.
+            events_tx.send(disconnected(2)).await.unwrap();
+            match receiver.next().await.unwrap() {
+                network_service::Event::Disconnected { peer_id, .. } => {
+                    assert_eq!(peer_id, peer(2))
+                }
+                _ => panic!(),
+            }
+            assert!(receiver.next().now_or_never().is_none());
+
+            // Once the receiver is destroyed, as happens when a lazy chain is suspended, events
+            // are drained again, and the peers still connected are replayed on the next receiver.
+            drop(receiver);
+            events_tx.send(connected(3)).await.unwrap();
+            let (send_back, receiver) = oneshot::channel();
+            start.unbounded_send(send_back).unwrap();
+            let mut receiver = receiver.await.unwrap();
+            match receiver.next().await.unwrap() {
+                network_service::Event::Connected { peer_id, .. } => assert_eq!(peer_id, peer(3)),
+                _ => panic!(),
+            }
+            assert!(receiver.next().now_or_never().is_none());
+        };
+
+        // The task only ends if its receiver is closed, which can't happen before the test ends.
+        let outcome = executor::block_on(future::select(task, Box::pin(test)));
+        assert!(matches!(outcome, future::Either::Right(_)));
+    }
+
+    #[test]
+    fn stoppable_tasks_stopped_together() {
+        let (new_task_tx, mut new_task_rx) = mpsc::unbounded();
+        let (stop_tx, tasks_tx) = stoppable_tasks(&new_task_tx);
+
+        let (_unused_tx, pending) = oneshot::channel::<()>();
+        tasks_tx
+            .unbounded_send((
+                "pending".into(),
+                Box::pin(async move {
+                    let _ = pending.await;
+                }),
+            ))
+            .unwrap();
+
+        // Tasks are spawned through the forwarding task.
+        let (_, mut forwarding) = new_task_rx.try_next().unwrap().unwrap();
+        assert!((&mut forwarding).now_or_never().is_none());
+        let (_, mut task) = new_task_rx.try_next().unwrap().unwrap();
+        assert!((&mut task).now_or_never().is_none());
+
+        drop(stop_tx);
+        assert!(forwarding.now_or_never().is_some());
+        assert!(task.now_or_never().is_some());
+    }
+}
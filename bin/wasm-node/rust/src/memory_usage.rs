@@ -0,0 +1,292 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Accounting of the memory allocated by the client.
+//!
+//! All the allocations of the client go through a [`TrackingAllocator`], installed as the global
+//! allocator, which keeps track of the number of bytes currently allocated, of the highest value
+//! ever reached, and of the number of allocations that have failed. See [`snapshot`].
+//!
+//! The highest value is also tracked per [`cpu_usage::Category`]: while a measurement started
+//! with [`cpu_usage::CpuUsage::measure`] is in progress, the peaks reached are attributed to the
+//! category of the measurement. This makes it possible to find out which kind of operation is
+//! responsible for the growth of the memory.
+//!
+//! The Wasm linear memory can grow but never shrinks. Allocation failures typically happen once
+//! it has reached the limit set by the environment, in which case the default behaviour of
+//! aborting the entire client isn't desirable. The large buffers whose size is controlled by
+//! other nodes or by the runtime are instead allocated with [`try_to_vec`], which returns an
+//! error if the allocation fails. The smoldot library similarly allocates fallibly the responses
+//! received from the network, the decompressed runtime code, and the memory of the runtime
+//! virtual machine.
+
+use crate::cpu_usage;
+
+use core::sync::atomic;
+use std::alloc::{GlobalAlloc, Layout, System};
+
+// Use the default "system" allocator. In the context of Wasm, this uses the `dlmalloc` library.
+// See <https://github.com/rust-lang/rust/tree/1.47.0/library/std/src/sys/wasm>.
+//
+// While the `wee_alloc` crate is usually the recommended choice in WebAssembly, testing has shown
+// that using it makes memory usage explode from ~100MiB to ~2GiB and more (the environment then
+// refuses to allocate 4GiB).
+#[global_allocator]
+static ALLOC: TrackingAllocator<System> = TrackingAllocator::new(System);
+
+/// Returns the current state of the memory of the client.
+pub fn snapshot() -> Snapshot {
+    ALLOC.tracker.snapshot()
+}
+
+/// Attributes the memory allocated until the returned object is destroyed to the given
+/// category.
+///
+/// Must not be held across `await` points, otherwise the allocations of the other tasks running
+/// in the meanwhile are attributed to this category as well.
+pub(crate) fn enter(category: cpu_usage::Category) -> Scope<'static> {
+    ALLOC.tracker.enter(category)
+}
+
+/// Copies the given data into a new `Vec`, or returns an error if the allocation fails.
+pub fn try_to_vec(data: &[u8]) -> Result<Vec<u8>, AllocError> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(data.len())
+        .map_err(|_| AllocError { size: data.len() })?;
+    vec.extend_from_slice(data);
+    Ok(vec)
+}
+
+/// Error potentially returned by [`try_to_vec`].
+#[derive(Debug, Clone, derive_more::Display)]
+#[display(fmt = "Failed to allocate {} bytes", size)]
+pub struct AllocError {
+    /// Number of bytes that couldn't be allocated.
+    pub size: usize,
+}
+
+/// State of the memory of the client. See [`snapshot`].
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    /// Number of bytes currently allocated.
+    pub allocated: usize,
+    /// Highest value ever reached by [`Snapshot::allocated`].
+    pub peak: usize,
+    /// For each [`cpu_usage::Category`], highest value reached by [`Snapshot::allocated`] while
+    /// a measurement of this category was in progress. Indexed by [`cpu_usage::Category::index`].
+    pub category_peaks: [usize; cpu_usage::Category::NUM],
+    /// Number of allocations that the underlying allocator has failed to perform.
+    pub failed_allocations: u64,
+    /// Size in bytes of the Wasm linear memory, or `None` if not running in Wasm.
+    pub linear_memory: Option<usize>,
+}
+
+/// Global allocator that forwards the allocations to another allocator and keeps track of them.
+pub struct TrackingAllocator<A> {
+    inner: A,
+    tracker: Tracker,
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Wraps around the given allocator.
+    pub const fn new(inner: A) -> Self {
+        TrackingAllocator {
+            inner,
+            tracker: Tracker::new(),
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for TrackingAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        self.tracker.on_alloc(!ptr.is_null(), layout.size());
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        self.tracker.on_alloc(!ptr.is_null(), layout.size());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout);
+        self.tracker.on_dealloc(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            self.tracker.on_dealloc(layout.size());
+        }
+        self.tracker.on_alloc(!new_ptr.is_null(), new_size);
+        new_ptr
+    }
+}
+
+/// Counters of a [`TrackingAllocator`].
+struct Tracker {
+    /// See [`Snapshot::allocated`].
+    allocated: atomic::AtomicUsize,
+    /// See [`Snapshot::peak`].
+    peak: atomic::AtomicUsize,
+    /// See [`Snapshot::category_peaks`].
+    category_peaks: [atomic::AtomicUsize; cpu_usage::Category::NUM],
+    /// See [`Snapshot::failed_allocations`].
+    failed_allocations: atomic::AtomicU64,
+    /// One plus the [`cpu_usage::Category::index`] of the category the allocations are
+    /// currently attributed to, or 0 if none.
+    current_category: atomic::AtomicUsize,
+}
+
+impl Tracker {
+    const fn new() -> Self {
+        // Can't use `Default` in a `const fn`.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: atomic::AtomicUsize = atomic::AtomicUsize::new(0);
+
+        Tracker {
+            allocated: atomic::AtomicUsize::new(0),
+            peak: atomic::AtomicUsize::new(0),
+            category_peaks: [ZERO; cpu_usage::Category::NUM],
+            failed_allocations: atomic::AtomicU64::new(0),
+            current_category: atomic::AtomicUsize::new(0),
+        }
+    }
+
+    fn on_alloc(&self, success: bool, size: usize) {
+        if !success {
+            self.failed_allocations
+                .fetch_add(1, atomic::Ordering::Relaxed);
+            return;
+        }
+
+        let total = self.allocated.fetch_add(size, atomic::Ordering::Relaxed) + size;
+        self.peak.fetch_max(total, atomic::Ordering::Relaxed);
+        if let Some(index) = self
+            .current_category
+            .load(atomic::Ordering::Relaxed)
+            .checked_sub(1)
+        {
+            self.category_peaks[index].fetch_max(total, atomic::Ordering::Relaxed);
+        }
+    }
+
+    fn on_dealloc(&self, size: usize) {
+        self.allocated.fetch_sub(size, atomic::Ordering::Relaxed);
+    }
+
+    fn enter(&self, category: cpu_usage::Category) -> Scope<'_> {
+        let previous = self
+            .current_category
+            .swap(category.index() + 1, atomic::Ordering::Relaxed);
+        Scope {
+            tracker: self,
+            previous,
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let mut category_peaks = [0; cpu_usage::Category::NUM];
+        for (peak, counter) in category_peaks.iter_mut().zip(&self.category_peaks) {
+            *peak = counter.load(atomic::Ordering::Relaxed);
+        }
+
+        Snapshot {
+            allocated: self.allocated.load(atomic::Ordering::Relaxed),
+            peak: self.peak.load(atomic::Ordering::Relaxed),
+            category_peaks,
+            failed_allocations: self.failed_allocations.load(atomic::Ordering::Relaxed),
+            linear_memory: linear_memory_size(),
+        }
+    }
+}
+
+/// Attribution of the allocations in progress. See [`enter`].
+#[must_use]
+pub(crate) struct Scope<'a> {
+    tracker: &'a Tracker,
+    /// Value of [`Tracker::current_category`] to restore when the scope ends.
+    previous: usize,
+}
+
+impl<'a> Drop for Scope<'a> {
+    fn drop(&mut self) {
+        self.tracker
+            .current_category
+            .store(self.previous, atomic::Ordering::Relaxed);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn linear_memory_size() -> Option<usize> {
+    Some(core::arch::wasm32::memory_size::<0>() * 65536)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn linear_memory_size() -> Option<usize> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{try_to_vec, Tracker};
+    use crate::cpu_usage::Category;
+
+    #[test]
+    fn peaks_per_category() {
+        let tracker = Tracker::new();
+
+        tracker.on_alloc(true, 100);
+        {
+            let _outer = tracker.enter(Category::RuntimeExecution);
+            tracker.on_alloc(true, 50);
+            {
+                let _inner = tracker.enter(Category::ProofVerification);
+                tracker.on_alloc(true, 20);
+                tracker.on_dealloc(20);
+            }
+            tracker.on_alloc(true, 10);
+            tracker.on_dealloc(60);
+        }
+        tracker.on_alloc(false, 1000);
+        tracker.on_alloc(true, 5);
+
+        let snapshot = tracker.snapshot();
+        assert_eq!(snapshot.allocated, 105);
+        assert_eq!(snapshot.peak, 170);
+        assert_eq!(
+            snapshot.category_peaks[Category::RuntimeExecution.index()],
+            160
+        );
+        assert_eq!(
+            snapshot.category_peaks[Category::ProofVerification.index()],
+            170
+        );
+        assert_eq!(
+            snapshot.category_peaks[Category::HeaderVerification.index()],
+            0
+        );
+        assert_eq!(snapshot.failed_allocations, 1);
+    }
+
+    #[test]
+    fn try_to_vec_copies() {
+        assert_eq!(try_to_vec(b"hello").unwrap(), b"hello".to_vec());
+    }
+}
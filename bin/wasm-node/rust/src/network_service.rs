@@ -35,22 +35,35 @@
 //! An important part of the API is the list of channel receivers of [`Event`] returned by
 //! [`NetworkService::new`]. These channels inform the foreground about updates to the network
 //! connectivity.
+//!
+//! Additionally, [`NetworkService::subscribe_peer_events`] makes it possible to be notified of
+//! [`PeerEvent`]s, which describe the list of peers of each chain in a structured way and are
+//! meant to be shown to the user.
 
-use crate::ffi;
+use crate::platform::{self, Host, Platform as _};
 
 use core::{cmp, fmt, num::NonZeroUsize, pin::Pin, time::Duration};
 use futures::{channel::mpsc, lock::Mutex, prelude::*};
 use smoldot::{
     informant::HashDisplay,
     libp2p::{
+        self,
         connection::{self, handshake::HandshakeError},
         multiaddr::Multiaddr,
-        peer_id::PeerId,
+        peer_id::{self, PeerId},
         ConnectionError,
     },
     network::{protocol, service},
 };
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+pub use service::ExtraRequestResponseProtocol;
 
 /// Configuration for a [`NetworkService`].
 pub struct Config {
@@ -62,6 +75,95 @@ pub struct Config {
 
     /// List of chains to connect to. Chains are later referred to by their index in this list.
     pub chains: Vec<ConfigChain>,
+
+    /// If true, block and storage proof requests indicate to the remote that the response can be
+    /// compressed. See [`NetworkService::request_compressed_responses`].
+    pub request_compressed_responses: bool,
+
+    /// Strategy used when opening outgoing connections.
+    pub dial_strategy: DialStrategy,
+
+    /// Maximum amount of time an outgoing connection attempt is allowed to take before being
+    /// considered as failed.
+    pub dial_timeout: Duration,
+
+    /// Limits applied to the connections opened with other nodes. Relayed connections go
+    /// through the connection with the relay, to which these limits apply.
+    ///
+    /// Since libp2p connections are streams of bytes, setting
+    /// [`platform::ConnectionLimits::max_outbound_message_size`] is always correct.
+    pub connection_limits: platform::ConnectionLimits,
+
+    /// Number of peers that the service tries to be connected to, per chain. No new outgoing
+    /// connection is opened once this number has been reached.
+    pub peers_target: usize,
+
+    /// If true, nodes whose address goes through a relay (i.e. contains `/p2p-circuit`) are
+    /// connected to by first connecting to the relay, then asking the relay to open a circuit to
+    /// them. This makes it possible to reach nodes that aren't directly reachable, for example
+    /// because they are behind a NAT.
+    ///
+    /// The relays of the bootstrap nodes are automatically added to the known nodes. Nodes
+    /// discovered through Kademlia can only be reached if their relay is known as well.
+    pub allow_relayed_connections: bool,
+
+    /// Settings that reduce the amount of information that other nodes can learn about the
+    /// local node.
+    pub privacy: PrivacyConfig,
+
+    /// Ed25519 private key that determines the [`PeerId`] of the local node. If `None`, a new
+    /// random key is generated, and the local node thus has a different [`PeerId`] every time.
+    ///
+    /// Passing a key that has been persisted by the user makes it possible for other nodes to
+    /// recognize the local node across restarts, for example because they have it configured as
+    /// a reserved peer.
+    pub network_key: Option<[u8; 32]>,
+
+    /// Additional request-response protocols that requests can be sent on with
+    /// [`NetworkService::raw_request`]. See
+    /// [`service::Config::extra_request_response_protocols`].
+    pub extra_request_response_protocols: Vec<ExtraRequestResponseProtocol>,
+}
+
+/// See [`Config::privacy`].
+///
+/// Note that, unless [`Config::network_key`] is `Some`, a new [`PeerId`] is generated every time
+/// the [`NetworkService`] is created.
+#[derive(Debug, Clone, Default)]
+pub struct PrivacyConfig {
+    /// If true, identify requests sent by other nodes are refused. Answering them would reveal
+    /// the name of the client and the list of protocols it supports, and thus the list of chains
+    /// it is connected to.
+    pub refuse_identify: bool,
+
+    /// If true, the block announces handshakes sent to other nodes report the genesis block as
+    /// the best block, instead of the block the client has started from. This block would
+    /// otherwise make it possible to recognize the client across restarts.
+    pub hide_best_block: bool,
+
+    /// If `Some`, a new [`PeerId`] is generated at the given interval and used for connections
+    /// opened afterwards. Existing connections keep using the previous [`PeerId`].
+    pub peer_id_rotation_interval: Option<Duration>,
+
+    /// Requests sent to other nodes are delayed by a random duration between zero and this value,
+    /// in order to make it harder to correlate them with the activity of the user.
+    /// `Duration::new(0, 0)` for no delay.
+    pub max_request_jitter: Duration,
+}
+
+/// See [`Config::dial_strategy`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DialStrategy {
+    /// Dial as many nodes as possible at the same time, up to [`Config::peers_target`]. Reaches
+    /// a responsive peer quickly if many of the known nodes are unreachable, at the cost of
+    /// opening more connections.
+    Parallel,
+    /// Start dialing one node at a time, waiting `delay` between two dials. Dials that are still
+    /// in progress are not interrupted.
+    Staggered {
+        /// Amount of time to wait after starting a dial before starting the next one.
+        delay: Duration,
+    },
 }
 
 /// See [`Config::chains`].
@@ -85,6 +187,11 @@ pub struct ConfigChain {
 
     /// If true, the chain uses the GrandPa networking protocol.
     pub has_grandpa_protocol: bool,
+
+    /// If true, no connection is opened and no discovery is performed on behalf of this chain
+    /// until [`NetworkService::resume_chain`] is called. Connections opened on behalf of other
+    /// chains can still be used by this chain. See also [`NetworkService::pause_chain`].
+    pub paused: bool,
 }
 
 pub struct NetworkService {
@@ -92,17 +199,66 @@ pub struct NetworkService {
     guarded: Mutex<Guarded>,
 
     /// Data structure holding the entire state of the networking.
-    network: service::ChainNetwork<ffi::Instant, (), ()>,
+    network: service::ChainNetwork<platform::Instant, (), ()>,
 
     /// List of nodes that are considered as important for logging purposes.
     // TODO: should also detect whenever we fail to open a block announces substream with any of these peers
     important_nodes: HashSet<PeerId, fnv::FnvBuildHasher>,
+
+    /// For each chain, the peer ids of its bootstrap nodes.
+    chains_bootstrap_nodes: Vec<HashSet<PeerId, fnv::FnvBuildHasher>>,
+
+    /// For each chain, the number of distinct [`PeerOrigin`]s that the peers of the chain must
+    /// be spread over. See [`PeerDiversity::required_origins`].
+    chains_required_origins: Vec<usize>,
+
+    /// For each chain, whether it is paused. See [`ConfigChain::paused`].
+    chains_paused: Vec<AtomicBool>,
+
+    /// See [`Config::request_compressed_responses`].
+    request_compressed_responses: bool,
+
+    /// See [`PrivacyConfig::refuse_identify`].
+    refuse_identify: bool,
+
+    /// See [`PrivacyConfig::max_request_jitter`].
+    max_request_jitter: Duration,
 }
 
 /// Fields of [`NetworkService`] behind a mutex.
 struct Guarded {
     /// See [`Config::tasks_executor`].
     tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
+
+    /// List of peers that are connected to each chain, indexed by peer and chain index.
+    peers: HashMap<(PeerId, usize), PeerInfo>,
+
+    /// List of peers we have a connection with, and their answer to the identify request sent
+    /// after connecting. Contains `None` if the peer hasn't answered yet.
+    identify_responses: HashMap<PeerId, Option<protocol::DecodedIdentifyResponse>>,
+
+    /// Origin of the address of each peer we have a connection with.
+    connection_origins: HashMap<PeerId, PeerOrigin>,
+
+    /// Senders of the channels returned by [`NetworkService::subscribe_peer_events`].
+    peer_events_senders: Vec<mpsc::Sender<PeerEvent>>,
+
+    /// For each chain, the peers that have reported a genesis block hash different from ours.
+    genesis_mismatches: Vec<GenesisMismatches>,
+}
+
+/// Peers of a chain whose genesis block hash is different from ours. See
+/// [`PeerEvent::GenesisMismatch`].
+#[derive(Default)]
+struct GenesisMismatches {
+    /// Number of peers that have reported a mismatching genesis block hash.
+    num_mismatches: u64,
+
+    /// Bootstrap nodes of the chain that have reported a mismatching genesis block hash.
+    bootstrap_nodes: HashSet<PeerId, fnv::FnvBuildHasher>,
+
+    /// True if [`PeerEvent::ChainSpecMismatch`] has been reported for this chain.
+    chain_spec_mismatch_reported: bool,
 }
 
 impl NetworkService {
@@ -116,11 +272,43 @@ impl NetworkService {
             .map(|_| mpsc::channel(16))
             .unzip();
 
-        let important_nodes = config
+        let chains_bootstrap_nodes = config
+            .chains
+            .iter()
+            .map(|chain| {
+                chain
+                    .bootstrap_nodes
+                    .iter()
+                    .map(|(peer_id, _)| peer_id.clone())
+                    .collect::<HashSet<_, _>>()
+            })
+            .collect::<Vec<_>>();
+
+        // Chains whose bootstrap nodes all share the same origin, typically local test networks,
+        // can't be expected to ever have more diverse peers.
+        let chains_required_origins = config
+            .chains
+            .iter()
+            .map(|chain| {
+                let bootstrap_origins = chain
+                    .bootstrap_nodes
+                    .iter()
+                    .map(|(_, addr)| PeerOrigin::from_multiaddr(addr))
+                    .collect::<HashSet<_>>();
+                cmp::min(bootstrap_origins.len(), MIN_PEER_ORIGINS)
+            })
+            .collect::<Vec<_>>();
+
+        let chains_paused = config
             .chains
             .iter()
-            .flat_map(|chain| chain.bootstrap_nodes.iter())
-            .map(|(peer_id, _)| peer_id.clone())
+            .map(|chain| AtomicBool::new(chain.paused))
+            .collect::<Vec<_>>();
+
+        let important_nodes = chains_bootstrap_nodes
+            .iter()
+            .flatten()
+            .cloned()
             .collect::<HashSet<_, _>>();
 
         let num_chains = config.chains.len();
@@ -146,10 +334,20 @@ impl NetworkService {
                     None
                 },
                 protocol_id: chain.protocol_id.clone(),
-                best_hash: chain.best_block.1,
-                best_number: chain.best_block.0,
+                best_hash: if config.privacy.hide_best_block {
+                    chain.genesis_block_hash
+                } else {
+                    chain.best_block.1
+                },
+                best_number: if config.privacy.hide_best_block {
+                    0
+                } else {
+                    chain.best_block.0
+                },
                 genesis_hash: chain.genesis_block_hash,
                 role: protocol::Role::Light,
+                // Light clients don't have the storage needed to answer these requests.
+                allow_inbound_light_requests: false,
             });
 
             known_nodes.extend(
@@ -160,21 +358,58 @@ impl NetworkService {
             );
         }
 
+        // The relays of the bootstrap nodes must be known in order to connect to them. They are
+        // appended at the end in order to not interfere with the indices above.
+        if config.allow_relayed_connections {
+            let relays = known_nodes
+                .iter()
+                .filter_map(|(_, _, addr)| libp2p::relay::split_relayed_address(addr))
+                .map(|(addr, peer_id)| ((), peer_id, addr))
+                .collect::<Vec<_>>();
+            known_nodes.extend(relays);
+        }
+
+        let noise_key = connection::NoiseKey::new(&config.network_key.unwrap_or_else(rand::random));
+        log::info!(
+            target: "network",
+            "Local peer id: {}",
+            PeerId::from_public_key(&peer_id::PublicKey::Ed25519(
+                *noise_key.libp2p_public_ed25519_key()
+            ))
+        );
+
         let network_service = Arc::new(NetworkService {
             guarded: Mutex::new(Guarded {
                 tasks_executor: config.tasks_executor,
+                peers: HashMap::new(),
+                identify_responses: HashMap::new(),
+                connection_origins: HashMap::new(),
+                peer_events_senders: Vec::new(),
+                genesis_mismatches: (0..num_chains).map(|_| Default::default()).collect(),
             }),
             network: service::ChainNetwork::new(service::Config {
                 chains,
                 known_nodes,
                 listen_addresses: Vec::new(), // TODO:
-                noise_key: connection::NoiseKey::new(&rand::random()),
+                noise_key,
                 // TODO: we use an abnormally large channel in order to by pass https://github.com/paritytech/smoldot/issues/615
                 // once the issue is solved, this should be restored to a smaller value, such as 16
                 pending_api_events_buffer_size: NonZeroUsize::new(2048).unwrap(),
+                max_connection_receive_buffer_size: 32 * 1024 * 1024,
                 randomness_seed: rand::random(),
+                extra_request_response_protocols: config.extra_request_response_protocols,
+                allow_relayed_connections: config.allow_relayed_connections,
+                // Platforms that don't provide decompression return `None`, in which case the
+                // responses are decompressed within the client.
+                response_decompressor: Some(Host::zstd_decompress),
             }),
             important_nodes,
+            chains_bootstrap_nodes,
+            chains_required_origins,
+            chains_paused,
+            request_compressed_responses: config.request_compressed_responses,
+            refuse_identify: config.privacy.refuse_identify,
+            max_request_jitter: config.privacy.max_request_jitter,
         });
 
         // Spawn a task pulling events from the network and transmitting them to the event senders.
@@ -196,12 +431,27 @@ impl NetworkService {
                             match network_service.network.next_event().await {
                                 service::Event::Connected(peer_id) => {
                                     log::info!(target: "network", "Connected to {}", peer_id);
+                                    network_service.identify_peer(peer_id).await;
                                 }
                                 service::Event::Disconnected {
                                     peer_id,
                                     chain_indices,
                                 } => {
                                     log::info!(target: "network", "Disconnected from {} (chains: {:?})", peer_id, chain_indices);
+                                    {
+                                        let mut guarded = network_service.guarded.lock().await;
+                                        guarded.identify_responses.remove(&peer_id);
+                                        guarded.connection_origins.remove(&peer_id);
+                                    }
+                                    for chain_index in &chain_indices {
+                                        network_service
+                                            .peer_disconnected(
+                                                &peer_id,
+                                                *chain_index,
+                                                DisconnectReason::ConnectionClosed,
+                                            )
+                                            .await;
+                                    }
                                     if !chain_indices.is_empty() {
                                         // TODO: properly implement when multiple chains
                                         if chain_indices.len() == 1 {
@@ -219,14 +469,26 @@ impl NetworkService {
                                     peer_id,
                                     announce,
                                 } => {
+                                    let decoded = announce.decode();
+                                    let header_hash = decoded.header.hash();
                                     log::debug!(
                                         target: "network",
                                         "Connection({}) => BlockAnnounce({}, {}, is_best={})",
                                         peer_id,
                                         chain_index,
-                                        HashDisplay(&announce.decode().header.hash()),
-                                        announce.decode().is_best
+                                        HashDisplay(&header_hash),
+                                        decoded.is_best
                                     );
+                                    if decoded.is_best {
+                                        network_service
+                                            .peer_best_block_update(
+                                                &peer_id,
+                                                chain_index,
+                                                decoded.header.number,
+                                                header_hash,
+                                            )
+                                            .await;
+                                    }
                                     break Event::BlockAnnounce {
                                         chain_index,
                                         peer_id,
@@ -236,9 +498,9 @@ impl NetworkService {
                                 service::Event::ChainConnected {
                                     peer_id,
                                     chain_index,
+                                    role,
                                     best_number,
                                     best_hash,
-                                    ..
                                 } => {
                                     log::debug!(
                                         target: "network",
@@ -248,6 +510,17 @@ impl NetworkService {
                                         best_number,
                                         HashDisplay(&best_hash)
                                     );
+                                    network_service
+                                        .peer_connected(PeerInfo {
+                                            peer_id: peer_id.clone(),
+                                            chain_index,
+                                            role,
+                                            agent_version: None,
+                                            protocols: Vec::new(),
+                                            best_block_number: best_number,
+                                            best_block_hash: best_hash,
+                                        })
+                                        .await;
                                     break Event::Connected {
                                         peer_id,
                                         chain_index,
@@ -265,18 +538,48 @@ impl NetworkService {
                                         peer_id,
                                         chain_index,
                                     );
+                                    network_service
+                                        .peer_disconnected(
+                                            &peer_id,
+                                            chain_index,
+                                            DisconnectReason::ChainSubstreamClosed,
+                                        )
+                                        .await;
                                     break Event::Disconnected {
                                         peer_id,
                                         chain_index,
                                     };
                                 }
+                                service::Event::GenesisMismatch {
+                                    peer_id,
+                                    chain_index,
+                                    remote_genesis_hash,
+                                } => {
+                                    network_service
+                                        .peer_genesis_mismatch(
+                                            &peer_id,
+                                            chain_index,
+                                            remote_genesis_hash,
+                                        )
+                                        .await;
+                                }
                                 service::Event::IdentifyRequestIn { peer_id, request } => {
                                     log::debug!(
                                         target: "network",
                                         "Connection({}) => IdentifyRequest",
                                         peer_id,
                                     );
-                                    request.respond("smoldot").await;
+                                    if network_service.refuse_identify {
+                                        request.refuse().await;
+                                    } else {
+                                        request.respond("smoldot").await;
+                                    }
+                                }
+                                service::Event::StorageProofRequestIn { .. }
+                                | service::Event::CallProofRequestIn { .. } => {
+                                    // Inbound light client requests are disabled in the
+                                    // configuration.
+                                    unreachable!()
                                 }
                                 service::Event::GrandpaCommitMessage {
                                     chain_index,
@@ -311,8 +614,12 @@ impl NetworkService {
         );
 
         // Spawn tasks dedicated to opening connections.
-        // TODO: spawn several, or do things asynchronously, so that we try open multiple connections simultaneously
         for chain_index in 0..num_chains {
+            let dial_strategy = config.dial_strategy;
+            let dial_timeout = config.dial_timeout;
+            let connection_limits = config.connection_limits;
+            let peers_target = config.peers_target;
+
             (network_service.guarded.try_lock().unwrap().tasks_executor)(
                 "connections-open".into(),
                 Box::pin({
@@ -321,7 +628,11 @@ impl NetworkService {
                     async move {
                         loop {
                             // TODO: very crappy way of not spamming the network service ; instead we should wake this task up when a disconnect or a discovery happens
-                            ffi::Delay::new(Duration::from_secs(1)).await;
+                            Host::sleep(match dial_strategy {
+                                DialStrategy::Parallel => Duration::from_secs(1),
+                                DialStrategy::Staggered { delay } => delay,
+                            })
+                            .await;
 
                             let network_service = match network_service.upgrade() {
                                 Some(ns) => ns,
@@ -330,44 +641,91 @@ impl NetworkService {
                                 }
                             };
 
-                            // TODO: should have a more robust way of limiting the number of connections
-                            if network_service.peers_list().await.count() >= 10 {
+                            if network_service.is_chain_paused(chain_index) {
                                 continue;
                             }
 
-                            let start_connect =
-                                match network_service.network.fill_out_slots(chain_index).await {
-                                    Some(sc) => sc,
-                                    None => continue,
-                                };
+                            // TODO: should have a more robust way of limiting the number of connections
+                            let num_peers = network_service.peers_list().await.count();
+                            let num_dials = match dial_strategy {
+                                DialStrategy::Parallel => peers_target.saturating_sub(num_peers),
+                                DialStrategy::Staggered { .. } => {
+                                    if num_peers >= peers_target {
+                                        0
+                                    } else {
+                                        1
+                                    }
+                                }
+                            };
 
-                            let is_important_peer = network_service
-                                .important_nodes
-                                .contains(&start_connect.expected_peer_id);
+                            for _ in 0..num_dials {
+                                let start_connect =
+                                    match network_service.network.fill_out_slots(chain_index).await
+                                    {
+                                        Some(sc) => sc,
+                                        None => break,
+                                    };
 
-                            // Convert the `multiaddr` (typically of the form `/ip4/a.b.c.d/tcp/d/ws`)
-                            // into a `Future<dyn Output = Result<TcpStream, ...>>`.
-                            let socket = {
-                                log::debug!(target: "connections", "Pending({:?}) started: {}", start_connect.id, start_connect.multiaddr);
-                                ffi::Connection::connect(&start_connect.multiaddr.to_string())
-                            };
+                                let is_important_peer = network_service
+                                    .important_nodes
+                                    .contains(&start_connect.expected_peer_id);
+
+                                // Nodes reached through a relay don't need a new socket, as the
+                                // connection goes through the existing connection to the relay.
+                                if let Some((_, relay_peer_id)) =
+                                    libp2p::relay::split_relayed_address(&start_connect.multiaddr)
+                                {
+                                    log::debug!(target: "connections", "Pending({:?}) started: {}", start_connect.id, start_connect.multiaddr);
+                                    let network_service2 = network_service.clone();
+                                    (network_service.guarded.lock().await.tasks_executor)(
+                                        format!("connection-{}", start_connect.expected_peer_id),
+                                        Box::pin(relayed_connection_task(
+                                            network_service2,
+                                            start_connect.id,
+                                            start_connect.expected_peer_id,
+                                            start_connect.multiaddr,
+                                            relay_peer_id,
+                                            dial_timeout,
+                                            is_important_peer,
+                                        )),
+                                    );
+                                    continue;
+                                }
 
-                            // TODO: handle dialing timeout here
-
-                            let network_service2 = network_service.clone();
-                            (network_service.guarded.lock().await.tasks_executor)(
-                                format!("connection-{}", start_connect.expected_peer_id),
-                                Box::pin({
-                                    connection_task(
-                                        socket,
-                                        network_service2,
-                                        start_connect.id,
-                                        start_connect.expected_peer_id,
-                                        start_connect.multiaddr,
-                                        is_important_peer,
-                                    )
-                                }),
-                            );
+                                // Convert the `multiaddr` (typically of the form `/ip4/a.b.c.d/tcp/d/ws`)
+                                // into a `Future<dyn Output = Result<TcpStream, ...>>`.
+                                let socket = {
+                                    log::debug!(target: "connections", "Pending({:?}) started: {}", start_connect.id, start_connect.multiaddr);
+                                    let connect = Host::connect(
+                                        &start_connect.multiaddr.to_string(),
+                                        connection_limits,
+                                    );
+                                    let timeout = Host::sleep(dial_timeout);
+                                    async move {
+                                        match future::select(connect, timeout).await {
+                                            future::Either::Left((result, _)) => result,
+                                            future::Either::Right(((), _)) => {
+                                                Err("Timeout while dialing".to_owned())
+                                            }
+                                        }
+                                    }
+                                };
+
+                                let network_service2 = network_service.clone();
+                                (network_service.guarded.lock().await.tasks_executor)(
+                                    format!("connection-{}", start_connect.expected_peer_id),
+                                    Box::pin({
+                                        connection_task(
+                                            socket,
+                                            network_service2,
+                                            start_connect.id,
+                                            start_connect.expected_peer_id,
+                                            start_connect.multiaddr,
+                                            is_important_peer,
+                                        )
+                                    }),
+                                );
+                            }
                         }
                     }
                 }),
@@ -385,17 +743,24 @@ impl NetworkService {
                         let mut next_discovery = Duration::from_secs(5);
 
                         loop {
-                            ffi::Delay::new(next_discovery).await;
-                            next_discovery = cmp::min(next_discovery * 2, Duration::from_secs(120));
+                            Host::sleep(next_discovery).await;
 
                             let network_service = match network_service.upgrade() {
                                 Some(ns) => ns,
                                 None => return,
                             };
 
+                            // The delay between discoveries only starts increasing once the
+                            // chain is resumed.
+                            if network_service.is_chain_paused(chain_index) {
+                                continue;
+                            }
+
+                            next_discovery = cmp::min(next_discovery * 2, Duration::from_secs(120));
+
                             match network_service
                                 .network
-                                .kademlia_discovery_round(ffi::Instant::now(), chain_index)
+                                .kademlia_discovery_round(Host::now(), chain_index)
                                 .await
                             {
                                 Ok(insert) => {
@@ -415,6 +780,36 @@ impl NetworkService {
             );
         }
 
+        if let Some(rotation_interval) = config.privacy.peer_id_rotation_interval {
+            (network_service.guarded.try_lock().unwrap().tasks_executor)(
+                "peer-id-rotation".into(),
+                Box::pin({
+                    // TODO: keeping a Weak here doesn't really work to shut down tasks
+                    let network_service = Arc::downgrade(&network_service);
+                    async move {
+                        loop {
+                            Host::sleep(rotation_interval).await;
+
+                            let network_service = match network_service.upgrade() {
+                                Some(ns) => ns,
+                                None => return,
+                            };
+
+                            let noise_key = connection::NoiseKey::new(&rand::random());
+                            log::debug!(
+                                target: "network",
+                                "New local peer id: {}",
+                                PeerId::from_public_key(&peer_id::PublicKey::Ed25519(
+                                    *noise_key.libp2p_public_ed25519_key()
+                                ))
+                            );
+                            network_service.network.set_noise_key(noise_key).await;
+                        }
+                    }
+                }),
+            );
+        }
+
         (network_service.guarded.try_lock().unwrap().tasks_executor)(
             "substreams-open".into(),
             Box::pin({
@@ -423,7 +818,7 @@ impl NetworkService {
                 async move {
                     loop {
                         // TODO: very crappy way of not spamming the network service ; instead we should wake this task up when a disconnect or a discovery happens
-                        ffi::Delay::new(Duration::from_secs(1)).await;
+                        Host::sleep(Duration::from_secs(1)).await;
 
                         let network_service = match network_service.upgrade() {
                             Some(ns) => ns,
@@ -436,7 +831,7 @@ impl NetworkService {
                             .network
                             .next_substream()
                             .await
-                            .open(ffi::Instant::now())
+                            .open(Host::now())
                             .await;
                     }
                 }
@@ -446,6 +841,42 @@ impl NetworkService {
         (network_service, receivers)
     }
 
+    /// Returns the value of [`Config::request_compressed_responses`] that was passed at
+    /// initialization.
+    ///
+    /// Should be used as the value of the `accept_compressed_response` field of the requests
+    /// configurations. Compressed responses are transparently decompressed, using
+    /// [`Platform::zstd_decompress`] if the platform provides it.
+    pub fn request_compressed_responses(&self) -> bool {
+        self.request_compressed_responses
+    }
+
+    /// Allows opening connections and performing discoveries on behalf of the given chain, if it
+    /// was paused. See [`ConfigChain::paused`].
+    pub fn resume_chain(&self, chain_index: usize) {
+        self.chains_paused[chain_index].store(false, Ordering::Relaxed);
+    }
+
+    /// Stops opening connections and performing discoveries on behalf of the given chain, until
+    /// [`NetworkService::resume_chain`] is called. See [`ConfigChain::paused`].
+    ///
+    /// Connections that are already open aren't closed.
+    pub fn pause_chain(&self, chain_index: usize) {
+        self.chains_paused[chain_index].store(true, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the given chain is paused. See [`ConfigChain::paused`].
+    fn is_chain_paused(&self, chain_index: usize) -> bool {
+        self.chains_paused[chain_index].load(Ordering::Relaxed)
+    }
+
+    /// Waits for a random duration in order to apply [`PrivacyConfig::max_request_jitter`].
+    async fn request_jitter(&self) {
+        if self.max_request_jitter != Duration::new(0, 0) {
+            Host::sleep(self.max_request_jitter.mul_f64(rand::random::<f64>())).await;
+        }
+    }
+
     /// Sends a blocks request to the given peer.
     // TODO: more docs
     pub async fn blocks_request(
@@ -456,9 +887,11 @@ impl NetworkService {
     ) -> Result<Vec<protocol::BlockData>, service::BlocksRequestError> {
         log::debug!(target: "network", "Connection({}) <= BlocksRequest({:?})", target, config);
 
+        self.request_jitter().await;
+
         let result = self
             .network
-            .blocks_request(ffi::Instant::now(), target.clone(), chain_index, config)
+            .blocks_request(Host::now(), target.clone(), chain_index, config)
             .await;
 
         log::debug!(
@@ -478,15 +911,18 @@ impl NetworkService {
         target: PeerId,
         chain_index: usize,
         begin_hash: [u8; 32],
-    ) -> Result<protocol::GrandpaWarpSyncResponse, service::GrandpaWarpSyncRequestError> {
+    ) -> Result<protocol::EncodedGrandpaWarpSyncResponse, service::GrandpaWarpSyncRequestError>
+    {
         log::debug!(
             target: "network", "Connection({}) <= GrandpaWarpSyncRequest({})",
             target, HashDisplay(&begin_hash)
         );
 
+        self.request_jitter().await;
+
         let result = self
             .network
-            .grandpa_warp_sync_request(ffi::Instant::now(), target.clone(), chain_index, begin_hash)
+            .grandpa_warp_sync_request(Host::now(), target.clone(), chain_index, begin_hash)
             .await;
 
         if let Ok(response) = result.as_ref() {
@@ -494,8 +930,8 @@ impl NetworkService {
                 target: "network",
                 "Connection({}) => GrandpaWarpSyncRequest(num_fragments: {:?}, finished: {:?})",
                 target,
-                response.fragments.len(),
-                response.is_finished,
+                response.num_fragments(),
+                response.is_finished(),
             );
         } else {
             log::debug!(
@@ -530,12 +966,16 @@ impl NetworkService {
     }
 
     /// Sends a storage proof request to the given peer.
+    ///
+    /// Responses larger than `max_response_size` bytes, if `Some`, are refused. See
+    /// [`service::ChainNetwork::storage_proof_request`].
     // TODO: more docs
     pub async fn storage_proof_request(
         self: Arc<Self>,
         chain_index: usize,
         target: PeerId,
         config: protocol::StorageProofRequestConfig<impl Iterator<Item = impl AsRef<[u8]>>>,
+        max_response_size: Option<usize>,
     ) -> Result<Vec<Vec<u8>>, service::StorageProofRequestError> {
         log::debug!(
             target: "network",
@@ -545,9 +985,17 @@ impl NetworkService {
             config.keys.size_hint().0
         );
 
+        self.request_jitter().await;
+
         let result = self
             .network
-            .storage_proof_request(ffi::Instant::now(), target.clone(), chain_index, config)
+            .storage_proof_request(
+                Host::now(),
+                target.clone(),
+                chain_index,
+                config,
+                max_response_size,
+            )
             .await;
 
         log::debug!(
@@ -563,12 +1011,15 @@ impl NetworkService {
     /// Sends a call proof request to the given peer.
     ///
     /// See also [`NetworkService::call_proof_request`].
+    ///
+    /// Responses larger than `max_response_size` bytes, if `Some`, are refused.
     // TODO: more docs
     pub async fn call_proof_request<'a>(
         self: Arc<Self>,
         chain_index: usize,
         target: PeerId,
         config: protocol::CallProofRequestConfig<'a, impl Iterator<Item = impl AsRef<[u8]>>>,
+        max_response_size: Option<usize>,
     ) -> Result<Vec<Vec<u8>>, service::CallProofRequestError> {
         log::debug!(
             target: "network",
@@ -578,9 +1029,17 @@ impl NetworkService {
             config.method
         );
 
+        self.request_jitter().await;
+
         let result = self
             .network
-            .call_proof_request(ffi::Instant::now(), target.clone(), chain_index, config)
+            .call_proof_request(
+                Host::now(),
+                target.clone(),
+                chain_index,
+                config,
+                max_response_size,
+            )
             .await;
 
         log::debug!(
@@ -593,6 +1052,41 @@ impl NetworkService {
         result
     }
 
+    /// Sends a request on the request-response protocol with the given name to the given peer,
+    /// and returns the response as-is.
+    ///
+    /// See [`service::ChainNetwork::raw_request`].
+    pub async fn raw_request(
+        self: Arc<Self>,
+        target: PeerId,
+        protocol_name: &str,
+        request: Vec<u8>,
+    ) -> Result<Vec<u8>, service::RawRequestError> {
+        log::debug!(
+            target: "network",
+            "Connection({}) <= RawRequest({}, {} bytes)",
+            target,
+            protocol_name,
+            request.len()
+        );
+
+        self.request_jitter().await;
+
+        let result = self
+            .network
+            .raw_request(Host::now(), target.clone(), protocol_name, request)
+            .await;
+
+        log::debug!(
+            target: "network",
+            "Connection({}) => RawRequest({:?})",
+            target,
+            result.as_ref().map(|r| r.len())
+        );
+
+        result
+    }
+
     /// Announces transaction to the peers we are connected to.
     ///
     /// Returns a list of peers that we have sent the transaction to. Can return an empty `Vec`
@@ -630,6 +1124,318 @@ impl NetworkService {
     pub async fn peers_list(&self) -> impl Iterator<Item = PeerId> {
         self.network.peers_list().await
     }
+
+    /// Returns how diverse the origins of the peers connected to the given chain are.
+    ///
+    /// All the data of a chain coming from peers of the same origin is the sign of a potential
+    /// eclipse attack, where an attacker controls all the peers we are connected to.
+    pub async fn peer_diversity(&self, chain_index: usize) -> PeerDiversity {
+        let guarded = self.guarded.lock().await;
+
+        let mut num_peers = 0;
+        let mut origins = HashSet::new();
+        for (peer_id, _) in guarded.peers.keys().filter(|(_, c)| *c == chain_index) {
+            num_peers += 1;
+            if let Some(origin) = guarded.connection_origins.get(peer_id) {
+                origins.insert(origin);
+            }
+        }
+
+        PeerDiversity {
+            num_peers,
+            num_origins: origins.len(),
+            required_origins: self.chains_required_origins[chain_index],
+        }
+    }
+
+    /// Adds the given addresses to the list of known addresses of the given peer, and marks this
+    /// peer as belonging to the given chain.
+    ///
+    /// The network service later tries to connect to this peer if it needs more connections.
+    pub async fn add_known_addresses(
+        &self,
+        chain_index: usize,
+        peer_id: PeerId,
+        addrs: Vec<Multiaddr>,
+    ) {
+        log::debug!(
+            target: "network",
+            "Chain({}) <= AddKnownAddresses({}, {:?})",
+            chain_index,
+            peer_id,
+            addrs
+        );
+
+        self.network
+            .add_known_addresses(chain_index, peer_id, addrs, || ())
+            .await
+    }
+
+    /// Returns the list of peers that are connected to the given chain.
+    pub async fn peers_info(&self, chain_index: usize) -> Vec<PeerInfo> {
+        self.guarded
+            .lock()
+            .await
+            .peers
+            .values()
+            .filter(|info| info.chain_index == chain_index)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns a channel onto which [`PeerEvent`]s concerning all chains are sent.
+    ///
+    /// The list of peers at the time of the subscription is also returned. Call
+    /// [`NetworkService::peers_info`] to obtain the list of peers of a specific chain instead.
+    ///
+    /// If the channel is full when a new event needs to be reported, this event is discarded
+    /// but the channel stays open.
+    pub async fn subscribe_peer_events(&self) -> (Vec<PeerInfo>, mpsc::Receiver<PeerEvent>) {
+        let (tx, rx) = mpsc::channel(32);
+        let mut guarded = self.guarded.lock().await;
+        guarded.peer_events_senders.push(tx);
+        (guarded.peers.values().cloned().collect(), rx)
+    }
+
+    /// Reports a [`PeerEvent::StateRootMismatch`] to the subscribers of the peer events.
+    ///
+    /// Must be called when `peer_id` has served, for the number of the finalized block
+    /// `block_hash` whose storage proof has been provided by `proof_peer_id`, a different header.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn report_state_root_mismatch(
+        &self,
+        chain_index: usize,
+        peer_id: PeerId,
+        proof_peer_id: PeerId,
+        block_number: u64,
+        block_hash: [u8; 32],
+        expected_state_root: [u8; 32],
+        reported_block_hash: [u8; 32],
+        reported_state_root: [u8; 32],
+    ) {
+        let mut guarded = self.guarded.lock().await;
+        report_peer_event(
+            &mut guarded.peer_events_senders,
+            PeerEvent::StateRootMismatch {
+                peer_id,
+                proof_peer_id,
+                chain_index,
+                block_number,
+                block_hash,
+                expected_state_root,
+                reported_block_hash,
+                reported_state_root,
+            },
+        );
+    }
+
+    /// Spawns a task that sends an identify request to the given newly-connected peer and
+    /// stores its answer.
+    ///
+    /// The answer is used to fill [`PeerInfo::agent_version`] and [`PeerInfo::protocols`].
+    /// Additionally, the underlying [`service::ChainNetwork`] no longer sends requests on
+    /// protocols that the peer doesn't support.
+    async fn identify_peer(self: Arc<Self>, peer_id: PeerId) {
+        let mut guarded = self.guarded.lock().await;
+        guarded.identify_responses.insert(peer_id.clone(), None);
+
+        let network_service = self.clone();
+        (guarded.tasks_executor)(
+            format!("identify-{}", peer_id),
+            Box::pin(async move {
+                log::debug!(target: "network", "Connection({}) <= IdentifyRequest", peer_id);
+
+                let response = match network_service
+                    .network
+                    .identify_request(Host::now(), peer_id.clone())
+                    .await
+                {
+                    Ok(r) => r,
+                    Err(err) => {
+                        log::debug!(
+                            target: "network",
+                            "Connection({}) => IdentifyRequest(error: {})",
+                            peer_id, err
+                        );
+                        return;
+                    }
+                };
+
+                log::debug!(
+                    target: "network",
+                    "Connection({}) => IdentifyRequest(agent: {:?}, num_protocols: {})",
+                    peer_id,
+                    response.agent_version,
+                    response.protocols.len()
+                );
+
+                let mut guarded = network_service.guarded.lock().await;
+                let guarded = &mut *guarded;
+
+                // The entry is missing if the peer has disconnected in the meanwhile.
+                if let Some(entry) = guarded.identify_responses.get_mut(&peer_id) {
+                    for info in guarded.peers.values_mut() {
+                        if info.peer_id == peer_id {
+                            info.agent_version = response.agent_version.clone();
+                            info.protocols = response.protocols.clone();
+                        }
+                    }
+                    *entry = Some(response);
+                }
+            }),
+        );
+    }
+
+    /// Updates the list of peers after a peer has connected to a chain.
+    async fn peer_connected(&self, mut info: PeerInfo) {
+        let mut guarded = self.guarded.lock().await;
+        if let Some(Some(identify)) = guarded.identify_responses.get(&info.peer_id) {
+            info.agent_version = identify.agent_version.clone();
+            info.protocols = identify.protocols.clone();
+        }
+        guarded
+            .peers
+            .insert((info.peer_id.clone(), info.chain_index), info.clone());
+        report_peer_event(&mut guarded.peer_events_senders, PeerEvent::Connected(info));
+    }
+
+    /// Updates the list of peers after a peer has disconnected from a chain.
+    ///
+    /// Does nothing if the peer wasn't connected to the chain.
+    async fn peer_disconnected(
+        &self,
+        peer_id: &PeerId,
+        chain_index: usize,
+        reason: DisconnectReason,
+    ) {
+        let mut guarded = self.guarded.lock().await;
+        if guarded
+            .peers
+            .remove(&(peer_id.clone(), chain_index))
+            .is_none()
+        {
+            return;
+        }
+        report_peer_event(
+            &mut guarded.peer_events_senders,
+            PeerEvent::Disconnected {
+                peer_id: peer_id.clone(),
+                chain_index,
+                reason,
+            },
+        );
+    }
+
+    /// Records that a peer has reported a genesis block hash different from the one of the
+    /// chain, and reports the mismatch.
+    async fn peer_genesis_mismatch(
+        &self,
+        peer_id: &PeerId,
+        chain_index: usize,
+        genesis_hash: [u8; 32],
+    ) {
+        let mut guarded = self.guarded.lock().await;
+        let guarded = &mut *guarded;
+
+        let is_bootstrap_node = self.chains_bootstrap_nodes[chain_index].contains(peer_id);
+        let mismatches = &mut guarded.genesis_mismatches[chain_index];
+        mismatches.num_mismatches += 1;
+
+        if is_bootstrap_node {
+            mismatches.bootstrap_nodes.insert(peer_id.clone());
+            log::warn!(
+                target: "network",
+                "Bootnode {} of chain {} reports genesis block {}, which doesn't match the chain specification",
+                peer_id, chain_index, HashDisplay(&genesis_hash)
+            );
+        } else {
+            log::debug!(
+                target: "network",
+                "Connection({}) => GenesisMismatch({}, {})",
+                peer_id, chain_index, HashDisplay(&genesis_hash)
+            );
+        }
+
+        report_peer_event(
+            &mut guarded.peer_events_senders,
+            PeerEvent::GenesisMismatch {
+                peer_id: peer_id.clone(),
+                chain_index,
+                genesis_hash,
+                num_mismatches: mismatches.num_mismatches,
+            },
+        );
+
+        // If all the bootstrap nodes are on a different chain, the chain specification most
+        // likely doesn't match the network it points to.
+        if !mismatches.chain_spec_mismatch_reported
+            && !self.chains_bootstrap_nodes[chain_index].is_empty()
+            && mismatches.bootstrap_nodes.len() == self.chains_bootstrap_nodes[chain_index].len()
+        {
+            mismatches.chain_spec_mismatch_reported = true;
+            log::error!(
+                target: "network",
+                "All the bootnodes of chain {} report a different genesis block (latest: {}). \
+                 The chain specification likely doesn't match the network.",
+                chain_index, HashDisplay(&genesis_hash)
+            );
+            report_peer_event(
+                &mut guarded.peer_events_senders,
+                PeerEvent::ChainSpecMismatch {
+                    chain_index,
+                    genesis_hash,
+                },
+            );
+        }
+    }
+
+    /// Updates the list of peers after a peer has announced a new best block.
+    async fn peer_best_block_update(
+        &self,
+        peer_id: &PeerId,
+        chain_index: usize,
+        best_block_number: u64,
+        best_block_hash: [u8; 32],
+    ) {
+        let mut guarded = self.guarded.lock().await;
+        if let Some(info) = guarded.peers.get_mut(&(peer_id.clone(), chain_index)) {
+            info.best_block_number = best_block_number;
+            info.best_block_hash = best_block_hash;
+        } else {
+            return;
+        }
+        report_peer_event(
+            &mut guarded.peer_events_senders,
+            PeerEvent::BestBlockUpdate {
+                peer_id: peer_id.clone(),
+                chain_index,
+                best_block_number,
+                best_block_hash,
+            },
+        );
+    }
+}
+
+/// Sends `event` to all the `senders`, removing the ones that are closed. The event is
+/// discarded for the senders whose channel is full.
+fn report_peer_event(senders: &mut Vec<mpsc::Sender<PeerEvent>>, event: PeerEvent) {
+    // Elements in `senders` are removed one by one and inserted back if the channel is still
+    // open.
+    for index in (0..senders.len()).rev() {
+        let mut sender = senders.swap_remove(index);
+        match sender.try_send(event.clone()) {
+            Ok(()) => senders.push(sender),
+            Err(err) if err.is_disconnected() => {}
+            Err(_) => {
+                log::debug!(
+                    target: "network",
+                    "Peer events subscriber is lagging behind; discarding {:?}",
+                    event
+                );
+                senders.push(sender);
+            }
+        }
+    }
 }
 
 /// Event that can happen on the network service.
@@ -657,11 +1463,164 @@ pub enum Event {
     },
 }
 
+/// Minimum number of distinct [`PeerOrigin`]s that the peers of a chain must be spread over in
+/// order for the chain to be considered as safe from eclipse attacks.
+const MIN_PEER_ORIGINS: usize = 2;
+
+/// Diversity of the peers of a chain. See [`NetworkService::peer_diversity`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerDiversity {
+    /// Number of peers connected to the chain.
+    pub num_peers: usize,
+    /// Number of distinct [`PeerOrigin`]s of the peers connected to the chain.
+    pub num_origins: usize,
+    /// Number of distinct origins required for [`PeerDiversity::is_sufficient`] to return
+    /// `true`. Lower than the default minimum if the bootstrap nodes of the chain don't reach
+    /// that minimum themselves.
+    pub required_origins: usize,
+}
+
+impl PeerDiversity {
+    /// Returns `true` if the peers are spread over enough origins.
+    pub fn is_sufficient(&self) -> bool {
+        self.num_origins >= self.required_origins
+    }
+}
+
+/// Approximation of the entity operating a peer, deduced from the address used to connect to
+/// it. Peers of the same origin are likely to be controlled by the same entity.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum PeerOrigin {
+    /// IPv4 address, truncated to its `/16` prefix.
+    Ip4Prefix([u8; 2]),
+    /// IPv6 address, truncated to its `/32` prefix.
+    Ip6Prefix([u8; 4]),
+    /// Domain name.
+    Dns(String),
+    /// Address of an unrecognized format.
+    Other(Multiaddr),
+}
+
+impl PeerOrigin {
+    /// Determines the origin of the given address. Nodes reached through a relay have the
+    /// origin of the relay, as relayed addresses start with the address of the relay.
+    fn from_multiaddr(addr: &Multiaddr) -> Self {
+        match addr.iter().next() {
+            Some(libp2p::multiaddr::Protocol::Ip4(ip)) => {
+                let [a, b, _, _] = ip.octets();
+                PeerOrigin::Ip4Prefix([a, b])
+            }
+            Some(libp2p::multiaddr::Protocol::Ip6(ip)) => {
+                let [a, b, c, d, ..] = ip.octets();
+                PeerOrigin::Ip6Prefix([a, b, c, d])
+            }
+            Some(libp2p::multiaddr::Protocol::Dns(name))
+            | Some(libp2p::multiaddr::Protocol::Dns4(name))
+            | Some(libp2p::multiaddr::Protocol::Dns6(name))
+            | Some(libp2p::multiaddr::Protocol::Dnsaddr(name)) => {
+                PeerOrigin::Dns(name.to_ascii_lowercase())
+            }
+            _ => PeerOrigin::Other(addr.clone()),
+        }
+    }
+}
+
+/// Information about a peer connected to a chain.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    /// Identity of the peer.
+    pub peer_id: PeerId,
+    /// Index of the chain within [`Config::chains`].
+    pub chain_index: usize,
+    /// Role the peer reports playing on the network.
+    pub role: protocol::Role,
+    /// Name and version of the software of the peer, as reported in its answer to the identify
+    /// request. `None` if the peer hasn't answered yet or didn't report it.
+    pub agent_version: Option<String>,
+    /// Names of the protocols the peer reports supporting in its answer to the identify request.
+    /// Empty if the peer hasn't answered yet.
+    pub protocols: Vec<String>,
+    /// Height of the best block according to the latest information sent by the peer.
+    pub best_block_number: u64,
+    /// Hash of the best block according to the latest information sent by the peer.
+    pub best_block_hash: [u8; 32],
+}
+
+/// Event about the peers of a chain. See [`NetworkService::subscribe_peer_events`].
+#[derive(Debug, Clone)]
+pub enum PeerEvent {
+    /// A peer has connected to a chain.
+    Connected(PeerInfo),
+    /// A peer that was previously reported as connected has disconnected from a chain.
+    Disconnected {
+        peer_id: PeerId,
+        chain_index: usize,
+        reason: DisconnectReason,
+    },
+    /// A peer has announced a new best block.
+    BestBlockUpdate {
+        peer_id: PeerId,
+        chain_index: usize,
+        best_block_number: u64,
+        best_block_hash: [u8; 32],
+    },
+    /// A peer has reported a genesis block hash different from the one of the chain, and has
+    /// been removed from the chain.
+    GenesisMismatch {
+        peer_id: PeerId,
+        chain_index: usize,
+        /// Genesis block hash reported by the peer.
+        genesis_hash: [u8; 32],
+        /// Total number of peers that have reported a mismatching genesis block hash on this
+        /// chain, including this one.
+        num_mismatches: u64,
+    },
+    /// All the bootstrap nodes of a chain have reported a genesis block hash different from the
+    /// one of the chain, meaning that the chain specification likely doesn't match the network.
+    /// Reported at most once per chain.
+    ChainSpecMismatch {
+        chain_index: usize,
+        /// Genesis block hash reported by the latest bootstrap node.
+        genesis_hash: [u8; 32],
+    },
+    /// A peer has served, for the number of a finalized block whose storage has been queried, a
+    /// header different from the one of this block. Either this peer or the peers the local node
+    /// has synchronized with are following a different finalized chain, which indicates an
+    /// attempt to eclipse one of the two. Only a sample of the storage queries is checked.
+    StateRootMismatch {
+        /// Peer that has served the header.
+        peer_id: PeerId,
+        /// Peer that has served the storage proof.
+        proof_peer_id: PeerId,
+        chain_index: usize,
+        /// Number of the block whose storage has been queried.
+        block_number: u64,
+        /// Hash of the block whose storage has been queried.
+        block_hash: [u8; 32],
+        /// State root the storage proof has been verified against.
+        expected_state_root: [u8; 32],
+        /// Hash of the header served by `peer_id`.
+        reported_block_hash: [u8; 32],
+        /// State root found in the header served by `peer_id`.
+        reported_state_root: [u8; 32],
+    },
+}
+
+/// Reason why a peer has disconnected. See [`PeerEvent::Disconnected`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DisconnectReason {
+    /// The connection with the peer has been closed.
+    ConnectionClosed,
+    /// The connection with the peer is still alive, but the substream dedicated to the chain
+    /// has been closed.
+    ChainSubstreamClosed,
+}
+
 /// Asynchronous task managing a specific connection.
 ///
 /// `is_important_peer` controls the log level used for problems that happen on this connection.
 async fn connection_task(
-    websocket: impl Future<Output = Result<Pin<Box<ffi::Connection>>, impl fmt::Display>>,
+    websocket: impl Future<Output = Result<platform::Connection, impl fmt::Display>>,
     network_service: Arc<NetworkService>,
     pending_id: service::PendingId,
     expected_peer_id: PeerId,
@@ -700,6 +1659,16 @@ async fn connection_task(
         .pending_outcome_ok(pending_id, ())
         .await;
 
+    network_service
+        .guarded
+        .lock()
+        .await
+        .connection_origins
+        .insert(
+            expected_peer_id.clone(),
+            PeerOrigin::from_multiaddr(&attemped_multiaddr),
+        );
+
     log::debug!(
         target: "connections",
         "Pending({:?}, {}) => Connection({:?}) through {}",
@@ -712,9 +1681,11 @@ async fn connection_task(
     let mut write_buffer = vec![0; 4096];
 
     loop {
-        let read_buffer = websocket.read_buffer().now_or_never().unwrap_or(Some(&[]));
+        let read_buffer = Host::read_buffer(&mut websocket)
+            .now_or_never()
+            .unwrap_or(Some(&[]));
 
-        let now = ffi::Instant::now();
+        let now = Host::now();
 
         let read_write = match network_service
             .network
@@ -761,10 +1732,10 @@ async fn connection_task(
         }
 
         if read_write.written_bytes != 0 {
-            websocket.send(&write_buffer[..read_write.written_bytes]);
+            Host::send(&mut websocket, &write_buffer[..read_write.written_bytes]);
         }
 
-        websocket.advance_read_cursor(read_write.read_bytes);
+        Host::advance_read_cursor(&mut websocket, read_write.read_bytes);
 
         // Starting from here, we block (or not) the current task until more processing needs
         // to happen.
@@ -773,7 +1744,7 @@ async fn connection_task(
         let poll_after = if let Some(wake_up) = read_write.wake_up_after {
             if wake_up > now {
                 let dur = wake_up - now;
-                future::Either::Left(ffi::Delay::new(dur))
+                future::Either::Left(Host::sleep(dur))
             } else {
                 continue;
             }
@@ -785,7 +1756,7 @@ async fn connection_task(
         // Future that is woken up when new data is ready on the socket.
         let read_buffer_ready =
             if !(read_buffer_has_data && read_write.read_bytes == 0) && !read_buffer_closed {
-                future::Either::Left(websocket.read_buffer())
+                future::Either::Left(Host::read_buffer(&mut websocket))
             } else {
                 future::Either::Right(future::pending())
             };
@@ -800,3 +1771,242 @@ async fn connection_task(
         .await;
     }
 }
+
+/// Asynchronous task managing a connection to a node reached through a relay.
+///
+/// Similar to [`connection_task`], except that the data of the connection goes through a
+/// substream of the connection with the relay rather than through a socket.
+async fn relayed_connection_task(
+    network_service: Arc<NetworkService>,
+    pending_id: service::PendingId,
+    expected_peer_id: PeerId,
+    attemped_multiaddr: Multiaddr,
+    relay_peer_id: PeerId,
+    dial_timeout: Duration,
+    is_important_peer: bool,
+) {
+    // Asking the relay to open a circuit to the target.
+    let circuit = {
+        let open = network_service.network.open_relay_circuit(
+            Host::now(),
+            &relay_peer_id,
+            &expected_peer_id,
+        );
+        let timeout = Host::sleep(dial_timeout);
+        futures::pin_mut!(open);
+        match future::select(open, timeout).await {
+            future::Either::Left((result, _)) => result.map_err(|err| err.to_string()),
+            future::Either::Right(((), _)) => Err("Timeout while dialing".to_owned()),
+        }
+    };
+
+    let mut circuit = match circuit {
+        Ok(c) => c,
+        Err(err) => {
+            if is_important_peer {
+                log::warn!(
+                    target: "connections",
+                    "Failed to reach {} through {}: {}",
+                    expected_peer_id, attemped_multiaddr, err
+                );
+            } else {
+                log::debug!(
+                    target: "connections",
+                    "Pending({:?}, {}) => Failed to reach ({}): {}",
+                    pending_id, expected_peer_id, attemped_multiaddr, err
+                );
+            }
+
+            network_service
+                .network
+                .pending_outcome_err(pending_id)
+                .await;
+
+            return;
+        }
+    };
+
+    let id = network_service
+        .network
+        .pending_outcome_ok(pending_id, ())
+        .await;
+
+    network_service
+        .guarded
+        .lock()
+        .await
+        .connection_origins
+        .insert(
+            expected_peer_id.clone(),
+            PeerOrigin::from_multiaddr(&attemped_multiaddr),
+        );
+
+    log::debug!(
+        target: "connections",
+        "Pending({:?}, {}) => Connection({:?}) through {}",
+        pending_id,
+        expected_peer_id,
+        id,
+        attemped_multiaddr
+    );
+
+    // Data sent by the target that hasn't been processed yet.
+    let mut read_buffer = Vec::new();
+    // True if the circuit has been closed, meaning that no more data will be received.
+    let mut read_buffer_closed = false;
+    let mut write_buffer = vec![0; 4096];
+
+    loop {
+        let now = Host::now();
+
+        let incoming_buffer = if read_buffer_closed && read_buffer.is_empty() {
+            None
+        } else {
+            Some(&read_buffer[..])
+        };
+
+        let read_write = match network_service
+            .network
+            .read_write(id, now, incoming_buffer, (&mut write_buffer, &mut []))
+            .await
+        {
+            Ok(rw) => rw,
+            Err(_err) => {
+                if is_important_peer {
+                    log::warn!(
+                        target: "connections", "Error in connection with {}: {}",
+                        expected_peer_id, _err
+                    );
+                } else {
+                    log::debug!(target: "connections", "Connection({:?}, {}) => Closed: {}", id, expected_peer_id, _err);
+                }
+
+                network_service.network.close_relay_circuit(circuit).await;
+                return;
+            }
+        };
+
+        if read_write.write_close && incoming_buffer.is_none() {
+            log::debug!(target: "connections", "Connection({:?}, {}) => Closed gracefully", id, expected_peer_id);
+            network_service.network.close_relay_circuit(circuit).await;
+            return;
+        }
+
+        if read_write.written_bytes != 0 {
+            let data = write_buffer[..read_write.written_bytes].to_vec();
+            if network_service
+                .network
+                .write_relay_circuit(&circuit, data)
+                .await
+                .is_err()
+            {
+                read_buffer_closed = true;
+            }
+        }
+
+        read_buffer.drain(..read_write.read_bytes);
+
+        // If some progress has been made, the connection state machine might be able to make
+        // further progress immediately.
+        if read_write.read_bytes != 0 || read_write.written_bytes != 0 {
+            continue;
+        }
+
+        // Future ready when the connection state machine requests more processing.
+        let poll_after = if let Some(wake_up) = read_write.wake_up_after {
+            if wake_up > now {
+                let dur = wake_up - now;
+                future::Either::Left(Host::sleep(dur))
+            } else {
+                continue;
+            }
+        } else {
+            future::Either::Right(future::pending())
+        }
+        .fuse();
+
+        // Future that is ready when the target has sent more data.
+        let next_data = if !read_buffer_closed {
+            future::Either::Left(circuit.next())
+        } else {
+            future::Either::Right(future::pending())
+        };
+
+        match future::select(
+            future::select(next_data, read_write.wake_up_future),
+            poll_after,
+        )
+        .await
+        {
+            future::Either::Left((future::Either::Left((Some(data), _)), _)) => {
+                read_buffer.extend_from_slice(&data)
+            }
+            future::Either::Left((future::Either::Left((None, _)), _)) => read_buffer_closed = true,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{report_peer_event, PeerEvent, PeerOrigin};
+    use futures::{channel::mpsc, prelude::*};
+
+    #[test]
+    fn peer_origins() {
+        let origin = |addr: &str| PeerOrigin::from_multiaddr(&addr.parse().unwrap());
+
+        assert_eq!(
+            origin("/ip4/1.2.3.4/tcp/30333/ws"),
+            origin("/ip4/1.2.200.1/tcp/30334/ws")
+        );
+        assert_ne!(
+            origin("/ip4/1.2.3.4/tcp/30333/ws"),
+            origin("/ip4/1.3.3.4/tcp/30333/ws")
+        );
+        assert_eq!(
+            origin("/dns/Example.com/tcp/443/wss"),
+            origin("/dns4/example.com/tcp/443/wss")
+        );
+        assert_eq!(
+            origin("/ip6/2001:db8::1/tcp/30333/ws"),
+            origin("/ip6/2001:db8:ffff::2/tcp/30333/ws")
+        );
+        assert_eq!(
+            origin("/ip4/1.2.3.4/tcp/30333/ws/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit"),
+            origin("/ip4/1.2.3.4/tcp/30333/ws")
+        );
+    }
+
+    #[test]
+    fn peer_events_full_channel() {
+        let event = |chain_index| PeerEvent::ChainSpecMismatch {
+            chain_index,
+            genesis_hash: [0; 32],
+        };
+
+        let (tx, mut rx) = mpsc::channel(0);
+        let (closed_tx, closed_rx) = mpsc::channel(0);
+        drop(closed_rx);
+        let mut senders = vec![tx, closed_tx];
+
+        // The capacity of a channel is its buffer size plus one per sender.
+        report_peer_event(&mut senders, event(0));
+        report_peer_event(&mut senders, event(1));
+        report_peer_event(&mut senders, event(2));
+        assert_eq!(senders.len(), 1);
+
+        assert!(matches!(
+            rx.next().now_or_never(),
+            Some(Some(PeerEvent::ChainSpecMismatch { chain_index: 0, .. }))
+        ));
+        assert!(rx.next().now_or_never().is_none());
+
+        // Events reported after the channel has been emptied are delivered again.
+        report_peer_event(&mut senders, event(3));
+        assert!(matches!(
+            rx.next().now_or_never(),
+            Some(Some(PeerEvent::ChainSpecMismatch { chain_index: 3, .. }))
+        ));
+    }
+}
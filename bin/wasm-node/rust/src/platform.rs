@@ -0,0 +1,494 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Access to the time, timers, network connections, and embedder of the environment the client
+//! runs in.
+//!
+//! The services of this crate never query the clock, start timers, open connections, or
+//! communicate with the program that embeds the client by themselves. Instead, they go through
+//! the [`Platform`] trait, implemented on [`Host`]. [`Host`] is the only place where the
+//! implementation is chosen:
+//!
+//! - [`Wasm`], which relies on the functions provided by the JavaScript code through the
+//!   [`crate::ffi`] module, when compiling for the `wasm32` architecture.
+//! - `native::Native`, which relies on the operating system, when compiling for any other
+//!   architecture. The JSON-RPC clients are then served by the `standalone` module.
+//! - The virtual clock of [`crate::test_utils`], when compiling tests.
+//!
+//! Using the client in a different environment, or replaying a recorded execution with a
+//! virtual clock, consists in implementing [`Platform`] and pointing [`Host`] to it.
+
+use crate::ffi;
+
+use core::{
+    fmt,
+    future::Future,
+    num::NonZeroUsize,
+    ops::{Add, Sub},
+    pin::Pin,
+    time::Duration,
+};
+use futures::{future::BoxFuture, prelude::*};
+use smoldot::{header, libp2p::multiaddr};
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native;
+
+/// Source of time, of timers, and of network connections.
+pub trait Platform {
+    /// Future returned by [`Platform::sleep`].
+    type Delay: Future<Output = ()> + Unpin + Send + 'static;
+
+    /// Moment in time, according to a monotonic clock. Only meaningful when compared with
+    /// other values returned by [`Platform::now`].
+    type Instant: fmt::Debug
+        + Copy
+        + Ord
+        + Send
+        + Sync
+        + Add<Duration, Output = Self::Instant>
+        + Sub<Duration, Output = Self::Instant>
+        + Sub<Self::Instant, Output = Duration>
+        + 'static;
+
+    /// Returns the duration elapsed since the UNIX epoch, ignoring leap seconds.
+    ///
+    /// Contrary to [`Platform::now`], the value isn't guaranteed to be monotonic.
+    fn now_from_unix_epoch() -> Duration;
+
+    /// Returns the current moment, according to the monotonic clock.
+    fn now() -> Self::Instant;
+
+    /// Returns a future that completes after `duration` has elapsed.
+    fn sleep(duration: Duration) -> Self::Delay;
+
+    /// Connection opened with [`Platform::connect`]. Closed when dropped.
+    type Connection: Send + 'static;
+
+    /// Returns `true` if [`Platform::connect`] is capable of opening connections using this
+    /// transport.
+    fn supports_transport(transport: Transport) -> bool;
+
+    /// Starts connecting to the given multiaddress. The future returns an error message if the
+    /// connection couldn't be established.
+    ///
+    /// The multiaddress is expected to be in one of the formats recognized by
+    /// [`Transport::from_multiaddr`].
+    ///
+    /// The given limits apply to the data sent and received on the connection for its entire
+    /// lifetime. See [`ConnectionLimits`].
+    fn connect(
+        multiaddr: &str,
+        limits: ConnectionLimits,
+    ) -> BoxFuture<'static, Result<Self::Connection, String>>;
+
+    /// Returns a buffer containing data received on the connection.
+    ///
+    /// Never returns an empty buffer. If no data is available, waits until more data arrives.
+    /// Dropping the returned future before it completes doesn't lose any data.
+    ///
+    /// Returns `None` if the connection has been closed.
+    fn read_buffer(connection: &mut Self::Connection) -> BoxFuture<'_, Option<&[u8]>>;
+
+    /// Advances the read cursor by the given amount of bytes. The first `bytes` will no longer
+    /// be returned by [`Platform::read_buffer`] the next time it is called.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `bytes` is larger than the size of the buffer returned by
+    /// [`Platform::read_buffer`].
+    ///
+    fn advance_read_cursor(connection: &mut Self::Connection, bytes: usize);
+
+    /// Queues the given data for sending. For WebSocket connections, the data is sent as a
+    /// binary frame, or as multiple binary frames if it is larger than
+    /// [`ConnectionLimits::max_outbound_message_size`].
+    ///
+    /// Does nothing if the connection has been closed.
+    fn send(connection: &mut Self::Connection, data: &[u8]);
+
+    /// Writes out the given log message. See [`Logger`].
+    fn log(record: &log::Record);
+
+    /// Stops the execution of the client after an unrecoverable error, such as an invalid
+    /// configuration or a panic.
+    fn throw(message: String) -> !;
+
+    /// Waits for the next message coming from the JSON-RPC clients.
+    fn next_json_rpc() -> BoxFuture<'static, JsonRpcMessage>;
+
+    /// Sends a JSON-RPC response or notification to the JSON-RPC client identified by
+    /// `user_data`, as found in the [`JsonRpcMessage::Request`] that targeted this chain.
+    fn emit_json_rpc_response(response: &str, chain_index: usize, user_data: u32);
+
+    /// Sends a chunk of a JSON-RPC response to the JSON-RPC client identified by `user_data`.
+    /// The response consists of the concatenation of all the chunks, the last of which has
+    /// `is_final` set to `true`.
+    fn emit_json_rpc_response_chunk(
+        chunk: &str,
+        chain_index: usize,
+        user_data: u32,
+        is_final: bool,
+    );
+
+    /// Reports an event about the peers of the given chain. See the [`crate::events`] module.
+    fn emit_peer_event(event: &str, chain_index: usize);
+
+    /// Waits until a checkpoint of a chain is requested, and returns the index of this chain.
+    fn next_checkpoint_request() -> BoxFuture<'static, usize>;
+
+    /// Reports a checkpoint of the given chain, in the format of the database content.
+    fn emit_checkpoint(checkpoint: &str, chain_index: usize);
+
+    /// Sends an HTTP GET request to the given URL with the given `Accept` header. The future
+    /// returns the body of the response, or an error message on failure.
+    fn http_fetch(url: &str, accept: &str) -> BoxFuture<'static, Result<Vec<u8>, String>>;
+
+    /// Obtains the runtime code whose BLAKE2-256 hash is `code_hash`. The future returns the
+    /// code, which isn't verified to match the hash, or an error message on failure.
+    fn code_substitute_fetch(code_hash: &[u8; 32]) -> BoxFuture<'static, Result<Vec<u8>, String>>;
+
+    /// Verifies an sr25519 signature whose signature context is `b"substrate"`.
+    ///
+    /// Returns `None` if the platform doesn't provide this operation, in which case the
+    /// verification must be performed locally.
+    fn sr25519_verify(signature: &[u8; 64], message: &[u8], public_key: &[u8; 32]) -> Option<bool>;
+
+    /// Same as [`Platform::sr25519_verify`], but for the deprecated variant of sr25519 that
+    /// runtimes use through the `ext_crypto_sr25519_verify_version_1` host function.
+    fn sr25519_verify_deprecated(
+        signature: &[u8; 64],
+        message: &[u8],
+        public_key: &[u8; 32],
+    ) -> Option<bool>;
+
+    /// Verifies the VRF proof of a Babe header. See
+    /// [`smoldot::verify::Sr25519Hooks::babe_vrf_verify`].
+    ///
+    /// Returns `None` if the platform doesn't provide this operation, in which case the
+    /// verification must be performed locally.
+    fn babe_vrf_verify(input: smoldot::verify::babe::VrfVerifyRef) -> Option<Result<[u8; 16], ()>>;
+
+    /// Verifies the digest items of a header of the given chain that belong to one of the custom
+    /// consensus engines of this chain, and returns `false` if any of them is invalid.
+    ///
+    /// Only ever called with a non-empty list, and for chains that have been given a non-empty
+    /// list of custom consensus engines.
+    fn verify_custom_digest_items(chain_index: usize, items: &[header::CustomDigestItem]) -> bool;
+
+    /// Calculates the 32 bytes BLAKE2b hash of the given data.
+    fn blake2_256(data: &[u8]) -> [u8; 32];
+
+    /// Decompresses the given zstandard frames. See [`smoldot::network::protocol::ResponseDecompressor`].
+    ///
+    /// Returns `None` if the platform doesn't provide this operation, in which case the
+    /// decompression must be performed locally. Returns `Some(Err(()))` if the data is invalid or
+    /// decompresses to more than `max_size` bytes.
+    fn zstd_decompress(data: &[u8], max_size: usize) -> Option<Result<Vec<u8>, ()>>;
+}
+
+/// Message coming from the JSON-RPC clients. See [`Platform::next_json_rpc`].
+pub enum JsonRpcMessage {
+    /// New JSON-RPC request targeting the given chain.
+    Request {
+        json_rpc_request: Box<[u8]>,
+        chain_index: usize,
+        /// Opaque value identifying the JSON-RPC client that has sent the request, and that
+        /// must be passed back alongside the responses.
+        user_data: u32,
+    },
+    /// All the subscriptions of the given JSON-RPC client must be destroyed.
+    UnsubscribeAll { user_data: u32 },
+    /// The subscriptions of the given JSON-RPC client must be detached from it, and stored under
+    /// `token`.
+    DetachAll { user_data: u32, token: u32 },
+    /// The subscriptions stored under `token` must be attached to the given JSON-RPC client.
+    Reattach { token: u32, user_data: u32 },
+}
+
+/// Implementation of [`log::Log`] that writes out the logs through [`Host`].
+pub struct Logger;
+
+impl log::Log for Logger {
+    fn enabled(&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        Host::log(record)
+    }
+
+    fn flush(&self) {}
+}
+
+/// Limits applied to a connection opened with [`Platform::connect`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ConnectionLimits {
+    /// Maximum size, in bytes, of a message sent on the connection. For WebSocket connections,
+    /// each message is sent as a single frame.
+    ///
+    /// If `Some`, the data passed to [`Platform::send`] is split into multiple messages of at
+    /// most this size. This is only correct if the protocol running on top of the connection
+    /// treats the connection as a stream of bytes, such as libp2p, and not if it assigns a
+    /// meaning to the boundaries between messages, such as JSON-RPC. If `None`, the data passed
+    /// to [`Platform::send`] is always sent as a single message.
+    ///
+    /// Some WebSocket gateways silently drop the frames larger than a certain threshold, which
+    /// this limit makes it possible to stay under.
+    pub max_outbound_message_size: Option<NonZeroUsize>,
+
+    /// Maximum size, in bytes, of a message received on the connection. For WebSocket
+    /// connections, this is the size of a message once all its frames have been reassembled.
+    /// The connection is closed if a larger message is received.
+    pub max_inbound_message_size: usize,
+}
+
+impl ConnectionLimits {
+    /// Returns the list of messages that [`Platform::send`] must send in order to send `data`.
+    pub fn split_outbound<'a>(&self, data: &'a [u8]) -> impl Iterator<Item = &'a [u8]> {
+        // `chunks` panics if the size is 0, and never yields any chunk if `data` is empty.
+        let max_size = self
+            .max_outbound_message_size
+            .map_or(data.len(), NonZeroUsize::get)
+            .max(1);
+        data.chunks(max_size)
+    }
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        ConnectionLimits {
+            max_outbound_message_size: None,
+            max_inbound_message_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Kind of connection that [`Platform::connect`] can be asked to open.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Transport {
+    /// Plain TCP connection.
+    Tcp,
+    /// WebSocket connection, without TLS.
+    WebSocket,
+    /// WebSocket connection encrypted with TLS.
+    SecureWebSocket,
+}
+
+impl Transport {
+    /// Determines the transport that would be used in order to connect to the given address.
+    ///
+    /// Returns `None` if the address isn't in a format that [`Platform::connect`] is able to
+    /// connect to, whatever transports it supports.
+    pub fn from_multiaddr(addr: &multiaddr::Multiaddr) -> Option<Self> {
+        let mut iter = addr.iter();
+
+        match iter.next()? {
+            multiaddr::Protocol::Ip4(_)
+            | multiaddr::Protocol::Ip6(_)
+            | multiaddr::Protocol::Dns(_)
+            | multiaddr::Protocol::Dns4(_)
+            | multiaddr::Protocol::Dns6(_) => {}
+            _ => return None,
+        }
+
+        if !matches!(iter.next()?, multiaddr::Protocol::Tcp(_)) {
+            return None;
+        }
+
+        let transport = match iter.next() {
+            None => Transport::Tcp,
+            Some(multiaddr::Protocol::Ws(path)) if path == "/" => Transport::WebSocket,
+            Some(multiaddr::Protocol::Wss(path)) if path == "/" => Transport::SecureWebSocket,
+            Some(_) => return None,
+        };
+
+        if iter.next().is_some() {
+            return None;
+        }
+
+        Some(transport)
+    }
+}
+
+/// Implementation of [`Platform`] that uses the functions provided by the JavaScript code.
+#[cfg_attr(not(all(not(test), target_arch = "wasm32")), allow(dead_code))]
+pub enum Wasm {}
+
+impl Platform for Wasm {
+    type Delay = ffi::Delay;
+    type Instant = ffi::Instant;
+
+    fn now_from_unix_epoch() -> Duration {
+        ffi::unix_time()
+    }
+
+    fn now() -> Self::Instant {
+        ffi::Instant::now()
+    }
+
+    fn sleep(duration: Duration) -> Self::Delay {
+        ffi::Delay::new(duration)
+    }
+
+    type Connection = Pin<Box<ffi::Connection>>;
+
+    fn supports_transport(transport: Transport) -> bool {
+        ffi::is_transport_supported(transport)
+    }
+
+    fn connect(
+        multiaddr: &str,
+        limits: ConnectionLimits,
+    ) -> BoxFuture<'static, Result<Self::Connection, String>> {
+        Box::pin(ffi::Connection::connect(multiaddr, limits))
+    }
+
+    fn read_buffer(connection: &mut Self::Connection) -> BoxFuture<'_, Option<&[u8]>> {
+        Box::pin(connection.read_buffer())
+    }
+
+    fn advance_read_cursor(connection: &mut Self::Connection, bytes: usize) {
+        connection.advance_read_cursor(bytes)
+    }
+
+    fn send(connection: &mut Self::Connection, data: &[u8]) {
+        connection.send(data)
+    }
+
+    fn log(record: &log::Record) {
+        ffi::log(record)
+    }
+
+    fn throw(message: String) -> ! {
+        ffi::throw(message)
+    }
+
+    fn next_json_rpc() -> BoxFuture<'static, JsonRpcMessage> {
+        Box::pin(ffi::next_json_rpc())
+    }
+
+    fn emit_json_rpc_response(response: &str, chain_index: usize, user_data: u32) {
+        ffi::emit_json_rpc_response(response, chain_index, user_data)
+    }
+
+    fn emit_json_rpc_response_chunk(
+        chunk: &str,
+        chain_index: usize,
+        user_data: u32,
+        is_final: bool,
+    ) {
+        ffi::emit_json_rpc_response_chunk(chunk, chain_index, user_data, is_final)
+    }
+
+    fn emit_peer_event(event: &str, chain_index: usize) {
+        ffi::emit_peer_event(event, chain_index)
+    }
+
+    fn next_checkpoint_request() -> BoxFuture<'static, usize> {
+        Box::pin(ffi::next_checkpoint_request())
+    }
+
+    fn emit_checkpoint(checkpoint: &str, chain_index: usize) {
+        ffi::emit_checkpoint(checkpoint, chain_index)
+    }
+
+    fn http_fetch(url: &str, accept: &str) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        ffi::http_fetch(url, accept).boxed()
+    }
+
+    fn code_substitute_fetch(code_hash: &[u8; 32]) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        ffi::code_substitute_fetch(code_hash).boxed()
+    }
+
+    fn sr25519_verify(signature: &[u8; 64], message: &[u8], public_key: &[u8; 32]) -> Option<bool> {
+        ffi::host_sr25519_verify(signature, message, public_key)
+    }
+
+    fn sr25519_verify_deprecated(
+        signature: &[u8; 64],
+        message: &[u8],
+        public_key: &[u8; 32],
+    ) -> Option<bool> {
+        ffi::host_sr25519_verify_deprecated(signature, message, public_key)
+    }
+
+    fn babe_vrf_verify(input: smoldot::verify::babe::VrfVerifyRef) -> Option<Result<[u8; 16], ()>> {
+        ffi::host_babe_vrf_verify(input)
+    }
+
+    fn verify_custom_digest_items(chain_index: usize, items: &[header::CustomDigestItem]) -> bool {
+        ffi::verify_custom_digest_items(chain_index, items)
+    }
+
+    fn blake2_256(data: &[u8]) -> [u8; 32] {
+        ffi::blake2_256(data)
+    }
+
+    fn zstd_decompress(data: &[u8], max_size: usize) -> Option<Result<Vec<u8>, ()>> {
+        ffi::host_zstd_decompress(data, max_size)
+    }
+}
+
+/// Implementation of [`Platform`] used by all the services of this crate.
+#[cfg(all(not(test), target_arch = "wasm32"))]
+pub type Host = Wasm;
+
+/// Implementation of [`Platform`] used by all the services of this crate.
+#[cfg(all(not(test), not(target_arch = "wasm32")))]
+pub type Host = native::Native;
+
+/// Implementation of [`Platform`] used by all the services of this crate.
+#[cfg(test)]
+pub type Host = crate::test_utils::Virtual;
+
+/// Moment in time according to [`Host`]. See [`Platform::Instant`].
+pub type Instant = <Host as Platform>::Instant;
+
+/// Future returned by [`Platform::sleep`] for [`Host`].
+pub type Delay = <Host as Platform>::Delay;
+
+/// Connection opened by [`Host`]. See [`Platform::Connection`].
+pub type Connection = <Host as Platform>::Connection;
+
+#[cfg(test)]
+mod tests {
+    use super::ConnectionLimits;
+    use core::num::NonZeroUsize;
+
+    #[test]
+    fn split_outbound() {
+        let data = [0u8; 10];
+
+        let limits = ConnectionLimits {
+            max_outbound_message_size: NonZeroUsize::new(4),
+            ..Default::default()
+        };
+        let messages = limits.split_outbound(&data).collect::<Vec<_>>();
+        assert_eq!(
+            messages.iter().map(|m| m.len()).collect::<Vec<_>>(),
+            [4, 4, 2]
+        );
+
+        let messages = ConnectionLimits::default()
+            .split_outbound(&data)
+            .collect::<Vec<_>>();
+        assert_eq!(messages, [&data[..]]);
+
+        assert_eq!(limits.split_outbound(&[]).count(), 0);
+    }
+}
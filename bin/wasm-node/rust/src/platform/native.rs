@@ -0,0 +1,444 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Implementation of [`Platform`] for targets other than `wasm32`, relying on the operating
+//! system.
+//!
+//! Connections are opened using the TCP stack of the operating system, and WebSocket
+//! connections, without TLS, on top of it. As TLS isn't supported, `/wss` addresses can't be
+//! connected to. The client refuses to start a chain whose bootnodes all have such an address,
+//! and ignores these bootnodes otherwise. Each connection is driven by two background tasks,
+//! one reading from the socket and one writing to it, spawned on the `async-std` executor.
+//!
+//! The [`ConnectionLimits`] of a connection are enforced by [`Native::send`] for outbound data,
+//! and by the reading task for inbound data.
+//!
+//! The JSON-RPC clients are connected to the client with [`register_json_rpc_client`] and
+//! [`send_json_rpc`], which is what the `standalone` module does. Logs are written to the
+//! standard error output. There is no way to request checkpoints, and the peer events and
+//! checkpoints are discarded.
+
+// When compiling tests, the virtual clock of `test_utils` is used instead.
+#![cfg_attr(test, allow(dead_code))]
+
+use super::{ConnectionLimits, JsonRpcMessage, Platform, Transport};
+
+use async_std::net::TcpStream;
+use core::{fmt, mem, time::Duration};
+use futures::{channel::mpsc, future::BoxFuture, prelude::*};
+use smoldot::{
+    header,
+    libp2p::multiaddr::{Multiaddr, Protocol},
+};
+use std::{collections::HashMap, net, sync::Mutex, time};
+
+/// Implementation of [`Platform`] that relies on the operating system.
+pub enum Native {}
+
+impl Platform for Native {
+    type Delay = futures_timer::Delay;
+    type Instant = time::Instant;
+
+    fn now_from_unix_epoch() -> Duration {
+        // A system clock set before 1970 is treated as if it was set to 1970.
+        time::SystemTime::now()
+            .duration_since(time::UNIX_EPOCH)
+            .unwrap_or_else(|_| Duration::new(0, 0))
+    }
+
+    fn now() -> Self::Instant {
+        time::Instant::now()
+    }
+
+    fn sleep(duration: Duration) -> Self::Delay {
+        futures_timer::Delay::new(duration)
+    }
+
+    type Connection = Connection;
+
+    fn supports_transport(transport: Transport) -> bool {
+        match transport {
+            Transport::Tcp | Transport::WebSocket => true,
+            // TODO: support TLS
+            Transport::SecureWebSocket => false,
+        }
+    }
+
+    fn connect(
+        multiaddr: &str,
+        limits: ConnectionLimits,
+    ) -> BoxFuture<'static, Result<Self::Connection, String>> {
+        let multiaddr = match multiaddr.parse::<Multiaddr>() {
+            Ok(a) => a,
+            Err(err) => return Box::pin(future::ready(Err(err.to_string()))),
+        };
+
+        Box::pin(Connection::connect(multiaddr, limits))
+    }
+
+    fn read_buffer(connection: &mut Self::Connection) -> BoxFuture<'_, Option<&[u8]>> {
+        Box::pin(connection.read_buffer())
+    }
+
+    fn advance_read_cursor(connection: &mut Self::Connection, bytes: usize) {
+        connection.read_cursor += bytes;
+        assert!(connection.read_cursor <= connection.read_buffer.len());
+    }
+
+    fn send(connection: &mut Self::Connection, data: &[u8]) {
+        for message in connection.limits.split_outbound(data) {
+            // An error is returned if the writing task has stopped, in other words if the
+            // connection has been closed.
+            let _ = connection.outgoing.unbounded_send(message.to_vec());
+        }
+    }
+
+    fn log(record: &log::Record) {
+        eprintln!(
+            "[{}] {}: {}",
+            record.level(),
+            record.target(),
+            record.args()
+        );
+    }
+
+    fn throw(message: String) -> ! {
+        eprintln!("{}", message);
+        std::process::exit(1)
+    }
+
+    fn next_json_rpc() -> BoxFuture<'static, JsonRpcMessage> {
+        Box::pin(async move {
+            let mut lock = JSON_RPC_REQUESTS.1.lock().await;
+            lock.next().await.unwrap()
+        })
+    }
+
+    fn emit_json_rpc_response(response: &str, _: usize, user_data: u32) {
+        let clients = JSON_RPC_CLIENTS.lock().unwrap();
+        if let Some(client) = clients.get(&user_data) {
+            let _ = client.responses.unbounded_send(response.to_owned());
+        }
+    }
+
+    fn emit_json_rpc_response_chunk(chunk: &str, _: usize, user_data: u32, is_final: bool) {
+        let mut clients = JSON_RPC_CLIENTS.lock().unwrap();
+        if let Some(client) = clients.get_mut(&user_data) {
+            client.partial_response.push_str(chunk);
+            if is_final {
+                let response = mem::take(&mut client.partial_response);
+                let _ = client.responses.unbounded_send(response);
+            }
+        }
+    }
+
+    fn emit_peer_event(_: &str, _: usize) {}
+
+    fn next_checkpoint_request() -> BoxFuture<'static, usize> {
+        Box::pin(future::pending())
+    }
+
+    fn emit_checkpoint(_: &str, _: usize) {}
+
+    fn http_fetch(_: &str, _: &str) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        // TODO: support HTTP requests, which requires TLS
+        Box::pin(future::ready(Err(
+            "HTTP requests aren't supported on this platform".to_owned(),
+        )))
+    }
+
+    fn code_substitute_fetch(_: &[u8; 32]) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        Box::pin(future::ready(Err(
+            "Code substitutes must be included in the chain specification on this platform"
+                .to_owned(),
+        )))
+    }
+
+    fn sr25519_verify(_: &[u8; 64], _: &[u8], _: &[u8; 32]) -> Option<bool> {
+        None
+    }
+
+    fn sr25519_verify_deprecated(_: &[u8; 64], _: &[u8], _: &[u8; 32]) -> Option<bool> {
+        None
+    }
+
+    fn babe_vrf_verify(_: smoldot::verify::babe::VrfVerifyRef) -> Option<Result<[u8; 16], ()>> {
+        None
+    }
+
+    fn verify_custom_digest_items(_: usize, _: &[header::CustomDigestItem]) -> bool {
+        // The standalone binary never configures custom consensus engines, and this function is
+        // thus never called.
+        false
+    }
+
+    fn blake2_256(data: &[u8]) -> [u8; 32] {
+        let mut out = [0; 32];
+        out.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes());
+        out
+    }
+
+    fn zstd_decompress(_: &[u8], _: usize) -> Option<Result<Vec<u8>, ()>> {
+        None
+    }
+}
+
+lazy_static::lazy_static! {
+    /// Messages passed to [`send_json_rpc`], and returned by [`Native::next_json_rpc`].
+    static ref JSON_RPC_REQUESTS: (mpsc::UnboundedSender<JsonRpcMessage>, futures::lock::Mutex<mpsc::UnboundedReceiver<JsonRpcMessage>>) = {
+        let (tx, rx) = mpsc::unbounded();
+        (tx, futures::lock::Mutex::new(rx))
+    };
+
+    /// JSON-RPC clients registered with [`register_json_rpc_client`], indexed by their
+    /// `user_data`.
+    static ref JSON_RPC_CLIENTS: Mutex<HashMap<u32, JsonRpcClient>> = Mutex::new(HashMap::new());
+}
+
+/// See [`JSON_RPC_CLIENTS`].
+struct JsonRpcClient {
+    /// Channel where to send the responses and notifications destined to this client.
+    responses: mpsc::UnboundedSender<String>,
+    /// Concatenation of the chunks of the response being emitted with
+    /// [`Native::emit_json_rpc_response_chunk`].
+    partial_response: String,
+}
+
+/// Sends a message coming from a JSON-RPC client. See [`Platform::next_json_rpc`].
+pub fn send_json_rpc(message: JsonRpcMessage) {
+    JSON_RPC_REQUESTS.0.unbounded_send(message).unwrap();
+}
+
+/// Registers a JSON-RPC client identified by `user_data`, and returns the receiving side of the
+/// responses and notifications destined to it. The client is forgotten once the returned receiver
+/// has been destroyed.
+///
+/// # Panic
+///
+/// Panics if a client with the same `user_data` is already registered.
+///
+pub fn register_json_rpc_client(user_data: u32) -> mpsc::UnboundedReceiver<String> {
+    let (responses, rx) = mpsc::unbounded();
+    let mut clients = JSON_RPC_CLIENTS.lock().unwrap();
+    clients.retain(|_, client| !client.responses.is_closed());
+    let previous = clients.insert(
+        user_data,
+        JsonRpcClient {
+            responses,
+            partial_response: String::new(),
+        },
+    );
+    assert!(previous.is_none());
+    rx
+}
+
+/// Connection opened by [`Native::connect`].
+pub struct Connection {
+    /// Clone of the socket, used to shut it down when the [`Connection`] is destroyed. This
+    /// stops the background tasks.
+    socket: TcpStream,
+    /// Data received by the reading task. Never contains empty buffers. Closed when the
+    /// connection has been closed.
+    incoming: mpsc::Receiver<Vec<u8>>,
+    /// Buffer returned by [`Native::read_buffer`]. Taken from [`Connection::incoming`].
+    read_buffer: Vec<u8>,
+    /// Position of the read cursor within [`Connection::read_buffer`].
+    read_cursor: usize,
+    /// True if [`Connection::incoming`] has been closed.
+    closed: bool,
+    /// Data to send by the writing task. Each element is sent as a separate WebSocket frame.
+    outgoing: mpsc::UnboundedSender<Vec<u8>>,
+    /// Limits passed to [`Native::connect`].
+    limits: ConnectionLimits,
+}
+
+impl Connection {
+    async fn connect(multiaddr: Multiaddr, limits: ConnectionLimits) -> Result<Self, String> {
+        let transport = match Transport::from_multiaddr(&multiaddr) {
+            Some(Transport::SecureWebSocket) => {
+                return Err(format!(
+                    "TLS isn't supported, can't connect to {}",
+                    multiaddr
+                ))
+            }
+            Some(t) if Native::supports_transport(t) => t,
+            _ => return Err(format!("Unsupported multiaddress: {}", multiaddr)),
+        };
+
+        let mut iter = multiaddr.iter();
+        let (host, port) = match (iter.next(), iter.next()) {
+            (Some(Protocol::Ip4(ip)), Some(Protocol::Tcp(port))) => (ip.to_string(), port),
+            (Some(Protocol::Ip6(ip)), Some(Protocol::Tcp(port))) => (ip.to_string(), port),
+            // TODO: differences between DNS, DNS4, DNS6 not respected
+            (Some(Protocol::Dns(addr)), Some(Protocol::Tcp(port)))
+            | (Some(Protocol::Dns4(addr)), Some(Protocol::Tcp(port)))
+            | (Some(Protocol::Dns6(addr)), Some(Protocol::Tcp(port))) => (addr.into_owned(), port),
+            // Guaranteed by `Transport::from_multiaddr`.
+            _ => unreachable!(),
+        };
+
+        let socket = TcpStream::connect((&*host, port))
+            .await
+            .map_err(|err| err.to_string())?;
+        // Nagle's algorithm would delay the small messages of the networking protocols.
+        let _ = socket.set_nodelay(true);
+
+        let (incoming_tx, incoming) = mpsc::channel(8);
+        let (outgoing, outgoing_rx) = mpsc::unbounded();
+
+        match transport {
+            // TCP connections have no notion of message, and the reading task instead reads
+            // buffers of fixed size.
+            Transport::Tcp => {
+                async_std::task::spawn(tcp_reader(socket.clone(), incoming_tx));
+                async_std::task::spawn(tcp_writer(socket.clone(), outgoing_rx));
+            }
+            Transport::WebSocket => {
+                let host_header = if host.contains(':') {
+                    format!("[{}]:{}", host, port)
+                } else {
+                    format!("{}:{}", host, port)
+                };
+
+                let mut client = soketto::handshake::Client::new(socket.clone(), &host_header, "/");
+                match client.handshake().await.map_err(|err| err.to_string())? {
+                    soketto::handshake::ServerResponse::Accepted { .. } => {}
+                    response => return Err(format!("WebSocket handshake failed: {:?}", response)),
+                }
+
+                let mut builder = client.into_builder();
+                // Frames can't be larger than the messages they belong to.
+                builder.set_max_message_size(limits.max_inbound_message_size);
+                builder.set_max_frame_size(limits.max_inbound_message_size);
+                let (sender, receiver) = builder.finish();
+                async_std::task::spawn(websocket_reader(receiver, incoming_tx));
+                async_std::task::spawn(websocket_writer(sender, outgoing_rx));
+            }
+            Transport::SecureWebSocket => unreachable!(),
+        }
+
+        Ok(Connection {
+            socket,
+            incoming,
+            read_buffer: Vec::new(),
+            read_cursor: 0,
+            closed: false,
+            outgoing,
+            limits,
+        })
+    }
+
+    async fn read_buffer(&mut self) -> Option<&[u8]> {
+        if self.read_cursor == self.read_buffer.len() && !self.closed {
+            // Reading from the channel can be interrupted without losing data.
+            match self.incoming.next().await {
+                Some(buffer) => {
+                    debug_assert!(!buffer.is_empty());
+                    self.read_buffer = buffer;
+                    self.read_cursor = 0;
+                }
+                None => self.closed = true,
+            }
+        }
+
+        if self.read_cursor == self.read_buffer.len() {
+            debug_assert!(self.closed);
+            None
+        } else {
+            Some(&self.read_buffer[self.read_cursor..])
+        }
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Connection")
+            .field(&self.socket.peer_addr().ok())
+            .finish()
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.socket.shutdown(net::Shutdown::Both);
+    }
+}
+
+/// Background task reading from a TCP socket and sending the data on `incoming`.
+async fn tcp_reader(mut socket: TcpStream, mut incoming: mpsc::Sender<Vec<u8>>) {
+    loop {
+        let mut buffer = vec![0; 16384];
+        match socket.read(&mut buffer).await {
+            Ok(0) | Err(_) => break,
+            Ok(n) => buffer.truncate(n),
+        }
+
+        if incoming.send(buffer).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Background task writing to a TCP socket the data received on `outgoing`.
+async fn tcp_writer(mut socket: TcpStream, mut outgoing: mpsc::UnboundedReceiver<Vec<u8>>) {
+    while let Some(data) = outgoing.next().await {
+        if socket.write_all(&data).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Background task reading WebSocket messages and sending their content on `incoming`.
+///
+/// Stops, closing the connection, if a message larger than the limit configured on `receiver`
+/// is received.
+async fn websocket_reader(
+    mut receiver: soketto::connection::Receiver<TcpStream>,
+    mut incoming: mpsc::Sender<Vec<u8>>,
+) {
+    loop {
+        let mut buffer = Vec::new();
+        if receiver.receive_data(&mut buffer).await.is_err() {
+            break;
+        }
+
+        if buffer.is_empty() {
+            continue;
+        }
+
+        if incoming.send(buffer).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Background task sending the data received on `outgoing` as binary WebSocket frames.
+async fn websocket_writer(
+    mut sender: soketto::connection::Sender<TcpStream>,
+    mut outgoing: mpsc::UnboundedReceiver<Vec<u8>>,
+) {
+    while let Some(data) = outgoing.next().await {
+        if sender.send_binary_mut(data).await.is_err() {
+            return;
+        }
+        if sender.flush().await.is_err() {
+            return;
+        }
+    }
+
+    let _ = sender.close().await;
+}
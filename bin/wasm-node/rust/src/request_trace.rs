@@ -0,0 +1,277 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Timelines of the work performed in order to answer JSON-RPC requests.
+//!
+//! Each JSON-RPC request is assigned a [`RequestTrace`] with an identifier unique within the
+//! chain. The trace is passed down to the services that the request goes through, which record
+//! in it the network requests that they emit and the time spent executing the runtime. The log
+//! lines related to the request mention the identifier of its trace.
+//!
+//! The traces of the most recent requests are kept in a [`RecentTraces`], and can be retrieved
+//! through the `system_unstable_requestTraces` JSON-RPC method in order to diagnose slow
+//! requests.
+
+use crate::platform::{self, Host, Platform as _};
+
+use core::{num::NonZeroUsize, time::Duration};
+use smoldot::libp2p::PeerId;
+use std::{
+    collections::VecDeque,
+    sync::{atomic, Arc, Mutex},
+};
+
+/// Traces of the most recent JSON-RPC requests of a chain.
+pub struct RecentTraces {
+    /// Identifier to assign to the next trace.
+    next_id: atomic::AtomicU64,
+    /// Traces, from the oldest to the most recent.
+    traces: Mutex<VecDeque<Arc<RequestTrace>>>,
+    /// Maximum number of traces in [`RecentTraces::traces`].
+    capacity: NonZeroUsize,
+}
+
+impl RecentTraces {
+    /// Initializes a new empty list. Only the `capacity` most recent traces are kept.
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        RecentTraces {
+            next_id: atomic::AtomicU64::new(0),
+            traces: Mutex::new(VecDeque::with_capacity(capacity.get())),
+            capacity,
+        }
+    }
+
+    /// Starts tracing a new request. `method` is the name of the JSON-RPC method, and
+    /// `request_id_json` the JSON-encoded identifier of the request chosen by the client.
+    pub fn start(&self, method: &'static str, request_id_json: &str) -> Arc<RequestTrace> {
+        let trace = Arc::new(RequestTrace {
+            id: self.next_id.fetch_add(1, atomic::Ordering::Relaxed),
+            method,
+            request_id_json: request_id_json.to_owned(),
+            start: Host::now(),
+            inner: Mutex::new(TraceInner {
+                events: Vec::new(),
+                duration: None,
+            }),
+        });
+
+        let mut traces = self.traces.lock().unwrap();
+        if traces.len() >= self.capacity.get() {
+            traces.pop_front();
+        }
+        traces.push_back(trace.clone());
+
+        trace
+    }
+
+    /// Returns the list of traces, from the oldest to the most recent.
+    pub fn list(&self) -> Vec<Arc<RequestTrace>> {
+        self.traces.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// Timeline of a single JSON-RPC request.
+pub struct RequestTrace {
+    /// See [`RequestTrace::id`].
+    id: u64,
+    /// See [`RequestTrace::method`].
+    method: &'static str,
+    /// See [`RequestTrace::request_id_json`].
+    request_id_json: String,
+    /// When the request has started being processed.
+    start: platform::Instant,
+    inner: Mutex<TraceInner>,
+}
+
+struct TraceInner {
+    /// Events recorded so far, ordered by end time.
+    events: Vec<TraceEvent>,
+    /// Time it took to process the request, or `None` if still in progress.
+    duration: Option<Duration>,
+}
+
+impl RequestTrace {
+    /// Returns the identifier of this trace, which is mentioned in the log lines.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the name of the JSON-RPC method of the request.
+    pub fn method(&self) -> &'static str {
+        self.method
+    }
+
+    /// Returns the JSON-encoded identifier of the request chosen by the JSON-RPC client.
+    pub fn request_id_json(&self) -> &str {
+        &self.request_id_json
+    }
+
+    /// Returns the time it took to process the request, or `None` if the request is still
+    /// being processed.
+    pub fn duration(&self) -> Option<Duration> {
+        self.inner.lock().unwrap().duration
+    }
+
+    /// Returns the events recorded so far, ordered by end time.
+    pub fn events(&self) -> Vec<TraceEvent> {
+        self.inner.lock().unwrap().events.clone()
+    }
+
+    /// Marks the request as processed.
+    ///
+    /// Requests that start a subscription are considered processed once the subscription has
+    /// been set up.
+    pub fn finish(&self) {
+        let duration = Host::now() - self.start;
+        log::debug!(
+            target: "json-rpc",
+            "Trace({}) => {} finished in {:?}", self.id, self.method, duration
+        );
+        self.inner.lock().unwrap().duration = Some(duration);
+    }
+
+    /// Records a network request started at `start` and just finished. `response_size` is the
+    /// size in bytes of the response, or `None` if the request has failed.
+    pub fn record_network_request(
+        &self,
+        protocol: &'static str,
+        peer_id: &PeerId,
+        start: platform::Instant,
+        response_size: Option<usize>,
+    ) {
+        log::debug!(
+            target: "json-rpc",
+            "Trace({}) <= {}({}) => {}", self.id, protocol, peer_id,
+            match response_size {
+                Some(size) => format!("{} bytes", size),
+                None => "failed".to_owned(),
+            }
+        );
+
+        self.record(
+            start,
+            TraceEventKind::NetworkRequest {
+                protocol,
+                peer_id: peer_id.clone(),
+                response_size,
+            },
+        );
+    }
+
+    /// Starts measuring an execution of the runtime. The event is recorded when the returned
+    /// object is destroyed.
+    pub fn measure_runtime_execution(&self, function: &str) -> RuntimeExecution<'_> {
+        RuntimeExecution {
+            trace: self,
+            function: function.to_owned(),
+            start: Host::now(),
+        }
+    }
+
+    fn record(&self, start: platform::Instant, kind: TraceEventKind) {
+        let now = Host::now();
+        self.inner.lock().unwrap().events.push(TraceEvent {
+            start: start - self.start,
+            duration: now - start,
+            kind,
+        });
+    }
+}
+
+/// Event recorded in a [`RequestTrace`].
+#[derive(Debug, Clone)]
+pub struct TraceEvent {
+    /// Time between the start of the request and the start of the event.
+    pub start: Duration,
+    /// Duration of the event.
+    pub duration: Duration,
+    /// What happened.
+    pub kind: TraceEventKind,
+}
+
+/// See [`TraceEvent::kind`].
+#[derive(Debug, Clone)]
+pub enum TraceEventKind {
+    /// Network request sent to a peer.
+    NetworkRequest {
+        /// Short name of the kind of request, such as `storage-proof`.
+        protocol: &'static str,
+        /// Peer the request has been sent to.
+        peer_id: PeerId,
+        /// Size in bytes of the response, or `None` if the request has failed.
+        response_size: Option<usize>,
+    },
+    /// Execution of the runtime.
+    RuntimeExecution {
+        /// Name of the runtime function that has been called.
+        function: String,
+    },
+}
+
+/// Measurement in progress. See [`RequestTrace::measure_runtime_execution`].
+#[must_use]
+pub struct RuntimeExecution<'a> {
+    trace: &'a RequestTrace,
+    function: String,
+    start: platform::Instant,
+}
+
+impl<'a> Drop for RuntimeExecution<'a> {
+    fn drop(&mut self) {
+        let function = core::mem::take(&mut self.function);
+        self.trace
+            .record(self.start, TraceEventKind::RuntimeExecution { function });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RecentTraces, TraceEventKind};
+    use crate::test_utils;
+    use core::{num::NonZeroUsize, time::Duration};
+
+    #[test]
+    fn records_timeline() {
+        let traces = RecentTraces::new(NonZeroUsize::new(2).unwrap());
+
+        let trace = traces.start("state_call", "1");
+        test_utils::advance(Duration::from_millis(5));
+        {
+            let _measure = trace.measure_runtime_execution("Core_version");
+            test_utils::advance(Duration::from_millis(3));
+        }
+        assert!(trace.duration().is_none());
+        trace.finish();
+
+        assert_eq!(trace.duration(), Some(Duration::from_millis(8)));
+        let events = trace.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].start, Duration::from_millis(5));
+        assert_eq!(events[0].duration, Duration::from_millis(3));
+        assert!(matches!(
+            &events[0].kind,
+            TraceEventKind::RuntimeExecution { function } if function == "Core_version"
+        ));
+
+        // Only the two most recent traces are kept.
+        let second = traces.start("system_health", "2");
+        let third = traces.start("system_health", "\"foo\"");
+        let ids = traces.list().iter().map(|t| t.id()).collect::<Vec<_>>();
+        assert_eq!(ids, vec![second.id(), third.id()]);
+        assert_eq!(third.request_id_json(), "\"foo\"");
+    }
+}
@@ -0,0 +1,361 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fallback data source answering storage queries and runtime calls through the JSON-RPC server
+//! of a trusted full node.
+//!
+//! Peers of the network typically only keep the storage of recent blocks. When a storage query
+//! or a runtime call can't be answered by any peer, for example because it targets an old block,
+//! the JSON-RPC service can instead forward it to the full node found at [`Config::address`].
+//!
+//! Contrary to the data obtained from peers, the data obtained through this module isn't
+//! accompanied with a proof and can't be verified. The JSON-RPC responses containing such data
+//! are built with [`smoldot::json_rpc::parse::build_unverified_success_response`], so that
+//! JSON-RPC clients can tell them apart.
+//!
+//! > **Note**: The requests that are forwarded are revealed to the full node.
+
+use crate::{
+    cross_validation::extract_values,
+    platform::{self, Host, Platform as _},
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
+use smoldot::json_rpc::methods;
+use std::{collections::HashMap, pin::Pin, time::Duration};
+
+/// Configuration for the fallback data source.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Multiaddress of the WebSocket JSON-RPC server of the trusted full node, for example
+    /// `/dns/rpc.example.com/tcp/443/wss`. See [`platform::Transport::from_multiaddr`].
+    ///
+    /// The requests are sent as binary WebSocket frames.
+    pub address: String,
+}
+
+/// Duration after which a request is considered failed if the full node hasn't answered it.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Handle to a background task that forwards requests to a trusted full node.
+pub struct RpcFallback {
+    /// Channel to the background task.
+    to_background: mpsc::UnboundedSender<Request>,
+}
+
+impl RpcFallback {
+    /// Spawns the background task of the fallback data source. `log_name` is the name of the
+    /// chain, used in the log messages.
+    pub fn new(
+        config: Config,
+        log_name: String,
+        tasks_executor: &mut dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>),
+    ) -> Self {
+        let (to_background, from_foreground) = mpsc::unbounded();
+
+        tasks_executor(
+            "rpc-fallback".into(),
+            Box::pin(run_background(config.address, log_name, from_foreground)),
+        );
+
+        RpcFallback { to_background }
+    }
+
+    /// Queries the storage value of the given key at the given block.
+    ///
+    /// Returns `Ok(None)` if the storage doesn't contain any value for this key.
+    pub async fn storage_query(
+        &self,
+        block_hash: &[u8; 32],
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, FallbackError> {
+        let result = self
+            .request(
+                "state_getStorage",
+                serde_json::to_value((
+                    methods::HexString(key.to_vec()),
+                    methods::HashHexString(*block_hash),
+                ))
+                .unwrap(),
+            )
+            .await?;
+
+        serde_json::from_value::<Option<methods::HexString>>(result)
+            .map(|value| value.map(|value| value.0))
+            .map_err(|_| FallbackError::InvalidResponse)
+    }
+
+    /// Calls the given runtime entry point at the given block, and returns the return value of
+    /// the call.
+    pub async fn runtime_call(
+        &self,
+        block_hash: &[u8; 32],
+        method: &str,
+        parameters: &[u8],
+    ) -> Result<Vec<u8>, FallbackError> {
+        let result = self
+            .request(
+                "state_call",
+                serde_json::to_value((
+                    method,
+                    methods::HexString(parameters.to_vec()),
+                    methods::HashHexString(*block_hash),
+                ))
+                .unwrap(),
+            )
+            .await?;
+
+        serde_json::from_value::<methods::HexString>(result)
+            .map(|value| value.0)
+            .map_err(|_| FallbackError::InvalidResponse)
+    }
+
+    /// Sends a request to the full node and waits for its result.
+    async fn request(
+        &self,
+        method: &'static str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, FallbackError> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .unbounded_send(Request {
+                method,
+                params,
+                send_back,
+            })
+            .map_err(|_| FallbackError::Disconnected)?;
+
+        match future::select(rx, Host::sleep(REQUEST_TIMEOUT)).await {
+            future::Either::Left((Ok(result), _)) => result,
+            future::Either::Left((Err(_), _)) => Err(FallbackError::Disconnected),
+            future::Either::Right(((), _)) => Err(FallbackError::Timeout),
+        }
+    }
+}
+
+/// Error potentially returned by the methods of [`RpcFallback`].
+#[derive(Debug, derive_more::Display)]
+pub enum FallbackError {
+    /// Failed to connect to the full node.
+    #[display(fmt = "Failed to connect to the trusted JSON-RPC server: {}", _0)]
+    Connection(String),
+    /// The connection to the full node has been closed before the response was received.
+    #[display(fmt = "Connection to the trusted JSON-RPC server closed")]
+    Disconnected,
+    /// The full node didn't answer in time.
+    #[display(fmt = "Timeout while waiting for the trusted JSON-RPC server")]
+    Timeout,
+    /// The full node has answered with an error.
+    #[display(fmt = "Trusted JSON-RPC server returned an error: {}", _0)]
+    ErrorResponse(String),
+    /// The response of the full node couldn't be decoded.
+    #[display(fmt = "Invalid response from the trusted JSON-RPC server")]
+    InvalidResponse,
+}
+
+/// Request sent from the foreground to the background task.
+struct Request {
+    method: &'static str,
+    params: serde_json::Value,
+    send_back: oneshot::Sender<Result<serde_json::Value, FallbackError>>,
+}
+
+async fn run_background(
+    address: String,
+    log_name: String,
+    mut from_foreground: mpsc::UnboundedReceiver<Request>,
+) {
+    // Connection to the full node. Opened when the first request is sent, and re-opened after
+    // it has been closed when the next request is sent.
+    let mut connection: Option<platform::Connection> = None;
+    // Data received from the full node and not yet parsed.
+    let mut received = Vec::new();
+
+    // Requests sent to the full node and not answered yet, indexed by their identifier.
+    // Dropping a sender notifies the foreground that the request has failed.
+    let mut pending =
+        HashMap::<u64, oneshot::Sender<Result<serde_json::Value, FallbackError>>>::new();
+    let mut next_request_id: u64 = 0;
+
+    loop {
+        enum Event {
+            Foreground(Request),
+            Received(usize),
+            Closed,
+        }
+
+        let event = match connection.as_mut() {
+            Some(connection) => {
+                match future::select(from_foreground.next(), Host::read_buffer(connection)).await {
+                    future::Either::Left((Some(request), _)) => Event::Foreground(request),
+                    future::Either::Left((None, _)) => return,
+                    future::Either::Right((Some(buffer), _)) => {
+                        received.extend_from_slice(buffer);
+                        Event::Received(buffer.len())
+                    }
+                    future::Either::Right((None, _)) => Event::Closed,
+                }
+            }
+            None => match from_foreground.next().await {
+                Some(request) => Event::Foreground(request),
+                None => return,
+            },
+        };
+
+        match event {
+            Event::Foreground(request) => {
+                // Requests whose foreground has timed out are never going to be answered by
+                // the full node, otherwise they would have been already.
+                pending.retain(|_, send_back| !send_back.is_canceled());
+
+                if connection.is_none() {
+                    // The default limits never split the JSON-RPC messages, which would corrupt
+                    // them.
+                    match Host::connect(&address, platform::ConnectionLimits::default()).await {
+                        Ok(c) => connection = Some(c),
+                        Err(err) => {
+                            log::warn!(
+                                target: "rpc-fallback",
+                                "Failed to connect to {} in order to query {} on {}: {}",
+                                address, request.method, log_name, err
+                            );
+                            let _ = request.send_back.send(Err(FallbackError::Connection(err)));
+                            continue;
+                        }
+                    }
+                }
+
+                log::debug!(
+                    target: "rpc-fallback",
+                    "Forwarding {} on {} to {}", request.method, log_name, address
+                );
+
+                Host::send(
+                    connection.as_mut().unwrap(),
+                    serde_json::json!({
+                        "jsonrpc": "2.0",
+                        "id": next_request_id,
+                        "method": request.method,
+                        "params": request.params,
+                    })
+                    .to_string()
+                    .as_bytes(),
+                );
+
+                pending.insert(next_request_id, request.send_back);
+                next_request_id += 1;
+            }
+            Event::Received(len) => {
+                Host::advance_read_cursor(connection.as_mut().unwrap(), len);
+
+                let responses = match extract_values(&mut received) {
+                    Ok(responses) => responses,
+                    Err(()) => {
+                        log::warn!(
+                            target: "rpc-fallback",
+                            "Invalid JSON received from {}; closing the connection", address
+                        );
+                        connection = None;
+                        pending.clear();
+                        received.clear();
+                        continue;
+                    }
+                };
+
+                for response in responses {
+                    let (id, result) = match decode_response(response) {
+                        Some(r) => r,
+                        None => continue,
+                    };
+
+                    if let Some(send_back) = pending.remove(&id) {
+                        let _ = send_back.send(result);
+                    }
+                }
+            }
+            Event::Closed => {
+                log::debug!(
+                    target: "rpc-fallback",
+                    "Connection to {} closed", address
+                );
+                connection = None;
+                pending.clear();
+                received.clear();
+            }
+        }
+    }
+}
+
+/// Decodes a response received from the full node into the identifier of the request and its
+/// result.
+///
+/// Returns `None` for notifications and for responses to requests that weren't sent by this
+/// module.
+fn decode_response(
+    mut response: serde_json::Value,
+) -> Option<(u64, Result<serde_json::Value, FallbackError>)> {
+    let id = response.get("id")?.as_u64()?;
+
+    let result = if let Some(error) = response.get("error") {
+        Err(FallbackError::ErrorResponse(
+            error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .map_or_else(|| error.to_string(), |m| m.to_owned()),
+        ))
+    } else if let Some(result) = response.get_mut("result") {
+        Ok(result.take())
+    } else {
+        Err(FallbackError::InvalidResponse)
+    };
+
+    Some((id, result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_response, FallbackError};
+
+    #[test]
+    fn decode_responses() {
+        let (id, result) = decode_response(
+            serde_json::from_str(r#"{"jsonrpc":"2.0","id":3,"result":null}"#).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(id, 3);
+        assert_eq!(result.unwrap(), serde_json::Value::Null);
+
+        let (id, result) = decode_response(
+            serde_json::from_str(
+                r#"{"jsonrpc":"2.0","id":4,"error":{"code":-32000,"message":"State pruned"}}"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(id, 4);
+        assert!(matches!(result, Err(FallbackError::ErrorResponse(m)) if m == "State pruned"));
+
+        assert!(decode_response(
+            serde_json::from_str(r#"{"jsonrpc":"2.0","method":"foo","params":[]}"#).unwrap()
+        )
+        .is_none());
+    }
+}
@@ -40,45 +40,102 @@
 //!
 //! The main service offered by the runtime service is
 //! [`RuntimeService::recent_best_block_runtime_call`], that performs a runtime call on the latest
-//! reported best block or more recent.
+//! reported best block or more recent. Calls can also be performed on the finalized block with
+//! [`RuntimeService::recent_finalized_block_runtime_call`], in which case the runtime of the
+//! finalized block is used, as it can differ from the one of the best block while a runtime
+//! upgrade isn't finalized yet.
 
 // TODO: the doc above mentions that you can subscribe to the finalized block, but this is isn't implemented yet ^
 
-use crate::{ffi, lossy_channel, sync_service};
-
-use futures::{lock::Mutex, prelude::*};
-use smoldot::{chain_spec, executor, header, metadata, network::protocol, trie::proof_verify};
-use std::{iter, pin::Pin, sync::Arc, time::Duration};
-
-pub use crate::lossy_channel::Receiver as NotificationsReceiver;
+use crate::{
+    cpu_usage, data_provider, header_cache, lossy_channel, memory_usage,
+    platform::{self, Host, Platform as _},
+    request_trace, sync_service,
+};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    lock::Mutex,
+    prelude::*,
+};
+use smoldot::{
+    chain::runtime_versions, chain_spec, executor, header, metadata, network::protocol,
+    trie::proof_verify,
+};
+use std::{
+    cmp,
+    collections::{BTreeMap, HashMap, VecDeque},
+    convert::TryFrom as _,
+    iter,
+    num::NonZeroU32,
+    pin::Pin,
+    sync::{Arc, Weak},
+    task::{Context, Poll},
+    time::Duration,
+};
 
 /// Configuration for a runtime service.
 pub struct Config<'a> {
     /// Closure that spawns background tasks.
     pub tasks_executor: Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>,
 
-    /// Service responsible for synchronizing the chain.
-    pub sync_service: Arc<sync_service::SyncService>,
+    /// Source of the blocks and storage of the chain. Typically the
+    /// [`sync_service::SyncService`](crate::sync_service::SyncService) of the chain.
+    pub data_provider: Arc<dyn data_provider::ChainDataProvider>,
+
+    /// Cache of the recent headers of the chain, shared with the other services of the chain.
+    /// Consulted before downloading headers through [`Config::data_provider`].
+    pub header_cache: Arc<header_cache::HeaderCache>,
 
     /// Specifications of the chain.
     pub chain_spec: &'a chain_spec::ChainSpec,
 
     /// Hash of the genesis block of the chain.
     ///
-    /// > **Note**: This can be derived from a [`chain_spec::ChainSpec`]. While the
-    /// >           [`RuntimeService::new`] function could in theory use the
-    /// >           [`Config::chain_spec`] parameter to derive this value, doing so is quite
-    /// >           expensive. We prefer to require this value from the upper layer instead, as
-    /// >           it is most likely needed anyway.
-    pub genesis_block_hash: [u8; 32],
+    /// If `None`, the value is derived from [`Config::chain_spec`] using
+    /// [`chain_spec::ChainSpec::genesis_block_header`]. Doing so is expensive the first time,
+    /// but the result is cached within the chain spec.
+    pub genesis_block_hash: Option<[u8; 32]>,
 
     /// Hash of the storage trie root of the genesis block of the chain.
     ///
-    /// > **Note**: This can be derived from a [`chain_spec::ChainSpec`]. While the
-    /// >           [`RuntimeService::new`] function could in theory use the
-    /// >           [`Config::chain_spec`] parameter to derive this value, doing so is quite
-    /// >           expensive. We prefer to require this value from the upper layer instead.
-    pub genesis_block_state_root: [u8; 32],
+    /// If `None`, the value is derived from [`Config::chain_spec`] in the same way as
+    /// [`Config::genesis_block_hash`].
+    pub genesis_block_state_root: Option<[u8; 32]>,
+
+    /// Cache of compiled runtimes, potentially shared with the runtime services of other chains.
+    pub compilation_cache: Arc<CompilationCache>,
+
+    /// Maximum size, in number of 64 kiB pages, of the memory of the virtual machine running the
+    /// runtime. `None` for no limit.
+    ///
+    /// Runtimes that would require more memory than this limit are considered as invalid, and
+    /// runtime calls return [`RuntimeCallError::MemoryLimitExceeded`].
+    pub max_runtime_memory_pages: Option<u32>,
+
+    /// Duration to wait after a new best block has been received before downloading its runtime
+    /// and notifying the subscriptions. Any other best block received in the meanwhile replaces
+    /// it, which avoids performing downloads and sending notifications for blocks that are
+    /// immediately superseded.
+    ///
+    /// A typical value is 500 milliseconds.
+    pub best_block_debounce: Duration,
+
+    /// Maximum number of notifications per second that each subscription yields, or `None` for
+    /// no limit.
+    ///
+    /// Notifications that exceed this rate are delayed. If multiple notifications are delayed,
+    /// only the latest one is yielded.
+    pub max_notifications_per_second: Option<NonZeroU32>,
+
+    /// CPU time accounting of the chain. The time spent executing the runtime and verifying
+    /// the storage proofs used by runtime calls is accounted for in there.
+    pub cpu_usage: Arc<cpu_usage::CpuUsage>,
+
+    /// Maximum number of failed runtime calls that are recorded and returned by
+    /// [`RuntimeService::failed_call_recordings`]. The oldest recordings are discarded when
+    /// this limit is reached. `0` disables the recording.
+    pub max_failed_call_recordings: usize,
 }
 
 /// See [the module-level documentation](..).
@@ -86,8 +143,38 @@ pub struct RuntimeService {
     /// See [`Config::tasks_executor`].
     tasks_executor: Mutex<Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send>>,
 
-    /// See [`Config::sync_service`].
-    sync_service: Arc<sync_service::SyncService>,
+    /// See [`Config::data_provider`].
+    data_provider: Arc<dyn data_provider::ChainDataProvider>,
+
+    /// See [`Config::header_cache`].
+    header_cache: Arc<header_cache::HeaderCache>,
+
+    /// See [`Config::compilation_cache`].
+    compilation_cache: Arc<CompilationCache>,
+
+    /// See [`Config::max_runtime_memory_pages`].
+    max_runtime_memory_pages: Option<u32>,
+
+    /// See [`Config::best_block_debounce`].
+    best_block_debounce: Duration,
+
+    /// See [`Config::cpu_usage`].
+    cpu_usage: Arc<cpu_usage::CpuUsage>,
+
+    /// See [`Config::max_failed_call_recordings`].
+    max_failed_call_recordings: usize,
+
+    /// Recordings of the most recent runtime calls that have failed, oldest first.
+    /// See [`RuntimeService::failed_call_recordings`].
+    failed_call_recordings: Mutex<VecDeque<executor::call_recording::RuntimeCallRecording>>,
+
+    /// Minimum duration between two notifications of the same subscription. Derived from
+    /// [`Config::max_notifications_per_second`].
+    notifications_min_interval: Option<Duration>,
+
+    /// Channel to the background task, used by [`RuntimeService::refresh_now`]. The sender is
+    /// signalled once the requested refresh has completed.
+    refresh_requests: mpsc::UnboundedSender<oneshot::Sender<()>>,
 
     /// Initially contains the runtime code of the genesis block. Whenever a best block is
     /// received, updated with the runtime of this new best block.
@@ -95,6 +182,14 @@ pub struct RuntimeService {
     /// the content will be left unchanged. However, if an error happens for example when compiling
     /// the new runtime, then the content will contain an error.
     latest_known_runtime: Mutex<LatestKnownRuntime>,
+
+    /// Runtime of the finalized block, used by
+    /// [`RuntimeService::recent_finalized_block_runtime_call`]. `None` if no such call has
+    /// been performed yet.
+    ///
+    /// Must never be locked while `latest_known_runtime` is locked, as this could lead to
+    /// deadlocks.
+    finalized_runtime: Mutex<Option<FinalizedRuntime>>,
 }
 
 impl RuntimeService {
@@ -119,8 +214,16 @@ impl RuntimeService {
             // Note that in the absolute we don't need to panic in case of a problem, and could
             // simply store an `Err` and continue running.
             // However, in practice, it seems more sane to detect problems in the genesis block.
-            let mut runtime = SuccessfulRuntime::from_params(&code, &heap_pages)
-                .expect("invalid runtime at genesis block");
+            let measure = config
+                .cpu_usage
+                .measure(cpu_usage::Category::RuntimeExecution);
+            let mut runtime = SuccessfulRuntime::from_params(
+                &config.compilation_cache,
+                &code,
+                &heap_pages,
+                config.max_runtime_memory_pages,
+            )
+            .expect("invalid runtime at genesis block");
 
             // As documented in the `metadata` field, we must fill it using the genesis storage.
             let mut query = metadata::query_metadata(runtime.virtual_machine.take().unwrap());
@@ -145,27 +248,74 @@ impl RuntimeService {
                     }
                 }
             }
+            drop(measure);
+
+            let mut runtime_versions = runtime_versions::RuntimeVersionsHistory::new();
+            runtime_versions.insert(0, runtime.runtime_spec.decode().spec_version);
+
+            let genesis_block_hash = config
+                .genesis_block_hash
+                .unwrap_or_else(|| config.chain_spec.genesis_block_header().hash());
+            let runtime_params_hash = runtime_params_hash(&code, &heap_pages);
+            let mut recent_best_blocks = RecentBestBlocks::new();
+            recent_best_blocks.insert(genesis_block_hash, runtime_params_hash);
 
             LatestKnownRuntime {
                 runtime: Ok(runtime),
                 runtime_code: code,
                 heap_pages,
-                runtime_block_hash: config.genesis_block_hash,
+                runtime_params_hash,
+                recent_best_blocks,
+                runtime_block_hash: genesis_block_hash,
                 runtime_block_height: 0,
-                runtime_block_state_root: config.genesis_block_state_root,
+                runtime_block_state_root: config
+                    .genesis_block_state_root
+                    .unwrap_or_else(|| config.chain_spec.genesis_block_header().state_root),
                 runtime_version_subscriptions: Vec::new(),
                 best_blocks_subscriptions: Vec::new(),
                 best_near_head_of_chain: config
-                    .sync_service
+                    .data_provider
                     .is_near_head_of_chain_heuristic()
                     .await,
+                runtime_versions,
             }
         };
 
+        let code_substitutes = config
+            .chain_spec
+            .code_substitutes()
+            .map(|(block_number, substitute)| {
+                let substitute = match substitute {
+                    chain_spec::CodeSubstitute::Code(code) => CodeSubstitute::Code {
+                        code: code.to_vec(),
+                        spec_version: None,
+                    },
+                    chain_spec::CodeSubstitute::Hash(hash) => CodeSubstitute::Hash(*hash),
+                };
+                (block_number, substitute)
+            })
+            .collect();
+
+        let (refresh_requests, refresh_requests_rx) = mpsc::unbounded();
+
         let runtime_service = Arc::new(RuntimeService {
             tasks_executor: Mutex::new(config.tasks_executor),
-            sync_service: config.sync_service,
+            data_provider: config.data_provider,
+            compilation_cache: config.compilation_cache,
+            max_runtime_memory_pages: config.max_runtime_memory_pages,
+            best_block_debounce: config.best_block_debounce,
+            header_cache: config.header_cache,
+            cpu_usage: config.cpu_usage,
+            max_failed_call_recordings: config.max_failed_call_recordings,
+            failed_call_recordings: Mutex::new(VecDeque::with_capacity(
+                config.max_failed_call_recordings,
+            )),
+            notifications_min_interval: config
+                .max_notifications_per_second
+                .map(|rate| Duration::from_secs(1) / rate.get()),
             latest_known_runtime: Mutex::new(latest_known_runtime),
+            finalized_runtime: Mutex::new(None),
+            refresh_requests,
         });
 
         // Spawns a task that downloads the runtime code at every block to check whether it has
@@ -174,11 +324,28 @@ impl RuntimeService {
         // This is strictly speaking not necessary as long as there is no active subscription.
         // However, in practice, there is most likely always going to be one. It is way easier to
         // always have a task active rather than create and destroy it.
-        start_background_task(&runtime_service).await;
+        start_background_task(&runtime_service, refresh_requests_rx, code_substitutes).await;
 
         runtime_service
     }
 
+    /// Immediately downloads the runtime code and heap pages of the current best block,
+    /// bypassing the delays that the [`RuntimeService`] normally applies between two downloads.
+    ///
+    /// The returned future yields once the download has been performed and, if the runtime has
+    /// changed, once the new runtime has been compiled and the subscriptions notified. Note that
+    /// the download might have failed, in which case the runtime is left unchanged.
+    ///
+    /// This is useful when the runtime is known to have just been upgraded.
+    pub async fn refresh_now(self: &Arc<RuntimeService>) {
+        let (tx, rx) = oneshot::channel();
+        if self.refresh_requests.unbounded_send(tx).is_err() {
+            // The background task has stopped.
+            return;
+        }
+        let _ = rx.await;
+    }
+
     /// Returns the current runtime version, plus an unlimited stream that produces one item every
     /// time the specs of the runtime of the best block are changed.
     ///
@@ -192,16 +359,18 @@ impl RuntimeService {
         let (tx, rx) = lossy_channel::channel();
         let mut latest_known_runtime = self.latest_known_runtime.lock().await;
         latest_known_runtime.runtime_version_subscriptions.push(tx);
+        let rx = NotificationsReceiver::new(rx, self.notifications_min_interval);
         let current_version = latest_known_runtime
             .runtime
             .as_ref()
             .map(|r| r.runtime_spec.clone())
-            .map_err(|&()| ());
+            .map_err(|_| ());
         (current_version, rx)
     }
 
     /// Returns the runtime version of the block with the given hash.
     // TODO: better error type
+    // TODO: code substitutes are ignored for blocks other than the latest best block
     pub async fn runtime_version_of_block(
         self: &Arc<RuntimeService>,
         block_hash: &[u8; 32],
@@ -215,44 +384,41 @@ impl RuntimeService {
                     .runtime
                     .as_ref()
                     .map(|r| r.runtime_spec.clone())
-                    .map_err(|&()| ());
+                    .map_err(|_| ());
             }
         }
 
-        // Ask the network for the header of this block, as we need to know the state root.
-        let state_root = {
-            let result = self
-                .sync_service
-                .clone()
-                .block_query(
-                    *block_hash,
-                    protocol::BlocksRequestFields {
-                        header: true,
-                        body: false,
-                        justification: false,
-                    },
-                )
-                .await;
-
-            // Note that the `block_query` method guarantees that the header is present
-            // and valid.
-            let header = if let Ok(block) = result {
-                block.header.unwrap()
-            } else {
-                return Err(());
-            };
+        // Same if the requested block is the finalized block whose runtime is known.
+        if let Some(finalized_runtime) = &*self.finalized_runtime.lock().await {
+            if finalized_runtime.block_hash == *block_hash {
+                return finalized_runtime
+                    .runtime
+                    .as_ref()
+                    .map(|r| r.runtime_spec.clone())
+                    .map_err(|_| ());
+            }
+        }
 
-            *header::decode(&header).map_err(|_| ())?.state_root
+        // Ask the network for the header of this block, as we need to know the state root.
+        let (number, state_root) = match self.header_cache.get(block_hash).await {
+            Some(header) => (header.number, header.state_root),
+            None => {
+                // Note that the `header_query` method guarantees that the header is valid.
+                let header = self.data_provider.clone().header_query(*block_hash).await?;
+                let header = self.header_cache.insert(header).await.map_err(|_| ())?;
+                (header.number, header.state_root)
+            }
         };
 
         // Download the runtime code of this block.
         let code_query_result = self
-            .sync_service
+            .data_provider
             .clone()
-            .storage_query(
+            .storage_query_quorum(
+                number,
                 block_hash,
                 &state_root,
-                iter::once(&b":code"[..]).chain(iter::once(&b":heappages"[..])),
+                vec![b":code".to_vec(), b":heappages".to_vec()],
             )
             .await;
 
@@ -267,7 +433,41 @@ impl RuntimeService {
             (code, heap_pages)
         };
 
-        SuccessfulRuntime::from_params(&code, &heap_pages).map(|r| r.runtime_spec)
+        // Recent runtimes embed their version in a custom section of the Wasm code, in which
+        // case there is no need to compile the runtime in order to know its version.
+        if let Some(code) = &code {
+            if let Ok(executor::code_info::RuntimeCodeInfo {
+                runtime_version: Some(runtime_version),
+                ..
+            }) = executor::code_info::extract(code)
+            {
+                return Ok(runtime_version);
+            }
+        }
+
+        SuccessfulRuntime::from_params(
+            &self.compilation_cache,
+            &code,
+            &heap_pages,
+            self.max_runtime_memory_pages,
+        )
+        .map(|r| r.runtime_spec)
+        .map_err(|_| ())
+    }
+
+    /// Returns the history of the spec versions of the runtime of the chain, as observed since
+    /// the service has started.
+    ///
+    /// See [`runtime_versions::RuntimeVersionsHistory`] for more information, and in particular
+    /// about the precision of the block numbers.
+    pub async fn runtime_versions_history(
+        self: &Arc<RuntimeService>,
+    ) -> runtime_versions::RuntimeVersionsHistory {
+        self.latest_known_runtime
+            .lock()
+            .await
+            .runtime_versions
+            .clone()
     }
 
     /// Returns the runtime version of the current best block.
@@ -279,11 +479,11 @@ impl RuntimeService {
             .runtime
             .as_ref()
             .map(|r| r.runtime_spec.clone())
-            .map_err(|&()| ())
+            .map_err(|_| ())
     }
 
-    /// Returns the SCALE-encoded header of the current best block, plus an unlimited stream that
-    /// produces one item every time the best block is changed.
+    /// Returns the header of the current best block, plus an unlimited stream that produces one
+    /// item every time the best block is changed.
     ///
     /// This function is similar to [`sync_service::SyncService::subscribe_best`], except that
     /// it is called less often. Additionally, it is guaranteed that when a notification is sent
@@ -293,12 +493,26 @@ impl RuntimeService {
     /// empty, you are guaranteed that the call has been performed on the best block.
     pub async fn subscribe_best(
         self: &Arc<RuntimeService>,
-    ) -> (Vec<u8>, NotificationsReceiver<Vec<u8>>) {
+    ) -> (
+        sync_service::HeaderNotification,
+        NotificationsReceiver<sync_service::HeaderNotification>,
+    ) {
         let (tx, rx) = lossy_channel::channel();
         let mut latest_known_runtime = self.latest_known_runtime.lock().await;
         latest_known_runtime.best_blocks_subscriptions.push(tx);
         drop(latest_known_runtime);
-        let (current, _) = self.sync_service.subscribe_best().await; // TODO: not correct; should load from latest_known_runtime
+        let rx = NotificationsReceiver::new(rx, self.notifications_min_interval);
+        // TODO: not correct; should load from latest_known_runtime
+        let current = {
+            let best = self.header_cache.best().await;
+            sync_service::HeaderNotification {
+                hash: best.hash,
+                number: best.number,
+                parent_hash: best.parent_hash,
+                state_root: best.state_root,
+                scale_encoded_header: best.scale_encoded.clone(),
+            }
+        };
         (current, rx)
     }
 
@@ -308,12 +522,22 @@ impl RuntimeService {
     /// but doesn't know anything about the storage, which the runtime might have to access. In
     /// order to make this work, a "call proof" is performed on the network in order to obtain
     /// the storage values corresponding to this call.
-    pub async fn recent_best_block_runtime_call<'a>(
-        self: &'a Arc<RuntimeService>,
+    ///
+    /// The parameter of the call is the concatenation of the buffers of `parameter_vectored`.
+    /// They are passed as is to the network and to the virtual machine, without being copied
+    /// into a single buffer.
+    ///
+    /// The call proof requests and the execution of the runtime are recorded in `trace`, if any.
+    ///
+    /// If the call fails, everything necessary to execute it again is recorded and can later be
+    /// retrieved with [`RuntimeService::failed_call_recordings`].
+    pub async fn recent_best_block_runtime_call(
+        self: &Arc<RuntimeService>,
         method: &str,
-        parameter_vectored: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+        parameter_vectored: &[&[u8]],
+        trace: Option<&request_trace::RequestTrace>,
     ) -> Result<Vec<u8>, RuntimeCallError> {
-        self.recent_best_block_runtime_call_inner(method, parameter_vectored)
+        self.recent_best_block_runtime_call_inner(method, parameter_vectored, trace)
             .await
             .map(|(ret, _)| ret)
     }
@@ -329,7 +553,8 @@ impl RuntimeService {
     async fn recent_best_block_runtime_call_inner<'a>(
         self: &'a Arc<RuntimeService>,
         method: &str,
-        parameter_vectored: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+        parameter_vectored: &[&[u8]],
+        trace: Option<&request_trace::RequestTrace>,
     ) -> Result<(Vec<u8>, futures::lock::MutexGuard<'a, LatestKnownRuntime>), RuntimeCallError>
     {
         // `latest_known_runtime` should be kept locked as little as possible.
@@ -338,6 +563,8 @@ impl RuntimeService {
         // it with the value previously found. If there is a mismatch, the entire runtime call
         // is restarted from scratch.
         loop {
+            self.cpu_usage.throttle().await;
+
             // Get `runtime_block_hash`, `runtime_block_height` and `runtime_block_state_root`,
             // the hash, height, and state trie root of a recent best block that uses this runtime.
             let (spec_version, runtime_block_hash, runtime_block_height, runtime_block_state_root) = {
@@ -345,7 +572,7 @@ impl RuntimeService {
                 (
                     lock.runtime
                         .as_ref()
-                        .map_err(|()| RuntimeCallError::InvalidRuntime)?
+                        .map_err(|err| err.into_call_error())?
                         .runtime_spec
                         .decode()
                         .spec_version,
@@ -360,15 +587,16 @@ impl RuntimeService {
             // If the call proof fail, do as if the proof was empty. This will enable the
             // fallback consisting in performing individual storage proof requests.
             let call_proof = self
-                .sync_service
+                .data_provider
                 .clone()
                 .call_proof_query(
                     runtime_block_height,
                     protocol::CallProofRequestConfig {
                         block_hash: runtime_block_hash,
                         method,
-                        parameter_vectored: parameter_vectored.clone(),
+                        parameter_vectored: parameter_vectored.iter().copied(),
                     },
+                    trace,
                 )
                 .await
                 .unwrap_or(Vec::new());
@@ -379,74 +607,413 @@ impl RuntimeService {
             let runtime = latest_known_runtime_lock
                 .runtime
                 .as_mut()
-                .map_err(|()| RuntimeCallError::InvalidRuntime)?;
+                .map_err(|err| err.into_call_error())?;
             if runtime.runtime_spec.decode().spec_version != spec_version {
                 continue;
             }
 
             // Perform the actual runtime call locally.
-            let mut runtime_call = match executor::read_only_runtime_host::run(
-                executor::read_only_runtime_host::Config {
-                    virtual_machine: runtime.virtual_machine.take().unwrap(),
-                    function_to_call: method,
-                    parameter: parameter_vectored,
-                },
+            // The measurements only cover the call itself, and must not be kept alive while
+            // recording a failed call below, as this involves waiting.
+            let outcome = {
+                let _measure = self
+                    .cpu_usage
+                    .measure(cpu_usage::Category::RuntimeExecution);
+                let _trace_measure = trace.map(|trace| trace.measure_runtime_execution(method));
+                runtime.read_only_call(
+                    &self.cpu_usage,
+                    method,
+                    parameter_vectored,
+                    &runtime_block_state_root,
+                    &call_proof,
+                    self.max_failed_call_recordings != 0,
+                )
+            };
+            let (error, injected_storage_values) = match outcome {
+                Ok(return_value) => return Ok((return_value, latest_known_runtime_lock)),
+                Err(err) => err,
+            };
+
+            if self.max_failed_call_recordings != 0 {
+                if let Some(runtime_code) = &latest_known_runtime_lock.runtime_code {
+                    let recording = executor::call_recording::RuntimeCallRecording {
+                        runtime_code_hash: Host::blake2_256(runtime_code),
+                        heap_pages: latest_known_runtime_lock.heap_pages.clone(),
+                        block_hash: runtime_block_hash,
+                        block_number: runtime_block_height,
+                        block_state_root: runtime_block_state_root,
+                        block_header: None,
+                        function_to_call: method.to_owned(),
+                        parameter: parameter_vectored.concat(),
+                        call_proof,
+                        storage_values: injected_storage_values,
+                        outcome: error.to_string(),
+                    };
+                    self.record_failed_call(recording).await;
+                }
+            }
+
+            return Err(error);
+        }
+    }
+
+    /// Stores a recording of a runtime call that has failed, in order for it to be returned by
+    /// [`RuntimeService::failed_call_recordings`]. The header of the block is filled from the
+    /// header cache.
+    async fn record_failed_call(
+        &self,
+        mut recording: executor::call_recording::RuntimeCallRecording,
+    ) {
+        debug_assert_ne!(self.max_failed_call_recordings, 0);
+
+        recording.block_header = self
+            .header_cache
+            .get(&recording.block_hash)
+            .await
+            .map(|header| header.scale_encoded.clone());
+
+        let mut recordings = self.failed_call_recordings.lock().await;
+        if recordings.len() >= self.max_failed_call_recordings {
+            recordings.pop_front();
+        }
+        recordings.push_back(recording);
+    }
+
+    /// Performs a runtime call using the current finalized block.
+    ///
+    /// Works similarly to [`RuntimeService::recent_best_block_runtime_call`], except that the
+    /// call is performed against the storage and the runtime of the finalized block. This
+    /// matters when a runtime upgrade has been enacted in a block that isn't finalized yet, in
+    /// which case the runtime of the finalized block is older than the one of the best block.
+    ///
+    /// The runtime of the finalized block is tracked separately from the runtime of the best
+    /// block. It is downloaded again whenever the finalized block changes, but the compiled
+    /// runtime of the best block is reused if the code is the same, which is the case outside of
+    /// runtime upgrades.
+    pub async fn recent_finalized_block_runtime_call(
+        self: &Arc<RuntimeService>,
+        method: &str,
+        parameter_vectored: &[&[u8]],
+        trace: Option<&request_trace::RequestTrace>,
+    ) -> Result<Vec<u8>, RuntimeCallError> {
+        // See the comments in `recent_best_block_runtime_call_inner`. Here, the entire call is
+        // restarted if the finalized block changes during the operation.
+        loop {
+            self.cpu_usage.throttle().await;
+
+            let finalized = self.header_cache.finalized().await;
+            self.update_finalized_runtime(&finalized).await?;
+
+            let call_proof = self
+                .data_provider
+                .clone()
+                .call_proof_query(
+                    finalized.number,
+                    protocol::CallProofRequestConfig {
+                        block_hash: finalized.hash,
+                        method,
+                        parameter_vectored: parameter_vectored.iter().copied(),
+                    },
+                    trace,
+                )
+                .await
+                .unwrap_or(Vec::new());
+
+            let mut finalized_runtime_lock = self.finalized_runtime.lock().await;
+            let finalized_runtime = match &mut *finalized_runtime_lock {
+                Some(rt) if rt.block_hash == finalized.hash => rt,
+                _ => continue,
+            };
+            let runtime = finalized_runtime
+                .runtime
+                .as_mut()
+                .map_err(|err| err.into_call_error())?;
+
+            // See the comments in `recent_best_block_runtime_call_inner`.
+            let outcome = {
+                let _measure = self
+                    .cpu_usage
+                    .measure(cpu_usage::Category::RuntimeExecution);
+                let _trace_measure = trace.map(|trace| trace.measure_runtime_execution(method));
+                runtime.read_only_call(
+                    &self.cpu_usage,
+                    method,
+                    parameter_vectored,
+                    &finalized.state_root,
+                    &call_proof,
+                    self.max_failed_call_recordings != 0,
+                )
+            };
+            let (error, injected_storage_values) = match outcome {
+                Ok(return_value) => return Ok(return_value),
+                Err(err) => err,
+            };
+
+            if self.max_failed_call_recordings != 0 {
+                if let Some(runtime_code) = &finalized_runtime.runtime_code {
+                    let recording = executor::call_recording::RuntimeCallRecording {
+                        runtime_code_hash: Host::blake2_256(runtime_code),
+                        heap_pages: finalized_runtime.heap_pages.clone(),
+                        block_hash: finalized.hash,
+                        block_number: finalized.number,
+                        block_state_root: finalized.state_root,
+                        block_header: Some(finalized.scale_encoded.clone()),
+                        function_to_call: method.to_owned(),
+                        parameter: parameter_vectored.concat(),
+                        call_proof,
+                        storage_values: injected_storage_values,
+                        outcome: error.to_string(),
+                    };
+                    drop(finalized_runtime_lock);
+                    self.record_failed_call(recording).await;
+                }
+            }
+
+            return Err(error);
+        }
+    }
+
+    /// Makes sure that [`RuntimeService::finalized_runtime`] corresponds to the given finalized
+    /// block, downloading and compiling its runtime if necessary.
+    async fn update_finalized_runtime(
+        self: &Arc<RuntimeService>,
+        finalized: &header_cache::CachedHeader,
+    ) -> Result<(), RuntimeCallError> {
+        if matches!(&*self.finalized_runtime.lock().await, Some(rt) if rt.block_hash == finalized.hash)
+        {
+            return Ok(());
+        }
+
+        // The finalized block has most likely been a best block in the past, in which case its
+        // `:code` and `:heappages` are already known and don't need to be downloaded again.
+        let known_code = {
+            let mut finalized_runtime_lock = self.finalized_runtime.lock().await;
+            let latest_known_runtime = self.latest_known_runtime.lock().await;
+            match finalized_runtime_source(
+                latest_known_runtime.recent_best_blocks.get(&finalized.hash),
+                finalized_runtime_lock.as_ref().map(|rt| &rt.params_hash),
+                &latest_known_runtime.runtime_params_hash,
             ) {
-                Ok(vm) => vm,
-                Err((err, prototype)) => {
-                    runtime.virtual_machine = Some(prototype);
-                    return Err(RuntimeCallError::StartError(err));
+                FinalizedRuntimeSource::Previous => {
+                    finalized_runtime_lock.as_mut().unwrap().block_hash = finalized.hash;
+                    return Ok(());
+                }
+                FinalizedRuntimeSource::Best => Some((
+                    latest_known_runtime.runtime_code.clone(),
+                    latest_known_runtime.heap_pages.clone(),
+                    latest_known_runtime.runtime_params_hash,
+                )),
+                FinalizedRuntimeSource::Download => None,
+            }
+        };
+
+        let (code, heap_pages, params_hash) = match known_code {
+            Some(known) => known,
+            None => {
+                let mut results = self
+                    .data_provider
+                    .clone()
+                    .storage_query_quorum(
+                        finalized.number,
+                        &finalized.hash,
+                        &finalized.state_root,
+                        vec![b":code".to_vec(), b":heappages".to_vec()],
+                    )
+                    .await
+                    .map_err(RuntimeCallError::FinalizedRuntimeDownload)?;
+                let heap_pages = results.pop().unwrap();
+                let code = results.pop().unwrap();
+                let params_hash = runtime_params_hash(&code, &heap_pages);
+                (code, heap_pages, params_hash)
+            }
+        };
+
+        let mut finalized_runtime_lock = self.finalized_runtime.lock().await;
+
+        let runtime = match finalized_runtime_lock.take() {
+            // The runtime hasn't changed since the previous finalized block.
+            Some(previous) if previous.params_hash == params_hash => previous.runtime,
+            _ => {
+                // Outside of runtime upgrades, the finalized block uses the same runtime as the
+                // best block. The compiled runtime of the best block, which might be a code
+                // substitute, is then reused.
+                let latest_known_runtime = self.latest_known_runtime.lock().await;
+
+                // The lock is acquired before measuring, in order to not account for the time
+                // spent waiting for it.
+                let _measure = self
+                    .cpu_usage
+                    .measure(cpu_usage::Category::RuntimeExecution);
+                match &latest_known_runtime.runtime {
+                    Ok(best_runtime) if latest_known_runtime.runtime_params_hash == params_hash => {
+                        best_runtime.with_heap_pages(&heap_pages, self.max_runtime_memory_pages)
+                    }
+                    _ => SuccessfulRuntime::from_params(
+                        &self.compilation_cache,
+                        &code,
+                        &heap_pages,
+                        self.max_runtime_memory_pages,
+                    ),
                 }
+            }
+        };
+
+        *finalized_runtime_lock = Some(FinalizedRuntime {
+            block_hash: finalized.hash,
+            runtime_code: code,
+            heap_pages,
+            params_hash,
+            runtime,
+        });
+
+        Ok(())
+    }
+
+    /// Returns the recordings of the most recent calls to
+    /// [`RuntimeService::recent_best_block_runtime_call`] that have failed, oldest first.
+    ///
+    /// Each recording contains everything necessary to execute the call again with
+    /// [`executor::call_recording::RuntimeCallRecording::replay`], apart from the runtime code.
+    pub async fn failed_call_recordings(
+        &self,
+    ) -> Vec<executor::call_recording::RuntimeCallRecording> {
+        self.failed_call_recordings
+            .lock()
+            .await
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// Similar to [`RuntimeService::recent_best_block_runtime_call`], except that
+    /// `Core_initialize_block` is called beforehand, as if a child of the best block was being
+    /// built. The requested function then observes the storage modifications performed by
+    /// `Core_initialize_block`.
+    ///
+    /// Some runtime functions only return correct results when called at the start of a block.
+    ///
+    /// The header passed to `Core_initialize_block` has the best block as parent, and an empty
+    /// digest. The state root and extrinsics root are set to zero, as they are unknown.
+    ///
+    /// The call proof of `Core_initialize_block` is downloaded first. Since nodes can't generate
+    /// a proof that covers both calls, the storage entries accessed by the requested function
+    /// that are missing from this proof are then downloaded one by one, and the calls are
+    /// executed again until the proof is complete. The storage modifications performed by the
+    /// calls are kept in memory and discarded afterwards.
+    ///
+    /// The execution of both calls is recorded in `trace` as a single execution of `method`.
+    pub async fn recent_best_block_runtime_call_after_initialize(
+        self: &Arc<RuntimeService>,
+        method: &str,
+        parameter_vectored: &[&[u8]],
+        trace: Option<&request_trace::RequestTrace>,
+    ) -> Result<Vec<u8>, RuntimeCallError> {
+        // Maximum number of storage proofs to download in order to complete the call proof.
+        const MAX_PROOF_COMPLETIONS: usize = 16;
+
+        // See the comments in `recent_best_block_runtime_call_inner`.
+        'runtime: loop {
+            self.cpu_usage.throttle().await;
+
+            let (spec_version, runtime_block_hash, runtime_block_height, runtime_block_state_root) = {
+                let lock = self.latest_known_runtime.lock().await;
+                (
+                    lock.runtime
+                        .as_ref()
+                        .map_err(|err| err.into_call_error())?
+                        .runtime_spec
+                        .decode()
+                        .spec_version,
+                    lock.runtime_block_hash,
+                    lock.runtime_block_height,
+                    lock.runtime_block_state_root,
+                )
             };
 
+            let initialized_block_header = header::HeaderRef {
+                parent_hash: &runtime_block_hash,
+                number: runtime_block_height + 1,
+                state_root: &[0; 32],
+                extrinsics_root: &[0; 32],
+                digest: header::DigestRef::empty(),
+            }
+            .scale_encoding_vec();
+
+            // Perform the call proof request.
+            // Note that `latest_known_runtime` is not locked.
+            let mut call_proof = self
+                .data_provider
+                .clone()
+                .call_proof_query(
+                    runtime_block_height,
+                    protocol::CallProofRequestConfig {
+                        block_hash: runtime_block_hash,
+                        method: "Core_initialize_block",
+                        parameter_vectored: [&initialized_block_header[..]].iter().copied(),
+                    },
+                    trace,
+                )
+                .await
+                .map_err(|()| RuntimeCallError::CallProofDownload)?;
+
+            let mut completed_keys = Vec::new();
             loop {
-                match runtime_call {
-                    executor::read_only_runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
-                        if !success.logs.is_empty() {
-                            log::debug!(
-                                target: "runtime",
-                                "Runtime logs: {}",
-                                success.logs
-                            );
-                        }
+                // Lock `latest_known_runtime_lock` again. `continue` if the runtime has changed
+                // in-between.
+                let mut latest_known_runtime_lock = self.latest_known_runtime.lock().await;
+                let runtime = latest_known_runtime_lock
+                    .runtime
+                    .as_mut()
+                    .map_err(|err| err.into_call_error())?;
+                if runtime.runtime_spec.decode().spec_version != spec_version {
+                    continue 'runtime;
+                }
 
-                        let return_value = success.virtual_machine.value().as_ref().to_owned();
-                        runtime.virtual_machine = Some(success.virtual_machine.into_prototype());
-                        return Ok((return_value, latest_known_runtime_lock));
-                    }
-                    executor::read_only_runtime_host::RuntimeHostVm::Finished(Err(error)) => {
-                        runtime.virtual_machine = Some(error.prototype);
-                        return Err(RuntimeCallError::CallError(error.detail));
-                    }
-                    executor::read_only_runtime_host::RuntimeHostVm::StorageGet(get) => {
-                        let requested_key = get.key_as_vec(); // TODO: optimization: don't use as_vec
-                        let storage_value =
-                            match proof_verify::verify_proof(proof_verify::VerifyProofConfig {
-                                requested_key: &requested_key,
-                                trie_root_hash: &runtime_block_state_root,
-                                proof: call_proof.iter().map(|v| &v[..]),
-                            }) {
-                                Ok(v) => v,
-                                Err(err) => {
-                                    // TODO: shouldn't return if error but do a storage_proof instead
-                                    runtime.virtual_machine = Some(
-                                    executor::read_only_runtime_host::RuntimeHostVm::StorageGet(
-                                        get,
-                                    )
-                                    .into_prototype(),
-                                );
-                                    return Err(RuntimeCallError::StorageRetrieval(err));
-                                }
-                            };
-                        runtime_call = get.inject_value(storage_value.as_ref().map(iter::once));
-                    }
-                    executor::read_only_runtime_host::RuntimeHostVm::NextKey(_) => {
-                        todo!() // TODO:
-                    }
-                    executor::read_only_runtime_host::RuntimeHostVm::StorageRoot(storage_root) => {
-                        runtime_call = storage_root.resume(&runtime_block_state_root);
-                    }
+                let (outcome, prototype) = {
+                    let _measure = self
+                        .cpu_usage
+                        .measure(cpu_usage::Category::RuntimeExecution);
+                    let _trace_measure = trace.map(|trace| trace.measure_runtime_execution(method));
+                    run_after_initialize(
+                        runtime.virtual_machine.take().unwrap(),
+                        &runtime_block_state_root,
+                        &initialized_block_header,
+                        method,
+                        parameter_vectored,
+                        &call_proof,
+                    )
+                };
+                runtime.virtual_machine = Some(prototype);
+
+                let missing_key = match outcome {
+                    AfterInitializeOutcome::Success(return_value) => return Ok(return_value),
+                    AfterInitializeOutcome::Error(error) => return Err(error),
+                    AfterInitializeOutcome::MissingProofEntry(key) => key,
+                };
+
+                drop(latest_known_runtime_lock);
+
+                // A storage proof of the key doesn't necessarily contain what is missing when
+                // the runtime enumerates keys. Give up if the same key is missing again.
+                if completed_keys.len() >= MAX_PROOF_COMPLETIONS
+                    || completed_keys.contains(&missing_key)
+                {
+                    return Err(RuntimeCallError::IncompleteCallProof);
                 }
+
+                let storage_proof = self
+                    .data_provider
+                    .clone()
+                    .storage_proof_query(
+                        runtime_block_height,
+                        &runtime_block_hash,
+                        vec![missing_key.clone()],
+                        trace,
+                    )
+                    .await
+                    .map_err(|()| RuntimeCallError::CallProofDownload)?;
+                call_proof.extend(storage_proof);
+                completed_keys.push(missing_key);
             }
         }
     }
@@ -471,16 +1038,29 @@ impl RuntimeService {
 
         // TODO: duplicated code compared to smoldot's metadata module
         match self
-            .recent_best_block_runtime_call_inner("Metadata_metadata", iter::empty::<Vec<u8>>())
+            .recent_best_block_runtime_call_inner("Metadata_metadata", &[], None)
             .await
         {
             Ok((return_value, mut latest_known_runtime_lock)) => {
                 match metadata::remove_metadata_length_prefix(&return_value) {
                     Ok(metadata) => {
                         // TODO: lot of cloning
-                        latest_known_runtime_lock.runtime.as_mut().unwrap().metadata =
-                            Some(metadata.to_vec());
-                        Ok(metadata.to_vec())
+                        let (cached, returned) = match (
+                            memory_usage::try_to_vec(metadata),
+                            memory_usage::try_to_vec(metadata),
+                        ) {
+                            (Ok(cached), Ok(returned)) => (cached, returned),
+                            (Err(err), _) | (_, Err(err)) => {
+                                log::warn!(
+                                    target: "runtime",
+                                    "Not enough memory to hold the metadata: {}",
+                                    err
+                                );
+                                return Err(MetadataError::OutOfMemory(err));
+                            }
+                        };
+                        latest_known_runtime_lock.runtime.as_mut().unwrap().metadata = Some(cached);
+                        Ok(returned)
                     }
                     Err(error) => {
                         log::warn!(
@@ -515,7 +1095,7 @@ impl RuntimeService {
         // unaffected.
 
         // If the sync service is far from the head, the runtime service is also far.
-        if !self.sync_service.is_near_head_of_chain_heuristic().await {
+        if !self.data_provider.is_near_head_of_chain_heuristic().await {
             return false;
         }
 
@@ -546,6 +1126,35 @@ pub enum RuntimeCallError {
     // TODO: change error type?
     #[display(fmt = "{}", _0)]
     StorageRetrieval(proof_verify::Error),
+    /// Runtime of the best block requires more memory than [`Config::max_runtime_memory_pages`].
+    #[display(fmt = "Runtime of the best block exceeds the memory limit")]
+    MemoryLimitExceeded,
+    /// Not enough memory available to compile or instantiate the runtime of the best block.
+    #[display(fmt = "Not enough memory to instantiate the runtime of the best block")]
+    RuntimeOutOfMemory,
+    /// Error during a call performed through
+    /// [`RuntimeService::recent_best_block_runtime_call_after_initialize`].
+    #[display(fmt = "{}", _0)]
+    CallAfterInitializeError(executor::runtime_host::ErrorDetail),
+    /// The call proof obtained from the network couldn't be decoded or doesn't match the state
+    /// of the block.
+    #[display(fmt = "Invalid call proof")]
+    InvalidCallProof,
+    /// The call proof obtained from the network doesn't contain all the storage entries
+    /// accessed by the runtime.
+    #[display(fmt = "Call proof is missing storage entries accessed by the runtime")]
+    IncompleteCallProof,
+    /// Not enough memory to hold the return value of the call.
+    #[display(fmt = "{}", _0)]
+    OutOfMemory(memory_usage::AllocError),
+    /// Failed to download from the network the proofs necessary to perform a call through
+    /// [`RuntimeService::recent_best_block_runtime_call_after_initialize`].
+    #[display(fmt = "Failed to download the call proof")]
+    CallProofDownload,
+    /// Error while downloading the runtime of the finalized block, during a call performed
+    /// through [`RuntimeService::recent_finalized_block_runtime_call`].
+    #[display(fmt = "Failed to download the runtime of the finalized block: {}", _0)]
+    FinalizedRuntimeDownload(data_provider::StorageQueryError),
 }
 
 impl RuntimeCallError {
@@ -556,9 +1165,17 @@ impl RuntimeCallError {
             RuntimeCallError::CallError(_) => false,
             RuntimeCallError::StartError(_) => false,
             RuntimeCallError::InvalidRuntime => false,
+            RuntimeCallError::MemoryLimitExceeded => false,
+            RuntimeCallError::RuntimeOutOfMemory => false,
+            RuntimeCallError::CallAfterInitializeError(_) => false,
+            RuntimeCallError::InvalidCallProof => true,
+            RuntimeCallError::IncompleteCallProof => true,
+            RuntimeCallError::CallProofDownload => true,
             // TODO: as a temporary hack, we consider `TrieRootNotFound` as the remote not knowing about the requested block; see https://github.com/paritytech/substrate/pull/8046
             RuntimeCallError::StorageRetrieval(proof_verify::Error::TrieRootNotFound) => true,
             RuntimeCallError::StorageRetrieval(_) => false,
+            RuntimeCallError::OutOfMemory(_) => false,
+            RuntimeCallError::FinalizedRuntimeDownload(err) => err.is_network_problem(),
         }
     }
 }
@@ -575,6 +1192,64 @@ pub enum MetadataError {
     /// Error while decoding metadata fetched from runtime.
     #[display(fmt = "{}", _0)]
     MetadataDecode(metadata::RemoveMetadataLengthPrefixError),
+    /// Not enough memory to hold the metadata.
+    #[display(fmt = "{}", _0)]
+    OutOfMemory(memory_usage::AllocError),
+}
+
+/// Stream of notifications returned by [`RuntimeService::subscribe_best`] and
+/// [`RuntimeService::subscribe_runtime_version`].
+///
+/// Only the latest notification is kept if the receiver is too slow to process notifications.
+/// See also [`Config::max_notifications_per_second`].
+pub struct NotificationsReceiver<T> {
+    inner: lossy_channel::Receiver<T>,
+    /// Minimum duration between two notifications. See [`Config::max_notifications_per_second`].
+    min_interval: Option<Duration>,
+    /// If `Some`, no notification must be yielded before this delay has elapsed.
+    next_allowed: Option<platform::Delay>,
+}
+
+impl<T> NotificationsReceiver<T> {
+    fn new(inner: lossy_channel::Receiver<T>, min_interval: Option<Duration>) -> Self {
+        NotificationsReceiver {
+            inner,
+            min_interval,
+            next_allowed: None,
+        }
+    }
+}
+
+impl<T> Stream for NotificationsReceiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        // Because the inner channel only ever keeps the latest item, waiting here before polling
+        // it automatically coalesces the notifications sent in the meanwhile.
+        if let Some(delay) = self.next_allowed.as_mut() {
+            if Future::poll(Pin::new(delay), cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.next_allowed = None;
+        }
+
+        let item = match Stream::poll_next(Pin::new(&mut self.inner), cx) {
+            Poll::Ready(Some(item)) => item,
+            other => return other,
+        };
+
+        if let Some(min_interval) = self.min_interval {
+            self.next_allowed = Some(Host::sleep(min_interval));
+        }
+
+        Poll::Ready(Some(item))
+    }
+}
+
+impl<T> stream::FusedStream for NotificationsReceiver<T> {
+    fn is_terminated(&self) -> bool {
+        self.inner.is_terminated()
+    }
 }
 
 struct LatestKnownRuntime {
@@ -582,7 +1257,7 @@ struct LatestKnownRuntime {
     /// happened, including a problem when obtaining the runtime specs or the metadata. It is
     /// better to report to the user an error about for example the metadata not being extractable
     /// compared to returning an obsolete version.
-    runtime: Result<SuccessfulRuntime, ()>,
+    runtime: Result<SuccessfulRuntime, RuntimeError>,
 
     /// Undecoded storage value of `:code` corresponding to the [`LatestKnownRuntime::runtime`]
     /// field.
@@ -590,6 +1265,12 @@ struct LatestKnownRuntime {
     /// Undecoded storage value of `:heappages` corresponding to the
     /// [`LatestKnownRuntime::runtime`] field.
     heap_pages: Option<Vec<u8>>,
+    /// Hash of [`LatestKnownRuntime::runtime_code`] and [`LatestKnownRuntime::heap_pages`], as
+    /// returned by [`runtime_params_hash`].
+    runtime_params_hash: [u8; 32],
+    /// Hashes of the `:code` and `:heappages` of the most recent best blocks. Used to avoid
+    /// downloading again the runtime of a block that is later finalized.
+    recent_best_blocks: RecentBestBlocks,
     /// Hash of a block known to have the runtime found in the [`LatestKnownRuntime::runtime`]
     /// field. Always updated to a recent block having this runtime.
     runtime_block_hash: [u8; 32],
@@ -606,94 +1287,653 @@ struct LatestKnownRuntime {
 
     /// List of senders that get notified when the best block is updated.
     /// See [`RuntimeService::subscribe_best`].
-    best_blocks_subscriptions: Vec<lossy_channel::Sender<Vec<u8>>>,
+    best_blocks_subscriptions: Vec<lossy_channel::Sender<sync_service::HeaderNotification>>,
 
     /// Return value of calling [`sync_service::SyncService::is_near_head_of_chain_heuristic`]
     /// after the latest best block update.
     best_near_head_of_chain: bool,
+
+    /// Spec versions of the on-chain runtimes observed so far, and the first best block they
+    /// have been observed at. Code substitutes aren't taken into account.
+    runtime_versions: runtime_versions::RuntimeVersionsHistory,
 }
 
-struct SuccessfulRuntime {
-    /// Cache of the metadata extracted from the runtime. `None` if unknown.
-    ///
-    /// This cache is filled lazily whenever it is requested through the public API.
-    ///
-    /// Note that building the metadata might require access to the storage, just like obtaining
-    /// the runtime code. if the runtime code gets an update, we can reasonably assume that the
-    /// network is able to serve us the storage of recent blocks, and thus the changes of being
-    /// able to build the metadata are very high.
-    ///
-    /// If the runtime is the one found in the genesis storage, the metadata must have been been
-    /// filled using the genesis storage as well. If we build the metadata of the genesis runtime
-    /// lazily, chances are that the network wouldn't be able to serve the storage of blocks near
-    /// the genesis.
-    ///
-    /// As documented in the smoldot metadata module, the metadata might access the storage, but
-    /// we intentionally don't watch for changes in these storage keys to refresh the metadata.
-    metadata: Option<Vec<u8>>,
+/// Storage keys read by a runtime call, in order, and the values that have been injected in
+/// response.
+type InjectedStorageValues = Vec<(Vec<u8>, Option<Vec<u8>>)>;
 
-    /// Runtime specs extracted from the runtime.
-    runtime_spec: executor::CoreVersion,
+/// See [`RuntimeService::finalized_runtime`].
+struct FinalizedRuntime {
+    /// Hash of the finalized block the runtime has been obtained from.
+    block_hash: [u8; 32],
+    /// Undecoded storage value of `:code` of the block.
+    runtime_code: Option<Vec<u8>>,
+    /// Undecoded storage value of `:heappages` of the block.
+    heap_pages: Option<Vec<u8>>,
+    /// Hash of [`FinalizedRuntime::runtime_code`] and [`FinalizedRuntime::heap_pages`], as
+    /// returned by [`runtime_params_hash`].
+    params_hash: [u8; 32],
+    /// Runtime built from the code and heap pages.
+    runtime: Result<SuccessfulRuntime, RuntimeError>,
+}
 
-    /// Virtual machine itself, to perform additional calls.
-    ///
+/// Maximum number of entries in [`RecentBestBlocks`].
+///
+/// Blocks are normally finalized a few seconds after having been the best block, and this
+/// value leaves a large margin.
+const MAX_RECENT_BEST_BLOCKS: usize = 256;
+
+/// Hashes of the most recent best blocks, and for each of them the hash of its `:code` and
+/// `:heappages` as returned by [`runtime_params_hash`].
+///
+/// Contains at most [`MAX_RECENT_BEST_BLOCKS`] entries. The oldest entries are removed first.
+struct RecentBestBlocks {
+    /// Entries of the list, oldest first.
+    entries: VecDeque<([u8; 32], [u8; 32])>,
+}
+
+impl RecentBestBlocks {
+    fn new() -> Self {
+        RecentBestBlocks {
+            entries: VecDeque::with_capacity(MAX_RECENT_BEST_BLOCKS),
+        }
+    }
+
+    /// Records that the block with the given hash has the given runtime parameters.
+    fn insert(&mut self, block_hash: [u8; 32], params_hash: [u8; 32]) {
+        if self.entries.len() >= MAX_RECENT_BEST_BLOCKS {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((block_hash, params_hash));
+    }
+
+    /// Returns the hash of the runtime parameters of the given block, if known.
+    fn get(&self, block_hash: &[u8; 32]) -> Option<&[u8; 32]> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(hash, _)| hash == block_hash)
+            .map(|(_, params_hash)| params_hash)
+    }
+}
+
+/// Returns a hash of the given `:code` and `:heappages`. Two blocks whose runtime parameters
+/// have the same hash have the same runtime.
+fn runtime_params_hash(code: &Option<Vec<u8>>, heap_pages: &Option<Vec<u8>>) -> [u8; 32] {
+    // Lengths are included in order for the hash to be unambiguous.
+    let mut encoded = Vec::with_capacity(code.as_ref().map_or(0, |c| c.len()) + 32);
+    for value in [code, heap_pages] {
+        match value {
+            Some(value) => {
+                encoded.push(1);
+                encoded.extend_from_slice(&u64::try_from(value.len()).unwrap().to_le_bytes());
+                encoded.extend_from_slice(value);
+            }
+            None => encoded.push(0),
+        }
+    }
+    Host::blake2_256(&encoded)
+}
+
+/// Where the runtime of a newly-finalized block can be obtained from.
+/// See [`finalized_runtime_source`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum FinalizedRuntimeSource {
+    /// The block has the same runtime as the previous [`FinalizedRuntime`].
+    Previous,
+    /// The block has the same runtime as the [`LatestKnownRuntime`].
+    Best,
+    /// The runtime of the block is unknown and must be downloaded.
+    Download,
+}
+
+/// Determines where the runtime of a newly-finalized block can be obtained from.
+///
+/// `known_params_hash` is the hash of the runtime parameters of the finalized block, if it has
+/// been a best block recently. During a runtime upgrade, the finalized block might not have the
+/// same runtime as the best block but the same one as the previous finalized block.
+fn finalized_runtime_source(
+    known_params_hash: Option<&[u8; 32]>,
+    previous_finalized_params_hash: Option<&[u8; 32]>,
+    best_params_hash: &[u8; 32],
+) -> FinalizedRuntimeSource {
+    match known_params_hash {
+        Some(known) if Some(known) == previous_finalized_params_hash => {
+            FinalizedRuntimeSource::Previous
+        }
+        Some(known) if known == best_params_hash => FinalizedRuntimeSource::Best,
+        _ => FinalizedRuntimeSource::Download,
+    }
+}
+
+/// Reason why [`LatestKnownRuntime::runtime`] contains an error.
+#[derive(Debug, Copy, Clone)]
+enum RuntimeError {
+    /// The runtime is invalid, for example because it fails to compile.
+    Invalid,
+    /// The runtime requires more memory than [`Config::max_runtime_memory_pages`].
+    MemoryLimitExceeded,
+    /// Not enough memory available to compile or instantiate the runtime.
+    OutOfMemory,
+}
+
+impl RuntimeError {
+    /// Returns the [`RuntimeCallError`] to return when attempting a call on this runtime.
+    fn into_call_error(self) -> RuntimeCallError {
+        match self {
+            RuntimeError::Invalid => RuntimeCallError::InvalidRuntime,
+            RuntimeError::MemoryLimitExceeded => RuntimeCallError::MemoryLimitExceeded,
+            RuntimeError::OutOfMemory => RuntimeCallError::RuntimeOutOfMemory,
+        }
+    }
+}
+
+struct SuccessfulRuntime {
+    /// Cache of the metadata extracted from the runtime. `None` if unknown.
+    ///
+    /// This cache is filled lazily whenever it is requested through the public API.
+    ///
+    /// Note that building the metadata might require access to the storage, just like obtaining
+    /// the runtime code. if the runtime code gets an update, we can reasonably assume that the
+    /// network is able to serve us the storage of recent blocks, and thus the changes of being
+    /// able to build the metadata are very high.
+    ///
+    /// If the runtime is the one found in the genesis storage, the metadata must have been been
+    /// filled using the genesis storage as well. If we build the metadata of the genesis runtime
+    /// lazily, chances are that the network wouldn't be able to serve the storage of blocks near
+    /// the genesis.
+    ///
+    /// As documented in the smoldot metadata module, the metadata might access the storage, but
+    /// we intentionally don't watch for changes in these storage keys to refresh the metadata.
+    metadata: Option<Vec<u8>>,
+
+    /// Runtime specs extracted from the runtime.
+    runtime_spec: executor::CoreVersion,
+
+    /// Virtual machine itself, to perform additional calls.
+    ///
     /// Always `Some`, except for temporary extractions. Should always be `Some`, when the
     /// [`SuccessfulRuntime`] is accessed.
     virtual_machine: Option<executor::host::HostVmPrototype>,
+
+    /// Compiled module of the runtime. Holding this reference keeps the module in the
+    /// [`CompilationCache`].
+    _compiled_module: Arc<executor::vm::Module>,
+
+    /// Limits of the memory imported by the runtime, if any. Needed in order to determine the
+    /// number of heap pages if `:heappages` changes.
+    memory_import: Option<executor::code_info::MemoryImportLimits>,
 }
 
 impl SuccessfulRuntime {
-    fn from_params(code: &Option<Vec<u8>>, heap_pages: &Option<Vec<u8>>) -> Result<Self, ()> {
-        let vm = match executor::host::HostVmPrototype::new(
-            code.as_ref().ok_or(())?,
-            executor::storage_heap_pages_to_value(heap_pages.as_deref()).map_err(|_| ())?,
-            executor::vm::ExecHint::CompileAheadOfTime,
-        ) {
-            Ok(vm) => vm,
-            Err(error) => {
-                log::warn!(target: "runtime", "Failed to compile best block runtime: {}", error);
-                return Err(());
-            }
-        };
-
-        let (runtime_spec, vm) = match executor::core_version(vm) {
-            Ok(v) => v,
-            Err(_error) => {
-                log::warn!(
-                    target: "runtime",
-                    "Failed to call Core_version on new runtime",  // TODO: print error message as well ; at the moment the type of the error is `()`
-                );
-                return Err(());
-            }
+    /// Builds a runtime from the values of `:code` and `:heappages`.
+    ///
+    /// The information embedded in the runtime code takes precedence over the storage:
+    ///
+    /// - The runtime version is read from the `runtime_version` custom section of the code if
+    ///   present, and obtained by calling `Core_version` otherwise.
+    /// - The number of heap pages is determined by [`runtime_heap_pages`].
+    fn from_params(
+        compilation_cache: &CompilationCache,
+        code: &Option<Vec<u8>>,
+        heap_pages: &Option<Vec<u8>>,
+        max_memory_pages: Option<u32>,
+    ) -> Result<Self, RuntimeError> {
+        let code = code.as_ref().ok_or(RuntimeError::Invalid)?;
+        let code_info = executor::code_info::extract(code).map_err(|error| {
+            log::warn!(target: "runtime", "Failed to parse new runtime: {}", error);
+            RuntimeError::Invalid
+        })?;
+
+        let (vm, compiled_module) = compilation_cache
+            .instantiate(
+                code,
+                runtime_heap_pages(code_info.memory_import.as_ref(), heap_pages.as_deref())?,
+                max_memory_pages,
+            )
+            .map_err(|error| instantiation_error(error, max_memory_pages))?;
+
+        let (runtime_spec, vm) = match code_info.runtime_version {
+            Some(runtime_spec) => (runtime_spec, vm),
+            None => match executor::core_version(vm) {
+                Ok(v) => v,
+                Err(_error) => {
+                    log::warn!(
+                        target: "runtime",
+                        "Failed to call Core_version on new runtime",  // TODO: print error message as well ; at the moment the type of the error is `()`
+                    );
+                    return Err(RuntimeError::Invalid);
+                }
+            },
         };
 
         Ok(SuccessfulRuntime {
             metadata: None,
             runtime_spec,
             virtual_machine: Some(vm),
+            _compiled_module: compiled_module,
+            memory_import: code_info.memory_import,
+        })
+    }
+
+    /// Builds a new [`SuccessfulRuntime`] identical to this one but with a different value of
+    /// `:heappages`.
+    ///
+    /// The already-compiled module is reused, and the runtime specs and metadata are kept, as
+    /// they don't depend on the number of heap pages.
+    fn with_heap_pages(
+        &self,
+        heap_pages: &Option<Vec<u8>>,
+        max_memory_pages: Option<u32>,
+    ) -> Result<Self, RuntimeError> {
+        let vm = executor::host::HostVmPrototype::from_module(
+            (*self._compiled_module).clone(),
+            runtime_heap_pages(self.memory_import.as_ref(), heap_pages.as_deref())?,
+            max_memory_pages,
+        )
+        .map_err(|error| instantiation_error(error, max_memory_pages))?;
+
+        Ok(SuccessfulRuntime {
+            metadata: self.metadata.clone(),
+            runtime_spec: self.runtime_spec.clone(),
+            virtual_machine: Some(vm),
+            _compiled_module: self._compiled_module.clone(),
+            memory_import: self.memory_import,
         })
     }
+
+    /// Performs a read-only runtime call, reading the storage from the given call proof.
+    ///
+    /// On error, also returns the storage values that have been injected in the call if
+    /// `record_storage_values` is `true`, or an empty list otherwise.
+    fn read_only_call(
+        &mut self,
+        cpu_usage: &cpu_usage::CpuUsage,
+        method: &str,
+        parameter_vectored: &[&[u8]],
+        state_root: &[u8; 32],
+        call_proof: &[Vec<u8>],
+        record_storage_values: bool,
+    ) -> Result<Vec<u8>, (RuntimeCallError, InjectedStorageValues)> {
+        let mut runtime_call =
+            match executor::read_only_runtime_host::run(executor::read_only_runtime_host::Config {
+                virtual_machine: self.virtual_machine.take().unwrap(),
+                function_to_call: method,
+                parameter: parameter_vectored.iter(),
+            }) {
+                Ok(vm) => vm,
+                Err((err, prototype)) => {
+                    self.virtual_machine = Some(prototype);
+                    return Err((RuntimeCallError::StartError(err), Vec::new()));
+                }
+            };
+
+        let mut injected_storage_values = Vec::new();
+
+        let error = loop {
+            match runtime_call {
+                executor::read_only_runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                    if !success.logs.is_empty() {
+                        log::debug!(
+                            target: "runtime",
+                            "Runtime logs: {}",
+                            success.logs
+                        );
+                    }
+
+                    let return_value =
+                        memory_usage::try_to_vec(success.virtual_machine.value().as_ref());
+                    self.virtual_machine = Some(success.virtual_machine.into_prototype());
+                    match return_value {
+                        Ok(return_value) => return Ok(return_value),
+                        Err(err) => break RuntimeCallError::OutOfMemory(err),
+                    }
+                }
+                executor::read_only_runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                    self.virtual_machine = Some(error.prototype);
+                    break RuntimeCallError::CallError(error.detail);
+                }
+                executor::read_only_runtime_host::RuntimeHostVm::StorageGet(get) => {
+                    let requested_key = get.key_as_vec(); // TODO: optimization: don't use as_vec
+                    let storage_value = match {
+                        let _measure = cpu_usage.measure(cpu_usage::Category::ProofVerification);
+                        proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                            requested_key: &requested_key,
+                            trie_root_hash: state_root,
+                            proof: call_proof.iter().map(|v| &v[..]),
+                        })
+                    } {
+                        Ok(v) => v,
+                        Err(err) => {
+                            // TODO: shouldn't return if error but do a storage_proof instead
+                            self.virtual_machine = Some(
+                                executor::read_only_runtime_host::RuntimeHostVm::StorageGet(get)
+                                    .into_prototype(),
+                            );
+                            break RuntimeCallError::StorageRetrieval(err);
+                        }
+                    };
+                    runtime_call = get.inject_value(storage_value.as_ref().map(iter::once));
+                    if record_storage_values {
+                        injected_storage_values
+                            .push((requested_key, storage_value.map(|v| v.to_vec())));
+                    }
+                }
+                executor::read_only_runtime_host::RuntimeHostVm::NextKey(_) => {
+                    todo!() // TODO:
+                }
+                executor::read_only_runtime_host::RuntimeHostVm::StorageRoot(storage_root) => {
+                    runtime_call = storage_root.resume(state_root);
+                }
+                executor::read_only_runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                    // Sr25519 signatures are verified by the host if it supports doing so, as
+                    // this is considerably faster than doing it within the Wasm VM.
+                    let host_outcome = match sig.algorithm() {
+                        executor::host::SignatureVerificationAlgorithm::Sr25519V1 => {
+                            Host::sr25519_verify_deprecated(
+                                &<[u8; 64]>::try_from(sig.signature().as_ref()).unwrap(),
+                                sig.message().as_ref(),
+                                &<[u8; 32]>::try_from(sig.public_key().as_ref()).unwrap(),
+                            )
+                        }
+                        executor::host::SignatureVerificationAlgorithm::Sr25519V2 => {
+                            Host::sr25519_verify(
+                                &<[u8; 64]>::try_from(sig.signature().as_ref()).unwrap(),
+                                sig.message().as_ref(),
+                                &<[u8; 32]>::try_from(sig.public_key().as_ref()).unwrap(),
+                            )
+                        }
+                        executor::host::SignatureVerificationAlgorithm::Ed25519 => None,
+                    };
+
+                    runtime_call = match host_outcome {
+                        Some(true) => sig.resume_success(),
+                        Some(false) => sig.resume_failed(),
+                        None => sig.verify_and_resume(),
+                    };
+                }
+            }
+        };
+
+        Err((error, injected_storage_values))
+    }
+}
+
+/// Determines the number of heap pages of a runtime, given the limits of the memory it imports
+/// and the value of `:heappages`.
+///
+/// The value of `:heappages` is used if present, and [`executor::DEFAULT_HEAP_PAGES`]
+/// otherwise. However, if the runtime declares a maximum size for its imported memory, the
+/// number of heap pages is capped to what this maximum allows, as instantiating the runtime
+/// would fail otherwise. In that situation, an invalid value of `:heappages` is treated as if
+/// it was missing.
+fn runtime_heap_pages(
+    memory_import: Option<&executor::code_info::MemoryImportLimits>,
+    storage_value: Option<&[u8]>,
+) -> Result<executor::host::HeapPages, RuntimeError> {
+    let runtime_max = memory_import.and_then(|limits| limits.max_heap_pages());
+
+    match (
+        executor::storage_heap_pages_to_value(storage_value),
+        runtime_max,
+    ) {
+        (Ok(heap_pages), None) => Ok(heap_pages),
+        (Ok(heap_pages), Some(max)) => Ok(cmp::min(heap_pages, max)),
+        (Err(_), Some(max)) => Ok(cmp::min(executor::DEFAULT_HEAP_PAGES, max)),
+        (Err(_), None) => Err(RuntimeError::Invalid),
+    }
+}
+
+/// Turns an error returned by [`executor::overlay_runtime_host::run`] into a
+/// [`RuntimeCallError`].
+fn call_proof_error(error: executor::overlay_runtime_host::ErrorDetail) -> RuntimeCallError {
+    match error {
+        executor::overlay_runtime_host::ErrorDetail::StartError(err) => {
+            RuntimeCallError::StartError(err)
+        }
+        executor::overlay_runtime_host::ErrorDetail::Execution(err) => {
+            RuntimeCallError::CallAfterInitializeError(err)
+        }
+        executor::overlay_runtime_host::ErrorDetail::InvalidProof => {
+            RuntimeCallError::InvalidCallProof
+        }
+        executor::overlay_runtime_host::ErrorDetail::MissingProofEntry { .. } => {
+            RuntimeCallError::IncompleteCallProof
+        }
+    }
+}
+
+/// Outcome of [`run_after_initialize`].
+enum AfterInitializeOutcome {
+    /// Both calls have succeeded. Contains the output of the requested function.
+    Success(Vec<u8>),
+    /// The proof doesn't contain the storage entry of the given key.
+    MissingProofEntry(Vec<u8>),
+    /// Any other error.
+    Error(RuntimeCallError),
+}
+
+/// Calls `Core_initialize_block` with the given header, then `method` on top of the storage
+/// modifications performed by `Core_initialize_block`. The storage is read from `proof`.
+///
+/// The virtual machine prototype is always returned back.
+fn run_after_initialize(
+    virtual_machine: executor::host::HostVmPrototype,
+    storage_trie_root: &[u8; 32],
+    initialized_block_header: &[u8],
+    method: &str,
+    parameter_vectored: &[&[u8]],
+    proof: &[Vec<u8>],
+) -> (AfterInitializeOutcome, executor::host::HostVmPrototype) {
+    let error_outcome = |error: executor::overlay_runtime_host::Error| {
+        let outcome = match error.detail {
+            executor::overlay_runtime_host::ErrorDetail::MissingProofEntry { key } => {
+                AfterInitializeOutcome::MissingProofEntry(key)
+            }
+            detail => AfterInitializeOutcome::Error(call_proof_error(detail)),
+        };
+        (outcome, error.prototype)
+    };
+
+    let initialize_success =
+        match executor::overlay_runtime_host::run(executor::overlay_runtime_host::Config {
+            virtual_machine,
+            function_to_call: "Core_initialize_block",
+            parameter: iter::once(initialized_block_header),
+            storage_trie_root,
+            proof: proof.iter().map(|v| &v[..]),
+            storage_top_trie_changes: Default::default(),
+            offchain_storage_changes: Default::default(),
+        }) {
+            Ok(success) => success,
+            Err(error) => return error_outcome(error),
+        };
+
+    // The same virtual machine is re-used for the actual call, on top of the storage changes
+    // performed by `Core_initialize_block`.
+    let call_success =
+        match executor::overlay_runtime_host::run(executor::overlay_runtime_host::Config {
+            virtual_machine: initialize_success.virtual_machine.into_prototype(),
+            function_to_call: method,
+            parameter: parameter_vectored.iter(),
+            storage_trie_root,
+            proof: proof.iter().map(|v| &v[..]),
+            storage_top_trie_changes: initialize_success.storage_top_trie_changes,
+            offchain_storage_changes: initialize_success.offchain_storage_changes,
+        }) {
+            Ok(success) => success,
+            Err(error) => return error_outcome(error),
+        };
+
+    if !call_success.logs.is_empty() {
+        log::debug!(target: "runtime", "Runtime logs: {}", call_success.logs);
+    }
+
+    let outcome = match memory_usage::try_to_vec(call_success.virtual_machine.value().as_ref()) {
+        Ok(return_value) => AfterInitializeOutcome::Success(return_value),
+        Err(err) => AfterInitializeOutcome::Error(RuntimeCallError::OutOfMemory(err)),
+    };
+    (outcome, call_success.virtual_machine.into_prototype())
+}
+
+/// Logs the given error that happened while instantiating a runtime, and turns it into a
+/// [`RuntimeError`].
+fn instantiation_error(
+    error: executor::host::NewErr,
+    max_memory_pages: Option<u32>,
+) -> RuntimeError {
+    match error {
+        executor::host::NewErr::VirtualMachine(executor::vm::NewErr::MemoryLimitExceeded) => {
+            log::warn!(
+                target: "runtime",
+                "Best block runtime exceeds the memory limit of {} pages",
+                max_memory_pages.unwrap()
+            );
+            RuntimeError::MemoryLimitExceeded
+        }
+        executor::host::NewErr::VirtualMachine(executor::vm::NewErr::CouldntAllocateMemory) => {
+            log::warn!(
+                target: "runtime",
+                "Not enough memory available to instantiate best block runtime"
+            );
+            RuntimeError::OutOfMemory
+        }
+        error => {
+            log::warn!(target: "runtime", "Failed to compile best block runtime: {}", error);
+            RuntimeError::Invalid
+        }
+    }
+}
+
+/// Cache of compiled runtimes, shared between the runtime services of all the chains.
+///
+/// Compiling a runtime is an expensive operation. When multiple chains use the exact same runtime
+/// code, which is common for example for test networks, the runtime is only compiled once.
+///
+/// Entries are reference-counted. A compiled runtime stays in the cache as long as at least one
+/// runtime service uses it, and is removed afterwards.
+#[derive(Default)]
+pub struct CompilationCache {
+    /// Compiled modules, indexed by the BLAKE2 hash of their (potentially compressed) code.
+    ///
+    /// Entries whose reference count has reached zero are lazily cleaned up.
+    modules: std::sync::Mutex<HashMap<[u8; 32], Weak<executor::vm::Module>, fnv::FnvBuildHasher>>,
+}
+
+impl CompilationCache {
+    /// Initializes a new empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds a virtual machine prototype from the given runtime code. The code is only compiled
+    /// if it isn't found in the cache.
+    ///
+    /// The returned `Arc` must be kept alive as long as the runtime is in use, in order for the
+    /// compiled module to stay in the cache.
+    fn instantiate(
+        &self,
+        code: &[u8],
+        heap_pages: executor::host::HeapPages,
+        max_memory_pages: Option<u32>,
+    ) -> Result<(executor::host::HostVmPrototype, Arc<executor::vm::Module>), executor::host::NewErr>
+    {
+        let code_hash = Host::blake2_256(code);
+
+        let mut modules = self.modules.lock().unwrap();
+
+        if let Some(module) = modules.get(&code_hash).and_then(Weak::upgrade) {
+            let vm = executor::host::HostVmPrototype::from_module(
+                (*module).clone(),
+                heap_pages,
+                max_memory_pages,
+            )?;
+            return Ok((vm, module));
+        }
+
+        let vm = executor::host::HostVmPrototype::new_with_memory_limit(
+            code,
+            heap_pages,
+            executor::vm::ExecHint::CompileAheadOfTime,
+            max_memory_pages,
+        )?;
+        let module = Arc::new(vm.module().clone());
+
+        modules.retain(|_, module| module.strong_count() != 0);
+        modules.insert(code_hash, Arc::downgrade(&module));
+
+        Ok((vm, module))
+    }
 }
 
 /// Starts the background task that updates the [`LatestKnownRuntime`].
-async fn start_background_task(runtime_service: &Arc<RuntimeService>) {
+///
+/// `refresh_requests` receives the requests sent by [`RuntimeService::refresh_now`].
+///
+/// `code_substitutes` contains the runtime code substitutes of the chain, indexed by block
+/// number. See [`chain_spec::ChainSpec::code_substitutes`].
+async fn start_background_task(
+    runtime_service: &Arc<RuntimeService>,
+    mut refresh_requests: mpsc::UnboundedReceiver<oneshot::Sender<()>>,
+    mut code_substitutes: BTreeMap<u64, CodeSubstitute>,
+) {
+    // Spec version of the on-chain runtime, as opposed to the spec version of the code
+    // substitute that might be in use. `None` if the on-chain runtime is invalid.
+    let mut onchain_spec_version = runtime_service
+        .latest_known_runtime
+        .lock()
+        .await
+        .runtime
+        .as_ref()
+        .ok()
+        .map(|runtime| runtime.runtime_spec.decode().spec_version);
+
+    // Code substitutes that aren't included in the chain specification are requested from the
+    // host in separate tasks, which send back the outcome on this channel.
+    let (substitutes_fetch_tx, mut substitutes_fetch_rx) = mpsc::unbounded();
+
     (runtime_service.tasks_executor.lock().await)("runtime-download".into(), {
         let runtime_service = runtime_service.clone();
-        let blocks_stream = {
-            let (best_block_header, best_blocks_subscription) =
-                runtime_service.sync_service.subscribe_best().await;
-            stream::once(future::ready(best_block_header)).chain(best_blocks_subscription)
+        let (mut current_best_block, mut blocks_stream) = {
+            let (best_block_header, best_blocks_subscription) = runtime_service
+                .data_provider
+                .subscribe_best()
+                .await
+                .unwrap();
+            (
+                best_block_header.clone(),
+                stream::once(future::ready(best_block_header))
+                    .chain(best_blocks_subscription)
+                    .boxed(),
+            )
         };
 
+        // Number of times the stream of best blocks has been subscribed to again since the last
+        // time a block has been received from it. See [`resubscribe_best`].
+        let mut consecutive_resubscriptions = 0;
+
+        // Requests from `refresh_requests` that are being processed. They are signalled at the
+        // start of the next iteration of the loop below.
+        let mut pending_refreshes = Vec::<oneshot::Sender<()>>::new();
+
         // Set to `true` when we expect the runtime in `latest_known_runtime` to match the runtime
         // of the best block. Initially `false`, as `latest_known_runtime` uses the genesis
         // runtime.
         let mut runtime_matches_best_block = false;
 
-        Box::pin(async move {
-            futures::pin_mut!(blocks_stream);
+        // Block number of the code substitute whose runtime is currently in use, if any.
+        let mut substitute_in_use = None::<u64>;
+        // Block number of the most recent code substitute that applies to the best block, if
+        // any, as of the previous iteration.
+        let mut latest_eligible_substitute = None::<u64>;
 
+        Box::pin(async move {
             loop {
+                // The previous iteration, if any, has finished processing the pending refreshes.
+                for refresh in pending_refreshes.drain(..) {
+                    let _ = refresh.send(());
+                }
+
                 // While major-syncing a chain, best blocks are updated continously. In that
                 // situation, the delay below is too short to prevent the runtime code from being
                 // continuously downloaded.
@@ -702,14 +1942,65 @@ async fn start_background_task(runtime_service: &Arc<RuntimeService>) {
                 // This delay is done at the beginning of the loop because the runtime is built
                 // as part of the initialization of the `RuntimeService`, and in order to make it
                 // possible to use `continue` without accidentally skipping this delay.
-                ffi::Delay::new(Duration::from_secs(3)).await;
+                // Refresh requests interrupt this delay.
+                if let future::Either::Right((Some(refresh), _)) =
+                    future::select(Host::sleep(Duration::from_secs(3)), refresh_requests.next())
+                        .await
+                {
+                    pending_refreshes.push(refresh);
+                }
 
-                // Wait until a new best block is known.
-                let mut new_best_block = match blocks_stream.next().await {
-                    Some(b) => b,
-                    None => break, // Stream is finished.
+                // Wait until a new best block is known, or until a refresh is requested, in which
+                // case the current best block is used.
+                let mut new_best_block = if pending_refreshes.is_empty() {
+                    match future::select(blocks_stream.next(), refresh_requests.next()).await {
+                        future::Either::Left((Some(b), _)) => {
+                            consecutive_resubscriptions = 0;
+                            b
+                        }
+                        future::Either::Left((None, _)) => {
+                            // The stream of best blocks has ended, for example because the data
+                            // provider has been reset. Subscribe again, and compare the head of
+                            // the new stream with the latest known best block.
+                            match resubscribe_best(
+                                &runtime_service,
+                                &mut consecutive_resubscriptions,
+                            )
+                            .await
+                            {
+                                Some((new_head, new_stream)) => {
+                                    blocks_stream = new_stream;
+                                    if new_head.hash == current_best_block.hash {
+                                        continue;
+                                    }
+                                    new_head
+                                }
+                                None => {
+                                    log::warn!(
+                                        target: "runtime",
+                                        "Failed to subscribe again to the best blocks of the \
+                                        chain. The runtime will no longer be updated."
+                                    );
+                                    notify_subscriptions_failure(&runtime_service).await;
+                                    break;
+                                }
+                            }
+                        }
+                        future::Either::Right((Some(refresh), _)) => {
+                            pending_refreshes.push(refresh);
+                            current_best_block.clone()
+                        }
+                        future::Either::Right((None, _)) => break, // Service is destroyed.
+                    }
+                } else {
+                    current_best_block.clone()
                 };
 
+                // Group together all the refresh requests that are already queued.
+                while let Some(Some(refresh)) = refresh_requests.next().now_or_never() {
+                    pending_refreshes.push(refresh);
+                }
+
                 // While the chain is running, it is often the case that more than one blocks
                 // is generated and announced roughly at the same time.
                 // We would like to avoid a situation where we receive a new best block, start
@@ -723,32 +2014,56 @@ async fn start_background_task(runtime_service: &Arc<RuntimeService>) {
                 // block already on the way.
                 // This delay needs to be long enough to de-duplicate forks, but it should still
                 // be small, as it adds artifical latency to the detecting runtime upgrades.
-                ffi::Delay::new(Duration::from_millis(500)).await;
+                // This delay is skipped if a refresh has been requested.
+                if pending_refreshes.is_empty() {
+                    Host::sleep(runtime_service.best_block_debounce).await;
+                }
                 while let Some(best_update) = blocks_stream.next().now_or_never() {
                     new_best_block = match best_update {
                         Some(b) => b,
                         None => break, // Stream is finished.
                     };
                 }
+                current_best_block = new_best_block.clone();
 
                 // Download the runtime code of this new best block.
-                let new_best_block_decoded = header::decode(&new_best_block).unwrap();
-                let new_best_block_hash = header::hash_from_scale_encoded_header(&new_best_block);
+                let new_best_block_hash = new_best_block.hash;
                 let code_query_result = runtime_service
-                    .sync_service
+                    .data_provider
                     .clone()
-                    .storage_query(
+                    .storage_query_quorum(
+                        new_best_block.number,
                         &new_best_block_hash,
-                        new_best_block_decoded.state_root,
-                        iter::once(&b":code"[..]).chain(iter::once(&b":heappages"[..])),
+                        &new_best_block.state_root,
+                        vec![b":code".to_vec(), b":heappages".to_vec()],
                     )
                     .await;
 
                 let best_near_head_of_chain = runtime_service
-                    .sync_service
+                    .data_provider
                     .is_near_head_of_chain_heuristic()
                     .await;
 
+                // Code substitutes that aren't included in the chain specification are obtained
+                // from the host once they become relevant. The fetches that have finished in the
+                // meanwhile are applied now.
+                let mut substitutes_fetched = false;
+                while let Some(Some((substitute_block, substitute))) =
+                    substitutes_fetch_rx.next().now_or_never()
+                {
+                    code_substitutes.insert(substitute_block, substitute);
+                    substitutes_fetched = true;
+                }
+                start_code_substitutes_fetch(
+                    &runtime_service,
+                    &mut code_substitutes,
+                    new_best_block.number,
+                    &substitutes_fetch_tx,
+                )
+                .await;
+
+                runtime_service.cpu_usage.throttle().await;
+
                 // Only lock `latest_known_runtime` now that everything is synchronous.
                 let mut latest_known_runtime = runtime_service.latest_known_runtime.lock().await;
                 let latest_known_runtime = &mut *latest_known_runtime;
@@ -799,34 +2114,130 @@ async fn start_background_task(runtime_service: &Arc<RuntimeService>) {
                 // `runtime_block_hash` is always updated in order to have the most recent
                 // block possible.
                 latest_known_runtime.runtime_block_hash = new_best_block_hash;
-                latest_known_runtime.runtime_block_height = new_best_block_decoded.number;
-                latest_known_runtime.runtime_block_state_root = *new_best_block_decoded.state_root;
-
-                // `continue` if there wasn't any change in `:code` and `:heappages`.
-                if new_code == latest_known_runtime.runtime_code
-                    && new_heap_pages == latest_known_runtime.heap_pages
+                latest_known_runtime.runtime_block_height = new_best_block.number;
+                latest_known_runtime.runtime_block_state_root = new_best_block.state_root;
+
+                let eligible_substitute = code_substitutes
+                    .range(..=new_best_block.number)
+                    .next_back()
+                    .map(|(block_number, _)| *block_number);
+                let code_changed = new_code != latest_known_runtime.runtime_code
+                    || new_heap_pages != latest_known_runtime.heap_pages;
+                let new_params_hash = if code_changed {
+                    runtime_params_hash(&new_code, &new_heap_pages)
+                } else {
+                    latest_known_runtime.runtime_params_hash
+                };
+                latest_known_runtime
+                    .recent_best_blocks
+                    .insert(new_best_block_hash, new_params_hash);
+
+                // `continue` if there wasn't any change in `:code` and `:heappages`, and if no
+                // new code substitute has become relevant or has been obtained from the host.
+                if !code_changed
+                    && eligible_substitute == latest_eligible_substitute
+                    && !substitutes_fetched
                 {
                     runtime_matches_best_block = true;
                     continue;
                 }
+                latest_eligible_substitute = eligible_substitute;
+
+                // Compiling the new runtime, if any, is accounted for as runtime execution.
+                let _measure = runtime_service
+                    .cpu_usage
+                    .measure(cpu_usage::Category::RuntimeExecution);
+
+                if code_changed {
+                    // If only `:heappages` has changed, the existing compiled module can be
+                    // reused rather than compiling the runtime again. This isn't possible if the
+                    // existing compiled module is a code substitute.
+                    let heap_pages_only_change = new_code == latest_known_runtime.runtime_code
+                        && latest_known_runtime.runtime.is_ok()
+                        && substitute_in_use.is_none();
+
+                    // Don't notify the user of an upgrade if we didn't expect the runtime to
+                    // match the best block in the first place.
+                    if runtime_matches_best_block {
+                        if heap_pages_only_change {
+                            log::info!(
+                                target: "runtime",
+                                "New heap pages detected around block #{} (block number might be wrong)",
+                                new_best_block.number
+                            );
+                        } else {
+                            log::info!(
+                                target: "runtime",
+                                "New runtime code detected around block #{} (block number might be wrong)",
+                                new_best_block.number
+                            );
+                        }
+                    }
 
-                // Don't notify the user of an upgrade if we didn't expect the runtime to match
-                // the best block in the first place.
-                if runtime_matches_best_block {
-                    log::info!(
-                        target: "runtime",
-                        "New runtime code detected around block #{} (block number might be wrong)",
-                        new_best_block_decoded.number
-                    );
+                    latest_known_runtime.runtime_code = new_code;
+                    latest_known_runtime.heap_pages = new_heap_pages;
+                    latest_known_runtime.runtime_params_hash = new_params_hash;
+                    latest_known_runtime.runtime = match &latest_known_runtime.runtime {
+                        Ok(runtime) if heap_pages_only_change => runtime.with_heap_pages(
+                            &latest_known_runtime.heap_pages,
+                            runtime_service.max_runtime_memory_pages,
+                        ),
+                        _ => SuccessfulRuntime::from_params(
+                            &runtime_service.compilation_cache,
+                            &latest_known_runtime.runtime_code,
+                            &latest_known_runtime.heap_pages,
+                            runtime_service.max_runtime_memory_pages,
+                        ),
+                    };
+
+                    onchain_spec_version = latest_known_runtime
+                        .runtime
+                        .as_ref()
+                        .ok()
+                        .map(|runtime| runtime.runtime_spec.decode().spec_version);
+                    substitute_in_use = None;
+
+                    if let Some(spec_version) = onchain_spec_version {
+                        latest_known_runtime
+                            .runtime_versions
+                            .insert(new_best_block.number, spec_version);
+                    }
                 }
 
                 runtime_matches_best_block = true;
-                latest_known_runtime.runtime_code = new_code;
-                latest_known_runtime.heap_pages = new_heap_pages;
-                latest_known_runtime.runtime = SuccessfulRuntime::from_params(
-                    &latest_known_runtime.runtime_code,
-                    &latest_known_runtime.heap_pages,
-                );
+
+                // A code substitute replaces the on-chain runtime if its spec version is the
+                // same as the one of the on-chain runtime.
+                let substitute = match onchain_spec_version {
+                    Some(spec_version) => find_code_substitute(
+                        &mut code_substitutes,
+                        new_best_block.number,
+                        spec_version,
+                        &runtime_service.compilation_cache,
+                        &latest_known_runtime.heap_pages,
+                        runtime_service.max_runtime_memory_pages,
+                    ),
+                    None => None,
+                };
+
+                match substitute {
+                    Some((block_number, _))
+                        if !code_changed && substitute_in_use == Some(block_number) =>
+                    {
+                        continue
+                    }
+                    Some((block_number, runtime)) => {
+                        log::info!(
+                            target: "runtime",
+                            "Using code substitute of block #{}",
+                            block_number
+                        );
+                        latest_known_runtime.runtime = Ok(runtime);
+                        substitute_in_use = Some(block_number);
+                    }
+                    None if !code_changed => continue,
+                    None => {}
+                }
 
                 // Elements in `runtime_version_subscriptions` are removed one by one and inserted
                 // back if the channel is still open.
@@ -838,7 +2249,7 @@ async fn start_background_task(runtime_service: &Arc<RuntimeService>) {
                         .runtime
                         .as_ref()
                         .map(|r| r.runtime_spec.clone())
-                        .map_err(|&()| ());
+                        .map_err(|_| ());
                     if subscription.send(to_send).is_ok() {
                         latest_known_runtime
                             .runtime_version_subscriptions
@@ -853,3 +2264,663 @@ async fn start_background_task(runtime_service: &Arc<RuntimeService>) {
         })
     });
 }
+
+/// Runtime code substitute, as found in the chain specification.
+enum CodeSubstitute {
+    /// Code of the substitute is known.
+    Code {
+        code: Vec<u8>,
+        /// Spec version of the runtime found in `code`. `None` if it hasn't been compiled yet.
+        spec_version: Option<u32>,
+    },
+    /// Only the BLAKE2-256 hash of the code is known. The code must be requested from the host.
+    Hash([u8; 32]),
+    /// The code is currently being requested from the host. See
+    /// [`start_code_substitutes_fetch`].
+    Fetching,
+    /// The code couldn't be obtained or is invalid. The substitute is ignored.
+    Invalid,
+}
+
+/// Maximum number of times in a row the stream of best blocks is subscribed to again without
+/// any block being received from it. See [`resubscribe_best`].
+const MAX_CONSECUTIVE_RESUBSCRIPTIONS: u32 = 5;
+
+/// Subscribes again to the best blocks of the data provider, after the previous stream has
+/// ended. Attempts are spaced with an exponentially increasing delay.
+///
+/// Returns `None` if the data provider refuses the subscription, or if
+/// [`MAX_CONSECUTIVE_RESUBSCRIPTIONS`] has been reached, in which case the stream is considered
+/// as unrecoverable.
+async fn resubscribe_best(
+    runtime_service: &Arc<RuntimeService>,
+    consecutive_resubscriptions: &mut u32,
+) -> Option<(
+    sync_service::HeaderNotification,
+    stream::BoxStream<'static, sync_service::HeaderNotification>,
+)> {
+    while *consecutive_resubscriptions < MAX_CONSECUTIVE_RESUBSCRIPTIONS {
+        Host::sleep(Duration::from_secs(1 << *consecutive_resubscriptions)).await;
+        *consecutive_resubscriptions += 1;
+
+        if let Ok(subscription) = runtime_service.data_provider.subscribe_best().await {
+            log::debug!(
+                target: "runtime",
+                "Subscribed again to the best blocks after the end of the previous stream"
+            );
+            return Some(subscription);
+        }
+    }
+
+    None
+}
+
+/// Notifies the subscriptions of the [`RuntimeService`] that no further update will happen.
+///
+/// The runtime version subscriptions receive an error, and all the subscriptions are then
+/// closed.
+async fn notify_subscriptions_failure(runtime_service: &Arc<RuntimeService>) {
+    let mut latest_known_runtime = runtime_service.latest_known_runtime.lock().await;
+    for mut subscription in latest_known_runtime.runtime_version_subscriptions.drain(..) {
+        let _ = subscription.send(Err(()));
+    }
+    latest_known_runtime.best_blocks_subscriptions.clear();
+}
+
+/// Spawns tasks that request from the host the code of all the substitutes that apply to blocks
+/// inferior or equal to `block_number` and whose code isn't known yet.
+///
+/// Once a request has finished, the new state of the substitute is sent on `results_tx`, and a
+/// refresh of the runtime is requested so that the substitute is taken into account.
+async fn start_code_substitutes_fetch(
+    runtime_service: &Arc<RuntimeService>,
+    code_substitutes: &mut BTreeMap<u64, CodeSubstitute>,
+    block_number: u64,
+    results_tx: &mpsc::UnboundedSender<(u64, CodeSubstitute)>,
+) {
+    for (substitute_block, substitute) in code_substitutes.range_mut(..=block_number) {
+        let hash = match substitute {
+            CodeSubstitute::Hash(hash) => *hash,
+            _ => continue,
+        };
+
+        *substitute = CodeSubstitute::Fetching;
+
+        let substitute_block = *substitute_block;
+        let results_tx = results_tx.clone();
+        let refresh_requests = runtime_service.refresh_requests.clone();
+
+        (runtime_service.tasks_executor.lock().await)(
+            "code-substitute-fetch".into(),
+            Box::pin(async move {
+                let substitute = match Host::code_substitute_fetch(&hash).await {
+                    Ok(code) if Host::blake2_256(&code) == hash => CodeSubstitute::Code {
+                        code,
+                        spec_version: None,
+                    },
+                    Ok(_) => {
+                        log::warn!(
+                            target: "runtime",
+                            "Code substitute of block #{} doesn't match its hash",
+                            substitute_block
+                        );
+                        CodeSubstitute::Invalid
+                    }
+                    Err(error) => {
+                        log::warn!(
+                            target: "runtime",
+                            "Failed to obtain code substitute of block #{}: {}",
+                            substitute_block, error
+                        );
+                        CodeSubstitute::Invalid
+                    }
+                };
+
+                if results_tx
+                    .unbounded_send((substitute_block, substitute))
+                    .is_ok()
+                {
+                    let (tx, _) = oneshot::channel();
+                    let _ = refresh_requests.unbounded_send(tx);
+                }
+            }),
+        );
+    }
+}
+
+/// Finds the most recent code substitute that applies to the block whose number is
+/// `block_number` and whose spec version is `onchain_spec_version`, and builds its runtime.
+///
+/// Returns the block number of the substitute and its runtime, or `None` if no substitute
+/// applies.
+fn find_code_substitute(
+    code_substitutes: &mut BTreeMap<u64, CodeSubstitute>,
+    block_number: u64,
+    onchain_spec_version: u32,
+    compilation_cache: &CompilationCache,
+    heap_pages: &Option<Vec<u8>>,
+    max_memory_pages: Option<u32>,
+) -> Option<(u64, SuccessfulRuntime)> {
+    for (substitute_block, substitute) in code_substitutes.range_mut(..=block_number).rev() {
+        let (code, spec_version) = match substitute {
+            CodeSubstitute::Code { code, spec_version } => (code, spec_version),
+            CodeSubstitute::Hash(_) | CodeSubstitute::Fetching | CodeSubstitute::Invalid => {
+                continue
+            }
+        };
+
+        if spec_version.map_or(false, |v| v != onchain_spec_version) {
+            continue;
+        }
+
+        match SuccessfulRuntime::from_params(
+            compilation_cache,
+            &Some(code.clone()),
+            heap_pages,
+            max_memory_pages,
+        ) {
+            Ok(runtime) => {
+                let runtime_spec_version = runtime.runtime_spec.decode().spec_version;
+                *spec_version = Some(runtime_spec_version);
+                if runtime_spec_version == onchain_spec_version {
+                    return Some((*substitute_block, runtime));
+                }
+            }
+            Err(error) => {
+                log::warn!(
+                    target: "runtime",
+                    "Failed to compile code substitute of block #{}: {:?}",
+                    substitute_block, error
+                );
+                *substitute = CodeSubstitute::Invalid;
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        finalized_runtime_source, run_after_initialize, runtime_heap_pages, runtime_params_hash,
+        AfterInitializeOutcome, CompilationCache, Config, FinalizedRuntimeSource,
+        NotificationsReceiver, RecentBestBlocks, RuntimeCallError, RuntimeService,
+        SuccessfulRuntime, MAX_RECENT_BEST_BLOCKS,
+    };
+    use crate::{
+        cpu_usage, header_cache, lossy_channel,
+        platform::{Host, Platform as _},
+        test_utils,
+    };
+    use core::{num::NonZeroU32, time::Duration};
+    use futures::prelude::*;
+    use smoldot::{chain_spec, executor, header, trie};
+    use std::{convert::TryFrom as _, sync::Arc};
+
+    /// Starts a [`RuntimeService`] whose data provider is the given [`test_utils::MockChain`],
+    /// built from the genesis block of `chain_spec`.
+    async fn start_on_mock_chain(
+        chain_spec: &chain_spec::ChainSpec,
+        chain: &Arc<test_utils::MockChain>,
+    ) -> Arc<RuntimeService> {
+        let genesis_header = chain_spec.genesis_block_header().scale_encoding_vec();
+        RuntimeService::new(Config {
+            tasks_executor: test_utils::tasks_executor(),
+            data_provider: chain.clone(),
+            header_cache: Arc::new(header_cache::HeaderCache::new(
+                16,
+                0,
+                Vec::new(),
+                genesis_header.clone(),
+                genesis_header,
+            )),
+            chain_spec,
+            genesis_block_hash: None,
+            genesis_block_state_root: None,
+            compilation_cache: Arc::new(CompilationCache::new()),
+            max_runtime_memory_pages: None,
+            best_block_debounce: Duration::from_millis(500),
+            max_notifications_per_second: None,
+            cpu_usage: Arc::new(cpu_usage::CpuUsage::new(
+                NonZeroU32::new(1).unwrap(),
+                NonZeroU32::new(1).unwrap(),
+            )),
+            max_failed_call_recordings: 0,
+        })
+        .await
+    }
+
+    #[test]
+    fn runtime_call_on_mock_chain() {
+        let chain_spec =
+            chain_spec::ChainSpec::from_json_bytes(&include_bytes!("../../../westend.json")[..])
+                .unwrap();
+        let genesis_hash = chain_spec.genesis_block_header().hash();
+
+        test_utils::block_on(
+            async move {
+                let chain = test_utils::MockChain::new(&chain_spec);
+                let service = start_on_mock_chain(&chain_spec, &chain).await;
+
+                let version = service
+                    .recent_best_block_runtime_call("Core_version", &[], None)
+                    .await
+                    .unwrap();
+                assert_eq!(&version[1..8], b"westend");
+                assert_eq!(chain.proof_requests(), vec![genesis_hash]);
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn runtime_call_with_scripted_peers() {
+        let chain_spec =
+            chain_spec::ChainSpec::from_json_bytes(&include_bytes!("../../../westend.json")[..])
+                .unwrap();
+
+        test_utils::block_on(
+            async move {
+                let chain = test_utils::MockChain::new(&chain_spec);
+                let service = start_on_mock_chain(&chain_spec, &chain).await;
+
+                chain.script_responses([
+                    test_utils::ScriptedResponse::Fail,
+                    test_utils::ScriptedResponse::Canned(vec![vec![1, 2, 3]]),
+                ]);
+
+                // Neither a missing proof nor a proof that doesn't match the state root can be
+                // used to perform the call.
+                let outcome = service
+                    .recent_best_block_runtime_call(
+                        "AccountNonceApi_account_nonce",
+                        &[&[0; 32][..]],
+                        None,
+                    )
+                    .await;
+                assert!(matches!(
+                    outcome,
+                    Err(RuntimeCallError::StorageRetrieval(_))
+                ));
+                let outcome = service
+                    .recent_best_block_runtime_call(
+                        "AccountNonceApi_account_nonce",
+                        &[&[0; 32][..]],
+                        None,
+                    )
+                    .await;
+                assert!(matches!(
+                    outcome,
+                    Err(RuntimeCallError::StorageRetrieval(_))
+                ));
+
+                // Once the scripted responses are exhausted, the peers answer honestly.
+                let nonce = service
+                    .recent_best_block_runtime_call(
+                        "AccountNonceApi_account_nonce",
+                        &[&[0; 32][..]],
+                        None,
+                    )
+                    .await
+                    .unwrap();
+                assert_eq!(nonce, vec![0, 0, 0, 0]);
+                assert_eq!(chain.proof_requests().len(), 3);
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn runtime_call_follows_best_block() {
+        let chain_spec =
+            chain_spec::ChainSpec::from_json_bytes(&include_bytes!("../../../westend.json")[..])
+                .unwrap();
+
+        test_utils::block_on(
+            async move {
+                let chain = test_utils::MockChain::new(&chain_spec);
+                let service = start_on_mock_chain(&chain_spec, &chain).await;
+                let (_, mut best_blocks) = service.subscribe_best().await;
+
+                let new_best = chain.push_best_block([(
+                    b":heappages".to_vec(),
+                    Some(4096u64.to_le_bytes().to_vec()),
+                )]);
+                assert_eq!(best_blocks.next().await.unwrap().hash, new_best);
+
+                assert!(service
+                    .recent_best_block_runtime_call("Core_version", &[], None)
+                    .await
+                    .is_ok());
+                assert_eq!(chain.proof_requests().last(), Some(&new_best));
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn notifications_rate_limited_and_coalesced() {
+        test_utils::block_on(
+            async move {
+                let (mut tx, rx) = lossy_channel::channel();
+                let mut rx = NotificationsReceiver::new(rx, Some(Duration::from_secs(1)));
+                let start = Host::now();
+
+                tx.send(1).unwrap();
+                assert_eq!(rx.next().await, Some(1));
+                assert_eq!(Host::now() - start, Duration::new(0, 0));
+
+                // Sent while the receiver is waiting for the minimum interval to elapse.
+                tx.send(2).unwrap();
+                tx.send(3).unwrap();
+                assert_eq!(rx.next().await, Some(3));
+                assert_eq!(Host::now() - start, Duration::from_secs(1));
+
+                Host::sleep(Duration::from_secs(5)).await;
+                tx.send(4).unwrap();
+                assert_eq!(rx.next().await, Some(4));
+                assert_eq!(Host::now() - start, Duration::from_secs(6));
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn notifications_not_limited() {
+        test_utils::block_on(
+            async move {
+                let (mut tx, rx) = lossy_channel::channel();
+                let mut rx = NotificationsReceiver::new(rx, None);
+                let start = Host::now();
+
+                for n in 0..10 {
+                    tx.send(n).unwrap();
+                    assert_eq!(rx.next().await, Some(n));
+                }
+
+                assert_eq!(Host::now() - start, Duration::new(0, 0));
+            },
+            None,
+        )
+    }
+
+    #[test]
+    fn heap_pages_precedence() {
+        let heap_pages =
+            |memory_import: Option<(u32, Option<u32>)>, storage: Option<&[u8]>| {
+                let memory_import = memory_import.map(|(initial, maximum)| {
+                    executor::code_info::MemoryImportLimits { initial, maximum }
+                });
+                runtime_heap_pages(memory_import.as_ref(), storage)
+                    .ok()
+                    .map(u32::from)
+            };
+        let storage = |n: u64| n.to_le_bytes();
+        let invalid = &[1, 2, 3][..];
+
+        // No information from the runtime.
+        assert_eq!(heap_pages(None, None), Some(2048));
+        assert_eq!(heap_pages(None, Some(&storage(4096))), Some(4096));
+        assert_eq!(heap_pages(None, Some(invalid)), None);
+
+        // Runtime imports its memory without a maximum.
+        assert_eq!(heap_pages(Some((20, None)), None), Some(2048));
+        assert_eq!(
+            heap_pages(Some((20, None)), Some(&storage(4096))),
+            Some(4096)
+        );
+        assert_eq!(heap_pages(Some((20, None)), Some(invalid)), None);
+
+        // Runtime imports its memory with a maximum above the requested heap pages.
+        assert_eq!(heap_pages(Some((20, Some(8000))), None), Some(2048));
+        assert_eq!(
+            heap_pages(Some((20, Some(8000))), Some(&storage(4096))),
+            Some(4096)
+        );
+        assert_eq!(
+            heap_pages(Some((20, Some(8000))), Some(invalid)),
+            Some(2048)
+        );
+
+        // Runtime imports its memory with a maximum below the requested heap pages.
+        assert_eq!(heap_pages(Some((20, Some(300))), None), Some(280));
+        assert_eq!(
+            heap_pages(Some((20, Some(300))), Some(&storage(4096))),
+            Some(280)
+        );
+        assert_eq!(heap_pages(Some((20, Some(300))), Some(invalid)), Some(280));
+        assert_eq!(heap_pages(Some((20, Some(10))), None), Some(0));
+    }
+
+    #[test]
+    fn runtime_params_hash_unambiguous() {
+        let hash = |code: Option<&[u8]>, heap_pages: Option<&[u8]>| {
+            runtime_params_hash(&code.map(|c| c.to_vec()), &heap_pages.map(|h| h.to_vec()))
+        };
+
+        assert_eq!(hash(Some(b"ab"), None), hash(Some(b"ab"), None));
+        assert_ne!(hash(Some(b"ab"), None), hash(Some(b"a"), Some(b"b")));
+        assert_ne!(hash(Some(b""), None), hash(None, None));
+        assert_ne!(hash(Some(b"ab"), None), hash(None, Some(b"ab")));
+    }
+
+    #[test]
+    fn recent_best_blocks_bounded() {
+        let hash = |n: usize| {
+            let mut hash = [0; 32];
+            hash[..8].copy_from_slice(&u64::try_from(n).unwrap().to_le_bytes());
+            hash
+        };
+
+        let mut recent = RecentBestBlocks::new();
+        for n in 0..MAX_RECENT_BEST_BLOCKS + 10 {
+            recent.insert(hash(n), hash(n + 1));
+        }
+
+        assert!(recent.get(&hash(0)).is_none());
+        assert!(recent.get(&hash(9)).is_none());
+        assert_eq!(recent.get(&hash(10)), Some(&hash(11)));
+
+        // The same block can be the best block multiple times, in which case the most recent
+        // entry is used.
+        recent.insert(hash(10), hash(50));
+        assert_eq!(recent.get(&hash(10)), Some(&hash(50)));
+    }
+
+    #[test]
+    fn finalized_runtime_during_upgrade() {
+        let old_runtime = runtime_params_hash(&Some(b"old".to_vec()), &None);
+        let new_runtime = runtime_params_hash(&Some(b"new".to_vec()), &None);
+
+        // Blocks 1 and 2 have the old runtime, and the runtime upgrade is enacted at block 3.
+        let mut recent = RecentBestBlocks::new();
+        recent.insert([1; 32], old_runtime);
+        recent.insert([2; 32], old_runtime);
+        recent.insert([3; 32], new_runtime);
+        recent.insert([4; 32], new_runtime);
+
+        // While the best block has the new runtime, finalizing a block that precedes the
+        // upgrade reuses the runtime of the previous finalized block.
+        assert_eq!(
+            finalized_runtime_source(recent.get(&[2; 32]), Some(&old_runtime), &new_runtime),
+            FinalizedRuntimeSource::Previous
+        );
+
+        // Finalizing the upgrade reuses the runtime of the best block.
+        assert_eq!(
+            finalized_runtime_source(recent.get(&[3; 32]), Some(&old_runtime), &new_runtime),
+            FinalizedRuntimeSource::Best
+        );
+        assert_eq!(
+            finalized_runtime_source(recent.get(&[4; 32]), None, &new_runtime),
+            FinalizedRuntimeSource::Best
+        );
+
+        // The old runtime isn't available if no block has been finalized yet.
+        assert_eq!(
+            finalized_runtime_source(recent.get(&[2; 32]), None, &new_runtime),
+            FinalizedRuntimeSource::Download
+        );
+
+        // Blocks that haven't been observed as best block, such as blocks of forks that have
+        // been finalized, must always be downloaded.
+        assert_eq!(
+            finalized_runtime_source(recent.get(&[5; 32]), Some(&new_runtime), &new_runtime),
+            FinalizedRuntimeSource::Download
+        );
+    }
+
+    #[test]
+    fn runtime_version_section_preferred() {
+        // The code of this chain isn't compressed, which makes it possible to modify it.
+        let chain_spec = smoldot::chain_spec::ChainSpec::from_json_bytes(
+            &include_bytes!("../../../westend.json")[..],
+        )
+        .unwrap();
+        let code = chain_spec.genesis_storage_value(b":code").unwrap().to_vec();
+
+        // Without the section, the version is obtained by calling `Core_version`.
+        let cache = CompilationCache::new();
+        let runtime = match SuccessfulRuntime::from_params(&cache, &Some(code.clone()), &None, None)
+        {
+            Ok(r) => r,
+            Err(err) => panic!("{:?}", err),
+        };
+        assert_eq!(runtime.runtime_spec.decode().spec_name, "westend");
+
+        // Append a `runtime_version` custom section to the code.
+        let version = [
+            16, b't', b'e', b's', b't', 16, b't', b'e', b's', b't', 1, 0, 0, 0, 5, 0, 0, 0, 1, 0,
+            0, 0, 0, 1, 0, 0, 0,
+        ];
+        let mut with_section = code;
+        with_section.push(0);
+        with_section.push((1 + 15 + version.len()) as u8);
+        with_section.push(15);
+        with_section.extend_from_slice(b"runtime_version");
+        with_section.extend_from_slice(&version);
+
+        let runtime = match SuccessfulRuntime::from_params(&cache, &Some(with_section), &None, None)
+        {
+            Ok(r) => r,
+            Err(err) => panic!("{:?}", err),
+        };
+        assert_eq!(runtime.runtime_spec.decode().spec_name, "test");
+        assert_eq!(runtime.runtime_spec.decode().spec_version, 5);
+        assert!(runtime.memory_import.is_some());
+    }
+
+    #[test]
+    fn heap_pages_change_doesnt_recompile() {
+        let chain_spec = smoldot::chain_spec::ChainSpec::from_json_bytes(
+            &include_bytes!("../../../westend-westmint.json")[..],
+        )
+        .unwrap();
+        let code = chain_spec
+            .genesis_storage_value(b":code")
+            .map(|code| code.to_vec());
+
+        let cache = CompilationCache::new();
+        let runtime = match SuccessfulRuntime::from_params(&cache, &code, &None, None) {
+            Ok(r) => r,
+            Err(err) => panic!("{:?}", err),
+        };
+        // The cache is no longer reachable, meaning that any compilation would necessarily
+        // produce a different module.
+        drop(cache);
+
+        let new_heap_pages = Some(4096u64.to_le_bytes().to_vec());
+        let updated = match runtime.with_heap_pages(&new_heap_pages, None) {
+            Ok(r) => r,
+            Err(err) => panic!("{:?}", err),
+        };
+
+        assert!(Arc::ptr_eq(
+            &runtime._compiled_module,
+            &updated._compiled_module
+        ));
+        assert_eq!(
+            updated.virtual_machine.as_ref().unwrap().heap_pages(),
+            executor::vm::HeapPages::from(4096)
+        );
+        assert_eq!(
+            updated.runtime_spec.decode().spec_version,
+            runtime.runtime_spec.decode().spec_version
+        );
+    }
+
+    #[test]
+    fn call_after_initialize_completes_proof() {
+        let chain_spec = smoldot::chain_spec::ChainSpec::from_json_bytes(
+            &include_bytes!("../../../westend.json")[..],
+        )
+        .unwrap();
+        let genesis_header = chain_spec.genesis_block_header().clone();
+        let genesis_hash = genesis_header.hash();
+        let code = chain_spec
+            .genesis_storage_value(b":code")
+            .map(|code| code.to_vec());
+        let cache = CompilationCache::new();
+        let mut runtime = match SuccessfulRuntime::from_params(&cache, &code, &None, None) {
+            Ok(r) => r,
+            Err(err) => panic!("{:?}", err),
+        };
+
+        let initialized_block_header = header::HeaderRef {
+            parent_hash: &genesis_hash,
+            number: 1,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::empty(),
+        }
+        .scale_encoding_vec();
+        let proof_of = |keys: &[Vec<u8>]| {
+            trie::proof_encode::build_proof(trie::proof_encode::Config {
+                entries: chain_spec.genesis_storage(),
+                requested_keys: keys.iter().map(|k| &k[..]),
+            })
+        };
+
+        // Start with a proof that only contains `:code`, and add the keys reported as missing
+        // until the call succeeds, as is done when downloading them from the network.
+        let mut proof_keys = vec![b":code".to_vec()];
+        let return_value = loop {
+            let (outcome, prototype) = run_after_initialize(
+                runtime.virtual_machine.take().unwrap(),
+                &genesis_header.state_root,
+                &initialized_block_header,
+                "AccountNonceApi_account_nonce",
+                &[&[0; 32][..]],
+                &proof_of(&proof_keys),
+            );
+            runtime.virtual_machine = Some(prototype);
+
+            match outcome {
+                AfterInitializeOutcome::Success(value) => break value,
+                AfterInitializeOutcome::MissingProofEntry(key) => {
+                    assert!(!proof_keys.contains(&key));
+                    proof_keys.push(key);
+                }
+                AfterInitializeOutcome::Error(err) => panic!("{}", err),
+            }
+        };
+
+        assert!(proof_keys.len() > 1);
+        assert_eq!(return_value, vec![0, 0, 0, 0]);
+
+        // A proof that doesn't match the state root is refused.
+        let (outcome, _) = run_after_initialize(
+            runtime.virtual_machine.take().unwrap(),
+            &[0; 32],
+            &initialized_block_header,
+            "AccountNonceApi_account_nonce",
+            &[&[0; 32][..]],
+            &proof_of(&proof_keys),
+        );
+        assert!(matches!(
+            outcome,
+            AfterInitializeOutcome::Error(RuntimeCallError::InvalidCallProof)
+        ));
+    }
+}
@@ -0,0 +1,233 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Running the client as a standalone program rather than within a browser.
+//!
+//! [`run`] starts the client with the given chain specifications, and serves the JSON-RPC
+//! clients that connect through WebSocket. The path of the WebSocket URL designates the chain:
+//! `/` and `/0` designate the first chain, `/1` the second chain, and so on.
+//!
+//! Each WebSocket connection is a separate JSON-RPC client, and all its subscriptions are
+//! destroyed when it disconnects.
+
+// When compiling tests, `platform::Host` isn't `native::Native` and this module is unused.
+#![cfg_attr(test, allow(dead_code))]
+
+use crate::{
+    json_rpc_service, network_service,
+    platform::{self, native, JsonRpcMessage},
+    sync_service, ChainConfig, ClientConfig,
+};
+
+use async_std::net::{TcpListener, TcpStream};
+use core::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use futures::prelude::*;
+use std::{io, net};
+
+/// Configuration for [`run`].
+pub struct Config {
+    /// Chain specifications of the chains to run, in JSON.
+    pub chain_specs: Vec<String>,
+    /// Address to listen on for WebSocket JSON-RPC connections.
+    pub json_rpc_address: net::SocketAddr,
+    /// Maximum level of the logs written to the standard error output.
+    pub max_log_level: log::LevelFilter,
+}
+
+/// Runs the client and the JSON-RPC server. Only returns if the JSON-RPC server couldn't start.
+pub async fn run(config: Config) -> Result<(), io::Error> {
+    let listener = TcpListener::bind(config.json_rpc_address).await?;
+    let num_chains = config.chain_specs.len();
+
+    let chains = config
+        .chain_specs
+        .into_iter()
+        .map(|specification| ChainConfig {
+            specification,
+            json_rpc_running: true,
+            json_rpc_methods_filter: Default::default(),
+            cpu_weight: NonZeroU32::new(1).unwrap(),
+            sync_mode: sync_service::SyncMode::HeadersAndJustifications,
+            quorum_size: NonZeroUsize::new(1).unwrap(),
+            lazy: false,
+            lazy_idle_timeout: None,
+            isolated_network: false,
+            json_rpc_cross_validation: None,
+            json_rpc_fallback: None,
+            json_rpc_storage_prefetch_keys: None,
+            json_rpc_validate_transactions: false,
+            custom_consensus_engines: Vec::new(),
+            max_runtime_memory_pages: None,
+        })
+        .collect::<Vec<_>>();
+
+    let client = crate::start_client(
+        chains,
+        ClientConfig {
+            max_log_level: config.max_log_level,
+            request_compressed_responses: false,
+            dns_over_https_url: None,
+            unstable_p2p_requests: false,
+            unstable_p2p_protocols: Vec::new(),
+            json_rpc_consumer_limits: json_rpc_service::ConsumerLimits {
+                max_concurrent_requests: None,
+                max_queued_requests: 0,
+                max_requests_per_second: None,
+            },
+            dial_strategy: network_service::DialStrategy::Staggered {
+                delay: Duration::from_secs(1),
+            },
+            dial_timeout: Duration::from_secs(10),
+            connection_limits: platform::ConnectionLimits::default(),
+            peers_target: 10,
+            allow_relayed_connections: false,
+            privacy: network_service::PrivacyConfig {
+                refuse_identify: false,
+                hide_best_block: false,
+                peer_id_rotation_interval: None,
+                max_request_jitter: Duration::new(0, 0),
+            },
+            network_key: None,
+            peer_events_version: crate::events::Version::V2,
+        },
+    );
+
+    let server = async move {
+        // Identifier assigned to the next JSON-RPC client, passed as `user_data`.
+        let mut next_user_data = 0u32;
+
+        loop {
+            let (socket, address) = match listener.accept().await {
+                Ok(s) => s,
+                Err(error) => {
+                    log::warn!(target: "json-rpc", "Failed to accept connection: {}", error);
+                    continue;
+                }
+            };
+
+            let user_data = next_user_data;
+            next_user_data = next_user_data.wrapping_add(1);
+
+            async_std::task::spawn(async move {
+                if let Err(error) = serve_client(socket, user_data, num_chains).await {
+                    log::debug!(
+                        target: "json-rpc",
+                        "JSON-RPC connection with {} closed: {}", address, error
+                    );
+                }
+            });
+        }
+    };
+
+    future::join(client, server).await;
+    Ok(())
+}
+
+/// Performs the WebSocket handshake on the given socket, then forwards the JSON-RPC requests
+/// received on it to the client, and the responses and notifications back.
+async fn serve_client(socket: TcpStream, user_data: u32, num_chains: usize) -> Result<(), String> {
+    let mut server = soketto::handshake::Server::new(socket);
+
+    let request = server
+        .receive_request()
+        .await
+        .map_err(|err| err.to_string())?;
+    let chain_index = match chain_index_from_path(request.path()) {
+        Some(index) if index < num_chains => index,
+        _ => {
+            server
+                .send_response(&soketto::handshake::server::Response::Reject { status_code: 404 })
+                .await
+                .map_err(|err| err.to_string())?;
+            return Err(format!("Invalid chain: {}", request.path()));
+        }
+    };
+    let key = request.into_key();
+    server
+        .send_response(&soketto::handshake::server::Response::Accept {
+            key: &key,
+            protocol: None,
+        })
+        .await
+        .map_err(|err| err.to_string())?;
+
+    let (mut sender, mut receiver) = server.into_builder().finish();
+    let mut responses = native::register_json_rpc_client(user_data);
+
+    let outcome = async {
+        let requests = async {
+            loop {
+                let mut message = Vec::new();
+                if let Err(error) = receiver.receive_data(&mut message).await {
+                    break Err::<(), _>(error.to_string());
+                }
+                native::send_json_rpc(JsonRpcMessage::Request {
+                    json_rpc_request: message.into_boxed_slice(),
+                    chain_index,
+                    user_data,
+                });
+            }
+        };
+
+        let responses = async {
+            while let Some(response) = responses.next().await {
+                sender
+                    .send_text(response)
+                    .await
+                    .map_err(|err| err.to_string())?;
+                sender.flush().await.map_err(|err| err.to_string())?;
+            }
+            Ok::<(), String>(())
+        };
+
+        futures::pin_mut!(requests, responses);
+        match future::select(requests, responses).await {
+            future::Either::Left((result, _)) | future::Either::Right((result, _)) => result,
+        }
+    }
+    .await;
+
+    // The subscriptions of the client are no longer needed once it has disconnected.
+    native::send_json_rpc(JsonRpcMessage::UnsubscribeAll { user_data });
+    outcome
+}
+
+/// Returns the index of the chain designated by the given path of a WebSocket URL, or `None` if
+/// the path is invalid.
+fn chain_index_from_path(path: &str) -> Option<usize> {
+    match path.trim_start_matches('/') {
+        "" => Some(0),
+        index => index.parse().ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chain_index_from_path;
+
+    #[test]
+    fn chain_index() {
+        assert_eq!(chain_index_from_path("/"), Some(0));
+        assert_eq!(chain_index_from_path("/0"), Some(0));
+        assert_eq!(chain_index_from_path("/2"), Some(2));
+        assert_eq!(chain_index_from_path("/foo"), None);
+        assert_eq!(chain_index_from_path("/1/2"), None);
+    }
+}
@@ -0,0 +1,183 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Background download of the storage values that are likely to be requested next.
+//!
+//! JSON-RPC clients such as dashboards typically query the same storage items of the best block
+//! again and again, every time a new block is produced. The [`StoragePrefetch`] remembers the
+//! keys that have recently been passed to [`StoragePrefetch::get`], and, whenever a new best
+//! block is reported by the [`sync_service::SyncService`], downloads and verifies the proof of
+//! these keys ahead of time, using the lowest priority class of network requests. Queries that
+//! target the best block can then be answered without waiting for the network.
+//!
+//! Only the values of the latest prefetched block are kept.
+
+use crate::sync_service;
+
+use futures::{lock::Mutex, prelude::*};
+use std::{collections::HashMap, num::NonZeroUsize, pin::Pin, sync::Arc};
+
+/// Configuration for [`start`].
+pub struct Config {
+    /// Service used to follow the best block and download the storage proofs.
+    pub sync_service: Arc<sync_service::SyncService>,
+
+    /// Maximum number of recently-requested keys whose value is prefetched. Keys are evicted in
+    /// a least-recently-requested fashion.
+    pub max_keys: NonZeroUsize,
+}
+
+/// Creates a new [`StoragePrefetch`] and spawns a background task that fills it at each new
+/// best block.
+pub fn start(
+    config: Config,
+    tasks_executor: &mut dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>),
+) -> Arc<StoragePrefetch> {
+    let prefetch = Arc::new(StoragePrefetch::new(config.max_keys));
+
+    tasks_executor("storage-prefetch".into(), {
+        let prefetch = prefetch.clone();
+        let sync_service = config.sync_service;
+        Box::pin(async move {
+            let (_, best_blocks_subscription) = sync_service.subscribe_best().await;
+            futures::pin_mut!(best_blocks_subscription);
+
+            while let Some(block) = best_blocks_subscription.next().await {
+                let keys = prefetch.keys().await;
+                if keys.is_empty() {
+                    continue;
+                }
+
+                match sync_service
+                    .clone()
+                    .storage_prefetch(&block.hash, &block.state_root, keys.iter())
+                    .await
+                {
+                    Ok(values) => prefetch.set(block.hash, keys, values).await,
+                    Err(err) => {
+                        log::debug!(
+                            target: "storage-prefetch",
+                            "Failed to prefetch {} key(s): {}", keys.len(), err
+                        );
+                    }
+                }
+            }
+        })
+    });
+
+    prefetch
+}
+
+/// See [the module-level documentation](..).
+pub struct StoragePrefetch {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Keys recently passed to [`StoragePrefetch::get`].
+    requested_keys: lru::LruCache<Vec<u8>, ()>,
+    /// Hash of the block the values in `values` belong to, or `None` if nothing has been
+    /// prefetched yet.
+    block_hash: Option<[u8; 32]>,
+    /// Verified storage values of the block whose hash is `block_hash`.
+    values: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+}
+
+impl StoragePrefetch {
+    /// Initializes a new empty [`StoragePrefetch`]. See [`Config::max_keys`].
+    pub fn new(max_keys: NonZeroUsize) -> Self {
+        StoragePrefetch {
+            inner: Mutex::new(Inner {
+                requested_keys: lru::LruCache::new(max_keys.get()),
+                block_hash: None,
+                values: Default::default(),
+            }),
+        }
+    }
+
+    /// Returns the value of `key` in the storage of the given block, if it has been prefetched.
+    /// The outer `Option` is `None` if the value isn't known, while the inner `Option` is `None`
+    /// if the storage doesn't contain any value for this key.
+    ///
+    /// The key is recorded as recently requested, meaning that it will be prefetched at the
+    /// next best block.
+    pub async fn get(&self, block_hash: &[u8; 32], key: &[u8]) -> Option<Option<Vec<u8>>> {
+        let mut inner = self.inner.lock().await;
+
+        inner.requested_keys.put(key.to_vec(), ());
+
+        if inner.block_hash.as_ref() != Some(block_hash) {
+            return None;
+        }
+        inner.values.get(key).cloned()
+    }
+
+    /// Returns the list of keys to prefetch.
+    async fn keys(&self) -> Vec<Vec<u8>> {
+        self.inner
+            .lock()
+            .await
+            .requested_keys
+            .iter()
+            .map(|(key, ())| key.clone())
+            .collect()
+    }
+
+    /// Replaces the prefetched values with the given ones.
+    async fn set(&self, block_hash: [u8; 32], keys: Vec<Vec<u8>>, values: Vec<Option<Vec<u8>>>) {
+        debug_assert_eq!(keys.len(), values.len());
+        let mut inner = self.inner.lock().await;
+        inner.block_hash = Some(block_hash);
+        inner.values = keys.into_iter().zip(values).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StoragePrefetch;
+    use futures::prelude::*;
+    use std::num::NonZeroUsize;
+
+    #[test]
+    fn learns_requested_keys() {
+        let prefetch = StoragePrefetch::new(NonZeroUsize::new(2).unwrap());
+
+        async move {
+            assert_eq!(prefetch.get(&[1; 32], b"foo").await, None);
+            assert_eq!(prefetch.get(&[1; 32], b"bar").await, None);
+            assert_eq!(prefetch.get(&[1; 32], b"foo").await, None);
+            assert_eq!(prefetch.get(&[1; 32], b"baz").await, None);
+
+            // `bar` is the least recently requested key and has been evicted.
+            let mut keys = prefetch.keys().await;
+            keys.sort();
+            assert_eq!(keys, vec![b"baz".to_vec(), b"foo".to_vec()]);
+
+            prefetch
+                .set([2; 32], keys, vec![None, Some(b"value".to_vec())])
+                .await;
+            assert_eq!(
+                prefetch.get(&[2; 32], b"foo").await,
+                Some(Some(b"value".to_vec()))
+            );
+            assert_eq!(prefetch.get(&[2; 32], b"baz").await, Some(None));
+            assert_eq!(prefetch.get(&[3; 32], b"foo").await, None);
+        }
+        .now_or_never()
+        .unwrap();
+    }
+}
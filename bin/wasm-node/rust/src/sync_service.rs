@@ -27,23 +27,40 @@
 //! Use [`SyncService::subscribe_best`] and [`SyncService::subscribe_finalized`] to get notified
 //! about updates of the best and finalized blocks.
 
-use crate::{ffi, lossy_channel, network_service, runtime_service};
+use crate::{
+    canonical_index, cpu_usage, lossy_channel, memory_usage, network_service,
+    platform::{Host, Platform as _},
+    request_trace, runtime_service, work_queues,
+};
 
-use blake2_rfc::blake2b::blake2b;
 use futures::{
     channel::{mpsc, oneshot},
     lock::Mutex,
     prelude::*,
 };
+use rand::seq::SliceRandom as _;
 use smoldot::{
-    chain, header,
+    chain,
+    finality::justification,
+    header,
     informant::HashDisplay,
     libp2p::{self, PeerId},
     network::{self, protocol, service},
     sync::{all, para},
     trie::{self, prefix_proof, proof_verify},
+    verify,
+};
+use std::{
+    cmp,
+    collections::{HashMap, VecDeque},
+    convert::TryFrom as _,
+    fmt,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    ops,
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
 };
-use std::{collections::HashMap, convert::TryFrom as _, fmt, num::NonZeroU32, pin::Pin, sync::Arc};
 
 pub use crate::lossy_channel::Receiver as NotificationsReceiver;
 
@@ -66,6 +83,98 @@ pub struct Config {
     /// Extra fields used when the chain is a parachain.
     /// If `None`, this chain is a standalone chain or a relay chain.
     pub parachain: Option<ConfigParachain>,
+
+    /// CPU time accounting of the chain. The time spent verifying headers, finality proofs, and
+    /// storage proofs is accounted for in there.
+    pub cpu_usage: Arc<cpu_usage::CpuUsage>,
+
+    /// Duration, in milliseconds, of a slot of the consensus engine of the chain, or `None` if
+    /// the chain doesn't use slots or if the duration is unknown.
+    ///
+    /// Used in order to verify that the blocks announced by peers have been produced at a
+    /// plausible time. See [`Config::max_announce_future_drift`].
+    pub slot_duration: Option<NonZeroU64>,
+
+    /// Maximum duration that the slot of an announced block can be in the future compared to
+    /// the current time. Block announces that exceed this limit are ignored, so that peers
+    /// can't make the node believe that the head of the chain is further than it really is.
+    ///
+    /// Has no effect if [`Config::slot_duration`] is `None`.
+    pub max_announce_future_drift: Duration,
+
+    /// If `true`, Babe secondary slot claims are accepted regardless of the types of slot claims
+    /// allowed by the Babe configuration. See [`all::Config::babe_relaxed_secondary_slots`].
+    ///
+    /// Has no effect for parachains.
+    pub babe_relaxed_secondary_slots: bool,
+
+    /// What to download from the network when synchronizing the chain.
+    pub sync_mode: SyncMode,
+
+    /// How to share the network requests between keeping track of the head of the chain and
+    /// answering the queries of the other services, such as [`SyncService::storage_query`].
+    pub work_queues: work_queues::Config,
+
+    /// Number of blocks below the best block whose hash is kept in memory, in order to be able
+    /// to answer [`SyncService::canonical_block_hash`] without any networking request.
+    pub canonical_index_capacity: u64,
+
+    /// Maximum size, in bytes, of the storage and call proofs accepted from peers. Larger proofs
+    /// are refused in order to bound the memory usage. [`SyncService::storage_query`] splits the
+    /// requested keys into multiple requests if the proof of all of them would be too large.
+    pub max_proof_size: usize,
+
+    /// Number of distinct and randomly chosen peers that must confirm a block before the result
+    /// of a critical query concerning this block is accepted. See
+    /// [`SyncService::storage_query_quorum`] and [`SyncService::block_quorum_check`]. Higher
+    /// values make the client more resistant to being surrounded by malicious peers, at the cost
+    /// of a higher latency.
+    pub quorum_size: NonZeroUsize,
+
+    /// If `Some`, the headers of the chain can contain digest items belonging to consensus
+    /// engines other than Aura, Babe, and GrandPa. Headers containing such items are otherwise
+    /// refused.
+    ///
+    /// Ignored for parachains.
+    pub custom_consensus_engines: Option<ConfigCustomConsensusEngines>,
+}
+
+/// See [`Config::custom_consensus_engines`].
+pub struct ConfigCustomConsensusEngines {
+    /// Identifiers of the consensus engines.
+    pub engines: Vec<[u8; 4]>,
+
+    /// Closure called with the digest items of a header that belong to one of the
+    /// [`ConfigCustomConsensusEngines::engines`], before the header is accepted. Must return
+    /// `false` if any of the items is invalid, in which case the header is refused.
+    ///
+    /// Never called with an empty list.
+    pub verifier: Box<dyn Fn(&[header::CustomDigestItem]) -> bool + Send>,
+}
+
+/// See [`Config::sync_mode`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Only the headers of blocks are downloaded. Blocks are finalized through the GrandPa
+    /// commits gossiped by peers, which requires downloading a justification only once in a
+    /// while.
+    HeadersOnly,
+    /// The headers of blocks are downloaded alongside with their justifications, if any.
+    HeadersAndJustifications,
+    /// Same as [`SyncMode::HeadersAndJustifications`]. In addition, the bodies of the
+    /// `num_blocks` most recent best blocks are downloaded and kept in memory, so that
+    /// [`SyncService::recent_block`] can return them without a network request.
+    RecentBodies {
+        /// Number of blocks whose bodies are kept in memory.
+        num_blocks: NonZeroU32,
+    },
+}
+
+impl SyncMode {
+    /// Returns true if justifications should be requested alongside with block headers.
+    fn downloads_justifications(&self) -> bool {
+        !matches!(self, SyncMode::HeadersOnly)
+    }
 }
 
 /// See [`Config::parachain`].
@@ -99,11 +208,83 @@ pub struct SyncService {
     network_service: Arc<network_service::NetworkService>,
     /// See [`Config::network_service`].
     network_chain_index: usize,
+
+    /// See [`Config::cpu_usage`].
+    cpu_usage: Arc<cpu_usage::CpuUsage>,
+
+    /// See [`Config::work_queues`].
+    work_queues: Arc<work_queues::WorkQueues>,
+
+    /// See [`Config::max_proof_size`].
+    max_proof_size: usize,
+
+    /// See [`Config::quorum_size`].
+    quorum_size: NonZeroUsize,
+
+    /// Most recent best blocks whose body has been downloaded, in increasing order of arrival.
+    /// Always empty unless [`Config::sync_mode`] is [`SyncMode::RecentBodies`].
+    recent_blocks: Arc<Mutex<VecDeque<protocol::BlockData>>>,
+
+    /// Hashes of the recent blocks of the canonical chain. Updated by a background task
+    /// following the best and finalized blocks, and by [`SyncService::ancestry_verified_header`].
+    canonical_index: Arc<Mutex<canonical_index::CanonicalIndex>>,
+
+    /// Sender of the samples of successful storage queries towards the task that double-checks
+    /// their state root. See [`state_root_watchdog`].
+    state_root_checks: Mutex<mpsc::Sender<StateRootCheck>>,
+
+    /// Calls to [`SyncService::ancestry_verified_header`] in progress. A synchronous mutex is
+    /// used so that entries can be removed when these calls are interrupted.
+    ancestry_queries: std::sync::Mutex<AncestryQueries>,
 }
 
 impl SyncService {
     pub async fn new(mut config: Config) -> Self {
         let (to_background, from_foreground) = mpsc::channel(16);
+        let recent_blocks = Arc::new(Mutex::new(VecDeque::new()));
+        let work_queues = Arc::new(work_queues::WorkQueues::new(config.work_queues));
+        let canonical_index = {
+            let finalized_header = config.chain_information.as_ref().finalized_block_header;
+            Arc::new(Mutex::new(canonical_index::CanonicalIndex::new(
+                config.canonical_index_capacity,
+                finalized_header.number,
+                finalized_header.hash(),
+            )))
+        };
+
+        (config.tasks_executor)(
+            "sync-canonical-index".into(),
+            Box::pin(update_canonical_index(
+                to_background.clone(),
+                canonical_index.clone(),
+            )),
+        );
+
+        let (state_root_checks, state_root_checks_rx) = mpsc::channel(4);
+        (config.tasks_executor)(
+            "sync-state-root-watchdog".into(),
+            Box::pin(state_root_watchdog(
+                config.network_service.0.clone(),
+                config.network_service.1,
+                work_queues.clone(),
+                canonical_index.clone(),
+                state_root_checks_rx,
+            )),
+        );
+
+        if let SyncMode::RecentBodies { num_blocks } = config.sync_mode {
+            (config.tasks_executor)(
+                "sync-bodies".into(),
+                Box::pin(download_recent_bodies(
+                    to_background.clone(),
+                    config.network_service.0.clone(),
+                    config.network_service.1,
+                    work_queues.clone(),
+                    recent_blocks.clone(),
+                    usize::try_from(num_blocks.get()).unwrap_or(usize::max_value()),
+                )),
+            );
+        }
 
         if let Some(config_parachain) = config.parachain {
             (config.tasks_executor)(
@@ -124,6 +305,14 @@ impl SyncService {
                         config.network_service.0.clone(),
                         config.network_service.1,
                         config.network_events_receiver,
+                        config.cpu_usage.clone(),
+                        work_queues.clone(),
+                        config.slot_duration,
+                        config.max_announce_future_drift,
+                        config.babe_relaxed_secondary_slots,
+                        config.sync_mode,
+                        config.max_proof_size,
+                        config.custom_consensus_engines,
                     )
                     .await,
                 ),
@@ -134,11 +323,63 @@ impl SyncService {
             to_background: Mutex::new(to_background),
             network_service: config.network_service.0,
             network_chain_index: config.network_service.1,
+            cpu_usage: config.cpu_usage,
+            work_queues,
+            max_proof_size: config.max_proof_size,
+            quorum_size: config.quorum_size,
+            recent_blocks,
+            canonical_index,
+            state_root_checks: Mutex::new(state_root_checks),
+            ancestry_queries: std::sync::Mutex::new(AncestryQueries {
+                next_id: 0,
+                in_progress: HashMap::new(),
+            }),
         }
     }
 
-    /// Returns the SCALE-encoded header of the current finalized block, alongside with a stream
-    /// producing updates of the finalized block.
+    /// Returns the number of network requests of the given class in progress and the number of
+    /// network requests of this class waiting to be started, in that order.
+    pub fn work_queue_depth(&self, class: work_queues::WorkClass) -> (usize, usize) {
+        (
+            self.work_queues.in_progress(class),
+            self.work_queues.queued(class),
+        )
+    }
+
+    /// Returns the progress of the calls to [`SyncService::ancestry_verified_header`] currently
+    /// in progress.
+    pub fn ancestry_queries(&self) -> Vec<AncestryQueryProgress> {
+        let queries = self.ancestry_queries.lock().unwrap();
+        let mut list = queries.in_progress.iter().collect::<Vec<_>>();
+        list.sort_by_key(|(id, _)| **id);
+        list.into_iter().map(|(_, query)| query.clone()).collect()
+    }
+
+    /// Returns the hash of the block of the canonical chain with the given number, if it is
+    /// known locally.
+    ///
+    /// Only recent blocks are known, and not necessarily all of them. Use
+    /// [`SyncService::header_query_by_number`] in order to query the network if `None` is
+    /// returned.
+    pub async fn canonical_block_hash(&self, block_number: u64) -> Option<[u8; 32]> {
+        self.canonical_index.lock().await.get(block_number)
+    }
+
+    /// Returns the header, body and justification of the given block if it is one of the recent
+    /// best blocks whose body has been downloaded ahead of time. See [`SyncMode::RecentBodies`].
+    ///
+    /// Always returns `None` if [`Config::sync_mode`] isn't [`SyncMode::RecentBodies`].
+    pub async fn recent_block(&self, hash: &[u8; 32]) -> Option<protocol::BlockData> {
+        self.recent_blocks
+            .lock()
+            .await
+            .iter()
+            .find(|b| b.hash == *hash)
+            .cloned()
+    }
+
+    /// Returns the header of the current finalized block, alongside with a stream producing
+    /// updates of the finalized block.
     ///
     /// Not all updates are necessarily reported. In particular, updates that weren't pulled from
     /// the `Stream` yet might get overwritten by newest updates.
@@ -146,7 +387,12 @@ impl SyncService {
     /// If you have subscribed to new blocks, the finalized blocks reported in this channel are
     /// guaranteed to have earlier been reported as new blocks.
     // TODO: is this last paragraph true for parachains?
-    pub async fn subscribe_finalized(&self) -> (Vec<u8>, NotificationsReceiver<Vec<u8>>) {
+    pub async fn subscribe_finalized(
+        &self,
+    ) -> (
+        HeaderNotification,
+        NotificationsReceiver<HeaderNotification>,
+    ) {
         let (send_back, rx) = oneshot::channel();
 
         self.to_background
@@ -159,12 +405,31 @@ impl SyncService {
         rx.await.unwrap()
     }
 
-    /// Returns the SCALE-encoded header of the current best block, alongside with a stream
-    /// producing updates of the best block.
+    /// Returns the header of the current best block, alongside with a stream producing updates
+    /// of the best block.
     ///
     /// Not all updates are necessarily reported. In particular, updates that weren't pulled from
     /// the `Stream` yet might get overwritten by newest updates.
-    pub async fn subscribe_best(&self) -> (Vec<u8>, NotificationsReceiver<Vec<u8>>) {
+    pub async fn subscribe_best(
+        &self,
+    ) -> (
+        HeaderNotification,
+        NotificationsReceiver<HeaderNotification>,
+    ) {
+        self.try_subscribe_best().await.unwrap()
+    }
+
+    /// Similar to [`SyncService::subscribe_best`], but returns an error instead of panicking if
+    /// the background task of the sync service is no longer running.
+    pub async fn try_subscribe_best(
+        &self,
+    ) -> Result<
+        (
+            HeaderNotification,
+            NotificationsReceiver<HeaderNotification>,
+        ),
+        (),
+    > {
         let (send_back, rx) = oneshot::channel();
 
         self.to_background
@@ -172,9 +437,9 @@ impl SyncService {
             .await
             .send(ToBackground::SubscribeBest { send_back })
             .await
-            .unwrap();
+            .map_err(|_| ())?;
 
-        rx.await.unwrap()
+        rx.await.map_err(|_| ())
     }
 
     /// Subscribes to the state of the chain: the current state and the new blocks.
@@ -185,6 +450,12 @@ impl SyncService {
     ///
     /// See [`SubscribeAll`] for information about the return value.
     pub async fn subscribe_all(&self, buffer_size: usize) -> SubscribeAll {
+        self.try_subscribe_all(buffer_size).await.unwrap()
+    }
+
+    /// Similar to [`SyncService::subscribe_all`], but returns an error instead of panicking if
+    /// the background task of the sync service is no longer running.
+    pub async fn try_subscribe_all(&self, buffer_size: usize) -> Result<SubscribeAll, ()> {
         let (send_back, rx) = oneshot::channel();
 
         self.to_background
@@ -195,15 +466,19 @@ impl SyncService {
                 buffer_size,
             })
             .await
-            .unwrap();
+            .map_err(|_| ())?;
 
-        rx.await.unwrap()
+        rx.await.map_err(|_| ())
     }
 
     /// Returns true if it is believed that we are near the head of the chain.
     ///
     /// The way this method is implemented is opaque and cannot be relied on. The return value
     /// should only ever be shown to the user and not used for any meaningful logic.
+    ///
+    /// Returns `false` if the peers we are connected to aren't diverse enough, as all the blocks
+    /// might then come from a single entity. See
+    /// [`network_service::NetworkService::peer_diversity`].
     pub async fn is_near_head_of_chain_heuristic(&self) -> bool {
         let (send_back, rx) = oneshot::channel();
 
@@ -214,28 +489,111 @@ impl SyncService {
             .await
             .unwrap();
 
+        if !rx.await.unwrap() {
+            return false;
+        }
+
+        self.network_service
+            .peer_diversity(self.network_chain_index)
+            .await
+            .is_sufficient()
+    }
+
+    /// Returns how diverse the origins of the peers of the chain are.
+    ///
+    /// See [`network_service::NetworkService::peer_diversity`].
+    pub async fn peer_diversity(&self) -> network_service::PeerDiversity {
+        self.network_service
+            .peer_diversity(self.network_chain_index)
+            .await
+    }
+
+    /// Returns the Sr25519 public key of the authority that has authored the given block.
+    ///
+    /// Returns `None` if the block isn't one of the non-finalized blocks known by the syncing
+    /// service, or if the chain doesn't have block authors (for example parachains).
+    pub async fn block_author(&self, hash: [u8; 32]) -> Option<[u8; 32]> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::BlockAuthor { send_back, hash })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns the state of GrandPa as of the current finalized block, or `None` if the chain
+    /// doesn't use GrandPa.
+    pub async fn grandpa_state(&self) -> Option<GrandpaState> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::GrandpaState { send_back })
+            .await
+            .unwrap();
+
         rx.await.unwrap()
     }
 
-    /// Returns the list of peers from the [`network_service::NetworkService`] that are used to
-    /// synchronize blocks.
+    /// Returns the GrandPa authorities sets that might have finalized the block with the given
+    /// number, as identifiers and lists of public keys, most recent first. See
+    /// [`GrandpaSetsHistory`].
     ///
-    /// Returns, for each peer, their identity and best block number and hash.
+    /// Returns `None` if the chain doesn't use GrandPa.
+    async fn grandpa_authorities_sets(
+        &self,
+        block_number: u64,
+    ) -> Option<Vec<GrandpaAuthoritiesSet>> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::GrandpaAuthoritiesSets {
+                send_back,
+                block_number,
+            })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns the information about the chain as of the current finalized block, including the
+    /// finalized block header, serialized in the same format as the database content.
     ///
-    /// This function is subject to race condition. The list returned by this function can change
-    /// at any moment. The return value should only ever be shown to the user and not used for any
-    /// meaningful logic
-    pub async fn syncing_peers(&self) -> impl ExactSizeIterator<Item = (PeerId, u64, [u8; 32])> {
+    /// Returns `None` if the syncing doesn't track this information, which is the case for
+    /// parachains.
+    pub async fn serialize_chain_information(&self) -> Option<String> {
         let (send_back, rx) = oneshot::channel();
 
         self.to_background
             .lock()
             .await
-            .send(ToBackground::SyncingPeers { send_back })
+            .send(ToBackground::SerializeChainInformation { send_back })
             .await
             .unwrap();
 
-        rx.await.unwrap().into_iter()
+        rx.await.unwrap()
+    }
+
+    /// Returns a snapshot of the state of the syncing, for debugging purposes.
+    pub async fn debug_state(&self) -> DebugState {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::DebugState { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
     }
 
     /// Returns the list of peers from the [`network_service::NetworkService`] that are expected to
@@ -276,69 +634,371 @@ impl SyncService {
         hash: [u8; 32],
         fields: protocol::BlocksRequestFields,
     ) -> Result<protocol::BlockData, ()> {
+        fetch_block(
+            &self.network_service,
+            self.network_chain_index,
+            &self.work_queues,
+            work_queues::WorkClass::JsonRpc,
+            hash,
+            fields,
+        )
+        .await
+    }
+
+    /// Returns the SCALE-encoded header of the block of the canonical chain with the given
+    /// number.
+    ///
+    /// This is a shortcut for [`SyncService::ancestry_verified_header`] with
+    /// [`AncestryTarget::Number`].
+    pub async fn header_query_by_number(self: Arc<Self>, block_number: u64) -> Result<Vec<u8>, ()> {
         // TODO: better error?
+        self.ancestry_verified_header(AncestryTarget::Number(block_number))
+            .await
+            .map_err(|_| ())
+    }
+
+    /// Returns the SCALE-encoded header of a block of the canonical chain, including blocks
+    /// that are older than the current finalized block.
+    ///
+    /// Headers are downloaded from peers by walking the chain backwards, starting from an anchor,
+    /// and verifying that each header is the parent of the previous one. The returned header is
+    /// thus guaranteed to be part of the canonical chain.
+    ///
+    /// If the target is a block number inferior or equal to the number of the current finalized
+    /// block, or a block hash, the anchor is the current finalized block. Otherwise, the anchor
+    /// is the current best block.
+    ///
+    /// Querying blocks far from the anchor is expensive. An error is returned if the requested
+    /// block is more than a certain number of blocks behind the anchor. The progress of the
+    /// query can be obtained with [`SyncService::ancestry_queries`] while it is running.
+    pub async fn ancestry_verified_header(
+        self: Arc<Self>,
+        target: AncestryTarget,
+    ) -> Result<Vec<u8>, AncestryQueryError> {
+        const NUM_ATTEMPTS: usize = 3;
+        /// Maximum number of blocks between the anchor and the requested block.
+        const MAX_DISTANCE: u64 = 4096;
+        /// Maximum number of headers to request at once.
+        const HEADERS_PER_REQUEST: u64 = 64;
+
+        // Determine the block to start walking backwards from.
+        let (anchor, anchor_is_finalized) = {
+            let (finalized, _) = self.subscribe_finalized().await;
+            match target {
+                AncestryTarget::Number(n) if n > finalized.number => {
+                    let (best, _) = self.subscribe_best().await;
+                    (best, false)
+                }
+                _ => (finalized, true),
+            }
+        };
+
+        if let AncestryTarget::Hash(hash) = target {
+            if anchor.hash == hash {
+                return Ok(anchor.scale_encoded_header);
+            }
+        }
+
+        let (anchor_number, mut expected_hash, mut expected_number) = {
+            match target {
+                AncestryTarget::Number(n) if n > anchor.number => {
+                    return Err(AncestryQueryError::NotInChain)
+                }
+                AncestryTarget::Number(n) if n == anchor.number => {
+                    return Ok(anchor.scale_encoded_header)
+                }
+                AncestryTarget::Number(n) if anchor.number - n > MAX_DISTANCE => {
+                    return Err(AncestryQueryError::TooFar)
+                }
+                _ => {}
+            }
+            if anchor.number == 0 {
+                return Err(AncestryQueryError::NotInChain);
+            }
+            (anchor.number, anchor.parent_hash, anchor.number - 1)
+        };
+
+        // Number of the lowest block that can possibly be requested.
+        let lowest_number = match target {
+            AncestryTarget::Number(n) => n,
+            AncestryTarget::Hash(_) => anchor_number.saturating_sub(MAX_DISTANCE),
+        };
+
+        // Register the query, so that its progress can be reported. The entry is removed when
+        // `progress` is dropped, including if this future is interrupted.
+        let progress = AncestryQueryGuard::new(
+            &self.ancestry_queries,
+            AncestryQueryProgress {
+                target,
+                anchor_number,
+                verified_number: anchor_number,
+                remaining: expected_number - lowest_number + 1,
+            },
+        );
+
+        let mut num_failures = 0;
+
+        loop {
+            if num_failures >= NUM_ATTEMPTS {
+                return Err(AncestryQueryError::RequestsFailed);
+            }
+
+            // TODO: better peers selection
+            let target_peer = match self
+                .peers_assumed_know_blocks(expected_number, &expected_hash)
+                .await
+                .nth(num_failures)
+            {
+                Some(t) => t,
+                None => return Err(AncestryQueryError::NoPeer),
+            };
+
+            let _permit = self
+                .work_queues
+                .acquire(work_queues::WorkClass::JsonRpc)
+                .await;
+            let result = self
+                .network_service
+                .clone()
+                .blocks_request(
+                    target_peer,
+                    self.network_chain_index,
+                    protocol::BlocksRequestConfig {
+                        start: protocol::BlocksRequestConfigStart::Hash(expected_hash),
+                        desired_count: NonZeroU32::new(
+                            u32::try_from(cmp::min(
+                                HEADERS_PER_REQUEST,
+                                expected_number - lowest_number + 1,
+                            ))
+                            .unwrap(),
+                        )
+                        .unwrap(),
+                        direction: protocol::BlocksRequestDirection::Descending,
+                        fields: protocol::BlocksRequestFields {
+                            header: true,
+                            body: false,
+                            justification: false,
+                            indexed_body: false,
+                        },
+                        accept_compressed_response: self
+                            .network_service
+                            .request_compressed_responses(),
+                    },
+                )
+                .await;
+
+            let blocks = match result {
+                Ok(b) if !b.is_empty() => b,
+                _ => {
+                    num_failures += 1;
+                    continue;
+                }
+            };
+
+            // Verify that the blocks form a chain that ends with `expected_hash`. Blocks that
+            // were successfully verified are kept even if a later block of the response is
+            // invalid.
+            let mut made_progress = false;
+            for block in blocks {
+                let scale_encoded_header = match block.header {
+                    Some(h) => h,
+                    None => break,
+                };
+
+                if Host::blake2_256(&scale_encoded_header) != expected_hash {
+                    break;
+                }
+
+                let decoded = match header::decode(&scale_encoded_header) {
+                    Ok(h) => h,
+                    Err(_) => break,
+                };
+
+                if decoded.number != expected_number {
+                    break;
+                }
+
+                made_progress = true;
+
+                // Ancestors of a finalized block are part of the canonical chain forever.
+                if anchor_is_finalized {
+                    self.canonical_index
+                        .lock()
+                        .await
+                        .insert_finalized_ancestor(decoded.number, expected_hash);
+                }
+
+                match target {
+                    AncestryTarget::Number(n) if n == decoded.number => {
+                        return Ok(scale_encoded_header)
+                    }
+                    AncestryTarget::Hash(h) if h == expected_hash => {
+                        return Ok(scale_encoded_header)
+                    }
+                    _ => {}
+                }
+
+                if decoded.number == lowest_number {
+                    debug_assert!(matches!(target, AncestryTarget::Hash(_)));
+                    return Err(if decoded.number == 0 {
+                        AncestryQueryError::NotInChain
+                    } else {
+                        AncestryQueryError::TooFar
+                    });
+                }
+
+                expected_hash = *decoded.parent_hash;
+                expected_number -= 1;
+            }
+
+            if made_progress {
+                progress.update(expected_number + 1, expected_number + 1 - lowest_number);
+                log::debug!(
+                    target: "sync-ancestry",
+                    "Verified ancestry down to #{} ({} blocks remaining)",
+                    expected_number + 1,
+                    expected_number + 1 - lowest_number
+                );
+            } else {
+                num_failures += 1;
+            }
+        }
+    }
+
+    /// Downloads the GrandPa justification of the given finalized block from peers, and
+    /// verifies it against the GrandPa authorities set that has finalized this block.
+    ///
+    /// Returns the SCALE-encoded justification.
+    ///
+    /// The block must be an ancestor of the current finalized block, or the current finalized
+    /// block itself. Nodes typically only store justifications for blocks that change the
+    /// authorities set and for blocks at regular intervals.
+    ///
+    /// > **Note**: Only the authorities sets that have been in use since the sync service has
+    /// >           started are known. Justifications of blocks that were finalized by an
+    /// >           earlier authorities set can't be verified, and an error is returned for them.
+    pub async fn justification_query(
+        self: Arc<Self>,
+        block_hash: [u8; 32],
+    ) -> Result<Vec<u8>, JustificationQueryError> {
         const NUM_ATTEMPTS: usize = 3;
 
-        let request_config = protocol::BlocksRequestConfig {
-            start: protocol::BlocksRequestConfigStart::Hash(hash),
-            desired_count: NonZeroU32::new(1).unwrap(),
-            direction: protocol::BlocksRequestDirection::Ascending,
-            fields: fields.clone(),
+        if self.grandpa_state().await.is_none() {
+            return Err(JustificationQueryError::NotGrandpa);
+        }
+
+        // Makes sure that the block is finalized, and obtains its number.
+        let block_number = {
+            let header = self
+                .clone()
+                .ancestry_verified_header(AncestryTarget::Hash(block_hash))
+                .await
+                .map_err(JustificationQueryError::Ancestry)?;
+            header::decode(&header).unwrap().number
         };
 
+        // The block at which a change of authorities set is enacted is finalized by the
+        // previous set. Since it isn't always known which block enacted a change, the
+        // justification is verified against all the sets that might have finalized the block.
+        let authorities_sets = self
+            .grandpa_authorities_sets(block_number)
+            .await
+            .ok_or(JustificationQueryError::NotGrandpa)?;
+
+        let mut num_peers = 0;
+
         // TODO: better peers selection ; don't just take the first 3
-        // TODO: must only ask the peers that know about this block
-        for target in self.network_service.peers_list().await.take(NUM_ATTEMPTS) {
-            let mut result = match self
+        for target in self
+            .peers_assumed_know_blocks(block_number, &block_hash)
+            .await
+            .take(NUM_ATTEMPTS)
+        {
+            num_peers += 1;
+
+            let _permit = self
+                .work_queues
+                .acquire(work_queues::WorkClass::JsonRpc)
+                .await;
+            let result = self
                 .network_service
                 .clone()
-                .blocks_request(target, self.network_chain_index, request_config.clone())
-                .await
+                .blocks_request(
+                    target,
+                    self.network_chain_index,
+                    protocol::BlocksRequestConfig {
+                        start: protocol::BlocksRequestConfigStart::Hash(block_hash),
+                        desired_count: NonZeroU32::new(1).unwrap(),
+                        direction: protocol::BlocksRequestDirection::Ascending,
+                        fields: protocol::BlocksRequestFields {
+                            header: false,
+                            body: false,
+                            justification: true,
+                            indexed_body: false,
+                        },
+                        accept_compressed_response: self
+                            .network_service
+                            .request_compressed_responses(),
+                    },
+                )
+                .await;
+
+            let scale_encoded_justification = match result {
+                Ok(mut blocks) if blocks.len() == 1 && blocks[0].hash == block_hash => {
+                    match blocks.remove(0).justification {
+                        Some(j) => j,
+                        None => continue,
+                    }
+                }
+                _ => continue,
+            };
+
+            let decoded = match justification::decode::decode_grandpa(&scale_encoded_justification)
             {
-                Ok(b) => b,
+                Ok(j) => j,
                 Err(_) => continue,
             };
 
-            if result.len() != 1 {
+            if *decoded.target_hash != block_hash
+                || u64::from(decoded.target_number) != block_number
+            {
                 continue;
             }
 
-            let result = result.remove(0);
+            for (authorities_set_id, authorities) in &authorities_sets {
+                let verify_result = {
+                    let _measure = self
+                        .cpu_usage
+                        .measure(cpu_usage::Category::HeaderVerification);
+                    justification::verify::verify(justification::verify::Config {
+                        // Decoding has already succeeded above.
+                        justification: justification::decode::decode_grandpa(
+                            &scale_encoded_justification,
+                        )
+                        .unwrap(),
+                        authorities_set_id: *authorities_set_id,
+                        authorities_list: authorities.iter(),
+                        randomness_seed: rand::random(),
+                    })
+                };
 
-            if result.header.is_none() && fields.header {
-                continue;
-            }
-            if result
-                .header
-                .as_ref()
-                .map_or(false, |h| header::decode(h).is_err())
-            {
-                continue;
-            }
-            if result.body.is_none() && fields.body {
-                continue;
-            }
-            // Note: the presence of a justification isn't checked and can't be checked, as not
-            // all blocks have a justification in the first place.
-            if result.hash != hash {
-                continue;
-            }
-            if result.header.as_ref().map_or(false, |h| {
-                header::hash_from_scale_encoded_header(&h) != result.hash
-            }) {
-                continue;
-            }
-            match (&result.header, &result.body) {
-                (Some(_), Some(_)) => {
-                    // TODO: verify correctness of body
+                match verify_result {
+                    Ok(()) => return Ok(scale_encoded_justification),
+                    Err(err) => {
+                        log::debug!(
+                            target: "sync-verify",
+                            "Failed to verify justification of block {} against set {}: {}",
+                            HashDisplay(&block_hash), authorities_set_id, err
+                        );
+                    }
                 }
-                _ => {}
             }
-
-            return Ok(result);
         }
 
-        Err(())
+        if num_peers == 0 {
+            Err(JustificationQueryError::NoPeer)
+        } else {
+            Err(JustificationQueryError::NoValidJustification)
+        }
     }
 
     /// Performs one or more storage proof requests in order to find the value of the given
@@ -356,60 +1016,399 @@ impl SyncService {
     /// [`network_service::NetworkService::storage_proof_request`] and verifying the proof,
     /// potentially multiple times until it succeeds. The number of attempts and the selection of
     /// peers is done through reasonable heuristics.
+    ///
+    /// If the proof of all the keys would be larger than [`Config::max_proof_size`], or if the
+    /// peers refuse to answer, the keys are split into two halves queried separately, and so on.
+    ///
+    /// The network requests are recorded in `trace`, if any.
     pub async fn storage_query(
         self: Arc<Self>,
         block_hash: &[u8; 32],
         storage_trie_root: &[u8; 32],
         requested_keys: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+        trace: Option<&request_trace::RequestTrace>,
     ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryError> {
-        const NUM_ATTEMPTS: usize = 3;
-
-        let mut outcome_errors = Vec::with_capacity(NUM_ATTEMPTS);
+        self.storage_query_inner(
+            work_queues::WorkClass::JsonRpc,
+            block_hash,
+            storage_trie_root,
+            requested_keys,
+            trace,
+        )
+        .await
+    }
 
-        // TODO: better peers selection ; don't just take the first 3
-        // TODO: must only ask the peers that know about this block
-        for target in self.network_service.peers_list().await.take(NUM_ATTEMPTS) {
+    /// Same as [`SyncService::storage_query`], except that the network requests have the
+    /// lowest priority. See [`work_queues::WorkClass::Prefetch`].
+    pub async fn storage_prefetch(
+        self: Arc<Self>,
+        block_hash: &[u8; 32],
+        storage_trie_root: &[u8; 32],
+        requested_keys: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryError> {
+        self.storage_query_inner(
+            work_queues::WorkClass::Prefetch,
+            block_hash,
+            storage_trie_root,
+            requested_keys,
+            None,
+        )
+        .await
+    }
+
+    /// Same as [`SyncService::storage_query`], except that the block is first confirmed with
+    /// [`SyncService::block_quorum_check`]. Meant to be used for critical queries, such as
+    /// downloading the runtime code.
+    ///
+    /// `block_number` must be the number of the block whose hash is `block_hash`.
+    pub async fn storage_query_quorum(
+        self: Arc<Self>,
+        block_number: u64,
+        block_hash: &[u8; 32],
+        storage_trie_root: &[u8; 32],
+        requested_keys: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryError> {
+        if let Err(error) = self.block_quorum_check(block_number, *block_hash).await {
+            // Note that if no peer has answered, the list of errors is empty, indicating that
+            // no peer is available.
+            let mut errors = Vec::with_capacity(2);
+            if error.num_disagreements != 0 {
+                errors.push(StorageQueryErrorDetail::QuorumMismatch);
+            }
+            if error.num_confirmations != 0 {
+                errors.push(StorageQueryErrorDetail::QuorumNotReached {
+                    num_agreeing: error.num_confirmations,
+                    quorum_size: error.quorum_size,
+                });
+            }
+            return Err(StorageQueryError { errors });
+        }
+
+        self.storage_query(block_hash, storage_trie_root, requested_keys, None)
+            .await
+    }
+
+    /// Asks [`Config::quorum_size`] distinct and randomly chosen peers for the header of the
+    /// block with the given number, and returns an error if fewer peers than that serve the
+    /// header of the block with the given hash.
+    ///
+    /// Because a header contains the state root of its block, this guarantees that all these
+    /// peers agree with the state root that the storage of the block is verified against. A
+    /// client kept on a fake chain by a small set of peers will therefore fail this check as
+    /// long as it is connected to enough honest peers.
+    ///
+    /// Always succeeds immediately if [`Config::quorum_size`] is 1, or for the genesis block.
+    pub async fn block_quorum_check(
+        &self,
+        block_number: u64,
+        block_hash: [u8; 32],
+    ) -> Result<(), QuorumCheckError> {
+        /// Number of peers, in addition to the quorum, that are tried in case some of the peers
+        /// fail to answer or disagree.
+        const NUM_EXTRA_ATTEMPTS: usize = 2;
+
+        let quorum_size = self.quorum_size.get();
+        let block_number = match NonZeroU64::new(block_number) {
+            Some(n) if quorum_size != 1 => n,
+            _ => return Ok(()),
+        };
+
+        let request_config = protocol::BlocksRequestConfig {
+            start: protocol::BlocksRequestConfigStart::Number(block_number),
+            desired_count: NonZeroU32::new(1).unwrap(),
+            direction: protocol::BlocksRequestDirection::Ascending,
+            fields: protocol::BlocksRequestFields {
+                header: true,
+                body: false,
+                justification: false,
+                indexed_body: false,
+            },
+            accept_compressed_response: self.network_service.request_compressed_responses(),
+        };
+
+        let mut quorum = Quorum::new(quorum_size, block_hash);
+        for target in self
+            .random_chain_peers()
+            .await
+            .take(quorum_size + NUM_EXTRA_ATTEMPTS)
+        {
+            let _permit = self
+                .work_queues
+                .acquire(work_queues::WorkClass::JsonRpc)
+                .await;
             let result = self
                 .network_service
                 .clone()
-                .storage_proof_request(
+                .blocks_request(
+                    target.clone(),
                     self.network_chain_index,
-                    target,
-                    protocol::StorageProofRequestConfig {
-                        block_hash: *block_hash,
-                        keys: requested_keys.clone(),
-                    },
+                    request_config.clone(),
                 )
-                .await
-                .map_err(StorageQueryErrorDetail::Network)
-                .and_then(|outcome| {
-                    let mut result = Vec::with_capacity(requested_keys.clone().count());
-                    for key in requested_keys.clone() {
-                        result.push(
-                            proof_verify::verify_proof(proof_verify::VerifyProofConfig {
-                                proof: outcome.iter().map(|nv| &nv[..]),
-                                requested_key: key.as_ref(),
-                                trie_root_hash: &storage_trie_root,
-                            })
-                            .map_err(StorageQueryErrorDetail::ProofVerification)?
-                            .map(|v| v.to_owned()),
+                .await;
+
+            // Peers that fail to answer, or don't send back a header with the requested number,
+            // are ignored.
+            let reported_header = match result {
+                Ok(mut blocks) if blocks.len() == 1 => match blocks.pop().unwrap().header {
+                    Some(h) => h,
+                    None => continue,
+                },
+                _ => continue,
+            };
+            if !matches!(header::decode(&reported_header), Ok(h) if h.number == block_number.get())
+            {
+                continue;
+            }
+            let reported_hash = Host::blake2_256(&reported_header);
+
+            if let Err(QuorumMismatch) = quorum.add(reported_hash) {
+                log::warn!(
+                    target: "sync-verify",
+                    "Peer {} reports block {} at height {}, while the local node follows {}",
+                    target, HashDisplay(&reported_hash), block_number, HashDisplay(&block_hash)
+                );
+            } else if quorum.is_reached() {
+                return Ok(());
+            }
+        }
+
+        Err(QuorumCheckError {
+            num_confirmations: quorum.num_agreeing(),
+            num_disagreements: quorum.num_disagreeing(),
+            quorum_size,
+        })
+    }
+
+    /// Returns the list of peers of the [`network_service::NetworkService`] that are connected
+    /// to this chain, in a random order.
+    async fn random_chain_peers(&self) -> impl Iterator<Item = PeerId> {
+        let mut peers = self
+            .network_service
+            .peers_info(self.network_chain_index)
+            .await
+            .into_iter()
+            .map(|info| info.peer_id)
+            .collect::<Vec<_>>();
+        peers.shuffle(&mut rand::thread_rng());
+        peers.into_iter()
+    }
+
+    /// Sends a storage proof request for the given keys to the given peer, and verifies the
+    /// proof against `storage_trie_root`. The request is recorded in `trace`, if any.
+    async fn verified_storage_proof(
+        &self,
+        target: PeerId,
+        block_hash: &[u8; 32],
+        storage_trie_root: &[u8; 32],
+        keys: &[impl AsRef<[u8]>],
+        trace: Option<&request_trace::RequestTrace>,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryErrorDetail> {
+        let request_start = Host::now();
+        let outcome = self
+            .network_service
+            .clone()
+            .storage_proof_request(
+                self.network_chain_index,
+                target.clone(),
+                protocol::StorageProofRequestConfig {
+                    block_hash: *block_hash,
+                    keys: keys.iter(),
+                    accept_compressed_response: self.network_service.request_compressed_responses(),
+                },
+                Some(self.max_proof_size),
+            )
+            .await;
+
+        if let Some(trace) = trace {
+            trace.record_network_request(
+                "storage-proof",
+                &target,
+                request_start,
+                outcome
+                    .as_ref()
+                    .ok()
+                    .map(|proof| proof.iter().map(|node| node.len()).sum()),
+            );
+        }
+
+        let outcome = outcome.map_err(StorageQueryErrorDetail::Network)?;
+
+        let _measure = self
+            .cpu_usage
+            .measure(cpu_usage::Category::ProofVerification);
+        let mut result = Vec::with_capacity(keys.len());
+        for key in keys {
+            result.push(
+                proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                    proof: outcome.iter().map(|nv| &nv[..]),
+                    requested_key: key.as_ref(),
+                    trie_root_hash: storage_trie_root,
+                })
+                .map_err(StorageQueryErrorDetail::ProofVerification)?
+                .map(memory_usage::try_to_vec)
+                .transpose()
+                .map_err(StorageQueryErrorDetail::OutOfMemory)?,
+            );
+        }
+        debug_assert_eq!(result.len(), result.capacity());
+        Ok(result)
+    }
+
+    async fn storage_query_inner(
+        self: Arc<Self>,
+        work_class: work_queues::WorkClass,
+        block_hash: &[u8; 32],
+        storage_trie_root: &[u8; 32],
+        requested_keys: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+        trace: Option<&request_trace::RequestTrace>,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryError> {
+        const NUM_ATTEMPTS: usize = 3;
+
+        let requested_keys = requested_keys.collect::<Vec<_>>();
+        let mut values = vec![None; requested_keys.len()];
+
+        // Ranges of indices within `requested_keys` that remain to be queried.
+        let mut remaining_ranges = Vec::with_capacity(1);
+        remaining_ranges.push(0..requested_keys.len());
+
+        while let Some(range) = remaining_ranges.pop() {
+            let keys = &requested_keys[range.clone()];
+            let mut outcome_errors = Vec::with_capacity(NUM_ATTEMPTS);
+            let mut succeeded = false;
+
+            // TODO: better peers selection ; don't just take the first 3
+            // TODO: must only ask the peers that know about this block
+            for target in self.network_service.peers_list().await.take(NUM_ATTEMPTS) {
+                let _permit = self.work_queues.acquire(work_class).await;
+                let result = self
+                    .verified_storage_proof(
+                        target.clone(),
+                        block_hash,
+                        storage_trie_root,
+                        keys,
+                        trace,
+                    )
+                    .await;
+
+                match result {
+                    Ok(result) => {
+                        for (value, result) in values[range.clone()].iter_mut().zip(result) {
+                            *value = result;
+                        }
+                        succeeded = true;
+
+                        // Occasionally double-check with another peer the block whose state
+                        // root the proof has been verified against. The check is skipped if the
+                        // watchdog is busy.
+                        if rand::random::<f64>() < STATE_ROOT_CHECK_PROBABILITY {
+                            let _ = self
+                                .state_root_checks
+                                .lock()
+                                .await
+                                .try_send(StateRootCheck {
+                                    block_hash: *block_hash,
+                                    state_root: *storage_trie_root,
+                                    proof_peer: target,
+                                });
+                        }
+                        break;
+                    }
+                    Err(err) => {
+                        // Other peers would send back a proof of the same size. There is no
+                        // point in trying them.
+                        let too_large = matches!(
+                            err,
+                            StorageQueryErrorDetail::Network(
+                                service::StorageProofRequestError::ProofTooLarge { .. }
+                            )
                         );
+                        outcome_errors.push(err);
+                        if too_large {
+                            break;
+                        }
                     }
-                    debug_assert_eq!(result.len(), result.capacity());
-                    Ok(result)
-                });
+                }
+            }
 
-            match result {
-                Ok(values) => return Ok(values),
-                Err(err) => {
-                    outcome_errors.push(err);
+            if succeeded {
+                continue;
+            }
+
+            match split_storage_query_range(&range, &outcome_errors) {
+                Some((first, second)) => {
+                    remaining_ranges.push(second);
+                    remaining_ranges.push(first);
+                }
+                None => {
+                    return Err(StorageQueryError {
+                        errors: outcome_errors,
+                    })
                 }
             }
         }
 
-        Err(StorageQueryError {
-            errors: outcome_errors,
-        })
+        Ok(values)
+    }
+
+    /// Similar to [`SyncService::storage_query`], but targets "the given block or any more
+    /// recent block" rather than exactly the given block.
+    ///
+    /// `block_hash` and `storage_trie_root` are used for the initial attempt. If this attempt
+    /// fails in a way that indicates that the peers don't know about the block, which is what
+    /// happens when they have pruned its storage, the query is automatically retargeted to the
+    /// current finalized block and attempted again.
+    ///
+    /// Must only be used if the caller has no particular interest in the storage of the given
+    /// block, and is for example only interested in the latest values. On success, returns the
+    /// hash of the block that the values have actually been read from alongside with the values.
+    pub async fn storage_query_finalized_or_newer(
+        self: Arc<Self>,
+        block_hash: &[u8; 32],
+        storage_trie_root: &[u8; 32],
+        requested_keys: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+        trace: Option<&request_trace::RequestTrace>,
+    ) -> Result<([u8; 32], Vec<Option<Vec<u8>>>), StorageQueryError> {
+        // Maximum number of times the query is retargeted to a new finalized block. Bounded in
+        // order to not loop forever if, for example, the finalized block advances quickly.
+        const MAX_RETARGETS: usize = 2;
+
+        let mut block_hash = *block_hash;
+        let mut storage_trie_root = *storage_trie_root;
+        let mut num_retargets = 0;
+
+        loop {
+            let error = match self
+                .clone()
+                .storage_query(
+                    &block_hash,
+                    &storage_trie_root,
+                    requested_keys.clone(),
+                    trace,
+                )
+                .await
+            {
+                Ok(values) => return Ok((block_hash, values)),
+                Err(err) => err,
+            };
+
+            // An empty list of errors means that no peer is available, in which case retrying
+            // with a different block wouldn't change anything.
+            if error.errors.is_empty()
+                || !error.is_network_problem()
+                || num_retargets >= MAX_RETARGETS
+            {
+                return Err(error);
+            }
+
+            let (finalized, _) = self.subscribe_finalized().await;
+            if finalized.hash == block_hash {
+                return Err(error);
+            }
+
+            block_hash = finalized.hash;
+            storage_trie_root = finalized.state_root;
+            num_retargets += 1;
+        }
     }
 
     pub async fn storage_prefix_keys_query(
@@ -435,6 +1434,10 @@ impl SyncService {
                 .await
                 .take(NUM_ATTEMPTS)
             {
+                let _permit = self
+                    .work_queues
+                    .acquire(work_queues::WorkClass::JsonRpc)
+                    .await;
                 let result = self
                     .network_service
                     .clone()
@@ -446,7 +1449,11 @@ impl SyncService {
                             keys: prefix_scan.requested_keys().map(|nibbles| {
                                 trie::nibbles_to_bytes_extend(nibbles).collect::<Vec<_>>()
                             }),
+                            accept_compressed_response: self
+                                .network_service
+                                .request_compressed_responses(),
                         },
+                        Some(self.max_proof_size),
                     )
                     .await
                     .map_err(StorageQueryErrorDetail::Network);
@@ -489,6 +1496,7 @@ impl SyncService {
             'a,
             impl Iterator<Item = impl AsRef<[u8]>> + Clone,
         >,
+        trace: Option<&request_trace::RequestTrace>,
     ) -> Result<Vec<Vec<u8>>, CallProofQueryError> {
         const NUM_ATTEMPTS: usize = 3;
 
@@ -500,12 +1508,34 @@ impl SyncService {
             .await
             .take(NUM_ATTEMPTS)
         {
+            let _permit = self
+                .work_queues
+                .acquire(work_queues::WorkClass::JsonRpc)
+                .await;
+            let request_start = Host::now();
             let result = self
                 .network_service
                 .clone()
-                .call_proof_request(self.network_chain_index, target, config.clone())
+                .call_proof_request(
+                    self.network_chain_index,
+                    target.clone(),
+                    config.clone(),
+                    Some(self.max_proof_size),
+                )
                 .await;
 
+            if let Some(trace) = trace {
+                trace.record_network_request(
+                    "call-proof",
+                    &target,
+                    request_start,
+                    result
+                        .as_ref()
+                        .ok()
+                        .map(|proof| proof.iter().map(|node| node.len()).sum()),
+                );
+            }
+
             match result {
                 Ok(value) => return Ok(value),
                 Err(err) => {
@@ -518,6 +1548,294 @@ impl SyncService {
             errors: outcome_errors,
         })
     }
+
+    /// Downloads from the network a proof of the storage values of the given keys.
+    ///
+    /// Contrary to [`SyncService::storage_query`], the proof isn't verified and is returned
+    /// as-is. This is useful in order to complete a call proof that misses some entries.
+    pub async fn storage_proof_query(
+        self: Arc<Self>,
+        block_number: u64,
+        block_hash: &[u8; 32],
+        keys: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+        trace: Option<&request_trace::RequestTrace>,
+    ) -> Result<Vec<Vec<u8>>, StorageProofQueryError> {
+        const NUM_ATTEMPTS: usize = 3;
+
+        let mut outcome_errors = Vec::with_capacity(NUM_ATTEMPTS);
+
+        for target in self
+            .peers_assumed_know_blocks(block_number, block_hash)
+            .await
+            .take(NUM_ATTEMPTS)
+        {
+            let _permit = self
+                .work_queues
+                .acquire(work_queues::WorkClass::JsonRpc)
+                .await;
+            let request_start = Host::now();
+            let result = self
+                .network_service
+                .clone()
+                .storage_proof_request(
+                    self.network_chain_index,
+                    target.clone(),
+                    protocol::StorageProofRequestConfig {
+                        block_hash: *block_hash,
+                        keys: keys.clone(),
+                        accept_compressed_response: self
+                            .network_service
+                            .request_compressed_responses(),
+                    },
+                    Some(self.max_proof_size),
+                )
+                .await;
+
+            if let Some(trace) = trace {
+                trace.record_network_request(
+                    "storage-proof",
+                    &target,
+                    request_start,
+                    result
+                        .as_ref()
+                        .ok()
+                        .map(|proof| proof.iter().map(|node| node.len()).sum()),
+                );
+            }
+
+            match result {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    outcome_errors.push(err);
+                }
+            }
+        }
+
+        Err(StorageProofQueryError {
+            errors: outcome_errors,
+        })
+    }
+}
+
+/// Block to query with [`SyncService::ancestry_verified_header`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AncestryTarget {
+    /// Block of the canonical chain with the given number.
+    Number(u64),
+    /// Block with the given hash. Must be an ancestor of the current finalized block.
+    Hash([u8; 32]),
+}
+
+/// Progress of a call to [`SyncService::ancestry_verified_header`]. See
+/// [`SyncService::ancestry_queries`].
+#[derive(Debug, Clone)]
+pub struct AncestryQueryProgress {
+    /// Block that has been requested.
+    pub target: AncestryTarget,
+    /// Height of the block the chain is walked backwards from.
+    pub anchor_number: u64,
+    /// Height of the lowest block whose header has been verified so far. Equal to
+    /// [`AncestryQueryProgress::anchor_number`] if no header has been verified yet.
+    pub verified_number: u64,
+    /// Maximum number of headers that remain to be downloaded. When the target is a block hash,
+    /// the query can finish before this number reaches zero.
+    pub remaining: u64,
+}
+
+/// See [`SyncService::ancestry_queries`].
+struct AncestryQueries {
+    /// Identifier to assign to the next query.
+    next_id: u64,
+    /// Queries in progress, by identifier.
+    in_progress: HashMap<u64, AncestryQueryProgress>,
+}
+
+/// Entry in [`SyncService::ancestry_queries`], removed when dropped.
+struct AncestryQueryGuard<'a> {
+    queries: &'a std::sync::Mutex<AncestryQueries>,
+    id: u64,
+}
+
+impl<'a> AncestryQueryGuard<'a> {
+    fn new(
+        queries: &'a std::sync::Mutex<AncestryQueries>,
+        progress: AncestryQueryProgress,
+    ) -> Self {
+        let mut lock = queries.lock().unwrap();
+        let id = lock.next_id;
+        lock.next_id += 1;
+        lock.in_progress.insert(id, progress);
+        AncestryQueryGuard { queries, id }
+    }
+
+    fn update(&self, verified_number: u64, remaining: u64) {
+        let mut lock = self.queries.lock().unwrap();
+        let progress = lock.in_progress.get_mut(&self.id).unwrap();
+        progress.verified_number = verified_number;
+        progress.remaining = remaining;
+    }
+}
+
+impl<'a> Drop for AncestryQueryGuard<'a> {
+    fn drop(&mut self) {
+        let _removed = self.queries.lock().unwrap().in_progress.remove(&self.id);
+        debug_assert!(_removed.is_some());
+    }
+}
+
+/// Error that can happen when calling [`SyncService::ancestry_verified_header`].
+#[derive(Debug, derive_more::Display)]
+pub enum AncestryQueryError {
+    /// Requested block isn't an ancestor of the anchor.
+    #[display(fmt = "Block not in the canonical chain")]
+    NotInChain,
+    /// Requested block is too far behind the anchor.
+    #[display(fmt = "Block too far behind the finalized block")]
+    TooFar,
+    /// No peer is known to have the blocks to download.
+    #[display(fmt = "No node available for ancestry query")]
+    NoPeer,
+    /// Too many block requests have failed.
+    #[display(fmt = "All block requests have failed")]
+    RequestsFailed,
+}
+
+/// Maximum number of GrandPa authorities sets kept in a [`GrandpaSetsHistory`].
+const MAX_GRANDPA_SETS_HISTORY: usize = 64;
+
+/// GrandPa authorities sets that have been in use since the sync service has started, used to
+/// verify the justifications of past blocks. See [`SyncService::justification_query`].
+///
+/// The block at which a change of authorities set is enacted is itself finalized by the previous
+/// set. As the finalized block can jump over several blocks at once, the block at which a set
+/// starts being used isn't always known. Each set is instead known to have finalized the blocks
+/// between the last finalized block at which the previous set was seen, excluded, and the first
+/// finalized block at which the next set was seen, included.
+#[derive(Debug)]
+struct GrandpaSetsHistory {
+    /// Sets ordered by increasing identifier. Contains at most [`MAX_GRANDPA_SETS_HISTORY`]
+    /// elements.
+    sets: VecDeque<GrandpaSetsHistoryEntry>,
+}
+
+#[derive(Debug)]
+struct GrandpaSetsHistoryEntry {
+    /// Number of the finalized block at which the set has been seen for the first time. The set
+    /// finalizes the children of this block.
+    first_seen: u64,
+    /// Number of the latest finalized block at which the set was still in use.
+    last_seen: u64,
+    set_id: u64,
+    /// Public keys of the authorities of the set.
+    authorities: Vec<[u8; 32]>,
+}
+
+impl GrandpaSetsHistory {
+    /// Initializes the history with the set that finalizes the children of the given finalized
+    /// block.
+    fn new(finalized_block_number: u64, set_id: u64, authorities: Vec<[u8; 32]>) -> Self {
+        let mut sets = VecDeque::with_capacity(MAX_GRANDPA_SETS_HISTORY);
+        sets.push_back(GrandpaSetsHistoryEntry {
+            first_seen: finalized_block_number,
+            last_seen: finalized_block_number,
+            set_id,
+            authorities,
+        });
+        GrandpaSetsHistory { sets }
+    }
+
+    /// Updates the history after the finalized block has changed. `authorities` is only called
+    /// if `set_id` is a new set.
+    fn observe(
+        &mut self,
+        finalized_block_number: u64,
+        set_id: u64,
+        authorities: impl FnOnce() -> Vec<[u8; 32]>,
+    ) {
+        if let Some(last) = self.sets.back_mut().filter(|last| last.set_id == set_id) {
+            last.last_seen = finalized_block_number;
+            return;
+        }
+
+        if self.sets.len() >= MAX_GRANDPA_SETS_HISTORY {
+            self.sets.pop_front();
+        }
+
+        self.sets.push_back(GrandpaSetsHistoryEntry {
+            first_seen: finalized_block_number,
+            last_seen: finalized_block_number,
+            set_id,
+            authorities: authorities(),
+        });
+    }
+
+    /// Returns the identifiers and authorities of the sets that might have finalized the block
+    /// with the given number, most recent first.
+    fn candidates(&self, block_number: u64) -> Vec<GrandpaAuthoritiesSet> {
+        (0..self.sets.len())
+            .rev()
+            .filter(|&index| {
+                (index == 0 || block_number > self.sets[index - 1].last_seen)
+                    && self
+                        .sets
+                        .get(index + 1)
+                        .map_or(true, |next| block_number <= next.first_seen)
+            })
+            .map(|index| {
+                (
+                    self.sets[index].set_id,
+                    self.sets[index].authorities.clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Error that can happen when calling [`SyncService::justification_query`].
+#[derive(Debug, derive_more::Display)]
+pub enum JustificationQueryError {
+    /// The chain doesn't use the GrandPa finality algorithm.
+    #[display(fmt = "Chain doesn't use GrandPa finality")]
+    NotGrandpa,
+    /// Failed to verify that the block is finalized.
+    #[display(fmt = "{}", _0)]
+    Ancestry(AncestryQueryError),
+    /// No peer is known to have the block.
+    #[display(fmt = "No node available for justification query")]
+    NoPeer,
+    /// None of the peers has sent back a justification that could be verified.
+    #[display(fmt = "No verifiable justification found")]
+    NoValidJustification,
+}
+
+/// Determines, after all the attempts at querying the keys whose indices are in `range` have
+/// failed with the given errors, whether the range should be split in two halves that are then
+/// queried separately.
+///
+/// Only a proof that is too large justifies splitting the range. Any other error, including the
+/// remote refusing to answer, would most likely happen again with fewer keys. Since the range is
+/// split in halves, the number of splits is bounded by the logarithm of the number of keys.
+fn split_storage_query_range(
+    range: &ops::Range<usize>,
+    errors: &[StorageQueryErrorDetail],
+) -> Option<(ops::Range<usize>, ops::Range<usize>)> {
+    if range.len() < 2 {
+        return None;
+    }
+
+    if !errors.iter().any(|err| {
+        matches!(
+            err,
+            StorageQueryErrorDetail::Network(
+                service::StorageProofRequestError::ProofTooLarge { .. }
+            )
+        )
+    }) {
+        return None;
+    }
+
+    let middle = range.start + range.len() / 2;
+    Some((range.start..middle, middle..range.end))
 }
 
 /// Error that can happen when calling [`SyncService::storage_query`].
@@ -535,11 +1853,18 @@ impl StorageQueryError {
         self.errors.iter().all(|err| match err {
             StorageQueryErrorDetail::Network(service::StorageProofRequestError::Request(_)) => true,
             StorageQueryErrorDetail::Network(service::StorageProofRequestError::Decode(_)) => false,
+            StorageQueryErrorDetail::Network(
+                service::StorageProofRequestError::ProofTooLarge { .. }
+                | service::StorageProofRequestError::Refused,
+            ) => true,
             // TODO: as a temporary hack, we consider `TrieRootNotFound` as the remote not knowing about the requested block; see https://github.com/paritytech/substrate/pull/8046
             StorageQueryErrorDetail::ProofVerification(proof_verify::Error::TrieRootNotFound) => {
                 true
             }
             StorageQueryErrorDetail::ProofVerification(_) => false,
+            StorageQueryErrorDetail::QuorumNotReached { .. } => true,
+            StorageQueryErrorDetail::QuorumMismatch => false,
+            StorageQueryErrorDetail::OutOfMemory(_) => false,
         })
     }
 }
@@ -567,80 +1892,691 @@ pub enum StorageQueryErrorDetail {
     /// Error verifying the proof.
     #[display(fmt = "{}", _0)]
     ProofVerification(proof_verify::Error),
+    /// Not enough peers have confirmed the queried block. See
+    /// [`SyncService::storage_query_quorum`].
+    #[display(
+        fmt = "Only {} peer(s) out of the {} required confirmed the block",
+        num_agreeing,
+        quorum_size
+    )]
+    QuorumNotReached {
+        num_agreeing: usize,
+        quorum_size: usize,
+    },
+    /// Peers have reported a different block with the same number as the queried block. See
+    /// [`SyncService::storage_query_quorum`].
+    #[display(fmt = "Peers disagree about the block and its state root")]
+    QuorumMismatch,
+    /// Not enough memory to hold the values found in the proof.
+    #[display(fmt = "{}", _0)]
+    OutOfMemory(memory_usage::AllocError),
+}
+
+/// Responses accumulated during [`SyncService::block_quorum_check`].
+struct Quorum<T> {
+    /// See [`Config::quorum_size`].
+    quorum_size: usize,
+    /// Value that the responses are expected to be equal to.
+    expected_value: T,
+    /// Number of responses equal to [`Quorum::expected_value`].
+    num_agreeing: usize,
+    /// Number of responses different from [`Quorum::expected_value`].
+    num_disagreeing: usize,
+}
+
+/// Error returned by [`Quorum::add`] if a response differs from the expected value.
+#[derive(Debug, PartialEq, Eq)]
+struct QuorumMismatch;
+
+impl<T: PartialEq> Quorum<T> {
+    fn new(quorum_size: usize, expected_value: T) -> Self {
+        Quorum {
+            quorum_size,
+            expected_value,
+            num_agreeing: 0,
+            num_disagreeing: 0,
+        }
+    }
+
+    /// Adds the valid response of a peer. Returns an error if it differs from the expected
+    /// value.
+    fn add(&mut self, value: T) -> Result<(), QuorumMismatch> {
+        if value == self.expected_value {
+            self.num_agreeing += 1;
+            Ok(())
+        } else {
+            self.num_disagreeing += 1;
+            Err(QuorumMismatch)
+        }
+    }
+
+    /// Returns the number of responses equal to the expected value.
+    fn num_agreeing(&self) -> usize {
+        self.num_agreeing
+    }
+
+    /// Returns the number of responses different from the expected value.
+    fn num_disagreeing(&self) -> usize {
+        self.num_disagreeing
+    }
+
+    /// Returns `true` if enough responses are equal to the expected value.
+    fn is_reached(&self) -> bool {
+        self.num_agreeing >= self.quorum_size
+    }
+}
+
+/// Error that can happen when calling [`SyncService::block_quorum_check`].
+#[derive(Debug, derive_more::Display)]
+#[display(
+    fmt = "Only {} peer(s) out of the {} required know about the block, and {} peer(s) \
+           reported a different block with the same number",
+    num_confirmations,
+    quorum_size,
+    num_disagreements
+)]
+pub struct QuorumCheckError {
+    /// Number of peers that have sent back the header of the block.
+    pub num_confirmations: usize,
+    /// Number of peers that have sent back the header of a different block with the same
+    /// number.
+    pub num_disagreements: usize,
+    /// See [`Config::quorum_size`].
+    pub quorum_size: usize,
+}
+
+/// Error that can happen when calling [`SyncService::call_proof_query`].
+#[derive(Debug)]
+pub struct CallProofQueryError {
+    /// Contains one error per peer that has been contacted. If this list is empty, then we
+    /// aren't connected to any node.
+    pub errors: Vec<service::CallProofRequestError>,
+}
+
+impl fmt::Display for CallProofQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.errors.is_empty() {
+            write!(f, "No node available for call proof query")
+        } else {
+            write!(f, "Call proof query errors:")?;
+            for err in &self.errors {
+                write!(f, "\n- {}", err)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Error that can happen when calling [`SyncService::storage_proof_query`].
+#[derive(Debug)]
+pub struct StorageProofQueryError {
+    /// Contains one error per peer that has been contacted. If this list is empty, then we
+    /// aren't connected to any node.
+    pub errors: Vec<service::StorageProofRequestError>,
+}
+
+impl fmt::Display for StorageProofQueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.errors.is_empty() {
+            write!(f, "No node available for storage proof query")
+        } else {
+            write!(f, "Storage proof query errors:")?;
+            for err in &self.errors {
+                write!(f, "\n- {}", err)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Return value of [`SyncService::grandpa_state`].
+#[derive(Debug, Clone)]
+pub struct GrandpaState {
+    /// Hash of the finalized block the state applies to.
+    pub finalized_block_hash: [u8; 32],
+    /// Height of the finalized block the state applies to.
+    pub finalized_block_number: u64,
+    /// Identifier of the authorities set that finalizes the children of the finalized block.
+    pub set_id: u64,
+    /// Authorities that finalize the children of the finalized block.
+    pub authorities: Vec<header::GrandpaAuthority>,
+    /// Change in the list of authorities that has been scheduled by a block that is already
+    /// finalized but that triggers at a future block, if any. Contains the block number where
+    /// the change triggers and the new list of authorities.
+    pub scheduled_change: Option<(u64, Vec<header::GrandpaAuthority>)>,
+}
+
+/// Return value of [`SyncService::debug_state`].
+#[derive(Debug, Clone)]
+pub struct DebugState {
+    /// Hash of the current finalized block.
+    pub finalized_block_hash: [u8; 32],
+    /// Height of the current finalized block.
+    pub finalized_block_number: u64,
+    /// Hash of the current best block.
+    pub best_block_hash: [u8; 32],
+    /// List of all the non-finalized blocks known by the syncing, in no particular order.
+    ///
+    /// Always empty for parachains, as the syncing doesn't track the non-finalized blocks.
+    pub non_finalized_blocks: Vec<DebugBlock>,
+    /// Number of sources, in other words peers, that the syncing is aware of.
+    pub num_sources: usize,
+    /// Number of blocks requests currently in progress.
+    pub pending_block_requests: usize,
+    /// Number of GrandPa warp sync requests currently in progress.
+    pub pending_grandpa_requests: usize,
+    /// Number of storage requests currently in progress.
+    pub pending_storage_requests: usize,
+}
+
+/// Block in [`DebugState::non_finalized_blocks`].
+#[derive(Debug, Clone)]
+pub struct DebugBlock {
+    /// Hash of the block.
+    pub hash: [u8; 32],
+    /// Height of the block.
+    pub number: u64,
+    /// Hash of the parent of the block.
+    pub parent_hash: [u8; 32],
+}
+
+/// Return value of [`SyncService::subscribe_all`].
+pub struct SubscribeAll {
+    /// SCALE-encoded header of the finalized block at the time of the subscription.
+    pub finalized_block_scale_encoded_header: Vec<u8>,
+    /// List of all known non-finalized blocks at the time of subscription.
+    ///
+    /// Only one element in this list has [`BlockNotification::is_new_best`] equal to true.
+    pub non_finalized_blocks: Vec<BlockNotification>,
+    /// Channel onto which new blocks are sent. The channel gets closed if it is full when a new
+    /// block needs to be reported.
+    pub new_blocks: mpsc::Receiver<BlockNotification>,
+}
+
+/// Header of a block reported by [`SyncService::subscribe_best`] or
+/// [`SyncService::subscribe_finalized`].
+///
+/// Contains, alongside with the SCALE-encoded header, the fields of the header that subscribers
+/// most commonly need, so that they don't have to decode the header themselves.
+#[derive(Debug, Clone)]
+pub struct HeaderNotification {
+    /// Hash of the block.
+    pub hash: [u8; 32],
+
+    /// Height of the block.
+    pub number: u64,
+
+    /// Hash of the parent of the block, as found in its header.
+    pub parent_hash: [u8; 32],
+
+    /// Merkle value of the root node of the storage trie of the block.
+    pub state_root: [u8; 32],
+
+    /// SCALE-encoded header of the block.
+    pub scale_encoded_header: Vec<u8>,
+}
+
+impl HeaderNotification {
+    fn from_header(header: header::HeaderRef) -> Self {
+        let scale_encoded_header = header.scale_encoding_vec();
+        HeaderNotification {
+            hash: Host::blake2_256(&scale_encoded_header),
+            number: header.number,
+            parent_hash: *header.parent_hash,
+            state_root: *header.state_root,
+            scale_encoded_header,
+        }
+    }
+}
+
+/// Notification about a new block.
+///
+/// See [`SyncService::subscribe_all`].
+#[derive(Debug, Clone)]
+pub struct BlockNotification {
+    /// True if this block is considered as the best block of the chain.
+    pub is_new_best: bool,
+
+    /// SCALE-encoded header of the block.
+    pub scale_encoded_header: Vec<u8>,
+
+    /// Hash of the header of the parent of this block.
+    ///
+    /// > **Note**: The header of a block contains the hash of its parent. When it comes to
+    /// >           consensus algorithms such as Babe or Aura, the syncing code verifies that this
+    /// >           hash, stored in the header, actually corresponds to a valid block. However,
+    /// >           when it comes to parachain consensus, no such verification is performed.
+    /// >           Contrary to the hash stored in the header, the value of this field is
+    /// >           guaranteed to refer to a block that is known by the syncing service. This
+    /// >           allows a subscriber of the state of the chain to precisely track the hierarchy
+    /// >           of blocks, without risking to run into a problem in case of a block with an
+    /// >           invalid header.
+    pub parent_hash: [u8; 32],
+
+    /// Sr25519 public key of the authority that has authored the block, as resolved against the
+    /// list of authorities of the epoch the block belongs to. `None` if the chain doesn't have
+    /// block authors.
+    pub author: Option<[u8; 32]>,
+}
+
+/// Requests the given block from the network. See [`SyncService::block_query`].
+async fn fetch_block(
+    network_service: &Arc<network_service::NetworkService>,
+    network_chain_index: usize,
+    work_queues: &Arc<work_queues::WorkQueues>,
+    work_class: work_queues::WorkClass,
+    hash: [u8; 32],
+    fields: protocol::BlocksRequestFields,
+) -> Result<protocol::BlockData, ()> {
+    // TODO: better error?
+    const NUM_ATTEMPTS: usize = 3;
+
+    let request_config = protocol::BlocksRequestConfig {
+        start: protocol::BlocksRequestConfigStart::Hash(hash),
+        desired_count: NonZeroU32::new(1).unwrap(),
+        direction: protocol::BlocksRequestDirection::Ascending,
+        fields: fields.clone(),
+        accept_compressed_response: network_service.request_compressed_responses(),
+    };
+
+    // TODO: better peers selection ; don't just take the first 3
+    // TODO: must only ask the peers that know about this block
+    for target in network_service.peers_list().await.take(NUM_ATTEMPTS) {
+        let _permit = work_queues.acquire(work_class).await;
+        let mut result = match network_service
+            .clone()
+            .blocks_request(target, network_chain_index, request_config.clone())
+            .await
+        {
+            Ok(b) => b,
+            Err(_) => continue,
+        };
+
+        if result.len() != 1 {
+            continue;
+        }
+
+        let result = result.remove(0);
+
+        if result.header.is_none() && fields.header {
+            continue;
+        }
+        if result
+            .header
+            .as_ref()
+            .map_or(false, |h| header::decode(h).is_err())
+        {
+            continue;
+        }
+        if result.body.is_none() && fields.body {
+            continue;
+        }
+        // Note: the presence of a justification isn't checked and can't be checked, as not
+        // all blocks have a justification in the first place.
+        if result.hash != hash {
+            continue;
+        }
+        if result
+            .header
+            .as_ref()
+            .map_or(false, |h| Host::blake2_256(&h) != result.hash)
+        {
+            continue;
+        }
+        match (&result.header, &result.body) {
+            (Some(_), Some(_)) => {
+                // TODO: verify correctness of body
+            }
+            _ => {}
+        }
+
+        return Ok(result);
+    }
+
+    Err(())
+}
+
+/// Background task that downloads the header, body and justification of each new best block,
+/// and keeps the `num_blocks` most recent ones in `recent_blocks`.
+async fn download_recent_bodies(
+    mut to_background: mpsc::Sender<ToBackground>,
+    network_service: Arc<network_service::NetworkService>,
+    network_chain_index: usize,
+    work_queues: Arc<work_queues::WorkQueues>,
+    recent_blocks: Arc<Mutex<VecDeque<protocol::BlockData>>>,
+    num_blocks: usize,
+) {
+    let mut new_best_blocks = {
+        let (send_back, rx) = oneshot::channel();
+        if to_background
+            .send(ToBackground::SubscribeBest { send_back })
+            .await
+            .is_err()
+        {
+            return;
+        }
+        match rx.await {
+            Ok((_, new_best_blocks)) => new_best_blocks,
+            Err(_) => return,
+        }
+    };
+
+    while let Some(new_best) = new_best_blocks.next().await {
+        let hash = new_best.hash;
+        if recent_blocks.lock().await.iter().any(|b| b.hash == hash) {
+            continue;
+        }
+
+        let block = match fetch_block(
+            &network_service,
+            network_chain_index,
+            &work_queues,
+            work_queues::WorkClass::BlockTracking,
+            hash,
+            protocol::BlocksRequestFields {
+                header: true,
+                body: true,
+                justification: true,
+                indexed_body: false,
+            },
+        )
+        .await
+        {
+            Ok(b) => b,
+            Err(()) => {
+                log::debug!(
+                    target: "sync-verify",
+                    "Failed to download body of block {}",
+                    HashDisplay(&hash)
+                );
+                continue;
+            }
+        };
+
+        let mut recent_blocks = recent_blocks.lock().await;
+        if recent_blocks.len() >= num_blocks {
+            recent_blocks.pop_front();
+        }
+        recent_blocks.push_back(block);
+    }
+}
+
+/// Probability for each successful storage query to be sent to the [`state_root_watchdog`].
+const STATE_ROOT_CHECK_PROBABILITY: f64 = 1.0 / 16.0;
+
+/// Sample of a successful storage query sent to the [`state_root_watchdog`].
+struct StateRootCheck {
+    /// Hash of the block whose storage has been queried.
+    block_hash: [u8; 32],
+    /// State root the storage proof has been verified against.
+    state_root: [u8; 32],
+    /// Peer that has provided the storage proof.
+    proof_peer: PeerId,
+}
+
+/// Downloads, for each sample received on `checks` whose block is finalized, the header with
+/// the number of this block from a randomly chosen peer other than the one that has provided the
+/// storage proof, and compares it with the header of the block.
+///
+/// All the honest peers agree on the finalized blocks, and the header of a finalized block can
+/// only differ if either the peer or the peers the local node has synchronized with are trying
+/// to eclipse the other. Such mismatches are reported as a
+/// [`network_service::PeerEvent::StateRootMismatch`]. Samples whose block isn't finalized,
+/// or isn't in the canonical index anymore, are ignored, as a different block with the same
+/// number might legitimately exist. Peers that fail to answer are ignored as well.
+async fn state_root_watchdog(
+    network_service: Arc<network_service::NetworkService>,
+    network_chain_index: usize,
+    work_queues: Arc<work_queues::WorkQueues>,
+    canonical_index: Arc<Mutex<canonical_index::CanonicalIndex>>,
+    mut checks: mpsc::Receiver<StateRootCheck>,
+) {
+    while let Some(check) = checks.next().await {
+        let block_number = match canonical_index
+            .lock()
+            .await
+            .finalized_block_number(&check.block_hash)
+        {
+            // The genesis block is never checked, as it is part of the chain specification.
+            Some(n) => match NonZeroU64::new(n) {
+                Some(n) => n,
+                None => continue,
+            },
+            None => continue,
+        };
+
+        let target = {
+            let mut candidates = network_service
+                .peers_info(network_chain_index)
+                .await
+                .into_iter()
+                .map(|info| info.peer_id)
+                .filter(|peer_id| *peer_id != check.proof_peer)
+                .collect::<Vec<_>>();
+            if candidates.is_empty() {
+                continue;
+            }
+            let index = rand::random::<usize>() % candidates.len();
+            candidates.swap_remove(index)
+        };
+
+        // The check is a background task whose result is never waited upon, and must not slow
+        // down the other requests.
+        let result = {
+            let _permit = work_queues.acquire(work_queues::WorkClass::Prefetch).await;
+            network_service
+                .clone()
+                .blocks_request(
+                    target.clone(),
+                    network_chain_index,
+                    protocol::BlocksRequestConfig {
+                        start: protocol::BlocksRequestConfigStart::Number(block_number),
+                        desired_count: NonZeroU32::new(1).unwrap(),
+                        direction: protocol::BlocksRequestDirection::Ascending,
+                        fields: protocol::BlocksRequestFields {
+                            header: true,
+                            body: false,
+                            justification: false,
+                            indexed_body: false,
+                        },
+                        accept_compressed_response: network_service.request_compressed_responses(),
+                    },
+                )
+                .await
+        };
+
+        let (reported_block_hash, reported_state_root) =
+            match compare_canonical_header(&check, block_number.get(), result.ok()) {
+                StateRootCheckOutcome::Mismatch {
+                    reported_block_hash,
+                    reported_state_root,
+                } => (reported_block_hash, reported_state_root),
+                StateRootCheckOutcome::Match | StateRootCheckOutcome::Inconclusive => continue,
+            };
+
+        log::error!(
+            target: "sync-verify",
+            "Finalized block mismatch at height {}: {} reports {} (state root {}), while the \
+             storage proof of {} was verified against {} (state root {})",
+            block_number,
+            target,
+            HashDisplay(&reported_block_hash),
+            HashDisplay(&reported_state_root),
+            check.proof_peer,
+            HashDisplay(&check.block_hash),
+            HashDisplay(&check.state_root)
+        );
+
+        network_service
+            .report_state_root_mismatch(
+                network_chain_index,
+                target,
+                check.proof_peer,
+                block_number.get(),
+                check.block_hash,
+                check.state_root,
+                reported_block_hash,
+                reported_state_root,
+            )
+            .await;
+    }
 }
 
-/// Error that can happen when calling [`SyncService::call_proof_query`].
-#[derive(Debug)]
-pub struct CallProofQueryError {
-    /// Contains one error per peer that has been contacted. If this list is empty, then we
-    /// aren't connected to any node.
-    pub errors: Vec<service::CallProofRequestError>,
+/// Outcome of [`compare_canonical_header`].
+#[derive(Debug, PartialEq, Eq)]
+enum StateRootCheckOutcome {
+    /// The header in the response is the one of the checked block.
+    Match,
+    /// The header in the response has the number of the checked block but a different hash.
+    Mismatch {
+        reported_block_hash: [u8; 32],
+        reported_state_root: [u8; 32],
+    },
+    /// The request has failed, or the response doesn't contain a valid header with the number
+    /// of the checked block.
+    Inconclusive,
 }
 
-impl fmt::Display for CallProofQueryError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.errors.is_empty() {
-            write!(f, "No node available for call proof query")
-        } else {
-            write!(f, "Call proof query errors:")?;
-            for err in &self.errors {
-                write!(f, "\n- {}", err)?;
-            }
-            Ok(())
+/// Compares the header of the block of a [`StateRootCheck`], whose number is `block_number`,
+/// with the header found in the response to the blocks request performed by the
+/// [`state_root_watchdog`].
+fn compare_canonical_header(
+    check: &StateRootCheck,
+    block_number: u64,
+    response: Option<Vec<protocol::BlockData>>,
+) -> StateRootCheckOutcome {
+    let header = match response
+        .and_then(|blocks| blocks.into_iter().next())
+        .and_then(|block| block.header)
+    {
+        Some(header) => header,
+        None => return StateRootCheckOutcome::Inconclusive,
+    };
+
+    let reported_state_root = match header::decode(&header) {
+        Ok(decoded) if decoded.number == block_number => *decoded.state_root,
+        _ => return StateRootCheckOutcome::Inconclusive,
+    };
+
+    let reported_block_hash = Host::blake2_256(&header);
+    if reported_block_hash == check.block_hash {
+        StateRootCheckOutcome::Match
+    } else {
+        StateRootCheckOutcome::Mismatch {
+            reported_block_hash,
+            reported_state_root,
         }
     }
 }
 
-/// Return value of [`SyncService::subscribe_all`].
-pub struct SubscribeAll {
-    /// SCALE-encoded header of the finalized block at the time of the subscription.
-    pub finalized_block_scale_encoded_header: Vec<u8>,
-    /// List of all known non-finalized blocks at the time of subscription.
-    ///
-    /// Only one element in this list has [`BlockNotification::is_new_best`] equal to true.
-    pub non_finalized_blocks: Vec<BlockNotification>,
-    /// Channel onto which new blocks are sent. The channel gets closed if it is full when a new
-    /// block needs to be reported.
-    pub new_blocks: mpsc::Receiver<BlockNotification>,
-}
+/// Keeps the given index up to date with the best and finalized blocks reported by the
+/// background task.
+async fn update_canonical_index(
+    mut to_background: mpsc::Sender<ToBackground>,
+    canonical_index: Arc<Mutex<canonical_index::CanonicalIndex>>,
+) {
+    let mut finalized_blocks = {
+        let (send_back, rx) = oneshot::channel();
+        if to_background
+            .send(ToBackground::SubscribeFinalized { send_back })
+            .await
+            .is_err()
+        {
+            return;
+        }
+        match rx.await {
+            Ok((current, finalized_blocks)) => {
+                canonical_index
+                    .lock()
+                    .await
+                    .set_finalized(current.number, current.hash);
+                finalized_blocks
+            }
+            Err(_) => return,
+        }
+    };
 
-/// Notification about a new block.
-///
-/// See [`SyncService::subscribe_all`].
-#[derive(Debug)]
-pub struct BlockNotification {
-    /// True if this block is considered as the best block of the chain.
-    pub is_new_best: bool,
+    let mut best_blocks = {
+        let (send_back, rx) = oneshot::channel();
+        if to_background
+            .send(ToBackground::SubscribeBest { send_back })
+            .await
+            .is_err()
+        {
+            return;
+        }
+        match rx.await {
+            Ok((current, best_blocks)) => {
+                canonical_index.lock().await.set_best(
+                    current.number,
+                    current.hash,
+                    current.parent_hash,
+                );
+                best_blocks
+            }
+            Err(_) => return,
+        }
+    };
 
-    /// SCALE-encoded header of the block.
-    pub scale_encoded_header: Vec<u8>,
+    loop {
+        match future::select(best_blocks.next(), finalized_blocks.next()).await {
+            future::Either::Left((Some(block), _)) => {
+                canonical_index
+                    .lock()
+                    .await
+                    .set_best(block.number, block.hash, block.parent_hash)
+            }
+            future::Either::Right((Some(block), _)) => canonical_index
+                .lock()
+                .await
+                .set_finalized(block.number, block.hash),
 
-    /// Hash of the header of the parent of this block.
-    ///
-    /// > **Note**: The header of a block contains the hash of its parent. When it comes to
-    /// >           consensus algorithms such as Babe or Aura, the syncing code verifies that this
-    /// >           hash, stored in the header, actually corresponds to a valid block. However,
-    /// >           when it comes to parachain consensus, no such verification is performed.
-    /// >           Contrary to the hash stored in the header, the value of this field is
-    /// >           guaranteed to refer to a block that is known by the syncing service. This
-    /// >           allows a subscriber of the state of the chain to precisely track the hierarchy
-    /// >           of blocks, without risking to run into a problem in case of a block with an
-    /// >           invalid header.
-    pub parent_hash: [u8; 32],
+            // One of the two streams is over.
+            _ => break,
+        }
+    }
 }
 
+/// Maximum number of headers verified in a single batch while catching up with the head of the
+/// chain. See [`all::AllSync::process_headers_batch`].
+const HEADERS_BATCH_SIZE: u32 = 64;
+
 async fn start_relay_chain(
     chain_information: chain::chain_information::ValidChainInformation,
     mut from_foreground: mpsc::Receiver<ToBackground>,
     network_service: Arc<network_service::NetworkService>,
     network_chain_index: usize,
     mut from_network_service: mpsc::Receiver<network_service::Event>,
+    cpu_usage: Arc<cpu_usage::CpuUsage>,
+    work_queues: Arc<work_queues::WorkQueues>,
+    slot_duration: Option<NonZeroU64>,
+    max_announce_future_drift: Duration,
+    babe_relaxed_secondary_slots: bool,
+    sync_mode: SyncMode,
+    max_proof_size: usize,
+    custom_consensus_engines: Option<ConfigCustomConsensusEngines>,
 ) -> impl Future<Output = ()> {
+    let (custom_consensus_engines, custom_digest_items_verifier) = match custom_consensus_engines {
+        Some(config) => (config.engines, Some(config.verifier)),
+        None => (Vec::new(), None),
+    };
+
+    // Returns `false` if the given digest items of custom consensus engines, extracted from a
+    // header, are refused by the API user.
+    let custom_digest_items_valid = move |items: &[header::CustomDigestItem]| {
+        items.is_empty()
+            || match &custom_digest_items_verifier {
+                Some(verifier) => verifier(items),
+                None => false,
+            }
+    };
+
     // TODO: implicit generics
     let mut sync = all::AllSync::<(), libp2p::PeerId, ()>::new(all::Config {
         chain_information,
         sources_capacity: 32,
         source_selection_randomness_seed: rand::random(),
+        randomness_seed: rand::random(),
         blocks_request_granularity: NonZeroU32::new(128).unwrap(),
         blocks_capacity: {
             // This is the maximum number of blocks between two consecutive justifications.
@@ -663,12 +2599,42 @@ async fn start_relay_chain(
             5000
         },
         full: None,
+        custom_consensus_engines,
+        babe_relaxed_secondary_slots,
+        // The signatures and VRF proofs of the headers are verified by the host if it supports
+        // doing so, as this is considerably faster than doing it within the Wasm VM.
+        sr25519_hooks: Some(verify::Sr25519Hooks {
+            verify: Host::sr25519_verify,
+            babe_vrf_verify: Host::babe_vrf_verify,
+        }),
     });
 
+    // Authorities sets used to verify the justifications of past blocks. `None` if the chain
+    // doesn't use GrandPa.
+    let mut grandpa_sets_history = match sync.as_chain_information().as_ref().finality {
+        chain::chain_information::ChainInformationFinalityRef::Grandpa {
+            after_finalized_block_authorities_set_id,
+            finalized_triggered_authorities,
+            ..
+        } => Some(GrandpaSetsHistory::new(
+            sync.finalized_block_header().number,
+            after_finalized_block_authorities_set_id,
+            finalized_triggered_authorities
+                .iter()
+                .map(|a| a.public_key)
+                .collect(),
+        )),
+        chain::chain_information::ChainInformationFinalityRef::Outsourced => None,
+    };
+
     async move {
         // TODO: remove
         let mut peers_source_id_map = HashMap::new();
 
+        // For each peer, number of block announces that have been ignored because the slot of
+        // the block was too far in the future. See [`Config::max_announce_future_drift`].
+        let mut implausible_announces = HashMap::<PeerId, u32>::new();
+
         // List of block requests currently in progress.
         let mut pending_block_requests = stream::FuturesUnordered::new();
         // List of grandpa warp sync requests currently in progress.
@@ -679,8 +2645,8 @@ async fn start_relay_chain(
         // TODO: remove; should store the aborthandle in the TRq user data instead
         let mut pending_requests = HashMap::new();
 
-        let mut finalized_notifications = Vec::<lossy_channel::Sender<Vec<u8>>>::new();
-        let mut best_notifications = Vec::<lossy_channel::Sender<Vec<u8>>>::new();
+        let mut finalized_notifications = Vec::<lossy_channel::Sender<HeaderNotification>>::new();
+        let mut best_notifications = Vec::<lossy_channel::Sender<HeaderNotification>>::new();
         let mut all_notifications = Vec::<mpsc::Sender<BlockNotification>>::new();
 
         // Queue of requests that the sync state machine wants to start and that haven't been
@@ -740,11 +2706,23 @@ async fn start_relay_chain(
                                 fields: network::protocol::BlocksRequestFields {
                                     header: request_headers,
                                     body: request_bodies,
-                                    justification: request_justification,
+                                    justification: request_justification
+                                        && sync_mode.downloads_justifications(),
+                                    indexed_body: false,
                                 },
+                                accept_compressed_response: network_service
+                                    .request_compressed_responses(),
                             },
                         );
 
+                        let work_queues = work_queues.clone();
+                        let block_request = async move {
+                            let _permit = work_queues
+                                .acquire(work_queues::WorkClass::BlockTracking)
+                                .await;
+                            block_request.await
+                        };
+
                         let (block_request, abort) = future::abortable(block_request);
                         pending_requests.insert(request_id, abort);
 
@@ -767,6 +2745,14 @@ async fn start_relay_chain(
                             sync_start_block_hash,
                         );
 
+                        let work_queues = work_queues.clone();
+                        let grandpa_request = async move {
+                            let _permit = work_queues
+                                .acquire(work_queues::WorkClass::BlockTracking)
+                                .await;
+                            grandpa_request.await
+                        };
+
                         let (grandpa_request, abort) = future::abortable(grandpa_request);
                         pending_requests.insert(request_id, abort);
 
@@ -791,11 +2777,21 @@ async fn start_relay_chain(
                             network::protocol::StorageProofRequestConfig {
                                 block_hash,
                                 keys: keys.clone().into_iter(),
+                                accept_compressed_response: network_service
+                                    .request_compressed_responses(),
                             },
+                            Some(max_proof_size),
                         );
 
+                        let cpu_usage = cpu_usage.clone();
+                        let work_queues = work_queues.clone();
                         let storage_request = async move {
+                            let _permit = work_queues
+                                .acquire(work_queues::WorkClass::BlockTracking)
+                                .await;
                             if let Ok(outcome) = storage_request.await {
+                                let _measure =
+                                    cpu_usage.measure(cpu_usage::Category::ProofVerification);
                                 // TODO: lots of copying around
                                 // TODO: log what happens
                                 keys.into_iter()
@@ -833,14 +2829,113 @@ async fn start_relay_chain(
             // verifying storage proof.
             // If the state is one of the "verifying" states, perform the actual verification and
             // loop again until the sync is in an idle state.
+            // Before doing so, pause if the chain has used more than its share of CPU time.
+            cpu_usage.throttle().await;
             loop {
+                // While catching up with the head of the chain, headers are verified in batches
+                // whose signatures are then checked all at once.
+                match sync.process_headers_batch(
+                    NonZeroU32::new(HEADERS_BATCH_SIZE).unwrap(),
+                    Host::now_from_unix_epoch(),
+                ) {
+                    all::ProcessHeadersBatch::AllSync(idle) => sync = idle,
+                    all::ProcessHeadersBatch::Batch(batch) => {
+                        let verified_hashes = batch.block_hashes().to_vec();
+
+                        let custom_items_valid = batch
+                            .custom_digest_items()
+                            .iter()
+                            .all(|items| custom_digest_items_valid(items));
+
+                        let outcome = if custom_items_valid {
+                            let _measure =
+                                cpu_usage.measure(cpu_usage::Category::HeaderVerification);
+                            batch.verify_and_finish()
+                        } else {
+                            batch.finish(Err(verify::header_only::Error::CustomDigestItemsRejected))
+                        };
+
+                        match outcome {
+                            all::HeadersBatchOutcome::Success {
+                                sync: sync_out,
+                                next_actions,
+                                is_new_finalized,
+                            } => {
+                                log::debug!(
+                                    target: "sync-verify",
+                                    "Successfully verified batch of {} headers",
+                                    verified_hashes.len()
+                                );
+
+                                requests_to_start.extend(next_actions);
+                                has_new_best = true;
+                                if is_new_finalized {
+                                    has_new_finalized = true;
+                                }
+
+                                // Each block of the batch has been the best block when it
+                                // was inserted. Blocks that have been finalized by the last
+                                // block of the batch are no longer reported.
+                                // The headers are indexed by hash once, rather than searching
+                                // through all the non-finalized blocks for each verified block.
+                                let mut headers = sync_out
+                                    .non_finalized_blocks()
+                                    .map(|h| (h.hash(), h))
+                                    .collect::<HashMap<_, _>>();
+                                for verified_hash in &verified_hashes {
+                                    let header = match headers.remove(verified_hash) {
+                                        Some(h) => h,
+                                        None => continue,
+                                    };
+                                    let notification = BlockNotification {
+                                        is_new_best: true,
+                                        scale_encoded_header: header.scale_encoding_vec(),
+                                        parent_hash: *header.parent_hash,
+                                        author: sync_out.block_author(verified_hash),
+                                    };
+
+                                    for index in (0..all_notifications.len()).rev() {
+                                        let mut subscription = all_notifications.swap_remove(index);
+                                        if subscription.try_send(notification.clone()).is_ok() {
+                                            all_notifications.push(subscription);
+                                        }
+                                    }
+                                }
+
+                                sync = sync_out;
+                            }
+                            all::HeadersBatchOutcome::Error {
+                                sync: sync_out,
+                                next_actions,
+                                error,
+                            } => {
+                                log::warn!(
+                                    target: "sync-verify",
+                                    "Error while verifying batch of {} headers: {}",
+                                    verified_hashes.len(),
+                                    error
+                                );
+
+                                requests_to_start.extend(next_actions);
+                                sync = sync_out;
+                            }
+                        }
+
+                        continue;
+                    }
+                }
+
                 match sync.process_one() {
                     all::ProcessOne::AllSync(idle) => {
                         sync = idle;
                         break;
                     }
                     all::ProcessOne::VerifyWarpSyncFragment(verify) => {
-                        let (sync_out, next_actions, result) = verify.perform();
+                        let (sync_out, next_actions, result) = {
+                            let _measure =
+                                cpu_usage.measure(cpu_usage::Category::HeaderVerification);
+                            verify.perform()
+                        };
                         sync = sync_out;
                         requests_to_start.extend(next_actions);
 
@@ -856,7 +2951,15 @@ async fn start_relay_chain(
                     all::ProcessOne::VerifyHeader(verify) => {
                         let verified_hash = verify.hash();
 
-                        match verify.perform(ffi::unix_time(), ()) {
+                        let outcome = if custom_digest_items_valid(&verify.custom_digest_items()) {
+                            let _measure =
+                                cpu_usage.measure(cpu_usage::Category::HeaderVerification);
+                            verify.perform(Host::now_from_unix_epoch(), ())
+                        } else {
+                            verify.reject(())
+                        };
+
+                        match outcome {
                             all::HeaderVerifyOutcome::Success {
                                 sync: sync_out,
                                 next_actions,
@@ -897,6 +3000,7 @@ async fn start_relay_chain(
                                         is_new_best,
                                         scale_encoded_header: header.scale_encoding_vec(),
                                         parent_hash: *header.parent_hash,
+                                        author: sync_out.block_author(&verified_hash),
                                     };
 
                                     if subscription.try_send(notification).is_ok() {
@@ -933,10 +3037,10 @@ async fn start_relay_chain(
             if has_new_best {
                 has_new_best = false;
 
-                let scale_encoded_header = sync.best_block_header().scale_encoding_vec();
+                let notification = HeaderNotification::from_header(sync.best_block_header());
                 // TODO: remove expired senders
                 for notif in &mut best_notifications {
-                    let _ = notif.send(scale_encoded_header.clone());
+                    let _ = notif.send(notification.clone());
                 }
 
                 // Since this task is verifying blocks, a heavy CPU-only operation, it is very
@@ -966,6 +3070,29 @@ async fn start_relay_chain(
                     } else {
                         None
                     };
+                if let (
+                    Some(history),
+                    chain::chain_information::ChainInformationFinalityRef::Grandpa {
+                        after_finalized_block_authorities_set_id,
+                        finalized_triggered_authorities,
+                        ..
+                    },
+                ) = (
+                    &mut grandpa_sets_history,
+                    sync.as_chain_information().as_ref().finality,
+                ) {
+                    history.observe(
+                        sync.finalized_block_header().number,
+                        after_finalized_block_authorities_set_id,
+                        || {
+                            finalized_triggered_authorities
+                                .iter()
+                                .map(|a| a.public_key)
+                                .collect()
+                        },
+                    );
+                }
+
                 if let Some(set_id) = grandpa_set_id {
                     let commit_finalized_height =
                         u32::try_from(sync.finalized_block_header().number).unwrap(); // TODO: unwrap :-/
@@ -981,10 +3108,10 @@ async fn start_relay_chain(
                         .await;
                 }
 
-                let scale_encoded_header = sync.finalized_block_header().scale_encoding_vec();
+                let notification = HeaderNotification::from_header(sync.finalized_block_header());
                 // TODO: remove expired senders
                 for notif in &mut finalized_notifications {
-                    let _ = notif.send(scale_encoded_header.clone());
+                    let _ = notif.send(notification.clone());
                 }
 
                 // Since this task is verifying blocks, a heavy CPU-only operation, it is very
@@ -1023,6 +3150,7 @@ async fn start_relay_chain(
                         network_service::Event::Disconnected { peer_id, chain_index }
                             if chain_index == network_chain_index =>
                         {
+                            implausible_announces.remove(&peer_id);
                             let id = peers_source_id_map.remove(&peer_id).unwrap();
                             let (requests, _) = sync.remove_source(id);
                             requests_to_start.extend(requests);
@@ -1032,6 +3160,24 @@ async fn start_relay_chain(
                         {
                             let id = *peers_source_id_map.get(&peer_id).unwrap();
                             let decoded = announce.decode();
+
+                            if let Some(slot_duration) = slot_duration {
+                                if !is_announce_time_plausible(
+                                    &decoded.header,
+                                    slot_duration,
+                                    Host::now_from_unix_epoch() + max_announce_future_drift,
+                                ) {
+                                    let counter = implausible_announces.entry(peer_id.clone()).or_insert(0u32);
+                                    *counter = counter.saturating_add(1);
+                                    log::debug!(
+                                        target: "sync-verify",
+                                        "Ignoring announce of block #{} from {} whose slot is too far in the future (total: {})",
+                                        decoded.header.number, peer_id, *counter
+                                    );
+                                    continue;
+                                }
+                            }
+
                             // TODO: stupid to re-encode header
                             // TODO: log the outcome
                             match sync.block_announce(id, decoded.header.scale_encoding_vec(), decoded.is_best) {
@@ -1084,16 +3230,19 @@ async fn start_relay_chain(
                         ToBackground::IsNearHeadOfChainHeuristic { send_back } => {
                             let _ = send_back.send(sync.is_near_head_of_chain_heuristic());
                         }
+                        ToBackground::BlockAuthor { send_back, hash } => {
+                            let _ = send_back.send(sync.block_author(&hash));
+                        }
                         ToBackground::SubscribeFinalized { send_back } => {
                             let (tx, rx) = lossy_channel::channel();
                             finalized_notifications.push(tx);
-                            let current = sync.finalized_block_header().scale_encoding_vec();
+                            let current = HeaderNotification::from_header(sync.finalized_block_header());
                             let _ = send_back.send((current, rx));
                         }
                         ToBackground::SubscribeBest { send_back } => {
                             let (tx, rx) = lossy_channel::channel();
                             best_notifications.push(tx);
-                            let current = sync.best_block_header().scale_encoding_vec();
+                            let current = HeaderNotification::from_header(sync.best_block_header());
                             let _ = send_back.send((current, rx));
                         }
                         ToBackground::SubscribeAll { send_back, buffer_size } => {
@@ -1105,16 +3254,69 @@ async fn start_relay_chain(
                                     let best_hash = sync.best_block_hash();
                                     sync.non_finalized_blocks().map(|h| {
                                         let scale_encoding = h.scale_encoding_vec();
+                                        let hash = Host::blake2_256(&scale_encoding);
                                         BlockNotification {
-                                            is_new_best: header::hash_from_scale_encoded_header(&scale_encoding) == best_hash,
+                                            is_new_best: hash == best_hash,
                                             scale_encoded_header: scale_encoding,
                                             parent_hash: *h.parent_hash,
+                                            author: sync.block_author(&hash),
                                         }
                                     }).collect()
                                 },
                                 new_blocks,
                             });
                         }
+                        ToBackground::GrandpaState { send_back } => {
+                            let outcome = match sync.as_chain_information().as_ref().finality {
+                                chain::chain_information::ChainInformationFinalityRef::Grandpa {
+                                    after_finalized_block_authorities_set_id,
+                                    finalized_triggered_authorities,
+                                    finalized_scheduled_change,
+                                } => Some(GrandpaState {
+                                    finalized_block_hash: sync.finalized_block_header().hash(),
+                                    finalized_block_number: sync.finalized_block_header().number,
+                                    set_id: after_finalized_block_authorities_set_id,
+                                    authorities: finalized_triggered_authorities.to_vec(),
+                                    scheduled_change: finalized_scheduled_change
+                                        .map(|(n, list)| (n, list.to_vec())),
+                                }),
+                                chain::chain_information::ChainInformationFinalityRef::Outsourced => None,
+                            };
+                            let _ = send_back.send(outcome);
+                        }
+                        ToBackground::GrandpaAuthoritiesSets {
+                            send_back,
+                            block_number,
+                        } => {
+                            let _ = send_back.send(
+                                grandpa_sets_history
+                                    .as_ref()
+                                    .map(|history| history.candidates(block_number)),
+                            );
+                        }
+                        ToBackground::SerializeChainInformation { send_back } => {
+                            let _ = send_back.send(Some(
+                                smoldot::database::finalized_serialize::encode_chain(
+                                    sync.as_chain_information(),
+                                ),
+                            ));
+                        }
+                        ToBackground::DebugState { send_back } => {
+                            let _ = send_back.send(DebugState {
+                                finalized_block_hash: sync.finalized_block_header().hash(),
+                                finalized_block_number: sync.finalized_block_header().number,
+                                best_block_hash: sync.best_block_hash(),
+                                non_finalized_blocks: sync.non_finalized_blocks().map(|h| DebugBlock {
+                                    hash: h.hash(),
+                                    number: h.number,
+                                    parent_hash: *h.parent_hash,
+                                }).collect(),
+                                num_sources: sync.sources().count(),
+                                pending_block_requests: pending_block_requests.len(),
+                                pending_grandpa_requests: pending_grandpa_requests.len(),
+                                pending_storage_requests: pending_storage_requests.len(),
+                            });
+                        }
                         ToBackground::PeersAssumedKnowBlock { send_back, block_number, block_hash } => {
                             let finalized_num = sync.finalized_block_header().number;
                             let outcome = if block_number <= finalized_num {
@@ -1135,16 +3337,6 @@ async fn start_relay_chain(
                             };
                             let _ = send_back.send(outcome);
                         }
-                        ToBackground::SyncingPeers { send_back } => {
-                            let out = sync.sources()
-                                .map(|src| {
-                                    let peer_id = sync.source_user_data(src).clone();
-                                    let (height, hash) = sync.source_best_block(src);
-                                    (peer_id, height, *hash)
-                                })
-                                .collect::<Vec<_>>();
-                            let _ = send_back.send(out);
-                        }
                     };
 
                     continue;
@@ -1243,6 +3435,26 @@ async fn start_relay_chain(
     }
 }
 
+/// Returns `false` if the slot found in the header implies that the block has been produced
+/// after `max_time`, which is a duration since the UNIX epoch. Returns `true` if the block
+/// doesn't contain any slot.
+fn is_announce_time_plausible(
+    header: &header::HeaderRef,
+    slot_duration: NonZeroU64,
+    max_time: Duration,
+) -> bool {
+    let slot_number = if let Some(babe) = header.digest.babe_pre_runtime() {
+        babe.slot_number()
+    } else if let Some(aura) = header.digest.aura_pre_runtime() {
+        aura.slot_number
+    } else {
+        return true;
+    };
+
+    let slot_start_ms = slot_number.saturating_mul(slot_duration.get());
+    u128::from(slot_start_ms) <= max_time.as_millis()
+}
+
 async fn start_parachain(
     chain_information: chain::chain_information::ValidChainInformation,
     mut from_foreground: mpsc::Receiver<ToBackground>,
@@ -1290,12 +3502,12 @@ async fn start_parachain(
                     ToBackground::SubscribeFinalized { send_back } => {
                         let (tx, rx) = lossy_channel::channel();
                         core::mem::forget(tx); // TODO:
-                        let _ = send_back.send((current_finalized_block.scale_encoding_vec(), rx));
+                        let _ = send_back.send((HeaderNotification::from_header((&current_finalized_block).into()), rx));
                     }
                     ToBackground::SubscribeBest { send_back } => {
                         let (tx, rx) = lossy_channel::channel();
                         best_subscriptions.push(tx);
-                        let _ = send_back.send((current_best_block.scale_encoding_vec(), rx));
+                        let _ = send_back.send((HeaderNotification::from_header((&current_best_block).into()), rx));
                     }
                     ToBackground::SubscribeAll { send_back, buffer_size } => {
                         let (_tx, new_blocks) = mpsc::channel(buffer_size.saturating_sub(1));
@@ -1307,10 +3519,35 @@ async fn start_parachain(
 
                         // TODO: `_tx` is immediately discarded; the feature isn't actually fully implemented
                     }
-                    ToBackground::PeersAssumedKnowBlock { send_back, .. } => {
-                        let _ = send_back.send(Vec::new()); // TODO: implement this somehow /!\
+                    ToBackground::BlockAuthor { send_back, .. } => {
+                        // Parachain blocks aren't verified by the syncing service, and their
+                        // author is thus unknown.
+                        let _ = send_back.send(None);
+                    }
+                    ToBackground::GrandpaState { send_back } => {
+                        // Parachains don't use GrandPa.
+                        let _ = send_back.send(None);
+                    }
+                    ToBackground::GrandpaAuthoritiesSets { send_back, .. } => {
+                        let _ = send_back.send(None);
+                    }
+                    ToBackground::SerializeChainInformation { send_back } => {
+                        // The chain information of parachains isn't tracked.
+                        let _ = send_back.send(None);
                     }
-                    ToBackground::SyncingPeers { send_back } => {
+                    ToBackground::DebugState { send_back } => {
+                        let _ = send_back.send(DebugState {
+                            finalized_block_hash: current_finalized_block.hash(),
+                            finalized_block_number: current_finalized_block.number,
+                            best_block_hash: current_best_block.hash(),
+                            non_finalized_blocks: Vec::new(),
+                            num_sources: 0,
+                            pending_block_requests: 0,
+                            pending_grandpa_requests: 0,
+                            pending_storage_requests: 0,
+                        });
+                    }
+                    ToBackground::PeersAssumedKnowBlock { send_back, .. } => {
                         let _ = send_back.send(Vec::new()); // TODO: implement this somehow /!\
                     }
                 }
@@ -1330,12 +3567,14 @@ async fn start_parachain(
 
                 // For each relay chain block, call `ParachainHost_persisted_validation_data` in
                 // order to know where the parachains are.
+                let pvd_parameter = para::persisted_validation_data_parameters(
+                    parachain_config.parachain_id,
+                    para::OccupiedCoreAssumption::TimedOut
+                ).collect::<Vec<_>>();
                 let pvd_result = parachain_config.relay_chain_sync.recent_best_block_runtime_call(
                     "ParachainHost_persisted_validation_data",
-                    para::persisted_validation_data_parameters(
-                        parachain_config.parachain_id,
-                        para::OccupiedCoreAssumption::TimedOut
-                    )
+                    &pvd_parameter.iter().map(|p| p.as_ref()).collect::<Vec<_>>(),
+                    None,
                 ).await;
 
                 // Even if there isn't any bug, the runtime call can likely fail because the relay
@@ -1391,22 +3630,23 @@ async fn start_parachain(
 
                 // Don't do anything more if the head data matches
                 // `previous_best_head_data_hash`.
-                match (&mut previous_best_head_data_hash, blake2b(32, &[], &head_data)) {
-                    (&mut Some(ref mut h1), h2) if *h1 == h2.as_bytes() => continue,
-                    (h1 @ _, h2) => *h1 = Some(<[u8; 32]>::try_from(h2.as_bytes()).unwrap()),
+                match (&mut previous_best_head_data_hash, Host::blake2_256(&head_data)) {
+                    (&mut Some(ref mut h1), h2) if *h1 == h2 => continue,
+                    (h1 @ _, h2) => *h1 = Some(h2),
                 };
 
                 // The meaning of `head_data` depends on the parachain. It can represent
                 // anything. In practice, however, it is most of the time a block header.
                 match header::decode(&head_data) {
                     Ok(header) => {
+                        let notification = HeaderNotification::from_header(header.clone());
                         current_best_block = header.into();
 
                         // Elements in `best_subscriptions` are removed one by one and inserted
                         // back if the channel is still open.
                         for index in (0..best_subscriptions.len()).rev() {
                             let mut sender = best_subscriptions.swap_remove(index);
-                            if sender.send(head_data.to_vec()).is_ok() {
+                            if sender.send(notification.clone()).is_ok() {
                                 best_subscriptions.push(sender);
                             }
                         }
@@ -1423,30 +3663,347 @@ async fn start_parachain(
     }
 }
 
+/// Identifier of a GrandPa authorities set and the public keys of its authorities.
+/// See [`SyncService::grandpa_authorities_sets`].
+type GrandpaAuthoritiesSet = (u64, Vec<[u8; 32]>);
+
 enum ToBackground {
     /// See [`SyncService::is_near_head_of_chain_heuristic`].
     IsNearHeadOfChainHeuristic { send_back: oneshot::Sender<bool> },
     /// See [`SyncService::subscribe_finalized`].
     SubscribeFinalized {
-        send_back: oneshot::Sender<(Vec<u8>, lossy_channel::Receiver<Vec<u8>>)>,
+        send_back: oneshot::Sender<(
+            HeaderNotification,
+            lossy_channel::Receiver<HeaderNotification>,
+        )>,
     },
     /// See [`SyncService::subscribe_best`].
     SubscribeBest {
-        send_back: oneshot::Sender<(Vec<u8>, lossy_channel::Receiver<Vec<u8>>)>,
+        send_back: oneshot::Sender<(
+            HeaderNotification,
+            lossy_channel::Receiver<HeaderNotification>,
+        )>,
     },
     /// See [`SyncService::subscribe_all`].
     SubscribeAll {
         send_back: oneshot::Sender<SubscribeAll>,
         buffer_size: usize,
     },
+    /// See [`SyncService::block_author`].
+    BlockAuthor {
+        send_back: oneshot::Sender<Option<[u8; 32]>>,
+        hash: [u8; 32],
+    },
+    /// See [`SyncService::grandpa_state`].
+    GrandpaState {
+        send_back: oneshot::Sender<Option<GrandpaState>>,
+    },
+    /// See [`SyncService::grandpa_authorities_sets`].
+    GrandpaAuthoritiesSets {
+        send_back: oneshot::Sender<Option<Vec<GrandpaAuthoritiesSet>>>,
+        block_number: u64,
+    },
+    /// See [`SyncService::serialize_chain_information`].
+    SerializeChainInformation {
+        send_back: oneshot::Sender<Option<String>>,
+    },
+    /// See [`SyncService::debug_state`].
+    DebugState {
+        send_back: oneshot::Sender<DebugState>,
+    },
     /// See [`SyncService::peers_assumed_know_blocks`].
     PeersAssumedKnowBlock {
         send_back: oneshot::Sender<Vec<PeerId>>,
         block_number: u64,
         block_hash: [u8; 32],
     },
-    /// See [`SyncService::syncing_peers`].
-    SyncingPeers {
-        send_back: oneshot::Sender<Vec<(PeerId, u64, [u8; 32])>>,
-    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        compare_canonical_header, header, is_announce_time_plausible, protocol, service,
+        split_storage_query_range, AncestryQueries, AncestryQueryGuard, AncestryQueryProgress,
+        AncestryTarget, GrandpaSetsHistory, Quorum, QuorumMismatch, StateRootCheck,
+        StateRootCheckOutcome, StorageQueryErrorDetail,
+    };
+    use core::{num::NonZeroU64, time::Duration};
+    use smoldot::libp2p::{peer_id::PublicKey, PeerId};
+
+    fn is_plausible(digest: &[header::DigestItem], max_time: Duration) -> bool {
+        let header = header::HeaderRef {
+            parent_hash: &[0; 32],
+            number: 1,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::from_slice(digest).unwrap(),
+        };
+
+        is_announce_time_plausible(&header, NonZeroU64::new(6000).unwrap(), max_time)
+    }
+
+    #[test]
+    fn grandpa_sets_history_candidates() {
+        let set_ids = |history: &GrandpaSetsHistory, block_number| {
+            history
+                .candidates(block_number)
+                .into_iter()
+                .map(|(set_id, _)| set_id)
+                .collect::<Vec<_>>()
+        };
+
+        // Set 3 is in use from #100 to #120, set 4 is first seen at #150, and set 5 at #200.
+        let mut history = GrandpaSetsHistory::new(100, 3, vec![[3; 32]]);
+        history.observe(120, 3, || unreachable!());
+        history.observe(150, 4, || vec![[4; 32]]);
+        history.observe(200, 5, || vec![[5; 32]]);
+
+        // Set 3 finalizes at least up to #120. The block enacting the change to set 4 is
+        // somewhere between #121 and #150, and is itself finalized by set 3.
+        assert_eq!(set_ids(&history, 90), [3]);
+        assert_eq!(set_ids(&history, 120), [3]);
+        assert_eq!(set_ids(&history, 121), [4, 3]);
+        assert_eq!(set_ids(&history, 150), [4, 3]);
+        assert_eq!(set_ids(&history, 151), [5, 4]);
+        assert_eq!(set_ids(&history, 201), [5]);
+        assert_eq!(history.candidates(151)[1].1, [[4; 32]]);
+    }
+
+    #[test]
+    fn ancestry_query_progress() {
+        let queries = std::sync::Mutex::new(AncestryQueries {
+            next_id: 0,
+            in_progress: Default::default(),
+        });
+        let progress = |queries: &std::sync::Mutex<AncestryQueries>| {
+            queries
+                .lock()
+                .unwrap()
+                .in_progress
+                .values()
+                .map(|q| (q.verified_number, q.remaining))
+                .collect::<Vec<_>>()
+        };
+
+        let guard = AncestryQueryGuard::new(
+            &queries,
+            AncestryQueryProgress {
+                target: AncestryTarget::Number(100),
+                anchor_number: 1000,
+                verified_number: 1000,
+                remaining: 900,
+            },
+        );
+        assert_eq!(progress(&queries), [(1000, 900)]);
+
+        guard.update(936, 836);
+        assert_eq!(progress(&queries), [(936, 836)]);
+
+        drop(guard);
+        assert!(progress(&queries).is_empty());
+    }
+
+    #[test]
+    fn announce_within_drift_accepted() {
+        let digest = [header::DigestItem::AuraPreDigest(header::AuraPreDigest {
+            slot_number: 1000,
+        })];
+        assert!(is_plausible(&digest, Duration::from_secs(6000)));
+        assert!(is_plausible(&digest, Duration::from_secs(7000)));
+    }
+
+    #[test]
+    fn announce_beyond_drift_rejected() {
+        let digest = [header::DigestItem::AuraPreDigest(header::AuraPreDigest {
+            slot_number: 1000,
+        })];
+        assert!(!is_plausible(
+            &digest,
+            Duration::from_secs(6000) - Duration::from_millis(1)
+        ));
+        assert!(!is_plausible(&digest, Duration::from_secs(0)));
+
+        let digest = [header::DigestItem::AuraPreDigest(header::AuraPreDigest {
+            slot_number: u64::max_value(),
+        })];
+        assert!(!is_plausible(&digest, Duration::from_secs(6000)));
+    }
+
+    #[test]
+    fn announce_without_slot_accepted() {
+        assert!(is_plausible(&[], Duration::from_secs(0)));
+    }
+
+    fn too_large() -> StorageQueryErrorDetail {
+        StorageQueryErrorDetail::Network(service::StorageProofRequestError::ProofTooLarge {
+            max_allowed: 1024,
+        })
+    }
+
+    #[test]
+    fn split_proof_too_large() {
+        assert_eq!(
+            split_storage_query_range(&(3..10), &[too_large()]),
+            Some((3..6, 6..10))
+        );
+    }
+
+    #[test]
+    fn no_split_when_refused() {
+        let refused =
+            || StorageQueryErrorDetail::Network(service::StorageProofRequestError::Refused);
+        assert_eq!(
+            split_storage_query_range(&(0..10), &[refused(), refused(), refused()]),
+            None
+        );
+    }
+
+    #[test]
+    fn no_split_without_errors() {
+        assert_eq!(split_storage_query_range(&(0..10), &[]), None);
+    }
+
+    #[test]
+    fn no_split_single_key() {
+        assert_eq!(split_storage_query_range(&(4..5), &[too_large()]), None);
+    }
+
+    #[test]
+    fn splits_terminate() {
+        // Splitting repeatedly while every proof is too large must end with single keys.
+        let mut remaining = Vec::with_capacity(1);
+        remaining.push(0..1000);
+        let mut num_queries = 0;
+        let mut single_keys = Vec::new();
+        while let Some(range) = remaining.pop() {
+            num_queries += 1;
+            match split_storage_query_range(&range, &[too_large()]) {
+                Some((first, second)) => {
+                    remaining.push(second);
+                    remaining.push(first);
+                }
+                None => single_keys.push(range),
+            }
+        }
+
+        assert_eq!(single_keys, (0..1000).map(|n| n..n + 1).collect::<Vec<_>>());
+        assert_eq!(num_queries, 1999);
+    }
+
+    fn block_response(header: Vec<u8>) -> Option<Vec<protocol::BlockData>> {
+        Some(vec![protocol::BlockData {
+            hash: header::hash_from_scale_encoded_header(&header),
+            header: Some(header),
+            body: None,
+            indexed_body: None,
+            justification: None,
+            justifications: None,
+        }])
+    }
+
+    fn header_with_number(number: u64, state_root: [u8; 32]) -> Vec<u8> {
+        header::HeaderRef {
+            parent_hash: &[0; 32],
+            number,
+            state_root: &state_root,
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::empty(),
+        }
+        .scale_encoding_vec()
+    }
+
+    fn check(header: &[u8]) -> StateRootCheck {
+        StateRootCheck {
+            block_hash: header::hash_from_scale_encoded_header(header),
+            state_root: *header::decode(header).unwrap().state_root,
+            proof_peer: PeerId::from_public_key(&PublicKey::Ed25519([0; 32])),
+        }
+    }
+
+    #[test]
+    fn canonical_header_match() {
+        let header = header_with_number(5, [1; 32]);
+        assert_eq!(
+            compare_canonical_header(&check(&header), 5, block_response(header)),
+            StateRootCheckOutcome::Match
+        );
+    }
+
+    #[test]
+    fn canonical_header_mismatch_detected() {
+        // A peer serves a different block with the number of the finalized block.
+        let local = header_with_number(5, [1; 32]);
+        let forked = header_with_number(5, [2; 32]);
+        assert_eq!(
+            compare_canonical_header(&check(&local), 5, block_response(forked.clone())),
+            StateRootCheckOutcome::Mismatch {
+                reported_block_hash: header::hash_from_scale_encoded_header(&forked),
+                reported_state_root: [2; 32],
+            }
+        );
+    }
+
+    #[test]
+    fn canonical_header_check_inconclusive() {
+        let header = header_with_number(5, [1; 32]);
+        let check = check(&header);
+
+        // Failed request and empty response.
+        assert_eq!(
+            compare_canonical_header(&check, 5, None),
+            StateRootCheckOutcome::Inconclusive
+        );
+        assert_eq!(
+            compare_canonical_header(&check, 5, Some(Vec::new())),
+            StateRootCheckOutcome::Inconclusive
+        );
+
+        // Missing header.
+        let mut response = block_response(header).unwrap();
+        response[0].header = None;
+        assert_eq!(
+            compare_canonical_header(&check, 5, Some(response)),
+            StateRootCheckOutcome::Inconclusive
+        );
+
+        // Header with another number.
+        assert_eq!(
+            compare_canonical_header(&check, 5, block_response(header_with_number(6, [1; 32]))),
+            StateRootCheckOutcome::Inconclusive
+        );
+    }
+
+    #[test]
+    fn quorum_reached() {
+        let mut quorum = Quorum::new(3, [1; 32]);
+        for _ in 0..2 {
+            assert_eq!(quorum.add([1; 32]), Ok(()));
+            assert!(!quorum.is_reached());
+        }
+        assert_eq!(quorum.add([1; 32]), Ok(()));
+        assert!(quorum.is_reached());
+        assert_eq!(quorum.num_disagreeing(), 0);
+    }
+
+    #[test]
+    fn quorum_disagreement() {
+        let mut quorum = Quorum::new(3, [1; 32]);
+        assert_eq!(quorum.add([1; 32]), Ok(()));
+        assert_eq!(quorum.add([2; 32]), Err(QuorumMismatch));
+        assert_eq!(quorum.add([1; 32]), Ok(()));
+        assert_eq!(quorum.num_agreeing(), 2);
+        assert_eq!(quorum.num_disagreeing(), 1);
+        assert!(!quorum.is_reached());
+
+        // Disagreeing peers don't prevent other peers from confirming the value.
+        assert_eq!(quorum.add([1; 32]), Ok(()));
+        assert!(quorum.is_reached());
+    }
+
+    #[test]
+    fn quorum_insufficient_confirmations() {
+        let quorum = Quorum::new(3, ());
+        assert_eq!(quorum.num_agreeing(), 0);
+        assert!(!quorum.is_reached());
+    }
 }
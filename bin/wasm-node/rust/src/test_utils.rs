@@ -0,0 +1,630 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Utilities for testing the code of this crate deterministically.
+//!
+//! The code of this crate normally relies on the host (see the [`crate::ffi`] module) in order to
+//! know the current time and to be woken up after a certain duration. When compiling tests,
+//! [`crate::platform::Host`] is instead [`Virtual`], a virtual clock found in this module.
+//!
+//! The virtual clock only advances when [`advance`] is called, or when a future driven by
+//! [`block_on`] has nothing else to do than to wait for a timer. This makes it possible to test
+//! code that uses [`crate::platform`] without actually waiting and with a deterministic outcome.
+//!
+//! The virtual clock is thread-local. Because each test runs in its own thread, tests don't
+//! interfere with each other.
+//!
+//! In order to test the services without a network, [`MockChain`] implements the traits of
+//! [`crate::data_provider`] and answers queries from blocks and storage held in memory. The
+//! peers that answer its proof requests can be scripted with [`MockChain::script_responses`] in
+//! order to fail or to return a canned proof.
+
+use crate::{
+    data_provider,
+    platform::{ConnectionLimits, JsonRpcMessage, Platform, Transport},
+    request_trace,
+    sync_service::HeaderNotification,
+};
+
+use core::{
+    convert,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    executor,
+    future::{self, BoxFuture},
+    prelude::*,
+    stream::BoxStream,
+    task::LocalSpawnExt as _,
+};
+use smoldot::{
+    chain_spec, header,
+    network::protocol,
+    trie::{calculate_root, proof_encode},
+};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+thread_local! {
+    static CLOCK: RefCell<Clock> = RefCell::new(Clock {
+        now: Duration::new(0, 0),
+        timers: BTreeMap::new(),
+        next_timer_id: 0,
+    });
+
+    /// Spawner of the [`block_on`] call in progress, if any.
+    static SPAWNER: RefCell<Option<executor::LocalSpawner>> = RefCell::new(None);
+}
+
+/// State of the virtual clock of the current thread.
+struct Clock {
+    /// Time elapsed since the virtual clock has been created.
+    now: Duration,
+    /// List of timers that haven't fired yet, indexed by the moment when they must fire and by
+    /// a unique identifier in order to keep them in order of creation.
+    timers: BTreeMap<(Duration, u64), Box<dyn FnOnce()>>,
+    /// Identifier to assign to the next timer.
+    next_timer_id: u64,
+}
+
+/// Implementation of [`Platform`] that uses the virtual clock of the current thread.
+///
+/// Instants are represented as the time elapsed since the virtual clock has been created. The
+/// virtual clock starts at the UNIX epoch.
+pub enum Virtual {}
+
+impl Platform for Virtual {
+    type Delay = Delay;
+    type Instant = Duration;
+
+    fn now_from_unix_epoch() -> Duration {
+        Self::now()
+    }
+
+    fn now() -> Duration {
+        CLOCK.with(|clock| clock.borrow().now)
+    }
+
+    fn sleep(duration: Duration) -> Delay {
+        let (tx, rx) = oneshot::channel();
+        if duration == Duration::new(0, 0) {
+            let _ = tx.send(());
+        } else {
+            start_timer(
+                duration,
+                Box::new(move || {
+                    let _ = tx.send(());
+                }),
+            );
+        }
+        Delay { rx }
+    }
+
+    type Connection = convert::Infallible;
+
+    fn supports_transport(_: Transport) -> bool {
+        false
+    }
+
+    fn connect(
+        _: &str,
+        _: ConnectionLimits,
+    ) -> BoxFuture<'static, Result<Self::Connection, String>> {
+        Box::pin(future::ready(Err(
+            "Connections aren't supported in tests".to_owned()
+        )))
+    }
+
+    fn read_buffer(connection: &mut Self::Connection) -> BoxFuture<'_, Option<&[u8]>> {
+        match *connection {}
+    }
+
+    fn advance_read_cursor(connection: &mut Self::Connection, _: usize) {
+        match *connection {}
+    }
+
+    fn send(connection: &mut Self::Connection, _: &[u8]) {
+        match *connection {}
+    }
+
+    fn log(_: &log::Record) {}
+
+    fn throw(message: String) -> ! {
+        panic!("{}", message)
+    }
+
+    fn next_json_rpc() -> BoxFuture<'static, JsonRpcMessage> {
+        Box::pin(future::pending())
+    }
+
+    fn emit_json_rpc_response(_: &str, _: usize, _: u32) {}
+
+    fn emit_json_rpc_response_chunk(_: &str, _: usize, _: u32, _: bool) {}
+
+    fn emit_peer_event(_: &str, _: usize) {}
+
+    fn next_checkpoint_request() -> BoxFuture<'static, usize> {
+        Box::pin(future::pending())
+    }
+
+    fn emit_checkpoint(_: &str, _: usize) {}
+
+    fn http_fetch(_: &str, _: &str) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        Box::pin(future::ready(Err(
+            "HTTP requests aren't supported in tests".to_owned(),
+        )))
+    }
+
+    fn code_substitute_fetch(_: &[u8; 32]) -> BoxFuture<'static, Result<Vec<u8>, String>> {
+        Box::pin(future::ready(Err(
+            "Code substitutes can't be fetched in tests".to_owned(),
+        )))
+    }
+
+    fn sr25519_verify(_: &[u8; 64], _: &[u8], _: &[u8; 32]) -> Option<bool> {
+        None
+    }
+
+    fn sr25519_verify_deprecated(_: &[u8; 64], _: &[u8], _: &[u8; 32]) -> Option<bool> {
+        None
+    }
+
+    fn babe_vrf_verify(_: smoldot::verify::babe::VrfVerifyRef) -> Option<Result<[u8; 16], ()>> {
+        None
+    }
+
+    fn verify_custom_digest_items(_: usize, _: &[smoldot::header::CustomDigestItem]) -> bool {
+        // Tests don't configure any custom consensus engine.
+        false
+    }
+
+    fn blake2_256(data: &[u8]) -> [u8; 32] {
+        // There is no host when running tests, and the Rust implementation is always used.
+        let mut out = [0; 32];
+        out.copy_from_slice(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes());
+        out
+    }
+
+    fn zstd_decompress(_: &[u8], _: usize) -> Option<Result<Vec<u8>, ()>> {
+        None
+    }
+}
+
+/// Future returned by [`Virtual::sleep`].
+pub struct Delay {
+    rx: oneshot::Receiver<()>,
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        Future::poll(Pin::new(&mut self.rx), cx).map(|v| v.unwrap())
+    }
+}
+
+/// Registers `closure` to be called once the virtual clock has advanced by `duration`.
+pub fn start_timer(duration: Duration, closure: Box<dyn FnOnce()>) {
+    CLOCK.with(|clock| {
+        let mut clock = clock.borrow_mut();
+        let when = clock.now + duration;
+        let id = clock.next_timer_id;
+        clock.next_timer_id += 1;
+        clock.timers.insert((when, id), closure);
+    })
+}
+
+/// Advances the virtual clock by `duration`, and calls all the timers that expire in the
+/// meanwhile, in chronological order.
+pub fn advance(duration: Duration) {
+    let target = CLOCK.with(|clock| clock.borrow().now) + duration;
+
+    loop {
+        // The lock on the clock must be released before calling the timer, as the timer might
+        // itself start a new timer.
+        let timer = CLOCK.with(|clock| {
+            let mut clock = clock.borrow_mut();
+            let key = *clock
+                .timers
+                .keys()
+                .next()
+                .filter(|(when, _)| *when <= target)?;
+            clock.now = key.0;
+            clock.timers.remove(&key)
+        });
+
+        match timer {
+            Some(timer) => timer(),
+            None => break,
+        }
+    }
+
+    CLOCK.with(|clock| clock.borrow_mut().now = target);
+}
+
+/// Returns the duration after which the next timer expires, or `None` if there isn't any
+/// pending timer.
+pub fn next_timer() -> Option<Duration> {
+    CLOCK.with(|clock| {
+        let clock = clock.borrow();
+        clock
+            .timers
+            .keys()
+            .next()
+            .map(|(when, _)| *when - clock.now)
+    })
+}
+
+/// Runs the given future to completion, advancing the virtual clock whenever all the tasks are
+/// waiting.
+///
+/// `background` tasks run alongside of `future`. They are dropped when `future` finishes.
+///
+/// # Panic
+///
+/// Panics if `future` can't make any progress and there isn't any timer pending, as this would
+/// block forever.
+///
+pub fn block_on<T: 'static>(
+    future: impl Future<Output = T> + 'static,
+    background: impl IntoIterator<Item = futures::future::BoxFuture<'static, ()>>,
+) -> T {
+    let mut pool = executor::LocalPool::new();
+    let spawner = pool.spawner();
+    for task in background {
+        spawner.spawn_local(task).unwrap();
+    }
+    SPAWNER.with(|s| *s.borrow_mut() = Some(spawner.clone()));
+
+    let output = std::rc::Rc::new(RefCell::new(None));
+    spawner
+        .spawn_local({
+            let output = output.clone();
+            async move {
+                *output.borrow_mut() = Some(future.await);
+            }
+        })
+        .unwrap();
+
+    loop {
+        pool.run_until_stalled();
+
+        if let Some(output) = output.borrow_mut().take() {
+            SPAWNER.with(|s| *s.borrow_mut() = None);
+            return output;
+        }
+
+        match next_timer() {
+            Some(duration) => advance(duration),
+            None => panic!("future is blocked forever"),
+        }
+    }
+}
+
+/// Spawns a task that runs alongside of the future passed to the [`block_on`] call in progress.
+///
+/// # Panic
+///
+/// Panics if no [`block_on`] call is in progress.
+///
+pub fn spawn(task: impl Future<Output = ()> + 'static) {
+    SPAWNER.with(|s| {
+        s.borrow()
+            .as_ref()
+            .expect("no block_on call in progress")
+            .spawn_local(task)
+            .unwrap()
+    })
+}
+
+/// Returns a closure that can be passed as the `tasks_executor` of the services, and that spawns
+/// the tasks with [`spawn`].
+pub fn tasks_executor() -> Box<dyn FnMut(String, Pin<Box<dyn Future<Output = ()> + Send>>) + Send> {
+    Box::new(|_, task| spawn(task))
+}
+
+/// Chain whose blocks and storage are held in memory, used in place of the sync service.
+///
+/// All the blocks are considered as finalized, and the best block is the latest block pushed
+/// with [`MockChain::push_best_block`]. Proof requests are answered by virtual peers whose
+/// behaviour is controlled with [`MockChain::script_responses`].
+pub struct MockChain {
+    inner: Mutex<MockChainInner>,
+}
+
+struct MockChainInner {
+    /// All the blocks of the chain, indexed by hash.
+    blocks: HashMap<[u8; 32], MockBlock>,
+    /// Hash of the current best block.
+    best_block_hash: [u8; 32],
+    /// Senders of the subscriptions to the best block.
+    best_subscriptions: Vec<mpsc::UnboundedSender<HeaderNotification>>,
+    /// Responses to the next proof requests, oldest first. Requests are answered with
+    /// [`ScriptedResponse::Honest`] if empty.
+    scripted_responses: VecDeque<ScriptedResponse>,
+    /// Hashes of the blocks proofs have been requested against, oldest first.
+    proof_requests: Vec<[u8; 32]>,
+}
+
+struct MockBlock {
+    header: header::Header,
+    storage: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+/// How the peers of a [`MockChain`] answer a call proof or storage proof request.
+pub enum ScriptedResponse {
+    /// Answer with a valid proof.
+    Honest,
+    /// Answer with the given proof, whether it is valid or not.
+    Canned(Vec<Vec<u8>>),
+    /// Fail to answer, as if the request had timed out.
+    Fail,
+}
+
+impl MockChain {
+    /// Builds a new [`MockChain`] whose best block is the genesis block of the given chain.
+    pub fn new(chain_spec: &chain_spec::ChainSpec) -> Arc<Self> {
+        let header = chain_spec.genesis_block_header().clone();
+        let storage = chain_spec
+            .genesis_storage()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect();
+
+        let best_block_hash = header.hash();
+        let mut blocks = HashMap::new();
+        blocks.insert(best_block_hash, MockBlock { header, storage });
+
+        Arc::new(MockChain {
+            inner: Mutex::new(MockChainInner {
+                blocks,
+                best_block_hash,
+                best_subscriptions: Vec::new(),
+                scripted_responses: VecDeque::new(),
+                proof_requests: Vec::new(),
+            }),
+        })
+    }
+
+    /// Pushes a child of the current best block that applies the given storage changes, and
+    /// notifies the subscriptions that it is the new best block. Returns the hash of the block.
+    pub fn push_best_block(
+        &self,
+        storage_changes: impl IntoIterator<Item = (Vec<u8>, Option<Vec<u8>>)>,
+    ) -> [u8; 32] {
+        let mut inner = self.inner.lock().unwrap();
+        let parent = &inner.blocks[&inner.best_block_hash];
+
+        let mut storage = parent.storage.clone();
+        for (key, value) in storage_changes {
+            match value {
+                Some(value) => storage.insert(key, value),
+                None => storage.remove(&key),
+            };
+        }
+
+        let mut header = parent.header.clone();
+        header.parent_hash = parent.header.hash();
+        header.number += 1;
+        header.state_root = trie_root(&storage);
+
+        let hash = header.hash();
+        let notification = header_notification(&header);
+        inner.blocks.insert(hash, MockBlock { header, storage });
+        inner.best_block_hash = hash;
+        inner
+            .best_subscriptions
+            .retain(|tx| tx.unbounded_send(notification.clone()).is_ok());
+        hash
+    }
+
+    /// Sets how the next proof requests are answered. The requests that follow are answered
+    /// with [`ScriptedResponse::Honest`].
+    pub fn script_responses(&self, responses: impl IntoIterator<Item = ScriptedResponse>) {
+        self.inner
+            .lock()
+            .unwrap()
+            .scripted_responses
+            .extend(responses);
+    }
+
+    /// Returns the hashes of the blocks proofs have been requested against, oldest first.
+    pub fn proof_requests(&self) -> Vec<[u8; 32]> {
+        self.inner.lock().unwrap().proof_requests.clone()
+    }
+
+    /// Answers a proof request against the given block according to the scripted responses.
+    /// `keys` is the list of keys that an honest proof must contain, or `None` for the entire
+    /// storage.
+    fn answer_proof_request(
+        &self,
+        block_hash: &[u8; 32],
+        keys: Option<&[Vec<u8>]>,
+    ) -> Result<Vec<Vec<u8>>, ()> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.proof_requests.push(*block_hash);
+
+        match inner
+            .scripted_responses
+            .pop_front()
+            .unwrap_or(ScriptedResponse::Honest)
+        {
+            ScriptedResponse::Honest => {
+                let storage = &inner.blocks.get(block_hash).ok_or(())?.storage;
+                let keys = keys.map_or_else(
+                    || storage.keys().cloned().collect::<Vec<_>>(),
+                    |keys| keys.to_vec(),
+                );
+                Ok(proof_encode::build_proof(proof_encode::Config {
+                    entries: storage.iter().map(|(k, v)| (&k[..], &v[..])),
+                    requested_keys: keys.iter().map(|k| &k[..]),
+                }))
+            }
+            ScriptedResponse::Canned(proof) => Ok(proof),
+            ScriptedResponse::Fail => Err(()),
+        }
+    }
+}
+
+impl data_provider::BlocksProvider for MockChain {
+    fn subscribe_best(
+        &self,
+    ) -> BoxFuture<'_, Result<(HeaderNotification, BoxStream<'static, HeaderNotification>), ()>>
+    {
+        let mut inner = self.inner.lock().unwrap();
+        let current = header_notification(&inner.blocks[&inner.best_block_hash].header);
+        let (tx, rx) = mpsc::unbounded();
+        inner.best_subscriptions.push(tx);
+        Box::pin(future::ready(Ok((current, rx.boxed()))))
+    }
+
+    fn is_near_head_of_chain_heuristic(&self) -> BoxFuture<'_, bool> {
+        Box::pin(future::ready(true))
+    }
+
+    fn header_query(self: Arc<Self>, hash: [u8; 32]) -> BoxFuture<'static, Result<Vec<u8>, ()>> {
+        let inner = self.inner.lock().unwrap();
+        let header = inner
+            .blocks
+            .get(&hash)
+            .map(|block| block.header.scale_encoding_vec())
+            .ok_or(());
+        Box::pin(future::ready(header))
+    }
+}
+
+impl data_provider::StorageProvider for MockChain {
+    fn storage_query<'a>(
+        self: Arc<Self>,
+        block_hash: &'a [u8; 32],
+        _: &'a [u8; 32],
+        requested_keys: Vec<Vec<u8>>,
+    ) -> BoxFuture<'a, Result<Vec<Option<Vec<u8>>>, data_provider::StorageQueryError>> {
+        let inner = self.inner.lock().unwrap();
+        let values = match inner.blocks.get(block_hash) {
+            Some(block) => Ok(requested_keys
+                .iter()
+                .map(|key| block.storage.get(key).cloned())
+                .collect()),
+            None => Err(data_provider::StorageQueryError {
+                message: "Unknown block".to_owned(),
+                network_problem: true,
+            }),
+        };
+        Box::pin(future::ready(values))
+    }
+}
+
+impl data_provider::CallProofProvider for MockChain {
+    fn call_proof_query<'a>(
+        self: Arc<Self>,
+        _: u64,
+        config: protocol::CallProofRequestConfig<'a, data_provider::ParameterVectored<'a>>,
+        _: Option<&'a request_trace::RequestTrace>,
+    ) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ()>> {
+        // An honest call proof contains the entire storage, which is a superset of the storage
+        // entries accessed by the call.
+        Box::pin(future::ready(
+            self.answer_proof_request(&config.block_hash, None),
+        ))
+    }
+
+    fn storage_proof_query<'a>(
+        self: Arc<Self>,
+        _: u64,
+        block_hash: &'a [u8; 32],
+        keys: Vec<Vec<u8>>,
+        _: Option<&'a request_trace::RequestTrace>,
+    ) -> BoxFuture<'a, Result<Vec<Vec<u8>>, ()>> {
+        Box::pin(future::ready(
+            self.answer_proof_request(block_hash, Some(&keys)),
+        ))
+    }
+}
+
+/// Builds the [`HeaderNotification`] corresponding to the given header.
+fn header_notification(header: &header::Header) -> HeaderNotification {
+    HeaderNotification {
+        hash: header.hash(),
+        number: header.number,
+        parent_hash: header.parent_hash,
+        state_root: header.state_root,
+        scale_encoded_header: header.scale_encoding_vec(),
+    }
+}
+
+/// Calculates the Merkle value of the root of the trie containing the given storage.
+fn trie_root(storage: &BTreeMap<Vec<u8>, Vec<u8>>) -> [u8; 32] {
+    let mut calculation = calculate_root::root_merkle_value(None);
+    loop {
+        match calculation {
+            calculate_root::RootMerkleValueCalculation::Finished { hash, .. } => break hash,
+            calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
+                calculation = keys.inject(storage.keys().map(|k| k.iter().cloned()));
+            }
+            calculate_root::RootMerkleValueCalculation::StorageValue(value_request) => {
+                let key = value_request.key().collect::<Vec<u8>>();
+                calculation = value_request.inject(storage.get(&key));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::platform::{Host, Platform as _};
+    use core::time::Duration;
+
+    #[test]
+    fn delay_advances_virtual_clock() {
+        let elapsed = super::block_on(
+            async move {
+                let start = Host::now();
+                Host::sleep(Duration::from_secs(5)).await;
+                Host::sleep(Duration::from_millis(250)).await;
+                Host::now() - start
+            },
+            None,
+        );
+
+        assert_eq!(elapsed, Duration::from_millis(5250));
+    }
+
+    #[test]
+    fn timers_fire_in_order() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        for (n, ms) in [(1, 300), (2, 100), (3, 200)].iter().copied() {
+            let tx = tx.clone();
+            super::start_timer(
+                Duration::from_millis(ms),
+                Box::new(move || tx.send(n).unwrap()),
+            );
+        }
+
+        super::advance(Duration::from_millis(250));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![2, 3]);
+        super::advance(Duration::from_millis(50));
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![1]);
+        assert!(super::next_timer().is_none());
+    }
+}
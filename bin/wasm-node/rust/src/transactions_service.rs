@@ -24,12 +24,45 @@
 //! transaction on the network, it gets reported to the service, which then tries to send it to
 //! the peers the node is currently connected to. Afterwards, the service will inspect the stream
 //! of best and finalized blocks to find out whether the transaction has been included or not.
+//!
+//! If [`Config::validate_locally`] is `true`, transactions are validated against the best
+//! block before being sent out, and invalid transactions are immediately rejected. Transactions
+//! whose validation can't be performed, for example because no peer provides the necessary
+//! proof, are sent out anyway.
+//!
+//! The era of each transaction is extracted using the metadata of the runtime. Once the best
+//! block reaches the end of the mortality window of a transaction, the transaction is reported
+//! as [`TransactionStatus::Invalid`] and the service stops tracking it.
+//!
+//! When the `transaction_version` of the runtime of the best block changes, the format of the
+//! transactions might have changed as well. The transactions being tracked are then validated
+//! again against the new runtime, and the ones that are no longer valid are reported as
+//! [`TransactionStatus::Invalid`]. If [`Config::validate_locally`] is `false`, they are instead
+//! all reported as [`TransactionStatus::Dropped`].
+//!
+//! The transactions currently tracked by the service can be inspected with
+//! [`TransactionsService::pending_transactions`], and removed with
+//! [`TransactionsService::remove_transaction`].
 
-use crate::{network_service, sync_service};
+use crate::{
+    network_service,
+    platform::{Host, Platform as _},
+    runtime_service, sync_service,
+};
 
-use futures::{channel::mpsc, lock::Mutex, prelude::*};
-use smoldot::libp2p::peer_id::PeerId;
-use std::{collections::HashMap, pin::Pin, sync::Arc};
+use core::{convert::TryFrom as _, fmt};
+use futures::{
+    channel::{mpsc, oneshot},
+    lock::Mutex,
+    prelude::*,
+};
+use smoldot::{
+    chain_spec,
+    libp2p::peer_id::PeerId,
+    metadata,
+    transactions::{era, validate},
+};
+use std::{collections::HashMap, iter, pin::Pin, sync::Arc};
 
 /// Configuration for a [`TransactionsService`].
 pub struct Config {
@@ -42,12 +75,35 @@ pub struct Config {
 
     /// Service responsible for synchronizing the chain.
     pub sync_service: Arc<sync_service::SyncService>,
+
+    /// Service responsible for performing runtime calls. Used in order to validate transactions.
+    pub runtime_service: Arc<runtime_service::RuntimeService>,
+
+    /// If `true`, transactions are validated by calling the runtime of the best block before
+    /// being sent out. If `false`, transactions are sent out without any verification.
+    ///
+    /// Validating transactions locally requires downloading call proofs from the network, but
+    /// makes it possible to report invalid transactions to the user. If the validation can't be
+    /// performed, the transaction is sent out as if this was `false`.
+    pub validate_locally: bool,
+
+    /// Properties of the chain, used in order to format the amounts found in error messages.
+    pub chain_properties: chain_spec::Properties,
 }
 
 /// See [the module-level documentation](..).
 pub struct TransactionsService {
     /// Sending messages to the background task.
     to_background: Mutex<mpsc::Sender<ToBackground>>,
+
+    /// See [`Config::runtime_service`].
+    runtime_service: Arc<runtime_service::RuntimeService>,
+
+    /// See [`Config::validate_locally`].
+    validate_locally: bool,
+
+    /// See [`Config::chain_properties`].
+    chain_properties: chain_spec::Properties,
 }
 
 impl TransactionsService {
@@ -61,23 +117,58 @@ impl TransactionsService {
                 config.network_service.0,
                 config.network_service.1,
                 config.sync_service,
+                config.runtime_service.clone(),
+                config.validate_locally,
                 from_foreground,
             )),
         );
 
         TransactionsService {
             to_background: Mutex::new(to_background),
+            runtime_service: config.runtime_service,
+            validate_locally: config.validate_locally,
+            chain_properties: config.chain_properties,
         }
     }
 
     /// Adds a transaction to the service. The service will try to send it out as soon as
     /// possible.
     ///
+    /// If [`Config::validate_locally`] was `true`, the transaction is first validated against
+    /// the best block, and an error is returned if the runtime reports it as invalid. The
+    /// transaction isn't sent out in that situation. If the validation couldn't be performed,
+    /// the transaction is sent out without having been validated.
+    ///
     /// The return value of this method is a channel which will receive updates on the state
     /// of the extrinsic. The channel is closed when no new update is expected.
     ///
     /// > **Note**: Dropping the value returned does not cancel sending out the extrinsic.
-    pub async fn submit_extrinsic(&self, transaction: &[u8]) -> mpsc::Receiver<TransactionStatus> {
+    pub async fn submit_extrinsic(
+        &self,
+        transaction: &[u8],
+    ) -> Result<mpsc::Receiver<TransactionStatus>, ValidateTransactionError> {
+        let validity = if self.validate_locally {
+            match validation_outcome(validate_transaction(&self.runtime_service, transaction).await)
+            {
+                Err(ValidateTransactionError::Invalid(validate::InvalidTransaction::Payment)) => {
+                    // Try to indicate to the user the amount that the transaction requires.
+                    return Err(match self.transaction_fee(transaction).await {
+                        Some(fee) => ValidateTransactionError::CannotPayFees {
+                            fee: self.chain_properties.format_balance(fee),
+                        },
+                        None => {
+                            ValidateTransactionError::Invalid(validate::InvalidTransaction::Payment)
+                        }
+                    });
+                }
+                outcome => outcome?,
+            }
+        } else {
+            None
+        };
+
+        let era = self.transaction_era(transaction).await;
+
         // TODO: think about the size and full-ness of this channel
         let (updates_report, rx) = mpsc::channel(16);
 
@@ -86,20 +177,278 @@ impl TransactionsService {
             .await
             .send(ToBackground::SubmitTransaction {
                 transaction_bytes: transaction.to_owned(),
+                era,
+                validity,
                 updates_report,
             })
             .await
             .unwrap();
 
-        rx
+        Ok(rx)
+    }
+
+    /// Returns the list of transactions that the service is currently tracking, in other words
+    /// that have been submitted and are neither finalized nor dropped.
+    pub async fn pending_transactions(&self) -> Vec<PendingTransactionInfo> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::PendingTransactions { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Stops tracking the transaction whose hash is `hash`. Returns `false` if no such
+    /// transaction is tracked by the service.
+    ///
+    /// A [`TransactionStatus::Dropped`] update is sent to the channel returned by
+    /// [`TransactionsService::submit_extrinsic`], which is then closed.
+    ///
+    /// > **Note**: The transaction might have already been sent out to other nodes, in which
+    /// >           case it can still be included in the chain.
+    pub async fn remove_transaction(&self, hash: &[u8; 32]) -> bool {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::RemoveTransaction {
+                hash: *hash,
+                send_back,
+            })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Asks the runtime of the best block for the fees that the given transaction requires.
+    ///
+    /// Returns `None` if the fees can't be determined.
+    async fn transaction_fee(&self, transaction: &[u8]) -> Option<u128> {
+        let len = u32::try_from(transaction.len()).ok()?.to_le_bytes();
+        let output = match self
+            .runtime_service
+            .recent_best_block_runtime_call(
+                "TransactionPaymentApi_query_info",
+                &[transaction, &len],
+                None,
+            )
+            .await
+        {
+            Ok(output) => output,
+            Err(error) => {
+                log::debug!(target: "tx-service", "Failed to query transaction fees: {}", error);
+                return None;
+            }
+        };
+
+        // The return value is a `RuntimeDispatchInfo`, whose last field is the fee. The encoding
+        // of the fields that precede it depends on the version of the runtime, but the fee is
+        // always a 128 bits little-endian number on the chains supported by this client.
+        let fee = output.get(output.len().checked_sub(16)?..)?;
+        Some(u128::from_le_bytes(<[u8; 16]>::try_from(fee).unwrap()))
+    }
+
+    /// Finds the era of the given transaction using the metadata of the runtime of the best
+    /// block.
+    ///
+    /// Returns `None` if the era can't be determined, in which case the transaction is treated
+    /// as if it was immortal.
+    async fn transaction_era(&self, transaction: &[u8]) -> Option<era::Era> {
+        let metadata = self.runtime_service.clone().metadata().await.ok()?;
+        let format = match metadata::extrinsic::decode(&metadata) {
+            Ok(format) => format,
+            Err(error) => {
+                log::warn!(target: "tx-service", "Failed to decode metadata: {}", error);
+                return None;
+            }
+        };
+
+        match era::decode_extrinsic_era(transaction, &format) {
+            Ok(era) => era,
+            Err(error) => {
+                log::debug!(
+                    target: "tx-service",
+                    "Failed to find era of transaction: {}",
+                    error
+                );
+                None
+            }
+        }
+    }
+}
+
+/// Validates the given transaction against the best block.
+async fn validate_transaction(
+    runtime_service: &Arc<runtime_service::RuntimeService>,
+    transaction: &[u8],
+) -> Result<validate::ValidTransaction, ValidateTransactionError> {
+    // Note that the buffers yielded by the iterator only reference `transaction`, meaning
+    // that collecting them doesn't copy the transaction.
+    let parameter = validate::validate_transaction_runtime_parameters(
+        iter::once(transaction),
+        validate::TransactionSource::External,
+    )
+    .collect::<Vec<_>>();
+    let parameter = parameter.iter().map(|p| p.as_ref()).collect::<Vec<_>>();
+
+    let output = runtime_service
+        .recent_best_block_runtime_call_after_initialize(
+            validate::VALIDATION_FUNCTION_NAME,
+            &parameter,
+            None,
+        )
+        .await
+        .map_err(ValidateTransactionError::Call)?;
+
+    match validate::decode_validate_transaction_return_value(&output) {
+        Ok(Ok(valid)) => Ok(valid),
+        Ok(Err(validate::TransactionValidityError::Invalid(error))) => {
+            Err(ValidateTransactionError::Invalid(error))
+        }
+        Ok(Err(validate::TransactionValidityError::Unknown(error))) => {
+            Err(ValidateTransactionError::Unknown(error))
+        }
+        Err(error) => Err(ValidateTransactionError::Output(error)),
+    }
+}
+
+/// Turns the outcome of [`validate_transaction`] into the validity of a submitted transaction.
+///
+/// Returns an error if the transaction must be refused, which is the case only if the runtime
+/// has reported it as invalid. If the validation couldn't be performed, the transaction is
+/// accepted with an unknown validity, in other words as if it hadn't been validated.
+fn validation_outcome(
+    outcome: Result<validate::ValidTransaction, ValidateTransactionError>,
+) -> Result<Option<validate::ValidTransaction>, ValidateTransactionError> {
+    match outcome {
+        Ok(validity) => Ok(Some(validity)),
+        Err(error @ ValidateTransactionError::Invalid(_))
+        | Err(error @ ValidateTransactionError::CannotPayFees { .. })
+        | Err(error @ ValidateTransactionError::Unknown(_)) => Err(error),
+        Err(error @ ValidateTransactionError::Call(_))
+        | Err(error @ ValidateTransactionError::Output(_)) => {
+            log::debug!(
+                target: "tx-service",
+                "Failed to validate transaction, sending it out anyway: {}",
+                error
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Error potentially returned by [`TransactionsService::submit_extrinsic`].
+#[derive(Debug)]
+pub enum ValidateTransactionError {
+    /// The runtime has reported the transaction as invalid.
+    Invalid(validate::InvalidTransaction),
+    /// The runtime has reported the transaction as invalid because the fees can't be paid.
+    CannotPayFees {
+        /// Fees that the transaction requires, formatted with the token of the chain.
+        fee: String,
+    },
+    /// The runtime couldn't determine the validity of the transaction.
+    Unknown(validate::UnknownTransaction),
+    /// Error while performing the runtime call that validates the transaction.
+    Call(runtime_service::RuntimeCallError),
+    /// The value returned by the runtime couldn't be interpreted.
+    Output(validate::Error),
+}
+
+impl fmt::Display for ValidateTransactionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The messages below are the same as the ones in Substrate, in order for JSON-RPC
+        // clients to be able to use them interchangeably.
+        match self {
+            ValidateTransactionError::Invalid(error) => match error {
+                validate::InvalidTransaction::Call => {
+                    write!(f, "Transaction call is not expected")
+                }
+                validate::InvalidTransaction::Payment => write!(
+                    f,
+                    "Inability to pay some fees (e.g. account balance too low)"
+                ),
+                validate::InvalidTransaction::Future => {
+                    write!(f, "Transaction will be valid in the future")
+                }
+                validate::InvalidTransaction::Stale => write!(f, "Transaction is outdated"),
+                validate::InvalidTransaction::BadProof => {
+                    write!(f, "Transaction has a bad signature")
+                }
+                validate::InvalidTransaction::AncientBirthBlock => {
+                    write!(f, "Transaction has an ancient birth block")
+                }
+                validate::InvalidTransaction::ExhaustsResources => {
+                    write!(f, "Transaction would exhaust the block limits")
+                }
+                validate::InvalidTransaction::Custom(code) => {
+                    write!(f, "Custom error: {}", code)
+                }
+                validate::InvalidTransaction::BadMandatory => write!(
+                    f,
+                    "A call was labelled as mandatory, but resulted in an Error."
+                ),
+                validate::InvalidTransaction::MandatoryDispatch => write!(
+                    f,
+                    "Transaction dispatch is mandatory; transactions may not have mandatory \
+                     dispatches."
+                ),
+            },
+            ValidateTransactionError::CannotPayFees { fee } => write!(
+                f,
+                "Inability to pay some fees (e.g. account balance too low): needs {}",
+                fee
+            ),
+            ValidateTransactionError::Unknown(error) => match error {
+                validate::UnknownTransaction::CannotLookup => write!(
+                    f,
+                    "Could not lookup information required to validate the transaction"
+                ),
+                validate::UnknownTransaction::NoUnsignedValidator => write!(
+                    f,
+                    "Could not find an unsigned validator for the unsigned transaction"
+                ),
+                validate::UnknownTransaction::Custom(code) => {
+                    write!(f, "Unknown validity custom error: {}", code)
+                }
+            },
+            ValidateTransactionError::Call(error) => {
+                write!(f, "Failed to validate the transaction: {}", error)
+            }
+            ValidateTransactionError::Output(error) => {
+                write!(f, "Failed to validate the transaction: {}", error)
+            }
+        }
     }
 }
 
+/// Information about a transaction tracked by the [`TransactionsService`].
+#[derive(Debug, Clone)]
+pub struct PendingTransactionInfo {
+    /// SCALE-encoded transaction, as passed to [`TransactionsService::submit_extrinsic`].
+    pub scale_encoded: Vec<u8>,
+    /// Outcome of the validation of the transaction against the best block at the time of the
+    /// submission, or `None` if [`Config::validate_locally`] is `false`.
+    pub validity: Option<validate::ValidTransaction>,
+    /// Number of times the transaction has been sent out to at least one peer.
+    pub num_broadcasts: u32,
+    /// Number of the first block where the transaction is no longer valid, or `None` if the
+    /// transaction is immortal or its era is unknown.
+    pub death_block: Option<u64>,
+}
+
 /// Update on the state of an extrinsic in the service.
 ///
-/// > **Note**: Because this code isn't an *actual* transactions pool that leverages the runtime,
-/// >           some variants (e.g. `Invalid`) are missing compared to the ones that can be found
-/// >           in Substrate, as they can't possibly be generated by this implementation.
+/// > **Note**: Because this code isn't an *actual* transactions pool, some variants are missing
+/// >           compared to the ones that can be found in Substrate. Transactions that are found
+/// >           invalid before being sent out are reported through the return value of
+/// >           [`TransactionsService::submit_extrinsic`] rather than with an `Invalid` status.
 /// >           Additionally, an equivalent to the `Ready` state in Substrate is missing as it
 /// >           is the default state.
 #[derive(Debug)]
@@ -113,8 +462,14 @@ pub enum TransactionStatus {
     ///
     /// Contains the same block as was previously passed in [`TransactionStatus::InBlock`].
     Retracted([u8; 32]),
-    /// Transaction has been dropped because the service was full.
+    /// Transaction has been dropped because the service was full or because it has been
+    /// removed with [`TransactionsService::remove_transaction`]. No further update will be
+    /// reported.
     Dropped,
+    /// Transaction is no longer valid, either because the best block has moved past the end of
+    /// its mortality window, or because it has been found invalid after the `transaction_version`
+    /// of the runtime has changed. No further update will be reported.
+    Invalid,
     /// Transaction has been included in a finalized block.
     Finalized([u8; 32]),
     /// Transaction is not in a finalized block, but is included in the 512th ancestor of the
@@ -127,8 +482,32 @@ pub enum TransactionStatus {
 enum ToBackground {
     SubmitTransaction {
         transaction_bytes: Vec<u8>,
+        /// Era of the transaction, or `None` if unknown.
+        era: Option<era::Era>,
+        /// Outcome of the local validation, if any.
+        validity: Option<validate::ValidTransaction>,
         updates_report: mpsc::Sender<TransactionStatus>,
     },
+    PendingTransactions {
+        send_back: oneshot::Sender<Vec<PendingTransactionInfo>>,
+    },
+    RemoveTransaction {
+        hash: [u8; 32],
+        send_back: oneshot::Sender<bool>,
+    },
+}
+
+/// Transaction tracked by the background task.
+struct PendingTransaction {
+    /// Channel where to send status updates.
+    updates_report: mpsc::Sender<TransactionStatus>,
+    /// Number of the first block where the transaction is no longer valid, or `None` if the
+    /// transaction is immortal or its era is unknown.
+    death_block: Option<u64>,
+    /// See [`PendingTransactionInfo::validity`].
+    validity: Option<validate::ValidTransaction>,
+    /// See [`PendingTransactionInfo::num_broadcasts`].
+    num_broadcasts: u32,
 }
 
 /// Background task running in parallel of the front service.
@@ -136,34 +515,361 @@ async fn background_task(
     network_service: Arc<network_service::NetworkService>,
     network_chain_index: usize,
     sync_service: Arc<sync_service::SyncService>,
+    runtime_service: Arc<runtime_service::RuntimeService>,
+    validate_locally: bool,
     mut from_foreground: mpsc::Receiver<ToBackground>,
 ) {
     let mut pending_transactions =
-        HashMap::<_, _, fnv::FnvBuildHasher>::with_capacity_and_hasher(16, Default::default());
+        HashMap::<Vec<u8>, PendingTransaction, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+            16,
+            Default::default(),
+        );
+
+    let (best_block, mut best_blocks) = sync_service.subscribe_best().await;
+    let mut best_block_number = best_block.number;
+
+    // `transaction_version` of the runtime of the best block, or `None` if unknown.
+    let (runtime_version, runtime_versions) = runtime_service.subscribe_runtime_version().await;
+    let mut transaction_version = runtime_version
+        .ok()
+        .and_then(|v| v.decode().transaction_version);
+    futures::pin_mut!(runtime_versions);
 
-    // TODO: must periodically re-send transactions that aren't included in block yet
     // TODO: must download the bodies of blocks as long as we have transactions in flight
 
+    // Revalidations of the pending transactions that are in progress, started after the
+    // `transaction_version` of the runtime has changed. Each yields the bytes of the
+    // transaction, the `transaction_version` it has been started for, and the outcome.
+    let mut pending_revalidations = stream::FuturesUnordered::new();
+
     loop {
-        match from_foreground.next().await {
-            None => return,
-            Some(ToBackground::SubmitTransaction {
-                transaction_bytes,
-                mut updates_report,
-            }) => {
-                let peers_sent = network_service
-                    .clone()
-                    .announce_transaction(network_chain_index, &transaction_bytes)
-                    .await;
-
-                if !peers_sent.is_empty() {
-                    let _ = updates_report
-                        .send(TransactionStatus::Broadcast(peers_sent))
+        futures::select! {
+            message = from_foreground.next().fuse() => {
+                let message = match message {
+                    Some(m) => m,
+                    None => return,
+                };
+
+                match message {
+                    ToBackground::SubmitTransaction {
+                        transaction_bytes,
+                        era,
+                        validity,
+                        mut updates_report,
+                    } => {
+                        let death_block = era.and_then(|era| era.death(best_block_number));
+                        if death_block.map_or(false, |death| death <= best_block_number) {
+                            let _ = updates_report.send(TransactionStatus::Invalid).await;
+                            continue;
+                        }
+
+                        let peers_sent = network_service
+                            .clone()
+                            .announce_transaction(network_chain_index, &transaction_bytes)
+                            .await;
+
+                        let num_broadcasts = if !peers_sent.is_empty() {
+                            let _ = updates_report
+                                .send(TransactionStatus::Broadcast(peers_sent))
+                                .await;
+                            1
+                        } else {
+                            0
+                        };
+
+                        pending_transactions.insert(
+                            transaction_bytes,
+                            PendingTransaction {
+                                updates_report,
+                                death_block,
+                                validity,
+                                num_broadcasts,
+                            },
+                        );
+                    }
+                    ToBackground::PendingTransactions { send_back } => {
+                        let list = pending_transactions
+                            .iter()
+                            .map(|(bytes, tx)| PendingTransactionInfo {
+                                scale_encoded: bytes.clone(),
+                                validity: tx.validity.clone(),
+                                num_broadcasts: tx.num_broadcasts,
+                                death_block: tx.death_block,
+                            })
+                            .collect();
+                        let _ = send_back.send(list);
+                    }
+                    ToBackground::RemoveTransaction { hash, send_back } => {
+                        let transaction_bytes = pending_transactions
+                            .keys()
+                            .find(|bytes| Host::blake2_256(bytes) == hash)
+                            .cloned();
+
+                        let removed = if let Some(transaction_bytes) = transaction_bytes {
+                            let mut transaction =
+                                pending_transactions.remove(&transaction_bytes).unwrap();
+                            let _ = transaction
+                                .updates_report
+                                .send(TransactionStatus::Dropped)
+                                .await;
+                            true
+                        } else {
+                            false
+                        };
+
+                        let _ = send_back.send(removed);
+                    }
+                }
+            },
+
+            best_block = best_blocks.next().fuse() => {
+                let best_block = match best_block {
+                    Some(b) => b,
+                    None => {
+                        // The sync service has shut down.
+                        return;
+                    }
+                };
+
+                best_block_number = best_block.number;
+
+                // Stop tracking the transactions whose mortality window has passed. Dropping
+                // the sender closes the channel, indicating that no further update will come.
+                let expired = pending_transactions
+                    .iter()
+                    .filter(|(_, tx)| {
+                        tx.death_block
+                            .map_or(false, |death| death <= best_block_number)
+                    })
+                    .map(|(bytes, _)| bytes.clone())
+                    .collect::<Vec<_>>();
+                for transaction_bytes in expired {
+                    let mut transaction = pending_transactions.remove(&transaction_bytes).unwrap();
+                    let _ = transaction
+                        .updates_report
+                        .send(TransactionStatus::Invalid)
                         .await;
                 }
 
-                pending_transactions.insert(transaction_bytes, updates_report);
-            }
+                // The transactions that are still pending are sent out again, as the peers they
+                // have previously been sent to might have disconnected or discarded them, and
+                // new peers might have connected in the meanwhile.
+                for (transaction_bytes, transaction) in pending_transactions.iter_mut() {
+                    let peers_sent = network_service
+                        .clone()
+                        .announce_transaction(network_chain_index, transaction_bytes)
+                        .await;
+                    if !peers_sent.is_empty() {
+                        transaction.num_broadcasts += 1;
+                        let _ = transaction
+                            .updates_report
+                            .send(TransactionStatus::Broadcast(peers_sent))
+                            .await;
+                    }
+                }
+            },
+
+            new_runtime = runtime_versions.next().fuse() => {
+                let new_runtime = match new_runtime {
+                    Some(r) => r,
+                    None => {
+                        // The runtime service has shut down.
+                        return;
+                    }
+                };
+
+                // A runtime that failed to compile or that doesn't report a transaction version
+                // isn't considered as a bump. Only an actual change of value is.
+                let new_transaction_version = match new_runtime
+                    .ok()
+                    .and_then(|v| v.decode().transaction_version)
+                {
+                    Some(v) => v,
+                    None => continue,
+                };
+                let previous_transaction_version =
+                    match transaction_version.replace(new_transaction_version) {
+                        Some(v) if v != new_transaction_version => v,
+                        _ => continue,
+                    };
+
+                log::debug!(
+                    target: "tx-service",
+                    "Transaction version changed from {} to {}; revalidating {} transaction(s)",
+                    previous_transaction_version,
+                    new_transaction_version,
+                    pending_transactions.len()
+                );
+
+                // Without local validation, the transactions can't be checked against the new
+                // runtime and are dropped.
+                if !validate_locally {
+                    for (_, mut transaction) in pending_transactions.drain() {
+                        let _ = transaction
+                            .updates_report
+                            .send(TransactionStatus::Dropped)
+                            .await;
+                    }
+                    continue;
+                }
+
+                // The revalidations are performed concurrently with each other and with the
+                // rest of this task, as each of them might take a long time.
+                for transaction_bytes in pending_transactions.keys().cloned() {
+                    let runtime_service = runtime_service.clone();
+                    pending_revalidations.push(
+                        async move {
+                            let outcome =
+                                validate_transaction(&runtime_service, &transaction_bytes).await;
+                            (transaction_bytes, new_transaction_version, outcome)
+                        }
+                        .boxed(),
+                    );
+                }
+            },
+
+            revalidation = pending_revalidations.select_next_some() => {
+                let (transaction_bytes, revalidated_version, outcome) = revalidation;
+
+                // The transaction might have been removed in the meanwhile, and the outcome is
+                // obsolete if the `transaction_version` has changed again.
+                if transaction_version != Some(revalidated_version) {
+                    continue;
+                }
+                let transaction = match pending_transactions.get_mut(&transaction_bytes) {
+                    Some(tx) => tx,
+                    None => continue,
+                };
+
+                match revalidation_outcome(outcome) {
+                    Ok(Some(validity)) => transaction.validity = Some(validity),
+                    Ok(None) => {}
+                    Err(status) => {
+                        let mut transaction =
+                            pending_transactions.remove(&transaction_bytes).unwrap();
+                        let _ = transaction.updates_report.send(status).await;
+                    }
+                }
+            },
         }
     }
 }
+
+/// Interprets the outcome of the revalidation of a pending transaction after the
+/// `transaction_version` of the runtime has changed.
+///
+/// Returns `Ok(Some)` if the transaction is still valid, `Ok(None)` if it should be kept
+/// unchanged, and `Err` with the status to report if it must be removed.
+fn revalidation_outcome(
+    outcome: Result<validate::ValidTransaction, ValidateTransactionError>,
+) -> Result<Option<validate::ValidTransaction>, TransactionStatus> {
+    match outcome {
+        Ok(validity) => Ok(Some(validity)),
+        Err(ValidateTransactionError::Invalid(_))
+        | Err(ValidateTransactionError::CannotPayFees { .. }) => Err(TransactionStatus::Invalid),
+        Err(ValidateTransactionError::Unknown(_)) => Err(TransactionStatus::Dropped),
+        Err(error) => {
+            // The validation couldn't be performed. Keep the transaction rather than reporting
+            // it as invalid without a proof.
+            log::debug!(
+                target: "tx-service",
+                "Failed to revalidate transaction: {}",
+                error
+            );
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        revalidation_outcome, validation_outcome, TransactionStatus, ValidateTransactionError,
+    };
+    use crate::runtime_service::RuntimeCallError;
+    use smoldot::transactions::validate;
+
+    #[test]
+    fn valid_transaction_kept() {
+        let validity = validate::ValidTransaction {
+            priority: 12,
+            requires: Vec::new(),
+            provides: vec![vec![1, 2, 3]],
+            longevity: core::num::NonZeroU64::new(64).unwrap(),
+            propagate: true,
+        };
+
+        let outcome = validation_outcome(Ok(validity)).unwrap().unwrap();
+        assert_eq!(outcome.priority, 12);
+    }
+
+    #[test]
+    fn invalid_transaction_refused() {
+        assert!(matches!(
+            validation_outcome(Err(ValidateTransactionError::Invalid(
+                validate::InvalidTransaction::BadProof
+            ))),
+            Err(ValidateTransactionError::Invalid(
+                validate::InvalidTransaction::BadProof
+            ))
+        ));
+        assert!(matches!(
+            validation_outcome(Err(ValidateTransactionError::Unknown(
+                validate::UnknownTransaction::CannotLookup
+            ))),
+            Err(ValidateTransactionError::Unknown(_))
+        ));
+    }
+
+    #[test]
+    fn failed_validation_falls_back_to_broadcast() {
+        assert!(matches!(
+            validation_outcome(Err(ValidateTransactionError::Call(
+                RuntimeCallError::InvalidCallProof
+            ))),
+            Ok(None)
+        ));
+        assert!(matches!(
+            validation_outcome(Err(ValidateTransactionError::Output(
+                validate::Error::OutputDecodeError
+            ))),
+            Ok(None)
+        ));
+    }
+
+    #[test]
+    fn revalidation() {
+        let validity = validate::ValidTransaction {
+            priority: 3,
+            requires: Vec::new(),
+            provides: Vec::new(),
+            longevity: core::num::NonZeroU64::new(64).unwrap(),
+            propagate: true,
+        };
+        assert!(matches!(
+            revalidation_outcome(Ok(validity)),
+            Ok(Some(v)) if v.priority == 3
+        ));
+
+        assert!(matches!(
+            revalidation_outcome(Err(ValidateTransactionError::Invalid(
+                validate::InvalidTransaction::Stale
+            ))),
+            Err(TransactionStatus::Invalid)
+        ));
+        assert!(matches!(
+            revalidation_outcome(Err(ValidateTransactionError::Unknown(
+                validate::UnknownTransaction::CannotLookup
+            ))),
+            Err(TransactionStatus::Dropped)
+        ));
+
+        // Failing to perform the revalidation isn't a proof that the transaction is invalid.
+        assert!(matches!(
+            revalidation_outcome(Err(ValidateTransactionError::Call(
+                RuntimeCallError::InvalidCallProof
+            ))),
+            Ok(None)
+        ));
+    }
+}
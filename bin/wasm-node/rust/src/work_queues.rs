@@ -0,0 +1,378 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Scheduling of the network requests of a chain between classes of work.
+//!
+//! The requests that the syncing service sends to peers serve two different purposes: keeping
+//! track of the head of the chain, for example by downloading the blocks that are announced,
+//! and answering queries performed by the other services, most notably the storage and call
+//! proofs needed by the JSON-RPC service. Without any coordination, a burst of JSON-RPC requests
+//! can occupy the network and the CPU with proof downloads and verifications and delay the
+//! processing of new blocks, and vice versa.
+//!
+//! A [`WorkQueues`] limits the number of requests in progress at the same time, and each
+//! [`WorkClass`] has its own queue of requests waiting for a slot. When a slot becomes
+//! available, it is given to the first request of one of the non-empty queues. Queues are
+//! picked using weighted round-robin: when all queues are non-empty, each class obtains a number
+//! of slots proportional to its weight. A class whose queue is empty doesn't consume any slot,
+//! meaning that a class can use all the slots while the other classes are idle.
+//!
+//! [`WorkClass::Prefetch`] doesn't take part in the round-robin, and only obtains the slots that
+//! no other class is waiting for.
+
+use futures::channel::oneshot;
+use std::{
+    collections::VecDeque,
+    num::{NonZeroU32, NonZeroUsize},
+    sync::{Arc, Mutex},
+};
+
+/// Class of work a request belongs to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WorkClass {
+    /// Requests necessary to keep track of the head of the chain, such as blocks requests.
+    BlockTracking,
+    /// Requests performed on behalf of the other services of the chain, most notably in order
+    /// to answer JSON-RPC requests.
+    JsonRpc,
+    /// Speculative requests whose result might never be used, such as storage proofs downloaded
+    /// ahead of time. Only granted a slot when no request of another class is waiting.
+    Prefetch,
+}
+
+/// Number of classes that take part in the weighted round-robin. These classes come first in
+/// the order of [`WorkClass::index`].
+const NUM_WEIGHTED_CLASSES: usize = 2;
+
+impl WorkClass {
+    fn index(&self) -> usize {
+        match self {
+            WorkClass::BlockTracking => 0,
+            WorkClass::JsonRpc => 1,
+            WorkClass::Prefetch => 2,
+        }
+    }
+}
+
+/// Configuration for a [`WorkQueues`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of requests, all classes combined, in progress at the same time.
+    pub max_in_progress: NonZeroUsize,
+
+    /// Relative weight of [`WorkClass::BlockTracking`].
+    pub block_tracking_weight: NonZeroU32,
+
+    /// Relative weight of [`WorkClass::JsonRpc`].
+    pub json_rpc_weight: NonZeroU32,
+}
+
+/// See [the module-level documentation](..).
+pub struct WorkQueues {
+    /// See [`Config::max_in_progress`].
+    max_in_progress: usize,
+    /// Weight of each class taking part in the round-robin, indexed by [`WorkClass::index`].
+    weights: [u32; NUM_WEIGHTED_CLASSES],
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    /// Number of requests in progress for each class, indexed by [`WorkClass::index`].
+    in_progress: [usize; 3],
+    /// Requests waiting for a slot, for each class, indexed by [`WorkClass::index`]. The sender
+    /// is notified when the request has been granted a slot.
+    queues: [VecDeque<oneshot::Sender<()>>; 3],
+    /// Number of slots each class can still obtain before the credits of all the classes are
+    /// replenished, indexed by [`WorkClass::index`].
+    credits: [u32; NUM_WEIGHTED_CLASSES],
+}
+
+impl WorkQueues {
+    /// Initializes a new [`WorkQueues`].
+    pub fn new(config: Config) -> Self {
+        let weights = [
+            config.block_tracking_weight.get(),
+            config.json_rpc_weight.get(),
+        ];
+
+        WorkQueues {
+            max_in_progress: config.max_in_progress.get(),
+            weights,
+            inner: Mutex::new(Inner {
+                in_progress: [0, 0, 0],
+                queues: Default::default(),
+                credits: weights,
+            }),
+        }
+    }
+
+    /// Waits until a request of the given class can be started. The request is considered in
+    /// progress until the returned [`Permit`] is dropped.
+    pub async fn acquire(self: &Arc<Self>, class: WorkClass) -> Permit {
+        let rx = {
+            let mut inner = self.inner.lock().unwrap();
+            // If a slot is available, all the queues are necessarily empty, as waiting requests
+            // are granted slots as soon as they become available.
+            if inner.in_progress.iter().sum::<usize>() < self.max_in_progress {
+                inner.in_progress[class.index()] += 1;
+                return Permit {
+                    queues: self.clone(),
+                    class,
+                };
+            }
+
+            let (tx, rx) = oneshot::channel();
+            inner.queues[class.index()].push_back(tx);
+            rx
+        };
+
+        let mut waiting = Waiting {
+            queues: self,
+            class,
+            rx: Some(rx),
+        };
+
+        // The sender is only ever destroyed after having been used, or if the receiver has been
+        // destroyed.
+        let _ = waiting.rx.as_mut().unwrap().await;
+        waiting.rx = None;
+
+        Permit {
+            queues: self.clone(),
+            class,
+        }
+    }
+
+    /// Returns the number of requests of the given class currently in progress.
+    pub fn in_progress(&self, class: WorkClass) -> usize {
+        self.inner.lock().unwrap().in_progress[class.index()]
+    }
+
+    /// Returns the number of requests of the given class waiting for a slot.
+    pub fn queued(&self, class: WorkClass) -> usize {
+        self.inner.lock().unwrap().queues[class.index()]
+            .iter()
+            .filter(|tx| !tx.is_canceled())
+            .count()
+    }
+
+    /// Marks a request of the given class as no longer in progress, and grants the freed slot
+    /// to a waiting request, if any.
+    fn release(&self, class: WorkClass) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.in_progress[class.index()] -= 1;
+
+        while inner.in_progress.iter().sum::<usize>() < self.max_in_progress {
+            // Remove the requests that are no longer waiting, in order to not pick an empty
+            // queue below.
+            for queue in &mut inner.queues {
+                while queue.front().map_or(false, |tx| tx.is_canceled()) {
+                    queue.pop_front();
+                }
+            }
+
+            let index = match self.pick_queue(&mut inner) {
+                Some(i) => i,
+                None => break,
+            };
+
+            let tx = inner.queues[index].pop_front().unwrap();
+            if tx.send(()).is_ok() {
+                inner.in_progress[index] += 1;
+            }
+        }
+    }
+
+    /// Returns the index of the non-empty queue that the next slot should be given to, and
+    /// consumes one credit of this queue. Returns `None` if all the queues are empty.
+    fn pick_queue(&self, inner: &mut Inner) -> Option<usize> {
+        if inner.queues[..NUM_WEIGHTED_CLASSES]
+            .iter()
+            .all(|q| q.is_empty())
+        {
+            let prefetch = WorkClass::Prefetch.index();
+            return if inner.queues[prefetch].is_empty() {
+                None
+            } else {
+                Some(prefetch)
+            };
+        }
+
+        // If all the non-empty queues have run out of credits, replenish the credits of
+        // everyone.
+        if (0..NUM_WEIGHTED_CLASSES).all(|i| inner.queues[i].is_empty() || inner.credits[i] == 0) {
+            inner.credits = self.weights;
+        }
+
+        // Pick the non-empty queue with the most credits left. In case of equality, the class
+        // with the lowest index wins.
+        let index = (0..NUM_WEIGHTED_CLASSES)
+            .filter(|i| !inner.queues[*i].is_empty())
+            .fold(None::<usize>, |best, i| match best {
+                Some(b) if inner.credits[b] >= inner.credits[i] => Some(b),
+                _ => Some(i),
+            })
+            .unwrap();
+        inner.credits[index] -= 1;
+        Some(index)
+    }
+}
+
+/// Slot granted to a request. See [`WorkQueues::acquire`].
+#[must_use]
+pub struct Permit {
+    queues: Arc<WorkQueues>,
+    class: WorkClass,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.queues.release(self.class);
+    }
+}
+
+/// Request waiting for a slot. Releases the slot in case it has been granted but the waiting
+/// future has been destroyed before noticing.
+struct Waiting<'a> {
+    queues: &'a Arc<WorkQueues>,
+    class: WorkClass,
+    rx: Option<oneshot::Receiver<()>>,
+}
+
+impl<'a> Drop for Waiting<'a> {
+    fn drop(&mut self) {
+        if let Some(mut rx) = self.rx.take() {
+            if let Ok(Some(())) = rx.try_recv() {
+                self.queues.release(self.class);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, WorkClass, WorkQueues};
+    use core::num::{NonZeroU32, NonZeroUsize};
+    use futures::prelude::*;
+    use std::sync::Arc;
+
+    fn queues(max_in_progress: usize, block_tracking: u32, json_rpc: u32) -> Arc<WorkQueues> {
+        Arc::new(WorkQueues::new(Config {
+            max_in_progress: NonZeroUsize::new(max_in_progress).unwrap(),
+            block_tracking_weight: NonZeroU32::new(block_tracking).unwrap(),
+            json_rpc_weight: NonZeroU32::new(json_rpc).unwrap(),
+        }))
+    }
+
+    #[test]
+    fn slots_shared_according_to_weights() {
+        let queues = queues(1, 2, 1);
+
+        let first = queues.acquire(WorkClass::JsonRpc).now_or_never().unwrap();
+
+        // Queue several requests of each class while the only slot is occupied.
+        let mut waiting = Vec::new();
+        for _ in 0..3 {
+            waiting.push((
+                WorkClass::JsonRpc,
+                queues.acquire(WorkClass::JsonRpc).boxed(),
+            ));
+        }
+        for _ in 0..3 {
+            waiting.push((
+                WorkClass::BlockTracking,
+                queues.acquire(WorkClass::BlockTracking).boxed(),
+            ));
+        }
+        for (_, fut) in &mut waiting {
+            assert!(fut.as_mut().now_or_never().is_none());
+        }
+        assert_eq!(queues.queued(WorkClass::JsonRpc), 3);
+        assert_eq!(queues.queued(WorkClass::BlockTracking), 3);
+
+        // Release the slot repeatedly and note the order in which classes obtain it.
+        let mut order = Vec::new();
+        let mut permit = Some(first);
+        while !waiting.is_empty() {
+            drop(permit.take());
+            let (index, new_permit) = waiting
+                .iter_mut()
+                .enumerate()
+                .find_map(|(n, (_, fut))| fut.as_mut().now_or_never().map(|p| (n, p)))
+                .unwrap();
+            order.push(waiting.remove(index).0);
+            permit = Some(new_permit);
+        }
+
+        assert_eq!(
+            order,
+            vec![
+                WorkClass::BlockTracking,
+                WorkClass::BlockTracking,
+                WorkClass::JsonRpc,
+                WorkClass::BlockTracking,
+                WorkClass::JsonRpc,
+                WorkClass::JsonRpc,
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_waiter_releases_slot() {
+        let queues = queues(1, 1, 1);
+
+        let permit = queues
+            .acquire(WorkClass::BlockTracking)
+            .now_or_never()
+            .unwrap();
+
+        let mut cancelled = queues.acquire(WorkClass::JsonRpc).boxed();
+        assert!(cancelled.as_mut().now_or_never().is_none());
+
+        // The slot is granted to the waiting request, which is then destroyed without having
+        // been polled again.
+        drop(permit);
+        assert_eq!(queues.in_progress(WorkClass::JsonRpc), 1);
+        drop(cancelled);
+        assert_eq!(queues.in_progress(WorkClass::JsonRpc), 0);
+
+        assert!(queues
+            .acquire(WorkClass::BlockTracking)
+            .now_or_never()
+            .is_some());
+    }
+
+    #[test]
+    fn prefetch_only_gets_unwanted_slots() {
+        let queues = queues(1, 1, 1);
+
+        let permit = queues.acquire(WorkClass::JsonRpc).now_or_never().unwrap();
+
+        let mut prefetch = queues.acquire(WorkClass::Prefetch).boxed();
+        let mut json_rpc = queues.acquire(WorkClass::JsonRpc).boxed();
+        assert!(prefetch.as_mut().now_or_never().is_none());
+        assert!(json_rpc.as_mut().now_or_never().is_none());
+
+        // The prefetch request has been queued first, but the JSON-RPC request has priority.
+        drop(permit);
+        assert!(prefetch.as_mut().now_or_never().is_none());
+        let permit = json_rpc.now_or_never().unwrap();
+
+        drop(permit);
+        assert!(prefetch.now_or_never().is_some());
+    }
+}
@@ -22,6 +22,7 @@ fn main() {
             "src/network/protocol/identify.proto",
             "src/network/protocol/light.v1.proto",
             "src/libp2p/discovery/kademlia/dht.proto",
+            "src/libp2p/relay/circuit.proto",
             "src/libp2p/connection/noise/payload.proto",
             "src/libp2p/peer_id/keys.proto",
         ],
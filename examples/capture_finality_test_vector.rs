@@ -0,0 +1,328 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Records a test vector for the `finality` module from a live chain.
+//!
+//! The data is queried from a Substrate node through its HTTP JSON-RPC server, and the test
+//! vector is printed on stdout. It can then be saved in the `src/finality/test-vectors`
+//! directory.
+//!
+//! Usage:
+//!
+//! ```text
+//! capture_finality_test_vector justification <node-address> <block-hash>
+//! capture_finality_test_vector warp-sync-proof <node-address> <start-block-hash> <proof-file>
+//! ```
+//!
+//! `<node-address>` is for example `127.0.0.1:9933`. `<proof-file>` must contain a
+//! SCALE-encoded GrandPa warp sync response, as received from the network, starting from
+//! `<start-block-hash>`.
+//!
+//! The expected outcome written in the test vector is the one obtained by verifying the data
+//! with the current version of smoldot. Double-check it before saving the test vector.
+
+use serde_json::json;
+use smoldot::{
+    chain::chain_information::{ChainInformationFinality, ChainInformationFinalityRef},
+    finality::{grandpa::warp_sync, justification},
+    header::GrandpaAuthority,
+    network::protocol,
+};
+use std::{
+    convert::TryFrom as _,
+    env, fs,
+    hash::Hasher as _,
+    io::{Read as _, Write as _},
+    net::TcpStream,
+    num::NonZeroU64,
+};
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let test_vector = match args.iter().map(|a| a.as_str()).collect::<Vec<_>>()[..] {
+        ["justification", node_address, block_hash] => justification(node_address, block_hash),
+        ["warp-sync-proof", node_address, start_block_hash, proof_file] => {
+            warp_sync_proof(node_address, start_block_hash, proof_file)
+        }
+        _ => {
+            eprintln!("Usage:");
+            eprintln!("  capture_finality_test_vector justification <node-address> <block-hash>");
+            eprintln!(
+                "  capture_finality_test_vector warp-sync-proof <node-address> \
+                 <start-block-hash> <proof-file>"
+            );
+            std::process::exit(1);
+        }
+    };
+
+    println!("{}", serde_json::to_string_pretty(&test_vector).unwrap());
+}
+
+fn justification(node_address: &str, block_hash: &str) -> serde_json::Value {
+    let chain = rpc(node_address, "system_chain", json!([]));
+    let block = rpc(node_address, "chain_getBlock", json!([block_hash]));
+    let header = &block["block"]["header"];
+    let block_number = hex_number(&header["number"]);
+    let parent_hash = header["parentHash"].as_str().unwrap();
+
+    // Depending on the version of the node, the justification is either found in a
+    // `justification` field, or in a `justifications` field alongside the consensus engine id.
+    let scale_encoded_justification =
+        if let Some(justifications) = block["justifications"].as_array() {
+            justifications
+                .iter()
+                .find(|j| bytes(&j[0]) == b"FRNK")
+                .map(|j| bytes(&j[1]))
+        } else {
+            block
+                .get("justification")
+                .filter(|j| !j.is_null())
+                .map(bytes)
+        }
+        .unwrap_or_else(|| panic!("Block {} doesn't have a GrandPa justification", block_hash));
+
+    // The authorities that sign the justification of a block are the ones found in the storage
+    // of its parent. If the block enacts an authorities change, the new authorities only sign
+    // its children.
+    let (authorities_set_id, authorities) = grandpa_state(node_address, parent_hash);
+
+    let outcome = justification::decode::decode_grandpa(&scale_encoded_justification)
+        .map_err(|err| format!("{:?}", err))
+        .and_then(|justification| {
+            justification::verify::verify(justification::verify::Config {
+                justification,
+                authorities_set_id,
+                authorities_list: authorities.iter().map(|a| a.public_key),
+                randomness_seed: rand::random(),
+            })
+            .map_err(|err| format!("{:?}", err))
+        });
+
+    json!({
+        "kind": "justification",
+        "description": format!("Justification of block #{} of {}", block_number, chain.as_str().unwrap()),
+        "authoritiesSetId": authorities_set_id,
+        "authorities": authorities_json(&authorities),
+        "justification": to_hex(&scale_encoded_justification),
+        "expected": match outcome {
+            Ok(()) => json!({ "success": null }),
+            Err(err) => json!({ "error": error_name(&err) }),
+        },
+    })
+}
+
+fn warp_sync_proof(
+    node_address: &str,
+    start_block_hash: &str,
+    proof_file: &str,
+) -> serde_json::Value {
+    let chain = rpc(node_address, "system_chain", json!([]));
+    let start_header = rpc(node_address, "chain_getHeader", json!([start_block_hash]));
+    let start_block_number = hex_number(&start_header["number"]);
+    let proof = fs::read(proof_file).unwrap();
+
+    // Contrary to justifications, the authorities that apply to the children of the start block
+    // are the ones found in its own storage.
+    let (authorities_set_id, authorities) = grandpa_state(node_address, start_block_hash);
+
+    let outcome = protocol::decode_grandpa_warp_sync_response(&proof)
+        .map_err(|err| format!("{:?}", err))
+        .and_then(|response| {
+            let mut verifier = warp_sync::Verifier::new(
+                (
+                    start_block_number,
+                    <[u8; 32]>::try_from(&bytes(&json!(start_block_hash))[..]).unwrap(),
+                ),
+                ChainInformationFinalityRef::Grandpa {
+                    after_finalized_block_authorities_set_id: authorities_set_id,
+                    finalized_triggered_authorities: &authorities,
+                    finalized_scheduled_change: None,
+                },
+                response.fragments,
+                response.is_finished,
+                rand::random(),
+            );
+
+            loop {
+                match verifier.next() {
+                    Ok(warp_sync::Next::NotFinished(v)) => verifier = v,
+                    Ok(warp_sync::Next::Success {
+                        header,
+                        chain_information_finality:
+                            ChainInformationFinality::Grandpa {
+                                after_finalized_block_authorities_set_id,
+                                ..
+                            },
+                    }) => break Ok((header.hash(), after_finalized_block_authorities_set_id)),
+                    Ok(warp_sync::Next::Success { .. }) => unreachable!(),
+                    Err(err) => break Err(format!("{:?}", err.error)),
+                }
+            }
+        });
+
+    json!({
+        "kind": "warpSyncProof",
+        "description": format!(
+            "Warp sync proof of {} starting from block #{}",
+            chain.as_str().unwrap(),
+            start_block_number
+        ),
+        "startBlockNumber": start_block_number,
+        "startBlockHash": start_block_hash,
+        "authoritiesSetId": authorities_set_id,
+        "authorities": authorities_json(&authorities),
+        "proof": to_hex(&proof),
+        "expected": match outcome {
+            Ok((hash, authorities_set_id)) => json!({
+                "success": {
+                    "finalizedBlockHash": to_hex(&hash),
+                    "authoritiesSetId": authorities_set_id,
+                }
+            }),
+            Err(err) => json!({ "error": error_name(&err) }),
+        },
+    })
+}
+
+/// Returns the GrandPa authorities set id and list of authorities found in the storage of the
+/// given block.
+fn grandpa_state(node_address: &str, block_hash: &str) -> (u64, Vec<GrandpaAuthority>) {
+    let mut set_id_key = twox_128(b"Grandpa").to_vec();
+    set_id_key.extend_from_slice(&twox_128(b"CurrentSetId"));
+    let set_id = bytes(&rpc(
+        node_address,
+        "state_getStorage",
+        json!([to_hex(&set_id_key), block_hash]),
+    ));
+    let set_id = u64::from_le_bytes(<[u8; 8]>::try_from(&set_id[..]).unwrap());
+
+    // The runtime call returns a SCALE-encoded `Vec<([u8; 32], u64)>`.
+    let encoded = bytes(&rpc(
+        node_address,
+        "state_call",
+        json!(["GrandpaApi_grandpa_authorities", "0x", block_hash]),
+    ));
+    let (num_authorities, mut encoded) = decode_compact(&encoded);
+    let mut authorities = Vec::with_capacity(num_authorities);
+    for _ in 0..num_authorities {
+        authorities.push(GrandpaAuthority {
+            public_key: <[u8; 32]>::try_from(&encoded[..32]).unwrap(),
+            weight: NonZeroU64::new(u64::from_le_bytes(
+                <[u8; 8]>::try_from(&encoded[32..40]).unwrap(),
+            ))
+            .unwrap(),
+        });
+        encoded = &encoded[40..];
+    }
+    assert!(encoded.is_empty());
+
+    (set_id, authorities)
+}
+
+/// Sends a JSON-RPC request to the node and returns the `result` field of the response.
+///
+/// Uses HTTP/1.0 in order to avoid having to deal with chunked responses.
+fn rpc(node_address: &str, method: &str, params: serde_json::Value) -> serde_json::Value {
+    let request =
+        json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }).to_string();
+
+    let mut stream = TcpStream::connect(node_address)
+        .unwrap_or_else(|err| panic!("Failed to connect to {}: {}", node_address, err));
+    write!(
+        stream,
+        "POST / HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\n\r\n{}",
+        node_address,
+        request.len(),
+        request
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let body = response
+        .split_once("\r\n\r\n")
+        .map(|(_, body)| body)
+        .unwrap_or_else(|| panic!("Invalid HTTP response to {}", method));
+
+    let mut response: serde_json::Value = serde_json::from_str(body).unwrap();
+    if !response["error"].is_null() {
+        panic!("Error in response to {}: {}", method, response["error"]);
+    }
+    response["result"].take()
+}
+
+fn authorities_json(authorities: &[GrandpaAuthority]) -> serde_json::Value {
+    authorities
+        .iter()
+        .map(|a| json!({ "publicKey": to_hex(&a.public_key), "weight": a.weight.get() }))
+        .collect()
+}
+
+/// Strips the data attached to the error, in order for the test vector to be readable.
+fn error_name(error: &str) -> &str {
+    match error.find("([") {
+        Some(pos) => &error[..pos],
+        None => error,
+    }
+}
+
+/// Decodes either a hexadecimal string or an array of numbers.
+fn bytes(value: &serde_json::Value) -> Vec<u8> {
+    match value {
+        serde_json::Value::String(s) => hex::decode(s.trim_start_matches("0x")).unwrap(),
+        serde_json::Value::Array(a) => a
+            .iter()
+            .map(|b| u8::try_from(b.as_u64().unwrap()).unwrap())
+            .collect(),
+        _ => panic!("Unexpected JSON value: {}", value),
+    }
+}
+
+fn hex_number(value: &serde_json::Value) -> u64 {
+    u64::from_str_radix(value.as_str().unwrap().trim_start_matches("0x"), 16).unwrap()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn decode_compact(bytes: &[u8]) -> (usize, &[u8]) {
+    match bytes[0] & 0b11 {
+        0b00 => (usize::from(bytes[0] >> 2), &bytes[1..]),
+        0b01 => (
+            usize::from(u16::from_le_bytes([bytes[0], bytes[1]]) >> 2),
+            &bytes[2..],
+        ),
+        0b10 => (
+            usize::try_from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) >> 2)
+                .unwrap(),
+            &bytes[4..],
+        ),
+        _ => panic!("Too many authorities"),
+    }
+}
+
+fn twox_128(data: &[u8]) -> [u8; 16] {
+    let mut out = [0; 16];
+    for (seed, chunk) in out.chunks_mut(8).enumerate() {
+        let mut hasher = twox_hash::XxHash64::with_seed(seed as u64);
+        hasher.write(data);
+        chunk.copy_from_slice(&hasher.finish().to_le_bytes());
+    }
+    out
+}
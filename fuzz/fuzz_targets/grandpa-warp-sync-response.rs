@@ -0,0 +1,42 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+
+use smoldot::network::protocol;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let eager = protocol::decode_grandpa_warp_sync_response(data);
+    let lazy = protocol::EncodedGrandpaWarpSyncResponse::new(data.to_vec());
+
+    // Decoding all the fragments at once and decoding them one by one must agree.
+    match (eager, lazy) {
+        (Ok(eager), Ok(lazy)) => {
+            assert_eq!(eager.is_finished, lazy.is_finished());
+            assert_eq!(eager.fragments.len(), lazy.num_fragments());
+            for (eager, lazy) in eager.fragments.iter().zip(lazy.into_fragments()) {
+                assert_eq!(eager.header.hash(), lazy.header.hash());
+                assert_eq!(
+                    eager.justification.target_hash,
+                    lazy.justification.target_hash
+                );
+            }
+        }
+        (Err(_), Err(_)) => {}
+        _ => panic!(),
+    }
+});
@@ -0,0 +1,32 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let header = match smoldot::header::decode(data) {
+        Ok(h) => h,
+        Err(_) => return,
+    };
+
+    // Re-encoding a decoded header must produce a header that decodes and re-encodes
+    // identically.
+    let reencoded = header.scale_encoding_vec();
+    let decoded_again = smoldot::header::decode(&reencoded).unwrap();
+    assert_eq!(decoded_again.scale_encoding_vec(), reencoded);
+    for _ in decoded_again.digest.logs() {}
+});
@@ -0,0 +1,34 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let (value, encoded_len) = match smoldot::fuzzing::decode_scale_compact_usize(data) {
+        Some(v) => v,
+        None => return,
+    };
+    assert!(encoded_len <= data.len());
+
+    // The decoder accepts non-canonical encodings, so the input isn't necessarily equal to the
+    // re-encoded value. The re-encoded value must however decode back to the same value.
+    let reencoded = smoldot::fuzzing::encode_scale_compact_usize(value);
+    assert_eq!(
+        smoldot::fuzzing::decode_scale_compact_usize(&reencoded),
+        Some((value, reencoded.len()))
+    );
+});
@@ -51,3 +51,4 @@
 pub mod blocks_tree;
 pub mod chain_information;
 pub mod fork_tree;
+pub mod runtime_versions;
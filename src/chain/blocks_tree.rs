@@ -69,6 +69,7 @@ use crate::{
 use alloc::{sync::Arc, vec::Vec};
 use core::{cmp, convert::TryFrom as _, fmt, mem, num::NonZeroU64, time::Duration};
 use hashbrown::HashMap;
+use rand::SeedableRng as _;
 
 mod best_block;
 mod finality;
@@ -85,6 +86,29 @@ pub struct Config {
 
     /// Pre-allocated size of the chain, in number of non-finalized blocks.
     pub blocks_capacity: usize,
+
+    /// Identifiers of the consensus engines, unknown to smoldot, whose digest items are accepted
+    /// in block headers. Headers containing digest items of other unknown consensus engines are
+    /// refused.
+    ///
+    /// Smoldot doesn't interpret the content of these items. They can be inspected by decoding
+    /// the headers with [`header::decode_with_custom_engines`] or with
+    /// [`header::DigestRef::custom_items`], and it is the responsibility of the API user to
+    /// check them before inserting the headers.
+    pub custom_consensus_engines: Vec<[u8; 4]>,
+
+    /// If `true`, Babe secondary slot claims are accepted regardless of the types of slot claims
+    /// allowed by the Babe configuration. See
+    /// [`crate::verify::babe::VerifyConfig::relaxed_secondary_slots`].
+    pub babe_relaxed_secondary_slots: bool,
+
+    /// Implementations of the sr25519 primitives to use when verifying the signatures of block
+    /// headers. If `None`, the implementations compiled in smoldot are always used.
+    pub sr25519_hooks: Option<crate::verify::Sr25519Hooks>,
+
+    /// Seed for the randomness used when verifying justifications and Grandpa commits. See
+    /// [`crate::finality::justification::verify::Config::randomness_seed`].
+    pub randomness_seed: [u8; 32],
 }
 
 /// Holds state about the current state of the chain for the purpose of verifying headers.
@@ -146,6 +170,10 @@ impl<T> NonFinalizedTree<T> {
                 },
                 blocks: fork_tree::ForkTree::with_capacity(config.blocks_capacity),
                 current_best: None,
+                custom_consensus_engines: config.custom_consensus_engines,
+                babe_relaxed_secondary_slots: config.babe_relaxed_secondary_slots,
+                sr25519_hooks: config.sr25519_hooks,
+                randomness: rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed),
             }),
         }
     }
@@ -167,6 +195,16 @@ impl<T> NonFinalizedTree<T> {
         self.inner.as_ref().unwrap().blocks.len()
     }
 
+    /// Returns the list of consensus engines passed as [`Config::custom_consensus_engines`].
+    pub fn custom_consensus_engines(&self) -> &[[u8; 4]] {
+        &self.inner.as_ref().unwrap().custom_consensus_engines
+    }
+
+    /// Returns the value passed as [`Config::sr25519_hooks`].
+    pub fn sr25519_hooks(&self) -> Option<&crate::verify::Sr25519Hooks> {
+        self.inner.as_ref().unwrap().sr25519_hooks.as_ref()
+    }
+
     /// Returns the header of all known non-finalized blocks in the chain.
     ///
     /// The order of the blocks is unspecified.
@@ -280,6 +318,25 @@ impl<T> NonFinalizedTree<T> {
             .is_some()
     }
 
+    /// Returns the Sr25519 public key of the authority that has authored the non-finalized block
+    /// with the given hash.
+    ///
+    /// Returns `None` if the block isn't in the [`NonFinalizedTree`], or if the chain doesn't
+    /// use a consensus engine with block authors.
+    pub fn non_finalized_block_author(&self, hash: &[u8; 32]) -> Option<[u8; 32]> {
+        let inner = self.inner.as_ref().unwrap();
+        let node_index = inner.blocks.find(|b| b.hash == *hash)?;
+        inner.blocks.get(node_index).unwrap().author
+    }
+
+    /// Returns the user data of the non-finalized block with the given hash, or `None` if the
+    /// block isn't in the [`NonFinalizedTree`].
+    pub fn non_finalized_block_user_data(&self, hash: &[u8; 32]) -> Option<&T> {
+        let inner = self.inner.as_ref().unwrap();
+        let node_index = inner.blocks.find(|b| b.hash == *hash)?;
+        Some(&inner.blocks.get(node_index).unwrap().user_data)
+    }
+
     /// Gives access to a block stored by the [`NonFinalizedTree`], identified by its hash.
     pub fn non_finalized_block_by_hash(&mut self, hash: &[u8; 32]) -> Option<BlockAccess<T>> {
         let inner = self.inner.as_mut().unwrap();
@@ -325,6 +382,15 @@ struct NonFinalizedTreeInner<T> {
     /// Index within [`NonFinalizedTreeInner::blocks`] of the current best block. `None` if and
     /// only if the fork tree is empty.
     current_best: Option<fork_tree::NodeIndex>,
+    /// See [`Config::custom_consensus_engines`].
+    custom_consensus_engines: Vec<[u8; 4]>,
+    /// See [`Config::babe_relaxed_secondary_slots`].
+    babe_relaxed_secondary_slots: bool,
+    /// See [`Config::sr25519_hooks`].
+    sr25519_hooks: Option<crate::verify::Sr25519Hooks>,
+    /// Source of the seeds passed to the finality verification functions. See
+    /// [`Config::randomness_seed`].
+    randomness: rand_chacha::ChaCha20Rng,
 }
 
 /// State of the consensus of the finalized block.
@@ -375,6 +441,9 @@ struct Block<T> {
     hash: [u8; 32],
     /// Changes to the consensus made by the block.
     consensus: BlockConsensus,
+    /// Sr25519 public key of the authority that has authored the block. `None` if the chain
+    /// uses [`chain_information::ChainInformationConsensus::AllAuthorized`].
+    author: Option<[u8; 32]>,
     /// Opaque data decided by the user.
     user_data: T,
 }
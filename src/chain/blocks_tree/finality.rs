@@ -21,6 +21,7 @@ use super::*;
 use crate::finality::{grandpa, justification};
 
 use core::{cmp::Ordering, iter};
+use rand::Rng as _;
 
 impl<T> NonFinalizedTree<T> {
     /// Returns a list of blocks (by their height and hash) that need to be finalized before any
@@ -303,6 +304,8 @@ impl<T> NonFinalizedTreeInner<T> {
                 let decoded = justification::decode::decode_grandpa(&scale_encoded_justification)
                     .map_err(JustificationVerifyError::InvalidJustification)?;
 
+                let randomness_seed = self.randomness.sample(rand::distributions::Standard);
+
                 // Delegate the first step to the other function.
                 let (block_index, authorities_set_id, authorities_list) = self
                     .verify_grandpa_finality(decoded.target_hash, u64::from(decoded.target_number))
@@ -312,6 +315,7 @@ impl<T> NonFinalizedTreeInner<T> {
                     justification: decoded,
                     authorities_set_id,
                     authorities_list,
+                    randomness_seed,
                 })
                 .map_err(JustificationVerifyError::VerificationFailed)?;
 
@@ -337,6 +341,8 @@ impl<T> NonFinalizedTreeInner<T> {
         let decoded_commit = grandpa::commit::decode::decode_grandpa_commit(scale_encoded_message)
             .map_err(|_| CommitVerifyError::InvalidCommit)?;
 
+        let randomness_seed = self.randomness.sample(rand::distributions::Standard);
+
         // Delegate the first step to the other function.
         let (block_index, expected_authorities_set_id, authorities_list) = self
             .verify_grandpa_finality(
@@ -349,6 +355,7 @@ impl<T> NonFinalizedTreeInner<T> {
             commit: scale_encoded_message,
             expected_authorities_set_id,
             num_authorities: u32::try_from(authorities_list.clone().count()).unwrap(),
+            randomness_seed,
         });
 
         loop {
@@ -47,18 +47,50 @@ impl<T> NonFinalizedTree<T> {
         &mut self,
         scale_encoded_header: Vec<u8>,
         now_from_unix_epoch: Duration,
+    ) -> Result<HeaderVerifySuccess<T>, HeaderVerifyError> {
+        self.verify_header_inner(scale_encoded_header, now_from_unix_epoch, false)
+    }
+
+    /// Same as [`NonFinalizedTree::verify_header`], except that the signatures of the header
+    /// aren't verified. Instead, [`HeaderVerifySuccess::Insert::signature_checks`] is `Some`.
+    ///
+    /// The block can be inserted in the chain before its signatures have been verified. This
+    /// makes it possible to verify the headers of its children without waiting. However, if the
+    /// signatures later turn out to be invalid, the caller is responsible for discarding the
+    /// block and all its descendants.
+    pub fn verify_header_deferred(
+        &mut self,
+        scale_encoded_header: Vec<u8>,
+        now_from_unix_epoch: Duration,
+    ) -> Result<HeaderVerifySuccess<T>, HeaderVerifyError> {
+        self.verify_header_inner(scale_encoded_header, now_from_unix_epoch, true)
+    }
+
+    /// Common implementation for [`NonFinalizedTree::verify_header`] and
+    /// [`NonFinalizedTree::verify_header_deferred`].
+    fn verify_header_inner(
+        &mut self,
+        scale_encoded_header: Vec<u8>,
+        now_from_unix_epoch: Duration,
+        defer_signature_checks: bool,
     ) -> Result<HeaderVerifySuccess<T>, HeaderVerifyError> {
         let self_inner = self.inner.take().unwrap();
-        match self_inner.verify(scale_encoded_header, now_from_unix_epoch, false) {
+        match self_inner.verify(
+            scale_encoded_header,
+            now_from_unix_epoch,
+            false,
+            defer_signature_checks,
+        ) {
             VerifyOut::HeaderErr(self_inner, err) => {
                 self.inner = Some(self_inner);
                 Err(err)
             }
-            VerifyOut::HeaderOk(context, is_new_best, consensus) => {
+            VerifyOut::HeaderOk(context, is_new_best, consensus, signature_checks) => {
                 let hash = context.header.hash();
                 Ok(HeaderVerifySuccess::Insert {
                     block_height: context.header.number,
                     is_new_best,
+                    signature_checks,
                     insert: HeaderInsert {
                         chain: self,
                         context: Some(context),
@@ -98,7 +130,7 @@ impl<T> NonFinalizedTree<T> {
         match self
             .inner
             .unwrap()
-            .verify(scale_encoded_header, now_from_unix_epoch, true)
+            .verify(scale_encoded_header, now_from_unix_epoch, true, false)
         {
             VerifyOut::Body(step) => step,
             VerifyOut::HeaderDuplicate(..) | VerifyOut::HeaderOk(..) | VerifyOut::HeaderErr(..) => {
@@ -112,13 +144,19 @@ impl<T> NonFinalizedTree<T> {
 impl<T> NonFinalizedTreeInner<T> {
     /// Common implementation for both [`NonFinalizedTree::verify_header`] and
     /// [`NonFinalizedTree::verify_body`].
+    ///
+    /// `defer_signature_checks` is ignored if `full` is `true`.
     fn verify(
         self,
         scale_encoded_header: Vec<u8>,
         now_from_unix_epoch: Duration,
         full: bool,
+        defer_signature_checks: bool,
     ) -> VerifyOut<T> {
-        let decoded_header = match header::decode(&scale_encoded_header) {
+        let decoded_header = match header::decode_with_custom_engines(
+            &scale_encoded_header,
+            &self.custom_consensus_engines,
+        ) {
             Ok(h) => h,
             Err(err) => {
                 return if full {
@@ -212,6 +250,7 @@ impl<T> NonFinalizedTreeInner<T> {
             header: decoded_header.into(),
             parent_tree_index,
             consensus,
+            author: None,
         };
 
         if full {
@@ -228,7 +267,7 @@ impl<T> NonFinalizedTreeInner<T> {
                 &context.chain.finalized_block_header
             };
 
-            let result = verify::header_only::verify(verify::header_only::Config {
+            let result = verify::header_only::verify_deferred(verify::header_only::Config {
                 consensus: match (&context.chain.finalized_consensus, &context.consensus) {
                     (
                         FinalizedConsensus::Aura { slot_duration, .. },
@@ -253,6 +292,7 @@ impl<T> NonFinalizedTreeInner<T> {
                         parent_block_next_epoch: (&**next_epoch).into(),
                         slots_per_epoch: *slots_per_epoch,
                         now_from_unix_epoch,
+                        relaxed_secondary_slots: context.chain.babe_relaxed_secondary_slots,
                     },
                     (FinalizedConsensus::AllAuthorized, VerifyConsensusSpecific::AllAuthorized) => {
                         verify::header_only::ConfigConsensus::AllAuthorized
@@ -267,12 +307,21 @@ impl<T> NonFinalizedTreeInner<T> {
                 block_header: (&context.header).into(), // TODO: inefficiency ; in case of header only verify we do an extra allocation to build the context above
                 parent_block_header: parent_block_header.into(),
             })
+            .and_then(|(success, signature_checks)| {
+                if defer_signature_checks {
+                    Ok((success, Some(signature_checks)))
+                } else {
+                    signature_checks
+                        .verify_with_optional_hooks(context.chain.sr25519_hooks.as_ref())?;
+                    Ok((success, None))
+                }
+            })
             .map_err(HeaderVerifyError::VerificationFailed);
 
             match result {
-                Ok(success) => {
+                Ok((success, signature_checks)) => {
                     let (is_new_best, consensus) = context.apply_success_header(success);
-                    VerifyOut::HeaderOk(context, is_new_best, consensus)
+                    VerifyOut::HeaderOk(context, is_new_best, consensus, signature_checks)
                 }
                 Err(err) => VerifyOut::HeaderErr(context.chain, err),
             }
@@ -281,7 +330,12 @@ impl<T> NonFinalizedTreeInner<T> {
 }
 
 enum VerifyOut<T> {
-    HeaderOk(VerifyContext<T>, bool, BlockConsensus),
+    HeaderOk(
+        VerifyContext<T>,
+        bool,
+        BlockConsensus,
+        Option<verify::header_only::SignatureChecks>,
+    ),
     HeaderErr(NonFinalizedTreeInner<T>, HeaderVerifyError),
     HeaderDuplicate(NonFinalizedTreeInner<T>),
     Body(BodyVerifyStep1<T>),
@@ -292,6 +346,8 @@ struct VerifyContext<T> {
     parent_tree_index: Option<fork_tree::NodeIndex>,
     header: header::Header,
     consensus: VerifyConsensusSpecific,
+    /// Public key of the author of the block. Filled after a successful verification.
+    author: Option<[u8; 32]>,
 }
 
 impl<T> VerifyContext<T> {
@@ -303,15 +359,21 @@ impl<T> VerifyContext<T> {
             verify::header_only::Success::AllAuthorized => {
                 verify::header_body::SuccessConsensus::AllAuthorized
             }
-            verify::header_only::Success::Aura { authorities_change } => {
-                verify::header_body::SuccessConsensus::Aura { authorities_change }
-            }
+            verify::header_only::Success::Aura {
+                authorities_change,
+                authority_public_key,
+            } => verify::header_body::SuccessConsensus::Aura {
+                authorities_change,
+                authority_public_key,
+            },
             verify::header_only::Success::Babe {
                 epoch_transition_target,
                 slot_number,
+                authority_public_key,
             } => verify::header_body::SuccessConsensus::Babe {
                 epoch_transition_target,
                 slot_number,
+                authority_public_key,
             },
         };
 
@@ -333,6 +395,18 @@ impl<T> VerifyContext<T> {
             true
         };
 
+        self.author = match success_consensus {
+            verify::header_body::SuccessConsensus::AllAuthorized => None,
+            verify::header_body::SuccessConsensus::Aura {
+                authority_public_key,
+                ..
+            }
+            | verify::header_body::SuccessConsensus::Babe {
+                authority_public_key,
+                ..
+            } => Some(authority_public_key),
+        };
+
         let consensus = match (
             success_consensus,
             &self.consensus,
@@ -347,7 +421,9 @@ impl<T> VerifyContext<T> {
                 _,
             ) => BlockConsensus::AllAuthorized,
             (
-                verify::header_body::SuccessConsensus::Aura { authorities_change },
+                verify::header_body::SuccessConsensus::Aura {
+                    authorities_change, ..
+                },
                 VerifyConsensusSpecific::Aura {
                     authorities_list: parent_authorities,
                 },
@@ -355,10 +431,22 @@ impl<T> VerifyContext<T> {
                 _,
             ) => {
                 if authorities_change {
-                    todo!() // TODO: fetch from header
-                            /*BlockConsensus::Aura {
-                                authorities_list:
-                            }*/
+                    // The new list of authorities applies to the children of this block.
+                    let new_list = self
+                        .header
+                        .digest
+                        .logs()
+                        .find_map(|item| match item {
+                            header::DigestItemRef::AuraConsensus(
+                                header::AuraConsensusLogRef::AuthoritiesChange(list),
+                            ) => Some(list.map(header::AuraAuthority::from).collect::<Vec<_>>()),
+                            _ => None,
+                        })
+                        .unwrap(); // Guaranteed by the verification.
+
+                    BlockConsensus::Aura {
+                        authorities_list: Arc::new(new_list),
+                    }
                 } else {
                     BlockConsensus::Aura {
                         authorities_list: parent_authorities.clone(),
@@ -643,6 +731,7 @@ impl<T> BodyVerifyRuntimeRequired<T> {
                 parent_block_next_epoch: (&**next_epoch).into(),
                 slots_per_epoch: *slots_per_epoch,
                 now_from_unix_epoch: self.now_from_unix_epoch,
+                relaxed_secondary_slots: self.context.chain.babe_relaxed_secondary_slots,
             },
             _ => {
                 return BodyVerifyStep2::Error {
@@ -950,6 +1039,10 @@ pub enum HeaderVerifySuccess<'c, T> {
         block_height: u64,
         /// True if the verified block will become the new "best" block after being inserted.
         is_new_best: bool,
+        /// `Some` if and only if the header has been verified with
+        /// [`NonFinalizedTree::verify_header_deferred`]. Signatures of the header that remain to
+        /// be verified.
+        signature_checks: Option<verify::header_only::SignatureChecks>,
         /// Use this struct to insert the block in the chain after its successful verification.
         insert: HeaderInsert<'c, T>,
     },
@@ -977,6 +1070,7 @@ impl<'c, T> HeaderInsert<'c, T> {
                 header: context.header,
                 hash: self.hash,
                 consensus: self.consensus.take().unwrap(),
+                author: context.author,
                 user_data,
             },
         );
@@ -1059,6 +1153,7 @@ impl<T> BodyInsert<T> {
                 header: self.context.header,
                 hash: self.hash,
                 consensus: self.consensus,
+                author: self.context.author,
                 user_data,
             },
         );
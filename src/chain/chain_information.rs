@@ -212,7 +212,7 @@ impl ChainInformation {
         };
 
         Ok(ChainInformation {
-            finalized_block_header: crate::calculate_genesis_block_header(chain_spec),
+            finalized_block_header: chain_spec.genesis_block_header().clone(),
             consensus,
             finality,
         })
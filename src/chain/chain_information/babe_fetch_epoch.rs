@@ -140,6 +140,9 @@ impl Query {
                 Query::StorageRoot(StorageRoot(inner))
             }
             read_only_runtime_host::RuntimeHostVm::NextKey(inner) => Query::NextKey(NextKey(inner)),
+            read_only_runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                Query::from_inner(sig.verify_and_resume())
+            }
         }
     }
 }
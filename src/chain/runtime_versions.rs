@@ -0,0 +1,155 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! History of the runtime versions of a chain.
+//!
+//! The runtime of a chain can be upgraded, and the encoding of the storage values, events, or
+//! transactions of a block depends on the version of the runtime that was active at this block.
+//! Tools that decode historical blocks, such as indexers, thus need to know which runtime
+//! version governed each block.
+//!
+//! The [`RuntimeVersionsHistory`] type holds a table of runtime versions and of the first block
+//! at which each of them has been observed. Since a light client doesn't download all the blocks
+//! of the chain, the block at which a runtime upgrade is observed is not necessarily the block
+//! where the upgrade actually happened, and the answers of
+//! [`RuntimeVersionsHistory::spec_version_at`] should be considered as an approximation.
+//!
+//! This table can be persisted alongside the rest of the chain information using the
+//! [`finalized_serialize`](crate::database::finalized_serialize) module.
+
+use alloc::collections::BTreeMap;
+use core::ops::Bound;
+
+/// Table of the runtime spec versions of a chain and the first block at which each of them has
+/// been observed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeVersionsHistory {
+    /// Keys are block numbers, and values the spec version observed starting from this block.
+    /// Two consecutive entries never have the same spec version.
+    entries: BTreeMap<u64, u32>,
+}
+
+impl RuntimeVersionsHistory {
+    /// Builds a new empty table.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the runtime with the given spec version is active at the given block.
+    ///
+    /// Has no effect if the table already indicates this spec version at this block.
+    pub fn insert(&mut self, block_number: u64, spec_version: u32) {
+        if self.spec_version_at(block_number) == Some(spec_version) {
+            return;
+        }
+
+        // If the next entry is the same spec version, this observation moves it backwards.
+        if let Some((&next_block, &next_version)) = self
+            .entries
+            .range((Bound::Excluded(block_number), Bound::Unbounded))
+            .next()
+        {
+            if next_version == spec_version {
+                self.entries.remove(&next_block);
+            }
+        }
+
+        self.entries.insert(block_number, spec_version);
+
+        // Overwriting an existing entry might have made it identical to its predecessor.
+        if let Some((_, previous)) = self.entries.range(..block_number).next_back() {
+            if *previous == spec_version {
+                self.entries.remove(&block_number);
+            }
+        }
+    }
+
+    /// Returns the spec version of the runtime that governed the given block, or `None` if the
+    /// block is before the first entry of the table.
+    pub fn spec_version_at(&self, block_number: u64) -> Option<u32> {
+        self.entry_at(block_number).map(|(_, v)| v)
+    }
+
+    /// Returns the entry of the table that applies to the given block, in the form of the number
+    /// of the first block the spec version has been observed at and the spec version, or `None`
+    /// if the block is before the first entry of the table.
+    pub fn entry_at(&self, block_number: u64) -> Option<(u64, u32)> {
+        self.entries
+            .range(..=block_number)
+            .next_back()
+            .map(|(n, v)| (*n, *v))
+    }
+
+    /// Returns the list of entries of the table, ordered by increasing block number. Each entry
+    /// is the number of the first block the spec version has been observed at and the spec
+    /// version.
+    pub fn iter(&self) -> impl ExactSizeIterator<Item = (u64, u32)> + '_ {
+        self.entries.iter().map(|(n, v)| (*n, *v))
+    }
+
+    /// Returns `true` if the table doesn't contain any entry.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl core::iter::FromIterator<(u64, u32)> for RuntimeVersionsHistory {
+    fn from_iter<T: IntoIterator<Item = (u64, u32)>>(iter: T) -> Self {
+        let mut history = RuntimeVersionsHistory::new();
+        for (block_number, spec_version) in iter {
+            history.insert(block_number, spec_version);
+        }
+        history
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RuntimeVersionsHistory;
+
+    #[test]
+    fn basic() {
+        let mut history = RuntimeVersionsHistory::new();
+        assert_eq!(history.spec_version_at(10), None);
+
+        history.insert(0, 1);
+        history.insert(50, 1);
+        history.insert(100, 2);
+        history.insert(200, 3);
+
+        assert_eq!(history.spec_version_at(0), Some(1));
+        assert_eq!(history.spec_version_at(99), Some(1));
+        assert_eq!(history.spec_version_at(100), Some(2));
+        assert_eq!(history.spec_version_at(1000), Some(3));
+        assert_eq!(history.iter().count(), 3);
+    }
+
+    #[test]
+    fn earlier_observation() {
+        let mut history = RuntimeVersionsHistory::new();
+        history.insert(0, 1);
+        history.insert(100, 2);
+
+        // Observing the new version earlier than previously known moves the entry.
+        history.insert(80, 2);
+        assert_eq!(history.iter().collect::<Vec<_>>(), vec![(0, 1), (80, 2)]);
+
+        // Conflicting observation at the exact block of an entry.
+        history.insert(80, 1);
+        assert_eq!(history.iter().collect::<Vec<_>>(), vec![(0, 1)]);
+    }
+}
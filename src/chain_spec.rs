@@ -34,16 +34,31 @@
 //! - Multiple other miscellaneous information.
 //!
 
-use crate::chain::chain_information::{
-    BabeEpochInformation, ChainInformation, ChainInformationConsensus, ChainInformationFinality,
-    ValidChainInformation,
+use crate::{
+    chain::chain_information::{
+        BabeEpochInformation, BabeEpochInformationRef, ChainInformation, ChainInformationConsensus,
+        ChainInformationConsensusRef, ChainInformationFinality, ChainInformationFinalityRef,
+        ValidChainInformation, ValidChainInformationRef, ValidityError,
+    },
+    header,
 };
-use alloc::{string::String, vec::Vec};
-use core::{convert::TryInto as _, num::NonZeroU64};
+use alloc::{
+    boxed::Box,
+    string::{String, ToString as _},
+    vec::Vec,
+};
+use core::{convert::TryInto as _, num::NonZeroU64, time::Duration};
 
 mod light_sync_state;
 mod structs;
 
+/// Checkpoint of a chain, as found in the `lightSyncState` field of chain specs.
+///
+/// A light sync state can be obtained from a chain spec with [`ChainSpec::light_sync_state`],
+/// parsed from JSON with [`LightSyncState::from_json_bytes`], or built from an existing chain
+/// information with [`LightSyncState::from_chain_information`]. The latter, combined with
+/// [`LightSyncState::to_json`], makes it possible to generate up-to-date checkpoints to put in
+/// chain specs.
 pub struct LightSyncState {
     inner: light_sync_state::DecodedLightSyncState,
 }
@@ -68,8 +83,140 @@ fn convert_epoch(epoch: &light_sync_state::BabeEpoch) -> BabeEpochInformation {
     }
 }
 
+/// Inverse of [`convert_epoch`]. Returns `None` if the epoch is the first epoch of the chain.
+fn convert_epoch_back(
+    epoch: BabeEpochInformationRef,
+    slots_per_epoch: NonZeroU64,
+) -> Option<light_sync_state::BabeEpoch> {
+    Some(light_sync_state::BabeEpoch {
+        epoch_index: epoch.epoch_index,
+        slot_number: epoch.start_slot_number?,
+        duration: slots_per_epoch.get(),
+        authorities: epoch
+            .authorities
+            .map(|authority| light_sync_state::BabeAuthority {
+                public_key: *authority.public_key,
+                weight: authority.weight,
+            })
+            .collect(),
+        randomness: *epoch.randomness,
+        config: header::BabeNextConfig {
+            c: epoch.c,
+            allowed_slots: epoch.allowed_slots,
+        },
+    })
+}
+
 impl LightSyncState {
+    /// Parses a light sync state in the JSON format of the `lightSyncState` field of chain specs.
+    pub fn from_json_bytes(json: impl AsRef<[u8]>) -> Result<Self, LightSyncStateParseError> {
+        let state: light_sync_state::LightSyncState =
+            serde_json::from_slice(json.as_ref()).map_err(LightSyncStateParseError::Json)?;
+        let light_sync_state = LightSyncState {
+            inner: state
+                .decode()
+                .map_err(LightSyncStateParseError::InvalidField)?,
+        };
+
+        // Makes sure that `as_chain_information` will not panic.
+        light_sync_state.try_as_chain_information()?;
+        Ok(light_sync_state)
+    }
+
+    /// Builds a light sync state corresponding to the given chain information.
+    ///
+    /// Only chains using Babe and GrandPa are supported.
+    ///
+    /// > **Note**: The chain information doesn't indicate which blocks have announced the Babe
+    /// >           epochs. The light sync state indicates that the current and next epochs have
+    /// >           been announced by the parent of the finalized block and by the finalized block,
+    /// >           which is enough for the light sync state to be used by smoldot.
+    pub fn from_chain_information(
+        chain_information: ValidChainInformationRef,
+    ) -> Result<Self, FromChainInformationError> {
+        let chain_information = chain_information.as_ref();
+
+        let (current_epoch, next_epoch) = match chain_information.consensus {
+            ChainInformationConsensusRef::Babe {
+                slots_per_epoch,
+                finalized_block_epoch_information: Some(current_epoch),
+                finalized_next_epoch_transition,
+            } => (
+                convert_epoch_back(current_epoch, slots_per_epoch)
+                    .ok_or(FromChainInformationError::FirstEpoch)?,
+                convert_epoch_back(finalized_next_epoch_transition, slots_per_epoch)
+                    .ok_or(FromChainInformationError::FirstEpoch)?,
+            ),
+            ChainInformationConsensusRef::Babe {
+                finalized_block_epoch_information: None,
+                ..
+            } => return Err(FromChainInformationError::FirstEpoch),
+            _ => return Err(FromChainInformationError::NotBabe),
+        };
+
+        let (grandpa_authorities_set_id, grandpa_authorities) = match chain_information.finality {
+            ChainInformationFinalityRef::Grandpa {
+                after_finalized_block_authorities_set_id,
+                finalized_triggered_authorities,
+                finalized_scheduled_change: None,
+            } => (
+                after_finalized_block_authorities_set_id,
+                finalized_triggered_authorities
+                    .iter()
+                    .map(|authority| light_sync_state::GrandpaAuthority {
+                        public_key: authority.public_key,
+                        weight: authority.weight.get(),
+                    })
+                    .collect(),
+            ),
+            ChainInformationFinalityRef::Grandpa {
+                finalized_scheduled_change: Some(_),
+                ..
+            } => return Err(FromChainInformationError::ScheduledChange),
+            ChainInformationFinalityRef::Outsourced => {
+                return Err(FromChainInformationError::NotGrandpa)
+            }
+        };
+
+        let finalized_block_header = chain_information.finalized_block_header;
+        let finalized_block_number: u32 = finalized_block_header
+            .number
+            .try_into()
+            .map_err(|_| FromChainInformationError::BlockNumberOverflow)?;
+        // The finalized block can't be the genesis block, as the current epoch is known.
+        debug_assert_ne!(finalized_block_number, 0);
+
+        Ok(LightSyncState {
+            inner: light_sync_state::DecodedLightSyncState::new(
+                finalized_block_header.clone().into(),
+                (
+                    (
+                        *finalized_block_header.parent_hash,
+                        finalized_block_number - 1,
+                    ),
+                    current_epoch,
+                ),
+                (
+                    (finalized_block_header.hash(), finalized_block_number),
+                    next_epoch,
+                ),
+                grandpa_authorities_set_id,
+                grandpa_authorities,
+            ),
+        })
+    }
+
+    /// Turns the light sync state into JSON, in the format of the `lightSyncState` field of
+    /// chain specs.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&self.inner.encode()).unwrap()
+    }
+
     pub fn as_chain_information(&self) -> ValidChainInformation {
+        self.try_as_chain_information().unwrap() // TODO: don't unwrap /!\ should fail when parsing the chain spec instead
+    }
+
+    fn try_as_chain_information(&self) -> Result<ValidChainInformation, LightSyncStateParseError> {
         // Create a sorted list of all regular epochs that haven't been pruned from the sync state.
         let mut epochs: Vec<_> = self
             .inner
@@ -91,41 +238,68 @@ impl LightSyncState {
         epochs.dedup_by_key(|(_, epoch)| epoch.epoch_index);
 
         // Get the latest two epochs.
+        if epochs.len() < 2 {
+            return Err(LightSyncStateParseError::MissingEpochs);
+        }
         let current_epoch = &epochs[epochs.len() - 2].1;
         let next_epoch = &epochs[epochs.len() - 1].1;
 
+        let finalized_triggered_authorities = self
+            .inner
+            .grandpa_authority_set
+            .current_authorities
+            .iter()
+            .map(|authority| {
+                Some(crate::header::GrandpaAuthority {
+                    public_key: authority.public_key,
+                    weight: NonZeroU64::new(authority.weight)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or(LightSyncStateParseError::InvalidField(
+                "grandpaAuthoritySet",
+            ))?;
+
         ChainInformation {
             finalized_block_header: self.inner.finalized_block_header.clone(),
             consensus: ChainInformationConsensus::Babe {
-                slots_per_epoch: NonZeroU64::new(current_epoch.duration).unwrap(),
+                slots_per_epoch: NonZeroU64::new(current_epoch.duration)
+                    .ok_or(LightSyncStateParseError::InvalidField("babeEpochChanges"))?,
                 finalized_block_epoch_information: Some(convert_epoch(current_epoch)),
                 finalized_next_epoch_transition: convert_epoch(next_epoch),
             },
             finality: ChainInformationFinality::Grandpa {
                 after_finalized_block_authorities_set_id: self.inner.grandpa_authority_set.set_id,
-                finalized_triggered_authorities: {
-                    self.inner
-                        .grandpa_authority_set
-                        .current_authorities
-                        .iter()
-                        .map(|authority| crate::header::GrandpaAuthority {
-                            public_key: authority.public_key,
-                            weight: NonZeroU64::new(authority.weight).unwrap(),
-                        })
-                        .collect()
-                },
+                finalized_triggered_authorities,
                 finalized_scheduled_change: None, // TODO: unimplemented
             },
         }
         .try_into()
-        .unwrap() // TODO: don't unwrap /!\ should fail when parsing the chain spec instead
+        .map_err(LightSyncStateParseError::InvalidChainInformation)
     }
 }
 
 /// A configuration of a chain. Can be used to build a genesis block.
-#[derive(Clone)]
 pub struct ChainSpec {
     client_spec: structs::ClientSpec,
+
+    /// Header of the genesis block. Lazily calculated from the genesis storage the first time
+    /// it is needed, or injected through [`ChainSpec::inject_genesis_block_header`].
+    genesis_block_header: once_cell::race::OnceBox<header::Header>,
+}
+
+impl Clone for ChainSpec {
+    fn clone(&self) -> Self {
+        let genesis_block_header = once_cell::race::OnceBox::new();
+        if let Some(header) = self.genesis_block_header.get() {
+            let _ = genesis_block_header.set(Box::new(header.clone()));
+        }
+
+        ChainSpec {
+            client_spec: self.client_spec.clone(),
+            genesis_block_header,
+        }
+    }
 }
 
 impl ChainSpec {
@@ -134,10 +308,57 @@ impl ChainSpec {
             .light_sync_state
             .as_ref()
             .map(|state| LightSyncState {
-                inner: state.decode(),
+                inner: state.decode().unwrap(), // TODO: don't unwrap /!\ should fail when parsing the chain spec instead
             })
     }
 
+    /// Returns the list of runtime code substitutes, ordered by block number.
+    ///
+    /// Each substitute replaces the on-chain runtime code starting from the block with the given
+    /// number, and until the on-chain runtime code is modified (i.e. until the next runtime
+    /// upgrade). This is typically used to work around bugs in runtimes that have been deployed
+    /// on a chain.
+    pub fn code_substitutes(&self) -> impl ExactSizeIterator<Item = (u64, CodeSubstitute)> {
+        self.client_spec
+            .code_substitutes
+            .iter()
+            .map(|(block_number, substitute)| {
+                let substitute = match substitute {
+                    structs::CodeSubstitute::Inline(code) => CodeSubstitute::Code(&code.0),
+                    structs::CodeSubstitute::Hash(hash) => CodeSubstitute::Hash(&hash.hash.0),
+                };
+                (*block_number, substitute)
+            })
+    }
+
+    /// Returns `true` if the Babe secondary slot claims of the chain must be accepted
+    /// regardless of the types of slot claims allowed by the Babe configuration. This is the
+    /// case of some test networks, and is indicated by the `babeRelaxedSecondarySlots` field of
+    /// the chain specification.
+    ///
+    /// See [`crate::verify::babe::VerifyConfig::relaxed_secondary_slots`].
+    pub fn babe_relaxed_secondary_slots(&self) -> bool {
+        self.client_spec.babe_relaxed_secondary_slots
+    }
+
+    /// Returns the duration, in milliseconds, of a consensus slot of the chain, as indicated by
+    /// the `slotDuration` field of the chain specification, if any.
+    ///
+    /// Contrary to Aura, the Babe slot duration isn't part of the chain information. This field
+    /// makes it possible to know it without executing the runtime of the genesis block.
+    pub fn slot_duration(&self) -> Option<NonZeroU64> {
+        self.client_spec.slot_duration
+    }
+
+    /// Returns the maximum amount of time by which the slot of a block announce is allowed to be
+    /// in the future, as indicated by the `maxAnnounceFutureDrift` field (in milliseconds) of the
+    /// chain specification, if any.
+    pub fn max_announce_future_drift(&self) -> Option<Duration> {
+        self.client_spec
+            .max_announce_future_drift
+            .map(Duration::from_millis)
+    }
+
     /// Parse JSON content into a [`ChainSpec`].
     pub fn from_json_bytes(json: impl AsRef<[u8]>) -> Result<Self, ParseError> {
         let client_spec: structs::ClientSpec =
@@ -148,7 +369,10 @@ impl ChainSpec {
             let structs::Genesis::Raw(genesis) = &client_spec.genesis;
             genesis.children_default.is_empty()
         });
-        Ok(ChainSpec { client_spec })
+        Ok(ChainSpec {
+            client_spec,
+            genesis_block_header: once_cell::race::OnceBox::new(),
+        })
     }
 
     /// Returns the name of the chain. Meant to be displayed to the user.
@@ -229,6 +453,35 @@ impl ChainSpec {
         genesis.top.get(key).map(|value| &value.0[..])
     }
 
+    /// Returns the header of the genesis block.
+    ///
+    /// Calculating the header requires calculating the Merkle value of the root of the genesis
+    /// storage trie, which is expensive. This calculation is only performed the first time this
+    /// method is called, and the result is cached afterwards.
+    ///
+    /// See also [`crate::calculate_genesis_block_header`].
+    pub fn genesis_block_header(&self) -> &header::Header {
+        self.genesis_block_header
+            .get_or_init(|| Box::new(crate::calculate_genesis_block_header(self)))
+    }
+
+    /// Sets the value that [`ChainSpec::genesis_block_header`] returns, without calculating it
+    /// from the genesis storage.
+    ///
+    /// This is useful if the header of the genesis block has been calculated ahead of time, for
+    /// example at compile time or during a previous execution. It is the responsibility of the
+    /// caller to make sure that the header matches the genesis storage.
+    ///
+    /// Returns back the header if a value had already been calculated or injected.
+    pub fn inject_genesis_block_header(
+        &self,
+        header: header::Header,
+    ) -> Result<(), header::Header> {
+        self.genesis_block_header
+            .set(Box::new(header))
+            .map_err(|header| *header)
+    }
+
     /// Returns a list of arbitrary properties contained in the chain specs, such as the name of
     /// the token or the number of decimals.
     ///
@@ -243,15 +496,129 @@ impl ChainSpec {
             .map(|p| p.get())
             .unwrap_or("{}")
     }
+
+    /// Returns the properties of [`ChainSpec::properties`] that have a well-known meaning, such
+    /// as the token symbol and number of decimals.
+    ///
+    /// Properties that are missing or that can't be parsed are set to `None`. Each property is
+    /// parsed individually, meaning that a malformed property doesn't affect the other ones.
+    pub fn known_properties(&self) -> Properties {
+        let properties =
+            serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(self.properties())
+                .unwrap_or_default();
+
+        fn field<T: serde::de::DeserializeOwned>(
+            properties: &serde_json::Map<String, serde_json::Value>,
+            name: &str,
+        ) -> Option<T> {
+            serde_json::from_value(properties.get(name)?.clone()).ok()
+        }
+
+        Properties {
+            token_decimals: field::<structs::SingleOrList<u8>>(&properties, "tokenDecimals")
+                .and_then(|d| d.into_first()),
+            token_symbol: field::<structs::SingleOrList<String>>(&properties, "tokenSymbol")
+                .and_then(|s| s.into_first()),
+            ss58_format: field(&properties, "ss58Format"),
+        }
+    }
+}
+
+/// Properties of a chain that have a well-known meaning. See [`ChainSpec::known_properties`].
+///
+/// If the chain has multiple tokens, only the native token is described.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Properties {
+    /// Number of decimals of the native token. For example, if this is 10, then a balance of
+    /// `10_000_000_000` corresponds to one unit of the token.
+    pub token_decimals: Option<u8>,
+    /// Symbol of the native token, for example `DOT`.
+    pub token_symbol: Option<String>,
+    /// Prefix to use when encoding addresses with the SS58 format.
+    pub ss58_format: Option<u16>,
+}
+
+impl Properties {
+    /// Formats the given amount of the smallest subdivision of the native token into a string
+    /// meant to be shown to the user, for example `1.5 DOT`.
+    ///
+    /// If the number of decimals is unknown, the amount is printed as is. Trailing zeroes of the
+    /// fractional part are omitted.
+    pub fn format_balance(&self, amount: u128) -> String {
+        let decimals = usize::from(self.token_decimals.unwrap_or(0));
+
+        let mut digits = amount.to_string();
+        if digits.len() <= decimals {
+            digits.insert_str(0, &"0".repeat(decimals + 1 - digits.len()));
+        }
+
+        let (integer, fraction) = digits.split_at(digits.len() - decimals);
+        let fraction = fraction.trim_end_matches('0');
+
+        let mut out = String::from(integer);
+        if !fraction.is_empty() {
+            out.push('.');
+            out.push_str(fraction);
+        }
+        if let Some(symbol) = &self.token_symbol {
+            out.push(' ');
+            out.push_str(symbol);
+        }
+        out
+    }
+}
+
+/// Runtime code substitute. See [`ChainSpec::code_substitutes`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CodeSubstitute<'a> {
+    /// The runtime code is included in the chain specs.
+    Code(&'a [u8]),
+    /// The runtime code isn't included in the chain specs, and must be obtained by other means.
+    /// Contains the BLAKE2-256 hash of the runtime code, which must be verified once the code
+    /// has been obtained.
+    Hash(&'a [u8; 32]),
 }
 
 /// Error that can happen when parsing a chain spec JSON.
 #[derive(Debug, derive_more::Display)]
 pub struct ParseError(serde_json::Error);
 
+/// Error that can happen when parsing a light sync state JSON.
+#[derive(Debug, derive_more::Display)]
+pub enum LightSyncStateParseError {
+    /// The JSON is invalid or doesn't have the expected format.
+    #[display(fmt = "{}", _0)]
+    Json(serde_json::Error),
+    /// Failed to decode the field with the given name.
+    #[display(fmt = "Failed to decode field `{}`", _0)]
+    InvalidField(&'static str),
+    /// The light sync state doesn't contain the current and next Babe epochs.
+    MissingEpochs,
+    /// The chain information found in the light sync state is invalid.
+    #[display(fmt = "{}", _0)]
+    InvalidChainInformation(ValidityError),
+}
+
+/// Error that can happen when calling [`LightSyncState::from_chain_information`].
+#[derive(Debug, derive_more::Display)]
+pub enum FromChainInformationError {
+    /// The chain doesn't use the Babe consensus engine.
+    NotBabe,
+    /// The chain doesn't use the GrandPa finality engine.
+    NotGrandpa,
+    /// The finalized block belongs to the first Babe epoch, which the light sync state format
+    /// can't represent.
+    FirstEpoch,
+    /// A change in the list of GrandPa authorities has been scheduled but isn't triggered yet,
+    /// which the light sync state format can't represent.
+    ScheduledChange,
+    /// The number of the finalized block doesn't fit in 32 bits.
+    BlockNumberOverflow,
+}
+
 #[cfg(test)]
 mod tests {
-    use super::ChainSpec;
+    use super::{ChainSpec, LightSyncState};
 
     #[test]
     fn can_decode_polkadot_genesis() {
@@ -259,4 +626,145 @@ mod tests {
         let specs = ChainSpec::from_json_bytes(&spec).unwrap();
         assert_eq!(specs.id(), "polkadot");
     }
+
+    #[test]
+    fn known_properties() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        let specs = ChainSpec::from_json_bytes(&spec).unwrap();
+        assert_eq!(
+            specs.known_properties(),
+            super::Properties {
+                token_decimals: Some(12),
+                token_symbol: Some("DOT".into()),
+                ss58_format: Some(0),
+            }
+        );
+    }
+
+    #[test]
+    fn known_properties_parsed_individually() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        let mut spec = serde_json::from_slice::<serde_json::Value>(spec).unwrap();
+        spec["properties"] = serde_json::json!({
+            "tokenDecimals": [10, 12],
+            "tokenSymbol": 5,
+            "ss58Format": 42,
+            "foo": "bar",
+        });
+        let specs = ChainSpec::from_json_bytes(&serde_json::to_vec(&spec).unwrap()).unwrap();
+        assert_eq!(
+            specs.known_properties(),
+            super::Properties {
+                token_decimals: Some(10),
+                token_symbol: None,
+                ss58_format: Some(42),
+            }
+        );
+    }
+
+    #[test]
+    fn format_balance() {
+        let properties = super::Properties {
+            token_decimals: Some(10),
+            token_symbol: Some("DOT".into()),
+            ss58_format: None,
+        };
+        assert_eq!(properties.format_balance(15_000_000_000), "1.5 DOT");
+        assert_eq!(properties.format_balance(20_000_000_000), "2 DOT");
+        assert_eq!(properties.format_balance(1), "0.0000000001 DOT");
+        assert_eq!(properties.format_balance(0), "0 DOT");
+
+        let properties = super::Properties::default();
+        assert_eq!(properties.format_balance(1234), "1234");
+    }
+
+    #[test]
+    fn code_substitutes() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        assert_eq!(
+            ChainSpec::from_json_bytes(&spec)
+                .unwrap()
+                .code_substitutes()
+                .count(),
+            0
+        );
+
+        let mut json: serde_json::Value = serde_json::from_slice(spec).unwrap();
+        json["codeSubstitutes"] = serde_json::json!({
+            "1000": "0x0102",
+            "25": { "hash": format!("0x{}", "ab".repeat(32)) },
+        });
+        let specs = ChainSpec::from_json_bytes(&serde_json::to_vec(&json).unwrap()).unwrap();
+        assert_eq!(
+            specs.code_substitutes().collect::<Vec<_>>(),
+            vec![
+                (25, super::CodeSubstitute::Hash(&[0xab; 32])),
+                (1000, super::CodeSubstitute::Code(&[1, 2])),
+            ]
+        );
+    }
+
+    #[test]
+    fn slot_duration_and_announce_drift() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        let specs = ChainSpec::from_json_bytes(&spec).unwrap();
+        assert!(specs.slot_duration().is_none());
+        assert!(specs.max_announce_future_drift().is_none());
+
+        let mut json: serde_json::Value = serde_json::from_slice(spec).unwrap();
+        json["slotDuration"] = serde_json::json!(6000);
+        json["maxAnnounceFutureDrift"] = serde_json::json!(12000);
+        let specs = ChainSpec::from_json_bytes(&serde_json::to_vec(&json).unwrap()).unwrap();
+        assert_eq!(specs.slot_duration().unwrap().get(), 6000);
+        assert_eq!(
+            specs.max_announce_future_drift(),
+            Some(core::time::Duration::from_secs(12))
+        );
+
+        json["slotDuration"] = serde_json::json!(0);
+        assert!(ChainSpec::from_json_bytes(&serde_json::to_vec(&json).unwrap()).is_err());
+    }
+
+    #[test]
+    fn genesis_block_header_cached_and_injectable() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        let specs = ChainSpec::from_json_bytes(&spec).unwrap();
+
+        let calculated = crate::calculate_genesis_block_header(&specs);
+        assert_eq!(specs.genesis_block_header().hash(), calculated.hash());
+        assert_eq!(
+            specs.clone().genesis_block_header().hash(),
+            calculated.hash()
+        );
+        assert!(specs
+            .inject_genesis_block_header(calculated.clone())
+            .is_err());
+
+        let other_specs = ChainSpec::from_json_bytes(&spec).unwrap();
+        let mut injected = calculated;
+        injected.number = 1;
+        assert!(other_specs
+            .inject_genesis_block_header(injected.clone())
+            .is_ok());
+        assert_eq!(other_specs.genesis_block_header().hash(), injected.hash());
+    }
+
+    #[test]
+    fn light_sync_state_json_round_trip() {
+        let spec = &include_bytes!("../bin/westend.json")[..];
+        let specs = ChainSpec::from_json_bytes(&spec).unwrap();
+        let chain_information = specs.light_sync_state().unwrap().as_chain_information();
+
+        let exported = LightSyncState::from_chain_information((&chain_information).into()).unwrap();
+        let json = exported.to_json();
+
+        let imported = LightSyncState::from_json_bytes(&json).unwrap();
+        assert_eq!(imported.to_json(), json);
+
+        let imported = imported.as_chain_information();
+        assert_eq!(
+            imported.as_ref().finalized_block_header.hash(),
+            chain_information.as_ref().finalized_block_header.hash()
+        );
+    }
 }
@@ -17,7 +17,7 @@
 
 use crate::header::BabeNextConfig;
 
-use alloc::{collections::BTreeMap, format, string::String, vec::Vec};
+use alloc::{collections::BTreeMap, format, string::String, vec, vec::Vec};
 use parity_scale_codec::{Decode, DecodeAll as _, Encode};
 use serde::{Deserialize, Serialize};
 
@@ -32,20 +32,24 @@ pub(super) struct LightSyncState {
 }
 
 impl LightSyncState {
-    pub(super) fn decode(&self) -> DecodedLightSyncState {
+    /// Decodes the SCALE-encoded fields. On failure, returns the name of the field that couldn't
+    /// be decoded.
+    pub(super) fn decode(&self) -> Result<DecodedLightSyncState, &'static str> {
         let grandpa_authority_set_slice = &self.grandpa_authority_set.0[..];
         let babe_epoch_changes_slice = &self.babe_epoch_changes.0[..];
 
         let decoded = DecodedLightSyncState {
             babe_finalized_block_weight: self.babe_finalized_block_weight,
             finalized_block_header: crate::header::decode(&self.finalized_block_header.0[..])
-                .unwrap()
+                .map_err(|_| "finalizedBlockHeader")?
                 .into(),
-            grandpa_authority_set: AuthoritySet::decode_all(&grandpa_authority_set_slice).unwrap(),
-            babe_epoch_changes: EpochChanges::decode_all(&babe_epoch_changes_slice).unwrap(),
+            grandpa_authority_set: AuthoritySet::decode_all(&grandpa_authority_set_slice)
+                .map_err(|_| "grandpaAuthoritySet")?,
+            babe_epoch_changes: EpochChanges::decode_all(&babe_epoch_changes_slice)
+                .map_err(|_| "babeEpochChanges")?,
         };
 
-        decoded
+        Ok(decoded)
     }
 }
 
@@ -57,6 +61,79 @@ pub(super) struct DecodedLightSyncState {
     pub(super) grandpa_authority_set: AuthoritySet,
 }
 
+impl DecodedLightSyncState {
+    /// Builds a light sync state that contains only the given Babe epochs and GrandPa
+    /// authorities.
+    ///
+    /// Each epoch is accompanied with the hash and number of the block it is considered to have
+    /// been announced by. The current epoch must come before the next epoch.
+    ///
+    /// Since the weight of the finalized block isn't known, it is set to 0.
+    pub(super) fn new(
+        finalized_block_header: crate::header::Header,
+        current_epoch: (([u8; 32], u32), BabeEpoch),
+        next_epoch: (([u8; 32], u32), BabeEpoch),
+        grandpa_authorities_set_id: u64,
+        grandpa_authorities: Vec<GrandpaAuthority>,
+    ) -> Self {
+        let epoch_header = |epoch: &BabeEpoch| {
+            PersistedEpochHeader::Regular(EpochHeader {
+                start_slot: epoch.slot_number,
+                end_slot: epoch.slot_number.saturating_add(epoch.duration),
+            })
+        };
+
+        let next_epoch_node = ForkTreeNode {
+            hash: (next_epoch.0).0,
+            number: (next_epoch.0).1,
+            data: epoch_header(&next_epoch.1),
+            children: Vec::new(),
+        };
+        let current_epoch_node = ForkTreeNode {
+            hash: (current_epoch.0).0,
+            number: (current_epoch.0).1,
+            data: epoch_header(&current_epoch.1),
+            children: vec![next_epoch_node],
+        };
+
+        let mut epochs = BTreeMap::new();
+        epochs.insert(current_epoch.0, PersistedEpoch::Regular(current_epoch.1));
+        epochs.insert(next_epoch.0, PersistedEpoch::Regular(next_epoch.1));
+
+        DecodedLightSyncState {
+            babe_epoch_changes: EpochChanges {
+                inner: ForkTree {
+                    roots: vec![current_epoch_node],
+                    best_finalized_number: Some((next_epoch.0).1),
+                },
+                epochs,
+            },
+            babe_finalized_block_weight: 0,
+            finalized_block_header,
+            grandpa_authority_set: AuthoritySet {
+                current_authorities: grandpa_authorities,
+                set_id: grandpa_authorities_set_id,
+                pending_standard_changes: ForkTree {
+                    roots: Vec::new(),
+                    best_finalized_number: None,
+                },
+                pending_forced_changes: Vec::new(),
+                authority_set_changes: Vec::new(),
+            },
+        }
+    }
+
+    /// Turns the light sync state into its JSON representation.
+    pub(super) fn encode(&self) -> LightSyncState {
+        LightSyncState {
+            babe_epoch_changes: HexString(self.babe_epoch_changes.encode()),
+            babe_finalized_block_weight: self.babe_finalized_block_weight,
+            finalized_block_header: HexString(self.finalized_block_header.scale_encoding_vec()),
+            grandpa_authority_set: HexString(self.grandpa_authority_set.encode()),
+        }
+    }
+}
+
 #[derive(Debug, Decode, Encode)]
 pub(super) struct EpochChanges {
     inner: ForkTree<PersistedEpochHeader>,
@@ -23,6 +23,7 @@
 use super::light_sync_state::LightSyncState;
 
 use alloc::{boxed::Box, collections::BTreeMap, format, string::String, vec::Vec};
+use core::num::NonZeroU64;
 use fnv::FnvBuildHasher;
 use hashbrown::HashSet;
 use serde::{Deserialize, Serialize};
@@ -47,10 +48,40 @@ pub(super) struct ClientSpec {
     pub(super) consensus_engine: (),
     pub(super) genesis: Genesis,
     pub(super) light_sync_state: Option<LightSyncState>,
+    /// Keys are block numbers, from which the on-chain runtime code is replaced with the value.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub(super) code_substitutes: BTreeMap<u64, CodeSubstitute>,
+    /// Smoldot-specific. See [`super::ChainSpec::babe_relaxed_secondary_slots`].
+    #[serde(default, skip_serializing_if = "core::ops::Not::not")]
+    pub(super) babe_relaxed_secondary_slots: bool,
+    /// Smoldot-specific. See [`super::ChainSpec::slot_duration`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) slot_duration: Option<NonZeroU64>,
+    /// Smoldot-specific. See [`super::ChainSpec::max_announce_future_drift`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(super) max_announce_future_drift: Option<u64>,
     #[serde(flatten)]
     pub(super) parachain: Option<ChainSpecParachain>,
 }
 
+/// Some chains have multiple tokens, in which case the properties contain a list. The first
+/// element of the list corresponds to the native token.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub(super) enum SingleOrList<T> {
+    Single(T),
+    List(Vec<T>),
+}
+
+impl<T> SingleOrList<T> {
+    pub(super) fn into_first(self) -> Option<T> {
+        match self {
+            SingleOrList::Single(v) => Some(v),
+            SingleOrList::List(list) => list.into_iter().next(),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub(super) struct ChainSpecParachain {
@@ -123,6 +154,22 @@ impl<'a> serde::Deserialize<'a> for HexString {
     }
 }
 
+/// Value in [`ClientSpec::code_substitutes`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(super) enum CodeSubstitute {
+    /// Runtime code included in the chain specs.
+    Inline(HexString),
+    /// Runtime code to obtain from elsewhere, identified by its BLAKE2-256 hash.
+    Hash(CodeSubstituteHash),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct CodeSubstituteHash {
+    pub(super) hash: HashHexString,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
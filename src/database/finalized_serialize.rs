@@ -19,7 +19,7 @@
 //!
 //! This module contains the [`encode_chain_storage`] and [`decode_chain`] functions that can turn
 //! a [`chain_information::ChainInformation`] into a string and back, with optionally the state of
-//! the finalized block.
+//! the finalized block and the [history of the runtime versions](runtime_versions) of the chain.
 //! With no finalized block storage, the string is expected to be in the order of magniture of a
 //! few dozens kilobytes.
 //!
@@ -29,7 +29,7 @@
 //! This feature is expected to be used for example by light clients in order to easily (but
 //! inefficiently) store the state of the finalized chain somewhere and later reload it.
 
-use crate::chain::chain_information;
+use crate::chain::{chain_information, runtime_versions};
 
 use alloc::{string::String, vec::Vec};
 use core::{convert::TryFrom, iter};
@@ -39,19 +39,27 @@ mod defs;
 
 /// Serializes the given chain information as a string.
 ///
-/// This is a shortcut for [`encode_chain_storage`] with no `finalized_storage`.
+/// This is a shortcut for [`encode_chain_storage`] with no `finalized_storage` and an empty
+/// history of runtime versions.
 pub fn encode_chain(information: chain_information::ValidChainInformationRef<'_>) -> String {
-    encode_chain_storage(information, None::<iter::Empty<(Vec<u8>, Vec<u8>)>>)
+    encode_chain_storage(
+        information,
+        None::<iter::Empty<(Vec<u8>, Vec<u8>)>>,
+        &runtime_versions::RuntimeVersionsHistory::new(),
+    )
 }
 
-/// Serializes the given chain information and finalized block storage as a string.
+/// Serializes the given chain information, finalized block storage, and history of runtime
+/// versions as a string.
 pub fn encode_chain_storage(
     information: chain_information::ValidChainInformationRef<'_>,
     finalized_storage: Option<impl Iterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>>,
+    runtime_versions: &runtime_versions::RuntimeVersionsHistory,
 ) -> String {
     let decoded = defs::SerializedChainInformation::V1(defs::SerializedChainInformationV1::new(
         information.as_ref(),
         finalized_storage,
+        runtime_versions,
     ));
 
     serde_json::to_string(&decoded).unwrap()
@@ -59,20 +67,22 @@ pub fn encode_chain_storage(
 
 /// Deserializes the information about the chain.
 ///
-/// This is the invert operation of [`encode_chain_storage`].
+/// This is the invert operation of [`encode_chain_storage`]. The returned history of runtime
+/// versions is empty if the serialized data doesn't contain any.
 pub fn decode_chain(
     encoded: &str,
 ) -> Result<
     (
         chain_information::ValidChainInformation,
         Option<HashMap<Vec<u8>, Vec<u8>, fnv::FnvBuildHasher>>,
+        runtime_versions::RuntimeVersionsHistory,
     ),
     CorruptedError,
 > {
     let encoded: defs::SerializedChainInformation = serde_json::from_str(&encoded)
         .map_err(|e| CorruptedError(CorruptedErrorInner::Serde(e)))?;
 
-    let (chain_info, storage) = encoded
+    let (chain_info, storage, runtime_versions) = encoded
         .decode()
         .map_err(|err| CorruptedError(CorruptedErrorInner::Deserialize(err)))?;
 
@@ -80,7 +90,7 @@ pub fn decode_chain(
         .map_err(CorruptedErrorInner::InvalidChain)
         .map_err(CorruptedError)?;
 
-    Ok((chain_info, storage))
+    Ok((chain_info, storage, runtime_versions))
 }
 
 /// Opaque error indicating a corruption in the data stored in the local storage.
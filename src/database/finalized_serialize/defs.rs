@@ -17,7 +17,10 @@
 
 //! Type definitions to help with serializing/deserializing from/to the local storage.
 
-use crate::{chain::chain_information, header};
+use crate::{
+    chain::{chain_information, runtime_versions},
+    header,
+};
 
 use alloc::vec::Vec;
 use core::{fmt, num::NonZeroU64};
@@ -46,6 +49,7 @@ impl SerializedChainInformation {
         (
             chain_information::ChainInformation,
             Option<HashMap<Vec<u8>, Vec<u8>, fnv::FnvBuildHasher>>,
+            runtime_versions::RuntimeVersionsHistory,
         ),
         DeserializeError,
     > {
@@ -81,12 +85,15 @@ pub(super) struct SerializedChainInformationV1 {
     grandpa_finalized_scheduled_change: Option<SerializedFinalizedScheduledChangeV1>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     finalized_storage: Option<Vec<SerializedFinalizedStorageEntryV1>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    runtime_versions: Vec<SerializedRuntimeVersionV1>,
 }
 
 impl SerializedChainInformationV1 {
     pub(super) fn new<'a>(
         from: chain_information::ChainInformationRef<'a>,
         finalized_storage: Option<impl Iterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>>,
+        runtime_versions: &runtime_versions::RuntimeVersionsHistory,
     ) -> Self {
         SerializedChainInformationV1 {
             finalized_block_header: from.finalized_block_header.scale_encoding().fold(
@@ -182,6 +189,15 @@ impl SerializedChainInformationV1 {
                     })
                     .collect()
             }),
+            runtime_versions: runtime_versions
+                .iter()
+                .map(
+                    |(first_block_number, spec_version)| SerializedRuntimeVersionV1 {
+                        first_block_number,
+                        spec_version,
+                    },
+                )
+                .collect(),
         }
     }
 }
@@ -193,6 +209,7 @@ impl SerializedChainInformationV1 {
         (
             chain_information::ChainInformation,
             Option<HashMap<Vec<u8>, Vec<u8>, fnv::FnvBuildHasher>>,
+            runtime_versions::RuntimeVersionsHistory,
         ),
         DeserializeError,
     > {
@@ -275,7 +292,13 @@ impl SerializedChainInformationV1 {
             None
         };
 
-        Ok((chain_info, finalized_storage))
+        let runtime_versions = self
+            .runtime_versions
+            .into_iter()
+            .map(|entry| (entry.first_block_number, entry.spec_version))
+            .collect();
+
+        Ok((chain_info, finalized_storage, runtime_versions))
     }
 }
 
@@ -294,6 +317,13 @@ struct SerializedFinalizedStorageEntryV1 {
     value: Vec<u8>,
 }
 
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SerializedRuntimeVersionV1 {
+    first_block_number: u64,
+    spec_version: u32,
+}
+
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
 #[serde(deny_unknown_fields)]
 struct SerializedAuraAuthorityV1 {
@@ -755,6 +755,55 @@ impl SqliteFullDatabase {
         Ok(Some(key))
     }
 
+    /// Returns the key in the storage of the finalized block that immediately precedes the key
+    /// passed as parameter, or the last key of the storage if `None` is passed.
+    ///
+    /// In order to avoid race conditions, the known finalized block hash must be passed as
+    /// parameter. If the finalized block in the database doesn't match the hash passed as
+    /// parameter, most likely because it has been updated in a parallel thread, a
+    /// [`FinalizedAccessError::Obsolete`] error is returned.
+    pub fn finalized_block_storage_top_trie_prev_key(
+        &self,
+        finalized_block_hash: &[u8; 32],
+        key: Option<&[u8]>,
+    ) -> Result<Option<Vec<u8>>, FinalizedAccessError> {
+        let connection = self.database.lock();
+
+        if finalized_hash(&connection)? != *finalized_block_hash {
+            return Err(FinalizedAccessError::Obsolete);
+        }
+
+        let mut statement = if let Some(key) = key {
+            let mut statement = connection
+                .prepare(r#"SELECT key FROM finalized_storage_top_trie WHERE key < ? ORDER BY key DESC LIMIT 1"#)
+                .map_err(InternalError)
+                .map_err(CorruptedError::Internal)
+                .map_err(AccessError::Corrupted)
+                .map_err(FinalizedAccessError::Access)?;
+            statement.bind(1, key).unwrap();
+            statement
+        } else {
+            connection
+                .prepare(r#"SELECT key FROM finalized_storage_top_trie ORDER BY key DESC LIMIT 1"#)
+                .map_err(InternalError)
+                .map_err(CorruptedError::Internal)
+                .map_err(AccessError::Corrupted)
+                .map_err(FinalizedAccessError::Access)?
+        };
+
+        if !matches!(statement.next().unwrap(), sqlite::State::Row) {
+            return Ok(None);
+        }
+
+        let key = statement
+            .read::<Vec<u8>>(0)
+            .map_err(InternalError)
+            .map_err(CorruptedError::Internal)
+            .map_err(AccessError::Corrupted)
+            .map_err(FinalizedAccessError::Access)?;
+        Ok(Some(key))
+    }
+
     /// Returns the list of keys of the storage of the finalized block that start with the given
     /// prefix. Pass `&[]` for the prefix to get the list of all keys.
     ///
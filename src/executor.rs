@@ -32,7 +32,10 @@ use alloc::vec::Vec;
 use core::{convert::TryFrom as _, str};
 
 mod allocator; // TODO: make public after refactoring
+pub mod call_recording;
+pub mod code_info;
 pub mod host;
+pub mod overlay_runtime_host;
 pub mod read_only_runtime_host;
 pub mod runtime_host;
 pub mod vm;
@@ -97,6 +100,7 @@ pub fn core_version(
             }
             host::HostVm::Error { .. } => return Err(()),
             host::HostVm::LogEmit(log) => vm = log.resume(),
+            host::HostVm::SignatureVerification(sig) => vm = sig.verify_and_resume(),
 
             // Since there are potential ambiguities we don't allow any storage access
             // or anything similar. The last thing we want is to have an infinite
@@ -183,7 +187,9 @@ fn decode(scale_encoded: &[u8]) -> Result<CoreVersionRef, ()> {
 
     match result {
         Ok((_, out)) => Ok(out),
-        Err(nom::Err::Error(_)) | Err(nom::Err::Failure(_)) => Err(()),
-        Err(_) => unreachable!(),
+        // `Incomplete` can be returned if the input is truncated in the middle of a string.
+        Err(nom::Err::Error(_)) | Err(nom::Err::Failure(_)) | Err(nom::Err::Incomplete(_)) => {
+            Err(())
+        }
     }
 }
@@ -0,0 +1,309 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Recording and replaying of read-only runtime calls.
+//!
+//! A light client performs runtime calls by downloading from the network a proof containing the
+//! storage entries that the call accesses, then running the runtime locally against this proof.
+//! When a call fails on a light client but succeeds on a full node, the cause is typically
+//! either an incomplete proof or a difference in the behaviour of the virtual machine.
+//!
+//! A [`RuntimeCallRecording`] contains everything necessary to execute the call again: the
+//! block the call was made against, the function and its parameter, the call proof, and the
+//! storage values that were obtained from this proof. It can be turned into a string with
+//! [`RuntimeCallRecording::to_json`], for example in order to be attached to a bug report, then
+//! decoded with [`RuntimeCallRecording::from_json`] and executed again with
+//! [`RuntimeCallRecording::replay`].
+//!
+//! The runtime code itself isn't part of the recording, as it is typically over a megabyte
+//! large. Only its hash is. The code must be provided when replaying the call, and can be
+//! obtained by querying the `:code` storage key of the block the call was made against.
+
+use super::{host, read_only_runtime_host, storage_heap_pages_to_value, vm};
+use crate::trie::proof_verify;
+
+use alloc::{
+    borrow::ToOwned as _,
+    string::{String, ToString as _},
+    vec::Vec,
+};
+use core::{convert::TryFrom as _, iter};
+
+/// Runtime call, and everything required in order to execute it again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeCallRecording {
+    /// BLAKE2 hash of the runtime code (the storage value of `:code`) that has performed the
+    /// call.
+    pub runtime_code_hash: [u8; 32],
+    /// Storage value of `:heappages` of the block the call was made against, if any.
+    pub heap_pages: Option<Vec<u8>>,
+    /// Hash of the block the call was made against.
+    pub block_hash: [u8; 32],
+    /// Height of the block the call was made against.
+    pub block_number: u64,
+    /// Storage trie root of the block the call was made against.
+    pub block_state_root: [u8; 32],
+    /// SCALE-encoded header of the block the call was made against, if known.
+    pub block_header: Option<Vec<u8>>,
+    /// Name of the runtime function that has been called.
+    pub function_to_call: String,
+    /// Parameter passed to the runtime function.
+    pub parameter: Vec<u8>,
+    /// Entries of the call proof, as downloaded from the network.
+    pub call_proof: Vec<Vec<u8>>,
+    /// Storage keys read by the runtime, in order, and the values that were injected in
+    /// response.
+    pub storage_values: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    /// Human-readable description of how the call ended, for example an error message.
+    pub outcome: String,
+}
+
+impl RuntimeCallRecording {
+    /// Serializes the recording as a string.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&SerializedRecordingV1 {
+            runtime_code_hash: hex::encode(self.runtime_code_hash),
+            heap_pages: self.heap_pages.as_ref().map(hex::encode),
+            block_hash: hex::encode(self.block_hash),
+            block_number: self.block_number,
+            block_state_root: hex::encode(self.block_state_root),
+            block_header: self.block_header.as_ref().map(hex::encode),
+            function_to_call: self.function_to_call.clone(),
+            parameter: hex::encode(&self.parameter),
+            call_proof: self.call_proof.iter().map(hex::encode).collect(),
+            storage_values: self
+                .storage_values
+                .iter()
+                .map(|(key, value)| SerializedStorageValueV1 {
+                    key: hex::encode(key),
+                    value: value.as_ref().map(hex::encode),
+                })
+                .collect(),
+            outcome: self.outcome.clone(),
+        })
+        .unwrap()
+    }
+
+    /// Deserializes a recording previously serialized with [`RuntimeCallRecording::to_json`].
+    pub fn from_json(encoded: &str) -> Result<Self, DecodeError> {
+        let decoded: SerializedRecordingV1 =
+            serde_json::from_str(encoded).map_err(|err| DecodeError(err.to_string()))?;
+
+        fn bytes(hex: &str) -> Result<Vec<u8>, DecodeError> {
+            hex::decode(hex).map_err(|err| DecodeError(err.to_string()))
+        }
+        fn hash(hex: &str) -> Result<[u8; 32], DecodeError> {
+            <[u8; 32]>::try_from(&bytes(hex)?[..])
+                .map_err(|_| DecodeError("invalid hash length".to_owned()))
+        }
+
+        Ok(RuntimeCallRecording {
+            runtime_code_hash: hash(&decoded.runtime_code_hash)?,
+            heap_pages: decoded.heap_pages.as_deref().map(bytes).transpose()?,
+            block_hash: hash(&decoded.block_hash)?,
+            block_number: decoded.block_number,
+            block_state_root: hash(&decoded.block_state_root)?,
+            block_header: decoded.block_header.as_deref().map(bytes).transpose()?,
+            function_to_call: decoded.function_to_call,
+            parameter: bytes(&decoded.parameter)?,
+            call_proof: decoded
+                .call_proof
+                .iter()
+                .map(|entry| bytes(entry))
+                .collect::<Result<_, _>>()?,
+            storage_values: decoded
+                .storage_values
+                .iter()
+                .map(|entry| {
+                    Ok((
+                        bytes(&entry.key)?,
+                        entry.value.as_deref().map(bytes).transpose()?,
+                    ))
+                })
+                .collect::<Result<_, _>>()?,
+            outcome: decoded.outcome,
+        })
+    }
+
+    /// Executes the call again, using the given runtime code.
+    ///
+    /// The storage accessed by the runtime is read from [`RuntimeCallRecording::call_proof`],
+    /// in the same way as during the original call. Signatures are verified within the virtual
+    /// machine. The execution is deterministic.
+    ///
+    /// On success, returns the value returned by the runtime. An error indicates that the
+    /// problem of the original call has been reproduced, or that the recording itself is
+    /// invalid.
+    pub fn replay(&self, runtime_code: &[u8]) -> Result<Vec<u8>, ReplayError> {
+        let runtime_code_hash = blake2_rfc::blake2b::blake2b(32, &[], runtime_code);
+        if runtime_code_hash.as_bytes() != self.runtime_code_hash {
+            return Err(ReplayError::CodeHashMismatch);
+        }
+
+        let heap_pages = storage_heap_pages_to_value(self.heap_pages.as_deref())
+            .map_err(ReplayError::InvalidHeapPages)?;
+        let virtual_machine =
+            host::HostVmPrototype::new(runtime_code, heap_pages, vm::ExecHint::Oneshot)
+                .map_err(ReplayError::VmInit)?;
+
+        let mut runtime_call = read_only_runtime_host::run(read_only_runtime_host::Config {
+            virtual_machine,
+            function_to_call: &self.function_to_call,
+            parameter: iter::once(&self.parameter),
+        })
+        .map_err(|(err, _)| ReplayError::Start(err))?;
+
+        loop {
+            match runtime_call {
+                read_only_runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                    return Ok(success.virtual_machine.value().as_ref().to_vec());
+                }
+                read_only_runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                    return Err(ReplayError::Call(error.detail));
+                }
+                read_only_runtime_host::RuntimeHostVm::StorageGet(get) => {
+                    let key = get.key_as_vec();
+                    let value = proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                        requested_key: &key,
+                        trie_root_hash: &self.block_state_root,
+                        proof: self.call_proof.iter().map(|v| &v[..]),
+                    })
+                    .map_err(ReplayError::StorageRetrieval)?;
+                    runtime_call = get.inject_value(value.map(iter::once));
+                }
+                read_only_runtime_host::RuntimeHostVm::NextKey(_) => {
+                    return Err(ReplayError::NextKeyUnsupported);
+                }
+                read_only_runtime_host::RuntimeHostVm::StorageRoot(storage_root) => {
+                    runtime_call = storage_root.resume(&self.block_state_root);
+                }
+                read_only_runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                    runtime_call = sig.verify_and_resume();
+                }
+            }
+        }
+    }
+}
+
+/// Error potentially returned by [`RuntimeCallRecording::from_json`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Failed to decode runtime call recording: {}", _0)]
+pub struct DecodeError(String);
+
+/// Error potentially returned by [`RuntimeCallRecording::replay`].
+#[derive(Debug, derive_more::Display)]
+pub enum ReplayError {
+    /// Hash of the runtime code doesn't match [`RuntimeCallRecording::runtime_code_hash`].
+    #[display(fmt = "Runtime code doesn't match the recording")]
+    CodeHashMismatch,
+    /// Value of [`RuntimeCallRecording::heap_pages`] is invalid.
+    #[display(fmt = "Invalid heap pages: {}", _0)]
+    InvalidHeapPages(super::InvalidHeapPagesError),
+    /// Error while instantiating the runtime.
+    #[display(fmt = "Failed to initialize the virtual machine: {}", _0)]
+    VmInit(host::NewErr),
+    /// Error when starting the call.
+    #[display(fmt = "Failed to start the call: {}", _0)]
+    Start(host::StartErr),
+    /// Error while executing the call.
+    #[display(fmt = "{}", _0)]
+    Call(read_only_runtime_host::ErrorDetail),
+    /// Error while retrieving a storage value from the call proof.
+    #[display(fmt = "Failed to retrieve storage value from the proof: {}", _0)]
+    StorageRetrieval(proof_verify::Error),
+    /// The runtime has requested the next key of the storage, which isn't supported.
+    NextKeyUnsupported,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SerializedRecordingV1 {
+    runtime_code_hash: String,
+    heap_pages: Option<String>,
+    block_hash: String,
+    block_number: u64,
+    block_state_root: String,
+    block_header: Option<String>,
+    function_to_call: String,
+    parameter: String,
+    call_proof: Vec<String>,
+    storage_values: Vec<SerializedStorageValueV1>,
+    outcome: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerializedStorageValueV1 {
+    key: String,
+    value: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReplayError, RuntimeCallRecording};
+    use core::convert::TryFrom as _;
+
+    fn recording(runtime_code: &[u8]) -> RuntimeCallRecording {
+        let hash = blake2_rfc::blake2b::blake2b(32, &[], runtime_code);
+        RuntimeCallRecording {
+            runtime_code_hash: <[u8; 32]>::try_from(hash.as_bytes()).unwrap(),
+            heap_pages: None,
+            block_hash: [1; 32],
+            block_number: 12,
+            block_state_root: [2; 32],
+            block_header: Some(vec![3, 4, 5]),
+            function_to_call: "Core_version".to_owned(),
+            parameter: Vec::new(),
+            call_proof: vec![vec![6, 7], vec![]],
+            storage_values: vec![(vec![8], Some(vec![9])), (vec![10], None)],
+            outcome: "Error while executing Wasm VM".to_owned(),
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let recording = recording(b"foo");
+        let decoded = RuntimeCallRecording::from_json(&recording.to_json()).unwrap();
+        assert_eq!(decoded, recording);
+    }
+
+    #[test]
+    fn replay_core_version() {
+        let chain_specs = crate::chain_spec::ChainSpec::from_json_bytes(
+            &include_bytes!("../author/runtime/example-chain-specs.json")[..],
+        )
+        .unwrap();
+        let runtime_code = chain_specs
+            .genesis_storage()
+            .find(|(k, _)| k == b":code")
+            .unwrap()
+            .1;
+
+        let version = RuntimeCallRecording::from_json(&recording(runtime_code).to_json())
+            .unwrap()
+            .replay(runtime_code)
+            .unwrap();
+        assert!(crate::executor::decode(&version).is_ok());
+    }
+
+    #[test]
+    fn replay_wrong_code() {
+        assert!(matches!(
+            recording(b"foo").replay(b"bar"),
+            Err(ReplayError::CodeHashMismatch)
+        ));
+    }
+}
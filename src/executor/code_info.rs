@@ -0,0 +1,464 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Information about a runtime found in its Wasm code, obtained without compiling it.
+//!
+//! Two pieces of information are extracted:
+//!
+//! - The content of the `runtime_version` custom section. Recent runtimes embed their version
+//!   in this section, SCALE-encoded in the same way as the output of the `Core_version`
+//!   runtime function. Reading the section makes it possible to know the version of a runtime
+//!   without executing it. If the code also contains a `runtime_apis` custom section, the list
+//!   of APIs it contains replaces the one of the `runtime_version` section. Older runtimes
+//!   don't contain these sections, in which case the version can only be obtained by calling
+//!   `Core_version`.
+//! - The limits of the memory that the runtime imports, if any. See
+//!   [the documentation of the `vm` module](super::vm) for more information about imported
+//!   memories. A runtime that declares a maximum size for its imported memory can't be given
+//!   more heap pages than this maximum allows.
+
+use super::{host, CoreVersion};
+use crate::util::leb128;
+
+use alloc::vec::Vec;
+use core::convert::TryFrom as _;
+
+/// See [the module-level documentation](..).
+#[derive(Debug, Clone)]
+pub struct RuntimeCodeInfo {
+    /// Version of the runtime found in the `runtime_version` custom section, or `None` if the
+    /// code doesn't contain such section. If the code contains a `runtime_apis` custom section,
+    /// the list of APIs of the version is the one found in this section.
+    pub runtime_version: Option<CoreVersion>,
+
+    /// Limits of the memory imported by the runtime, or `None` if the runtime doesn't import
+    /// its memory, in which case it exports it instead.
+    pub memory_import: Option<MemoryImportLimits>,
+}
+
+/// Limits of a memory imported by a runtime, in number of 64 kiB pages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemoryImportLimits {
+    /// Minimum size of the memory, as declared by the runtime.
+    pub initial: u32,
+    /// Maximum size of the memory, as declared by the runtime. `None` if unbounded.
+    pub maximum: Option<u32>,
+}
+
+impl MemoryImportLimits {
+    /// Returns the maximum number of heap pages that can be allocated in addition to the
+    /// initial size of the memory, or `None` if unbounded.
+    pub fn max_heap_pages(&self) -> Option<host::HeapPages> {
+        self.maximum
+            .map(|max| host::HeapPages::new(max.saturating_sub(self.initial)))
+    }
+}
+
+/// Extracts information from the given runtime code.
+///
+/// The code can be either directly Wasm bytecode, or zstandard-compressed.
+pub fn extract(code: &[u8]) -> Result<RuntimeCodeInfo, Error> {
+    // The maximum size is the same as the one used by `HostVmPrototype::new`.
+    let code =
+        host::zstd::zstd_decode_if_necessary(code, 50 * 1024 * 1024).map_err(Error::BadFormat)?;
+
+    let mut runtime_version = None;
+    let mut runtime_apis = None;
+    let mut memory_import = None;
+
+    let mut sections = code
+        .strip_prefix(b"\0asm\x01\0\0\0")
+        .ok_or(Error::InvalidWasm)?;
+
+    while !sections.is_empty() {
+        let (rest, (section_id, section)) = section(sections).map_err(|_| Error::InvalidWasm)?;
+        sections = rest;
+
+        match section_id {
+            // Custom section.
+            0 => {
+                let (content, name) = name(section).map_err(|_| Error::InvalidWasm)?;
+                if name == b"runtime_version" && runtime_version.is_none() {
+                    runtime_version = Some(content);
+                } else if name == b"runtime_apis" && runtime_apis.is_none() {
+                    runtime_apis = Some(content);
+                }
+            }
+            // Import section.
+            2 => {
+                memory_import = imported_memory(section).map_err(|_| Error::InvalidWasm)?.1;
+            }
+            _ => {}
+        }
+    }
+
+    // The sections are only decoded after the loop, as their order in the code is unspecified.
+    let runtime_version = match (runtime_version, runtime_apis) {
+        (Some(version), None) => {
+            if super::decode(version).is_err() {
+                return Err(Error::InvalidRuntimeVersion);
+            }
+            Some(CoreVersion(version.to_vec()))
+        }
+        (Some(version), Some(apis)) => {
+            let mut version = super::decode(version).map_err(|()| Error::InvalidRuntimeVersion)?;
+            version.apis = decode_runtime_apis(apis)?;
+            Some(CoreVersion(encode_core_version(&version)))
+        }
+        (None, _) => None,
+    };
+
+    Ok(RuntimeCodeInfo {
+        runtime_version,
+        memory_import,
+    })
+}
+
+/// Error potentially returned by [`extract`].
+#[derive(Debug, derive_more::Display)]
+pub enum Error {
+    /// Error in the format of the runtime code.
+    #[display(fmt = "{}", _0)]
+    BadFormat(host::ModuleFormatError),
+    /// The runtime code isn't a valid Wasm module.
+    InvalidWasm,
+    /// The content of the `runtime_version` custom section can't be decoded.
+    InvalidRuntimeVersion,
+    /// The content of the `runtime_apis` custom section can't be decoded.
+    InvalidRuntimeApis,
+}
+
+/// Decodes the content of the `runtime_apis` custom section, which consists in a list of
+/// concatenated 8 bytes API identifiers each followed with a little-endian `u32` version.
+/// Contrary to the `runtime_version` section, the list isn't prefixed with its length.
+fn decode_runtime_apis(bytes: &[u8]) -> Result<Vec<([u8; 8], u32)>, Error> {
+    let entries = bytes.chunks_exact(12);
+    if !entries.remainder().is_empty() {
+        return Err(Error::InvalidRuntimeApis);
+    }
+
+    Ok(entries
+        .map(|entry| {
+            let id = <[u8; 8]>::try_from(&entry[..8]).unwrap();
+            let version = u32::from_le_bytes(<[u8; 4]>::try_from(&entry[8..]).unwrap());
+            (id, version)
+        })
+        .collect())
+}
+
+/// SCALE-encodes the given runtime version, in the same format as the output of
+/// `Core_version`.
+fn encode_core_version(version: &super::CoreVersionRef) -> Vec<u8> {
+    let mut out = Vec::new();
+    for string in [version.spec_name, version.impl_name] {
+        out.extend_from_slice(crate::util::encode_scale_compact_usize(string.len()).as_ref());
+        out.extend_from_slice(string.as_bytes());
+    }
+    out.extend_from_slice(&version.authoring_version.to_le_bytes());
+    out.extend_from_slice(&version.spec_version.to_le_bytes());
+    out.extend_from_slice(&version.impl_version.to_le_bytes());
+    out.extend_from_slice(crate::util::encode_scale_compact_usize(version.apis.len()).as_ref());
+    for (id, api_version) in &version.apis {
+        out.extend_from_slice(id);
+        out.extend_from_slice(&api_version.to_le_bytes());
+    }
+    if let Some(transaction_version) = version.transaction_version {
+        out.extend_from_slice(&transaction_version.to_le_bytes());
+    }
+    out
+}
+
+/// Parses a section, and returns its id and content.
+fn section(bytes: &[u8]) -> nom::IResult<&[u8], (u8, &[u8])> {
+    nom::sequence::tuple((
+        nom::number::complete::u8,
+        nom::combinator::flat_map(leb128_u32, nom::bytes::complete::take),
+    ))(bytes)
+}
+
+/// Parses a name, as found for example in imports and custom sections.
+fn name(bytes: &[u8]) -> nom::IResult<&[u8], &[u8]> {
+    nom::combinator::flat_map(leb128_u32, nom::bytes::complete::take)(bytes)
+}
+
+/// Parses the content of the import section, and returns the limits of the imported memory,
+/// if any. If multiple memories are imported, which isn't supported by the virtual machine
+/// anyway, returns the first one.
+fn imported_memory(bytes: &[u8]) -> nom::IResult<&[u8], Option<MemoryImportLimits>> {
+    nom::combinator::all_consuming(nom::combinator::map(
+        nom::combinator::flat_map(leb128_u32, |num_imports| {
+            nom::multi::many_m_n(
+                num_imports as usize,
+                num_imports as usize,
+                nom::sequence::preceded(nom::sequence::tuple((name, name)), import_descriptor),
+            )
+        }),
+        |imports: Vec<Option<MemoryImportLimits>>| imports.into_iter().flatten().next(),
+    ))(bytes)
+}
+
+/// Parses the description of an import. Returns the limits of the memory if the import is a
+/// memory, and `None` otherwise.
+fn import_descriptor(bytes: &[u8]) -> nom::IResult<&[u8], Option<MemoryImportLimits>> {
+    nom::branch::alt((
+        // Function, followed with the index of its type.
+        nom::combinator::map(
+            nom::sequence::preceded(nom::bytes::complete::tag(&[0x00]), leb128_u32),
+            |_| None,
+        ),
+        // Table, followed with its element type and limits.
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::sequence::tuple((
+                    nom::bytes::complete::tag(&[0x01]),
+                    nom::number::complete::u8,
+                )),
+                limits,
+            ),
+            |_| None,
+        ),
+        // Memory.
+        nom::combinator::map(
+            nom::sequence::preceded(nom::bytes::complete::tag(&[0x02]), limits),
+            Some,
+        ),
+        // Global, followed with its value type and mutability.
+        nom::combinator::map(
+            nom::sequence::tuple((
+                nom::bytes::complete::tag(&[0x03]),
+                nom::number::complete::u8,
+                nom::number::complete::u8,
+            )),
+            |_| None,
+        ),
+    ))(bytes)
+}
+
+/// Parses limits, as found in the description of tables and memories.
+fn limits(bytes: &[u8]) -> nom::IResult<&[u8], MemoryImportLimits> {
+    nom::branch::alt((
+        nom::combinator::map(
+            nom::sequence::preceded(nom::bytes::complete::tag(&[0x00]), leb128_u32),
+            |initial| MemoryImportLimits {
+                initial,
+                maximum: None,
+            },
+        ),
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[0x01]),
+                nom::sequence::tuple((leb128_u32, leb128_u32)),
+            ),
+            |(initial, maximum)| MemoryImportLimits {
+                initial,
+                maximum: Some(maximum),
+            },
+        ),
+    ))(bytes)
+}
+
+fn leb128_u32(bytes: &[u8]) -> nom::IResult<&[u8], u32> {
+    match leb128::Decoder::new().update(bytes) {
+        Ok((num_read, leb128::Decoded::Finished(value))) => match u32::try_from(value) {
+            Ok(value) => Ok((&bytes[num_read..], value)),
+            Err(_) => Err(nom::Err::Error(nom::error::make_error(
+                bytes,
+                nom::error::ErrorKind::TooLarge,
+            ))),
+        },
+        Ok((_, leb128::Decoded::InProgress(_))) | Err(_) => Err(nom::Err::Error(
+            nom::error::make_error(bytes, nom::error::ErrorKind::Eof),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract, Error, MemoryImportLimits};
+    use crate::executor::host::HeapPages;
+
+    /// Builds a Wasm module containing only the given sections.
+    fn module(sections: &[(u8, Vec<u8>)]) -> Vec<u8> {
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+        for (id, content) in sections {
+            out.push(*id);
+            out.extend(crate::util::leb128::encode_usize(content.len()));
+            out.extend_from_slice(content);
+        }
+        out
+    }
+
+    fn name(name: &[u8]) -> Vec<u8> {
+        let mut out = crate::util::leb128::encode_usize(name.len()).collect::<Vec<_>>();
+        out.extend_from_slice(name);
+        out
+    }
+
+    /// Builds an import section importing a function and the given memory.
+    fn import_section(memory_limits: &[u8]) -> Vec<u8> {
+        let mut out = vec![2];
+        out.extend(name(b"env"));
+        out.extend(name(b"ext_logging_log_version_1"));
+        out.extend([0x00, 0x05]);
+        out.extend(name(b"env"));
+        out.extend(name(b"memory"));
+        out.push(0x02);
+        out.extend_from_slice(memory_limits);
+        out
+    }
+
+    fn runtime_version_section(version: &[u8]) -> Vec<u8> {
+        let mut out = name(b"runtime_version");
+        out.extend_from_slice(version);
+        out
+    }
+
+    /// SCALE-encoded runtime version whose spec name is `test` and spec version 5.
+    const VERSION: &[u8] = &[
+        16, b't', b'e', b's', b't', 16, b't', b'e', b's', b't', 1, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0,
+        0, 1, 0, 0, 0,
+    ];
+
+    #[test]
+    fn empty_module() {
+        let info = extract(&module(&[])).unwrap();
+        assert!(info.runtime_version.is_none());
+        assert!(info.memory_import.is_none());
+    }
+
+    #[test]
+    fn memory_import_without_maximum() {
+        let info = extract(&module(&[(2, import_section(&[0x00, 0x14]))])).unwrap();
+        assert_eq!(
+            info.memory_import,
+            Some(MemoryImportLimits {
+                initial: 20,
+                maximum: None
+            })
+        );
+        assert_eq!(info.memory_import.unwrap().max_heap_pages(), None);
+    }
+
+    #[test]
+    fn memory_import_with_maximum() {
+        // Maximum of 300 pages, LEB128-encoded on two bytes.
+        let info = extract(&module(&[(2, import_section(&[0x01, 0x14, 0xac, 0x02]))])).unwrap();
+        assert_eq!(
+            info.memory_import,
+            Some(MemoryImportLimits {
+                initial: 20,
+                maximum: Some(300)
+            })
+        );
+        assert_eq!(
+            info.memory_import.unwrap().max_heap_pages(),
+            Some(HeapPages::new(280))
+        );
+    }
+
+    #[test]
+    fn runtime_version_section_found() {
+        let info = extract(&module(&[
+            (1, vec![0]),
+            (0, runtime_version_section(VERSION)),
+        ]))
+        .unwrap();
+        let version = info.runtime_version.unwrap();
+        assert_eq!(version.decode().spec_name, "test");
+        assert_eq!(version.decode().spec_version, 5);
+    }
+
+    fn runtime_apis_section(apis: &[([u8; 8], u32)]) -> Vec<u8> {
+        let mut out = name(b"runtime_apis");
+        for (id, version) in apis {
+            out.extend_from_slice(id);
+            out.extend_from_slice(&version.to_le_bytes());
+        }
+        out
+    }
+
+    #[test]
+    fn runtime_version_re_encoded() {
+        let version = crate::executor::decode(VERSION).unwrap();
+        assert_eq!(super::encode_core_version(&version), VERSION);
+    }
+
+    #[test]
+    fn runtime_apis_section_replaces_apis() {
+        let apis = [(*b"\xdf\x6a\xcb\x68\x99\x07\x60\x9b", 3), (*b"apis_id2", 1)];
+
+        // The order of the sections in the code must not matter.
+        for sections in [
+            [
+                (0, runtime_version_section(VERSION)),
+                (0, runtime_apis_section(&apis)),
+            ],
+            [
+                (0, runtime_apis_section(&apis)),
+                (0, runtime_version_section(VERSION)),
+            ],
+        ] {
+            let info = extract(&module(&sections)).unwrap();
+            let version = info.runtime_version.unwrap();
+            let decoded = version.decode();
+            assert_eq!(decoded.spec_name, "test");
+            assert_eq!(decoded.spec_version, 5);
+            assert_eq!(decoded.apis, apis);
+            assert_eq!(decoded.transaction_version, Some(1));
+        }
+    }
+
+    #[test]
+    fn runtime_apis_section_without_version_ignored() {
+        let info = extract(&module(&[(0, runtime_apis_section(&[(*b"apis_id1", 1)]))])).unwrap();
+        assert!(info.runtime_version.is_none());
+    }
+
+    #[test]
+    fn invalid_runtime_apis() {
+        let mut apis = runtime_apis_section(&[(*b"apis_id1", 1)]);
+        apis.pop();
+        assert!(matches!(
+            extract(&module(&[(0, runtime_version_section(VERSION)), (0, apis)])),
+            Err(Error::InvalidRuntimeApis)
+        ));
+    }
+
+    #[test]
+    fn other_custom_sections_ignored() {
+        let mut section = name(b"producers");
+        section.extend_from_slice(b"garbage");
+        let info = extract(&module(&[(0, section)])).unwrap();
+        assert!(info.runtime_version.is_none());
+    }
+
+    #[test]
+    fn invalid_runtime_version() {
+        assert!(matches!(
+            extract(&module(&[(0, runtime_version_section(&VERSION[1..]))])),
+            Err(Error::InvalidRuntimeVersion)
+        ));
+    }
+
+    #[test]
+    fn truncated_module() {
+        let mut code = module(&[(2, import_section(&[0x00, 0x14]))]);
+        code.pop();
+        assert!(matches!(extract(&code), Err(Error::InvalidWasm)));
+        assert!(matches!(extract(b"not wasm"), Err(Error::InvalidWasm)));
+    }
+}
@@ -192,7 +192,7 @@ use tiny_keccak::Hasher as _;
 pub use vm::HeapPages;
 pub use zstd::Error as ModuleFormatError;
 
-mod zstd;
+pub(crate) mod zstd;
 
 /// Prototype for an [`HostVm`].
 ///
@@ -221,6 +221,9 @@ pub struct HostVmPrototype {
 
     /// Value of `heap_pages` passed to [`HostVmPrototype::new`].
     heap_pages: HeapPages,
+
+    /// Value of `max_memory_pages` passed to [`HostVmPrototype::new_with_memory_limit`].
+    max_memory_pages: Option<u32>,
 }
 
 impl HostVmPrototype {
@@ -232,15 +235,39 @@ impl HostVmPrototype {
         module: impl AsRef<[u8]>,
         heap_pages: HeapPages,
         exec_hint: vm::ExecHint,
+    ) -> Result<Self, NewErr> {
+        Self::new_with_memory_limit(module, heap_pages, exec_hint, None)
+    }
+
+    /// Same as [`HostVmPrototype::new`], but returns an error if the memory of the virtual
+    /// machine, in number of 64 kiB pages, would exceed `max_memory_pages`.
+    ///
+    /// The limit is also enforced when the prototype is cloned, and when the runtime grows its
+    /// memory while being executed.
+    pub fn new_with_memory_limit(
+        module: impl AsRef<[u8]>,
+        heap_pages: HeapPages,
+        exec_hint: vm::ExecHint,
+        max_memory_pages: Option<u32>,
     ) -> Result<Self, NewErr> {
         // TODO: configurable maximum allowed size? a uniform value is important for consensus
         let module = zstd::zstd_decode_if_necessary(module.as_ref(), 50 * 1024 * 1024)
             .map_err(NewErr::BadFormat)?;
         let module = vm::Module::new(module, exec_hint)?;
-        Self::from_module(module, heap_pages)
+        Self::from_module(module, heap_pages, max_memory_pages)
     }
 
-    fn from_module(module: vm::Module, heap_pages: HeapPages) -> Result<Self, NewErr> {
+    /// Creates a new [`HostVmPrototype`] from an already-compiled module, such as one obtained
+    /// with [`HostVmPrototype::module`].
+    ///
+    /// This makes it possible to instantiate the same runtime multiple times without having to
+    /// parse and compile it again. See [`HostVmPrototype::new_with_memory_limit`] for the other
+    /// parameters.
+    pub fn from_module(
+        module: vm::Module,
+        heap_pages: HeapPages,
+        max_memory_pages: Option<u32>,
+    ) -> Result<Self, NewErr> {
         // Initialize the virtual machine.
         // Each symbol requested by the Wasm runtime will be put in `registered_functions`. Later,
         // when a function is invoked, the Wasm virtual machine will pass indices within that
@@ -250,6 +277,7 @@ impl HostVmPrototype {
             let vm_proto = vm::VirtualMachinePrototype::new(
                 &module,
                 heap_pages,
+                max_memory_pages,
                 // This closure is called back for each function that the runtime imports.
                 |mod_name, f_name, _signature| {
                     if mod_name != "env" {
@@ -280,6 +308,7 @@ impl HostVmPrototype {
             heap_base,
             registered_functions,
             heap_pages,
+            max_memory_pages,
         })
     }
 
@@ -288,6 +317,21 @@ impl HostVmPrototype {
         self.heap_pages
     }
 
+    /// Returns the memory limit that was passed to [`HostVmPrototype::new_with_memory_limit`],
+    /// or `None` if there isn't any.
+    pub fn max_memory_pages(&self) -> Option<u32> {
+        self.max_memory_pages
+    }
+
+    /// Returns the compiled module that this prototype has been instantiated from.
+    ///
+    /// The module can later be passed to [`HostVmPrototype::from_module`].
+    ///
+    /// > **Note**: Cloning the returned value is cheap.
+    pub fn module(&self) -> &vm::Module {
+        &self.module
+    }
+
     /// Starts the VM, calling the function passed as parameter.
     pub fn run(self, function_to_call: &str, data: &[u8]) -> Result<ReadyToRun, (StartErr, Self)> {
         self.run_vectored(function_to_call, iter::once(data))
@@ -354,6 +398,7 @@ impl HostVmPrototype {
                 vm,
                 heap_base: self.heap_base,
                 heap_pages: self.heap_pages,
+                max_memory_pages: self.max_memory_pages,
                 registered_functions: self.registered_functions,
                 within_storage_transaction: false,
                 allocator,
@@ -367,7 +412,7 @@ impl Clone for HostVmPrototype {
         // The `from_module` function returns an error if the format of the module is invalid.
         // Since we have successfully called `from_module` with that same `module` earlier, it
         // is assumed that errors cannot happen.
-        Self::from_module(self.module.clone(), self.heap_pages).unwrap()
+        Self::from_module(self.module.clone(), self.heap_pages, self.max_memory_pages).unwrap()
     }
 }
 
@@ -415,6 +460,9 @@ pub enum HostVm {
     /// Need to provide the storage key that follows a specific one.
     #[from]
     ExternalStorageNextKey(ExternalStorageNextKey),
+    /// Need to verify whether a signature is valid.
+    #[from]
+    SignatureVerification(SignatureVerification),
     /// Must the set value of an offchain storage entry.
     #[from]
     ExternalOffchainStorageSet(ExternalOffchainStorageSet),
@@ -462,6 +510,7 @@ impl HostVm {
             HostVm::ExternalStorageRoot(inner) => inner.inner.into_prototype(),
             HostVm::ExternalStorageChangesRoot(inner) => inner.inner.into_prototype(),
             HostVm::ExternalStorageNextKey(inner) => inner.inner.into_prototype(),
+            HostVm::SignatureVerification(inner) => inner.inner.into_prototype(),
             HostVm::ExternalOffchainStorageSet(inner) => inner.inner.into_prototype(),
             HostVm::CallRuntimeVersion(inner) => inner.inner.into_prototype(),
             HostVm::StartStorageTransaction(inner) => inner.inner.into_prototype(),
@@ -785,6 +834,39 @@ impl ReadyToRun {
                 }}
             }
 
+            macro_rules! expect_pointer_constant_size_raw {
+                ($num:expr, $size:expr) => {{
+                    let ptr = match params[$num] {
+                        vm::WasmValue::I32(v) => u32::from_ne_bytes(v.to_ne_bytes()),
+                        v => {
+                            return HostVm::Error {
+                                error: Error::WrongParamTy {
+                                    function: host_fn.name(),
+                                    param_num: $num,
+                                    expected: vm::ValueType::I32,
+                                    actual: v.ty(),
+                                },
+                                prototype: self.inner.into_prototype(),
+                            }
+                        }
+                    };
+
+                    if u32::saturating_add($size, ptr) > self.inner.vm.memory_size() {
+                        return HostVm::Error {
+                            error: Error::ParamOutOfRange {
+                                function: host_fn.name(),
+                                param_num: $num,
+                                pointer: ptr,
+                                length: $size,
+                            },
+                            prototype: self.inner.into_prototype(),
+                        };
+                    }
+
+                    ptr
+                }};
+            }
+
             macro_rules! expect_u32 {
                 ($num:expr) => {{
                     match &params[$num] {
@@ -966,68 +1048,46 @@ impl ReadyToRun {
                 HostFunction::ext_crypto_ed25519_generate_version_1 => todo!(),
                 HostFunction::ext_crypto_ed25519_sign_version_1 => todo!(),
                 HostFunction::ext_crypto_ed25519_verify_version_1 => {
-                    let sig = expect_pointer_constant_size!(0, 64);
-                    let message = expect_pointer_size!(1);
-                    let pubkey = expect_pointer_constant_size!(2, 32);
-
-                    // TODO: copy overhead?
-                    let success = if let Ok(public_key) =
-                        ed25519_zebra::VerificationKey::try_from(&pubkey[..])
-                    {
-                        // TODO: copy overhead?
-                        let signature =
-                            ed25519_zebra::Signature::from(<[u8; 64]>::try_from(&sig[..]).unwrap());
-                        public_key.verify(&signature, &message).is_ok()
-                    } else {
-                        false
-                    };
-
-                    self = ReadyToRun {
-                        resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+                    let signature_ptr = expect_pointer_constant_size_raw!(0, 64);
+                    let (message_ptr, message_size) = expect_pointer_size_raw!(1);
+                    let public_key_ptr = expect_pointer_constant_size_raw!(2, 32);
+                    return HostVm::SignatureVerification(SignatureVerification {
+                        algorithm: SignatureVerificationAlgorithm::Ed25519,
+                        signature_ptr,
+                        public_key_ptr,
+                        message_ptr,
+                        message_size,
                         inner: self.inner,
-                    };
+                    });
                 }
                 HostFunction::ext_crypto_sr25519_public_keys_version_1 => todo!(),
                 HostFunction::ext_crypto_sr25519_generate_version_1 => todo!(),
                 HostFunction::ext_crypto_sr25519_sign_version_1 => todo!(),
                 HostFunction::ext_crypto_sr25519_verify_version_1 => {
-                    let sig = expect_pointer_constant_size!(0, 64);
-                    let message = expect_pointer_size!(1);
-                    let pubkey = expect_pointer_constant_size!(2, 32);
-
-                    // The `unwrap()` below can only panic if the input is the wrong length, which
-                    // we know can't happen.
-                    // TODO: copy overhead?
-                    let signing_public_key = schnorrkel::PublicKey::from_bytes(&pubkey).unwrap();
-                    let success = signing_public_key
-                        .verify_simple_preaudit_deprecated(b"substrate", &message, &sig)
-                        .is_ok();
-
-                    self = ReadyToRun {
-                        resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+                    let signature_ptr = expect_pointer_constant_size_raw!(0, 64);
+                    let (message_ptr, message_size) = expect_pointer_size_raw!(1);
+                    let public_key_ptr = expect_pointer_constant_size_raw!(2, 32);
+                    return HostVm::SignatureVerification(SignatureVerification {
+                        algorithm: SignatureVerificationAlgorithm::Sr25519V1,
+                        signature_ptr,
+                        public_key_ptr,
+                        message_ptr,
+                        message_size,
                         inner: self.inner,
-                    };
+                    });
                 }
                 HostFunction::ext_crypto_sr25519_verify_version_2 => {
-                    let sig = expect_pointer_constant_size!(0, 64);
-                    let message = expect_pointer_size!(1);
-                    let pubkey = expect_pointer_constant_size!(2, 32);
-
-                    // The two `unwrap()`s below can only panic if the input is the wrong length,
-                    // which we know can't happen.
-                    // TODO: copy overhead?
-                    let signing_public_key = schnorrkel::PublicKey::from_bytes(&pubkey).unwrap();
-                    // TODO: copy overhead?
-                    let signature = schnorrkel::Signature::from_bytes(&sig).unwrap();
-
-                    let success = signing_public_key
-                        .verify_simple(b"substrate", &message, &signature)
-                        .is_ok();
-
-                    self = ReadyToRun {
-                        resume_value: Some(vm::WasmValue::I32(if success { 1 } else { 0 })),
+                    let signature_ptr = expect_pointer_constant_size_raw!(0, 64);
+                    let (message_ptr, message_size) = expect_pointer_size_raw!(1);
+                    let public_key_ptr = expect_pointer_constant_size_raw!(2, 32);
+                    return HostVm::SignatureVerification(SignatureVerification {
+                        algorithm: SignatureVerificationAlgorithm::Sr25519V2,
+                        signature_ptr,
+                        public_key_ptr,
+                        message_ptr,
+                        message_size,
                         inner: self.inner,
-                    };
+                    });
                 }
                 HostFunction::ext_crypto_ecdsa_generate_version_1 => todo!(),
                 HostFunction::ext_crypto_secp256k1_ecdsa_recover_version_1 => {
@@ -1942,6 +2002,156 @@ impl fmt::Debug for ExternalStorageNextKey {
     }
 }
 
+/// Must verify whether a signature is correct.
+///
+/// The verification can either be performed by calling
+/// [`SignatureVerification::verify_and_resume`], or by the user of this module (for example in
+/// order to offload it to hardware-accelerated code) and reported back through
+/// [`SignatureVerification::resume_success`] or [`SignatureVerification::resume_failed`].
+pub struct SignatureVerification {
+    inner: Inner,
+
+    /// Cryptographic algorithm of the signature.
+    algorithm: SignatureVerificationAlgorithm,
+
+    /// Pointer to the signature. Guaranteed to be in range. The signature is always 64 bytes.
+    signature_ptr: u32,
+    /// Pointer to the public key. Guaranteed to be in range. The public key is always 32 bytes.
+    public_key_ptr: u32,
+    /// Pointer to the message. Guaranteed to be in range.
+    message_ptr: u32,
+    /// Size of the message. Guaranteed to be in range.
+    message_size: u32,
+}
+
+impl SignatureVerification {
+    /// Returns the cryptographic algorithm that the signature uses.
+    pub fn algorithm(&self) -> SignatureVerificationAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the message that the signature is expected to sign.
+    pub fn message(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner
+            .vm
+            .read_memory(self.message_ptr, self.message_size)
+            .unwrap()
+    }
+
+    /// Returns the signature. Always 64 bytes.
+    ///
+    /// > **Note**: Be aware that this signature is untrusted input and might not be part of the
+    /// >           set of valid signatures.
+    pub fn signature(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.vm.read_memory(self.signature_ptr, 64).unwrap()
+    }
+
+    /// Returns the public key the signature is against. Always 32 bytes.
+    ///
+    /// > **Note**: Be aware that this public key is untrusted input and might not be part of the
+    /// >           set of valid public keys.
+    pub fn public_key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.vm.read_memory(self.public_key_ptr, 32).unwrap()
+    }
+
+    /// Verifies the signature and resumes execution.
+    pub fn verify_and_resume(self) -> HostVm {
+        let success = match self.algorithm {
+            SignatureVerificationAlgorithm::Ed25519 => {
+                // TODO: copy overhead?
+                if let Ok(public_key) =
+                    ed25519_zebra::VerificationKey::try_from(self.public_key().as_ref())
+                {
+                    // The `unwrap()` below can only panic if the input is the wrong length, which
+                    // we know can't happen.
+                    let signature = ed25519_zebra::Signature::from(
+                        <[u8; 64]>::try_from(self.signature().as_ref()).unwrap(),
+                    );
+                    public_key
+                        .verify(&signature, self.message().as_ref())
+                        .is_ok()
+                } else {
+                    false
+                }
+            }
+            SignatureVerificationAlgorithm::Sr25519V1 => {
+                // The `unwrap()` below can only panic if the input is the wrong length, which
+                // we know can't happen.
+                let signing_public_key =
+                    schnorrkel::PublicKey::from_bytes(self.public_key().as_ref()).unwrap();
+                signing_public_key
+                    .verify_simple_preaudit_deprecated(
+                        b"substrate",
+                        self.message().as_ref(),
+                        self.signature().as_ref(),
+                    )
+                    .is_ok()
+            }
+            SignatureVerificationAlgorithm::Sr25519V2 => {
+                // The two `unwrap()`s below can only panic if the input is the wrong length,
+                // which we know can't happen.
+                let signing_public_key =
+                    schnorrkel::PublicKey::from_bytes(self.public_key().as_ref()).unwrap();
+                let signature =
+                    schnorrkel::Signature::from_bytes(self.signature().as_ref()).unwrap();
+                signing_public_key
+                    .verify_simple(b"substrate", self.message().as_ref(), &signature)
+                    .is_ok()
+            }
+        };
+
+        if success {
+            self.resume_success()
+        } else {
+            self.resume_failed()
+        }
+    }
+
+    /// Resumes the execution assuming that the signature is valid.
+    ///
+    /// > **Note**: You are strongly encouraged to call
+    /// >           [`SignatureVerification::verify_and_resume`] instead. This function is meant
+    /// >           to be used when the verification has been performed by other means.
+    pub fn resume_success(self) -> HostVm {
+        HostVm::ReadyToRun(ReadyToRun {
+            resume_value: Some(vm::WasmValue::I32(1)),
+            inner: self.inner,
+        })
+    }
+
+    /// Resumes the execution assuming that the signature is invalid.
+    ///
+    /// > **Note**: You are strongly encouraged to call
+    /// >           [`SignatureVerification::verify_and_resume`] instead. This function is meant
+    /// >           to be used when the verification has been performed by other means.
+    pub fn resume_failed(self) -> HostVm {
+        HostVm::ReadyToRun(ReadyToRun {
+            resume_value: Some(vm::WasmValue::I32(0)),
+            inner: self.inner,
+        })
+    }
+}
+
+impl fmt::Debug for SignatureVerification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SignatureVerification")
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+/// Cryptographic algorithm of a [`SignatureVerification`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SignatureVerificationAlgorithm {
+    /// Ed25519 signature, as used by `ext_crypto_ed25519_verify_version_1`.
+    Ed25519,
+    /// Sr25519 signature, as used by `ext_crypto_sr25519_verify_version_1`. Uses the deprecated
+    /// "pre-audit" verification algorithm of schnorrkel.
+    Sr25519V1,
+    /// Sr25519 signature, as used by `ext_crypto_sr25519_verify_version_2`.
+    Sr25519V2,
+}
+
 /// Must provide the runtime version obtained by calling the `Core_version` entry point of a Wasm
 /// blob.
 pub struct CallRuntimeVersion {
@@ -2109,6 +2319,9 @@ struct Inner {
     /// Value of `heap_pages` passed to [`HostVmPrototype::new`].
     heap_pages: HeapPages,
 
+    /// Value of `max_memory_pages` passed to [`HostVmPrototype::new_with_memory_limit`].
+    max_memory_pages: Option<u32>,
+
     /// If true, a transaction has been started using `ext_storage_start_transaction_version_1`.
     /// No further transaction start is allowed before the current one ends.
     within_storage_transaction: bool,
@@ -2237,6 +2450,7 @@ impl Inner {
             heap_base: self.heap_base,
             registered_functions: self.registered_functions,
             heap_pages: self.heap_pages,
+            max_memory_pages: self.max_memory_pages,
         }
     }
 }
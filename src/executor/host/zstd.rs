@@ -16,6 +16,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use alloc::{borrow::Cow, vec::Vec};
+use core::convert::TryFrom as _;
 
 mod tests;
 
@@ -23,12 +24,12 @@ mod tests;
 /// compression.
 ///
 /// This differs from the Wasm magic bytes, so real Wasm blobs will not have this prefix.
-pub(super) const ZSTD_PREFIX: [u8; 8] = [82, 188, 83, 118, 70, 219, 142, 5];
+pub(crate) const ZSTD_PREFIX: [u8; 8] = [82, 188, 83, 118, 70, 219, 142, 5];
 
 /// If the given blob starts with [`ZSTD_PREFIX`], decompresses it. Otherwise, passes it through.
 ///
 /// The output data shall not be larger than `max_allowed`, to avoid potential zip bombs.
-pub(super) fn zstd_decode_if_necessary(
+pub(crate) fn zstd_decode_if_necessary(
     data: &[u8],
     max_allowed: usize,
 ) -> Result<Cow<[u8]>, Error> {
@@ -47,22 +48,59 @@ pub(super) fn zstd_decode_if_necessary(
 /// Decompresses the given blob of zstd-compressed data.
 ///
 /// The output data shall not be larger than `max_allowed`, to avoid potential zip bombs.
+///
+/// The data is decompressed in chunks, and the output buffer is grown with fallible
+/// allocations. Not having enough memory to hold the decompressed data is reported as
+/// [`Error::AllocationFailed`].
 fn zstd_decode(mut data: &[u8], max_allowed: usize) -> Result<Vec<u8>, Error> {
+    /// Number of bytes to decompress before moving them to the output buffer.
+    const CHUNK_SIZE: usize = 1024 * 1024;
+
     let mut decoder = ruzstd::frame_decoder::FrameDecoder::new();
     decoder.init(&mut data).map_err(|_| Error::InvalidZstd)?;
 
-    match decoder.decode_blocks(
-        &mut data,
-        ruzstd::frame_decoder::BlockDecodingStrategy::UptoBytes(max_allowed),
-    ) {
-        Ok(true) => {}
-        Ok(false) => return Err(Error::TooLarge),
-        Err(_) => return Err(Error::InvalidZstd),
+    let mut out_buf = Vec::new();
+
+    // If the frame indicates the size of its content, the output buffer is allocated at once.
+    if let Some(content_size) = decoder
+        .content_size()
+        .and_then(|size| usize::try_from(size).ok())
+    {
+        if content_size > max_allowed {
+            return Err(Error::TooLarge);
+        }
+        out_buf
+            .try_reserve_exact(content_size)
+            .map_err(|_| Error::AllocationFailed { size: content_size })?;
     }
-    debug_assert!(decoder.is_finished());
 
-    // When the decoding is finished, `Some` is always guaranteed to be returned.
-    let out_buf = decoder.collect().unwrap();
+    loop {
+        let finished = decoder
+            .decode_blocks(
+                &mut data,
+                ruzstd::frame_decoder::BlockDecodingStrategy::UptoBytes(CHUNK_SIZE),
+            )
+            .map_err(|_| Error::InvalidZstd)?;
+
+        let available = decoder.can_collect();
+        if out_buf.len().saturating_add(available) > max_allowed {
+            return Err(Error::TooLarge);
+        }
+        out_buf
+            .try_reserve(available)
+            .map_err(|_| Error::AllocationFailed {
+                size: out_buf.len() + available,
+            })?;
+        decoder
+            .collect_to_writer(&mut out_buf)
+            .map_err(|_| Error::InvalidZstd)?;
+
+        if finished {
+            break;
+        }
+    }
+
+    debug_assert!(decoder.is_finished());
     debug_assert!(out_buf.len() <= max_allowed);
     Ok(out_buf)
 }
@@ -74,4 +112,10 @@ pub enum Error {
     InvalidZstd,
     /// The size of the code exceeds the maximum allowed length.
     TooLarge,
+    /// Not enough memory to allocate the buffer of the decompressed data.
+    #[display(fmt = "Failed to allocate {} bytes for the decompressed data", size)]
+    AllocationFailed {
+        /// Number of bytes that couldn't be allocated.
+        size: usize,
+    },
 }
@@ -0,0 +1,644 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Wasm virtual machine, with storage reads backed by a Merkle proof and storage writes kept in
+//! an in-memory overlay.
+//!
+//! This module is a variant of the [`runtime_host`] module. Rather than asking the user for the
+//! storage values and keys that the runtime accesses, it answers these requests by decoding a
+//! Merkle proof of the storage of the block the call is made against. Similarly to the
+//! [`runtime_host`] module, the modifications performed by the runtime are accumulated in an
+//! overlay and returned at the end of the execution, alongside with the Merkle value of the root
+//! of the storage trie with these modifications applied.
+//!
+//! This makes it possible for a node that doesn't have access to the storage, such as a light
+//! client, to perform calls that modify the storage, for example in order to dry-run a
+//! transaction or to produce a block locally.
+//!
+//! # Proof requirements
+//!
+//! The proof must contain all the trie nodes necessary in order to answer the storage accesses
+//! performed by the runtime. A call proof obtained from the network for the same function and
+//! parameters normally fulfills this condition.
+//!
+//! If the runtime calculates the storage trie root during the call, which is for example the
+//! case of `BlockBuilder_finalize_block`, the proof must contain the entire storage.
+//!
+//! [`ErrorDetail::MissingProofEntry`] is returned if one of these conditions isn't met.
+//!
+//! Calculating the new trie root at the end of the execution additionally requires knowing the
+//! nodes on the path to each modified key, which a call proof doesn't necessarily contain if a
+//! key is written without having been read beforehand. If that is the case, the execution
+//! succeeds but [`Success::new_storage_trie_root`] is `None`.
+
+use crate::{
+    executor::{host, runtime_host},
+    trie::{self, node_value, Nibble},
+};
+
+use alloc::{collections::BTreeMap, string::String, vec, vec::Vec};
+use core::{convert::TryFrom as _, iter, ops::Bound};
+use hashbrown::HashMap;
+
+/// Configuration for [`run`].
+pub struct Config<'a, TParams, TProof> {
+    /// Virtual machine to be run.
+    pub virtual_machine: host::HostVmPrototype,
+
+    /// Name of the function to be called.
+    pub function_to_call: &'a str,
+
+    /// Parameter of the call, as an iterator of bytes. The concatenation of bytes forms the
+    /// actual input.
+    pub parameter: TParams,
+
+    /// Merkle value of the root of the storage trie of the block the call is made against.
+    pub storage_trie_root: &'a [u8; 32],
+
+    /// List of node values of nodes of the storage trie. No specific order is required. See the
+    /// module-level documentation for what the proof must contain.
+    pub proof: TProof,
+
+    /// Initial state of [`Success::storage_top_trie_changes`]. The changes made during this
+    /// execution will be pushed over the value in this field.
+    ///
+    /// Can be used in order to chain multiple calls, for example `Core_initialize_block`
+    /// followed with `BlockBuilder_apply_extrinsic`.
+    pub storage_top_trie_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+
+    /// Initial state of [`Success::offchain_storage_changes`]. The changes made during this
+    /// execution will be pushed over the value in this field.
+    pub offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+}
+
+/// Runs the WebAssembly virtual machine until the end of the call.
+pub fn run<'p>(
+    config: Config<impl Iterator<Item = impl AsRef<[u8]>> + Clone, impl Iterator<Item = &'p [u8]>>,
+) -> Result<Success, Error> {
+    let trie = match ProofTrie::decode(config.storage_trie_root, config.proof) {
+        Ok(trie) => trie,
+        Err(()) => {
+            return Err(Error {
+                detail: ErrorDetail::InvalidProof,
+                prototype: config.virtual_machine,
+            })
+        }
+    };
+
+    let mut execution = match runtime_host::run(runtime_host::Config {
+        virtual_machine: config.virtual_machine,
+        function_to_call: config.function_to_call,
+        parameter: config.parameter,
+        top_trie_root_calculation_cache: None,
+        storage_top_trie_changes: config.storage_top_trie_changes,
+        offchain_storage_changes: config.offchain_storage_changes,
+    }) {
+        Ok(execution) => execution,
+        Err((error, prototype)) => {
+            return Err(Error {
+                detail: ErrorDetail::StartError(error),
+                prototype,
+            })
+        }
+    };
+
+    loop {
+        execution = match execution {
+            runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                let new_storage_trie_root = trie
+                    .root_with_changes(
+                        success
+                            .storage_top_trie_changes
+                            .iter()
+                            .map(|(key, value)| (&key[..], value.as_ref().map(|v| &v[..]))),
+                    )
+                    .ok();
+
+                return Ok(Success {
+                    virtual_machine: success.virtual_machine,
+                    storage_top_trie_changes: success.storage_top_trie_changes,
+                    offchain_storage_changes: success.offchain_storage_changes,
+                    new_storage_trie_root,
+                    logs: success.logs,
+                });
+            }
+
+            runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                return Err(Error {
+                    detail: ErrorDetail::Execution(error.detail),
+                    prototype: error.prototype,
+                })
+            }
+
+            runtime_host::RuntimeHostVm::StorageGet(req) => {
+                let key = req.key_as_vec();
+                match trie.storage_value(&key) {
+                    Ok(value) => req.inject_value(value.map(iter::once)),
+                    Err(MissingProofEntry) => {
+                        return Err(Error {
+                            detail: ErrorDetail::MissingProofEntry { key },
+                            prototype: runtime_host::RuntimeHostVm::StorageGet(req)
+                                .into_prototype(),
+                        })
+                    }
+                }
+            }
+
+            runtime_host::RuntimeHostVm::PrefixKeys(req) => {
+                let prefix = req.prefix().as_ref().to_vec();
+                match trie.prefix_keys(&prefix) {
+                    Ok(keys) => req.inject_keys(keys.into_iter()),
+                    Err(MissingProofEntry) => {
+                        return Err(Error {
+                            detail: ErrorDetail::MissingProofEntry { key: prefix },
+                            prototype: runtime_host::RuntimeHostVm::PrefixKeys(req)
+                                .into_prototype(),
+                        })
+                    }
+                }
+            }
+
+            runtime_host::RuntimeHostVm::NextKey(req) => {
+                let key = req.key().as_ref().to_vec();
+                match trie.next_key(&key) {
+                    Ok(key) => req.inject_key(key),
+                    Err(MissingProofEntry) => {
+                        return Err(Error {
+                            detail: ErrorDetail::MissingProofEntry { key },
+                            prototype: runtime_host::RuntimeHostVm::NextKey(req).into_prototype(),
+                        })
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Execution is successful.
+#[derive(Debug)]
+pub struct Success {
+    /// Contains the output value of the runtime, and the virtual machine that was passed at
+    /// initialization.
+    pub virtual_machine: runtime_host::SuccessVirtualMachine,
+    /// List of changes to the storage top trie that the call performs, including the ones passed
+    /// through [`Config::storage_top_trie_changes`].
+    pub storage_top_trie_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+    /// List of changes to the offchain storage that the call performs, including the ones passed
+    /// through [`Config::offchain_storage_changes`].
+    pub offchain_storage_changes: HashMap<Vec<u8>, Option<Vec<u8>>, fnv::FnvBuildHasher>,
+    /// Merkle value of the root of the storage trie after
+    /// [`Success::storage_top_trie_changes`] have been applied. `None` if the proof doesn't
+    /// contain enough information to calculate it.
+    pub new_storage_trie_root: Option<[u8; 32]>,
+    /// Concatenation of all the log messages printed by the runtime.
+    pub logs: String,
+}
+
+/// Error that can happen during the execution.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "{}", detail)]
+pub struct Error {
+    /// Exact error that happened.
+    pub detail: ErrorDetail,
+    /// Prototype of the virtual machine that was passed through [`Config::virtual_machine`].
+    pub prototype: host::HostVmPrototype,
+}
+
+/// See [`Error::detail`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum ErrorDetail {
+    /// Error while starting the virtual machine.
+    #[display(fmt = "Error while starting Wasm VM: {}", _0)]
+    StartError(host::StartErr),
+    /// Error during the execution of the call.
+    Execution(runtime_host::ErrorDetail),
+    /// The proof couldn't be decoded or doesn't contain the root of the storage trie.
+    InvalidProof,
+    /// The proof doesn't contain enough information to perform the call.
+    #[display(fmt = "Proof is missing the storage entry of 0x{}", "hex::encode(key)")]
+    MissingProofEntry {
+        /// Key whose storage value, or prefix whose list of keys, couldn't be determined from
+        /// the proof. When looking for the key that follows a given key, contains this key.
+        key: Vec<u8>,
+    },
+}
+
+/// Error returned by the methods of [`ProofTrie`] when the proof doesn't contain enough
+/// information.
+struct MissingProofEntry;
+
+/// Subset of a storage trie, decoded from a Merkle proof.
+struct ProofTrie<'p> {
+    /// Storage entries found in the proof.
+    entries: BTreeMap<Vec<u8>, &'p [u8]>,
+
+    /// Children of the nodes of the proof whose node value is missing from the proof, indexed by
+    /// the key of their parent followed with their child index. The partial key of these
+    /// children, and thus the content of their subtree, is unknown. Values are the Merkle values
+    /// of these children.
+    unknown_subtrees: BTreeMap<Vec<Nibble>, &'p [u8]>,
+}
+
+impl<'p> ProofTrie<'p> {
+    /// Decodes the given proof, starting from the node whose Merkle value is `trie_root`.
+    fn decode(trie_root: &[u8; 32], proof: impl Iterator<Item = &'p [u8]>) -> Result<Self, ()> {
+        let nodes = proof
+            .map(|node_value| {
+                let hash = blake2_rfc::blake2b::blake2b(32, &[], node_value);
+                (<[u8; 32]>::try_from(hash.as_bytes()).unwrap(), node_value)
+            })
+            .collect::<HashMap<_, _, fnv::FnvBuildHasher>>();
+
+        let mut entries = BTreeMap::new();
+        let mut unknown_subtrees = BTreeMap::new();
+
+        // List of node values to decode, with the key of their parent followed with their child
+        // index.
+        let mut to_decode = vec![(Vec::new(), *nodes.get(trie_root).ok_or(())?)];
+
+        while let Some((mut key, node_value)) = to_decode.pop() {
+            let node = node_value::decode(node_value).map_err(|_| ())?;
+            key.extend(node.partial_key());
+
+            for (child_index, child) in node.children.iter().enumerate() {
+                let child = match child {
+                    Some(child) => *child,
+                    None => continue,
+                };
+
+                let mut child_key = key.clone();
+                child_key.push(Nibble::try_from(u8::try_from(child_index).unwrap()).unwrap());
+
+                // Merkle values shorter than 32 bytes are the node value of the child.
+                if child.len() < 32 {
+                    to_decode.push((child_key, child));
+                    continue;
+                }
+
+                let child_hash = <[u8; 32]>::try_from(child).map_err(|_| ())?;
+                if let Some(child_node_value) = nodes.get(&child_hash) {
+                    to_decode.push((child_key, *child_node_value));
+                } else {
+                    unknown_subtrees.insert(child_key, child);
+                }
+            }
+
+            if let Some(storage_value) = node.storage_value {
+                // Storage keys always have an even number of nibbles.
+                if key.len() % 2 != 0 {
+                    return Err(());
+                }
+
+                let key = trie::nibbles_to_bytes_extend(key.into_iter()).collect::<Vec<_>>();
+                entries.insert(key, storage_value);
+            }
+        }
+
+        Ok(ProofTrie {
+            entries,
+            unknown_subtrees,
+        })
+    }
+
+    /// Returns the storage value associated with the given key.
+    fn storage_value(&self, key: &[u8]) -> Result<Option<&'p [u8]>, MissingProofEntry> {
+        self.ensure_known(&to_nibbles(key))?;
+        Ok(self.entries.get(key).copied())
+    }
+
+    /// Returns the list of keys that start with the given prefix.
+    fn prefix_keys(&self, prefix: &[u8]) -> Result<Vec<&[u8]>, MissingProofEntry> {
+        let prefix_nibbles = to_nibbles(prefix);
+        self.ensure_known(&prefix_nibbles)?;
+
+        // Unknown subtrees within the prefix might contain additional keys.
+        if self
+            .unknown_subtrees
+            .range::<[Nibble], _>((Bound::Included(&prefix_nibbles[..]), Bound::Unbounded))
+            .next()
+            .map_or(false, |(key, _)| key.starts_with(&prefix_nibbles))
+        {
+            return Err(MissingProofEntry);
+        }
+
+        Ok(self
+            .entries
+            .range::<[u8], _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, _)| &key[..])
+            .collect())
+    }
+
+    /// Returns the key that immediately follows the given key, if any.
+    fn next_key(&self, key: &[u8]) -> Result<Option<&[u8]>, MissingProofEntry> {
+        let key_nibbles = to_nibbles(key);
+        self.ensure_known(&key_nibbles)?;
+
+        let next = self
+            .entries
+            .range::<[u8], _>((Bound::Excluded(key), Bound::Unbounded))
+            .next()
+            .map(|(key, _)| &key[..]);
+
+        // An unknown subtree located between `key` and `next` might contain the actual next key.
+        let next_nibbles = next.map(to_nibbles);
+        let upper_bound = match &next_nibbles {
+            Some(next_nibbles) => Bound::Excluded(&next_nibbles[..]),
+            None => Bound::Unbounded,
+        };
+        if self
+            .unknown_subtrees
+            .range::<[Nibble], _>((Bound::Excluded(&key_nibbles[..]), upper_bound))
+            .next()
+            .is_some()
+        {
+            return Err(MissingProofEntry);
+        }
+
+        Ok(next)
+    }
+
+    /// Calculates the Merkle value of the root of the trie after the given changes have been
+    /// applied to it.
+    fn root_with_changes<'a>(
+        &'a self,
+        changes: impl Iterator<Item = (&'a [u8], Option<&'a [u8]>)>,
+    ) -> Result<[u8; 32], MissingProofEntry> {
+        let mut items = self
+            .entries
+            .iter()
+            .map(|(key, value)| (to_nibbles(key), TrieItem::StorageValue(value)))
+            .collect::<BTreeMap<_, _>>();
+
+        for (key, value) in changes {
+            let key = to_nibbles(key);
+            self.ensure_known(&key)?;
+            if let Some(value) = value {
+                items.insert(key, TrieItem::StorageValue(value));
+            } else {
+                items.remove(&key);
+            }
+        }
+
+        items.extend(
+            self.unknown_subtrees
+                .iter()
+                .map(|(key, merkle_value)| (key.clone(), TrieItem::UnknownSubtree(merkle_value))),
+        );
+
+        if items.is_empty() {
+            return Ok(trie::empty_trie_merkle_value());
+        }
+
+        let items = items.into_iter().collect::<Vec<_>>();
+        Ok(node_merkle_value(&items, None)?.into())
+    }
+
+    /// Returns an error if `key` is within an unknown subtree.
+    fn ensure_known(&self, key: &[Nibble]) -> Result<(), MissingProofEntry> {
+        if (0..=key.len()).any(|n| self.unknown_subtrees.contains_key(&key[..n])) {
+            Err(MissingProofEntry)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Element of the trie passed to [`node_merkle_value`].
+#[derive(Copy, Clone)]
+enum TrieItem<'a> {
+    /// Storage value of the key.
+    StorageValue(&'a [u8]),
+    /// The key is the position of a subtree whose content is unknown. Contains the Merkle value
+    /// of the root of this subtree.
+    UnknownSubtree(&'a [u8]),
+}
+
+/// Calculates the Merkle value of the node at the root of the given list of items, which must
+/// be ordered by key and non-empty.
+///
+/// `position` is `None` for the root node of the trie, or the number of nibbles of the key of
+/// the parent of the node plus one.
+fn node_merkle_value(
+    items: &[(Vec<Nibble>, TrieItem)],
+    position: Option<usize>,
+) -> Result<node_value::Output, MissingProofEntry> {
+    debug_assert!(!items.is_empty());
+
+    // An unknown subtree alone can be left untouched, provided that its parent hasn't changed.
+    if let [(key, TrieItem::UnknownSubtree(merkle_value))] = items {
+        return if position == Some(key.len()) {
+            Ok(node_value::Output::from_bytes(merkle_value))
+        } else {
+            Err(MissingProofEntry)
+        };
+    }
+
+    // The key of the node is the longest prefix shared by all the items. Since the items are
+    // ordered, it is the one shared by the first and last items.
+    let first_key = &items[0].0;
+    let node_key_len = first_key
+        .iter()
+        .zip(items[items.len() - 1].0.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut remaining = items;
+    let mut stored_value = None;
+    if remaining[0].0.len() == node_key_len {
+        match remaining[0].1 {
+            TrieItem::StorageValue(value) => stored_value = Some(value),
+            // All the other items are within the unknown subtree.
+            TrieItem::UnknownSubtree(_) => return Err(MissingProofEntry),
+        }
+        remaining = &remaining[1..];
+    }
+
+    let mut children: [Option<node_value::Output>; 16] = Default::default();
+    while let Some((key, _)) = remaining.first() {
+        let child_index = key[node_key_len];
+        let num_in_child = remaining
+            .iter()
+            .take_while(|(key, _)| key[node_key_len] == child_index)
+            .count();
+        children[usize::from(u8::from(child_index))] = Some(node_merkle_value(
+            &remaining[..num_in_child],
+            Some(node_key_len + 1),
+        )?);
+        remaining = &remaining[num_in_child..];
+    }
+
+    let ty = match position {
+        None => node_value::NodeTy::Root {
+            key: first_key[..node_key_len].iter().copied(),
+        },
+        Some(position) => node_value::NodeTy::NonRoot {
+            partial_key: first_key[position..node_key_len].iter().copied(),
+        },
+    };
+
+    Ok(node_value::calculate_merkle_root(node_value::Config {
+        ty,
+        children: children.iter().map(|child| child.as_ref()),
+        stored_value,
+    }))
+}
+
+fn to_nibbles(key: &[u8]) -> Vec<Nibble> {
+    trie::bytes_to_nibbles(key.iter().copied()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProofTrie;
+    use crate::trie;
+    use core::{convert::TryFrom as _, iter};
+
+    fn hash(data: &[u8]) -> [u8; 32] {
+        <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes()).unwrap()
+    }
+
+    fn root_of(entries: &[(&[u8], &[u8])]) -> [u8; 32] {
+        let mut trie = trie::Trie::new();
+        for (key, value) in entries {
+            trie.insert(key, *value);
+        }
+        trie.root_merkle_value(None)
+    }
+
+    #[test]
+    fn full_proof() {
+        // Trie containing `0x10 => "a"` and `0x20 => "b"`. Both children of the root node are
+        // small enough to be inlined.
+        let root_node = [
+            0x80, 0x06, 0x00, 0x10, 0x41, 0x00, 0x04, b'a', 0x10, 0x41, 0x00, 0x04, b'b',
+        ];
+        let trie_root = hash(&root_node);
+        assert_eq!(trie_root, root_of(&[(&[0x10], b"a"), (&[0x20], b"b")]));
+
+        let trie = ProofTrie::decode(&trie_root, iter::once(&root_node[..])).unwrap();
+        assert_eq!(trie.storage_value(&[0x10]).ok().unwrap(), Some(&b"a"[..]));
+        assert_eq!(trie.storage_value(&[0x30]).ok().unwrap(), None);
+        assert_eq!(trie.next_key(&[0x10]).ok().unwrap(), Some(&[0x20][..]));
+        assert_eq!(trie.next_key(&[0x20]).ok().unwrap(), None);
+        assert_eq!(trie.prefix_keys(&[]).ok().unwrap().len(), 2);
+
+        assert_eq!(
+            trie.root_with_changes(iter::empty()).ok().unwrap(),
+            trie_root
+        );
+        assert_eq!(
+            trie.root_with_changes(
+                vec![
+                    (&[0x10][..], None),
+                    (&[0x20][..], Some(&b"c"[..])),
+                    (&[0x30, 0x01][..], Some(&b"d"[..])),
+                ]
+                .into_iter()
+            )
+            .ok()
+            .unwrap(),
+            root_of(&[(&[0x20], b"c"), (&[0x30, 0x01], b"d")])
+        );
+        assert_eq!(
+            trie.root_with_changes(vec![(&[0x10][..], None), (&[0x20][..], None)].into_iter())
+                .ok()
+                .unwrap(),
+            trie::empty_trie_merkle_value()
+        );
+    }
+
+    #[test]
+    fn unknown_subtree() {
+        // Trie containing `0x10 => [0xff; 40]` and `0x20 => "b"`. The node of `0x10` is hashed
+        // and missing from the proof.
+        let big_value = [0xff; 40];
+        let child_node = {
+            let mut node = vec![0x41, 0x00, 40 << 2];
+            node.extend_from_slice(&big_value);
+            node
+        };
+        let root_node = {
+            let mut node = vec![0x80, 0x06, 0x00, 32 << 2];
+            node.extend_from_slice(&hash(&child_node));
+            node.extend_from_slice(&[0x10, 0x41, 0x00, 0x04, b'b']);
+            node
+        };
+        let trie_root = hash(&root_node);
+        assert_eq!(
+            trie_root,
+            root_of(&[(&[0x10], &big_value[..]), (&[0x20], b"b")])
+        );
+
+        let trie = ProofTrie::decode(&trie_root, iter::once(&root_node[..])).unwrap();
+        assert!(trie.storage_value(&[0x10]).is_err());
+        assert!(trie.storage_value(&[0x15]).is_err());
+        assert_eq!(trie.storage_value(&[0x20]).ok().unwrap(), Some(&b"b"[..]));
+        assert!(trie.next_key(&[0x00]).is_err());
+        assert_eq!(trie.next_key(&[0x20]).ok().unwrap(), None);
+        assert!(trie.prefix_keys(&[]).is_err());
+        assert_eq!(trie.prefix_keys(&[0x20]).ok().unwrap(), vec![&[0x20][..]]);
+
+        assert_eq!(
+            trie.root_with_changes(
+                vec![
+                    (&[0x20][..], Some(&b"c"[..])),
+                    (&[0x00][..], Some(&b"d"[..]))
+                ]
+                .into_iter()
+            )
+            .ok()
+            .unwrap(),
+            root_of(&[(&[0x00], b"d"), (&[0x10], &big_value[..]), (&[0x20], b"c")])
+        );
+
+        // Modifying a key within the unknown subtree.
+        assert!(trie
+            .root_with_changes(iter::once((&[0x15][..], Some(&b"c"[..]))))
+            .is_err());
+
+        // Removing `0x20` would require knowing the partial key of the unknown subtree.
+        assert!(trie
+            .root_with_changes(iter::once((&[0x20][..], None)))
+            .is_err());
+
+        // Proof with the missing node included.
+        let trie = ProofTrie::decode(
+            &trie_root,
+            vec![&root_node[..], &child_node[..]].into_iter(),
+        )
+        .unwrap();
+        assert_eq!(
+            trie.storage_value(&[0x10]).ok().unwrap(),
+            Some(&big_value[..])
+        );
+        assert_eq!(
+            trie.root_with_changes(iter::once((&[0x20][..], None)))
+                .ok()
+                .unwrap(),
+            root_of(&[(&[0x10], &big_value[..])])
+        );
+    }
+
+    #[test]
+    fn invalid_proof() {
+        assert!(ProofTrie::decode(&[0; 32], iter::once(&[0x80, 0x06, 0x00][..])).is_err());
+
+        let truncated = [0x80, 0x06, 0x00, 0x10, 0x41];
+        assert!(ProofTrie::decode(&hash(&truncated), iter::once(&truncated[..])).is_err());
+    }
+}
@@ -122,6 +122,8 @@ pub enum RuntimeHostVm {
     NextKey(NextKey),
     /// Fetching the storage trie root is required in order to continue.
     StorageRoot(StorageRoot),
+    /// Verifying whether a signature is correct is required in order to continue.
+    SignatureVerification(SignatureVerification),
 }
 
 impl RuntimeHostVm {
@@ -133,6 +135,7 @@ impl RuntimeHostVm {
             RuntimeHostVm::StorageGet(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::NextKey(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::StorageRoot(inner) => inner.inner.vm.into_prototype(),
+            RuntimeHostVm::SignatureVerification(inner) => inner.inner.vm.into_prototype(),
         }
     }
 }
@@ -250,6 +253,102 @@ impl StorageRoot {
     }
 }
 
+/// Verifying whether a signature is correct is required in order to continue.
+#[must_use]
+pub struct SignatureVerification {
+    inner: Inner,
+}
+
+impl SignatureVerification {
+    /// Returns the cryptographic algorithm that the signature uses.
+    pub fn algorithm(&self) -> host::SignatureVerificationAlgorithm {
+        match &self.inner.vm {
+            host::HostVm::SignatureVerification(req) => req.algorithm(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the message that the signature is expected to sign.
+    pub fn message(&'_ self) -> impl AsRef<[u8]> + '_ {
+        match &self.inner.vm {
+            host::HostVm::SignatureVerification(req) => req.message(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the signature. Always 64 bytes.
+    ///
+    /// > **Note**: Be aware that this signature is untrusted input and might not be part of the
+    /// >           set of valid signatures.
+    pub fn signature(&'_ self) -> impl AsRef<[u8]> + '_ {
+        match &self.inner.vm {
+            host::HostVm::SignatureVerification(req) => req.signature(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the public key the signature is against. Always 32 bytes.
+    ///
+    /// > **Note**: Be aware that this public key is untrusted input and might not be part of the
+    /// >           set of valid public keys.
+    pub fn public_key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        match &self.inner.vm {
+            host::HostVm::SignatureVerification(req) => req.public_key(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Verifies the signature and resumes execution.
+    pub fn verify_and_resume(mut self) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::SignatureVerification(req) => {
+                self.inner.vm = req.verify_and_resume();
+            }
+
+            // We only create a `SignatureVerification` if the state is the one above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+
+    /// Resumes the execution assuming that the signature is valid.
+    ///
+    /// > **Note**: You are strongly encouraged to call
+    /// >           [`SignatureVerification::verify_and_resume`] instead. This function is meant
+    /// >           to be used when the verification has been performed by other means.
+    pub fn resume_success(mut self) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::SignatureVerification(req) => {
+                self.inner.vm = req.resume_success();
+            }
+
+            // We only create a `SignatureVerification` if the state is the one above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+
+    /// Resumes the execution assuming that the signature is invalid.
+    ///
+    /// > **Note**: You are strongly encouraged to call
+    /// >           [`SignatureVerification::verify_and_resume`] instead. This function is meant
+    /// >           to be used when the verification has been performed by other means.
+    pub fn resume_failed(mut self) -> RuntimeHostVm {
+        match self.inner.vm {
+            host::HostVm::SignatureVerification(req) => {
+                self.inner.vm = req.resume_failed();
+            }
+
+            // We only create a `SignatureVerification` if the state is the one above.
+            _ => unreachable!(),
+        };
+
+        self.inner.run()
+    }
+}
+
 /// Implementation detail of the execution. Shared by all the variants of [`RuntimeHostVm`]
 /// other than [`RuntimeHostVm::Finished`].
 struct Inner {
@@ -329,6 +428,13 @@ impl Inner {
                     return RuntimeHostVm::StorageRoot(StorageRoot { inner: self });
                 }
 
+                host::HostVm::SignatureVerification(req) => {
+                    self.vm = req.into();
+                    return RuntimeHostVm::SignatureVerification(SignatureVerification {
+                        inner: self,
+                    });
+                }
+
                 host::HostVm::LogEmit(req) => {
                     // We add a hardcoded limit to the logs generated by the runtime in order to
                     // make sure that there is no memory leak. In practice, the runtime should
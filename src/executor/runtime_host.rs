@@ -673,6 +673,10 @@ impl Inner {
                     });
                 }
 
+                host::HostVm::SignatureVerification(req) => {
+                    self.vm = req.verify_and_resume();
+                }
+
                 host::HostVm::ExternalOffchainStorageSet(req) => {
                     self.offchain_storage_changes.insert(
                         req.key().as_ref().to_vec(),
@@ -151,20 +151,31 @@ impl VirtualMachinePrototype {
     /// functions, this number will be returned back in order for the user to know how to handle
     /// the call.
     ///
+    /// If `max_memory_pages` is `Some`, an error is returned if the memory of the virtual
+    /// machine, in number of 64 kiB pages, would exceed the given value. The memory of the virtual
+    /// machine consists of the initial size of the memory of the module, plus `heap_pages`.
+    /// Afterwards, the `memory.grow` instruction can't grow the memory beyond this value.
+    ///
     /// See [the module-level documentation](..) for an explanation of the parameters.
     pub fn new(
         module: &Module,
         heap_pages: HeapPages,
+        max_memory_pages: Option<u32>,
         symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
         Ok(VirtualMachinePrototype {
             inner: match &module.inner {
                 ModuleInner::Interpreter(module) => VirtualMachinePrototypeInner::Interpreter(
-                    interpreter::InterpreterPrototype::new(module, heap_pages, symbols)?,
+                    interpreter::InterpreterPrototype::new(
+                        module,
+                        heap_pages,
+                        max_memory_pages,
+                        symbols,
+                    )?,
                 ),
                 #[cfg(all(target_arch = "x86_64", feature = "std"))]
                 ModuleInner::Jit(module) => VirtualMachinePrototypeInner::Jit(
-                    jit::JitPrototype::new(module, heap_pages, symbols)?,
+                    jit::JitPrototype::new(module, heap_pages, max_memory_pages, symbols)?,
                 ),
             },
         })
@@ -607,10 +618,60 @@ pub enum ExecOutcome {
     },
 }
 
-/// Opaque error that happened during execution, such as an `unreachable` instruction.
-#[derive(Debug, derive_more::Display, Clone)]
-#[display(fmt = "{}", _0)]
-pub struct Trap(String);
+/// Error that happened during execution, such as an `unreachable` instruction.
+#[derive(Debug, Clone)]
+pub struct Trap {
+    message: String,
+    backtrace: Vec<TrapFrame>,
+}
+
+impl Trap {
+    /// Returns a human-readable description of the reason for the trap.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns the WebAssembly call stack at the moment of the trap, starting with the innermost
+    /// function.
+    ///
+    /// > **Note**: The backtrace is only available when executing with the JIT. It is always
+    /// >           empty when executing with the interpreter, as the latter doesn't expose its
+    /// >           call stack.
+    pub fn backtrace(&self) -> &[TrapFrame] {
+        &self.backtrace
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if !self.backtrace.is_empty() {
+            write!(f, "\nWasm backtrace:")?;
+            for (n, frame) in self.backtrace.iter().enumerate() {
+                write!(f, "\n{:>4}: {}", n, frame)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Function in the backtrace of a [`Trap`].
+#[derive(Debug, Clone)]
+pub struct TrapFrame {
+    /// Index of the function in the function index space of the module.
+    pub function_index: u32,
+    /// Demangled name of the function, if the module contains a name section.
+    pub function_name: Option<String>,
+}
+
+impl fmt::Display for TrapFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.function_name {
+            Some(name) => write!(f, "{} (function #{})", name, self.function_index),
+            None => write!(f, "<unknown> (function #{})", self.function_index),
+        }
+    }
+}
 
 /// Error that can happen when initializing a [`VirtualMachinePrototype`].
 #[derive(Debug, derive_more::Display)]
@@ -626,6 +687,10 @@ pub enum NewErr {
     IndirectTableIsntTable,
     /// Failed to allocate memory for the virtual machine.
     CouldntAllocateMemory,
+    /// The memory of the virtual machine would exceed the limit passed to
+    /// [`VirtualMachinePrototype::new`].
+    #[display(fmt = "Memory of the virtual machine would exceed the configured limit.")]
+    MemoryLimitExceeded,
 }
 
 /// Error that can happen when calling [`VirtualMachinePrototype::start`].
@@ -692,4 +757,83 @@ mod tests {
         test::<super::VirtualMachine>();
         test::<super::VirtualMachinePrototype>();
     }
+
+    /// Module whose `main` export calls a function named `inner` that executes an `unreachable`
+    /// instruction. Contains a name section.
+    const TRAPPING_MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // Header.
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // Types: `() -> ()`.
+        0x03, 0x03, 0x02, 0x00, 0x00, // Functions: two of type 0.
+        0x07, 0x08, 0x01, 0x04, b'm', b'a', b'i', b'n', 0x00, 0x01, // Exports: `main`.
+        0x0a, 0x0a, 0x02, // Code: two bodies.
+        0x03, 0x00, 0x00, 0x0b, // `inner`: `unreachable`.
+        0x04, 0x00, 0x10, 0x00, 0x0b, // `main`: `call 0`.
+        0x00, 0x15, 0x04, b'n', b'a', b'm', b'e', // Custom section `name`.
+        0x01, 0x0e, 0x02, // Function names subsection, with two names.
+        0x00, 0x05, b'i', b'n', b'n', b'e', b'r', // Function 0 is `inner`.
+        0x01, 0x04, b'm', b'a', b'i', b'n', // Function 1 is `main`.
+    ];
+
+    fn run_trapping_module(exec_hint: super::ExecHint) -> super::Trap {
+        let module = super::Module::new(TRAPPING_MODULE, exec_hint).unwrap();
+        let prototype = super::VirtualMachinePrototype::new(
+            &module,
+            super::HeapPages::new(0),
+            None,
+            |_, _, _| Err(()),
+        )
+        .unwrap();
+        let mut vm = prototype.start("main", &[]).unwrap();
+        match vm.run(None).unwrap() {
+            super::ExecOutcome::Finished {
+                return_value: Err(trap),
+            } => trap,
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn trap_interpreter() {
+        let trap = run_trapping_module(super::ExecHint::Oneshot);
+        assert!(!trap.message().is_empty());
+        assert!(trap.backtrace().is_empty());
+    }
+
+    /// Module that defines a memory of one page without maximum, and whose `grow` export grows
+    /// this memory by four pages and returns the result of `memory.grow`.
+    const GROWING_MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // Header.
+        0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7f, // Types: `() -> i32`.
+        0x03, 0x02, 0x01, 0x00, // Functions: one of type 0.
+        0x05, 0x03, 0x01, 0x00, 0x01, // Memories: one page, no maximum.
+        0x07, 0x08, 0x01, 0x04, b'g', b'r', b'o', b'w', 0x00, 0x00, // Exports: `grow`.
+        0x0a, 0x08, 0x01, // Code: one body.
+        0x06, 0x00, 0x41, 0x04, 0x40, 0x00, 0x0b, // `i32.const 4`, `memory.grow`.
+    ];
+
+    fn grow_memory(max_memory_pages: Option<u32>) -> i32 {
+        let module = super::Module::new(GROWING_MODULE, super::ExecHint::Oneshot).unwrap();
+        let prototype = super::VirtualMachinePrototype::new(
+            &module,
+            super::HeapPages::new(0),
+            max_memory_pages,
+            |_, _, _| Err(()),
+        )
+        .unwrap();
+        let mut vm = prototype.start("grow", &[]).unwrap();
+        match vm.run(None).unwrap() {
+            super::ExecOutcome::Finished {
+                return_value: Ok(Some(super::WasmValue::I32(result))),
+            } => result,
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn memory_grow_limit() {
+        // `memory.grow` returns the previous number of pages on success, and -1 on failure.
+        assert_eq!(grow_memory(None), 1);
+        assert_eq!(grow_memory(Some(5)), 1);
+        assert_eq!(grow_memory(Some(4)), -1);
+    }
 }
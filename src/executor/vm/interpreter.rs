@@ -22,9 +22,12 @@ use super::{
     Signature, StartErr, Trap, ValueType, WasmValue,
 };
 
-use alloc::{borrow::ToOwned as _, boxed::Box, format, string::ToString as _, sync::Arc, vec::Vec};
+use alloc::{
+    borrow::ToOwned as _, boxed::Box, format, string::ToString as _, sync::Arc, vec, vec::Vec,
+};
 use core::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
+    cmp,
     convert::{TryFrom, TryInto as _},
     fmt,
 };
@@ -37,21 +40,92 @@ pub struct Module {
     // wasmtime happened to no longer use internal reference counting, this `Arc` should be
     // removed.
     inner: Arc<wasmi::Module>,
+
+    /// `true` if the module originally defined its own memory, which has been turned into an
+    /// imported memory. See [`import_own_memory`].
+    own_memory: bool,
 }
 
 impl Module {
     /// See [`super::Module::new`].
     pub fn new(module_bytes: impl AsRef<[u8]>) -> Result<Self, NewErr> {
-        let module = wasmi::Module::from_buffer(module_bytes.as_ref())
+        // `wasmi` allocates infallibly, and the compiled module is at least as large as the
+        // code.
+        if !crate::util::can_allocate(module_bytes.as_ref().len()) {
+            return Err(NewErr::CouldntAllocateMemory);
+        }
+
+        let mut module =
+            parity_wasm::elements::deserialize_buffer::<parity_wasm::elements::Module>(
+                module_bytes.as_ref(),
+            )
+            .map_err(|err| ModuleError(err.to_string()))
+            .map_err(NewErr::ModuleError)?;
+
+        let own_memory = import_own_memory(&mut module)?;
+
+        let module = wasmi::Module::from_parity_wasm_module(module)
             .map_err(|err| ModuleError(err.to_string()))
             .map_err(NewErr::ModuleError)?;
 
         Ok(Module {
             inner: Arc::new(module),
+            own_memory,
         })
     }
 }
 
+/// If the given module defines its own memory, removes this definition and adds instead an
+/// import of a memory named `memory` with the same limits. Returns `true` if that is the case.
+///
+/// The maximum size of a memory defined by the module can't be modified after compilation, and
+/// `memory.grow` would ignore the limit passed when instantiating the module. The maximum size
+/// of an imported memory, however, is chosen when it is allocated.
+fn import_own_memory(module: &mut parity_wasm::elements::Module) -> Result<bool, NewErr> {
+    use parity_wasm::elements;
+
+    let memory_type = match module.memory_section().map(|section| section.entries()) {
+        None | Some([]) => return Ok(false),
+        Some([memory_type]) => *memory_type,
+        Some(_) => {
+            return Err(NewErr::ModuleError(ModuleError(
+                "Multiple memories aren't supported".to_owned(),
+            )))
+        }
+    };
+
+    module
+        .sections_mut()
+        .retain(|section| !matches!(section, elements::Section::Memory(_)));
+
+    let import = elements::ImportEntry::new(
+        "env".to_owned(),
+        "memory".to_owned(),
+        elements::External::Memory(memory_type),
+    );
+    if let Some(imports) = module.import_section_mut() {
+        imports.entries_mut().push(import);
+    } else {
+        module
+            .insert_section(elements::Section::Import(
+                elements::ImportSection::with_entries(vec![import]),
+            ))
+            .map_err(|err| NewErr::ModuleError(ModuleError(err.to_string())))?;
+    }
+
+    Ok(true)
+}
+
+/// Returns `false` if a memory of `num_pages` 64 kiB pages can't currently be allocated.
+///
+/// `wasmi` allocates the memory of the virtual machine infallibly. See
+/// [`crate::util::can_allocate`].
+fn can_allocate_pages(num_pages: usize) -> bool {
+    num_pages
+        .checked_mul(64 * 1024)
+        .map_or(false, crate::util::can_allocate)
+}
+
 /// See [`super::VirtualMachinePrototype`].
 pub struct InterpreterPrototype {
     /// Original module, with resolved imports.
@@ -76,12 +150,20 @@ impl InterpreterPrototype {
     pub fn new(
         module: &Module,
         heap_pages: HeapPages,
+        max_memory_pages: Option<u32>,
         mut symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
         struct ImportResolve<'a> {
             functions: RefCell<&'a mut dyn FnMut(&str, &str, &Signature) -> Result<usize, ()>>,
             import_memory: RefCell<&'a mut Option<wasmi::MemoryRef>>,
             heap_pages: usize,
+            max_memory_pages: Option<usize>,
+            /// See [`Module::own_memory`].
+            own_memory: bool,
+            /// Set to `true` if the imported memory would exceed `max_memory_pages`.
+            memory_limit_exceeded: Cell<bool>,
+            /// Set to `true` if there isn't enough memory to allocate the imported memory.
+            allocation_failed: Cell<bool>,
         }
 
         impl<'a> wasmi::ImportResolver for ImportResolve<'a> {
@@ -157,14 +239,40 @@ impl InterpreterPrototype {
                                     .map(|m| m.saturating_sub(memory_type.initial()))
                                     .unwrap(),
                             )))
+                        } else if self.max_memory_pages.map_or(false, |max| {
+                            (memory_type.initial() as usize).saturating_add(self.heap_pages) > max
+                        }) {
+                            self.memory_limit_exceeded.set(true);
+                            Err(wasmi::Error::Instantiation(
+                                "Memory limit exceeded".to_owned(),
+                            ))
+                        } else if !can_allocate_pages(
+                            memory_type.initial() as usize + self.heap_pages,
+                        ) {
+                            self.allocation_failed.set(true);
+                            Err(wasmi::Error::Instantiation(
+                                "Couldn't allocate memory".to_owned(),
+                            ))
                         } else {
+                            let initial = memory_type.initial() as usize + self.heap_pages;
+                            // Substrate/Polkadot runtimes are forbidden from using
+                            // `memory.grow`, and imported memories thus can't grow. Memories
+                            // that the module defines itself keep their declared maximum, but
+                            // can't grow beyond `max_memory_pages`.
+                            let maximum = if self.own_memory {
+                                match (memory_type.maximum(), self.max_memory_pages) {
+                                    (Some(declared), Some(limit)) => {
+                                        Some(cmp::min(declared as usize, limit))
+                                    }
+                                    (Some(declared), None) => Some(declared as usize),
+                                    (None, limit) => limit,
+                                }
+                            } else {
+                                Some(initial)
+                            };
                             let memory = wasmi::MemoryInstance::alloc(
-                                wasmi::memory_units::Pages(
-                                    memory_type.initial() as usize + self.heap_pages,
-                                ),
-                                Some(wasmi::memory_units::Pages(
-                                    memory_type.initial() as usize + self.heap_pages,
-                                )),
+                                wasmi::memory_units::Pages(initial),
+                                maximum.map(wasmi::memory_units::Pages),
                             )?;
                             **memory_ref = Some(memory.clone());
                             Ok(memory)
@@ -186,6 +294,8 @@ impl InterpreterPrototype {
         }
 
         let heap_pages = usize::try_from(u32::from(heap_pages)).unwrap_or(usize::max_value());
+        let max_memory_pages =
+            max_memory_pages.map(|max| usize::try_from(max).unwrap_or(usize::max_value()));
 
         let mut import_memory = None;
         let not_started = {
@@ -193,10 +303,21 @@ impl InterpreterPrototype {
                 functions: RefCell::new(&mut symbols),
                 import_memory: RefCell::new(&mut import_memory),
                 heap_pages,
+                max_memory_pages,
+                own_memory: module.own_memory,
+                memory_limit_exceeded: Cell::new(false),
+                allocation_failed: Cell::new(false),
             };
-            wasmi::ModuleInstance::new(&module.inner, &resolver)
-                .map_err(|err| ModuleError(err.to_string()))
-                .map_err(NewErr::ModuleError)?
+            match wasmi::ModuleInstance::new(&module.inner, &resolver) {
+                Ok(m) => m,
+                Err(_) if resolver.memory_limit_exceeded.get() => {
+                    return Err(NewErr::MemoryLimitExceeded)
+                }
+                Err(_) if resolver.allocation_failed.get() => {
+                    return Err(NewErr::CouldntAllocateMemory)
+                }
+                Err(err) => return Err(NewErr::ModuleError(ModuleError(err.to_string()))),
+            }
         };
         // TODO: explain `assert_no_start`
         let module = not_started.assert_no_start();
@@ -205,6 +326,16 @@ impl InterpreterPrototype {
             Some(import_memory)
         } else if let Some(mem) = module.export_by_name("memory") {
             if let Some(mem) = mem.as_memory() {
+                if max_memory_pages.map_or(false, |max| {
+                    mem.current_size().0.saturating_add(heap_pages) > max
+                }) {
+                    return Err(NewErr::MemoryLimitExceeded);
+                }
+
+                if !can_allocate_pages(mem.current_size().0.saturating_add(heap_pages)) {
+                    return Err(NewErr::CouldntAllocateMemory);
+                }
+
                 // TODO: don't unwrap /!\ need to figure out how heap_pages really works
                 mem.grow(wasmi::memory_units::Pages(heap_pages)).unwrap();
                 Some(mem.clone())
@@ -447,7 +578,10 @@ impl Interpreter {
             Err(wasmi::ResumableError::Trap(err)) => {
                 self.is_poisoned = true;
                 Ok(ExecOutcome::Finished {
-                    return_value: Err(Trap(err.to_string())),
+                    return_value: Err(Trap {
+                        message: err.to_string(),
+                        backtrace: Vec::new(),
+                    }),
                 })
             }
         }
@@ -19,10 +19,10 @@
 
 use super::{
     ExecOutcome, GlobalValueErr, HeapPages, ModuleError, NewErr, OutOfBoundsError, RunErr,
-    Signature, StartErr, Trap, WasmValue,
+    Signature, StartErr, Trap, TrapFrame, WasmValue,
 };
 
-use alloc::{boxed::Box, rc::Rc, string::String, vec::Vec};
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
 use core::{
     cell::RefCell,
     cmp,
@@ -82,9 +82,20 @@ impl JitPrototype {
     pub fn new(
         module: &Module,
         heap_pages: HeapPages,
+        max_memory_pages: Option<u32>,
         mut symbols: impl FnMut(&str, &str, &Signature) -> Result<usize, ()>,
     ) -> Result<Self, NewErr> {
-        let store = wasmtime::Store::new(&module.inner.engine());
+        // The limit of the store applies to the memory defined by the module as well, including
+        // when it is grown with `memory.grow`.
+        let store = match max_memory_pages {
+            Some(max_memory_pages) => wasmtime::Store::new_with_limits(
+                &module.inner.engine(),
+                wasmtime::StoreLimitsBuilder::new()
+                    .memory_pages(max_memory_pages)
+                    .build(),
+            ),
+            None => wasmtime::Store::new(&module.inner.engine()),
+        };
 
         let mut imported_memory = None;
         let shared = Rc::new(RefCell::new(Shared {
@@ -183,6 +194,10 @@ impl JitPrototype {
                             wasmtime::Limits::new(num, Some(num))
                         };
 
+                        if max_memory_pages.map_or(false, |max| limits.min() > max) {
+                            return Err(NewErr::MemoryLimitExceeded);
+                        }
+
                         // TODO: check name and all?
                         // TODO: proper error instead of asserting?
                         assert!(imported_memory.is_none());
@@ -209,6 +224,12 @@ impl JitPrototype {
 
         let exported_memory = if let Some(mem) = instance.get_export("memory") {
             if let Some(mem) = mem.into_memory() {
+                if max_memory_pages.map_or(false, |max| {
+                    mem.size().saturating_add(u32::from(heap_pages)) > max
+                }) {
+                    return Err(NewErr::MemoryLimitExceeded);
+                }
+
                 // TODO: do this properly
                 mem.grow(u32::try_from(heap_pages).unwrap()).unwrap();
                 Some(mem)
@@ -275,10 +296,17 @@ impl JitPrototype {
         // Now running the `start` function of the Wasm code.
         let params = params.iter().map(|v| (*v).into()).collect::<Vec<_>>();
         let function_call = Box::pin(async move {
-            let result = start_function
-                .call_async(&params)
-                .await
-                .map_err(|err| err.to_string())?; // The type of error is from the `anyhow` library. By using `to_string()` we avoid having to deal with it.
+            let result = start_function.call_async(&params).await.map_err(|err| {
+                match err.downcast_ref::<wasmtime::Trap>() {
+                    Some(trap) => convert_trap(trap),
+                    // The type of error is from the `anyhow` library. By using `to_string()` we
+                    // avoid having to deal with it.
+                    None => Trap {
+                        message: err.to_string(),
+                        backtrace: Vec::new(),
+                    },
+                }
+            })?;
 
             // Execution resumes here when the Wasm code has gracefully finished.
             // The signature of the function has been chedk earlier, and as such it is
@@ -354,7 +382,7 @@ pub struct Jit {
     /// `Future` that drives the execution. Contains an invocation of
     /// `wasmtime::Func::call_async`.
     /// `None` if the execution has finished and future has returned `Poll::Ready` in the past.
-    function_call: Option<Pin<Box<dyn Future<Output = Result<Option<WasmValue>, String>>>>>,
+    function_call: Option<Pin<Box<dyn Future<Output = Result<Option<WasmValue>, Trap>>>>>,
 
     /// Shared between the "outside" and the external functions. See [`Shared`].
     shared: Rc<RefCell<Shared>>,
@@ -402,7 +430,7 @@ impl Jit {
             Poll::Ready(Err(err)) => {
                 self.function_call = None;
                 Ok(ExecOutcome::Finished {
-                    return_value: Err(Trap(err)),
+                    return_value: Err(err),
                 })
             }
             Poll::Pending => {
@@ -532,3 +560,26 @@ impl fmt::Debug for Jit {
         f.debug_tuple("Jit").finish()
     }
 }
+
+/// Converts a trap reported by `wasmtime` into a [`Trap`].
+fn convert_trap(trap: &wasmtime::Trap) -> Trap {
+    // The `Display` implementation of `wasmtime::Trap` appends a backtrace to the reason of the
+    // trap. The backtrace is reported separately, and only the reason is kept here.
+    let mut message = trap.to_string();
+    if let Some(pos) = message.find("\nwasm backtrace:") {
+        message.truncate(pos);
+    }
+
+    let backtrace = trap
+        .trace()
+        .iter()
+        .map(|frame| TrapFrame {
+            function_index: frame.func_index(),
+            function_name: frame
+                .func_name()
+                .map(|name| format!("{:#}", rustc_demangle::demangle(name))),
+        })
+        .collect();
+
+    Trap { message, backtrace }
+}
@@ -19,3 +19,6 @@
 
 pub mod grandpa;
 pub mod justification;
+
+#[cfg(all(test, feature = "warp-sync"))]
+mod test_vectors;
@@ -96,11 +96,10 @@ fn compact_commit(bytes: &[u8]) -> nom::IResult<&[u8], CompactCommitRef> {
                 nom::bytes::complete::take(32u32),
                 nom::number::complete::le_u32,
                 nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
-                    nom::multi::many_m_n(num_elems, num_elems, unsigned_precommit)
+                    crate::util::nom_many_exact(num_elems, unsigned_precommit)
                 }),
                 nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
-                    nom::multi::many_m_n(
-                        num_elems,
+                    crate::util::nom_many_exact(
                         num_elems,
                         nom::combinator::map(
                             nom::sequence::tuple((
@@ -19,6 +19,7 @@ use crate::finality::grandpa::commit::decode;
 
 use alloc::vec::Vec;
 use core::convert::TryFrom as _;
+use rand7::SeedableRng as _;
 
 /// Configuration for a commit verification process.
 #[derive(Debug)]
@@ -32,6 +33,10 @@ pub struct Config<C> {
     /// Number of authorities that are allowed to emit pre-commits. Used to calculate the
     /// threshold of the number of required signatures.
     pub num_authorities: u32,
+
+    /// Seed for the randomness used in order to verify all the signatures at once. See
+    /// [`crate::finality::justification::verify::Config::randomness_seed`].
+    pub randomness_seed: [u8; 32],
 }
 
 /// Commit verification in progress.
@@ -74,6 +79,7 @@ pub fn verify<C: AsRef<[u8]>>(config: Config<C>) -> InProgress<C> {
         num_verified_signatures: 0,
         num_authorities: config.num_authorities,
         signatures_batch: ed25519_zebra::batch::Verifier::new(),
+        randomness_seed: config.randomness_seed,
     }
     .resume()
 }
@@ -191,6 +197,9 @@ struct Verification<C> {
     /// See https://docs.rs/ed25519-zebra/2.2.0/ed25519_zebra/batch/index.html and
     /// https://github.com/zcash/zips/blob/master/zip-0215.rst
     signatures_batch: ed25519_zebra::batch::Verifier,
+
+    /// See [`Config::randomness_seed`].
+    randomness_seed: [u8; 32],
 }
 
 impl<C: AsRef<[u8]>> Verification<C> {
@@ -269,9 +278,11 @@ impl<C: AsRef<[u8]>> Verification<C> {
                 }
 
                 // Actual signatures verification performed here.
-                // TODO: thread_rng()?!?! what to do here?
                 // TODO: ed25519_zebra depends on rand_core 0.5, which forces us to use an older version of rand; really annoying
-                match self.signatures_batch.verify(rand7::thread_rng()) {
+                match self
+                    .signatures_batch
+                    .verify(rand7::rngs::StdRng::from_seed(self.randomness_seed))
+                {
                     Ok(()) => {}
                     Err(_) => return InProgress::Finished(Err(Error::BadSignature)),
                 }
@@ -15,6 +15,9 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+#![cfg(feature = "warp-sync")]
+#![cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
+
 use crate::chain::chain_information::{ChainInformationFinality, ChainInformationFinalityRef};
 use crate::finality::justification::verify::{
     verify, Config as VerifyConfig, Error as VerifyError,
@@ -22,7 +25,9 @@ use crate::finality::justification::verify::{
 use crate::header::{DigestItemRef, GrandpaAuthority, GrandpaConsensusLogRef, Header};
 use crate::network::protocol::GrandpaWarpSyncResponseFragment;
 
-use alloc::vec::Vec;
+use alloc::{boxed::Box, vec::Vec};
+use core::{fmt, iter};
+use rand::{Rng as _, SeedableRng as _};
 
 #[derive(Debug, derive_more::Display)]
 pub enum Error {
@@ -34,22 +39,65 @@ pub enum Error {
     NonMinimalProof,
     #[display(fmt = "Warp sync proof is empty.")]
     EmptyProof,
+    /// The fragment isn't a descendant of the block the proof starts from or of the previous
+    /// fragment.
+    #[display(fmt = "Warp sync proof fragment doesn't follow the previously-verified block.")]
+    Discontinuity,
+}
+
+/// Error returned by [`Verifier::next`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "{}", error)]
+pub struct NextError {
+    /// Problem encountered while verifying the fragment.
+    pub error: Error,
+    /// Header of the last fragment of the proof that has been successfully verified, and
+    /// finality information that applies to its children. `None` if no fragment of this proof
+    /// has been verified.
+    ///
+    /// The verified fragments remain valid despite the error, and can be used as the starting
+    /// point of another proof.
+    pub verified: Option<Box<(Header, ChainInformationFinality)>>,
 }
 
-#[derive(Debug)]
-pub struct Verifier {
-    index: usize,
+/// Verifies the fragments of a GrandPa warp sync proof one by one.
+///
+/// The fragments are pulled from an iterator, which makes it possible to decode them lazily
+/// rather than holding the entire decoded proof in memory.
+///
+/// The proof might continue a previous proof, potentially obtained from a different source. Its
+/// first fragment is allowed to repeat the block the proof starts from, in which case this
+/// fragment is skipped. All the other fragments must have a strictly increasing block number
+/// greater than the one of the starting block.
+pub struct Verifier<I: Iterator> {
     authorities_set_id: u64,
     authorities_list: Vec<GrandpaAuthority>,
-    fragments: Vec<GrandpaWarpSyncResponseFragment>,
+    fragments: iter::Peekable<I>,
     is_proof_complete: bool,
+    /// Number and hash of the block the proof starts from.
+    start_block: (u64, [u8; 32]),
+    /// Header of the last fragment that has been verified.
+    last_verified: Option<Header>,
+    /// Source of the seeds passed to [`VerifyConfig::randomness_seed`].
+    randomness: rand_chacha::ChaCha20Rng,
 }
 
-impl Verifier {
+impl<I> Verifier<I>
+where
+    I: Iterator<Item = GrandpaWarpSyncResponseFragment>,
+{
+    /// Initializes a new verifier.
+    ///
+    /// `start_block` is the number and hash of the block the proof starts from, and
+    /// `start_chain_information_finality` the finality information that applies to its children.
+    /// `randomness_seed` is used in order to verify the justifications of the fragments. See
+    /// [`VerifyConfig::randomness_seed`].
     pub fn new(
+        start_block: (u64, [u8; 32]),
         start_chain_information_finality: ChainInformationFinalityRef,
-        warp_sync_response_fragments: Vec<GrandpaWarpSyncResponseFragment>,
+        warp_sync_response_fragments: impl IntoIterator<IntoIter = I>,
         is_proof_complete: bool,
+        randomness_seed: [u8; 32],
     ) -> Self {
         let (authorities_list, authorities_set_id) = match start_chain_information_finality {
             ChainInformationFinalityRef::Grandpa {
@@ -65,21 +113,80 @@ impl Verifier {
         };
 
         Self {
-            index: 0,
             authorities_set_id,
             authorities_list,
-            fragments: warp_sync_response_fragments,
+            fragments: warp_sync_response_fragments.into_iter().peekable(),
             is_proof_complete,
+            start_block,
+            last_verified: None,
+            randomness: rand_chacha::ChaCha20Rng::from_seed(randomness_seed),
+        }
+    }
+
+    /// Verifies the next fragment of the proof.
+    pub fn next(mut self) -> Result<Next<I>, NextError> {
+        match self.next_inner() {
+            Ok(false) => Ok(Next::NotFinished(self)),
+            Ok(true) => {
+                let (header, chain_information_finality) = self.into_verified().unwrap();
+                Ok(Next::Success {
+                    header,
+                    chain_information_finality,
+                })
+            }
+            Err(error) => Err(NextError {
+                error,
+                verified: self.into_verified().map(Box::new),
+            }),
         }
     }
 
-    pub fn next(mut self) -> Result<Next, Error> {
-        if self.fragments.is_empty() {
-            return Err(Error::EmptyProof);
+    /// Returns the header of the last fragment of the proof that has been verified, and the
+    /// finality information that applies to its children. `None` if no fragment has been
+    /// verified yet.
+    ///
+    /// This can be used in order to continue the warp syncing with a different proof, for
+    /// example if the source of this proof is no longer available.
+    pub fn into_verified(self) -> Option<(Header, ChainInformationFinality)> {
+        let header = self.last_verified?;
+        Some((
+            header,
+            ChainInformationFinality::Grandpa {
+                after_finalized_block_authorities_set_id: self.authorities_set_id,
+                finalized_triggered_authorities: self.authorities_list,
+                finalized_scheduled_change: None,
+            },
+        ))
+    }
+
+    /// Verifies the next fragment. Returns `true` if this was the last fragment of the proof.
+    fn next_inner(&mut self) -> Result<bool, Error> {
+        // `next` is never called again after the last fragment has been verified. If there isn't
+        // any fragment, then the proof was empty to begin with.
+        let mut fragment = match self.fragments.next() {
+            Some(f) => f,
+            None => return Err(Error::EmptyProof),
+        };
+
+        let (previous_number, previous_hash) = match &self.last_verified {
+            Some(header) => (header.number, header.hash()),
+            None => self.start_block,
+        };
+
+        // A continuation is allowed to start with the block that has already been verified.
+        if self.last_verified.is_none()
+            && fragment.header.number == previous_number
+            && fragment.header.hash() == previous_hash
+        {
+            fragment = match self.fragments.next() {
+                Some(f) => f,
+                None => return Err(Error::EmptyProof),
+            };
         }
 
-        debug_assert!(self.fragments.len() > self.index);
-        let fragment = &self.fragments[self.index];
+        if fragment.header.number <= previous_number {
+            return Err(Error::Discontinuity);
+        }
 
         if fragment.justification.target_hash != fragment.header.hash() {
             return Err(Error::TargetHashMismatch);
@@ -89,6 +196,7 @@ impl Verifier {
             justification: (&fragment.justification).into(),
             authorities_list: self.authorities_list.iter().map(|a| &a.public_key),
             authorities_set_id: self.authorities_set_id,
+            randomness_seed: self.randomness.sample(rand::distributions::Standard),
         })
         .map_err(Error::Verify)?;
 
@@ -109,34 +217,235 @@ impl Verifier {
             .next()
             .map(|next_authorities| next_authorities.map(GrandpaAuthority::from).collect());
 
-        self.index += 1;
+        let is_last = self.fragments.peek().is_none();
 
         if let Some(authorities_list) = authorities_list {
             self.authorities_list = authorities_list;
             self.authorities_set_id += 1;
-        } else if !self.is_proof_complete || self.index != self.fragments.len() {
+        } else if !self.is_proof_complete || !is_last {
             return Err(Error::NonMinimalProof);
         }
 
-        if self.index == self.fragments.len() {
-            Ok(Next::Success {
-                header: fragment.header.clone(),
-                chain_information_finality: ChainInformationFinality::Grandpa {
-                    after_finalized_block_authorities_set_id: self.authorities_set_id,
-                    finalized_triggered_authorities: self.authorities_list,
-                    finalized_scheduled_change: None,
-                },
-            })
-        } else {
-            Ok(Next::NotFinished(self))
-        }
+        self.last_verified = Some(fragment.header);
+        Ok(is_last)
+    }
+}
+
+impl<I: Iterator> fmt::Debug for Verifier<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Verifier")
+            .field("authorities_set_id", &self.authorities_set_id)
+            .field("authorities_list", &self.authorities_list)
+            .field("is_proof_complete", &self.is_proof_complete)
+            .finish()
     }
 }
 
-pub enum Next {
-    NotFinished(Verifier),
+pub enum Next<I: Iterator> {
+    NotFinished(Verifier<I>),
     Success {
         header: Header,
         chain_information_finality: ChainInformationFinality,
     },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Next, NextError, Verifier};
+    use crate::chain::chain_information::{ChainInformationFinality, ChainInformationFinalityRef};
+    use crate::header::{GrandpaAuthority, Header};
+    use crate::network::protocol::{self, GrandpaWarpSyncResponseFragment};
+
+    use core::{convert::TryFrom as _, num::NonZeroU64};
+
+    /// Returns the starting block, authorities set id, authorities and fragments of a proof
+    /// containing two authorities set changes followed by the latest finalized block.
+    fn complete_proof() -> (
+        (u64, [u8; 32]),
+        u64,
+        Vec<GrandpaAuthority>,
+        Vec<GrandpaWarpSyncResponseFragment>,
+    ) {
+        let vector: serde_json::Value =
+            serde_json::from_str(include_str!("../test-vectors/warp-sync-complete.json")).unwrap();
+        let hex = |value: &serde_json::Value| {
+            hex::decode(value.as_str().unwrap().trim_start_matches("0x")).unwrap()
+        };
+
+        let start_block = (
+            vector["startBlockNumber"].as_u64().unwrap(),
+            <[u8; 32]>::try_from(&hex(&vector["startBlockHash"])[..]).unwrap(),
+        );
+        let authorities = vector["authorities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|authority| GrandpaAuthority {
+                public_key: <[u8; 32]>::try_from(&hex(&authority["publicKey"])[..]).unwrap(),
+                weight: NonZeroU64::new(authority["weight"].as_u64().unwrap()).unwrap(),
+            })
+            .collect();
+        let response = protocol::decode_grandpa_warp_sync_response(&hex(&vector["proof"])).unwrap();
+        assert_eq!(response.fragments.len(), 3);
+
+        (
+            start_block,
+            vector["authoritiesSetId"].as_u64().unwrap(),
+            authorities,
+            response.fragments,
+        )
+    }
+
+    /// Verifies all the given fragments, and returns the outcome of the last verification.
+    fn verify(
+        start_block: (u64, [u8; 32]),
+        authorities_set_id: u64,
+        authorities: &[GrandpaAuthority],
+        fragments: Vec<GrandpaWarpSyncResponseFragment>,
+        is_proof_complete: bool,
+    ) -> Result<(Header, ChainInformationFinality), NextError> {
+        let mut verifier = Verifier::new(
+            start_block,
+            ChainInformationFinalityRef::Grandpa {
+                after_finalized_block_authorities_set_id: authorities_set_id,
+                finalized_triggered_authorities: authorities,
+                finalized_scheduled_change: None,
+            },
+            fragments,
+            is_proof_complete,
+            [0; 32],
+        );
+
+        loop {
+            match verifier.next()? {
+                Next::NotFinished(v) => verifier = v,
+                Next::Success {
+                    header,
+                    chain_information_finality,
+                } => break Ok((header, chain_information_finality)),
+            }
+        }
+    }
+
+    fn set_id_and_authorities(finality: &ChainInformationFinality) -> (u64, &[GrandpaAuthority]) {
+        match finality {
+            ChainInformationFinality::Grandpa {
+                after_finalized_block_authorities_set_id,
+                finalized_triggered_authorities,
+                ..
+            } => (
+                *after_finalized_block_authorities_set_id,
+                finalized_triggered_authorities,
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn continuation_with_repeated_start_block() {
+        let (start_block, set_id, authorities, fragments) = complete_proof();
+        let (expected_header, _) =
+            verify(start_block, set_id, &authorities, fragments, true).unwrap();
+
+        // First source only sends the first fragment, and isn't finished.
+        let (_, _, _, mut fragments) = complete_proof();
+        fragments.truncate(1);
+        let (partial_header, partial_finality) =
+            verify(start_block, set_id, &authorities, fragments, false).unwrap();
+        let (partial_set_id, partial_authorities) = set_id_and_authorities(&partial_finality);
+        assert_eq!(partial_set_id, set_id + 1);
+
+        // Second source starts its proof with the block that has already been verified.
+        let (_, _, _, fragments) = complete_proof();
+        let (header, finality) = verify(
+            (partial_header.number, partial_header.hash()),
+            partial_set_id,
+            partial_authorities,
+            fragments,
+            true,
+        )
+        .unwrap();
+        assert_eq!(header.hash(), expected_header.hash());
+        assert_eq!(set_id_and_authorities(&finality).0, set_id + 2);
+
+        // Same, but without repeating the block that has already been verified.
+        let (_, _, _, mut fragments) = complete_proof();
+        fragments.remove(0);
+        let (header, _) = verify(
+            (partial_header.number, partial_header.hash()),
+            partial_set_id,
+            partial_authorities,
+            fragments,
+            true,
+        )
+        .unwrap();
+        assert_eq!(header.hash(), expected_header.hash());
+    }
+
+    #[test]
+    fn repeated_start_number_with_different_hash() {
+        let (_, set_id, authorities, fragments) = complete_proof();
+        let first_number = fragments[0].header.number;
+
+        // A fragment with the number of the starting block but a different hash isn't skipped.
+        let error = verify(
+            (first_number, [0xaa; 32]),
+            set_id,
+            &authorities,
+            fragments,
+            true,
+        )
+        .unwrap_err();
+        assert!(matches!(error.error, Error::Discontinuity));
+        assert!(error.verified.is_none());
+    }
+
+    #[test]
+    fn discontinuity_after_verified_fragments() {
+        let (start_block, set_id, authorities, mut fragments) = complete_proof();
+        let (_, _, _, mut repeated) = complete_proof();
+        fragments.truncate(2);
+        let second_hash = fragments[1].header.hash();
+        // The first fragment comes again after the second one.
+        fragments.push(repeated.remove(0));
+
+        let error = verify(start_block, set_id, &authorities, fragments, false).unwrap_err();
+        assert!(matches!(error.error, Error::Discontinuity));
+
+        // The two fragments before the discontinuity remain verified, and can be continued from.
+        let verified = error.verified.unwrap();
+        let (header, finality) = &*verified;
+        assert_eq!(header.hash(), second_hash);
+        assert_eq!(set_id_and_authorities(finality).0, set_id + 2);
+
+        let (_, _, _, mut fragments) = complete_proof();
+        let expected_hash = fragments[2].header.hash();
+        fragments.drain(..2);
+        let (header, _) = verify(
+            (header.number, header.hash()),
+            set_id + 2,
+            set_id_and_authorities(finality).1,
+            fragments,
+            true,
+        )
+        .unwrap();
+        assert_eq!(header.hash(), expected_hash);
+    }
+
+    #[test]
+    fn discontinuity_before_start_block() {
+        let (_, set_id, authorities, fragments) = complete_proof();
+        let last_number = fragments[2].header.number;
+
+        let error = verify(
+            (last_number, [0; 32]),
+            set_id,
+            &authorities,
+            fragments,
+            true,
+        )
+        .unwrap_err();
+        assert!(matches!(error.error, Error::Discontinuity));
+        assert!(error.verified.is_none());
+    }
+}
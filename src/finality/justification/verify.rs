@@ -18,6 +18,7 @@
 use crate::finality::justification::decode;
 
 use alloc::vec::Vec;
+use rand7::SeedableRng as _;
 
 /// Configuration for a justification verification process.
 #[derive(Debug)]
@@ -32,6 +33,12 @@ pub struct Config<'a, I> {
     /// the justification. Must implement `Iterator<Item = impl AsRef<[u8]>> + Clone`, where
     /// each item is the public key of an authority.
     pub authorities_list: I,
+
+    /// Seed for the randomness used in order to verify all the signatures at once.
+    ///
+    /// Must be unpredictable to the authors of the signatures. You are encouraged to use
+    /// something like `rand::random()` to fill this field.
+    pub randomness_seed: [u8; 32],
 }
 
 // TODO: rewrite as a generator-style process?
@@ -93,10 +100,9 @@ pub fn verify(config: Config<impl Iterator<Item = impl AsRef<[u8]>> + Clone>) ->
     }
 
     // Actual signatures verification performed here.
-    // TODO: thread_rng()?!?! what to do here?
     // TODO: ed25519_zebra depends on rand_core 0.5, which forces us to use an older version of rand; really annoying
     batch
-        .verify(rand7::thread_rng())
+        .verify(rand7::rngs::StdRng::from_seed(config.randomness_seed))
         .map_err(|_| Error::BadSignature)?;
 
     // TODO: must check that votes_ancestries doesn't contain any unused entry
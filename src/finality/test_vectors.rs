@@ -0,0 +1,253 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Conformance test vectors for the verification of justifications and warp sync proofs.
+//!
+//! Each file of the `test-vectors` directory is a JSON object describing some input data, the
+//! state of the chain it applies to, and the expected outcome of the verification. All the files
+//! of the directory are loaded and checked, meaning that adding a test vector doesn't require
+//! modifying this module.
+//!
+//! Test vectors can be recorded from a node with the `capture_finality_test_vector` example.
+//!
+//! # Origin of the test vectors
+//!
+//! The test vectors currently in the directory are synthetic: their justifications and proofs
+//! were generated locally with test keys rather than recorded from a live chain, and they only
+//! cover the edge cases that are described in their `description` field.
+//!
+//! TODO: add test vectors recorded from Polkadot and Kusama, which require access to a node of
+//!       these chains. At least a justification of a block at the end of an era and a warp sync
+//!       proof starting from genesis should be recorded for each chain, for example with:
+//!
+//! ```text
+//! cargo run --example capture_finality_test_vector -- justification 127.0.0.1:9933 <block-hash> \
+//!     > src/finality/test-vectors/polkadot-justification-<block-number>.json
+//! ```
+//!
+//! Expected errors are compared with the beginning of the `Debug` representation of the actual
+//! error, for example `NotAuthority` or `Verify(BadSignature)`.
+
+use crate::{
+    chain::chain_information::{ChainInformationFinality, ChainInformationFinalityRef},
+    finality::{grandpa::warp_sync, justification},
+    header::GrandpaAuthority,
+    network::protocol,
+};
+
+use core::{convert::TryFrom as _, num::NonZeroU64};
+use std::{fs, path::Path};
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum TestVector {
+    /// A GrandPa justification, verified against a list of authorities.
+    #[serde(rename_all = "camelCase")]
+    Justification {
+        description: String,
+        authorities_set_id: u64,
+        authorities: Vec<Authority>,
+        /// SCALE-encoded justification.
+        justification: HexString,
+        expected: Expected<()>,
+    },
+    /// A GrandPa warp sync response, verified starting from the given block.
+    #[serde(rename_all = "camelCase")]
+    WarpSyncProof {
+        description: String,
+        start_block_number: u64,
+        start_block_hash: HexString,
+        /// Authorities set id and list of authorities that apply to the children of the start
+        /// block.
+        authorities_set_id: u64,
+        authorities: Vec<Authority>,
+        /// SCALE-encoded warp sync response, as received from the network.
+        proof: HexString,
+        expected: Expected<WarpSyncSuccess>,
+    },
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Expected<T> {
+    Success(T),
+    /// Beginning of the `Debug` representation of the error.
+    Error(String),
+}
+
+#[derive(Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WarpSyncSuccess {
+    finalized_block_hash: HexString,
+    authorities_set_id: u64,
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Authority {
+    public_key: HexString,
+    weight: NonZeroU64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct HexString(Vec<u8>);
+
+impl<'a> serde::Deserialize<'a> for HexString {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        let string = String::deserialize(deserializer)?;
+        let string = string.strip_prefix("0x").unwrap_or(&string);
+        hex::decode(string)
+            .map(HexString)
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+fn grandpa_authorities(authorities: &[Authority]) -> Vec<GrandpaAuthority> {
+    authorities
+        .iter()
+        .map(|a| GrandpaAuthority {
+            public_key: <[u8; 32]>::try_from(&a.public_key.0[..]).unwrap(),
+            weight: a.weight,
+        })
+        .collect()
+}
+
+/// Runs the given test vector. Returns an error message if the outcome doesn't match the
+/// expected one.
+fn run(test_vector: &TestVector) -> Result<(), String> {
+    match test_vector {
+        TestVector::Justification {
+            authorities_set_id,
+            authorities,
+            justification,
+            expected,
+            ..
+        } => {
+            let outcome = justification::decode::decode_grandpa(&justification.0)
+                .map_err(|err| format!("{:?}", err))
+                .and_then(|justification| {
+                    justification::verify::verify(justification::verify::Config {
+                        justification,
+                        authorities_set_id: *authorities_set_id,
+                        authorities_list: authorities.iter().map(|a| &a.public_key.0),
+                        randomness_seed: [0; 32],
+                    })
+                    .map_err(|err| format!("{:?}", err))
+                });
+            compare(outcome, expected)
+        }
+        TestVector::WarpSyncProof {
+            start_block_number,
+            start_block_hash,
+            authorities_set_id,
+            authorities,
+            proof,
+            expected,
+            ..
+        } => {
+            let authorities = grandpa_authorities(authorities);
+            let outcome = protocol::decode_grandpa_warp_sync_response(&proof.0)
+                .map_err(|err| format!("{:?}", err))
+                .and_then(|response| {
+                    let mut verifier = warp_sync::Verifier::new(
+                        (
+                            *start_block_number,
+                            <[u8; 32]>::try_from(&start_block_hash.0[..]).unwrap(),
+                        ),
+                        ChainInformationFinalityRef::Grandpa {
+                            after_finalized_block_authorities_set_id: *authorities_set_id,
+                            finalized_triggered_authorities: &authorities,
+                            finalized_scheduled_change: None,
+                        },
+                        response.fragments,
+                        response.is_finished,
+                        [0; 32],
+                    );
+
+                    loop {
+                        match verifier.next() {
+                            Ok(warp_sync::Next::NotFinished(v)) => verifier = v,
+                            Ok(warp_sync::Next::Success {
+                                header,
+                                chain_information_finality,
+                            }) => {
+                                break Ok(WarpSyncSuccess {
+                                    finalized_block_hash: HexString(header.hash().to_vec()),
+                                    authorities_set_id: match chain_information_finality {
+                                        ChainInformationFinality::Grandpa {
+                                            after_finalized_block_authorities_set_id,
+                                            ..
+                                        } => after_finalized_block_authorities_set_id,
+                                        _ => unreachable!(),
+                                    },
+                                })
+                            }
+                            Err(err) => break Err(format!("{:?}", err.error)),
+                        }
+                    }
+                });
+            compare(outcome, expected)
+        }
+    }
+}
+
+fn compare<T: PartialEq + core::fmt::Debug>(
+    outcome: Result<T, String>,
+    expected: &Expected<T>,
+) -> Result<(), String> {
+    match (outcome, expected) {
+        (Ok(actual), Expected::Success(expected)) if actual == *expected => Ok(()),
+        (Err(actual), Expected::Error(expected)) if actual.starts_with(expected.as_str()) => Ok(()),
+        (Ok(actual), expected) => Err(format!("expected {:?}, got success {:?}", expected, actual)),
+        (Err(actual), expected) => Err(format!("expected {:?}, got error {}", expected, actual)),
+    }
+}
+
+#[test]
+fn test_vectors() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/finality/test-vectors");
+
+    let mut num_test_vectors = 0;
+    let mut failures = Vec::new();
+
+    for entry in fs::read_dir(&directory).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension() != Some("json".as_ref()) {
+            continue;
+        }
+
+        let test_vector: TestVector = serde_json::from_slice(&fs::read(&path).unwrap())
+            .unwrap_or_else(|err| {
+                panic!("failed to parse {}: {}", path.display(), err);
+            });
+        num_test_vectors += 1;
+
+        if let Err(err) = run(&test_vector) {
+            let description = match &test_vector {
+                TestVector::Justification { description, .. }
+                | TestVector::WarpSyncProof { description, .. } => description,
+            };
+            failures.push(format!("{} ({}): {}", path.display(), description, err));
+        }
+    }
+
+    assert_ne!(num_test_vectors, 0);
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
@@ -100,22 +100,81 @@ pub fn hash_from_scale_encoded_header(header: impl AsRef<[u8]>) -> [u8; 32] {
 pub fn hash_from_scale_encoded_header_vectored(
     header: impl Iterator<Item = impl AsRef<[u8]>>,
 ) -> [u8; 32] {
-    let mut hasher = blake2_rfc::blake2b::Blake2b::with_key(32, &[]);
+    let mut hasher = HeaderHasher::new();
     for buf in header {
         hasher.update(buf.as_ref());
     }
+    hasher.finalize()
+}
+
+/// Incremental calculation of the hash of a SCALE-encoded header.
+///
+/// The SCALE-encoded header can be passed in multiple chunks through [`HeaderHasher::update`].
+/// Contrary to concatenating these chunks in a buffer and calling
+/// [`hash_from_scale_encoded_header`], no heap allocation is performed.
+///
+/// Does not verify the validity of the header.
+#[derive(Clone)]
+pub struct HeaderHasher {
+    inner: blake2_rfc::blake2b::Blake2b,
+}
+
+impl HeaderHasher {
+    /// Initializes a new hasher.
+    pub fn new() -> Self {
+        HeaderHasher {
+            inner: blake2_rfc::blake2b::Blake2b::with_key(32, &[]),
+        }
+    }
+
+    /// Appends the given data to the SCALE-encoded header being hashed.
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
 
-    let result = hasher.finalize();
-    debug_assert_eq!(result.as_bytes().len(), 32);
+    /// Returns the hash of the SCALE-encoded header that has been passed through
+    /// [`HeaderHasher::update`].
+    pub fn finalize(self) -> [u8; 32] {
+        let result = self.inner.finalize();
+        debug_assert_eq!(result.as_bytes().len(), 32);
 
-    let mut out = [0; 32];
-    out.copy_from_slice(result.as_bytes());
-    out
+        let mut out = [0; 32];
+        out.copy_from_slice(result.as_bytes());
+        out
+    }
+}
+
+impl Default for HeaderHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for HeaderHasher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HeaderHasher").finish()
+    }
 }
 
 /// Attempt to decode the given SCALE-encoded header.
+///
+/// Digest items of consensus engines that smoldot doesn't know about lead to an
+/// [`Error::UnknownConsensusEngine`]. Use [`decode_with_custom_engines`] in order to accept them.
 pub fn decode(scale_encoded: &[u8]) -> Result<HeaderRef, Error> {
-    let (header, remainder) = decode_partial(scale_encoded)?;
+    decode_with_custom_engines(scale_encoded, &[])
+}
+
+/// Attempt to decode the given SCALE-encoded header.
+///
+/// Contrary to [`decode`], digest items whose consensus engine is found in `custom_engines` are
+/// accepted and decoded as [`DigestItemRef::Custom`]. Smoldot doesn't interpret the content of
+/// these items, and it is the responsibility of the caller to check them.
+pub fn decode_with_custom_engines<'a>(
+    scale_encoded: &'a [u8],
+    custom_engines: &[[u8; 4]],
+) -> Result<HeaderRef<'a>, Error> {
+    let (header, remainder) =
+        decode_partial_inner(scale_encoded, CustomEngines::Only(custom_engines))?;
     if !remainder.is_empty() {
         return Err(Error::TooLong);
     }
@@ -127,7 +186,32 @@ pub fn decode(scale_encoded: &[u8]) -> Result<HeaderRef, Error> {
 ///
 /// Contrary to [`decode`], doesn't return an error if the slice is too long but returns the
 /// remainder.
-pub fn decode_partial(mut scale_encoded: &[u8]) -> Result<(HeaderRef, &[u8]), Error> {
+pub fn decode_partial(scale_encoded: &[u8]) -> Result<(HeaderRef, &[u8]), Error> {
+    decode_partial_inner(scale_encoded, CustomEngines::Only(&[]))
+}
+
+/// Similar to [`decode_partial`], but accepts the digest items of all consensus engines.
+///
+/// Meant to be used in order to find the boundaries of a header within a larger message. The
+/// items of unknown consensus engines must be checked when the header is decoded again, with
+/// [`decode`] or [`decode_with_custom_engines`].
+pub(crate) fn decode_partial_any_engine(scale_encoded: &[u8]) -> Result<(HeaderRef, &[u8]), Error> {
+    decode_partial_inner(scale_encoded, CustomEngines::Any)
+}
+
+/// Which consensus engines unknown to smoldot are accepted when decoding digest items.
+#[derive(Copy, Clone)]
+enum CustomEngines<'b> {
+    /// Only the ones in the list.
+    Only(&'b [[u8; 4]]),
+    /// All of them.
+    Any,
+}
+
+fn decode_partial_inner<'a>(
+    mut scale_encoded: &'a [u8],
+    custom_engines: CustomEngines,
+) -> Result<(HeaderRef<'a>, &'a [u8]), Error> {
     if scale_encoded.len() < 32 + 1 {
         return Err(Error::TooShort);
     }
@@ -148,7 +232,7 @@ pub fn decode_partial(mut scale_encoded: &[u8]) -> Result<(HeaderRef, &[u8]), Er
     let extrinsics_root: &[u8; 32] = TryFrom::try_from(&scale_encoded[0..32]).unwrap();
     scale_encoded = &scale_encoded[32..];
 
-    let (digest, remainder) = DigestRef::from_scale_bytes(scale_encoded)?;
+    let (digest, remainder) = DigestRef::from_scale_bytes(scale_encoded, custom_engines)?;
 
     let header = HeaderRef {
         parent_hash,
@@ -228,12 +312,13 @@ pub struct HeaderRef<'a> {
 impl<'a> HeaderRef<'a> {
     /// Returns an iterator to list of buffers which, when concatenated, produces the SCALE
     /// encoding of the header.
+    ///
+    /// No heap allocation is performed if the header has been obtained through [`decode`]. See
+    /// [`DigestRef::scale_encoding`].
     pub fn scale_encoding(
         &self,
     ) -> impl Iterator<Item = impl AsRef<[u8]> + Clone + 'a> + Clone + 'a {
-        // TODO: don't allocate?
-        let encoded_number =
-            parity_scale_codec::Encode::encode(&parity_scale_codec::Compact(self.number));
+        let encoded_number = util::encode_scale_compact_u64(self.number);
 
         iter::once(either::Either::Left(either::Either::Left(
             &self.parent_hash[..],
@@ -531,6 +616,25 @@ impl<'a> DigestRef<'a> {
         }
     }
 
+    /// Returns the log items of this digest that belong to consensus engines unknown to smoldot.
+    ///
+    /// Always empty if the header hasn't been decoded with [`decode_with_custom_engines`]. See
+    /// [`DigestItemRef::Custom`].
+    pub fn custom_items(&self) -> impl Iterator<Item = CustomDigestItem> + 'a {
+        self.logs().filter_map(|item| match item {
+            DigestItemRef::Custom {
+                kind,
+                engine_id,
+                opaque,
+            } => Some(CustomDigestItem {
+                kind,
+                engine_id,
+                opaque: opaque.to_vec(),
+            }),
+            _ => None,
+        })
+    }
+
     /// Returns an iterator to the log items in this digest.
     pub fn logs(&self) -> LogsIter<'a> {
         LogsIter {
@@ -549,14 +653,25 @@ impl<'a> DigestRef<'a> {
 
     /// Returns an iterator to list of buffers which, when concatenated, produces the SCALE
     /// encoding of the digest items.
+    ///
+    /// If the digest has been decoded from a SCALE-encoded header, the items are not re-encoded
+    /// and the original bytes are returned instead, which avoids any heap allocation.
     pub fn scale_encoding(
         &self,
     ) -> impl Iterator<Item = impl AsRef<[u8]> + Clone + 'a> + Clone + 'a {
         let encoded_len = util::encode_scale_compact_usize(self.logs().len());
-        iter::once(either::Left(encoded_len)).chain(
-            self.logs()
-                .flat_map(|v| v.scale_encoding().map(either::Right)),
-        )
+
+        let items = match self.inner {
+            DigestRefInner::Undecoded { digest, .. } => {
+                either::Left(iter::once(either::Left(digest)))
+            }
+            DigestRefInner::Parsed(_) => either::Right(
+                self.logs()
+                    .flat_map(|v| v.scale_encoding().map(either::Right)),
+            ),
+        };
+
+        iter::once(either::Left(encoded_len)).chain(items.map(either::Right))
     }
 
     /// Turns an already-decoded list of items into a [`DigestRef`].
@@ -614,7 +729,9 @@ impl<'a> DigestRef<'a> {
                     babe_seal_index = Some(item_num);
                 }
                 DigestItem::BabeSeal(_) => return Err(Error::SealIsntLastItem),
-                DigestItem::ChangesTrieSignal(_) | DigestItem::Beefy { .. } => {}
+                DigestItem::ChangesTrieSignal(_)
+                | DigestItem::Beefy { .. }
+                | DigestItem::Custom { .. } => {}
             }
         }
 
@@ -634,7 +751,10 @@ impl<'a> DigestRef<'a> {
     }
 
     /// Try to decode a list of digest items, from their SCALE encoding.
-    fn from_scale_bytes(mut scale_encoded: &'a [u8]) -> Result<(Self, &'a [u8]), Error> {
+    fn from_scale_bytes(
+        mut scale_encoded: &'a [u8],
+        custom_engines: CustomEngines,
+    ) -> Result<(Self, &'a [u8]), Error> {
         let digest_logs_len = {
             let len: parity_scale_codec::Compact<u64> =
                 parity_scale_codec::Decode::decode(&mut scale_encoded)
@@ -654,7 +774,7 @@ impl<'a> DigestRef<'a> {
         // Iterate through the log items to see if anything is wrong.
         let mut next_digest = scale_encoded;
         for item_num in 0..digest_logs_len {
-            let (item, next) = decode_item(next_digest)?;
+            let (item, next) = decode_item(next_digest, custom_engines)?;
             next_digest = next;
 
             match item {
@@ -702,7 +822,9 @@ impl<'a> DigestRef<'a> {
                     babe_seal_index = Some(item_num);
                 }
                 DigestItemRef::BabeSeal(_) => return Err(Error::SealIsntLastItem),
-                DigestItemRef::ChangesTrieSignal(_) | DigestItemRef::Beefy { .. } => {}
+                DigestItemRef::ChangesTrieSignal(_)
+                | DigestItemRef::Beefy { .. }
+                | DigestItemRef::Custom { .. } => {}
             }
         }
 
@@ -713,7 +835,7 @@ impl<'a> DigestRef<'a> {
         let out = DigestRef {
             inner: DigestRefInner::Undecoded {
                 digest_logs_len,
-                digest: scale_encoded,
+                digest: &scale_encoded[..scale_encoded.len() - next_digest.len()],
             },
             aura_seal_index,
             aura_predigest_index,
@@ -882,7 +1004,7 @@ impl<'a> Iterator for LogsIter<'a> {
                 }
 
                 // Validity is guaranteed when the `DigestRef` is constructed.
-                let (item, new_pointer) = decode_item(*pointer).unwrap();
+                let (item, new_pointer) = decode_item(*pointer, CustomEngines::Any).unwrap();
                 *pointer = new_pointer;
                 *remaining_len -= 1;
 
@@ -927,6 +1049,52 @@ pub enum DigestItemRef<'a> {
         /// Smoldot doesn't interpret the content of the log item at the moment.
         opaque: &'a [u8],
     },
+
+    /// Item belonging to a consensus engine that smoldot doesn't know about.
+    ///
+    /// Only ever produced when decoding a header with [`decode_with_custom_engines`], if the
+    /// consensus engine is in the list passed as parameter.
+    Custom {
+        /// Whether the item is a pre-runtime item, a consensus item, or a seal.
+        kind: CustomDigestItemKind,
+        /// Identifier of the consensus engine the item belongs to.
+        engine_id: [u8; 4],
+        /// Smoldot doesn't interpret the content of the log item.
+        opaque: &'a [u8],
+    },
+}
+
+/// Owned version of a [`DigestItemRef::Custom`] item. See [`DigestRef::custom_items`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CustomDigestItem {
+    /// Whether the item is a pre-runtime item, a consensus item, or a seal.
+    pub kind: CustomDigestItemKind,
+    /// Identifier of the consensus engine the item belongs to.
+    pub engine_id: [u8; 4],
+    /// Content of the item. Smoldot doesn't interpret it.
+    pub opaque: Vec<u8>,
+}
+
+/// Kind of a [`DigestItemRef::Custom`] item.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CustomDigestItemKind {
+    /// Item provided by the block author and destined to the runtime.
+    PreRuntime,
+    /// Item emitted by the runtime and destined to the consensus engine.
+    Consensus,
+    /// Signature of the block author.
+    Seal,
+}
+
+impl CustomDigestItemKind {
+    /// Returns the index of the digest item type in the SCALE encoding.
+    fn encoding_index(&self) -> u8 {
+        match self {
+            CustomDigestItemKind::Consensus => 4,
+            CustomDigestItemKind::Seal => 5,
+            CustomDigestItemKind::PreRuntime => 6,
+        }
+    }
 }
 
 impl<'a> DigestItemRef<'a> {
@@ -1060,6 +1228,17 @@ impl<'a> DigestItemRef<'a> {
                 ret.extend_from_slice(opaque);
                 iter::once(ret)
             }
+            DigestItemRef::Custom {
+                kind,
+                engine_id,
+                opaque,
+            } => {
+                let mut ret = vec![kind.encoding_index()];
+                ret.extend_from_slice(&engine_id);
+                ret.extend_from_slice(util::encode_scale_compact_usize(opaque.len()).as_ref());
+                ret.extend_from_slice(opaque);
+                iter::once(ret)
+            }
         }
     }
 }
@@ -1077,6 +1256,15 @@ impl<'a> From<&'a DigestItem> for DigestItemRef<'a> {
             DigestItem::ChangesTrieRoot(v) => DigestItemRef::ChangesTrieRoot(v),
             DigestItem::ChangesTrieSignal(v) => DigestItemRef::ChangesTrieSignal(v.clone()),
             DigestItem::Beefy { opaque } => DigestItemRef::Beefy { opaque: &*opaque },
+            DigestItem::Custom {
+                kind,
+                engine_id,
+                opaque,
+            } => DigestItemRef::Custom {
+                kind: *kind,
+                engine_id: *engine_id,
+                opaque: &*opaque,
+            },
         }
     }
 }
@@ -1104,6 +1292,13 @@ pub enum DigestItem {
         /// Smoldot doesn't interpret the content of the log item at the moment.
         opaque: Vec<u8>,
     },
+
+    /// See [`DigestItemRef::Custom`].
+    Custom {
+        kind: CustomDigestItemKind,
+        engine_id: [u8; 4],
+        opaque: Vec<u8>,
+    },
 }
 
 impl<'a> From<DigestItemRef<'a>> for DigestItem {
@@ -1129,6 +1324,15 @@ impl<'a> From<DigestItemRef<'a>> for DigestItem {
             DigestItemRef::Beefy { opaque } => DigestItem::Beefy {
                 opaque: opaque.to_vec(),
             },
+            DigestItemRef::Custom {
+                kind,
+                engine_id,
+                opaque,
+            } => DigestItem::Custom {
+                kind,
+                engine_id,
+                opaque: opaque.to_vec(),
+            },
         }
     }
 }
@@ -1173,7 +1377,10 @@ pub struct ChangesTrieConfiguration {
 
 /// Decodes a single digest log item. On success, returns the item and the data that remains
 /// after the item.
-fn decode_item(mut slice: &[u8]) -> Result<(DigestItemRef, &[u8]), Error> {
+fn decode_item<'a>(
+    mut slice: &'a [u8],
+    custom_engines: CustomEngines,
+) -> Result<(DigestItemRef<'a>, &'a [u8]), Error> {
     let index = *slice.get(0).ok_or(Error::TooShort)?;
     slice = &slice[1..];
 
@@ -1199,7 +1406,7 @@ fn decode_item(mut slice: &[u8]) -> Result<(DigestItemRef, &[u8]), Error> {
             let content = &slice[..len];
             slice = &slice[len..];
 
-            let item = decode_item_from_parts(index, engine_id, content)?;
+            let item = decode_item_from_parts(index, engine_id, content, custom_engines)?;
             Ok((item, slice))
         }
         2 => {
@@ -1225,7 +1432,13 @@ fn decode_item_from_parts<'a>(
     index: u8,
     engine_id: &'a [u8; 4],
     content: &'a [u8],
+    custom_engines: CustomEngines,
 ) -> Result<DigestItemRef<'a>, Error> {
+    let is_custom_engine = |engine_id: &[u8; 4]| match custom_engines {
+        CustomEngines::Only(list) => list.contains(engine_id),
+        CustomEngines::Any => true,
+    };
+
     Ok(match (index, engine_id) {
         (_, b"pow_") => return Err(Error::PowIdeologicallyNotSupported),
         (4, b"aura") => DigestItemRef::AuraConsensus(AuraConsensusLogRef::from_slice(content)?),
@@ -1234,17 +1447,29 @@ fn decode_item_from_parts<'a>(
             DigestItemRef::GrandpaConsensus(GrandpaConsensusLogRef::from_slice(content)?)
         }
         (4, b"BEEF") => DigestItemRef::Beefy { opaque: content },
-        (4, e) => return Err(Error::UnknownConsensusEngine(*e)),
+        (4, e) if is_custom_engine(e) => DigestItemRef::Custom {
+            kind: CustomDigestItemKind::Consensus,
+            engine_id: *e,
+            opaque: content,
+        },
         (5, b"aura") => DigestItemRef::AuraSeal({
             TryFrom::try_from(content).map_err(|_| Error::BadAuraSealLength)?
         }),
         (5, b"BABE") => DigestItemRef::BabeSeal({
             TryFrom::try_from(content).map_err(|_| Error::BadBabeSealLength)?
         }),
-        (5, e) => return Err(Error::UnknownConsensusEngine(*e)),
+        (5, e) if is_custom_engine(e) => DigestItemRef::Custom {
+            kind: CustomDigestItemKind::Seal,
+            engine_id: *e,
+            opaque: content,
+        },
         (6, b"aura") => DigestItemRef::AuraPreDigest(AuraPreDigest::from_slice(content)?),
         (6, b"BABE") => DigestItemRef::BabePreDigest(BabePreDigestRef::from_slice(content)?),
-        (6, e) => return Err(Error::UnknownConsensusEngine(*e)),
-        _ => unreachable!(),
+        (6, e) if is_custom_engine(e) => DigestItemRef::Custom {
+            kind: CustomDigestItemKind::PreRuntime,
+            engine_id: *e,
+            opaque: content,
+        },
+        (_, e) => return Err(Error::UnknownConsensusEngine(*e)),
     })
 }
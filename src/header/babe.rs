@@ -20,7 +20,7 @@ use crate::util;
 
 use alloc::vec::Vec;
 use core::{cmp, convert::TryFrom, fmt, iter, slice};
-use parity_scale_codec::{Decode as _, DecodeAll as _};
+use parity_scale_codec::DecodeAll as _;
 
 /// A consensus log item for BABE.
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -149,16 +149,16 @@ pub struct BabeNextEpochRef<'a> {
 
 impl<'a> BabeNextEpochRef<'a> {
     /// Decodes a [`BabePreDigestRef`] from a slice of bytes.
-    pub fn from_slice(mut slice: &'a [u8]) -> Result<Self, Error> {
-        // TODO: don't unwrap on decode fail
-        let authorities_len = usize::try_from(
-            parity_scale_codec::Compact::<u64>::decode(&mut slice)
-                .unwrap()
-                .0,
-        )
-        .unwrap();
-
-        if slice.len() != authorities_len * 40 + 32 {
+    pub fn from_slice(slice: &'a [u8]) -> Result<Self, Error> {
+        let (slice, authorities_len) =
+            util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(slice)
+                .map_err(|_| Error::TooShort)?;
+
+        if authorities_len
+            .checked_mul(40)
+            .and_then(|len| len.checked_add(32))
+            .map_or(true, |len| slice.len() != len)
+        {
             return Err(Error::TooShort);
         }
 
@@ -47,3 +47,77 @@ fn decode_polkadot() {
     // Has a GrandPa scheduled change.
     super::decode(include_bytes!("./tests-header-polkadot-512271")).unwrap();
 }
+
+#[test]
+fn reencode_and_hash_polkadot() {
+    let encoded = include_bytes!("./tests-header-polkadot-512271");
+    let decoded = super::decode(encoded).unwrap();
+
+    assert_eq!(decoded.scale_encoding_vec(), &encoded[..]);
+    assert_eq!(
+        decoded.hash(),
+        super::hash_from_scale_encoded_header(&encoded[..])
+    );
+
+    let mut hasher = super::HeaderHasher::new();
+    for chunk in encoded.chunks(7) {
+        hasher.update(chunk);
+    }
+    assert_eq!(
+        hasher.finalize(),
+        super::hash_from_scale_encoded_header(&encoded[..])
+    );
+}
+
+#[test]
+fn reencode_parsed_digest() {
+    let encoded = include_bytes!("./tests-header-polkadot-512271");
+    let decoded = super::decode(encoded).unwrap();
+
+    // Converting to an owned header and back forces the digest items to be re-encoded one by
+    // one rather than copied from `encoded`.
+    let owned = super::Header::from(decoded);
+    assert_eq!(
+        super::HeaderRef::from(&owned).scale_encoding_vec(),
+        &encoded[..]
+    );
+}
+
+#[test]
+fn custom_consensus_engine() {
+    let mut encoded = vec![0; 32];
+    encoded.push(4); // Block number 1.
+    encoded.extend_from_slice(&[0; 64]);
+    // One consensus item of engine `test` containing `[1, 2, 3]`.
+    encoded.extend_from_slice(&[4, 4, b't', b'e', b's', b't', 12, 1, 2, 3]);
+
+    assert!(matches!(
+        super::decode(&encoded),
+        Err(super::Error::UnknownConsensusEngine(e)) if e == *b"test"
+    ));
+    assert!(matches!(
+        super::decode_with_custom_engines(&encoded, &[*b"othr"]),
+        Err(super::Error::UnknownConsensusEngine(_))
+    ));
+
+    let decoded = super::decode_with_custom_engines(&encoded, &[*b"test"]).unwrap();
+    assert_eq!(
+        decoded.digest.logs().collect::<Vec<_>>(),
+        vec![super::DigestItemRef::Custom {
+            kind: super::CustomDigestItemKind::Consensus,
+            engine_id: *b"test",
+            opaque: &[1, 2, 3],
+        }]
+    );
+    assert_eq!(
+        decoded.digest.custom_items().collect::<Vec<_>>(),
+        vec![super::CustomDigestItem {
+            kind: super::CustomDigestItemKind::Consensus,
+            engine_id: *b"test",
+            opaque: vec![1, 2, 3],
+        }]
+    );
+
+    let owned = super::Header::from(decoded);
+    assert_eq!(super::HeaderRef::from(&owned).scale_encoding_vec(), encoded);
+}
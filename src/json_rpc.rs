@@ -59,6 +59,9 @@
 
 // TODO: write docs about usage ^
 
+#![cfg(feature = "json-rpc")]
+#![cfg_attr(docsrs, doc(cfg(feature = "json-rpc")))]
+
 pub mod methods;
 pub mod parse;
 pub mod websocket_server;
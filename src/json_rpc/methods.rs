@@ -161,6 +161,18 @@ macro_rules! define_methods {
                 [$(stringify!($name)),*].iter().copied()
             }
 
+            /// Returns the name of the method being called, as found in
+            /// [`MethodCall::method_names`].
+            ///
+            /// If the method has been called through an alias, the non-alias name is returned.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(
+                        MethodCall::$name { .. } => stringify!($name),
+                    )*
+                }
+            }
+
             fn from_defs(name: &'a str, params: &'a str) -> Result<Self, MethodError<'a>> {
                 #![allow(unused, unused_mut)]
 
@@ -272,11 +284,12 @@ define_methods! {
     author_hasKey() -> (), // TODO:
     author_hasSessionKeys() -> (), // TODO:
     author_insertKey() -> (), // TODO:
-    author_pendingExtrinsics() -> Vec<HexString>,  // TODO: what does the returned value mean?
-    author_removeExtrinsic() -> (), // TODO:
+    author_pendingExtrinsics() -> Vec<HexString>,
+    author_removeExtrinsic(bytes_or_hash: Vec<ExtrinsicOrHash>) -> Vec<HashHexString>,
     author_rotateKeys() -> HexString,
     author_submitAndWatchExtrinsic(transaction: HexString) -> &'a str,
     author_submitExtrinsic(transaction: HexString) -> HashHexString,
+    author_unstable_pool() -> Vec<PooledTransaction>,
     author_unwatchExtrinsic(subscription: &'a str) -> bool,
     babe_epochAuthorship() -> (), // TODO:
     chain_getBlock(hash: Option<HashHexString>) -> Block,
@@ -293,12 +306,13 @@ define_methods! {
     childstate_getStorage() -> (), // TODO:
     childstate_getStorageHash() -> (), // TODO:
     childstate_getStorageSize() -> (), // TODO:
-    grandpa_roundState() -> (), // TODO:
+    grandpa_proveFinality(block_number: u64) -> Option<HexString>,
+    grandpa_roundState() -> GrandpaRoundState,
     offchain_localStorageGet() -> (), // TODO:
     offchain_localStorageSet() -> (), // TODO:
     payment_queryInfo(extrinsic: HexString, hash: Option<HashHexString>) -> RuntimeDispatchInfo,
     rpc_methods() -> RpcMethods,
-    state_call() -> () [state_callAt], // TODO:
+    state_call(name: String, parameters: HexString, hash: Option<HashHexString>) -> HexString [state_callAt],
     state_getKeys() -> (), // TODO:
     state_getKeysPaged(prefix: Option<HexString>, count: u32, start_key: Option<HexString>, hash: Option<HashHexString>) -> Vec<HexString> [state_getKeysPagedAt],
     state_getMetadata() -> HexString,
@@ -314,11 +328,21 @@ define_methods! {
     state_subscribeStorage(list: Vec<HexString>) -> &'a str,
     state_unsubscribeRuntimeVersion() -> bool [chain_unsubscribeRuntimeVersion],
     state_unsubscribeStorage(subscription: &'a str) -> bool,
+    sudo_unstable_chainHeadState() -> ChainHeadState,
+    sudo_unstable_p2pRequest(peer_id: String, protocol_name: String, request: HexString) -> HexString,
+    sudo_unstable_parachainMessageQueues() -> ParachainMessageQueues,
+    sudo_unstable_refreshRuntime() -> (),
+    sudo_unstable_runtimeVersionAt(block_number: u64) -> Option<RuntimeVersionHistoryEntry>,
+    sudo_unstable_storageKey(module: String, entry: String, keys: Vec<HexString>) -> HexString,
+    sudo_unstable_unwatchAccount(subscription: &'a str) -> bool,
+    sudo_unstable_unwatchCandidateEvents(subscription: &'a str) -> bool,
+    sudo_unstable_watchAccount(account: AccountId) -> &'a str,
+    sudo_unstable_watchCandidateEvents() -> &'a str,
     system_accountNextIndex(account: AccountId) -> u64,
     system_addReservedPeer() -> (), // TODO:
     system_chain() -> &'a str,
     system_chainType() -> &'a str,
-    system_dryRun() -> () [system_dryRunAt], // TODO:
+    system_dryRun(extrinsic: HexString, hash: Option<HashHexString>) -> HexString [system_dryRunAt],
     system_health() -> SystemHealth,
     system_localListenAddresses() -> Vec<String>,
     system_localPeerId() -> &'a str,
@@ -328,6 +352,10 @@ define_methods! {
     system_peers() -> Vec<SystemPeer>,
     system_properties() -> Box<serde_json::value::RawValue>,
     system_removeReservedPeer() -> (), // TODO:
+    system_unstable_cpuUsage() -> CpuUsage,
+    system_unstable_memoryUsage() -> MemoryUsage,
+    system_unstable_requestTraces() -> Vec<RequestTrace>,
+    system_unstable_runtimeCallRecordings() -> Vec<Box<serde_json::value::RawValue>>,
     system_version() -> &'a str,
 }
 
@@ -414,6 +442,15 @@ impl<'a> serde::Deserialize<'a> for AccountId {
     }
 }
 
+/// Designates an extrinsic either by its SCALE encoding or by its hash.
+#[derive(Debug, Clone, serde::Deserialize)]
+pub enum ExtrinsicOrHash {
+    #[serde(rename = "hash")]
+    Hash(HashHexString),
+    #[serde(rename = "extrinsic")]
+    Extrinsic(HexString),
+}
+
 #[derive(Debug, Clone)]
 pub struct Block {
     pub extrinsics: Vec<Extrinsic>,
@@ -435,6 +472,10 @@ pub struct Header {
     #[serde(serialize_with = "hex_num")]
     pub number: u64,
     pub digest: HeaderDigest,
+    /// Public key of the author of the block. Not part of the SCALE-encoded header, and only
+    /// filled by the head subscriptions when it is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<HashHexString>,
 }
 
 impl Header {
@@ -460,6 +501,7 @@ impl Header {
                     })
                     .collect(),
             },
+            author: None,
         })
     }
 }
@@ -500,17 +542,358 @@ pub enum DispatchClass {
     Mandatory,
 }
 
+/// State of an account, as sent in `sudo_unstable_watchAccount` notifications.
+#[derive(Debug, Clone)]
+pub struct AccountState {
+    /// Hash of the block the state was read from.
+    pub block: HashHexString,
+    pub nonce: u32,
+    pub free: u128,
+    pub reserved: u128,
+    pub misc_frozen: u128,
+    pub fee_frozen: u128,
+}
+
+/// Event that happened to a candidate of a parachain, as sent in
+/// `sudo_unstable_watchCandidateEvents` notifications.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CandidateEvent {
+    /// Hash of the relay chain block the event has been read from.
+    #[serde(rename = "relayBlockHash")]
+    pub relay_block_hash: HashHexString,
+    /// Either `backed`, `included`, or `timedOut`.
+    pub kind: &'static str,
+    /// Hash of the relay chain block the candidate has been built against.
+    #[serde(rename = "relayParent")]
+    pub relay_parent: HashHexString,
+    /// Public key of the collator that has produced the candidate.
+    pub collator: HashHexString,
+    #[serde(rename = "povHash")]
+    pub pov_hash: HashHexString,
+    #[serde(rename = "paraHead")]
+    pub para_head: HashHexString,
+    #[serde(rename = "commitmentsHash")]
+    pub commitments_hash: HashHexString,
+    #[serde(rename = "headData")]
+    pub head_data: HexString,
+    #[serde(rename = "coreIndex")]
+    pub core_index: u32,
+    /// Always `None` for `timedOut` events.
+    #[serde(rename = "groupIndex")]
+    pub group_index: Option<u32>,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct StorageChangeSet {
     pub block: HashHexString,
     pub changes: Vec<(HexString, Option<HexString>)>,
 }
 
+/// CPU time spent by the client on behalf of a chain.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CpuUsage {
+    /// Number of milliseconds spent compiling and executing the runtime.
+    #[serde(rename = "runtimeExecutionMs")]
+    pub runtime_execution_ms: u64,
+    /// Number of milliseconds spent verifying Merkle proofs of the storage.
+    #[serde(rename = "proofVerificationMs")]
+    pub proof_verification_ms: u64,
+    /// Number of milliseconds spent verifying headers and finality proofs.
+    #[serde(rename = "headerVerificationMs")]
+    pub header_verification_ms: u64,
+    /// Relative weight of the chain when it comes to sharing the CPU with other chains.
+    pub weight: u32,
+}
+
+/// Memory used by the client. Shared between all the chains.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct MemoryUsage {
+    /// Number of bytes currently allocated.
+    #[serde(rename = "allocatedBytes")]
+    pub allocated_bytes: u64,
+    /// Highest number of bytes allocated at the same time since the client has started.
+    #[serde(rename = "peakAllocatedBytes")]
+    pub peak_allocated_bytes: u64,
+    /// Highest number of bytes allocated at the same time while executing the runtime.
+    #[serde(rename = "runtimeExecutionPeakBytes")]
+    pub runtime_execution_peak_bytes: u64,
+    /// Highest number of bytes allocated at the same time while verifying Merkle proofs.
+    #[serde(rename = "proofVerificationPeakBytes")]
+    pub proof_verification_peak_bytes: u64,
+    /// Highest number of bytes allocated at the same time while verifying headers and finality
+    /// proofs.
+    #[serde(rename = "headerVerificationPeakBytes")]
+    pub header_verification_peak_bytes: u64,
+    /// Number of memory allocations that have failed.
+    #[serde(rename = "failedAllocations")]
+    pub failed_allocations: u64,
+    /// Size of the WebAssembly linear memory, if relevant.
+    #[serde(rename = "linearMemoryBytes")]
+    pub linear_memory_bytes: Option<u64>,
+}
+
+/// Timeline of the work performed by the client in order to answer a JSON-RPC request.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RequestTrace {
+    /// Identifier of the trace, mentioned in the logs of the client.
+    #[serde(rename = "traceId")]
+    pub trace_id: u64,
+    /// Name of the JSON-RPC method of the request.
+    pub method: String,
+    /// Identifier of the request, as chosen by the JSON-RPC client.
+    #[serde(rename = "requestId")]
+    pub request_id: Box<serde_json::value::RawValue>,
+    /// Number of microseconds it took to process the request, or `None` if the request is still
+    /// being processed.
+    #[serde(rename = "durationUs")]
+    pub duration_us: Option<u64>,
+    /// What has been done in order to answer the request, ordered by end time.
+    pub events: Vec<RequestTraceEvent>,
+}
+
+/// See [`RequestTrace::events`].
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RequestTraceEvent {
+    /// Number of microseconds between the start of the request and the start of the event.
+    #[serde(rename = "startUs")]
+    pub start_us: u64,
+    /// Duration of the event in microseconds.
+    #[serde(rename = "durationUs")]
+    pub duration_us: u64,
+    /// Either `runtimeExecution`, or the kind of network request, such as `storage-proof`.
+    pub kind: String,
+    /// Peer the network request has been sent to. `None` for runtime executions.
+    #[serde(rename = "peerId", skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    /// Size in bytes of the response to the network request. `None` for runtime executions and
+    /// failed requests.
+    #[serde(rename = "responseSize", skip_serializing_if = "Option::is_none")]
+    pub response_size: Option<u64>,
+    /// Name of the runtime function that has been called. `None` for network requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub function: Option<String>,
+}
+
+/// Transaction tracked by the transactions service of the client.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct PooledTransaction {
+    pub hash: HashHexString,
+    pub extrinsic: HexString,
+    pub status: PooledTransactionStatus,
+    /// Tags provided by the transaction, or `None` if the transaction hasn't been validated.
+    pub provides: Option<Vec<HexString>>,
+    /// Tags required by the transaction, or `None` if the transaction hasn't been validated.
+    pub requires: Option<Vec<HexString>>,
+    /// Number of times the transaction has been sent out to at least one peer.
+    #[serde(rename = "broadcastCount")]
+    pub broadcast_count: u32,
+    /// Number of the first block where the transaction is no longer valid, or `None` if the
+    /// transaction is immortal or its era is unknown.
+    #[serde(rename = "deathBlock")]
+    pub death_block: Option<u64>,
+}
+
+/// See [`PooledTransaction::status`].
+#[derive(Debug, Copy, Clone, serde::Serialize)]
+pub enum PooledTransactionStatus {
+    /// Transaction hasn't been sent out to any peer yet.
+    #[serde(rename = "ready")]
+    Ready,
+    /// Transaction has been sent out to at least one peer.
+    #[serde(rename = "broadcast")]
+    Broadcast,
+}
+
+/// Lightweight version of the GrandPa state returned by full nodes.
+///
+/// The light client doesn't follow the progress of the GrandPa rounds, and only reports the
+/// authorities set of the finalized block.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GrandpaRoundState {
+    /// Identifier of the authorities set.
+    #[serde(rename = "setId")]
+    pub set_id: u64,
+    /// Hash of the finalized block the state applies to.
+    #[serde(rename = "finalizedBlockHash")]
+    pub finalized_block_hash: HashHexString,
+    /// Height of the finalized block the state applies to.
+    #[serde(rename = "finalizedBlockNumber")]
+    pub finalized_block_number: u64,
+    pub authorities: Vec<GrandpaAuthority>,
+    /// Sum of the weights of all the authorities.
+    #[serde(rename = "totalWeight")]
+    pub total_weight: u64,
+    /// Minimum weight of the votes necessary in order to finalize a block.
+    #[serde(rename = "thresholdWeight")]
+    pub threshold_weight: u64,
+    /// Change of authorities set scheduled for a future block, if any.
+    #[serde(rename = "scheduledChange")]
+    pub scheduled_change: Option<GrandpaScheduledChange>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GrandpaAuthority {
+    /// Ed25519 public key of the authority.
+    #[serde(rename = "publicKey")]
+    pub public_key: HashHexString,
+    pub weight: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GrandpaScheduledChange {
+    /// Height of the block at which the new authorities set is enacted.
+    #[serde(rename = "blockNumber")]
+    pub block_number: u64,
+    pub authorities: Vec<GrandpaAuthority>,
+}
+
+/// Snapshot of the state of the head of the chain, meant to be attached to bug reports.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChainHeadState {
+    /// Hash of the current finalized block.
+    #[serde(rename = "finalizedBlockHash")]
+    pub finalized_block_hash: HashHexString,
+    /// Height of the current finalized block.
+    #[serde(rename = "finalizedBlockNumber")]
+    pub finalized_block_number: u64,
+    /// Hash of the current best block.
+    #[serde(rename = "bestBlockHash")]
+    pub best_block_hash: HashHexString,
+    /// Tree of the blocks that descend from the finalized block.
+    #[serde(rename = "nonFinalizedBlocks")]
+    pub non_finalized_blocks: Vec<ChainHeadStateBlock>,
+    /// Authorities set tracked by GrandPa, or `None` if the chain doesn't use GrandPa.
+    pub grandpa: Option<GrandpaRoundState>,
+    /// Specification version of the runtime of the best block, or `None` if it isn't known.
+    #[serde(rename = "runtimeSpecVersion")]
+    pub runtime_spec_version: Option<u64>,
+    /// Number of peers the syncing is aware of.
+    pub sources: u64,
+    /// Number of blocks requests in progress.
+    #[serde(rename = "pendingBlockRequests")]
+    pub pending_block_requests: u64,
+    /// Number of GrandPa warp sync requests in progress.
+    #[serde(rename = "pendingGrandpaRequests")]
+    pub pending_grandpa_requests: u64,
+    /// Number of storage requests in progress.
+    #[serde(rename = "pendingStorageRequests")]
+    pub pending_storage_requests: u64,
+    /// State of the queue of network requests necessary to keep track of the head of the chain.
+    #[serde(rename = "blockTrackingQueue")]
+    pub block_tracking_queue: ChainHeadStateQueue,
+    /// State of the queue of network requests performed in order to answer JSON-RPC requests.
+    #[serde(rename = "jsonRpcQueue")]
+    pub json_rpc_queue: ChainHeadStateQueue,
+    /// Downloads of the headers of old blocks in progress, such as the ones necessary to answer
+    /// `chain_getHeader` for a block older than the finalized block.
+    #[serde(rename = "ancestryQueries")]
+    pub ancestry_queries: Vec<ChainHeadStateAncestryQuery>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChainHeadStateBlock {
+    pub hash: HashHexString,
+    pub number: u64,
+    #[serde(rename = "parentHash")]
+    pub parent_hash: HashHexString,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChainHeadStateQueue {
+    /// Number of requests of this queue in progress.
+    #[serde(rename = "inProgress")]
+    pub in_progress: u64,
+    /// Number of requests of this queue waiting to be started.
+    pub queued: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ChainHeadStateAncestryQuery {
+    /// Number of the requested block, if the block was requested by number.
+    #[serde(rename = "targetNumber")]
+    pub target_number: Option<u64>,
+    /// Hash of the requested block, if the block was requested by hash.
+    #[serde(rename = "targetHash")]
+    pub target_hash: Option<HashHexString>,
+    /// Height of the block whose ancestry is verified.
+    #[serde(rename = "anchorNumber")]
+    pub anchor_number: u64,
+    /// Height of the lowest block whose header has been verified so far.
+    #[serde(rename = "verifiedNumber")]
+    pub verified_number: u64,
+    /// Maximum number of headers that remain to be downloaded.
+    pub remaining: u64,
+}
+
+/// State of the message queues of a parachain, as found in the storage of its relay chain.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ParachainMessageQueues {
+    /// Hash of the relay chain block whose storage the state has been read from.
+    #[serde(rename = "relayBlockHash")]
+    pub relay_block_hash: HashHexString,
+    /// Identifier of the parachain within the relay chain.
+    #[serde(rename = "paraId")]
+    pub para_id: u32,
+    /// Head of the chain of messages sent by the relay chain to the parachain, or `None` if no
+    /// such message has ever been sent.
+    #[serde(rename = "downwardQueueHead")]
+    pub downward_queue_head: Option<HashHexString>,
+    /// Number of messages sent by the parachain to the relay chain and not processed yet.
+    #[serde(rename = "upwardQueueCount")]
+    pub upward_queue_count: u32,
+    /// Total size in bytes of the messages sent by the parachain to the relay chain and not
+    /// processed yet.
+    #[serde(rename = "upwardQueueSize")]
+    pub upward_queue_size: u32,
+    /// HRMP channels whose recipient is the parachain.
+    #[serde(rename = "inboundChannels")]
+    pub inbound_channels: Vec<HrmpChannelState>,
+    /// HRMP channels whose sender is the parachain.
+    #[serde(rename = "outboundChannels")]
+    pub outbound_channels: Vec<HrmpChannelState>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HrmpChannelState {
+    /// Identifier of the parachain at the other end of the channel.
+    #[serde(rename = "paraId")]
+    pub para_id: u32,
+    /// Number of messages pending in the channel.
+    #[serde(rename = "messageCount")]
+    pub message_count: u32,
+    /// Total size in bytes of the messages pending in the channel.
+    #[serde(rename = "totalSize")]
+    pub total_size: u32,
+    #[serde(rename = "maxCapacity")]
+    pub max_capacity: u32,
+    #[serde(rename = "maxTotalSize")]
+    pub max_total_size: u32,
+    #[serde(rename = "maxMessageSize")]
+    pub max_message_size: u32,
+    /// Head of the chain of messages sent through the channel, or `None` if no message has ever
+    /// been sent.
+    #[serde(rename = "mqcHead")]
+    pub mqc_head: Option<HashHexString>,
+}
+
+/// Runtime version that governed a block, as returned by `sudo_unstable_runtimeVersionAt`.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RuntimeVersionHistoryEntry {
+    #[serde(rename = "specVersion")]
+    pub spec_version: u32,
+    /// Number of the first block this runtime version has been observed at. The runtime upgrade
+    /// might have happened at an earlier block.
+    #[serde(rename = "firstObservedBlock")]
+    pub first_observed_block: u64,
+}
+
 #[derive(Debug, Clone)]
 pub struct SystemHealth {
     pub is_syncing: bool,
     pub peers: u64,
     pub should_have_peers: bool,
+    pub peer_origins: Option<u64>, // Number of distinct network origins of the peers. Not part of Substrate's API.
+    pub required_peer_origins: Option<u64>, // Not part of Substrate's API.
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -518,6 +901,10 @@ pub struct SystemPeer {
     #[serde(rename = "peerId")]
     pub peer_id: String, // Example: "12D3KooWHEQXbvCzLYvc87obHV6HY4rruHz8BJ9Lw1Gg2csVfR6Z"
     pub roles: String, // "AUTHORITY", "FULL", or "LIGHT"
+    #[serde(rename = "agentVersion", skip_serializing_if = "Option::is_none")]
+    pub agent_version: Option<String>, // Example: "Parity Polkadot/v0.9.8-3a10ee63c-x86_64-linux-gnu (node-name)"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub protocols: Vec<String>,
     #[serde(rename = "bestHash")]
     pub best_hash: HashHexString,
     #[serde(rename = "bestNumber")]
@@ -686,6 +1073,36 @@ impl serde::Serialize for RuntimeDispatchInfo {
     }
 }
 
+impl serde::Serialize for AccountState {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        #[derive(serde::Serialize)]
+        struct SerdeAccountState<'a> {
+            block: &'a HashHexString,
+            nonce: u32,
+            // Balances are sent back as strings in order to not accidentally lose precision.
+            free: String,
+            reserved: String,
+            #[serde(rename = "miscFrozen")]
+            misc_frozen: String,
+            #[serde(rename = "feeFrozen")]
+            fee_frozen: String,
+        }
+
+        SerdeAccountState {
+            block: &self.block,
+            nonce: self.nonce,
+            free: self.free.to_string(),
+            reserved: self.reserved.to_string(),
+            misc_frozen: self.misc_frozen.to_string(),
+            fee_frozen: self.fee_frozen.to_string(),
+        }
+        .serialize(serializer)
+    }
+}
+
 impl serde::Serialize for SystemHealth {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -698,12 +1115,21 @@ impl serde::Serialize for SystemHealth {
             peers: u64,
             #[serde(rename = "shouldHavePeers")]
             should_have_peers: bool,
+            #[serde(rename = "peerOrigins", skip_serializing_if = "Option::is_none")]
+            peer_origins: Option<u64>,
+            #[serde(
+                rename = "requiredPeerOrigins",
+                skip_serializing_if = "Option::is_none"
+            )]
+            required_peer_origins: Option<u64>,
         }
 
         SerdeSystemHealth {
             is_syncing: self.is_syncing,
             peers: self.peers,
             should_have_peers: self.should_have_peers,
+            peer_origins: self.peer_origins,
+            required_peer_origins: self.required_peer_origins,
         }
         .serialize(serializer)
     }
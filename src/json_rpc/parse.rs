@@ -18,6 +18,7 @@
 //! Parse JSON-RPC method calls and notifications, and build responses messages.
 
 use alloc::string::String;
+use core::iter;
 
 /// Parses a JSON-encoded RPC method call or notification.
 pub fn parse_call(call_json: &str) -> Result<Call, ParseError> {
@@ -87,6 +88,90 @@ pub fn build_success_response(id_json: &str, result_json: &str) -> String {
     .unwrap()
 }
 
+/// Builds a JSON response whose result couldn't be verified by the client, for example because
+/// it was obtained from a trusted JSON-RPC server rather than from proofs.
+///
+/// The response is identical to the one of [`build_success_response`], with an additional
+/// `unverified` field set to `true`. JSON-RPC clients that aren't aware of this field ignore it.
+///
+/// `id_json` must be the JSON-formatted identifier of the request, found in [`Call::id_json`].
+/// `result_json` must be the JSON-formatted result of the request.
+///
+/// # Example
+///
+/// ```
+/// # use smoldot::json_rpc::parse;
+/// let result_json = parse::build_unverified_success_response("27", r#""0x1234""#);
+///
+/// assert_eq!(
+///     result_json,
+///     r#"{"jsonrpc":"2.0","id":27,"result":"0x1234","unverified":true}"#
+/// );
+/// ```
+///
+/// # Panic
+///
+/// Panics if `id_json` or `result_json` aren't valid JSON.
+///
+pub fn build_unverified_success_response(id_json: &str, result_json: &str) -> String {
+    serde_json::to_string(&SerdeUnverifiedSuccess {
+        jsonrpc: SerdeVersion::V2,
+        id: serde_json::from_str(id_json).expect("invalid id_json"),
+        result: serde_json::from_str(result_json).expect("invalid result_json"),
+        unverified: true,
+    })
+    .unwrap()
+}
+
+/// Builds a JSON response whose result is the hexadecimal encoding of `result`, and returns it
+/// as a list of chunks.
+///
+/// Concatenating all the chunks yields the same output as [`build_success_response`] would
+/// when passed `"0x"` followed with the hexadecimal encoding of `result`. Contrary to
+/// [`build_success_response`], however, the response never has to be entirely held in memory.
+/// This is useful for responses that can be several megabytes large, such as the runtime
+/// metadata.
+///
+/// Each chunk encodes at most `chunk_size` bytes of `result`, except for the first and last
+/// chunks which contain the beginning and end of the JSON response.
+///
+/// `id_json` must be the JSON-formatted identifier of the request, found in [`Call::id_json`].
+///
+/// # Example
+///
+/// ```
+/// # use smoldot::json_rpc::parse;
+/// let chunks = parse::build_success_response_hex_chunks("27", &[0xde, 0xad, 0xbe, 0xef], 3)
+///     .collect::<Vec<_>>();
+///
+/// assert_eq!(chunks.concat(), r#"{"jsonrpc":"2.0","id":27,"result":"0xdeadbeef"}"#);
+/// assert_eq!(chunks.len(), 4);
+/// ```
+///
+/// # Panic
+///
+/// Panics if `id_json` isn't valid JSON.
+/// Panics if `chunk_size` is 0.
+///
+pub fn build_success_response_hex_chunks<'a>(
+    id_json: &str,
+    result: &'a [u8],
+    chunk_size: usize,
+) -> impl Iterator<Item = String> + 'a {
+    assert_ne!(chunk_size, 0);
+
+    // The prefix is generated by building a response with an empty hexadecimal string and
+    // removing the closing quote and brace, in order to guarantee that the output is identical
+    // to the one of `build_success_response`.
+    let mut prefix = build_success_response(id_json, r#""0x""#);
+    debug_assert!(prefix.ends_with(r#""0x"}"#));
+    prefix.truncate(prefix.len() - 2);
+
+    iter::once(prefix)
+        .chain(result.chunks(chunk_size).map(hex::encode))
+        .chain(iter::once(String::from(r#""}"#)))
+}
+
 /// Builds a JSON event to a subscription.
 ///
 /// `method` must be the name of the method that was used for the subscription. `id` must
@@ -149,6 +234,7 @@ pub fn build_error_response(
             "Invalid method parameter(s).",
         ),
         ErrorResponse::InternalError => (SerdeErrorCode::InternalError, "Internal JSON-RPC error."),
+        ErrorResponse::InternalErrorWithMessage(msg) => (SerdeErrorCode::InternalError, msg),
         ErrorResponse::ServerError(n, msg) => {
             assert!((-32099..=-32000).contains(&n));
             (SerdeErrorCode::ServerError(n), msg)
@@ -189,6 +275,9 @@ pub enum ErrorResponse<'a> {
     /// Internal JSON-RPC error.
     InternalError,
 
+    /// Same as [`ErrorResponse::InternalError`], but with a custom message.
+    InternalErrorWithMessage(&'a str),
+
     /// Other internal server error.
     /// Contains a more precise error code and a custom message.
     /// Error code must be in the range -32000 to -32099 included.
@@ -249,6 +338,14 @@ struct SerdeSuccess<'a> {
     result: &'a serde_json::value::RawValue,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+struct SerdeUnverifiedSuccess<'a> {
+    jsonrpc: SerdeVersion,
+    id: &'a serde_json::value::RawValue,
+    result: &'a serde_json::value::RawValue,
+    unverified: bool,
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(deny_unknown_fields)]
 struct SerdeFailure<'a> {
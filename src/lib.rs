@@ -195,6 +195,9 @@ extern crate alloc;
 // Necessary because of `#![deny(unused_crate_dependencies)]`.
 #[cfg(test)]
 use criterion as _;
+// These crates are only used by the JSON-RPC websocket server.
+#[cfg(all(feature = "std", not(feature = "json-rpc")))]
+use {async_std as _, soketto as _};
 
 pub mod author;
 pub mod chain;
@@ -211,12 +214,35 @@ pub mod network;
 pub mod sync;
 pub mod transactions;
 pub mod trie;
+pub mod util;
 pub mod verify;
 
-mod util;
+/// Wrappers around crate-private functions, exposed for the fuzzing targets of the `fuzz`
+/// directory. Not part of the public API.
+#[doc(hidden)]
+#[cfg(fuzzing)]
+pub mod fuzzing {
+    /// Decodes a SCALE-compact-encoded `usize` at the start of `bytes`. Returns the value and
+    /// the number of bytes it occupies, or `None` if decoding failed.
+    pub fn decode_scale_compact_usize(bytes: &[u8]) -> Option<(usize, usize)> {
+        let (rest, value) =
+            crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(bytes).ok()?;
+        Some((value, bytes.len() - rest.len()))
+    }
+
+    /// Returns the SCALE-compact encoding of `value`.
+    pub fn encode_scale_compact_usize(value: usize) -> alloc::vec::Vec<u8> {
+        crate::util::encode_scale_compact_usize(value)
+            .as_ref()
+            .to_vec()
+    }
+}
 
 /// Builds the header of the genesis block, from the values in storage.
 ///
+/// This function performs the calculation every time it is called. Prefer
+/// [`chain_spec::ChainSpec::genesis_block_header`], which caches the result.
+///
 /// # Example
 ///
 /// ```no_run
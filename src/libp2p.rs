@@ -94,7 +94,7 @@
 // the substream which is thought to be the old one but is actually the new one.
 //
 
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
 use connection::established;
 use core::{
     iter, mem,
@@ -117,6 +117,7 @@ pub mod connection;
 pub mod discovery;
 pub mod peer_id;
 pub mod peerset;
+pub mod relay;
 
 pub use established::{ConfigRequestResponse, ConfigRequestResponseIn};
 pub use multiaddr::Multiaddr;
@@ -165,6 +166,23 @@ pub struct Config<TPeer> {
     /// This value is important if [`Network::next_event`] is called at a slower than the calls to
     /// [`Network::read_write`] generate events.
     pub pending_api_events_buffer_size: NonZeroUsize,
+
+    /// Maximum number of bytes of partially-received messages buffered for each connection.
+    ///
+    /// When this limit is reached on a connection, calls to [`Network::request`] targeting this
+    /// connection wait for some of the buffered messages to finish being received before sending
+    /// out the request. This prevents a single peer from using an unbounded amount of memory.
+    ///
+    /// Each individual message is additionally limited by the maximum size configured for its
+    /// protocol, such as [`ConfigRequestResponse::max_response_size`].
+    pub max_connection_receive_buffer_size: usize,
+
+    /// If `true`, nodes whose addresses go through a relay (i.e. contain `/p2p-circuit`) can be
+    /// connected to. See [the `relay` module](relay).
+    ///
+    /// If `false`, such addresses are ignored by [`Network::fill_out_slots`], and
+    /// [`Network::open_relay_circuit`] always returns an error.
+    pub allow_relayed_connections: bool,
 }
 
 /// Configuration for a specific overlay network.
@@ -204,8 +222,9 @@ pub struct Network<TNow, TPeer, TConn> {
     /// Fields behind a mutex.
     guarded: Mutex<Guarded<TNow, TPeer, TConn>>,
 
-    /// See [`Config::noise_key`].
-    noise_key: connection::NoiseKey,
+    /// Key used for the encryption layer of new connections. Initially [`Config::noise_key`],
+    /// and can later be replaced with [`Network::set_noise_key`].
+    noise_key: Mutex<Arc<connection::NoiseKey>>,
 
     /// See [`OverlayNetwork`].
     overlay_networks: Arc<[OverlayNetwork]>,
@@ -216,6 +235,12 @@ pub struct Network<TNow, TPeer, TConn> {
     /// See [`Config::ping_protocol`].
     ping_protocol: String,
 
+    /// See [`Config::max_connection_receive_buffer_size`].
+    max_connection_receive_buffer_size: usize,
+
+    /// See [`Config::allow_relayed_connections`].
+    allow_relayed_connections: bool,
+
     /// Generator for randomness seeds given to the established connections.
     randomness_seeds: Mutex<ChaCha20Rng>,
 
@@ -244,7 +269,15 @@ struct Guarded<TNow, TPeer, TConn> {
     peerset: peerset::Peerset<
         TPeer,
         Arc<Mutex<Connection<TNow, TConn>>>,
-        Arc<Mutex<Option<(connection::handshake::HealthyHandshake, TConn)>>>,
+        Arc<
+            Mutex<
+                Option<(
+                    connection::handshake::HealthyHandshake,
+                    Arc<connection::NoiseKey>,
+                    TConn,
+                )>,
+            >,
+        >,
         established::SubstreamId,
         established::SubstreamId,
     >,
@@ -292,10 +325,12 @@ where
         }
 
         Network {
-            noise_key: config.noise_key,
+            noise_key: Mutex::new(Arc::new(config.noise_key)),
             overlay_networks,
             request_response_protocols: config.request_response_protocols,
             ping_protocol: config.ping_protocol,
+            max_connection_receive_buffer_size: config.max_connection_receive_buffer_size,
+            allow_relayed_connections: config.allow_relayed_connections,
             events_rx: Mutex::new(events_rx),
             guarded: Mutex::new(Guarded { peerset, events_tx }),
             randomness_seeds: Mutex::new(ChaCha20Rng::from_seed(config.randomness_seed)),
@@ -311,9 +346,41 @@ where
             .num_established_connections()
     }
 
-    /// Returns the Noise key originalled passed as [`Config::noise_key`].
-    pub fn noise_key(&self) -> &connection::NoiseKey {
-        &self.noise_key
+    /// Returns the Noise key used for new connections. This is the key originally passed as
+    /// [`Config::noise_key`], unless [`Network::set_noise_key`] has been called.
+    pub async fn noise_key(&self) -> Arc<connection::NoiseKey> {
+        self.noise_key.lock().await.clone()
+    }
+
+    /// Replaces the Noise key used for new connections, and thus the identity of the local node
+    /// as seen by the remotes of these connections.
+    ///
+    /// Connections that are already established, or whose dialing has been reported as
+    /// successful with [`Network::pending_outcome_ok`], continue to use the previous key.
+    pub async fn set_noise_key(&self, key: connection::NoiseKey) {
+        *self.noise_key.lock().await = Arc::new(key);
+    }
+
+    /// Returns the Noise key that was used for the given connection, or `None` if the connection
+    /// is no longer alive.
+    ///
+    /// This can differ from [`Network::noise_key`] if [`Network::set_noise_key`] has been called
+    /// after the connection has been opened.
+    pub async fn connection_noise_key(
+        &self,
+        id: ConnectionId,
+    ) -> Option<Arc<connection::NoiseKey>> {
+        let connection_arc: Arc<Mutex<Connection<_, _>>> = {
+            let mut guarded = self.guarded.lock().await;
+            guarded
+                .peerset
+                .connection_mut(id.0)?
+                .user_data_mut()
+                .clone()
+        };
+
+        let connection_lock = connection_arc.lock().await;
+        Some(connection_lock.noise_key.clone())
     }
 
     /// Returns the list the overlay networks originally passed as [`Config::overlay_networks`].
@@ -359,6 +426,60 @@ where
         node.add_to_overlay(self.overlay_networks[overlay_network_index].peerset_id);
     }
 
+    /// Removes the given peer from the given overlay network, and closes the outbound
+    /// notifications substream of this overlay network with this peer, if any.
+    ///
+    /// No new substream or connection is opened with this peer for the purpose of this overlay
+    /// network, until [`Network::add_addresses`] is called again. The existing connections with
+    /// the peer are kept alive.
+    ///
+    /// Has no effect if the peer isn't part of this overlay network.
+    pub async fn remove_from_overlay(&self, peer_id: &PeerId, overlay_network_index: usize) {
+        let peerset_id = self.overlay_networks[overlay_network_index].peerset_id;
+
+        // Find the substreams to close, and remove them from the peerset.
+        let substreams = {
+            let mut guarded = self.guarded.lock().await;
+
+            let connections = match guarded.peerset.node_mut(peer_id.clone()) {
+                peerset::NodeMut::Known(mut node) => {
+                    node.remove_from_overlay(peerset_id);
+                    node.connections().collect::<Vec<_>>()
+                }
+                peerset::NodeMut::Unknown(_) => return,
+            };
+
+            let mut substreams = Vec::with_capacity(connections.len());
+            for connection_id in connections {
+                let mut connection = guarded.peerset.connection_mut(connection_id).unwrap();
+                if let Ok(substream_id) =
+                    connection.remove_substream(peerset_id, peerset::SubstreamDirection::Out)
+                {
+                    substreams.push((connection.user_data_mut().clone(), substream_id));
+                }
+            }
+            substreams
+        };
+
+        for (connection_arc, substream_id) in substreams {
+            let mut connection_lock = connection_arc.lock().await;
+
+            // The substream might have been reset by the remote in the meanwhile.
+            if let Some(established) = connection_lock.connection.as_alive() {
+                if established
+                    .notifications_substream_user_data_mut(substream_id)
+                    .is_some()
+                {
+                    established.close_notifications_substream(substream_id);
+                }
+            }
+
+            if let Some(waker) = connection_lock.waker.take() {
+                let _ = waker.send(());
+            }
+        }
+    }
+
     pub fn add_incoming_connection(
         &self,
         _local_listen_address: &Multiaddr,
@@ -383,6 +504,10 @@ where
     /// the protocol limits (see [`ConfigRequestResponse`]), or if the remote takes too much time
     /// to answer.
     ///
+    /// If `max_response_size` is `Some`, responses larger than this value are refused even if
+    /// they respect [`ConfigRequestResponse::max_response_size`]. This makes it possible to
+    /// refuse a response early when its expected size is known in advance.
+    ///
     /// As the API of this module is inherently subject to race conditions, it is never possible
     /// to guarantee that this function will succeed. [`RequestError::ConnectionClosed`] should
     /// be handled by retrying the same request again.
@@ -401,6 +526,7 @@ where
         target: PeerId,
         protocol_index: usize,
         request_data: Vec<u8>,
+        max_response_size: Option<usize>,
     ) -> Result<Vec<u8>, RequestError> {
         // Determine which connect to use to send the request.
         let connection_arc: Arc<Mutex<Connection<_, _>>> = {
@@ -428,7 +554,42 @@ where
 
         // Lock to the connection. This waits for any other call to `request`,
         // `queue_notification` or `read_write` to finish.
-        let mut connection_lock = connection_arc.lock().await;
+        // If the receive buffer of the connection is full, wait for it to drain before starting
+        // the request. This wait is bounded by the timeout of the protocol.
+        let mut now = now;
+        let wait_deadline = now.clone() + self.request_response_protocols[protocol_index].timeout;
+        let mut connection_lock = loop {
+            let mut connection_lock = connection_arc.lock().await;
+
+            let is_full = connection_lock
+                .connection
+                .as_alive()
+                .ok_or(RequestError::ConnectionClosed)?
+                .is_receive_buffer_full();
+            if !is_full {
+                break connection_lock;
+            }
+
+            if now >= wait_deadline {
+                return Err(RequestError::Connection(established::RequestError::Timeout));
+            }
+
+            let (tx, rx) = oneshot::channel();
+            connection_lock
+                .receive_buffer_waiters
+                .push((wait_deadline.clone(), tx));
+            // Wake up the connection so that it takes the deadline of the new waiter into
+            // account.
+            if let Some(waker) = connection_lock.waker.take() {
+                let _ = waker.send(());
+            }
+            drop(connection_lock);
+
+            // Waiters are always resumed with the value of `now` of the `read_write` call that
+            // resumed them, as the `now` passed as parameter is potentially stale at this point.
+            // The sender is only ever dropped without resuming when the connection is destroyed.
+            now = rx.await.map_err(|_| RequestError::ConnectionClosed)?;
+        };
 
         // Actually start the request by updating the underlying state machine specific to that
         // connection.
@@ -436,7 +597,13 @@ where
             .connection
             .as_alive()
             .ok_or(RequestError::ConnectionClosed)?
-            .add_request(now, protocol_index, request_data, send_back);
+            .add_request(
+                now,
+                protocol_index,
+                request_data,
+                max_response_size,
+                send_back,
+            );
 
         // Note that no update of the `Guarded` is necessary. The `Guarded` doesn't track ongoing
         // requests.
@@ -462,6 +629,159 @@ where
         }
     }
 
+    /// Asks `relay` to relay a connection to `target`. There must be an established connection
+    /// with `relay`.
+    ///
+    /// On success, the returned [`RelayCircuit`] behaves like a raw connection to `target`. Data
+    /// sent by `target` can be read from the [`RelayCircuit`], and data can be sent to `target`
+    /// with [`Network::write_relay_circuit`]. This is typically used in response to a
+    /// [`StartConnect`] whose address goes through a relay, in which case the libp2p handshake
+    /// with `target` is then performed on top of the [`RelayCircuit`].
+    ///
+    /// > **Note**: Similarly to [`Network::request`], this function doesn't return before the
+    /// >           relay has answered.
+    pub async fn open_relay_circuit(
+        &self,
+        now: TNow,
+        relay: &PeerId,
+        target: &PeerId,
+    ) -> Result<RelayCircuit, OpenRelayCircuitError> {
+        if !self.allow_relayed_connections {
+            return Err(OpenRelayCircuitError::Disabled);
+        }
+
+        // Determine which connection to the relay to use.
+        let connection_arc: Arc<Mutex<Connection<_, _>>> = {
+            let mut guarded = self.guarded.lock().await;
+
+            let connection = match guarded.peerset.node_mut(relay.clone()) {
+                peerset::NodeMut::Known(n) => n
+                    .connections()
+                    .next()
+                    .ok_or(OpenRelayCircuitError::NotConnected)?,
+                peerset::NodeMut::Unknown(_) => return Err(OpenRelayCircuitError::NotConnected),
+            };
+
+            guarded
+                .peerset
+                .connection_mut(connection)
+                .unwrap()
+                .into_user_data()
+                .clone()
+        };
+
+        // The outcome of the opening is sent back on `send_back`, while the data later sent by
+        // the target is sent on `data_tx`.
+        let (send_back, receive_result) = oneshot::channel();
+        let (data_tx, data_rx) = mpsc::unbounded();
+
+        let mut connection_lock = connection_arc.lock().await;
+        let relay_connection_id = ConnectionId(connection_lock.id);
+        let substream_id = connection_lock
+            .connection
+            .as_alive()
+            .ok_or(OpenRelayCircuitError::ConnectionClosed)?
+            .open_relay_circuit(now, target);
+        connection_lock.relay_circuits.insert(
+            substream_id,
+            RelayCircuitState::Opening { send_back, data_tx },
+        );
+
+        let waker = connection_lock.waker.take();
+        drop(connection_lock);
+        drop(connection_arc);
+
+        if let Some(waker) = waker {
+            let _ = waker.send(());
+        }
+
+        // If this future is dropped, `send_back` fails and the substream is closed.
+        match receive_result.await {
+            Ok(Ok(())) => Ok(RelayCircuit {
+                relay_connection_id,
+                substream_id,
+                incoming: data_rx,
+            }),
+            Ok(Err(err)) => Err(err),
+            Err(_) => Err(OpenRelayCircuitError::ConnectionClosed),
+        }
+    }
+
+    /// Queues data to be sent to the target of a [`RelayCircuit`].
+    ///
+    /// Returns an error if the circuit or the connection to the relay has been closed.
+    // TODO: no back-pressure
+    pub async fn write_relay_circuit(
+        &self,
+        circuit: &RelayCircuit,
+        data: Vec<u8>,
+    ) -> Result<(), RelayCircuitClosedError> {
+        let connection_arc = match self.relay_circuit_connection(circuit).await {
+            Some(c) => c,
+            None => return Err(RelayCircuitClosedError),
+        };
+
+        let mut connection_lock = connection_arc.lock().await;
+        if !matches!(
+            connection_lock.relay_circuits.get(&circuit.substream_id),
+            Some(RelayCircuitState::Open(_))
+        ) {
+            return Err(RelayCircuitClosedError);
+        }
+
+        connection_lock
+            .connection
+            .as_alive()
+            .ok_or(RelayCircuitClosedError)?
+            .write_relay_circuit(circuit.substream_id, data);
+
+        if let Some(waker) = connection_lock.waker.take() {
+            let _ = waker.send(());
+        }
+
+        Ok(())
+    }
+
+    /// Abruptly closes a [`RelayCircuit`].
+    ///
+    /// Has no effect if the circuit or the connection to the relay has already been closed.
+    pub async fn close_relay_circuit(&self, circuit: RelayCircuit) {
+        let connection_arc = match self.relay_circuit_connection(&circuit).await {
+            Some(c) => c,
+            None => return,
+        };
+
+        let mut connection_lock = connection_arc.lock().await;
+        if connection_lock
+            .relay_circuits
+            .remove(&circuit.substream_id)
+            .is_none()
+        {
+            return;
+        }
+
+        if let Some(established) = connection_lock.connection.as_alive() {
+            established.close_relay_circuit(circuit.substream_id);
+        }
+
+        if let Some(waker) = connection_lock.waker.take() {
+            let _ = waker.send(());
+        }
+    }
+
+    /// Returns the connection to the relay that the given [`RelayCircuit`] goes through, or
+    /// `None` if this connection no longer exists.
+    async fn relay_circuit_connection(
+        &self,
+        circuit: &RelayCircuit,
+    ) -> Option<Arc<Mutex<Connection<TNow, TConn>>>> {
+        let mut guarded = self.guarded.lock().await;
+        let mut connection = guarded
+            .peerset
+            .connection_mut(circuit.relay_connection_id.0)?;
+        Some(connection.user_data_mut().clone())
+    }
+
     /// Adds a notification to the queue of notifications to send to the given peer.
     ///
     /// Each substream maintains a queue of notifications to be sent to the remote. This method
@@ -584,6 +904,7 @@ where
         assert!(conn.is_none());
         *conn = Some((
             connection::handshake::HealthyHandshake::new(true),
+            self.noise_key().await,
             user_data,
         ));
         ConnectionId(id.0)
@@ -803,7 +1124,7 @@ where
                     }
                 };
 
-                let (handshake, user_data) = pending.take().unwrap();
+                let (handshake, noise_key, user_data) = pending.take().unwrap();
 
                 let mut tx = Some(tx);
 
@@ -836,7 +1157,7 @@ where
                 loop {
                     match result {
                         connection::handshake::Handshake::Healthy(updated_handshake) => {
-                            *pending = Some((updated_handshake, user_data));
+                            *pending = Some((updated_handshake, noise_key, user_data));
                             break;
                         }
                         connection::handshake::Handshake::Success {
@@ -863,6 +1184,9 @@ where
                                         user_data: Some(user_data),
                                         pending_event: None,
                                         waker: None,
+                                        receive_buffer_waiters: Vec::new(),
+                                        relay_circuits: BTreeMap::new(),
+                                        noise_key,
                                     }))
                                 }
                             });
@@ -892,7 +1216,7 @@ where
                             break;
                         }
                         connection::handshake::Handshake::NoiseKeyRequired(key) => {
-                            result = key.resume(&self.noise_key).into();
+                            result = key.resume(&noise_key).into();
                         }
                     }
                 }
@@ -942,6 +1266,7 @@ where
             request_protocols: self.request_response_protocols.clone(),
             randomness_seed,
             ping_protocol: self.ping_protocol.clone(), // TODO: cloning :-/
+            max_receive_buffer_size: self.max_connection_receive_buffer_size,
         }
     }
 
@@ -979,12 +1304,44 @@ where
         // TODO: limit number of slots
 
         // TODO: very wip
-        while let Some(mut node) = guarded
+        let mut node = guarded
             .peerset
-            .random_not_connected(self.overlay_networks[overlay_network_index].peerset_id)
-        {
-            let first_addr = node.known_addresses().cloned().next();
-            if let Some(multiaddr) = first_addr {
+            .random_not_connected(self.overlay_networks[overlay_network_index].peerset_id)?;
+
+        // Addresses that can be connected to directly are preferred over the ones that go through
+        // a relay.
+        let direct_addr = node
+            .known_addresses()
+            .find(|addr| !relay::is_relayed_address(addr))
+            .cloned();
+        if let Some(multiaddr) = direct_addr {
+            let id = node.add_outbound_attempt(multiaddr.clone(), Arc::new(Mutex::new(None)));
+            return Some(StartConnect {
+                id: PendingId(id),
+                multiaddr,
+                expected_peer_id: node.peer_id().clone(),
+            });
+        }
+
+        if !self.allow_relayed_connections {
+            return None;
+        }
+
+        let target = node.peer_id().clone();
+        let relayed_addrs = node
+            .known_addresses()
+            .filter_map(|addr| Some((addr.clone(), relay::split_relayed_address(addr)?)))
+            .collect::<Vec<_>>();
+
+        for (multiaddr, (relay_addr, relay_peer_id)) in relayed_addrs {
+            let mut relay_node = match guarded.peerset.node_mut(relay_peer_id.clone()) {
+                peerset::NodeMut::Known(n) => n,
+                peerset::NodeMut::Unknown(_) => continue,
+            };
+
+            if relay_node.connections().next().is_some() {
+                // The target can be reached through this relay.
+                let mut node = guarded.peerset.node_mut(target).into_known().unwrap();
                 let id = node.add_outbound_attempt(multiaddr.clone(), Arc::new(Mutex::new(None)));
                 return Some(StartConnect {
                     id: PendingId(id),
@@ -992,6 +1349,20 @@ where
                     expected_peer_id: node.peer_id().clone(),
                 });
             }
+
+            if relay_node.pending_connections().next().is_some() {
+                continue;
+            }
+
+            // Not connected to the relay yet. Connect to the relay first. The target will be
+            // reached through it during a later call to this function.
+            let id =
+                relay_node.add_outbound_attempt(relay_addr.clone(), Arc::new(Mutex::new(None)));
+            return Some(StartConnect {
+                id: PendingId(id),
+                multiaddr: relay_addr,
+                expected_peer_id: relay_peer_id,
+            });
         }
 
         None
@@ -1024,6 +1395,35 @@ struct Connection<TNow, TConn> {
     /// Send a value on that channel in order to notify that data is potentially available to be
     /// sent on the socket, or that the user should call [`Network::read_write`] in general.
     waker: Option<oneshot::Sender<()>>,
+
+    /// Senders notified when the receive buffer of the connection is no longer full, when the
+    /// connection is no longer alive, or when their deadline is reached. Used to delay new
+    /// requests. The current time is sent on the channel. See
+    /// [`Config::max_connection_receive_buffer_size`].
+    receive_buffer_waiters: Vec<(TNow, oneshot::Sender<TNow>)>,
+
+    /// Relayed connections going through this connection. See [`Network::open_relay_circuit`].
+    ///
+    /// Events concerning these substreams are handled directly by [`Connection::read_write`] and
+    /// never reach the [`Guarded`].
+    relay_circuits: BTreeMap<established::SubstreamId, RelayCircuitState>,
+
+    /// Noise key used during the handshake of this connection.
+    /// See [`Network::connection_noise_key`].
+    noise_key: Arc<connection::NoiseKey>,
+}
+
+/// State of a relayed connection going through a [`Connection`].
+enum RelayCircuitState {
+    /// Waiting for the relay to accept or refuse.
+    Opening {
+        /// Sender connected to [`Network::open_relay_circuit`].
+        send_back: oneshot::Sender<Result<(), OpenRelayCircuitError>>,
+        /// Sender connected to [`RelayCircuit::incoming`].
+        data_tx: mpsc::UnboundedSender<Vec<u8>>,
+    },
+    /// Circuit is open. Contains a sender connected to [`RelayCircuit::incoming`].
+    Open(mpsc::UnboundedSender<Vec<u8>>),
 }
 
 enum ConnectionInner<TNow> {
@@ -1047,6 +1447,27 @@ impl<TNow> ConnectionInner<TNow> {
     }
 }
 
+/// Resumes the entries of [`Connection::receive_buffer_waiters`] that must be resumed, which is
+/// all of them if `is_full` is `false`, or those whose deadline is inferior or equal to `now`
+/// otherwise.
+///
+/// Returns the earliest deadline of the entries that haven't been resumed, if any.
+fn resume_receive_buffer_waiters<TNow: Clone + Ord>(
+    waiters: &mut Vec<(TNow, oneshot::Sender<TNow>)>,
+    is_full: bool,
+    now: &TNow,
+) -> Option<TNow> {
+    for (deadline, waiter) in mem::take(waiters) {
+        if !is_full || deadline <= *now {
+            let _ = waiter.send(now.clone());
+        } else {
+            waiters.push((deadline, waiter));
+        }
+    }
+
+    waiters.iter().map(|(deadline, _)| deadline).min().cloned()
+}
+
 enum PendingEvent {
     Inner(established::Event<oneshot::Sender<Result<Vec<u8>, RequestError>>, usize>),
     Disconnect,
@@ -1070,7 +1491,7 @@ where
             ConnectionInner::Poisoned => unreachable!(),
         };
 
-        match connection.read_write(now, incoming_buffer, outgoing_buffer) {
+        match connection.read_write(now.clone(), incoming_buffer, outgoing_buffer) {
             Ok(read_write_result) => {
                 read_write.read_bytes += read_write_result.read_bytes;
                 read_write.written_bytes += read_write_result.written_bytes;
@@ -1094,8 +1515,23 @@ where
                 }
 
                 if let Some(event) = read_write_result.event {
-                    debug_assert!(self.pending_event.is_none());
-                    self.pending_event = Some(PendingEvent::Inner(event));
+                    if let Some(event) = self.on_relay_circuit_event(event) {
+                        debug_assert!(self.pending_event.is_none());
+                        self.pending_event = Some(PendingEvent::Inner(event));
+                    }
+                }
+
+                // Resume the requests waiting for the receive buffer to drain, or whose deadline
+                // has been reached, and make sure that `read_write` is called again when the
+                // earliest remaining deadline is reached.
+                let is_full =
+                    matches!(self.connection.as_alive(), Some(c) if c.is_receive_buffer_full());
+                if let Some(earliest) =
+                    resume_receive_buffer_waiters(&mut self.receive_buffer_waiters, is_full, &now)
+                {
+                    if !matches!(read_write.wake_up_after, Some(ref w) if *w <= earliest) {
+                        read_write.wake_up_after = Some(earliest);
+                    }
                 }
             }
             Err(err) => {
@@ -1103,6 +1539,13 @@ where
                     let _ = waker.send(());
                 }
 
+                for (_, waiter) in self.receive_buffer_waiters.drain(..) {
+                    let _ = waiter.send(now.clone());
+                }
+
+                // Dropping the senders notifies the relayed connections that they are closed.
+                self.relay_circuits.clear();
+
                 self.connection = ConnectionInner::Errored(ConnectionError::Established(err));
                 self.pending_event = Some(PendingEvent::Disconnect);
             }
@@ -1111,6 +1554,62 @@ where
         Ok(())
     }
 
+    /// Handles the events concerning relayed connections. Returns back the event if it isn't
+    /// related to a relayed connection.
+    fn on_relay_circuit_event(
+        &mut self,
+        event: established::Event<oneshot::Sender<Result<Vec<u8>, RequestError>>, usize>,
+    ) -> Option<established::Event<oneshot::Sender<Result<Vec<u8>, RequestError>>, usize>> {
+        match event {
+            established::Event::RelayCircuitOpen { id, initial_data } => {
+                if let Some(RelayCircuitState::Opening { send_back, data_tx }) =
+                    self.relay_circuits.remove(&id)
+                {
+                    if !initial_data.is_empty() {
+                        let _ = data_tx.unbounded_send(initial_data);
+                    }
+
+                    if send_back.send(Ok(())).is_ok() {
+                        self.relay_circuits
+                            .insert(id, RelayCircuitState::Open(data_tx));
+                    } else if let Some(established) = self.connection.as_alive() {
+                        // The call to `open_relay_circuit` has been interrupted.
+                        established.close_relay_circuit(id);
+                    }
+                }
+                None
+            }
+            established::Event::RelayCircuitOpenFailure { id, error } => {
+                if let Some(RelayCircuitState::Opening { send_back, .. }) =
+                    self.relay_circuits.remove(&id)
+                {
+                    let _ = send_back.send(Err(OpenRelayCircuitError::Relay(error)));
+                }
+                None
+            }
+            established::Event::RelayCircuitData { id, data } => {
+                let is_closed = match self.relay_circuits.get(&id) {
+                    Some(RelayCircuitState::Open(data_tx)) => data_tx.unbounded_send(data).is_err(),
+                    _ => false,
+                };
+
+                // The `RelayCircuit` has been dropped without being closed.
+                if is_closed {
+                    self.relay_circuits.remove(&id);
+                    if let Some(established) = self.connection.as_alive() {
+                        established.close_relay_circuit(id);
+                    }
+                }
+                None
+            }
+            established::Event::RelayCircuitClosed { id } => {
+                self.relay_circuits.remove(&id);
+                None
+            }
+            event => Some(event),
+        }
+    }
+
     /// Removes the pending event stored within that connection and updates the [`Guarded`]
     /// accordingly.
     /// See the implementations notes at the top of the file for more information.
@@ -1300,13 +1799,16 @@ where
             }) => {
                 let mut connection = guarded.peerset.connection_mut(self.id).unwrap();
                 let peer_id = connection.peer_id().clone();
-                let _expected_id = connection
-                    .remove_substream(
-                        self.overlay_networks[overlay_network_index].peerset_id,
-                        peerset::SubstreamDirection::Out,
-                    )
-                    .unwrap();
-                debug_assert_eq!(id, _expected_id);
+
+                // The substream is missing from the peerset if it has been closed with
+                // `Network::remove_from_overlay` in the meanwhile.
+                match connection.remove_substream(
+                    self.overlay_networks[overlay_network_index].peerset_id,
+                    peerset::SubstreamDirection::Out,
+                ) {
+                    Ok(_expected_id) => debug_assert_eq!(id, _expected_id),
+                    Err(()) => return,
+                }
 
                 guarded
                     .events_tx
@@ -1317,6 +1819,13 @@ where
                     })
                     .unwrap();
             }
+            PendingEvent::Inner(established::Event::RelayCircuitOpen { .. })
+            | PendingEvent::Inner(established::Event::RelayCircuitOpenFailure { .. })
+            | PendingEvent::Inner(established::Event::RelayCircuitData { .. })
+            | PendingEvent::Inner(established::Event::RelayCircuitClosed { .. }) => {
+                // Handled by `on_relay_circuit_event`.
+                unreachable!()
+            }
             PendingEvent::Disconnect => {
                 let mut out_overlay_network_indices =
                     Vec::with_capacity(guarded.peerset.num_overlay_networks());
@@ -1493,6 +2002,46 @@ impl Future for ConnectionReadyFuture {
     }
 }
 
+/// Connection to a node established through a relay. See [`Network::open_relay_circuit`].
+///
+/// Implements the [`Stream`] trait. Yields the data sent by the target, and ends when the circuit
+/// has been closed.
+pub struct RelayCircuit {
+    /// Connection to the relay.
+    relay_connection_id: ConnectionId,
+    /// Substream within the connection to the relay.
+    substream_id: established::SubstreamId,
+    /// Receives the data sent by the target.
+    incoming: mpsc::UnboundedReceiver<Vec<u8>>,
+}
+
+impl Stream for RelayCircuit {
+    type Item = Vec<u8>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Vec<u8>>> {
+        self.incoming.poll_next_unpin(cx)
+    }
+}
+
+/// Error potentially returned by [`Network::open_relay_circuit`].
+#[derive(Debug, derive_more::Display)]
+pub enum OpenRelayCircuitError {
+    /// Relayed connections are disabled. See [`Config::allow_relayed_connections`].
+    Disabled,
+    /// Not connected to the relay.
+    NotConnected,
+    /// Connection to the relay has been closed during the opening.
+    ConnectionClosed,
+    /// Error in the context of the connection to the relay.
+    #[display(fmt = "{}", _0)]
+    Relay(established::RelayCircuitError),
+}
+
+/// Error returned by [`Network::write_relay_circuit`] when the circuit has been closed.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Relayed connection closed")]
+pub struct RelayCircuitClosedError;
+
 /// Protocol error within the context of a connection. See [`Network::read_write`].
 #[derive(Debug, derive_more::Display)]
 pub enum ConnectionError {
@@ -1609,3 +2158,56 @@ pub enum QueueNotificationError {
     /// Queue of notifications with that peer is full.
     QueueFull,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::resume_receive_buffer_waiters;
+    use core::time::Duration;
+    use futures::channel::oneshot;
+
+    #[test]
+    fn receive_buffer_waiters_resumed_when_not_full() {
+        let mut waiters = Vec::new();
+        let (tx1, mut rx1) = oneshot::channel();
+        let (tx2, mut rx2) = oneshot::channel();
+        waiters.push((Duration::from_secs(10), tx1));
+        waiters.push((Duration::from_secs(20), tx2));
+
+        let now = Duration::from_secs(5);
+        assert_eq!(
+            resume_receive_buffer_waiters(&mut waiters, false, &now),
+            None
+        );
+        assert!(waiters.is_empty());
+        assert_eq!(rx1.try_recv().unwrap(), Some(now));
+        assert_eq!(rx2.try_recv().unwrap(), Some(now));
+    }
+
+    #[test]
+    fn receive_buffer_waiters_resumed_at_deadline() {
+        let mut waiters = Vec::new();
+        let (tx1, mut rx1) = oneshot::channel();
+        let (tx2, mut rx2) = oneshot::channel();
+        waiters.push((Duration::from_secs(20), tx1));
+        waiters.push((Duration::from_secs(10), tx2));
+
+        // Buffer still full and no deadline reached.
+        assert_eq!(
+            resume_receive_buffer_waiters(&mut waiters, true, &Duration::from_secs(5)),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(rx1.try_recv().unwrap(), None);
+        assert_eq!(rx2.try_recv().unwrap(), None);
+
+        // The waiter is resumed with the current time, which lets the request notice that its
+        // deadline has been reached.
+        let now = Duration::from_secs(15);
+        assert_eq!(
+            resume_receive_buffer_waiters(&mut waiters, true, &now),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(rx1.try_recv().unwrap(), None);
+        assert_eq!(rx2.try_recv().unwrap(), Some(now));
+        assert_eq!(waiters.len(), 1);
+    }
+}
@@ -48,6 +48,7 @@
 
 // TODO: expand docs ^
 
+use crate::libp2p::{peer_id::PeerId, relay};
 use crate::util::leb128;
 
 use super::{multistream_select, noise, yamux};
@@ -57,11 +58,15 @@ use alloc::{
     vec::{self, Vec},
 };
 use core::{
-    cmp, fmt, iter, mem,
+    cmp,
+    convert::TryFrom as _,
+    fmt, iter, mem,
     ops::{Add, Sub},
     time::Duration,
 };
 
+mod tests;
+
 /// State machine of a fully-established connection.
 pub struct Established<TNow, TRqUd, TNotifUd> {
     /// Encryption layer applied directly on top of the incoming data and outgoing data.
@@ -95,6 +100,8 @@ struct Inner<TNow, TRqUd, TNotifUd> {
     notifications_protocols: Vec<ConfigNotifications>,
     /// See [`Config::ping_protocol`].
     ping_protocol: String,
+    /// See [`Config::max_receive_buffer_size`].
+    max_receive_buffer_size: usize,
 }
 
 enum Substream<TNow, TRqUd, TNotifUd> {
@@ -178,6 +185,10 @@ enum Substream<TNow, TRqUd, TNotifUd> {
         /// If `None`, nothing should be sent on the substream at all, not even the length prefix.
         /// This contrasts with `Some(empty_vec)` where a `0` length prefix must be sent.
         request: Option<Vec<u8>>,
+        /// Index of the protocol within [`Config::request_protocols`].
+        protocol_index: usize,
+        /// Maximum size of the response, in bytes.
+        max_response_size: usize,
         /// Data passed by the user to [`Established::add_request`].
         user_data: TRqUd,
     },
@@ -205,6 +216,27 @@ enum Substream<TNow, TRqUd, TNotifUd> {
 
     /// Inbound ping substream. Waiting for the ping payload to be received.
     PingIn(arrayvec::ArrayVec<u8, 32>),
+
+    /// Negotiating the relay protocol on an outgoing substream, in order to reach another node
+    /// through the remote.
+    RelayOutNegotiating {
+        /// When the opening will time out in the absence of response.
+        timeout: TNow,
+        /// State of the protocol negotiation.
+        negotiation: multistream_select::InProgress<vec::IntoIter<String>, String>,
+        /// Bytes of the connect request to send after the substream is open.
+        request: Vec<u8>,
+    },
+    /// The relay protocol has been negotiated and the connect request sent. Waiting for the
+    /// relay to report whether the target could be reached.
+    RelayOutStatus {
+        /// When the opening will time out in the absence of response.
+        timeout: TNow,
+        /// Buffer for the incoming status message.
+        response: leb128::FramedInProgress,
+    },
+    /// Substream connected to another node through the remote. Data is passed through as-is.
+    RelayCircuit,
 }
 
 impl<TNow, TRqUd, TNotifUd> Established<TNow, TRqUd, TNotifUd>
@@ -441,6 +473,34 @@ where
                                 }),
                             });
                         }
+                        Substream::RelayOutNegotiating { .. }
+                        | Substream::RelayOutStatus { .. } => {
+                            let wake_up_after = self.inner.next_timeout.clone();
+                            return Ok(ReadWrite {
+                                connection: self,
+                                read_bytes: total_read,
+                                written_bytes: total_written,
+                                write_close: false,
+                                wake_up_after,
+                                event: Some(Event::RelayCircuitOpenFailure {
+                                    id: SubstreamId(substream_id),
+                                    error: RelayCircuitError::SubstreamClosed,
+                                }),
+                            });
+                        }
+                        Substream::RelayCircuit => {
+                            let wake_up_after = self.inner.next_timeout.clone();
+                            return Ok(ReadWrite {
+                                connection: self,
+                                read_bytes: total_read,
+                                written_bytes: total_written,
+                                write_close: false,
+                                wake_up_after,
+                                event: Some(Event::RelayCircuitClosed {
+                                    id: SubstreamId(substream_id),
+                                }),
+                            });
+                        }
                     }
                 }
 
@@ -461,6 +521,14 @@ where
                     self.encryption
                         .consume_inbound_data(yamux_decode.bytes_read);
 
+                    // If the data has made the receive buffer go over the limit, the substream
+                    // that has received it is reset. It is not possible to simply stop reading
+                    // from the socket, as this would also block the other substreams.
+                    let event = match event {
+                        Some(event) => Some(event),
+                        None => self.enforce_receive_buffer_limit(substream_id),
+                    };
+
                     if let Some(event) = event {
                         let wake_up_after = self.inner.next_timeout.clone();
                         return Ok(ReadWrite {
@@ -523,6 +591,30 @@ where
         })
     }
 
+    /// Resets the given substream if [`Established::receive_buffer_size`] exceeds
+    /// [`Config::max_receive_buffer_size`]. Returns the event to report to the user, if any.
+    fn enforce_receive_buffer_limit(
+        &mut self,
+        substream_id: yamux::SubstreamId,
+    ) -> Option<Event<TRqUd, TNotifUd>> {
+        if self.receive_buffer_size() <= self.inner.max_receive_buffer_size {
+            return None;
+        }
+
+        // The substream might have already been destroyed while processing the data.
+        let substream = self.inner.yamux.substream_by_id(substream_id)?;
+        let ty = substream.reset();
+
+        match self.on_substream_reset(substream_id, ty) {
+            Some(Event::Response { id, user_data, .. }) => Some(Event::Response {
+                id,
+                user_data,
+                response: Err(RequestError::ReceiveBufferFull),
+            }),
+            other => other,
+        }
+    }
+
     fn on_substream_reset(
         &mut self,
         substream_id: yamux::SubstreamId,
@@ -565,6 +657,15 @@ where
             }),
             Substream::NotificationsOutClosed { .. } => None,
             Substream::RequestInSend => None,
+            Substream::RelayOutNegotiating { .. } | Substream::RelayOutStatus { .. } => {
+                Some(Event::RelayCircuitOpenFailure {
+                    id: SubstreamId(substream_id),
+                    error: RelayCircuitError::SubstreamReset,
+                })
+            }
+            Substream::RelayCircuit => Some(Event::RelayCircuitClosed {
+                id: SubstreamId(substream_id),
+            }),
         }
     }
 
@@ -587,6 +688,8 @@ where
                 Substream::NotificationsOutNegotiating { timeout, .. }
                 | Substream::RequestOutNegotiating { timeout, .. }
                 | Substream::RequestOut { timeout, .. }
+                | Substream::RelayOutNegotiating { timeout, .. }
+                | Substream::RelayOutStatus { timeout, .. }
                     if *timeout <= now =>
                 {
                     true
@@ -619,6 +722,12 @@ where
                     response: Err(RequestError::Timeout),
                     user_data,
                 },
+                Substream::RelayOutNegotiating { .. } | Substream::RelayOutStatus { .. } => {
+                    Event::RelayCircuitOpenFailure {
+                        id: SubstreamId(timed_out_substream),
+                        error: RelayCircuitError::Timeout,
+                    }
+                }
                 _ => unreachable!(),
             })
         } else {
@@ -636,7 +745,9 @@ where
             .filter_map(|(_, substream)| match &substream {
                 Substream::NotificationsOutNegotiating { timeout, .. }
                 | Substream::RequestOutNegotiating { timeout, .. }
-                | Substream::RequestOut { timeout, .. } => Some(timeout),
+                | Substream::RequestOut { timeout, .. }
+                | Substream::RelayOutNegotiating { timeout, .. }
+                | Substream::RelayOutStatus { timeout, .. } => Some(timeout),
                 _ => None,
             })
             .min()
@@ -657,11 +768,16 @@ where
     ///
     /// After the remote has sent back a response, an [`Event::Response`] event will be generated
     /// locally. The `user_data` parameter will be passed back.
+    ///
+    /// If `max_response_size` is `Some`, it is used as the maximum size of the response instead
+    /// of [`ConfigRequestResponse::max_response_size`] if it is lower. Responses that are larger
+    /// than this limit are rejected as soon as their length prefix is received.
     pub fn add_request(
         &mut self,
         now: TNow,
         protocol_index: usize,
         request: Vec<u8>,
+        max_response_size: Option<usize>,
         user_data: TRqUd,
     ) -> SubstreamId {
         let mut negotiation =
@@ -699,6 +815,11 @@ where
             self.inner.next_timeout = Some(timeout.clone());
         }
 
+        let max_response_size = {
+            let protocol_max = self.inner.request_protocols[protocol_index].max_response_size;
+            max_response_size.map_or(protocol_max, |max| cmp::min(max, protocol_max))
+        };
+
         let mut substream = self
             .inner
             .yamux
@@ -710,10 +831,14 @@ where
                 } else {
                     None
                 },
+                protocol_index,
+                max_response_size,
                 user_data,
             });
 
-        substream.reserve_window(128 * 1024 * 1024 + 128); // TODO: proper max size
+        // The remote is allowed to send the response at once. The `128` accounts for the
+        // protocol negotiation and the length prefix of the response.
+        substream.reserve_window(u64::try_from(max_response_size).unwrap() + 128);
         substream.write(out_buffer);
 
         SubstreamId(substream.id())
@@ -796,6 +921,8 @@ where
                     user_data,
                 });
 
+        // Notifications are small and latency-sensitive, and are sent out ahead of responses.
+        substream.set_priority(yamux::Priority::High);
         substream.write(out_buffer);
 
         SubstreamId(substream.id())
@@ -894,6 +1021,33 @@ where
         substream.queued_bytes()
     }
 
+    /// Returns the number of bytes of partially-received messages currently buffered for this
+    /// connection, all substreams combined.
+    pub fn receive_buffer_size(&self) -> usize {
+        self.inner
+            .yamux
+            .user_datas()
+            .map(|(_, substream)| match substream {
+                Substream::NotificationsOutHandshakeRecv { handshake, .. }
+                | Substream::NotificationsInHandshake { handshake, .. } => handshake.buffered_len(),
+                Substream::NotificationsIn {
+                    next_notification, ..
+                } => next_notification.buffered_len(),
+                Substream::RequestOut { response, .. } => response.buffered_len(),
+                Substream::RequestInRecv { request, .. } => request.buffered_len(),
+                Substream::RelayOutStatus { response, .. } => response.buffered_len(),
+                _ => 0,
+            })
+            .fold(0, |a, b| a.saturating_add(b))
+    }
+
+    /// Returns `true` if [`Established::receive_buffer_size`] has reached
+    /// [`Config::max_receive_buffer_size`], in which case no new request should be started on
+    /// this connection until some of the buffered messages have finished being received.
+    pub fn is_receive_buffer_full(&self) -> bool {
+        self.receive_buffer_size() >= self.inner.max_receive_buffer_size
+    }
+
     /// Closes a notifications substream.
     ///
     /// # Panic
@@ -910,6 +1064,109 @@ where
         substream.close();
     }
 
+    /// Asks the remote to relay a connection to the given target, using the circuit relay v2
+    /// protocol. See [the `relay` module](crate::libp2p::relay).
+    ///
+    /// This method only inserts the opening request into the connection object. Use
+    /// [`Established::read_write`] in order to actually send out the request.
+    ///
+    /// Either an [`Event::RelayCircuitOpen`] or an [`Event::RelayCircuitOpenFailure`] will later
+    /// be generated. After an [`Event::RelayCircuitOpen`], the substream behaves like a raw
+    /// connection to the target, and [`Event::RelayCircuitData`] events are generated whenever
+    /// the target sends data.
+    pub fn open_relay_circuit(&mut self, now: TNow, target: &PeerId) -> SubstreamId {
+        let mut negotiation =
+            multistream_select::InProgress::new(multistream_select::Config::Dialer {
+                requested_protocol: relay::HOP_PROTOCOL_NAME.into(),
+            });
+
+        let (new_state, _, out_buffer) = negotiation.read_write_vec(&[]).unwrap();
+        match new_state {
+            multistream_select::Negotiation::InProgress(n) => negotiation = n,
+            _ => unreachable!(),
+        }
+
+        let timeout = now + Duration::from_secs(20); // TODO:
+
+        if self
+            .inner
+            .next_timeout
+            .as_ref()
+            .map_or(true, |t| *t > timeout)
+        {
+            self.inner.next_timeout = Some(timeout.clone());
+        }
+
+        let mut substream = self
+            .inner
+            .yamux
+            .open_substream(Substream::RelayOutNegotiating {
+                timeout,
+                negotiation,
+                request: relay::build_connect_request(target),
+            });
+
+        substream.write(out_buffer);
+
+        SubstreamId(substream.id())
+    }
+
+    /// Queues data to be sent to the target of a relayed connection.
+    ///
+    /// # About back-pressure
+    ///
+    /// Similarly to [`Established::write_notification_unbounded`], this method unconditionally
+    /// queues up data. Use [`Established::relay_circuit_queued_bytes`] in order to determine
+    /// whether the remote is keeping up.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SubstreamId`] doesn't correspond to an open relayed connection.
+    ///
+    pub fn write_relay_circuit(&mut self, id: SubstreamId, data: Vec<u8>) {
+        let mut substream = self.inner.yamux.substream_by_id(id.0).unwrap();
+        if !matches!(substream.user_data(), Substream::RelayCircuit) {
+            panic!()
+        }
+        substream.write(data)
+    }
+
+    /// Returns the number of bytes waiting to be sent out on that relayed connection.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SubstreamId`] doesn't correspond to an open relayed connection.
+    ///
+    // TODO: shouldn't require `&mut self`
+    pub fn relay_circuit_queued_bytes(&mut self, id: SubstreamId) -> usize {
+        let mut substream = self.inner.yamux.substream_by_id(id.0).unwrap();
+        if !matches!(substream.user_data(), Substream::RelayCircuit) {
+            panic!()
+        }
+        substream.queued_bytes()
+    }
+
+    /// Abruptly closes a relayed connection, or cancels its opening.
+    ///
+    /// No event is generated about this substream afterwards.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SubstreamId`] doesn't correspond to a relayed connection.
+    ///
+    pub fn close_relay_circuit(&mut self, id: SubstreamId) {
+        let mut substream = self.inner.yamux.substream_by_id(id.0).unwrap();
+        if !matches!(
+            substream.user_data(),
+            Substream::RelayOutNegotiating { .. }
+                | Substream::RelayOutStatus { .. }
+                | Substream::RelayCircuit
+        ) {
+            panic!()
+        }
+        substream.reset();
+    }
+
     /// Responds to an incoming request. Must be called in response to a [`Event::RequestIn`].
     ///
     /// Passing an `Err` corresponds, on the other side, to a [`RequestError::SubstreamClosed`].
@@ -1011,6 +1268,7 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                             .iter()
                             .position(|p| p.name == protocol)
                         {
+                            substream.set_priority(yamux::Priority::High);
                             *substream.user_data() = Substream::NotificationsInHandshake {
                                 protocol_index,
                                 handshake: leb128::FramedInProgress::new(
@@ -1115,57 +1373,55 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
                     negotiation,
                     timeout,
                     request,
+                    protocol_index,
+                    max_response_size,
                     user_data,
-                } => {
-                    match negotiation.read_write_vec(data) {
-                        Ok((
-                            multistream_select::Negotiation::InProgress(nego),
-                            _read,
-                            out_buffer,
-                        )) => {
-                            debug_assert_eq!(_read, data.len());
-                            data = &data[_read..];
-                            substream.write(out_buffer);
-                            *substream.user_data() = Substream::RequestOutNegotiating {
-                                negotiation: nego,
-                                timeout,
-                                request,
-                                user_data,
-                            };
-                        }
-                        Ok((multistream_select::Negotiation::Success(_), num_read, out_buffer)) => {
-                            substream.write(out_buffer);
-                            data = &data[num_read..];
-                            if let Some(request) = request {
-                                substream.write(leb128::encode_usize(request.len()).collect());
-                                substream.write(request);
-                            }
-                            *substream.user_data() = Substream::RequestOut {
-                                timeout,
-                                user_data,
-                                response: leb128::FramedInProgress::new(128 * 1024 * 1024), // TODO: proper max size
-                            };
-                            let _already_closed = substream.close();
-                            debug_assert!(_already_closed.is_none());
-                        }
-                        Ok((multistream_select::Negotiation::NotAvailable, ..)) => {
-                            substream.reset();
-                            return Some(Event::Response {
-                                id: substream_id,
-                                user_data,
-                                response: Err(RequestError::ProtocolNotAvailable),
-                            });
-                        }
-                        Err(err) => {
-                            substream.reset();
-                            return Some(Event::Response {
-                                id: substream_id,
-                                user_data,
-                                response: Err(RequestError::NegotiationError(err)),
-                            });
+                } => match negotiation.read_write_vec(data) {
+                    Ok((multistream_select::Negotiation::InProgress(nego), _read, out_buffer)) => {
+                        debug_assert_eq!(_read, data.len());
+                        data = &data[_read..];
+                        substream.write(out_buffer);
+                        *substream.user_data() = Substream::RequestOutNegotiating {
+                            negotiation: nego,
+                            timeout,
+                            request,
+                            protocol_index,
+                            max_response_size,
+                            user_data,
+                        };
+                    }
+                    Ok((multistream_select::Negotiation::Success(_), num_read, out_buffer)) => {
+                        substream.write(out_buffer);
+                        data = &data[num_read..];
+                        if let Some(request) = request {
+                            substream.write(leb128::encode_usize(request.len()).collect());
+                            substream.write(request);
                         }
+                        *substream.user_data() = Substream::RequestOut {
+                            timeout,
+                            user_data,
+                            response: leb128::FramedInProgress::new(max_response_size),
+                        };
+                        let _already_closed = substream.close();
+                        debug_assert!(_already_closed.is_none());
                     }
-                }
+                    Ok((multistream_select::Negotiation::NotAvailable, ..)) => {
+                        substream.reset();
+                        return Some(Event::Response {
+                            id: substream_id,
+                            user_data,
+                            response: Err(RequestError::ProtocolNotAvailable),
+                        });
+                    }
+                    Err(err) => {
+                        substream.reset();
+                        return Some(Event::Response {
+                            id: substream_id,
+                            user_data,
+                            response: Err(RequestError::NegotiationError(err)),
+                        });
+                    }
+                },
                 Substream::RequestOut {
                     timeout,
                     user_data,
@@ -1323,6 +1579,90 @@ impl<TNow, TRqUd, TNotifUd> Inner<TNow, TRqUd, TNotifUd> {
 
                     *substream.user_data() = Substream::PingIn(payload);
                 }
+                Substream::RelayOutNegotiating {
+                    negotiation,
+                    timeout,
+                    request,
+                } => match negotiation.read_write_vec(data) {
+                    Ok((multistream_select::Negotiation::InProgress(nego), _read, out_buffer)) => {
+                        debug_assert_eq!(_read, data.len());
+                        data = &data[_read..];
+                        substream.write(out_buffer);
+                        *substream.user_data() = Substream::RelayOutNegotiating {
+                            negotiation: nego,
+                            timeout,
+                            request,
+                        };
+                    }
+                    Ok((multistream_select::Negotiation::Success(_), num_read, out_buffer)) => {
+                        substream.write(out_buffer);
+                        data = &data[num_read..];
+                        substream.write(leb128::encode_usize(request.len()).collect());
+                        substream.write(request);
+                        *substream.user_data() = Substream::RelayOutStatus {
+                            timeout,
+                            response: leb128::FramedInProgress::new(
+                                relay::MAX_CONNECT_RESPONSE_SIZE,
+                            ),
+                        };
+                    }
+                    Ok((multistream_select::Negotiation::NotAvailable, ..)) => {
+                        substream.reset();
+                        return Some(Event::RelayCircuitOpenFailure {
+                            id: substream_id,
+                            error: RelayCircuitError::ProtocolNotAvailable,
+                        });
+                    }
+                    Err(err) => {
+                        substream.reset();
+                        return Some(Event::RelayCircuitOpenFailure {
+                            id: substream_id,
+                            error: RelayCircuitError::NegotiationError(err),
+                        });
+                    }
+                },
+                Substream::RelayOutStatus { timeout, response } => match response.update(&data) {
+                    Ok((num_read, leb128::Framed::Finished(response))) => {
+                        match relay::decode_connect_response(&response) {
+                            Ok(()) => {
+                                *substream.user_data() = Substream::RelayCircuit;
+                                // The target is allowed to send data immediately after the
+                                // status message, in which case it is passed along with the
+                                // event.
+                                return Some(Event::RelayCircuitOpen {
+                                    id: substream_id,
+                                    initial_data: data[num_read..].to_vec(),
+                                });
+                            }
+                            Err(err) => {
+                                substream.reset();
+                                return Some(Event::RelayCircuitOpenFailure {
+                                    id: substream_id,
+                                    error: RelayCircuitError::Connect(err),
+                                });
+                            }
+                        }
+                    }
+                    Ok((num_read, leb128::Framed::InProgress(response))) => {
+                        debug_assert_eq!(num_read, data.len());
+                        data = &data[num_read..];
+                        *substream.user_data() = Substream::RelayOutStatus { timeout, response };
+                    }
+                    Err(err) => {
+                        substream.reset();
+                        return Some(Event::RelayCircuitOpenFailure {
+                            id: substream_id,
+                            error: RelayCircuitError::ResponseLebError(err),
+                        });
+                    }
+                },
+                Substream::RelayCircuit => {
+                    *substream.user_data() = Substream::RelayCircuit;
+                    return Some(Event::RelayCircuitData {
+                        id: substream_id,
+                        data: data.to_vec(),
+                    });
+                }
                 _ => todo!("other substream kind"),
             };
         }
@@ -1370,6 +1710,10 @@ where
                 todo!() // TODO:
             }
             Substream::PingIn(_) => f.debug_tuple("ping-in").finish(),
+            Substream::RelayOutNegotiating { .. } | Substream::RelayOutStatus { .. } => {
+                f.debug_tuple("relay-out-opening").finish()
+            }
+            Substream::RelayCircuit => f.debug_tuple("relay-circuit").finish(),
         }
     }
 }
@@ -1513,6 +1857,41 @@ pub enum Event<TRqUd, TNotifUd> {
         /// Value that was passed to [`Established::open_notifications_substream`].
         user_data: TNotifUd,
     },
+
+    /// Remote has successfully relayed a connection opened with
+    /// [`Established::open_relay_circuit`]. The substream is now connected to the target.
+    RelayCircuitOpen {
+        /// Identifier of the substream. Value that was returned by
+        /// [`Established::open_relay_circuit`].
+        id: SubstreamId,
+        /// Data already sent by the target. Often empty.
+        initial_data: Vec<u8>,
+    },
+
+    /// Failed to open a relayed connection with [`Established::open_relay_circuit`].
+    RelayCircuitOpenFailure {
+        /// Identifier of the substream. Value that was returned by
+        /// [`Established::open_relay_circuit`].
+        id: SubstreamId,
+        /// Reason for the failure.
+        error: RelayCircuitError,
+    },
+
+    /// Target of a relayed connection has sent data.
+    RelayCircuitData {
+        /// Identifier of the substream. Value that was returned by
+        /// [`Established::open_relay_circuit`].
+        id: SubstreamId,
+        /// Data sent by the target.
+        data: Vec<u8>,
+    },
+
+    /// A relayed connection has been closed by the remote. The substream is instantly closed.
+    RelayCircuitClosed {
+        /// Identifier of the substream. Value that was returned by
+        /// [`Established::open_relay_circuit`].
+        id: SubstreamId,
+    },
 }
 
 /// Error during a connection. The connection should be shut down.
@@ -1541,6 +1920,28 @@ pub enum RequestError {
     NegotiationError(multistream_select::Error),
     /// Error while receiving the response.
     ResponseLebError(leb128::FramedError),
+    /// Receiving the response would have made the connection buffer more than
+    /// [`Config::max_receive_buffer_size`] bytes.
+    ReceiveBufferFull,
+}
+
+/// Error that can happen while opening a relayed connection.
+#[derive(Debug, derive_more::Display)]
+pub enum RelayCircuitError {
+    /// Relay hasn't answered in time.
+    Timeout,
+    /// Remote doesn't support acting as a relay.
+    ProtocolNotAvailable,
+    /// Remote has decided to close the substream.
+    SubstreamClosed,
+    /// Remote has decided to RST the substream.
+    SubstreamReset,
+    /// Error during protocol negotiation.
+    NegotiationError(multistream_select::Error),
+    /// Error while receiving the status message.
+    ResponseLebError(leb128::FramedError),
+    /// Relay has refused the connection or sent back an invalid status message.
+    Connect(relay::DecodeConnectResponseError),
 }
 
 /// Successfully negotiated connection. Ready to be turned into a [`Established`].
@@ -1575,6 +1976,7 @@ impl ConnectionPrototype {
                 request_protocols: config.request_protocols,
                 notifications_protocols: config.notifications_protocols,
                 ping_protocol: config.ping_protocol,
+                max_receive_buffer_size: config.max_receive_buffer_size,
             },
         }
     }
@@ -1598,6 +2000,14 @@ pub struct Config {
     pub ping_protocol: String,
     /// Entropy used for the randomness specific to this connection.
     pub randomness_seed: [u8; 32],
+    /// Maximum number of bytes of partially-received messages (responses, requests,
+    /// notifications, handshakes) buffered for this connection, all substreams combined.
+    ///
+    /// Each individual message is additionally limited by the maximum size configured for its
+    /// protocol. When this limit is reached, [`Established::is_receive_buffer_full`] returns
+    /// `true`, and no new request should be started on this connection. Substreams whose
+    /// incoming data would exceed this limit are reset.
+    pub max_receive_buffer_size: usize,
 }
 
 /// Configuration for a request-response protocol.
@@ -0,0 +1,169 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{
+    super::{handshake, NoiseKey},
+    Config, ConfigRequestResponse, ConfigRequestResponseIn, Established, Event,
+};
+use core::time::Duration;
+
+type Connection = Established<Duration, (), ()>;
+
+/// Performs the handshake between two connections, then turns them into [`Established`].
+/// Returns the two connections, plus the data that is in flight from the first to the second
+/// and from the second to the first.
+///
+/// `max_receive_buffer_size` is the receive buffer limit of the second connection.
+fn connect(max_receive_buffer_size: usize) -> (Connection, Connection, Vec<u8>, Vec<u8>) {
+    let mut handshake1 = handshake::Handshake::new(true);
+    let mut handshake2 = handshake::Handshake::new(false);
+    let key1 = NoiseKey::new(&rand::random());
+    let key2 = NoiseKey::new(&rand::random());
+
+    let mut buf_1_to_2 = Vec::new();
+    let mut buf_2_to_1 = Vec::new();
+
+    let (prototype1, prototype2) = loop {
+        match (handshake1, handshake2) {
+            (
+                handshake::Handshake::Success {
+                    connection: prototype1,
+                    ..
+                },
+                handshake::Handshake::Success {
+                    connection: prototype2,
+                    ..
+                },
+            ) => break (prototype1, prototype2),
+            (h1, h2) => {
+                handshake1 = handshake_step(h1, &key1, &mut buf_2_to_1, &mut buf_1_to_2);
+                handshake2 = handshake_step(h2, &key2, &mut buf_1_to_2, &mut buf_2_to_1);
+            }
+        }
+    };
+
+    let config = |max_receive_buffer_size| Config {
+        request_protocols: vec![ConfigRequestResponse {
+            name: "/test/1".to_owned(),
+            inbound_config: ConfigRequestResponseIn::Payload {
+                max_size: 1024 * 1024,
+            },
+            max_response_size: 1024 * 1024,
+            inbound_allowed: true,
+            timeout: Duration::from_secs(20),
+        }],
+        notifications_protocols: Vec::new(),
+        ping_protocol: "/ping/1.0.0".to_owned(),
+        randomness_seed: rand::random(),
+        max_receive_buffer_size,
+    };
+
+    (
+        prototype1.into_connection(config(usize::max_value())),
+        prototype2.into_connection(config(max_receive_buffer_size)),
+        buf_1_to_2,
+        buf_2_to_1,
+    )
+}
+
+fn handshake_step(
+    handshake: handshake::Handshake,
+    key: &NoiseKey,
+    incoming: &mut Vec<u8>,
+    outgoing: &mut Vec<u8>,
+) -> handshake::Handshake {
+    match handshake {
+        handshake::Handshake::NoiseKeyRequired(req) => req.resume(key).into(),
+        handshake::Handshake::Healthy(nego) => {
+            let mut out = [0; 1024];
+            let (updated, num_read, written) =
+                nego.read_write(incoming, (&mut out, &mut [])).unwrap();
+            incoming.drain(..num_read);
+            outgoing.extend_from_slice(&out[..written]);
+            updated
+        }
+        success @ handshake::Handshake::Success { .. } => success,
+    }
+}
+
+/// Calls [`Established::read_write`] once, transferring data between the buffers.
+fn read_write(
+    connection: Connection,
+    incoming: &mut Vec<u8>,
+    outgoing: &mut Vec<u8>,
+) -> (Connection, Option<Event<(), ()>>) {
+    let mut out = [0; 4096];
+    let read_write = connection
+        .read_write(Duration::new(0, 0), Some(incoming), (&mut out, &mut []))
+        .unwrap();
+    incoming.drain(..read_write.read_bytes);
+    outgoing.extend_from_slice(&out[..read_write.written_bytes]);
+    (read_write.connection, read_write.event)
+}
+
+/// Sends a request of `request_size` bytes from the first connection to the second one, whose
+/// receive buffer is limited to `max_receive_buffer_size` bytes. Returns the request as
+/// received by the second connection, plus the second connection.
+fn send_request(
+    max_receive_buffer_size: usize,
+    request_size: usize,
+) -> (Option<Vec<u8>>, Connection) {
+    let (mut connection1, mut connection2, mut buf_1_to_2, mut buf_2_to_1) =
+        connect(max_receive_buffer_size);
+
+    connection1.add_request(Duration::new(0, 0), 0, vec![0xaa; request_size], None, ());
+
+    let mut received = None;
+
+    // Transfer data back and forth for long enough for the whole request to be sent.
+    for _ in 0..1000 {
+        let (updated, event) = read_write(connection1, &mut buf_2_to_1, &mut buf_1_to_2);
+        connection1 = updated;
+        assert!(event.is_none());
+
+        let (updated, event) = read_write(connection2, &mut buf_1_to_2, &mut buf_2_to_1);
+        connection2 = updated;
+        match event {
+            Some(Event::RequestIn { request, .. }) => {
+                assert!(received.is_none());
+                received = Some(request);
+            }
+            None => {}
+            _ => unreachable!(),
+        }
+
+        assert!(connection2.receive_buffer_size() <= max_receive_buffer_size);
+    }
+
+    (received, connection2)
+}
+
+#[test]
+fn request_within_receive_buffer_limit() {
+    let (request, connection) = send_request(256 * 1024, 100 * 1024);
+    assert_eq!(request.unwrap(), vec![0xaa; 100 * 1024]);
+    assert_eq!(connection.receive_buffer_size(), 0);
+}
+
+#[test]
+fn request_exceeding_receive_buffer_limit_is_refused() {
+    let (request, connection) = send_request(16 * 1024, 100 * 1024);
+    assert!(request.is_none());
+    assert_eq!(connection.receive_buffer_size(), 0);
+}
@@ -559,6 +559,9 @@ impl HandshakeInProgress {
             }
         };
 
+        // Clearing the empty buffer resets its internal offset, which guarantees that the
+        // resized buffer is contiguous.
+        self.tx_buffer_encrypted.clear();
         self.tx_buffer_encrypted.resize(512, 0);
         debug_assert!(self.tx_buffer_encrypted.as_slices().1.is_empty());
         let written = self
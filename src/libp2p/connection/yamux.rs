@@ -41,6 +41,18 @@
 //! [`Yamux`] object. This data will then be progressively returned by
 //! [`Yamux::extract_out`].
 //!
+//! Each substream has a [`Priority`], modifiable with [`SubstreamMut::set_priority`]. When
+//! multiple substreams have data waiting to be sent out, the data of the substreams with the
+//! highest priority is sent first. In order for a high priority substream to not have to wait
+//! for a large frame of a lower priority substream to be sent out, the data frames of
+//! substreams of [`Priority::Normal`] are kept small.
+//!
+//! The priority also applies to the data received from the remote. While the order in which the
+//! remote sends its frames is out of control of the [`Yamux`], the window update frames that
+//! allow the remote to send more data are sent out in priority order, so that the remote is
+//! never left waiting for credits on a high priority substream because of the window updates
+//! of lower priority substreams.
+//!
 //! It is the responsibility of the user to enforce a bound to the amount of enqueued data, as
 //! the [`Yamux`] itself doesn't enforce any limit. Enforcing such a bound must be done based
 //! on the logic of the higher-level protocols. Failing to do so might lead to potential DoS
@@ -108,6 +120,8 @@ struct Substream<T> {
     /// Number of bytes in `self.write_buffers[0]` has have already been written out to the
     /// socket.
     first_write_buffer_offset: usize,
+    /// Priority of the data of this substream compared to the other substreams.
+    priority: Priority,
     /// Data chosen by the user.
     user_data: T,
 }
@@ -233,6 +247,7 @@ impl<T> Yamux<T> {
             remote_write_closed: false,
             write_buffers: Vec::with_capacity(16),
             first_write_buffer_offset: 0,
+            priority: Priority::Normal,
             user_data,
         });
 
@@ -657,11 +672,12 @@ impl<T> Yamux<T> {
             debug_assert!(self.pending_out_header.is_empty());
             debug_assert!(self.writing_out_substream.is_none());
 
-            // Send window update frames.
+            // Send window update frames, picking the substream with the highest priority.
             if let Some((id, sub)) = self
                 .substreams
                 .iter_mut()
-                .find(|(_, s)| s.remote_window_pending_increase != 0)
+                .filter(|(_, s)| s.remote_window_pending_increase != 0)
+                .max_by_key(|(_, s)| s.priority)
                 .map(|(id, sub)| (*id, sub))
             {
                 let syn_ack_flag = !sub.first_message_queued;
@@ -675,18 +691,29 @@ impl<T> Yamux<T> {
                 continue;
             }
 
-            // Start writing more data from another substream.
-            // TODO: choose substreams in some sort of round-robin way
+            // Start writing more data from another substream, picking the substream with the
+            // highest priority.
+            // TODO: choose substreams of the same priority in some sort of round-robin way
             if let Some((id, sub)) = self
                 .substreams
                 .iter_mut()
-                .find(|(_, s)| !s.write_buffers.is_empty())
+                .filter(|(_, s)| !s.write_buffers.is_empty())
+                .max_by_key(|(_, s)| s.priority)
                 .map(|(id, sub)| (*id, sub))
             {
-                let pending_len = sub.write_buffers.iter().fold(0, |l, b| l + b.len());
+                // The beginning of the first buffer has potentially already been sent out.
+                let pending_len = sub.write_buffers.iter().fold(0, |l, b| l + b.len())
+                    - sub.first_write_buffer_offset;
+                let max_frame_len = match sub.priority {
+                    Priority::High => u32::max_value(),
+                    Priority::Normal => NORMAL_PRIORITY_MAX_FRAME_SIZE,
+                };
                 let len_out = cmp::min(
-                    u32::try_from(pending_len).unwrap_or(u32::max_value()),
-                    u32::try_from(sub.allowed_window).unwrap_or(u32::max_value()),
+                    cmp::min(
+                        u32::try_from(pending_len).unwrap_or(u32::max_value()),
+                        u32::try_from(sub.allowed_window).unwrap_or(u32::max_value()),
+                    ),
+                    max_frame_len,
                 );
                 let len_out_usize = usize::try_from(len_out).unwrap();
                 sub.allowed_window -= u64::from(len_out);
@@ -732,6 +759,7 @@ impl<T> Yamux<T> {
                         remote_write_closed: data_frame_size == 0 && fin,
                         write_buffers: Vec::new(),
                         first_write_buffer_offset: 0,
+                        priority: Priority::Normal,
                         user_data,
                     },
                 );
@@ -889,6 +917,16 @@ where
     }
 }
 
+/// Priority of a substream. See [`SubstreamMut::set_priority`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Default priority. Suitable for bulk transfers, such as responses to requests.
+    Normal,
+    /// Data on this substream is sent out ahead of the data of [`Priority::Normal`] substreams.
+    /// Suitable for small and latency-sensitive messages, such as notifications.
+    High,
+}
+
 /// Configuration for a new [`Yamux`].
 #[derive(Debug)]
 pub struct Config {
@@ -939,6 +977,16 @@ impl<'a, T> SubstreamMut<'a, T> {
         substream.write_buffers.push(data);
     }
 
+    /// Sets the priority of the data sent out on this substream compared to the other
+    /// substreams. Substreams have a priority of [`Priority::Normal`] by default.
+    ///
+    /// This only affects the data that hasn't been passed to [`Yamux::extract_out`] yet. The
+    /// window updates granting the remote the right to send more data on this substream are
+    /// prioritized the same way.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.substream.get_mut().priority = priority;
+    }
+
     /// Allow the remote to send up to `bytes` bytes at once in the next packet.
     ///
     /// This method sets the number of allowed bytes to at least this value. In other words,
@@ -1114,3 +1162,102 @@ pub enum Error {
 
 /// By default, all new substreams have this implicit window size.
 const DEFAULT_FRAME_SIZE: u64 = 256 * 1024;
+
+/// Maximum size of the data frames of substreams of [`Priority::Normal`]. Keeping these frames
+/// small avoids delaying the data of [`Priority::High`] substreams for too long.
+const NORMAL_PRIORITY_MAX_FRAME_SIZE: u32 = 16 * 1024;
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Priority, SubstreamId, Yamux};
+    use core::convert::TryFrom as _;
+
+    fn new_yamux() -> Yamux<()> {
+        Yamux::new(Config {
+            is_initiator: true,
+            capacity: 16,
+            randomness_seed: [0; 32],
+        })
+    }
+
+    fn extract_out(yamux: &mut Yamux<()>, size_bytes: usize) -> Vec<u8> {
+        let mut out = Vec::new();
+        for buffer in yamux.extract_out(size_bytes).buffers() {
+            out.extend_from_slice(buffer.as_ref());
+        }
+        out
+    }
+
+    /// Splits the given data into frames and returns the type and substream ID of each frame.
+    fn frames(mut data: &[u8]) -> Vec<(u8, u32)> {
+        let mut frames = Vec::new();
+        while !data.is_empty() {
+            let ty = data[1];
+            let id = u32::from_be_bytes(<[u8; 4]>::try_from(&data[4..8]).unwrap());
+            let len = u32::from_be_bytes(<[u8; 4]>::try_from(&data[8..12]).unwrap());
+            data = &data[12..];
+            if ty == 0 {
+                data = &data[usize::try_from(len).unwrap()..];
+            }
+            frames.push((ty, id));
+        }
+        frames
+    }
+
+    #[test]
+    fn high_priority_data_sent_before_queued_normal_data() {
+        let mut yamux = new_yamux();
+
+        let normal = {
+            let mut substream = yamux.open_substream(());
+            substream.write(vec![0; 64 * 1024]);
+            substream.id().0.get()
+        };
+
+        // Start writing out a frame of the normal priority substream.
+        let mut out = extract_out(&mut yamux, 100);
+
+        let high = {
+            let mut substream = yamux.open_substream(());
+            substream.set_priority(Priority::High);
+            substream.write(vec![1; 10]);
+            substream.id().0.get()
+        };
+
+        out.extend(extract_out(&mut yamux, 1024 * 1024));
+        assert_eq!(
+            frames(&out),
+            vec![
+                (0, normal),
+                (0, high),
+                (0, normal),
+                (0, normal),
+                (0, normal)
+            ]
+        );
+    }
+
+    #[test]
+    fn high_priority_window_updates_sent_first() {
+        let mut yamux = new_yamux();
+
+        let mut substreams = Vec::new();
+        for n in 0..8 {
+            let mut substream = yamux.open_substream(());
+            if n == 5 {
+                substream.set_priority(Priority::High);
+            }
+            substream.reserve_window(1024 * 1024);
+            substreams.push(substream.id());
+        }
+
+        let out = extract_out(&mut yamux, 1024 * 1024);
+        let frames = frames(&out);
+        assert_eq!(frames.len(), substreams.len());
+        assert!(frames.iter().all(|(ty, _)| *ty == 1));
+        assert_eq!(
+            SubstreamId::from(core::num::NonZeroU32::new(frames[0].1).unwrap()),
+            substreams[5]
+        );
+    }
+}
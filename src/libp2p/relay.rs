@@ -0,0 +1,247 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Client side of the libp2p circuit relay v2 protocol.
+//!
+//! Nodes that aren't directly reachable (for example because they are behind a NAT) can be
+//! reached through a *relay*: a publicly-reachable node that forwards data between two other
+//! nodes. The addresses of such nodes are of the form `<relay address>/p2p/<relay peer id>/
+//! p2p-circuit`, followed with the peer id of the target.
+//!
+//! In order to reach a node through a relay, one opens a substream with the relay on the
+//! [`HOP_PROTOCOL_NAME`] protocol, sends a request built with [`build_connect_request`], then
+//! waits for a response that can be decoded with [`decode_connect_response`]. If the relay
+//! accepts, the substream then behaves as if it was a raw connection with the target, on top of
+//! which a normal libp2p handshake is performed.
+//!
+//! Only dialing through a relay is supported. Making a reservation with a relay in order to be
+//! reachable through it, and acting as a relay, aren't.
+
+use crate::libp2p::{multiaddr, peer_id::PeerId};
+
+use alloc::vec::Vec;
+use prost::Message as _;
+
+mod circuit_proto {
+    // File generated by the build script.
+    include!(concat!(env!("OUT_DIR"), "/circuit.pb.rs"));
+}
+
+/// Name of the protocol to negotiate with a relay in order to reach another node through it.
+pub const HOP_PROTOCOL_NAME: &str = "/libp2p/circuit/relay/0.2.0/hop";
+
+/// Maximum size, in bytes, of a response to a request built with [`build_connect_request`].
+pub const MAX_CONNECT_RESPONSE_SIZE: usize = 4096;
+
+/// Builds a message to send on a [`HOP_PROTOCOL_NAME`] substream in order to ask the relay to
+/// connect to the given target.
+///
+/// The message must be preceded with its length encoded as a LEB128 number.
+pub fn build_connect_request(target: &PeerId) -> Vec<u8> {
+    let protobuf = circuit_proto::HopMessage {
+        r#type: circuit_proto::hop_message::Type::Connect as i32,
+        peer: Some(circuit_proto::Peer {
+            id: target.as_bytes().to_vec(),
+            addrs: Vec::new(),
+        }),
+        ..Default::default()
+    };
+
+    let mut buf = Vec::with_capacity(protobuf.encoded_len());
+    protobuf.encode(&mut buf).unwrap();
+    buf
+}
+
+/// Decodes the response to a request built using [`build_connect_request`].
+///
+/// Returns `Ok` if the relay has accepted to relay the connection, in which case the substream
+/// is now connected to the target.
+pub fn decode_connect_response(response_bytes: &[u8]) -> Result<(), DecodeConnectResponseError> {
+    let response = circuit_proto::HopMessage::decode(response_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeConnectResponseError::ProtobufDecode)?;
+
+    if response.r#type != circuit_proto::hop_message::Type::Status as i32 {
+        return Err(DecodeConnectResponseError::BadResponseTy);
+    }
+
+    let status = response
+        .status
+        .ok_or(DecodeConnectResponseError::MissingStatus)?;
+
+    if status == circuit_proto::Status::Ok as i32 {
+        return Ok(());
+    }
+
+    Err(DecodeConnectResponseError::Refused(
+        match circuit_proto::Status::from_i32(status) {
+            Some(circuit_proto::Status::ResourceLimitExceeded) => {
+                ConnectRefusedReason::ResourceLimitExceeded
+            }
+            Some(circuit_proto::Status::PermissionDenied) => ConnectRefusedReason::PermissionDenied,
+            Some(circuit_proto::Status::ConnectionFailed) => ConnectRefusedReason::ConnectionFailed,
+            Some(circuit_proto::Status::NoReservation) => ConnectRefusedReason::NoReservation,
+            Some(circuit_proto::Status::MalformedMessage) => ConnectRefusedReason::MalformedMessage,
+            Some(circuit_proto::Status::UnexpectedMessage) => {
+                ConnectRefusedReason::UnexpectedMessage
+            }
+            _ => ConnectRefusedReason::Other(status),
+        },
+    ))
+}
+
+/// If the given address is an address reachable through a relay, returns the address of the
+/// relay and its [`PeerId`].
+///
+/// The address must be of the form `<relay address>/p2p/<relay peer id>/p2p-circuit`, in other
+/// words without the `/p2p/<target peer id>` suffix. Addresses that go through multiple relays
+/// aren't supported, and `None` is returned for them.
+pub fn split_relayed_address(
+    address: &multiaddr::Multiaddr,
+) -> Option<(multiaddr::Multiaddr, PeerId)> {
+    let mut relay_address = address.clone();
+    if !matches!(relay_address.pop(), Some(multiaddr::Protocol::P2pCircuit)) {
+        return None;
+    }
+
+    let relay_peer_id = match relay_address.pop() {
+        Some(multiaddr::Protocol::P2p(peer_id)) => PeerId::from_multihash(peer_id).ok()?,
+        _ => return None,
+    };
+
+    if is_relayed_address(&relay_address) {
+        return None;
+    }
+
+    Some((relay_address, relay_peer_id))
+}
+
+/// Returns `true` if the given address contains a `/p2p-circuit` component, in other words if
+/// it can only be reached through a relay.
+pub fn is_relayed_address(address: &multiaddr::Multiaddr) -> bool {
+    address
+        .iter()
+        .any(|p| matches!(p, multiaddr::Protocol::P2pCircuit))
+}
+
+/// Error potentially returned by [`decode_connect_response`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeConnectResponseError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+    /// Response isn't a status message.
+    BadResponseTy,
+    /// Response doesn't contain any status.
+    MissingStatus,
+    /// Relay has refused to connect to the target.
+    #[display(fmt = "Relay has refused to connect to the target: {:?}", _0)]
+    Refused(ConnectRefusedReason),
+}
+
+/// Reason why a relay refused to connect to a target.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ConnectRefusedReason {
+    /// Relay has reached its limit of relayed connections.
+    ResourceLimitExceeded,
+    /// Relay refuses to relay connections for the local node.
+    PermissionDenied,
+    /// Relay has failed to reach the target.
+    ConnectionFailed,
+    /// Target doesn't have a reservation with the relay.
+    NoReservation,
+    /// Relay couldn't decode the request.
+    MalformedMessage,
+    /// Relay didn't expect the request.
+    UnexpectedMessage,
+    /// Status code unknown to this implementation.
+    Other(i32),
+}
+
+/// Error while decoding the protobuf encoding.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "{}", _0)]
+pub struct ProtobufDecodeError(prost::DecodeError);
+
+#[cfg(test)]
+mod tests {
+    use super::{circuit_proto, multiaddr, ConnectRefusedReason, DecodeConnectResponseError};
+    use prost::Message as _;
+
+    fn encode_status(status: circuit_proto::Status) -> Vec<u8> {
+        let protobuf = circuit_proto::HopMessage {
+            r#type: circuit_proto::hop_message::Type::Status as i32,
+            status: Some(status as i32),
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        protobuf.encode(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn connect_request_contains_target() {
+        let target = crate::libp2p::peer_id::PublicKey::Ed25519([3; 32]).into_peer_id();
+        let request = super::build_connect_request(&target);
+        let decoded = circuit_proto::HopMessage::decode(&request[..]).unwrap();
+        assert_eq!(
+            decoded.r#type,
+            circuit_proto::hop_message::Type::Connect as i32
+        );
+        assert_eq!(decoded.peer.unwrap().id, target.as_bytes());
+    }
+
+    #[test]
+    fn connect_response_status() {
+        assert!(super::decode_connect_response(&encode_status(circuit_proto::Status::Ok)).is_ok());
+
+        match super::decode_connect_response(&encode_status(circuit_proto::Status::NoReservation)) {
+            Err(DecodeConnectResponseError::Refused(ConnectRefusedReason::NoReservation)) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn split_relayed_address() {
+        let relay = crate::libp2p::peer_id::PublicKey::Ed25519([1; 32]).into_peer_id();
+
+        let address = format!("/dns/example.com/tcp/443/wss/p2p/{}/p2p-circuit", relay)
+            .parse::<multiaddr::Multiaddr>()
+            .unwrap();
+        let (relay_address, relay_peer_id) = super::split_relayed_address(&address).unwrap();
+        assert_eq!(
+            relay_address,
+            "/dns/example.com/tcp/443/wss"
+                .parse::<multiaddr::Multiaddr>()
+                .unwrap()
+        );
+        assert_eq!(relay_peer_id, relay);
+
+        let direct = "/ip4/1.2.3.4/tcp/30333"
+            .parse::<multiaddr::Multiaddr>()
+            .unwrap();
+        assert!(super::split_relayed_address(&direct).is_none());
+        assert!(!super::is_relayed_address(&direct));
+
+        let nested = format!(
+            "/ip4/1.2.3.4/tcp/30333/p2p/{}/p2p-circuit/p2p/{}/p2p-circuit",
+            relay, relay
+        )
+        .parse::<multiaddr::Multiaddr>()
+        .unwrap();
+        assert!(super::split_relayed_address(&nested).is_none());
+    }
+}
@@ -34,6 +34,10 @@
 //! - A list of calls that can be performed by emitting transactions.
 //! - A list of *events* that can happen in a block, such as a new account. See the
 //! [`events`](events) module for more information.
+//! - The storage key where information about an account, such as its balance, can be found.
+//! See the [`account`](account) module for more information.
+//! - The storage key of each storage entry of each module. See the [`storage`](storage) module
+//! for more information.
 //! - ...
 //!
 //! In order to obtain the metadata, a call to an entry point of the runtime code is necessary.
@@ -44,9 +48,12 @@
 //! - https://substrate.dev/docs/en/knowledgebase/runtime/metadata
 //!
 
+pub mod account;
 pub mod decode;
 pub mod events;
+pub mod extrinsic;
 mod query;
+pub mod storage;
 
 pub use query::*;
 
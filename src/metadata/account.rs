@@ -0,0 +1,292 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Accounts retrieval and decoding.
+//!
+//! # Overview
+//!
+//! Substrate-compatible blockchains built using the Substrate framework store information about
+//! each account, such as its nonce and its balance, in a storage map named `Account` in the
+//! `System` module.
+//!
+//! This module provides the tooling necessary to help retrieve and decode this information.
+//!
+//! # Usage
+//!
+//! In order to know the state of an account at a specific block:
+//!
+//! - Obtain the *metadata* of the runtime used by the desired block. This is out of scope of this
+//! module. See the [metadata](crate::metadata) module for more information.
+//! - Call [`account_storage_key`] in order to obtain the key where to find the information about
+//! the account in the storage. If [`account_storage_key`] returns an error, the runtime most
+//! likely doesn't store accounts in the standard way.
+//! - Obtain the storage value corresponding to the key obtained at the previous step. This is out
+//! of scope of this module. If there is no storage value at this key, use
+//! [`AccountStorageKey::default_value`] instead.
+//! - Call [`decode_account_info`] on this storage value, passing the
+//!   [`AccountStorageKey::layout`] obtained at the previous step.
+//!
+//! # Flaw in the design
+//!
+//! Similarly to events (see the [`events`](crate::metadata::events) module), the metadata only
+//! provides the type of the storage value in the form of a string representing a Rust type, here
+//! `AccountInfo<T::Index, T::AccountData>`. The layout of this type has changed over time, and
+//! `T::AccountData` is chosen by each runtime.
+//!
+//! It is assumed that `T::Index` is a `u32` and that `T::AccountData` is the `AccountData` type
+//! of the Substrate balances module, which is the case for the Polkadot, Kusama and Westend
+//! runtimes. The reference counters found in-between, whose layout has changed over time, are
+//! ignored.
+//!
+//! In order to detect runtimes that don't match these assumptions, [`account_storage_key`]
+//! compares the length of the default value of the storage entry, as found in the metadata, with
+//! the lengths of the known layouts, and returns an error if it doesn't match any of them.
+//! [`decode_account_info`] then refuses storage values whose length doesn't match the layout.
+//!
+
+use crate::metadata::{decode as metadata, storage};
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Key in the storage where information about an account can be found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountStorageKey<'a> {
+    /// Key in the storage.
+    pub key: Vec<u8>,
+
+    /// Value to consider if the storage doesn't contain any value at [`AccountStorageKey::key`].
+    /// Can be passed to [`decode_account_info`].
+    pub default_value: &'a [u8],
+
+    /// Layout of the storage value, to pass to [`decode_account_info`].
+    pub layout: AccountInfoLayout,
+}
+
+/// Layout of the storage value containing the information about an account, as determined
+/// by [`account_storage_key`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountInfoLayout {
+    /// Number of bytes of the reference counters between the nonce and the account data.
+    ref_counts_len: usize,
+}
+
+impl AccountInfoLayout {
+    /// Size in bytes of the SCALE-encoded nonce, which is at the start of the value.
+    const NONCE_LEN: usize = 4;
+    /// Size in bytes of the SCALE-encoded account data, which is at the end of the value.
+    const ACCOUNT_DATA_LEN: usize = 64;
+
+    /// Builds the layout corresponding to a storage value of the given length.
+    ///
+    /// Over time, the reference counters have been a single `u8`, then a single `u32`, then
+    /// the `consumers` and `providers` `u32`s, then additionally the `sufficients` `u32`.
+    fn from_value_len(value_len: usize) -> Option<Self> {
+        let ref_counts_len = value_len.checked_sub(Self::NONCE_LEN + Self::ACCOUNT_DATA_LEN)?;
+        if matches!(ref_counts_len, 1 | 4 | 8 | 12) {
+            Some(AccountInfoLayout { ref_counts_len })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the length in bytes of storage values that follow this layout.
+    pub fn value_len(&self) -> usize {
+        Self::NONCE_LEN + self.ref_counts_len + Self::ACCOUNT_DATA_LEN
+    }
+}
+
+/// Returns the key in the storage at which information about the given account can be found.
+///
+/// > **Note**: This key is based entirely on the metadata passed as parameter. Be aware that,
+/// >           albeit unlikely, if the metadata changes, the key might change as well.
+///
+/// An error is returned if the metadata doesn't indicate any storage entry for accounts, or if
+/// the type of the content of the storage entry isn't recognized.
+pub fn account_storage_key<'a>(
+    metadata: metadata::MetadataRef<'a>,
+    account_id: &[u8; 32],
+) -> Result<AccountStorageKey<'a>, AccountStorageKeyError> {
+    let key = match storage::storage_key(metadata, "System", "Account", &[&account_id[..]]) {
+        Ok(key) => key,
+        Err(storage::StorageKeyError::NoModule) => {
+            return Err(AccountStorageKeyError::NoSystemModule)
+        }
+        Err(storage::StorageKeyError::NoEntry) => return Err(AccountStorageKeyError::NoAccountKey),
+        Err(storage::StorageKeyError::TooManyMapKeys { .. }) => {
+            return Err(AccountStorageKeyError::WrongType)
+        }
+    };
+
+    if !matches!(
+        key.ty,
+        metadata::StorageEntryTypeRef::Map {
+            key: "T::AccountId",
+            value: "AccountInfo<T::Index, T::AccountData>",
+            ..
+        }
+    ) {
+        return Err(AccountStorageKeyError::WrongType);
+    }
+
+    let layout = AccountInfoLayout::from_value_len(key.default_value.len())
+        .ok_or(AccountStorageKeyError::UnsupportedLayout)?;
+
+    Ok(AccountStorageKey {
+        key: key.key,
+        default_value: key.default_value,
+        layout,
+    })
+}
+
+/// Error potentially returned by [`account_storage_key`].
+#[derive(Debug, derive_more::Display)]
+pub enum AccountStorageKeyError {
+    /// No module called `System` has been found.
+    NoSystemModule,
+    /// No storage entry called `Account` has been found.
+    NoAccountKey,
+    /// The `Account` storage key doesn't have the type expected for a map of accounts.
+    WrongType,
+    /// The default value of the `Account` storage entry doesn't match any supported layout.
+    UnsupportedLayout,
+}
+
+/// Information about an account, as decoded by [`decode_account_info`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountInfo {
+    /// Number of transactions that this account has sent.
+    pub nonce: u32,
+    /// Non-reserved part of the balance of the account.
+    pub free: u128,
+    /// Part of the balance of the account that is reserved and can't be spent.
+    pub reserved: u128,
+    /// Amount that `free` may not drop below when withdrawing for anything except transaction
+    /// fee payment.
+    pub misc_frozen: u128,
+    /// Amount that `free` may not drop below when withdrawing specifically for transaction fee
+    /// payment.
+    pub fee_frozen: u128,
+}
+
+/// Decodes the storage value found at [`AccountStorageKey::key`], or
+/// [`AccountStorageKey::default_value`] if there isn't any.
+///
+/// `layout` must be the [`AccountStorageKey::layout`] returned by [`account_storage_key`]. See
+/// the module-level documentation for the assumptions made about the layout of the value.
+pub fn decode_account_info(
+    layout: AccountInfoLayout,
+    scale_encoded: &[u8],
+) -> Result<AccountInfo, DecodeAccountInfoError> {
+    if scale_encoded.len() != layout.value_len() {
+        return Err(DecodeAccountInfoError::WrongLength);
+    }
+
+    let data = &scale_encoded[scale_encoded.len() - AccountInfoLayout::ACCOUNT_DATA_LEN..];
+    let read_u128 = |n: usize| u128::from_le_bytes(<[u8; 16]>::try_from(&data[n..n + 16]).unwrap());
+
+    Ok(AccountInfo {
+        nonce: u32::from_le_bytes(<[u8; 4]>::try_from(&scale_encoded[..4]).unwrap()),
+        free: read_u128(0),
+        reserved: read_u128(16),
+        misc_frozen: read_u128(32),
+        fee_frozen: read_u128(48),
+    })
+}
+
+/// Error potentially returned by [`decode_account_info`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeAccountInfoError {
+    /// Length of the value doesn't match the layout found in the metadata.
+    WrongLength,
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::TryFrom as _;
+
+    #[test]
+    fn alice_storage_key() {
+        let metadata =
+            crate::metadata::decode(&include_bytes!("decode/example-metadata")[..]).unwrap();
+
+        let alice = hex::decode("d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d")
+            .unwrap();
+        let key = super::account_storage_key(metadata, &<[u8; 32]>::try_from(&alice[..]).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            hex::encode(&key.key),
+            "26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9\
+             de1e86a9a8c739864cf3cc5ec2bea59f\
+             d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d"
+        );
+
+        // The default value is an account that has never been used.
+        assert_eq!(
+            super::decode_account_info(key.layout, key.default_value).unwrap(),
+            super::AccountInfo {
+                nonce: 0,
+                free: 0,
+                reserved: 0,
+                misc_frozen: 0,
+                fee_frozen: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn decode_account_info() {
+        let mut value = Vec::new();
+        value.extend_from_slice(&7u32.to_le_bytes());
+        // `consumers`, `providers` and `sufficients` reference counters of recent runtimes.
+        value.extend_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        for n in 1..=4u128 {
+            value.extend_from_slice(&(n * 1_000_000_000_000).to_le_bytes());
+        }
+
+        let layout = super::AccountInfoLayout::from_value_len(value.len()).unwrap();
+        assert_eq!(
+            super::decode_account_info(layout, &value).unwrap(),
+            super::AccountInfo {
+                nonce: 7,
+                free: 1_000_000_000_000,
+                reserved: 2_000_000_000_000,
+                misc_frozen: 3_000_000_000_000,
+                fee_frozen: 4_000_000_000_000,
+            }
+        );
+
+        assert!(super::decode_account_info(layout, &value[..60]).is_err());
+        assert!(super::decode_account_info(layout, &value[1..]).is_err());
+    }
+
+    #[test]
+    fn unsupported_layouts() {
+        // Nonce and account data without any reference counter.
+        assert!(super::AccountInfoLayout::from_value_len(4 + 64).is_none());
+        assert!(super::AccountInfoLayout::from_value_len(4 + 2 + 64).is_none());
+        assert!(super::AccountInfoLayout::from_value_len(4 + 16 + 64).is_none());
+        assert!(super::AccountInfoLayout::from_value_len(10).is_none());
+
+        // A value whose length corresponds to a different layout is refused.
+        let layout = super::AccountInfoLayout::from_value_len(4 + 12 + 64).unwrap();
+        assert!(matches!(
+            super::decode_account_info(layout, &[0; 4 + 8 + 64]),
+            Err(super::DecodeAccountInfoError::WrongLength)
+        ));
+    }
+}
@@ -75,8 +75,8 @@
 //! updated to support decoding events.
 //!
 
-use crate::metadata::decode as metadata;
-use core::{convert::TryFrom, hash::Hasher as _};
+use crate::metadata::{decode as metadata, storage};
+use core::convert::TryFrom;
 
 /// Returns the key in the storage at which events can be found.
 ///
@@ -86,33 +86,22 @@ use core::{convert::TryFrom, hash::Hasher as _};
 /// An error is returned if the metadata doesn't indicate any storage entry for events, or if the
 /// type of the content of the storage entry isn't recognized.
 pub fn events_storage_key(
-    mut metadata: metadata::MetadataRef,
+    metadata: metadata::MetadataRef,
 ) -> Result<[u8; 32], EventsStorageKeyError> {
-    let module = metadata
-        .modules
-        .find(|m| m.name == "System")
-        .ok_or(EventsStorageKeyError::NoSystemModule)?;
+    let key = match storage::storage_key(metadata, "System", "Events", &[]) {
+        Ok(key) => key,
+        Err(storage::StorageKeyError::NoModule) => {
+            return Err(EventsStorageKeyError::NoSystemModule)
+        }
+        Err(storage::StorageKeyError::NoEntry) => return Err(EventsStorageKeyError::NoEventsKey),
+        Err(storage::StorageKeyError::TooManyMapKeys { .. }) => unreachable!(),
+    };
 
-    let mut storage = module.storage.ok_or(EventsStorageKeyError::NoEventsKey)?;
-
-    let entry = storage
-        .entries
-        .find(|e| e.name == "Events")
-        .ok_or(EventsStorageKeyError::NoEventsKey)?;
-    if entry.ty != metadata::StorageEntryTypeRef::Plain("Vec<EventRecord<T::Event, T::Hash>>") {
+    if key.ty != metadata::StorageEntryTypeRef::Plain("Vec<EventRecord<T::Event, T::Hash>>") {
         return Err(EventsStorageKeyError::WrongType);
     }
 
-    let mut out = [0; 32];
-    twox_128(
-        storage.prefix.as_bytes(),
-        TryFrom::try_from(&mut out[..16]).unwrap(),
-    );
-    twox_128(
-        entry.name.as_bytes(),
-        TryFrom::try_from(&mut out[16..]).unwrap(),
-    );
-    Ok(out)
+    Ok(<[u8; 32]>::try_from(&key.key[..]).unwrap())
 }
 
 /// Error potentially returned by [`events_storage_key`].
@@ -125,16 +114,3 @@ pub enum EventsStorageKeyError {
     /// The `Events` storage key doesn't have the type expected for a list of events.
     WrongType,
 }
-
-/// Fills `dest` with the XXHash of `data`.
-fn twox_128(data: &[u8], dest: &mut [u8; 16]) {
-    let mut h0 = twox_hash::XxHash::with_seed(0);
-    let mut h1 = twox_hash::XxHash::with_seed(1);
-    h0.write(&data);
-    h1.write(&data);
-    let r0 = h0.finish();
-    let r1 = h1.finish();
-
-    dest[..8].copy_from_slice(&r0.to_le_bytes()[..]);
-    dest[8..].copy_from_slice(&r1.to_le_bytes()[..]);
-}
@@ -0,0 +1,858 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Format of the extrinsics of a runtime, as described by its metadata.
+//!
+//! A signed extrinsic contains, after its version byte, the address of its sender, a signature,
+//! the *extra* data of each signed extension of the runtime, and finally the call. Each of these
+//! fields is SCALE-encoded one behind the other, and finding the position of a field requires
+//! knowing the types of all the fields that precede it.
+//!
+//! Starting from version 14, the metadata contains a registry of all the types used by the
+//! runtime, including the types of the address, of the signature, and of the extra data of each
+//! signed extension. Older versions of the metadata only contain the names of the signed
+//! extensions. For these versions, the extra data of the signed extensions commonly found in
+//! Substrate-based chains is hardcoded, and the address and signature are assumed to use the
+//! `MultiAddress` and `MultiSignature` formats of Substrate.
+//!
+//! # Usage
+//!
+//! Call [`decode`] with the metadata of the runtime in order to obtain an [`ExtrinsicFormat`],
+//! then use its methods in order to skip over the fields of an extrinsic.
+
+use alloc::vec::Vec;
+use core::convert::TryFrom as _;
+
+/// Decodes the parts of the given SCALE-encoded metadata that describe the format of the
+/// extrinsics.
+pub fn decode(scale_encoded_metadata: &[u8]) -> Result<ExtrinsicFormat, DecodeError> {
+    let (version, bytes) = match scale_encoded_metadata {
+        [0x6d, 0x65, 0x74, 0x61, version, bytes @ ..] => (*version, bytes),
+        _ => return Err(DecodeError::InvalidMetadata),
+    };
+
+    match version {
+        // Versions 12 and 13 aren't supported by the metadata decoder.
+        11 => {
+            let metadata =
+                super::decode(scale_encoded_metadata).map_err(|_| DecodeError::InvalidMetadata)?;
+            Ok(ExtrinsicFormat {
+                version: metadata.extrinsic.version,
+                layout: Layout::Legacy {
+                    signed_extensions: metadata.extrinsic.signed_extensions.collect(),
+                },
+            })
+        }
+        14 => {
+            let (_, decoded) = nom::combinator::all_consuming(metadata_v14)(bytes)
+                .map_err(|_: nom::Err<NomError>| DecodeError::InvalidMetadata)?;
+
+            // Types are expected to be ordered by identifier, which makes it possible to look
+            // them up by index.
+            let mut types = Vec::with_capacity(decoded.types.len());
+            for (index, (id, ty)) in decoded.types.into_iter().enumerate() {
+                if usize::try_from(id).map_or(true, |id| id != index) {
+                    return Err(DecodeError::InvalidMetadata);
+                }
+                types.push(ty);
+            }
+
+            // All the type identifiers are verified ahead of time, so that they can later be
+            // looked up without any check.
+            let check = |id: u32| -> Result<(), DecodeError> {
+                if usize::try_from(id).map_or(false, |id| id < types.len()) {
+                    Ok(())
+                } else {
+                    Err(DecodeError::UnknownType(id))
+                }
+            };
+            for ty in &types {
+                for (_, param) in &ty.params {
+                    if let Some(param) = param {
+                        check(*param)?;
+                    }
+                }
+                match &ty.def {
+                    TypeDef::Composite(fields) | TypeDef::Tuple(fields) => {
+                        for field in fields {
+                            check(*field)?;
+                        }
+                    }
+                    TypeDef::Variant(variants) => {
+                        for field in variants.iter().flat_map(|(_, fields)| fields) {
+                            check(*field)?;
+                        }
+                    }
+                    TypeDef::Sequence(elem) | TypeDef::Array(_, elem) => check(*elem)?,
+                    TypeDef::Compact | TypeDef::Fixed(_) | TypeDef::Str => {}
+                    TypeDef::BitSequence { store } => check(*store)?,
+                }
+            }
+            check(decoded.extrinsic_type)?;
+            for (_, ty) in &decoded.signed_extensions {
+                check(*ty)?;
+            }
+
+            // The type of the extrinsic is normally Substrate's `UncheckedExtrinsic`, whose
+            // generic parameters indicate the types of the address and of the signature.
+            let extrinsic_params = &types[usize::try_from(decoded.extrinsic_type).unwrap()].params;
+            let param = |name: &str| {
+                extrinsic_params
+                    .iter()
+                    .find(|(n, _)| *n == name)
+                    .and_then(|(_, ty)| *ty)
+            };
+            let address = param("Address");
+            let signature = param("Signature");
+
+            Ok(ExtrinsicFormat {
+                version: decoded.extrinsic_version,
+                layout: Layout::Registry {
+                    address,
+                    signature,
+                    signed_extensions: decoded.signed_extensions,
+                    types,
+                },
+            })
+        }
+        version => Err(DecodeError::UnsupportedVersion(version)),
+    }
+}
+
+/// Error that can happen during the decoding.
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The metadata is malformed.
+    InvalidMetadata,
+    /// The version of the metadata isn't supported.
+    #[display(fmt = "Unsupported metadata version: {}", _0)]
+    UnsupportedVersion(u8),
+    /// The metadata refers to a type that isn't in its registry of types.
+    #[display(fmt = "Unknown type identifier: {}", _0)]
+    UnknownType(u32),
+}
+
+/// Format of the extrinsics of a runtime. See [the module-level documentation](self).
+#[derive(Debug, Clone)]
+pub struct ExtrinsicFormat<'a> {
+    version: u8,
+    layout: Layout<'a>,
+}
+
+#[derive(Debug, Clone)]
+enum Layout<'a> {
+    /// Metadata of version 14 or above. The types of all the fields can be found in `types`.
+    Registry {
+        /// `None` if the type of the extrinsic doesn't indicate the type of the address, in
+        /// which case it is assumed to be a `MultiAddress`.
+        address: Option<u32>,
+        /// `None` if the type of the extrinsic doesn't indicate the type of the signature, in
+        /// which case it is assumed to be a `MultiSignature`.
+        signature: Option<u32>,
+        /// Identifier and type of the extra data of each signed extension.
+        signed_extensions: Vec<(&'a str, u32)>,
+        /// Registry of types. Indices in this list are type identifiers.
+        types: Vec<Type<'a>>,
+    },
+    /// Metadata older than version 14, which only contains the names of the signed
+    /// extensions.
+    Legacy { signed_extensions: Vec<&'a str> },
+}
+
+impl<'a> ExtrinsicFormat<'a> {
+    /// Returns the version of the extrinsics, as found in the lowest 7 bits of the first byte
+    /// of an extrinsic.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Returns the identifiers of the signed extensions, in the order in which their extra data
+    /// appears in signed extrinsics.
+    pub fn signed_extensions(&'_ self) -> impl ExactSizeIterator<Item = &'a str> + '_ {
+        match &self.layout {
+            Layout::Registry {
+                signed_extensions, ..
+            } => either::Left(signed_extensions.iter().map(|(name, _)| *name)),
+            Layout::Legacy { signed_extensions } => {
+                either::Right(signed_extensions.iter().copied())
+            }
+        }
+    }
+
+    /// Skips over the address of the sender of a signed extrinsic, and returns the rest of
+    /// `bytes`.
+    pub fn skip_address<'b>(&self, bytes: &'b [u8]) -> Result<&'b [u8], SkipError> {
+        match &self.layout {
+            Layout::Registry {
+                address: Some(address),
+                types,
+                ..
+            } => skip_type(types, *address, bytes, 0),
+            _ => skip_multi_address(bytes),
+        }
+    }
+
+    /// Skips over the signature of a signed extrinsic, and returns the rest of `bytes`.
+    pub fn skip_signature<'b>(&self, bytes: &'b [u8]) -> Result<&'b [u8], SkipError> {
+        match &self.layout {
+            Layout::Registry {
+                signature: Some(signature),
+                types,
+                ..
+            } => skip_type(types, *signature, bytes, 0),
+            _ => skip_multi_signature(bytes),
+        }
+    }
+
+    /// Skips over the extra data of the signed extension whose index is `index` in the list
+    /// returned by [`ExtrinsicFormat::signed_extensions`], and returns the rest of `bytes`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `index` is out of range.
+    ///
+    pub fn skip_signed_extension_extra<'b>(
+        &self,
+        index: usize,
+        bytes: &'b [u8],
+    ) -> Result<&'b [u8], SkipError> {
+        match &self.layout {
+            Layout::Registry {
+                signed_extensions,
+                types,
+                ..
+            } => skip_type(types, signed_extensions[index].1, bytes, 0),
+            Layout::Legacy { signed_extensions } => {
+                skip_legacy_signed_extension_extra(signed_extensions[index], bytes)
+            }
+        }
+    }
+}
+
+/// Error potentially returned when skipping over a field of an extrinsic.
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum SkipError {
+    /// Unexpected end of data.
+    TooShort,
+    /// Variant index not found in the definition of an enum.
+    #[display(fmt = "Unknown variant index: {}", _0)]
+    UnknownVariant(u8),
+    /// Types are nested too deeply.
+    RecursionLimit,
+    /// The data contains a bit sequence whose storage type isn't a primitive integer.
+    UnsupportedBitSequence,
+    /// The format of the extra data of a signed extension isn't known. Can only happen with
+    /// metadata older than version 14.
+    UnknownSignedExtension,
+}
+
+/// Maximum nesting depth of the types that are skipped over. Types found in extrinsics are
+/// normally only a few levels deep, but recursive types could otherwise be used to exhaust the
+/// stack.
+const MAX_DEPTH: u32 = 64;
+
+/// Skips over a value of the given type, and returns the rest of `bytes`.
+///
+/// `type_id` and all the types it refers to must exist in `types`.
+fn skip_type<'b>(
+    types: &[Type],
+    type_id: u32,
+    bytes: &'b [u8],
+    depth: u32,
+) -> Result<&'b [u8], SkipError> {
+    if depth >= MAX_DEPTH {
+        return Err(SkipError::RecursionLimit);
+    }
+
+    match &types[usize::try_from(type_id).unwrap()].def {
+        TypeDef::Fixed(size) => bytes.get(*size..).ok_or(SkipError::TooShort),
+        TypeDef::Str => {
+            let (bytes, len) = skip_scale_compact(bytes)?;
+            skip_bytes(bytes, len)
+        }
+        TypeDef::Compact => Ok(skip_scale_compact(bytes)?.0),
+        TypeDef::Composite(fields) | TypeDef::Tuple(fields) => {
+            fields.iter().try_fold(bytes, |bytes, field| {
+                skip_type(types, *field, bytes, depth + 1)
+            })
+        }
+        TypeDef::Variant(variants) => {
+            let (index, bytes) = bytes.split_first().ok_or(SkipError::TooShort)?;
+            let (_, fields) = variants
+                .iter()
+                .find(|(i, _)| i == index)
+                .ok_or(SkipError::UnknownVariant(*index))?;
+            fields.iter().try_fold(bytes, |bytes, field| {
+                skip_type(types, *field, bytes, depth + 1)
+            })
+        }
+        TypeDef::Sequence(elem) => {
+            let (bytes, len) = skip_scale_compact(bytes)?;
+            skip_repeated(types, *elem, len, bytes, depth + 1)
+        }
+        TypeDef::Array(len, elem) => {
+            skip_repeated(types, *elem, u128::from(*len), bytes, depth + 1)
+        }
+        TypeDef::BitSequence { store } => {
+            let store_size = match types[usize::try_from(*store).unwrap()].def {
+                TypeDef::Fixed(size) if size != 0 => size,
+                _ => return Err(SkipError::UnsupportedBitSequence),
+            };
+            let store_bits = u128::try_from(store_size * 8).unwrap();
+            let (bytes, num_bits) = skip_scale_compact(bytes)?;
+            let num_stores = num_bits / store_bits + if num_bits % store_bits == 0 { 0 } else { 1 };
+            skip_bytes(
+                bytes,
+                num_stores.saturating_mul(u128::try_from(store_size).unwrap()),
+            )
+        }
+    }
+}
+
+/// Skips over `len` values of the given type, and returns the rest of `bytes`.
+fn skip_repeated<'b>(
+    types: &[Type],
+    type_id: u32,
+    len: u128,
+    mut bytes: &'b [u8],
+    depth: u32,
+) -> Result<&'b [u8], SkipError> {
+    if let TypeDef::Fixed(size) = types[usize::try_from(type_id).unwrap()].def {
+        return skip_bytes(bytes, len.saturating_mul(u128::try_from(size).unwrap()));
+    }
+
+    for _ in 0..len {
+        let rest = skip_type(types, type_id, bytes, depth)?;
+        // A value that doesn't occupy any byte means that the type is zero-sized, in which case
+        // skipping over the other values wouldn't do anything either.
+        if rest.len() == bytes.len() {
+            break;
+        }
+        bytes = rest;
+    }
+
+    Ok(bytes)
+}
+
+/// Skips over the first `len` bytes of `bytes`.
+fn skip_bytes(bytes: &[u8], len: u128) -> Result<&[u8], SkipError> {
+    let len = usize::try_from(len).map_err(|_| SkipError::TooShort)?;
+    bytes.get(len..).ok_or(SkipError::TooShort)
+}
+
+/// Skips over a SCALE-compact-encoded number. Returns the rest of the data and the number.
+fn skip_scale_compact(bytes: &[u8]) -> Result<(&[u8], u128), SkipError> {
+    crate::util::nom_scale_compact_u128::<nom::error::Error<&[u8]>>(bytes)
+        .map_err(|_| SkipError::TooShort)
+}
+
+/// Skips over a `MultiAddress` of Substrate, and returns the rest of `bytes`.
+fn skip_multi_address(bytes: &[u8]) -> Result<&[u8], SkipError> {
+    match bytes.split_first().ok_or(SkipError::TooShort)? {
+        (0, rest) | (3, rest) => skip_bytes(rest, 32),
+        (1, rest) => Ok(skip_scale_compact(rest)?.0),
+        (2, rest) => {
+            let (rest, len) = skip_scale_compact(rest)?;
+            skip_bytes(rest, len)
+        }
+        (4, rest) => skip_bytes(rest, 20),
+        (n, _) => Err(SkipError::UnknownVariant(*n)),
+    }
+}
+
+/// Skips over a `MultiSignature` of Substrate, and returns the rest of `bytes`.
+fn skip_multi_signature(bytes: &[u8]) -> Result<&[u8], SkipError> {
+    match bytes.split_first().ok_or(SkipError::TooShort)? {
+        (0, rest) | (1, rest) => skip_bytes(rest, 64),
+        (2, rest) => skip_bytes(rest, 65),
+        (n, _) => Err(SkipError::UnknownVariant(*n)),
+    }
+}
+
+/// Skips over the extra data of the signed extension with the given name, for metadata older
+/// than version 14, and returns the rest of `bytes`.
+fn skip_legacy_signed_extension_extra<'b>(
+    name: &str,
+    bytes: &'b [u8],
+) -> Result<&'b [u8], SkipError> {
+    match name {
+        "CheckMortality" | "CheckEra" => match bytes.first() {
+            Some(0) => skip_bytes(bytes, 1),
+            Some(_) => skip_bytes(bytes, 2),
+            None => Err(SkipError::TooShort),
+        },
+        "CheckSpecVersion"
+        | "CheckTxVersion"
+        | "CheckVersion"
+        | "CheckGenesis"
+        | "CheckWeight"
+        | "CheckNonZeroSender"
+        | "CheckBlockGasLimit"
+        | "PrevalidateAttests"
+        | "RestrictFunctionality"
+        | "LimitParathreadCommits"
+        | "OnlyStakingAndClaims" => Ok(bytes),
+        "CheckNonce" | "ChargeTransactionPayment" => Ok(skip_scale_compact(bytes)?.0),
+        _ => Err(SkipError::UnknownSignedExtension),
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Type<'a> {
+    /// Names and types of the generic parameters of the type.
+    params: Vec<(&'a str, Option<u32>)>,
+    def: TypeDef,
+}
+
+#[derive(Debug, Clone)]
+enum TypeDef {
+    /// Struct. Contains the type of each field.
+    Composite(Vec<u32>),
+    /// Enum. Contains the index and the type of the fields of each variant.
+    Variant(Vec<(u8, Vec<u32>)>),
+    /// Length-prefixed list of elements of the given type.
+    Sequence(u32),
+    /// Fixed number of elements of the given type.
+    Array(u32, u32),
+    /// Contains the type of each element.
+    Tuple(Vec<u32>),
+    /// Primitive type of the given size in bytes.
+    Fixed(usize),
+    /// Length-prefixed UTF-8 string.
+    Str,
+    /// SCALE-compact-encoded number.
+    Compact,
+    /// Length-prefixed list of bits, stored in elements of type `store`.
+    BitSequence { store: u32 },
+}
+
+/// Parts of a version 14 metadata that are relevant to this module.
+struct MetadataV14<'a> {
+    types: Vec<(u32, Type<'a>)>,
+    extrinsic_type: u32,
+    extrinsic_version: u8,
+    signed_extensions: Vec<(&'a str, u32)>,
+}
+
+/// `nom` error type that is used.
+type NomError<'a> = nom::error::Error<&'a [u8]>;
+
+/// `nom` parser function that decodes an element of a `Vec`.
+type DecodingFn<'a, O> = fn(&'a [u8]) -> nom::IResult<&'a [u8], O, NomError<'a>>;
+
+// `nom` parser functions can be found below.
+
+fn metadata_v14(bytes: &[u8]) -> nom::IResult<&[u8], MetadataV14, NomError> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            vec_decode(portable_type),
+            |i| skip_vec(i, pallet),
+            type_id,
+            nom::number::complete::u8,
+            vec_decode(signed_extension),
+            // Type of the runtime.
+            type_id,
+        )),
+        |(types, (), extrinsic_type, extrinsic_version, signed_extensions, _)| MetadataV14 {
+            types,
+            extrinsic_type,
+            extrinsic_version,
+            signed_extensions,
+        },
+    )(bytes)
+}
+
+fn portable_type(bytes: &[u8]) -> nom::IResult<&[u8], (u32, Type), NomError> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            type_id,
+            |i| skip_vec(i, crate::util::nom_string_decode),
+            vec_decode(type_param),
+            type_def,
+            docs,
+        )),
+        |(id, (), params, def, ())| (id, Type { params, def }),
+    )(bytes)
+}
+
+fn type_param(bytes: &[u8]) -> nom::IResult<&[u8], (&str, Option<u32>), NomError> {
+    nom::sequence::pair(
+        crate::util::nom_string_decode,
+        crate::util::nom_option_decode(type_id),
+    )(bytes)
+}
+
+fn type_def(bytes: &[u8]) -> nom::IResult<&[u8], TypeDef, NomError> {
+    let (bytes, tag) = nom::number::complete::u8(bytes)?;
+    match tag {
+        0 => nom::combinator::map(vec_decode(field), TypeDef::Composite)(bytes),
+        1 => nom::combinator::map(vec_decode(variant), TypeDef::Variant)(bytes),
+        2 => nom::combinator::map(type_id, TypeDef::Sequence)(bytes),
+        3 => nom::combinator::map(
+            nom::sequence::pair(nom::number::complete::le_u32, type_id),
+            |(len, elem)| TypeDef::Array(len, elem),
+        )(bytes),
+        4 => nom::combinator::map(vec_decode(type_id), TypeDef::Tuple)(bytes),
+        5 => {
+            let (bytes, primitive) = nom::number::complete::u8(bytes)?;
+            let def = match primitive {
+                2 => TypeDef::Str,
+                0 | 3 | 9 => TypeDef::Fixed(1),
+                4 | 10 => TypeDef::Fixed(2),
+                1 | 5 | 11 => TypeDef::Fixed(4),
+                6 | 12 => TypeDef::Fixed(8),
+                7 | 13 => TypeDef::Fixed(16),
+                8 | 14 => TypeDef::Fixed(32),
+                _ => {
+                    return Err(nom::Err::Error(nom::error::make_error(
+                        bytes,
+                        nom::error::ErrorKind::Switch,
+                    )))
+                }
+            };
+            Ok((bytes, def))
+        }
+        6 => nom::combinator::map(type_id, |_| TypeDef::Compact)(bytes),
+        7 => nom::combinator::map(nom::sequence::pair(type_id, type_id), |(store, _order)| {
+            TypeDef::BitSequence { store }
+        })(bytes),
+        _ => Err(nom::Err::Error(nom::error::make_error(
+            bytes,
+            nom::error::ErrorKind::Switch,
+        ))),
+    }
+}
+
+fn field(bytes: &[u8]) -> nom::IResult<&[u8], u32, NomError> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            crate::util::nom_option_decode(crate::util::nom_string_decode),
+            type_id,
+            crate::util::nom_option_decode(crate::util::nom_string_decode),
+            docs,
+        )),
+        |(_, ty, _, ())| ty,
+    )(bytes)
+}
+
+fn variant(bytes: &[u8]) -> nom::IResult<&[u8], (u8, Vec<u32>), NomError> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            crate::util::nom_string_decode,
+            vec_decode(field),
+            nom::number::complete::u8,
+            docs,
+        )),
+        |(_, fields, index, ())| (index, fields),
+    )(bytes)
+}
+
+fn pallet(bytes: &[u8]) -> nom::IResult<&[u8], (), NomError> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            crate::util::nom_string_decode,
+            crate::util::nom_option_decode(pallet_storage),
+            // Calls, events.
+            crate::util::nom_option_decode(type_id),
+            crate::util::nom_option_decode(type_id),
+            |i| skip_vec(i, pallet_constant),
+            // Errors.
+            crate::util::nom_option_decode(type_id),
+            nom::number::complete::u8,
+        )),
+        |_| (),
+    )(bytes)
+}
+
+fn pallet_storage(bytes: &[u8]) -> nom::IResult<&[u8], (), NomError> {
+    nom::combinator::map(
+        nom::sequence::pair(crate::util::nom_string_decode, |i| {
+            skip_vec(i, storage_entry)
+        }),
+        |_| (),
+    )(bytes)
+}
+
+fn storage_entry(bytes: &[u8]) -> nom::IResult<&[u8], (), NomError> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            crate::util::nom_string_decode,
+            // Modifier.
+            nom::number::complete::u8,
+            storage_entry_type,
+            // Default value.
+            crate::util::nom_bytes_decode,
+            docs,
+        )),
+        |_| (),
+    )(bytes)
+}
+
+fn storage_entry_type(bytes: &[u8]) -> nom::IResult<&[u8], (), NomError> {
+    let (bytes, tag) = nom::number::complete::u8(bytes)?;
+    match tag {
+        0 => nom::combinator::map(type_id, |_| ())(bytes),
+        1 => nom::combinator::map(
+            nom::sequence::tuple((|i| skip_vec(i, nom::number::complete::u8), type_id, type_id)),
+            |_| (),
+        )(bytes),
+        _ => Err(nom::Err::Error(nom::error::make_error(
+            bytes,
+            nom::error::ErrorKind::Switch,
+        ))),
+    }
+}
+
+fn pallet_constant(bytes: &[u8]) -> nom::IResult<&[u8], (), NomError> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            crate::util::nom_string_decode,
+            type_id,
+            crate::util::nom_bytes_decode,
+            docs,
+        )),
+        |_| (),
+    )(bytes)
+}
+
+fn signed_extension(bytes: &[u8]) -> nom::IResult<&[u8], (&str, u32), NomError> {
+    nom::combinator::map(
+        nom::sequence::tuple((crate::util::nom_string_decode, type_id, type_id)),
+        |(identifier, ty, _additional_signed)| (identifier, ty),
+    )(bytes)
+}
+
+fn type_id(bytes: &[u8]) -> nom::IResult<&[u8], u32, NomError> {
+    nom::combinator::map_opt(crate::util::nom_scale_compact_u64, |id| {
+        u32::try_from(id).ok()
+    })(bytes)
+}
+
+fn docs(bytes: &[u8]) -> nom::IResult<&[u8], (), NomError> {
+    skip_vec(bytes, crate::util::nom_string_decode)
+}
+
+/// Returns a parser that decodes a SCALE-encoded `Vec`.
+fn vec_decode<'a, O>(
+    decoding_fn: DecodingFn<'a, O>,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], Vec<O>, NomError<'a>> {
+    move |bytes| {
+        let (bytes, num_elems) = crate::util::nom_scale_compact_usize(bytes)?;
+        crate::util::nom_many_exact(num_elems, decoding_fn)(bytes)
+    }
+}
+
+/// Skips over a SCALE-encoded `Vec` without collecting its elements.
+fn skip_vec<'a, O>(
+    bytes: &'a [u8],
+    decoding_fn: DecodingFn<'a, O>,
+) -> nom::IResult<&'a [u8], (), NomError<'a>> {
+    let (mut bytes, num_elems) = crate::util::nom_scale_compact_usize(bytes)?;
+    for _ in 0..num_elems {
+        bytes = decoding_fn(bytes)?.0;
+    }
+    Ok((bytes, ()))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{decode, DecodeError, SkipError};
+    use alloc::vec::Vec;
+
+    /// Builds a SCALE-encoded version 14 metadata. Its extrinsics use a simplified
+    /// `MultiAddress` and `MultiSignature`, and have a `CustomExtension` signed extension whose
+    /// extra data is a `u32`, followed with the era, the nonce, and the tip.
+    pub(crate) fn example_metadata_v14() -> Vec<u8> {
+        fn compact(out: &mut Vec<u8>, value: usize) {
+            out.extend_from_slice(crate::util::encode_scale_compact_usize(value).as_ref());
+        }
+        fn string(out: &mut Vec<u8>, value: &str) {
+            compact(out, value.len());
+            out.extend_from_slice(value.as_bytes());
+        }
+        // Type without path, generic parameters, or documentation.
+        fn ty(out: &mut Vec<u8>, id: usize, def: &[u8]) {
+            compact(out, id);
+            out.extend_from_slice(&[0, 0]);
+            out.extend_from_slice(def);
+            out.push(0);
+        }
+        // Variant with at most one unnamed field.
+        fn variant(out: &mut Vec<u8>, name: &str, field: Option<u8>, index: u8) {
+            string(out, name);
+            match field {
+                Some(field) => out.extend_from_slice(&[1 << 2, 0, field << 2, 0, 0]),
+                None => out.push(0),
+            }
+            out.extend_from_slice(&[index, 0]);
+        }
+
+        let mut out = b"meta".to_vec();
+        out.push(14);
+
+        // Types registry.
+        compact(&mut out, 10);
+        ty(&mut out, 0, &[5, 3]); // u8
+        ty(&mut out, 1, &[3, 32, 0, 0, 0, 0]); // [u8; 32]
+        let mut address = vec![1, 2 << 2];
+        variant(&mut address, "Id", Some(1), 0);
+        variant(&mut address, "Index", Some(5), 1);
+        ty(&mut out, 2, &address);
+        ty(&mut out, 3, &[3, 64, 0, 0, 0, 0]); // [u8; 64]
+        let mut signature = vec![1, 1 << 2];
+        variant(&mut signature, "Sr25519", Some(3), 1);
+        ty(&mut out, 4, &signature);
+        ty(&mut out, 5, &[6, 6 << 2]); // Compact<u32>
+        ty(&mut out, 6, &[5, 5]); // u32
+        let mut era = vec![1, 2 << 2];
+        variant(&mut era, "Immortal", None, 0);
+        variant(&mut era, "Mortal165", Some(0), 165);
+        ty(&mut out, 7, &era);
+        {
+            compact(&mut out, 8);
+            compact(&mut out, 1);
+            string(&mut out, "UncheckedExtrinsic");
+            compact(&mut out, 3);
+            string(&mut out, "Address");
+            out.extend_from_slice(&[1, 2 << 2]);
+            string(&mut out, "Call");
+            out.push(0);
+            string(&mut out, "Signature");
+            out.extend_from_slice(&[1, 4 << 2]);
+            out.extend_from_slice(&[0, 0, 0]);
+        }
+        ty(&mut out, 9, &[4, 0]); // ()
+
+        // Pallets.
+        compact(&mut out, 1);
+        string(&mut out, "System");
+        out.push(1);
+        string(&mut out, "System");
+        compact(&mut out, 2);
+        string(&mut out, "Number");
+        out.extend_from_slice(&[0, 0, 6 << 2, 4 << 2, 0, 0, 0, 0, 0]);
+        string(&mut out, "Account");
+        out.extend_from_slice(&[1, 1, 1 << 2, 0, 1 << 2, 6 << 2, 0, 1 << 2]);
+        string(&mut out, "Account information.");
+        out.extend_from_slice(&[0, 1, 9 << 2]);
+        compact(&mut out, 1);
+        string(&mut out, "Version");
+        out.extend_from_slice(&[6 << 2, 4 << 2, 1, 0, 0, 0, 0]);
+        out.extend_from_slice(&[0, 0]);
+
+        // Extrinsic.
+        out.extend_from_slice(&[8 << 2, 4]);
+        compact(&mut out, 5);
+        for (name, ty) in [
+            ("CheckSpecVersion", 9),
+            ("CustomExtension", 6),
+            ("CheckMortality", 7),
+            ("CheckNonce", 5),
+            ("ChargeTransactionPayment", 5),
+        ]
+        .iter()
+        {
+            string(&mut out, name);
+            out.extend_from_slice(&[ty << 2, 9 << 2]);
+        }
+
+        // Type of the runtime.
+        out.push(9 << 2);
+        out
+    }
+
+    #[test]
+    fn decode_v14() {
+        let metadata = example_metadata_v14();
+        let format = decode(&metadata).unwrap();
+        assert_eq!(format.version(), 4);
+        assert_eq!(
+            format.signed_extensions().collect::<Vec<_>>(),
+            [
+                "CheckSpecVersion",
+                "CustomExtension",
+                "CheckMortality",
+                "CheckNonce",
+                "ChargeTransactionPayment"
+            ]
+        );
+
+        let mut address = vec![0x00];
+        address.extend_from_slice(&[0xaa; 32]);
+        address.push(0xff);
+        assert_eq!(format.skip_address(&address).unwrap(), &[0xff]);
+        assert_eq!(format.skip_address(&[0x01, 0x04, 0xff]).unwrap(), &[0xff]);
+        assert_eq!(
+            format.skip_address(&[0x02]),
+            Err(SkipError::UnknownVariant(2))
+        );
+        assert_eq!(format.skip_signature(&[0x01; 10]), Err(SkipError::TooShort));
+
+        assert_eq!(
+            format.skip_signed_extension_extra(0, &[0xff]).unwrap(),
+            &[0xff]
+        );
+        assert_eq!(
+            format
+                .skip_signed_extension_extra(1, &[1, 2, 3, 4, 0xff])
+                .unwrap(),
+            &[0xff]
+        );
+        assert_eq!(
+            format
+                .skip_signed_extension_extra(2, &[0xa5, 0x02, 0xff])
+                .unwrap(),
+            &[0xff]
+        );
+        assert_eq!(
+            format
+                .skip_signed_extension_extra(3, &[0x04, 0xff])
+                .unwrap(),
+            &[0xff]
+        );
+    }
+
+    #[test]
+    fn unsupported_metadata() {
+        assert_eq!(
+            decode(b"meta\x0d").unwrap_err(),
+            DecodeError::UnsupportedVersion(13)
+        );
+        assert_eq!(
+            decode(b"atem\x0e").unwrap_err(),
+            DecodeError::InvalidMetadata
+        );
+
+        let mut metadata = example_metadata_v14();
+        metadata.push(0);
+        assert_eq!(decode(&metadata).unwrap_err(), DecodeError::InvalidMetadata);
+    }
+
+    #[test]
+    fn legacy_signed_extensions() {
+        let metadata = &include_bytes!("decode/example-metadata")[..];
+        let format = decode(metadata).unwrap();
+        assert_eq!(
+            format.signed_extensions().next(),
+            Some("TransactionCallFilter")
+        );
+        assert_eq!(
+            format.skip_signed_extension_extra(0, &[]),
+            Err(SkipError::UnknownSignedExtension)
+        );
+    }
+}
@@ -109,6 +109,9 @@ impl Query {
             read_only_runtime_host::RuntimeHostVm::StorageRoot(_) => {
                 Query::Finished(Err(Error::HostFunctionNotAllowed))
             }
+            read_only_runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                Query::from_inner(sig.verify_and_resume())
+            }
         }
     }
 }
@@ -0,0 +1,226 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage keys construction.
+//!
+//! # Overview
+//!
+//! Substrate-compatible blockchains built using the Substrate framework store each storage
+//! entry declared by a module of the runtime under a key made of:
+//!
+//! - The XXHash of the storage prefix of the module, which is usually the name of the module.
+//! - The XXHash of the name of the storage entry.
+//! - If the storage entry is a map or a double map, each SCALE-encoded map key hashed with the
+//! hasher indicated in the metadata for this key.
+//!
+//! This module provides the tooling necessary to build such keys from the metadata, for example
+//! in order to then query the storage value through the `state_getStorage` JSON-RPC method.
+//!
+//! # Usage
+//!
+//! - Obtain the *metadata* of the runtime used by the desired block. This is out of scope of this
+//! module. See the [metadata](crate::metadata) module for more information.
+//! - Call [`storage_key`] with the name of the module, the name of the storage entry, and the
+//! SCALE-encoded map keys if the entry is a map.
+//! - Obtain the storage value corresponding to the key obtained at the previous step. This is out
+//! of scope of this module. If there is no storage value at this key, use
+//! [`StorageKey::default_value`] instead.
+//!
+//! > **Note**: The type of the storage value, and of the map keys, is found in
+//! >           [`StorageKey::ty`] in the form of a string representing a Rust type. See the
+//! >           [`events`](crate::metadata::events) module for more information about why this
+//! >           module doesn't provide any way to encode or decode them.
+//!
+
+use crate::metadata::decode as metadata;
+use alloc::vec::Vec;
+use core::{convert::TryFrom, hash::Hasher as _};
+
+/// Key in the storage of a storage entry, as returned by [`storage_key`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageKey<'a> {
+    /// Key in the storage.
+    pub key: Vec<u8>,
+
+    /// Type of the storage entry, as found in the metadata.
+    pub ty: metadata::StorageEntryTypeRef<'a>,
+
+    /// Value to consider if the storage doesn't contain any value at [`StorageKey::key`].
+    pub default_value: &'a [u8],
+}
+
+/// Returns the key in the storage of the given storage entry of the given module.
+///
+/// `map_keys` must contain the SCALE-encoded keys of the entry: none for a plain storage entry,
+/// one for a map, and two for a double map. If fewer keys are passed, the returned key is
+/// instead the prefix shared by the keys of all the values of the map, which can for example be
+/// passed to the `state_getKeysPaged` JSON-RPC method.
+///
+/// > **Note**: This key is based entirely on the metadata passed as parameter. Be aware that,
+/// >           albeit unlikely, if the metadata changes, the key might change as well.
+///
+/// An error is returned if the metadata doesn't contain the requested storage entry, or if too
+/// many map keys are passed.
+pub fn storage_key<'a>(
+    mut metadata: metadata::MetadataRef<'a>,
+    module_name: &str,
+    entry_name: &str,
+    map_keys: &[&[u8]],
+) -> Result<StorageKey<'a>, StorageKeyError> {
+    let module = metadata
+        .modules
+        .find(|m| m.name == module_name)
+        .ok_or(StorageKeyError::NoModule)?;
+
+    let mut storage = module.storage.ok_or(StorageKeyError::NoEntry)?;
+
+    let entry = storage
+        .entries
+        .find(|e| e.name == entry_name)
+        .ok_or(StorageKeyError::NoEntry)?;
+
+    let hashers = match entry.ty {
+        metadata::StorageEntryTypeRef::Plain(_) => &[][..],
+        metadata::StorageEntryTypeRef::Map { hasher, .. } => &[hasher][..],
+        metadata::StorageEntryTypeRef::DoubleMap {
+            hasher,
+            key2_hasher,
+            ..
+        } => &[hasher, key2_hasher][..],
+    };
+
+    if map_keys.len() > hashers.len() {
+        return Err(StorageKeyError::TooManyMapKeys {
+            expected: hashers.len(),
+        });
+    }
+
+    let mut key = Vec::with_capacity(32 + map_keys.iter().map(|k| 16 + k.len()).sum::<usize>());
+    key.extend_from_slice(&[0; 32]);
+    twox_128(
+        storage.prefix.as_bytes(),
+        TryFrom::try_from(&mut key[..16]).unwrap(),
+    );
+    twox_128(
+        entry.name.as_bytes(),
+        TryFrom::try_from(&mut key[16..32]).unwrap(),
+    );
+    for (hasher, map_key) in hashers.iter().zip(map_keys) {
+        append_hashed_map_key(*hasher, map_key, &mut key);
+    }
+
+    Ok(StorageKey {
+        key,
+        ty: entry.ty,
+        default_value: entry.default,
+    })
+}
+
+/// Error potentially returned by [`storage_key`].
+#[derive(Debug, derive_more::Display)]
+pub enum StorageKeyError {
+    /// No module with the requested name has been found.
+    NoModule,
+    /// The module doesn't have any storage entry with the requested name.
+    NoEntry,
+    /// More map keys than the storage entry has have been passed.
+    #[display(fmt = "Too many map keys; the storage entry has {}", expected)]
+    TooManyMapKeys {
+        /// Number of map keys of the storage entry.
+        expected: usize,
+    },
+}
+
+/// Fills `dest` with the XXHash of `data`.
+fn twox_128(data: &[u8], dest: &mut [u8; 16]) {
+    let mut h0 = twox_hash::XxHash::with_seed(0);
+    let mut h1 = twox_hash::XxHash::with_seed(1);
+    h0.write(data);
+    h1.write(data);
+    let r0 = h0.finish();
+    let r1 = h1.finish();
+
+    dest[..8].copy_from_slice(&r0.to_le_bytes()[..]);
+    dest[8..].copy_from_slice(&r1.to_le_bytes()[..]);
+}
+
+/// Hashes `map_key` the way the runtime does for the given hasher, and appends the result to
+/// `out`.
+fn append_hashed_map_key(hasher: metadata::StorageHasher, map_key: &[u8], out: &mut Vec<u8>) {
+    match hasher {
+        metadata::StorageHasher::Blake2_128 => {
+            out.extend_from_slice(blake2_rfc::blake2b::blake2b(16, &[], map_key).as_bytes())
+        }
+        metadata::StorageHasher::Blake2_256 => {
+            out.extend_from_slice(blake2_rfc::blake2b::blake2b(32, &[], map_key).as_bytes())
+        }
+        metadata::StorageHasher::Blake2_128Concat => {
+            out.extend_from_slice(blake2_rfc::blake2b::blake2b(16, &[], map_key).as_bytes());
+            out.extend_from_slice(map_key);
+        }
+        metadata::StorageHasher::Twox128 => {
+            let mut hash = [0; 16];
+            twox_128(map_key, &mut hash);
+            out.extend_from_slice(&hash);
+        }
+        metadata::StorageHasher::Twox256 => {
+            for seed in 0..4 {
+                let mut hasher = twox_hash::XxHash::with_seed(seed);
+                hasher.write(map_key);
+                out.extend_from_slice(&hasher.finish().to_le_bytes());
+            }
+        }
+        metadata::StorageHasher::Twox64Concat => {
+            let mut hasher = twox_hash::XxHash::with_seed(0);
+            hasher.write(map_key);
+            out.extend_from_slice(&hasher.finish().to_le_bytes());
+            out.extend_from_slice(map_key);
+        }
+        metadata::StorageHasher::Identity => out.extend_from_slice(map_key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn plain_entry() {
+        let metadata =
+            crate::metadata::decode(&include_bytes!("decode/example-metadata")[..]).unwrap();
+        let key = super::storage_key(metadata, "System", "Events", &[]).unwrap();
+        assert_eq!(
+            hex::encode(&key.key),
+            "26aa394eea5630e07c48ae0c9558cef780d41e5e16056765bc8461851072c9d7"
+        );
+    }
+
+    #[test]
+    fn map_prefix_and_too_many_keys() {
+        let metadata =
+            crate::metadata::decode(&include_bytes!("decode/example-metadata")[..]).unwrap();
+
+        let prefix = super::storage_key(metadata, "System", "Account", &[]).unwrap();
+        assert_eq!(
+            hex::encode(&prefix.key),
+            "26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9"
+        );
+
+        assert!(matches!(
+            super::storage_key(metadata, "System", "Account", &[&[0; 32], &[0; 32]]),
+            Err(super::StorageKeyError::TooManyMapKeys { expected: 1 })
+        ));
+    }
+}
@@ -21,16 +21,42 @@
 //! used. This module contains the domain-specific protocols specific to Substrate and Polkadot.
 //!
 //! This module only provides the tools to encode/decode messages.
+//!
+//! # Compressed responses
+//!
+//! Block requests and storage proof requests can optionally indicate that the response can be
+//! compressed, by setting the `accept_compressed_response` field of their configuration. This is
+//! a smoldot-specific extension that isn't part of the Substrate protocol.
+//!
+//! On the wire, the request then contains a `support_compressed_response` boolean field with the
+//! protobuf field number 64, which is far from the field numbers used by Substrate in order to
+//! avoid collisions with future versions of the protocol. The field is omitted entirely when
+//! compression isn't requested, so that the encoding of requests is otherwise unchanged.
+//!
+//! There is no explicit negotiation. Protobuf decoders ignore unknown fields, and
+//! implementations that don't know about the extension, such as Substrate, thus always answer
+//! with an uncompressed response. Implementations that support it can instead answer with the
+//! 8 bytes prefix used by compressed runtime codes, followed with the zstandard compression of
+//! the encoded response. Uncompressed responses start with the tag of one of the fields of the
+//! response, none of which has the field number 10 that the first byte of the prefix designates.
+//!
+//! The decoding functions of this module transparently decompress responses, within the limit of
+//! the size passed to them. Alternatively, [`decompress_response_with`] can be used to delegate
+//! the decompression to a different implementation, for example one provided by the environment
+//! the client runs in.
 
 // TODO: expand docs
 
 // Implementation note: each protocol goes into a different sub-module whose content is
 // re-exported here.
 
+use alloc::{borrow::Cow, vec::Vec};
+
 mod block_announces;
 mod block_request;
 mod call_proof;
 mod grandpa;
+#[cfg(feature = "warp-sync")]
 mod grandpa_warp_sync;
 mod identify;
 mod storage_proof;
@@ -39,11 +65,14 @@ pub use self::block_announces::*;
 pub use self::block_request::*;
 pub use self::call_proof::*;
 pub use self::grandpa::*;
+#[cfg(feature = "warp-sync")]
 pub use self::grandpa_warp_sync::*;
 pub use self::identify::*;
 pub use self::storage_proof::*;
 
 // Protobuf schemas are gathered here.
+// Some messages, such as `BlockResponse`, are decoded manually and are only used by the tests.
+#[allow(dead_code)]
 mod schema {
     include!(concat!(env!("OUT_DIR"), "/api.v1.rs"));
     include!(concat!(env!("OUT_DIR"), "/api.v1.light.rs"));
@@ -54,3 +83,99 @@ mod schema {
 #[derive(Debug, derive_more::Display)]
 #[display(fmt = "{}", _0)]
 pub struct ProtobufDecodeError(prost::DecodeError);
+
+/// Error while decompressing a zstandard-compressed response.
+#[derive(Debug, derive_more::Display)]
+pub enum DecompressionError {
+    /// Invalid compressed data, or decompressed data larger than the limit.
+    #[display(fmt = "{}", _0)]
+    Zstd(crate::executor::host::ModuleFormatError),
+    /// The [`ResponseDecompressor`] passed to [`decompress_response_with`] has failed to
+    /// decompress the response.
+    #[display(fmt = "Failed to decompress the response")]
+    External,
+}
+
+/// Function that decompresses zstandard-compressed data. See [`decompress_response_with`].
+///
+/// Receives the zstandard frames, without the prefix indicating that the response is compressed,
+/// and the maximum allowed size of the decompressed data. Must return `None` if the decompression
+/// isn't available, in which case it is performed locally, and `Some(Err(()))` if the data is
+/// invalid or decompresses to more than the maximum size.
+pub type ResponseDecompressor = fn(&[u8], usize) -> Option<Result<Vec<u8>, ()>>;
+
+/// Maximum size, in bytes, of a response after decompression, for requests whose response
+/// doesn't have a more precise size limit.
+///
+/// Decompressed responses must always be bounded in size in order to avoid zip bombs.
+pub const MAX_DECOMPRESSED_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Maximum size, in bytes, of a GrandPa warp sync response.
+///
+/// Responses are received entirely in memory before being decoded. Responders cut their proofs
+/// well below this size (8 MiB for Substrate at the time of writing), and larger responses are
+/// refused while they are being received rather than buffered.
+pub const MAX_GRANDPA_WARP_SYNC_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+/// If `max_decompressed_size` is `Some`, meaning that the request indicated support for
+/// compressed responses, and the given response starts with the zstandard prefix, decompresses
+/// it. Otherwise, passes it through.
+///
+/// The decompressed response can't be larger than `max_decompressed_size` bytes.
+fn decompress_response_if_necessary(
+    response_bytes: &[u8],
+    max_decompressed_size: Option<usize>,
+) -> Result<Cow<[u8]>, DecompressionError> {
+    match max_decompressed_size {
+        Some(max_decompressed_size) => crate::executor::host::zstd::zstd_decode_if_necessary(
+            response_bytes,
+            max_decompressed_size,
+        )
+        .map_err(DecompressionError::Zstd),
+        None => Ok(Cow::Borrowed(response_bytes)),
+    }
+}
+
+/// Decompresses the given response using `decompressor` if it starts with the zstandard prefix.
+/// Otherwise, or if `decompressor` returns `None`, passes the response through unchanged.
+///
+/// Must only be called with responses to requests that indicated support for compressed
+/// responses. If the returned response is the decompressed one, it must then be decoded as if
+/// compressed responses weren't supported, in order to not decompress it a second time.
+///
+/// Returns `true` alongside the response if it has been decompressed.
+pub fn decompress_response_with(
+    response_bytes: Vec<u8>,
+    max_decompressed_size: usize,
+    decompressor: ResponseDecompressor,
+) -> Result<(Vec<u8>, bool), DecompressionError> {
+    let prefix = crate::executor::host::zstd::ZSTD_PREFIX;
+    if !response_bytes.starts_with(&prefix) {
+        return Ok((response_bytes, false));
+    }
+
+    match decompressor(&response_bytes[prefix.len()..], max_decompressed_size) {
+        Some(Ok(decompressed)) if decompressed.len() <= max_decompressed_size => {
+            Ok((decompressed, true))
+        }
+        Some(_) => Err(DecompressionError::External),
+        None => Ok((response_bytes, false)),
+    }
+}
+
+/// Encodes a list of trie node values the way proofs are found in light client responses, in
+/// other words as a SCALE-encoded `Vec<Vec<u8>>`.
+fn encode_proof(proof: impl Iterator<Item = impl AsRef<[u8]>>) -> alloc::vec::Vec<u8> {
+    let proof = proof.collect::<alloc::vec::Vec<_>>();
+
+    let mut out = crate::util::encode_scale_compact_usize(proof.len())
+        .as_ref()
+        .to_vec();
+    for node_value in &proof {
+        out.extend_from_slice(
+            crate::util::encode_scale_compact_usize(node_value.as_ref().len()).as_ref(),
+        );
+        out.extend_from_slice(node_value.as_ref());
+    }
+    out
+}
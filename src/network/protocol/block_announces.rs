@@ -87,10 +87,14 @@ pub fn encode_block_announce<'a>(
 pub fn decode_block_announce(bytes: &[u8]) -> Result<BlockAnnounceRef, DecodeBlockAnnounceError> {
     nom::combinator::all_consuming(nom::combinator::map(
         nom::sequence::tuple((
+            // The header is decoded again by the syncing code, which knows which consensus
+            // engines are accepted.
             |s| {
-                header::decode_partial(s).map(|(a, b)| (b, a)).map_err(|_| {
-                    nom::Err::Failure(nom::error::make_error(s, nom::error::ErrorKind::Verify))
-                })
+                header::decode_partial_any_engine(s)
+                    .map(|(a, b)| (b, a))
+                    .map_err(|_| {
+                        nom::Err::Failure(nom::error::make_error(s, nom::error::ErrorKind::Verify))
+                    })
             },
             nom::branch::alt((
                 nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| false),
@@ -15,9 +15,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{schema, ProtobufDecodeError};
+use super::{schema, DecompressionError, ProtobufDecodeError};
+use crate::util::leb128;
 
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 use core::{
     convert::TryFrom,
     iter,
@@ -37,6 +38,12 @@ pub struct BlocksRequestConfig {
     pub direction: BlocksRequestDirection,
     /// Which fields should be present in the response.
     pub fields: BlocksRequestFields,
+    /// If true, indicates to the remote that the response can be zstandard-compressed.
+    ///
+    /// This relies on a non-standard field of the request, which is only present on the wire if
+    /// this flag is `true`. Remotes that don't support compression ignore this field. Compressed
+    /// responses must be decoded by passing `Some` to [`decode_block_response`].
+    pub accept_compressed_response: bool,
 }
 
 /// Whether the first block should be the one with the highest number, of the one with the lowest
@@ -54,7 +61,12 @@ pub enum BlocksRequestDirection {
 pub struct BlocksRequestFields {
     pub header: bool,
     pub body: bool,
+    /// Requests the justifications of the blocks. See [`BlockData::justification`] and
+    /// [`BlockData::justifications`].
     pub justification: bool,
+    /// Requests the data indexed by the transactions of the blocks. Remotes that don't support
+    /// indexing ignore this flag.
+    pub indexed_body: bool,
 }
 
 /// Which block the remote must return first.
@@ -82,6 +94,9 @@ pub fn build_block_request(config: BlocksRequestConfig) -> impl Iterator<Item =
         if config.fields.justification {
             fields |= 1 << 28;
         }
+        if config.fields.indexed_body {
+            fields |= 1 << 29;
+        }
 
         schema::BlockRequest {
             fields,
@@ -99,6 +114,10 @@ pub fn build_block_request(config: BlocksRequestConfig) -> impl Iterator<Item =
                 BlocksRequestDirection::Descending => schema::Direction::Descending as i32,
             },
             max_blocks: config.desired_count.get(),
+            support_compressed_response: config.accept_compressed_response,
+            // Remotes that support multiple justifications fill the `justifications` field
+            // instead of `justification` in their response. Both are supported when decoding.
+            support_multiple_justifications: true,
         }
     };
 
@@ -111,21 +130,65 @@ pub fn build_block_request(config: BlocksRequestConfig) -> impl Iterator<Item =
     iter::once(request_bytes)
 }
 
+/// Maximum size, in bytes, of the header and justifications of a single block in a response.
+///
+/// Headers are normally small, but can contain a new list of authorities, and justifications
+/// contain one signature per authority.
+const MAX_BLOCK_SIZE_WITHOUT_BODY: usize = 256 * 1024;
+
+/// Returns the maximum size of a response to the given request that is considered plausible,
+/// or `None` if the size of the response can't be bounded.
+///
+/// The size of block bodies isn't bounded by anything other than the runtime of the chain.
+/// Consequently, `None` is returned if bodies are requested.
+pub fn block_response_size_limit(config: &BlocksRequestConfig) -> Option<usize> {
+    if config.fields.body || config.fields.indexed_body {
+        return None;
+    }
+
+    let num_blocks = usize::try_from(config.desired_count.get()).unwrap_or(usize::max_value());
+    Some(num_blocks.saturating_mul(MAX_BLOCK_SIZE_WITHOUT_BODY))
+}
+
 /// Decodes a response to a block request.
+///
+/// The blocks of the response are decoded one by one, and each block is verified before the
+/// next one is decoded: if a block contains a header whose hash doesn't match the hash of the
+/// block, an error is returned immediately and the rest of the response isn't decoded.
+///
+/// `max_decompressed_size` must be `Some` if and only if
+/// [`BlocksRequestConfig::accept_compressed_response`] was `true` in the request, in which case
+/// a compressed response is decompressed, as long as its decompressed size doesn't exceed this
+/// value.
 // TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
 pub fn decode_block_response(
     response_bytes: &[u8],
+    max_decompressed_size: Option<usize>,
 ) -> Result<Vec<BlockData>, DecodeBlockResponseError> {
-    let response = schema::BlockResponse::decode(&response_bytes[..])
-        .map_err(ProtobufDecodeError)
-        .map_err(DecodeBlockResponseError::ProtobufDecode)?;
+    let response_bytes =
+        super::decompress_response_if_necessary(response_bytes, max_decompressed_size)
+            .map_err(DecodeBlockResponseError::Decompression)?;
+
+    let mut blocks = Vec::new();
+    let mut remaining = &response_bytes[..];
+    while !remaining.is_empty() {
+        let block = match next_encoded_block(&mut remaining)? {
+            Some(block) => schema::BlockData::decode(block)
+                .map_err(ProtobufDecodeError)
+                .map_err(DecodeBlockResponseError::ProtobufDecode)?,
+            None => continue,
+        };
 
-    let mut blocks = Vec::with_capacity(response.blocks.len());
-    for block in response.blocks {
         if block.hash.len() != 32 {
             return Err(DecodeBlockResponseError::InvalidHashLength);
         }
 
+        if !block.header.is_empty()
+            && crate::header::hash_from_scale_encoded_header(&block.header)[..] != block.hash[..]
+        {
+            return Err(DecodeBlockResponseError::HeaderHashMismatch);
+        }
+
         let mut body = Vec::with_capacity(block.body.len());
         for extrinsic in block.body {
             // TODO: this encoding really is a bit stupid
@@ -141,6 +204,21 @@ pub fn decode_block_response(
             }
         }
 
+        // Remotes that support multiple justifications fill the `justifications` field, while
+        // the other remotes fill the `justification` field with a GrandPa justification.
+        let justifications = if !block.justifications.is_empty() {
+            let parsing: nom::IResult<_, _> =
+                nom::combinator::all_consuming(decode_justifications)(&block.justifications);
+            match parsing {
+                Ok((_, j)) => Some(j),
+                Err(_) => return Err(DecodeBlockResponseError::JustificationsDecodeError),
+            }
+        } else if !block.justification.is_empty() || block.is_empty_justification {
+            Some(vec![(GRANDPA_ENGINE_ID, block.justification)])
+        } else {
+            None
+        };
+
         blocks.push(BlockData {
             hash: <[u8; 32]>::try_from(&block.hash[..]).unwrap(),
             header: if !block.header.is_empty() {
@@ -150,19 +228,78 @@ pub fn decode_block_response(
             },
             // TODO: no; we might not have asked for the body
             body: Some(body),
-            justification: if !block.justification.is_empty() {
-                Some(block.justification)
-            } else if block.is_empty_justification {
-                Some(Vec::new())
+            indexed_body: if !block.indexed_body.is_empty() {
+                Some(block.indexed_body)
             } else {
                 None
             },
+            justification: justifications.as_ref().and_then(|list| {
+                list.iter()
+                    .find(|(engine_id, _)| *engine_id == GRANDPA_ENGINE_ID)
+                    .map(|(_, justification)| justification.clone())
+            }),
+            justifications,
         });
     }
 
     Ok(blocks)
 }
 
+/// Reads the field of the protobuf-encoded `BlockResponse` found at the start of `bytes`, and
+/// updates `bytes` to point after this field.
+///
+/// Returns the protobuf-encoded `BlockData` if the field is one of the blocks of the response.
+/// Other fields are skipped, as required by the protobuf specification.
+fn next_encoded_block<'a>(
+    bytes: &mut &'a [u8],
+) -> Result<Option<&'a [u8]>, DecodeBlockResponseError> {
+    let key = read_protobuf_varint(bytes)?;
+    let (field_number, wire_type) = (key >> 3, key & 0b111);
+
+    let field_len = match wire_type {
+        // Variable-length integer.
+        0 => {
+            read_protobuf_varint(bytes)?;
+            0
+        }
+        // 64 bits value.
+        1 => 8,
+        // Length-delimited value.
+        2 => usize::try_from(read_protobuf_varint(bytes)?)
+            .map_err(|_| DecodeBlockResponseError::ProtobufFraming)?,
+        // 32 bits value.
+        5 => 4,
+        _ => return Err(DecodeBlockResponseError::ProtobufFraming),
+    };
+
+    if bytes.len() < field_len {
+        return Err(DecodeBlockResponseError::ProtobufFraming);
+    }
+    let (field, rest) = bytes.split_at(field_len);
+    *bytes = rest;
+
+    match (field_number, wire_type) {
+        // `repeated BlockData blocks = 1;`
+        (1, 2) => Ok(Some(field)),
+        (0, _) | (1, _) => Err(DecodeBlockResponseError::ProtobufFraming),
+        _ => Ok(None),
+    }
+}
+
+/// Reads a protobuf variable-length integer at the start of `bytes`, and updates `bytes` to
+/// point after it.
+fn read_protobuf_varint(bytes: &mut &[u8]) -> Result<u64, DecodeBlockResponseError> {
+    match leb128::Decoder::new().update(bytes) {
+        Ok((num_read, leb128::Decoded::Finished(value))) => {
+            *bytes = &bytes[num_read..];
+            Ok(value)
+        }
+        Ok((_, leb128::Decoded::InProgress(_))) | Err(_) => {
+            Err(DecodeBlockResponseError::ProtobufFraming)
+        }
+    }
+}
+
 /// Block sent in a block response.
 ///
 /// > **Note**: Assuming that this response comes from the network, the information in this struct
@@ -181,16 +318,351 @@ pub struct BlockData {
     /// Block body, if requested.
     pub body: Option<Vec<Vec<u8>>>,
 
-    /// Justification, if requested and available.
+    /// Data indexed by the transactions of the block, if requested and available.
+    pub indexed_body: Option<Vec<Vec<u8>>>,
+
+    /// GrandPa justification, if requested and available.
+    ///
+    /// Identical to the entry of [`BlockData::justifications`] whose consensus engine is
+    /// GrandPa.
     pub justification: Option<Vec<u8>>,
+
+    /// List of consensus engine ids and justifications, if requested and available.
+    ///
+    /// Remotes that don't support multiple justifications only ever return a GrandPa
+    /// justification.
+    pub justifications: Option<Vec<([u8; 4], Vec<u8>)>>,
+}
+
+/// Consensus engine id of GrandPa justifications.
+const GRANDPA_ENGINE_ID: [u8; 4] = *b"FRNK";
+
+/// Decodes a SCALE-encoded list of consensus engine ids and justifications.
+fn decode_justifications<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], Vec<([u8; 4], Vec<u8>)>, E> {
+    nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+        crate::util::nom_many_exact(
+            num_elems,
+            nom::sequence::tuple((
+                nom::combinator::map(nom::bytes::complete::take(4u32), |id: &[u8]| {
+                    <[u8; 4]>::try_from(id).unwrap()
+                }),
+                nom::combinator::map(
+                    nom::multi::length_data(crate::util::nom_scale_compact_usize),
+                    |j: &[u8]| j.to_vec(),
+                ),
+            )),
+        )
+    })(bytes)
 }
 
 /// Error potentially returned by [`decode_block_response`].
 #[derive(Debug, derive_more::Display)]
 pub enum DecodeBlockResponseError {
+    /// Error while decompressing the response.
+    Decompression(DecompressionError),
     /// Error while decoding the protobuf encoding.
     ProtobufDecode(ProtobufDecodeError),
+    /// The response isn't a valid protobuf-encoded list of blocks.
+    ProtobufFraming,
     /// Hash length isn't of the correct length.
     InvalidHashLength,
+    /// Hash of the header of a block doesn't match the hash of the block.
+    HeaderHashMismatch,
     BodyDecodeError,
+    /// Error while decoding the list of justifications.
+    JustificationsDecodeError,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::schema;
+    use core::convert::TryFrom as _;
+    use prost::Message as _;
+
+    fn encode_response(block: schema::BlockData) -> Vec<u8> {
+        let response = schema::BlockResponse {
+            blocks: vec![block],
+        };
+        let mut buf = Vec::with_capacity(response.encoded_len());
+        response.encode(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn legacy_justification() {
+        let response = encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            justification: vec![1, 2, 3],
+            ..Default::default()
+        });
+
+        let blocks = super::decode_block_response(&response, None).unwrap();
+        assert_eq!(blocks[0].justification, Some(vec![1, 2, 3]));
+        assert_eq!(
+            blocks[0].justifications,
+            Some(vec![(*b"FRNK", vec![1, 2, 3])])
+        );
+    }
+
+    #[test]
+    fn multiple_justifications() {
+        let response = encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            // Two justifications: `BEEF` with `[5]` and `FRNK` with `[1, 2, 3]`.
+            justifications: vec![
+                8, b'B', b'E', b'E', b'F', 4, 5, b'F', b'R', b'N', b'K', 12, 1, 2, 3,
+            ],
+            ..Default::default()
+        });
+
+        let blocks = super::decode_block_response(&response, None).unwrap();
+        assert_eq!(blocks[0].justification, Some(vec![1, 2, 3]));
+        assert_eq!(
+            blocks[0].justifications,
+            Some(vec![(*b"BEEF", vec![5]), (*b"FRNK", vec![1, 2, 3])])
+        );
+    }
+
+    #[test]
+    fn invalid_justifications() {
+        let response = encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            justifications: vec![4, b'F', b'R'],
+            ..Default::default()
+        });
+
+        assert!(super::decode_block_response(&response, None).is_err());
+    }
+
+    #[test]
+    fn header_hash_mismatch() {
+        let response = encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            header: vec![1, 2, 3],
+            ..Default::default()
+        });
+
+        assert!(matches!(
+            super::decode_block_response(&response, None),
+            Err(super::DecodeBlockResponseError::HeaderHashMismatch)
+        ));
+    }
+
+    #[test]
+    fn header_hash_mismatch_stops_decoding() {
+        // The mismatching block is followed by bytes that aren't valid protobuf. The mismatch is
+        // reported, as the decoding stops before reaching them.
+        let mut response = encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            header: vec![1, 2, 3],
+            ..Default::default()
+        });
+        response.extend_from_slice(&[0x0a, 0xff, 0xff]);
+
+        assert!(matches!(
+            super::decode_block_response(&response, None),
+            Err(super::DecodeBlockResponseError::HeaderHashMismatch)
+        ));
+    }
+
+    #[test]
+    fn invalid_framing() {
+        let mut response = encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            ..Default::default()
+        });
+        response.extend_from_slice(&[0x0a, 0xff, 0xff]);
+
+        assert!(matches!(
+            super::decode_block_response(&response, None),
+            Err(super::DecodeBlockResponseError::ProtobufFraming)
+        ));
+    }
+
+    #[test]
+    fn unknown_fields_skipped() {
+        let block = encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            ..Default::default()
+        });
+
+        // Fields 2 to 5, with each wire type, around and between the blocks.
+        let mut response = vec![0x10, 0x80, 0x01, 0x19, 0, 0, 0, 0, 0, 0, 0, 0];
+        response.extend_from_slice(&block);
+        response.extend_from_slice(&[0x22, 2, 0, 0, 0x2d, 0, 0, 0, 0]);
+        response.extend_from_slice(&block);
+
+        let blocks = super::decode_block_response(&response, None).unwrap();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].hash, [0; 32]);
+    }
+
+    #[test]
+    fn header_hash_match() {
+        let header = vec![1, 2, 3];
+        let response = encode_response(schema::BlockData {
+            hash: crate::header::hash_from_scale_encoded_header(&header).to_vec(),
+            header: header.clone(),
+            ..Default::default()
+        });
+
+        let blocks = super::decode_block_response(&response, None).unwrap();
+        assert_eq!(blocks[0].header, Some(header));
+    }
+
+    /// Builds a zstandard-compressed response, as sent by remotes that support compression.
+    ///
+    /// The data is stored in raw (i.e. uncompressed) zstandard blocks, which is enough to test
+    /// the decompression.
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut out = crate::executor::host::zstd::ZSTD_PREFIX.to_vec();
+        // Magic number, then frame header descriptor indicating a single segment whose size is
+        // encoded on 8 bytes.
+        out.extend_from_slice(&[0x28, 0xb5, 0x2f, 0xfd, 0xe0]);
+        out.extend_from_slice(&u64::try_from(data.len()).unwrap().to_le_bytes());
+        let mut chunks = data.chunks(128 * 1024).peekable();
+        while let Some(chunk) = chunks.next() {
+            let is_last = u32::from(chunks.peek().is_none());
+            let header = is_last | (u32::try_from(chunk.len()).unwrap() << 3);
+            out.extend_from_slice(&header.to_le_bytes()[..3]);
+            out.extend_from_slice(chunk);
+        }
+        out
+    }
+
+    #[test]
+    fn compressed_response() {
+        let header = vec![1, 2, 3];
+        let response = compress(&encode_response(schema::BlockData {
+            hash: crate::header::hash_from_scale_encoded_header(&header).to_vec(),
+            header: header.clone(),
+            ..Default::default()
+        }));
+
+        let blocks = super::decode_block_response(&response, Some(1024)).unwrap();
+        assert_eq!(blocks[0].header, Some(header));
+    }
+
+    #[test]
+    fn compressed_response_not_requested() {
+        let response = compress(&encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            ..Default::default()
+        }));
+
+        // The response must not be decompressed if compression wasn't requested.
+        assert!(matches!(
+            super::decode_block_response(&response, None),
+            Err(super::DecodeBlockResponseError::ProtobufDecode(_))
+                | Err(super::DecodeBlockResponseError::ProtobufFraming)
+        ));
+    }
+
+    #[test]
+    fn compressed_response_size_limit() {
+        let response = compress(&encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            justification: vec![0; 300 * 1024],
+            ..Default::default()
+        }));
+
+        super::decode_block_response(&response, Some(1024 * 1024)).unwrap();
+        assert!(matches!(
+            super::decode_block_response(&response, Some(256 * 1024)),
+            Err(super::DecodeBlockResponseError::Decompression(_))
+        ));
+    }
+
+    #[test]
+    fn external_decompression() {
+        let encoded = encode_response(schema::BlockData {
+            hash: vec![0; 32],
+            ..Default::default()
+        });
+        let response = compress(&encoded);
+
+        // A decompressor that only returns what was compressed above.
+        fn decompressor(_: &[u8], _: usize) -> Option<Result<Vec<u8>, ()>> {
+            Some(Ok(encode_response(schema::BlockData {
+                hash: vec![0; 32],
+                ..Default::default()
+            })))
+        }
+        fn unavailable(_: &[u8], _: usize) -> Option<Result<Vec<u8>, ()>> {
+            None
+        }
+
+        let (decompressed, was_decompressed) =
+            super::super::decompress_response_with(response.clone(), 1024, decompressor).unwrap();
+        assert!(was_decompressed);
+        assert_eq!(decompressed, encoded);
+
+        let (passed_through, was_decompressed) =
+            super::super::decompress_response_with(response.clone(), 1024, unavailable).unwrap();
+        assert!(!was_decompressed);
+        assert_eq!(passed_through, response);
+
+        // The size limit is enforced even if the decompressor doesn't.
+        assert!(super::super::decompress_response_with(response, 1, decompressor).is_err());
+    }
+
+    #[test]
+    fn compression_field_only_sent_if_requested() {
+        let mut config = super::BlocksRequestConfig {
+            start: super::BlocksRequestConfigStart::Number(core::num::NonZeroU64::new(1).unwrap()),
+            desired_count: core::num::NonZeroU32::new(4).unwrap(),
+            direction: super::BlocksRequestDirection::Ascending,
+            fields: super::BlocksRequestFields {
+                header: true,
+                body: false,
+                justification: false,
+                indexed_body: false,
+            },
+            accept_compressed_response: false,
+        };
+
+        let encode = |config: super::BlocksRequestConfig| {
+            super::build_block_request(config).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            })
+        };
+
+        let without_compression = encode(config.clone());
+        config.accept_compressed_response = true;
+        let with_compression = encode(config);
+
+        // The non-standard field number 64 is appended at the end, and is completely absent
+        // from requests that don't ask for compression.
+        let mut expected = without_compression.clone();
+        expected.extend_from_slice(&[0x80, 0x04, 0x01]);
+        assert_eq!(with_compression, expected);
+        assert!(!without_compression.windows(2).any(|w| w == [0x80, 0x04]));
+    }
+
+    #[test]
+    fn response_size_limit() {
+        let mut config = super::BlocksRequestConfig {
+            start: super::BlocksRequestConfigStart::Hash([0; 32]),
+            desired_count: core::num::NonZeroU32::new(4).unwrap(),
+            direction: super::BlocksRequestDirection::Ascending,
+            fields: super::BlocksRequestFields {
+                header: true,
+                body: false,
+                justification: true,
+                indexed_body: false,
+            },
+            accept_compressed_response: false,
+        };
+
+        assert_eq!(
+            super::block_response_size_limit(&config),
+            Some(4 * super::MAX_BLOCK_SIZE_WITHOUT_BODY)
+        );
+
+        config.fields.body = true;
+        assert_eq!(super::block_response_size_limit(&config), None);
+    }
 }
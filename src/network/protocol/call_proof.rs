@@ -17,8 +17,8 @@
 
 use super::{schema, ProtobufDecodeError};
 
-use alloc::vec::Vec;
-use core::iter;
+use alloc::{string::String, vec::Vec};
+use core::{convert::TryFrom as _, iter};
 use prost::Message as _;
 
 /// Description of a call proof request that can be sent to a peer.
@@ -62,11 +62,87 @@ pub fn build_call_proof_request(
     iter::once(request_bytes)
 }
 
+/// Call proof request received from a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallProofRequest {
+    /// Hash of the block whose storage the call must be performed against.
+    pub block_hash: [u8; 32],
+    /// Name of the runtime function to call.
+    pub method: String,
+    /// Input to pass to the call.
+    pub parameter: Vec<u8>,
+}
+
+/// Decodes a call proof request received from a peer.
+// TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
+pub fn decode_call_proof_request(
+    request_bytes: &[u8],
+) -> Result<CallProofRequest, DecodeCallProofRequestError> {
+    let request = schema::Request::decode(request_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeCallProofRequestError::ProtobufDecode)?;
+
+    let request = match request.request {
+        Some(schema::request::Request::RemoteCallRequest(rq)) => rq,
+        _ => return Err(DecodeCallProofRequestError::BadRequestTy),
+    };
+
+    Ok(CallProofRequest {
+        block_hash: <[u8; 32]>::try_from(&request.block[..])
+            .map_err(|_| DecodeCallProofRequestError::BadBlockHash)?,
+        method: request.method,
+        parameter: request.data,
+    })
+}
+
+/// Error potentially returned by [`decode_call_proof_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeCallProofRequestError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+    /// Request isn't a call proof request.
+    BadRequestTy,
+    /// Block hash has an invalid length.
+    BadBlockHash,
+}
+
+/// Builds the bytes corresponding to a response to a call proof request.
+///
+/// The proof is a list of node values of the storage trie containing all the storage entries
+/// accessed during the call.
+pub fn build_call_proof_response(
+    proof: impl Iterator<Item = impl AsRef<[u8]>>,
+) -> impl Iterator<Item = impl AsRef<[u8]>> {
+    // Note: while the API of this function allows for a zero-cost implementation, the protobuf
+    // library doesn't permit to avoid allocations.
+
+    let response = schema::Response {
+        response: Some(schema::response::Response::RemoteCallResponse(
+            schema::RemoteCallResponse {
+                proof: super::encode_proof(proof),
+            },
+        )),
+    };
+
+    let response_bytes = {
+        let mut buf = Vec::with_capacity(response.encoded_len());
+        response.encode(&mut buf).unwrap();
+        buf
+    };
+
+    iter::once(response_bytes)
+}
+
 /// Decodes a response to a call proof request.
 // TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
 pub fn decode_call_proof_response(
     response_bytes: &[u8],
 ) -> Result<Vec<Vec<u8>>, DecodeCallProofResponseError> {
+    // The protobuf decoder copies the proof infallibly.
+    if !crate::util::can_allocate(response_bytes.len()) {
+        return Err(DecodeCallProofResponseError::AllocationFailed);
+    }
+
     let response = schema::Response::decode(&response_bytes[..])
         .map_err(ProtobufDecodeError)
         .map_err(DecodeCallProofResponseError::ProtobufDecode)?;
@@ -80,19 +156,24 @@ pub fn decode_call_proof_response(
     // Each inner `Vec<u8>` is a node value in the storage trie.
     let (_, decoded) = nom::combinator::all_consuming(nom::combinator::flat_map(
         crate::util::nom_scale_compact_usize,
-        |num_elems| {
-            nom::multi::many_m_n(
-                num_elems,
-                num_elems,
-                nom::combinator::map(crate::util::nom_bytes_decode, |b| b.to_vec()),
-            )
-        },
+        |num_elems| crate::util::nom_many_exact(num_elems, crate::util::nom_bytes_decode),
     ))(&proof)
     .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| {
         DecodeCallProofResponseError::ProofDecodeError
     })?;
 
-    Ok(decoded)
+    // The size of the proof is controlled by the remote. The node values are copied with
+    // fallible allocations.
+    let mut out = Vec::new();
+    out.try_reserve_exact(decoded.len())
+        .map_err(|_| DecodeCallProofResponseError::AllocationFailed)?;
+    for node_value in decoded {
+        out.push(
+            crate::util::try_to_vec(node_value)
+                .ok_or(DecodeCallProofResponseError::AllocationFailed)?,
+        );
+    }
+    Ok(out)
 }
 
 /// Error potentially returned by [`decode_call_proof_response`].
@@ -104,4 +185,6 @@ pub enum DecodeCallProofResponseError {
     BadResponseTy,
     /// Failed to decode response as a call proof.
     ProofDecodeError,
+    /// Not enough memory to hold the proof.
+    AllocationFailed,
 }
@@ -310,10 +310,10 @@ fn catch_up(bytes: &[u8]) -> nom::IResult<&[u8], CatchUpRef> {
                 nom::number::complete::le_u64,
                 nom::number::complete::le_u64,
                 nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
-                    nom::multi::many_m_n(num_elems, num_elems, prevote)
+                    crate::util::nom_many_exact(num_elems, prevote)
                 }),
                 nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
-                    nom::multi::many_m_n(num_elems, num_elems, |s| {
+                    crate::util::nom_many_exact(num_elems, |s| {
                         crate::finality::justification::decode::PrecommitRef::decode_partial(s)
                             .map(|(a, b)| (b, a))
                             .map_err(|_| {
@@ -38,6 +38,7 @@
 use crate::{finality, header};
 
 use alloc::vec::Vec;
+use core::{fmt, iter};
 
 // TODO: all the constraints explained here should be checked when decoding the message
 
@@ -68,57 +69,293 @@ pub struct GrandpaWarpSyncResponseFragment {
     pub justification: finality::justification::decode::GrandpaJustification,
 }
 
-/// Error potentially returned by [`decode_grandpa_warp_sync_response`].
+/// Fragment of a GrandPa warp sync response, referencing the undecoded response.
+#[derive(Debug)]
+pub struct GrandpaWarpSyncResponseFragmentRef<'a> {
+    /// Header of a block in the chain.
+    pub header: header::HeaderRef<'a>,
+
+    /// Justification that proofs the finality of
+    /// [`GrandpaWarpSyncResponseFragmentRef::header`].
+    pub justification: finality::justification::decode::GrandpaJustificationRef<'a>,
+}
+
+impl<'a> From<GrandpaWarpSyncResponseFragmentRef<'a>> for GrandpaWarpSyncResponseFragment {
+    fn from(fragment: GrandpaWarpSyncResponseFragmentRef<'a>) -> Self {
+        GrandpaWarpSyncResponseFragment {
+            header: fragment.header.into(),
+            justification: fragment.justification.into(),
+        }
+    }
+}
+
+/// SCALE-encoded GrandPa warp sync response whose structure has been verified, but whose
+/// fragments haven't been decoded yet.
+///
+/// Contrary to [`GrandpaWarpSyncResponse`], the fragments are decoded one at a time when
+/// iterating over them. This avoids holding in memory both the encoded response and all the
+/// decoded fragments at the same time, which matters as warp sync responses can weigh several
+/// megabytes. The size of the encoded response itself is bounded by
+/// [`super::MAX_GRANDPA_WARP_SYNC_RESPONSE_SIZE`] when it is received from the network.
+#[derive(Clone)]
+pub struct EncodedGrandpaWarpSyncResponse {
+    /// SCALE-encoded response. Guaranteed to be valid.
+    encoded: Vec<u8>,
+    /// Offset within [`EncodedGrandpaWarpSyncResponse::encoded`] of the first fragment.
+    fragments_offset: usize,
+    /// Number of fragments in the response.
+    num_fragments: usize,
+    /// See [`GrandpaWarpSyncResponse::is_finished`].
+    is_finished: bool,
+}
+
+impl EncodedGrandpaWarpSyncResponse {
+    /// Checks the validity of the given SCALE-encoded GrandPa warp sync response.
+    ///
+    /// No heap allocation is performed apart from the ones already done by the caller.
+    pub fn new(encoded: Vec<u8>) -> Result<Self, DecodeGrandpaWarpSyncResponseError> {
+        let (fragments_offset, num_fragments, is_finished) = verify_structure(&encoded)?;
+        Ok(EncodedGrandpaWarpSyncResponse {
+            encoded,
+            fragments_offset,
+            num_fragments,
+            is_finished,
+        })
+    }
+
+    /// Returns the number of fragments in the response.
+    pub fn num_fragments(&self) -> usize {
+        self.num_fragments
+    }
+
+    /// See [`GrandpaWarpSyncResponse::is_finished`].
+    pub fn is_finished(&self) -> bool {
+        self.is_finished
+    }
+
+    /// Returns an iterator to the fragments of the response, decoded on the fly.
+    pub fn fragments(
+        &self,
+    ) -> impl ExactSizeIterator<Item = GrandpaWarpSyncResponseFragmentRef> + '_ {
+        // The response has been verified in `new`, so decoding can't fail.
+        fragments_unchecked(&self.encoded[self.fragments_offset..], self.num_fragments)
+    }
+
+    /// Turns this response into an iterator that yields the fragments one by one.
+    ///
+    /// Only the fragment being yielded is decoded in memory.
+    pub fn into_fragments(self) -> GrandpaWarpSyncResponseFragmentsIntoIter {
+        GrandpaWarpSyncResponseFragmentsIntoIter {
+            offset: self.fragments_offset,
+            remaining: self.num_fragments,
+            encoded: self.encoded,
+        }
+    }
+
+    /// Decodes all the fragments of the response at once.
+    pub fn decode(&self) -> GrandpaWarpSyncResponse {
+        GrandpaWarpSyncResponse {
+            fragments: self.fragments().map(Into::into).collect(),
+            is_finished: self.is_finished,
+        }
+    }
+}
+
+impl fmt::Debug for EncodedGrandpaWarpSyncResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("EncodedGrandpaWarpSyncResponse")
+            .field("num_fragments", &self.num_fragments)
+            .field("is_finished", &self.is_finished)
+            .finish()
+    }
+}
+
+/// Iterator returned by [`EncodedGrandpaWarpSyncResponse::into_fragments`].
+pub struct GrandpaWarpSyncResponseFragmentsIntoIter {
+    /// SCALE-encoded response. Guaranteed to be valid.
+    encoded: Vec<u8>,
+    /// Offset within [`GrandpaWarpSyncResponseFragmentsIntoIter::encoded`] of the next fragment
+    /// to yield.
+    offset: usize,
+    /// Number of fragments remaining to be yielded.
+    remaining: usize,
+}
+
+impl Iterator for GrandpaWarpSyncResponseFragmentsIntoIter {
+    type Item = GrandpaWarpSyncResponseFragment;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+
+        let encoded_fragment = &self.encoded[self.offset..];
+        // The response has been verified when building the `EncodedGrandpaWarpSyncResponse`,
+        // so decoding can't fail.
+        let (fragment, after_fragment) = decode_fragment_partial(encoded_fragment).unwrap();
+        self.offset += encoded_fragment.len() - after_fragment.len();
+        self.remaining -= 1;
+        Some(fragment.into())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for GrandpaWarpSyncResponseFragmentsIntoIter {}
+
+impl iter::FusedIterator for GrandpaWarpSyncResponseFragmentsIntoIter {}
+
+impl fmt::Debug for GrandpaWarpSyncResponseFragmentsIntoIter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GrandpaWarpSyncResponseFragmentsIntoIter")
+            .field("remaining", &self.remaining)
+            .finish()
+    }
+}
+
+/// Error potentially returned by [`decode_grandpa_warp_sync_response`] or
+/// [`EncodedGrandpaWarpSyncResponse::new`].
 #[derive(Debug, derive_more::Display)]
 pub struct DecodeGrandpaWarpSyncResponseError;
 
 /// Decodes a SCALE-encoded GrandPa warp sync response.
-// TODO: make this a zero-cost API
+///
+/// All the fragments are decoded at once. See also [`EncodedGrandpaWarpSyncResponse`].
 pub fn decode_grandpa_warp_sync_response(
     encoded: &[u8],
 ) -> Result<GrandpaWarpSyncResponse, DecodeGrandpaWarpSyncResponseError> {
-    nom::combinator::all_consuming(nom::combinator::map(
-        nom::sequence::tuple((decode_fragments, nom::number::complete::le_u8)),
-        |(fragments, is_finished)| GrandpaWarpSyncResponse {
-            fragments,
-            is_finished: is_finished != 0,
+    let (fragments_offset, num_fragments, is_finished) = verify_structure(encoded)?;
+    Ok(GrandpaWarpSyncResponse {
+        fragments: fragments_unchecked(&encoded[fragments_offset..], num_fragments)
+            .map(Into::into)
+            .collect(),
+        is_finished,
+    })
+}
+
+/// Checks the validity of the given SCALE-encoded response. Returns the offset of the first
+/// fragment, the number of fragments, and the value of the `is_finished` field.
+fn verify_structure(
+    encoded: &[u8],
+) -> Result<(usize, usize, bool), DecodeGrandpaWarpSyncResponseError> {
+    let (after_len, num_fragments) =
+        crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(encoded)
+            .map_err(|_| DecodeGrandpaWarpSyncResponseError)?;
+    let fragments_offset = encoded.len() - after_len.len();
+
+    let mut remainder = after_len;
+    for _ in 0..num_fragments {
+        let (_, after_fragment) = decode_fragment_partial(remainder)?;
+        remainder = after_fragment;
+    }
+
+    match remainder {
+        [is_finished] => Ok((fragments_offset, num_fragments, *is_finished != 0)),
+        _ => Err(DecodeGrandpaWarpSyncResponseError),
+    }
+}
+
+/// Decodes `num_fragments` fragments at the start of `encoded`.
+///
+/// # Panic
+///
+/// Panics if the fragments are invalid. [`verify_structure`] must have been called beforehand.
+///
+fn fragments_unchecked(
+    mut encoded: &[u8],
+    num_fragments: usize,
+) -> impl ExactSizeIterator<Item = GrandpaWarpSyncResponseFragmentRef> {
+    (0..num_fragments).map(move |_| {
+        let (fragment, after_fragment) = decode_fragment_partial(encoded).unwrap();
+        encoded = after_fragment;
+        fragment
+    })
+}
+
+/// Decodes a single fragment at the start of `bytes`, and returns the remainder.
+fn decode_fragment_partial(
+    bytes: &[u8],
+) -> Result<(GrandpaWarpSyncResponseFragmentRef, &[u8]), DecodeGrandpaWarpSyncResponseError> {
+    let (header, after_header) =
+        header::decode_partial(bytes).map_err(|_| DecodeGrandpaWarpSyncResponseError)?;
+    let (justification, after_justification) =
+        finality::justification::decode::decode_partial_grandpa(after_header)
+            .map_err(|_| DecodeGrandpaWarpSyncResponseError)?;
+    Ok((
+        GrandpaWarpSyncResponseFragmentRef {
+            header,
+            justification,
         },
-    ))(encoded)
-    .map(|(_, parse_result)| parse_result)
-    .map_err(|_| DecodeGrandpaWarpSyncResponseError)
+        after_justification,
+    ))
 }
 
-fn decode_fragments(bytes: &[u8]) -> nom::IResult<&[u8], Vec<GrandpaWarpSyncResponseFragment>> {
-    nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
-        nom::multi::many_m_n(
-            num_elems,
-            num_elems,
-            nom::combinator::map(
-                nom::sequence::tuple((
-                    |s| {
-                        header::decode_partial(s).map(|(a, b)| (b, a)).map_err(|_| {
-                            nom::Err::Failure(nom::error::make_error(
-                                s,
-                                nom::error::ErrorKind::Verify,
-                            ))
-                        })
-                    },
-                    |s| {
-                        finality::justification::decode::decode_partial_grandpa(s)
-                            .map(|(a, b)| (b, a))
-                            .map_err(|_| {
-                                nom::Err::Failure(nom::error::make_error(
-                                    s,
-                                    nom::error::ErrorKind::Verify,
-                                ))
-                            })
-                    },
-                )),
-                move |(header, justification)| GrandpaWarpSyncResponseFragment {
-                    header: header.into(),
-                    justification: justification.into(),
-                },
-            ),
-        )
-    })(bytes)
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    fn encoded_response(num_fragments: u8, is_finished: bool) -> Vec<u8> {
+        let header = include_bytes!("../../header/tests-header-polkadot-512271");
+        let header_hash = crate::header::hash_from_scale_encoded_header(&header[..]);
+
+        let mut out = alloc::vec![num_fragments << 2];
+        for _ in 0..num_fragments {
+            out.extend_from_slice(&header[..]);
+            // Justification with no precommit and no votes ancestry.
+            out.extend_from_slice(&5u64.to_le_bytes());
+            out.extend_from_slice(&header_hash);
+            out.extend_from_slice(&512271u32.to_le_bytes());
+            out.extend_from_slice(&[0, 0]);
+        }
+        out.push(if is_finished { 1 } else { 0 });
+        out
+    }
+
+    #[test]
+    fn lazy_and_eager_decoding_match() {
+        let encoded = encoded_response(3, true);
+        let eager = super::decode_grandpa_warp_sync_response(&encoded).unwrap();
+        let lazy = super::EncodedGrandpaWarpSyncResponse::new(encoded).unwrap();
+
+        assert!(eager.is_finished);
+        assert!(lazy.is_finished());
+        assert_eq!(eager.fragments.len(), 3);
+        assert_eq!(lazy.num_fragments(), 3);
+        assert_eq!(lazy.fragments().len(), 3);
+
+        for (eager, lazy) in eager.fragments.iter().zip(lazy.into_fragments()) {
+            assert_eq!(eager.header.hash(), lazy.header.hash());
+            assert_eq!(
+                eager.justification.target_hash,
+                lazy.justification.target_hash
+            );
+        }
+    }
+
+    #[test]
+    fn empty_response() {
+        let response = super::EncodedGrandpaWarpSyncResponse::new(alloc::vec![0, 0]).unwrap();
+        assert!(!response.is_finished());
+        assert_eq!(response.into_fragments().count(), 0);
+    }
+
+    #[test]
+    fn invalid_responses() {
+        // Trailing data.
+        let mut encoded = encoded_response(1, false);
+        encoded.push(0);
+        assert!(super::EncodedGrandpaWarpSyncResponse::new(encoded).is_err());
+
+        // Truncated.
+        let mut encoded = encoded_response(2, false);
+        encoded.truncate(encoded.len() - 10);
+        assert!(super::EncodedGrandpaWarpSyncResponse::new(encoded).is_err());
+
+        // Missing `is_finished` field.
+        let mut encoded = encoded_response(1, false);
+        encoded.pop();
+        assert!(super::EncodedGrandpaWarpSyncResponse::new(encoded).is_err());
+    }
 }
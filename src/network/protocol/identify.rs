@@ -28,13 +28,17 @@
 //! [`IdentifyResponse::observed_addr`]. They are necessary in order for nodes to discover their
 //! public address, and in order to insert peers in the Kademlia k-buckets.
 //!
+//! Responses sent by remotes can be decoded with [`decode_identify_response`]. The list of
+//! protocols they contain is notably useful in order to avoid sending requests that the remote
+//! doesn't support.
+//!
 //! See also [the official specifications](https://github.com/libp2p/specs/tree/69e57d59dc5d59d3979d79842b577ec2c483f7fa/identify).
 
-use super::schema;
+use super::{schema, ProtobufDecodeError};
 use crate::libp2p::{peer_id::PublicKey, Multiaddr};
 
-use alloc::{borrow::ToOwned as _, vec::Vec};
-use core::iter;
+use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+use core::{convert::TryFrom as _, iter};
 use prost::Message as _;
 
 /// Description of a response to an identify request.
@@ -81,3 +85,87 @@ pub fn build_identify_response<'a>(
 
     iter::once(request_bytes)
 }
+
+/// Decoded response to an identify request.
+///
+/// Contrary to [`IdentifyResponse`], which is used to build responses, all the fields are owned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedIdentifyResponse {
+    /// Version of the protocol family (e.g. `/substrate/1.0`) used by the remote.
+    pub protocol_version: Option<String>,
+    /// Name and version of the software of the remote, similar to a user agent.
+    pub agent_version: Option<String>,
+    /// List of addresses the remote reports listening on.
+    ///
+    /// Addresses that couldn't be parsed, for example because they use a transport that isn't
+    /// known to this implementation, are silently ignored.
+    pub listen_addrs: Vec<Multiaddr>,
+    /// Address of the local node, as seen from the remote. `None` if the remote didn't provide
+    /// it or if it couldn't be parsed.
+    pub observed_addr: Option<Multiaddr>,
+    /// Names of the protocols supported by the remote.
+    pub protocols: Vec<String>,
+}
+
+/// Decodes a response to an identify request.
+pub fn decode_identify_response(
+    response_bytes: &[u8],
+) -> Result<DecodedIdentifyResponse, DecodeIdentifyResponseError> {
+    let response = schema::Identify::decode(response_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeIdentifyResponseError::ProtobufDecode)?;
+
+    Ok(DecodedIdentifyResponse {
+        protocol_version: response.protocol_version,
+        agent_version: response.agent_version,
+        // Nodes are free to report addresses using transports that this implementation doesn't
+        // know about. Refusing the entire response because of them would be too strict.
+        listen_addrs: response
+            .listen_addrs
+            .into_iter()
+            .filter_map(|addr| Multiaddr::try_from(addr).ok())
+            .collect(),
+        observed_addr: response
+            .observed_addr
+            .and_then(|addr| Multiaddr::try_from(addr).ok()),
+        protocols: response.protocols,
+    })
+}
+
+/// Error potentially returned by [`decode_identify_response`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeIdentifyResponseError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::libp2p::Multiaddr;
+
+    #[test]
+    fn build_then_decode() {
+        let listen_addr = "/ip4/1.2.3.4/tcp/30333".parse::<Multiaddr>().unwrap();
+        let observed_addr = "/ip4/5.6.7.8/tcp/1234".parse::<Multiaddr>().unwrap();
+
+        let response = super::build_identify_response(super::IdentifyResponse {
+            protocol_version: "/substrate/1.0",
+            agent_version: "smoldot",
+            ed25519_public_key: &[0; 32],
+            listen_addrs: core::iter::once(&listen_addr),
+            observed_addr: &observed_addr,
+            protocols: ["/ipfs/id/1.0.0", "/dot/sync/2"].iter().copied(),
+        })
+        .fold(Vec::new(), |mut a, b| {
+            a.extend_from_slice(b.as_ref());
+            a
+        });
+
+        let decoded = super::decode_identify_response(&response).unwrap();
+        assert_eq!(decoded.protocol_version.as_deref(), Some("/substrate/1.0"));
+        assert_eq!(decoded.agent_version.as_deref(), Some("smoldot"));
+        assert_eq!(decoded.listen_addrs, vec![listen_addr]);
+        assert_eq!(decoded.observed_addr, Some(observed_addr));
+        assert_eq!(decoded.protocols, vec!["/ipfs/id/1.0.0", "/dot/sync/2"]);
+    }
+}
@@ -15,10 +15,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{schema, ProtobufDecodeError};
+use super::{schema, DecompressionError, ProtobufDecodeError};
 
 use alloc::vec::Vec;
-use core::iter;
+use core::{convert::TryFrom as _, iter};
 use prost::Message as _;
 
 /// Description of a storage proof request that can be sent to a peer.
@@ -28,6 +28,12 @@ pub struct StorageProofRequestConfig<TKeysIter> {
     pub block_hash: [u8; 32],
     /// List of storage keys to query.
     pub keys: TKeysIter,
+    /// If true, indicates to the remote that the response can be zstandard-compressed.
+    ///
+    /// This relies on a non-standard field of the request, which is only present on the wire if
+    /// this flag is `true`. Remotes that don't support compression ignore this field. Compressed
+    /// responses must be decoded by passing `Some` to [`decode_storage_proof_response`].
+    pub accept_compressed_response: bool,
 }
 
 /// Builds the bytes corresponding to a storage proof request.
@@ -42,6 +48,7 @@ pub fn build_storage_proof_request(
             schema::RemoteReadRequest {
                 block: config.block_hash.to_vec(),
                 keys: config.keys.map(|k| k.as_ref().to_vec()).collect(),
+                support_compressed_response: config.accept_compressed_response,
             },
         )),
     };
@@ -55,11 +62,97 @@ pub fn build_storage_proof_request(
     iter::once(request_bytes)
 }
 
+/// Storage proof request received from a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageProofRequest {
+    /// Hash of the block whose storage is requested.
+    pub block_hash: [u8; 32],
+    /// List of storage keys to query.
+    pub keys: Vec<Vec<u8>>,
+    /// If true, the remote accepts a zstandard-compressed response.
+    pub accept_compressed_response: bool,
+}
+
+/// Decodes a storage proof request received from a peer.
+// TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
+pub fn decode_storage_proof_request(
+    request_bytes: &[u8],
+) -> Result<StorageProofRequest, DecodeStorageProofRequestError> {
+    let request = schema::Request::decode(request_bytes)
+        .map_err(ProtobufDecodeError)
+        .map_err(DecodeStorageProofRequestError::ProtobufDecode)?;
+
+    let request = match request.request {
+        Some(schema::request::Request::RemoteReadRequest(rq)) => rq,
+        _ => return Err(DecodeStorageProofRequestError::BadRequestTy),
+    };
+
+    Ok(StorageProofRequest {
+        block_hash: <[u8; 32]>::try_from(&request.block[..])
+            .map_err(|_| DecodeStorageProofRequestError::BadBlockHash)?,
+        keys: request.keys,
+        accept_compressed_response: request.support_compressed_response,
+    })
+}
+
+/// Error potentially returned by [`decode_storage_proof_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum DecodeStorageProofRequestError {
+    /// Error while decoding the protobuf encoding.
+    ProtobufDecode(ProtobufDecodeError),
+    /// Request isn't a storage proof request.
+    BadRequestTy,
+    /// Block hash has an invalid length.
+    BadBlockHash,
+}
+
+/// Builds the bytes corresponding to a response to a storage proof request.
+///
+/// The proof is a list of node values of the storage trie, as generated by
+/// [`crate::trie::proof_encode::build_proof`]. The response is never compressed.
+pub fn build_storage_proof_response(
+    proof: impl Iterator<Item = impl AsRef<[u8]>>,
+) -> impl Iterator<Item = impl AsRef<[u8]>> {
+    // Note: while the API of this function allows for a zero-cost implementation, the protobuf
+    // library doesn't permit to avoid allocations.
+
+    let response = schema::Response {
+        response: Some(schema::response::Response::RemoteReadResponse(
+            schema::RemoteReadResponse {
+                proof: super::encode_proof(proof),
+            },
+        )),
+    };
+
+    let response_bytes = {
+        let mut buf = Vec::with_capacity(response.encoded_len());
+        response.encode(&mut buf).unwrap();
+        buf
+    };
+
+    iter::once(response_bytes)
+}
+
 /// Decodes a response to a storage proof request.
+///
+/// `max_decompressed_size` must be `Some` if and only if
+/// [`StorageProofRequestConfig::accept_compressed_response`] was `true` in the request, in which
+/// case a compressed response is decompressed, as long as its decompressed size doesn't exceed
+/// this value.
 // TODO: should have a more zero-cost API, but we're limited by the protobuf library for that
 pub fn decode_storage_proof_response(
     response_bytes: &[u8],
+    max_decompressed_size: Option<usize>,
 ) -> Result<Vec<Vec<u8>>, DecodeStorageProofResponseError> {
+    let response_bytes =
+        super::decompress_response_if_necessary(response_bytes, max_decompressed_size)
+            .map_err(DecodeStorageProofResponseError::Decompression)?;
+
+    // The protobuf decoder copies the proof infallibly.
+    if !crate::util::can_allocate(response_bytes.len()) {
+        return Err(DecodeStorageProofResponseError::AllocationFailed);
+    }
+
     let response = schema::Response::decode(&response_bytes[..])
         .map_err(ProtobufDecodeError)
         .map_err(DecodeStorageProofResponseError::ProtobufDecode)?;
@@ -73,28 +166,37 @@ pub fn decode_storage_proof_response(
     // Each inner `Vec<u8>` is a node value in the storage trie.
     let (_, decoded) = nom::combinator::all_consuming(nom::combinator::flat_map(
         crate::util::nom_scale_compact_usize,
-        |num_elems| {
-            nom::multi::many_m_n(
-                num_elems,
-                num_elems,
-                nom::combinator::map(crate::util::nom_bytes_decode, |b| b.to_vec()),
-            )
-        },
+        |num_elems| crate::util::nom_many_exact(num_elems, crate::util::nom_bytes_decode),
     ))(&proof)
     .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| {
         DecodeStorageProofResponseError::ProofDecodeError
     })?;
 
-    Ok(decoded)
+    // The size of the proof is controlled by the remote. The node values are copied with
+    // fallible allocations.
+    let mut out = Vec::new();
+    out.try_reserve_exact(decoded.len())
+        .map_err(|_| DecodeStorageProofResponseError::AllocationFailed)?;
+    for node_value in decoded {
+        out.push(
+            crate::util::try_to_vec(node_value)
+                .ok_or(DecodeStorageProofResponseError::AllocationFailed)?,
+        );
+    }
+    Ok(out)
 }
 
 /// Error potentially returned by [`decode_storage_proof_response`].
 #[derive(Debug, derive_more::Display)]
 pub enum DecodeStorageProofResponseError {
+    /// Error while decompressing the response.
+    Decompression(DecompressionError),
     /// Error while decoding the protobuf encoding.
     ProtobufDecode(ProtobufDecodeError),
     /// Response isn't a response to a storage proof request.
     BadResponseTy,
     /// Failed to decode response as a storage proof.
     ProofDecodeError,
+    /// Not enough memory to hold the proof.
+    AllocationFailed,
 }
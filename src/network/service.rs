@@ -22,6 +22,7 @@ use crate::network::protocol;
 use crate::util;
 
 use alloc::{
+    collections::BTreeMap,
     format,
     string::{String, ToString as _},
     vec::Vec,
@@ -78,6 +79,52 @@ pub struct Config<TPeer> {
     /// This value is important if [`ChainNetwork::next_event`] is called at a slower than the
     /// calls to [`ChainNetwork::read_write`] generate events.
     pub pending_api_events_buffer_size: NonZeroUsize,
+
+    /// Maximum number of bytes of partially-received messages buffered for each connection.
+    ///
+    /// When this limit is reached on a connection, new requests to the corresponding peer are
+    /// delayed until some of the buffered messages have finished being received. This prevents
+    /// a single peer from using an unbounded amount of memory.
+    pub max_connection_receive_buffer_size: usize,
+
+    /// Additional request-response protocols, on top of the ones used by the chains. Requests
+    /// can be sent on these protocols with [`ChainNetwork::raw_request`].
+    ///
+    /// Incoming requests on these protocols are refused.
+    ///
+    /// This is typically an empty list. It exists for the purpose of experimenting with custom
+    /// protocols.
+    pub extra_request_response_protocols: Vec<ExtraRequestResponseProtocol>,
+
+    /// If `true`, nodes whose addresses go through a relay (i.e. contain `/p2p-circuit`) can be
+    /// connected to. The connection with the relay is opened first, and the connection with the
+    /// target is then established with [`ChainNetwork::open_relay_circuit`].
+    pub allow_relayed_connections: bool,
+
+    /// If `Some`, used to decompress the responses to requests that indicate support for
+    /// compressed responses, instead of the implementation of the [`protocol`] module. This
+    /// makes it possible to use a faster implementation provided by the environment.
+    pub response_decompressor: Option<protocol::ResponseDecompressor>,
+}
+
+/// Configuration for a request-response protocol that isn't used by the [`ChainNetwork`] itself.
+///
+/// See [`Config::extra_request_response_protocols`].
+#[derive(Debug, Clone)]
+pub struct ExtraRequestResponseProtocol {
+    /// Name of the protocol negotiated on the wire.
+    pub name: String,
+
+    /// Maximum size, in bytes, of a request. [`ChainNetwork::raw_request`] returns an error if
+    /// the request is larger.
+    pub max_request_size: usize,
+
+    /// Maximum size, in bytes, of a response. Larger responses lead to an error.
+    pub max_response_size: usize,
+
+    /// Maximum duration of a request, between the moment the substream is opened and the moment
+    /// the response has been received.
+    pub timeout: Duration,
 }
 
 /// Configuration for a specific overlay network.
@@ -108,6 +155,11 @@ pub struct ChainConfig {
     /// Hash of the genesis block (i.e. block number 0) according to the local node.
     pub genesis_hash: [u8; 32],
     pub role: protocol::Role,
+
+    /// If `true`, storage proof and call proof requests sent by light clients are accepted and
+    /// reported with [`Event::StorageProofRequestIn`] and [`Event::CallProofRequestIn`].
+    /// Otherwise, these requests are refused.
+    pub allow_inbound_light_requests: bool,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -129,6 +181,13 @@ pub struct PendingId(libp2p::PendingId);
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ConnectionId(libp2p::ConnectionId);
 
+/// Identifier of a request received from a remote and waiting for a response.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InRequestId(
+    libp2p::ConnectionId,
+    libp2p::connection::established::SubstreamId,
+);
+
 /// Data structure containing the list of all connections, pending or not, and their latest known
 /// state. See also [the module-level documentation](..).
 pub struct ChainNetwork<TNow, TPeer, TConn> {
@@ -144,11 +203,20 @@ pub struct ChainNetwork<TNow, TPeer, TConn> {
 
     pending_in_accept: Mutex<Option<(libp2p::ConnectionId, usize, Vec<u8>)>>,
 
+    /// For each connected peer that has answered a request sent with
+    /// [`ChainNetwork::identify_request`], list of protocols it reports supporting.
+    ///
+    /// Peers that aren't in this list are assumed to support all protocols.
+    peers_protocols: Mutex<BTreeMap<PeerId, Vec<String>>>,
+
     substreams_open_tx: Mutex<mpsc::Sender<()>>,
     substreams_open_rx: Mutex<mpsc::Receiver<()>>,
 
     /// Generator for randomness.
     randomness: Mutex<rand_chacha::ChaCha20Rng>,
+
+    /// See [`Config::response_decompressor`].
+    response_decompressor: Option<protocol::ResponseDecompressor>,
 }
 
 // Update this when a new request response protocol is added.
@@ -229,8 +297,7 @@ where
                     max_size: 1024 * 512,
                 },
                 max_response_size: 10 * 1024 * 1024,
-                // TODO: make this configurable
-                inbound_allowed: false,
+                inbound_allowed: chain.allow_inbound_light_requests,
                 timeout: Duration::from_secs(20),
             }))
             .chain(iter::once(libp2p::ConfigRequestResponse {
@@ -244,12 +311,26 @@ where
             .chain(iter::once(libp2p::ConfigRequestResponse {
                 name: format!("/{}/sync/warp", chain.protocol_id),
                 inbound_config: libp2p::ConfigRequestResponseIn::Payload { max_size: 32 },
-                max_response_size: 128 * 1024 * 1024, // TODO: this is way too large at the moment ; see https://github.com/paritytech/substrate/pull/8578
+                max_response_size: protocol::MAX_GRANDPA_WARP_SYNC_RESPONSE_SIZE,
                 // We don't support inbound warp sync requests (yet).
                 inbound_allowed: false,
                 timeout: Duration::from_secs(20),
             }))
         }))
+        .chain(
+            config
+                .extra_request_response_protocols
+                .into_iter()
+                .map(|protocol| libp2p::ConfigRequestResponse {
+                    name: protocol.name,
+                    inbound_config: libp2p::ConfigRequestResponseIn::Payload {
+                        max_size: protocol.max_request_size,
+                    },
+                    max_response_size: protocol.max_response_size,
+                    inbound_allowed: false,
+                    timeout: protocol.timeout,
+                }),
+        )
         .collect();
 
         let (substreams_open_tx, substreams_open_rx) = mpsc::channel(0);
@@ -271,15 +352,19 @@ where
                 noise_key: config.noise_key,
                 randomness_seed: inner_randomness_seed,
                 pending_api_events_buffer_size: config.pending_api_events_buffer_size,
+                max_connection_receive_buffer_size: config.max_connection_receive_buffer_size,
                 overlay_networks,
                 ping_protocol: "/ipfs/ping/1.0.0".into(),
+                allow_relayed_connections: config.allow_relayed_connections,
             }),
             chain_configs: config.chains,
             chain_grandpa_config,
             pending_in_accept: Mutex::new(None),
+            peers_protocols: Mutex::new(BTreeMap::new()),
             substreams_open_tx: Mutex::new(substreams_open_tx),
             substreams_open_rx: Mutex::new(substreams_open_rx),
             randomness: Mutex::new(randomness),
+            response_decompressor: config.response_decompressor,
         }
     }
 
@@ -379,48 +464,69 @@ where
         chain_index: usize,
         config: protocol::BlocksRequestConfig,
     ) -> Result<Vec<protocol::BlockData>, BlocksRequestError> {
+        // Responses that don't contain any block body are refused early if they are larger than
+        // what the requested fields can plausibly take.
+        let max_response_size = protocol::block_response_size_limit(&config);
+        let max_decompressed_size = if config.accept_compressed_response {
+            Some(max_response_size.unwrap_or(protocol::MAX_DECOMPRESSED_RESPONSE_SIZE))
+        } else {
+            None
+        };
+
         let request_data = protocol::build_block_request(config).fold(Vec::new(), |mut a, b| {
             a.extend_from_slice(b.as_ref());
             a
         });
         let response = self
-            .libp2p
             .request(
                 now,
                 target,
                 self.protocol_index(chain_index, 0),
                 request_data,
+                max_response_size,
             )
             .map_err(BlocksRequestError::Request)
             .await?;
-        protocol::decode_block_response(&response).map_err(BlocksRequestError::Decode)
+        let (response, max_decompressed_size) = self
+            .decompress_response(response, max_decompressed_size)
+            .map_err(|err| {
+                BlocksRequestError::Decode(protocol::DecodeBlockResponseError::Decompression(err))
+            })?;
+        protocol::decode_block_response(&response, max_decompressed_size)
+            .map_err(BlocksRequestError::Decode)
     }
 
+    #[cfg(feature = "warp-sync")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
     pub async fn grandpa_warp_sync_request(
         &self,
         now: TNow,
         target: peer_id::PeerId,
         chain_index: usize,
         begin_hash: [u8; 32],
-    ) -> Result<protocol::GrandpaWarpSyncResponse, GrandpaWarpSyncRequestError> {
+    ) -> Result<protocol::EncodedGrandpaWarpSyncResponse, GrandpaWarpSyncRequestError> {
         let request_data = begin_hash.to_vec();
 
         let response = self
-            .libp2p
             .request(
                 now,
                 target,
                 self.protocol_index(chain_index, 3),
                 request_data,
+                None,
             )
-            .map_err(GrandpaWarpSyncRequestError::Request)
+            .map_err(GrandpaWarpSyncRequestError::from_request_error)
             .await?;
 
-        protocol::decode_grandpa_warp_sync_response(&response)
+        protocol::EncodedGrandpaWarpSyncResponse::new(response)
             .map_err(GrandpaWarpSyncRequestError::Decode)
     }
 
     /// Sends a storage request to the given peer.
+    ///
+    /// If `max_response_size` is `Some`, responses larger than this size in bytes are refused
+    /// and [`StorageProofRequestError::ProofTooLarge`] is returned. The limit of the protocol
+    /// applies if it is lower.
     // TODO: more docs
     pub async fn storage_proof_request(
         &self,
@@ -428,23 +534,69 @@ where
         target: peer_id::PeerId,
         chain_index: usize,
         config: protocol::StorageProofRequestConfig<impl Iterator<Item = impl AsRef<[u8]>>>,
+        max_response_size: Option<usize>,
     ) -> Result<Vec<Vec<u8>>, StorageProofRequestError> {
+        let max_decompressed_size = if config.accept_compressed_response {
+            Some(max_response_size.unwrap_or(protocol::MAX_DECOMPRESSED_RESPONSE_SIZE))
+        } else {
+            None
+        };
+
         let request_data =
             protocol::build_storage_proof_request(config).fold(Vec::new(), |mut a, b| {
                 a.extend_from_slice(b.as_ref());
                 a
             });
         let response = self
-            .libp2p
             .request(
                 now,
                 target,
                 self.protocol_index(chain_index, 1),
                 request_data,
+                max_response_size,
             )
-            .map_err(StorageProofRequestError::Request)
+            .map_err(StorageProofRequestError::from_request_error)
             .await?;
-        protocol::decode_storage_proof_response(&response).map_err(StorageProofRequestError::Decode)
+        let (response, max_decompressed_size) = self
+            .decompress_response(response, max_decompressed_size)
+            .map_err(|err| {
+                StorageProofRequestError::Decode(
+                    protocol::DecodeStorageProofResponseError::Decompression(err),
+                )
+            })?;
+        protocol::decode_storage_proof_response(&response, max_decompressed_size)
+            .map_err(StorageProofRequestError::Decode)
+    }
+
+    /// If `max_decompressed_size` is `Some`, meaning that the request indicated support for
+    /// compressed responses, and [`Config::response_decompressor`] is `Some`, decompresses the
+    /// response with this function.
+    ///
+    /// Returns the response and the value of `max_decompressed_size` to pass to its decoder,
+    /// which decompresses the response itself if it hasn't been decompressed here.
+    fn decompress_response(
+        &self,
+        response: Vec<u8>,
+        max_decompressed_size: Option<usize>,
+    ) -> Result<(Vec<u8>, Option<usize>), protocol::DecompressionError> {
+        match (max_decompressed_size, self.response_decompressor) {
+            (Some(max_decompressed_size), Some(decompressor)) => {
+                let (response, decompressed) = protocol::decompress_response_with(
+                    response,
+                    max_decompressed_size,
+                    decompressor,
+                )?;
+                Ok((
+                    response,
+                    if decompressed {
+                        None
+                    } else {
+                        Some(max_decompressed_size)
+                    },
+                ))
+            }
+            _ => Ok((response, max_decompressed_size)),
+        }
     }
 
     /// Sends a call proof request to the given peer.
@@ -457,12 +609,15 @@ where
     /// necessary entries), as it is impossible to know this from just the proof itself. As such,
     /// this method is just an optimization. When performing the actual call, regular storage proof
     /// requests should be performed if the key is not present in the call proof response.
+    ///
+    /// See [`ChainNetwork::storage_proof_request`] for the meaning of `max_response_size`.
     pub async fn call_proof_request<'a>(
         &self,
         now: TNow,
         target: peer_id::PeerId,
         chain_index: usize,
         config: protocol::CallProofRequestConfig<'a, impl Iterator<Item = impl AsRef<[u8]>>>,
+        max_response_size: Option<usize>,
     ) -> Result<Vec<Vec<u8>>, CallProofRequestError> {
         let request_data =
             protocol::build_call_proof_request(config).fold(Vec::new(), |mut a, b| {
@@ -470,18 +625,112 @@ where
                 a
             });
         let response = self
-            .libp2p
             .request(
                 now,
                 target,
                 self.protocol_index(chain_index, 1),
                 request_data,
+                max_response_size,
             )
-            .map_err(CallProofRequestError::Request)
+            .map_err(CallProofRequestError::from_request_error)
             .await?;
         protocol::decode_call_proof_response(&response).map_err(CallProofRequestError::Decode)
     }
 
+    /// Sends a request on the request-response protocol with the given name, and returns the
+    /// response as-is.
+    ///
+    /// The protocol can be any of the protocols used by the chains (for example
+    /// `/dot/sync/2`) or any of the protocols passed in
+    /// [`Config::extra_request_response_protocols`]. The request and response aren't
+    /// interpreted in any way, apart from the length prefix.
+    ///
+    /// This is an escape hatch for experimenting with protocols that aren't supported by this
+    /// module.
+    pub async fn raw_request(
+        &self,
+        now: TNow,
+        target: peer_id::PeerId,
+        protocol_name: &str,
+        request_data: Vec<u8>,
+    ) -> Result<Vec<u8>, RawRequestError> {
+        let (protocol_index, protocol) = self
+            .libp2p
+            .request_response_protocols()
+            .enumerate()
+            .find(|(_, protocol)| protocol.name == protocol_name)
+            .ok_or(RawRequestError::UnknownProtocol)?;
+
+        let max_request_size = match protocol.inbound_config {
+            libp2p::ConfigRequestResponseIn::Empty => 0,
+            libp2p::ConfigRequestResponseIn::Payload { max_size } => max_size,
+        };
+        if request_data.len() > max_request_size {
+            return Err(RawRequestError::RequestTooLarge);
+        }
+
+        self.request(now, target, protocol_index, request_data, None)
+            .map_err(RawRequestError::Request)
+            .await
+    }
+
+    /// Sends an identify request to the given peer.
+    ///
+    /// On success, the list of protocols reported by the peer is remembered for as long as it
+    /// stays connected. Requests on protocols that the peer doesn't support are then refused
+    /// locally with [`connection::established::RequestError::ProtocolNotAvailable`] instead of
+    /// being sent.
+    pub async fn identify_request(
+        &self,
+        now: TNow,
+        target: peer_id::PeerId,
+    ) -> Result<protocol::DecodedIdentifyResponse, IdentifyRequestError> {
+        let response = self
+            .libp2p
+            .request(now, target.clone(), 0, Vec::new(), None)
+            .map_err(IdentifyRequestError::Request)
+            .await?;
+        let decoded =
+            protocol::decode_identify_response(&response).map_err(IdentifyRequestError::Decode)?;
+
+        // The peer might have disconnected while the request was in progress, in which case the
+        // information must not be stored, as it would never be cleaned up.
+        let mut peers_protocols = self.peers_protocols.lock().await;
+        if self.libp2p.peers_list_lock().await.any(|p| p == target) {
+            peers_protocols.insert(target, decoded.protocols.clone());
+        }
+
+        Ok(decoded)
+    }
+
+    /// Sends a request to the given peer, unless the peer is known to not support the protocol.
+    async fn request(
+        &self,
+        now: TNow,
+        target: peer_id::PeerId,
+        protocol_index: usize,
+        request_data: Vec<u8>,
+        max_response_size: Option<usize>,
+    ) -> Result<Vec<u8>, libp2p::RequestError> {
+        if let Some(protocols) = self.peers_protocols.lock().await.get(&target) {
+            let protocol_name = &self
+                .libp2p
+                .request_response_protocols()
+                .nth(protocol_index)
+                .unwrap()
+                .name;
+            if !protocols.iter().any(|p| p == protocol_name) {
+                return Err(libp2p::RequestError::Connection(
+                    connection::established::RequestError::ProtocolNotAvailable,
+                ));
+            }
+        }
+
+        self.libp2p
+            .request(now, target, protocol_index, request_data, max_response_size)
+            .await
+    }
+
     pub async fn announce_transaction(
         &self,
         target: &peer_id::PeerId,
@@ -526,6 +775,52 @@ where
         self.libp2p.pending_outcome_err(id.0).await
     }
 
+    /// Sends back the response to a request reported with [`Event::StorageProofRequestIn`].
+    ///
+    /// Passing `None` refuses the request, for example if the storage of the requested block
+    /// isn't available.
+    ///
+    /// Has no effect if the connection the request was received on has since been closed.
+    pub async fn respond_storage_proof(
+        &self,
+        request_id: InRequestId,
+        proof: Option<impl Iterator<Item = impl AsRef<[u8]>>>,
+    ) {
+        let response = proof.map(|proof| {
+            protocol::build_storage_proof_response(proof).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            })
+        });
+
+        self.libp2p
+            .respond_in_request(request_id.0, request_id.1, response.ok_or(()))
+            .await;
+    }
+
+    /// Sends back the response to a request reported with [`Event::CallProofRequestIn`].
+    ///
+    /// Passing `None` refuses the request, for example if the storage of the requested block
+    /// isn't available or if the call has failed.
+    ///
+    /// Has no effect if the connection the request was received on has since been closed.
+    pub async fn respond_call_proof(
+        &self,
+        request_id: InRequestId,
+        proof: Option<impl Iterator<Item = impl AsRef<[u8]>>>,
+    ) {
+        let response = proof.map(|proof| {
+            protocol::build_call_proof_response(proof).fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            })
+        });
+
+        self.libp2p
+            .respond_in_request(request_id.0, request_id.1, response.ok_or(()))
+            .await;
+    }
+
     /// Returns the next event produced by the service.
     ///
     /// This function should be called at a high enough rate that [`ChainNetwork::read_write`] can
@@ -558,6 +853,7 @@ where
                     mut out_overlay_network_indices,
                     ..
                 } => {
+                    self.peers_protocols.lock().await.remove(&peer_id);
                     out_overlay_network_indices
                         .retain(|i| (i % NOTIFICATIONS_PROTOCOLS_PER_CHAIN) == 0);
                     for elem in &mut out_overlay_network_indices {
@@ -571,13 +867,10 @@ where
                 libp2p::Event::RequestIn {
                     id,
                     substream_id,
-                    protocol_index,
+                    protocol_index: 0,
                     peer_id,
                     ..
                 } => {
-                    // Only protocol 0 (identify) can receive requests at the moment.
-                    debug_assert_eq!(protocol_index, 0);
-
                     return Event::IdentifyRequestIn {
                         peer_id,
                         request: IdentifyRequestIn {
@@ -587,6 +880,56 @@ where
                         },
                     };
                 }
+                libp2p::Event::RequestIn {
+                    id,
+                    substream_id,
+                    protocol_index,
+                    peer_id,
+                    request_payload,
+                } => {
+                    // Apart from identify, only the light protocol of each chain can receive
+                    // requests at the moment.
+                    let chain_index = (protocol_index - 1) / REQUEST_RESPONSE_PROTOCOLS_PER_CHAIN;
+                    debug_assert_eq!(self.protocol_index(chain_index, 1), protocol_index);
+
+                    // The light protocol is used for several kinds of requests.
+                    let request_id = InRequestId(id, substream_id);
+                    match protocol::decode_storage_proof_request(&request_payload) {
+                        Ok(request) => {
+                            return Event::StorageProofRequestIn {
+                                chain_index,
+                                peer_id,
+                                request_id,
+                                request,
+                            };
+                        }
+                        Err(protocol::DecodeStorageProofRequestError::BadRequestTy) => {}
+                        Err(_) => {
+                            self.libp2p
+                                .respond_in_request(id, substream_id, Err(()))
+                                .await;
+                            continue;
+                        }
+                    }
+
+                    match protocol::decode_call_proof_request(&request_payload) {
+                        Ok(request) => {
+                            return Event::CallProofRequestIn {
+                                chain_index,
+                                peer_id,
+                                request_id,
+                                request,
+                            };
+                        }
+                        Err(_) => {
+                            // Other kinds of light client requests aren't supported.
+                            self.libp2p
+                                .respond_in_request(id, substream_id, Err(()))
+                                .await;
+                            continue;
+                        }
+                    }
+                }
                 libp2p::Event::NotificationsOutAccept {
                     peer_id,
                     overlay_network_index,
@@ -598,7 +941,29 @@ where
                         let remote_handshake =
                             protocol::decode_block_announces_handshake(&remote_handshake).unwrap();
                         // TODO: don't unwrap
-                        // TODO: compare genesis hash with ours
+
+                        // A peer whose genesis block is different from ours is on a different
+                        // chain, and is removed from all the overlay networks of this chain.
+                        if *remote_handshake.genesis_hash
+                            != self.chain_configs[chain_index].genesis_hash
+                        {
+                            // TODO: below is not futures-cancellation-safe!
+                            for overlay_network_index in (chain_index
+                                * NOTIFICATIONS_PROTOCOLS_PER_CHAIN)
+                                ..((chain_index + 1) * NOTIFICATIONS_PROTOCOLS_PER_CHAIN)
+                            {
+                                self.libp2p
+                                    .remove_from_overlay(&peer_id, overlay_network_index)
+                                    .await;
+                            }
+
+                            return Event::GenesisMismatch {
+                                peer_id,
+                                chain_index,
+                                remote_genesis_hash: *remote_handshake.genesis_hash,
+                            };
+                        }
+
                         return Event::ChainConnected {
                             peer_id,
                             chain_index,
@@ -769,15 +1134,18 @@ where
         };
 
         let request_data = kademlia::build_find_node_request(random_peer_id.as_bytes());
-        if let Some(target) = self.libp2p.peers_list_lock().await.next() {
+        // The list of peers must not stay locked during the request, as `Self::request` locks
+        // `peers_protocols`, and `identify_request` locks them in the opposite order.
+        let target = self.libp2p.peers_list_lock().await.next();
+        if let Some(target) = target {
             // TODO: better peer selection
             let response = self
-                .libp2p
                 .request(
                     now,
                     target,
                     self.protocol_index(chain_index, 2),
                     request_data,
+                    None,
                 )
                 .await
                 .map_err(DiscoveryError::RequestFailed)?;
@@ -793,6 +1161,52 @@ where
         }
     }
 
+    /// Adds the given addresses to the list of known addresses of the given peer, and marks this
+    /// peer as belonging to the given chain.
+    ///
+    /// This is typically used in order to insert nodes whose addresses have been obtained through
+    /// a mechanism other than the discovery, such as resolving the addresses of bootstrap nodes.
+    ///
+    /// `or_insert` is called in order to build the user data of the peer if it isn't known yet.
+    pub async fn add_known_addresses(
+        &self,
+        chain_index: usize,
+        peer_id: peer_id::PeerId,
+        addrs: Vec<multiaddr::Multiaddr>,
+        mut or_insert: impl FnMut() -> TPeer,
+    ) {
+        self.libp2p
+            .add_addresses(
+                || or_insert(),
+                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
+                peer_id.clone(), // TODO: clone :(
+                addrs.iter().cloned(),
+            )
+            .await;
+        self.libp2p
+            .add_addresses(
+                || or_insert(),
+                chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 1,
+                peer_id.clone(), // TODO: clone :(
+                addrs.iter().cloned(),
+            )
+            .await;
+
+        if self.chain_configs[chain_index]
+            .grandpa_protocol_config
+            .is_some()
+        {
+            self.libp2p
+                .add_addresses(
+                    || or_insert(),
+                    chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+                    peer_id,
+                    addrs,
+                )
+                .await;
+        }
+    }
+
     /// Waits until a connection is in a state in which a substream can be opened.
     pub async fn next_substream<'a>(&'a self) -> SubstreamOpen<'a, TNow, TPeer, TConn> {
         loop {
@@ -852,11 +1266,54 @@ where
         })
     }
 
+    /// Asks `relay` to relay a connection to `target`.
+    ///
+    /// Must be called after [`ChainNetwork::fill_out_slots`] returns a [`StartConnect`] whose
+    /// address goes through a relay (see [`libp2p::relay::split_relayed_address`]). The
+    /// returned [`libp2p::RelayCircuit`] must then be used as the socket of the connection.
+    ///
+    /// See [`libp2p::Network::open_relay_circuit`].
+    pub async fn open_relay_circuit(
+        &self,
+        now: TNow,
+        relay: &PeerId,
+        target: &PeerId,
+    ) -> Result<libp2p::RelayCircuit, libp2p::OpenRelayCircuitError> {
+        self.libp2p.open_relay_circuit(now, relay, target).await
+    }
+
+    /// Queues data to be sent to the target of a [`libp2p::RelayCircuit`].
+    ///
+    /// See [`libp2p::Network::write_relay_circuit`].
+    pub async fn write_relay_circuit(
+        &self,
+        circuit: &libp2p::RelayCircuit,
+        data: Vec<u8>,
+    ) -> Result<(), libp2p::RelayCircuitClosedError> {
+        self.libp2p.write_relay_circuit(circuit, data).await
+    }
+
+    /// Abruptly closes a [`libp2p::RelayCircuit`].
+    ///
+    /// See [`libp2p::Network::close_relay_circuit`].
+    pub async fn close_relay_circuit(&self, circuit: libp2p::RelayCircuit) {
+        self.libp2p.close_relay_circuit(circuit).await
+    }
+
     /// Returns an iterator to the list of [`PeerId`]s that we have an established connection
     /// with.
     pub async fn peers_list(&self) -> impl Iterator<Item = PeerId> {
         self.libp2p.peers_list_lock().await
     }
+
+    /// Replaces the key used for the encryption layer of new connections, and thus the
+    /// [`PeerId`] of the local node as seen by the remotes of these connections.
+    ///
+    /// This can be used in order to make it harder for other nodes to track the local node over
+    /// time. Existing connections are unaffected and continue to use the previous key.
+    pub async fn set_noise_key(&self, noise_key: connection::NoiseKey) {
+        self.libp2p.set_noise_key(noise_key).await
+    }
 }
 
 /// User must start connecting to the given multiaddress.
@@ -899,6 +1356,16 @@ pub enum Event<'a, TNow, TPeer, TConn> {
         peer_id: peer_id::PeerId,
     },
 
+    /// The block announces handshake of a peer indicates a genesis block hash different from
+    /// [`ChainConfig::genesis_hash`]. The peer has been removed from the chain, and no new
+    /// substream or connection is opened with it for the purpose of this chain.
+    GenesisMismatch {
+        chain_index: usize,
+        peer_id: peer_id::PeerId,
+        /// Hash of the genesis block according to this peer.
+        remote_genesis_hash: [u8; 32],
+    },
+
     BlockAnnounce {
         chain_index: usize,
         peer_id: peer_id::PeerId,
@@ -920,6 +1387,35 @@ pub enum Event<'a, TNow, TPeer, TConn> {
         /// Object allowing sending back the answer.
         request: IdentifyRequestIn<'a, TNow, TPeer, TConn>,
     },
+
+    /// A remote has sent a request for a proof of some storage entries.
+    ///
+    /// Can only happen if [`ChainConfig::allow_inbound_light_requests`] is `true`. You are
+    /// strongly encouraged to call [`ChainNetwork::respond_storage_proof`].
+    StorageProofRequestIn {
+        chain_index: usize,
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Identifier to pass back when responding.
+        request_id: InRequestId,
+        /// The request itself.
+        request: protocol::StorageProofRequest,
+    },
+
+    /// A remote has sent a request for a proof of the storage entries accessed by a runtime
+    /// call.
+    ///
+    /// Can only happen if [`ChainConfig::allow_inbound_light_requests`] is `true`. You are
+    /// strongly encouraged to call [`ChainNetwork::respond_call_proof`].
+    CallProofRequestIn {
+        chain_index: usize,
+        /// Remote that has sent the request.
+        peer_id: PeerId,
+        /// Identifier to pass back when responding.
+        request_id: InRequestId,
+        /// The request itself.
+        request: protocol::CallProofRequest,
+    },
     /*Transactions {
         peer_id: peer_id::PeerId,
         transactions: EncodedTransactions,
@@ -1009,38 +1505,13 @@ where
     pub async fn insert(self, mut or_insert: impl FnMut(&peer_id::PeerId) -> TPeer) {
         for (peer_id, addrs) in self.outcome {
             self.service
-                .libp2p
-                .add_addresses(
-                    || or_insert(&peer_id),
-                    self.chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
+                .add_known_addresses(
+                    self.chain_index,
                     peer_id.clone(), // TODO: clone :(
-                    addrs.iter().cloned(),
-                )
-                .await;
-            self.service
-                .libp2p
-                .add_addresses(
+                    addrs,
                     || or_insert(&peer_id),
-                    self.chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 1,
-                    peer_id.clone(), // TODO: clone :(
-                    addrs.iter().cloned(),
                 )
                 .await;
-
-            if self.service.chain_configs[self.chain_index]
-                .grandpa_protocol_config
-                .is_some()
-            {
-                self.service
-                    .libp2p
-                    .add_addresses(
-                        || or_insert(&peer_id),
-                        self.chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
-                        peer_id.clone(), // TODO: clone :(
-                        addrs,
-                    )
-                    .await;
-            }
         }
     }
 }
@@ -1123,10 +1594,17 @@ where
     /// Queue the response to send back. The future provided by [`ChainNetwork::read_write`] will
     /// automatically be woken up.
     pub async fn respond(self, agent_version: &str) {
+        // The public key must be the one the remote knows us by on this connection, which might
+        // not be the current one if [`ChainNetwork::set_noise_key`] has been called since.
+        let noise_key = match self.service.libp2p.connection_noise_key(self.id).await {
+            Some(k) => k,
+            None => return,
+        };
+
         let response = protocol::build_identify_response(protocol::IdentifyResponse {
             protocol_version: "/substrate/1.0", // TODO: same value as in Substrate
             agent_version,
-            ed25519_public_key: self.service.libp2p.noise_key().libp2p_public_ed25519_key(),
+            ed25519_public_key: noise_key.libp2p_public_ed25519_key(),
             listen_addrs: iter::empty(),                // TODO:
             observed_addr: &libp2p::Multiaddr::empty(), // TODO:
             protocols: self
@@ -1152,6 +1630,15 @@ where
             .respond_in_request(self.id, self.substream_id, Ok(response))
             .await;
     }
+
+    /// Refuses to answer the request. This doesn't reveal any information about the local node,
+    /// such as its agent version and the list of protocols (and thus chains) it supports.
+    pub async fn refuse(self) {
+        self.service
+            .libp2p
+            .respond_in_request(self.id, self.substream_id, Err(()))
+            .await;
+    }
 }
 
 impl<'a, TNow, TPeer, TConn> fmt::Debug for IdentifyRequestIn<'a, TNow, TPeer, TConn> {
@@ -1168,6 +1655,13 @@ pub enum DiscoveryError {
     DecodeError(kademlia::DecodeFindNodeResponseError),
 }
 
+/// Error returned by [`ChainNetwork::identify_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum IdentifyRequestError {
+    Request(libp2p::RequestError),
+    Decode(protocol::DecodeIdentifyResponseError),
+}
+
 /// Error returned by [`ChainNetwork::blocks_request`].
 #[derive(Debug, derive_more::Display)]
 pub enum BlocksRequestError {
@@ -1178,20 +1672,107 @@ pub enum BlocksRequestError {
 /// Error returned by [`ChainNetwork::storage_proof_request`].
 #[derive(Debug, derive_more::Display)]
 pub enum StorageProofRequestError {
+    /// The response is larger than the maximum allowed size. Requesting fewer keys at once might
+    /// solve the problem.
+    #[display(fmt = "Proof larger than the limit of {} bytes", max_allowed)]
+    ProofTooLarge {
+        /// Maximum size in bytes of the response.
+        max_allowed: usize,
+    },
+    /// The remote has closed the substream without sending back a response. This indicates that
+    /// the remote refuses to answer, which can for example happen if the proof would be larger
+    /// than what the remote is willing to send.
+    #[display(fmt = "Request refused by the remote")]
+    Refused,
     Request(libp2p::RequestError),
     Decode(protocol::DecodeStorageProofResponseError),
 }
 
+impl StorageProofRequestError {
+    fn from_request_error(error: libp2p::RequestError) -> Self {
+        match error {
+            libp2p::RequestError::Connection(
+                connection::established::RequestError::ResponseLebError(
+                    util::leb128::FramedError::MaxLengthExceeded { max_allowed },
+                ),
+            ) => StorageProofRequestError::ProofTooLarge { max_allowed },
+            libp2p::RequestError::Connection(
+                connection::established::RequestError::SubstreamClosed,
+            ) => StorageProofRequestError::Refused,
+            error => StorageProofRequestError::Request(error),
+        }
+    }
+}
+
 /// Error returned by [`ChainNetwork::call_proof_request`].
 #[derive(Debug, derive_more::Display)]
 pub enum CallProofRequestError {
+    /// The response is larger than the maximum allowed size.
+    #[display(fmt = "Proof larger than the limit of {} bytes", max_allowed)]
+    ProofTooLarge {
+        /// Maximum size in bytes of the response.
+        max_allowed: usize,
+    },
+    /// The remote has closed the substream without sending back a response. See
+    /// [`StorageProofRequestError::Refused`].
+    #[display(fmt = "Request refused by the remote")]
+    Refused,
     Request(libp2p::RequestError),
     Decode(protocol::DecodeCallProofResponseError),
 }
 
+impl CallProofRequestError {
+    fn from_request_error(error: libp2p::RequestError) -> Self {
+        match error {
+            libp2p::RequestError::Connection(
+                connection::established::RequestError::ResponseLebError(
+                    util::leb128::FramedError::MaxLengthExceeded { max_allowed },
+                ),
+            ) => CallProofRequestError::ProofTooLarge { max_allowed },
+            libp2p::RequestError::Connection(
+                connection::established::RequestError::SubstreamClosed,
+            ) => CallProofRequestError::Refused,
+            error => CallProofRequestError::Request(error),
+        }
+    }
+}
+
+/// Error returned by [`ChainNetwork::raw_request`].
+#[derive(Debug, derive_more::Display)]
+pub enum RawRequestError {
+    /// No request-response protocol with this name has been configured.
+    UnknownProtocol,
+    /// Request is larger than what the protocol allows.
+    RequestTooLarge,
+    Request(libp2p::RequestError),
+}
+
 /// Error returned by [`ChainNetwork::grandpa_warp_sync_request`].
+#[cfg(feature = "warp-sync")]
+#[cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
 #[derive(Debug, derive_more::Display)]
 pub enum GrandpaWarpSyncRequestError {
+    /// The response is larger than [`protocol::MAX_GRANDPA_WARP_SYNC_RESPONSE_SIZE`]. It has
+    /// been refused without being entirely received.
+    #[display(fmt = "Response larger than the limit of {} bytes", max_allowed)]
+    ResponseTooLarge {
+        /// Maximum size in bytes of the response.
+        max_allowed: usize,
+    },
     Request(libp2p::RequestError),
     Decode(protocol::DecodeGrandpaWarpSyncResponseError),
 }
+
+#[cfg(feature = "warp-sync")]
+impl GrandpaWarpSyncRequestError {
+    fn from_request_error(error: libp2p::RequestError) -> Self {
+        match error {
+            libp2p::RequestError::Connection(
+                connection::established::RequestError::ResponseLebError(
+                    util::leb128::FramedError::MaxLengthExceeded { max_allowed },
+                ),
+            ) => GrandpaWarpSyncRequestError::ResponseTooLarge { max_allowed },
+            error => GrandpaWarpSyncRequestError::Request(error),
+        }
+    }
+}
@@ -32,13 +32,18 @@
 
 use crate::{
     chain::{blocks_tree, chain_information},
-    executor::{host, vm::ExecHint},
+    executor::host,
     header,
-    sync::{all_forks, grandpa_warp_sync, optimistic},
+    sync::{all_forks, optimistic},
     verify,
 };
+#[cfg(feature = "warp-sync")]
+use crate::{executor::vm::ExecHint, sync::grandpa_warp_sync};
 
-use alloc::{vec, vec::Vec};
+#[cfg(feature = "warp-sync")]
+use alloc::vec;
+use alloc::vec::Vec;
+use rand::{Rng as _, SeedableRng as _};
 
 use core::{
     iter, mem,
@@ -89,6 +94,29 @@ pub struct Config {
     /// If `Some`, the block bodies and storage are also synchronized. Contains the extra
     /// configuration.
     pub full: Option<ConfigFull>,
+
+    /// Identifiers of the consensus engines, unknown to smoldot, whose digest items are accepted
+    /// in block headers. Headers containing digest items of other unknown consensus engines are
+    /// refused.
+    ///
+    /// Smoldot doesn't interpret the content of these items. Before a header is accepted, its
+    /// items are yielded by [`HeaderVerify::custom_digest_items`],
+    /// [`HeaderBodyVerify::custom_digest_items`], or [`HeadersBatch::custom_digest_items`], and
+    /// the API user must check them and refuse the header if they are invalid.
+    pub custom_consensus_engines: Vec<[u8; 4]>,
+
+    /// If `true`, Babe secondary slot claims are accepted regardless of the types of slot claims
+    /// allowed by the Babe configuration. See
+    /// [`verify::babe::VerifyConfig::relaxed_secondary_slots`].
+    pub babe_relaxed_secondary_slots: bool,
+
+    /// Implementations of the sr25519 primitives to use when verifying the signatures of block
+    /// headers. See [`blocks_tree::Config::sr25519_hooks`].
+    pub sr25519_hooks: Option<verify::Sr25519Hooks>,
+
+    /// Seed for the randomness used when verifying justifications, Grandpa commits, and warp
+    /// sync proofs. See [`crate::finality::justification::verify::Config::randomness_seed`].
+    pub randomness_seed: [u8; 32],
 }
 
 /// See [`Config::full`].
@@ -118,6 +146,16 @@ pub struct AllSync<TRq, TSrc, TBl> {
 impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
     /// Initializes a new state machine.
     pub fn new(config: Config) -> Self {
+        let mut shared = Shared {
+            sources: slab::Slab::with_capacity(config.sources_capacity),
+            requests: slab::Slab::with_capacity(config.sources_capacity),
+            highest_block_on_network: 0,
+            custom_consensus_engines: config.custom_consensus_engines.clone(),
+            babe_relaxed_secondary_slots: config.babe_relaxed_secondary_slots,
+            sr25519_hooks: config.sr25519_hooks,
+            randomness: rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed),
+        };
+
         AllSync {
             inner: if config.full.is_some() {
                 AllSyncInner::Optimistic(optimistic::OptimisticSync::new(optimistic::Config {
@@ -130,20 +168,18 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                     full: config.full.map(|cfg| optimistic::ConfigFull {
                         finalized_runtime: cfg.finalized_runtime,
                     }),
+                    custom_consensus_engines: config.custom_consensus_engines.clone(),
+                    babe_relaxed_secondary_slots: config.babe_relaxed_secondary_slots,
+                    sr25519_hooks: config.sr25519_hooks,
+                    randomness_seed: shared.randomness.sample(rand::distributions::Standard),
                 }))
             } else {
-                AllSyncInner::GrandpaWarpSync(grandpa_warp_sync::grandpa_warp_sync(
-                    grandpa_warp_sync::Config {
-                        start_chain_information: config.chain_information.into(),
-                        sources_capacity: config.sources_capacity,
-                    },
-                ))
-            },
-            shared: Shared {
-                sources: slab::Slab::with_capacity(config.sources_capacity),
-                requests: slab::Slab::with_capacity(config.sources_capacity),
-                highest_block_on_network: 0,
+                headers_only_inner(
+                    config,
+                    shared.randomness.sample(rand::distributions::Standard),
+                )
             },
+            shared,
         }
     }
 
@@ -153,6 +189,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         match &self.inner {
             AllSyncInner::Optimistic(sync) => sync.as_chain_information(),
             AllSyncInner::AllForks(sync) => sync.as_chain_information(),
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(sync) => sync.as_chain_information(),
             AllSyncInner::Poisoned => unreachable!(),
         }
@@ -163,6 +200,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         match &self.inner {
             AllSyncInner::Optimistic(sync) => sync.finalized_block_header(),
             AllSyncInner::AllForks(sync) => sync.finalized_block_header(),
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(sync) => {
                 sync.as_chain_information().as_ref().finalized_block_header
             }
@@ -178,6 +216,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         match &self.inner {
             AllSyncInner::Optimistic(sync) => sync.best_block_header(),
             AllSyncInner::AllForks(sync) => sync.best_block_header(),
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(_) => self.finalized_block_header(),
             AllSyncInner::Poisoned => unreachable!(),
         }
@@ -191,6 +230,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         match &self.inner {
             AllSyncInner::Optimistic(sync) => sync.best_block_number(),
             AllSyncInner::AllForks(sync) => sync.best_block_number(),
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(_) => self.best_block_header().number,
             AllSyncInner::Poisoned => unreachable!(),
         }
@@ -204,6 +244,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         match &self.inner {
             AllSyncInner::Optimistic(sync) => sync.best_block_hash(),
             AllSyncInner::AllForks(sync) => sync.best_block_hash(),
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(_) => self.best_block_header().hash(),
             AllSyncInner::Poisoned => unreachable!(),
         }
@@ -213,7 +254,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
     ///
     /// The order of the blocks is unspecified.
     pub fn non_finalized_blocks(&self) -> impl Iterator<Item = header::HeaderRef> {
-        match &self.inner {
+        let blocks = match &self.inner {
             AllSyncInner::Optimistic(sync) => {
                 let iter = sync.non_finalized_blocks();
                 either::Left(either::Left(iter))
@@ -222,8 +263,48 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                 let iter = sync.non_finalized_blocks();
                 either::Left(either::Right(iter))
             }
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(_) => either::Right(iter::empty()),
             AllSyncInner::Poisoned => unreachable!(),
+        };
+
+        // Without GrandPa warp syncing, the variant of the `Either` that corresponds to it is
+        // never constructed and its type can't be inferred.
+        #[cfg(not(feature = "warp-sync"))]
+        let blocks: either::Either<_, iter::Empty<_>> = blocks;
+        blocks
+    }
+
+    /// Returns the Sr25519 public key of the authority that has authored the given
+    /// non-finalized block.
+    ///
+    /// Returns `None` if the block isn't known or if the chain doesn't have block authors.
+    pub fn block_author(&self, hash: &[u8; 32]) -> Option<[u8; 32]> {
+        match &self.inner {
+            AllSyncInner::Optimistic(sync) => sync.block_author(hash),
+            AllSyncInner::AllForks(sync) => sync.block_author(hash),
+            #[cfg(feature = "warp-sync")]
+            AllSyncInner::GrandpaWarpSync(_) => None,
+            AllSyncInner::Poisoned => unreachable!(),
+        }
+    }
+
+    /// Returns the changes to the storage made by the given non-finalized block compared to its
+    /// parent, as a list of keys and new values. A value of `None` means that the key is
+    /// removed.
+    ///
+    /// Returns `None` if the block isn't known, or if the state machine isn't currently using
+    /// the strategy that executes blocks. The list is empty if [`Config::full`] is `None`, as
+    /// the blocks aren't executed.
+    pub fn non_finalized_block_storage_top_trie_changes(
+        &'_ self,
+        hash: &[u8; 32],
+    ) -> Option<impl Iterator<Item = (&'_ [u8], Option<&'_ [u8]>)> + '_> {
+        match &self.inner {
+            AllSyncInner::Optimistic(sync) => {
+                sync.non_finalized_block_storage_top_trie_changes(hash)
+            }
+            _ => None,
         }
     }
 
@@ -235,6 +316,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         match &self.inner {
             AllSyncInner::Optimistic(_) => false,
             AllSyncInner::AllForks(_) => true,
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(_) => false,
             AllSyncInner::Poisoned => unreachable!(),
         }
@@ -260,6 +342,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         // `inner` is temporarily replaced with `Poisoned`. A new value must be put back before
         // returning.
         match mem::replace(&mut self.inner, AllSyncInner::Poisoned) {
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(
                 grandpa_warp_sync::InProgressGrandpaWarpSync::WaitingForSources(waiting),
             ) => {
@@ -284,6 +367,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                 self.inner = AllSyncInner::GrandpaWarpSync(warp_sync_request.into());
                 (outer_source_id, vec![action])
             }
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(mut grandpa) => {
                 let outer_source_id_entry = self.shared.sources.vacant_entry();
                 let outer_source_id = SourceId(outer_source_id_entry.key());
@@ -418,6 +502,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                     .collect();
                 (requests, user_data.user_data)
             }
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(_), SourceMapping::GrandpaWarpSync(source_id)) => {
                 let sync = match mem::replace(&mut self.inner, AllSyncInner::Poisoned) {
                     AllSyncInner::GrandpaWarpSync(sync) => sync,
@@ -463,8 +548,9 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
             }
             (AllSyncInner::Poisoned, _) => unreachable!(),
             (AllSyncInner::Optimistic(_), SourceMapping::AllForks(_))
-            | (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
-            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_))
+            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
+            (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::AllForks(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::AllForks(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::Optimistic(_)) => unreachable!(),
@@ -473,7 +559,8 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
 
     /// Returns the list of sources in this state machine.
     pub fn sources(&'_ self) -> impl Iterator<Item = SourceId> + '_ {
-        match &self.inner {
+        let sources = match &self.inner {
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(sync) => {
                 let iter = sync
                     .sources()
@@ -493,7 +580,13 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                 either::Right(iter)
             }
             AllSyncInner::Poisoned => unreachable!(),
-        }
+        };
+
+        // Without GrandPa warp syncing, the variant of the `Either` that corresponds to it is
+        // never constructed and its type can't be inferred.
+        #[cfg(not(feature = "warp-sync"))]
+        let sources: either::Either<either::Either<_, iter::Empty<_>>, _> = sources;
+        sources
     }
 
     /// Returns the user data (`TSrc`) corresponding to the given source.
@@ -511,13 +604,15 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
             (AllSyncInner::AllForks(sync), SourceMapping::AllForks(src)) => {
                 &sync.source_user_data(*src).user_data
             }
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(sync), SourceMapping::GrandpaWarpSync(src)) => {
                 &sync.source_user_data(*src).user_data
             }
             (AllSyncInner::Poisoned, _) => unreachable!(),
             (AllSyncInner::Optimistic(_), SourceMapping::AllForks(_))
-            | (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
-            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_))
+            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
+            (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::AllForks(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::AllForks(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::Optimistic(_)) => unreachable!(),
@@ -542,13 +637,15 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
             (AllSyncInner::AllForks(sync), SourceMapping::AllForks(src)) => {
                 &mut sync.source_user_data_mut(*src).user_data
             }
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(sync), SourceMapping::GrandpaWarpSync(src)) => {
                 &mut sync.source_user_data_mut(*src).user_data
             }
             (AllSyncInner::Poisoned, _) => unreachable!(),
             (AllSyncInner::Optimistic(_), SourceMapping::AllForks(_))
-            | (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
-            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_))
+            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
+            (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::AllForks(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::AllForks(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::Optimistic(_)) => unreachable!(),
@@ -574,14 +671,16 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
             (AllSyncInner::AllForks(sync), SourceMapping::AllForks(src)) => {
                 sync.source_best_block(*src)
             }
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(sync), SourceMapping::GrandpaWarpSync(src)) => {
                 let ud = sync.source_user_data(*src);
                 (ud.best_block_number, &ud.best_block_hash)
             }
             (AllSyncInner::Poisoned, _) => unreachable!(),
             (AllSyncInner::Optimistic(_), SourceMapping::AllForks(_))
-            | (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
-            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_))
+            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
+            (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::AllForks(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::AllForks(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::Optimistic(_)) => unreachable!(),
@@ -613,6 +712,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
             (AllSyncInner::AllForks(sync), SourceMapping::AllForks(src)) => {
                 sync.source_knows_non_finalized_block(*src, height, hash)
             }
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(sync), SourceMapping::GrandpaWarpSync(src)) => {
                 assert!(
                     height
@@ -628,8 +728,9 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
             }
             (AllSyncInner::Poisoned, _) => unreachable!(),
             (AllSyncInner::Optimistic(_), SourceMapping::AllForks(_))
-            | (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
-            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_))
+            | (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
+            (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::AllForks(_), SourceMapping::GrandpaWarpSync(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::AllForks(_))
             | (AllSyncInner::GrandpaWarpSync(_), SourceMapping::Optimistic(_)) => unreachable!(),
@@ -650,7 +751,8 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         height: u64,
         hash: &[u8; 32],
     ) -> impl Iterator<Item = SourceId> + '_ {
-        match &self.inner {
+        let sources = match &self.inner {
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(sync) => {
                 assert!(
                     height
@@ -680,7 +782,13 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                 either::Right(iter)
             }
             AllSyncInner::Poisoned => unreachable!(),
-        }
+        };
+
+        // Without GrandPa warp syncing, the variant of the `Either` that corresponds to it is
+        // never constructed and its type can't be inferred.
+        #[cfg(not(feature = "warp-sync"))]
+        let sources: either::Either<iter::Empty<_>, _> = sources;
+        sources
     }
 
     /// Process the next block in the queue of verification.
@@ -689,9 +797,11 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
     /// [`AllSync`] is yielded back at the end of this process.
     pub fn process_one(mut self) -> ProcessOne<TRq, TSrc, TBl> {
         match self.inner {
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(
                 grandpa_warp_sync::InProgressGrandpaWarpSync::Verifier(_),
             ) => ProcessOne::VerifyWarpSyncFragment(WarpSyncFragmentVerify { inner: self }),
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(_) => ProcessOne::AllSync(self),
             AllSyncInner::Optimistic(sync) => match sync.process_one() {
                 optimistic::ProcessOne::AllSync { sync } => {
@@ -729,6 +839,44 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         }
     }
 
+    /// Verifies the headers of up to `max_blocks` blocks of the queue of verification, deferring
+    /// the verification of their signatures. See
+    /// [`optimistic::OptimisticSync::process_headers_batch`].
+    ///
+    /// Batches are only possible while catching up with the head of the chain, and if the bodies
+    /// of the blocks aren't verified. In any other situation, [`ProcessHeadersBatch::AllSync`] is
+    /// returned and [`AllSync::process_one`] must be used instead.
+    ///
+    /// This method takes ownership of the [`AllSync`]. The [`AllSync`] is yielded back in the
+    /// returned value.
+    pub fn process_headers_batch(
+        mut self,
+        max_blocks: NonZeroU32,
+        now_from_unix_epoch: Duration,
+    ) -> ProcessHeadersBatch<TRq, TSrc, TBl> {
+        match self.inner {
+            AllSyncInner::Optimistic(sync) => {
+                match sync.process_headers_batch(max_blocks, now_from_unix_epoch) {
+                    optimistic::ProcessHeadersBatch::AllSync { sync } => {
+                        self.inner = AllSyncInner::Optimistic(sync);
+                        ProcessHeadersBatch::AllSync(self)
+                    }
+                    optimistic::ProcessHeadersBatch::Batch(inner) => {
+                        ProcessHeadersBatch::Batch(HeadersBatch {
+                            inner,
+                            shared: self.shared,
+                            marker: core::marker::PhantomData,
+                        })
+                    }
+                }
+            }
+            inner => {
+                self.inner = inner;
+                ProcessHeadersBatch::AllSync(self)
+            }
+        }
+    }
+
     /// Injects a block announcement made by a source into the state machine.
     pub fn block_announce(
         &mut self,
@@ -736,20 +884,25 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         announced_scale_encoded_header: Vec<u8>,
         is_best: bool,
     ) -> BlockAnnounceOutcome {
-        if let Ok(header) = header::decode(&announced_scale_encoded_header) {
-            if header.number > self.shared.highest_block_on_network {
-                self.shared.highest_block_on_network = header.number;
-            }
+        let announced_block_number = match header::decode_with_custom_engines(
+            &announced_scale_encoded_header,
+            &self.shared.custom_consensus_engines,
+        ) {
+            Ok(header) => header.number,
+            Err(error) => return BlockAnnounceOutcome::InvalidHeader(error),
+        };
+
+        if announced_block_number > self.shared.highest_block_on_network {
+            self.shared.highest_block_on_network = announced_block_number;
         }
 
         let source_id = self.shared.sources.get(source_id.0).unwrap();
 
         match (&mut self.inner, source_id) {
             (AllSyncInner::Optimistic(sync), &SourceMapping::Optimistic(source_id)) => {
-                let decoded = header::decode(&announced_scale_encoded_header).unwrap();
                 sync.source_user_data_mut(source_id).best_block_hash =
                     header::hash_from_scale_encoded_header(&announced_scale_encoded_header);
-                sync.raise_source_best_block(source_id, decoded.number);
+                sync.raise_source_best_block(source_id, announced_block_number);
 
                 let mut next_actions = Vec::new();
                 while let Some(action) = sync.next_request_action() {
@@ -779,16 +932,16 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                     }
                 }
             }
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(sync), &SourceMapping::GrandpaWarpSync(source_id)) => {
                 // If GrandPa warp syncing is in progress, the best block of the source is stored
                 // in the user data. It will be useful later when transitioning to another
                 // syncing strategy.
                 if is_best {
                     let mut user_data = sync.source_user_data_mut(source_id);
-                    // TODO: this can't panic right now, but it should be made explicit in the API that the header must be valid
-                    let header = header::decode(&announced_scale_encoded_header).unwrap();
-                    user_data.best_block_number = header.number;
-                    user_data.best_block_hash = header.hash();
+                    user_data.best_block_number = announced_block_number;
+                    user_data.best_block_hash =
+                        header::hash_from_scale_encoded_header(&announced_scale_encoded_header);
                 }
 
                 BlockAnnounceOutcome::Disjoint {
@@ -801,10 +954,14 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
             // This indicates a internal bug during the switch from one state machine to the
             // other.
             (AllSyncInner::Optimistic(_), SourceMapping::AllForks(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::Optimistic(_), SourceMapping::GrandpaWarpSync(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(_), SourceMapping::AllForks(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(_), SourceMapping::Optimistic(_)) => unreachable!(),
             (AllSyncInner::AllForks(_), SourceMapping::Optimistic(_)) => unreachable!(),
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::AllForks(_), SourceMapping::GrandpaWarpSync(_)) => unreachable!(),
         }
     }
@@ -821,6 +978,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         match &mut self.inner {
             AllSyncInner::Optimistic(_) => Ok(()),
             AllSyncInner::AllForks(sync) => sync.grandpa_commit_message(scale_encoded_message),
+            #[cfg(feature = "warp-sync")]
             AllSyncInner::GrandpaWarpSync(_) => Ok(()),
             AllSyncInner::Poisoned => unreachable!(),
         }
@@ -842,6 +1000,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         let request = self.shared.requests.remove(request_id.0);
 
         match (&mut self.inner, request) {
+            #[cfg(feature = "warp-sync")]
             (AllSyncInner::GrandpaWarpSync(_), _) => panic!(), // Grandpa warp sync never starts block requests.
             (AllSyncInner::Optimistic(sync), RequestMapping::Optimistic(request_id)) => {
                 let _ = sync.finish_request(
@@ -903,6 +1062,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         }
     }
 
+    #[cfg(feature = "warp-sync")]
     /// Inject a response to a previously-emitted GrandPa warp sync request.
     ///
     /// # Panic
@@ -915,7 +1075,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         request_id: RequestId,
         // TODO: don't use crate::network::protocol
         // TODO: Result instead of Option?
-        response: Option<crate::network::protocol::GrandpaWarpSyncResponse>,
+        response: Option<crate::network::protocol::EncodedGrandpaWarpSyncResponse>,
     ) -> ResponseOutcome {
         debug_assert!(self.shared.requests.contains(request_id.0));
         let request = self.shared.requests.remove(request_id.0);
@@ -934,6 +1094,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         }
     }
 
+    #[cfg(feature = "warp-sync")]
     /// Inject a response to a previously-emitted storage proof request.
     ///
     /// # Panic
@@ -996,6 +1157,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         }
     }
 
+    #[cfg(feature = "warp-sync")]
     fn inject_grandpa(
         &mut self,
         grandpa_warp_sync: grandpa_warp_sync::GrandpaWarpSync<GrandpaWarpSyncSourceExtra<TSrc>>,
@@ -1013,6 +1175,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         }
     }
 
+    #[cfg(feature = "warp-sync")]
     fn inject_in_progress_grandpa(
         &mut self,
         grandpa_warp_sync: grandpa_warp_sync::InProgressGrandpaWarpSync<
@@ -1143,12 +1306,16 @@ pub enum RequestDetail {
     },
 
     /// Sending a Grandpa warp sync request is requested.
+    #[cfg(feature = "warp-sync")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
     GrandpaWarpSync {
         /// Hash of the known finalized block. Starting point of the request.
         sync_start_block_hash: [u8; 32],
     },
 
     /// Sending a storage query is requested.
+    #[cfg(feature = "warp-sync")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
     StorageGet {
         /// Hash of the block whose storage is requested.
         block_hash: [u8; 32],
@@ -1210,6 +1377,8 @@ pub enum ProcessOne<TRq, TSrc, TBl> {
     VerifyHeaderBody(HeaderBodyVerify<TRq, TSrc, TBl>),
 
     /// Ready to start verifying a warp sync fragment.
+    #[cfg(feature = "warp-sync")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
     VerifyWarpSyncFragment(WarpSyncFragmentVerify<TRq, TSrc, TBl>),
 }
 
@@ -1222,6 +1391,8 @@ pub enum ResponseOutcome {
     },
 
     /// Response has made it possible to finish warp syncing.
+    #[cfg(feature = "warp-sync")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
     WarpSyncFinished {
         /// Next requests that must be started.
         next_actions: Vec<Action>,
@@ -1278,6 +1449,65 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
         }
     }
 
+    /// Returns the digest items of the block to be verified that belong to one of the consensus
+    /// engines of [`Config::custom_consensus_engines`].
+    ///
+    /// Smoldot can't verify these items. If this list isn't empty, the API user must check the
+    /// items, then call [`HeaderVerify::perform`] if they are valid or [`HeaderVerify::reject`]
+    /// if they aren't.
+    pub fn custom_digest_items(&self) -> Vec<header::CustomDigestItem> {
+        match &self.inner {
+            HeaderVerifyInner::Optimistic(verify) => verify.custom_digest_items(),
+            HeaderVerifyInner::AllForks(verify) => verify.custom_digest_items(),
+        }
+    }
+
+    /// Refuses the block because its [custom digest items](Self::custom_digest_items) are
+    /// invalid, without performing the rest of the verification.
+    ///
+    /// The outcome is the same as if [`HeaderVerify::perform`] had failed with
+    /// [`verify::header_only::Error::CustomDigestItemsRejected`].
+    pub fn reject(mut self, user_data: TBl) -> HeaderVerifyOutcome<TRq, TSrc, TBl> {
+        match self.inner {
+            HeaderVerifyInner::Optimistic(verify) => {
+                match optimistic_verification_outcome(self.shared, verify.reject()) {
+                    HeadersBatchOutcome::Error {
+                        sync,
+                        error,
+                        next_actions,
+                    } => HeaderVerifyOutcome::Error {
+                        sync,
+                        error,
+                        user_data,
+                        next_actions,
+                    },
+                    HeadersBatchOutcome::Success { .. } => unreachable!(),
+                }
+            }
+            HeaderVerifyInner::AllForks(verify) => match verify.reject(user_data) {
+                all_forks::HeaderVerifyOutcome::Error {
+                    mut sync,
+                    user_data,
+                    ..
+                } => {
+                    let next_actions = self.shared.all_forks_next_actions(&mut sync);
+                    HeaderVerifyOutcome::Error {
+                        sync: AllSync {
+                            inner: AllSyncInner::AllForks(sync),
+                            shared: self.shared,
+                        },
+                        error: HeaderVerifyError::VerificationFailed(
+                            verify::header_only::Error::CustomDigestItemsRejected,
+                        ),
+                        user_data,
+                        next_actions,
+                    }
+                }
+                all_forks::HeaderVerifyOutcome::Success { .. } => unreachable!(),
+            },
+        }
+    }
+
     /// Perform the verification.
     pub fn perform(
         mut self,
@@ -1285,78 +1515,33 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
         user_data: TBl,
     ) -> HeaderVerifyOutcome<TRq, TSrc, TBl> {
         match self.inner {
-            HeaderVerifyInner::Optimistic(verify) => match verify.start(now_from_unix_epoch) {
-                outcome @ optimistic::BlockVerification::NewBest { .. }
-                | outcome @ optimistic::BlockVerification::Finalized { .. } => {
-                    let (mut sync, new_best_number, is_new_finalized) = match outcome {
-                        optimistic::BlockVerification::NewBest {
-                            sync,
-                            new_best_number,
-                            ..
-                        } => (sync, new_best_number, false),
-                        optimistic::BlockVerification::Finalized {
-                            sync,
-                            finalized_blocks,
-                            ..
-                        } => (sync, finalized_blocks.last().unwrap().header.number, true),
-                        _ => unreachable!(),
-                    };
-
-                    if new_best_number >= self.shared.highest_block_on_network - 1024 {
-                        // TODO: do this better ^
-                        let (all_forks, next_actions) =
-                            self.shared.transition_optimistic_all_forks(sync);
-                        return HeaderVerifyOutcome::Success {
-                            is_new_best: true,
-                            is_new_finalized,
-                            sync: AllSync {
-                                inner: AllSyncInner::AllForks(all_forks),
-                                shared: self.shared,
-                            }
-                            .into(),
-                            next_actions,
-                        };
-                    }
-
-                    let mut next_actions = Vec::new();
-                    while let Some(action) = sync.next_request_action() {
-                        next_actions.push(self.shared.optimistic_action_to_request(action));
-                    }
-
-                    HeaderVerifyOutcome::Success {
+            HeaderVerifyInner::Optimistic(verify) => {
+                match optimistic_verification_outcome(
+                    self.shared,
+                    verify.start(now_from_unix_epoch),
+                ) {
+                    HeadersBatchOutcome::Success {
+                        is_new_finalized,
+                        sync,
+                        next_actions,
+                    } => HeaderVerifyOutcome::Success {
                         is_new_best: true,
                         is_new_finalized,
-                        sync: AllSync {
-                            inner: AllSyncInner::Optimistic(sync),
-                            shared: self.shared,
-                        },
+                        sync,
                         next_actions,
-                    }
-                }
-                optimistic::BlockVerification::Reset { mut sync, .. } => {
-                    let mut next_actions = Vec::new();
-                    while let Some(action) = sync.next_request_action() {
-                        next_actions.push(self.shared.optimistic_action_to_request(action));
-                    }
-
-                    HeaderVerifyOutcome::Error {
-                        sync: AllSync {
-                            inner: AllSyncInner::Optimistic(sync),
-                            shared: self.shared,
-                        },
+                    },
+                    HeadersBatchOutcome::Error {
+                        sync,
+                        error,
                         next_actions,
-                        error: HeaderVerifyError::VerificationFailed(
-                            verify::header_only::Error::BadBlockNumber,
-                        ), // TODO: this is the completely wrong error; needs some deeper API changes
+                    } => HeaderVerifyOutcome::Error {
+                        sync,
+                        error,
                         user_data,
-                    }
-                }
-                optimistic::BlockVerification::FinalizedStorageGet(_)
-                | optimistic::BlockVerification::FinalizedStorageNextKey(_)
-                | optimistic::BlockVerification::FinalizedStoragePrefixKeys(_) => {
-                    unreachable!()
+                        next_actions,
+                    },
                 }
-            },
+            }
             HeaderVerifyInner::AllForks(verify) => {
                 match verify.perform(now_from_unix_epoch, user_data) {
                     all_forks::HeaderVerifyOutcome::Success {
@@ -1440,6 +1625,185 @@ pub enum HeaderVerifyError {
     VerificationFailed(verify::header_only::Error),
 }
 
+/// Outcome of calling [`AllSync::process_headers_batch`].
+pub enum ProcessHeadersBatch<TRq, TSrc, TBl> {
+    /// No batch of headers is ready to be verified. [`AllSync::process_one`] might still have
+    /// something to process.
+    AllSync(AllSync<TRq, TSrc, TBl>),
+
+    /// Headers have been added to the chain, and their signatures must now be verified.
+    Batch(HeadersBatch<TRq, TSrc, TBl>),
+}
+
+/// Batch of headers whose signatures must be verified. See
+/// [`optimistic::HeadersBatch`].
+#[must_use]
+pub struct HeadersBatch<TRq, TSrc, TBl> {
+    inner: optimistic::HeadersBatch<(), OptimisticSourceExtra<TSrc>, TBl>,
+    shared: Shared,
+    marker: core::marker::PhantomData<TRq>,
+}
+
+impl<TRq, TSrc, TBl> HeadersBatch<TRq, TSrc, TBl> {
+    /// Returns the signatures that must be verified, one entry per block of the batch.
+    ///
+    /// The checks don't borrow anything and can be cloned, which makes it possible to verify
+    /// them on other threads or in parallel.
+    pub fn signature_checks(&self) -> &[verify::header_only::SignatureChecks] {
+        self.inner.signature_checks()
+    }
+
+    /// Returns the hashes of the blocks of the batch, in the same order as
+    /// [`HeadersBatch::signature_checks`].
+    pub fn block_hashes(&self) -> &[[u8; 32]] {
+        self.inner.block_hashes()
+    }
+
+    /// Returns, for each block of the batch and in the same order as
+    /// [`HeadersBatch::signature_checks`], the digest items that belong to one of the consensus
+    /// engines of [`Config::custom_consensus_engines`].
+    ///
+    /// Smoldot can't verify these items. If any of them is invalid, pass
+    /// [`verify::header_only::Error::CustomDigestItemsRejected`] to [`HeadersBatch::finish`].
+    pub fn custom_digest_items(&self) -> &[Vec<header::CustomDigestItem>] {
+        self.inner.custom_digest_items()
+    }
+
+    /// Verifies all the signatures of the batch in the current thread, then calls
+    /// [`HeadersBatch::finish`].
+    ///
+    /// The items returned by [`HeadersBatch::custom_digest_items`], if any, are considered as
+    /// valid. They must have been checked beforehand.
+    pub fn verify_and_finish(self) -> HeadersBatchOutcome<TRq, TSrc, TBl> {
+        optimistic_verification_outcome(self.shared, self.inner.verify_and_finish())
+    }
+
+    /// Injects the outcome of verifying the signatures returned by
+    /// [`HeadersBatch::signature_checks`] and the items returned by
+    /// [`HeadersBatch::custom_digest_items`].
+    ///
+    /// `outcome` must be `Ok` if all the signatures and items are valid, or contain any of the
+    /// errors otherwise.
+    pub fn finish(
+        self,
+        outcome: Result<(), verify::header_only::Error>,
+    ) -> HeadersBatchOutcome<TRq, TSrc, TBl> {
+        optimistic_verification_outcome(self.shared, self.inner.finish(outcome))
+    }
+}
+
+/// Outcome of calling [`HeadersBatch::finish`] or [`HeadersBatch::verify_and_finish`].
+pub enum HeadersBatchOutcome<TRq, TSrc, TBl> {
+    /// All the headers of the batch have been successfully verified. The last block of the batch
+    /// is the new best block.
+    Success {
+        /// True if the last block of the batch is considered the latest finalized block.
+        is_new_finalized: bool,
+        /// State machine yielded back. Use to continue the processing.
+        sync: AllSync<TRq, TSrc, TBl>,
+        /// Next requests that must be started.
+        next_actions: Vec<Action>,
+    },
+
+    /// Verification of the batch has failed. The blocks of the batch, and possibly other
+    /// non-finalized blocks, have been discarded.
+    Error {
+        /// State machine yielded back. Use to continue the processing.
+        sync: AllSync<TRq, TSrc, TBl>,
+        /// Error that happened.
+        error: HeaderVerifyError,
+        /// Next requests that must be started.
+        next_actions: Vec<Action>,
+    },
+}
+
+/// Turns the outcome of a headers-only verification performed by the optimistic syncing
+/// strategy into an [`AllSync`], switching to the all-forks strategy if the head of the chain
+/// is close.
+fn optimistic_verification_outcome<TRq, TSrc, TBl>(
+    mut shared: Shared,
+    outcome: optimistic::BlockVerification<(), OptimisticSourceExtra<TSrc>, TBl>,
+) -> HeadersBatchOutcome<TRq, TSrc, TBl> {
+    match outcome {
+        outcome @ optimistic::BlockVerification::NewBest { .. }
+        | outcome @ optimistic::BlockVerification::Finalized { .. } => {
+            let (mut sync, new_best_number, is_new_finalized) = match outcome {
+                optimistic::BlockVerification::NewBest {
+                    sync,
+                    new_best_number,
+                    ..
+                } => (sync, new_best_number, false),
+                optimistic::BlockVerification::Finalized {
+                    sync,
+                    finalized_blocks,
+                    ..
+                } => (sync, finalized_blocks.last().unwrap().header.number, true),
+                _ => unreachable!(),
+            };
+
+            if new_best_number >= shared.highest_block_on_network - 1024 {
+                // TODO: do this better ^
+                let (all_forks, next_actions) = shared.transition_optimistic_all_forks(sync);
+                return HeadersBatchOutcome::Success {
+                    is_new_finalized,
+                    sync: AllSync {
+                        inner: AllSyncInner::AllForks(all_forks),
+                        shared,
+                    },
+                    next_actions,
+                };
+            }
+
+            let mut next_actions = Vec::new();
+            while let Some(action) = sync.next_request_action() {
+                next_actions.push(shared.optimistic_action_to_request(action));
+            }
+
+            HeadersBatchOutcome::Success {
+                is_new_finalized,
+                sync: AllSync {
+                    inner: AllSyncInner::Optimistic(sync),
+                    shared,
+                },
+                next_actions,
+            }
+        }
+        optimistic::BlockVerification::Reset {
+            mut sync, reason, ..
+        } => {
+            let mut next_actions = Vec::new();
+            while let Some(action) = sync.next_request_action() {
+                next_actions.push(shared.optimistic_action_to_request(action));
+            }
+
+            HeadersBatchOutcome::Error {
+                sync: AllSync {
+                    inner: AllSyncInner::Optimistic(sync),
+                    shared,
+                },
+                next_actions,
+                error: match reason {
+                    optimistic::ResetCause::HeaderError(
+                        blocks_tree::HeaderVerifyError::VerificationFailed(error),
+                    ) => HeaderVerifyError::VerificationFailed(error),
+                    optimistic::ResetCause::HeaderError(
+                        blocks_tree::HeaderVerifyError::ConsensusMismatch,
+                    ) => HeaderVerifyError::ConsensusMismatch,
+                    // TODO: this is the completely wrong error; needs some deeper API changes
+                    _ => HeaderVerifyError::VerificationFailed(
+                        verify::header_only::Error::BadBlockNumber,
+                    ),
+                },
+            }
+        }
+        optimistic::BlockVerification::FinalizedStorageGet(_)
+        | optimistic::BlockVerification::FinalizedStorageNextKey(_)
+        | optimistic::BlockVerification::FinalizedStoragePrefixKeys(_) => {
+            unreachable!()
+        }
+    }
+}
+
 pub struct HeaderBodyVerify<TRq, TSrc, TBl> {
     inner: HeaderBodyVerifyInner<TSrc, TBl>,
     shared: Shared,
@@ -1465,6 +1829,28 @@ impl<TRq, TSrc, TBl> HeaderBodyVerify<TRq, TSrc, TBl> {
         }
     }
 
+    /// Returns the digest items of the block to be verified that belong to one of the consensus
+    /// engines of [`Config::custom_consensus_engines`].
+    ///
+    /// Smoldot can't verify these items. If this list isn't empty, the API user must check the
+    /// items, then call [`HeaderBodyVerify::start`] if they are valid or
+    /// [`HeaderBodyVerify::reject`] if they aren't.
+    pub fn custom_digest_items(&self) -> Vec<header::CustomDigestItem> {
+        match &self.inner {
+            HeaderBodyVerifyInner::Optimistic(verify) => verify.custom_digest_items(),
+        }
+    }
+
+    /// Refuses the block because its [custom digest items](Self::custom_digest_items) are
+    /// invalid, without performing the rest of the verification.
+    pub fn reject(self, user_data: TBl) -> BlockVerification<TRq, TSrc, TBl> {
+        match self.inner {
+            HeaderBodyVerifyInner::Optimistic(verify) => {
+                BlockVerification::from_inner(verify.reject(), self.shared, user_data)
+            }
+        }
+    }
+
     /// Start the verification process.
     pub fn start(
         self,
@@ -1575,7 +1961,9 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                     finalized_blocks,
                 }
             }
-            optimistic::BlockVerification::Reset { mut sync, .. } => {
+            optimistic::BlockVerification::Reset {
+                mut sync, reason, ..
+            } => {
                 let mut next_actions = Vec::new();
                 while let Some(action) = sync.next_request_action() {
                     next_actions.push(shared.optimistic_action_to_request(action));
@@ -1587,7 +1975,12 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                         shared,
                     },
                     next_actions,
-                    error: verify::header_only::Error::BadBlockNumber, // TODO: this is the completely wrong error; needs some deeper API changes
+                    error: match reason {
+                        optimistic::ResetCause::HeaderError(
+                            blocks_tree::HeaderVerifyError::VerificationFailed(error),
+                        ) => error,
+                        _ => verify::header_only::Error::BadBlockNumber, // TODO: this is the completely wrong error; needs some deeper API changes
+                    },
                     user_data,
                 }
             }
@@ -1699,10 +2092,13 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
     }
 }
 
+#[cfg(feature = "warp-sync")]
+#[cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
 pub struct WarpSyncFragmentVerify<TRq, TSrc, TBl> {
     inner: AllSync<TRq, TSrc, TBl>,
 }
 
+#[cfg(feature = "warp-sync")]
 impl<TRq, TSrc, TBl> WarpSyncFragmentVerify<TRq, TSrc, TBl> {
     /// Perform the verification.
     pub fn perform(
@@ -1732,9 +2128,50 @@ impl<TRq, TSrc, TBl> WarpSyncFragmentVerify<TRq, TSrc, TBl> {
     }
 }
 
+/// Builds the initial state of an [`AllSync`] that only synchronizes headers.
+///
+/// The chain is first warp synced using GrandPa before switching to the "all-forks" strategy.
+#[cfg(feature = "warp-sync")]
+fn headers_only_inner<TRq, TSrc, TBl>(
+    config: Config,
+    randomness_seed: [u8; 32],
+) -> AllSyncInner<TRq, TSrc, TBl> {
+    AllSyncInner::GrandpaWarpSync(grandpa_warp_sync::grandpa_warp_sync(
+        grandpa_warp_sync::Config {
+            start_chain_information: config.chain_information.into(),
+            sources_capacity: config.sources_capacity,
+            randomness_seed,
+        },
+    ))
+}
+
+/// Builds the initial state of an [`AllSync`] that only synchronizes headers.
+///
+/// Without GrandPa warp syncing, the "all-forks" strategy is used starting from the chain
+/// information passed in the configuration.
+#[cfg(not(feature = "warp-sync"))]
+fn headers_only_inner<TRq, TSrc, TBl>(
+    config: Config,
+    randomness_seed: [u8; 32],
+) -> AllSyncInner<TRq, TSrc, TBl> {
+    AllSyncInner::AllForks(all_forks::AllForksSync::new(all_forks::Config {
+        chain_information: config.chain_information,
+        sources_capacity: config.sources_capacity,
+        blocks_capacity: config.blocks_capacity,
+        max_disjoint_headers: 1024, // TODO: arbitrary config
+        max_requests_per_block: NonZeroU32::new(3).unwrap(),
+        full: false,
+        custom_consensus_engines: config.custom_consensus_engines,
+        babe_relaxed_secondary_slots: config.babe_relaxed_secondary_slots,
+        sr25519_hooks: config.sr25519_hooks,
+        randomness_seed,
+    }))
+}
+
 enum AllSyncInner<TRq, TSrc, TBl> {
     Optimistic(optimistic::OptimisticSync<(), OptimisticSourceExtra<TSrc>, TBl>),
     /// > **Note**: Must never contain [`grandpa_warp_sync::GrandpaWarpSync::Finished`].
+    #[cfg(feature = "warp-sync")]
     GrandpaWarpSync(grandpa_warp_sync::InProgressGrandpaWarpSync<GrandpaWarpSyncSourceExtra<TSrc>>),
     AllForks(all_forks::AllForksSync<TBl, AllForksRequestExtra<TRq>, AllForksSourceExtra<TSrc>>),
     Poisoned,
@@ -1756,6 +2193,7 @@ struct AllForksRequestExtra<TRq> {
     user_data: Option<TRq>,
 }
 
+#[cfg(feature = "warp-sync")]
 struct GrandpaWarpSyncSourceExtra<TSrc> {
     outer_source_id: SourceId,
     user_data: TSrc,
@@ -1768,6 +2206,14 @@ struct Shared {
     requests: slab::Slab<RequestMapping>,
     // TODO: this is an insecure way to do things; see https://github.com/paritytech/smoldot/issues/490
     highest_block_on_network: u64,
+    /// See [`Config::custom_consensus_engines`].
+    custom_consensus_engines: Vec<[u8; 4]>,
+    /// See [`Config::babe_relaxed_secondary_slots`].
+    babe_relaxed_secondary_slots: bool,
+    /// See [`Config::sr25519_hooks`].
+    sr25519_hooks: Option<verify::Sr25519Hooks>,
+    /// Source of the seeds passed to the syncing strategies. See [`Config::randomness_seed`].
+    randomness: rand_chacha::ChaCha20Rng,
 }
 
 impl Shared {
@@ -1869,6 +2315,7 @@ impl Shared {
         out
     }
 
+    #[cfg(feature = "warp-sync")]
     fn grandpa_warp_sync_request_to_request<TSrc>(
         &mut self,
         grandpa_warp_sync: &grandpa_warp_sync::WarpSyncRequest<GrandpaWarpSyncSourceExtra<TSrc>>,
@@ -1913,6 +2360,10 @@ impl Shared {
             max_disjoint_headers: 1024,
             max_requests_per_block: NonZeroU32::new(3).unwrap(),
             full: false,
+            custom_consensus_engines: self.custom_consensus_engines.clone(),
+            babe_relaxed_secondary_slots: self.babe_relaxed_secondary_slots,
+            sr25519_hooks: self.sr25519_hooks,
+            randomness_seed: self.randomness.sample(rand::distributions::Standard),
         });
 
         for source in disassembled.sources {
@@ -1949,6 +2400,7 @@ impl Shared {
         (all_forks, next_actions)
     }
 
+    #[cfg(feature = "warp-sync")]
     /// Transitions the sync state machine from the grandpa warp strategy to the "all-forks"
     /// strategy.
     fn transition_grandpa_warp_sync_all_forks<TRq, TSrc, TBl>(
@@ -1972,6 +2424,10 @@ impl Shared {
             max_disjoint_headers: 1024,
             max_requests_per_block: NonZeroU32::new(3).unwrap(),
             full: false,
+            custom_consensus_engines: self.custom_consensus_engines.clone(),
+            babe_relaxed_secondary_slots: self.babe_relaxed_secondary_slots,
+            sr25519_hooks: self.sr25519_hooks,
+            randomness_seed: self.randomness.sample(rand::distributions::Standard),
         });
 
         for source in grandpa.sources {
@@ -2003,6 +2459,7 @@ impl Shared {
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum RequestMapping {
     Optimistic(optimistic::RequestId),
+    #[cfg(feature = "warp-sync")]
     GrandpaWarpSync,
     AllForks(all_forks::RequestId),
 }
@@ -2010,6 +2467,7 @@ enum RequestMapping {
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum SourceMapping {
     Optimistic(optimistic::SourceId),
+    #[cfg(feature = "warp-sync")]
     GrandpaWarpSync(grandpa_warp_sync::SourceId),
     AllForks(all_forks::SourceId),
 }
@@ -146,6 +146,28 @@ pub struct Config {
 
     /// If true, the block bodies and storage are also synchronized.
     pub full: bool,
+
+    /// Identifiers of the consensus engines, unknown to smoldot, whose digest items are accepted
+    /// in block headers. Headers containing digest items of other unknown consensus engines are
+    /// refused.
+    ///
+    /// Smoldot doesn't interpret the content of these items. Before a header is accepted, its
+    /// items are yielded by [`HeaderVerify::custom_digest_items`], and the API user must check
+    /// them and call [`HeaderVerify::reject`] if they are invalid.
+    pub custom_consensus_engines: Vec<[u8; 4]>,
+
+    /// If `true`, Babe secondary slot claims are accepted regardless of the types of slot claims
+    /// allowed by the Babe configuration. See
+    /// [`verify::babe::VerifyConfig::relaxed_secondary_slots`].
+    pub babe_relaxed_secondary_slots: bool,
+
+    /// Implementations of the sr25519 primitives to use when verifying the signatures of block
+    /// headers. See [`blocks_tree::Config::sr25519_hooks`].
+    pub sr25519_hooks: Option<verify::Sr25519Hooks>,
+
+    /// Seed for the randomness used when verifying justifications and Grandpa commits. See
+    /// [`blocks_tree::Config::randomness_seed`].
+    pub randomness_seed: [u8; 32],
 }
 
 pub struct AllForksSync<TBl, TRq, TSrc> {
@@ -186,6 +208,10 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         let chain = blocks_tree::NonFinalizedTree::new(blocks_tree::Config {
             chain_information: config.chain_information,
             blocks_capacity: config.blocks_capacity,
+            custom_consensus_engines: config.custom_consensus_engines,
+            babe_relaxed_secondary_slots: config.babe_relaxed_secondary_slots,
+            sr25519_hooks: config.sr25519_hooks,
+            randomness_seed: config.randomness_seed,
         });
 
         Self {
@@ -248,6 +274,14 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         self.chain.iter()
     }
 
+    /// Returns the Sr25519 public key of the authority that has authored the given
+    /// non-finalized block.
+    ///
+    /// Returns `None` if the block isn't known or if the chain doesn't have block authors.
+    pub fn block_author(&self, hash: &[u8; 32]) -> Option<[u8; 32]> {
+        self.chain.non_finalized_block_author(hash)
+    }
+
     /// Inform the [`AllForksSync`] of a new potential source of blocks.
     ///
     /// The `user_data` parameter is opaque and decided entirely by the user. It can later be
@@ -507,7 +541,10 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
 
             // Invalid headers are skipped. The next iteration will likely fail when comparing
             // actual with expected hash, but we give it a chance.
-            let decoded_header = match header::decode(scale_encoded_header) {
+            let decoded_header = match header::decode_with_custom_engines(
+                scale_encoded_header,
+                self.chain.custom_consensus_engines(),
+            ) {
                 Ok(h) => h,
                 Err(_) => continue,
             };
@@ -602,12 +639,16 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         announced_scale_encoded_header: Vec<u8>,
         is_best: bool,
     ) -> BlockAnnounceOutcome {
-        let announced_header = match header::decode(&announced_scale_encoded_header) {
+        let announced_header = match header::decode_with_custom_engines(
+            &announced_scale_encoded_header,
+            self.chain.custom_consensus_engines(),
+        ) {
             Ok(h) => h,
             Err(error) => return BlockAnnounceOutcome::InvalidHeader(error),
         };
 
-        let announced_header_hash = announced_header.hash();
+        let announced_header_hash =
+            header::hash_from_scale_encoded_header(&announced_scale_encoded_header);
 
         match self.block_from_source(
             source_id,
@@ -960,6 +1001,46 @@ impl<TBl, TRq, TSrc> HeaderVerify<TBl, TRq, TSrc> {
         &self.block_to_verify.block_hash
     }
 
+    /// Returns the digest items of the header to verify that belong to one of the consensus
+    /// engines of [`Config::custom_consensus_engines`].
+    ///
+    /// Smoldot can't verify these items. If this list isn't empty, the API user must check the
+    /// items, then call [`HeaderVerify::perform`] if they are valid or [`HeaderVerify::reject`]
+    /// if they aren't.
+    pub fn custom_digest_items(&self) -> Vec<header::CustomDigestItem> {
+        let header = self
+            .parent
+            .inner
+            .blocks
+            .block_user_data(
+                self.block_to_verify.block_number,
+                &self.block_to_verify.block_hash,
+            )
+            .header
+            .as_ref()
+            .unwrap();
+        header::DigestRef::from(&header.digest)
+            .custom_items()
+            .collect()
+    }
+
+    /// Marks the block as invalid because its [custom digest items](Self::custom_digest_items)
+    /// have been refused, without performing the rest of the verification.
+    pub fn reject(mut self, user_data: TBl) -> HeaderVerifyOutcome<TBl, TRq, TSrc> {
+        self.parent.inner.blocks.set_block_bad(
+            self.block_to_verify.block_number,
+            &self.block_to_verify.block_hash,
+        );
+
+        HeaderVerifyOutcome::Error {
+            sync: self.parent,
+            error: HeaderVerifyError::VerificationFailed(
+                verify::header_only::Error::CustomDigestItemsRejected,
+            ),
+            user_data,
+        }
+    }
+
     /// Perform the verification.
     pub fn perform(
         mut self,
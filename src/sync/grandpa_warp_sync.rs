@@ -27,6 +27,9 @@
 //! - Verifying the fragments. Each fragment that is successfully verified progresses towards
 //! towards the head of the chain. Even if one fragment is invalid, all the previously-verified
 //! fragments can still be kept, and the warp syncing can resume from there.
+//! - If the source indicates that its proof isn't finished, requesting the rest of the proof,
+//! starting from the last verified fragment. If the source is removed or its proof is invalid,
+//! the rest of the proof is requested from a different source instead.
 //! - Downloading from a source the runtime code of the final block of the proof.
 //! - Performing some runtime calls in order to obtain the current consensus-related parameters
 //! of the chain. This might require obtaining some storage items, in which case they must also
@@ -50,9 +53,18 @@
 //! The [`InProgressGrandpaWarpSync`] enum must be examined in order to determine how to make the
 //! warp syncing process.
 //!
+//! Each source is tried at most once. Once all sources have been tried, the state machine waits
+//! for new sources to be added. The API user can inspect the health of each source with
+//! [`InProgressGrandpaWarpSync::source_already_tried`] and
+//! [`InProgressGrandpaWarpSync::source_last_error`], and can allow the state machine to try again
+//! the existing sources by calling [`InProgressGrandpaWarpSync::reset_sources`].
+//!
 //! At the end of the process, a [`Success`] is returned and can be used to kick-off another
 //! syncing phase.
 
+#![cfg(feature = "warp-sync")]
+#![cfg_attr(docsrs, doc(cfg(feature = "warp-sync")))]
+
 use crate::{
     chain::chain_information::{
         self, babe_fetch_epoch, BabeEpochInformation, ChainInformation, ChainInformationConsensus,
@@ -66,11 +78,12 @@ use crate::{
     },
     finality::grandpa::warp_sync,
     header::{Header, HeaderRef},
-    network::protocol::GrandpaWarpSyncResponse,
+    network::protocol::{EncodedGrandpaWarpSyncResponse, GrandpaWarpSyncResponseFragmentsIntoIter},
 };
 
 use alloc::vec::Vec;
 use core::convert::TryFrom as _;
+use rand::{Rng as _, SeedableRng as _};
 
 pub use warp_sync::Error as FragmentError;
 
@@ -90,12 +103,32 @@ pub enum Error {
     InvalidChain(chain_information::ValidityError),
 }
 
+/// Problem that happened the last time a source has been used.
+///
+/// See [`InProgressGrandpaWarpSync::source_last_error`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, derive_more::Display)]
+pub enum SourceError {
+    /// The source didn't answer the warp sync request.
+    #[display(fmt = "No response to the warp sync request")]
+    NoResponse,
+    /// The warp sync proof sent by the source is invalid.
+    #[display(fmt = "Invalid warp sync proof")]
+    InvalidProof,
+    /// The chain state at the block the source has warp synced to is invalid or couldn't be
+    /// obtained.
+    #[display(fmt = "Invalid chain state at the warp sync target")]
+    InvalidChainState,
+}
+
 /// The configuration for [`grandpa_warp_sync`].
 pub struct Config {
     /// The chain information of the starting point of the warp syncing.
     pub start_chain_information: ValidChainInformation,
     /// The initial capacity of the list of sources.
     pub sources_capacity: usize,
+    /// Seed for the randomness used when verifying the warp sync proofs. See
+    /// [`crate::finality::justification::verify::Config::randomness_seed`].
+    pub randomness_seed: [u8; 32],
 }
 
 /// Starts syncing via GrandPa warp sync.
@@ -103,6 +136,7 @@ pub fn grandpa_warp_sync<TSrc>(config: Config) -> InProgressGrandpaWarpSync<TSrc
     InProgressGrandpaWarpSync::WaitingForSources(WaitingForSources {
         state: PreVerificationState {
             start_chain_information: config.start_chain_information,
+            randomness: rand_chacha::ChaCha20Rng::from_seed(config.randomness_seed),
         },
         sources: slab::Slab::with_capacity(config.sources_capacity),
         previous_verifier_values: None,
@@ -195,18 +229,20 @@ impl<TSrc> GrandpaWarpSync<TSrc> {
                             },
                         }) {
                             Ok(ci) => ci,
-                            Err(err) => return (
-                                Self::InProgress(
-                                    InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
-                                        state.sources,
-                                        PreVerificationState {
-                                            start_chain_information: state.start_chain_information,
-                                        },
-                                        None,
-                                    ),
-                                ),
-                                Some(Error::InvalidChain(err)),
-                            ),
+                            Err(err) => {
+                                // `state` has been partially moved out, which prevents using
+                                // `into_next_source_request`.
+                                let next_state = PostVerificationState::next_source_request(
+                                    state.sources,
+                                    state.warp_sync_source_id,
+                                    state.start_chain_information,
+                                    state.randomness,
+                                );
+                                return (
+                                    Self::InProgress(next_state),
+                                    Some(Error::InvalidChain(err)),
+                                );
+                            }
                         };
 
                     return (
@@ -248,15 +284,7 @@ impl<TSrc> GrandpaWarpSync<TSrc> {
                     _,
                 ) => {
                     return (
-                        Self::InProgress(
-                            InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
-                                state.sources,
-                                PreVerificationState {
-                                    start_chain_information: state.start_chain_information,
-                                },
-                                None,
-                            ),
-                        ),
+                        Self::InProgress(state.into_next_source_request()),
                         Some(Error::BabeFetchEpoch(error)),
                     )
                 }
@@ -311,18 +339,7 @@ impl<TSrc> InProgressGrandpaWarpSync<TSrc> {
 
     /// Returns a list of all known sources stored in the state machine.
     pub fn sources(&'_ self) -> impl Iterator<Item = SourceId> + '_ {
-        let sources = match self {
-            Self::StorageGet(storage_get) => &storage_get.state.sources,
-            Self::NextKey(next_key) => &next_key.state.sources,
-            Self::Verifier(verifier) => &verifier.sources,
-            Self::WarpSyncRequest(warp_sync_request) => &warp_sync_request.sources,
-            Self::VirtualMachineParamsGet(virtual_machine_params_get) => {
-                &virtual_machine_params_get.state.sources
-            }
-            Self::WaitingForSources(waiting_for_sources) => &waiting_for_sources.sources,
-        };
-
-        sources.iter().map(|(id, _)| SourceId(id))
+        self.sources_list().iter().map(|(id, _)| SourceId(id))
     }
 
     /// Returns the user data (`TSrc`) corresponding to the given source.
@@ -332,17 +349,7 @@ impl<TSrc> InProgressGrandpaWarpSync<TSrc> {
     /// Panics if the [`SourceId`] is invalid.
     ///
     pub fn source_user_data(&self, source_id: SourceId) -> &TSrc {
-        let sources = match self {
-            Self::StorageGet(storage_get) => &storage_get.state.sources,
-            Self::NextKey(next_key) => &next_key.state.sources,
-            Self::Verifier(verifier) => &verifier.sources,
-            Self::WarpSyncRequest(warp_sync_request) => &warp_sync_request.sources,
-            Self::VirtualMachineParamsGet(virtual_machine_params_get) => {
-                &virtual_machine_params_get.state.sources
-            }
-            Self::WaitingForSources(waiting_for_sources) => &waiting_for_sources.sources,
-        };
-
+        let sources = self.sources_list();
         debug_assert!(sources.contains(source_id.0));
         &sources[source_id.0].user_data
     }
@@ -354,7 +361,89 @@ impl<TSrc> InProgressGrandpaWarpSync<TSrc> {
     /// Panics if the [`SourceId`] is invalid.
     ///
     pub fn source_user_data_mut(&mut self, source_id: SourceId) -> &mut TSrc {
-        let sources = match self {
+        let sources = self.sources_list_mut();
+        debug_assert!(sources.contains(source_id.0));
+        &mut sources[source_id.0].user_data
+    }
+
+    /// Returns `true` if the given source has already been asked for a warp sync proof, in which
+    /// case the state machine will not use it again unless
+    /// [`InProgressGrandpaWarpSync::reset_sources`] is called.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_already_tried(&self, source_id: SourceId) -> bool {
+        let sources = self.sources_list();
+        debug_assert!(sources.contains(source_id.0));
+        sources[source_id.0].already_tried
+    }
+
+    /// Returns the problem that happened the last time the given source has been used, if any.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SourceId`] is invalid.
+    ///
+    pub fn source_last_error(&self, source_id: SourceId) -> Option<SourceError> {
+        let sources = self.sources_list();
+        debug_assert!(sources.contains(source_id.0));
+        sources[source_id.0].last_error
+    }
+
+    /// Allows the state machine to use again all the sources that have already been tried.
+    ///
+    /// The source currently in use, if any, isn't affected. The last error of each source, as
+    /// returned by [`InProgressGrandpaWarpSync::source_last_error`], is kept.
+    ///
+    /// If the state machine is waiting for sources and at least one source is known, a warp
+    /// sync request is started towards one of them.
+    pub fn reset_sources(mut self) -> Self {
+        let current_source = match &self {
+            Self::StorageGet(storage_get) => Some(storage_get.state.warp_sync_source_id),
+            Self::NextKey(next_key) => Some(next_key.state.warp_sync_source_id),
+            Self::Verifier(verifier) => Some(verifier.warp_sync_source_id),
+            Self::WarpSyncRequest(warp_sync_request) => Some(warp_sync_request.source_id),
+            Self::VirtualMachineParamsGet(virtual_machine_params_get) => {
+                Some(virtual_machine_params_get.state.warp_sync_source_id)
+            }
+            Self::WaitingForSources(_) => None,
+        };
+
+        for (id, source) in self.sources_list_mut().iter_mut() {
+            if current_source != Some(SourceId(id)) {
+                source.already_tried = false;
+            }
+        }
+
+        match self {
+            Self::WaitingForSources(waiting_for_sources) => {
+                Self::warp_sync_request_from_next_source(
+                    waiting_for_sources.sources,
+                    waiting_for_sources.state,
+                    waiting_for_sources.previous_verifier_values,
+                )
+            }
+            other => other,
+        }
+    }
+
+    fn sources_list(&self) -> &slab::Slab<Source<TSrc>> {
+        match self {
+            Self::StorageGet(storage_get) => &storage_get.state.sources,
+            Self::NextKey(next_key) => &next_key.state.sources,
+            Self::Verifier(verifier) => &verifier.sources,
+            Self::WarpSyncRequest(warp_sync_request) => &warp_sync_request.sources,
+            Self::VirtualMachineParamsGet(virtual_machine_params_get) => {
+                &virtual_machine_params_get.state.sources
+            }
+            Self::WaitingForSources(waiting_for_sources) => &waiting_for_sources.sources,
+        }
+    }
+
+    fn sources_list_mut(&mut self) -> &mut slab::Slab<Source<TSrc>> {
+        match self {
             Self::StorageGet(storage_get) => &mut storage_get.state.sources,
             Self::NextKey(next_key) => &mut next_key.state.sources,
             Self::Verifier(verifier) => &mut verifier.sources,
@@ -363,10 +452,7 @@ impl<TSrc> InProgressGrandpaWarpSync<TSrc> {
                 &mut virtual_machine_params_get.state.sources
             }
             Self::WaitingForSources(waiting_for_sources) => &mut waiting_for_sources.sources,
-        };
-
-        debug_assert!(sources.contains(source_id.0));
-        &mut sources[source_id.0].user_data
+        }
     }
 
     fn warp_sync_request_from_next_source(
@@ -482,6 +568,7 @@ impl<TSrc> StorageGet<TSrc> {
         SourceId(self.state.sources.insert(Source {
             user_data,
             already_tried: false,
+            last_error: None,
         }))
     }
 
@@ -541,6 +628,7 @@ impl<TSrc> NextKey<TSrc> {
         SourceId(self.state.sources.insert(Source {
             user_data,
             already_tried: false,
+            last_error: None,
         }))
     }
 
@@ -564,7 +652,7 @@ impl<TSrc> NextKey<TSrc> {
 
 /// Verifying the warp sync response is required to continue.
 pub struct Verifier<TSrc> {
-    verifier: warp_sync::Verifier,
+    verifier: warp_sync::Verifier<GrandpaWarpSyncResponseFragmentsIntoIter>,
     state: PreVerificationState,
     warp_sync_source_id: SourceId,
     sources: slab::Slab<Source<TSrc>>,
@@ -578,6 +666,7 @@ impl<TSrc> Verifier<TSrc> {
         SourceId(self.sources.insert(Source {
             user_data,
             already_tried: false,
+            last_error: None,
         }))
     }
 
@@ -592,10 +681,17 @@ impl<TSrc> Verifier<TSrc> {
         let removed = self.sources.remove(to_remove.0).user_data;
 
         if to_remove == self.warp_sync_source_id {
+            // The fragments verified so far remain valid, and the rest of the proof is requested
+            // from a different source.
+            let previous_verifier_values = self
+                .verifier
+                .into_verified()
+                .or(self.previous_verifier_values);
+
             let next_state = InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
                 self.sources,
                 self.state,
-                self.previous_verifier_values,
+                previous_verifier_values,
             );
 
             (removed, next_state)
@@ -605,7 +701,7 @@ impl<TSrc> Verifier<TSrc> {
     }
 
     /// Verifies the next warp sync fragment in queue.
-    pub fn next(self) -> (InProgressGrandpaWarpSync<TSrc>, Result<(), FragmentError>) {
+    pub fn next(mut self) -> (InProgressGrandpaWarpSync<TSrc>, Result<(), FragmentError>) {
         match self.verifier.next() {
             Ok(warp_sync::Next::NotFinished(next_verifier)) => (
                 InProgressGrandpaWarpSync::Verifier(Self {
@@ -630,6 +726,7 @@ impl<TSrc> Verifier<TSrc> {
                                     header,
                                     chain_information_finality,
                                     start_chain_information: self.state.start_chain_information,
+                                    randomness: self.state.randomness,
                                     sources: self.sources,
                                     warp_sync_source_id: self.warp_sync_source_id,
                                 },
@@ -649,26 +746,39 @@ impl<TSrc> Verifier<TSrc> {
                     )
                 }
             }
-            Err(error) => (
-                InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
-                    self.sources,
-                    self.state,
-                    self.previous_verifier_values,
-                ),
-                Err(error),
-            ),
+            Err(error) => {
+                debug_assert!(self.sources.contains(self.warp_sync_source_id.0));
+                self.sources[self.warp_sync_source_id.0].last_error =
+                    Some(SourceError::InvalidProof);
+
+                (
+                    InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
+                        self.sources,
+                        self.state,
+                        error
+                            .verified
+                            .map(|verified| *verified)
+                            .or(self.previous_verifier_values),
+                    ),
+                    Err(error.error),
+                )
+            }
         }
     }
 }
 
 struct PreVerificationState {
     start_chain_information: ValidChainInformation,
+    /// Source of the seeds passed to [`warp_sync::Verifier::new`].
+    randomness: rand_chacha::ChaCha20Rng,
 }
 
 struct PostVerificationState<TSrc> {
     header: Header,
     chain_information_finality: ChainInformationFinality,
     start_chain_information: ValidChainInformation,
+    /// See [`PreVerificationState::randomness`].
+    randomness: rand_chacha::ChaCha20Rng,
     sources: slab::Slab<Source<TSrc>>,
     warp_sync_source_id: SourceId,
 }
@@ -686,6 +796,7 @@ impl<TSrc> PostVerificationState<TSrc> {
                         self.sources,
                         PreVerificationState {
                             start_chain_information: self.start_chain_information,
+                            randomness: self.randomness,
                         },
                         None,
                     ),
@@ -697,6 +808,40 @@ impl<TSrc> PostVerificationState<TSrc> {
     }
 }
 
+impl<TSrc> PostVerificationState<TSrc> {
+    /// Marks the current source as having provided an invalid chain state, and starts a warp
+    /// sync request from the next source.
+    fn into_next_source_request(self) -> InProgressGrandpaWarpSync<TSrc> {
+        Self::next_source_request(
+            self.sources,
+            self.warp_sync_source_id,
+            self.start_chain_information,
+            self.randomness,
+        )
+    }
+
+    /// Same as [`PostVerificationState::into_next_source_request`], but accepts the fields
+    /// individually.
+    fn next_source_request(
+        mut sources: slab::Slab<Source<TSrc>>,
+        warp_sync_source_id: SourceId,
+        start_chain_information: ValidChainInformation,
+        randomness: rand_chacha::ChaCha20Rng,
+    ) -> InProgressGrandpaWarpSync<TSrc> {
+        debug_assert!(sources.contains(warp_sync_source_id.0));
+        sources[warp_sync_source_id.0].last_error = Some(SourceError::InvalidChainState);
+
+        InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
+            sources,
+            PreVerificationState {
+                start_chain_information,
+                randomness,
+            },
+            None,
+        )
+    }
+}
+
 enum StateRemoveSourceResult<TSrc> {
     RemovedCurrent(InProgressGrandpaWarpSync<TSrc>),
     RemovedOther(PostVerificationState<TSrc>),
@@ -735,6 +880,7 @@ impl<TSrc> WarpSyncRequest<TSrc> {
         SourceId(self.sources.insert(Source {
             user_data,
             already_tried: false,
+            last_error: None,
         }))
     }
 
@@ -764,7 +910,7 @@ impl<TSrc> WarpSyncRequest<TSrc> {
     /// Submit a GrandPa warp sync response if the request succeeded or `None` if it did not.
     pub fn handle_response(
         mut self,
-        response: Option<GrandpaWarpSyncResponse>,
+        response: Option<EncodedGrandpaWarpSyncResponse>,
     ) -> InProgressGrandpaWarpSync<TSrc> {
         debug_assert!(self.sources.contains(self.source_id.0));
 
@@ -772,19 +918,30 @@ impl<TSrc> WarpSyncRequest<TSrc> {
 
         match response {
             Some(response) => {
-                let final_set_of_fragments = response.is_finished;
+                let final_set_of_fragments = response.is_finished();
+                let randomness_seed = self.state.randomness.sample(rand::distributions::Standard);
 
                 let verifier = match &self.previous_verifier_values {
-                    Some((_, chain_information_finality)) => warp_sync::Verifier::new(
+                    Some((header, chain_information_finality)) => warp_sync::Verifier::new(
+                        (header.number, header.hash()),
                         chain_information_finality.into(),
-                        response.fragments,
-                        final_set_of_fragments,
-                    ),
-                    None => warp_sync::Verifier::new(
-                        self.state.start_chain_information.as_ref().finality,
-                        response.fragments,
+                        response.into_fragments(),
                         final_set_of_fragments,
+                        randomness_seed,
                     ),
+                    None => {
+                        let start_chain_information = self.state.start_chain_information.as_ref();
+                        warp_sync::Verifier::new(
+                            (
+                                start_chain_information.finalized_block_header.number,
+                                start_chain_information.finalized_block_header.hash(),
+                            ),
+                            start_chain_information.finality,
+                            response.into_fragments(),
+                            final_set_of_fragments,
+                            randomness_seed,
+                        )
+                    }
                 };
 
                 InProgressGrandpaWarpSync::Verifier(Verifier {
@@ -796,11 +953,14 @@ impl<TSrc> WarpSyncRequest<TSrc> {
                     previous_verifier_values: self.previous_verifier_values,
                 })
             }
-            None => InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
-                self.sources,
-                self.state,
-                self.previous_verifier_values,
-            ),
+            None => {
+                self.sources[self.source_id.0].last_error = Some(SourceError::NoResponse);
+                InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
+                    self.sources,
+                    self.state,
+                    self.previous_verifier_values,
+                )
+            }
         }
     }
 }
@@ -834,6 +994,7 @@ impl<TSrc> VirtualMachineParamsGet<TSrc> {
         SourceId(self.state.sources.insert(Source {
             user_data,
             already_tried: false,
+            last_error: None,
         }))
     }
 
@@ -849,15 +1010,7 @@ impl<TSrc> VirtualMachineParamsGet<TSrc> {
             Some(code) => code,
             None => {
                 return (
-                    GrandpaWarpSync::InProgress(
-                        InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
-                            self.state.sources,
-                            PreVerificationState {
-                                start_chain_information: self.state.start_chain_information,
-                            },
-                            None,
-                        ),
-                    ),
+                    GrandpaWarpSync::InProgress(self.state.into_next_source_request()),
                     Some(Error::MissingCode),
                 )
             }
@@ -868,15 +1021,7 @@ impl<TSrc> VirtualMachineParamsGet<TSrc> {
                 Ok(hp) => hp,
                 Err(err) => {
                     return (
-                        GrandpaWarpSync::InProgress(
-                            InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
-                                self.state.sources,
-                                PreVerificationState {
-                                    start_chain_information: self.state.start_chain_information,
-                                },
-                                None,
-                            ),
-                        ),
+                        GrandpaWarpSync::InProgress(self.state.into_next_source_request()),
                         Some(Error::InvalidHeapPages(err)),
                     )
                 }
@@ -899,15 +1044,7 @@ impl<TSrc> VirtualMachineParamsGet<TSrc> {
                 (grandpa_warp_sync, error)
             }
             Err(error) => (
-                GrandpaWarpSync::InProgress(
-                    InProgressGrandpaWarpSync::warp_sync_request_from_next_source(
-                        self.state.sources,
-                        PreVerificationState {
-                            start_chain_information: self.state.start_chain_information,
-                        },
-                        None,
-                    ),
-                ),
+                GrandpaWarpSync::InProgress(self.state.into_next_source_request()),
                 Some(Error::NewRuntime(error)),
             ),
         }
@@ -916,7 +1053,8 @@ impl<TSrc> VirtualMachineParamsGet<TSrc> {
 
 /// Adding more sources of GrandPa warp sync data to is required to continue.
 pub struct WaitingForSources<TSrc> {
-    /// List of sources. It is guaranteed that they all have `already_tried` equal to `true`.
+    /// List of sources. It is guaranteed that they all have `already_tried` equal to `true`,
+    /// except within [`InProgressGrandpaWarpSync::reset_sources`].
     sources: slab::Slab<Source<TSrc>>,
     state: PreVerificationState,
     previous_verifier_values: Option<(Header, ChainInformationFinality)>,
@@ -928,6 +1066,7 @@ impl<TSrc> WaitingForSources<TSrc> {
         let source_id = SourceId(self.sources.insert(Source {
             user_data,
             already_tried: false,
+            last_error: None,
         }));
 
         WarpSyncRequest {
@@ -957,4 +1096,209 @@ struct Source<TSrc> {
     /// `true` if this source has been in a past `WarpSyncRequest`. `false` if the source is
     /// currently in a `WarpSyncRequest`.
     already_tried: bool,
+    /// Problem that happened the last time this source has been used, if any.
+    last_error: Option<SourceError>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        grandpa_warp_sync, Config, Error, GrandpaWarpSync, InProgressGrandpaWarpSync, SourceError,
+        SourceId,
+    };
+    use crate::{
+        chain::chain_information::{
+            ChainInformation, ChainInformationConsensus, ChainInformationFinality,
+            ValidChainInformation,
+        },
+        executor::vm::ExecHint,
+        header::{self, GrandpaAuthority},
+        network::protocol::EncodedGrandpaWarpSyncResponse,
+    };
+
+    use core::{convert::TryFrom as _, num::NonZeroU64};
+
+    const COMPLETE_PROOF: &str = include_str!("../finality/test-vectors/warp-sync-complete.json");
+    const EMPTY_PROOF: &str = include_str!("../finality/test-vectors/warp-sync-empty.json");
+
+    fn hex(value: &serde_json::Value) -> Vec<u8> {
+        hex::decode(value.as_str().unwrap().trim_start_matches("0x")).unwrap()
+    }
+
+    /// Returns the proof found in the given test vector.
+    fn proof(test_vector: &str) -> EncodedGrandpaWarpSyncResponse {
+        let test_vector: serde_json::Value = serde_json::from_str(test_vector).unwrap();
+        EncodedGrandpaWarpSyncResponse::new(hex(&test_vector["proof"])).unwrap()
+    }
+
+    /// Starts a warp sync from a genesis block whose GrandPa authorities are the ones of the
+    /// test vectors, and adds two sources to it. The first source is asked for a proof.
+    fn start() -> InProgressGrandpaWarpSync<u32> {
+        let test_vector: serde_json::Value = serde_json::from_str(COMPLETE_PROOF).unwrap();
+        let authorities = test_vector["authorities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|authority| GrandpaAuthority {
+                public_key: <[u8; 32]>::try_from(&hex(&authority["publicKey"])[..]).unwrap(),
+                weight: NonZeroU64::new(authority["weight"].as_u64().unwrap()).unwrap(),
+            })
+            .collect();
+
+        let start_chain_information = ValidChainInformation::try_from(ChainInformation {
+            finalized_block_header: header::Header {
+                parent_hash: [0; 32],
+                number: 0,
+                state_root: [0; 32],
+                extrinsics_root: [0; 32],
+                digest: header::DigestRef::empty().into(),
+            },
+            consensus: ChainInformationConsensus::AllAuthorized,
+            finality: ChainInformationFinality::Grandpa {
+                after_finalized_block_authorities_set_id: 0,
+                finalized_triggered_authorities: authorities,
+                finalized_scheduled_change: None,
+            },
+        })
+        .unwrap();
+
+        let mut request = match grandpa_warp_sync(Config {
+            start_chain_information,
+            sources_capacity: 2,
+            randomness_seed: [0; 32],
+        }) {
+            InProgressGrandpaWarpSync::WaitingForSources(waiting) => waiting.add_source(0),
+            _ => panic!(),
+        };
+        request.add_source(1);
+        InProgressGrandpaWarpSync::WarpSyncRequest(request)
+    }
+
+    /// Answers the ongoing warp sync request with the given response, then verifies all the
+    /// fragments of the response.
+    fn respond(
+        sync: InProgressGrandpaWarpSync<u32>,
+        expected_source: u32,
+        response: Option<EncodedGrandpaWarpSyncResponse>,
+    ) -> InProgressGrandpaWarpSync<u32> {
+        let mut sync = match sync {
+            InProgressGrandpaWarpSync::WarpSyncRequest(request) => {
+                assert_eq!(*request.current_source().1, expected_source);
+                request.handle_response(response)
+            }
+            _ => panic!(),
+        };
+
+        loop {
+            sync = match sync {
+                InProgressGrandpaWarpSync::Verifier(verifier) => verifier.next().0,
+                other => break other,
+            };
+        }
+    }
+
+    /// Reports the chain state at the warp sync target as invalid.
+    fn reject_chain_state(
+        sync: InProgressGrandpaWarpSync<u32>,
+        expected_source: u32,
+    ) -> InProgressGrandpaWarpSync<u32> {
+        match sync {
+            InProgressGrandpaWarpSync::VirtualMachineParamsGet(params_get) => {
+                assert_eq!(*params_get.warp_sync_source().1, expected_source);
+                match params_get.set_virtual_machine_params(
+                    None::<Vec<u8>>,
+                    None::<Vec<u8>>,
+                    ExecHint::Oneshot,
+                ) {
+                    (GrandpaWarpSync::InProgress(sync), Some(Error::MissingCode)) => sync,
+                    _ => panic!(),
+                }
+            }
+            _ => panic!(),
+        }
+    }
+
+    /// Checks that both sources have failed with the given error, resets them, and checks that
+    /// the first source is then successfully used again.
+    fn check_reset(sync: InProgressGrandpaWarpSync<u32>, error: SourceError) {
+        assert!(matches!(
+            sync,
+            InProgressGrandpaWarpSync::WaitingForSources(_)
+        ));
+        for source in [SourceId(0), SourceId(1)] {
+            assert!(sync.source_already_tried(source));
+            assert_eq!(sync.source_last_error(source), Some(error));
+        }
+
+        let sync = sync.reset_sources();
+        assert!(!sync.source_already_tried(SourceId(0)));
+        assert!(!sync.source_already_tried(SourceId(1)));
+        // The errors are kept in order for the API user to be able to pick a source.
+        assert_eq!(sync.source_last_error(SourceId(0)), Some(error));
+
+        let sync = respond(sync, 0, Some(proof(COMPLETE_PROOF)));
+        assert!(matches!(
+            sync,
+            InProgressGrandpaWarpSync::VirtualMachineParamsGet(_)
+        ));
+        assert!(sync.source_already_tried(SourceId(0)));
+        assert!(!sync.source_already_tried(SourceId(1)));
+    }
+
+    #[test]
+    fn reset_after_no_response() {
+        let sync = start();
+        let sync = respond(sync, 0, None);
+        assert_eq!(
+            sync.source_last_error(SourceId(0)),
+            Some(SourceError::NoResponse)
+        );
+        assert_eq!(sync.source_last_error(SourceId(1)), None);
+        let sync = respond(sync, 1, None);
+        check_reset(sync, SourceError::NoResponse);
+    }
+
+    #[test]
+    fn reset_after_invalid_proof() {
+        let sync = start();
+        let sync = respond(sync, 0, Some(proof(EMPTY_PROOF)));
+        assert_eq!(
+            sync.source_last_error(SourceId(0)),
+            Some(SourceError::InvalidProof)
+        );
+        let sync = respond(sync, 1, Some(proof(EMPTY_PROOF)));
+        check_reset(sync, SourceError::InvalidProof);
+    }
+
+    #[test]
+    fn reset_after_invalid_chain_state() {
+        let sync = start();
+        let sync = respond(sync, 0, Some(proof(COMPLETE_PROOF)));
+        let sync = reject_chain_state(sync, 0);
+        assert_eq!(
+            sync.source_last_error(SourceId(0)),
+            Some(SourceError::InvalidChainState)
+        );
+        let sync = respond(sync, 1, Some(proof(COMPLETE_PROOF)));
+        let sync = reject_chain_state(sync, 1);
+        check_reset(sync, SourceError::InvalidChainState);
+    }
+
+    #[test]
+    fn reset_keeps_current_source() {
+        let sync = start();
+        let sync = match sync {
+            InProgressGrandpaWarpSync::WarpSyncRequest(request) => {
+                request.handle_response(Some(proof(COMPLETE_PROOF)))
+            }
+            _ => panic!(),
+        };
+        assert!(matches!(sync, InProgressGrandpaWarpSync::Verifier(_)));
+
+        // The source whose proof is being verified isn't available for another request.
+        let sync = sync.reset_sources();
+        assert!(matches!(sync, InProgressGrandpaWarpSync::Verifier(_)));
+        assert!(sync.source_already_tried(SourceId(0)));
+        assert!(!sync.source_already_tried(SourceId(1)));
+    }
 }
@@ -41,6 +41,15 @@
 //! The *optimism* aspect comes from the fact that, while a bad source can't corrupt the state of
 //! the local chain, and can't stall the syncing process (unless there isn't any other source
 //! available), it can still slow it down.
+//!
+//! # Batch verification of headers
+//!
+//! When only headers are synchronized, blocks can be verified either one by one with
+//! [`OptimisticSync::process_one`], or in batches with [`OptimisticSync::process_headers_batch`].
+//! In the latter case, the headers of the batch are verified and added to the chain, but the
+//! verification of their signatures, which is by far the most CPU-intensive part, is deferred.
+//! These signature checks can be performed by the API user in any way, for example in parallel
+//! on multiple threads, after which the outcome is injected back with [`HeadersBatch::finish`].
 
 // TODO: document better
 // TODO: this entire module needs clean up
@@ -50,6 +59,7 @@ use crate::{
     executor::host,
     header,
     trie::calculate_root,
+    verify,
 };
 
 use alloc::{
@@ -111,6 +121,29 @@ pub struct Config {
     /// If `Some`, the block bodies and storage are also synchronized. Contains the extra
     /// configuration.
     pub full: Option<ConfigFull>,
+
+    /// Identifiers of the consensus engines, unknown to smoldot, whose digest items are accepted
+    /// in block headers. Headers containing digest items of other unknown consensus engines are
+    /// refused.
+    ///
+    /// Smoldot doesn't interpret the content of these items. Before a header is accepted, its
+    /// items are yielded by [`Verify::custom_digest_items`] or
+    /// [`HeadersBatch::custom_digest_items`], and the API user must check them and refuse the
+    /// header if they are invalid.
+    pub custom_consensus_engines: Vec<[u8; 4]>,
+
+    /// If `true`, Babe secondary slot claims are accepted regardless of the types of slot claims
+    /// allowed by the Babe configuration. See
+    /// [`verify::babe::VerifyConfig::relaxed_secondary_slots`].
+    pub babe_relaxed_secondary_slots: bool,
+
+    /// Implementations of the sr25519 primitives to use when verifying the signatures of block
+    /// headers. See [`blocks_tree::Config::sr25519_hooks`].
+    pub sr25519_hooks: Option<verify::Sr25519Hooks>,
+
+    /// Seed for the randomness used when verifying justifications and Grandpa commits. See
+    /// [`blocks_tree::Config::randomness_seed`].
+    pub randomness_seed: [u8; 32],
 }
 
 /// See [`Config::full`].
@@ -251,6 +284,10 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
             chain_information: config.chain_information,
             blocks_capacity: usize::try_from(config.blocks_request_granularity.get())
                 .unwrap_or(usize::max_value()),
+            custom_consensus_engines: config.custom_consensus_engines,
+            babe_relaxed_secondary_slots: config.babe_relaxed_secondary_slots,
+            sr25519_hooks: config.sr25519_hooks,
+            randomness_seed: config.randomness_seed,
         };
 
         let chain = blocks_tree::NonFinalizedTree::new(blocks_tree_config.clone());
@@ -332,6 +369,32 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
         self.chain.iter()
     }
 
+    /// Returns the Sr25519 public key of the authority that has authored the given
+    /// non-finalized block.
+    ///
+    /// Returns `None` if the block isn't known or if the chain doesn't have block authors.
+    pub fn block_author(&self, hash: &[u8; 32]) -> Option<[u8; 32]> {
+        self.chain.non_finalized_block_author(hash)
+    }
+
+    /// Returns the changes to the storage made by the given non-finalized block compared to its
+    /// parent. `None` means that the key is removed.
+    ///
+    /// Returns `None` if the block isn't known. The list is empty if [`Config::full`] was
+    /// `None`, as the blocks aren't executed.
+    pub fn non_finalized_block_storage_top_trie_changes(
+        &'_ self,
+        hash: &[u8; 32],
+    ) -> Option<impl Iterator<Item = (&'_ [u8], Option<&'_ [u8]>)> + '_> {
+        let block = self.chain.non_finalized_block_user_data(hash)?;
+        Some(
+            block
+                .storage_top_trie_changes
+                .iter()
+                .map(|(k, v)| (&k[..], v.as_deref())),
+        )
+    }
+
     /// Disassembles the state machine into its raw components.
     pub fn disassemble(self) -> Disassemble<TRq, TSrc> {
         Disassemble {
@@ -659,6 +722,109 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
             chain: self.chain,
         })
     }
+
+    /// Verifies the headers of up to `max_blocks` blocks of the queue of verification, deferring
+    /// the verification of their signatures. See [the module-level documentation](self).
+    ///
+    /// A batch never contains more than one block with a justification, and this block is
+    /// always the last of the batch, as verifying a justification requires the blocks it
+    /// finalizes to be fully verified.
+    ///
+    /// If [`Config::full`] was `Some` at initialization, blocks must be verified one by one with
+    /// [`OptimisticSync::process_one`], and [`ProcessHeadersBatch::AllSync`] is always returned.
+    ///
+    /// This method takes ownership of the [`OptimisticSync`]. The [`OptimisticSync`] is yielded
+    /// back in the returned value.
+    pub fn process_headers_batch(
+        mut self,
+        max_blocks: NonZeroU32,
+        now_from_unix_epoch: Duration,
+    ) -> ProcessHeadersBatch<TRq, TSrc, TBl> {
+        if self.inner.finalized_runtime.is_some() {
+            return ProcessHeadersBatch::AllSync { sync: self };
+        }
+
+        let source_id = match self.process_one() {
+            ProcessOne::AllSync { sync } => return ProcessHeadersBatch::AllSync { sync },
+            ProcessOne::Verify(verify) => {
+                self = OptimisticSync {
+                    inner: verify.inner,
+                    chain: verify.chain,
+                };
+                match &self.inner.verification_queue[0].ty {
+                    VerificationQueueEntryTy::Queued { source, .. } => *source,
+                    _ => unreachable!(),
+                }
+            }
+        };
+
+        let previous_best_height = self.chain.best_block_header().number;
+        let mut signature_checks =
+            Vec::with_capacity(usize::try_from(max_blocks.get()).unwrap_or(usize::max_value()));
+        let mut block_hashes = Vec::with_capacity(signature_checks.capacity());
+        let mut custom_digest_items = Vec::with_capacity(signature_checks.capacity());
+        let mut header_error = None;
+        let mut pending_encoded_justification = None;
+
+        for _ in 0..max_blocks.get() {
+            // Be aware that `source_id` might refer to an obsolete source.
+            let block = match &mut self.inner.verification_queue[0].ty {
+                VerificationQueueEntryTy::Queued { blocks, .. } => match blocks.pop_front() {
+                    Some(b) => b,
+                    None => break,
+                },
+                _ => unreachable!(),
+            };
+
+            let hash = header::hash_from_scale_encoded_header(&block.scale_encoded_header);
+            match self
+                .chain
+                .verify_header_deferred(block.scale_encoded_header, now_from_unix_epoch)
+            {
+                Ok(blocks_tree::HeaderVerifySuccess::Duplicate) => {}
+                Ok(blocks_tree::HeaderVerifySuccess::Insert {
+                    insert,
+                    signature_checks: checks,
+                    ..
+                }) => {
+                    let header = insert.header().into();
+                    custom_digest_items
+                        .push(insert.header().digest.custom_items().collect::<Vec<_>>());
+                    // TODO: half of the fields of `Block` are irrelevant for headers-only
+                    insert.insert(Block {
+                        header,
+                        body: Vec::new(),
+                        justification: block.scale_encoded_justification.clone(),
+                        storage_top_trie_changes: Default::default(),
+                        offchain_storage_changes: Default::default(),
+                        user_data: block.user_data,
+                    });
+                    signature_checks.push(checks.unwrap());
+                    block_hashes.push(hash);
+
+                    if block.scale_encoded_justification.is_some() {
+                        pending_encoded_justification = block.scale_encoded_justification;
+                        break;
+                    }
+                }
+                Err(err) => {
+                    header_error = Some(err);
+                    break;
+                }
+            }
+        }
+
+        ProcessHeadersBatch::Batch(HeadersBatch {
+            sync: self,
+            signature_checks,
+            block_hashes,
+            custom_digest_items,
+            source_id,
+            previous_best_height,
+            header_error,
+            pending_encoded_justification,
+        })
+    }
 }
 
 pub struct RequestSuccessBlock<TBl> {
@@ -693,7 +859,9 @@ impl<TRq, TSrc, TBl> Verify<TRq, TSrc, TBl> {
     /// Returns the height of the block about to be verified.
     pub fn height(&self) -> u64 {
         // TODO: unwrap?
-        header::decode(self.header()).unwrap().number
+        header::decode_with_custom_engines(self.header(), self.chain.custom_consensus_engines())
+            .unwrap()
+            .number
     }
 
     /// Returns the hash of the block about to be verified.
@@ -706,6 +874,54 @@ impl<TRq, TSrc, TBl> Verify<TRq, TSrc, TBl> {
         self.inner.finalized_runtime.is_some()
     }
 
+    /// Returns the digest items of the block about to be verified that belong to one of the
+    /// consensus engines of [`Config::custom_consensus_engines`].
+    ///
+    /// Smoldot can't verify these items. If this list isn't empty, the API user must check the
+    /// items, then call [`Verify::start`] if they are valid or [`Verify::reject`] if they aren't.
+    pub fn custom_digest_items(&self) -> Vec<header::CustomDigestItem> {
+        // TODO: unwrap?
+        header::decode_with_custom_engines(self.header(), self.chain.custom_consensus_engines())
+            .unwrap()
+            .digest
+            .custom_items()
+            .collect()
+    }
+
+    /// Refuses the block because its [custom digest items](Self::custom_digest_items) are
+    /// invalid. The source the block comes from is banned, similar to when the verification of
+    /// the block fails.
+    pub fn reject(mut self) -> BlockVerification<TRq, TSrc, TBl> {
+        // Be aware that `source_id` might refer to an obsolete source.
+        let source_id = match &mut self.inner.verification_queue[0].ty {
+            VerificationQueueEntryTy::Queued { blocks, source } => {
+                blocks.pop_front().unwrap();
+                *source
+            }
+            _ => unreachable!(),
+        };
+
+        if let Some(src) = self.inner.sources.get_mut(&source_id) {
+            src.banned = true;
+        }
+        self.inner.cancelling_requests = true;
+        self.inner.best_to_finalized_storage_diff = Default::default();
+        self.inner.best_runtime = None;
+        self.inner.top_trie_root_calculation_cache = None;
+
+        let previous_best_height = self.chain.best_block_header().number;
+        BlockVerification::Reset {
+            sync: OptimisticSync {
+                inner: self.inner,
+                chain: self.chain,
+            },
+            previous_best_height,
+            reason: ResetCause::HeaderError(blocks_tree::HeaderVerifyError::VerificationFailed(
+                verify::header_only::Error::CustomDigestItemsRejected,
+            )),
+        }
+    }
+
     /// Returns the SCALE-encoded header of the block about to be verified.
     fn header(&self) -> &[u8] {
         &self
@@ -813,6 +1029,153 @@ impl<TRq, TSrc, TBl> Verify<TRq, TSrc, TBl> {
     }
 }
 
+/// Outcome of calling [`OptimisticSync::process_headers_batch`].
+pub enum ProcessHeadersBatch<TRq, TSrc, TBl> {
+    /// No processing is necessary.
+    AllSync {
+        /// The state machine.
+        /// The [`OptimisticSync::process_headers_batch`] method takes ownership of the
+        /// [`OptimisticSync`]. This field yields it back.
+        sync: OptimisticSync<TRq, TSrc, TBl>,
+    },
+
+    /// Headers have been added to the chain, and their signatures must now be verified.
+    Batch(HeadersBatch<TRq, TSrc, TBl>),
+}
+
+/// Batch of headers that have been added to the chain, but whose signatures haven't been
+/// verified yet.
+///
+/// The signatures returned by [`HeadersBatch::signature_checks`] and the items returned by
+/// [`HeadersBatch::custom_digest_items`] must be verified, then [`HeadersBatch::finish`] called
+/// with the outcome. Destroying this object without calling [`HeadersBatch::finish`] destroys
+/// the [`OptimisticSync`] as well.
+#[must_use]
+pub struct HeadersBatch<TRq, TSrc, TBl> {
+    /// The state machine, containing the blocks of the batch.
+    sync: OptimisticSync<TRq, TSrc, TBl>,
+    /// Signatures of the blocks of the batch, in the order in which they have been added to the
+    /// chain.
+    signature_checks: Vec<verify::header_only::SignatureChecks>,
+    /// Hashes of the blocks of the batch, in the same order as `signature_checks`.
+    block_hashes: Vec<[u8; 32]>,
+    /// Digest items of custom consensus engines of the blocks of the batch, in the same order
+    /// as `signature_checks`.
+    custom_digest_items: Vec<Vec<header::CustomDigestItem>>,
+    /// Source the blocks have been downloaded from. Might be obsolete.
+    source_id: SourceId,
+    /// Height of the best block before the batch has been added.
+    previous_best_height: u64,
+    /// If `Some`, the header that follows the last block of the batch has failed to verify.
+    header_error: Option<blocks_tree::HeaderVerifyError>,
+    /// Justification of the last block of the batch, if any.
+    pending_encoded_justification: Option<Vec<u8>>,
+}
+
+impl<TRq, TSrc, TBl> HeadersBatch<TRq, TSrc, TBl> {
+    /// Returns the signatures that must be verified, one entry per block of the batch.
+    ///
+    /// The checks don't borrow anything and can be cloned, which makes it possible to verify
+    /// them on other threads or in parallel.
+    pub fn signature_checks(&self) -> &[verify::header_only::SignatureChecks] {
+        &self.signature_checks
+    }
+
+    /// Returns the hashes of the blocks of the batch, in the same order as
+    /// [`HeadersBatch::signature_checks`].
+    pub fn block_hashes(&self) -> &[[u8; 32]] {
+        &self.block_hashes
+    }
+
+    /// Returns, for each block of the batch and in the same order as
+    /// [`HeadersBatch::signature_checks`], the digest items that belong to one of the consensus
+    /// engines of [`Config::custom_consensus_engines`].
+    ///
+    /// Smoldot can't verify these items. If any of them is invalid, pass
+    /// [`verify::header_only::Error::CustomDigestItemsRejected`] to [`HeadersBatch::finish`].
+    pub fn custom_digest_items(&self) -> &[Vec<header::CustomDigestItem>] {
+        &self.custom_digest_items
+    }
+
+    /// Verifies all the signatures of the batch in the current thread, then calls
+    /// [`HeadersBatch::finish`].
+    ///
+    /// The items returned by [`HeadersBatch::custom_digest_items`], if any, are considered as
+    /// valid. They must have been checked beforehand.
+    pub fn verify_and_finish(self) -> BlockVerification<TRq, TSrc, TBl> {
+        let outcome = self.signature_checks.iter().try_for_each(|check| {
+            check.verify_with_optional_hooks(self.sync.chain.sr25519_hooks())
+        });
+        self.finish(outcome)
+    }
+
+    /// Injects the outcome of verifying the signatures returned by
+    /// [`HeadersBatch::signature_checks`] and the items returned by
+    /// [`HeadersBatch::custom_digest_items`], and merges the batch into the state machine.
+    ///
+    /// `outcome` must be `Ok` if all the signatures and items are valid, or contain any of the
+    /// errors otherwise. If anything is invalid, the chain is reset to the latest finalized
+    /// block.
+    pub fn finish(
+        mut self,
+        outcome: Result<(), verify::header_only::Error>,
+    ) -> BlockVerification<TRq, TSrc, TBl> {
+        if let Err(error) = outcome {
+            // Blocks of the batch, and possibly their descendants, can't be removed individually
+            // from the chain. Jump back to the latest finalized block instead.
+            if let Some(source) = self.sync.inner.sources.get_mut(&self.source_id) {
+                source.banned = true;
+            }
+
+            return BlockVerification::Reset {
+                previous_best_height: self.previous_best_height,
+                sync: OptimisticSync {
+                    chain: blocks_tree::NonFinalizedTree::new(
+                        self.sync.inner.finalized_chain_information.clone(),
+                    ),
+                    inner: OptimisticSyncInner {
+                        best_to_finalized_storage_diff: Default::default(),
+                        best_runtime: None,
+                        top_trie_root_calculation_cache: None,
+                        cancelling_requests: true,
+                        ..self.sync.inner
+                    },
+                },
+                reason: ResetCause::HeaderError(
+                    blocks_tree::HeaderVerifyError::VerificationFailed(error),
+                ),
+            };
+        }
+
+        if let Some(error) = self.header_error {
+            if let Some(src) = self.sync.inner.sources.get_mut(&self.source_id) {
+                src.banned = true;
+            }
+            self.sync.inner.cancelling_requests = true;
+            self.sync.inner.best_to_finalized_storage_diff = Default::default();
+            self.sync.inner.best_runtime = None;
+            self.sync.inner.top_trie_root_calculation_cache = None;
+
+            return BlockVerification::Reset {
+                sync: self.sync,
+                previous_best_height: self.previous_best_height,
+                reason: ResetCause::HeaderError(error),
+            };
+        }
+
+        BlockVerification::from(
+            Inner::JustificationVerif(self.sync.chain),
+            BlockVerificationShared {
+                pending_encoded_justification: self.pending_encoded_justification,
+                inner: self.sync.inner,
+                block_body: Vec::new(),
+                block_user_data: None,
+                source_id: self.source_id,
+            },
+        )
+    }
+}
+
 /// State of the processing of blocks.
 pub enum BlockVerification<TRq, TSrc, TBl> {
     /// An issue happened when verifying the block or its justification, resulting in resetting
@@ -1552,3 +1915,190 @@ pub struct DisassembleSource<TSrc> {
     /// Best block that the source has reported having.
     pub best_block_number: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        BlockVerification, Config, OptimisticSync, ProcessHeadersBatch, RequestAction,
+        RequestSuccessBlock, ResetCause,
+    };
+    use crate::{chain::chain_information, header, verify};
+
+    use core::{convert::TryFrom as _, num::NonZeroU32, time::Duration};
+
+    fn child(parent: &header::Header) -> header::Header {
+        header::Header {
+            parent_hash: parent.hash(),
+            number: parent.number + 1,
+            state_root: [1; 32],
+            extrinsics_root: [2; 32],
+            digest: header::DigestRef::empty().into(),
+        }
+    }
+
+    /// Builds an [`OptimisticSync`] whose chain doesn't require any signature, and whose
+    /// verification queue contains the given headers.
+    fn sync_with_headers(
+        headers: impl Fn(&header::Header) -> Vec<header::Header>,
+    ) -> (OptimisticSync<(), (), ()>, Vec<header::Header>) {
+        let genesis = header::Header {
+            parent_hash: [0; 32],
+            number: 0,
+            state_root: [1; 32],
+            extrinsics_root: [2; 32],
+            digest: header::DigestRef::empty().into(),
+        };
+        let headers = headers(&genesis);
+
+        let chain_information = chain_information::ValidChainInformation::try_from(
+            chain_information::ChainInformation {
+                finalized_block_header: genesis,
+                consensus: chain_information::ChainInformationConsensus::AllAuthorized,
+                finality: chain_information::ChainInformationFinality::Outsourced,
+            },
+        )
+        .unwrap();
+
+        let mut sync = OptimisticSync::new(Config {
+            chain_information,
+            sources_capacity: 1,
+            blocks_capacity: 16,
+            blocks_request_granularity: NonZeroU32::new(16).unwrap(),
+            download_ahead_blocks: 16,
+            source_selection_randomness_seed: 0,
+            full: None,
+            custom_consensus_engines: Vec::new(),
+            babe_relaxed_secondary_slots: false,
+            sr25519_hooks: None,
+            randomness_seed: [0; 32],
+        });
+
+        sync.add_source((), 100);
+        let request_id = match sync.next_request_action() {
+            Some(RequestAction::Start {
+                start,
+                block_height,
+                ..
+            }) => {
+                assert_eq!(block_height.get(), 1);
+                start.start(())
+            }
+            _ => panic!(),
+        };
+
+        let _ = sync.finish_request(
+            request_id,
+            Ok(headers.iter().map(|header| RequestSuccessBlock {
+                scale_encoded_header: header.scale_encoding_vec(),
+                scale_encoded_justification: None,
+                scale_encoded_extrinsics: Vec::new(),
+                user_data: (),
+            })),
+        );
+
+        (sync, headers)
+    }
+
+    fn chain_of(num_blocks: usize) -> impl Fn(&header::Header) -> Vec<header::Header> {
+        move |genesis| {
+            let mut headers = Vec::with_capacity(num_blocks);
+            for _ in 0..num_blocks {
+                let next = child(headers.last().unwrap_or(genesis));
+                headers.push(next);
+            }
+            headers
+        }
+    }
+
+    #[test]
+    fn batches_verify_all_headers() {
+        let (mut sync, headers) = sync_with_headers(chain_of(5));
+
+        for expected in [&headers[..3], &headers[3..]] {
+            let batch = match sync
+                .process_headers_batch(NonZeroU32::new(3).unwrap(), Duration::from_secs(0))
+            {
+                ProcessHeadersBatch::Batch(batch) => batch,
+                ProcessHeadersBatch::AllSync { .. } => panic!(),
+            };
+
+            let hashes = expected.iter().map(|h| h.hash()).collect::<Vec<_>>();
+            assert_eq!(batch.block_hashes(), &hashes[..]);
+            assert_eq!(batch.signature_checks().len(), expected.len());
+
+            sync = match batch.verify_and_finish() {
+                BlockVerification::NewBest {
+                    sync,
+                    new_best_number,
+                    new_best_hash,
+                } => {
+                    assert_eq!(new_best_number, expected.last().unwrap().number);
+                    assert_eq!(new_best_hash, *hashes.last().unwrap());
+                    sync
+                }
+                _ => panic!(),
+            };
+        }
+
+        assert!(matches!(
+            sync.process_headers_batch(NonZeroU32::new(3).unwrap(), Duration::from_secs(0)),
+            ProcessHeadersBatch::AllSync { .. }
+        ));
+    }
+
+    #[test]
+    fn batch_stops_at_invalid_header() {
+        let (sync, headers) = sync_with_headers(|genesis| {
+            let mut headers = chain_of(4)(genesis);
+            headers[2].parent_hash = [0xff; 32];
+            headers
+        });
+
+        let batch = match sync
+            .process_headers_batch(NonZeroU32::new(16).unwrap(), Duration::from_secs(0))
+        {
+            ProcessHeadersBatch::Batch(batch) => batch,
+            ProcessHeadersBatch::AllSync { .. } => panic!(),
+        };
+        assert_eq!(
+            batch.block_hashes(),
+            &[headers[0].hash(), headers[1].hash()]
+        );
+
+        match batch.verify_and_finish() {
+            BlockVerification::Reset {
+                previous_best_height,
+                reason: ResetCause::HeaderError(_),
+                ..
+            } => assert_eq!(previous_best_height, 0),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn invalid_signature_resets_to_finalized() {
+        let (sync, _) = sync_with_headers(chain_of(4));
+
+        let batch = match sync
+            .process_headers_batch(NonZeroU32::new(16).unwrap(), Duration::from_secs(0))
+        {
+            ProcessHeadersBatch::Batch(batch) => batch,
+            ProcessHeadersBatch::AllSync { .. } => panic!(),
+        };
+        assert_eq!(batch.block_hashes().len(), 4);
+
+        // The blocks of the batch are already in the chain, but the reported height is the one
+        // from before the batch.
+        match batch.finish(Err(verify::header_only::Error::BadBlockNumber)) {
+            BlockVerification::Reset {
+                sync,
+                previous_best_height,
+                reason: ResetCause::HeaderError(_),
+            } => {
+                assert_eq!(previous_best_height, 0);
+                assert_eq!(sync.best_block_number(), 0);
+            }
+            _ => panic!(),
+        }
+    }
+}
@@ -32,10 +32,39 @@
 //! See the [`persisted_validation_data_parameters`] to obtain the input to pass to the runtime
 //! function. The first parameter is a `para_id` found in the chain specification of the
 //! parachain of parathread.
+//!
+//! # Candidate events
+//!
+//! Collators of a parachain produce *candidates*, which are then backed by validators of the
+//! relay chain, and later either included in the relay chain or timed out. The events that
+//! happened to candidates during a relay chain block can be obtained by calling the
+//! `ParachainHost_candidate_events` runtime function, which doesn't take any parameter, on this
+//! block. Use [`decode_candidate_events_return_value`] to decode its return value.
+//!
+//! # Messages queues
+//!
+//! Parachains exchange messages with each other (HRMP) and with the relay chain (UMP for
+//! messages towards the relay chain, DMP for messages from the relay chain). The state of these
+//! message queues is found in the storage of the relay chain, in the following storage entries,
+//! whose keys can be obtained with [`crate::metadata::storage::storage_key`]:
+//!
+//! - `Hrmp::HrmpIngressChannelsIndex` and `Hrmp::HrmpEgressChannelsIndex`, maps whose key is a
+//! `para_id` and whose value is the list of parachains that have an HRMP channel towards,
+//! respectively from, this parachain. Use [`decode_para_ids`] to decode the value.
+//! - `Hrmp::HrmpChannels`, a map whose key is the output of [`hrmp_channel_id_scale_encoded`] and
+//! whose value can be decoded with [`decode_hrmp_channel`].
+//! - `Ump::RelayDispatchQueueSize`, a map whose key is a `para_id` and whose value can be decoded
+//! with [`decode_upward_queue_size`].
+//! - `Dmp::DownwardMessageQueueHeads`, a map whose key is a `para_id` and whose value can be
+//! decoded with [`decode_message_queue_head`].
+//!
+//! The `para_id` keys of these maps are SCALE-encoded, in other words the little endian
+//! representation of the `u32`.
 
 // TODO: at the time of writing of this comment, parachains aren't shipped yet, and everything might still change
 // see https://github.com/paritytech/polkadot/blob/master/primitives/src/v1.rs for the reference version
 
+use alloc::vec::Vec;
 use core::{convert::TryFrom as _, iter};
 
 /// Produces the input to pass to the `ParachainHost_persisted_validation_data` runtime call.
@@ -124,4 +153,367 @@ fn persisted_validation_data<'a, E: nom::error::ParseError<&'a [u8]>>(
     )(bytes)
 }
 
-// TODO: add tests
+/// Attempt to decode the return value of the `ParachainHost_candidate_events` runtime call.
+pub fn decode_candidate_events_return_value(
+    scale_encoded: &[u8],
+) -> Result<Vec<CandidateEventRef>, Error> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::flat_map(
+        crate::util::nom_scale_compact_usize,
+        |num_elems| nom::multi::many_m_n(num_elems, num_elems, candidate_event),
+    ))(scale_encoded);
+    match result {
+        Ok((_, events)) => Ok(events),
+        Err(err) => Err(Error(err)),
+    }
+}
+
+/// Event that happened to a candidate during a relay chain block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateEventRef<'a> {
+    /// What happened to the candidate.
+    pub kind: CandidateEventKind,
+    /// Description of the candidate.
+    pub descriptor: CandidateDescriptorRef<'a>,
+    /// Hash of the commitments made by the candidate, such as the messages it sends.
+    pub commitments_hash: &'a [u8; 32],
+    /// Head data of the parachain produced by the candidate.
+    ///
+    /// The meaning of this data depends on the chain, but most of the time it consists in a
+    /// block hash.
+    pub head_data: &'a [u8],
+    /// Index of the availability core the candidate occupies.
+    pub core_index: u32,
+    /// Index of the group of validators responsible for the candidate. Always `None` if
+    /// [`CandidateEventRef::kind`] is [`CandidateEventKind::TimedOut`].
+    pub group_index: Option<u32>,
+}
+
+/// See [`CandidateEventRef::kind`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CandidateEventKind {
+    /// The candidate has been backed by validators and occupies a core.
+    Backed,
+    /// The candidate has been made available and included in the relay chain.
+    Included,
+    /// The candidate has failed to become available in time and has been removed from its core.
+    TimedOut,
+}
+
+/// Description of a candidate, as found in a [`CandidateEventRef`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateDescriptorRef<'a> {
+    /// Identifier of the parachain or parathread the candidate belongs to.
+    pub para_id: u32,
+    /// Hash of the relay chain block the candidate has been built against.
+    pub relay_parent: &'a [u8; 32],
+    /// Public key of the collator that has produced the candidate.
+    pub collator: &'a [u8; 32],
+    pub persisted_validation_data_hash: &'a [u8; 32],
+    /// Hash of the proof of validity of the candidate.
+    pub pov_hash: &'a [u8; 32],
+    pub erasure_root: &'a [u8; 32],
+    /// Signature of the collator of the other fields of the descriptor.
+    pub signature: &'a [u8; 64],
+    /// Hash of the head data produced by the candidate.
+    pub para_head: &'a [u8; 32],
+    /// Hash of the validation code of the parachain used to validate the candidate.
+    pub validation_code_hash: &'a [u8; 32],
+}
+
+/// Nom combinator that parses a [`CandidateEventRef`].
+fn candidate_event<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], CandidateEventRef, E> {
+    nom::branch::alt((
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[0]),
+                nom::sequence::tuple((
+                    candidate_descriptor,
+                    nom::bytes::complete::take(32u32),
+                    crate::util::nom_bytes_decode,
+                    nom::number::complete::le_u32,
+                    nom::number::complete::le_u32,
+                )),
+            ),
+            |(descriptor, commitments_hash, head_data, core_index, group_index)| {
+                CandidateEventRef {
+                    kind: CandidateEventKind::Backed,
+                    descriptor,
+                    commitments_hash: <&[u8; 32]>::try_from(commitments_hash).unwrap(),
+                    head_data,
+                    core_index,
+                    group_index: Some(group_index),
+                }
+            },
+        ),
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[1]),
+                nom::sequence::tuple((
+                    candidate_descriptor,
+                    nom::bytes::complete::take(32u32),
+                    crate::util::nom_bytes_decode,
+                    nom::number::complete::le_u32,
+                    nom::number::complete::le_u32,
+                )),
+            ),
+            |(descriptor, commitments_hash, head_data, core_index, group_index)| {
+                CandidateEventRef {
+                    kind: CandidateEventKind::Included,
+                    descriptor,
+                    commitments_hash: <&[u8; 32]>::try_from(commitments_hash).unwrap(),
+                    head_data,
+                    core_index,
+                    group_index: Some(group_index),
+                }
+            },
+        ),
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[2]),
+                nom::sequence::tuple((
+                    candidate_descriptor,
+                    nom::bytes::complete::take(32u32),
+                    crate::util::nom_bytes_decode,
+                    nom::number::complete::le_u32,
+                )),
+            ),
+            |(descriptor, commitments_hash, head_data, core_index)| CandidateEventRef {
+                kind: CandidateEventKind::TimedOut,
+                descriptor,
+                commitments_hash: <&[u8; 32]>::try_from(commitments_hash).unwrap(),
+                head_data,
+                core_index,
+                group_index: None,
+            },
+        ),
+    ))(bytes)
+}
+
+/// Nom combinator that parses a [`CandidateDescriptorRef`].
+fn candidate_descriptor<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], CandidateDescriptorRef, E> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            nom::number::complete::le_u32,
+            nom::bytes::complete::take(32u32),
+            nom::bytes::complete::take(32u32),
+            nom::bytes::complete::take(32u32),
+            nom::bytes::complete::take(32u32),
+            nom::bytes::complete::take(32u32),
+            nom::bytes::complete::take(64u32),
+            nom::bytes::complete::take(32u32),
+            nom::bytes::complete::take(32u32),
+        )),
+        |(
+            para_id,
+            relay_parent,
+            collator,
+            persisted_validation_data_hash,
+            pov_hash,
+            erasure_root,
+            signature,
+            para_head,
+            validation_code_hash,
+        )| CandidateDescriptorRef {
+            para_id,
+            relay_parent: <&[u8; 32]>::try_from(relay_parent).unwrap(),
+            collator: <&[u8; 32]>::try_from(collator).unwrap(),
+            persisted_validation_data_hash: <&[u8; 32]>::try_from(persisted_validation_data_hash)
+                .unwrap(),
+            pov_hash: <&[u8; 32]>::try_from(pov_hash).unwrap(),
+            erasure_root: <&[u8; 32]>::try_from(erasure_root).unwrap(),
+            signature: <&[u8; 64]>::try_from(signature).unwrap(),
+            para_head: <&[u8; 32]>::try_from(para_head).unwrap(),
+            validation_code_hash: <&[u8; 32]>::try_from(validation_code_hash).unwrap(),
+        },
+    )(bytes)
+}
+
+/// Returns the SCALE encoding of the identifier of the HRMP channel from `sender` to
+/// `recipient`, used as a key of the `Hrmp::HrmpChannels` storage map of the relay chain.
+pub fn hrmp_channel_id_scale_encoded(sender: u32, recipient: u32) -> [u8; 8] {
+    let mut out = [0; 8];
+    out[..4].copy_from_slice(&sender.to_le_bytes());
+    out[4..].copy_from_slice(&recipient.to_le_bytes());
+    out
+}
+
+/// Attempt to decode a SCALE-encoded list of `para_id`s, such as the values of the
+/// `Hrmp::HrmpIngressChannelsIndex` and `Hrmp::HrmpEgressChannelsIndex` storage maps.
+pub fn decode_para_ids(scale_encoded: &[u8]) -> Result<Vec<u32>, Error> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::flat_map(
+        crate::util::nom_scale_compact_usize,
+        |num_elems| nom::multi::many_m_n(num_elems, num_elems, nom::number::complete::le_u32),
+    ))(scale_encoded);
+    match result {
+        Ok((_, para_ids)) => Ok(para_ids),
+        Err(err) => Err(Error(err)),
+    }
+}
+
+/// State of an HRMP channel between two parachains, as found in the `Hrmp::HrmpChannels`
+/// storage map of the relay chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HrmpChannel {
+    /// Maximum number of messages that can be pending in the channel at once.
+    pub max_capacity: u32,
+    /// Maximum total size in bytes of the messages that can be pending in the channel at once.
+    pub max_total_size: u32,
+    /// Maximum size in bytes of a single message.
+    pub max_message_size: u32,
+    /// Number of messages currently pending in the channel.
+    pub msg_count: u32,
+    /// Total size in bytes of the messages currently pending in the channel.
+    pub total_size: u32,
+    /// Head of the message queue chain of the channel. `None` if no message has ever been sent
+    /// through this channel.
+    pub mqc_head: Option<[u8; 32]>,
+    /// Amount deposited by the sender when opening the channel.
+    pub sender_deposit: u128,
+    /// Amount deposited by the recipient when accepting the channel.
+    pub recipient_deposit: u128,
+}
+
+/// Attempt to decode a value of the `Hrmp::HrmpChannels` storage map.
+pub fn decode_hrmp_channel(scale_encoded: &[u8]) -> Result<HrmpChannel, Error> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::map(
+        nom::sequence::tuple((
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+            crate::util::nom_option_decode(nom::bytes::complete::take(32u32)),
+            nom::number::complete::le_u128,
+            nom::number::complete::le_u128,
+        )),
+        |(
+            max_capacity,
+            max_total_size,
+            max_message_size,
+            msg_count,
+            total_size,
+            mqc_head,
+            sender_deposit,
+            recipient_deposit,
+        )| HrmpChannel {
+            max_capacity,
+            max_total_size,
+            max_message_size,
+            msg_count,
+            total_size,
+            mqc_head: mqc_head.map(|h: &[u8]| <[u8; 32]>::try_from(h).unwrap()),
+            sender_deposit,
+            recipient_deposit,
+        },
+    ))(scale_encoded);
+    match result {
+        Ok((_, channel)) => Ok(channel),
+        Err(err) => Err(Error(err)),
+    }
+}
+
+/// Attempt to decode a value of the `Ump::RelayDispatchQueueSize` storage map.
+///
+/// Returns the number of messages and the total size in bytes of the messages sent by the
+/// parachain to the relay chain and not yet processed.
+pub fn decode_upward_queue_size(scale_encoded: &[u8]) -> Result<(u32, u32), Error> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::sequence::tuple((
+        nom::number::complete::le_u32,
+        nom::number::complete::le_u32,
+    )))(scale_encoded);
+    match result {
+        Ok((_, sizes)) => Ok(sizes),
+        Err(err) => Err(Error(err)),
+    }
+}
+
+/// Attempt to decode a value of the `Dmp::DownwardMessageQueueHeads` storage map.
+pub fn decode_message_queue_head(scale_encoded: &[u8]) -> Result<[u8; 32], Error> {
+    let result: nom::IResult<_, _> =
+        nom::combinator::all_consuming(nom::bytes::complete::take(32u32))(scale_encoded);
+    match result {
+        Ok((_, head)) => Ok(<[u8; 32]>::try_from(head).unwrap()),
+        Err(err) => Err(Error(err)),
+    }
+}
+
+// TODO: add tests for the persisted validation data
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn decode_hrmp_channel() {
+        let mut value = Vec::new();
+        for n in 1..=5u32 {
+            value.extend_from_slice(&n.to_le_bytes());
+        }
+        value.push(1);
+        value.extend_from_slice(&[0xaa; 32]);
+        value.extend_from_slice(&10u128.to_le_bytes());
+        value.extend_from_slice(&20u128.to_le_bytes());
+
+        assert_eq!(
+            super::decode_hrmp_channel(&value).unwrap(),
+            super::HrmpChannel {
+                max_capacity: 1,
+                max_total_size: 2,
+                max_message_size: 3,
+                msg_count: 4,
+                total_size: 5,
+                mqc_head: Some([0xaa; 32]),
+                sender_deposit: 10,
+                recipient_deposit: 20,
+            }
+        );
+
+        assert!(super::decode_hrmp_channel(&value[1..]).is_err());
+    }
+
+    #[test]
+    fn decode_candidate_events() {
+        let mut value = vec![8];
+        // Backed.
+        value.push(0);
+        value.extend_from_slice(&2000u32.to_le_bytes());
+        value.extend_from_slice(&[1; 32 * 5 + 64 + 32 * 2]);
+        value.extend_from_slice(&[2; 32]);
+        value.extend_from_slice(&[12, 3, 3, 3]);
+        value.extend_from_slice(&7u32.to_le_bytes());
+        value.extend_from_slice(&9u32.to_le_bytes());
+        // Timed out.
+        value.push(2);
+        value.extend_from_slice(&1000u32.to_le_bytes());
+        value.extend_from_slice(&[1; 32 * 5 + 64 + 32 * 2]);
+        value.extend_from_slice(&[2; 32]);
+        value.push(0);
+        value.extend_from_slice(&4u32.to_le_bytes());
+
+        let events = super::decode_candidate_events_return_value(&value).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, super::CandidateEventKind::Backed);
+        assert_eq!(events[0].descriptor.para_id, 2000);
+        assert_eq!(events[0].descriptor.signature, &[1; 64]);
+        assert_eq!(events[0].commitments_hash, &[2; 32]);
+        assert_eq!(events[0].head_data, &[3, 3, 3]);
+        assert_eq!(events[0].core_index, 7);
+        assert_eq!(events[0].group_index, Some(9));
+        assert_eq!(events[1].kind, super::CandidateEventKind::TimedOut);
+        assert_eq!(events[1].descriptor.para_id, 1000);
+        assert!(events[1].head_data.is_empty());
+        assert_eq!(events[1].core_index, 4);
+        assert_eq!(events[1].group_index, None);
+
+        assert!(super::decode_candidate_events_return_value(&value[..value.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn decode_para_ids() {
+        let value = [8, 0xe8, 0x03, 0, 0, 0xd0, 0x07, 0, 0];
+        assert_eq!(super::decode_para_ids(&value).unwrap(), vec![1000, 2000]);
+        assert!(super::decode_para_ids(&value[..7]).is_err());
+    }
+}
@@ -86,5 +86,9 @@
 //! client also attempts to not cache that information for *too long* through heuristics.
 //!
 
+#![cfg(feature = "transactions")]
+#![cfg_attr(docsrs, doc(cfg(feature = "transactions")))]
+
+pub mod era;
 pub mod pool;
 pub mod validate;
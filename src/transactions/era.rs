@@ -0,0 +1,237 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Mortality of transactions.
+//!
+//! Signed transactions generally contain an *era*, which indicates the range of blocks during
+//! which the transaction is valid. Once the end of this range has been reached, the transaction
+//! can never be included in the chain anymore and can be discarded.
+//!
+//! A transaction whose era is [`Era::Immortal`] is valid forever (or, more precisely, until the
+//! nonce of its sender has moved past the nonce of the transaction).
+//!
+//! A mortal era consists of a `period` and a `phase`. The transaction is valid starting from its
+//! *birth* block, which is the block whose number modulo `period` is equal to `phase`, and for
+//! `period` blocks afterwards. The birth block must be provided by the author of the transaction,
+//! which typically picks the best block at the time when the transaction is built.
+//!
+//! # Finding the era of a transaction
+//!
+//! The era of a transaction is found in its *extra* data, alongside the other signed extensions.
+//! Use [`decode_extrinsic_era`] in order to extract it, passing the format of the extrinsics
+//! obtained from the metadata of the runtime (see [`crate::metadata::extrinsic`]).
+
+use crate::metadata::extrinsic::{ExtrinsicFormat, SkipError};
+use core::convert::TryFrom as _;
+
+/// Era of a transaction.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Era {
+    /// Transaction is valid forever.
+    Immortal,
+    /// Transaction is valid during `period` blocks, starting at its birth block.
+    Mortal {
+        /// Number of blocks during which the transaction is valid. Always a power of two
+        /// between 4 and 65536.
+        period: u64,
+        /// Birth block number modulo `period`.
+        phase: u64,
+    },
+}
+
+impl Era {
+    /// Decodes a SCALE-encoded era. Returns the era and the number of bytes that have been read.
+    pub fn decode(scale_encoded: &[u8]) -> Result<(Era, usize), Error> {
+        match scale_encoded {
+            [0, ..] => Ok((Era::Immortal, 1)),
+            [first, second, ..] => {
+                let encoded = u64::from(*first) | (u64::from(*second) << 8);
+                let period = 2u64 << (encoded % (1 << 4));
+                let quantize_factor = (period >> 12).max(1);
+                let phase = (encoded >> 4) * quantize_factor;
+                if period >= 4 && phase < period {
+                    Ok((Era::Mortal { period, phase }, 2))
+                } else {
+                    Err(Error::InvalidEra)
+                }
+            }
+            _ => Err(Error::TooShort),
+        }
+    }
+
+    /// Returns the number of the block where a transaction with this era is born, assuming that
+    /// `current_block` is a block during which the transaction is valid.
+    ///
+    /// Returns `0` for [`Era::Immortal`].
+    pub fn birth(&self, current_block: u64) -> u64 {
+        match *self {
+            Era::Immortal => 0,
+            Era::Mortal { period, phase } => {
+                (current_block.max(phase) - phase) / period * period + phase
+            }
+        }
+    }
+
+    /// Returns the number of the first block where a transaction with this era is no longer
+    /// valid, assuming that `current_block` is a block during which the transaction is valid.
+    ///
+    /// Returns `None` for [`Era::Immortal`].
+    pub fn death(&self, current_block: u64) -> Option<u64> {
+        match *self {
+            Era::Immortal => None,
+            Era::Mortal { period, .. } => Some(self.birth(current_block).saturating_add(period)),
+        }
+    }
+}
+
+/// Finds the era of the given SCALE-encoded extrinsic.
+///
+/// `format` describes the layout of the extrinsics of the runtime, and must have been obtained
+/// from its metadata.
+///
+/// The extrinsic must include its SCALE-compact length prefix, as is the case for extrinsics
+/// submitted through the JSON-RPC interface or found in block bodies.
+///
+/// Returns `Ok(None)` if the extrinsic is unsigned or if the runtime doesn't have any signed
+/// extension that contains an era.
+pub fn decode_extrinsic_era(
+    scale_encoded_extrinsic: &[u8],
+    format: &ExtrinsicFormat,
+) -> Result<Option<Era>, Error> {
+    let (bytes, length) = skip_scale_compact(scale_encoded_extrinsic)?;
+    if u128::try_from(bytes.len()).map_or(true, |len| len != length) {
+        return Err(Error::LengthMismatch);
+    }
+
+    let (version, bytes) = bytes.split_first().ok_or(Error::TooShort)?;
+    if (version & 0x7f) != format.version() {
+        return Err(Error::UnsupportedVersion(version & 0x7f));
+    }
+    if (version & 0x80) == 0 {
+        return Ok(None);
+    }
+
+    let bytes = format.skip_address(bytes).map_err(Error::Layout)?;
+    let mut bytes = format.skip_signature(bytes).map_err(Error::Layout)?;
+
+    // Extra data of each signed extension, one after the other.
+    for (index, extension) in format.signed_extensions().enumerate() {
+        if extension == "CheckMortality" || extension == "CheckEra" {
+            return Ok(Some(Era::decode(bytes)?.0));
+        }
+        bytes = format
+            .skip_signed_extension_extra(index, bytes)
+            .map_err(Error::Layout)?;
+    }
+
+    Ok(None)
+}
+
+/// Error potentially returned by [`Era::decode`] and [`decode_extrinsic_era`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Unexpected end of data.
+    TooShort,
+    /// Length prefix of the extrinsic doesn't match its actual length.
+    LengthMismatch,
+    /// Extrinsic version isn't supported.
+    #[display(fmt = "Unsupported extrinsic version: {}", _0)]
+    UnsupportedVersion(u8),
+    /// Failed to skip over the fields that precede the era.
+    #[display(fmt = "{}", _0)]
+    Layout(SkipError),
+    /// Encoded era is invalid.
+    InvalidEra,
+}
+
+/// Skips over a SCALE-compact-encoded number. Returns the rest of the data and the number.
+fn skip_scale_compact(bytes: &[u8]) -> Result<(&[u8], u128), Error> {
+    crate::util::nom_scale_compact_u128::<nom::error::Error<&[u8]>>(bytes)
+        .map_err(|_| Error::TooShort)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_extrinsic_era, Era};
+
+    #[test]
+    fn era_decode() {
+        assert_eq!(Era::decode(&[0]).unwrap(), (Era::Immortal, 1));
+        // Period 64, phase 42. Example taken from Substrate.
+        assert_eq!(
+            Era::decode(&[0xa5, 0x02]).unwrap(),
+            (
+                Era::Mortal {
+                    period: 64,
+                    phase: 42
+                },
+                2
+            )
+        );
+        assert!(Era::decode(&[0x05]).is_err());
+    }
+
+    #[test]
+    fn birth_and_death() {
+        let era = Era::Mortal {
+            period: 64,
+            phase: 42,
+        };
+        assert_eq!(era.birth(1000), 1002 - 64);
+        assert_eq!(era.death(1000), Some(1002));
+        assert_eq!(era.birth(1002), 1002);
+        assert_eq!(era.death(1002), Some(1066));
+        assert_eq!(Era::Immortal.death(1000), None);
+    }
+
+    #[test]
+    fn extrinsic_era() {
+        let metadata = crate::metadata::extrinsic::tests::example_metadata_v14();
+        let format = crate::metadata::extrinsic::decode(&metadata).unwrap();
+
+        // Signed extrinsic, with a `MultiAddress::Id` address and a Sr25519 signature,
+        // followed with the custom extension, the era, the nonce, the tip, and an empty call.
+        let mut extrinsic = vec![0x84, 0x00];
+        extrinsic.extend_from_slice(&[0xaa; 32]);
+        extrinsic.push(0x01);
+        extrinsic.extend_from_slice(&[0xbb; 64]);
+        extrinsic.extend_from_slice(&[0x01, 0x02, 0x03, 0x04, 0xa5, 0x02, 0x04, 0x00]);
+        let extrinsic = {
+            let mut with_length = crate::util::encode_scale_compact_usize(extrinsic.len())
+                .as_ref()
+                .to_vec();
+            with_length.extend_from_slice(&extrinsic);
+            with_length
+        };
+        assert_eq!(
+            decode_extrinsic_era(&extrinsic, &format).unwrap(),
+            Some(Era::Mortal {
+                period: 64,
+                phase: 42
+            })
+        );
+
+        // Unsigned extrinsic.
+        assert_eq!(
+            decode_extrinsic_era(&[0x0c, 0x04, 0x00, 0x00], &format).unwrap(),
+            None
+        );
+
+        // Length mismatch.
+        assert!(decode_extrinsic_era(&[0x10, 0x04, 0x00], &format).is_err());
+    }
+}
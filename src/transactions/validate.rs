@@ -195,27 +195,64 @@ pub enum TransactionValidityError {
     Unknown(UnknownTransaction),
 }
 
+/// Name of the runtime function to call in order to validate a transaction.
+pub const VALIDATION_FUNCTION_NAME: &str = "TaggedTransactionQueue_validate_transaction";
+
+/// Returns the parameter to pass to the [`VALIDATION_FUNCTION_NAME`] runtime function, as an
+/// iterator of buffers that must be concatenated.
+///
+/// Use this function, alongside with [`decode_validate_transaction_return_value`], if the
+/// runtime call is performed by other means than [`validate_transaction`].
+pub fn validate_transaction_runtime_parameters(
+    scale_encoded_transaction: impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone,
+    source: TransactionSource,
+) -> impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone {
+    // The `TaggedTransactionQueue_validate_transaction` function expects a SCALE-encoded
+    // `(source, tx)`. The encoding is performed manually in order to avoid performing
+    // redundant data copies.
+    let source: &'static [u8] = match source {
+        TransactionSource::InBlock => &[0],
+        TransactionSource::Local => &[1],
+        TransactionSource::External => &[2],
+    };
+
+    iter::once(source)
+        .map(either::Either::Left)
+        .chain(scale_encoded_transaction.map(either::Either::Right))
+}
+
+/// Decodes the value returned by the [`VALIDATION_FUNCTION_NAME`] runtime function.
+///
+/// The outer `Result` contains an error if the output is invalid, while the inner `Result`
+/// contains an error if the transaction is invalid.
+pub fn decode_validate_transaction_return_value(
+    output: &[u8],
+) -> Result<Result<ValidTransaction, TransactionValidityError>, Error> {
+    let result = match nom::combinator::all_consuming(transaction_validity)(output) {
+        Ok((_, result)) => result,
+        Err(_) => return Err(Error::OutputDecodeError),
+    };
+
+    if let Ok(valid) = &result {
+        if valid.provides.is_empty() {
+            return Err(Error::EmptyProvidedTags);
+        }
+    }
+
+    Ok(result)
+}
+
 /// Validates a transaction by calling `TaggedTransactionQueue_validate_transaction`.
 pub fn validate_transaction(
     config: Config<impl ExactSizeIterator<Item = impl AsRef<[u8]> + Clone> + Clone>,
 ) -> Query {
     let vm = read_only_runtime_host::run(read_only_runtime_host::Config {
         virtual_machine: config.runtime,
-        function_to_call: "TaggedTransactionQueue_validate_transaction",
-        parameter: {
-            // The `TaggedTransactionQueue_validate_transaction` function expects a SCALE-encoded
-            // `(source, tx)`. The encoding is performed manually in order to avoid performing
-            // redundant data copies.
-            let source = match config.source {
-                TransactionSource::InBlock => &[0],
-                TransactionSource::Local => &[1],
-                TransactionSource::External => &[2],
-            };
-
-            iter::once(source)
-                .map(either::Either::Left)
-                .chain(config.scale_encoded_transaction.map(either::Either::Right))
-        },
+        function_to_call: VALIDATION_FUNCTION_NAME,
+        parameter: validate_transaction_runtime_parameters(
+            config.scale_encoded_transaction,
+            config.source,
+        ),
     });
 
     match vm {
@@ -252,40 +289,12 @@ impl Query {
     fn from_inner(inner: read_only_runtime_host::RuntimeHostVm) -> Self {
         match inner {
             read_only_runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
-                // This decoding is done in multiple steps in order to solve borrow checking
-                // errors.
-                let result = {
-                    let output = success.virtual_machine.value();
-                    let decoded =
-                        nom::combinator::all_consuming(transaction_validity)(output.as_ref());
-                    match decoded {
-                        Ok((_, s)) => Ok(s),
-                        Err(_) => Err(Error::OutputDecodeError),
-                    }
-                };
-
-                let result = match result {
-                    Ok(res) => {
-                        if let Ok(res) = res.as_ref() {
-                            if res.provides.is_empty() {
-                                return Query::Finished {
-                                    result: Err(Error::EmptyProvidedTags),
-                                    virtual_machine: success.virtual_machine.into_prototype(),
-                                };
-                            }
-                        }
-                        res
-                    }
-                    Err(err) => {
-                        return Query::Finished {
-                            result: Err(err),
-                            virtual_machine: success.virtual_machine.into_prototype(),
-                        }
-                    }
-                };
+                let result = decode_validate_transaction_return_value(
+                    success.virtual_machine.value().as_ref(),
+                );
 
                 Query::Finished {
-                    result: Ok(result),
+                    result,
                     virtual_machine: success.virtual_machine.into_prototype(),
                 }
             }
@@ -300,6 +309,9 @@ impl Query {
                 Query::StorageRoot(StorageRoot(inner))
             }
             read_only_runtime_host::RuntimeHostVm::NextKey(inner) => Query::NextKey(NextKey(inner)),
+            read_only_runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                Query::from_inner(sig.verify_and_resume())
+            }
         }
     }
 }
@@ -20,6 +20,10 @@
 //! Use the [`calculate_merkle_root`] function to calculate the Merkle value. The [`Config`]
 //! struct contains all the input required for the calculation.
 //!
+//! The [`calculate_node_value`] function instead returns the node value itself, as found in
+//! Merkle proofs. The [`decode`] function does the opposite and extracts the information from a
+//! node value.
+//!
 //! # Example
 //!
 //! ```
@@ -71,6 +75,7 @@
 use super::nibble::Nibble;
 use crate::util;
 
+use alloc::vec::Vec;
 use arrayvec::ArrayVec;
 use core::{convert::TryFrom as _, fmt};
 
@@ -127,17 +132,52 @@ where
     TPKey: ExactSizeIterator<Item = Nibble>,
     TVal: AsRef<[u8]>,
 {
-    assert_eq!(config.children.len(), 16);
-
-    let has_children = config.children.clone().any(|c| c.is_some());
-
     // This value will be used as the sink for all the components of the merkle value.
-    let mut merkle_value_sink = if matches!(config.ty, NodeTy::Root { .. }) {
+    let merkle_value_sink = if matches!(config.ty, NodeTy::Root { .. }) {
         HashOrInline::Hasher(blake2_rfc::blake2b::Blake2b::new(32))
     } else {
         HashOrInline::Inline(ArrayVec::new())
     };
 
+    encode_node(config, merkle_value_sink).finalize()
+}
+
+/// Calculates the node value of a node given the information about this node.
+///
+/// Contrary to [`calculate_merkle_root`], the node value is never hashed. The Merkle value of
+/// the node is the hash of this node value if the node is the root or if the node value is 32
+/// bytes or more, and the node value itself otherwise.
+///
+/// # Panic
+///
+/// Panics if `config.children.len() != 16`.
+///
+pub fn calculate_node_value<'a, TChIter, TPKey, TVal>(
+    config: Config<TChIter, TPKey, TVal>,
+) -> Vec<u8>
+where
+    TChIter: ExactSizeIterator<Item = Option<&'a Output>> + Clone,
+    TPKey: ExactSizeIterator<Item = Nibble>,
+    TVal: AsRef<[u8]>,
+{
+    encode_node(config, Vec::new())
+}
+
+/// Pushes the node value of the given node to `merkle_value_sink`, then returns the sink.
+fn encode_node<'a, TChIter, TPKey, TVal, TSink>(
+    config: Config<TChIter, TPKey, TVal>,
+    mut merkle_value_sink: TSink,
+) -> TSink
+where
+    TChIter: ExactSizeIterator<Item = Option<&'a Output>> + Clone,
+    TPKey: ExactSizeIterator<Item = Nibble>,
+    TVal: AsRef<[u8]>,
+    TSink: Sink,
+{
+    assert_eq!(config.children.len(), 16);
+
+    let has_children = config.children.clone().any(|c| c.is_some());
+
     // For node value calculation purposes, the root key is treated the same as the partial key.
     let mut partial_key = match config.ty {
         NodeTy::Root { key } => key,
@@ -208,7 +248,7 @@ where
             merkle_value_sink.update(stored_value.as_ref());
         }
 
-        return merkle_value_sink.finalize();
+        return merkle_value_sink;
     }
 
     // If there is any child, we a `u16` where each bit is `1` if there exists a child there.
@@ -247,7 +287,130 @@ where
         merkle_value_sink.update(stored_value.as_ref());
     }
 
-    merkle_value_sink.finalize()
+    merkle_value_sink
+}
+
+/// Decodes the given node value.
+///
+/// The partial key, the Merkle values of the children, and the storage value all point within
+/// `node_value`. The root node is encoded the same way as other nodes, in which case the
+/// partial key is the key of the root node.
+pub fn decode(mut node_value: &[u8]) -> Result<Decoded<'_>, Error> {
+    let (&header, rest) = node_value.split_first().ok_or(Error::TooShort)?;
+    node_value = rest;
+
+    let has_children = (header & 0x80) != 0;
+    let has_storage_value = (header & 0x40) != 0;
+
+    // Length of the partial key, in nibbles.
+    let mut pk_len = usize::from(header & 0x3f);
+    if pk_len == 63 {
+        loop {
+            let (&byte, rest) = node_value.split_first().ok_or(Error::TooShort)?;
+            node_value = rest;
+            pk_len = pk_len
+                .checked_add(usize::from(byte))
+                .ok_or(Error::PartialKeyTooLong)?;
+            if byte != 255 {
+                break;
+            }
+        }
+    }
+
+    // Length of the partial key, in bytes.
+    let pk_len_bytes = pk_len / 2 + pk_len % 2;
+    if node_value.len() < pk_len_bytes {
+        return Err(Error::TooShort);
+    }
+    let partial_key = &node_value[..pk_len_bytes];
+    node_value = &node_value[pk_len_bytes..];
+
+    let mut children = [None; 16];
+    if has_children {
+        if node_value.len() < 2 {
+            return Err(Error::TooShort);
+        }
+        let children_bitmap = u16::from_le_bytes([node_value[0], node_value[1]]);
+        node_value = &node_value[2..];
+
+        for (child_index, child) in children.iter_mut().enumerate() {
+            if children_bitmap & (1 << child_index) == 0 {
+                continue;
+            }
+
+            let (rest, len) = util::nom_scale_compact_usize(node_value)
+                .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::TooShort)?;
+            if rest.len() < len {
+                return Err(Error::TooShort);
+            }
+            *child = Some(&rest[..len]);
+            node_value = &rest[len..];
+        }
+    }
+
+    let storage_value = if has_storage_value {
+        let (rest, len) = util::nom_scale_compact_usize(node_value)
+            .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::TooShort)?;
+        if rest.len() != len {
+            return Err(Error::InvalidStorageValueLength);
+        }
+        Some(rest)
+    } else if node_value.is_empty() {
+        None
+    } else {
+        return Err(Error::TrailingData);
+    };
+
+    Ok(Decoded {
+        partial_key,
+        partial_key_odd: pk_len % 2 != 0,
+        children,
+        storage_value,
+    })
+}
+
+/// Node value decoded by [`decode`].
+#[derive(Debug, Clone)]
+pub struct Decoded<'a> {
+    /// Encoded partial key. See [`Decoded::partial_key`].
+    partial_key: &'a [u8],
+    /// If `true`, the first nibble of [`Decoded::partial_key`] is padding.
+    partial_key_odd: bool,
+    /// Merkle values of the 16 possible children of the node. `None` if there is no child at
+    /// this index.
+    pub children: [Option<&'a [u8]>; 16],
+    /// Storage value of the node, if any.
+    pub storage_value: Option<&'a [u8]>,
+}
+
+impl<'a> Decoded<'a> {
+    /// Returns an iterator to the nibbles of the partial key of the node.
+    pub fn partial_key(&self) -> impl ExactSizeIterator<Item = Nibble> + Clone + 'a {
+        super::nibble::bytes_to_nibbles(self.partial_key.iter().copied())
+            .skip(if self.partial_key_odd { 1 } else { 0 })
+    }
+
+    /// Returns a bitmap where the bit `n` is set if the node has a child at index `n`.
+    pub fn children_bitmap(&self) -> u16 {
+        self.children
+            .iter()
+            .enumerate()
+            .filter(|(_, child)| child.is_some())
+            .fold(0, |bitmap, (child_index, _)| bitmap | (1 << child_index))
+    }
+}
+
+/// Error potentially returned by [`decode`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Node value is shorter than indicated by its content.
+    TooShort,
+    /// Length of the partial key overflows.
+    PartialKeyTooLong,
+    /// Length of the storage value doesn't match the rest of the node value.
+    InvalidStorageValueLength,
+    /// Node value has no storage value but contains data after the children.
+    TrailingData,
 }
 
 /// Output of the calculation.
@@ -306,6 +469,17 @@ impl fmt::Debug for Output {
     }
 }
 
+/// Destination of the components of a node value.
+trait Sink {
+    fn update(&mut self, data: &[u8]);
+}
+
+impl Sink for Vec<u8> {
+    fn update(&mut self, data: &[u8]) {
+        self.extend_from_slice(data);
+    }
+}
+
 /// The merkle value of a node is defined as either the hash of the node value, or the node value
 /// itself if it is shorted than 32 bytes (or if we are the root).
 ///
@@ -317,7 +491,7 @@ enum HashOrInline {
     Hasher(blake2_rfc::blake2b::Blake2b),
 }
 
-impl HashOrInline {
+impl Sink for HashOrInline {
     /// Adds data to the node value. If this is a [`HashOrInline::Inline`] and the total size would
     /// go above 32 bytes, then we switch to a hasher.
     fn update(&mut self, data: &[u8]) {
@@ -335,7 +509,9 @@ impl HashOrInline {
             }
         }
     }
+}
 
+impl HashOrInline {
     fn finalize(self) -> Output {
         Output {
             inner: match self {
@@ -433,4 +609,29 @@ mod tests {
             stored_value: None::<Vec<u8>>,
         });
     }
+
+    #[test]
+    fn decode_basic() {
+        let node_value = [
+            195, 8, 193, 4, 4, 12, 102, 111, 111, 12, 98, 97, 114, 44, 104, 101, 108, 108, 111, 32,
+            119, 111, 114, 108, 100,
+        ];
+
+        let decoded = super::decode(&node_value).unwrap();
+        assert_eq!(
+            decoded.partial_key().map(u8::from).collect::<Vec<_>>(),
+            vec![8, 12, 1]
+        );
+        assert_eq!(decoded.children_bitmap(), (1 << 2) | (1 << 10));
+        assert_eq!(decoded.children[2], Some(&b"foo"[..]));
+        assert_eq!(decoded.children[10], Some(&b"bar"[..]));
+        assert_eq!(decoded.storage_value, Some(&b"hello world"[..]));
+
+        assert_eq!(
+            super::decode(&node_value[..node_value.len() - 1]).unwrap_err(),
+            super::Error::InvalidStorageValueLength
+        );
+        assert_eq!(super::decode(&[]).unwrap_err(), super::Error::TooShort);
+        assert!(super::decode(&[0]).unwrap().storage_value.is_none());
+    }
 }
@@ -0,0 +1,475 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Generation of a trie proof.
+//!
+//! A trie proof is a list of node values that makes it possible for someone who only knows the
+//! Merkle value of the root node of the trie to verify the storage value of some keys. See the
+//! [`proof_verify`](super::proof_verify) module for details about the format.
+//!
+//! Use the [`build_proof`] function to generate a proof given the content of a trie and a list
+//! of keys. The proof contains, for each requested key, the node values of all the nodes
+//! between the root node and the node closest to that key, as well as the node value of the
+//! child that diverges from the requested key, if any. The proofs of all the requested keys are
+//! merged into a single list.
+//!
+//! > **Note**: The Merkle value of a node depends on the content of all its descendants.
+//! >           Generating a proof consequently requires accessing the entire content of the trie,
+//! >           even if only a few keys are requested.
+//!
+//! When multiple proofs are generated against the same trie, use a [`TrieNodes`] instead. The
+//! node values of all the nodes are then calculated only once, in [`TrieNodes::new`], and
+//! [`TrieNodes::build_proof`] only walks down the nodes on the path to the requested keys.
+
+use super::{
+    nibble::{bytes_to_nibbles, Nibble},
+    node_value,
+};
+
+use alloc::{vec, vec::Vec};
+use core::iter;
+
+/// Configuration to pass to [`build_proof`].
+pub struct Config<TEntries, TKeys> {
+    /// Iterator to all the entries of the trie, as key and storage value. No specific order is
+    /// required. If a key is found multiple times, only one of its values is used.
+    pub entries: TEntries,
+
+    /// Iterator to the keys whose storage value (or lack of storage value) must be provable by
+    /// the generated proof.
+    pub requested_keys: TKeys,
+}
+
+/// Generates a proof of the storage values of the keys designated by
+/// [`Config::requested_keys`].
+///
+/// Returns a list of node values, without any duplicate, in no specific order. The returned list
+/// is empty if no key is requested.
+pub fn build_proof<'a, 'b>(
+    config: Config<impl Iterator<Item = (&'a [u8], &'a [u8])>, impl Iterator<Item = &'b [u8]>>,
+) -> Vec<Vec<u8>> {
+    let requested_keys = config
+        .requested_keys
+        .map(|key| bytes_to_nibbles(key.iter().cloned()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    if requested_keys.is_empty() {
+        return Vec::new();
+    }
+
+    let mut entries = config
+        .entries
+        .map(|(key, value)| {
+            (
+                bytes_to_nibbles(key.iter().cloned()).collect::<Vec<_>>(),
+                value,
+            )
+        })
+        .collect::<Vec<_>>();
+    entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+    entries.dedup_by(|a, b| a.0 == b.0);
+
+    // The root node of an empty trie has no key, no children, and no storage value. Its node
+    // value is enough to prove that none of the requested keys has a storage value.
+    if entries.is_empty() {
+        return vec![node_value::calculate_node_value(node_value::Config {
+            ty: node_value::NodeTy::Root {
+                key: iter::empty::<Nibble>(),
+            },
+            children: (0..16).map(|_| None),
+            stored_value: None::<&[u8]>,
+        })];
+    }
+
+    let mut proof = Vec::new();
+    build_node(
+        &entries,
+        0,
+        &requested_keys.iter().map(|k| &k[..]).collect::<Vec<_>>(),
+        &mut proof,
+    );
+    proof.sort_unstable();
+    proof.dedup();
+    proof
+}
+
+/// Node values of all the nodes of a trie, calculated ahead of time in order to generate
+/// multiple proofs. See [the module-level documentation](..).
+pub struct TrieNodes {
+    /// List of all the nodes of the trie. The root node, if any, is the first element.
+    nodes: Vec<TrieNode>,
+}
+
+struct TrieNode {
+    /// Full key of the node.
+    key: Vec<Nibble>,
+    /// Node value of the node.
+    node_value: Vec<u8>,
+    /// Index within [`TrieNodes::nodes`] of each child of the node.
+    children: [Option<usize>; 16],
+}
+
+impl TrieNodes {
+    /// Calculates the node values of all the nodes of the trie containing the given entries,
+    /// as key and storage value. No specific order is required. If a key is found multiple
+    /// times, only one of its values is used.
+    pub fn new<'a>(entries: impl Iterator<Item = (&'a [u8], &'a [u8])>) -> Self {
+        let mut entries = entries
+            .map(|(key, value)| {
+                (
+                    bytes_to_nibbles(key.iter().cloned()).collect::<Vec<_>>(),
+                    value,
+                )
+            })
+            .collect::<Vec<_>>();
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        entries.dedup_by(|a, b| a.0 == b.0);
+
+        let mut nodes = Vec::new();
+        if entries.is_empty() {
+            nodes.push(TrieNode {
+                key: Vec::new(),
+                node_value: node_value::calculate_node_value(node_value::Config {
+                    ty: node_value::NodeTy::Root {
+                        key: iter::empty::<Nibble>(),
+                    },
+                    children: (0..16).map(|_| None),
+                    stored_value: None::<&[u8]>,
+                }),
+                children: [None; 16],
+            });
+        } else {
+            calculate_node(&entries, 0, &mut nodes);
+        }
+
+        TrieNodes { nodes }
+    }
+
+    /// Generates a proof of the storage values of the given keys.
+    ///
+    /// Returns a list of node values, without any duplicate, in no specific order. The returned
+    /// list is empty if no key is requested. The proof is identical to the one that
+    /// [`build_proof`] generates.
+    pub fn build_proof<'a>(&self, requested_keys: impl Iterator<Item = &'a [u8]>) -> Vec<Vec<u8>> {
+        let mut proof = Vec::new();
+
+        for requested_key in requested_keys {
+            let requested_key = bytes_to_nibbles(requested_key.iter().cloned()).collect::<Vec<_>>();
+
+            let mut node_index = 0;
+            loop {
+                let node = &self.nodes[node_index];
+
+                // Node values shorter than 32 bytes are directly included in the node value of
+                // their parent, and thus don't need to be part of the proof.
+                if node_index == 0 || node.node_value.len() >= 32 {
+                    proof.push(&node.node_value[..]);
+                }
+
+                if requested_key.len() <= node.key.len() || !requested_key.starts_with(&node.key) {
+                    break;
+                }
+
+                let child_index = usize::from(u8::from(requested_key[node.key.len()]));
+                match node.children[child_index] {
+                    Some(child) => node_index = child,
+                    None => break,
+                }
+            }
+        }
+
+        proof.sort_unstable();
+        proof.dedup();
+        proof
+            .into_iter()
+            .map(|node_value| node_value.to_vec())
+            .collect()
+    }
+}
+
+/// Pushes to `nodes` the node that is the closest common ancestor of all the entries of
+/// `entries`, followed with all its descendants, and returns the index of this node and its
+/// Merkle value.
+///
+/// See [`build_node`] for the meaning of the parameters.
+fn calculate_node(
+    entries: &[(Vec<Nibble>, &[u8])],
+    parent_key_len: usize,
+    nodes: &mut Vec<TrieNode>,
+) -> (usize, node_value::Output) {
+    let first_key = &entries[0].0;
+    let key_len = first_key
+        .iter()
+        .zip(entries[entries.len() - 1].0.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let (stored_value, mut children_entries) = if first_key.len() == key_len {
+        (Some(entries[0].1), &entries[1..])
+    } else {
+        (None, entries)
+    };
+
+    // The node is pushed before its children, so that the root node is the first element.
+    let node_index = nodes.len();
+    nodes.push(TrieNode {
+        key: first_key[..key_len].to_vec(),
+        node_value: Vec::new(),
+        children: [None; 16],
+    });
+
+    let mut children = (0..16).map(|_| None).collect::<Vec<_>>();
+    while !children_entries.is_empty() {
+        let child_index = usize::from(u8::from(children_entries[0].0[key_len]));
+        let num_entries = children_entries
+            .iter()
+            .take_while(|(key, _)| usize::from(u8::from(key[key_len])) == child_index)
+            .count();
+        let (child_entries, rest) = children_entries.split_at(num_entries);
+        let (child_node_index, child_merkle_value) =
+            calculate_node(child_entries, key_len + 1, nodes);
+        nodes[node_index].children[child_index] = Some(child_node_index);
+        children[child_index] = Some(child_merkle_value);
+        children_entries = rest;
+    }
+
+    let is_root = parent_key_len == 0;
+    let node_value = node_value::calculate_node_value(node_value::Config {
+        ty: if is_root {
+            node_value::NodeTy::Root {
+                key: first_key[..key_len].iter().cloned(),
+            }
+        } else {
+            node_value::NodeTy::NonRoot {
+                partial_key: first_key[parent_key_len..key_len].iter().cloned(),
+            }
+        },
+        children: children.iter().map(|c| c.as_ref()),
+        stored_value,
+    });
+
+    let merkle_value = if is_root || node_value.len() >= 32 {
+        node_value::Output::from_bytes(
+            blake2_rfc::blake2b::blake2b(32, &[], &node_value).as_bytes(),
+        )
+    } else {
+        node_value::Output::from_bytes(&node_value)
+    };
+
+    nodes[node_index].node_value = node_value;
+    (node_index, merkle_value)
+}
+
+/// Calculates the Merkle value of the node that is the closest common ancestor of all the
+/// entries of `entries`, and pushes to `proof` the node values of this node and its descendants
+/// that are necessary to prove the storage values of `requested_keys`.
+///
+/// `entries` must be non-empty and ordered by key. `parent_key_len` is the number of nibbles of
+/// the key of the node that are already known by the parent node, in other words the length of
+/// the key of the parent plus one for the child index. The node is the root node if and only if
+/// `parent_key_len` is 0.
+fn build_node(
+    entries: &[(Vec<Nibble>, &[u8])],
+    parent_key_len: usize,
+    requested_keys: &[&[Nibble]],
+    proof: &mut Vec<Vec<u8>>,
+) -> node_value::Output {
+    // Since `entries` is ordered, the longest prefix shared by all the keys is the prefix shared
+    // by the first and the last keys.
+    let first_key = &entries[0].0;
+    let key_len = first_key
+        .iter()
+        .zip(entries[entries.len() - 1].0.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    // The node value is needed in order to verify a requested key if the verification reaches
+    // this node, in other words if the key starts with the part of the key known by the parent.
+    // This includes the requested keys that diverge from the partial key of this node.
+    let requested_keys = requested_keys
+        .iter()
+        .filter(|k| k.starts_with(&first_key[..parent_key_len]))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // If the first entry has the same key as the node, it is the storage value of the node.
+    let (stored_value, mut children_entries) = if first_key.len() == key_len {
+        (Some(entries[0].1), &entries[1..])
+    } else {
+        (None, entries)
+    };
+
+    let mut children = (0..16).map(|_| None).collect::<Vec<_>>();
+    while !children_entries.is_empty() {
+        let child_index = children_entries[0].0[key_len];
+        let num_entries = children_entries
+            .iter()
+            .take_while(|(key, _)| key[key_len] == child_index)
+            .count();
+        let (child_entries, rest) = children_entries.split_at(num_entries);
+        children[usize::from(u8::from(child_index))] = Some(build_node(
+            child_entries,
+            key_len + 1,
+            &requested_keys,
+            proof,
+        ));
+        children_entries = rest;
+    }
+
+    let is_root = parent_key_len == 0;
+    let config = node_value::Config {
+        ty: if is_root {
+            node_value::NodeTy::Root {
+                key: first_key[..key_len].iter().cloned(),
+            }
+        } else {
+            node_value::NodeTy::NonRoot {
+                partial_key: first_key[parent_key_len..key_len].iter().cloned(),
+            }
+        },
+        children: children.iter().map(|c| c.as_ref()),
+        stored_value,
+    };
+
+    if requested_keys.is_empty() {
+        return node_value::calculate_merkle_root(config);
+    }
+
+    // Node values shorter than 32 bytes are directly included in the node value of their
+    // parent, and thus don't need to be part of the proof.
+    let node_value = node_value::calculate_node_value(config);
+    if is_root || node_value.len() >= 32 {
+        let merkle_value = node_value::Output::from_bytes(
+            blake2_rfc::blake2b::blake2b(32, &[], &node_value).as_bytes(),
+        );
+        proof.push(node_value);
+        merkle_value
+    } else {
+        node_value::Output::from_bytes(&node_value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{calculate_root, proof_verify};
+    use std::collections::BTreeMap;
+
+    fn trie_root(storage: &BTreeMap<Vec<u8>, Vec<u8>>) -> [u8; 32] {
+        let mut calculation = calculate_root::root_merkle_value(None);
+        loop {
+            match calculation {
+                calculate_root::RootMerkleValueCalculation::Finished { hash, .. } => break hash,
+                calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
+                    calculation = keys.inject(storage.keys().map(|k| k.iter().cloned()));
+                }
+                calculate_root::RootMerkleValueCalculation::StorageValue(value_request) => {
+                    let key = value_request.key().collect::<Vec<u8>>();
+                    calculation = value_request.inject(storage.get(&key));
+                }
+            }
+        }
+    }
+
+    fn check(storage: &BTreeMap<Vec<u8>, Vec<u8>>, requested_keys: &[Vec<u8>]) {
+        let trie_root = trie_root(storage);
+
+        let proof = super::build_proof(super::Config {
+            entries: storage.iter().map(|(k, v)| (&k[..], &v[..])),
+            requested_keys: requested_keys.iter().map(|k| &k[..]),
+        });
+
+        let mut sorted_proof = proof.clone();
+        sorted_proof.sort_unstable();
+        let trie_nodes = super::TrieNodes::new(storage.iter().map(|(k, v)| (&k[..], &v[..])));
+        let mut nodes_proof = trie_nodes.build_proof(requested_keys.iter().map(|k| &k[..]));
+        nodes_proof.sort_unstable();
+        assert_eq!(nodes_proof, sorted_proof);
+
+        for key in requested_keys {
+            let obtained = proof_verify::verify_proof(proof_verify::VerifyProofConfig {
+                requested_key: key,
+                trie_root_hash: &trie_root,
+                proof: proof.iter().map(|p| &p[..]),
+            })
+            .unwrap();
+            assert_eq!(obtained, storage.get(key).map(|v| &v[..]));
+        }
+    }
+
+    #[test]
+    fn empty_trie() {
+        check(&BTreeMap::new(), &[vec![], b"foo".to_vec()]);
+    }
+
+    #[test]
+    fn no_requested_key() {
+        let mut storage = BTreeMap::new();
+        storage.insert(b"foo".to_vec(), b"bar".to_vec());
+        assert!(super::build_proof(super::Config {
+            entries: storage.iter().map(|(k, v)| (&k[..], &v[..])),
+            requested_keys: core::iter::empty(),
+        })
+        .is_empty());
+    }
+
+    #[test]
+    fn random_tries() {
+        // Simple deterministic pseudo-random generator, in order to not depend on any external
+        // library.
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move |max: u64| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed % max
+        };
+
+        for _ in 0..100 {
+            let mut storage = BTreeMap::new();
+            let mut absent_keys = Vec::new();
+
+            for _ in 0..next(200) {
+                // Keys use a small alphabet in order to generate many common prefixes.
+                let key = (0..next(6))
+                    .map(|_| [0x00, 0x01, 0x10, 0xff][next(4) as usize])
+                    .collect::<Vec<u8>>();
+                // Mix of values whose node values are shorter and longer than 32 bytes.
+                let value = (0..next(48)).map(|_| next(256) as u8).collect::<Vec<u8>>();
+
+                if next(4) == 0 {
+                    absent_keys.push(key);
+                } else {
+                    storage.insert(key, value);
+                }
+            }
+
+            absent_keys.retain(|k| !storage.contains_key(k));
+
+            let requested_keys = storage
+                .keys()
+                .filter(|_| next(3) == 0)
+                .cloned()
+                .chain(absent_keys)
+                .collect::<Vec<_>>();
+            check(&storage, &requested_keys);
+
+            // Proofs for a single key.
+            for key in storage.keys().take(5) {
+                check(&storage, &[key.clone()]);
+            }
+        }
+    }
+}
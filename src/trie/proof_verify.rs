@@ -45,10 +45,9 @@
 //! >           corresponding to the storage entries necessary for a certain runtime call.
 //!
 
-use super::nibble;
+use super::{nibble, node_value};
 
 use alloc::vec::Vec;
-use core::{convert::TryFrom as _, iter};
 
 /// Configuration to pass to [`verify_proof`].
 pub struct VerifyProofConfig<'a, I> {
@@ -143,66 +142,26 @@ pub fn trie_node_info<'a, 'b>(
 
     // Find the expected trie root in the proof and put it in `node_value`. This is the start
     // point of the verification.
+    // Contrary to the other nodes, the Merkle value of the root node is always the hash of its
+    // node value, even if the node value is shorter than 32 bytes.
     // `node_value` is updated as the decoding progresses.
-    let mut node_value = {
-        let proof_iter = merkle_values
-            .iter()
-            .position(|v| v[..] == config.trie_root_hash[..])
-            .ok_or(Error::TrieRootNotFound)?;
-        config.proof.clone().nth(proof_iter).unwrap()
-    };
+    let mut node_value = config
+        .proof
+        .clone()
+        .find(|proof_entry| {
+            blake2_rfc::blake2b::blake2b(32, &[], proof_entry).as_bytes()
+                == &config.trie_root_hash[..]
+        })
+        .ok_or(Error::TrieRootNotFound)?;
 
     // The verification consists in iterating using `expected_nibbles_iter` and `node_value`.
     let mut expected_nibbles_iter = config.requested_key;
     loop {
-        if node_value.is_empty() {
-            return Err(Error::InvalidNodeValue);
-        }
-
-        let has_children = (node_value[0] & 0x80) != 0;
-        let has_storage_value = (node_value[0] & 0x40) != 0;
-
-        // Iterator to the partial key found in the node value of `proof_iter`.
-        let mut partial_key = {
-            // Length of the partial key, in nibbles.
-            let pk_len = {
-                let mut accumulator = usize::from(node_value[0] & 0x3f);
-                node_value = &node_value[1..];
-                let mut continue_iter = accumulator == 63;
-                while continue_iter {
-                    if node_value.is_empty() {
-                        return Err(Error::InvalidNodeValue);
-                    }
-                    continue_iter = node_value[0] == 255;
-                    accumulator = accumulator
-                        .checked_add(usize::from(node_value[0]))
-                        .ok_or(Error::InvalidNodeValue)?;
-                    node_value = &node_value[1..];
-                }
-                accumulator
-            };
-
-            // Length of the partial key, in bytes.
-            let pk_len_bytes = if pk_len == 0 {
-                0
-            } else {
-                1 + ((pk_len - 1) / 2)
-            };
-            if node_value.len() < pk_len_bytes {
-                return Err(Error::InvalidNodeValue);
-            }
-
-            let pk_nibbles_iter = node_value
-                .iter()
-                .take(pk_len_bytes)
-                .flat_map(|byte| nibble::bytes_to_nibbles(iter::once(*byte)))
-                .skip(pk_len % 2);
-            node_value = &node_value[pk_len_bytes..];
-            pk_nibbles_iter
-        };
+        let decoded = node_value::decode(node_value).map_err(|_| Error::InvalidNodeValue)?;
 
-        // Iterating over this partial key, checking if it matches `expected_nibbles_iter`.
-        while let Some(nibble) = partial_key.next() {
+        // Iterating over the partial key of the node, checking if it matches
+        // `expected_nibbles_iter`.
+        for nibble in decoded.partial_key() {
             match expected_nibbles_iter.next() {
                 None => {
                     return Ok(TrieNodeInfo {
@@ -220,100 +179,44 @@ pub fn trie_node_info<'a, 'b>(
             }
         }
 
-        // After the partial key, the node value optionally contains a bitfield of child nodes.
-        let children_bitmap = if has_children {
-            if node_value.len() < 2 {
-                return Err(Error::InvalidNodeValue);
+        let expected_nibble = match expected_nibbles_iter.next() {
+            Some(n) => n,
+            None => {
+                // The current node exactly matches the requested key.
+                return Ok(TrieNodeInfo {
+                    node_value: decoded.storage_value,
+                    children: Children::Multiple {
+                        children_bitmap: decoded.children_bitmap(),
+                    },
+                });
             }
-            let val = u16::from_le_bytes(<[u8; 2]>::try_from(&node_value[..2]).unwrap());
-            node_value = &node_value[2..];
-            val
-        } else {
-            0
         };
 
-        if let Some(expected_nibble) = expected_nibbles_iter.next() {
-            // The iteration needs to continue with another node.
-            // Update `proof_iter` to the point to the child whose index matches next nibble that
-            // was just pulled from `expected_nibbles_iter`.
-
+        // The iteration needs to continue with the child whose index matches the next nibble
+        // that was just pulled from `expected_nibbles_iter`.
+        let child_merkle_value = match decoded.children[usize::from(u8::from(expected_nibble))] {
+            Some(v) => v,
             // No child with the requested index exists.
-            if children_bitmap & (1 << u8::from(expected_nibble)) == 0 {
+            None => {
                 return Ok(TrieNodeInfo {
                     node_value: None,
                     children: Children::None,
-                });
-            }
-
-            for n in 0.. {
-                if children_bitmap & (1 << n) == 0 {
-                    continue;
-                }
-
-                // Find the Merkle value of that child in `node_value`.
-                let (node_value_update, len) = crate::util::nom_scale_compact_usize(node_value)
-                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::InvalidNodeValue)?;
-                node_value = node_value_update;
-                if node_value.len() < len {
-                    return Err(Error::InvalidNodeValue);
-                }
-
-                // The Merkle value that was just found is the one that interests us.
-                if n == u8::from(expected_nibble) {
-                    if len < 32 {
-                        // If the node value is less than 32 bytes, it means it's unhashed. In that
-                        // case, the child isn't part of `proof`.
-                        node_value = &node_value[..len];
-                    } else {
-                        // Find the entry in `proof` matching this Merkle value and update
-                        // `proof_iter`.
-                        let proof_iter = merkle_values
-                            .iter()
-                            .position(|v| v[..] == node_value[..len])
-                            .ok_or(Error::MissingProofEntry)?;
-                        node_value = config.proof.clone().nth(proof_iter).unwrap();
-                    }
-
-                    // Break out of the children iteration, to jump to the next node.
-                    break;
-                }
-
-                node_value = &node_value[len..];
-            }
-        } else if has_storage_value {
-            // The current node (as per `proof_iter`) exactly matches the requested key, and
-            // a storage value exists.
-
-            // Skip over the Merkle values of the children.
-            for _ in 0..children_bitmap.count_ones() {
-                let (node_value_update, len) = crate::util::nom_scale_compact_usize(node_value)
-                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::InvalidNodeValue)?;
-                node_value = node_value_update;
-                if node_value.len() < len {
-                    return Err(Error::InvalidNodeValue);
-                }
-                node_value = &node_value[len..];
+                })
             }
+        };
 
-            // Now at the value that interests us.
-            let (node_value_update, len) = crate::util::nom_scale_compact_usize(node_value)
-                .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| Error::InvalidNodeValue)?;
-            node_value = node_value_update;
-            if node_value.len() != len {
-                return Err(Error::InvalidNodeValue);
-            }
-            return Ok(TrieNodeInfo {
-                node_value: Some(node_value),
-                children: Children::Multiple { children_bitmap },
-            });
+        node_value = if child_merkle_value.len() < 32 {
+            // If the node value is less than 32 bytes, it means it's unhashed. In that case, the
+            // child isn't part of `proof`.
+            child_merkle_value
         } else {
-            // The current node (as per `proof_iter`) exactly matches the requested key, but no
-            // storage value exists.
-            return Ok(TrieNodeInfo {
-                node_value: None,
-                children: Children::Multiple { children_bitmap },
-            });
-        }
+            // Find the entry in `proof` matching this Merkle value.
+            let proof_iter = merkle_values
+                .iter()
+                .position(|v| v[..] == *child_merkle_value)
+                .ok_or(Error::MissingProofEntry)?;
+            config.proof.clone().nth(proof_iter).unwrap()
+        };
     }
 }
 
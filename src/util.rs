@@ -15,9 +15,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-//! Internal module. Contains functions that aren't Substrate/Polkadot-specific and should ideally
-//! be found in third party libraries, but that aren't worth a third-party library.
+//! Mostly internal module. Contains functions that aren't Substrate/Polkadot-specific and should
+//! ideally be found in third party libraries, but that aren't worth a third-party library.
+//!
+//! The few public items are useful to API users that need to build SCALE-encoded payloads.
 
+use alloc::vec::Vec;
 use core::{convert::TryFrom as _, str};
 
 pub(crate) mod leb128;
@@ -45,6 +48,50 @@ pub(crate) fn nom_bytes_decode<'a, E: nom::error::ParseError<&'a [u8]>>(
     nom::multi::length_data(crate::util::nom_scale_compact_usize)(bytes)
 }
 
+/// Returns a parser that applies `inner_decode` exactly `num_elems` times and collects the
+/// decoded elements.
+///
+/// Contrary to `nom::multi::many_m_n`, the returned `Vec` isn't preallocated to `num_elems`
+/// elements. The number of elements is typically decoded from untrusted data, and preallocating
+/// it would make it possible to trigger a huge memory allocation with just a few bytes.
+pub(crate) fn nom_many_exact<'a, O, E: nom::error::ParseError<&'a [u8]>>(
+    num_elems: usize,
+    mut inner_decode: impl nom::Parser<&'a [u8], O, E>,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], Vec<O>, E> {
+    /// Maximum number of elements that are preallocated. Any additional element is allocated
+    /// only after having been successfully decoded.
+    const MAX_PREALLOCATED: usize = 64;
+
+    move |mut bytes| {
+        let mut elements = Vec::with_capacity(core::cmp::min(num_elems, MAX_PREALLOCATED));
+        for _ in 0..num_elems {
+            match inner_decode.parse(bytes) {
+                // Same as `many_m_n`, elements that don't consume any byte are refused, as they
+                // would make it possible to decode an arbitrary number of elements.
+                Ok((rest, _)) if rest.len() == bytes.len() => {
+                    return Err(nom::Err::Error(E::from_error_kind(
+                        bytes,
+                        nom::error::ErrorKind::ManyMN,
+                    )))
+                }
+                Ok((rest, element)) => {
+                    elements.push(element);
+                    bytes = rest;
+                }
+                Err(nom::Err::Error(err)) => {
+                    return Err(nom::Err::Error(E::append(
+                        bytes,
+                        nom::error::ErrorKind::ManyMN,
+                        err,
+                    )))
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok((bytes, elements))
+    }
+}
+
 /// Decodes a SCALE-encoded string.
 pub(crate) fn nom_string_decode<
     'a,
@@ -59,6 +106,7 @@ pub(crate) fn nom_string_decode<
 }
 
 /// Decodes a SCALE-encoded boolean.
+#[cfg(feature = "transactions")]
 pub(crate) fn nom_bool_decode<'a, E: nom::error::ParseError<&'a [u8]>>(
     bytes: &'a [u8],
 ) -> nom::IResult<&'a [u8], bool, E> {
@@ -75,103 +123,100 @@ pub(crate) fn nom_bool_decode<'a, E: nom::error::ParseError<&'a [u8]>>(
 pub(crate) fn nom_scale_compact_usize<'a, E: nom::error::ParseError<&'a [u8]>>(
     bytes: &'a [u8],
 ) -> nom::IResult<&'a [u8], usize, E> {
-    if bytes.is_empty() {
+    nom::combinator::map_opt(nom_scale_compact_u64, |value| usize::try_from(value).ok())(bytes)
+}
+
+/// Decodes a SCALE-compact-encoded u64.
+///
+/// Contrary to [`nom_scale_compact_usize`], the value doesn't go through a `usize` and thus
+/// isn't limited to 32 bits on 32 bits platforms. Use this function for values that aren't
+/// lengths, such as block numbers.
+///
+/// > **Note**: When using this function outside of a `nom` "context", you might have to explicit
+/// >           the type of `E`. Use `nom::error::Error`.
+pub(crate) fn nom_scale_compact_u64<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], u64, E> {
+    nom::combinator::map_opt(nom_scale_compact_u128, |value| u64::try_from(value).ok())(bytes)
+}
+
+/// Decodes a SCALE-compact-encoded u128, such as a balance.
+///
+/// > **Note**: When using this function outside of a `nom` "context", you might have to explicit
+/// >           the type of `E`. Use `nom::error::Error`.
+pub(crate) fn nom_scale_compact_u128<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], u128, E> {
+    let first = match bytes.first() {
+        Some(b) => *b,
+        None => {
+            return Err(nom::Err::Error(nom::error::make_error(
+                bytes,
+                nom::error::ErrorKind::Eof,
+            )))
+        }
+    };
+
+    // Number of bytes of the encoding, including the first byte.
+    let encoded_len = match first & 0b11 {
+        0b00 => 1,
+        0b01 => 2,
+        0b10 => 4,
+        0b11 => usize::from(first >> 2) + 4 + 1,
+        _ => unreachable!(),
+    };
+
+    if bytes.len() < encoded_len {
         return Err(nom::Err::Error(nom::error::make_error(
             bytes,
             nom::error::ErrorKind::Eof,
         )));
     }
 
-    match bytes[0] & 0b11 {
-        0b00 => {
-            let value = bytes[0] >> 2;
-            Ok((&bytes[1..], usize::from(value)))
-        }
-        0b01 => {
-            if bytes.len() < 2 {
-                return Err(nom::Err::Error(nom::error::make_error(
-                    bytes,
-                    nom::error::ErrorKind::Eof,
-                )));
-            }
-
-            let byte0 = u16::from(bytes[0] >> 2);
-            let byte1 = u16::from(bytes[1]);
-            let value = (byte1 << 6) | byte0;
-            Ok((&bytes[2..], usize::from(value)))
-        }
-        0b10 => {
-            if bytes.len() < 4 {
-                return Err(nom::Err::Error(nom::error::make_error(
-                    bytes,
-                    nom::error::ErrorKind::Eof,
-                )));
-            }
-
-            let byte0 = u32::from(bytes[0] >> 2);
-            let byte1 = u32::from(bytes[1]);
-            let byte2 = u32::from(bytes[2]);
-            let byte3 = u32::from(bytes[3]);
-            let value = (byte3 << 22) | (byte2 << 14) | (byte1 << 6) | byte0;
-            let value = match usize::try_from(value) {
-                Ok(v) => v,
-                Err(_) => {
-                    return Err(nom::Err::Error(nom::error::make_error(
-                        bytes,
-                        nom::error::ErrorKind::Satisfy,
-                    )))
-                }
-            };
-            Ok((&bytes[4..], value))
-        }
+    let value = match first & 0b11 {
+        0b00 => u128::from(first >> 2),
+        0b01 => u128::from(u16::from_le_bytes([bytes[0], bytes[1]]) >> 2),
+        0b10 => u128::from(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) >> 2),
         0b11 => {
-            let num_bytes = usize::from(bytes[0] >> 2) + 4;
-
-            if bytes.len() < num_bytes + 1 {
-                return Err(nom::Err::Error(nom::error::make_error(
-                    bytes,
-                    nom::error::ErrorKind::Eof,
-                )));
-            }
+            let value_bytes = &bytes[1..encoded_len];
 
-            // Value is invalid if highest byte is 0.
-            if bytes[num_bytes] == 0 {
+            // Value is invalid if highest byte is 0, or if it is too large to fit a `u128`.
+            if value_bytes[value_bytes.len() - 1] == 0 || value_bytes.len() > 16 {
                 return Err(nom::Err::Error(nom::error::make_error(
                     bytes,
                     nom::error::ErrorKind::Satisfy,
                 )));
             }
 
-            let mut out_value = 0;
-            let mut shift = 0u32;
-            for byte_index in 1..=num_bytes {
-                out_value |= match usize::from(bytes[byte_index]).checked_mul(1 << shift) {
-                    Some(v) => v,
-                    None => {
-                        // Overflow. The SCALE-encoded value is too large to fit a `usize`.
-                        return Err(nom::Err::Error(nom::error::make_error(
-                            bytes,
-                            nom::error::ErrorKind::Satisfy,
-                        )));
-                    }
-                };
-
-                // Overflows aren't properly handled because `out_value` is expected to overflow
-                // way sooner than `shift`.
-                shift += 8;
-            }
-
-            Ok((&bytes[num_bytes + 1..], out_value))
+            let mut buf = [0; 16];
+            buf[..value_bytes.len()].copy_from_slice(value_bytes);
+            u128::from_le_bytes(buf)
         }
         _ => unreachable!(),
-    }
+    };
+
+    Ok((&bytes[encoded_len..], value))
 }
 
 /// Returns a buffer containing the SCALE-compact encoding of the parameter.
-pub(crate) fn encode_scale_compact_usize(mut value: usize) -> impl AsRef<[u8]> + Clone {
-    // TODO: use usize::BITS after https://github.com/rust-lang/rust/issues/76904 is stable
-    // TODO: should be `(1 + usize::BITS / 8)` instead of `9`, but this causes compilation errors
-    let mut array = arrayvec::ArrayVec::<u8, 9>::new();
+///
+/// Doesn't perform any heap allocation.
+pub fn encode_scale_compact_usize(value: usize) -> impl AsRef<[u8]> + Clone {
+    encode_scale_compact_u64(u64::try_from(value).unwrap())
+}
+
+/// Returns a buffer containing the SCALE-compact encoding of the parameter.
+///
+/// Doesn't perform any heap allocation.
+pub(crate) fn encode_scale_compact_u64(value: u64) -> impl AsRef<[u8]> + Clone {
+    encode_scale_compact_u128(u128::from(value))
+}
+
+/// Returns a buffer containing the SCALE-compact encoding of the parameter.
+///
+/// Doesn't perform any heap allocation.
+pub(crate) fn encode_scale_compact_u128(mut value: u128) -> impl AsRef<[u8]> + Clone {
+    let mut array = arrayvec::ArrayVec::<u8, 17>::new();
 
     if value < 64 {
         array.push(u8::try_from(value).unwrap() << 2);
@@ -194,3 +239,200 @@ pub(crate) fn encode_scale_compact_usize(mut value: usize) -> impl AsRef<[u8]> +
 
     array
 }
+
+/// Copies the given data into a new `Vec`. Returns `None` if the allocation fails.
+///
+/// Used for buffers whose size is controlled by other nodes or by the runtime, in order to
+/// report an error rather than aborting in case of a memory shortage.
+pub(crate) fn try_to_vec(data: &[u8]) -> Option<Vec<u8>> {
+    let mut vec = Vec::new();
+    vec.try_reserve_exact(data.len()).ok()?;
+    vec.extend_from_slice(data);
+    Some(vec)
+}
+
+/// Returns `false` if `size` bytes can't currently be allocated.
+///
+/// Some third-party libraries, such as `wasmi` or `prost`, allocate memory infallibly, in which
+/// case a failed allocation aborts the entire program. Calling this function with the size they
+/// are about to allocate makes it possible to instead report an error in the likely case where
+/// the allocation would fail. The memory is freed before this function returns.
+pub(crate) fn can_allocate(size: usize) -> bool {
+    Vec::<u8>::new().try_reserve_exact(size).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::TryFrom as _;
+
+    fn decode_u64(bytes: &[u8]) -> Option<u64> {
+        super::nom_scale_compact_u64::<nom::error::Error<&[u8]>>(bytes)
+            .ok()
+            .filter(|(rest, _)| rest.is_empty())
+            .map(|(_, v)| v)
+    }
+
+    fn decode_u128(bytes: &[u8]) -> Option<u128> {
+        super::nom_scale_compact_u128::<nom::error::Error<&[u8]>>(bytes)
+            .ok()
+            .filter(|(rest, _)| rest.is_empty())
+            .map(|(_, v)| v)
+    }
+
+    #[test]
+    fn encode_known_values() {
+        let encode = |v: u64| super::encode_scale_compact_u64(v).as_ref().to_vec();
+        assert_eq!(encode(0), &[0x00]);
+        assert_eq!(encode(1), &[0x04]);
+        assert_eq!(encode(63), &[0xfc]);
+        assert_eq!(encode(64), &[0x01, 0x01]);
+        assert_eq!(encode(16383), &[0xfd, 0xff]);
+        assert_eq!(encode(16384), &[0x02, 0x00, 0x01, 0x00]);
+        assert_eq!(encode(1 << 30), &[0x03, 0x00, 0x00, 0x00, 0x40]);
+        assert_eq!(
+            encode(u64::max_value()),
+            &[0x13, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn boundaries_round_trip() {
+        for value in &[
+            0u128,
+            63,
+            64,
+            (1 << 14) - 1,
+            1 << 14,
+            (1 << 30) - 1,
+            1 << 30,
+            (1 << 32) - 1,
+            1 << 32,
+            u128::from(u64::max_value()),
+            u128::from(u64::max_value()) + 1,
+            u128::max_value(),
+        ] {
+            let encoded = super::encode_scale_compact_u128(*value);
+            assert_eq!(decode_u128(encoded.as_ref()), Some(*value));
+            assert_eq!(decode_u64(encoded.as_ref()), u64::try_from(*value).ok());
+        }
+    }
+
+    #[test]
+    fn random_u64_round_trip() {
+        for _ in 0..10000 {
+            // Shift the random value in order to cover all the possible encoding lengths.
+            let value = rand::random::<u64>() >> (rand::random::<u32>() % 64);
+            let encoded = super::encode_scale_compact_u64(value);
+            assert_eq!(decode_u64(encoded.as_ref()), Some(value));
+            assert_eq!(decode_u128(encoded.as_ref()), Some(u128::from(value)));
+        }
+    }
+
+    #[test]
+    fn random_u128_round_trip() {
+        for _ in 0..10000 {
+            let value = rand::random::<u128>() >> (rand::random::<u32>() % 128);
+            let encoded = super::encode_scale_compact_u128(value);
+            assert_eq!(decode_u128(encoded.as_ref()), Some(value));
+        }
+    }
+
+    #[test]
+    fn usize_matches_u64() {
+        for _ in 0..10000 {
+            let value = rand::random::<u64>() >> (rand::random::<u32>() % 64);
+            let encoded = super::encode_scale_compact_u64(value);
+            let decoded =
+                super::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(encoded.as_ref())
+                    .ok()
+                    .map(|(_, v)| v);
+            assert_eq!(decoded, usize::try_from(value).ok());
+        }
+    }
+
+    #[test]
+    fn non_canonical_rejected() {
+        // Highest byte of the big-integer mode is zero.
+        assert!(decode_u128(&[0x03, 0x00, 0x00, 0x00, 0x00]).is_none());
+        assert!(decode_u128(&[0x07, 0xff, 0xff, 0xff, 0xff, 0x00]).is_none());
+    }
+
+    #[test]
+    fn too_large_rejected() {
+        // 17 bytes, doesn't fit in a `u128`.
+        let mut encoded = vec![(13 << 2) | 0b11];
+        encoded.extend(core::iter::repeat(0xff).take(17));
+        assert!(decode_u128(&encoded).is_none());
+
+        // Fits in a `u128` but not in a `u64`.
+        let encoded = super::encode_scale_compact_u128(u128::from(u64::max_value()) + 1);
+        assert!(decode_u64(encoded.as_ref()).is_none());
+    }
+
+    #[test]
+    fn truncated_rejected() {
+        for _ in 0..1000 {
+            let value = rand::random::<u128>() >> (rand::random::<u32>() % 128);
+            let encoded = super::encode_scale_compact_u128(value);
+            let encoded = encoded.as_ref();
+            for len in 0..encoded.len() {
+                assert!(
+                    super::nom_scale_compact_u128::<nom::error::Error<&[u8]>>(&encoded[..len])
+                        .is_err()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn random_bytes_dont_panic() {
+        for _ in 0..10000 {
+            let len = rand::random::<usize>() % 24;
+            let bytes = (0..len).map(|_| rand::random::<u8>()).collect::<Vec<_>>();
+            let _ = super::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(&bytes);
+            let _ = super::nom_scale_compact_u64::<nom::error::Error<&[u8]>>(&bytes);
+            if let Ok((_, value)) =
+                super::nom_scale_compact_u128::<nom::error::Error<&[u8]>>(&bytes)
+            {
+                // Non-minimal encodings are accepted, but re-encoding must round-trip.
+                let encoded = super::encode_scale_compact_u128(value);
+                assert_eq!(decode_u128(encoded.as_ref()), Some(value));
+            }
+        }
+    }
+
+    #[test]
+    fn many_exact() {
+        let decode = |num_elems, bytes| {
+            super::nom_many_exact::<_, nom::error::Error<&[u8]>>(
+                num_elems,
+                nom::number::complete::le_u16,
+            )(bytes)
+            .map(|(rest, elems)| (rest.len(), elems))
+            .ok()
+        };
+
+        assert_eq!(decode(0, &[1, 0]), Some((2, Vec::new())));
+        assert_eq!(decode(2, &[1, 0, 2, 0, 3]), Some((1, vec![1, 2])));
+        assert_eq!(decode(3, &[1, 0, 2, 0, 3]), None);
+
+        // A huge number of elements fails without allocating memory for all of them.
+        assert_eq!(decode(usize::max_value(), &[1, 0, 2, 0]), None);
+    }
+
+    #[test]
+    fn many_exact_refuses_empty_elements() {
+        let decoded = super::nom_many_exact::<_, nom::error::Error<&[u8]>>(
+            usize::max_value(),
+            nom::combinator::success(()),
+        )(&[1, 2, 3]);
+        assert!(decoded.is_err());
+    }
+
+    #[test]
+    fn allocation_failures() {
+        assert_eq!(super::try_to_vec(b"hello"), Some(b"hello".to_vec()));
+        assert!(super::can_allocate(1024));
+        assert!(!super::can_allocate(usize::max_value()));
+    }
+}
@@ -23,7 +23,7 @@
 //! See [https://en.wikipedia.org/wiki/LEB128].
 
 use alloc::vec::Vec;
-use core::{cmp, convert::TryFrom as _, mem};
+use core::{cmp, convert::TryFrom as _};
 
 /// Returns an LEB128-encoded integer as a list of bytes.
 ///
@@ -75,6 +75,74 @@ pub fn encode_usize(value: usize) -> impl ExactSizeIterator<Item = u8> {
     encode(u64::try_from(value).unwrap())
 }
 
+/// Incremental decoder for a single LEB128-encoded integer.
+///
+/// Contrary to decoding from a contiguous slice, the encoded integer can be fed to the decoder
+/// in multiple chunks, for example as they are received from the network. No buffering of the
+/// bytes of the encoded integer is performed.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    /// Value decoded so far.
+    value: u64,
+    /// Number of bits already decoded. Always a multiple of 7.
+    shift: u32,
+}
+
+impl Decoder {
+    /// Initializes a new decoder.
+    pub fn new() -> Self {
+        Decoder { value: 0, shift: 0 }
+    }
+
+    /// Feeds bytes to the decoder.
+    ///
+    /// On success, returns the number of bytes that have been read from `data`, and either the
+    /// decoded value or the decoder to feed the next bytes to. Bytes found after the end of the
+    /// encoded integer are never read.
+    pub fn update(mut self, data: &[u8]) -> Result<(usize, Decoded), DecodeError> {
+        for (num_read, byte) in data.iter().enumerate() {
+            let bits = u64::from(*byte & 0b1111111);
+
+            // The 64th bit is the last one that can be represented. Anything beyond is an
+            // overflow.
+            if self.shift >= 64 || (self.shift == 63 && bits > 1) {
+                return Err(DecodeError::Overflow);
+            }
+
+            self.value |= bits << self.shift;
+            self.shift += 7;
+
+            if (*byte & 0x80) == 0 {
+                return Ok((num_read + 1, Decoded::Finished(self.value)));
+            }
+        }
+
+        Ok((data.len(), Decoded::InProgress(self)))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Outcome of [`Decoder::update`].
+#[derive(Debug, Clone)]
+pub enum Decoded {
+    /// More data is needed in order to finish decoding.
+    InProgress(Decoder),
+    /// The integer has been fully decoded.
+    Finished(u64),
+}
+
+/// Error potentially returned by [`Decoder::update`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The encoded integer doesn't fit in a `u64`.
+    Overflow,
+}
+
 // TODO: document all this below
 
 pub enum Framed {
@@ -89,7 +157,7 @@ pub struct FramedInProgress {
 }
 
 enum FramedInner {
-    Length,
+    Length(Decoder),
     Body { expected_len: usize },
 }
 
@@ -102,57 +170,51 @@ impl FramedInProgress {
     pub fn new(max_len: usize) -> Self {
         FramedInProgress {
             max_len,
-            buffer: Vec::with_capacity(32), // Reserve enough for the length prefix.
-            inner: FramedInner::Length,
+            buffer: Vec::new(),
+            inner: FramedInner::Length(Decoder::new()),
         }
     }
 
-    pub fn update(mut self, mut data: &[u8]) -> Result<(usize, Framed), FramedError> {
-        fn decode_leb128(buffer: &[u8]) -> Option<Result<usize, FramedError>> {
-            let mut out = 0usize;
-
-            for (n, byte) in buffer.iter().enumerate() {
-                match usize::from(*byte & 0b1111111).checked_mul(1 << (7 * n)) {
-                    Some(o) => out |= o,
-                    None => return Some(Err(FramedError::LengthPrefixTooLarge)),
-                };
-
-                if (*byte & 0x80) == 0 {
-                    assert_eq!(n, buffer.len() - 1);
-                    return Some(Ok(out));
-                }
-            }
-
-            None
-        }
+    /// Returns the number of bytes of the body of the frame that have been received so far.
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
 
+    pub fn update(mut self, mut data: &[u8]) -> Result<(usize, Framed), FramedError> {
         let mut total_read = 0;
 
         loop {
             match self.inner {
-                FramedInner::Length => {
-                    if data.is_empty() {
-                        return Ok((total_read, Framed::InProgress(self)));
-                    }
-
-                    self.buffer.push(data[0]);
-                    data = &data[1..];
-                    total_read += 1;
-
-                    if self.buffer.len() >= 2 * mem::size_of::<usize>() {
-                        return Err(FramedError::LengthPrefixTooLarge);
-                    }
-
-                    if let Some(expected_len) = decode_leb128(&self.buffer) {
-                        let expected_len = expected_len?;
-                        if expected_len > self.max_len {
-                            return Err(FramedError::MaxLengthExceeded {
-                                max_allowed: self.max_len,
-                            });
+                FramedInner::Length(decoder) => {
+                    // The length prefix is decoded without being buffered, and the buffer of
+                    // the body is only allocated once the length is known.
+                    let (num_read, decoded) = decoder
+                        .update(data)
+                        .map_err(|_| FramedError::LengthPrefixTooLarge)?;
+                    data = &data[num_read..];
+                    total_read += num_read;
+
+                    match decoded {
+                        Decoded::InProgress(decoder) => {
+                            debug_assert!(data.is_empty());
+                            self.inner = FramedInner::Length(decoder);
+                            return Ok((total_read, Framed::InProgress(self)));
+                        }
+                        Decoded::Finished(expected_len) => {
+                            let expected_len = usize::try_from(expected_len)
+                                .map_err(|_| FramedError::LengthPrefixTooLarge)?;
+                            if expected_len > self.max_len {
+                                return Err(FramedError::MaxLengthExceeded {
+                                    max_allowed: self.max_len,
+                                });
+                            }
+                            // The length is controlled by the remote. Failing to allocate
+                            // the buffer is reported as an error rather than aborting.
+                            self.buffer.try_reserve_exact(expected_len).map_err(|_| {
+                                FramedError::AllocationFailed { size: expected_len }
+                            })?;
+                            self.inner = FramedInner::Body { expected_len };
                         }
-                        self.buffer.clear();
-                        self.buffer.reserve(expected_len);
-                        self.inner = FramedInner::Body { expected_len };
                     }
                 }
                 FramedInner::Body { expected_len } => {
@@ -188,10 +250,18 @@ pub enum FramedError {
         /// Maximum number of bytes allowed.
         max_allowed: usize,
     },
+    /// Not enough memory to allocate the buffer of the frame.
+    #[display(fmt = "Failed to allocate {} bytes for the frame", size)]
+    AllocationFailed {
+        /// Length of the frame, as indicated by its prefix.
+        size: usize,
+    },
 }
 
 #[cfg(test)]
 mod tests {
+    use core::cmp;
+
     #[test]
     fn basic_encode() {
         let obtained = super::encode(0x123456789abcdefu64).collect::<Vec<_>>();
@@ -214,5 +284,81 @@ mod tests {
         }
     }
 
-    // TODO: more tests
+    #[test]
+    fn decoder_round_trip() {
+        for _ in 0..1024 {
+            let value = rand::random::<u64>() >> (rand::random::<u32>() % 64);
+            let encoded = super::encode(value).collect::<Vec<_>>();
+
+            // Feed the encoded value in chunks of random sizes.
+            let mut decoder = super::Decoder::new();
+            let mut remain = &encoded[..];
+            let decoded = loop {
+                let chunk_len = cmp::min(remain.len(), 1 + rand::random::<usize>() % 4);
+                let (num_read, outcome) = decoder.update(&remain[..chunk_len]).unwrap();
+                remain = &remain[num_read..];
+                match outcome {
+                    super::Decoded::Finished(v) => break v,
+                    super::Decoded::InProgress(d) => {
+                        assert_eq!(num_read, chunk_len);
+                        decoder = d;
+                    }
+                }
+            };
+
+            assert_eq!(decoded, value);
+            assert!(remain.is_empty());
+        }
+    }
+
+    #[test]
+    fn decoder_stops_at_end_of_integer() {
+        let (num_read, outcome) = super::Decoder::new().update(&[0xac, 0x02, 0xff]).unwrap();
+        assert_eq!(num_read, 2);
+        assert!(matches!(outcome, super::Decoded::Finished(300)));
+    }
+
+    #[test]
+    fn decoder_overflow() {
+        let max = super::encode(u64::max_value()).collect::<Vec<_>>();
+        let (_, outcome) = super::Decoder::new().update(&max).unwrap();
+        assert!(matches!(outcome, super::Decoded::Finished(v) if v == u64::max_value()));
+
+        let mut too_large = max.clone();
+        *too_large.last_mut().unwrap() = 0x02;
+        assert!(super::Decoder::new().update(&too_large).is_err());
+
+        assert!(super::Decoder::new().update(&[0xff; 11]).is_err());
+    }
+
+    #[test]
+    fn framed_partial() {
+        let mut frame = super::encode_usize(300).collect::<Vec<_>>();
+        frame.extend((0..300).map(|n| n as u8));
+
+        for split in 0..frame.len() {
+            let framed = super::FramedInProgress::new(1024);
+            let (num_read, framed) = framed.update(&frame[..split]).unwrap();
+            assert_eq!(num_read, split);
+            let framed = match framed {
+                super::Framed::InProgress(f) => f,
+                super::Framed::Finished(_) => panic!(),
+            };
+            let (num_read, framed) = framed.update(&frame[split..]).unwrap();
+            assert_eq!(num_read, frame.len() - split);
+            match framed {
+                super::Framed::Finished(body) => assert_eq!(body, &frame[2..]),
+                super::Framed::InProgress(_) => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn framed_max_len() {
+        let frame = super::encode_usize(2048).collect::<Vec<_>>();
+        assert!(matches!(
+            super::FramedInProgress::new(1024).update(&frame),
+            Err(super::FramedError::MaxLengthExceeded { max_allowed: 1024 })
+        ));
+    }
 }
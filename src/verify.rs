@@ -72,3 +72,27 @@ pub mod aura;
 pub mod babe;
 pub mod header_body;
 pub mod header_only;
+
+/// Alternative implementations of the sr25519 primitives used to verify the seal and VRF proof
+/// of block headers, for example faster implementations provided by the environment.
+///
+/// Each function can return `None` in order to indicate that it can't perform the operation, in
+/// which case the implementation compiled in smoldot is used instead.
+#[derive(Debug, Copy, Clone)]
+pub struct Sr25519Hooks {
+    /// Verifies an sr25519 signature. See [`Sr25519Verify`].
+    pub verify: Sr25519Verify,
+
+    /// Verifies the VRF proof of a Babe header. See [`BabeVrfVerify`].
+    pub babe_vrf_verify: BabeVrfVerify,
+}
+
+/// Verifies that `signature` is a valid signature of `message` by `public_key`, with the signing
+/// context `b"substrate"`.
+pub type Sr25519Verify =
+    fn(signature: &[u8; 64], message: &[u8], public_key: &[u8; 32]) -> Option<bool>;
+
+/// Verifies the VRF proof of a Babe header. On success, must return the 16 bytes generated from
+/// the VRF input and output with the context `b"substrate-babe-vrf"`. Must return
+/// `Some(Err(()))` if the proof is invalid.
+pub type BabeVrfVerify = fn(babe::VrfVerifyRef) -> Option<Result<[u8; 16], ()>>;
@@ -82,6 +82,9 @@ pub struct VerifySuccess {
     /// If true, the block has a change of authorities that must be reflected when verifying the
     /// following block.
     pub authorities_change: bool,
+
+    /// Sr25519 public key of the authority that has authored the block.
+    pub authority_public_key: [u8; 32],
 }
 
 /// Failure to verify a block.
@@ -112,8 +115,24 @@ pub enum VerifyError {
 /// Panics if `config.parent_block_header` is invalid.
 ///
 pub fn verify_header<'a>(
-    mut config: VerifyConfig<'a, impl ExactSizeIterator<Item = header::AuraAuthorityRef<'a>>>,
+    config: VerifyConfig<'a, impl ExactSizeIterator<Item = header::AuraAuthorityRef<'a>>>,
 ) -> Result<VerifySuccess, VerifyError> {
+    let (success, signature_check) = verify_header_deferred(config)?;
+    signature_check.verify()?;
+    Ok(success)
+}
+
+/// Same as [`verify_header`], except that the signature of the header isn't verified. Instead,
+/// a [`SignatureCheck`] is returned alongside with the success, and the header must only be
+/// considered as valid after [`SignatureCheck::verify`] has succeeded.
+///
+/// # Panic
+///
+/// See [`verify_header`].
+///
+pub fn verify_header_deferred<'a>(
+    mut config: VerifyConfig<'a, impl ExactSizeIterator<Item = header::AuraAuthorityRef<'a>>>,
+) -> Result<(VerifySuccess, SignatureCheck), VerifyError> {
     // TODO: handle OnDisabled
 
     // Gather the slot number from the header.
@@ -183,22 +202,62 @@ pub fn verify_header<'a>(
         usize::try_from(slot_number % u64::try_from(config.current_authorities.len()).unwrap())
             .unwrap();
 
+    let author_public_key = *config
+        .current_authorities
+        .nth(signing_authority)
+        .unwrap()
+        .public_key;
+
     // This `unwrap()` can only panic if `public_key` is the wrong length, which we know can't
     // happen as it's of type `[u8; 32]`.
-    let authority_public_key = schnorrkel::PublicKey::from_bytes(
-        config
-            .current_authorities
-            .nth(signing_authority)
-            .unwrap()
-            .public_key,
-    )
-    .unwrap();
-
-    // Now verifying the signature in the seal.
-    authority_public_key
-        .verify_simple(b"substrate", &pre_seal_hash, &seal_signature)
-        .map_err(|_| VerifyError::BadSignature)?;
+    let authority_public_key = schnorrkel::PublicKey::from_bytes(&author_public_key).unwrap();
 
     // Success! 🚀
-    Ok(VerifySuccess { authorities_change })
+    Ok((
+        VerifySuccess {
+            authorities_change,
+            authority_public_key: author_public_key,
+        },
+        SignatureCheck {
+            authority_public_key,
+            pre_seal_hash,
+            seal_signature,
+        },
+    ))
+}
+
+/// Signature of a header, whose verification has been deferred.
+///
+/// See [`verify_header_deferred`].
+#[derive(Debug, Clone)]
+pub struct SignatureCheck {
+    /// Public key of the authority that has supposedly signed the header.
+    authority_public_key: schnorrkel::PublicKey,
+    /// Hash of the header without its seal. This is what the seal signs.
+    pre_seal_hash: [u8; 32],
+    /// Signature found in the seal of the header.
+    seal_signature: schnorrkel::Signature,
+}
+
+impl SignatureCheck {
+    /// Verifies the signature of the header.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        self.authority_public_key
+            .verify_simple(b"substrate", &self.pre_seal_hash, &self.seal_signature)
+            .map_err(|_| VerifyError::BadSignature)
+    }
+
+    /// Same as [`SignatureCheck::verify`], but uses the given implementations of the sr25519
+    /// primitives when they support the operation.
+    pub fn verify_with_hooks(&self, hooks: &super::Sr25519Hooks) -> Result<(), VerifyError> {
+        match (hooks.verify)(
+            &self.seal_signature.to_bytes(),
+            &self.pre_seal_hash,
+            &self.authority_public_key.to_bytes(),
+        ) {
+            Some(true) => Ok(()),
+            Some(false) => Err(VerifyError::BadSignature),
+            None => self.verify(),
+        }
+    }
 }
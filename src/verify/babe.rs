@@ -160,6 +160,14 @@ pub struct VerifyConfig<'a> {
 
     /// Epoch that follows the epoch the parent block belongs to.
     pub parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
+
+    /// If `true`, secondary slot claims, plain or VRF, are accepted regardless of the types of
+    /// slot claims allowed by the configuration of the epoch the block belongs to. The author of
+    /// a secondary slot claim must still be the authority the slot is assigned to.
+    ///
+    /// Some test networks produce blocks that rely on this. Should be `false` for all other
+    /// chains.
+    pub relaxed_secondary_slots: bool,
 }
 
 /// Information yielded back after successfully verifying a block.
@@ -177,6 +185,10 @@ pub struct VerifySuccess {
     /// [`VerifyConfig::parent_block_next_epoch`] must instead be passed as
     /// [`VerifyConfig::parent_block_epoch`].
     pub epoch_transition_target: Option<chain_information::BabeEpochInformation>,
+
+    /// Sr25519 public key of the authority that has authored the block, as found in the list of
+    /// authorities of the epoch the block belongs to.
+    pub authority_public_key: [u8; 32],
 }
 
 /// Failure to verify a block.
@@ -206,6 +218,9 @@ pub enum VerifyError {
     OverPrimaryClaimThreshold,
     /// Type of slot claim forbidden by current configuration.
     ForbiddenSlotType,
+    /// The block contains a change of the Babe configuration whose `c` constant is invalid,
+    /// either because its denominator is 0 or because it is greater than 1.
+    InvalidNextEpochConfig,
 }
 
 /// Verifies whether a block header provides a correct proof of the legitimacy of the authorship.
@@ -217,24 +232,47 @@ pub enum VerifyError {
 /// Panics if `config.header.number` is not `config.parent_block_header.number + 1`.
 ///
 pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError> {
+    let (success, signature_check) = verify_header_deferred(config)?;
+    signature_check.verify()?;
+    Ok(success)
+}
+
+/// Same as [`verify_header`], except that the signature and VRF proof of the header aren't
+/// verified. Instead, a [`SignatureCheck`] is returned alongside with the success, and the
+/// header must only be considered as valid after [`SignatureCheck::verify`] has succeeded.
+///
+/// Verifying signatures and VRF proofs is by far the most CPU-intensive part of the
+/// verification, and doesn't depend on any state. The [`SignatureCheck`] can thus be moved to a
+/// different thread, or verified together with the checks of other headers.
+///
+/// # Panic
+///
+/// See [`verify_header`].
+///
+pub fn verify_header_deferred(
+    config: VerifyConfig,
+) -> Result<(VerifySuccess, SignatureCheck), VerifyError> {
     // TODO: handle OnDisabled
 
     // Gather the BABE-related information from the header.
-    let (authority_index, slot_number, primary_slot_claim, vrf_output_and_proof) =
+    let (authority_index, slot_number, claim_kind, vrf_output_and_proof) =
         match config.header.digest.babe_pre_runtime() {
             Some(header::BabePreDigestRef::Primary(digest)) => (
                 digest.authority_index,
                 digest.slot_number,
-                true,
+                ClaimKind::Primary,
                 Some((*digest.vrf_output, *digest.vrf_proof)),
             ),
-            Some(header::BabePreDigestRef::SecondaryPlain(digest)) => {
-                (digest.authority_index, digest.slot_number, false, None)
-            }
+            Some(header::BabePreDigestRef::SecondaryPlain(digest)) => (
+                digest.authority_index,
+                digest.slot_number,
+                ClaimKind::SecondaryPlain,
+                None,
+            ),
             Some(header::BabePreDigestRef::SecondaryVRF(digest)) => (
                 digest.authority_index,
                 digest.slot_number,
-                false,
+                ClaimKind::SecondaryVrf,
                 Some((*digest.vrf_output, *digest.vrf_proof)),
             ),
             None => return Err(VerifyError::MissingPreRuntimeDigest),
@@ -310,16 +348,14 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
     //       checks that the randomness value is correct, light clients in particular do not
     //       execute the runtime
 
-    // Check that the claim is one of the allowed slot types.
-    match (
-        block_epoch_info.allowed_slots,
-        primary_slot_claim,
-        vrf_output_and_proof,
-    ) {
-        (_, true, None) => unreachable!(),
-        (_, true, Some(_)) => {}
-        (header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots, false, None) => {}
-        (header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots, false, Some(_)) => {}
+    // Check that the claim is one of the allowed slot types. Primary slot claims are always
+    // allowed.
+    match (block_epoch_info.allowed_slots, claim_kind) {
+        (_, ClaimKind::Primary) => {}
+        (_, ClaimKind::SecondaryPlain) | (_, ClaimKind::SecondaryVrf)
+            if config.relaxed_secondary_slots => {}
+        (header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots, ClaimKind::SecondaryPlain) => {}
+        (header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots, ClaimKind::SecondaryVrf) => {}
         _ => return Err(VerifyError::ForbiddenSlotType),
     }
 
@@ -349,6 +385,9 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
             c: block_epoch_info.c,
             allowed_slots: block_epoch_info.allowed_slots,
         }),
+        Some((_, Some(epoch_cfg))) if epoch_cfg.c.1 == 0 || epoch_cfg.c.0 > epoch_cfg.c.1 => {
+            return Err(VerifyError::InvalidNextEpochConfig);
+        }
         Some((info, Some(epoch_cfg))) => Some(chain_information::BabeEpochInformation {
             epoch_index: block_epoch_info.epoch_index.checked_add(1).unwrap(),
             start_slot_number: Some(
@@ -382,61 +421,49 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
         .nth(usize::try_from(authority_index).map_err(|_| VerifyError::InvalidAuthorityIndex)?)
         .ok_or(VerifyError::InvalidAuthorityIndex)?;
 
-    // This `unwrap()` can only panic if `public_key` is the wrong length, which we know can't
-    // happen as it's of type `[u8; 32]`.
-    let signing_public_key =
-        schnorrkel::PublicKey::from_bytes(signing_authority.public_key).unwrap();
-
-    // Now verifying the signature in the seal.
-    signing_public_key
-        .verify_simple(b"substrate", &pre_seal_hash, &seal_signature)
+    // The public key comes from the list of authorities, which is itself found in a block
+    // header, and isn't necessarily a valid point. No valid signature can be produced for an
+    // invalid public key.
+    let signing_public_key = schnorrkel::PublicKey::from_bytes(signing_authority.public_key)
         .map_err(|_| VerifyError::BadSignature)?;
 
-    // Now verify the VRF output and proof, if any.
-    // The lack of VRF output/proof in the header is checked when we check whether the slot
-    // type is allowed by the current configuration.
-    if let Some((vrf_output, vrf_proof)) = vrf_output_and_proof {
-        // In order to verify the VRF output, we first need to create a transcript containing all
-        // the data to verify the VRF against.
-        let transcript = {
-            let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
-            transcript.append_u64(b"slot number", slot_number);
-            transcript.append_u64(b"current epoch", block_epoch_info.epoch_index);
-            transcript.append_message(b"chain randomness", &block_epoch_info.randomness[..]);
-            transcript
-        };
-
-        // These `unwrap()`s can only panic if `vrf_output` or `vrf_proof` are of the wrong
-        // length, which we know can't happen as they're of types `[u8; 32]` and `[u8; 64]`.
-        let vrf_output = schnorrkel::vrf::VRFPreOut::from_bytes(&vrf_output[..]).unwrap();
-        let vrf_proof = schnorrkel::vrf::VRFProof::from_bytes(&vrf_proof[..]).unwrap();
-
-        let (vrf_in_out, _) = signing_public_key
-            .vrf_verify(transcript, &vrf_output, &vrf_proof)
-            .map_err(|_| VerifyError::BadVrfProof)?;
-
+    // The VRF output and proof, if any, are verified at the same time as the signature.
+    // Primary and secondary VRF slot claims always contain a VRF output and proof, while
+    // secondary plain slot claims never do.
+    let vrf_check = if let Some((vrf_output, vrf_proof)) = vrf_output_and_proof {
         // If this is a primary slot claim, we need to make sure that the VRF output is below
         // a certain threshold, otherwise all the authorities could claim all the slots.
-        if primary_slot_claim {
-            let threshold = calculate_primary_threshold(
+        let primary_threshold = if claim_kind == ClaimKind::Primary {
+            Some(calculate_primary_threshold(
                 block_epoch_info.c,
                 block_epoch_info.authorities.clone().map(|a| a.weight),
                 signing_authority.weight,
-            );
-            if u128::from_le_bytes(vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"))
-                >= threshold
-            {
-                return Err(VerifyError::OverPrimaryClaimThreshold);
-            }
-        }
+            ))
+        } else {
+            None
+        };
+
+        // This `unwrap()` can only panic if `vrf_output` is of the wrong length, which we know
+        // can't happen as it's of type `[u8; 32]`. The VRF proof, however, contains scalars
+        // that aren't necessarily in their canonical form.
+        Some(VrfCheck {
+            slot_number,
+            epoch_index: block_epoch_info.epoch_index,
+            randomness: *block_epoch_info.randomness,
+            vrf_output: schnorrkel::vrf::VRFPreOut::from_bytes(&vrf_output[..]).unwrap(),
+            vrf_proof: schnorrkel::vrf::VRFProof::from_bytes(&vrf_proof[..])
+                .map_err(|_| VerifyError::BadVrfProof)?,
+            primary_threshold,
+        })
     } else {
-        debug_assert!(!primary_slot_claim);
-    }
+        debug_assert_eq!(claim_kind, ClaimKind::SecondaryPlain);
+        None
+    };
 
     // Each slot can be claimed by one specific authority in what is called a secondary slot
-    // claim. If the block is a secondary slot claim, we need to make sure that the author
-    // is indeed the one that is expected.
-    if !primary_slot_claim {
+    // claim. If the block is a secondary slot claim, plain or VRF, we need to make sure that
+    // the author is indeed the one that is expected.
+    if claim_kind != ClaimKind::Primary {
         // Expected author is determined based on `blake2(randomness | slot_number)`.
         let hash = {
             let mut hash = blake2_rfc::blake2b::Blake2b::new(32);
@@ -459,10 +486,164 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
     }
 
     // Success! 🚀
-    Ok(VerifySuccess {
-        epoch_transition_target,
-        slot_number,
-    })
+    Ok((
+        VerifySuccess {
+            epoch_transition_target,
+            slot_number,
+            authority_public_key: *signing_authority.public_key,
+        },
+        SignatureCheck {
+            signing_public_key,
+            pre_seal_hash,
+            seal_signature,
+            vrf_check,
+        },
+    ))
+}
+
+/// Type of slot claim found in the pre-runtime digest of a header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum ClaimKind {
+    Primary,
+    SecondaryPlain,
+    SecondaryVrf,
+}
+
+/// Signature and VRF proof of a header, whose verification has been deferred.
+///
+/// See [`verify_header_deferred`].
+#[derive(Debug, Clone)]
+pub struct SignatureCheck {
+    /// Public key of the authority that has supposedly signed the header.
+    signing_public_key: schnorrkel::PublicKey,
+    /// Hash of the header without its seal. This is what the seal signs.
+    pre_seal_hash: [u8; 32],
+    /// Signature found in the seal of the header.
+    seal_signature: schnorrkel::Signature,
+    /// VRF output and proof of the header, if any.
+    vrf_check: Option<VrfCheck>,
+}
+
+/// See [`SignatureCheck::vrf_check`].
+#[derive(Debug, Clone)]
+struct VrfCheck {
+    slot_number: u64,
+    epoch_index: u64,
+    randomness: [u8; 32],
+    vrf_output: schnorrkel::vrf::VRFPreOut,
+    vrf_proof: schnorrkel::vrf::VRFProof,
+    /// `Some` if the block is a primary slot claim, in which case the VRF output must be below
+    /// this threshold.
+    primary_threshold: Option<u128>,
+}
+
+/// Information about the VRF proof of a header, passed to
+/// [`super::Sr25519Hooks::babe_vrf_verify`].
+///
+/// The VRF input is a transcript labelled `b"BABE"` and containing, in this order, the slot
+/// number with the label `b"slot number"`, the epoch index with the label `b"current epoch"`,
+/// and the randomness with the label `b"chain randomness"`.
+#[derive(Debug)]
+pub struct VrfVerifyRef<'a> {
+    /// Public key of the authority that has supposedly produced the VRF output.
+    pub public_key: &'a [u8; 32],
+    /// Slot number claimed by the header.
+    pub slot_number: u64,
+    /// Index of the epoch the slot belongs to.
+    pub epoch_index: u64,
+    /// Randomness of the epoch the slot belongs to.
+    pub randomness: &'a [u8; 32],
+    /// VRF output found in the header.
+    pub vrf_output: &'a [u8; 32],
+    /// VRF proof found in the header.
+    pub vrf_proof: &'a [u8; 64],
+}
+
+impl SignatureCheck {
+    /// Verifies the signature and VRF proof of the header.
+    pub fn verify(&self) -> Result<(), VerifyError> {
+        self.verify_inner(None)
+    }
+
+    /// Same as [`SignatureCheck::verify`], but uses the given implementations of the sr25519
+    /// primitives when they support the operation.
+    pub fn verify_with_hooks(&self, hooks: &super::Sr25519Hooks) -> Result<(), VerifyError> {
+        self.verify_inner(Some(hooks))
+    }
+
+    fn verify_inner(&self, hooks: Option<&super::Sr25519Hooks>) -> Result<(), VerifyError> {
+        let public_key = self.signing_public_key.to_bytes();
+
+        // Now verifying the signature in the seal.
+        let signature_valid = match hooks.and_then(|hooks| {
+            (hooks.verify)(
+                &self.seal_signature.to_bytes(),
+                &self.pre_seal_hash,
+                &public_key,
+            )
+        }) {
+            Some(valid) => valid,
+            None => self
+                .signing_public_key
+                .verify_simple(b"substrate", &self.pre_seal_hash, &self.seal_signature)
+                .is_ok(),
+        };
+        if !signature_valid {
+            return Err(VerifyError::BadSignature);
+        }
+
+        if let Some(vrf_check) = &self.vrf_check {
+            let hook_outcome = hooks.and_then(|hooks| {
+                (hooks.babe_vrf_verify)(VrfVerifyRef {
+                    public_key: &public_key,
+                    slot_number: vrf_check.slot_number,
+                    epoch_index: vrf_check.epoch_index,
+                    randomness: &vrf_check.randomness,
+                    vrf_output: &vrf_check.vrf_output.to_bytes(),
+                    vrf_proof: &vrf_check.vrf_proof.to_bytes(),
+                })
+            });
+
+            let vrf_bytes = match hook_outcome {
+                Some(Ok(bytes)) => bytes,
+                Some(Err(())) => return Err(VerifyError::BadVrfProof),
+                None => vrf_check.verify_locally(&self.signing_public_key)?,
+            };
+
+            if let Some(threshold) = vrf_check.primary_threshold {
+                if u128::from_le_bytes(vrf_bytes) >= threshold {
+                    return Err(VerifyError::OverPrimaryClaimThreshold);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl VrfCheck {
+    /// Verifies the VRF proof using the implementation compiled in smoldot, and returns the
+    /// bytes generated from the VRF input and output.
+    fn verify_locally(
+        &self,
+        signing_public_key: &schnorrkel::PublicKey,
+    ) -> Result<[u8; 16], VerifyError> {
+        // In order to verify the VRF output, we first need to create a transcript containing
+        // all the data to verify the VRF against.
+        let transcript = {
+            let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
+            transcript.append_u64(b"slot number", self.slot_number);
+            transcript.append_u64(b"current epoch", self.epoch_index);
+            transcript.append_message(b"chain randomness", &self.randomness[..]);
+            transcript
+        };
+
+        let (vrf_in_out, _) = signing_public_key
+            .vrf_verify(transcript, &self.vrf_output, &self.vrf_proof)
+            .map_err(|_| VerifyError::BadVrfProof)?;
+
+        Ok(vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"))
+    }
 }
 
 /// Calculates the primary selection threshold for a given authority, taking
@@ -473,10 +654,13 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
 /// `authorities_weights` must be the list of all weights of all autorities.
 /// `authority_weight` must be the weight of the authority whose threshold to calculate.
 ///
+/// An authority whose weight is 0 can never claim a primary slot, and its threshold is 0.
+///
 /// # Panic
 ///
 /// Panics if `authorities_weights` is empty.
-/// Panics if `authority_weight` is 0.
+/// Panics if the denominator of `c` is 0 or if `c` is greater than 1. Configurations found in
+/// headers are checked against this before being used.
 ///
 fn calculate_primary_threshold(
     c: (u64, u64),
@@ -485,17 +669,28 @@ fn calculate_primary_threshold(
 ) -> u128 {
     assert!(authorities_weights.len() != 0);
 
+    if authority_weight == 0 {
+        return 0;
+    }
+
     let c = c.0 as f64 / c.1 as f64;
     assert!(c.is_finite());
 
-    let theta = authority_weight as f64 / authorities_weights.sum::<u64>() as f64;
+    // The sum is calculated using floating point numbers, as the sum of the weights might
+    // overflow a `u64`.
+    let theta = authority_weight as f64 / authorities_weights.map(|w| w as f64).sum::<f64>();
     assert!(theta > 0.0);
 
     // The calculations below has been copy-pasted from Substrate and is guaranteed to not panic.
+    // If `c` is equal to 1, the probability is 1 and the threshold can't be represented in a
+    // `u128`, in which case the maximum value is used instead.
     let p = num_rational::BigRational::from_float(1f64 - (1f64 - c).powf(theta)).unwrap();
     let numer = p.numer().to_biguint().unwrap();
     let denom = p.denom().to_biguint().unwrap();
     ((num_bigint::BigUint::one() << 128u32) * numer / denom)
         .to_u128()
-        .unwrap()
+        .unwrap_or(u128::max_value())
 }
+
+#[cfg(test)]
+mod tests;
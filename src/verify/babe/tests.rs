@@ -0,0 +1,393 @@
+// Smoldot
+// Copyright (C) 2019-2021  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{
+    calculate_primary_threshold, verify_header_deferred, SignatureCheck, VerifyConfig, VerifyError,
+};
+use crate::{chain::chain_information, header, verify::Sr25519Hooks};
+
+use core::{num::NonZeroU64, time::Duration};
+
+const NUM_AUTHORITIES: u32 = 4;
+
+fn epoch(
+    epoch_index: u64,
+    start_slot_number: Option<u64>,
+    allowed_slots: header::BabeAllowedSlots,
+) -> chain_information::BabeEpochInformation {
+    chain_information::BabeEpochInformation {
+        epoch_index,
+        start_slot_number,
+        authorities: (0..NUM_AUTHORITIES)
+            .map(|n| header::BabeAuthority {
+                public_key: schnorrkel::MiniSecretKey::from_bytes(&[n as u8 + 1; 32])
+                    .unwrap()
+                    .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+                    .public
+                    .to_bytes(),
+                weight: 1,
+            })
+            .collect(),
+        randomness: [0xab; 32],
+        c: (1, 4),
+        allowed_slots,
+    }
+}
+
+/// Verifies, without checking the signature, a child of block #1 with the given pre-runtime
+/// digest. Block #1 is at slot 100.
+fn verify(
+    pre_digest: header::BabePreDigest,
+    allowed_slots: header::BabeAllowedSlots,
+    relaxed_secondary_slots: bool,
+) -> Result<(), VerifyError> {
+    signature_check(pre_digest, allowed_slots, relaxed_secondary_slots).map(|_| ())
+}
+
+/// Same as [`verify`], but returns the signature check of the block.
+fn signature_check(
+    pre_digest: header::BabePreDigest,
+    allowed_slots: header::BabeAllowedSlots,
+    relaxed_secondary_slots: bool,
+) -> Result<SignatureCheck, VerifyError> {
+    let parent_digest = vec![header::DigestItem::BabePreDigest(
+        header::BabePreDigest::SecondaryPlain(header::BabeSecondaryPlainPreDigest {
+            authority_index: 0,
+            slot_number: 100,
+        }),
+    )];
+    let parent_header = header::HeaderRef {
+        parent_hash: &[0; 32],
+        number: 1,
+        state_root: &[0; 32],
+        extrinsics_root: &[0; 32],
+        digest: header::DigestRef::from_slice(&parent_digest).unwrap(),
+    };
+
+    // The seal is a well-formed signature. Its validity isn't checked by
+    // `verify_header_deferred`.
+    let mut seal = [0; 64];
+    seal[63] = 0x80;
+    let digest = vec![
+        header::DigestItem::BabePreDigest(pre_digest),
+        header::DigestItem::BabeSeal(seal),
+    ];
+    let parent_hash = parent_header.hash();
+    let block_header = header::HeaderRef {
+        parent_hash: &parent_hash,
+        number: 2,
+        state_root: &[0; 32],
+        extrinsics_root: &[0; 32],
+        digest: header::DigestRef::from_slice(&digest).unwrap(),
+    };
+
+    let current_epoch = epoch(0, None, allowed_slots);
+    let next_epoch = epoch(1, Some(700), allowed_slots);
+
+    verify_header_deferred(VerifyConfig {
+        header: block_header,
+        parent_block_header: parent_header,
+        now_from_unix_epoch: Duration::new(0, 0),
+        slots_per_epoch: NonZeroU64::new(600).unwrap(),
+        parent_block_epoch: Some((&current_epoch).into()),
+        parent_block_next_epoch: (&next_epoch).into(),
+        relaxed_secondary_slots,
+    })
+    .map(|(_, check)| check)
+}
+
+fn secondary_plain(authority_index: u32) -> header::BabePreDigest {
+    header::BabePreDigest::SecondaryPlain(header::BabeSecondaryPlainPreDigest {
+        authority_index,
+        slot_number: 101,
+    })
+}
+
+fn secondary_vrf(authority_index: u32) -> header::BabePreDigest {
+    header::BabePreDigest::SecondaryVRF(header::BabeSecondaryVRFPreDigest {
+        authority_index,
+        slot_number: 101,
+        vrf_output: [0; 32],
+        vrf_proof: [0; 64],
+    })
+}
+
+/// Returns the index of the authority that the secondary slot of the test block is assigned to,
+/// and makes sure that the other authorities are refused.
+fn secondary_slot_author(
+    pre_digest: impl Fn(u32) -> header::BabePreDigest,
+    allowed_slots: header::BabeAllowedSlots,
+    relaxed_secondary_slots: bool,
+) -> u32 {
+    let mut author = None;
+    for authority_index in 0..NUM_AUTHORITIES {
+        match verify(
+            pre_digest(authority_index),
+            allowed_slots,
+            relaxed_secondary_slots,
+        ) {
+            Ok(()) => {
+                assert!(author.is_none());
+                author = Some(authority_index);
+            }
+            Err(VerifyError::BadSecondarySlotAuthor) => {}
+            Err(err) => panic!("{:?}", err),
+        }
+    }
+    author.unwrap()
+}
+
+#[test]
+fn secondary_plain_slots() {
+    let author = secondary_slot_author(
+        secondary_plain,
+        header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots,
+        false,
+    );
+
+    for allowed_slots in [
+        header::BabeAllowedSlots::PrimarySlots,
+        header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+    ] {
+        assert!(matches!(
+            verify(secondary_plain(author), allowed_slots, false),
+            Err(VerifyError::ForbiddenSlotType)
+        ));
+    }
+}
+
+#[test]
+fn secondary_vrf_slots() {
+    let author = secondary_slot_author(
+        secondary_vrf,
+        header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        false,
+    );
+
+    // The slot is assigned to the same authority no matter the type of secondary slot claim.
+    assert_eq!(
+        author,
+        secondary_slot_author(
+            secondary_plain,
+            header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots,
+            false,
+        )
+    );
+
+    for allowed_slots in [
+        header::BabeAllowedSlots::PrimarySlots,
+        header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots,
+    ] {
+        assert!(matches!(
+            verify(secondary_vrf(author), allowed_slots, false),
+            Err(VerifyError::ForbiddenSlotType)
+        ));
+    }
+}
+
+#[test]
+fn relaxed_secondary_slots() {
+    for allowed_slots in [
+        header::BabeAllowedSlots::PrimarySlots,
+        header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots,
+        header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+    ] {
+        // `secondary_slot_author` also checks that the authorities the slot isn't assigned to
+        // are still refused.
+        let author = secondary_slot_author(secondary_plain, allowed_slots, true);
+        assert_eq!(
+            author,
+            secondary_slot_author(secondary_vrf, allowed_slots, true)
+        );
+    }
+}
+
+#[test]
+fn non_canonical_vrf_proof() {
+    let author = secondary_slot_author(
+        secondary_vrf,
+        header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        false,
+    );
+
+    let pre_digest = header::BabePreDigest::SecondaryVRF(header::BabeSecondaryVRFPreDigest {
+        authority_index: author,
+        slot_number: 101,
+        vrf_output: [0; 32],
+        vrf_proof: [0xff; 64],
+    });
+
+    assert!(matches!(
+        verify(
+            pre_digest,
+            header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+            false
+        ),
+        Err(VerifyError::BadVrfProof)
+    ));
+}
+
+#[test]
+fn primary_threshold() {
+    // With a single authority, the probability of claiming a slot is `c`.
+    assert_eq!(
+        calculate_primary_threshold((1, 4), [1].iter().copied(), 1),
+        1 << 126
+    );
+    assert_eq!(
+        calculate_primary_threshold((1, 2), [5].iter().copied(), 5),
+        1 << 127
+    );
+
+    // Authorities with a weight of 0 can't claim any slot.
+    assert_eq!(
+        calculate_primary_threshold((1, 4), [0, 3].iter().copied(), 0),
+        0
+    );
+
+    // The sum of the weights doesn't fit in a `u64`. The probability is too small to be
+    // represented by a `f64`, but the calculation must not panic.
+    assert!(
+        calculate_primary_threshold((1, 4), [u64::max_value(); 2].iter().copied(), 1) < 1 << 64
+    );
+
+    // All slots can be claimed.
+    assert_eq!(
+        calculate_primary_threshold((1, 1), [1].iter().copied(), 1),
+        u128::max_value()
+    );
+}
+
+#[test]
+fn invalid_next_epoch_config() {
+    let parent_digest = vec![header::DigestItem::BabePreDigest(secondary_plain(0))];
+    let parent_header = header::HeaderRef {
+        parent_hash: &[0; 32],
+        number: 1,
+        state_root: &[0; 32],
+        extrinsics_root: &[0; 32],
+        digest: header::DigestRef::from_slice(&parent_digest).unwrap(),
+    };
+    let parent_hash = parent_header.hash();
+
+    let current_epoch = epoch(0, None, header::BabeAllowedSlots::PrimarySlots);
+    let next_epoch = epoch(
+        1,
+        Some(700),
+        header::BabeAllowedSlots::PrimaryAndSecondaryPlainSlots,
+    );
+
+    // The block is the first of the next epoch, and announces the configuration of the epoch
+    // after. The configuration is checked before the author of the slot.
+    for c in [(1, 0), (0, 0), (5, 4)] {
+        let mut seal = [0; 64];
+        seal[63] = 0x80;
+        let digest = vec![
+            header::DigestItem::BabePreDigest(header::BabePreDigest::SecondaryPlain(
+                header::BabeSecondaryPlainPreDigest {
+                    authority_index: 0,
+                    slot_number: 701,
+                },
+            )),
+            header::DigestItem::BabeConsensus(header::BabeConsensusLog::NextEpochData(
+                header::BabeNextEpoch {
+                    authorities: Vec::new(),
+                    randomness: [0; 32],
+                },
+            )),
+            header::DigestItem::BabeConsensus(header::BabeConsensusLog::NextConfigData(
+                header::BabeNextConfig {
+                    c,
+                    allowed_slots: header::BabeAllowedSlots::PrimarySlots,
+                },
+            )),
+            header::DigestItem::BabeSeal(seal),
+        ];
+        let block_header = header::HeaderRef {
+            parent_hash: &parent_hash,
+            number: 2,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest: header::DigestRef::from_slice(&digest).unwrap(),
+        };
+
+        assert!(matches!(
+            verify_header_deferred(VerifyConfig {
+                header: block_header,
+                parent_block_header: parent_header.clone(),
+                now_from_unix_epoch: Duration::new(0, 0),
+                slots_per_epoch: NonZeroU64::new(600).unwrap(),
+                parent_block_epoch: Some((&current_epoch).into()),
+                parent_block_next_epoch: (&next_epoch).into(),
+                relaxed_secondary_slots: false,
+            }),
+            Err(VerifyError::InvalidNextEpochConfig)
+        ));
+    }
+}
+
+#[test]
+fn sr25519_hooks() {
+    let allowed_slots = header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots;
+    let author = secondary_slot_author(secondary_vrf, allowed_slots, false);
+    let check = signature_check(secondary_vrf(author), allowed_slots, false).unwrap();
+
+    // The seal and VRF proof of the test block are bogus.
+    assert!(matches!(check.verify(), Err(VerifyError::BadSignature)));
+
+    // Hooks that don't support the operations fall back to the local implementation.
+    let unsupported = Sr25519Hooks {
+        verify: |_, _, _| None,
+        babe_vrf_verify: |_| None,
+    };
+    assert!(matches!(
+        check.verify_with_hooks(&unsupported),
+        Err(VerifyError::BadSignature)
+    ));
+
+    // The outcome of the hooks is used when they support the operations.
+    let accept_all = Sr25519Hooks {
+        verify: |_, _, _| Some(true),
+        babe_vrf_verify: |input| {
+            assert_eq!(input.slot_number, 101);
+            assert_eq!(input.epoch_index, 0);
+            assert_eq!(*input.randomness, [0xab; 32]);
+            Some(Ok([0; 16]))
+        },
+    };
+    assert!(check.verify_with_hooks(&accept_all).is_ok());
+
+    let bad_vrf = Sr25519Hooks {
+        verify: |_, _, _| Some(true),
+        babe_vrf_verify: |_| Some(Err(())),
+    };
+    assert!(matches!(
+        check.verify_with_hooks(&bad_vrf),
+        Err(VerifyError::BadVrfProof)
+    ));
+
+    let bad_signature = Sr25519Hooks {
+        verify: |_, _, _| Some(false),
+        babe_vrf_verify: |_| Some(Ok([0; 16])),
+    };
+    assert!(matches!(
+        check.verify_with_hooks(&bad_signature),
+        Err(VerifyError::BadSignature)
+    ));
+}
@@ -96,6 +96,9 @@ pub enum ConfigConsensus<'a> {
         /// Epoch that follows the epoch the parent block belongs to.
         parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
 
+        /// See [`babe::VerifyConfig::relaxed_secondary_slots`].
+        relaxed_secondary_slots: bool,
+
         /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
         /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
         now_from_unix_epoch: Duration,
@@ -137,6 +140,9 @@ pub enum SuccessConsensus {
     Aura {
         /// True if the list of authorities is modified by this block.
         authorities_change: bool,
+
+        /// Sr25519 public key of the authority that has authored the block.
+        authority_public_key: [u8; 32],
     },
 
     /// Chain is using the Babe consensus engine.
@@ -153,6 +159,9 @@ pub enum SuccessConsensus {
         /// value previously in [`ConfigConsensus::Babe::parent_block_next_epoch`] must instead be
         /// passed as [`ConfigConsensus::Babe::parent_block_epoch`].
         epoch_transition_target: Option<chain_information::BabeEpochInformation>,
+
+        /// Sr25519 public key of the authority that has authored the block.
+        authority_public_key: [u8; 32],
     },
 }
 
@@ -211,6 +220,7 @@ pub fn verify(
             match result {
                 Ok(s) => SuccessConsensus::Aura {
                     authorities_change: s.authorities_change,
+                    authority_public_key: s.authority_public_key,
                 },
                 Err(err) => {
                     return Verify::Finished(Err((
@@ -225,6 +235,7 @@ pub fn verify(
             parent_block_next_epoch,
             slots_per_epoch,
             now_from_unix_epoch,
+            relaxed_secondary_slots,
         } => {
             if config.block_header.digest.has_any_aura() {
                 return Verify::Finished(Err((
@@ -240,12 +251,14 @@ pub fn verify(
                 parent_block_epoch,
                 slots_per_epoch,
                 now_from_unix_epoch,
+                relaxed_secondary_slots,
             });
 
             match result {
                 Ok(s) => SuccessConsensus::Babe {
                     epoch_transition_target: s.epoch_transition_target,
                     slot_number: s.slot_number,
+                    authority_public_key: s.authority_public_key,
                 },
                 Err(err) => {
                     return Verify::Finished(Err((
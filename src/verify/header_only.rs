@@ -80,6 +80,9 @@ pub enum ConfigConsensus<'a> {
         /// Epoch that follows the epoch the parent block belongs to.
         parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
 
+        /// See [`babe::VerifyConfig::relaxed_secondary_slots`].
+        relaxed_secondary_slots: bool,
+
         /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
         /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
         now_from_unix_epoch: Duration,
@@ -95,6 +98,9 @@ pub enum Success {
     Aura {
         /// True if the list of authorities is modified by this block.
         authorities_change: bool,
+
+        /// Sr25519 public key of the authority that has authored the block.
+        authority_public_key: [u8; 32],
     },
 
     /// Chain is using the Babe consensus engine.
@@ -111,6 +117,9 @@ pub enum Success {
         /// value previously in [`ConfigConsensus::Babe::parent_block_next_epoch`] must instead be
         /// passed as [`ConfigConsensus::Babe::parent_block_epoch`].
         epoch_transition_target: Option<chain_information::BabeEpochInformation>,
+
+        /// Sr25519 public key of the authority that has authored the block.
+        authority_public_key: [u8; 32],
     },
 }
 
@@ -129,10 +138,26 @@ pub enum Error {
     /// Failed to verify the authenticity of the block with the BABE algorithm.
     #[display(fmt = "{}", _0)]
     BabeVerification(babe::VerifyError),
+    /// The digest items of consensus engines unknown to smoldot have been refused by the API
+    /// user. See [`crate::header::DigestRef::custom_items`].
+    CustomDigestItemsRejected,
 }
 
 /// Verifies whether a block is valid.
 pub fn verify(config: Config) -> Result<Success, Error> {
+    let (success, signature_checks) = verify_deferred(config)?;
+    signature_checks.verify()?;
+    Ok(success)
+}
+
+/// Same as [`verify`], except that the signatures found in the header aren't verified. Instead,
+/// a [`SignatureChecks`] is returned alongside with the success, and the block must only be
+/// considered as valid after [`SignatureChecks::verify`] has succeeded.
+///
+/// Since verifying signatures is the most CPU-intensive part of the verification and doesn't
+/// require any state, this makes it possible to verify the signatures of multiple headers in a
+/// batch, for example on a different thread.
+pub fn verify_deferred(config: Config) -> Result<(Success, SignatureChecks), Error> {
     // Check that there is no mismatch in the parent header hash.
     // Note that the user is expected to pass a parent block that matches the parent indicated by
     // the header to verify, and not blindly pass an "expected parent". As such, this check is
@@ -166,7 +191,12 @@ pub fn verify(config: Config) -> Result<Success, Error> {
                 return Err(Error::MultipleConsensusEngines);
             }
 
-            Ok(Success::AllAuthorized)
+            Ok((
+                Success::AllAuthorized,
+                SignatureChecks {
+                    inner: SignatureChecksInner::None,
+                },
+            ))
         }
         ConfigConsensus::Aura {
             current_authorities,
@@ -177,7 +207,7 @@ pub fn verify(config: Config) -> Result<Success, Error> {
                 return Err(Error::MultipleConsensusEngines);
             }
 
-            let result = aura::verify_header(aura::VerifyConfig {
+            let result = aura::verify_header_deferred(aura::VerifyConfig {
                 header: config.block_header.clone(),
                 parent_block_header: config.parent_block_header,
                 now_from_unix_epoch,
@@ -186,9 +216,15 @@ pub fn verify(config: Config) -> Result<Success, Error> {
             });
 
             match result {
-                Ok(s) => Ok(Success::Aura {
-                    authorities_change: s.authorities_change,
-                }),
+                Ok((s, check)) => Ok((
+                    Success::Aura {
+                        authorities_change: s.authorities_change,
+                        authority_public_key: s.authority_public_key,
+                    },
+                    SignatureChecks {
+                        inner: SignatureChecksInner::Aura(check),
+                    },
+                )),
                 Err(err) => Err(Error::AuraVerification(err)),
             }
         }
@@ -197,27 +233,87 @@ pub fn verify(config: Config) -> Result<Success, Error> {
             parent_block_next_epoch,
             slots_per_epoch,
             now_from_unix_epoch,
+            relaxed_secondary_slots,
         } => {
             if config.block_header.digest.has_any_aura() {
                 return Err(Error::MultipleConsensusEngines);
             }
 
-            let result = babe::verify_header(babe::VerifyConfig {
+            let result = babe::verify_header_deferred(babe::VerifyConfig {
                 header: config.block_header.clone(),
                 parent_block_header: config.parent_block_header,
                 parent_block_epoch,
                 parent_block_next_epoch,
                 slots_per_epoch,
                 now_from_unix_epoch,
+                relaxed_secondary_slots,
             });
 
             match result {
-                Ok(s) => Ok(Success::Babe {
-                    epoch_transition_target: s.epoch_transition_target,
-                    slot_number: s.slot_number,
-                }),
+                Ok((s, check)) => Ok((
+                    Success::Babe {
+                        epoch_transition_target: s.epoch_transition_target,
+                        slot_number: s.slot_number,
+                        authority_public_key: s.authority_public_key,
+                    },
+                    SignatureChecks {
+                        inner: SignatureChecksInner::Babe(check),
+                    },
+                )),
                 Err(err) => Err(Error::BabeVerification(err)),
             }
         }
     }
 }
+
+/// Signatures of a block header whose verification has been deferred.
+///
+/// See [`verify_deferred`].
+#[derive(Debug, Clone)]
+pub struct SignatureChecks {
+    inner: SignatureChecksInner,
+}
+
+#[derive(Debug, Clone)]
+enum SignatureChecksInner {
+    None,
+    Aura(aura::SignatureCheck),
+    Babe(babe::SignatureCheck),
+}
+
+impl SignatureChecks {
+    /// Verifies the signatures of the block header.
+    pub fn verify(&self) -> Result<(), Error> {
+        match &self.inner {
+            SignatureChecksInner::None => Ok(()),
+            SignatureChecksInner::Aura(check) => check.verify().map_err(Error::AuraVerification),
+            SignatureChecksInner::Babe(check) => check.verify().map_err(Error::BabeVerification),
+        }
+    }
+
+    /// Same as [`SignatureChecks::verify`], but uses the given implementations of the sr25519
+    /// primitives when they support the operation.
+    pub fn verify_with_hooks(&self, hooks: &super::Sr25519Hooks) -> Result<(), Error> {
+        match &self.inner {
+            SignatureChecksInner::None => Ok(()),
+            SignatureChecksInner::Aura(check) => check
+                .verify_with_hooks(hooks)
+                .map_err(Error::AuraVerification),
+            SignatureChecksInner::Babe(check) => check
+                .verify_with_hooks(hooks)
+                .map_err(Error::BabeVerification),
+        }
+    }
+
+    /// Verifies the signatures of the block header, using the given implementations of the
+    /// sr25519 primitives if any.
+    pub fn verify_with_optional_hooks(
+        &self,
+        hooks: Option<&super::Sr25519Hooks>,
+    ) -> Result<(), Error> {
+        match hooks {
+            Some(hooks) => self.verify_with_hooks(hooks),
+            None => self.verify(),
+        }
+    }
+}